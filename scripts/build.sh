@@ -1,11 +1,12 @@
 #!/bin/bash
 
-# Build the entire project
+# Build the entire project, the kernel for the machine profile in
+# VAELIX_PROFILE (laptop or qemu) or generic x86_64 when unset
 echo "Building the entire project..."
 
 # Compile the kernel
 echo "Compiling the kernel..."
-cargo build --release --package vaelix_core
+cargo build --release --package vaelix_core ${VAELIX_PROFILE:+--features profile-$VAELIX_PROFILE}
 
 # Compile the graphics modules
 echo "Compiling the graphics modules..."
@@ -5,7 +5,7 @@ echo "Building full system ISO..."
 
 # Compile the kernel
 echo "Compiling the kernel..."
-cargo build --release --package vaelix_core
+cargo build --release --package vaelix_core ${VAELIX_PROFILE:+--features profile-$VAELIX_PROFILE}
 
 # Create the ISO directory structure
 echo "Creating ISO directory structure..."
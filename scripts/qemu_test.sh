@@ -0,0 +1,55 @@
+#!/bin/bash
+
+# Boot the system under QEMU with the in-kernel test suites and report the
+# result as the exit code: 0 when every test passed, 1 when one failed and
+# 2 when the kernel crashed, hung or never got to the tests.
+#
+# Usage: scripts/qemu_test.sh [suite,suite...]
+#   VXTEST_TIMEOUT  seconds before the run counts as hung (300)
+#   VXTEST_KERNEL   kernel image to boot
+#   VXTEST_HOSTED   set to run the kernel as a host process instead
+
+SUITES="${1:-all}"
+TIMEOUT="${VXTEST_TIMEOUT:-300}"
+KERNEL="${VXTEST_KERNEL:-iso/system/kernel/vaelix_kernel}"
+
+if [ -n "$VXTEST_HOSTED" ]; then
+    echo "Running the in-kernel tests hosted with vxtest=$SUITES..."
+    cargo build || exit 2
+    timeout "$TIMEOUT" target/debug/vaelixos "vxtest=$SUITES"
+else
+    echo "Building the system image..."
+    VAELIX_PROFILE=qemu bash scripts/build_full_system_iso.sh || exit 2
+
+    # isa-debug-exit turns the kernel's write to port 0xf4 into QEMU's
+    # exit status: 33 for a pass and 35 for a failure
+    echo "Booting under QEMU with vxtest=$SUITES..."
+    timeout "$TIMEOUT" qemu-system-x86_64 \
+        -m 1G \
+        -kernel "$KERNEL" \
+        -append "console=ttyS0 vxtest=$SUITES" \
+        -device isa-debug-exit,iobase=0xf4,iosize=0x04 \
+        -serial stdio \
+        -display none \
+        -no-reboot
+fi
+STATUS=$?
+
+case $STATUS in
+    33)
+        echo "All in-kernel tests passed."
+        exit 0
+        ;;
+    35)
+        echo "In-kernel tests failed."
+        exit 1
+        ;;
+    124)
+        echo "Timed out after ${TIMEOUT}s."
+        exit 2
+        ;;
+    *)
+        echo "Kernel exited unexpectedly with status $STATUS."
+        exit 2
+        ;;
+esac
@@ -23,4 +23,8 @@ cargo test --package vaelix_package
 echo "Running tests for vaelix_ui..."
 cargo test --package vaelix_ui
 
+# Run the in-kernel test suites, hosted so no emulator is needed
+echo "Running the in-kernel test suites..."
+VXTEST_HOSTED=1 bash scripts/qemu_test.sh
+
 echo "All tests completed."
@@ -1,6 +1,9 @@
 // src/graphics/mod.rs
 
 pub mod vegagx;
+pub mod vxcursor;
 pub mod vxfont;
+pub mod vxoutput;
 pub mod vxtheme;
 pub mod vxwin;
+pub mod vxwm;
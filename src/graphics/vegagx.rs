@@ -1,3 +1,724 @@
+// src/graphics/vegagx.rs
+
 pub mod vegagx {
-    // Implementation of the vegagx module
+    use crate::vxwin::vxwin::Rect;
+
+    // Only Vec is needed beyond core, and the float helpers below stand in
+    // for the std-only f32 methods, so this builds for no_std with alloc
+
+    fn floor(value: f32) -> i32 {
+        let truncated = value as i32;
+        if (truncated as f32) > value {
+            truncated - 1
+        } else {
+            truncated
+        }
+    }
+
+    fn ceil(value: f32) -> i32 {
+        -floor(-value)
+    }
+
+    fn sqrt(value: f32) -> f32 {
+        if value <= 0.0 {
+            return 0.0;
+        }
+        // Bit-level first guess, then Newton steps
+        let mut root = f32::from_bits((value.to_bits() >> 1) + 0x1FC0_0000);
+        for _ in 0..3 {
+            root = 0.5 * (root + value / root);
+        }
+        root
+    }
+
+    // Scales every channel of a premultiplied pixel by alpha/255
+    fn scale(color: u32, alpha: u32) -> u32 {
+        let rb = (color & 0x00FF_00FF) * alpha;
+        let ag = ((color >> 8) & 0x00FF_00FF) * alpha;
+        (((rb + 0x0080_0080 + ((rb >> 8) & 0x00FF_00FF)) >> 8) & 0x00FF_00FF)
+            | ((ag + 0x0080_0080 + ((ag >> 8) & 0x00FF_00FF)) & 0xFF00_FF00)
+    }
+
+    // Source-over for premultiplied pixels
+    fn over(src: u32, dst: u32) -> u32 {
+        src + scale(dst, 255 - (src >> 24))
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Color {
+        pub r: u8,
+        pub g: u8,
+        pub b: u8,
+        pub a: u8,
+    }
+
+    impl Color {
+        pub const fn rgba(r: u8, g: u8, b: u8, a: u8) -> Self {
+            Color { r, g, b, a }
+        }
+
+        // Straight (not premultiplied) 0xAARRGGBB
+        pub const fn argb(value: u32) -> Self {
+            Color {
+                r: (value >> 16) as u8,
+                g: (value >> 8) as u8,
+                b: value as u8,
+                a: (value >> 24) as u8,
+            }
+        }
+
+        pub fn premultiplied(&self) -> u32 {
+            let straight = (self.r as u32) << 16 | (self.g as u32) << 8 | self.b as u32;
+            scale(straight, self.a as u32) | (self.a as u32) << 24
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct GradientStop {
+        // 0.0-1.0 along the gradient
+        pub offset: f32,
+        pub color: Color,
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Paint {
+        Solid(Color),
+        // Stops must be in offset order; outside them the end colors extend
+        LinearGradient {
+            start: Point,
+            end: Point,
+            stops: Vec<GradientStop>,
+        },
+        RadialGradient {
+            center: Point,
+            radius: f32,
+            stops: Vec<GradientStop>,
+        },
+    }
+
+    impl Paint {
+        fn gradient(stops: &[GradientStop], t: f32) -> u32 {
+            let Some(first) = stops.first() else {
+                return 0;
+            };
+            if t <= first.offset {
+                return first.color.premultiplied();
+            }
+            for pair in stops.windows(2) {
+                let (from, to) = (pair[0], pair[1]);
+                if t <= to.offset {
+                    let span = to.offset - from.offset;
+                    let weight = if span > 0.0 { (t - from.offset) / span } else { 1.0 };
+                    let weight = (weight * 255.0 + 0.5) as u32;
+                    // Interpolating premultiplied avoids dark fringes
+                    return scale(from.color.premultiplied(), 255 - weight) + scale(to.color.premultiplied(), weight);
+                }
+            }
+            stops[stops.len() - 1].color.premultiplied()
+        }
+
+        // Premultiplied color at a pixel center
+        pub fn sample(&self, x: i32, y: i32) -> u32 {
+            let point = Point::new(x as f32 + 0.5, y as f32 + 0.5);
+            match self {
+                Paint::Solid(color) => color.premultiplied(),
+                Paint::LinearGradient { start, end, stops } => {
+                    let (dx, dy) = (end.x - start.x, end.y - start.y);
+                    let length = dx * dx + dy * dy;
+                    let t = if length > 0.0 {
+                        ((point.x - start.x) * dx + (point.y - start.y) * dy) / length
+                    } else {
+                        0.0
+                    };
+                    Self::gradient(stops, t)
+                }
+                Paint::RadialGradient { center, radius, stops } => {
+                    let (dx, dy) = (point.x - center.x, point.y - center.y);
+                    let t = if *radius > 0.0 { sqrt(dx * dx + dy * dy) / radius } else { 1.0 };
+                    Self::gradient(stops, t)
+                }
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Point {
+        pub x: f32,
+        pub y: f32,
+    }
+
+    impl Point {
+        pub const fn new(x: f32, y: f32) -> Self {
+            Point { x, y }
+        }
+
+        fn lerp(&self, other: Point, t: f32) -> Point {
+            Point::new(self.x + (other.x - self.x) * t, self.y + (other.y - self.y) * t)
+        }
+
+        fn distance(&self, other: Point) -> f32 {
+            let (dx, dy) = (other.x - self.x, other.y - self.y);
+            sqrt(dx * dx + dy * dy)
+        }
+    }
+
+    // Circle approximation constant for cubic quarter arcs
+    const KAPPA: f32 = 0.552_284_8;
+
+    // Curves are flattened into line segments as they are added
+    #[derive(Debug, Clone, Default, PartialEq)]
+    pub struct Path {
+        contours: Vec<(Vec<Point>, bool)>,
+    }
+
+    impl Path {
+        pub fn new() -> Self {
+            Path { contours: Vec::new() }
+        }
+
+        fn current(&mut self) -> &mut Vec<Point> {
+            if self.contours.last().is_none_or(|(_, closed)| *closed) {
+                self.contours.push((vec![Point::new(0.0, 0.0)], false));
+            }
+            &mut self.contours.last_mut().unwrap().0
+        }
+
+        fn last_point(&mut self) -> Point {
+            *self.current().last().unwrap()
+        }
+
+        pub fn move_to(&mut self, x: f32, y: f32) -> &mut Self {
+            self.contours.push((vec![Point::new(x, y)], false));
+            self
+        }
+
+        pub fn line_to(&mut self, x: f32, y: f32) -> &mut Self {
+            self.current().push(Point::new(x, y));
+            self
+        }
+
+        // About one segment per two pixels of control polygon
+        fn segments(length: f32) -> usize {
+            (ceil(length / 2.0).max(2) as usize).min(128)
+        }
+
+        pub fn quad_to(&mut self, cx: f32, cy: f32, x: f32, y: f32) -> &mut Self {
+            let (start, control, end) = (self.last_point(), Point::new(cx, cy), Point::new(x, y));
+            let segments = Self::segments(start.distance(control) + control.distance(end));
+            for step in 1..=segments {
+                let t = step as f32 / segments as f32;
+                let point = start.lerp(control, t).lerp(control.lerp(end, t), t);
+                self.current().push(point);
+            }
+            self
+        }
+
+        pub fn cubic_to(&mut self, c1x: f32, c1y: f32, c2x: f32, c2y: f32, x: f32, y: f32) -> &mut Self {
+            let start = self.last_point();
+            let (c1, c2, end) = (Point::new(c1x, c1y), Point::new(c2x, c2y), Point::new(x, y));
+            let segments = Self::segments(start.distance(c1) + c1.distance(c2) + c2.distance(end));
+            for step in 1..=segments {
+                let t = step as f32 / segments as f32;
+                let (a, b, c) = (start.lerp(c1, t), c1.lerp(c2, t), c2.lerp(end, t));
+                let point = a.lerp(b, t).lerp(b.lerp(c, t), t);
+                self.current().push(point);
+            }
+            self
+        }
+
+        pub fn close(&mut self) -> &mut Self {
+            if let Some((_, closed)) = self.contours.last_mut() {
+                *closed = true;
+            }
+            self
+        }
+
+        pub fn rect(x: f32, y: f32, width: f32, height: f32) -> Self {
+            let mut path = Path::new();
+            path.move_to(x, y)
+                .line_to(x + width, y)
+                .line_to(x + width, y + height)
+                .line_to(x, y + height)
+                .close();
+            path
+        }
+
+        pub fn rounded_rect(x: f32, y: f32, width: f32, height: f32, radius: f32) -> Self {
+            let r = radius.min(width / 2.0).min(height / 2.0).max(0.0);
+            let k = r * (1.0 - KAPPA);
+            let (right, bottom) = (x + width, y + height);
+            let mut path = Path::new();
+            path.move_to(x + r, y)
+                .line_to(right - r, y)
+                .cubic_to(right - k, y, right, y + k, right, y + r)
+                .line_to(right, bottom - r)
+                .cubic_to(right, bottom - k, right - k, bottom, right - r, bottom)
+                .line_to(x + r, bottom)
+                .cubic_to(x + k, bottom, x, bottom - k, x, bottom - r)
+                .line_to(x, y + r)
+                .cubic_to(x, y + k, x + k, y, x + r, y)
+                .close();
+            path
+        }
+
+        pub fn circle(cx: f32, cy: f32, radius: f32) -> Self {
+            let k = radius * KAPPA;
+            let mut path = Path::new();
+            path.move_to(cx + radius, cy)
+                .cubic_to(cx + radius, cy + k, cx + k, cy + radius, cx, cy + radius)
+                .cubic_to(cx - k, cy + radius, cx - radius, cy + k, cx - radius, cy)
+                .cubic_to(cx - radius, cy - k, cx - k, cy - radius, cx, cy - radius)
+                .cubic_to(cx + k, cy - radius, cx + radius, cy - k, cx + radius, cy)
+                .close();
+            path
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.contours.iter().all(|(points, _)| points.len() < 2)
+        }
+
+        // Smallest pixel rectangle holding every point
+        pub fn bounds(&self) -> Option<Rect> {
+            let mut points = self.contours.iter().flat_map(|(points, _)| points.iter());
+            let first = points.next()?;
+            let (mut left, mut top, mut right, mut bottom) = (first.x, first.y, first.x, first.y);
+            for point in points {
+                (left, top) = (left.min(point.x), top.min(point.y));
+                (right, bottom) = (right.max(point.x), bottom.max(point.y));
+            }
+            let (x, y) = (floor(left), floor(top));
+            Some(Rect::new(x, y, (ceil(right) - x) as u32, (ceil(bottom) - y) as u32))
+        }
+
+        // Each segment becomes a quad and each vertex a round join, all
+        // wound the same way so the nonzero fill takes their union
+        fn stroke(&self, width: f32) -> Path {
+            let half = width / 2.0;
+            let mut outline = Path::new();
+            let mut add = |polygon: Vec<Point>| {
+                let area: f32 = (0..polygon.len())
+                    .map(|index| {
+                        let (a, b) = (polygon[index], polygon[(index + 1) % polygon.len()]);
+                        a.x * b.y - b.x * a.y
+                    })
+                    .sum();
+                let mut polygon = polygon;
+                if area < 0.0 {
+                    polygon.reverse();
+                }
+                outline.contours.push((polygon, true));
+            };
+            for (points, closed) in &self.contours {
+                let mut segments: Vec<(Point, Point)> = points.windows(2).map(|pair| (pair[0], pair[1])).collect();
+                if *closed && points.len() > 2 {
+                    segments.push((points[points.len() - 1], points[0]));
+                }
+                for (a, b) in segments {
+                    let length = a.distance(b);
+                    if length == 0.0 {
+                        continue;
+                    }
+                    let (nx, ny) = (-(b.y - a.y) / length * half, (b.x - a.x) / length * half);
+                    add(vec![
+                        Point::new(a.x + nx, a.y + ny),
+                        Point::new(b.x + nx, b.y + ny),
+                        Point::new(b.x - nx, b.y - ny),
+                        Point::new(a.x - nx, a.y - ny),
+                    ]);
+                }
+                if half >= 1.0 {
+                    for point in points {
+                        let circle = Path::circle(point.x, point.y, half);
+                        add(circle.contours[0].0.clone());
+                    }
+                }
+            }
+            outline
+        }
+    }
+
+    // Premultiplied ARGB pixels
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Image {
+        width: u32,
+        height: u32,
+        pixels: Vec<u32>,
+    }
+
+    impl Image {
+        pub fn new(width: u32, height: u32, pixels: Vec<u32>) -> Result<Self, &'static str> {
+            if pixels.len() != (width * height) as usize {
+                return Err("Image size does not match its pixels");
+            }
+            Ok(Image { width, height, pixels })
+        }
+
+        pub fn width(&self) -> u32 {
+            self.width
+        }
+
+        pub fn height(&self) -> u32 {
+            self.height
+        }
+
+        pub fn pixels(&self) -> &[u32] {
+            &self.pixels
+        }
+
+        // Binary PPM (P6) with at most 8 bits per channel
+        pub fn decode_ppm(data: &[u8]) -> Result<Self, &'static str> {
+            let mut fields = Vec::new();
+            let mut position = 0;
+            while fields.len() < 4 {
+                match data.get(position) {
+                    None => return Err("Truncated PPM header"),
+                    Some(b'#') => {
+                        while data.get(position).is_some_and(|byte| *byte != b'\n') {
+                            position += 1;
+                        }
+                    }
+                    Some(byte) if byte.is_ascii_whitespace() => position += 1,
+                    Some(_) => {
+                        let start = position;
+                        while data.get(position).is_some_and(|byte| !byte.is_ascii_whitespace()) {
+                            position += 1;
+                        }
+                        fields.push(&data[start..position]);
+                    }
+                }
+            }
+            if fields[0] != b"P6" {
+                return Err("Not a binary PPM");
+            }
+            let number = |field: &[u8]| -> Result<u32, &'static str> {
+                std::str::from_utf8(field).ok().and_then(|text| text.parse().ok()).ok_or("Invalid PPM header")
+            };
+            let (width, height, max) = (number(fields[1])?, number(fields[2])?, number(fields[3])?);
+            if !(1..=255).contains(&max) {
+                return Err("Unsupported PPM depth");
+            }
+            // A single whitespace byte separates the header from the samples
+            let samples = data.get(position + 1..).ok_or("Truncated PPM data")?;
+            let count = width as usize * height as usize;
+            if samples.len() < count * 3 {
+                return Err("Truncated PPM data");
+            }
+            let channel = |value: u8| (value as u32 * 255 + max / 2) / max;
+            let pixels = samples[..count * 3]
+                .chunks_exact(3)
+                .map(|rgb| 0xFF00_0000 | channel(rgb[0]) << 16 | channel(rgb[1]) << 8 | channel(rgb[2]))
+                .collect();
+            Image::new(width, height, pixels)
+        }
+
+        // Binary PPM, as decode_ppm reads. PPM has no alpha, so translucent
+        // pixels come out as if over black.
+        pub fn encode_ppm(&self) -> Vec<u8> {
+            let mut data = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+            for pixel in &self.pixels {
+                data.extend_from_slice(&[(pixel >> 16) as u8, (pixel >> 8) as u8, *pixel as u8]);
+            }
+            data
+        }
+
+        // Bilinear resampling to the given size
+        pub fn scaled(&self, width: u32, height: u32) -> Image {
+            let (width, height) = (width.max(1), height.max(1));
+            let (scale_x, scale_y) = (self.width as f32 / width as f32, self.height as f32 / height as f32);
+            let mut pixels = Vec::with_capacity((width * height) as usize);
+            for y in 0..height {
+                let source_y = ((y as f32 + 0.5) * scale_y - 0.5).clamp(0.0, (self.height - 1) as f32);
+                let (top, fy) = (source_y.floor() as u32, source_y.fract());
+                let bottom = (top + 1).min(self.height - 1);
+                for x in 0..width {
+                    let source_x = ((x as f32 + 0.5) * scale_x - 0.5).clamp(0.0, (self.width - 1) as f32);
+                    let (left, fx) = (source_x.floor() as u32, source_x.fract());
+                    let right = (left + 1).min(self.width - 1);
+                    let at = |x: u32, y: u32| self.pixels[(y * self.width + x) as usize];
+                    let mut color = 0;
+                    for shift in [0, 8, 16, 24] {
+                        let channel = |pixel: u32| ((pixel >> shift) & 0xFF) as f32;
+                        let upper = channel(at(left, top)) * (1.0 - fx) + channel(at(right, top)) * fx;
+                        let lower = channel(at(left, bottom)) * (1.0 - fx) + channel(at(right, bottom)) * fx;
+                        color |= ((upper * (1.0 - fy) + lower * fy).round() as u32) << shift;
+                    }
+                    pixels.push(color);
+                }
+            }
+            Image { width, height, pixels }
+        }
+    }
+
+    // Coverage accumulation rasterizer: every edge adds its signed area to
+    // the cells it crosses and a running sum along each row gives the
+    // winding, so antialiasing falls out of the exact areas
+    struct Rasterizer {
+        left: f32,
+        top: f32,
+        width: usize,
+        height: usize,
+        // Two spare cells per row take what lands on the right edge
+        cells: Vec<f32>,
+    }
+
+    impl Rasterizer {
+        fn new(area: Rect) -> Self {
+            Rasterizer {
+                left: area.x as f32,
+                top: area.y as f32,
+                width: area.width as usize,
+                height: area.height as usize,
+                cells: vec![0.0; (area.width as usize + 2) * area.height as usize],
+            }
+        }
+
+        // Splits at the left and right of the area so the parts outside can
+        // be flattened onto its edges without changing the winding inside
+        fn add_line(&mut self, a: Point, b: Point) {
+            let (a, b) = (Point::new(a.x - self.left, a.y - self.top), Point::new(b.x - self.left, b.y - self.top));
+            let right = self.width as f32;
+            let mut cuts = vec![0.0, 1.0];
+            for edge in [0.0, right] {
+                if (a.x - edge) * (b.x - edge) < 0.0 {
+                    cuts.push((edge - a.x) / (b.x - a.x));
+                }
+            }
+            cuts.sort_by(|x, y| x.partial_cmp(y).unwrap());
+            for pair in cuts.windows(2) {
+                let clamp = |point: Point| Point::new(point.x.clamp(0.0, right), point.y);
+                self.line(clamp(a.lerp(b, pair[0])), clamp(a.lerp(b, pair[1])));
+            }
+        }
+
+        fn line(&mut self, p0: Point, p1: Point) {
+            if p0.y == p1.y {
+                return;
+            }
+            let (direction, p0, p1) = if p0.y < p1.y { (1.0, p0, p1) } else { (-1.0, p1, p0) };
+            let dxdy = (p1.x - p0.x) / (p1.y - p0.y);
+            let stride = self.width + 2;
+            let mut x = p0.x;
+            if p0.y < 0.0 {
+                x -= p0.y * dxdy;
+            }
+            let (first, last) = (floor(p0.y).max(0) as usize, (ceil(p1.y).max(0) as usize).min(self.height));
+            for y in first..last {
+                let row = y * stride;
+                let dy = ((y + 1) as f32).min(p1.y) - (y as f32).max(p0.y);
+                let next = x + dxdy * dy;
+                let d = dy * direction;
+                // Rounding can step a hair outside the row
+                let (x0, x1) = if x < next { (x, next) } else { (next, x) };
+                let (x0, x1) = (x0.max(0.0), x1.min(self.width as f32));
+                let (x0i, x1i) = (floor(x0), ceil(x1));
+                let x0floor = x0i as f32;
+                let start = row + x0i as usize;
+                if x1i <= x0i + 1 {
+                    let middle = 0.5 * (x + next) - x0floor;
+                    self.cells[start] += d - d * middle;
+                    self.cells[start + 1] += d * middle;
+                } else {
+                    let s = 1.0 / (x1 - x0);
+                    let x0f = x0 - x0floor;
+                    let a0 = 0.5 * s * (1.0 - x0f) * (1.0 - x0f);
+                    let x1f = x1 - x1i as f32 + 1.0;
+                    let am = 0.5 * s * x1f * x1f;
+                    self.cells[start] += d * a0;
+                    if x1i == x0i + 2 {
+                        self.cells[start + 1] += d * (1.0 - a0 - am);
+                    } else {
+                        let a1 = s * (1.5 - x0f);
+                        self.cells[start + 1] += d * (a1 - a0);
+                        for xi in x0i + 2..x1i - 1 {
+                            self.cells[row + xi as usize] += d * s;
+                        }
+                        let a2 = a1 + (x1i - x0i - 3) as f32 * s;
+                        self.cells[row + (x1i - 1) as usize] += d * (1.0 - a2 - am);
+                    }
+                    self.cells[row + x1i as usize] += d * am;
+                }
+                x = next;
+            }
+        }
+
+        // Calls back with each covered pixel's coverage, 1-255
+        fn coverage(&self, mut visit: impl FnMut(usize, usize, u32)) {
+            let stride = self.width + 2;
+            for y in 0..self.height {
+                let mut winding = 0.0;
+                for x in 0..self.width {
+                    winding += self.cells[y * stride + x];
+                    let coverage = (winding.abs().min(1.0) * 255.0 + 0.5) as u32;
+                    if coverage > 0 {
+                        visit(x, y, coverage);
+                    }
+                }
+            }
+        }
+    }
+
+    // Draws into a borrowed pixel buffer, clipped to a rectangle that
+    // save() and restore() scope
+    pub struct Canvas<'a> {
+        pixels: &'a mut [u32],
+        width: u32,
+        height: u32,
+        // None once clipped away entirely
+        clip: Option<Rect>,
+        saved: Vec<Option<Rect>>,
+    }
+
+    impl<'a> Canvas<'a> {
+        pub fn new(pixels: &'a mut [u32], width: u32, height: u32) -> Result<Self, &'static str> {
+            if pixels.len() < (width * height) as usize {
+                return Err("Pixel buffer smaller than the canvas");
+            }
+            Ok(Canvas {
+                pixels,
+                width,
+                height,
+                clip: Some(Rect::new(0, 0, width, height)),
+                saved: Vec::new(),
+            })
+        }
+
+        pub fn width(&self) -> u32 {
+            self.width
+        }
+
+        pub fn height(&self) -> u32 {
+            self.height
+        }
+
+        pub fn clip(&self) -> Option<Rect> {
+            self.clip
+        }
+
+        pub fn save(&mut self) {
+            self.saved.push(self.clip);
+        }
+
+        pub fn restore(&mut self) {
+            if let Some(clip) = self.saved.pop() {
+                self.clip = clip;
+            }
+        }
+
+        // Narrows the clip; only restore() widens it again
+        pub fn clip_rect(&mut self, rect: Rect) {
+            self.clip = self.clip.and_then(|clip| clip.intersect(&rect));
+        }
+
+        pub fn pixel(&self, x: u32, y: u32) -> Option<u32> {
+            (x < self.width && y < self.height).then(|| self.pixels[(y * self.width + x) as usize])
+        }
+
+        fn blend(&mut self, x: i32, y: i32, color: u32, coverage: u32) {
+            let pixel = &mut self.pixels[(y as u32 * self.width + x as u32) as usize];
+            let source = if coverage >= 255 { color } else { scale(color, coverage) };
+            *pixel = over(source, *pixel);
+        }
+
+        // Replaces the clipped area, alpha included
+        pub fn clear(&mut self, color: Color) {
+            let Some(clip) = self.clip else {
+                return;
+            };
+            let color = color.premultiplied();
+            for y in clip.y..clip.y + clip.height as i32 {
+                let row = (y as u32 * self.width) as usize;
+                self.pixels[row + clip.x as usize..row + clip.x as usize + clip.width as usize].fill(color);
+            }
+        }
+
+        pub fn fill_rect(&mut self, rect: Rect, paint: &Paint) {
+            let Some(area) = self.clip.and_then(|clip| clip.intersect(&rect)) else {
+                return;
+            };
+            for y in area.y..area.y + area.height as i32 {
+                for x in area.x..area.x + area.width as i32 {
+                    let color = paint.sample(x, y);
+                    self.blend(x, y, color, 255);
+                }
+            }
+        }
+
+        // The stroke runs inside the rectangle
+        pub fn stroke_rect(&mut self, rect: Rect, width: u32, paint: &Paint) {
+            let width = width.min(rect.width / 2).min(rect.height / 2).max(1);
+            let (right, bottom) = (rect.x + (rect.width - width) as i32, rect.y + (rect.height - width) as i32);
+            let inner = rect.height.saturating_sub(2 * width);
+            self.fill_rect(Rect::new(rect.x, rect.y, rect.width, width), paint);
+            self.fill_rect(Rect::new(rect.x, bottom, rect.width, width), paint);
+            self.fill_rect(Rect::new(rect.x, rect.y + width as i32, width, inner), paint);
+            self.fill_rect(Rect::new(right, rect.y + width as i32, width, inner), paint);
+        }
+
+        // Antialiased nonzero fill; open contours are closed implicitly
+        pub fn fill_path(&mut self, path: &Path, paint: &Paint) {
+            let Some(area) = path
+                .bounds()
+                .and_then(|bounds| self.clip.and_then(|clip| clip.intersect(&bounds)))
+            else {
+                return;
+            };
+            let mut rasterizer = Rasterizer::new(area);
+            for (points, _) in &path.contours {
+                for index in 0..points.len() {
+                    rasterizer.add_line(points[index], points[(index + 1) % points.len()]);
+                }
+            }
+            rasterizer.coverage(|x, y, coverage| {
+                let (x, y) = (area.x + x as i32, area.y + y as i32);
+                let color = paint.sample(x, y);
+                self.blend(x, y, color, coverage);
+            });
+        }
+
+        // Round joins and caps once the line is at least two pixels wide
+        pub fn stroke_path(&mut self, path: &Path, width: f32, paint: &Paint) {
+            if width > 0.0 {
+                self.fill_path(&path.stroke(width), paint);
+            }
+        }
+
+        // Paints through the 8-bit coverage in `area` of a mask `stride`
+        // bytes wide, placing the area's corner at (x, y); glyphs use this
+        pub fn fill_mask(&mut self, mask: &[u8], stride: u32, area: Rect, x: i32, y: i32, paint: &Paint) {
+            let Some(target) = self
+                .clip
+                .and_then(|clip| clip.intersect(&Rect::new(x, y, area.width, area.height)))
+            else {
+                return;
+            };
+            for row in target.y..target.y + target.height as i32 {
+                let source = ((area.y + row - y) as u32 * stride) as usize + area.x as usize;
+                for column in target.x..target.x + target.width as i32 {
+                    let coverage = mask[source + (column - x) as usize] as u32;
+                    if coverage > 0 {
+                        let color = paint.sample(column, row);
+                        self.blend(column, row, color, coverage);
+                    }
+                }
+            }
+        }
+
+        // Composites the image with its top-left corner at (x, y); opacity
+        // scales its alpha
+        pub fn draw_image(&mut self, image: &Image, x: i32, y: i32, opacity: u8) {
+            let Some(area) = self
+                .clip
+                .and_then(|clip| clip.intersect(&Rect::new(x, y, image.width, image.height)))
+            else {
+                return;
+            };
+            for row in area.y..area.y + area.height as i32 {
+                let source = ((row - y) as u32 * image.width) as usize;
+                for column in area.x..area.x + area.width as i32 {
+                    let color = image.pixels[source + (column - x) as usize];
+                    self.blend(column, row, color, opacity as u32);
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,183 @@
+// src/graphics/vxcursor.rs
+
+pub mod vxcursor {
+    use crate::vegagx::vegagx::{Color, Paint, Path};
+    use crate::vxtheme::vxtheme::CursorStyle;
+    use crate::vxwin::vxwin::{Buffer, BufferStorage, CursorImage};
+    use crate::vxwm::vxwm::{EDGE_BOTTOM, EDGE_LEFT, EDGE_RIGHT, EDGE_TOP};
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub enum CursorShape {
+        Default,
+        Text,
+        Pointer,
+        Move,
+        ResizeHorizontal,
+        ResizeVertical,
+        // Top-left to bottom-right
+        ResizeDiagonal,
+        // Top-right to bottom-left
+        ResizeAntiDiagonal,
+        Wait,
+        Crosshair,
+        NotAllowed,
+        Hidden,
+    }
+
+    impl CursorShape {
+        pub const ALL: [CursorShape; 12] = [
+            CursorShape::Default,
+            CursorShape::Text,
+            CursorShape::Pointer,
+            CursorShape::Move,
+            CursorShape::ResizeHorizontal,
+            CursorShape::ResizeVertical,
+            CursorShape::ResizeDiagonal,
+            CursorShape::ResizeAntiDiagonal,
+            CursorShape::Wait,
+            CursorShape::Crosshair,
+            CursorShape::NotAllowed,
+            CursorShape::Hidden,
+        ];
+
+        // As in CSS
+        pub fn name(&self) -> &'static str {
+            match self {
+                CursorShape::Default => "default",
+                CursorShape::Text => "text",
+                CursorShape::Pointer => "pointer",
+                CursorShape::Move => "move",
+                CursorShape::ResizeHorizontal => "ew-resize",
+                CursorShape::ResizeVertical => "ns-resize",
+                CursorShape::ResizeDiagonal => "nwse-resize",
+                CursorShape::ResizeAntiDiagonal => "nesw-resize",
+                CursorShape::Wait => "wait",
+                CursorShape::Crosshair => "crosshair",
+                CursorShape::NotAllowed => "not-allowed",
+                CursorShape::Hidden => "none",
+            }
+        }
+
+        pub fn parse(name: &str) -> Result<Self, &'static str> {
+            Self::ALL.into_iter().find(|shape| shape.name() == name).ok_or("Unknown cursor shape")
+        }
+
+        // For resizing by the given EDGE_* bits
+        pub fn for_edges(edges: u8) -> Self {
+            let horizontal = edges & (EDGE_LEFT | EDGE_RIGHT) != 0;
+            let vertical = edges & (EDGE_TOP | EDGE_BOTTOM) != 0;
+            let falling = edges & (EDGE_LEFT | EDGE_TOP) == EDGE_LEFT | EDGE_TOP
+                || edges & (EDGE_RIGHT | EDGE_BOTTOM) == EDGE_RIGHT | EDGE_BOTTOM;
+            match (horizontal, vertical) {
+                (true, true) if falling => CursorShape::ResizeDiagonal,
+                (true, true) => CursorShape::ResizeAntiDiagonal,
+                (true, false) => CursorShape::ResizeHorizontal,
+                (false, true) => CursorShape::ResizeVertical,
+                (false, false) => CursorShape::Default,
+            }
+        }
+    }
+
+    // Polygons in a unit square, rotated about its centre by `angle`
+    // radians and scaled to `size`
+    fn polygon(points: &[(f32, f32)], angle: f32, size: f32) -> Path {
+        let (sin, cos) = angle.sin_cos();
+        let mut path = Path::new();
+        for (index, (x, y)) in points.iter().enumerate() {
+            let (x, y) = (x - 0.5, y - 0.5);
+            let (x, y) = ((x * cos - y * sin + 0.5) * size, (x * sin + y * cos + 0.5) * size);
+            if index == 0 {
+                path.move_to(x, y);
+            } else {
+                path.line_to(x, y);
+            }
+        }
+        path.close();
+        path
+    }
+
+    // A horizontal double-headed arrow across the square
+    const DOUBLE_ARROW: [(f32, f32); 10] = [
+        (0.08, 0.5),
+        (0.3, 0.28),
+        (0.3, 0.43),
+        (0.7, 0.43),
+        (0.7, 0.28),
+        (0.92, 0.5),
+        (0.7, 0.72),
+        (0.7, 0.57),
+        (0.3, 0.57),
+        (0.3, 0.72),
+    ];
+
+    // Parts filled one by one, so overlaps do not cancel out, and the
+    // hotspot as a fraction of the size
+    fn outline(shape: CursorShape, size: f32) -> (Vec<Path>, (f32, f32)) {
+        use std::f32::consts::{FRAC_PI_2, FRAC_PI_4};
+        let centre = (0.5, 0.5);
+        let scaled = |x: f32, y: f32, width: f32, height: f32| Path::rect(x * size, y * size, width * size, height * size);
+        match shape {
+            CursorShape::Default | CursorShape::Hidden => {
+                let arrow = [(0.2, 0.05), (0.2, 0.8), (0.37, 0.64), (0.5, 0.93), (0.6, 0.89), (0.47, 0.6), (0.7, 0.6)];
+                (vec![polygon(&arrow, 0.0, size)], (0.2, 0.05))
+            }
+            CursorShape::Text => (
+                vec![scaled(0.45, 0.12, 0.1, 0.76), scaled(0.3, 0.08, 0.4, 0.08), scaled(0.3, 0.84, 0.4, 0.08)],
+                centre,
+            ),
+            CursorShape::Pointer => (
+                vec![
+                    Path::rounded_rect(0.34 * size, 0.05 * size, 0.16 * size, 0.55 * size, 0.08 * size),
+                    Path::rounded_rect(0.22 * size, 0.42 * size, 0.56 * size, 0.52 * size, 0.14 * size),
+                ],
+                (0.42, 0.05),
+            ),
+            CursorShape::Move => (
+                vec![polygon(&DOUBLE_ARROW, 0.0, size), polygon(&DOUBLE_ARROW, FRAC_PI_2, size)],
+                centre,
+            ),
+            CursorShape::ResizeHorizontal => (vec![polygon(&DOUBLE_ARROW, 0.0, size)], centre),
+            CursorShape::ResizeVertical => (vec![polygon(&DOUBLE_ARROW, FRAC_PI_2, size)], centre),
+            CursorShape::ResizeDiagonal => (vec![polygon(&DOUBLE_ARROW, FRAC_PI_4, size)], centre),
+            CursorShape::ResizeAntiDiagonal => (vec![polygon(&DOUBLE_ARROW, -FRAC_PI_4, size)], centre),
+            CursorShape::Wait => (
+                vec![Path::circle(0.5 * size, 0.5 * size, 0.4 * size), scaled(0.47, 0.2, 0.06, 0.32), scaled(0.47, 0.47, 0.24, 0.06)],
+                centre,
+            ),
+            CursorShape::Crosshair => (vec![scaled(0.46, 0.05, 0.08, 0.9), scaled(0.05, 0.46, 0.9, 0.08)], centre),
+            CursorShape::NotAllowed => (
+                vec![Path::circle(0.5 * size, 0.5 * size, 0.4 * size), polygon(&[(0.15, 0.45), (0.85, 0.45), (0.85, 0.55), (0.15, 0.55)], FRAC_PI_4, size)],
+                centre,
+            ),
+        }
+    }
+
+    // Draws a shape in the theme's cursor colours at its logical size;
+    // None for Hidden
+    pub fn render(shape: CursorShape, style: &CursorStyle) -> Option<CursorImage> {
+        if shape == CursorShape::Hidden {
+            return None;
+        }
+        let size = style.size.max(8);
+        let (parts, (hotspot_x, hotspot_y)) = outline(shape, size as f32);
+        let buffer = Buffer::new(size, size, BufferStorage::SharedMemory);
+        let line = (size as f32 / 12.0).max(1.0);
+        let (fill, outline) = (Paint::Solid(Color::argb(style.fill)), Paint::Solid(Color::argb(style.outline)));
+        buffer.draw(|canvas| {
+            for part in &parts {
+                canvas.stroke_path(part, line * 2.0, &outline);
+            }
+            for part in &parts {
+                canvas.fill_path(part, &fill);
+            }
+            // The marks inside the clock face and the no-entry bar
+            if matches!(shape, CursorShape::Wait | CursorShape::NotAllowed) {
+                for part in &parts[1..] {
+                    canvas.fill_path(part, &outline);
+                }
+            }
+        });
+        let hotspot = ((hotspot_x * size as f32).round() as i32, (hotspot_y * size as f32).round() as i32);
+        Some(CursorImage { buffer, hotspot })
+    }
+}
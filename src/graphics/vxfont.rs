@@ -1,21 +1,687 @@
+// src/graphics/vxfont.rs
+
 pub mod vxfont {
-    pub fn init() {
-        println!("Initializing VXFont...");
-        // Initialize the VXFont system
+    use crate::vegagx::vegagx::{Canvas, Color, Paint, Path};
+    use crate::vxwin::vxwin::Rect;
+    use vaelix_core::vxfs::vxfs::VXFS;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    // Composite glyphs nest; anything deeper is treated as a loop
+    const MAX_COMPONENT_DEPTH: usize = 8;
+
+    const ARG_1_AND_2_ARE_WORDS: u16 = 0x0001;
+    const ARGS_ARE_XY_VALUES: u16 = 0x0002;
+    const WE_HAVE_A_SCALE: u16 = 0x0008;
+    const MORE_COMPONENTS: u16 = 0x0020;
+    const WE_HAVE_AN_X_AND_Y_SCALE: u16 = 0x0040;
+    const WE_HAVE_A_TWO_BY_TWO: u16 = 0x0080;
+
+    fn read_u8(data: &[u8], offset: usize) -> Result<u8, &'static str> {
+        data.get(offset).copied().ok_or("Truncated font")
+    }
+
+    fn read_u16(data: &[u8], offset: usize) -> Result<u16, &'static str> {
+        let bytes = data.get(offset..offset + 2).ok_or("Truncated font")?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn read_i16(data: &[u8], offset: usize) -> Result<i16, &'static str> {
+        read_u16(data, offset).map(|value| value as i16)
+    }
+
+    fn read_u32(data: &[u8], offset: usize) -> Result<u32, &'static str> {
+        let bytes = data.get(offset..offset + 4).ok_or("Truncated font")?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    // 2.14 fixed point, as used by composite glyph scales
+    fn read_f2dot14(data: &[u8], offset: usize) -> Result<f32, &'static str> {
+        read_i16(data, offset).map(|value| value as f32 / 16384.0)
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum CharMap {
+        Segments(usize),
+        Groups(usize),
+    }
+
+    // Points in font units; on-curve or quadratic control points
+    type OutlinePoint = (f32, f32, bool);
+    type Contour = Vec<OutlinePoint>;
+
+    // A TrueType (glyf-outline) font. Tables are read in place from the
+    // file data; only kerning is unpacked up front.
+    pub struct Font {
+        data: Vec<u8>,
+        units_per_em: u16,
+        ascender: i16,
+        descender: i16,
+        line_gap: i16,
+        glyph_count: u16,
+        long_loca: bool,
+        loca: usize,
+        glyf: usize,
+        hmtx: usize,
+        metrics_count: u16,
+        cmap: CharMap,
+        kerning: HashMap<(u16, u16), i16>,
+    }
+
+    impl Font {
+        fn table(data: &[u8], tag: &[u8; 4]) -> Result<Option<usize>, &'static str> {
+            let count = read_u16(data, 4)? as usize;
+            for index in 0..count {
+                let record = 12 + index * 16;
+                if data.get(record..record + 4).ok_or("Truncated font")? != tag {
+                    continue;
+                }
+                let (offset, length) = (read_u32(data, record + 8)? as usize, read_u32(data, record + 12)? as usize);
+                if offset + length > data.len() {
+                    return Err("Font table out of bounds");
+                }
+                return Ok(Some(offset));
+            }
+            Ok(None)
+        }
+
+        fn required(data: &[u8], tag: &[u8; 4]) -> Result<usize, &'static str> {
+            Self::table(data, tag)?.ok_or("Missing required font table")
+        }
+
+        // Prefers a full-Unicode format 12 subtable over the BMP-only
+        // format 4 one
+        fn find_cmap(data: &[u8], cmap: usize) -> Result<CharMap, &'static str> {
+            let mut best = None;
+            for index in 0..read_u16(data, cmap + 2)? as usize {
+                let record = cmap + 4 + index * 8;
+                let (platform, encoding) = (read_u16(data, record)?, read_u16(data, record + 2)?);
+                let offset = cmap + read_u32(data, record + 4)? as usize;
+                let unicode = platform == 0 || (platform == 3 && (encoding == 1 || encoding == 10));
+                match read_u16(data, offset)? {
+                    12 if unicode => return Ok(CharMap::Groups(offset)),
+                    4 if unicode => best = Some(CharMap::Segments(offset)),
+                    _ => {}
+                }
+            }
+            best.ok_or("No Unicode character map")
+        }
+
+        // Horizontal format 0 pairs from the old-style kern table
+        fn read_kerning(data: &[u8], kern: usize) -> Result<HashMap<(u16, u16), i16>, &'static str> {
+            let mut pairs = HashMap::new();
+            let mut subtable = kern + 4;
+            for _ in 0..read_u16(data, kern + 2)? {
+                let (length, coverage) = (read_u16(data, subtable + 2)? as usize, read_u16(data, subtable + 4)?);
+                if coverage >> 8 == 0 && coverage & 1 != 0 {
+                    for pair in 0..read_u16(data, subtable + 6)? as usize {
+                        let entry = subtable + 14 + pair * 6;
+                        pairs.insert(
+                            (read_u16(data, entry)?, read_u16(data, entry + 2)?),
+                            read_i16(data, entry + 4)?,
+                        );
+                    }
+                }
+                subtable += length;
+            }
+            Ok(pairs)
+        }
+
+        pub fn parse(data: Vec<u8>) -> Result<Self, &'static str> {
+            match read_u32(&data, 0)? {
+                0x0001_0000 | 0x7472_7565 => {}
+                0x4F54_544F => return Err("CFF outlines are not supported"),
+                _ => return Err("Not a TrueType font"),
+            }
+            let head = Self::required(&data, b"head")?;
+            let hhea = Self::required(&data, b"hhea")?;
+            let cmap = Self::required(&data, b"cmap")?;
+            let kerning = match Self::table(&data, b"kern")? {
+                Some(kern) => Self::read_kerning(&data, kern)?,
+                None => HashMap::new(),
+            };
+            let font = Font {
+                units_per_em: read_u16(&data, head + 18)?.max(1),
+                long_loca: read_i16(&data, head + 50)? != 0,
+                ascender: read_i16(&data, hhea + 4)?,
+                descender: read_i16(&data, hhea + 6)?,
+                line_gap: read_i16(&data, hhea + 8)?,
+                metrics_count: read_u16(&data, hhea + 34)?.max(1),
+                glyph_count: read_u16(&data, Self::required(&data, b"maxp")? + 4)?,
+                loca: Self::required(&data, b"loca")?,
+                glyf: Self::required(&data, b"glyf")?,
+                hmtx: Self::required(&data, b"hmtx")?,
+                cmap: Self::find_cmap(&data, cmap)?,
+                kerning,
+                data,
+            };
+            Ok(font)
+        }
+
+        pub fn load(path: &str) -> Result<Self, &'static str> {
+            let data = VXFS::new().read_bytes(path).map_err(|_| "Failed to read font")?;
+            Self::parse(data)
+        }
+
+        pub fn units_per_em(&self) -> u16 {
+            self.units_per_em
+        }
+
+        pub fn glyph_count(&self) -> u16 {
+            self.glyph_count
+        }
+
+        fn scale(&self, size: u32) -> f32 {
+            size as f32 / self.units_per_em as f32
+        }
+
+        // Distances from the baseline in pixels at a size in pixels per em
+        pub fn ascent(&self, size: u32) -> f32 {
+            self.ascender as f32 * self.scale(size)
+        }
+
+        pub fn descent(&self, size: u32) -> f32 {
+            -(self.descender as f32) * self.scale(size)
+        }
+
+        pub fn line_height(&self, size: u32) -> f32 {
+            (self.ascender as f32 - self.descender as f32 + self.line_gap as f32) * self.scale(size)
+        }
+
+        // Glyph 0 (.notdef) for characters the font lacks
+        pub fn glyph_index(&self, character: char) -> u16 {
+            self.lookup(character as u32).unwrap_or(0)
+        }
+
+        fn lookup(&self, code: u32) -> Result<u16, &'static str> {
+            let data = &self.data;
+            match self.cmap {
+                CharMap::Segments(table) => {
+                    if code > 0xFFFF {
+                        return Ok(0);
+                    }
+                    let segments = read_u16(data, table + 6)? as usize / 2;
+                    let ends = table + 14;
+                    let starts = ends + segments * 2 + 2;
+                    let deltas = starts + segments * 2;
+                    let ranges = deltas + segments * 2;
+                    for segment in 0..segments {
+                        if (read_u16(data, ends + segment * 2)? as u32) < code {
+                            continue;
+                        }
+                        let start = read_u16(data, starts + segment * 2)? as u32;
+                        if start > code {
+                            return Ok(0);
+                        }
+                        let delta = read_u16(data, deltas + segment * 2)?;
+                        let range = read_u16(data, ranges + segment * 2)? as usize;
+                        if range == 0 {
+                            return Ok((code as u16).wrapping_add(delta));
+                        }
+                        let glyph = read_u16(data, ranges + segment * 2 + range + (code - start) as usize * 2)?;
+                        return Ok(if glyph == 0 { 0 } else { glyph.wrapping_add(delta) });
+                    }
+                    Ok(0)
+                }
+                CharMap::Groups(table) => {
+                    let (mut low, mut high) = (0, read_u32(data, table + 12)? as usize);
+                    while low < high {
+                        let middle = (low + high) / 2;
+                        let group = table + 16 + middle * 12;
+                        let (start, end) = (read_u32(data, group)?, read_u32(data, group + 4)?);
+                        if code < start {
+                            high = middle;
+                        } else if code > end {
+                            low = middle + 1;
+                        } else {
+                            return Ok((read_u32(data, group + 8)? + code - start) as u16);
+                        }
+                    }
+                    Ok(0)
+                }
+            }
+        }
+
+        // Horizontal advance in font units
+        pub fn advance_units(&self, glyph: u16) -> u16 {
+            let index = glyph.min(self.metrics_count - 1) as usize;
+            read_u16(&self.data, self.hmtx + index * 4).unwrap_or(0)
+        }
+
+        pub fn advance(&self, glyph: u16, size: u32) -> f32 {
+            self.advance_units(glyph) as f32 * self.scale(size)
+        }
+
+        // Adjustment in font units between a logically ordered pair
+        pub fn kerning(&self, left: u16, right: u16) -> i16 {
+            self.kerning.get(&(left, right)).copied().unwrap_or(0)
+        }
+
+        fn glyph_range(&self, glyph: u16) -> Result<(usize, usize), &'static str> {
+            if glyph >= self.glyph_count {
+                return Err("Glyph index out of range");
+            }
+            let offset = |index: usize| -> Result<usize, &'static str> {
+                Ok(if self.long_loca {
+                    read_u32(&self.data, self.loca + index * 4)? as usize
+                } else {
+                    read_u16(&self.data, self.loca + index * 2)? as usize * 2
+                })
+            };
+            Ok((self.glyf + offset(glyph as usize)?, self.glyf + offset(glyph as usize + 1)?))
+        }
+
+        fn simple_contours(&self, glyph: usize, count: usize) -> Result<Vec<Contour>, &'static str> {
+            let data = &self.data;
+            let mut ends = Vec::with_capacity(count);
+            for index in 0..count {
+                ends.push(read_u16(data, glyph + 10 + index * 2)? as usize);
+            }
+            let points = ends.last().map_or(0, |end| end + 1);
+            let mut position = glyph + 12 + count * 2 + read_u16(data, glyph + 10 + count * 2)? as usize;
+            let mut flags = Vec::with_capacity(points);
+            while flags.len() < points {
+                let flag = read_u8(data, position)?;
+                position += 1;
+                flags.push(flag);
+                if flag & 0x08 != 0 {
+                    for _ in 0..read_u8(data, position)? {
+                        flags.push(flag);
+                    }
+                    position += 1;
+                }
+            }
+            // X then Y deltas; short deltas carry their sign in the flag,
+            // otherwise the flag marks a repeat of the previous value
+            let mut coordinates = [vec![0i32; points], vec![0i32; points]];
+            for (axis, (short, same)) in [(0x02, 0x10), (0x04, 0x20)].into_iter().enumerate() {
+                let mut value = 0i32;
+                for (index, flag) in flags.iter().take(points).enumerate() {
+                    if flag & short != 0 {
+                        let delta = read_u8(data, position)? as i32;
+                        position += 1;
+                        value += if flag & same != 0 { delta } else { -delta };
+                    } else if flag & same == 0 {
+                        value += read_i16(data, position)? as i32;
+                        position += 2;
+                    }
+                    coordinates[axis][index] = value;
+                }
+            }
+            let mut contours = Vec::with_capacity(count);
+            let mut start = 0;
+            for end in ends {
+                if end < start || end >= points {
+                    return Err("Malformed glyph contours");
+                }
+                contours.push(
+                    (start..=end)
+                        .map(|index| (coordinates[0][index] as f32, coordinates[1][index] as f32, flags[index] & 1 != 0))
+                        .collect(),
+                );
+                start = end + 1;
+            }
+            Ok(contours)
+        }
+
+        fn composite_contours(&self, glyph: usize, depth: usize) -> Result<Vec<Contour>, &'static str> {
+            let data = &self.data;
+            let mut contours = Vec::new();
+            let mut position = glyph + 10;
+            loop {
+                let (flags, component) = (read_u16(data, position)?, read_u16(data, position + 2)?);
+                position += 4;
+                let (dx, dy) = if flags & ARG_1_AND_2_ARE_WORDS != 0 {
+                    position += 4;
+                    (read_i16(data, position - 4)? as f32, read_i16(data, position - 2)? as f32)
+                } else {
+                    position += 2;
+                    (read_u8(data, position - 2)? as i8 as f32, read_u8(data, position - 1)? as i8 as f32)
+                };
+                // Anchor-point matching is not supported; such components
+                // are placed without an offset
+                let (dx, dy) = if flags & ARGS_ARE_XY_VALUES != 0 { (dx, dy) } else { (0.0, 0.0) };
+                let (mut a, mut b, mut c, mut d) = (1.0, 0.0, 0.0, 1.0);
+                if flags & WE_HAVE_A_SCALE != 0 {
+                    a = read_f2dot14(data, position)?;
+                    d = a;
+                    position += 2;
+                } else if flags & WE_HAVE_AN_X_AND_Y_SCALE != 0 {
+                    (a, d) = (read_f2dot14(data, position)?, read_f2dot14(data, position + 2)?);
+                    position += 4;
+                } else if flags & WE_HAVE_A_TWO_BY_TWO != 0 {
+                    (a, b) = (read_f2dot14(data, position)?, read_f2dot14(data, position + 2)?);
+                    (c, d) = (read_f2dot14(data, position + 4)?, read_f2dot14(data, position + 6)?);
+                    position += 8;
+                }
+                for contour in self.contours(component, depth + 1)? {
+                    contours.push(
+                        contour
+                            .into_iter()
+                            .map(|(x, y, on)| (a * x + c * y + dx, b * x + d * y + dy, on))
+                            .collect(),
+                    );
+                }
+                if flags & MORE_COMPONENTS == 0 {
+                    return Ok(contours);
+                }
+            }
+        }
+
+        fn contours(&self, glyph: u16, depth: usize) -> Result<Vec<Contour>, &'static str> {
+            if depth > MAX_COMPONENT_DEPTH {
+                return Err("Composite glyphs nested too deeply");
+            }
+            let (start, end) = self.glyph_range(glyph)?;
+            if end <= start {
+                return Ok(Vec::new());
+            }
+            match read_i16(&self.data, start)? {
+                count if count >= 0 => self.simple_contours(start, count as usize),
+                _ => self.composite_contours(start, depth),
+            }
+        }
+
+        // Adds one contour, scaled and flipped so y grows downward. Two
+        // control points in a row imply an on-curve point between them.
+        fn trace(path: &mut Path, contour: &Contour, transform: impl Fn(f32, f32) -> (f32, f32)) {
+            let Some(&first) = contour.first() else {
+                return;
+            };
+            let last = contour[contour.len() - 1];
+            let middle = |a: (f32, f32), b: (f32, f32)| ((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0);
+            let (start, rest): ((f32, f32), &[OutlinePoint]) = if first.2 {
+                ((first.0, first.1), &contour[1..])
+            } else if last.2 {
+                ((last.0, last.1), &contour[..contour.len() - 1])
+            } else {
+                (middle((first.0, first.1), (last.0, last.1)), &contour[..])
+            };
+            let (x, y) = transform(start.0, start.1);
+            path.move_to(x, y);
+            let mut control: Option<(f32, f32)> = None;
+            for &(x, y, on) in rest.iter().chain([(start.0, start.1, true)].iter()) {
+                let point = transform(x, y);
+                match (on, control) {
+                    (true, Some(c)) => {
+                        path.quad_to(c.0, c.1, point.0, point.1);
+                        control = None;
+                    }
+                    (true, None) => {
+                        path.line_to(point.0, point.1);
+                    }
+                    (false, Some(c)) => {
+                        let implied = middle(c, point);
+                        path.quad_to(c.0, c.1, implied.0, implied.1);
+                        control = Some(point);
+                    }
+                    (false, None) => control = Some(point),
+                }
+            }
+            path.close();
+        }
+
+        // Antialiased coverage for one glyph; no hinting is applied
+        pub fn rasterize(&self, glyph: u16, size: u32) -> Result<GlyphBitmap, &'static str> {
+            let contours = self.contours(glyph, 0)?;
+            let scale = self.scale(size);
+            let mut points = contours.iter().flatten();
+            let Some(&(x, y, _)) = points.next() else {
+                return Ok(GlyphBitmap::default());
+            };
+            let (mut left, mut top, mut right, mut bottom) = (x, -y, x, -y);
+            for &(x, y, _) in points {
+                (left, right) = (left.min(x), right.max(x));
+                (top, bottom) = (top.min(-y), bottom.max(-y));
+            }
+            let (left, top) = ((left * scale) as i32 - 1, (top * scale) as i32 - 1);
+            let (width, height) = ((right * scale) as i32 + 2 - left, (bottom * scale) as i32 + 2 - top);
+            let mut path = Path::new();
+            for contour in &contours {
+                Self::trace(&mut path, contour, |x, y| (x * scale - left as f32, -y * scale - top as f32));
+            }
+            let mut pixels = vec![0u32; (width * height) as usize];
+            Canvas::new(&mut pixels, width as u32, height as u32)?
+                .fill_path(&path, &Paint::Solid(Color::argb(0xFFFF_FFFF)));
+            Ok(GlyphBitmap {
+                width: width as u32,
+                height: height as u32,
+                left,
+                top,
+                coverage: pixels.into_iter().map(|pixel| (pixel >> 24) as u8).collect(),
+            })
+        }
+
+        // Lays text out left to right from x = 0 on the baseline, with
+        // right-to-left runs reordered for display
+        pub fn shape(&self, text: &str, size: u32) -> Vec<PositionedGlyph> {
+            let scale = self.scale(size);
+            let mut glyphs: Vec<PositionedGlyph> = Vec::new();
+            let mut pen = 0.0;
+            let mut previous: Option<(u16, bool)> = None;
+            for (character, rtl) in visual_order(text) {
+                let glyph = self.glyph_index(character);
+                // Kerning pairs are in logical order, which runs backwards
+                // through a reversed run
+                if let Some((before, previous_rtl)) = previous.filter(|(_, previous_rtl)| *previous_rtl == rtl) {
+                    let pair = if previous_rtl { (glyph, before) } else { (before, glyph) };
+                    pen += self.kerning(pair.0, pair.1) as f32 * scale;
+                }
+                let advance = self.advance(glyph, size);
+                glyphs.push(PositionedGlyph {
+                    glyph,
+                    character,
+                    x: pen,
+                    advance,
+                });
+                pen += advance;
+                previous = Some((glyph, rtl));
+            }
+            glyphs
+        }
+
+        pub fn measure(&self, text: &str, size: u32) -> f32 {
+            self.shape(text, size).last().map_or(0.0, |glyph| glyph.x + glyph.advance)
+        }
+    }
+
+    #[derive(Debug, Clone, Default, PartialEq, Eq)]
+    pub struct GlyphBitmap {
+        pub width: u32,
+        pub height: u32,
+        // Offsets of the top-left corner from the pen position on the
+        // baseline; top is negative above the baseline
+        pub left: i32,
+        pub top: i32,
+        pub coverage: Vec<u8>,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct PositionedGlyph {
+        pub glyph: u16,
+        pub character: char,
+        pub x: f32,
+        pub advance: f32,
+    }
+
+    // Strong direction: Some(true) for right-to-left scripts, Some(false)
+    // for other letters and digits, None for spaces and punctuation
+    fn direction(character: char) -> Option<bool> {
+        match character as u32 {
+            0x0590..=0x08FF | 0xFB1D..=0xFDFF | 0xFE70..=0xFEFF | 0x10800..=0x10FFF | 0x1E800..=0x1EFFF => Some(true),
+            _ if character.is_alphanumeric() => Some(false),
+            _ => None,
+        }
+    }
+
+    // A simplified bidi pass in place of the full Unicode algorithm: the
+    // first strong character sets the paragraph direction, neutrals
+    // between runs of one direction join them and otherwise follow the
+    // paragraph, then right-to-left runs are reversed. No mirroring.
+    pub fn visual_order(text: &str) -> Vec<(char, bool)> {
+        let characters: Vec<char> = text.chars().collect();
+        let strong: Vec<Option<bool>> = characters.iter().map(|character| direction(*character)).collect();
+        let base = strong.iter().flatten().next().copied().unwrap_or(false);
+        let mut levels = Vec::with_capacity(characters.len());
+        for index in 0..characters.len() {
+            levels.push(strong[index].unwrap_or_else(|| {
+                let before = strong[..index].iter().rev().flatten().next();
+                let after = strong[index + 1..].iter().flatten().next();
+                match (before, after) {
+                    (Some(before), Some(after)) if before == after => *before,
+                    _ => base,
+                }
+            }));
+        }
+        let mut runs: Vec<(bool, Vec<char>)> = Vec::new();
+        for (character, rtl) in characters.into_iter().zip(levels) {
+            match runs.last_mut() {
+                Some((direction, run)) if *direction == rtl => run.push(character),
+                _ => runs.push((rtl, vec![character])),
+            }
+        }
+        if base {
+            runs.reverse();
+        }
+        runs.into_iter()
+            .flat_map(|(rtl, mut run)| {
+                if rtl {
+                    run.reverse();
+                }
+                run.into_iter().map(move |character| (character, rtl))
+            })
+            .collect()
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct AtlasEntry {
+        // Where the glyph's coverage sits in the atlas
+        pub area: Rect,
+        pub left: i32,
+        pub top: i32,
+    }
+
+    // Rasterized glyphs packed onto shelves in one coverage texture. When
+    // it fills up everything is dropped and packing starts over.
+    pub struct GlyphAtlas {
+        width: u32,
+        height: u32,
+        coverage: Vec<u8>,
+        shelf_x: u32,
+        shelf_y: u32,
+        shelf_height: u32,
+        entries: HashMap<(u16, u32), AtlasEntry>,
     }
 
-    pub fn load_font(font_name: &str) {
-        println!("Loading font: {}", font_name);
-        // Load the font
+    impl GlyphAtlas {
+        pub fn new(width: u32, height: u32) -> Self {
+            GlyphAtlas {
+                width,
+                height,
+                coverage: vec![0; (width * height) as usize],
+                shelf_x: 0,
+                shelf_y: 0,
+                shelf_height: 0,
+                entries: HashMap::new(),
+            }
+        }
+
+        pub fn width(&self) -> u32 {
+            self.width
+        }
+
+        pub fn coverage(&self) -> &[u8] {
+            &self.coverage
+        }
+
+        pub fn len(&self) -> usize {
+            self.entries.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.entries.is_empty()
+        }
+
+        pub fn clear(&mut self) {
+            self.entries.clear();
+            (self.shelf_x, self.shelf_y, self.shelf_height) = (0, 0, 0);
+        }
+
+        fn allocate(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+            if self.shelf_x + width > self.width {
+                (self.shelf_x, self.shelf_y) = (0, self.shelf_y + self.shelf_height);
+                self.shelf_height = 0;
+            }
+            if width > self.width || self.shelf_y + height > self.height {
+                return None;
+            }
+            let position = (self.shelf_x, self.shelf_y);
+            self.shelf_x += width;
+            self.shelf_height = self.shelf_height.max(height);
+            Some(position)
+        }
+
+        pub fn get(&mut self, font: &Font, glyph: u16, size: u32) -> Result<AtlasEntry, &'static str> {
+            if let Some(entry) = self.entries.get(&(glyph, size)) {
+                return Ok(*entry);
+            }
+            let bitmap = font.rasterize(glyph, size)?;
+            let (x, y) = match self.allocate(bitmap.width, bitmap.height) {
+                Some(position) => position,
+                None => {
+                    self.clear();
+                    self.allocate(bitmap.width, bitmap.height).ok_or("Glyph larger than the atlas")?
+                }
+            };
+            for row in 0..bitmap.height {
+                let source = (row * bitmap.width) as usize;
+                let target = ((y + row) * self.width + x) as usize;
+                self.coverage[target..target + bitmap.width as usize]
+                    .copy_from_slice(&bitmap.coverage[source..source + bitmap.width as usize]);
+            }
+            let entry = AtlasEntry {
+                area: Rect::new(x as i32, y as i32, bitmap.width, bitmap.height),
+                left: bitmap.left,
+                top: bitmap.top,
+            };
+            self.entries.insert((glyph, size), entry);
+            Ok(entry)
+        }
     }
 
-    pub fn render_text(text: &str, font_name: &str) {
-        println!("Rendering text '{}' with font '{}'", text, font_name);
-        // Render the text
+    // A font with its glyph cache, shareable between the toolkit,
+    // terminal and notifications
+    pub struct TextRenderer {
+        font: Font,
+        atlas: Mutex<GlyphAtlas>,
     }
 
-    pub fn update() {
-        println!("Updating VXFont...");
-        // Update the VXFont system
+    impl TextRenderer {
+        pub fn new(font: Font) -> Self {
+            TextRenderer {
+                font,
+                atlas: Mutex::new(GlyphAtlas::new(512, 512)),
+            }
+        }
+
+        pub fn font(&self) -> &Font {
+            &self.font
+        }
+
+        // Draws with the baseline starting at (x, y); returns the width
+        pub fn draw(&self, canvas: &mut Canvas, text: &str, size: u32, x: f32, y: f32, paint: &Paint) -> Result<f32, &'static str> {
+            let glyphs = self.font.shape(text, size);
+            let mut atlas = self.atlas.lock().unwrap();
+            for glyph in &glyphs {
+                let entry = atlas.get(&self.font, glyph.glyph, size)?;
+                if entry.area.is_empty() {
+                    continue;
+                }
+                let origin = ((x + glyph.x + 0.5) as i32, (y + 0.5) as i32);
+                let width = atlas.width();
+                canvas.fill_mask(atlas.coverage(), width, entry.area, origin.0 + entry.left, origin.1 + entry.top, paint);
+            }
+            Ok(glyphs.last().map_or(0.0, |glyph| glyph.x + glyph.advance))
+        }
     }
 }
@@ -0,0 +1,271 @@
+// src/graphics/vxoutput.rs
+
+pub mod vxoutput {
+    use crate::vxwin::vxwin::{Compositor, Display, FrameStats, Mode, Output, Rect, Viewport};
+    use crate::vxwm::vxwm::WindowManager;
+    use std::collections::BTreeMap;
+    use std::sync::mpsc::{self, Receiver, Sender};
+
+    // Queued by the display driver's hotplug interrupt handler
+    pub enum Hotplug {
+        Connected(Box<dyn Output>),
+        Disconnected(String),
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum LayoutMode {
+        // Side by side, each output showing its own part of the desktop
+        Extend,
+        // Every output showing the same desktop at a mode they all support
+        Mirror,
+    }
+
+    // None picks the preferred mode, places the output to the right of
+    // the ones before it, or takes the scale from the EDID
+    #[derive(Debug, Clone, Copy, Default, PartialEq)]
+    pub struct OutputConfig {
+        pub mode: Option<Mode>,
+        pub position: Option<(i32, i32)>,
+        pub scale: Option<f32>,
+    }
+
+    // Scales are picked in these steps
+    pub const SCALE_STEP: f32 = 0.25;
+    pub const MAX_SCALE: f32 = 3.0;
+    // What a scale of 1 is meant for
+    const REFERENCE_DPI: f32 = 96.0;
+
+    // Width and height of the image in millimetres, from the first
+    // detailed timing descriptor, or the centimetre fields of the basic
+    // parameters when there is none. Projectors and TVs report 0.
+    pub fn physical_size(edid: &[u8]) -> Option<(u32, u32)> {
+        const HEADER: [u8; 8] = [0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00];
+        if edid.len() < 128 || edid[..8] != HEADER {
+            return None;
+        }
+        let timing = &edid[54..72];
+        let pixel_clock = u16::from_le_bytes([timing[0], timing[1]]);
+        let size = if pixel_clock != 0 {
+            let width = timing[12] as u32 | ((timing[14] as u32 >> 4) << 8);
+            let height = timing[13] as u32 | ((timing[14] as u32 & 0x0F) << 8);
+            (width, height)
+        } else {
+            (edid[21] as u32 * 10, edid[22] as u32 * 10)
+        };
+        (size.0 > 0 && size.1 > 0).then_some(size)
+    }
+
+    // The step closest to the panel's density, never below 1
+    pub fn detect_scale(mode: Mode, size: (u32, u32)) -> f32 {
+        let dpi = mode.width as f32 * 25.4 / size.0 as f32;
+        let scale = (dpi / REFERENCE_DPI / SCALE_STEP).round() * SCALE_STEP;
+        scale.clamp(1.0, MAX_SCALE)
+    }
+
+    struct Head {
+        output: Box<dyn Output>,
+        viewport: Viewport,
+    }
+
+    // Tracks connected outputs, sets their modes and lays them out over
+    // the desktop. The first connected output is the primary; it paces
+    // frames and takes the windows of outputs that go away.
+    pub struct OutputManager {
+        heads: Vec<Head>,
+        // Kept by name so a replugged output comes back as it was
+        configs: BTreeMap<String, OutputConfig>,
+        layout: LayoutMode,
+        hotplug: Sender<Hotplug>,
+        events: Receiver<Hotplug>,
+    }
+
+    impl OutputManager {
+        pub fn new(layout: LayoutMode) -> Self {
+            let (hotplug, events) = mpsc::channel();
+            OutputManager {
+                heads: Vec::new(),
+                configs: BTreeMap::new(),
+                layout,
+                hotplug,
+                events,
+            }
+        }
+
+        // For the driver to report connector changes from its interrupt
+        // handler; they take effect on the next process_hotplug
+        pub fn hotplug_sender(&self) -> Sender<Hotplug> {
+            self.hotplug.clone()
+        }
+
+        pub fn layout(&self) -> LayoutMode {
+            self.layout
+        }
+
+        pub fn set_layout(&mut self, layout: LayoutMode) {
+            self.layout = layout;
+        }
+
+        pub fn configure(&mut self, name: &str, config: OutputConfig) {
+            self.configs.insert(name.to_string(), config);
+        }
+
+        // Names and viewports, primary first
+        pub fn outputs(&self) -> Vec<(&str, Viewport)> {
+            self.heads.iter().map(|head| (head.output.name(), head.viewport)).collect()
+        }
+
+        pub fn viewport(&self, name: &str) -> Option<Viewport> {
+            self.heads.iter().find(|head| head.output.name() == name).map(|head| head.viewport)
+        }
+
+        pub fn connect(&mut self, output: Box<dyn Output>) -> Result<(), &'static str> {
+            if self.viewport(output.name()).is_some() {
+                return Err("Output already connected");
+            }
+            if output.modes().is_empty() {
+                return Err("Output reports no modes");
+            }
+            log::info!("Output {} connected", output.name());
+            self.heads.push(Head {
+                output,
+                viewport: Viewport::new(Rect::new(0, 0, 0, 0), 1.0),
+            });
+            Ok(())
+        }
+
+        pub fn disconnect(&mut self, name: &str) -> Result<(), &'static str> {
+            let index = self.heads.iter().position(|head| head.output.name() == name).ok_or("Output not found")?;
+            log::info!("Output {} disconnected", name);
+            self.heads.remove(index);
+            Ok(())
+        }
+
+        fn mode_for(&self, head: &Head) -> Result<Mode, &'static str> {
+            let modes = head.output.modes();
+            match self.configs.get(head.output.name()).and_then(|config| config.mode) {
+                Some(mode) if modes.contains(&mode) => Ok(mode),
+                Some(_) => Err("Output does not support the configured mode"),
+                None => Ok(modes[0]),
+            }
+        }
+
+        // The primary's mode if everyone has it, otherwise the largest
+        // common one
+        fn mirror_mode(&self) -> Result<Mode, &'static str> {
+            let primary = self.mode_for(&self.heads[0])?;
+            let shared = |mode: &Mode| self.heads.iter().all(|head| head.output.modes().contains(mode));
+            if shared(&primary) {
+                return Ok(primary);
+            }
+            self.heads[0]
+                .output
+                .modes()
+                .into_iter()
+                .filter(shared)
+                .max_by_key(|mode| (mode.width as u64 * mode.height as u64, mode.refresh))
+                .ok_or("Outputs share no mode to mirror")
+        }
+
+        fn scale_for(&self, head: &Head, mode: Mode) -> f32 {
+            let configured = self.configs.get(head.output.name()).and_then(|config| config.scale);
+            let detected = || {
+                let size = head.output.edid().and_then(|edid| physical_size(&edid))?;
+                Some(detect_scale(mode, size))
+            };
+            configured.or_else(detected).unwrap_or(1.0).clamp(1.0, MAX_SCALE)
+        }
+
+        // Picks modes and viewports without touching the hardware. The
+        // desktop is laid out in logical pixels, so a scaled output takes
+        // up less of it than its mode.
+        fn arrange(&self) -> Result<Vec<(Mode, Viewport)>, &'static str> {
+            if self.heads.is_empty() {
+                return Err("No outputs");
+            }
+            let logical = |mode: Mode, scale: f32| {
+                ((mode.width as f32 / scale).round() as u32, (mode.height as f32 / scale).round() as u32)
+            };
+            if self.layout == LayoutMode::Mirror {
+                let mode = self.mirror_mode()?;
+                let scale = self.scale_for(&self.heads[0], mode);
+                let (width, height) = logical(mode, scale);
+                let viewport = Viewport::new(Rect::new(0, 0, width, height), scale);
+                return Ok(vec![(mode, viewport); self.heads.len()]);
+            }
+            let mut arranged: Vec<(Mode, Viewport)> = Vec::new();
+            for head in &self.heads {
+                let mode = self.mode_for(head)?;
+                let scale = self.scale_for(head, mode);
+                let (width, height) = logical(mode, scale);
+                let next = arranged.iter().map(|(_, viewport)| viewport.area.right()).max().unwrap_or(0);
+                let position = self.configs.get(head.output.name()).and_then(|config| config.position);
+                let (x, y) = position.unwrap_or((next, 0));
+                arranged.push((mode, Viewport::new(Rect::new(x, y, width, height), scale)));
+            }
+            // The desktop starts at the origin whatever the configured
+            // positions
+            let left = arranged.iter().map(|(_, viewport)| viewport.area.x).min().unwrap();
+            let top = arranged.iter().map(|(_, viewport)| viewport.area.y).min().unwrap();
+            for (_, viewport) in &mut arranged {
+                viewport.area = viewport.area.translate(-left, -top);
+            }
+            Ok(arranged)
+        }
+
+        // Sets every output's mode and hands the layout to the window
+        // manager. On failure the outputs keep their previous state.
+        pub fn apply(&mut self, wm: &mut WindowManager) -> Result<(), &'static str> {
+            let arranged = self.arrange()?;
+            for (head, (mode, viewport)) in self.heads.iter_mut().zip(&arranged) {
+                if head.output.resolution() != (mode.width, mode.height) {
+                    head.output.set_mode(*mode)?;
+                }
+                head.viewport = *viewport;
+            }
+            let viewports: Vec<Viewport> = arranged.iter().map(|(_, viewport)| *viewport).collect();
+            wm.set_outputs(&viewports)
+        }
+
+        // Takes in queued hotplug events and re-lays out the desktop if
+        // anything changed; returns how many events there were. With every
+        // output gone the desktop stays as it was until one comes back.
+        pub fn process_hotplug(&mut self, wm: &mut WindowManager) -> Result<usize, &'static str> {
+            let mut count = 0;
+            while let Ok(event) = self.events.try_recv() {
+                let result = match event {
+                    Hotplug::Connected(output) => self.connect(output),
+                    Hotplug::Disconnected(name) => self.disconnect(&name),
+                };
+                // A repeated interrupt for the same connector is harmless
+                if let Err(error) = result {
+                    log::warn!("Ignoring hotplug event: {}", error);
+                }
+                count += 1;
+            }
+            if count > 0 && !self.heads.is_empty() {
+                self.apply(wm)?;
+            }
+            Ok(count)
+        }
+
+        // Moves the cursor planes to the compositor's pointer position right
+        // away, for the input path
+        pub fn update_cursor(&mut self, compositor: &mut Compositor) -> Result<(), &'static str> {
+            let mut outputs = self.displays();
+            compositor.update_cursor(&mut outputs)
+        }
+
+        fn displays(&mut self) -> Vec<(Viewport, &mut dyn Display)> {
+            self.heads
+                .iter_mut()
+                .map(|head| (head.viewport, head.output.as_mut() as &mut dyn Display))
+                .collect()
+        }
+
+        // One iteration of the compositor loop across all outputs
+        pub fn run_frame(&mut self, compositor: &mut Compositor) -> Result<Option<FrameStats>, &'static str> {
+            let mut outputs = self.displays();
+            compositor.run_frame_outputs(&mut outputs)
+        }
+    }
+}
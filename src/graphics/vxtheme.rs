@@ -1,3 +1,620 @@
+// src/graphics/vxtheme.rs
+
 pub mod vxtheme {
-    // Implementation of the vxtheme module
+    use vaelix_core::vxchan::vxchan::VXChanManager;
+    use vaelix_core::vxfs::vxfs::VXFS;
+    use std::collections::BTreeMap;
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::sync::Mutex;
+
+    pub const REQUEST_CHANNEL: &str = "vxtheme";
+    pub const REPLY_CHANNEL: &str = "vxtheme.reply";
+    // "theme NAME VARIANT" whenever either changes, including the font
+    // scale
+    pub const EVENT_CHANNEL: &str = "vxtheme.events";
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Variant {
+        Dark,
+        Light,
+        // For low vision; ignores the theme's per-class colors
+        HighContrast,
+    }
+
+    impl Variant {
+        pub fn name(&self) -> &'static str {
+            match self {
+                Variant::Dark => "dark",
+                Variant::Light => "light",
+                Variant::HighContrast => "high-contrast",
+            }
+        }
+
+        pub fn parse(name: &str) -> Result<Self, &'static str> {
+            match name {
+                "dark" => Ok(Variant::Dark),
+                "light" => Ok(Variant::Light),
+                "high-contrast" => Ok(Variant::HighContrast),
+                _ => Err("Unknown theme variant"),
+            }
+        }
+    }
+
+    // Straight ARGB; written as #RRGGBB or #AARRGGBB in theme files
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct ColorScheme {
+        pub background: u32,
+        pub surface: u32,
+        pub text: u32,
+        pub text_dim: u32,
+        pub accent: u32,
+        pub button: u32,
+        pub button_hover: u32,
+        pub button_pressed: u32,
+        pub border: u32,
+    }
+
+    impl ColorScheme {
+        pub const DARK: ColorScheme = ColorScheme {
+            background: 0xFF2B_2B2B,
+            surface: 0xFF1E_1E1E,
+            text: 0xFFE6_E6E6,
+            text_dim: 0xFF8C_8C8C,
+            accent: 0xFF3A_6EA5,
+            button: 0xFF44_4444,
+            button_hover: 0xFF50_5050,
+            button_pressed: 0xFF38_3838,
+            border: 0xFF5A_5A5A,
+        };
+
+        pub const LIGHT: ColorScheme = ColorScheme {
+            background: 0xFFF2_F2F2,
+            surface: 0xFFFF_FFFF,
+            text: 0xFF20_2020,
+            text_dim: 0xFF70_7070,
+            accent: 0xFF2F_64B0,
+            button: 0xFFE0_E0E0,
+            button_hover: 0xFFD4_D4D4,
+            button_pressed: 0xFFC4_C4C4,
+            border: 0xFFB0_B0B0,
+        };
+
+        pub const HIGH_CONTRAST: ColorScheme = ColorScheme {
+            background: 0xFF00_0000,
+            surface: 0xFF00_0000,
+            text: 0xFFFF_FFFF,
+            text_dim: 0xFFFF_FF00,
+            accent: 0xFF00_FFFF,
+            button: 0xFF00_0000,
+            button_hover: 0xFF1A_1A5C,
+            button_pressed: 0xFF00_0080,
+            border: 0xFFFF_FFFF,
+        };
+
+        fn set(&mut self, key: &str, value: u32) -> Result<(), &'static str> {
+            let field = match key {
+                "background" => &mut self.background,
+                "surface" => &mut self.surface,
+                "text" => &mut self.text,
+                "text_dim" => &mut self.text_dim,
+                "accent" => &mut self.accent,
+                "button" => &mut self.button,
+                "button_hover" => &mut self.button_hover,
+                "button_pressed" => &mut self.button_pressed,
+                "border" => &mut self.border,
+                _ => return Err("Unknown theme color"),
+            };
+            *field = value;
+            Ok(())
+        }
+
+        fn entries(&self) -> [(&'static str, u32); 9] {
+            [
+                ("background", self.background),
+                ("surface", self.surface),
+                ("text", self.text),
+                ("text_dim", self.text_dim),
+                ("accent", self.accent),
+                ("button", self.button),
+                ("button_hover", self.button_hover),
+                ("button_pressed", self.button_pressed),
+                ("border", self.border),
+            ]
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Spacing {
+        // Between a control's edge and its content
+        pub inset: u32,
+        // Default gap between a container's children
+        pub gap: u32,
+        pub radius: f32,
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Typography {
+        // Pixels per em
+        pub size: u32,
+        // Font file the session loads for the toolkit
+        pub font: Option<String>,
+        // The user's enlargement of every text size
+        pub scale: f32,
+    }
+
+    impl Typography {
+        pub const MIN_SCALE: f32 = 0.5;
+        pub const MAX_SCALE: f32 = 4.0;
+
+        // Pixels per em after scaling
+        pub fn scaled_size(&self) -> u32 {
+            ((self.size as f32 * self.scale).round() as u32).max(1)
+        }
+    }
+
+    // The pointer's look; vxcursor draws every shape from these
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct CursorStyle {
+        // Logical pixels, for the arrow's height
+        pub size: u32,
+        pub fill: u32,
+        pub outline: u32,
+    }
+
+    impl Default for CursorStyle {
+        fn default() -> Self {
+            CursorStyle {
+                size: 24,
+                fill: 0xFFFF_FFFF,
+                outline: 0xFF00_0000,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub enum WidgetClass {
+        Label,
+        Button,
+        TextInput,
+        List,
+    }
+
+    impl WidgetClass {
+        fn section(&self) -> &'static str {
+            match self {
+                WidgetClass::Label => "label",
+                WidgetClass::Button => "button",
+                WidgetClass::TextInput => "input",
+                WidgetClass::List => "list",
+            }
+        }
+    }
+
+    // Replaces parts of the resolved style; from a theme's per-class
+    // sections or set on a single widget
+    #[derive(Debug, Clone, Copy, Default, PartialEq)]
+    pub struct StyleOverride {
+        pub background: Option<u32>,
+        pub foreground: Option<u32>,
+        pub border: Option<u32>,
+        pub radius: Option<f32>,
+    }
+
+    impl StyleOverride {
+        fn apply(&self, style: &mut WidgetStyle) {
+            style.background = self.background.unwrap_or(style.background);
+            style.foreground = self.foreground.unwrap_or(style.foreground);
+            style.border = self.border.unwrap_or(style.border);
+            style.radius = self.radius.unwrap_or(style.radius);
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct WidgetState {
+        pub hovered: bool,
+        pub pressed: bool,
+        pub focused: bool,
+    }
+
+    // Everything a widget needs to paint itself
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct WidgetStyle {
+        pub background: u32,
+        pub foreground: u32,
+        // Shown when focused; otherwise the border color
+        pub border: u32,
+        pub accent: u32,
+        pub placeholder: u32,
+        pub radius: f32,
+        pub inset: u32,
+        pub text_size: u32,
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Theme {
+        pub name: String,
+        pub dark: ColorScheme,
+        pub light: ColorScheme,
+        pub high_contrast: ColorScheme,
+        // Used until the user picks one
+        pub default_variant: Variant,
+        pub spacing: Spacing,
+        pub typography: Typography,
+        pub cursor: CursorStyle,
+        pub classes: BTreeMap<WidgetClass, StyleOverride>,
+    }
+
+    impl Default for Theme {
+        fn default() -> Self {
+            Theme {
+                name: "vaelix".to_string(),
+                dark: ColorScheme::DARK,
+                light: ColorScheme::LIGHT,
+                high_contrast: ColorScheme::HIGH_CONTRAST,
+                default_variant: Variant::Dark,
+                spacing: Spacing {
+                    inset: 6,
+                    gap: 4,
+                    radius: 4.0,
+                },
+                typography: Typography { size: 14, font: None, scale: 1.0 },
+                cursor: CursorStyle::default(),
+                classes: BTreeMap::new(),
+            }
+        }
+    }
+
+    fn parse_color(value: &str) -> Result<u32, &'static str> {
+        let digits = value.strip_prefix('#').ok_or("Colors start with #")?;
+        let color = u32::from_str_radix(digits, 16).map_err(|_| "Invalid color")?;
+        match digits.len() {
+            6 => Ok(0xFF00_0000 | color),
+            8 => Ok(color),
+            _ => Err("Invalid color"),
+        }
+    }
+
+    // A font scale, clamped to the supported range
+    pub fn parse_scale(value: &str) -> Result<f32, &'static str> {
+        let scale = value.parse::<f32>().ok().filter(|scale| scale.is_finite()).ok_or("Invalid number")?;
+        Ok(scale.clamp(Typography::MIN_SCALE, Typography::MAX_SCALE))
+    }
+
+    fn format_color(color: u32) -> String {
+        if color >> 24 == 0xFF {
+            format!("#{:06X}", color & 0xFF_FFFF)
+        } else {
+            format!("#{:08X}", color)
+        }
+    }
+
+    impl Theme {
+        pub fn colors(&self, variant: Variant) -> &ColorScheme {
+            match variant {
+                Variant::Dark => &self.dark,
+                Variant::Light => &self.light,
+                Variant::HighContrast => &self.high_contrast,
+            }
+        }
+
+        // Defaults from the color scheme, then the theme's section for the
+        // class, then the widget's own override
+        pub fn resolve(&self, variant: Variant, class: WidgetClass, state: WidgetState, own: &StyleOverride) -> WidgetStyle {
+            let colors = self.colors(variant);
+            let mut style = WidgetStyle {
+                background: colors.surface,
+                foreground: colors.text,
+                border: colors.border,
+                accent: colors.accent,
+                placeholder: colors.text_dim,
+                radius: 0.0,
+                inset: self.spacing.inset,
+                text_size: self.typography.scaled_size(),
+            };
+            if class == WidgetClass::Button {
+                style.background = colors.button;
+                style.radius = self.spacing.radius;
+            }
+            if let Some(overrides) = self.classes.get(&class).filter(|_| variant != Variant::HighContrast) {
+                overrides.apply(&mut style);
+            }
+            own.apply(&mut style);
+            if class == WidgetClass::Button && state.pressed {
+                style.background = colors.button_pressed;
+            } else if class == WidgetClass::Button && state.hovered {
+                style.background = colors.button_hover;
+            }
+            if state.focused {
+                style.border = colors.accent;
+            }
+            style
+        }
+
+        // Sections: [dark], [light] and [high-contrast] colors, [spacing],
+        // [typography], [cursor], and
+        // per-class overrides in [label], [button], [input] and [list].
+        // Keys before the first section name the theme.
+        pub fn parse(contents: &str) -> Result<Self, &'static str> {
+            let mut theme = Theme::default();
+            let mut section = String::new();
+            for line in contents.lines().map(str::trim) {
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                    section = name.to_string();
+                    continue;
+                }
+                let (key, value) = line.split_once('=').ok_or("Malformed theme line")?;
+                let (key, value) = (key.trim(), value.trim());
+                let number = || value.parse::<u32>().map_err(|_| "Invalid number");
+                match (section.as_str(), key) {
+                    ("", "name") => theme.name = value.to_string(),
+                    ("", "variant") => theme.default_variant = Variant::parse(value)?,
+                    ("dark", key) => theme.dark.set(key, parse_color(value)?)?,
+                    ("light", key) => theme.light.set(key, parse_color(value)?)?,
+                    ("high-contrast", key) => theme.high_contrast.set(key, parse_color(value)?)?,
+                    ("spacing", "inset") => theme.spacing.inset = number()?,
+                    ("spacing", "gap") => theme.spacing.gap = number()?,
+                    ("spacing", "radius") => theme.spacing.radius = number()? as f32,
+                    ("typography", "size") => theme.typography.size = number()?.max(1),
+                    ("typography", "font") => theme.typography.font = Some(value.to_string()),
+                    ("typography", "scale") => theme.typography.scale = parse_scale(value)?,
+                    ("cursor", "size") => theme.cursor.size = number()?.clamp(8, 128),
+                    ("cursor", "fill") => theme.cursor.fill = parse_color(value)?,
+                    ("cursor", "outline") => theme.cursor.outline = parse_color(value)?,
+                    (class, key) => {
+                        let class = [WidgetClass::Label, WidgetClass::Button, WidgetClass::TextInput, WidgetClass::List]
+                            .into_iter()
+                            .find(|candidate| candidate.section() == class)
+                            .ok_or("Unknown theme setting")?;
+                        let overrides = theme.classes.entry(class).or_default();
+                        match key {
+                            "background" => overrides.background = Some(parse_color(value)?),
+                            "foreground" => overrides.foreground = Some(parse_color(value)?),
+                            "border" => overrides.border = Some(parse_color(value)?),
+                            "radius" => overrides.radius = Some(number()? as f32),
+                            _ => return Err("Unknown theme setting"),
+                        }
+                    }
+                }
+            }
+            Ok(theme)
+        }
+
+        pub fn serialize(&self) -> String {
+            let mut contents = format!("name={}\nvariant={}\n", self.name, self.default_variant.name());
+            for (section, colors) in [("dark", &self.dark), ("light", &self.light), ("high-contrast", &self.high_contrast)] {
+                contents += &format!("[{}]\n", section);
+                for (key, color) in colors.entries() {
+                    contents += &format!("{}={}\n", key, format_color(color));
+                }
+            }
+            contents += &format!(
+                "[spacing]\ninset={}\ngap={}\nradius={}\n[typography]\nsize={}\n",
+                self.spacing.inset, self.spacing.gap, self.spacing.radius as u32, self.typography.size
+            );
+            if let Some(font) = &self.typography.font {
+                contents += &format!("font={}\n", font);
+            }
+            if self.typography.scale != 1.0 {
+                contents += &format!("scale={}\n", self.typography.scale);
+            }
+            contents += &format!(
+                "[cursor]\nsize={}\nfill={}\noutline={}\n",
+                self.cursor.size,
+                format_color(self.cursor.fill),
+                format_color(self.cursor.outline)
+            );
+            for (class, overrides) in &self.classes {
+                contents += &format!("[{}]\n", class.section());
+                for (key, color) in [
+                    ("background", overrides.background),
+                    ("foreground", overrides.foreground),
+                    ("border", overrides.border),
+                ] {
+                    if let Some(color) = color {
+                        contents += &format!("{}={}\n", key, format_color(color));
+                    }
+                }
+                if let Some(radius) = overrides.radius {
+                    contents += &format!("radius={}\n", radius as u32);
+                }
+            }
+            contents
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct ThemeChange {
+        pub theme: Theme,
+        pub variant: Variant,
+    }
+
+    struct ThemeState {
+        theme: Theme,
+        variant: Variant,
+        // Journals the theme file's checksum so edits can be spotted
+        fs: VXFS,
+        subscribers: Vec<Sender<ThemeChange>>,
+    }
+
+    // The session's current theme. Toolkit clients subscribe and restyle
+    // when the file is edited or the variant switches.
+    pub struct ThemeManager {
+        state: Mutex<ThemeState>,
+        path: Option<String>,
+    }
+
+    impl ThemeManager {
+        pub fn new(theme: Theme) -> Self {
+            ThemeManager {
+                state: Mutex::new(ThemeState {
+                    variant: theme.default_variant,
+                    theme,
+                    fs: VXFS::new(),
+                    subscribers: Vec::new(),
+                }),
+                path: None,
+            }
+        }
+
+        pub fn load(path: &str) -> Result<Self, &'static str> {
+            let mut fs = VXFS::new();
+            let contents = fs.read_file(path).map_err(|_| "Failed to read theme")?;
+            let mut manager = ThemeManager::new(Theme::parse(&contents)?);
+            manager.state.get_mut().unwrap().fs = fs;
+            manager.path = Some(path.to_string());
+            Ok(manager)
+        }
+
+        pub fn theme(&self) -> Theme {
+            self.state.lock().unwrap().theme.clone()
+        }
+
+        pub fn variant(&self) -> Variant {
+            self.state.lock().unwrap().variant
+        }
+
+        fn notify(state: &mut ThemeState) {
+            log::info!("Applying theme {} ({})", state.theme.name, state.variant.name());
+            let change = ThemeChange {
+                theme: state.theme.clone(),
+                variant: state.variant,
+            };
+            state.subscribers.retain(|subscriber| subscriber.send(change.clone()).is_ok());
+        }
+
+        pub fn set_variant(&self, variant: Variant) {
+            let mut state = self.state.lock().unwrap();
+            if state.variant != variant {
+                state.variant = variant;
+                Self::notify(&mut state);
+            }
+        }
+
+        // Every text size is multiplied by `scale`, within the supported
+        // range
+        pub fn set_font_scale(&self, scale: f32) {
+            if !scale.is_finite() {
+                return;
+            }
+            let scale = scale.clamp(Typography::MIN_SCALE, Typography::MAX_SCALE);
+            let mut state = self.state.lock().unwrap();
+            if state.theme.typography.scale != scale {
+                state.theme.typography.scale = scale;
+                Self::notify(&mut state);
+            }
+        }
+
+        pub fn set_theme(&self, theme: Theme) {
+            let mut state = self.state.lock().unwrap();
+            if state.theme != theme {
+                state.theme = theme;
+                Self::notify(&mut state);
+            }
+        }
+
+        // Re-reads the theme file. A file that fails to parse leaves the
+        // current theme in place.
+        pub fn reload(&self) -> Result<bool, &'static str> {
+            let path = self.path.as_deref().ok_or("Theme was not loaded from a file")?;
+            let mut state = self.state.lock().unwrap();
+            let contents = state.fs.read_file(path).map_err(|_| "Failed to read theme")?;
+            let theme = Theme::parse(&contents)?;
+            if theme == state.theme {
+                return Ok(false);
+            }
+            state.theme = theme;
+            Self::notify(&mut state);
+            Ok(true)
+        }
+
+        // Hot reload: reloads only when the file's checksum changed
+        pub fn check_for_changes(&self) -> Result<bool, &'static str> {
+            let Some(path) = &self.path else {
+                return Ok(false);
+            };
+            let unchanged = self.state.lock().unwrap().fs.verify_integrity(path).unwrap_or(false);
+            if unchanged {
+                return Ok(false);
+            }
+            self.reload()
+        }
+
+        pub fn subscribe(&self) -> Receiver<ThemeChange> {
+            let (sender, receiver) = mpsc::channel();
+            self.state.lock().unwrap().subscribers.push(sender);
+            receiver
+        }
+    }
+
+    // Grammar, one request per message:
+    //   current | variant (dark|light|high-contrast) | font-scale FACTOR
+    //   reload
+    // Messages and replies are framed as in vxnetctl
+    pub struct ThemeService {
+        events: Receiver<ThemeChange>,
+    }
+
+    impl ThemeService {
+        pub fn new(manager: &VXChanManager, themes: &ThemeManager) -> Result<Self, &'static str> {
+            manager.create_channel(REQUEST_CHANNEL)?;
+            manager.create_channel(REPLY_CHANNEL)?;
+            manager.create_channel(EVENT_CHANNEL)?;
+            Ok(ThemeService {
+                events: themes.subscribe(),
+            })
+        }
+
+        fn execute(line: &str, themes: &ThemeManager) -> Result<String, &'static str> {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                ["current"] => Ok(format!("{} {}", themes.theme().name, themes.variant().name())),
+                ["variant", variant] => {
+                    themes.set_variant(Variant::parse(variant)?);
+                    Ok(String::new())
+                }
+                ["font-scale", scale] => {
+                    themes.set_font_scale(parse_scale(scale)?);
+                    Ok(String::new())
+                }
+                ["reload"] => themes.reload().map(|_| String::new()),
+                [] => Err("Empty request"),
+                _ => Err("Unknown command"),
+            }
+        }
+
+        // Picks up edits to the theme file, answers queued requests and
+        // forwards changes to the event channel; returns how many
+        // requests were handled
+        pub fn poll(&mut self, manager: &VXChanManager, themes: &ThemeManager) -> Result<usize, &'static str> {
+            if let Err(error) = themes.check_for_changes() {
+                log::warn!("Keeping the current theme: {}", error);
+            }
+            let mut count = 0;
+            while let Some(message) = manager.try_receive_message(REQUEST_CHANNEL)? {
+                let (id, line) = message.split_once(' ').unwrap_or((message.as_str(), ""));
+                let result = id
+                    .parse::<u64>()
+                    .map_err(|_| "Invalid request id")
+                    .and_then(|_| Self::execute(line, themes));
+                let reply = match result {
+                    Ok(body) if body.is_empty() => format!("{} ok", id),
+                    Ok(body) => format!("{} ok\n{}", id, body),
+                    Err(error) => format!("{} error {}", id, error),
+                };
+                manager.send_message(REPLY_CHANNEL, reply)?;
+                count += 1;
+            }
+            while let Ok(change) = self.events.try_recv() {
+                manager.send_message(EVENT_CHANNEL, format!("theme {} {}", change.theme.name, change.variant.name()))?;
+            }
+            Ok(count)
+        }
+    }
+
+    pub fn send_request(manager: &VXChanManager, id: u64, request: &str) -> Result<(), &'static str> {
+        manager.send_message(REQUEST_CHANNEL, format!("{} {}", id, request))
+    }
 }
@@ -1,3 +1,1150 @@
+// src/graphics/vxwin.rs
+
 pub mod vxwin {
-    // Implementation of the vxwin module
+    use crate::vegagx::vegagx::{Canvas, Image};
+    use std::collections::BTreeMap;
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::sync::{Arc, RwLock};
+    use std::time::Instant;
+
+    // Past this many damage rectangles a frame just repaints their bounds
+    const MAX_DAMAGE_RECTS: usize = 16;
+    // Frames kept for timing figures, a couple of seconds at 60Hz
+    pub const TIMING_HISTORY: usize = 120;
+    const BACKGROUND: u32 = 0xFF20_2020;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Rect {
+        pub x: i32,
+        pub y: i32,
+        pub width: u32,
+        pub height: u32,
+    }
+
+    impl Rect {
+        pub fn new(x: i32, y: i32, width: u32, height: u32) -> Self {
+            Rect { x, y, width, height }
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.width == 0 || self.height == 0
+        }
+
+        pub fn right(&self) -> i32 {
+            self.x + self.width as i32
+        }
+
+        pub fn bottom(&self) -> i32 {
+            self.y + self.height as i32
+        }
+
+        pub fn intersect(&self, other: &Rect) -> Option<Rect> {
+            let (x, y) = (self.x.max(other.x), self.y.max(other.y));
+            let (right, bottom) = (self.right().min(other.right()), self.bottom().min(other.bottom()));
+            (right > x && bottom > y).then(|| Rect::new(x, y, (right - x) as u32, (bottom - y) as u32))
+        }
+
+        pub fn union(&self, other: &Rect) -> Rect {
+            let (x, y) = (self.x.min(other.x), self.y.min(other.y));
+            let (right, bottom) = (self.right().max(other.right()), self.bottom().max(other.bottom()));
+            Rect::new(x, y, (right - x) as u32, (bottom - y) as u32)
+        }
+
+        pub fn translate(&self, dx: i32, dy: i32) -> Rect {
+            Rect::new(self.x + dx, self.y + dy, self.width, self.height)
+        }
+
+        pub fn contains(&self, x: i32, y: i32) -> bool {
+            (self.x..self.right()).contains(&x) && (self.y..self.bottom()).contains(&y)
+        }
+    }
+
+    // Where a client's pixels live. Both kinds are mapped into the
+    // compositor; GPU objects through the aperture.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum BufferStorage {
+        SharedMemory,
+        GpuObject { handle: u32 },
+    }
+
+    // Premultiplied ARGB8888, one u32 per pixel, rows packed
+    #[derive(Clone)]
+    pub struct Buffer {
+        width: u32,
+        height: u32,
+        storage: BufferStorage,
+        pixels: Arc<RwLock<Vec<u32>>>,
+    }
+
+    impl Buffer {
+        pub fn new(width: u32, height: u32, storage: BufferStorage) -> Self {
+            Buffer {
+                width,
+                height,
+                storage,
+                pixels: Arc::new(RwLock::new(vec![0; (width * height) as usize])),
+            }
+        }
+
+        pub fn width(&self) -> u32 {
+            self.width
+        }
+
+        pub fn height(&self) -> u32 {
+            self.height
+        }
+
+        pub fn storage(&self) -> BufferStorage {
+            self.storage
+        }
+
+        // Clients draw here, then damage and commit the surface
+        pub fn fill(&self, area: Rect, color: u32) {
+            let Some(area) = area.intersect(&Rect::new(0, 0, self.width, self.height)) else {
+                return;
+            };
+            let mut pixels = self.pixels.write().unwrap();
+            for y in area.y..area.bottom() {
+                let row = (y as u32 * self.width) as usize;
+                pixels[row + area.x as usize..row + area.right() as usize].fill(color);
+            }
+        }
+
+        // Runs a vegagx drawing pass over the pixels
+        pub fn draw<R>(&self, draw: impl FnOnce(&mut Canvas) -> R) -> R {
+            let mut pixels = self.pixels.write().unwrap();
+            draw(&mut Canvas::new(&mut pixels, self.width, self.height).unwrap())
+        }
+
+        // A copy of the pixels as they are now
+        pub fn to_image(&self) -> Image {
+            Image::new(self.width, self.height, self.pixels.read().unwrap().clone()).unwrap()
+        }
+
+        pub fn pixel(&self, x: u32, y: u32) -> Option<u32> {
+            (x < self.width && y < self.height).then(|| self.pixels.read().unwrap()[(y * self.width + x) as usize])
+        }
+    }
+
+    // The display engine the compositor scans out to; the i915 driver
+    // implements it for the primary plane
+    pub trait Display {
+        fn resolution(&self) -> (u32, u32);
+        // Copies the damaged areas of the frame into the scanout buffer
+        fn scanout(&mut self, frame: &[u32], damage: &[Rect]) -> Result<(), &'static str>;
+        // Blocks until the next vertical blank; returns its timestamp in
+        // microseconds
+        fn wait_vblank(&mut self) -> Result<u64, &'static str>;
+        // The hardware cursor plane, scanned out over the frame so the
+        // pointer moves without waiting for composition. Displays without
+        // one keep these defaults and get the pointer drawn into the frame.
+        // Largest image the plane takes
+        fn cursor_size(&self) -> Option<(u32, u32)> {
+            None
+        }
+        // None hides the plane
+        fn set_cursor(&mut self, _image: Option<&Buffer>) -> Result<(), &'static str> {
+            Err("No cursor plane")
+        }
+        // The image's top-left corner, in the display's pixels
+        fn move_cursor(&mut self, _x: i32, _y: i32) -> Result<(), &'static str> {
+            Err("No cursor plane")
+        }
+    }
+
+    // A pointer image and the pixel in it that points
+    #[derive(Clone)]
+    pub struct CursorImage {
+        pub buffer: Buffer,
+        pub hotspot: (i32, i32),
+    }
+
+    impl CursorImage {
+        // Nearest-neighbour, for outputs with a scale
+        pub fn scaled(&self, scale: f32) -> CursorImage {
+            let (width, height) = (self.buffer.width, self.buffer.height);
+            let (scaled_width, scaled_height) = ((width as f32 * scale).round() as u32, (height as f32 * scale).round() as u32);
+            let buffer = Buffer::new(scaled_width.max(1), scaled_height.max(1), self.buffer.storage);
+            {
+                let source = self.buffer.pixels.read().unwrap();
+                let mut target = buffer.pixels.write().unwrap();
+                for y in 0..buffer.height {
+                    let source_y = (y as u64 * height as u64 / buffer.height as u64) as u32;
+                    for x in 0..buffer.width {
+                        let source_x = (x as u64 * width as u64 / buffer.width as u64) as u32;
+                        target[(y * buffer.width + x) as usize] = source[(source_y * width + source_x) as usize];
+                    }
+                }
+            }
+            let hotspot = ((self.hotspot.0 as f32 * scale).round() as i32, (self.hotspot.1 as f32 * scale).round() as i32);
+            CursorImage { buffer, hotspot }
+        }
+
+        // Where the image goes with the pointer at a position
+        pub fn bounds(&self, x: i32, y: i32) -> Rect {
+            Rect::new(x - self.hotspot.0, y - self.hotspot.1, self.buffer.width, self.buffer.height)
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct Mode {
+        pub width: u32,
+        pub height: u32,
+        // Millihertz, as in the EDID detailed timings
+        pub refresh: u32,
+    }
+
+    // A connector with a display attached; its resolution follows the
+    // mode last set
+    pub trait Output: Display + Send {
+        fn name(&self) -> &str;
+        // Preferred mode first
+        fn modes(&self) -> Vec<Mode>;
+        fn set_mode(&mut self, mode: Mode) -> Result<(), &'static str>;
+        // The sink's EDID block, read over DDC
+        fn edid(&self) -> Option<Vec<u8>> {
+            None
+        }
+    }
+
+    // The part of the desktop an output shows, in logical pixels, and how
+    // many physical pixels each one takes up there
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Viewport {
+        pub area: Rect,
+        pub scale: f32,
+    }
+
+    impl Viewport {
+        pub fn new(area: Rect, scale: f32) -> Self {
+            Viewport { area, scale }
+        }
+
+        pub fn physical_size(&self) -> (u32, u32) {
+            let (width, height) = (self.area.width as f32 * self.scale, self.area.height as f32 * self.scale);
+            (width.round() as u32, height.round() as u32)
+        }
+
+        // Covers every physical pixel the logical rectangle touches
+        pub fn to_physical(&self, rect: Rect) -> Rect {
+            let scale = |value: i32, origin: i32| (value - origin) as f32 * self.scale;
+            let (x, y) = (scale(rect.x, self.area.x).floor(), scale(rect.y, self.area.y).floor());
+            let (right, bottom) = (scale(rect.right(), self.area.x).ceil(), scale(rect.bottom(), self.area.y).ceil());
+            Rect::new(x as i32, y as i32, (right - x) as u32, (bottom - y) as u32)
+        }
+    }
+
+    // One step of composing a damaged area
+    pub enum BlitOp<'a> {
+        Fill { area: Rect, color: u32 },
+        // Source-over blend of the buffer stretched over `bounds`, limited
+        // to `clip`, with its alpha scaled by `opacity`
+        Blend { buffer: &'a Buffer, bounds: Rect, clip: Rect, opacity: u8 },
+    }
+
+    // A 2D engine that carries out the composition; the i915 blitter
+    // implements it on top of GPU command submission. The target is the
+    // frame as mapped through the aperture, `stride` pixels per row.
+    pub trait Blitter: Send {
+        fn name(&self) -> &'static str;
+        fn submit(&mut self, target: &mut [u32], stride: u32, ops: &[BlitOp]) -> Result<(), &'static str>;
+    }
+
+    // The CPU path, used when no GPU is bound or the GPU fails
+    pub struct SoftwareBlitter;
+
+    impl SoftwareBlitter {
+        // Source-over blend of premultiplied pixels
+        fn blend(src: u32, dst: u32) -> u32 {
+            let alpha = src >> 24;
+            if alpha == 0xFF {
+                return src;
+            }
+            let inverse = 255 - alpha;
+            let channel = |shift: u32| {
+                let (s, d) = ((src >> shift) & 0xFF, (dst >> shift) & 0xFF);
+                ((s + d * inverse / 255).min(255)) << shift
+            };
+            channel(24) | channel(16) | channel(8) | channel(0)
+        }
+    }
+
+    impl SoftwareBlitter {
+        fn fade(pixel: u32, opacity: u32) -> u32 {
+            let channel = |shift: u32| (((pixel >> shift) & 0xFF) * opacity / 255) << shift;
+            channel(24) | channel(16) | channel(8) | channel(0)
+        }
+    }
+
+    impl Blitter for SoftwareBlitter {
+        fn name(&self) -> &'static str {
+            "software"
+        }
+
+        // Scaling samples the nearest source pixel
+        fn submit(&mut self, target: &mut [u32], stride: u32, ops: &[BlitOp]) -> Result<(), &'static str> {
+            for op in ops {
+                match op {
+                    BlitOp::Fill { area, color } => {
+                        for y in area.y..area.bottom() {
+                            let row = (y as u32 * stride) as usize;
+                            target[row + area.x as usize..row + area.right() as usize].fill(*color);
+                        }
+                    }
+                    BlitOp::Blend { buffer, bounds, clip, opacity } => {
+                        let pixels = buffer.pixels.read().unwrap();
+                        for y in clip.y..clip.bottom() {
+                            let row = (y as u32 * stride) as usize;
+                            let source_y = (y - bounds.y) as u64 * buffer.height as u64 / bounds.height as u64;
+                            let source = (source_y as u32 * buffer.width) as usize;
+                            for x in clip.x..clip.right() {
+                                let source_x = (x - bounds.x) as u64 * buffer.width as u64 / bounds.width as u64;
+                                let mut src = pixels[source + source_x as usize];
+                                if *opacity < 255 {
+                                    src = Self::fade(src, *opacity as u32);
+                                }
+                                let dst = &mut target[row + x as usize];
+                                *dst = Self::blend(src, *dst);
+                            }
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct WindowId(pub u32);
+
+    // Applied at composition time on top of the window's position and
+    // size, for animations. Scaling is about the window's centre.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Transform {
+        pub dx: f32,
+        pub dy: f32,
+        pub scale: f32,
+        pub opacity: f32,
+    }
+
+    impl Transform {
+        pub const IDENTITY: Transform = Transform {
+            dx: 0.0,
+            dy: 0.0,
+            scale: 1.0,
+            opacity: 1.0,
+        };
+    }
+
+    impl Default for Transform {
+        fn default() -> Self {
+            Self::IDENTITY
+        }
+    }
+
+    // Attached buffer and damage only take effect on commit, as with
+    // Wayland's double-buffered surface state
+    struct Window {
+        x: i32,
+        y: i32,
+        visible: bool,
+        buffer: Option<Buffer>,
+        pending_buffer: Option<Buffer>,
+        // On-screen size when the buffer is scaled
+        destination: Option<(u32, u32)>,
+        transform: Transform,
+        // Surface-local
+        pending_damage: Vec<Rect>,
+        frame_callbacks: Vec<Sender<u64>>,
+    }
+
+    impl Window {
+        fn bounds(&self) -> Option<Rect> {
+            let buffer = self.buffer.as_ref()?;
+            let (width, height) = self.destination.unwrap_or((buffer.width, buffer.height));
+            let transform = self.transform;
+            let (scaled_width, scaled_height) = (width as f32 * transform.scale, height as f32 * transform.scale);
+            let x = self.x as f32 + transform.dx + (width as f32 - scaled_width) / 2.0;
+            let y = self.y as f32 + transform.dy + (height as f32 - scaled_height) / 2.0;
+            let bounds = Rect::new(x.round() as i32, y.round() as i32, scaled_width.round() as u32, scaled_height.round() as u32);
+            (self.visible && !bounds.is_empty()).then_some(bounds)
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct FrameStats {
+        pub rects: usize,
+        pub pixels: u64,
+        pub vblank: u64,
+        // Whether the GPU composed the frame
+        pub accelerated: bool,
+    }
+
+    // Microseconds on a monotonic timeline, for frame timing
+    pub trait Clock: Send {
+        fn now(&self) -> u64;
+    }
+
+    // Counts from when it was made
+    pub struct MonotonicClock {
+        start: Instant,
+    }
+
+    impl Default for MonotonicClock {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl MonotonicClock {
+        pub fn new() -> Self {
+            MonotonicClock { start: Instant::now() }
+        }
+    }
+
+    impl Clock for MonotonicClock {
+        fn now(&self) -> u64 {
+            self.start.elapsed().as_micros() as u64
+        }
+    }
+
+    // When a composed frame's inputs arrived and when it reached the
+    // screen, on the compositor's clock
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct FrameTiming {
+        // The earliest input event and client commit the frame shows
+        pub input: Option<u64>,
+        pub commit: Option<u64>,
+        pub composite_start: u64,
+        pub composite_end: u64,
+        // The vblank that put the frame on screen; None when it was composed
+        // outside run_frame
+        pub flip: Option<u64>,
+        // Vblanks missed since the frame before, while animating
+        pub dropped: u32,
+    }
+
+    impl FrameTiming {
+        pub fn composite_time(&self) -> u64 {
+            self.composite_end.saturating_sub(self.composite_start)
+        }
+
+        pub fn input_latency(&self) -> Option<u64> {
+            Some(self.flip?.saturating_sub(self.input?))
+        }
+
+        pub fn commit_latency(&self) -> Option<u64> {
+            Some(self.flip?.saturating_sub(self.commit?))
+        }
+    }
+
+    // Figures over the timing history; times are (average, worst)
+    #[derive(Debug, Clone, Copy, Default, PartialEq)]
+    pub struct TimingSummary {
+        pub frames: usize,
+        pub dropped: u64,
+        // Presented frames per second, while animating
+        pub fps: f32,
+        pub composite: (u64, u64),
+        pub input_latency: Option<(u64, u64)>,
+        pub commit_latency: Option<(u64, u64)>,
+    }
+
+    impl TimingSummary {
+        fn figures(values: impl Iterator<Item = u64>) -> Option<(u64, u64)> {
+            let (count, total, worst) = values.fold((0, 0, 0), |(count, total, worst), value| (count + 1, total + value, worst.max(value)));
+            (count > 0).then(|| (total / count, worst))
+        }
+
+        pub fn new(timings: &[FrameTiming]) -> Self {
+            let flips: Vec<u64> = timings.iter().filter_map(|timing| timing.flip).collect();
+            let span = flips.last().zip(flips.first()).map_or(0, |(last, first)| last - first);
+            TimingSummary {
+                frames: timings.len(),
+                dropped: timings.iter().map(|timing| timing.dropped as u64).sum(),
+                fps: if span == 0 { 0.0 } else { (flips.len() - 1) as f32 * 1_000_000.0 / span as f32 },
+                composite: Self::figures(timings.iter().map(FrameTiming::composite_time)).unwrap_or_default(),
+                input_latency: Self::figures(timings.iter().filter_map(FrameTiming::input_latency)),
+                commit_latency: Self::figures(timings.iter().filter_map(FrameTiming::commit_latency)),
+            }
+        }
+
+        // One figure per line, in milliseconds
+        pub fn lines(&self) -> Vec<String> {
+            let millis = |value: u64| format!("{:.1}", value as f32 / 1000.0);
+            let figures = |name: &str, figures: Option<(u64, u64)>| match figures {
+                Some((average, worst)) => format!("{} {} ms (worst {})", name, millis(average), millis(worst)),
+                None => format!("{} -", name),
+            };
+            vec![
+                format!("fps {:.1} dropped {}", self.fps, self.dropped),
+                figures("composite", Some(self.composite)),
+                figures("input", self.input_latency),
+                figures("commit", self.commit_latency),
+            ]
+        }
+    }
+
+    pub struct Compositor {
+        width: u32,
+        height: u32,
+        windows: BTreeMap<WindowId, Window>,
+        // Bottom to top
+        stacking: Vec<WindowId>,
+        next_id: u32,
+        // Screen-space areas to repaint on the next frame
+        damage: Vec<Rect>,
+        frame: Vec<u32>,
+        // Composed at physical resolution for each scaled output
+        scaled_frames: Vec<(Viewport, Vec<u32>)>,
+        frames: u64,
+        accelerator: Option<Box<dyn Blitter>>,
+        cursor: Option<CursorImage>,
+        cursor_position: (i32, i32),
+        // Drawn into the frame because some output has no usable cursor
+        // plane
+        software_cursor: bool,
+        // Outputs the planes were last programmed for; cleared to load a
+        // new image
+        cursor_outputs: Vec<Viewport>,
+        plane_position: (i32, i32),
+        clock: Box<dyn Clock>,
+        // Microseconds between vblanks
+        refresh_interval: u64,
+        // Waiting to show on the next composed frame
+        pending_input: Option<u64>,
+        pending_commit: Option<u64>,
+        // Composed and waiting for the vblank that shows it
+        unpresented: Option<FrameTiming>,
+        // Cleared by a vblank with nothing new to show
+        last_flip: Option<u64>,
+        timings: Vec<FrameTiming>,
+        dropped_frames: u64,
+    }
+
+    impl Compositor {
+        pub fn new(width: u32, height: u32) -> Self {
+            Compositor {
+                width,
+                height,
+                windows: BTreeMap::new(),
+                stacking: Vec::new(),
+                next_id: 1,
+                damage: vec![Rect::new(0, 0, width, height)],
+                frame: vec![BACKGROUND; (width * height) as usize],
+                scaled_frames: Vec::new(),
+                frames: 0,
+                accelerator: None,
+                cursor: None,
+                cursor_position: (0, 0),
+                software_cursor: false,
+                cursor_outputs: Vec::new(),
+                plane_position: (0, 0),
+                clock: Box::new(MonotonicClock::new()),
+                refresh_interval: 16_667,
+                pending_input: None,
+                pending_commit: None,
+                unpresented: None,
+                last_flip: None,
+                timings: Vec::new(),
+                dropped_frames: 0,
+            }
+        }
+
+        // Composes on the GPU from now on. A failed submission falls back
+        // to software for the rest of the session.
+        pub fn set_accelerator(&mut self, blitter: Box<dyn Blitter>) {
+            log::info!("Compositing with the {} blitter", blitter.name());
+            self.accelerator = Some(blitter);
+        }
+
+        pub fn accelerator(&self) -> Option<&'static str> {
+            self.accelerator.as_ref().map(|blitter| blitter.name())
+        }
+
+        pub fn resolution(&self) -> (u32, u32) {
+            (self.width, self.height)
+        }
+
+        // Resizes the desktop, e.g. when the output layout changes; windows
+        // keep their positions
+        pub fn resize(&mut self, width: u32, height: u32) {
+            (self.width, self.height) = (width, height);
+            self.frame = vec![BACKGROUND; (width * height) as usize];
+            self.scaled_frames.clear();
+            self.damage = vec![Rect::new(0, 0, width, height)];
+        }
+
+        // Recomposes everything on the next frame, as when switching back
+        // from a text console that drew over the scanout buffer
+        pub fn repaint(&mut self) {
+            self.damage = vec![Rect::new(0, 0, self.width, self.height)];
+        }
+
+        // None hides the pointer
+        pub fn set_cursor(&mut self, image: Option<CursorImage>) {
+            self.damage_cursor();
+            self.cursor = image;
+            self.cursor_outputs.clear();
+            self.damage_cursor();
+        }
+
+        // Desktop position of the hotspot. Cursor planes follow on the
+        // next update_cursor, without a full frame.
+        pub fn move_cursor(&mut self, x: i32, y: i32) {
+            if self.cursor_position == (x, y) {
+                return;
+            }
+            self.damage_cursor();
+            self.cursor_position = (x, y);
+            self.damage_cursor();
+        }
+
+        pub fn cursor_position(&self) -> (i32, i32) {
+            self.cursor_position
+        }
+
+        // Whether the pointer is composed into the frame
+        pub fn software_cursor(&self) -> bool {
+            self.software_cursor
+        }
+
+        fn damage_cursor(&mut self) {
+            if let Some(bounds) = self.cursor.as_ref().filter(|_| self.software_cursor).map(|cursor| {
+                let (x, y) = self.cursor_position;
+                cursor.bounds(x, y)
+            }) {
+                self.add_damage(bounds);
+            }
+        }
+
+        // Loads and positions the cursor planes. Called with every frame,
+        // and from the input path after pointer motion so the pointer keeps
+        // up when composition is slow. If any output cannot take the image
+        // the pointer is drawn into the frame everywhere, so it never shows
+        // twice.
+        pub fn update_cursor(&mut self, outputs: &mut [(Viewport, &mut dyn Display)]) -> Result<(), &'static str> {
+            let viewports: Vec<Viewport> = outputs.iter().map(|(viewport, _)| *viewport).collect();
+            let load = viewports != self.cursor_outputs;
+            let hardware = match &self.cursor {
+                Some(_) if !load && self.software_cursor => false,
+                Some(_) if !load && self.plane_position == self.cursor_position => true,
+                Some(cursor) => match Self::program_planes(cursor, self.cursor_position, outputs, load) {
+                    Ok(()) => {
+                        self.plane_position = self.cursor_position;
+                        true
+                    }
+                    Err(error) => {
+                        if !self.software_cursor {
+                            log::info!("Drawing the pointer in software: {}", error);
+                        }
+                        false
+                    }
+                },
+                None => false,
+            };
+            // Planes never show a stale image next to the drawn one
+            if !hardware && (load || (self.cursor.is_some() && !self.software_cursor)) {
+                for (_, display) in outputs.iter_mut() {
+                    let _ = display.set_cursor(None);
+                }
+            }
+            let software = self.cursor.is_some() && !hardware;
+            if software != self.software_cursor {
+                // Draws or erases the pointer in the frame
+                let (x, y) = self.cursor_position;
+                if let Some(bounds) = self.cursor.as_ref().map(|cursor| cursor.bounds(x, y)) {
+                    self.add_damage(bounds);
+                }
+                self.software_cursor = software;
+            }
+            self.cursor_outputs = viewports;
+            Ok(())
+        }
+
+        fn program_planes(
+            cursor: &CursorImage,
+            (x, y): (i32, i32),
+            outputs: &mut [(Viewport, &mut dyn Display)],
+            load: bool,
+        ) -> Result<(), &'static str> {
+            for (viewport, display) in outputs.iter_mut() {
+                let scale = viewport.scale;
+                if load {
+                    let image = if scale == 1.0 { cursor.clone() } else { cursor.scaled(scale) };
+                    let (width, height) = display.cursor_size().ok_or("No cursor plane")?;
+                    if image.buffer.width > width || image.buffer.height > height {
+                        return Err("Cursor image too large for the plane");
+                    }
+                    display.set_cursor(Some(&image.buffer))?;
+                }
+                let (hotspot_x, hotspot_y) = ((cursor.hotspot.0 as f32 * scale).round() as i32, (cursor.hotspot.1 as f32 * scale).round() as i32);
+                let physical = |value: i32, origin: i32| ((value - origin) as f32 * scale).floor() as i32;
+                let (x, y) = (physical(x, viewport.area.x), physical(y, viewport.area.y));
+                display.move_cursor(x - hotspot_x, y - hotspot_y)?;
+            }
+            Ok(())
+        }
+
+        pub fn frames(&self) -> u64 {
+            self.frames
+        }
+
+        // Frame timing reads this clock rather than the displays' vblank
+        // timestamps, so input, commit and flip times share a timeline
+        pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+            self.clock = clock;
+        }
+
+        // Of the primary output, in millihertz as in Mode
+        pub fn set_refresh_rate(&mut self, refresh: u32) {
+            self.refresh_interval = 1_000_000_000 / refresh.max(1) as u64;
+        }
+
+        pub fn refresh_interval(&self) -> u64 {
+            self.refresh_interval
+        }
+
+        // Marks an input event whose effect shows on the next composed
+        // frame; the window manager calls it as events arrive
+        pub fn note_input(&mut self) {
+            if self.pending_input.is_none() {
+                self.pending_input = Some(self.clock.now());
+            }
+        }
+
+        // Oldest first, up to TIMING_HISTORY
+        pub fn frame_timings(&self) -> &[FrameTiming] {
+            &self.timings
+        }
+
+        pub fn timing_summary(&self) -> TimingSummary {
+            TimingSummary::new(&self.timings)
+        }
+
+        // Since the compositor started or the timings were last reset
+        pub fn dropped_frames(&self) -> u64 {
+            self.dropped_frames
+        }
+
+        pub fn reset_timings(&mut self) {
+            self.timings.clear();
+            self.dropped_frames = 0;
+        }
+
+        fn record_timing(&mut self, timing: FrameTiming) {
+            if self.timings.len() == TIMING_HISTORY {
+                self.timings.remove(0);
+            }
+            self.timings.push(timing);
+        }
+
+        // A vblank at `flip` put the last composed frame on screen. Missed
+        // vblanks only count between frames of a continuous run, as when
+        // animating; an idle compositor skips them on purpose.
+        fn present(&mut self, flip: u64) {
+            let Some(mut timing) = self.unpresented.take() else {
+                self.last_flip = None;
+                return;
+            };
+            timing.flip = Some(flip);
+            if let Some(last) = self.last_flip {
+                let vblanks = (flip.saturating_sub(last) + self.refresh_interval / 2) / self.refresh_interval;
+                timing.dropped = vblanks.saturating_sub(1) as u32;
+                self.dropped_frames += timing.dropped as u64;
+            }
+            self.last_flip = Some(flip);
+            self.record_timing(timing);
+        }
+
+        // The composed image as last sent to scanout
+        pub fn frame(&self) -> &[u32] {
+            &self.frame
+        }
+
+        pub fn pending_damage(&self) -> &[Rect] {
+            &self.damage
+        }
+
+        fn add_damage(&mut self, rect: Rect) {
+            let Some(rect) = rect.intersect(&Rect::new(0, 0, self.width, self.height)) else {
+                return;
+            };
+            if let Some(existing) = self.damage.iter_mut().find(|existing| existing.intersect(&rect).is_some()) {
+                *existing = existing.union(&rect);
+            } else {
+                self.damage.push(rect);
+            }
+            if self.damage.len() > MAX_DAMAGE_RECTS {
+                let bounds = self.damage.iter().fold(self.damage[0], |bounds, rect| bounds.union(rect));
+                self.damage = vec![bounds];
+            }
+        }
+
+        fn damage_window(&mut self, id: WindowId) {
+            if let Some(bounds) = self.windows.get(&id).and_then(Window::bounds) {
+                self.add_damage(bounds);
+            }
+        }
+
+        // New windows are mapped on top once their first buffer is committed
+        pub fn create_window(&mut self, x: i32, y: i32) -> WindowId {
+            let id = WindowId(self.next_id);
+            self.next_id += 1;
+            self.windows.insert(
+                id,
+                Window {
+                    x,
+                    y,
+                    visible: true,
+                    buffer: None,
+                    pending_buffer: None,
+                    destination: None,
+                    transform: Transform::IDENTITY,
+                    pending_damage: Vec::new(),
+                    frame_callbacks: Vec::new(),
+                },
+            );
+            self.stacking.push(id);
+            id
+        }
+
+        pub fn destroy_window(&mut self, id: WindowId) -> Result<(), &'static str> {
+            self.damage_window(id);
+            self.windows.remove(&id).ok_or("Window not found")?;
+            self.stacking.retain(|window| *window != id);
+            Ok(())
+        }
+
+        fn window_mut(&mut self, id: WindowId) -> Result<&mut Window, &'static str> {
+            self.windows.get_mut(&id).ok_or("Window not found")
+        }
+
+        // Whether the window has a committed buffer
+        pub fn is_mapped(&self, id: WindowId) -> bool {
+            self.windows.get(&id).is_some_and(|window| window.buffer.is_some())
+        }
+
+        pub fn size(&self, id: WindowId) -> Option<(u32, u32)> {
+            let buffer = self.windows.get(&id)?.buffer.as_ref()?;
+            Some((buffer.width, buffer.height))
+        }
+
+        // Size of the buffer the next commit will bring in, if any
+        pub fn pending_size(&self, id: WindowId) -> Option<(u32, u32)> {
+            let buffer = self.windows.get(&id)?.pending_buffer.as_ref()?;
+            Some((buffer.width, buffer.height))
+        }
+
+        pub fn attach(&mut self, id: WindowId, buffer: Buffer) -> Result<(), &'static str> {
+            self.window_mut(id)?.pending_buffer = Some(buffer);
+            Ok(())
+        }
+
+        // Surface-local area the client redrew
+        pub fn damage(&mut self, id: WindowId, area: Rect) -> Result<(), &'static str> {
+            self.window_mut(id)?.pending_damage.push(area);
+            Ok(())
+        }
+
+        // Delivers the vblank timestamp of the frame that next shows the
+        // window, so the client can pace its drawing
+        pub fn request_frame(&mut self, id: WindowId) -> Result<Receiver<u64>, &'static str> {
+            let (sender, receiver) = mpsc::channel();
+            self.window_mut(id)?.frame_callbacks.push(sender);
+            Ok(receiver)
+        }
+
+        pub fn commit(&mut self, id: WindowId) -> Result<(), &'static str> {
+            if self.pending_commit.is_none() && self.windows.contains_key(&id) {
+                self.pending_commit = Some(self.clock.now());
+            }
+            let window = self.window_mut(id)?;
+            let damage: Vec<Rect> = window.pending_damage.drain(..).collect();
+            if let Some(buffer) = window.pending_buffer.take() {
+                // A new size or first buffer repaints the whole area
+                let old = window.bounds();
+                window.buffer = Some(buffer);
+                let new = window.bounds();
+                for bounds in [old, new].into_iter().flatten() {
+                    self.add_damage(bounds);
+                }
+                return Ok(());
+            }
+            let Some(bounds) = window.bounds() else {
+                return Ok(());
+            };
+            // Buffer pixels map onto the window's on-screen size, which
+            // differs for scaled and transformed windows
+            let buffer = window.buffer.as_ref().unwrap();
+            let (scale_x, scale_y) = (bounds.width as f32 / buffer.width as f32, bounds.height as f32 / buffer.height as f32);
+            for area in damage {
+                let (x, y) = ((area.x as f32 * scale_x).floor(), (area.y as f32 * scale_y).floor());
+                let (right, bottom) = ((area.right() as f32 * scale_x).ceil(), (area.bottom() as f32 * scale_y).ceil());
+                let area = Rect::new(x as i32, y as i32, (right - x) as u32, (bottom - y) as u32);
+                self.add_damage(area.translate(bounds.x, bounds.y));
+            }
+            Ok(())
+        }
+
+        pub fn move_window(&mut self, id: WindowId, x: i32, y: i32) -> Result<(), &'static str> {
+            self.damage_window(id);
+            let window = self.window_mut(id)?;
+            (window.x, window.y) = (x, y);
+            self.damage_window(id);
+            Ok(())
+        }
+
+        // Stretches the buffer over the given size, or shows it at its
+        // own size with None
+        pub fn set_destination(&mut self, id: WindowId, size: Option<(u32, u32)>) -> Result<(), &'static str> {
+            if size.is_some_and(|(width, height)| width == 0 || height == 0) {
+                return Err("Destination size must not be empty");
+            }
+            self.damage_window(id);
+            self.window_mut(id)?.destination = size;
+            self.damage_window(id);
+            Ok(())
+        }
+
+        pub fn set_transform(&mut self, id: WindowId, transform: Transform) -> Result<(), &'static str> {
+            self.damage_window(id);
+            self.window_mut(id)?.transform = transform;
+            self.damage_window(id);
+            Ok(())
+        }
+
+        pub fn transform(&self, id: WindowId) -> Option<Transform> {
+            self.windows.get(&id).map(|window| window.transform)
+        }
+
+        pub fn set_visible(&mut self, id: WindowId, visible: bool) -> Result<(), &'static str> {
+            self.damage_window(id);
+            self.window_mut(id)?.visible = visible;
+            self.damage_window(id);
+            Ok(())
+        }
+
+        pub fn raise(&mut self, id: WindowId) -> Result<(), &'static str> {
+            let index = self.stacking.iter().position(|window| *window == id).ok_or("Window not found")?;
+            self.stacking.remove(index);
+            self.stacking.push(id);
+            self.damage_window(id);
+            Ok(())
+        }
+
+        pub fn lower(&mut self, id: WindowId) -> Result<(), &'static str> {
+            let index = self.stacking.iter().position(|window| *window == id).ok_or("Window not found")?;
+            self.stacking.remove(index);
+            self.stacking.insert(0, id);
+            self.damage_window(id);
+            Ok(())
+        }
+
+        // Bottom to top
+        pub fn stacking_order(&self) -> &[WindowId] {
+            &self.stacking
+        }
+
+        // Topmost visible window under a screen position
+        pub fn window_at(&self, x: i32, y: i32) -> Option<WindowId> {
+            self.stacking.iter().rev().copied().find(|id| {
+                self.windows[id]
+                    .bounds()
+                    .is_some_and(|bounds| bounds.intersect(&Rect::new(x, y, 1, 1)).is_some())
+            })
+        }
+
+        // `area` is in the target's pixels, which the viewport maps the
+        // desktop onto
+        fn paint_ops<'a>(
+            windows: &'a BTreeMap<WindowId, Window>,
+            stacking: &[WindowId],
+            cursor: Option<(&'a Buffer, Rect)>,
+            area: Rect,
+            viewport: &Viewport,
+        ) -> Vec<BlitOp<'a>> {
+            let mut ops = vec![BlitOp::Fill { area, color: BACKGROUND }];
+            for id in stacking {
+                let window = &windows[id];
+                let Some(bounds) = window.bounds().map(|bounds| viewport.to_physical(bounds)) else {
+                    continue;
+                };
+                let opacity = (window.transform.opacity.clamp(0.0, 1.0) * 255.0).round() as u8;
+                if let Some(clip) = bounds.intersect(&area).filter(|_| opacity > 0) {
+                    let buffer = window.buffer.as_ref().unwrap();
+                    ops.push(BlitOp::Blend { buffer, bounds, clip, opacity });
+                }
+            }
+            if let Some((buffer, bounds)) = cursor {
+                let bounds = viewport.to_physical(bounds);
+                if let Some(clip) = bounds.intersect(&area) {
+                    ops.push(BlitOp::Blend { buffer, bounds, clip, opacity: 255 });
+                }
+            }
+            ops
+        }
+
+        // The pointer when it is composed into the frame
+        fn drawn_cursor(cursor: &Option<CursorImage>, software: bool, (x, y): (i32, i32)) -> Option<(&Buffer, Rect)> {
+            cursor.as_ref().filter(|_| software).map(|cursor| (&cursor.buffer, cursor.bounds(x, y)))
+        }
+
+        // Recomposes the damaged areas and hands them to scanout. Returns
+        // None when nothing changed.
+        pub fn compose(&mut self, display: &mut dyn Display) -> Result<Option<FrameStats>, &'static str> {
+            let desktop = Viewport::new(Rect::new(0, 0, self.width, self.height), 1.0);
+            self.compose_outputs(&mut [(desktop, display)])
+        }
+
+        // Runs the ops on the GPU if there is one, dropping it for good on
+        // failure
+        fn blit(accelerator: &mut Option<Box<dyn Blitter>>, target: &mut [u32], stride: u32, ops: &[BlitOp]) -> Result<(), &'static str> {
+            if let Some(blitter) = accelerator.as_mut() {
+                match blitter.submit(target, stride, ops) {
+                    Ok(()) => return Ok(()),
+                    Err(error) => {
+                        log::warn!("GPU composition failed, using software: {}", error);
+                        *accelerator = None;
+                    }
+                }
+            }
+            SoftwareBlitter.submit(target, stride, ops)
+        }
+
+        // As compose, with each display showing its viewport of the
+        // desktop. Mirrored displays share a viewport. Unscaled outputs
+        // are cut from the desktop frame; scaled ones are composed at their
+        // own resolution so high-density client buffers stay sharp.
+        pub fn compose_outputs(&mut self, outputs: &mut [(Viewport, &mut dyn Display)]) -> Result<Option<FrameStats>, &'static str> {
+            // Scaled outputs may be a few pixels off where the logical size
+            // was rounded
+            for (viewport, display) in outputs.iter() {
+                let (width, height) = display.resolution();
+                let (expected_width, expected_height) = viewport.physical_size();
+                let slack = if viewport.scale == 1.0 { 0 } else { viewport.scale.ceil() as u32 };
+                if width.abs_diff(expected_width) > slack || height.abs_diff(expected_height) > slack {
+                    return Err("Display resolution does not match the compositor");
+                }
+            }
+            self.update_cursor(outputs)?;
+            if self.damage.is_empty() {
+                // Nothing they did needs a frame
+                self.pending_input = None;
+                self.pending_commit = None;
+                return Ok(None);
+            }
+            let composite_start = self.clock.now();
+            let damage = std::mem::take(&mut self.damage);
+            let mut stats = FrameStats::default();
+            let desktop = Viewport::new(Rect::new(0, 0, self.width, self.height), 1.0);
+            if outputs.iter().any(|(viewport, _)| viewport.scale == 1.0) {
+                let cursor = Self::drawn_cursor(&self.cursor, self.software_cursor, self.cursor_position);
+                let ops: Vec<BlitOp> = damage
+                    .iter()
+                    .flat_map(|area| Self::paint_ops(&self.windows, &self.stacking, cursor, *area, &desktop))
+                    .collect();
+                Self::blit(&mut self.accelerator, &mut self.frame, self.width, &ops)?;
+            }
+            for area in &damage {
+                stats.rects += 1;
+                stats.pixels += area.width as u64 * area.height as u64;
+            }
+            for (viewport, display) in outputs.iter_mut() {
+                if viewport.scale != 1.0 {
+                    self.compose_scaled(*viewport, &damage, *display)?;
+                    continue;
+                }
+                let viewport = viewport.area;
+                let local: Vec<Rect> = damage
+                    .iter()
+                    .filter_map(|area| area.intersect(&viewport))
+                    .map(|area| area.translate(-viewport.x, -viewport.y))
+                    .collect();
+                if local.is_empty() {
+                    continue;
+                }
+                if viewport == desktop.area {
+                    display.scanout(&self.frame, &local)?;
+                    continue;
+                }
+                let visible = viewport.intersect(&desktop.area).ok_or("Viewport is off the desktop")?;
+                let mut frame = vec![BACKGROUND; (viewport.width * viewport.height) as usize];
+                for y in visible.y..visible.bottom() {
+                    let source = (y as u32 * self.width) as usize;
+                    let target = ((y - viewport.y) as u32 * viewport.width) as usize + (visible.x - viewport.x) as usize;
+                    frame[target..target + visible.width as usize]
+                        .copy_from_slice(&self.frame[source + visible.x as usize..source + visible.right() as usize]);
+                }
+                display.scanout(&frame, &local)?;
+            }
+            stats.accelerated = self.accelerator.is_some();
+            let timing = FrameTiming {
+                input: self.pending_input.take(),
+                commit: self.pending_commit.take(),
+                composite_start,
+                composite_end: self.clock.now(),
+                ..FrameTiming::default()
+            };
+            // Composed twice without a vblank in between, as with compose
+            if let Some(unshown) = self.unpresented.replace(timing) {
+                self.record_timing(unshown);
+            }
+            Ok(Some(stats))
+        }
+
+        fn compose_scaled(&mut self, viewport: Viewport, damage: &[Rect], display: &mut dyn Display) -> Result<(), &'static str> {
+            let (width, height) = display.resolution();
+            let screen = Rect::new(0, 0, width, height);
+            let (index, fresh) = match self.scaled_frames.iter().position(|(existing, _)| *existing == viewport) {
+                Some(index) => (index, false),
+                None => {
+                    self.scaled_frames.push((viewport, vec![BACKGROUND; (width * height) as usize]));
+                    (self.scaled_frames.len() - 1, true)
+                }
+            };
+            // A new frame has nothing in it yet
+            let whole = [viewport.area];
+            let damage = if fresh { &whole[..] } else { damage };
+            let local: Vec<Rect> = damage
+                .iter()
+                .filter_map(|area| area.intersect(&viewport.area))
+                .filter_map(|area| viewport.to_physical(area).intersect(&screen))
+                .collect();
+            if local.is_empty() {
+                return Ok(());
+            }
+            let cursor = Self::drawn_cursor(&self.cursor, self.software_cursor, self.cursor_position);
+            let ops: Vec<BlitOp> = local
+                .iter()
+                .flat_map(|area| Self::paint_ops(&self.windows, &self.stacking, cursor, *area, &viewport))
+                .collect();
+            let frame = &mut self.scaled_frames[index].1;
+            Self::blit(&mut self.accelerator, frame, width, &ops)?;
+            display.scanout(frame, &local)
+        }
+
+        // One iteration of the compositor loop, paced by vblank: waits for
+        // the blank, composes, then tells clients their frame is up
+        pub fn run_frame(&mut self, display: &mut dyn Display) -> Result<Option<FrameStats>, &'static str> {
+            let desktop = Viewport::new(Rect::new(0, 0, self.width, self.height), 1.0);
+            self.run_frame_outputs(&mut [(desktop, display)])
+        }
+
+        // As run_frame across several outputs, paced by the first
+        pub fn run_frame_outputs(&mut self, outputs: &mut [(Viewport, &mut dyn Display)]) -> Result<Option<FrameStats>, &'static str> {
+            let (_, primary) = outputs.first_mut().ok_or("No outputs")?;
+            let vblank = primary.wait_vblank()?;
+            let flip = self.clock.now();
+            self.present(flip);
+            let stats = self.compose_outputs(outputs)?.map(|stats| FrameStats { vblank, ..stats });
+            self.frames += 1;
+            for window in self.windows.values_mut() {
+                for callback in window.frame_callbacks.drain(..) {
+                    let _ = callback.send(vblank);
+                }
+            }
+            Ok(stats)
+        }
+    }
 }
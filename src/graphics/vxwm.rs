@@ -0,0 +1,1620 @@
+// src/graphics/vxwm.rs
+
+pub mod vxwm {
+    use crate::vxcursor::vxcursor::{self, CursorShape};
+    use crate::vxtheme::vxtheme::CursorStyle;
+    use crate::vxwin::vxwin::{Buffer, BufferStorage, Compositor, CursorImage, Rect, Viewport, WindowId};
+    use vaelix_core::input::input::{
+        InputEvent, InputHub, InputRecord, ABS_MAX, BTN_LEFT, KEY_ENTER, KEY_F, KEY_H, KEY_J, KEY_K, KEY_L, KEY_LEFTMETA,
+        KEY_LEFTSHIFT, KEY_RIGHTMETA, KEY_RIGHTSHIFT, KEY_SPACE,
+    };
+    use vaelix_core::vxchan::vxchan::VXChanManager;
+    use std::collections::BTreeMap;
+    use std::sync::mpsc::Receiver;
+
+    pub const REQUEST_CHANNEL: &str = "vxwin";
+    pub const REPLY_CHANNEL: &str = "vxwin.reply";
+
+    // Smallest client area an interactive resize leaves
+    pub const MIN_SIZE: u32 = 32;
+
+    // Between tiled windows and around them
+    pub const TILE_GAP: u32 = 8;
+    // How much Grow and Shrink change the master area's share
+    const RATIO_STEP: f32 = 0.05;
+    pub const DEFAULT_WORKSPACES: usize = 4;
+
+    pub const EDGE_LEFT: u8 = 1;
+    pub const EDGE_RIGHT: u8 = 2;
+    pub const EDGE_TOP: u8 = 4;
+    pub const EDGE_BOTTOM: u8 = 8;
+
+    // Each surface gets its own event channel: "configure W H STATE",
+    // "focus in", "focus out", "key CODE down|up", "close", and for the
+    // pointer "enter X Y", "leave", "motion X Y", "button CODE down|up",
+    // "scroll DX DY" and "popup_done". Positions are surface-local, in
+    // logical pixels. "scale FACTOR" suggests a buffer scale for the output
+    // the surface is on.
+    pub fn event_channel(surface: SurfaceId) -> String {
+        format!("vxwin.{}", surface.0)
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct SurfaceId(pub u32);
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct BufferId(pub u32);
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum WindowState {
+        Normal,
+        Maximized,
+        Minimized,
+    }
+
+    impl WindowState {
+        pub fn name(&self) -> &'static str {
+            match self {
+                WindowState::Normal => "normal",
+                WindowState::Maximized => "maximized",
+                WindowState::Minimized => "minimized",
+            }
+        }
+    }
+
+    // How windows are laid out. Tiling layouts share each output between
+    // its windows in the tiling order, without overlap.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Layout {
+        // Windows go where they are moved
+        Floating,
+        // The first window on the left, the others stacked on the right
+        Split,
+        // Every window fills the output, the focused one on top
+        Stack,
+    }
+
+    impl Layout {
+        pub fn name(&self) -> &'static str {
+            match self {
+                Layout::Floating => "floating",
+                Layout::Split => "split",
+                Layout::Stack => "stack",
+            }
+        }
+
+        pub fn parse(name: &str) -> Result<Self, &'static str> {
+            match name {
+                "floating" => Ok(Layout::Floating),
+                "split" => Ok(Layout::Split),
+                "stack" => Ok(Layout::Stack),
+                _ => Err("Unknown layout"),
+            }
+        }
+
+        fn next(&self) -> Self {
+            match self {
+                Layout::Floating => Layout::Split,
+                Layout::Split => Layout::Stack,
+                Layout::Stack => Layout::Floating,
+            }
+        }
+    }
+
+    // What the layout key bindings do
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum TileAction {
+        FocusNext,
+        FocusPrevious,
+        // Trade places in the tiling order with the next or previous window
+        SwapNext,
+        SwapPrevious,
+        // Makes the focused window the first, or the second if it already is
+        Promote,
+        // Widen or narrow the first window's area
+        Grow,
+        Shrink,
+        ToggleFloating,
+        NextLayout,
+    }
+
+    impl TileAction {
+        pub fn name(&self) -> &'static str {
+            match self {
+                TileAction::FocusNext => "focus-next",
+                TileAction::FocusPrevious => "focus-previous",
+                TileAction::SwapNext => "swap-next",
+                TileAction::SwapPrevious => "swap-previous",
+                TileAction::Promote => "promote",
+                TileAction::Grow => "grow",
+                TileAction::Shrink => "shrink",
+                TileAction::ToggleFloating => "toggle-floating",
+                TileAction::NextLayout => "next-layout",
+            }
+        }
+
+        pub fn parse(name: &str) -> Result<Self, &'static str> {
+            match name {
+                "focus-next" => Ok(TileAction::FocusNext),
+                "focus-previous" => Ok(TileAction::FocusPrevious),
+                "swap-next" => Ok(TileAction::SwapNext),
+                "swap-previous" => Ok(TileAction::SwapPrevious),
+                "promote" => Ok(TileAction::Promote),
+                "grow" => Ok(TileAction::Grow),
+                "shrink" => Ok(TileAction::Shrink),
+                "toggle-floating" => Ok(TileAction::ToggleFloating),
+                "next-layout" => Ok(TileAction::NextLayout),
+                _ => Err("Unknown tile action"),
+            }
+        }
+    }
+
+    // Frame rectangles for a split layout of count windows in an area
+    fn split(area: Rect, count: usize, ratio: f32) -> Vec<Rect> {
+        let inner = Rect::new(
+            area.x + TILE_GAP as i32,
+            area.y + TILE_GAP as i32,
+            area.width.saturating_sub(2 * TILE_GAP),
+            area.height.saturating_sub(2 * TILE_GAP),
+        );
+        if count <= 1 {
+            return vec![inner; count];
+        }
+        let master = ((inner.width.saturating_sub(TILE_GAP)) as f32 * ratio).round() as u32;
+        let mut rects = vec![Rect::new(inner.x, inner.y, master, inner.height)];
+        let x = inner.x + (master + TILE_GAP) as i32;
+        let width = inner.width.saturating_sub(master + TILE_GAP);
+        let stacked = count as u32 - 1;
+        let height = inner.height.saturating_sub(TILE_GAP * (stacked - 1)) / stacked;
+        for index in 0..stacked {
+            let y = inner.y + (index * (height + TILE_GAP)) as i32;
+            // The last takes what division left over
+            let height = if index + 1 == stacked { (inner.bottom() - y).max(0) as u32 } else { height };
+            rects.push(Rect::new(x, y, width, height));
+        }
+        rects
+    }
+
+    // What a point in the frame around a surface does when pressed
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum FrameArea {
+        Client,
+        TitleBar,
+        Close,
+        Maximize,
+        Minimize,
+        // EDGE_* bits
+        Edge(u8),
+    }
+
+    // Server-side decorations. The frame is a window of its own, sized to
+    // the client plus the borders and title bar, placed behind the client.
+    pub trait Decorations: Send {
+        fn title_height(&self) -> u32;
+        fn border_width(&self) -> u32;
+        fn render(&self, frame: &Buffer, title: &str, focused: bool, state: WindowState);
+        // Position relative to the frame's top-left corner
+        fn hit_test(&self, width: u32, height: u32, x: i32, y: i32) -> FrameArea;
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum PointerGrabKind {
+        Popup,
+        Drag,
+    }
+
+    struct Toplevel {
+        title: String,
+        frame: WindowId,
+        window: WindowId,
+        // Frame position and client size
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+        maximized: bool,
+        minimized: bool,
+        // Geometry to go back to when unmaximized
+        restore: Option<(i32, i32, u32, u32)>,
+        // Buffer pixels per logical pixel, as declared by the client
+        buffer_scale: f32,
+        // Last scale suggested to the client
+        preferred_scale: f32,
+        // Shown over the client area
+        cursor: CursorShape,
+        // Left out of tiling layouts
+        floating: bool,
+        // Geometry from before the window was first tiled, to go back to
+        // when it floats again
+        untiled: Option<(i32, i32, u32, u32)>,
+        workspace: usize,
+    }
+
+    impl Toplevel {
+        fn state(&self) -> WindowState {
+            if self.minimized {
+                WindowState::Minimized
+            } else if self.maximized {
+                WindowState::Maximized
+            } else {
+                WindowState::Normal
+            }
+        }
+    }
+
+    // A set of windows shown together, with its own layout
+    struct Workspace {
+        name: String,
+        layout: Layout,
+        // The first window's share of the width in a split layout
+        ratio: f32,
+        // Focused when switching back
+        focus: Option<SurfaceId>,
+    }
+
+    impl Workspace {
+        fn new(index: usize) -> Self {
+            Workspace {
+                name: (index + 1).to_string(),
+                layout: Layout::Floating,
+                ratio: 0.5,
+                focus: None,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Grab {
+        Move {
+            surface: SurfaceId,
+            // Pointer offset from the frame origin
+            dx: i32,
+            dy: i32,
+        },
+        Resize {
+            surface: SurfaceId,
+            edges: u8,
+            origin: (i32, i32),
+            geometry: (i32, i32, u32, u32),
+        },
+    }
+
+    // Manages toplevel surfaces on top of the compositor: frames, focus,
+    // stacking and the window states. Events for clients are queued and
+    // delivered by WmService.
+    pub struct WindowManager {
+        compositor: Compositor,
+        decorations: Box<dyn Decorations>,
+        width: u32,
+        height: u32,
+        // Desktop areas shown by outputs, primary first
+        outputs: Vec<Viewport>,
+        toplevels: BTreeMap<SurfaceId, Toplevel>,
+        buffers: BTreeMap<BufferId, Buffer>,
+        next_surface: u32,
+        next_buffer: u32,
+        focus: Option<SurfaceId>,
+        pointer: (i32, i32),
+        grab: Option<Grab>,
+        // Client area under the pointer
+        pointer_focus: Option<SurfaceId>,
+        // Surface a button was pressed in, until all buttons are up
+        implicit_grab: Option<SurfaceId>,
+        pointer_grab: Option<(SurfaceId, PointerGrabKind)>,
+        buttons: Vec<u32>,
+        input: Option<Receiver<InputRecord>>,
+        events: Vec<(SurfaceId, String)>,
+        // Rendered from the theme; no pointer is shown until there is one
+        cursors: BTreeMap<CursorShape, CursorImage>,
+        cursor: Option<CursorShape>,
+        workspaces: Vec<Workspace>,
+        // Index of the workspace shown
+        current: usize,
+        // Every surface, in tiling order
+        order: Vec<SurfaceId>,
+        // Super plus a key, and whether shift is held too
+        bindings: BTreeMap<(u32, bool), TileAction>,
+        // Modifier keys held down
+        modifiers: Vec<u32>,
+        // Keys whose press was a binding, so their release goes nowhere
+        swallowed: Vec<u32>,
+    }
+
+    fn default_bindings() -> BTreeMap<(u32, bool), TileAction> {
+        BTreeMap::from([
+            ((KEY_J, false), TileAction::FocusNext),
+            ((KEY_K, false), TileAction::FocusPrevious),
+            ((KEY_J, true), TileAction::SwapNext),
+            ((KEY_K, true), TileAction::SwapPrevious),
+            ((KEY_ENTER, false), TileAction::Promote),
+            ((KEY_L, false), TileAction::Grow),
+            ((KEY_H, false), TileAction::Shrink),
+            ((KEY_F, false), TileAction::ToggleFloating),
+            ((KEY_SPACE, false), TileAction::NextLayout),
+        ])
+    }
+
+    impl WindowManager {
+        pub fn new(width: u32, height: u32, decorations: Box<dyn Decorations>) -> Self {
+            WindowManager {
+                compositor: Compositor::new(width, height),
+                decorations,
+                width,
+                height,
+                outputs: vec![Viewport::new(Rect::new(0, 0, width, height), 1.0)],
+                toplevels: BTreeMap::new(),
+                buffers: BTreeMap::new(),
+                next_surface: 1,
+                next_buffer: 1,
+                focus: None,
+                pointer: (0, 0),
+                grab: None,
+                pointer_focus: None,
+                implicit_grab: None,
+                pointer_grab: None,
+                buttons: Vec::new(),
+                input: None,
+                events: Vec::new(),
+                cursors: BTreeMap::new(),
+                cursor: None,
+                workspaces: (0..DEFAULT_WORKSPACES).map(Workspace::new).collect(),
+                current: 0,
+                order: Vec::new(),
+                bindings: default_bindings(),
+                modifiers: Vec::new(),
+                swallowed: Vec::new(),
+            }
+        }
+
+        pub fn compositor(&self) -> &Compositor {
+            &self.compositor
+        }
+
+        pub fn compositor_mut(&mut self) -> &mut Compositor {
+            &mut self.compositor
+        }
+
+        // Makes a client buffer known so requests can refer to it by id
+        pub fn register_buffer(&mut self, buffer: Buffer) -> BufferId {
+            let id = BufferId(self.next_buffer);
+            self.next_buffer += 1;
+            self.buffers.insert(id, buffer);
+            id
+        }
+
+        pub fn release_buffer(&mut self, id: BufferId) -> Result<(), &'static str> {
+            self.buffers.remove(&id).map(|_| ()).ok_or("Buffer not found")
+        }
+
+        fn post(&mut self, surface: SurfaceId, event: String) {
+            self.events.push((surface, event));
+        }
+
+        pub fn take_events(&mut self) -> Vec<(SurfaceId, String)> {
+            std::mem::take(&mut self.events)
+        }
+
+        fn toplevel(&self, surface: SurfaceId) -> Result<&Toplevel, &'static str> {
+            self.toplevels.get(&surface).ok_or("Surface not found")
+        }
+
+        fn toplevel_mut(&mut self, surface: SurfaceId) -> Result<&mut Toplevel, &'static str> {
+            self.toplevels.get_mut(&surface).ok_or("Surface not found")
+        }
+
+        // New surfaces are placed in a cascade and asked for their size;
+        // they appear once the client commits a buffer
+        pub fn create_surface(&mut self, title: &str, width: u32, height: u32) -> SurfaceId {
+            let surface = SurfaceId(self.next_surface);
+            self.next_surface += 1;
+            let offset = (self.toplevels.len() as i32 % 8) * 32;
+            let frame = self.compositor.create_window(offset, offset);
+            let window = self.compositor.create_window(0, 0);
+            self.toplevels.insert(
+                surface,
+                Toplevel {
+                    title: title.to_string(),
+                    frame,
+                    window,
+                    x: offset,
+                    y: offset,
+                    width: width.max(MIN_SIZE),
+                    height: height.max(MIN_SIZE),
+                    maximized: false,
+                    minimized: false,
+                    restore: None,
+                    buffer_scale: 1.0,
+                    preferred_scale: 1.0,
+                    cursor: CursorShape::Default,
+                    floating: false,
+                    untiled: None,
+                    workspace: self.current,
+                },
+            );
+            self.order.push(surface);
+            let size = (width.max(MIN_SIZE), height.max(MIN_SIZE));
+            self.arrange();
+            // Tiling already told the client if it changed the size
+            if self.geometry(surface).is_some_and(|(_, _, width, height)| (width, height) == size) {
+                self.place(surface);
+                self.configure(surface);
+            }
+            self.suggest_scale(surface);
+            self.focus(surface).unwrap();
+            surface
+        }
+
+        pub fn destroy_surface(&mut self, surface: SurfaceId) -> Result<(), &'static str> {
+            let toplevel = self.toplevels.remove(&surface).ok_or("Surface not found")?;
+            self.compositor.destroy_window(toplevel.frame)?;
+            self.compositor.destroy_window(toplevel.window)?;
+            self.order.retain(|other| *other != surface);
+            for workspace in &mut self.workspaces {
+                if workspace.focus == Some(surface) {
+                    workspace.focus = None;
+                }
+            }
+            self.arrange();
+            if self.grab.is_some_and(|grab| Self::grab_surface(grab) == surface) {
+                self.grab = None;
+            }
+            if self.pointer_grab.is_some_and(|(grab, _)| grab == surface) {
+                self.pointer_grab = None;
+            }
+            if self.implicit_grab == Some(surface) {
+                self.implicit_grab = None;
+            }
+            if self.pointer_focus == Some(surface) {
+                self.pointer_focus = None;
+            }
+            self.update_pointer_focus();
+            if self.focus == Some(surface) {
+                self.focus = None;
+                self.focus_topmost();
+            }
+            self.update_cursor();
+            Ok(())
+        }
+
+        pub fn surfaces(&self) -> Vec<SurfaceId> {
+            self.toplevels.keys().copied().collect()
+        }
+
+        pub fn title(&self, surface: SurfaceId) -> Option<&str> {
+            self.toplevels.get(&surface).map(|toplevel| toplevel.title.as_str())
+        }
+
+        pub fn set_title(&mut self, surface: SurfaceId, title: &str) -> Result<(), &'static str> {
+            self.toplevel_mut(surface)?.title = title.to_string();
+            self.redecorate(surface);
+            Ok(())
+        }
+
+        // The frame and client windows in the compositor
+        pub fn windows(&self, surface: SurfaceId) -> Option<(WindowId, WindowId)> {
+            self.toplevels.get(&surface).map(|toplevel| (toplevel.frame, toplevel.window))
+        }
+
+        // Frame position and client size
+        pub fn geometry(&self, surface: SurfaceId) -> Option<(i32, i32, u32, u32)> {
+            let toplevel = self.toplevels.get(&surface)?;
+            Some((toplevel.x, toplevel.y, toplevel.width, toplevel.height))
+        }
+
+        pub fn state(&self, surface: SurfaceId) -> Option<WindowState> {
+            self.toplevels.get(&surface).map(Toplevel::state)
+        }
+
+        pub fn focused(&self) -> Option<SurfaceId> {
+            self.focus
+        }
+
+        fn frame_size(&self, width: u32, height: u32) -> (u32, u32) {
+            let (border, title) = (self.decorations.border_width(), self.decorations.title_height());
+            (width + 2 * border, height + title + border)
+        }
+
+        fn configure(&mut self, surface: SurfaceId) {
+            let toplevel = &self.toplevels[&surface];
+            let event = format!("configure {} {} {}", toplevel.width, toplevel.height, toplevel.state().name());
+            self.post(surface, event);
+        }
+
+        // Repaints the frame at the current size, title and focus. Frames
+        // only appear once the client has a buffer up.
+        fn redecorate(&mut self, surface: SurfaceId) {
+            let toplevel = &self.toplevels[&surface];
+            if !self.compositor.is_mapped(toplevel.window) {
+                return;
+            }
+            let (width, height) = self.frame_size(toplevel.width, toplevel.height);
+            let buffer = Buffer::new(width, height, BufferStorage::SharedMemory);
+            let focused = self.focus == Some(surface);
+            self.decorations.render(&buffer, &toplevel.title, focused, toplevel.state());
+            let frame = toplevel.frame;
+            self.compositor.attach(frame, buffer).unwrap();
+            self.compositor.commit(frame).unwrap();
+        }
+
+        // Moves the frame and client windows to the toplevel's position
+        fn place(&mut self, surface: SurfaceId) {
+            let (border, title) = (self.decorations.border_width() as i32, self.decorations.title_height() as i32);
+            let toplevel = &self.toplevels[&surface];
+            let (frame, window, x, y) = (toplevel.frame, toplevel.window, toplevel.x, toplevel.y);
+            self.compositor.move_window(frame, x, y).unwrap();
+            self.compositor.move_window(window, x + border, y + title).unwrap();
+        }
+
+        pub fn attach(&mut self, surface: SurfaceId, buffer: BufferId) -> Result<(), &'static str> {
+            let buffer = self.buffers.get(&buffer).ok_or("Buffer not found")?.clone();
+            let window = self.toplevel(surface)?.window;
+            self.compositor.attach(window, buffer)
+        }
+
+        pub fn damage(&mut self, surface: SurfaceId, area: Rect) -> Result<(), &'static str> {
+            let window = self.toplevel(surface)?.window;
+            self.compositor.damage(window, area)
+        }
+
+        // The committed buffer decides the client size; the frame follows
+        pub fn commit(&mut self, surface: SurfaceId) -> Result<(), &'static str> {
+            let toplevel = self.toplevel(surface)?;
+            let (window, scale) = (toplevel.window, toplevel.buffer_scale);
+            // Sizes in the window manager are logical
+            let size = self.compositor.pending_size(window).map(|(width, height)| {
+                let logical = |size: u32| ((size as f32 / scale).round() as u32).max(1);
+                (logical(width), logical(height))
+            });
+            if let Some(size) = size {
+                self.compositor.set_destination(window, (scale != 1.0).then_some(size))?;
+            }
+            self.compositor.commit(window)?;
+            let Some((width, height)) = size else {
+                return Ok(());
+            };
+            let toplevel = self.toplevel_mut(surface)?;
+            let resized = (toplevel.width, toplevel.height) != (width, height);
+            (toplevel.width, toplevel.height) = (width, height);
+            let frame = toplevel.frame;
+            if resized || !self.compositor.is_mapped(frame) {
+                self.redecorate(surface);
+            }
+            Ok(())
+        }
+
+        fn focus_topmost(&mut self) {
+            let topmost = self.compositor.stacking_order().iter().rev().find_map(|window| {
+                self.toplevels
+                    .iter()
+                    .find(|(_, toplevel)| toplevel.window == *window && !toplevel.minimized && toplevel.workspace == self.current)
+                    .map(|(surface, _)| *surface)
+            });
+            if let Some(surface) = topmost {
+                self.focus(surface).unwrap();
+            }
+        }
+
+        // Raises the surface and gives it the keyboard; a minimized surface
+        // is restored first, and its workspace shown
+        pub fn focus(&mut self, surface: SurfaceId) -> Result<(), &'static str> {
+            let toplevel = self.toplevel(surface)?;
+            let (frame, window, minimized, workspace) = (toplevel.frame, toplevel.window, toplevel.minimized, toplevel.workspace);
+            if workspace != self.current {
+                self.workspaces[workspace].focus = Some(surface);
+                self.switch_workspace(workspace)?;
+            }
+            if minimized {
+                self.toplevel_mut(surface)?.minimized = false;
+                self.compositor.set_visible(frame, true)?;
+                self.compositor.set_visible(window, true)?;
+                self.configure(surface);
+                self.arrange();
+            }
+            self.compositor.raise(frame)?;
+            self.compositor.raise(window)?;
+            if self.focus == Some(surface) {
+                return Ok(());
+            }
+            if let Some(previous) = self.focus.replace(surface) {
+                if self.toplevels.contains_key(&previous) {
+                    self.post(previous, "focus out".to_string());
+                    self.redecorate(previous);
+                }
+            }
+            self.workspaces[workspace].focus = Some(surface);
+            self.post(surface, "focus in".to_string());
+            self.redecorate(surface);
+            Ok(())
+        }
+
+        pub fn minimize(&mut self, surface: SurfaceId) -> Result<(), &'static str> {
+            let toplevel = self.toplevel_mut(surface)?;
+            if toplevel.minimized {
+                return Ok(());
+            }
+            toplevel.minimized = true;
+            let (frame, window) = (toplevel.frame, toplevel.window);
+            self.compositor.set_visible(frame, false)?;
+            self.compositor.set_visible(window, false)?;
+            self.configure(surface);
+            self.arrange();
+            if self.focus == Some(surface) {
+                self.post(surface, "focus out".to_string());
+                self.focus = None;
+                self.focus_topmost();
+            }
+            Ok(())
+        }
+
+        // Fills the output the window is on; the client is asked to resize
+        // and the frame catches up when it commits
+        pub fn maximize(&mut self, surface: SurfaceId) -> Result<(), &'static str> {
+            let toplevel = self.toplevel(surface)?;
+            if toplevel.maximized {
+                return Ok(());
+            }
+            let output = self.output_of(toplevel.x, toplevel.y, toplevel.width, toplevel.height).area;
+            let toplevel = self.toplevel_mut(surface)?;
+            toplevel.restore = Some((toplevel.x, toplevel.y, toplevel.width, toplevel.height));
+            toplevel.maximized = true;
+            self.fill_output(surface, output);
+            self.arrange();
+            self.focus(surface)
+        }
+
+        fn fill_output(&mut self, surface: SurfaceId, output: Rect) {
+            let (frame_width, frame_height) = self.frame_size(0, 0);
+            let toplevel = self.toplevels.get_mut(&surface).unwrap();
+            (toplevel.x, toplevel.y) = (output.x, output.y);
+            toplevel.width = output.width.saturating_sub(frame_width);
+            toplevel.height = output.height.saturating_sub(frame_height);
+            self.place(surface);
+            self.configure(surface);
+        }
+
+        // The output showing most of the given frame position and client
+        // size, or the primary when none does
+        fn output_of(&self, x: i32, y: i32, width: u32, height: u32) -> Viewport {
+            let (frame_width, frame_height) = self.frame_size(width, height);
+            let frame = Rect::new(x, y, frame_width, frame_height);
+            let overlap = |output: &Viewport| output.area.intersect(&frame).map_or(0, |area| area.width as u64 * area.height as u64);
+            self.outputs
+                .iter()
+                .copied()
+                .filter(|output| overlap(output) > 0)
+                .max_by_key(overlap)
+                .unwrap_or(self.outputs[0])
+        }
+
+        pub fn outputs(&self) -> &[Viewport] {
+            &self.outputs
+        }
+
+        // Takes a new output layout, primary first, in desktop coordinates
+        // from (0, 0). Windows left on no output move to the primary at the
+        // same offset they had on their old output; maximized windows are
+        // refitted to the output they end up on.
+        pub fn set_outputs(&mut self, outputs: &[Viewport]) -> Result<(), &'static str> {
+            let first = outputs.first().ok_or("No outputs")?.area;
+            let bounds = outputs.iter().fold(first, |bounds, output| bounds.union(&output.area));
+            if bounds.x < 0 || bounds.y < 0 {
+                return Err("Outputs must start at the desktop origin");
+            }
+            let old = std::mem::replace(&mut self.outputs, outputs.to_vec());
+            (self.width, self.height) = (bounds.right() as u32, bounds.bottom() as u32);
+            self.compositor.resize(self.width, self.height);
+            let surfaces: Vec<SurfaceId> = self.toplevels.keys().copied().collect();
+            for surface in surfaces {
+                let toplevel = &self.toplevels[&surface];
+                let (x, y, width, height) = (toplevel.x, toplevel.y, toplevel.width, toplevel.height);
+                let (frame_width, frame_height) = self.frame_size(width, height);
+                let frame = Rect::new(x, y, frame_width, frame_height);
+                let stranded = !self.outputs.iter().any(|output| output.area.intersect(&frame).is_some());
+                if stranded {
+                    let previous = old
+                        .iter()
+                        .map(|output| output.area)
+                        .find(|output| output.intersect(&frame).is_some())
+                        .unwrap_or(first);
+                    let toplevel = self.toplevels.get_mut(&surface).unwrap();
+                    // Kept on screen when the primary is the smaller one
+                    let max_x = (first.width as i32 - frame_width as i32).max(0);
+                    let max_y = (first.height as i32 - frame_height as i32).max(0);
+                    toplevel.x = first.x + (x - previous.x).clamp(0, max_x);
+                    toplevel.y = first.y + (y - previous.y).clamp(0, max_y);
+                    if let Some(restore) = &mut toplevel.restore {
+                        (restore.0, restore.1) = (first.x, first.y);
+                    }
+                }
+                let toplevel = &self.toplevels[&surface];
+                if toplevel.maximized {
+                    let output = self.output_of(toplevel.x, toplevel.y, toplevel.width, toplevel.height);
+                    self.fill_output(surface, output.area);
+                } else {
+                    self.place(surface);
+                }
+                self.suggest_scale(surface);
+            }
+            self.arrange();
+            self.pointer_motion(self.pointer.0, self.pointer.1);
+            Ok(())
+        }
+
+        // Tells the client when the output it is mostly on has a different
+        // scale from the one it was last told
+        fn suggest_scale(&mut self, surface: SurfaceId) {
+            let toplevel = &self.toplevels[&surface];
+            let scale = self.output_of(toplevel.x, toplevel.y, toplevel.width, toplevel.height).scale;
+            if scale != toplevel.preferred_scale {
+                self.toplevels.get_mut(&surface).unwrap().preferred_scale = scale;
+                self.post(surface, format!("scale {}", scale));
+            }
+        }
+
+        // Buffers committed from now on are shown at their size divided by
+        // the scale, so a client can draw at the output's density
+        pub fn set_buffer_scale(&mut self, surface: SurfaceId, scale: f32) -> Result<(), &'static str> {
+            if !(scale >= 1.0 && scale.is_finite()) {
+                return Err("Invalid buffer scale");
+            }
+            self.toplevel_mut(surface)?.buffer_scale = scale;
+            Ok(())
+        }
+
+        // Back to normal from either maximized or minimized
+        pub fn restore(&mut self, surface: SurfaceId) -> Result<(), &'static str> {
+            if self.toplevel(surface)?.minimized {
+                return self.focus(surface);
+            }
+            let toplevel = self.toplevel_mut(surface)?;
+            let Some(geometry) = toplevel.restore.take() else {
+                return Ok(());
+            };
+            toplevel.maximized = false;
+            (toplevel.x, toplevel.y, toplevel.width, toplevel.height) = geometry;
+            self.place(surface);
+            self.configure(surface);
+            self.arrange();
+            Ok(())
+        }
+
+        fn is_tiled(&self, toplevel: &Toplevel) -> bool {
+            let layout = self.workspaces[toplevel.workspace].layout;
+            layout != Layout::Floating && !toplevel.floating && !toplevel.minimized && !toplevel.maximized
+        }
+
+        // The current workspace's layout
+        pub fn layout(&self) -> Layout {
+            self.workspaces[self.current].layout
+        }
+
+        // Windows go back to where they were before tiling when switching
+        // to the floating layout
+        pub fn set_layout(&mut self, layout: Layout) {
+            if layout == self.layout() {
+                return;
+            }
+            log::info!("Switching to the {} layout", layout.name());
+            self.workspaces[self.current].layout = layout;
+            if layout == Layout::Floating {
+                for surface in self.surfaces_on(self.current) {
+                    self.untile(surface);
+                }
+            }
+            self.arrange();
+        }
+
+        fn tiled_on(&self, workspace: usize) -> Vec<SurfaceId> {
+            self.surfaces_on(workspace).into_iter().filter(|surface| self.is_tiled(&self.toplevels[surface])).collect()
+        }
+
+        // Surfaces the current workspace's layout lays out, in tiling order
+        pub fn tiled(&self) -> Vec<SurfaceId> {
+            self.tiled_on(self.current)
+        }
+
+        pub fn is_floating(&self, surface: SurfaceId) -> Option<bool> {
+            self.toplevels.get(&surface).map(|toplevel| toplevel.floating)
+        }
+
+        // Floating windows stay out of tiling layouts, where they were
+        // before they were tiled
+        pub fn set_floating(&mut self, surface: SurfaceId, floating: bool) -> Result<(), &'static str> {
+            let toplevel = self.toplevel_mut(surface)?;
+            if toplevel.floating == floating {
+                return Ok(());
+            }
+            toplevel.floating = floating;
+            if floating {
+                self.untile(surface);
+                let (frame, window) = (self.toplevels[&surface].frame, self.toplevels[&surface].window);
+                self.compositor.raise(frame)?;
+                self.compositor.raise(window)?;
+            }
+            self.arrange();
+            Ok(())
+        }
+
+        fn untile(&mut self, surface: SurfaceId) {
+            let toplevel = self.toplevels.get_mut(&surface).unwrap();
+            let Some(geometry) = toplevel.untiled.take() else {
+                return;
+            };
+            if toplevel.maximized {
+                toplevel.restore = Some(geometry);
+                return;
+            }
+            let resized = (toplevel.width, toplevel.height) != (geometry.2, geometry.3);
+            (toplevel.x, toplevel.y, toplevel.width, toplevel.height) = geometry;
+            self.place(surface);
+            if resized {
+                self.configure(surface);
+            }
+        }
+
+        // Lays the tiled windows of every workspace out again on each
+        // output, asking clients whose size changed to resize
+        fn arrange(&mut self) {
+            for workspace in 0..self.workspaces.len() {
+                let mut outputs: Vec<(Rect, Vec<SurfaceId>)> = Vec::new();
+                for surface in self.tiled_on(workspace) {
+                    let toplevel = &self.toplevels[&surface];
+                    let area = self.output_of(toplevel.x, toplevel.y, toplevel.width, toplevel.height).area;
+                    match outputs.iter_mut().find(|(output, _)| *output == area) {
+                        Some((_, surfaces)) => surfaces.push(surface),
+                        None => outputs.push((area, vec![surface])),
+                    }
+                }
+                let Workspace { layout, ratio, .. } = self.workspaces[workspace];
+                for (area, surfaces) in outputs {
+                    let rects = match layout {
+                        Layout::Stack => vec![Rect::new(area.x, area.y, area.width, area.height); surfaces.len()],
+                        _ => split(area, surfaces.len(), ratio),
+                    };
+                    for (surface, rect) in surfaces.into_iter().zip(rects) {
+                        self.fit(surface, rect);
+                    }
+                }
+            }
+            if let Some(focus) = self.focus.filter(|_| self.layout() == Layout::Stack) {
+                let toplevel = &self.toplevels[&focus];
+                let (frame, window) = (toplevel.frame, toplevel.window);
+                self.compositor.raise(frame).unwrap();
+                self.compositor.raise(window).unwrap();
+            }
+        }
+
+        // Puts a frame in the rectangle
+        fn fit(&mut self, surface: SurfaceId, rect: Rect) {
+            let (frame_width, frame_height) = self.frame_size(0, 0);
+            let toplevel = self.toplevels.get_mut(&surface).unwrap();
+            if toplevel.untiled.is_none() {
+                toplevel.untiled = Some((toplevel.x, toplevel.y, toplevel.width, toplevel.height));
+            }
+            let width = rect.width.saturating_sub(frame_width).max(MIN_SIZE);
+            let height = rect.height.saturating_sub(frame_height).max(MIN_SIZE);
+            let resized = (toplevel.width, toplevel.height) != (width, height);
+            (toplevel.x, toplevel.y, toplevel.width, toplevel.height) = (rect.x, rect.y, width, height);
+            self.place(surface);
+            if resized {
+                self.configure(surface);
+            }
+        }
+
+        // Steps through the current workspace's windows that are not
+        // minimized, in tiling order
+        fn cycle_focus(&mut self, step: isize) -> Result<(), &'static str> {
+            let visible: Vec<SurfaceId> =
+                self.surfaces_on(self.current).into_iter().filter(|surface| !self.toplevels[surface].minimized).collect();
+            if visible.is_empty() {
+                return Ok(());
+            }
+            let next = match self.focus.and_then(|focus| visible.iter().position(|surface| *surface == focus)) {
+                Some(index) => (index as isize + step).rem_euclid(visible.len() as isize) as usize,
+                None => 0,
+            };
+            self.focus(visible[next])
+        }
+
+        fn swap(&mut self, surface: SurfaceId, step: isize) {
+            let tiled = self.tiled();
+            let Some(index) = tiled.iter().position(|other| *other == surface) else {
+                return;
+            };
+            let other = tiled[(index as isize + step).rem_euclid(tiled.len() as isize) as usize];
+            let (a, b) = (self.order.iter().position(|id| *id == surface).unwrap(), self.order.iter().position(|id| *id == other).unwrap());
+            self.order.swap(a, b);
+            self.arrange();
+        }
+
+        pub fn perform(&mut self, action: TileAction) -> Result<(), &'static str> {
+            match action {
+                TileAction::FocusNext => return self.cycle_focus(1),
+                TileAction::FocusPrevious => return self.cycle_focus(-1),
+                TileAction::Grow | TileAction::Shrink => {
+                    let step = if action == TileAction::Grow { RATIO_STEP } else { -RATIO_STEP };
+                    let workspace = &mut self.workspaces[self.current];
+                    workspace.ratio = (workspace.ratio + step).clamp(0.1, 0.9);
+                    self.arrange();
+                    return Ok(());
+                }
+                TileAction::NextLayout => {
+                    self.set_layout(self.layout().next());
+                    return Ok(());
+                }
+                _ => {}
+            }
+            let surface = self.focus.ok_or("No surface has focus")?;
+            match action {
+                TileAction::SwapNext => self.swap(surface, 1),
+                TileAction::SwapPrevious => self.swap(surface, -1),
+                TileAction::Promote => {
+                    let tiled = self.tiled();
+                    let target = match tiled.as_slice() {
+                        [first, second, ..] if *first == surface => *second,
+                        _ if tiled.contains(&surface) => surface,
+                        _ => return Ok(()),
+                    };
+                    self.order.retain(|other| *other != target);
+                    self.order.insert(0, target);
+                    self.arrange();
+                }
+                TileAction::ToggleFloating => {
+                    let floating = self.toplevels[&surface].floating;
+                    self.set_floating(surface, !floating)?;
+                }
+                _ => unreachable!(),
+            }
+            Ok(())
+        }
+
+        pub fn workspaces(&self) -> Vec<&str> {
+            self.workspaces.iter().map(|workspace| workspace.name.as_str()).collect()
+        }
+
+        pub fn current_workspace(&self) -> usize {
+            self.current
+        }
+
+        pub fn workspace_of(&self, surface: SurfaceId) -> Option<usize> {
+            self.toplevels.get(&surface).map(|toplevel| toplevel.workspace)
+        }
+
+        // In tiling order
+        pub fn surfaces_on(&self, workspace: usize) -> Vec<SurfaceId> {
+            self.order.iter().copied().filter(|surface| self.toplevels[surface].workspace == workspace).collect()
+        }
+
+        pub fn set_workspace_name(&mut self, workspace: usize, name: &str) -> Result<(), &'static str> {
+            self.workspaces.get_mut(workspace).ok_or("Workspace not found")?.name = name.to_string();
+            Ok(())
+        }
+
+        // Windows on workspaces that go away move to the last one left
+        pub fn set_workspace_count(&mut self, count: usize) -> Result<(), &'static str> {
+            if count == 0 {
+                return Err("At least one workspace is needed");
+            }
+            if self.current >= count {
+                self.switch_workspace(count - 1)?;
+            }
+            for surface in self.order.clone() {
+                if self.toplevels[&surface].workspace >= count {
+                    self.move_to_workspace(surface, count - 1)?;
+                }
+            }
+            let existing = self.workspaces.len();
+            self.workspaces.truncate(count);
+            self.workspaces.extend((existing..count).map(Workspace::new));
+            Ok(())
+        }
+
+        // Shown on the current workspace unless minimized
+        fn update_visibility(&mut self, surface: SurfaceId) {
+            let toplevel = &self.toplevels[&surface];
+            let visible = toplevel.workspace == self.current && !toplevel.minimized;
+            let (frame, window) = (toplevel.frame, toplevel.window);
+            self.compositor.set_visible(frame, visible).unwrap();
+            self.compositor.set_visible(window, visible).unwrap();
+        }
+
+        // Hides the windows of the workspace shown and shows those of
+        // another, focusing the window that had focus there last
+        pub fn switch_workspace(&mut self, workspace: usize) -> Result<(), &'static str> {
+            if workspace >= self.workspaces.len() {
+                return Err("Workspace not found");
+            }
+            if workspace == self.current {
+                return Ok(());
+            }
+            log::info!("Switching to workspace {}", self.workspaces[workspace].name);
+            let leaving = std::mem::replace(&mut self.current, workspace);
+            self.workspaces[leaving].focus = self.focus;
+            for surface in self.order.clone() {
+                let on = self.toplevels[&surface].workspace;
+                if on == leaving || on == workspace {
+                    self.update_visibility(surface);
+                }
+            }
+            self.grab = None;
+            if let Some((surface, _)) = self.pointer_grab.take() {
+                self.post(surface, "popup_done".to_string());
+            }
+            if let Some(previous) = self.focus.take() {
+                self.post(previous, "focus out".to_string());
+                self.redecorate(previous);
+            }
+            let remembered = self.workspaces[workspace]
+                .focus
+                .filter(|surface| self.toplevels.get(surface).is_some_and(|toplevel| toplevel.workspace == workspace && !toplevel.minimized));
+            match remembered {
+                Some(surface) => self.focus(surface)?,
+                None => self.focus_topmost(),
+            }
+            self.update_pointer_focus();
+            self.update_cursor();
+            Ok(())
+        }
+
+        // Sends a window to another workspace, where it keeps its place
+        pub fn move_to_workspace(&mut self, surface: SurfaceId, workspace: usize) -> Result<(), &'static str> {
+            if workspace >= self.workspaces.len() {
+                return Err("Workspace not found");
+            }
+            let toplevel = self.toplevel_mut(surface)?;
+            let leaving = std::mem::replace(&mut toplevel.workspace, workspace);
+            if leaving == workspace {
+                return Ok(());
+            }
+            if self.workspaces[leaving].focus == Some(surface) {
+                self.workspaces[leaving].focus = None;
+            }
+            // Floating workspaces put it back where it was before tiling
+            if self.workspaces[workspace].layout == Layout::Floating {
+                self.untile(surface);
+            }
+            self.update_visibility(surface);
+            if self.focus == Some(surface) && workspace != self.current {
+                self.post(surface, "focus out".to_string());
+                self.redecorate(surface);
+                self.focus = None;
+                self.focus_topmost();
+            }
+            self.arrange();
+            self.update_pointer_focus();
+            Ok(())
+        }
+
+        // Binds Super plus the key, with or without shift; None removes
+        // the binding
+        pub fn bind(&mut self, code: u32, shift: bool, action: Option<TileAction>) {
+            match action {
+                Some(action) => self.bindings.insert((code, shift), action),
+                None => self.bindings.remove(&(code, shift)),
+            };
+        }
+
+        pub fn bindings(&self) -> Vec<(u32, bool, TileAction)> {
+            self.bindings.iter().map(|((code, shift), action)| (*code, *shift, *action)).collect()
+        }
+
+        // Input from the keyboard goes to the focused surface, or to a popup
+        // holding a grab. Key bindings are taken first and go nowhere.
+        pub fn key(&mut self, code: u32, pressed: bool) -> Option<SurfaceId> {
+            if matches!(code, KEY_LEFTMETA | KEY_RIGHTMETA | KEY_LEFTSHIFT | KEY_RIGHTSHIFT) {
+                self.modifiers.retain(|held| *held != code);
+                if pressed {
+                    self.modifiers.push(code);
+                }
+            }
+            let held = |keys: [u32; 2]| self.modifiers.iter().any(|code| keys.contains(code));
+            let (meta, shift) = (held([KEY_LEFTMETA, KEY_RIGHTMETA]), held([KEY_LEFTSHIFT, KEY_RIGHTSHIFT]));
+            let binding = self.bindings.get(&(code, shift)).copied().filter(|_| pressed && meta);
+            if let Some(action) = binding {
+                self.swallowed.push(code);
+                if let Err(error) = self.perform(action) {
+                    log::warn!("Key binding {} failed: {}", action.name(), error);
+                }
+                return None;
+            }
+            if !pressed && self.swallowed.contains(&code) {
+                self.swallowed.retain(|swallowed| *swallowed != code);
+                return None;
+            }
+            let popup = self.pointer_grab.filter(|(_, kind)| *kind == PointerGrabKind::Popup);
+            let surface = popup.map(|(surface, _)| surface).or(self.focus)?;
+            self.post(surface, format!("key {} {}", code, if pressed { "down" } else { "up" }));
+            Some(surface)
+        }
+
+        fn surface_at(&self, x: i32, y: i32) -> Option<(SurfaceId, bool)> {
+            let window = self.compositor.window_at(x, y)?;
+            self.toplevels.iter().find_map(|(surface, toplevel)| {
+                (toplevel.window == window || toplevel.frame == window).then_some((*surface, toplevel.frame == window))
+            })
+        }
+
+        fn grab_surface(grab: Grab) -> SurfaceId {
+            match grab {
+                Grab::Move { surface, .. } | Grab::Resize { surface, .. } => surface,
+            }
+        }
+
+        // Starts an interactive move from the current pointer position, e.g.
+        // when the client's own title area is dragged
+        pub fn begin_move(&mut self, surface: SurfaceId) -> Result<(), &'static str> {
+            let toplevel = self.toplevel(surface)?;
+            if toplevel.maximized {
+                return Err("Surface is maximized");
+            }
+            if self.is_tiled(toplevel) {
+                return Err("Surface is tiled");
+            }
+            let (dx, dy) = (self.pointer.0 - toplevel.x, self.pointer.1 - toplevel.y);
+            self.grab = Some(Grab::Move { surface, dx, dy });
+            self.update_cursor();
+            Ok(())
+        }
+
+        pub fn begin_resize(&mut self, surface: SurfaceId, edges: u8) -> Result<(), &'static str> {
+            let toplevel = self.toplevel(surface)?;
+            if toplevel.maximized {
+                return Err("Surface is maximized");
+            }
+            if self.is_tiled(toplevel) {
+                return Err("Surface is tiled");
+            }
+            if edges == 0 || edges & !(EDGE_LEFT | EDGE_RIGHT | EDGE_TOP | EDGE_BOTTOM) != 0 {
+                return Err("Invalid resize edges");
+            }
+            self.grab = Some(Grab::Resize {
+                surface,
+                edges,
+                origin: self.pointer,
+                geometry: (toplevel.x, toplevel.y, toplevel.width, toplevel.height),
+            });
+            self.update_cursor();
+            Ok(())
+        }
+
+        pub fn pointer_position(&self) -> (i32, i32) {
+            self.pointer
+        }
+
+        pub fn pointer_focus(&self) -> Option<SurfaceId> {
+            self.pointer_focus
+        }
+
+        fn local(&self, surface: SurfaceId, x: i32, y: i32) -> (i32, i32) {
+            let toplevel = &self.toplevels[&surface];
+            let (border, title) = (self.decorations.border_width() as i32, self.decorations.title_height() as i32);
+            (x - toplevel.x - border, y - toplevel.y - title)
+        }
+
+        // An explicit grab wins, then the surface a button went down in,
+        // then whatever is under the pointer
+        fn pointer_target(&self) -> Option<SurfaceId> {
+            self.pointer_grab.map(|(surface, _)| surface).or(self.implicit_grab).or(self.pointer_focus)
+        }
+
+        // Follows the client area under the pointer with enter and leave
+        // events; held while any grab is active
+        fn update_pointer_focus(&mut self) {
+            if self.pointer_grab.is_some() || self.implicit_grab.is_some() {
+                return;
+            }
+            let (x, y) = self.pointer;
+            let under = self.surface_at(x, y).filter(|(_, on_frame)| !on_frame).map(|(surface, _)| surface);
+            if under == self.pointer_focus {
+                return;
+            }
+            if let Some(previous) = std::mem::replace(&mut self.pointer_focus, under) {
+                self.post(previous, "leave".to_string());
+            }
+            if let Some(surface) = under {
+                let (x, y) = self.local(surface, x, y);
+                self.post(surface, format!("enter {} {}", x, y));
+            }
+        }
+
+        pub fn pointer_motion(&mut self, x: i32, y: i32) {
+            self.track_pointer(x, y);
+            self.update_cursor();
+        }
+
+        fn track_pointer(&mut self, x: i32, y: i32) {
+            // Kept to the nearest output where they differ in size
+            let (x, y) = self
+                .outputs
+                .iter()
+                .map(|output| {
+                    let x = x.clamp(output.area.x, output.area.right() - 1);
+                    let y = y.clamp(output.area.y, output.area.bottom() - 1);
+                    (x, y)
+                })
+                .min_by_key(|(clamped_x, clamped_y)| (clamped_x - x).abs() + (clamped_y - y).abs())
+                .unwrap();
+            self.pointer = (x, y);
+            match self.grab {
+                Some(Grab::Move { surface, dx, dy }) => {
+                    let toplevel = self.toplevels.get_mut(&surface).unwrap();
+                    (toplevel.x, toplevel.y) = (x - dx, y - dy);
+                    self.place(surface);
+                    return;
+                }
+                Some(Grab::Resize {
+                    surface,
+                    edges,
+                    origin,
+                    geometry: (left, top, width, height),
+                }) => {
+                    let (dx, dy) = (x - origin.0, y - origin.1);
+                    let resize = |size: u32, delta: i32| (size as i32 + delta).max(MIN_SIZE as i32) as u32;
+                    let toplevel = self.toplevels.get_mut(&surface).unwrap();
+                    if edges & EDGE_LEFT != 0 {
+                        toplevel.width = resize(width, -dx);
+                        toplevel.x = left + width as i32 - toplevel.width as i32;
+                    } else if edges & EDGE_RIGHT != 0 {
+                        toplevel.width = resize(width, dx);
+                    }
+                    if edges & EDGE_TOP != 0 {
+                        toplevel.height = resize(height, -dy);
+                        toplevel.y = top + height as i32 - toplevel.height as i32;
+                    } else if edges & EDGE_BOTTOM != 0 {
+                        toplevel.height = resize(height, dy);
+                    }
+                    self.place(surface);
+                    self.configure(surface);
+                    return;
+                }
+                None => {}
+            }
+            self.update_pointer_focus();
+            if let Some(surface) = self.pointer_target() {
+                let (x, y) = self.local(surface, x, y);
+                self.post(surface, format!("motion {} {}", x, y));
+            }
+        }
+
+        fn button_released(&mut self, code: u32) {
+            self.buttons.retain(|button| *button != code);
+            if let Some(surface) = self.pointer_target() {
+                self.post(surface, format!("button {} up", code));
+            }
+            if self.buttons.is_empty() {
+                self.implicit_grab = None;
+                if self.pointer_grab.is_some_and(|(_, kind)| kind == PointerGrabKind::Drag) {
+                    self.pointer_grab = None;
+                }
+                self.update_pointer_focus();
+            }
+        }
+
+        // Presses focus the surface under the pointer. In the client area
+        // they go to the client, which keeps the pointer until the buttons
+        // are up; on the frame the left button starts a move or resize or
+        // works the title bar buttons.
+        pub fn pointer_button(&mut self, code: u32, pressed: bool) -> Result<(), &'static str> {
+            let result = self.route_button(code, pressed);
+            self.update_cursor();
+            result
+        }
+
+        fn route_button(&mut self, code: u32, pressed: bool) -> Result<(), &'static str> {
+            if let Some(grab) = self.grab {
+                if !pressed && code == BTN_LEFT {
+                    self.grab = None;
+                    self.suggest_scale(Self::grab_surface(grab));
+                }
+                return Ok(());
+            }
+            if !pressed {
+                self.button_released(code);
+                return Ok(());
+            }
+            let (x, y) = self.pointer;
+            let under = self.surface_at(x, y);
+            match self.pointer_grab {
+                // A press outside the popup dismisses it and goes no further
+                Some((surface, PointerGrabKind::Popup)) if under.map(|(under, _)| under) != Some(surface) => {
+                    self.pointer_grab = None;
+                    self.post(surface, "popup_done".to_string());
+                    self.update_pointer_focus();
+                    return Ok(());
+                }
+                Some((surface, _)) => {
+                    self.buttons.push(code);
+                    self.post(surface, format!("button {} down", code));
+                    return Ok(());
+                }
+                None => {}
+            }
+            let Some((surface, on_frame)) = under else {
+                return Ok(());
+            };
+            self.focus(surface)?;
+            if !on_frame {
+                self.buttons.push(code);
+                self.implicit_grab = Some(surface);
+                self.post(surface, format!("button {} down", code));
+                return Ok(());
+            }
+            if code != BTN_LEFT {
+                return Ok(());
+            }
+            // Hit-test what is on screen, which lags a resize until the
+            // client commits
+            let toplevel = self.toplevel(surface)?;
+            let (width, height) = self.compositor.size(toplevel.frame).ok_or("Surface not mapped")?;
+            let fixed = toplevel.maximized || self.is_tiled(toplevel);
+            match self.decorations.hit_test(width, height, x - toplevel.x, y - toplevel.y) {
+                FrameArea::TitleBar if !fixed => self.begin_move(surface),
+                FrameArea::Edge(edges) if !fixed => self.begin_resize(surface, edges),
+                FrameArea::Close => {
+                    self.post(surface, "close".to_string());
+                    Ok(())
+                }
+                FrameArea::Maximize if toplevel.maximized => self.restore(surface),
+                FrameArea::Maximize => self.maximize(surface),
+                FrameArea::Minimize => self.minimize(surface),
+                _ => Ok(()),
+            }
+        }
+
+        // Renders every shape in the theme's style and shows the pointer
+        pub fn set_cursor_theme(&mut self, style: &CursorStyle) {
+            self.cursors = CursorShape::ALL
+                .into_iter()
+                .filter_map(|shape| vxcursor::render(shape, style).map(|image| (shape, image)))
+                .collect();
+            self.cursor = None;
+            self.update_cursor();
+        }
+
+        // The client's shape for the pointer over its area
+        pub fn set_cursor(&mut self, surface: SurfaceId, shape: CursorShape) -> Result<(), &'static str> {
+            self.toplevel_mut(surface)?.cursor = shape;
+            self.update_cursor();
+            Ok(())
+        }
+
+        // Grabs decide first, then the client holding or under the pointer,
+        // then the frame's edges
+        pub fn cursor_shape(&self) -> CursorShape {
+            match self.grab {
+                Some(Grab::Move { .. }) => return CursorShape::Move,
+                Some(Grab::Resize { edges, .. }) => return CursorShape::for_edges(edges),
+                None => {}
+            }
+            if let Some(toplevel) = self.pointer_target().and_then(|surface| self.toplevels.get(&surface)) {
+                return toplevel.cursor;
+            }
+            let (x, y) = self.pointer;
+            let Some((surface, true)) = self.surface_at(x, y) else {
+                return CursorShape::Default;
+            };
+            let toplevel = &self.toplevels[&surface];
+            let Some((width, height)) = self.compositor.size(toplevel.frame) else {
+                return CursorShape::Default;
+            };
+            match self.decorations.hit_test(width, height, x - toplevel.x, y - toplevel.y) {
+                FrameArea::Edge(edges) if !toplevel.maximized && !self.is_tiled(toplevel) => CursorShape::for_edges(edges),
+                _ => CursorShape::Default,
+            }
+        }
+
+        fn update_cursor(&mut self) {
+            if self.cursors.is_empty() {
+                return;
+            }
+            let shape = self.cursor_shape();
+            if self.cursor != Some(shape) {
+                self.cursor = Some(shape);
+                self.compositor.set_cursor(self.cursors.get(&shape).cloned());
+            }
+            self.compositor.move_cursor(self.pointer.0, self.pointer.1);
+        }
+
+        pub fn scroll(&mut self, dx: i32, dy: i32) -> Option<SurfaceId> {
+            let surface = self.pointer_target()?;
+            self.post(surface, format!("scroll {} {}", dx, dy));
+            Some(surface)
+        }
+
+        // Popup grabs keep pointer and keyboard input on the surface until a
+        // press lands outside it. Drag grabs start from a press in the
+        // surface and end when the buttons are released.
+        pub fn grab_pointer(&mut self, surface: SurfaceId, kind: PointerGrabKind) -> Result<(), &'static str> {
+            self.toplevel(surface)?;
+            if self.pointer_grab.is_some() {
+                return Err("Pointer already grabbed");
+            }
+            if kind == PointerGrabKind::Drag && self.implicit_grab != Some(surface) {
+                return Err("No button held in the surface");
+            }
+            self.pointer_grab = Some((surface, kind));
+            Ok(())
+        }
+
+        pub fn ungrab_pointer(&mut self, surface: SurfaceId) -> Result<(), &'static str> {
+            if self.pointer_grab.map(|(grab, _)| grab) != Some(surface) {
+                return Err("Pointer not grabbed by the surface");
+            }
+            self.pointer_grab = None;
+            self.update_pointer_focus();
+            Ok(())
+        }
+
+        pub fn handle_input(&mut self, event: InputEvent) -> Result<(), &'static str> {
+            self.compositor.note_input();
+            match event {
+                InputEvent::Key { code, pressed } => {
+                    self.key(code, pressed);
+                }
+                InputEvent::PointerMotion { dx, dy } => self.pointer_motion(self.pointer.0 + dx, self.pointer.1 + dy),
+                InputEvent::PointerAbsolute { x, y } => {
+                    let scale = |value: u32, size: u32| (value.min(ABS_MAX) as u64 * (size as u64 - 1) / ABS_MAX as u64) as i32;
+                    self.pointer_motion(scale(x, self.width), scale(y, self.height));
+                }
+                InputEvent::Button { code, pressed } => self.pointer_button(code, pressed)?,
+                InputEvent::Scroll { dx, dy } => {
+                    self.scroll(dx, dy);
+                }
+            }
+            Ok(())
+        }
+
+        pub fn connect_input(&mut self, hub: &InputHub) {
+            self.input = Some(hub.subscribe());
+        }
+
+        // Handles everything the input drivers reported since the last call
+        pub fn process_input(&mut self) -> usize {
+            let Some(input) = &self.input else {
+                return 0;
+            };
+            let records: Vec<InputRecord> = input.try_iter().collect();
+            for record in &records {
+                if let Err(err) = self.handle_input(record.event) {
+                    log::debug!("Dropping input from device {}: {}", record.device.0, err);
+                }
+            }
+            records.len()
+        }
+    }
+
+    pub fn parse_grab(name: &str) -> Result<PointerGrabKind, &'static str> {
+        match name {
+            "popup" => Ok(PointerGrabKind::Popup),
+            "drag" => Ok(PointerGrabKind::Drag),
+            _ => Err("Unknown grab kind"),
+        }
+    }
+
+    pub fn parse_edges(name: &str) -> Result<u8, &'static str> {
+        let mut edges = 0;
+        for part in name.split('-') {
+            edges |= match part {
+                "left" => EDGE_LEFT,
+                "right" => EDGE_RIGHT,
+                "top" => EDGE_TOP,
+                "bottom" => EDGE_BOTTOM,
+                _ => return Err("Unknown edge"),
+            };
+        }
+        Ok(edges)
+    }
+
+    fn parse<T: std::str::FromStr>(value: &str) -> Result<T, &'static str> {
+        value.parse().map_err(|_| "Invalid number")
+    }
+
+    // Grammar, one request per message:
+    //   create WIDTH HEIGHT TITLE... | destroy S | title S TITLE...
+    //   attach S BUFFER | damage S X Y W H | commit S
+    //   focus S | minimize S | maximize S | restore S
+    //   move S | resize S EDGES (e.g. bottom-right)
+    //   grab S (popup|drag) | ungrab S | scale S FACTOR
+    //   cursor S SHAPE (CSS names, e.g. text or ew-resize; none hides it)
+    //   timing (frame timing figures, one per line)
+    //   layout [NAME] (replies with the current one) | tile ACTION
+    //   floating S 1|0
+    //   workspace [INDEX] (replies with the current one) | send S INDEX
+    // Messages and replies are framed as in vxnetctl; create replies with
+    // the surface id, whose events then arrive on event_channel()
+    pub struct WmService;
+
+    impl WmService {
+        pub fn new(manager: &VXChanManager) -> Result<Self, &'static str> {
+            manager.create_channel(REQUEST_CHANNEL)?;
+            manager.create_channel(REPLY_CHANNEL)?;
+            Ok(WmService)
+        }
+
+        fn execute(line: &str, manager: &VXChanManager, wm: &mut WindowManager) -> Result<String, &'static str> {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if let ["create", width, height, title @ ..] = words.as_slice() {
+                let surface = wm.create_surface(&title.join(" "), parse(width)?, parse(height)?);
+                manager.create_channel(&event_channel(surface))?;
+                return Ok(surface.0.to_string());
+            }
+            match words.as_slice() {
+                ["timing"] => return Ok(wm.compositor().timing_summary().lines().join("\n")),
+                ["layout"] => return Ok(wm.layout().name().to_string()),
+                ["layout", name] => {
+                    wm.set_layout(Layout::parse(name)?);
+                    return Ok(String::new());
+                }
+                ["workspace"] => return Ok(wm.current_workspace().to_string()),
+                ["workspace", index] => {
+                    wm.switch_workspace(parse(index)?)?;
+                    return Ok(String::new());
+                }
+                ["tile", action] => {
+                    wm.perform(TileAction::parse(action)?)?;
+                    return Ok(String::new());
+                }
+                _ => {}
+            }
+            let (command, surface, args) = match words.as_slice() {
+                [command, surface, args @ ..] => (*command, SurfaceId(parse(surface)?), args),
+                [] => return Err("Empty request"),
+                _ => return Err("Missing argument"),
+            };
+            match (command, args) {
+                ("destroy", []) => wm.destroy_surface(surface)?,
+                ("title", title) if !title.is_empty() => wm.set_title(surface, &title.join(" "))?,
+                ("attach", [buffer]) => wm.attach(surface, BufferId(parse(buffer)?))?,
+                ("damage", [x, y, width, height]) => {
+                    wm.damage(surface, Rect::new(parse(x)?, parse(y)?, parse(width)?, parse(height)?))?
+                }
+                ("commit", []) => wm.commit(surface)?,
+                ("focus", []) => wm.focus(surface)?,
+                ("minimize", []) => wm.minimize(surface)?,
+                ("maximize", []) => wm.maximize(surface)?,
+                ("restore", []) => wm.restore(surface)?,
+                ("scale", [factor]) => wm.set_buffer_scale(surface, parse(factor)?)?,
+                ("move", []) => wm.begin_move(surface)?,
+                ("resize", [edges]) => wm.begin_resize(surface, parse_edges(edges)?)?,
+                ("grab", [kind]) => wm.grab_pointer(surface, parse_grab(kind)?)?,
+                ("ungrab", []) => wm.ungrab_pointer(surface)?,
+                ("cursor", [shape]) => wm.set_cursor(surface, CursorShape::parse(shape)?)?,
+                ("floating", ["1"]) => wm.set_floating(surface, true)?,
+                ("floating", ["0"]) => wm.set_floating(surface, false)?,
+                ("send", [index]) => wm.move_to_workspace(surface, parse(index)?)?,
+                _ => return Err("Unknown command"),
+            }
+            Ok(String::new())
+        }
+
+        // Routes pending input, answers queued requests, then delivers the
+        // window manager's events
+        pub fn poll(&mut self, manager: &VXChanManager, wm: &mut WindowManager) -> Result<usize, &'static str> {
+            wm.process_input();
+            let mut count = 0;
+            while let Some(message) = manager.try_receive_message(REQUEST_CHANNEL)? {
+                let (id, line) = message.split_once(' ').unwrap_or((message.as_str(), ""));
+                let result = id
+                    .parse::<u64>()
+                    .map_err(|_| "Invalid request id")
+                    .and_then(|_| Self::execute(line, manager, wm));
+                let reply = match result {
+                    Ok(body) if body.is_empty() => format!("{} ok", id),
+                    Ok(body) => format!("{} ok\n{}", id, body),
+                    Err(error) => format!("{} error {}", id, error),
+                };
+                manager.send_message(REPLY_CHANNEL, reply)?;
+                count += 1;
+            }
+            for (surface, event) in wm.take_events() {
+                // Surfaces created in-process have no channel
+                let _ = manager.send_message(&event_channel(surface), event);
+            }
+            Ok(count)
+        }
+    }
+
+    pub fn send_request(manager: &VXChanManager, id: u64, request: &str) -> Result<(), &'static str> {
+        manager.send_message(REQUEST_CHANNEL, format!("{} {}", id, request))
+    }
+}
@@ -0,0 +1,405 @@
+// src/kernel/crashdump.rs
+
+pub mod crashdump {
+    use crate::drivers::block::block::{registry, BlockDevice};
+    use crate::kallsyms::kallsyms;
+    use crate::log::log::uptime;
+    use crate::process::task::task::UserContext;
+    use sha2::{Digest, Sha256};
+    use std::backtrace::Backtrace;
+    use std::cell::Cell;
+    use std::collections::VecDeque;
+    use std::fmt::Write;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex, OnceLock};
+
+    const DUMP_MAGIC: &[u8; 8] = b"VXDUMP01";
+    // magic, dump size, SHA-256 of the dump
+    const HEADER_SIZE: usize = 8 + 8 + 32;
+    // Events kept for the next dump, oldest dropped first
+    pub const TRACE_ENTRIES: usize = 128;
+    pub const MAX_BACKTRACE_FRAMES: usize = 64;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct TraceEntry {
+        // Nanoseconds since boot
+        pub time: u64,
+        pub text: String,
+    }
+
+    static TRACE: Mutex<VecDeque<TraceEntry>> = Mutex::new(VecDeque::new());
+    static MODULES: Mutex<Vec<ModuleInfo>> = Mutex::new(Vec::new());
+    static AREA: OnceLock<DumpArea> = OnceLock::new();
+    static DUMPED: AtomicBool = AtomicBool::new(false);
+
+    // Notes an event for whoever reads the next crash dump
+    pub fn trace(text: &str) {
+        let mut trace = TRACE.lock().unwrap();
+        if trace.len() == TRACE_ENTRIES {
+            trace.pop_front();
+        }
+        trace.push_back(TraceEntry { time: uptime(), text: text.to_string() });
+    }
+
+    pub fn recent_trace() -> Vec<TraceEntry> {
+        TRACE.lock().unwrap().iter().cloned().collect()
+    }
+
+    // Code loaded into the kernel half, so addresses in a dump can be
+    // told apart
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct ModuleInfo {
+        pub name: String,
+        pub base: u64,
+        pub size: u64,
+    }
+
+    pub fn register_module(name: &str, base: u64, size: u64) {
+        MODULES.lock().unwrap().push(ModuleInfo { name: name.to_string(), base, size });
+    }
+
+    pub fn unregister_module(name: &str) {
+        MODULES.lock().unwrap().retain(|module| module.name != name);
+    }
+
+    pub fn modules() -> Vec<ModuleInfo> {
+        MODULES.lock().unwrap().clone()
+    }
+
+    thread_local! {
+        // The thread whose trap this CPU is handling, and its registers
+        static TRAP_FRAME: Cell<Option<(u32, UserContext)>> = const { Cell::new(None) };
+    }
+
+    pub struct TrapFrame {
+        previous: Option<(u32, UserContext)>,
+    }
+
+    impl Drop for TrapFrame {
+        fn drop(&mut self) {
+            TRAP_FRAME.with(|frame| frame.set(self.previous));
+        }
+    }
+
+    // For as long as the guard lives, a panic is put down to handling
+    // this trap
+    pub fn enter_trap(tid: u32, context: UserContext) -> TrapFrame {
+        TrapFrame { previous: TRAP_FRAME.with(|frame| frame.replace(Some((tid, context)))) }
+    }
+
+    // What is known of the kernel when it panicked
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Minidump {
+        pub reason: String,
+        // Nanoseconds since boot
+        pub time: u64,
+        // The thread whose trap was being handled, with the registers it
+        // trapped with
+        pub trap: Option<(u32, UserContext)>,
+        // Innermost frame first
+        pub backtrace: Vec<String>,
+        pub trace: Vec<TraceEntry>,
+        pub modules: Vec<ModuleInfo>,
+    }
+
+    fn put_u32(output: &mut Vec<u8>, value: u32) {
+        output.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn put_u64(output: &mut Vec<u8>, value: u64) {
+        output.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn put_str(output: &mut Vec<u8>, text: &str) {
+        put_u32(output, text.len() as u32);
+        output.extend_from_slice(text.as_bytes());
+    }
+
+    struct Reader<'a> {
+        data: &'a [u8],
+    }
+
+    impl Reader<'_> {
+        fn take(&mut self, count: usize) -> Result<&[u8], &'static str> {
+            if self.data.len() < count {
+                return Err("Truncated crash dump");
+            }
+            let (bytes, rest) = self.data.split_at(count);
+            self.data = rest;
+            Ok(bytes)
+        }
+
+        fn u16(&mut self) -> Result<u16, &'static str> {
+            Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+        }
+
+        fn u32(&mut self) -> Result<u32, &'static str> {
+            Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+        }
+
+        fn u64(&mut self) -> Result<u64, &'static str> {
+            Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+        }
+
+        fn string(&mut self) -> Result<String, &'static str> {
+            let length = self.u32()? as usize;
+            String::from_utf8(self.take(length)?.to_vec()).map_err(|_| "Malformed crash dump")
+        }
+
+        // Of items at least size bytes each, so a corrupt count cannot
+        // have a huge vector allocated
+        fn count(&mut self, size: usize) -> Result<usize, &'static str> {
+            let count = self.u32()? as usize;
+            match count.checked_mul(size).is_some_and(|bytes| bytes <= self.data.len()) {
+                true => Ok(count),
+                false => Err("Truncated crash dump"),
+            }
+        }
+    }
+
+    // rax, rbx, rcx, rdx, rsi, rdi, rbp and r8 to r15, as UserContext
+    // holds them
+    const REGISTER_NAMES: [&str; 15] = ["rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rbp", "r8", "r9", "r10", "r11", "r12", "r13", "r14", "r15"];
+
+    impl Minidump {
+        // Everything but the reason comes from the panicking thread and
+        // what has been registered
+        pub fn capture(reason: &str) -> Self {
+            // The kernel's own symbols when it has them, as a stripped
+            // image has nothing else to go on
+            let backtrace: Vec<String> = match kallsyms::installed() {
+                true => kallsyms::capture_backtrace().iter().map(|address| format!("{:#018x} {}", address, kallsyms::symbolize(*address))).collect(),
+                false => Backtrace::force_capture().to_string().lines().map(|line| line.trim().to_string()).collect(),
+            };
+            Minidump {
+                reason: reason.to_string(),
+                time: uptime(),
+                trap: TRAP_FRAME.with(Cell::get),
+                backtrace: backtrace.into_iter().take(MAX_BACKTRACE_FRAMES).collect(),
+                trace: recent_trace(),
+                modules: modules(),
+            }
+        }
+
+        pub fn encode(&self) -> Vec<u8> {
+            let mut output = Vec::new();
+            put_str(&mut output, &self.reason);
+            put_u64(&mut output, self.time);
+            match &self.trap {
+                Some((tid, context)) => {
+                    output.push(1);
+                    put_u32(&mut output, *tid);
+                    for value in [context.rip, context.rsp, context.rflags] {
+                        put_u64(&mut output, value);
+                    }
+                    output.extend_from_slice(&context.cs.to_le_bytes());
+                    output.extend_from_slice(&context.ss.to_le_bytes());
+                    for value in context.registers {
+                        put_u64(&mut output, value);
+                    }
+                    put_u64(&mut output, context.fs_base);
+                }
+                None => output.push(0),
+            }
+            put_u32(&mut output, self.backtrace.len() as u32);
+            for line in &self.backtrace {
+                put_str(&mut output, line);
+            }
+            put_u32(&mut output, self.trace.len() as u32);
+            for entry in &self.trace {
+                put_u64(&mut output, entry.time);
+                put_str(&mut output, &entry.text);
+            }
+            put_u32(&mut output, self.modules.len() as u32);
+            for module in &self.modules {
+                put_str(&mut output, &module.name);
+                put_u64(&mut output, module.base);
+                put_u64(&mut output, module.size);
+            }
+            output
+        }
+
+        pub fn decode(data: &[u8]) -> Result<Self, &'static str> {
+            let mut reader = Reader { data };
+            let reason = reader.string()?;
+            let time = reader.u64()?;
+            let trap = match reader.take(1)?[0] {
+                0 => None,
+                _ => {
+                    let tid = reader.u32()?;
+                    let mut context = UserContext {
+                        rip: reader.u64()?,
+                        rsp: reader.u64()?,
+                        rflags: reader.u64()?,
+                        cs: reader.u16()?,
+                        ss: reader.u16()?,
+                        ..UserContext::default()
+                    };
+                    for register in context.registers.iter_mut() {
+                        *register = reader.u64()?;
+                    }
+                    context.fs_base = reader.u64()?;
+                    Some((tid, context))
+                }
+            };
+            let count = reader.count(4)?;
+            let backtrace = (0..count).map(|_| reader.string()).collect::<Result<_, _>>()?;
+            let count = reader.count(12)?;
+            let trace = (0..count).map(|_| Ok(TraceEntry { time: reader.u64()?, text: reader.string()? })).collect::<Result<_, _>>()?;
+            let count = reader.count(20)?;
+            let modules = (0..count).map(|_| Ok(ModuleInfo { name: reader.string()?, base: reader.u64()?, size: reader.u64()? })).collect::<Result<_, _>>()?;
+            Ok(Minidump { reason, time, trap, backtrace, trace, modules })
+        }
+
+        // What vxdump shows
+        pub fn report(&self) -> String {
+            let mut report = String::new();
+            let seconds = |time: u64| format!("[{:5}.{:06}]", time / 1_000_000_000, time % 1_000_000_000 / 1000);
+            writeln!(report, "Kernel panic at {}: {}", seconds(self.time), self.reason).unwrap();
+            if let Some((tid, context)) = &self.trap {
+                writeln!(report, "\nHandling a trap from thread {}:", tid).unwrap();
+                writeln!(report, "rip {:#018x} rsp {:#018x} rflags {:#018x}", context.rip, context.rsp, context.rflags).unwrap();
+                writeln!(report, "cs {:#06x} ss {:#06x} fs_base {:#018x}", context.cs, context.ss, context.fs_base).unwrap();
+                for (names, values) in REGISTER_NAMES.chunks(3).zip(context.registers.chunks(3)) {
+                    let line: Vec<String> = names.iter().zip(values).map(|(name, value)| format!("{:<3} {:#018x}", name, value)).collect();
+                    writeln!(report, "{}", line.join(" ")).unwrap();
+                }
+            }
+            writeln!(report, "\nBacktrace:").unwrap();
+            for line in &self.backtrace {
+                writeln!(report, "  {}", line).unwrap();
+            }
+            writeln!(report, "\nRecent events:").unwrap();
+            for entry in &self.trace {
+                writeln!(report, "{} {}", seconds(entry.time), entry.text).unwrap();
+            }
+            writeln!(report, "\nModules:").unwrap();
+            for module in &self.modules {
+                writeln!(report, "  {:<24} {:#018x} {:#x}", module.name, module.base, module.size).unwrap();
+            }
+            report
+        }
+    }
+
+    // Blocks on a drive set aside for dumps, as for hibernation: the first
+    // holds the header, written last so a half-written dump is never read
+    pub struct DumpArea {
+        device: Arc<dyn BlockDevice>,
+        start: u64,
+        blocks: u64,
+    }
+
+    impl DumpArea {
+        pub fn new(device: Arc<dyn BlockDevice>, start: u64, blocks: u64) -> Result<Self, &'static str> {
+            if blocks < 2 || start.checked_add(blocks).is_none_or(|end| end > device.block_count()) {
+                return Err("Dump area outside the device");
+            }
+            if device.block_size() < HEADER_SIZE {
+                return Err("Block size too small for the dump header");
+            }
+            Ok(DumpArea { device, start, blocks })
+        }
+
+        // Looks the device up in the block registry, e.g. nvme0n1
+        pub fn open(name: &str, start: u64, blocks: u64) -> Result<Self, &'static str> {
+            let device = registry().get(name).ok_or("Block device not found")?;
+            Self::new(device, start, blocks)
+        }
+
+        // Bytes available for the dump
+        pub fn capacity(&self) -> u64 {
+            (self.blocks - 1) * self.device.block_size() as u64
+        }
+
+        // Drops the oldest events, then the outermost frames, until the
+        // dump fits
+        pub fn write(&self, dump: &Minidump) -> Result<(), &'static str> {
+            let mut dump = dump.clone();
+            let mut data = dump.encode();
+            while data.len() as u64 > self.capacity() {
+                match (dump.trace.is_empty(), dump.backtrace.is_empty()) {
+                    (false, _) => drop(dump.trace.remove(0)),
+                    (true, false) => drop(dump.backtrace.pop()),
+                    (true, true) => return Err("Crash dump does not fit the dump area"),
+                }
+                data = dump.encode();
+            }
+            let block_size = self.device.block_size();
+            let mut header = vec![0u8; block_size];
+            self.device.write_blocks(self.start, &header)?;
+            let size = data.len() as u64;
+            let digest = Sha256::digest(&data);
+            data.resize(data.len().div_ceil(block_size) * block_size, 0);
+            self.device.write_blocks(self.start + 1, &data)?;
+            header[..8].copy_from_slice(DUMP_MAGIC);
+            header[8..16].copy_from_slice(&size.to_le_bytes());
+            header[16..48].copy_from_slice(&digest);
+            self.device.write_blocks(self.start, &header)
+        }
+
+        // None when no dump has been written since the area was cleared
+        pub fn read(&self) -> Result<Option<Minidump>, &'static str> {
+            let block_size = self.device.block_size();
+            let mut header = vec![0u8; block_size];
+            self.device.read_blocks(self.start, &mut header)?;
+            if &header[..8] != DUMP_MAGIC {
+                return Ok(None);
+            }
+            let size = u64::from_le_bytes(header[8..16].try_into().unwrap());
+            if size > self.capacity() {
+                return Err("Crash dump larger than the dump area");
+            }
+            let mut data = vec![0u8; (size as usize).div_ceil(block_size) * block_size];
+            self.device.read_blocks(self.start + 1, &mut data)?;
+            data.truncate(size as usize);
+            if Sha256::digest(&data).as_slice() != &header[16..48] {
+                return Err("Crash dump checksum mismatch");
+            }
+            Minidump::decode(&data).map(Some)
+        }
+
+        pub fn clear(&self) -> Result<(), &'static str> {
+            self.device.write_blocks(self.start, &vec![0u8; self.device.block_size()])
+        }
+    }
+
+    // Sets up dumps on panic to go to the area. Only the first panic of a
+    // boot is dumped: later ones are likely fallout from it.
+    pub fn install(area: DumpArea) -> Result<(), &'static str> {
+        AREA.set(area).map_err(|_| "Crash dumps already set up")?;
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            previous(info);
+            if DUMPED.swap(true, Ordering::AcqRel) {
+                return;
+            }
+            let message = info
+                .payload()
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| info.payload().downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "Box<dyn Any>".to_string());
+            let reason = match info.location() {
+                Some(location) => format!("{} at {}:{}", message, location.file(), location.line()),
+                None => message,
+            };
+            let area = AREA.get().unwrap();
+            // Straight to the console, as the logger may be what panicked
+            match area.write(&Minidump::capture(&reason)) {
+                Ok(()) => println!("Crash dump written"),
+                Err(error) => println!("Crash dump failed: {}", error),
+            }
+        }));
+        Ok(())
+    }
+
+    // The vxdump tool: the report of the dump left by the last crash, if
+    // any, clearing it once read when asked to
+    pub fn vxdump(area: &DumpArea, clear: bool) -> Result<Option<String>, &'static str> {
+        let report = area.read()?.map(|dump| dump.report());
+        if clear && report.is_some() {
+            area.clear()?;
+        }
+        Ok(report)
+    }
+}
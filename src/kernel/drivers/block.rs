@@ -0,0 +1,82 @@
+// src/kernel/drivers/block.rs
+
+pub mod block {
+    use crate::rcu::rcu::Rcu;
+    use std::collections::HashMap;
+    use std::sync::{Arc, OnceLock};
+
+    pub trait BlockDevice: Send + Sync {
+        fn block_size(&self) -> usize;
+        fn block_count(&self) -> u64;
+        fn read_blocks(&self, lba: u64, buffer: &mut [u8]) -> Result<(), &'static str>;
+        fn write_blocks(&self, lba: u64, buffer: &[u8]) -> Result<(), &'static str>;
+
+        fn read_only(&self) -> bool {
+            false
+        }
+    }
+
+    // Looked up on every I/O, changed on hotplug, so read under RCU
+    pub struct BlockRegistry {
+        devices: Rcu<HashMap<String, Arc<dyn BlockDevice>>>,
+    }
+
+    impl BlockRegistry {
+        pub fn new() -> Self {
+            BlockRegistry {
+                devices: Rcu::new("block.registry", HashMap::new()),
+            }
+        }
+
+        pub fn register(&self, name: &str, device: Arc<dyn BlockDevice>) -> Result<(), &'static str> {
+            self.devices.update(|devices| {
+                if devices.contains_key(name) {
+                    return Err("Block device already registered");
+                }
+                log::info!(
+                    "Registering block device {} ({} blocks of {} bytes)",
+                    name,
+                    device.block_count(),
+                    device.block_size()
+                );
+                devices.insert(name.to_string(), device);
+                Ok(())
+            })
+        }
+
+        // Readers that already have the device keep it until they drop it
+        pub fn unregister(&self, name: &str) -> Result<(), &'static str> {
+            self.devices.update(|devices| devices.remove(name).map(|_| ()).ok_or("Block device not found"))
+        }
+
+        pub fn get(&self, name: &str) -> Option<Arc<dyn BlockDevice>> {
+            self.devices.read().get(name).cloned()
+        }
+
+        pub fn names(&self) -> Vec<String> {
+            let mut names: Vec<String> = self.devices.read().keys().cloned().collect();
+            names.sort();
+            names
+        }
+
+        // Picks the first free name with the given prefix, e.g. mmcblk0, mmcblk1
+        pub fn next_name(&self, prefix: &str) -> String {
+            let devices = self.devices.read();
+            (0..)
+                .map(|index| format!("{}{}", prefix, index))
+                .find(|name| !devices.contains_key(name))
+                .unwrap()
+        }
+    }
+
+    impl Default for BlockRegistry {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    pub fn registry() -> &'static BlockRegistry {
+        static REGISTRY: OnceLock<BlockRegistry> = OnceLock::new();
+        REGISTRY.get_or_init(BlockRegistry::new)
+    }
+}
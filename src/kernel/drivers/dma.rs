@@ -0,0 +1,573 @@
+// src/kernel/drivers/dma.rs
+
+pub mod dma {
+    use crate::drivers::mmio::mmio::RegisterIo;
+    use crate::drivers::msr::msr::MsrIo;
+    use crate::drivers::pci::pci::{self, COMMAND_BUS_MASTER, COMMAND_MEMORY};
+    use crate::drivers::resource::resource::{resources, Region};
+    use crate::faultinject::faultinject::FAIL_DMA;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    // Engine registers, in the window BAR 0 points at
+    pub const REGISTERS_SIZE: usize = 0x1000;
+    const CAPABILITIES: usize = 0x00;
+    const CAPABILITIES_CHANNELS: u32 = 0xFF;
+    pub const MAX_CHANNELS: usize = 16;
+
+    // Each channel's, from its base
+    const CHANNEL_BASE: usize = 0x100;
+    const CHANNEL_STRIDE: usize = 0x20;
+    pub const CHANNEL_CONTROL: usize = 0x00;
+    pub const CHANNEL_STATUS: usize = 0x04;
+    pub const CHANNEL_TABLE_LOW: usize = 0x08;
+    pub const CHANNEL_TABLE_HIGH: usize = 0x0C;
+    pub const CHANNEL_TABLE_ENTRIES: usize = 0x10;
+
+    pub const CONTROL_START: u32 = 1 << 0;
+    pub const CONTROL_ABORT: u32 = 1 << 1;
+    pub const CONTROL_TO_DEVICE: u32 = 1 << 2;
+    pub const STATUS_BUSY: u32 = 1 << 0;
+    // Write 1 to clear
+    pub const STATUS_DONE: u32 = 1 << 1;
+    pub const STATUS_ERROR: u32 = 1 << 2;
+
+    // Descriptors: a 64-bit bus address, a 32-bit length and flags, all
+    // little-endian, in a table the engine walks until one marked last
+    pub const DESCRIPTOR_SIZE: usize = 16;
+    const DESCRIPTOR_ALIGN: usize = 64;
+    pub const DESCRIPTOR_LAST: u32 = 1 << 0;
+    pub const DESCRIPTOR_INTERRUPT: u32 = 1 << 1;
+    // The longest run one descriptor moves; longer buffers take several
+    pub const MAX_SEGMENT: usize = 64 << 10;
+
+    const CPUID_FEATURES: u32 = 1;
+    const CPUID_EXTENDED_FEATURES: u32 = 7;
+    const FEATURE_CLFLUSH: u32 = 1 << 19;
+    const FEATURE_PAT: u32 = 1 << 16;
+    const EXTENDED_CLFLUSHOPT: u32 = 1 << 23;
+
+    // Eight memory types page tables pick between with their PAT, PCD
+    // and PWT bits
+    pub const IA32_PAT: u32 = 0x277;
+    pub const PAGE_PWT: u64 = 1 << 3;
+    pub const PAGE_PCD: u64 = 1 << 4;
+    pub const PAGE_PAT: u64 = 1 << 7;
+
+    // An engine that was told to stop and has not is still writing, so
+    // its buffers are never freed
+    const ABORT_TIMEOUT: Duration = Duration::from_millis(10);
+
+    // Memory a device reads or writes. It never moves while it exists, so
+    // its bus address stays valid; without an IOMMU that is the physical
+    // address, and the kernel maps memory one to one.
+    pub struct DmaBuffer {
+        storage: Box<[u8]>,
+        offset: usize,
+        len: usize,
+    }
+
+    impl DmaBuffer {
+        pub fn new(len: usize, align: usize) -> Result<Self, &'static str> {
+            if len == 0 {
+                return Err("Empty DMA buffer");
+            }
+            if !align.is_power_of_two() {
+                return Err("DMA alignment is not a power of two");
+            }
+            let storage = vec![0u8; len + align - 1].into_boxed_slice();
+            let offset = storage.as_ptr().align_offset(align);
+            Ok(DmaBuffer { storage, offset, len })
+        }
+
+        pub fn from_slice(data: &[u8], align: usize) -> Result<Self, &'static str> {
+            let mut buffer = DmaBuffer::new(data.len(), align)?;
+            buffer.as_mut_slice().copy_from_slice(data);
+            Ok(buffer)
+        }
+
+        pub fn len(&self) -> usize {
+            self.len
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.len == 0
+        }
+
+        pub fn bus_address(&self) -> u64 {
+            self.as_slice().as_ptr() as u64
+        }
+
+        pub fn as_slice(&self) -> &[u8] {
+            &self.storage[self.offset..self.offset + self.len]
+        }
+
+        pub fn as_mut_slice(&mut self) -> &mut [u8] {
+            &mut self.storage[self.offset..self.offset + self.len]
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum DmaDirection {
+        ToDevice,
+        FromDevice,
+    }
+
+    // A scatter-gather table in memory the engine reads, one descriptor
+    // per run of at most MAX_SEGMENT bytes
+    pub struct SgTable {
+        memory: DmaBuffer,
+        entries: usize,
+    }
+
+    impl SgTable {
+        pub fn build(buffers: &[DmaBuffer]) -> Result<Self, &'static str> {
+            let segments: Vec<(u64, u32)> = buffers
+                .iter()
+                .flat_map(|buffer| (0..buffer.len()).step_by(MAX_SEGMENT).map(move |start| (buffer.bus_address() + start as u64, (buffer.len() - start).min(MAX_SEGMENT) as u32)))
+                .collect();
+            if segments.is_empty() {
+                return Err("Nothing to transfer");
+            }
+            let mut memory = DmaBuffer::new(segments.len() * DESCRIPTOR_SIZE, DESCRIPTOR_ALIGN)?;
+            let last = segments.len() - 1;
+            for (index, (descriptor, (address, length))) in memory.as_mut_slice().chunks_exact_mut(DESCRIPTOR_SIZE).zip(segments).enumerate() {
+                let flags = if index == last { DESCRIPTOR_LAST | DESCRIPTOR_INTERRUPT } else { 0 };
+                descriptor[0..8].copy_from_slice(&address.to_le_bytes());
+                descriptor[8..12].copy_from_slice(&length.to_le_bytes());
+                descriptor[12..16].copy_from_slice(&flags.to_le_bytes());
+            }
+            Ok(SgTable { memory, entries: last + 1 })
+        }
+
+        pub fn bus_address(&self) -> u64 {
+            self.memory.bus_address()
+        }
+
+        pub fn len(&self) -> usize {
+            self.entries
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.entries == 0
+        }
+
+        // Each descriptor's address, length and flags, as the engine sees
+        // them
+        pub fn descriptors(&self) -> Vec<(u64, u32, u32)> {
+            self.memory
+                .as_slice()
+                .chunks_exact(DESCRIPTOR_SIZE)
+                .map(|descriptor| {
+                    let word = |at: usize| u32::from_le_bytes(descriptor[at..at + 4].try_into().unwrap());
+                    (word(0) as u64 | (word(4) as u64) << 32, word(8), word(12))
+                })
+                .collect()
+        }
+    }
+
+    // A PCI scatter-gather engine with its registers mapped from its own
+    // BAR 0, so each instance drives its own function
+    pub struct DmaController<R: RegisterIo> {
+        registers: R,
+        // Which channels have a transfer
+        claimed: Box<[AtomicBool]>,
+        _region: Region,
+    }
+
+    impl<R: RegisterIo> DmaController<R> {
+        // Claims the registers where firmware assigned them, turns on
+        // memory decoding and bus mastering, then maps them
+        pub fn probe<C: RegisterIo + ?Sized>(config: &C, map: impl FnOnce(u64, usize) -> Result<R, &'static str>) -> Result<Self, &'static str> {
+            let address = pci::bar_address(config, 0)?;
+            if address == 0 {
+                return Err("DMA engine BAR is not assigned");
+            }
+            let region = resources().request_mem_region(address, REGISTERS_SIZE as u64, "dma")?;
+            pci::update_command(config, COMMAND_MEMORY | COMMAND_BUS_MASTER, true);
+            let registers = map(address, REGISTERS_SIZE)?;
+            let channels = (registers.read32(CAPABILITIES) & CAPABILITIES_CHANNELS) as usize;
+            if channels == 0 || channels > MAX_CHANNELS {
+                return Err("Bad DMA channel count");
+            }
+            log::info!("DMA engine at {:#x} with {} channels", address, channels);
+            Ok(DmaController { registers, claimed: (0..channels).map(|_| AtomicBool::new(false)).collect(), _region: region })
+        }
+
+        pub fn channels(&self) -> usize {
+            self.claimed.len()
+        }
+
+        fn channel_base(channel: usize) -> usize {
+            CHANNEL_BASE + channel * CHANNEL_STRIDE
+        }
+
+        // Starts moving buffers, which the device owns until the transfer
+        // hands them back
+        pub fn submit(&self, channel: usize, direction: DmaDirection, buffers: Vec<DmaBuffer>) -> Result<Transfer<'_, R>, &'static str> {
+            let claimed = self.claimed.get(channel).ok_or("No such DMA channel")?;
+            let table = SgTable::build(&buffers)?;
+            if claimed.swap(true, Ordering::AcqRel) {
+                return Err("DMA channel is busy");
+            }
+            let base = Self::channel_base(channel);
+            self.registers.write32(base + CHANNEL_STATUS, STATUS_DONE | STATUS_ERROR);
+            self.registers.write32(base + CHANNEL_TABLE_LOW, table.bus_address() as u32);
+            self.registers.write32(base + CHANNEL_TABLE_HIGH, (table.bus_address() >> 32) as u32);
+            self.registers.write32(base + CHANNEL_TABLE_ENTRIES, table.len() as u32);
+            let to_device = if direction == DmaDirection::ToDevice { CONTROL_TO_DEVICE } else { 0 };
+            self.registers.write32(base + CHANNEL_CONTROL, CONTROL_START | to_device);
+            Ok(Transfer { controller: self, channel, direction, buffers, table: Some(table) })
+        }
+    }
+
+    // A transfer in flight. Its buffers are out of the CPU's reach until
+    // wait() returns them, and it cannot outlive the controller; dropping
+    // it stops the channel before anything is freed.
+    pub struct Transfer<'a, R: RegisterIo> {
+        controller: &'a DmaController<R>,
+        channel: usize,
+        direction: DmaDirection,
+        buffers: Vec<DmaBuffer>,
+        // Only taken when the engine will not let go of it
+        table: Option<SgTable>,
+    }
+
+    impl<R: RegisterIo> Transfer<'_, R> {
+        fn status(&self) -> u32 {
+            self.controller.registers.read32(DmaController::<R>::channel_base(self.channel) + CHANNEL_STATUS)
+        }
+
+        pub fn table(&self) -> &SgTable {
+            self.table.as_ref().unwrap()
+        }
+
+        pub fn is_done(&self) -> bool {
+            self.status() & (STATUS_DONE | STATUS_ERROR) != 0
+        }
+
+        pub fn wait(mut self, timeout: Duration) -> Result<Vec<DmaBuffer>, &'static str> {
+            let deadline = Instant::now() + timeout;
+            loop {
+                let status = self.status();
+                if status & STATUS_ERROR != 0 {
+                    return Err("DMA transfer failed");
+                }
+                if status & STATUS_DONE != 0 {
+                    break;
+                }
+                if Instant::now() > deadline {
+                    return Err("DMA transfer timed out");
+                }
+                std::hint::spin_loop();
+            }
+            let mut buffers = std::mem::take(&mut self.buffers);
+            if self.direction == DmaDirection::FromDevice {
+                for buffer in &mut buffers {
+                    FAIL_DMA.corrupt(buffer.as_mut_slice());
+                }
+            }
+            Ok(buffers)
+        }
+    }
+
+    impl<R: RegisterIo> Drop for Transfer<'_, R> {
+        fn drop(&mut self) {
+            let registers = &self.controller.registers;
+            let base = DmaController::<R>::channel_base(self.channel);
+            if self.status() & STATUS_BUSY != 0 {
+                registers.write32(base + CHANNEL_CONTROL, CONTROL_ABORT);
+                let deadline = Instant::now() + ABORT_TIMEOUT;
+                while self.status() & STATUS_BUSY != 0 {
+                    if Instant::now() > deadline {
+                        // Leaves the channel claimed, so nothing reuses it
+                        log::error!("dma: channel {} did not stop, leaking its buffers", self.channel);
+                        std::mem::forget(std::mem::take(&mut self.buffers));
+                        std::mem::forget(self.table.take());
+                        return;
+                    }
+                    std::hint::spin_loop();
+                }
+            }
+            registers.write32(base + CHANNEL_CONTROL, 0);
+            registers.write32(base + CHANNEL_STATUS, STATUS_DONE | STATUS_ERROR);
+            self.controller.claimed[self.channel].store(false, Ordering::Release);
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum MemoryType {
+        Uncacheable,
+        WriteCombining,
+        WriteThrough,
+        WriteProtected,
+        WriteBack,
+        // Uncacheable unless an MTRR says write-combining
+        UncachedMinus,
+    }
+
+    impl MemoryType {
+        fn encoding(self) -> u64 {
+            match self {
+                MemoryType::Uncacheable => 0x00,
+                MemoryType::WriteCombining => 0x01,
+                MemoryType::WriteThrough => 0x04,
+                MemoryType::WriteProtected => 0x05,
+                MemoryType::WriteBack => 0x06,
+                MemoryType::UncachedMinus => 0x07,
+            }
+        }
+    }
+
+    // The PAT entries as Linux sets them: the first four are what the
+    // power-on default gives PCD and PWT, except that PWT alone is
+    // write-combining instead of write-through
+    pub const PAT_ENTRIES: [MemoryType; 8] = [
+        MemoryType::WriteBack,
+        MemoryType::WriteCombining,
+        MemoryType::UncachedMinus,
+        MemoryType::Uncacheable,
+        MemoryType::WriteBack,
+        MemoryType::WriteProtected,
+        MemoryType::UncachedMinus,
+        MemoryType::WriteThrough,
+    ];
+
+    // On each CPU before it maps anything other than write-back, since
+    // every CPU must agree on what the page bits mean
+    pub fn init_pat<M: MsrIo + ?Sized>(msr: &M, cpu: usize) -> Result<(), &'static str> {
+        if msr.cpuid(cpu, CPUID_FEATURES).3 & FEATURE_PAT == 0 {
+            return Err("CPU has no PAT");
+        }
+        let value = PAT_ENTRIES.iter().enumerate().fold(0, |value, (index, memory_type)| value | memory_type.encoding() << (index * 8));
+        msr.wrmsr(cpu, IA32_PAT, value)
+    }
+
+    // The page table bits that select a memory type under init_pat()
+    pub fn page_cache_bits(memory_type: MemoryType) -> u64 {
+        let index = PAT_ENTRIES.iter().position(|entry| *entry == memory_type).unwrap();
+        [0, PAGE_PWT, PAGE_PCD, PAGE_PCD | PAGE_PWT, PAGE_PAT, PAGE_PAT | PAGE_PWT, PAGE_PAT | PAGE_PCD, PAGE_PAT | PAGE_PCD | PAGE_PWT][index]
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum FlushInstruction {
+        // Ordered against every store, so slow over large ranges
+        Clflush,
+        // Only ordered by the fence after it
+        Clflushopt,
+    }
+
+    // Writing back and invalidating cache lines, behind a trait so the
+    // sync paths run against a simulated CPU
+    pub trait CacheIo: Send + Sync {
+        fn flush_line(&self, instruction: FlushInstruction, address: u64);
+        // SFENCE: earlier CLFLUSHOPTs and write-combined stores complete
+        fn fence(&self);
+    }
+
+    // Records what would have been flushed, for running hosted
+    #[derive(Clone, Default)]
+    pub struct SimulatedCache {
+        flushed: Arc<Mutex<Vec<(FlushInstruction, u64)>>>,
+        fences: Arc<AtomicUsize>,
+    }
+
+    impl SimulatedCache {
+        pub fn new() -> Self {
+            SimulatedCache::default()
+        }
+
+        // The lines flushed since the last call
+        pub fn take_flushed(&self) -> Vec<(FlushInstruction, u64)> {
+            std::mem::take(&mut *self.flushed.lock().unwrap())
+        }
+
+        pub fn fences(&self) -> usize {
+            self.fences.load(Ordering::Relaxed)
+        }
+    }
+
+    impl CacheIo for SimulatedCache {
+        fn flush_line(&self, instruction: FlushInstruction, address: u64) {
+            self.flushed.lock().unwrap().push((instruction, address));
+        }
+
+        fn fence(&self) {
+            self.fences.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // Whether the CPU can maintain its caches for devices that do not
+    // snoop them, and how
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct CacheFlush {
+        pub instruction: FlushInstruction,
+        pub line_size: usize,
+    }
+
+    impl CacheFlush {
+        pub fn detect<M: MsrIo + ?Sized>(msr: &M) -> Result<Self, &'static str> {
+            let (_, ebx, _, edx) = msr.cpuid(0, CPUID_FEATURES);
+            if edx & FEATURE_CLFLUSH == 0 {
+                return Err("CPU has no CLFLUSH");
+            }
+            let instruction = match msr.cpuid_count(0, CPUID_EXTENDED_FEATURES, 0).1 & EXTENDED_CLFLUSHOPT {
+                0 => FlushInstruction::Clflush,
+                _ => FlushInstruction::Clflushopt,
+            };
+            // In 8-byte units
+            let line_size = ((ebx >> 8) & 0xFF) as usize * 8;
+            if !line_size.is_power_of_two() {
+                return Err("Bad CLFLUSH line size");
+            }
+            Ok(CacheFlush { instruction, line_size })
+        }
+
+        // Every line a range touches, once each
+        pub fn lines(&self, address: u64, len: usize) -> impl Iterator<Item = u64> {
+            let start = address & !(self.line_size as u64 - 1);
+            (start..address + len as u64).step_by(self.line_size)
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum DmaOwner {
+        Cpu,
+        Device,
+    }
+
+    // Streaming mappings, which a driver hands back and forth between the
+    // CPU and the device with the sync calls, each side only touching the
+    // buffer while it owns it. For devices that do not snoop the CPU's
+    // caches, those calls write back and drop the lines in between.
+    pub struct StreamingDma<C: CacheIo> {
+        cache: C,
+        flush: CacheFlush,
+        coherent: bool,
+    }
+
+    impl<C: CacheIo> StreamingDma<C> {
+        pub fn new(cache: C, flush: CacheFlush, coherent: bool) -> Self {
+            StreamingDma { cache, flush, coherent }
+        }
+
+        pub fn is_coherent(&self) -> bool {
+            self.coherent
+        }
+
+        // Hands a write-back buffer to the device
+        pub fn map_single(&self, buffer: DmaBuffer, direction: DmaDirection) -> MappedBuffer {
+            self.map(buffer, direction, MemoryType::WriteBack)
+        }
+
+        // A buffer mapped write-combining, such as a framebuffer: stores
+        // to it skip the caches, so nothing is flushed, but they sit in
+        // the CPU's write-combining buffers until fenced
+        pub fn map_write_combining(&self, buffer: DmaBuffer) -> MappedBuffer {
+            self.map(buffer, DmaDirection::ToDevice, MemoryType::WriteCombining)
+        }
+
+        fn map(&self, buffer: DmaBuffer, direction: DmaDirection, memory_type: MemoryType) -> MappedBuffer {
+            let mut mapped = MappedBuffer { buffer: Some(buffer), direction, memory_type, owner: DmaOwner::Cpu };
+            self.sync_single_for_device(&mut mapped);
+            mapped
+        }
+
+        fn flush(&self, buffer: &DmaBuffer) {
+            for line in self.flush.lines(buffer.bus_address(), buffer.len()) {
+                self.cache.flush_line(self.flush.instruction, line);
+            }
+            if self.flush.instruction == FlushInstruction::Clflushopt {
+                self.cache.fence();
+            }
+        }
+
+        // Before the device reads or writes: what the CPU wrote reaches
+        // memory, and no dirty line is left to be written back over what
+        // the device puts there
+        pub fn sync_single_for_device(&self, mapped: &mut MappedBuffer) {
+            debug_assert!(mapped.owner == DmaOwner::Cpu, "DMA buffer synced for the device twice; missing sync_single_for_cpu()");
+            let buffer = mapped.buffer.as_ref().unwrap();
+            match mapped.memory_type {
+                MemoryType::WriteBack if !self.coherent => self.flush(buffer),
+                MemoryType::WriteBack => {}
+                _ => self.cache.fence(),
+            }
+            mapped.owner = DmaOwner::Device;
+        }
+
+        // Once the device is done: lines the CPU fetched ahead while the
+        // device was writing are dropped, so reads see what it wrote
+        pub fn sync_single_for_cpu(&self, mapped: &mut MappedBuffer) {
+            debug_assert!(mapped.owner == DmaOwner::Device, "DMA buffer synced for the CPU twice; missing sync_single_for_device()");
+            if mapped.direction == DmaDirection::FromDevice && mapped.memory_type == MemoryType::WriteBack && !self.coherent {
+                self.flush(mapped.buffer.as_ref().unwrap());
+            }
+            mapped.owner = DmaOwner::Cpu;
+        }
+
+        pub fn unmap_single(&self, mut mapped: MappedBuffer) -> DmaBuffer {
+            if mapped.owner == DmaOwner::Device {
+                self.sync_single_for_cpu(&mut mapped);
+            }
+            mapped.buffer.take().unwrap()
+        }
+    }
+
+    // A streaming mapping; its contents are only reachable while the CPU
+    // owns it, and debug builds panic on reaching them otherwise
+    pub struct MappedBuffer {
+        // Only taken by unmap_single()
+        buffer: Option<DmaBuffer>,
+        direction: DmaDirection,
+        memory_type: MemoryType,
+        owner: DmaOwner,
+    }
+
+    impl MappedBuffer {
+        pub fn owner(&self) -> DmaOwner {
+            self.owner
+        }
+
+        pub fn direction(&self) -> DmaDirection {
+            self.direction
+        }
+
+        pub fn memory_type(&self) -> MemoryType {
+            self.memory_type
+        }
+
+        pub fn bus_address(&self) -> u64 {
+            self.buffer.as_ref().unwrap().bus_address()
+        }
+
+        pub fn len(&self) -> usize {
+            self.buffer.as_ref().unwrap().len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.len() == 0
+        }
+
+        pub fn as_slice(&self) -> &[u8] {
+            debug_assert!(self.owner == DmaOwner::Cpu, "DMA buffer read while the device owns it; missing sync_single_for_cpu()");
+            self.buffer.as_ref().unwrap().as_slice()
+        }
+
+        pub fn as_mut_slice(&mut self) -> &mut [u8] {
+            debug_assert!(self.owner == DmaOwner::Cpu, "DMA buffer written while the device owns it; missing sync_single_for_cpu()");
+            self.buffer.as_mut().unwrap().as_mut_slice()
+        }
+    }
+
+    impl Drop for MappedBuffer {
+        fn drop(&mut self) {
+            // Unless already panicking, so that a report is not lost to
+            // an abort
+            if self.buffer.is_some() && !std::thread::panicking() {
+                debug_assert!(self.owner == DmaOwner::Cpu, "DMA buffer freed while the device owns it; missing unmap_single()");
+            }
+        }
+    }
+}
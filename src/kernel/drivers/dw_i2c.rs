@@ -0,0 +1,276 @@
+// src/kernel/drivers/dw_i2c.rs
+
+pub mod dw_i2c {
+    use crate::drivers::i2c::i2c::{I2cBus, I2cMessage};
+    use crate::drivers::mmio::mmio::RegisterIo;
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    // Synopsys DesignWare I2C registers as found in Intel LPSS controllers
+    const IC_CON: usize = 0x00;
+    const IC_TAR: usize = 0x04;
+    const IC_DATA_CMD: usize = 0x10;
+    const IC_SS_SCL_HCNT: usize = 0x14;
+    const IC_SS_SCL_LCNT: usize = 0x18;
+    const IC_FS_SCL_HCNT: usize = 0x1C;
+    const IC_FS_SCL_LCNT: usize = 0x20;
+    const IC_INTR_MASK: usize = 0x30;
+    const IC_RAW_INTR_STAT: usize = 0x34;
+    const IC_RX_TL: usize = 0x38;
+    const IC_TX_TL: usize = 0x3C;
+    const IC_CLR_INTR: usize = 0x40;
+    const IC_CLR_TX_ABRT: usize = 0x54;
+    const IC_ENABLE: usize = 0x6C;
+    const IC_STATUS: usize = 0x70;
+    const IC_RXFLR: usize = 0x78;
+    const IC_SDA_HOLD: usize = 0x7C;
+    const IC_TX_ABRT_SOURCE: usize = 0x80;
+    const IC_ENABLE_STATUS: usize = 0x9C;
+    const IC_COMP_PARAM_1: usize = 0xF4;
+    const LPSS_RESETS: usize = 0x204;
+
+    const CON_MASTER: u32 = 1 << 0;
+    const CON_SPEED_STD: u32 = 1 << 1;
+    const CON_SPEED_FAST: u32 = 2 << 1;
+    const CON_10BIT_MASTER: u32 = 1 << 4;
+    const CON_RESTART_EN: u32 = 1 << 5;
+    const CON_SLAVE_DISABLE: u32 = 1 << 6;
+
+    const CMD_READ: u32 = 1 << 8;
+    const CMD_STOP: u32 = 1 << 9;
+    const CMD_RESTART: u32 = 1 << 10;
+
+    const STATUS_ACTIVITY: u32 = 1 << 0;
+    const STATUS_TFNF: u32 = 1 << 1;
+    const STATUS_TFE: u32 = 1 << 2;
+    const STATUS_RFNE: u32 = 1 << 3;
+
+    const INTR_TX_ABRT: u32 = 1 << 6;
+    const ABRT_7BIT_ADDR_NOACK: u32 = 1 << 0;
+    const ABRT_TXDATA_NOACK: u32 = 1 << 3;
+
+    const TRANSFER_TIMEOUT: Duration = Duration::from_millis(100);
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum I2cSpeed {
+        Standard,
+        Fast,
+    }
+
+    // SCL high/low counts for the given input clock, from the minimum tHIGH and
+    // tLOW in the I2C specification. The controller adds ~8 cycles to HCNT
+    // and 1 to LCNT internally.
+    pub fn scl_counts(clock_hz: u32, speed: I2cSpeed) -> (u16, u16) {
+        let (high_ns, low_ns) = match speed {
+            I2cSpeed::Standard => (4000u64, 4700u64),
+            I2cSpeed::Fast => (600, 1300),
+        };
+        let cycles = |ns: u64| (clock_hz as u64 * ns).div_ceil(1_000_000_000);
+        let high = cycles(high_ns).saturating_sub(8).max(6);
+        let low = cycles(low_ns).saturating_sub(1).max(8);
+        (high as u16, low as u16)
+    }
+
+    pub struct DesignWareI2c<R: RegisterIo> {
+        regs: R,
+        clock_hz: u32,
+        speed: I2cSpeed,
+        tx_fifo_depth: usize,
+        // Serializes transfers; the controller has a single target register
+        lock: Mutex<()>,
+    }
+
+    impl<R: RegisterIo> DesignWareI2c<R> {
+        pub fn new(regs: R, clock_hz: u32, speed: I2cSpeed) -> Self {
+            let params = regs.read32(IC_COMP_PARAM_1);
+            let tx_fifo_depth = (((params >> 16) & 0xFF) + 1) as usize;
+            DesignWareI2c {
+                regs,
+                clock_hz,
+                speed,
+                tx_fifo_depth,
+                lock: Mutex::new(()),
+            }
+        }
+
+        pub fn initialize(&self) -> Result<(), &'static str> {
+            log::info!("Initializing DesignWare I2C controller...");
+            // Take the controller out of LPSS reset
+            self.regs.write32(LPSS_RESETS, 0x7);
+            self.disable()?;
+
+            let (hcnt, lcnt) = scl_counts(self.clock_hz, self.speed);
+            let speed = match self.speed {
+                I2cSpeed::Standard => {
+                    self.regs.write32(IC_SS_SCL_HCNT, hcnt as u32);
+                    self.regs.write32(IC_SS_SCL_LCNT, lcnt as u32);
+                    CON_SPEED_STD
+                }
+                I2cSpeed::Fast => {
+                    self.regs.write32(IC_FS_SCL_HCNT, hcnt as u32);
+                    self.regs.write32(IC_FS_SCL_LCNT, lcnt as u32);
+                    CON_SPEED_FAST
+                }
+            };
+            // 300ns SDA hold time
+            let hold = (self.clock_hz as u64 * 300).div_ceil(1_000_000_000) as u32;
+            self.regs.write32(IC_SDA_HOLD, hold);
+            self.regs.write32(IC_CON, CON_MASTER | speed | CON_RESTART_EN | CON_SLAVE_DISABLE);
+            self.regs.write32(IC_TX_TL, 0);
+            self.regs.write32(IC_RX_TL, 0);
+            // Transfers are polled
+            self.regs.write32(IC_INTR_MASK, 0);
+            Ok(())
+        }
+
+        fn disable(&self) -> Result<(), &'static str> {
+            self.regs.write32(IC_ENABLE, 0);
+            self.wait_for(|regs| regs.read32(IC_ENABLE_STATUS) & 1 == 0, "I2C controller did not disable")
+        }
+
+        fn enable(&self) -> Result<(), &'static str> {
+            self.regs.write32(IC_ENABLE, 1);
+            self.wait_for(|regs| regs.read32(IC_ENABLE_STATUS) & 1 != 0, "I2C controller did not enable")
+        }
+
+        fn wait_for<F: Fn(&R) -> bool>(&self, condition: F, error: &'static str) -> Result<(), &'static str> {
+            let start = Instant::now();
+            while !condition(&self.regs) {
+                if start.elapsed() > TRANSFER_TIMEOUT {
+                    return Err(error);
+                }
+                thread::yield_now();
+            }
+            Ok(())
+        }
+
+        fn check_abort(&self) -> Result<(), &'static str> {
+            if self.regs.read32(IC_RAW_INTR_STAT) & INTR_TX_ABRT == 0 {
+                return Ok(());
+            }
+            let source = self.regs.read32(IC_TX_ABRT_SOURCE);
+            self.regs.read32(IC_CLR_TX_ABRT);
+            if source & ABRT_7BIT_ADDR_NOACK != 0 {
+                Err("I2C address not acknowledged")
+            } else if source & ABRT_TXDATA_NOACK != 0 {
+                Err("I2C data not acknowledged")
+            } else {
+                Err("I2C transfer aborted")
+            }
+        }
+
+        fn push_command(&self, command: u32) -> Result<(), &'static str> {
+            let start = Instant::now();
+            while self.regs.read32(IC_STATUS) & STATUS_TFNF == 0 {
+                self.check_abort()?;
+                if start.elapsed() > TRANSFER_TIMEOUT {
+                    return Err("I2C TX FIFO stalled");
+                }
+                thread::yield_now();
+            }
+            self.regs.write32(IC_DATA_CMD, command);
+            Ok(())
+        }
+
+        fn pop_data(&self) -> Result<u8, &'static str> {
+            let start = Instant::now();
+            while self.regs.read32(IC_STATUS) & STATUS_RFNE == 0 {
+                self.check_abort()?;
+                if start.elapsed() > TRANSFER_TIMEOUT {
+                    return Err("I2C read timed out");
+                }
+                thread::yield_now();
+            }
+            Ok(self.regs.read32(IC_DATA_CMD) as u8)
+        }
+
+        fn run_transfer(&self, messages: &mut [I2cMessage]) -> Result<(), &'static str> {
+            let count = messages.len();
+            for (index, message) in messages.iter_mut().enumerate() {
+                let first = index == 0;
+                let last = index + 1 == count;
+                match message {
+                    I2cMessage::Write { data, .. } => {
+                        for (position, byte) in data.iter().enumerate() {
+                            let mut command = *byte as u32;
+                            if position == 0 && !first {
+                                command |= CMD_RESTART;
+                            }
+                            if last && position + 1 == data.len() {
+                                command |= CMD_STOP;
+                            }
+                            self.push_command(command)?;
+                        }
+                    }
+                    I2cMessage::Read { buffer, .. } => {
+                        let length = buffer.len();
+                        let mut requested = 0;
+                        let mut received = 0;
+                        // Keep at most a FIFO's worth of read requests in flight
+                        while received < length {
+                            while requested < length && requested - received < self.tx_fifo_depth {
+                                let mut command = CMD_READ;
+                                if requested == 0 && !first {
+                                    command |= CMD_RESTART;
+                                }
+                                if last && requested + 1 == length {
+                                    command |= CMD_STOP;
+                                }
+                                self.push_command(command)?;
+                                requested += 1;
+                            }
+                            buffer[received] = self.pop_data()?;
+                            received += 1;
+                        }
+                    }
+                }
+            }
+
+            self.wait_for(
+                |regs| regs.read32(IC_STATUS) & STATUS_TFE != 0 && regs.read32(IC_STATUS) & STATUS_ACTIVITY == 0,
+                "I2C bus did not go idle",
+            )?;
+            self.check_abort()
+        }
+
+        pub fn rx_fifo_level(&self) -> u32 {
+            self.regs.read32(IC_RXFLR)
+        }
+    }
+
+    impl<R: RegisterIo> I2cBus for DesignWareI2c<R> {
+        fn transfer(&self, messages: &mut [I2cMessage]) -> Result<(), &'static str> {
+            let Some(first) = messages.first() else {
+                return Ok(());
+            };
+            let address = first.address();
+            if messages.iter().any(|message| message.address() != address) {
+                return Err("All messages in a transfer must target one address");
+            }
+            // Every command pushed carries a data byte, so a zero-length
+            // message could neither be sent nor end the transfer with a stop
+            if messages.iter().any(|message| message.is_empty()) {
+                return Err("Zero-length I2C messages are not supported");
+            }
+
+            let _guard = self.lock.lock().unwrap();
+            self.disable()?;
+            let mut con = self.regs.read32(IC_CON) & !CON_10BIT_MASTER;
+            let target = if address > 0x7F {
+                con |= CON_10BIT_MASTER;
+                (address as u32 & 0x3FF) | (1 << 12)
+            } else {
+                address as u32
+            };
+            self.regs.write32(IC_CON, con);
+            self.regs.write32(IC_TAR, target);
+            self.regs.read32(IC_CLR_INTR);
+            self.enable()?;
+
+            let result = self.run_transfer(messages);
+            self.disable()?;
+            result
+        }
+    }
+}
@@ -0,0 +1,84 @@
+// src/kernel/drivers/ec.rs
+
+pub mod ec {
+    use crate::drivers::port::port::PortIo;
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    const EC_DATA_PORT: u16 = 0x62;
+    const EC_COMMAND_PORT: u16 = 0x66;
+
+    const EC_STATUS_OBF: u8 = 1 << 0;
+    const EC_STATUS_IBF: u8 = 1 << 1;
+
+    const EC_CMD_READ: u8 = 0x80;
+    const EC_CMD_WRITE: u8 = 0x81;
+
+    const EC_TIMEOUT: Duration = Duration::from_millis(50);
+
+    // ACPI embedded controller accessed through the standard 0x62/0x66 interface
+    pub struct EmbeddedController<P: PortIo> {
+        ports: P,
+        data_port: u16,
+        command_port: u16,
+        lock: Mutex<()>,
+    }
+
+    impl<P: PortIo> EmbeddedController<P> {
+        pub fn new(ports: P) -> Self {
+            Self::with_ports(ports, EC_DATA_PORT, EC_COMMAND_PORT)
+        }
+
+        // ECDT/_CRS may relocate the interface
+        pub fn with_ports(ports: P, data_port: u16, command_port: u16) -> Self {
+            EmbeddedController {
+                ports,
+                data_port,
+                command_port,
+                lock: Mutex::new(()),
+            }
+        }
+
+        fn wait_status(&self, mask: u8, set: bool) -> Result<(), &'static str> {
+            let start = Instant::now();
+            loop {
+                let status = self.ports.inb(self.command_port);
+                if (status & mask != 0) == set {
+                    return Ok(());
+                }
+                if start.elapsed() > EC_TIMEOUT {
+                    return Err("Embedded controller timed out");
+                }
+                thread::yield_now();
+            }
+        }
+
+        pub fn read(&self, address: u8) -> Result<u8, &'static str> {
+            let _guard = self.lock.lock().unwrap();
+            self.wait_status(EC_STATUS_IBF, false)?;
+            self.ports.outb(self.command_port, EC_CMD_READ);
+            self.wait_status(EC_STATUS_IBF, false)?;
+            self.ports.outb(self.data_port, address);
+            self.wait_status(EC_STATUS_OBF, true)?;
+            Ok(self.ports.inb(self.data_port))
+        }
+
+        pub fn write(&self, address: u8, value: u8) -> Result<(), &'static str> {
+            let _guard = self.lock.lock().unwrap();
+            self.wait_status(EC_STATUS_IBF, false)?;
+            self.ports.outb(self.command_port, EC_CMD_WRITE);
+            self.wait_status(EC_STATUS_IBF, false)?;
+            self.ports.outb(self.data_port, address);
+            self.wait_status(EC_STATUS_IBF, false)?;
+            self.ports.outb(self.data_port, value);
+            Ok(())
+        }
+
+        pub fn read_u16(&self, low_address: u8) -> Result<u16, &'static str> {
+            let low = self.read(low_address)? as u16;
+            let high = self.read(low_address.wrapping_add(1))? as u16;
+            Ok((high << 8) | low)
+        }
+    }
+}
@@ -0,0 +1,172 @@
+// src/kernel/drivers/gpio.rs
+
+pub mod gpio {
+    use crate::drivers::mmio::mmio::RegisterIo;
+    use crate::sync::sync::SpinLock;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Direction {
+        Input,
+        Output,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum IrqTrigger {
+        LevelHigh,
+        LevelLow,
+        EdgeRising,
+        EdgeFalling,
+    }
+
+    pub trait GpioController: Send + Sync {
+        fn pin_count(&self) -> usize;
+        fn set_direction(&self, pin: usize, direction: Direction) -> Result<(), &'static str>;
+        fn get(&self, pin: usize) -> Result<bool, &'static str>;
+        fn set(&self, pin: usize, value: bool) -> Result<(), &'static str>;
+        fn configure_irq(&self, pin: usize, trigger: IrqTrigger) -> Result<(), &'static str>;
+        fn set_irq_enabled(&self, pin: usize, enabled: bool) -> Result<(), &'static str>;
+        // Returns and acknowledges pins with a pending interrupt
+        fn take_pending_irqs(&self) -> Vec<usize>;
+    }
+
+    // Intel LPSS pad configuration (PADCFG DW0) bits
+    const PAD_TX_STATE: u32 = 1 << 0;
+    const PAD_RX_STATE: u32 = 1 << 1;
+    const PAD_TX_DISABLE: u32 = 1 << 8;
+    const PAD_RX_DISABLE: u32 = 1 << 9;
+    const PAD_MODE_MASK: u32 = 0x7 << 10;
+    const PAD_RX_INVERT: u32 = 1 << 23;
+    const PAD_RX_EVCFG_SHIFT: u32 = 25;
+    const PAD_RX_EVCFG_MASK: u32 = 0x3 << PAD_RX_EVCFG_SHIFT;
+    const PAD_RX_EVCFG_LEVEL: u32 = 0;
+    const PAD_RX_EVCFG_EDGE: u32 = 1;
+    const PAD_OWNERSHIP_ACPI: u32 = 1;
+
+    const PADCFG_STRIDE: usize = 0x10;
+    const PINS_PER_GROUP: usize = 32;
+
+    pub struct IntelGpioCommunity {
+        pub padcfg_offset: usize,
+        pub pad_ownership_offset: usize,
+        pub gpi_is_offset: usize,
+        pub gpi_ie_offset: usize,
+        pub pin_count: usize,
+    }
+
+    pub struct IntelGpio<R: RegisterIo> {
+        regs: R,
+        community: IntelGpioCommunity,
+        // Pad registers are read-modify-write, from interrupt handlers too
+        lock: SpinLock<()>,
+    }
+
+    impl<R: RegisterIo> IntelGpio<R> {
+        pub fn new(regs: R, community: IntelGpioCommunity) -> Self {
+            log::info!("Initializing Intel GPIO community with {} pads...", community.pin_count);
+            IntelGpio {
+                regs,
+                community,
+                lock: SpinLock::new("gpio.pads", ()),
+            }
+        }
+
+        fn pad_offset(&self, pin: usize) -> Result<usize, &'static str> {
+            if pin >= self.community.pin_count {
+                return Err("GPIO pin out of range");
+            }
+            // Pads owned by firmware must not be reprogrammed
+            let ownership = self.regs.read32(self.community.pad_ownership_offset + (pin / 8) * 4);
+            if (ownership >> ((pin % 8) * 4)) & 0x3 == PAD_OWNERSHIP_ACPI {
+                return Err("GPIO pad is owned by ACPI firmware");
+            }
+            Ok(self.community.padcfg_offset + pin * PADCFG_STRIDE)
+        }
+
+        fn update_pad(&self, pin: usize, clear: u32, set: u32) -> Result<(), &'static str> {
+            let offset = self.pad_offset(pin)?;
+            let _guard = self.lock.lock_irqsave();
+            let value = self.regs.read32(offset);
+            self.regs.write32(offset, (value & !clear) | set);
+            Ok(())
+        }
+
+        fn group_register(base: usize, pin: usize) -> (usize, u32) {
+            (base + (pin / PINS_PER_GROUP) * 4, 1 << (pin % PINS_PER_GROUP))
+        }
+    }
+
+    impl<R: RegisterIo> GpioController for IntelGpio<R> {
+        fn pin_count(&self) -> usize {
+            self.community.pin_count
+        }
+
+        fn set_direction(&self, pin: usize, direction: Direction) -> Result<(), &'static str> {
+            // Pad mode 0 routes the pad to the GPIO block instead of a native function
+            match direction {
+                Direction::Input => self.update_pad(pin, PAD_MODE_MASK | PAD_RX_DISABLE, PAD_TX_DISABLE),
+                Direction::Output => self.update_pad(pin, PAD_MODE_MASK | PAD_TX_DISABLE, PAD_RX_DISABLE),
+            }
+        }
+
+        fn get(&self, pin: usize) -> Result<bool, &'static str> {
+            let value = self.regs.read32(self.pad_offset(pin)?);
+            if value & PAD_TX_DISABLE == 0 {
+                Ok(value & PAD_TX_STATE != 0)
+            } else {
+                Ok(value & PAD_RX_STATE != 0)
+            }
+        }
+
+        fn set(&self, pin: usize, value: bool) -> Result<(), &'static str> {
+            if value {
+                self.update_pad(pin, 0, PAD_TX_STATE)
+            } else {
+                self.update_pad(pin, PAD_TX_STATE, 0)
+            }
+        }
+
+        fn configure_irq(&self, pin: usize, trigger: IrqTrigger) -> Result<(), &'static str> {
+            let (event, invert) = match trigger {
+                IrqTrigger::LevelHigh => (PAD_RX_EVCFG_LEVEL, false),
+                IrqTrigger::LevelLow => (PAD_RX_EVCFG_LEVEL, true),
+                IrqTrigger::EdgeRising => (PAD_RX_EVCFG_EDGE, false),
+                IrqTrigger::EdgeFalling => (PAD_RX_EVCFG_EDGE, true),
+            };
+            let mut set = event << PAD_RX_EVCFG_SHIFT;
+            if invert {
+                set |= PAD_RX_INVERT;
+            }
+            self.update_pad(pin, PAD_RX_EVCFG_MASK | PAD_RX_INVERT, set)
+        }
+
+        fn set_irq_enabled(&self, pin: usize, enabled: bool) -> Result<(), &'static str> {
+            self.pad_offset(pin)?;
+            let (offset, bit) = Self::group_register(self.community.gpi_ie_offset, pin);
+            let _guard = self.lock.lock_irqsave();
+            let value = self.regs.read32(offset);
+            self.regs.write32(offset, if enabled { value | bit } else { value & !bit });
+            Ok(())
+        }
+
+        fn take_pending_irqs(&self) -> Vec<usize> {
+            let mut pending = Vec::new();
+            let groups = self.community.pin_count.div_ceil(PINS_PER_GROUP);
+            for group in 0..groups {
+                let status_offset = self.community.gpi_is_offset + group * 4;
+                let enabled = self.regs.read32(self.community.gpi_ie_offset + group * 4);
+                let status = self.regs.read32(status_offset) & enabled;
+                if status == 0 {
+                    continue;
+                }
+                // GPI_IS is write-one-to-clear
+                self.regs.write32(status_offset, status);
+                for bit in 0..PINS_PER_GROUP {
+                    if status & (1 << bit) != 0 {
+                        pending.push(group * PINS_PER_GROUP + bit);
+                    }
+                }
+            }
+            pending
+        }
+    }
+}
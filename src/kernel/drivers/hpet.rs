@@ -0,0 +1,302 @@
+// src/kernel/drivers/hpet.rs
+
+pub mod hpet {
+    use crate::drivers::mmio::mmio::RegisterIo;
+    use crate::drivers::msr::msr::MsrIo;
+    use std::time::{Duration, Instant};
+
+    // Registers (IA-PC HPET Specification 1.0a), 64 bits wide and read as
+    // two halves
+    const CAPABILITIES: usize = 0x000;
+    const CONFIGURATION: usize = 0x010;
+    const INTERRUPT_STATUS: usize = 0x020;
+    const MAIN_COUNTER: usize = 0x0F0;
+    const TIMER_CONFIGURATION: usize = 0x100;
+    const TIMER_COMPARATOR: usize = 0x108;
+    const TIMER_STRIDE: usize = 0x20;
+
+    const CAP_TIMERS_SHIFT: u64 = 8;
+    const CAP_COUNTER_64BIT: u64 = 1 << 13;
+    const CAP_PERIOD_SHIFT: u64 = 32;
+    const CONFIG_ENABLE: u64 = 1 << 0;
+    // Timers 0 and 1 on IRQs 0 and 8 in place of the PIT and RTC
+    const CONFIG_LEGACY_ROUTE: u64 = 1 << 1;
+
+    const TIMER_LEVEL: u64 = 1 << 1;
+    const TIMER_INTERRUPT_ENABLE: u64 = 1 << 2;
+    const TIMER_PERIODIC: u64 = 1 << 3;
+    const TIMER_PERIODIC_CAPABLE: u64 = 1 << 4;
+    // Lets the next comparator write set the period's accumulator
+    const TIMER_SET_VALUE: u64 = 1 << 6;
+    const TIMER_ROUTE_SHIFT: u64 = 9;
+    const TIMER_ROUTE_MASK: u64 = 0x1F << TIMER_ROUTE_SHIFT;
+    const TIMER_ROUTE_CAPABLE_SHIFT: u64 = 32;
+
+    // The spec's slowest allowed tick, 100ns
+    const MAX_PERIOD_FS: u64 = 100_000_000;
+    const FEMTOS_PER_SECOND: u128 = 1_000_000_000_000_000;
+    // Longer than any calibration; a counter that has not moved by then
+    // is not running
+    const CALIBRATION_TIMEOUT: Duration = Duration::from_millis(500);
+
+    const CPUID_POWER_LEAF: u32 = 0x06;
+    const CPUID_ARAT: u32 = 1 << 2;
+    const CPUID_TSC_LEAF: u32 = 0x15;
+
+    // What the ACPI HPET table says of the one event timer block
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct HpetTable {
+        pub block_id: u32,
+        pub address: u64,
+        pub number: u8,
+        // Ticks a periodic timer can be set to without losing interrupts
+        pub minimum_tick: u16,
+    }
+
+    impl HpetTable {
+        pub const SIGNATURE: &'static [u8; 4] = b"HPET";
+        const LENGTH: usize = 56;
+
+        pub fn parse(table: &[u8]) -> Result<Self, &'static str> {
+            if table.len() < Self::LENGTH || &table[0..4] != Self::SIGNATURE {
+                return Err("Not an HPET table");
+            }
+            let length = u32::from_le_bytes(table[4..8].try_into().unwrap()) as usize;
+            if length < Self::LENGTH || length > table.len() {
+                return Err("Truncated HPET table");
+            }
+            if table[..length].iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+                return Err("Bad HPET table checksum");
+            }
+            // A generic address structure, which has to be in memory
+            if table[40] != 0 {
+                return Err("HPET is not memory mapped");
+            }
+            Ok(HpetTable {
+                block_id: u32::from_le_bytes(table[36..40].try_into().unwrap()),
+                address: u64::from_le_bytes(table[44..52].try_into().unwrap()),
+                number: table[52],
+                minimum_tick: u16::from_le_bytes(table[53..55].try_into().unwrap()),
+            })
+        }
+    }
+
+    // The High Precision Event Timer: a main counter at a fixed frequency,
+    // known from its capabilities rather than measured, and comparators
+    // that interrupt when it reaches them. Used to calibrate the TSC and
+    // as the tick where the local APIC timer cannot be trusted.
+    pub struct Hpet<R: RegisterIo> {
+        registers: R,
+        period_fs: u64,
+        timers: usize,
+        counter_64bit: bool,
+    }
+
+    impl<R: RegisterIo> Hpet<R> {
+        pub fn probe(registers: R) -> Result<Self, &'static str> {
+            let capabilities = read64(&registers, CAPABILITIES);
+            let period_fs = capabilities >> CAP_PERIOD_SHIFT;
+            if period_fs == 0 || period_fs > MAX_PERIOD_FS {
+                return Err("Invalid HPET counter period");
+            }
+            let hpet = Hpet { period_fs, timers: ((capabilities >> CAP_TIMERS_SHIFT) & 0x1F) as usize + 1, counter_64bit: capabilities & CAP_COUNTER_64BIT != 0, registers };
+            log::info!("HPET at {} Hz with {} timers", hpet.frequency_hz(), hpet.timers);
+            Ok(hpet)
+        }
+
+        pub fn frequency_hz(&self) -> u64 {
+            (FEMTOS_PER_SECOND / self.period_fs as u128) as u64
+        }
+
+        pub fn timers(&self) -> usize {
+            self.timers
+        }
+
+        pub fn is_64bit(&self) -> bool {
+            self.counter_64bit
+        }
+
+        // Starts the main counter, with the timers routed as configured
+        // rather than in place of the PIT and RTC
+        pub fn enable(&self) {
+            let config = read64(&self.registers, CONFIGURATION);
+            write64(&self.registers, CONFIGURATION, (config & !CONFIG_LEGACY_ROUTE) | CONFIG_ENABLE);
+        }
+
+        pub fn disable(&self) {
+            let config = read64(&self.registers, CONFIGURATION);
+            write64(&self.registers, CONFIGURATION, config & !CONFIG_ENABLE);
+        }
+
+        pub fn is_enabled(&self) -> bool {
+            read64(&self.registers, CONFIGURATION) & CONFIG_ENABLE != 0
+        }
+
+        pub fn counter(&self) -> u64 {
+            if !self.counter_64bit {
+                return self.registers.read32(MAIN_COUNTER) as u64;
+            }
+            // The high half again, in case the low one wrapped in between
+            loop {
+                let high = self.registers.read32(MAIN_COUNTER + 4);
+                let low = self.registers.read32(MAIN_COUNTER);
+                if self.registers.read32(MAIN_COUNTER + 4) == high {
+                    return (high as u64) << 32 | low as u64;
+                }
+            }
+        }
+
+        pub fn ticks(&self, duration: Duration) -> u64 {
+            (duration.as_nanos() * 1_000_000 / self.period_fs as u128) as u64
+        }
+
+        pub fn duration(&self, ticks: u64) -> Duration {
+            Duration::from_nanos((ticks as u128 * self.period_fs as u128 / 1_000_000) as u64)
+        }
+
+        // Ticks from one reading to a later one, across a wrap of a 32-bit
+        // counter
+        fn elapsed(&self, from: u64, to: u64) -> u64 {
+            match self.counter_64bit {
+                true => to.wrapping_sub(from),
+                false => (to as u32).wrapping_sub(from as u32) as u64,
+            }
+        }
+
+        // The TSC's frequency, from how far it moves while the HPET counts
+        // out duration. Interrupts should be off so that nothing stretches
+        // the readings.
+        pub fn calibrate_tsc(&self, read_tsc: impl Fn() -> u64, duration: Duration) -> Result<u64, &'static str> {
+            if !self.is_enabled() {
+                return Err("HPET is not enabled");
+            }
+            let ticks = self.ticks(duration).max(1);
+            let deadline = Instant::now() + CALIBRATION_TIMEOUT.max(duration * 2);
+            let (hpet_start, tsc_start) = (self.counter(), read_tsc());
+            loop {
+                let (hpet_now, tsc_now) = (self.counter(), read_tsc());
+                let elapsed = self.elapsed(hpet_start, hpet_now);
+                if elapsed >= ticks {
+                    let hz = tsc_now.wrapping_sub(tsc_start) as u128 * self.frequency_hz() as u128 / elapsed as u128;
+                    log::info!("TSC calibrated against the HPET at {} kHz", hz / 1000);
+                    return Ok(hz as u64);
+                }
+                if Instant::now() > deadline {
+                    return Err("HPET counter is not running");
+                }
+                std::hint::spin_loop();
+            }
+        }
+
+        fn timer_register(&self, timer: usize, register: usize) -> Result<usize, &'static str> {
+            if timer >= self.timers {
+                return Err("No such HPET timer");
+            }
+            Ok(register + timer * TIMER_STRIDE)
+        }
+
+        // The I/O APIC inputs a timer can be routed to
+        pub fn routes(&self, timer: usize) -> Result<u32, &'static str> {
+            Ok((read64(&self.registers, self.timer_register(timer, TIMER_CONFIGURATION)?) >> TIMER_ROUTE_CAPABLE_SHIFT) as u32)
+        }
+
+        pub fn is_periodic_capable(&self, timer: usize) -> Result<bool, &'static str> {
+            Ok(read64(&self.registers, self.timer_register(timer, TIMER_CONFIGURATION)?) & TIMER_PERIODIC_CAPABLE != 0)
+        }
+
+        fn configure(&self, timer: usize, input: u8, periodic: bool) -> Result<usize, &'static str> {
+            let register = self.timer_register(timer, TIMER_CONFIGURATION)?;
+            if input >= 32 || self.routes(timer)? & (1 << input) == 0 {
+                return Err("HPET timer cannot be routed there");
+            }
+            if periodic && !self.is_periodic_capable(timer)? {
+                return Err("HPET timer is not periodic capable");
+            }
+            let mut config = read64(&self.registers, register) & !(TIMER_ROUTE_MASK | TIMER_PERIODIC | TIMER_LEVEL);
+            config |= (input as u64) << TIMER_ROUTE_SHIFT | TIMER_INTERRUPT_ENABLE;
+            if periodic {
+                config |= TIMER_PERIODIC | TIMER_SET_VALUE;
+            }
+            write64(&self.registers, register, config);
+            Ok(register)
+        }
+
+        // Interrupts on an I/O APIC input once, delay from now
+        pub fn arm_oneshot(&self, timer: usize, input: u8, delay: Duration) -> Result<(), &'static str> {
+            self.configure(timer, input, false)?;
+            let deadline = self.counter().wrapping_add(self.ticks(delay).max(1));
+            write64(&self.registers, self.timer_register(timer, TIMER_COMPARATOR)?, deadline);
+            Ok(())
+        }
+
+        // Interrupts every period, the first one period from now
+        pub fn arm_periodic(&self, timer: usize, input: u8, period: Duration) -> Result<(), &'static str> {
+            self.configure(timer, input, true)?;
+            let comparator = self.timer_register(timer, TIMER_COMPARATOR)?;
+            let ticks = self.ticks(period).max(1);
+            // With the accumulator unlocked the first write is the first
+            // deadline and the second the period
+            write64(&self.registers, comparator, self.counter().wrapping_add(ticks));
+            write64(&self.registers, comparator, ticks);
+            Ok(())
+        }
+
+        pub fn disarm(&self, timer: usize) -> Result<(), &'static str> {
+            let register = self.timer_register(timer, TIMER_CONFIGURATION)?;
+            let config = read64(&self.registers, register);
+            write64(&self.registers, register, config & !(TIMER_INTERRUPT_ENABLE | TIMER_PERIODIC));
+            Ok(())
+        }
+
+        // Clears a level triggered timer's interrupt once handled
+        pub fn acknowledge(&self, timer: usize) {
+            self.registers.write32(INTERRUPT_STATUS, 1 << timer);
+        }
+    }
+
+    fn read64<R: RegisterIo + ?Sized>(registers: &R, offset: usize) -> u64 {
+        (registers.read32(offset + 4) as u64) << 32 | registers.read32(offset) as u64
+    }
+
+    fn write64<R: RegisterIo + ?Sized>(registers: &R, offset: usize, value: u64) {
+        registers.write32(offset, value as u32);
+        registers.write32(offset + 4, (value >> 32) as u32);
+    }
+
+    // The local APIC timer's input clock, the core crystal, where CPUID
+    // leaf 0x15 gives it
+    pub fn lapic_timer_hz<M: MsrIo + ?Sized>(msr: &M) -> Option<u64> {
+        let (max_leaf, _, _, _) = msr.cpuid(0, 0);
+        if max_leaf < CPUID_TSC_LEAF {
+            return None;
+        }
+        let (_, _, crystal_hz, _) = msr.cpuid(0, CPUID_TSC_LEAF);
+        (crystal_hz != 0).then_some(crystal_hz as u64)
+    }
+
+    // Whether the local APIC timer keeps counting in deep C-states (ARAT);
+    // without it a CPU in one misses its tick
+    pub fn lapic_always_running<M: MsrIo + ?Sized>(msr: &M) -> bool {
+        let (eax, _, _, _) = msr.cpuid(0, CPUID_POWER_LEAF);
+        eax & CPUID_ARAT != 0
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ClockEventSource {
+        // Each CPU's own timer, at the frequency given
+        LocalApic(u64),
+        // An HPET comparator for every CPU, broadcast
+        Hpet,
+    }
+
+    // The local APIC timer where its frequency is known and it keeps
+    // running in deep C-states, the HPET otherwise
+    pub fn choose_clock_event(lapic_hz: Option<u64>, lapic_always_running: bool, hpet: bool) -> Result<ClockEventSource, &'static str> {
+        match (lapic_hz, hpet) {
+            (Some(hz), false) => Ok(ClockEventSource::LocalApic(hz)),
+            (Some(hz), true) if lapic_always_running => Ok(ClockEventSource::LocalApic(hz)),
+            (_, true) => Ok(ClockEventSource::Hpet),
+            (None, false) => Err("No usable clock event source"),
+        }
+    }
+}
@@ -0,0 +1,103 @@
+// src/kernel/drivers/i2c.rs
+
+pub mod i2c {
+    use crate::rcu::rcu::Rcu;
+    use std::collections::HashMap;
+    use std::sync::{Arc, OnceLock};
+
+    pub enum I2cMessage<'a> {
+        Write { address: u16, data: &'a [u8] },
+        Read { address: u16, buffer: &'a mut [u8] },
+    }
+
+    impl I2cMessage<'_> {
+        pub fn address(&self) -> u16 {
+            match self {
+                I2cMessage::Write { address, .. } | I2cMessage::Read { address, .. } => *address,
+            }
+        }
+
+        pub fn len(&self) -> usize {
+            match self {
+                I2cMessage::Write { data, .. } => data.len(),
+                I2cMessage::Read { buffer, .. } => buffer.len(),
+            }
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.len() == 0
+        }
+    }
+
+    // Interface consumed by sensor, touchpad and EC drivers. Messages in one
+    // transfer are joined with repeated starts and end with a single stop.
+    pub trait I2cBus: Send + Sync {
+        fn transfer(&self, messages: &mut [I2cMessage]) -> Result<(), &'static str>;
+
+        fn read(&self, address: u16, buffer: &mut [u8]) -> Result<(), &'static str> {
+            self.transfer(&mut [I2cMessage::Read { address, buffer }])
+        }
+
+        fn write(&self, address: u16, data: &[u8]) -> Result<(), &'static str> {
+            self.transfer(&mut [I2cMessage::Write { address, data }])
+        }
+
+        fn write_read(&self, address: u16, data: &[u8], buffer: &mut [u8]) -> Result<(), &'static str> {
+            self.transfer(&mut [I2cMessage::Write { address, data }, I2cMessage::Read { address, buffer }])
+        }
+
+        fn read_register(&self, address: u16, register: u8) -> Result<u8, &'static str> {
+            let mut value = [0u8; 1];
+            self.write_read(address, &[register], &mut value)?;
+            Ok(value[0])
+        }
+
+        fn write_register(&self, address: u16, register: u8, value: u8) -> Result<(), &'static str> {
+            self.write(address, &[register, value])
+        }
+    }
+
+    pub struct I2cRegistry {
+        buses: Rcu<HashMap<String, Arc<dyn I2cBus>>>,
+    }
+
+    impl I2cRegistry {
+        pub fn new() -> Self {
+            I2cRegistry {
+                buses: Rcu::new("i2c.registry", HashMap::new()),
+            }
+        }
+
+        pub fn register(&self, name: &str, bus: Arc<dyn I2cBus>) -> Result<(), &'static str> {
+            self.buses.update(|buses| {
+                if buses.contains_key(name) {
+                    return Err("I2C bus already registered");
+                }
+                log::info!("Registering I2C bus {}", name);
+                buses.insert(name.to_string(), bus);
+                Ok(())
+            })
+        }
+
+        pub fn get(&self, name: &str) -> Option<Arc<dyn I2cBus>> {
+            self.buses.read().get(name).cloned()
+        }
+
+        pub fn names(&self) -> Vec<String> {
+            let mut names: Vec<String> = self.buses.read().keys().cloned().collect();
+            names.sort();
+            names
+        }
+    }
+
+    impl Default for I2cRegistry {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    pub fn registry() -> &'static I2cRegistry {
+        static REGISTRY: OnceLock<I2cRegistry> = OnceLock::new();
+        REGISTRY.get_or_init(I2cRegistry::new)
+    }
+}
@@ -0,0 +1,73 @@
+// src/kernel/drivers/mmio.rs
+
+pub mod mmio {
+    use std::sync::{Arc, Mutex};
+
+    // Register access used by every driver, so a controller can be backed by
+    // a real BAR mapping or by a plain memory window when running hosted.
+    pub trait RegisterIo: Send + Sync {
+        fn read32(&self, offset: usize) -> u32;
+        fn write32(&self, offset: usize, value: u32);
+
+        fn read16(&self, offset: usize) -> u16 {
+            let shift = (offset & 0x2) * 8;
+            (self.read32(offset & !0x3) >> shift) as u16
+        }
+
+        fn write16(&self, offset: usize, value: u16) {
+            let shift = (offset & 0x2) * 8;
+            let current = self.read32(offset & !0x3);
+            let mask = !(0xFFFF << shift);
+            self.write32(offset & !0x3, (current & mask) | ((value as u32) << shift));
+        }
+
+        fn read8(&self, offset: usize) -> u8 {
+            let shift = (offset & 0x3) * 8;
+            (self.read32(offset & !0x3) >> shift) as u8
+        }
+
+        fn write8(&self, offset: usize, value: u8) {
+            let shift = (offset & 0x3) * 8;
+            let current = self.read32(offset & !0x3);
+            let mask = !(0xFF << shift);
+            self.write32(offset & !0x3, (current & mask) | ((value as u32) << shift));
+        }
+    }
+
+    #[derive(Clone)]
+    pub struct MmioRegion {
+        base: usize,
+        registers: Arc<Mutex<Vec<u32>>>,
+    }
+
+    impl MmioRegion {
+        pub fn new(base: usize, size: usize) -> Self {
+            MmioRegion {
+                base,
+                registers: Arc::new(Mutex::new(vec![0; size.div_ceil(4)])),
+            }
+        }
+
+        pub fn base(&self) -> usize {
+            self.base
+        }
+
+        pub fn size(&self) -> usize {
+            self.registers.lock().unwrap().len() * 4
+        }
+    }
+
+    impl RegisterIo for MmioRegion {
+        fn read32(&self, offset: usize) -> u32 {
+            let registers = self.registers.lock().unwrap();
+            registers.get(offset / 4).copied().unwrap_or(0xFFFF_FFFF)
+        }
+
+        fn write32(&self, offset: usize, value: u32) {
+            let mut registers = self.registers.lock().unwrap();
+            if let Some(register) = registers.get_mut(offset / 4) {
+                *register = value;
+            }
+        }
+    }
+}
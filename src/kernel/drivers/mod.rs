@@ -0,0 +1,21 @@
+// src/kernel/drivers/mod.rs
+
+pub mod block;
+pub mod dma;
+pub mod dw_i2c;
+pub mod ec;
+pub mod gpio;
+pub mod hpet;
+pub mod i2c;
+pub mod mmio;
+pub mod msix;
+pub mod msr;
+pub mod nvme;
+pub mod pci;
+pub mod pci_pm;
+pub mod port;
+pub mod resource;
+pub mod rtl8168;
+pub mod sdhci;
+pub mod tpm;
+pub mod uart;
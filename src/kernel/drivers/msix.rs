@@ -0,0 +1,125 @@
+// src/kernel/drivers/msix.rs
+
+pub mod msix {
+    use crate::drivers::mmio::mmio::RegisterIo;
+    use crate::drivers::pci::pci::{self, CAP_MSIX, COMMAND_INTX_DISABLE};
+
+    // Capability registers, from its offset
+    const MESSAGE_CONTROL: usize = 0x02;
+    const TABLE_LOCATION: usize = 0x04;
+
+    const CONTROL_TABLE_SIZE: u16 = 0x7FF;
+    const CONTROL_FUNCTION_MASK: u16 = 1 << 14;
+    const CONTROL_ENABLE: u16 = 1 << 15;
+    const LOCATION_BIR: u32 = 0x7;
+
+    // Table entries, from the start of each
+    const ENTRY_SIZE: usize = 16;
+    const ENTRY_ADDRESS_LOW: usize = 0x0;
+    const ENTRY_ADDRESS_HIGH: usize = 0x4;
+    const ENTRY_DATA: usize = 0x8;
+    const ENTRY_CONTROL: usize = 0xC;
+    const ENTRY_MASKED: u32 = 1 << 0;
+
+    // Writes here are interrupts: to the local APIC whose ID is in bits
+    // 19:12, with the vector in the data, fixed delivery and edge triggered
+    pub const MSI_ADDRESS_BASE: u32 = 0xFEE0_0000;
+
+    pub fn message(apic_id: u32, vector: u8) -> Result<(u32, u32), &'static str> {
+        // Wider x2APIC IDs only fit through interrupt remapping
+        if apic_id > 0xFF {
+            return Err("APIC ID needs interrupt remapping");
+        }
+        Ok((MSI_ADDRESS_BASE | (apic_id << 12), vector as u32))
+    }
+
+    // A PCI function's MSI-X table, each entry an interrupt it can raise
+    // on its own vector and CPU. Entries start masked, and stay so until
+    // programmed and unmasked.
+    pub struct Msix<R: RegisterIo> {
+        config: R,
+        capability: usize,
+        // The BAR table_location() names, mapped
+        bar: R,
+    }
+
+    impl<R: RegisterIo> Msix<R> {
+        pub fn probe(config: R, bar: R) -> Result<Self, &'static str> {
+            let capability = pci::find_capability(&config, CAP_MSIX).ok_or("No MSI-X capability")?;
+            Ok(Msix { config, capability, bar })
+        }
+
+        fn control(&self) -> u16 {
+            self.config.read16(self.capability + MESSAGE_CONTROL)
+        }
+
+        fn set_control(&self, mask: u16, set: bool) {
+            let control = self.control();
+            self.config.write16(self.capability + MESSAGE_CONTROL, if set { control | mask } else { control & !mask });
+        }
+
+        pub fn table_size(&self) -> usize {
+            (self.control() & CONTROL_TABLE_SIZE) as usize + 1
+        }
+
+        // The BAR the table is in and where in it
+        pub fn table_location(&self) -> (u8, usize) {
+            let location = self.config.read32(self.capability + TABLE_LOCATION);
+            ((location & LOCATION_BIR) as u8, (location & !LOCATION_BIR) as usize)
+        }
+
+        fn entry(&self, entry: usize) -> Result<usize, &'static str> {
+            if entry >= self.table_size() {
+                return Err("MSI-X entry out of range");
+            }
+            Ok(self.table_location().1 + entry * ENTRY_SIZE)
+        }
+
+        // Points an entry at a vector on a CPU, masked while it changes
+        pub fn program(&self, entry: usize, apic_id: u32, vector: u8) -> Result<(), &'static str> {
+            let (address, data) = message(apic_id, vector)?;
+            let base = self.entry(entry)?;
+            let control = self.bar.read32(base + ENTRY_CONTROL);
+            self.bar.write32(base + ENTRY_CONTROL, control | ENTRY_MASKED);
+            self.bar.write32(base + ENTRY_ADDRESS_LOW, address);
+            self.bar.write32(base + ENTRY_ADDRESS_HIGH, 0);
+            self.bar.write32(base + ENTRY_DATA, data);
+            self.bar.write32(base + ENTRY_CONTROL, control);
+            Ok(())
+        }
+
+        // The APIC ID and vector an entry raises
+        pub fn destination(&self, entry: usize) -> Result<(u32, u8), &'static str> {
+            let base = self.entry(entry)?;
+            let address = self.bar.read32(base + ENTRY_ADDRESS_LOW);
+            Ok(((address >> 12) & 0xFF, self.bar.read32(base + ENTRY_DATA) as u8))
+        }
+
+        pub fn set_masked(&self, entry: usize, masked: bool) -> Result<(), &'static str> {
+            let base = self.entry(entry)?;
+            let control = self.bar.read32(base + ENTRY_CONTROL);
+            self.bar.write32(base + ENTRY_CONTROL, if masked { control | ENTRY_MASKED } else { control & !ENTRY_MASKED });
+            Ok(())
+        }
+
+        pub fn is_masked(&self, entry: usize) -> Result<bool, &'static str> {
+            Ok(self.bar.read32(self.entry(entry)? + ENTRY_CONTROL) & ENTRY_MASKED != 0)
+        }
+
+        // Turns the legacy interrupt off in favour of the table
+        pub fn enable(&self) {
+            pci::update_command(&self.config, COMMAND_INTX_DISABLE, true);
+            self.set_control(CONTROL_FUNCTION_MASK, false);
+            self.set_control(CONTROL_ENABLE, true);
+        }
+
+        pub fn disable(&self) {
+            self.set_control(CONTROL_ENABLE, false);
+            pci::update_command(&self.config, COMMAND_INTX_DISABLE, false);
+        }
+
+        pub fn is_enabled(&self) -> bool {
+            self.control() & CONTROL_ENABLE != 0
+        }
+    }
+}
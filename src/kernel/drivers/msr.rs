@@ -0,0 +1,157 @@
+// src/kernel/drivers/msr.rs
+
+pub mod msr {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    // (eax, ebx, ecx, edx)
+    pub type CpuidRegisters = (u32, u32, u32, u32);
+    // CPU, leaf and subleaf
+    type Subleaves = HashMap<(usize, u32, u32), CpuidRegisters>;
+
+    // Per-CPU model specific registers, CPUID and CR4, mirrored on PortIo
+    // so power management and CPU set-up can run against a simulated CPU
+    pub trait MsrIo: Send + Sync {
+        fn cpu_count(&self) -> usize;
+        fn rdmsr(&self, cpu: usize, msr: u32) -> Result<u64, &'static str>;
+        fn wrmsr(&self, cpu: usize, msr: u32, value: u64) -> Result<(), &'static str>;
+        fn cpuid(&self, cpu: usize, leaf: u32) -> CpuidRegisters;
+        // Leaves with subleaves in ECX, such as the topology and cache ones
+        fn cpuid_count(&self, cpu: usize, leaf: u32, subleaf: u32) -> CpuidRegisters {
+            match subleaf {
+                0 => self.cpuid(cpu, leaf),
+                _ => (0, 0, 0, 0),
+            }
+        }
+        fn read_cr4(&self, cpu: usize) -> Result<u64, &'static str>;
+        fn write_cr4(&self, cpu: usize, value: u64) -> Result<(), &'static str>;
+    }
+
+    // Unset MSRs raise an error as a #GP would on hardware
+    #[derive(Clone)]
+    pub struct MsrSpace {
+        cpus: usize,
+        registers: Arc<Mutex<HashMap<(usize, u32), u64>>>,
+        leaves: Arc<Mutex<HashMap<u32, CpuidRegisters>>>,
+        // Leaves that differ between CPUs, e.g. the hybrid core type
+        cpu_leaves: Arc<Mutex<HashMap<(usize, u32), CpuidRegisters>>>,
+        subleaves: Arc<Mutex<Subleaves>>,
+        // Clear until written
+        cr4: Arc<Mutex<HashMap<usize, u64>>>,
+    }
+
+    impl MsrSpace {
+        pub fn new(cpus: usize) -> Self {
+            MsrSpace {
+                cpus,
+                registers: Arc::new(Mutex::new(HashMap::new())),
+                leaves: Arc::new(Mutex::new(HashMap::new())),
+                cpu_leaves: Arc::new(Mutex::new(HashMap::new())),
+                subleaves: Arc::new(Mutex::new(HashMap::new())),
+                cr4: Arc::new(Mutex::new(HashMap::new())),
+            }
+        }
+
+        pub fn set_cpuid(&self, leaf: u32, registers: CpuidRegisters) {
+            self.leaves.lock().unwrap().insert(leaf, registers);
+        }
+
+        pub fn set_cpu_cpuid(&self, cpu: usize, leaf: u32, registers: CpuidRegisters) {
+            self.cpu_leaves.lock().unwrap().insert((cpu, leaf), registers);
+        }
+
+        pub fn set_cpu_cpuid_count(&self, cpu: usize, leaf: u32, subleaf: u32, registers: CpuidRegisters) {
+            self.subleaves.lock().unwrap().insert((cpu, leaf, subleaf), registers);
+        }
+    }
+
+    impl MsrIo for MsrSpace {
+        fn cpu_count(&self) -> usize {
+            self.cpus
+        }
+
+        fn rdmsr(&self, cpu: usize, msr: u32) -> Result<u64, &'static str> {
+            self.registers.lock().unwrap().get(&(cpu, msr)).copied().ok_or("Unsupported MSR")
+        }
+
+        fn wrmsr(&self, cpu: usize, msr: u32, value: u64) -> Result<(), &'static str> {
+            if cpu >= self.cpus {
+                return Err("CPU out of range");
+            }
+            self.registers.lock().unwrap().insert((cpu, msr), value);
+            Ok(())
+        }
+
+        fn cpuid(&self, cpu: usize, leaf: u32) -> CpuidRegisters {
+            if let Some(registers) = self.cpu_leaves.lock().unwrap().get(&(cpu, leaf)) {
+                return *registers;
+            }
+            self.leaves.lock().unwrap().get(&leaf).copied().unwrap_or((0, 0, 0, 0))
+        }
+
+        fn cpuid_count(&self, cpu: usize, leaf: u32, subleaf: u32) -> CpuidRegisters {
+            match self.subleaves.lock().unwrap().get(&(cpu, leaf, subleaf)) {
+                Some(registers) => *registers,
+                None if subleaf == 0 => self.cpuid(cpu, leaf),
+                None => (0, 0, 0, 0),
+            }
+        }
+
+        fn read_cr4(&self, cpu: usize) -> Result<u64, &'static str> {
+            if cpu >= self.cpus {
+                return Err("CPU out of range");
+            }
+            Ok(self.cr4.lock().unwrap().get(&cpu).copied().unwrap_or(0))
+        }
+
+        fn write_cr4(&self, cpu: usize, value: u64) -> Result<(), &'static str> {
+            if cpu >= self.cpus {
+                return Err("CPU out of range");
+            }
+            self.cr4.lock().unwrap().insert(cpu, value);
+            Ok(())
+        }
+    }
+
+    // The CPU the kernel runs on. CPUID works at any privilege level, but
+    // MSRs and CR4 need ring 0, so hosted they fail as they would with a
+    // #GP. Hosted, the kernel runs on the one CPU.
+    pub struct BootCpu;
+
+    impl MsrIo for BootCpu {
+        fn cpu_count(&self) -> usize {
+            1
+        }
+
+        fn rdmsr(&self, _cpu: usize, _msr: u32) -> Result<u64, &'static str> {
+            Err("MSR access needs ring 0")
+        }
+
+        fn wrmsr(&self, _cpu: usize, _msr: u32, _value: u64) -> Result<(), &'static str> {
+            Err("MSR access needs ring 0")
+        }
+
+        fn cpuid(&self, cpu: usize, leaf: u32) -> CpuidRegisters {
+            self.cpuid_count(cpu, leaf, 0)
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        fn cpuid_count(&self, _cpu: usize, leaf: u32, subleaf: u32) -> CpuidRegisters {
+            let registers = std::arch::x86_64::__cpuid_count(leaf, subleaf);
+            (registers.eax, registers.ebx, registers.ecx, registers.edx)
+        }
+
+        #[cfg(not(target_arch = "x86_64"))]
+        fn cpuid_count(&self, _cpu: usize, _leaf: u32, _subleaf: u32) -> CpuidRegisters {
+            (0, 0, 0, 0)
+        }
+
+        fn read_cr4(&self, _cpu: usize) -> Result<u64, &'static str> {
+            Err("CR4 access needs ring 0")
+        }
+
+        fn write_cr4(&self, _cpu: usize, _value: u64) -> Result<(), &'static str> {
+            Err("CR4 access needs ring 0")
+        }
+    }
+}
@@ -0,0 +1,119 @@
+// src/kernel/drivers/nvme.rs
+
+pub mod nvme {
+    use crate::drivers::mmio::mmio::RegisterIo;
+    use crate::drivers::msix::msix::Msix;
+    use crate::irq::irq::{IrqAction, IrqController, IrqReturn};
+    use crate::topology::topology::Topology;
+    use std::sync::Arc;
+
+    pub const ADMIN_QUEUE: u16 = 0;
+
+    const OPCODE_CREATE_IO_CQ: u32 = 0x05;
+    const CQ_PHYSICALLY_CONTIGUOUS: u32 = 1 << 0;
+    const CQ_INTERRUPTS_ENABLED: u32 = 1 << 1;
+
+    // Called from the interrupt handler with the queue to reap
+    pub type CompletionHandler = Arc<dyn Fn(u16) + Send + Sync>;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct QueueVector {
+        // Completion queue ID, the admin queue's 0
+        pub queue: u16,
+        // MSI-X table entry, the queue's interrupt vector (IV) when it is
+        // created
+        pub entry: u16,
+        pub vector: u8,
+        pub cpu: usize,
+    }
+
+    // Create I/O Completion Queue, as submitted to the admin queue
+    pub fn create_io_cq_command(command_id: u16, queue: QueueVector, entries: u16, address: u64) -> [u32; 16] {
+        let mut command = [0; 16];
+        command[0] = OPCODE_CREATE_IO_CQ | (command_id as u32) << 16;
+        command[6] = address as u32;
+        command[7] = (address >> 32) as u32;
+        command[10] = (entries as u32 - 1) << 16 | queue.queue as u32;
+        command[11] = (queue.entry as u32) << 16 | CQ_INTERRUPTS_ENABLED | CQ_PHYSICALLY_CONTIGUOUS;
+        command
+    }
+
+    // An NVMe controller's completion interrupts: one MSI-X vector per I/O
+    // completion queue, each delivered to the core that submits to the
+    // queue, so completions are handled where the I/O was issued rather
+    // than all on one CPU. The admin queue has entry 0.
+    pub struct NvmeInterrupts<R: RegisterIo> {
+        name: String,
+        msix: Msix<R>,
+        irq: &'static IrqController,
+        queues: Vec<QueueVector>,
+    }
+
+    impl<R: RegisterIo> NvmeInterrupts<R> {
+        // Up to io_queues I/O queues, a core each in the order topology
+        // prefers; fewer when the table or the cores run out. The
+        // controller is then asked for as many queues with Set Features,
+        // and each created with create_io_cq_command().
+        pub fn setup(name: &str, msix: Msix<R>, irq: &'static IrqController, topology: &Topology, io_queues: usize, completion: CompletionHandler) -> Result<Self, &'static str> {
+            let cpus: Vec<usize> = topology.preferred_order().into_iter().filter(|cpu| *cpu < irq.online_cpus()).collect();
+            if cpus.is_empty() {
+                return Err("No CPUs for NVMe interrupts");
+            }
+            let io_queues = io_queues.min(msix.table_size() - 1).min(cpus.len());
+            let mut interrupts = NvmeInterrupts { name: name.to_string(), msix, irq, queues: Vec::new() };
+            for queue in 0..=io_queues as u16 {
+                let cpu = cpus[(queue as usize).saturating_sub(1)];
+                let completion = Arc::clone(&completion);
+                let action = IrqAction::new(&interrupts.handler_name(queue), move |_| {
+                    completion(queue);
+                    IrqReturn::Handled
+                });
+                // Those set up so far are freed as it is dropped
+                let vector = interrupts.irq.request_any(action.affinity(&[cpu]))?;
+                interrupts.queues.push(QueueVector { queue, entry: queue, vector, cpu });
+                interrupts.msix.program(queue as usize, topology.cpus[cpu].apic_id, vector)?;
+                interrupts.msix.set_masked(queue as usize, false)?;
+            }
+            interrupts.msix.enable();
+            log::info!("{}: {} I/O queues with their own MSI-X vectors", name, io_queues);
+            Ok(interrupts)
+        }
+
+        // As Linux names them, nvme0q1 for queue 1
+        fn handler_name(&self, queue: u16) -> String {
+            format!("{}q{}", self.name, queue)
+        }
+
+        pub fn queues(&self) -> &[QueueVector] {
+            &self.queues
+        }
+
+        pub fn io_queues(&self) -> usize {
+            self.queues.len() - 1
+        }
+
+        // The I/O queue a CPU submits to: its own, or shared round robin
+        // when there are more CPUs than queues
+        pub fn queue_for_cpu(&self, cpu: usize) -> u16 {
+            let io = &self.queues[1..];
+            match io.iter().find(|queue| queue.cpu == cpu) {
+                Some(queue) => queue.queue,
+                None => io.get(cpu % io.len().max(1)).map(|queue| queue.queue).unwrap_or(ADMIN_QUEUE),
+            }
+        }
+
+        pub fn msix(&self) -> &Msix<R> {
+            &self.msix
+        }
+    }
+
+    impl<R: RegisterIo> Drop for NvmeInterrupts<R> {
+        fn drop(&mut self) {
+            for queue in &self.queues {
+                let _ = self.msix.set_masked(queue.entry as usize, true);
+                let _ = self.irq.free(queue.vector, &self.handler_name(queue.queue));
+            }
+            self.msix.disable();
+        }
+    }
+}
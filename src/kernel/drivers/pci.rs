@@ -0,0 +1,122 @@
+// src/kernel/drivers/pci.rs
+
+pub mod pci {
+    use crate::drivers::mmio::mmio::RegisterIo;
+
+    // Type 0 configuration space header, as ECAM maps each function's
+    // 4 KiB of it
+    pub const VENDOR_ID: usize = 0x00;
+    pub const DEVICE_ID: usize = 0x02;
+    pub const COMMAND: usize = 0x04;
+    pub const STATUS: usize = 0x06;
+    // Revision in the low byte, then programming interface, subclass, class
+    pub const CLASS_REVISION: usize = 0x08;
+    pub const HEADER_TYPE: usize = 0x0E;
+    // Six of them, 64-bit memory BARs taking two
+    pub const BAR0: usize = 0x10;
+    pub const CAPABILITIES_POINTER: usize = 0x34;
+
+    pub const COMMAND_MEMORY: u16 = 1 << 1;
+    pub const COMMAND_BUS_MASTER: u16 = 1 << 2;
+    pub const COMMAND_INTX_DISABLE: u16 = 1 << 10;
+    pub const STATUS_CAPABILITIES: u16 = 1 << 4;
+    pub const HEADER_MULTI_FUNCTION: u8 = 1 << 7;
+
+    // Where QEMU's q35 machine puts ECAM; firmware reports it in MCFG
+    pub const ECAM_BASE: usize = 0xB000_0000;
+    pub const ECAM_FUNCTION_SIZE: usize = 4096;
+
+    const BAR_IO: u32 = 1 << 0;
+    const BAR_TYPE: u32 = 0x3 << 1;
+    const BAR_TYPE_32: u32 = 0x0 << 1;
+    const BAR_TYPE_64: u32 = 0x2 << 1;
+    const BAR_MEMORY_ADDRESS: u32 = !0xF;
+
+    pub const CAP_POWER_MANAGEMENT: u8 = 0x01;
+    pub const CAP_MSI: u8 = 0x05;
+    pub const CAP_MSIX: u8 = 0x11;
+
+    // More than fit in the 192 bytes after the header, so a list that
+    // loops is cut off
+    const MAX_CAPABILITIES: usize = 48;
+
+    // Each capability's ID and offset, in list order
+    pub fn capabilities<R: RegisterIo + ?Sized>(config: &R) -> Vec<(u8, usize)> {
+        let mut capabilities = Vec::new();
+        if config.read16(STATUS) & STATUS_CAPABILITIES == 0 {
+            return capabilities;
+        }
+        let mut offset = (config.read8(CAPABILITIES_POINTER) & !0x3) as usize;
+        while offset >= 0x40 && capabilities.len() < MAX_CAPABILITIES {
+            capabilities.push((config.read8(offset), offset));
+            offset = (config.read8(offset + 1) & !0x3) as usize;
+        }
+        capabilities
+    }
+
+    pub fn find_capability<R: RegisterIo + ?Sized>(config: &R, id: u8) -> Option<usize> {
+        capabilities(config).into_iter().find(|(capability, _)| *capability == id).map(|(_, offset)| offset)
+    }
+
+    pub fn update_command<R: RegisterIo + ?Sized>(config: &R, mask: u16, set: bool) {
+        let command = config.read16(COMMAND);
+        config.write16(COMMAND, if set { command | mask } else { command & !mask });
+    }
+
+    // Where firmware put a memory BAR; a 64-bit one has its high half in
+    // the next
+    pub fn bar_address<R: RegisterIo + ?Sized>(config: &R, index: usize) -> Result<u64, &'static str> {
+        if index >= 6 {
+            return Err("No such BAR");
+        }
+        let low = config.read32(BAR0 + index * 4);
+        if low & BAR_IO != 0 {
+            return Err("BAR is for I/O ports");
+        }
+        let address = (low & BAR_MEMORY_ADDRESS) as u64;
+        match low & BAR_TYPE {
+            BAR_TYPE_32 => Ok(address),
+            BAR_TYPE_64 if index < 5 => Ok(address | (config.read32(BAR0 + (index + 1) * 4) as u64) << 32),
+            _ => Err("Bad BAR type"),
+        }
+    }
+
+    pub fn ecam_offset(bus: u8, device: u8, function: u8) -> usize {
+        (bus as usize) << 20 | (device as usize) << 15 | (function as usize) << 12
+    }
+
+    // Class, subclass and programming interface
+    pub fn class_code<R: RegisterIo + ?Sized>(config: &R) -> u32 {
+        config.read32(CLASS_REVISION) >> 8
+    }
+
+    // Absent functions read as all ones on hardware, and as zero from an
+    // empty hosted window
+    pub fn present<R: RegisterIo + ?Sized>(config: &R) -> bool {
+        !matches!(config.read16(VENDOR_ID), 0x0000 | 0xFFFF)
+    }
+
+    // The functions on a bus, named as lspci names them, each with the
+    // window map gives onto its configuration space at an ECAM offset
+    pub fn scan_bus<R: RegisterIo>(bus: u8, map: impl Fn(usize) -> R) -> Vec<(String, R)> {
+        let mut functions = Vec::new();
+        for device in 0..32 {
+            for function in 0..8 {
+                let config = map(ecam_offset(bus, device, function));
+                if !present(&config) {
+                    // Function 0 missing means the whole device is
+                    if function == 0 {
+                        break;
+                    }
+                    continue;
+                }
+                let multi_function = config.read8(HEADER_TYPE) & HEADER_MULTI_FUNCTION != 0;
+                functions.push((format!("0000:{:02x}:{:02x}.{}", bus, device, function), config));
+                if function == 0 && !multi_function {
+                    break;
+                }
+            }
+        }
+        functions
+    }
+}
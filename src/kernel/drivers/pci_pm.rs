@@ -0,0 +1,205 @@
+// src/kernel/drivers/pci_pm.rs
+
+pub mod pci_pm {
+    use crate::drivers::mmio::mmio::RegisterIo;
+    use crate::drivers::pci::pci::{self, CAP_POWER_MANAGEMENT};
+    use crate::drivers::port::port::PortIo;
+    use crate::power::runtime::runtime::{DeviceClass, DevicePowerState, RuntimePm, RuntimePower};
+    use crate::power::wake::wake::WakeManager;
+    use std::thread;
+    use std::time::Duration;
+
+    // Capability registers, from its offset
+    const PMC: usize = 0x02;
+    const PMCSR: usize = 0x04;
+
+    const PMC_D1: u16 = 1 << 9;
+    const PMC_D2: u16 = 1 << 10;
+    // One bit each for D0, D1, D2, D3hot and D3cold
+    const PMC_PME_SHIFT: u16 = 11;
+    const PMCSR_STATE: u16 = 0x3;
+    // Set when D3hot to D0 keeps the configuration
+    const PMCSR_NO_SOFT_RESET: u16 = 1 << 3;
+    const PMCSR_PME_ENABLE: u16 = 1 << 8;
+    // Write 1 to clear
+    const PMCSR_PME_STATUS: u16 = 1 << 15;
+
+    // How long the device may take before it answers again, as the PCI
+    // PM spec has it, after going to or from D3hot and D2
+    pub const D3HOT_DELAY: Duration = Duration::from_millis(10);
+    pub const D2_DELAY: Duration = Duration::from_micros(200);
+
+    // The header a soft reset in D3hot loses
+    const SAVED_DWORDS: usize = 16;
+
+    fn encoding(state: DevicePowerState) -> u16 {
+        match state {
+            DevicePowerState::D0 => 0,
+            DevicePowerState::D1 => 1,
+            DevicePowerState::D2 => 2,
+            DevicePowerState::D3Hot => 3,
+        }
+    }
+
+    // A PCI function's power states through its PM capability
+    pub struct PciPm<R: RegisterIo> {
+        config: R,
+        capability: usize,
+        // The header from before D3hot, put back if the device lost it
+        saved: Option<Vec<u32>>,
+    }
+
+    impl<R: RegisterIo> PciPm<R> {
+        pub fn probe(config: R) -> Result<Self, &'static str> {
+            let capability = pci::find_capability(&config, CAP_POWER_MANAGEMENT).ok_or("No power management capability")?;
+            Ok(PciPm { config, capability, saved: None })
+        }
+
+        fn pmc(&self) -> u16 {
+            self.config.read16(self.capability + PMC)
+        }
+
+        fn pmcsr(&self) -> u16 {
+            self.config.read16(self.capability + PMCSR)
+        }
+
+        pub fn supports(&self, state: DevicePowerState) -> bool {
+            match state {
+                DevicePowerState::D1 => self.pmc() & PMC_D1 != 0,
+                DevicePowerState::D2 => self.pmc() & PMC_D2 != 0,
+                DevicePowerState::D0 | DevicePowerState::D3Hot => true,
+            }
+        }
+
+        // Whether the device can assert PME from a state
+        pub fn can_wake_from(&self, state: DevicePowerState) -> bool {
+            self.pmc() >> (PMC_PME_SHIFT + encoding(state)) & 1 != 0
+        }
+
+        pub fn state(&self) -> DevicePowerState {
+            match self.pmcsr() & PMCSR_STATE {
+                0 => DevicePowerState::D0,
+                1 => DevicePowerState::D1,
+                2 => DevicePowerState::D2,
+                _ => DevicePowerState::D3Hot,
+            }
+        }
+
+        // Moves the device and waits until it may be used again. Only D0
+        // is reachable from a low-power state other than going deeper.
+        pub fn set_state(&mut self, state: DevicePowerState) -> Result<(), &'static str> {
+            let current = self.state();
+            if state == current {
+                return Ok(());
+            }
+            if !self.supports(state) {
+                return Err("Power state not supported");
+            }
+            if state != DevicePowerState::D0 && state < current {
+                return Err("Invalid power state transition");
+            }
+            if state == DevicePowerState::D3Hot {
+                self.saved = Some((0..SAVED_DWORDS).map(|dword| self.config.read32(dword * 4)).collect());
+            }
+            // Writing PME status back as 1 would clear it
+            let pmcsr = self.pmcsr() & !(PMCSR_STATE | PMCSR_PME_STATUS);
+            self.config.write16(self.capability + PMCSR, pmcsr | encoding(state));
+            if current == DevicePowerState::D3Hot || state == DevicePowerState::D3Hot {
+                thread::sleep(D3HOT_DELAY);
+            } else if current == DevicePowerState::D2 || state == DevicePowerState::D2 {
+                thread::sleep(D2_DELAY);
+            }
+            if self.state() != state {
+                return Err("Device did not change power state");
+            }
+            if current == DevicePowerState::D3Hot {
+                self.restore(pmcsr & PMCSR_NO_SOFT_RESET != 0);
+            }
+            Ok(())
+        }
+
+        // Puts back the header after a soft reset, the command register
+        // last so the BARs are right before decoding comes back on
+        fn restore(&mut self, kept: bool) {
+            let Some(saved) = self.saved.take() else {
+                return;
+            };
+            if kept {
+                return;
+            }
+            for (dword, value) in saved.iter().enumerate().skip(1).rev() {
+                if self.config.read32(dword * 4) != *value {
+                    self.config.write32(dword * 4, *value);
+                }
+            }
+            log::info!("pci: restored configuration lost in D3hot");
+        }
+
+        pub fn pme_status(&self) -> bool {
+            self.pmcsr() & PMCSR_PME_STATUS != 0
+        }
+
+        pub fn clear_pme(&self) {
+            let pmcsr = self.pmcsr();
+            self.config.write16(self.capability + PMCSR, pmcsr | PMCSR_PME_STATUS);
+        }
+
+        pub fn is_wake_enabled(&self) -> bool {
+            self.pmcsr() & PMCSR_PME_ENABLE != 0
+        }
+
+        // Lets the device assert PME from state, clearing any old PME
+        // first so that it cannot wake the system straight back up
+        pub fn enable_wake(&self, state: DevicePowerState, enable: bool) -> Result<(), &'static str> {
+            if enable && !self.can_wake_from(state) {
+                return Err("Device cannot wake from that state");
+            }
+            let pmcsr = self.pmcsr() & !PMCSR_PME_ENABLE;
+            self.config.write16(self.capability + PMCSR, pmcsr | PMCSR_PME_STATUS | if enable { PMCSR_PME_ENABLE } else { 0 });
+            Ok(())
+        }
+
+        // PME reaches the CPU as the GPE the device's _PRW names, so
+        // waking the system takes both
+        pub fn arm_wake<P: PortIo>(&self, wake: &mut WakeManager<P>, device: &str, gpe: u32, state: DevicePowerState) -> Result<(), &'static str> {
+            self.enable_wake(state, true)?;
+            wake.register(device, gpe)
+        }
+
+        // After resume: whether this device was what woke the system
+        pub fn disarm_wake<P: PortIo>(&self, wake: &mut WakeManager<P>, device: &str) -> Result<bool, &'static str> {
+            let woke = self.pme_status();
+            self.enable_wake(DevicePowerState::D0, false)?;
+            wake.unregister(device)?;
+            Ok(woke)
+        }
+    }
+
+    impl<R: RegisterIo> RuntimePower for PciPm<R> {
+        fn set_power_state(&mut self, state: DevicePowerState) -> Result<(), &'static str> {
+            self.set_state(state)
+        }
+    }
+
+    // The functions runtime PM looks after, by class code: WiFi cards
+    // mostly call themselves other network controllers, then HD audio
+    // and anything with a display
+    pub fn runtime_class(class_code: u32) -> Option<DeviceClass> {
+        match (class_code >> 16, class_code >> 8 & 0xFF) {
+            (0x02, 0x80) | (0x0D, _) => Some(DeviceClass::Wifi),
+            (0x04, 0x01) | (0x04, 0x03) => Some(DeviceClass::Audio),
+            (0x03, _) => Some(DeviceClass::Gpu),
+            _ => None,
+        }
+    }
+
+    // Hands a WiFi, audio or GPU function to runtime PM, which then moves
+    // it between D0 and D3hot; other functions are left alone
+    pub fn register_runtime<R: RegisterIo + 'static>(runtime: &RuntimePm, name: &str, config: R) -> Result<Option<DeviceClass>, &'static str> {
+        let Some(class) = runtime_class(pci::class_code(&config)) else {
+            return Ok(None);
+        };
+        runtime.register(name, class, Box::new(PciPm::probe(config)?))?;
+        Ok(Some(class))
+    }
+}
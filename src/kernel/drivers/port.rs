@@ -0,0 +1,36 @@
+// src/kernel/drivers/port.rs
+
+pub mod port {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    // Legacy x86 I/O port access, mirrored on RegisterIo so port-based
+    // devices (EC, CMOS, UART) can run against a simulated port space
+    pub trait PortIo: Send + Sync {
+        fn inb(&self, port: u16) -> u8;
+        fn outb(&self, port: u16, value: u8);
+    }
+
+    #[derive(Clone, Default)]
+    pub struct PortSpace {
+        ports: Arc<Mutex<HashMap<u16, u8>>>,
+    }
+
+    impl PortSpace {
+        pub fn new() -> Self {
+            PortSpace {
+                ports: Arc::new(Mutex::new(HashMap::new())),
+            }
+        }
+    }
+
+    impl PortIo for PortSpace {
+        fn inb(&self, port: u16) -> u8 {
+            *self.ports.lock().unwrap().get(&port).unwrap_or(&0xFF)
+        }
+
+        fn outb(&self, port: u16, value: u8) {
+            self.ports.lock().unwrap().insert(port, value);
+        }
+    }
+}
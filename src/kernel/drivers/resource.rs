@@ -0,0 +1,170 @@
+// src/kernel/drivers/resource.rs
+
+pub mod resource {
+    use crate::drivers::port::port::PortIo;
+    use crate::sync::sync::Mutex;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub enum ResourceKind {
+        Io,
+        Memory,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Claim {
+        pub kind: ResourceKind,
+        pub start: u64,
+        // Inclusive, as /proc/ioports shows it
+        pub end: u64,
+        pub owner: String,
+    }
+
+    // Which driver owns each I/O port range and MMIO window. A driver
+    // requests its ranges before touching them, so two that would drive
+    // the same hardware fail at init rather than corrupt each other.
+    pub struct ResourceRegistry {
+        claims: Mutex<Vec<Claim>>,
+    }
+
+    impl ResourceRegistry {
+        pub const fn new() -> Self {
+            ResourceRegistry { claims: Mutex::new("resource.registry", Vec::new()) }
+        }
+
+        fn request(&'static self, kind: ResourceKind, start: u64, len: u64, owner: &str) -> Result<Region, &'static str> {
+            let limit = match kind {
+                ResourceKind::Io => u16::MAX as u64,
+                ResourceKind::Memory => u64::MAX,
+            };
+            if len == 0 || start > limit || len - 1 > limit - start {
+                return Err("Bad resource range");
+            }
+            let end = start + (len - 1);
+            let mut claims = self.claims.lock();
+            if let Some(other) = claims.iter().find(|claim| claim.kind == kind && claim.start <= end && start <= claim.end) {
+                log::warn!("resource: {} wants {:#x}-{:#x}, already claimed by {}", owner, start, end, other.owner);
+                return Err("Resource busy");
+            }
+            claims.push(Claim { kind, start, end, owner: owner.to_string() });
+            claims.sort_by_key(|claim| (claim.kind, claim.start));
+            Ok(Region { registry: self, kind, start, end })
+        }
+
+        pub fn request_region(&'static self, start: u16, len: u16, owner: &str) -> Result<Region, &'static str> {
+            self.request(ResourceKind::Io, start as u64, len as u64, owner)
+        }
+
+        pub fn request_mem_region(&'static self, start: u64, len: u64, owner: &str) -> Result<Region, &'static str> {
+            self.request(ResourceKind::Memory, start, len, owner)
+        }
+
+        fn release(&self, kind: ResourceKind, start: u64) {
+            self.claims.lock().retain(|claim| claim.kind != kind || claim.start != start);
+        }
+
+        pub fn claims(&self, kind: ResourceKind) -> Vec<Claim> {
+            self.claims.lock().iter().filter(|claim| claim.kind == kind).cloned().collect()
+        }
+
+        pub fn owner(&self, kind: ResourceKind, address: u64) -> Option<String> {
+            self.claims.lock().iter().find(|claim| claim.kind == kind && (claim.start..=claim.end).contains(&address)).map(|claim| claim.owner.clone())
+        }
+
+        // /proc/ioports or /proc/iomem
+        pub fn report(&self, kind: ResourceKind) -> String {
+            let width = match kind {
+                ResourceKind::Io => 4,
+                ResourceKind::Memory => 8,
+            };
+            self.claims(kind).iter().map(|claim| format!("{:0width$x}-{:0width$x} : {}\n", claim.start, claim.end, claim.owner, width = width)).collect()
+        }
+    }
+
+    impl Default for ResourceRegistry {
+        fn default() -> Self {
+            ResourceRegistry::new()
+        }
+    }
+
+    // A claimed range, released when dropped
+    pub struct Region {
+        registry: &'static ResourceRegistry,
+        kind: ResourceKind,
+        start: u64,
+        end: u64,
+    }
+
+    impl Region {
+        pub fn kind(&self) -> ResourceKind {
+            self.kind
+        }
+
+        pub fn start(&self) -> u64 {
+            self.start
+        }
+
+        pub fn end(&self) -> u64 {
+            self.end
+        }
+
+        pub fn contains(&self, address: u64) -> bool {
+            (self.start..=self.end).contains(&address)
+        }
+
+        // Port access limited to this region, for handing to a driver
+        pub fn ports<P: PortIo>(self, ports: P) -> Result<ClaimedPorts<P>, &'static str> {
+            if self.kind != ResourceKind::Io {
+                return Err("Not an I/O port region");
+            }
+            Ok(ClaimedPorts { region: self, ports })
+        }
+    }
+
+    impl Drop for Region {
+        fn drop(&mut self) {
+            self.registry.release(self.kind, self.start);
+        }
+    }
+
+    // Ports outside the region read as nothing there and ignore writes,
+    // so a driver with the wrong base cannot reach another's hardware
+    pub struct ClaimedPorts<P: PortIo> {
+        region: Region,
+        ports: P,
+    }
+
+    impl<P: PortIo> ClaimedPorts<P> {
+        pub fn region(&self) -> &Region {
+            &self.region
+        }
+
+        fn check(&self, port: u16) -> bool {
+            if self.region.contains(port as u64) {
+                return true;
+            }
+            log::error!("resource: port {:#x} is outside {:#x}-{:#x}", port, self.region.start, self.region.end);
+            false
+        }
+    }
+
+    impl<P: PortIo> PortIo for ClaimedPorts<P> {
+        fn inb(&self, port: u16) -> u8 {
+            match self.check(port) {
+                true => self.ports.inb(port),
+                false => 0xFF,
+            }
+        }
+
+        fn outb(&self, port: u16, value: u8) {
+            if self.check(port) {
+                self.ports.outb(port, value);
+            }
+        }
+    }
+
+    // The kernel's own
+    pub fn resources() -> &'static ResourceRegistry {
+        static RESOURCES: ResourceRegistry = ResourceRegistry::new();
+        &RESOURCES
+    }
+}
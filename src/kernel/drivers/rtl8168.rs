@@ -0,0 +1,73 @@
+// src/kernel/drivers/rtl8168.rs
+
+pub mod rtl8168 {
+    use crate::drivers::mmio::mmio::RegisterIo;
+    use crate::power::wake::wake::WakeOnLan;
+
+    const CFG9346: usize = 0x50;
+    const CONFIG1: usize = 0x52;
+    const CONFIG3: usize = 0x59;
+    const CONFIG5: usize = 0x56;
+
+    const CFG9346_UNLOCK: u8 = 0xC0;
+    const CFG9346_LOCK: u8 = 0x00;
+    const CONFIG1_PM_ENABLE: u8 = 1 << 0;
+    const CONFIG3_LINK_UP: u8 = 1 << 4;
+    const CONFIG3_MAGIC_PACKET: u8 = 1 << 5;
+    const CONFIG5_LAN_WAKE: u8 = 1 << 1;
+    const CONFIG5_UNICAST: u8 = 1 << 4;
+    const CONFIG5_MULTICAST: u8 = 1 << 5;
+    const CONFIG5_BROADCAST: u8 = 1 << 6;
+
+    // Wake-on-LAN programming for the RTL8168 family. The wake filters live
+    // in the Config registers, which are write-protected until Cfg9346
+    // unlocks them; they survive the D3 transition with PME enabled.
+    pub struct Rtl8168Wake<R: RegisterIo> {
+        registers: R,
+    }
+
+    impl<R: RegisterIo> Rtl8168Wake<R> {
+        pub fn new(registers: R) -> Self {
+            Rtl8168Wake { registers }
+        }
+
+        pub fn capabilities(&self) -> WakeOnLan {
+            WakeOnLan {
+                magic_packet: true,
+                unicast: true,
+                multicast: true,
+                broadcast: true,
+                link_change: true,
+            }
+        }
+
+        fn update(&self, offset: usize, mask: u8, set: bool) {
+            let value = self.registers.read8(offset);
+            self.registers.write8(offset, if set { value | mask } else { value & !mask });
+        }
+
+        pub fn configure(&self, config: WakeOnLan) {
+            self.registers.write8(CFG9346, CFG9346_UNLOCK);
+            self.update(CONFIG3, CONFIG3_MAGIC_PACKET, config.magic_packet);
+            self.update(CONFIG3, CONFIG3_LINK_UP, config.link_change);
+            self.update(CONFIG5, CONFIG5_UNICAST, config.unicast);
+            self.update(CONFIG5, CONFIG5_MULTICAST, config.multicast);
+            self.update(CONFIG5, CONFIG5_BROADCAST, config.broadcast);
+            self.update(CONFIG5, CONFIG5_LAN_WAKE, config.any());
+            self.update(CONFIG1, CONFIG1_PM_ENABLE, config.any());
+            self.registers.write8(CFG9346, CFG9346_LOCK);
+        }
+
+        pub fn configuration(&self) -> WakeOnLan {
+            let config3 = self.registers.read8(CONFIG3);
+            let config5 = self.registers.read8(CONFIG5);
+            WakeOnLan {
+                magic_packet: config3 & CONFIG3_MAGIC_PACKET != 0,
+                unicast: config5 & CONFIG5_UNICAST != 0,
+                multicast: config5 & CONFIG5_MULTICAST != 0,
+                broadcast: config5 & CONFIG5_BROADCAST != 0,
+                link_change: config3 & CONFIG3_LINK_UP != 0,
+            }
+        }
+    }
+}
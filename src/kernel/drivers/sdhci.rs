@@ -0,0 +1,702 @@
+// src/kernel/drivers/sdhci.rs
+
+pub mod sdhci {
+    use crate::drivers::block::block::{registry, BlockDevice};
+    use crate::drivers::mmio::mmio::RegisterIo;
+    use crate::faultinject::faultinject::{FAIL_DMA, FAIL_IO};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    // SD Host Controller register offsets (SD Host Controller Simplified Spec v3.00)
+    const BLOCK_SIZE: usize = 0x04;
+    const BLOCK_COUNT: usize = 0x06;
+    const ARGUMENT: usize = 0x08;
+    const TRANSFER_MODE: usize = 0x0C;
+    const COMMAND: usize = 0x0E;
+    const RESPONSE: usize = 0x10;
+    const BUFFER_DATA: usize = 0x20;
+    const PRESENT_STATE: usize = 0x24;
+    const HOST_CONTROL1: usize = 0x28;
+    const POWER_CONTROL: usize = 0x29;
+    const CLOCK_CONTROL: usize = 0x2C;
+    const TIMEOUT_CONTROL: usize = 0x2E;
+    const SOFTWARE_RESET: usize = 0x2F;
+    const NORMAL_INT_STATUS: usize = 0x30;
+    const ERROR_INT_STATUS: usize = 0x32;
+    const NORMAL_INT_ENABLE: usize = 0x34;
+    const ERROR_INT_ENABLE: usize = 0x36;
+    const HOST_CONTROL2: usize = 0x3E;
+    const CAPABILITIES: usize = 0x40;
+    const CAPABILITIES1: usize = 0x44;
+
+    const PRESENT_CMD_INHIBIT: u32 = 1 << 0;
+    const PRESENT_DAT_INHIBIT: u32 = 1 << 1;
+    const PRESENT_DAT_LINES: u32 = 0xF << 20;
+    const PRESENT_CARD_INSERTED: u32 = 1 << 16;
+    const PRESENT_WRITE_ENABLED: u32 = 1 << 19;
+
+    const INT_CMD_COMPLETE: u16 = 1 << 0;
+    const INT_TRANSFER_COMPLETE: u16 = 1 << 1;
+    const INT_BUFFER_WRITE_READY: u16 = 1 << 4;
+    const INT_BUFFER_READ_READY: u16 = 1 << 5;
+    const INT_CARD_INSERTION: u16 = 1 << 6;
+    const INT_CARD_REMOVAL: u16 = 1 << 7;
+    const INT_ERROR: u16 = 1 << 15;
+
+    const CLOCK_INTERNAL_ENABLE: u16 = 1 << 0;
+    const CLOCK_INTERNAL_STABLE: u16 = 1 << 1;
+    const CLOCK_SD_ENABLE: u16 = 1 << 2;
+
+    const HC2_UHS_MASK: u16 = 0x7;
+    const HC2_1V8_SIGNALING: u16 = 1 << 3;
+    const HC2_EXECUTE_TUNING: u16 = 1 << 6;
+    const HC2_SAMPLING_CLOCK: u16 = 1 << 7;
+
+    const CAP1_SDR50: u32 = 1 << 0;
+    const CAP1_SDR104: u32 = 1 << 1;
+    const CAP1_TUNING_FOR_SDR50: u32 = 1 << 13;
+
+    const RESET_ALL: u8 = 1 << 0;
+    const RESET_CMD: u8 = 1 << 1;
+    const RESET_DAT: u8 = 1 << 2;
+
+    const SECTOR_SIZE: usize = 512;
+    const TUNING_BLOCK_SIZE: usize = 64;
+    const MAX_TUNING_LOOPS: usize = 40;
+    const COMMAND_TIMEOUT: Duration = Duration::from_millis(100);
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ResponseType {
+        None,
+        R2,
+        R1,
+        R1b,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum BusSpeed {
+        DefaultSpeed,
+        HighSpeed,
+        Sdr50,
+        Sdr104,
+    }
+
+    impl BusSpeed {
+        fn clock_hz(&self) -> u32 {
+            match self {
+                BusSpeed::DefaultSpeed => 25_000_000,
+                BusSpeed::HighSpeed => 50_000_000,
+                BusSpeed::Sdr50 => 100_000_000,
+                BusSpeed::Sdr104 => 208_000_000,
+            }
+        }
+
+        fn uhs_mode(&self) -> u16 {
+            match self {
+                BusSpeed::DefaultSpeed => 0,
+                BusSpeed::HighSpeed => 1,
+                BusSpeed::Sdr50 => 2,
+                BusSpeed::Sdr104 => 3,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct CardInfo {
+        pub rca: u16,
+        pub high_capacity: bool,
+        pub block_count: u64,
+        pub speed: BusSpeed,
+        pub write_protected: bool,
+    }
+
+    pub struct Command {
+        pub index: u8,
+        pub argument: u32,
+        pub response: ResponseType,
+        pub data: Option<DataDirection>,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum DataDirection {
+        Read { block_size: usize, blocks: u16 },
+        Write { block_size: usize, blocks: u16 },
+    }
+
+    // 10-bit divided clock mode: SDCLK = base / (2 * N), N == 0 means base clock
+    pub fn clock_divider(base_hz: u32, target_hz: u32) -> u16 {
+        if target_hz == 0 || base_hz <= target_hz {
+            return 0;
+        }
+        let divisor = base_hz.div_ceil(target_hz);
+        (divisor.div_ceil(2)).min(0x3FF) as u16
+    }
+
+    fn csd_bits(response: u128, high: u32, low: u32) -> u64 {
+        // The controller strips the CRC byte, so CSD bit n lives at response bit n - 8
+        let width = high - low + 1;
+        ((response >> (low - 8)) & ((1u128 << width) - 1)) as u64
+    }
+
+    // Returns the card capacity in 512-byte sectors from a raw R2 CSD response
+    pub fn parse_csd_capacity(response: [u32; 4]) -> Result<u64, &'static str> {
+        let raw = response[0] as u128
+            | (response[1] as u128) << 32
+            | (response[2] as u128) << 64
+            | (response[3] as u128) << 96;
+        match csd_bits(raw, 127, 126) {
+            0 => {
+                let read_bl_len = csd_bits(raw, 83, 80);
+                let c_size = csd_bits(raw, 73, 62);
+                let c_size_mult = csd_bits(raw, 49, 47);
+                let bytes = (c_size + 1) << (c_size_mult + 2 + read_bl_len);
+                Ok(bytes / SECTOR_SIZE as u64)
+            }
+            1 => {
+                let c_size = csd_bits(raw, 69, 48);
+                Ok((c_size + 1) * 1024)
+            }
+            _ => Err("Unsupported CSD structure version"),
+        }
+    }
+
+    pub struct SdhciController<R: RegisterIo> {
+        regs: R,
+        base_clock_hz: u32,
+    }
+
+    impl<R: RegisterIo> SdhciController<R> {
+        pub fn new(regs: R) -> Self {
+            let capabilities = regs.read32(CAPABILITIES);
+            let base_clock_mhz = (capabilities >> 8) & 0xFF;
+            SdhciController {
+                regs,
+                base_clock_hz: base_clock_mhz * 1_000_000,
+            }
+        }
+
+        pub fn card_present(&self) -> bool {
+            self.regs.read32(PRESENT_STATE) & PRESENT_CARD_INSERTED != 0
+        }
+
+        pub fn write_protected(&self) -> bool {
+            self.regs.read32(PRESENT_STATE) & PRESENT_WRITE_ENABLED == 0
+        }
+
+        pub fn reset(&self, mask: u8) -> Result<(), &'static str> {
+            self.regs.write8(SOFTWARE_RESET, mask);
+            self.wait_for(|regs| regs.read8(SOFTWARE_RESET) & mask == 0, "Controller reset timed out")
+        }
+
+        pub fn initialize(&self) -> Result<(), &'static str> {
+            log::info!("Initializing SDHCI controller...");
+            self.reset(RESET_ALL)?;
+            self.regs.write8(TIMEOUT_CONTROL, 0xE);
+            self.regs.write16(NORMAL_INT_ENABLE, 0xFFFF);
+            self.regs.write16(ERROR_INT_ENABLE, 0xFFFF);
+            // 3.3V bus power
+            self.regs.write8(POWER_CONTROL, 0b1111);
+            self.set_clock(400_000)
+        }
+
+        pub fn set_clock(&self, target_hz: u32) -> Result<(), &'static str> {
+            self.regs.write16(CLOCK_CONTROL, 0);
+            let divider = clock_divider(self.base_clock_hz, target_hz);
+            let clock = ((divider & 0xFF) << 8) | (((divider >> 8) & 0x3) << 6) | CLOCK_INTERNAL_ENABLE;
+            self.regs.write16(CLOCK_CONTROL, clock);
+            self.wait_for(
+                |regs| regs.read16(CLOCK_CONTROL) & CLOCK_INTERNAL_STABLE != 0,
+                "Internal clock did not stabilize",
+            )?;
+            self.regs.write16(CLOCK_CONTROL, self.regs.read16(CLOCK_CONTROL) | CLOCK_SD_ENABLE);
+            Ok(())
+        }
+
+        fn wait_for<F: Fn(&R) -> bool>(&self, condition: F, error: &'static str) -> Result<(), &'static str> {
+            let start = Instant::now();
+            while !condition(&self.regs) {
+                if start.elapsed() > COMMAND_TIMEOUT {
+                    return Err(error);
+                }
+                thread::yield_now();
+            }
+            Ok(())
+        }
+
+        fn wait_interrupt(&self, mask: u16) -> Result<(), &'static str> {
+            let start = Instant::now();
+            loop {
+                let status = self.regs.read16(NORMAL_INT_STATUS);
+                if status & INT_ERROR != 0 {
+                    let errors = self.regs.read16(ERROR_INT_STATUS);
+                    self.regs.write16(ERROR_INT_STATUS, errors);
+                    self.regs.write16(NORMAL_INT_STATUS, status);
+                    self.reset(RESET_CMD | RESET_DAT)?;
+                    return Err("SD command failed");
+                }
+                if status & mask != 0 {
+                    // Status bits are write-one-to-clear
+                    self.regs.write16(NORMAL_INT_STATUS, status & mask);
+                    return Ok(());
+                }
+                if start.elapsed() > COMMAND_TIMEOUT {
+                    return Err("SD command timed out");
+                }
+                thread::yield_now();
+            }
+        }
+
+        pub fn send_command(&self, command: &Command) -> Result<[u32; 4], &'static str> {
+            let inhibit = match command.data {
+                Some(_) => PRESENT_CMD_INHIBIT | PRESENT_DAT_INHIBIT,
+                None => PRESENT_CMD_INHIBIT,
+            };
+            self.wait_for(|regs| regs.read32(PRESENT_STATE) & inhibit == 0, "Command line busy")?;
+
+            let mut flags: u16 = match command.response {
+                ResponseType::None => 0,
+                ResponseType::R2 => 0x1 | (1 << 3),
+                ResponseType::R1 => 0x2 | (1 << 3) | (1 << 4),
+                ResponseType::R1b => 0x3 | (1 << 3) | (1 << 4),
+            };
+
+            if let Some(direction) = command.data {
+                let (block_size, blocks, read) = match direction {
+                    DataDirection::Read { block_size, blocks } => (block_size, blocks, true),
+                    DataDirection::Write { block_size, blocks } => (block_size, blocks, false),
+                };
+                self.regs.write16(BLOCK_SIZE, block_size as u16);
+                self.regs.write16(BLOCK_COUNT, blocks);
+                let mut mode: u16 = 1 << 1;
+                if read {
+                    mode |= 1 << 4;
+                }
+                if blocks > 1 {
+                    // Multi-block with Auto CMD12
+                    mode |= (1 << 5) | (1 << 2);
+                }
+                self.regs.write16(TRANSFER_MODE, mode);
+                flags |= 1 << 5;
+            }
+
+            self.regs.write32(ARGUMENT, command.argument);
+            self.regs.write16(COMMAND, ((command.index as u16) << 8) | flags);
+            self.wait_interrupt(INT_CMD_COMPLETE)?;
+
+            Ok([
+                self.regs.read32(RESPONSE),
+                self.regs.read32(RESPONSE + 4),
+                self.regs.read32(RESPONSE + 8),
+                self.regs.read32(RESPONSE + 12),
+            ])
+        }
+
+        fn send_app_command(&self, rca: u16, command: &Command) -> Result<[u32; 4], &'static str> {
+            self.send_command(&Command {
+                index: 55,
+                argument: (rca as u32) << 16,
+                response: ResponseType::R1,
+                data: None,
+            })?;
+            self.send_command(command)
+        }
+
+        fn read_buffer(&self, buffer: &mut [u8], block_size: usize) -> Result<(), &'static str> {
+            for block in buffer.chunks_mut(block_size) {
+                self.wait_interrupt(INT_BUFFER_READ_READY)?;
+                for word in block.chunks_mut(4) {
+                    let value = self.regs.read32(BUFFER_DATA).to_le_bytes();
+                    word.copy_from_slice(&value[..word.len()]);
+                }
+            }
+            self.wait_interrupt(INT_TRANSFER_COMPLETE)
+        }
+
+        fn write_buffer(&self, buffer: &[u8], block_size: usize) -> Result<(), &'static str> {
+            for block in buffer.chunks(block_size) {
+                self.wait_interrupt(INT_BUFFER_WRITE_READY)?;
+                for word in block.chunks(4) {
+                    let mut value = [0u8; 4];
+                    value[..word.len()].copy_from_slice(word);
+                    self.regs.write32(BUFFER_DATA, u32::from_le_bytes(value));
+                }
+            }
+            self.wait_interrupt(INT_TRANSFER_COMPLETE)
+        }
+
+        fn supports(&self, speed: BusSpeed) -> bool {
+            let capabilities = self.regs.read32(CAPABILITIES1);
+            match speed {
+                BusSpeed::Sdr104 => capabilities & CAP1_SDR104 != 0,
+                BusSpeed::Sdr50 => capabilities & CAP1_SDR50 != 0,
+                _ => true,
+            }
+        }
+
+        fn needs_tuning(&self, speed: BusSpeed) -> bool {
+            match speed {
+                BusSpeed::Sdr104 => true,
+                BusSpeed::Sdr50 => self.regs.read32(CAPABILITIES1) & CAP1_TUNING_FOR_SDR50 != 0,
+                _ => false,
+            }
+        }
+
+        // Runs the standard tuning procedure with CMD19 until the controller
+        // clears Execute Tuning, then checks that a sampling point was locked
+        pub fn execute_tuning(&self) -> Result<(), &'static str> {
+            log::info!("Executing SDHCI tuning...");
+            let control = self.regs.read16(HOST_CONTROL2);
+            self.regs.write16(HOST_CONTROL2, control | HC2_EXECUTE_TUNING);
+
+            let mut pattern = [0u8; TUNING_BLOCK_SIZE];
+            for _ in 0..MAX_TUNING_LOOPS {
+                self.send_command(&Command {
+                    index: 19,
+                    argument: 0,
+                    response: ResponseType::R1,
+                    data: Some(DataDirection::Read {
+                        block_size: TUNING_BLOCK_SIZE,
+                        blocks: 1,
+                    }),
+                })?;
+                self.wait_interrupt(INT_BUFFER_READ_READY)?;
+                for word in pattern.chunks_mut(4) {
+                    word.copy_from_slice(&self.regs.read32(BUFFER_DATA).to_le_bytes());
+                }
+
+                let control = self.regs.read16(HOST_CONTROL2);
+                if control & HC2_EXECUTE_TUNING == 0 {
+                    if control & HC2_SAMPLING_CLOCK != 0 {
+                        return Ok(());
+                    }
+                    break;
+                }
+            }
+
+            let control = self.regs.read16(HOST_CONTROL2);
+            self.regs.write16(HOST_CONTROL2, control & !(HC2_EXECUTE_TUNING | HC2_SAMPLING_CLOCK));
+            self.reset(RESET_CMD | RESET_DAT)?;
+            Err("SDHCI tuning failed")
+        }
+
+        fn switch_bus_speed(&self, speed: BusSpeed) -> Result<(), &'static str> {
+            // CMD6 switch function, access mode group 1
+            let mut status = [0u8; 64];
+            self.send_command(&Command {
+                index: 6,
+                argument: 0x80FF_FFF0 | speed.uhs_mode() as u32,
+                response: ResponseType::R1,
+                data: Some(DataDirection::Read { block_size: 64, blocks: 1 }),
+            })?;
+            self.read_buffer(&mut status, 64)?;
+            if (status[16] & 0xF) as u16 != speed.uhs_mode() {
+                return Err("Card rejected bus speed switch");
+            }
+
+            let control = self.regs.read16(HOST_CONTROL2) & !HC2_UHS_MASK;
+            self.regs.write16(HOST_CONTROL2, control | speed.uhs_mode());
+            if speed != BusSpeed::DefaultSpeed {
+                self.regs.write8(HOST_CONTROL1, self.regs.read8(HOST_CONTROL1) | (1 << 2));
+            }
+            self.set_clock(speed.clock_hz())?;
+            if self.needs_tuning(speed) {
+                self.execute_tuning()?;
+            }
+            Ok(())
+        }
+
+        // VOLTAGE_SWITCH before the card is identified, following the signal
+        // voltage switch sequence of the host controller spec (3.6.1)
+        fn switch_signal_voltage(&self) -> Result<(), &'static str> {
+            self.send_command(&Command { index: 11, argument: 0, response: ResponseType::R1, data: None })?;
+
+            // The card drives DAT[3:0] low once it has accepted the switch
+            self.regs.write16(CLOCK_CONTROL, self.regs.read16(CLOCK_CONTROL) & !CLOCK_SD_ENABLE);
+            if self.regs.read32(PRESENT_STATE) & PRESENT_DAT_LINES != 0 {
+                return Err("Card did not accept the voltage switch");
+            }
+
+            let control = self.regs.read16(HOST_CONTROL2);
+            self.regs.write16(HOST_CONTROL2, control | HC2_1V8_SIGNALING);
+            thread::sleep(Duration::from_millis(5));
+            if self.regs.read16(HOST_CONTROL2) & HC2_1V8_SIGNALING == 0 {
+                return Err("1.8V signaling regulator failed");
+            }
+
+            // And releases them high within 1ms of getting its clock back
+            self.regs.write16(CLOCK_CONTROL, self.regs.read16(CLOCK_CONTROL) | CLOCK_SD_ENABLE);
+            thread::sleep(Duration::from_millis(1));
+            if self.regs.read32(PRESENT_STATE) & PRESENT_DAT_LINES != PRESENT_DAT_LINES {
+                return Err("Card failed to switch to 1.8V signaling");
+            }
+            Ok(())
+        }
+
+        pub fn initialize_card(&self) -> Result<CardInfo, &'static str> {
+            if !self.card_present() {
+                return Err("No card present");
+            }
+            log::info!("Initializing SD card...");
+
+            // GO_IDLE_STATE, then SEND_IF_COND with the 0xAA check pattern
+            self.send_command(&Command { index: 0, argument: 0, response: ResponseType::None, data: None })?;
+            let if_cond = self.send_command(&Command {
+                index: 8,
+                argument: 0x1AA,
+                response: ResponseType::R1,
+                data: None,
+            })?;
+            let sd_v2 = if_cond[0] & 0xFFF == 0x1AA;
+
+            // SD_SEND_OP_COND until the card leaves the busy state
+            let mut argument = 0x00FF_8000;
+            if sd_v2 {
+                argument |= (1 << 30) | (1 << 24);
+            }
+            let start = Instant::now();
+            let ocr = loop {
+                let response = self.send_app_command(
+                    0,
+                    &Command { index: 41, argument, response: ResponseType::R1, data: None },
+                )?;
+                if response[0] & (1 << 31) != 0 {
+                    break response[0];
+                }
+                if start.elapsed() > Duration::from_secs(1) {
+                    return Err("Card did not leave busy state");
+                }
+                thread::sleep(Duration::from_millis(10));
+            };
+            let high_capacity = ocr & (1 << 30) != 0;
+            let accepts_1v8 = ocr & (1 << 24) != 0;
+
+            if accepts_1v8 {
+                self.switch_signal_voltage()?;
+            }
+
+            self.send_command(&Command { index: 2, argument: 0, response: ResponseType::R2, data: None })?;
+            let rca_response =
+                self.send_command(&Command { index: 3, argument: 0, response: ResponseType::R1, data: None })?;
+            let rca = (rca_response[0] >> 16) as u16;
+
+            let csd = self.send_command(&Command {
+                index: 9,
+                argument: (rca as u32) << 16,
+                response: ResponseType::R2,
+                data: None,
+            })?;
+            let block_count = parse_csd_capacity(csd)?;
+
+            self.send_command(&Command {
+                index: 7,
+                argument: (rca as u32) << 16,
+                response: ResponseType::R1b,
+                data: None,
+            })?;
+
+            // 4-bit bus width on both sides
+            self.send_app_command(rca, &Command { index: 6, argument: 2, response: ResponseType::R1, data: None })?;
+            self.regs.write8(HOST_CONTROL1, self.regs.read8(HOST_CONTROL1) | (1 << 1));
+
+            if !high_capacity {
+                self.send_command(&Command {
+                    index: 16,
+                    argument: SECTOR_SIZE as u32,
+                    response: ResponseType::R1,
+                    data: None,
+                })?;
+            }
+
+            let candidates: &[BusSpeed] = if accepts_1v8 {
+                &[BusSpeed::Sdr104, BusSpeed::Sdr50, BusSpeed::HighSpeed]
+            } else {
+                &[BusSpeed::HighSpeed]
+            };
+            let mut speed = BusSpeed::DefaultSpeed;
+            for candidate in candidates {
+                if self.supports(*candidate) && self.switch_bus_speed(*candidate).is_ok() {
+                    speed = *candidate;
+                    break;
+                }
+            }
+            if speed == BusSpeed::DefaultSpeed {
+                self.set_clock(speed.clock_hz())?;
+            }
+
+            log::info!("SD card ready: {} sectors, {:?}", block_count, speed);
+            Ok(CardInfo {
+                rca,
+                high_capacity,
+                block_count,
+                speed,
+                write_protected: self.write_protected(),
+            })
+        }
+
+        pub fn read_blocks(&self, card: &CardInfo, lba: u64, buffer: &mut [u8]) -> Result<(), &'static str> {
+            let blocks = check_transfer(card, lba, buffer.len())?;
+            self.send_command(&Command {
+                index: if blocks > 1 { 18 } else { 17 },
+                argument: block_address(card, lba),
+                response: ResponseType::R1,
+                data: Some(DataDirection::Read { block_size: SECTOR_SIZE, blocks }),
+            })?;
+            self.read_buffer(buffer, SECTOR_SIZE)?;
+            FAIL_DMA.corrupt(buffer);
+            Ok(())
+        }
+
+        pub fn write_blocks(&self, card: &CardInfo, lba: u64, buffer: &[u8]) -> Result<(), &'static str> {
+            if card.write_protected {
+                return Err("Card is write protected");
+            }
+            let blocks = check_transfer(card, lba, buffer.len())?;
+            self.send_command(&Command {
+                index: if blocks > 1 { 25 } else { 24 },
+                argument: block_address(card, lba),
+                response: ResponseType::R1,
+                data: Some(DataDirection::Write { block_size: SECTOR_SIZE, blocks }),
+            })?;
+            self.write_buffer(buffer, SECTOR_SIZE)
+        }
+
+        // Acknowledges card detect interrupts; returns Some(true) on insertion
+        // and Some(false) on removal
+        pub fn take_card_event(&self) -> Option<bool> {
+            let status = self.regs.read16(NORMAL_INT_STATUS);
+            let events = status & (INT_CARD_INSERTION | INT_CARD_REMOVAL);
+            if events == 0 {
+                return None;
+            }
+            self.regs.write16(NORMAL_INT_STATUS, events);
+            Some(events & INT_CARD_INSERTION != 0 && self.card_present())
+        }
+    }
+
+    fn check_transfer(card: &CardInfo, lba: u64, length: usize) -> Result<u16, &'static str> {
+        if length == 0 || length % SECTOR_SIZE != 0 {
+            return Err("Buffer must be a multiple of the sector size");
+        }
+        let blocks = (length / SECTOR_SIZE) as u64;
+        if lba + blocks > card.block_count {
+            return Err("Transfer beyond end of card");
+        }
+        u16::try_from(blocks).map_err(|_| "Transfer too large")
+    }
+
+    fn block_address(card: &CardInfo, lba: u64) -> u32 {
+        // Standard capacity cards are byte addressed
+        if card.high_capacity {
+            lba as u32
+        } else {
+            (lba * SECTOR_SIZE as u64) as u32
+        }
+    }
+
+    pub struct SdCard<R: RegisterIo> {
+        controller: Arc<Mutex<SdhciController<R>>>,
+        info: CardInfo,
+    }
+
+    impl<R: RegisterIo> SdCard<R> {
+        pub fn info(&self) -> CardInfo {
+            self.info
+        }
+    }
+
+    impl<R: RegisterIo> BlockDevice for SdCard<R> {
+        fn block_size(&self) -> usize {
+            SECTOR_SIZE
+        }
+
+        fn block_count(&self) -> u64 {
+            self.info.block_count
+        }
+
+        fn read_blocks(&self, lba: u64, buffer: &mut [u8]) -> Result<(), &'static str> {
+            if FAIL_IO.should_fail() {
+                return Err("Injected I/O error");
+            }
+            self.controller.lock().unwrap().read_blocks(&self.info, lba, buffer)
+        }
+
+        fn write_blocks(&self, lba: u64, buffer: &[u8]) -> Result<(), &'static str> {
+            if FAIL_IO.should_fail() {
+                return Err("Injected I/O error");
+            }
+            self.controller.lock().unwrap().write_blocks(&self.info, lba, buffer)
+        }
+
+        fn read_only(&self) -> bool {
+            self.info.write_protected
+        }
+    }
+
+    // Owns a controller and keeps the block registry in sync with the slot
+    pub struct SdhciHost<R: RegisterIo + 'static> {
+        controller: Arc<Mutex<SdhciController<R>>>,
+        device_name: Option<String>,
+    }
+
+    impl<R: RegisterIo + 'static> SdhciHost<R> {
+        pub fn new(regs: R) -> Result<Self, &'static str> {
+            let controller = SdhciController::new(regs);
+            controller.initialize()?;
+            Ok(SdhciHost {
+                controller: Arc::new(Mutex::new(controller)),
+                device_name: None,
+            })
+        }
+
+        pub fn device_name(&self) -> Option<&str> {
+            self.device_name.as_deref()
+        }
+
+        pub fn probe_card(&mut self) -> Result<(), &'static str> {
+            if self.device_name.is_some() {
+                return Ok(());
+            }
+            let info = self.controller.lock().unwrap().initialize_card()?;
+            let name = registry().next_name("mmcblk");
+            registry().register(
+                &name,
+                Arc::new(SdCard {
+                    controller: Arc::clone(&self.controller),
+                    info,
+                }),
+            )?;
+            self.device_name = Some(name);
+            Ok(())
+        }
+
+        pub fn remove_card(&mut self) {
+            if let Some(name) = self.device_name.take() {
+                log::info!("SD card removed from {}", name);
+                let _ = registry().unregister(&name);
+            }
+        }
+
+        // Called from the controller interrupt (or a polling tasklet)
+        pub fn handle_card_detect(&mut self) -> Result<(), &'static str> {
+            let event = self.controller.lock().unwrap().take_card_event();
+            match event {
+                Some(true) => {}
+                Some(false) => {
+                    self.remove_card();
+                    return Ok(());
+                }
+                None => return Ok(()),
+            }
+            self.probe_card()
+        }
+    }
+
+    pub fn sdhci_init<R: RegisterIo + 'static>(regs: R) -> Result<SdhciHost<R>, &'static str> {
+        let mut host = SdhciHost::new(regs)?;
+        if host.controller.lock().unwrap().card_present() {
+            host.probe_card()?;
+        }
+        Ok(host)
+    }
+}
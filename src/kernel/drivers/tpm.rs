@@ -0,0 +1,504 @@
+// src/kernel/drivers/tpm.rs
+
+pub mod tpm {
+    use crate::drivers::mmio::mmio::RegisterIo;
+    use sha2::{Digest, Sha256};
+    use zeroize::Zeroize;
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    const TPM_TIMEOUT: Duration = Duration::from_millis(750);
+
+    // TIS (FIFO) interface, locality 0
+    const TIS_ACCESS: usize = 0x00;
+    const TIS_STS: usize = 0x18;
+    const TIS_DATA_FIFO: usize = 0x24;
+    const TIS_DID_VID: usize = 0xF00;
+
+    const ACCESS_REQUEST_USE: u8 = 1 << 1;
+    const ACCESS_ACTIVE_LOCALITY: u8 = 1 << 5;
+    const ACCESS_VALID: u8 = 1 << 7;
+
+    const STS_EXPECT: u32 = 1 << 3;
+    const STS_DATA_AVAIL: u32 = 1 << 4;
+    const STS_GO: u32 = 1 << 5;
+    const STS_COMMAND_READY: u32 = 1 << 6;
+    const STS_VALID: u32 = 1 << 7;
+
+    // CRB interface, locality 0
+    const CRB_LOC_CTRL: usize = 0x08;
+    const CRB_LOC_STS: usize = 0x0C;
+    const CRB_CTRL_REQ: usize = 0x40;
+    const CRB_CTRL_STS: usize = 0x44;
+    const CRB_CTRL_START: usize = 0x4C;
+    const CRB_CTRL_CMD_SIZE: usize = 0x58;
+    const CRB_CTRL_RSP_SIZE: usize = 0x64;
+    const CRB_DATA_BUFFER: usize = 0x80;
+
+    const CRB_LOC_REQUEST_ACCESS: u32 = 1 << 0;
+    const CRB_LOC_GRANTED: u32 = 1 << 0;
+    const CRB_REQ_CMD_READY: u32 = 1 << 0;
+    const CRB_REQ_GO_IDLE: u32 = 1 << 1;
+    const CRB_STS_ERROR: u32 = 1 << 0;
+
+    // TPM 2.0 command encoding
+    const TPM_ST_NO_SESSIONS: u16 = 0x8001;
+    const TPM_ST_SESSIONS: u16 = 0x8002;
+    const TPM_CC_STARTUP: u32 = 0x0000_0144;
+    const TPM_CC_CREATE: u32 = 0x0000_0153;
+    const TPM_CC_LOAD: u32 = 0x0000_0157;
+    const TPM_CC_UNSEAL: u32 = 0x0000_015E;
+    const TPM_CC_FLUSH_CONTEXT: u32 = 0x0000_0165;
+    const TPM_CC_QUOTE: u32 = 0x0000_0158;
+    const TPM_CC_GET_RANDOM: u32 = 0x0000_017B;
+    const TPM_CC_PCR_READ: u32 = 0x0000_017E;
+    const TPM_CC_PCR_EXTEND: u32 = 0x0000_0182;
+    const TPM_SU_CLEAR: u16 = 0x0000;
+    const TPM_RS_PW: u32 = 0x4000_0009;
+    const TPM_ALG_SHA256: u16 = 0x000B;
+    const TPM_ALG_KEYEDHASH: u16 = 0x0008;
+    const TPM_ALG_NULL: u16 = 0x0010;
+    // fixedTPM, fixedParent and userWithAuth: a sealed object that never
+    // leaves this TPM or its parent, unsealed with an empty password
+    const SEALED_OBJECT_ATTRIBUTES: u32 = 0x0000_0052;
+    const TPM_RC_INITIALIZE: u32 = 0x0000_0100;
+
+    const HEADER_SIZE: usize = 10;
+    pub const PCR_COUNT: u32 = 24;
+    pub const SHA256_SIZE: usize = 32;
+    // The storage root key, at its usual persistent handle
+    pub const SRK_HANDLE: u32 = 0x8100_0001;
+    // The most a sealed data object can hold
+    pub const MAX_SEALED_SIZE: usize = 128;
+
+    pub trait TpmTransport: Send + Sync {
+        fn transmit(&self, command: &[u8]) -> Result<Vec<u8>, &'static str>;
+    }
+
+    fn wait_for<R: RegisterIo, F: Fn(&R) -> bool>(regs: &R, condition: F, error: &'static str) -> Result<(), &'static str> {
+        let start = Instant::now();
+        while !condition(regs) {
+            if start.elapsed() > TPM_TIMEOUT {
+                return Err(error);
+            }
+            thread::yield_now();
+        }
+        Ok(())
+    }
+
+    pub struct TisTransport<R: RegisterIo> {
+        regs: R,
+    }
+
+    impl<R: RegisterIo> TisTransport<R> {
+        pub fn new(regs: R) -> Result<Self, &'static str> {
+            let did_vid = regs.read32(TIS_DID_VID);
+            if did_vid == 0 || did_vid == 0xFFFF_FFFF {
+                return Err("No TIS TPM present");
+            }
+            log::info!("Found TIS TPM {:04x}:{:04x}", did_vid & 0xFFFF, did_vid >> 16);
+            regs.write8(TIS_ACCESS, ACCESS_REQUEST_USE);
+            wait_for(
+                &regs,
+                |regs| {
+                    let access = regs.read8(TIS_ACCESS);
+                    access & ACCESS_VALID != 0 && access & ACCESS_ACTIVE_LOCALITY != 0
+                },
+                "TPM locality 0 not granted",
+            )?;
+            Ok(TisTransport { regs })
+        }
+
+        fn burst_count(&self) -> usize {
+            ((self.regs.read32(TIS_STS) >> 8) & 0xFFFF) as usize
+        }
+
+        fn wait_burst(&self) -> Result<usize, &'static str> {
+            let start = Instant::now();
+            loop {
+                let burst = self.burst_count();
+                if burst > 0 {
+                    return Ok(burst);
+                }
+                if start.elapsed() > TPM_TIMEOUT {
+                    return Err("TPM burst count stayed zero");
+                }
+                thread::yield_now();
+            }
+        }
+
+        fn read_fifo(&self, buffer: &mut [u8]) -> Result<(), &'static str> {
+            let mut position = 0;
+            while position < buffer.len() {
+                let burst = self.wait_burst()?.min(buffer.len() - position);
+                for byte in &mut buffer[position..position + burst] {
+                    *byte = self.regs.read8(TIS_DATA_FIFO);
+                }
+                position += burst;
+            }
+            Ok(())
+        }
+    }
+
+    impl<R: RegisterIo> TpmTransport for TisTransport<R> {
+        fn transmit(&self, command: &[u8]) -> Result<Vec<u8>, &'static str> {
+            self.regs.write32(TIS_STS, STS_COMMAND_READY);
+            wait_for(&self.regs, |regs| regs.read32(TIS_STS) & STS_COMMAND_READY != 0, "TPM not ready")?;
+
+            let mut position = 0;
+            while position < command.len() {
+                let burst = self.wait_burst()?.min(command.len() - position);
+                for byte in &command[position..position + burst] {
+                    self.regs.write8(TIS_DATA_FIFO, *byte);
+                }
+                position += burst;
+            }
+            wait_for(&self.regs, |regs| regs.read32(TIS_STS) & STS_VALID != 0, "TPM status not valid")?;
+            if self.regs.read32(TIS_STS) & STS_EXPECT != 0 {
+                return Err("TPM expects more command data");
+            }
+
+            self.regs.write32(TIS_STS, STS_GO);
+            wait_for(
+                &self.regs,
+                |regs| regs.read32(TIS_STS) & (STS_VALID | STS_DATA_AVAIL) == (STS_VALID | STS_DATA_AVAIL),
+                "TPM command timed out",
+            )?;
+
+            let mut header = [0u8; HEADER_SIZE];
+            self.read_fifo(&mut header)?;
+            let size = u32::from_be_bytes([header[2], header[3], header[4], header[5]]) as usize;
+            if size < HEADER_SIZE {
+                return Err("TPM response too short");
+            }
+            let mut response = header.to_vec();
+            response.resize(size, 0);
+            self.read_fifo(&mut response[HEADER_SIZE..])?;
+            self.regs.write32(TIS_STS, STS_COMMAND_READY);
+            Ok(response)
+        }
+    }
+
+    pub struct CrbTransport<R: RegisterIo> {
+        regs: R,
+        buffer_size: usize,
+    }
+
+    impl<R: RegisterIo> CrbTransport<R> {
+        pub fn new(regs: R, buffer_size: usize) -> Result<Self, &'static str> {
+            regs.write32(CRB_LOC_CTRL, CRB_LOC_REQUEST_ACCESS);
+            wait_for(&regs, |regs| regs.read32(CRB_LOC_STS) & CRB_LOC_GRANTED != 0, "CRB locality not granted")?;
+            log::info!("Found CRB TPM with {} byte buffer", buffer_size);
+            Ok(CrbTransport { regs, buffer_size })
+        }
+    }
+
+    impl<R: RegisterIo> TpmTransport for CrbTransport<R> {
+        fn transmit(&self, command: &[u8]) -> Result<Vec<u8>, &'static str> {
+            if command.len() > self.buffer_size {
+                return Err("TPM command exceeds CRB buffer");
+            }
+            self.regs.write32(CRB_CTRL_REQ, CRB_REQ_CMD_READY);
+            wait_for(&self.regs, |regs| regs.read32(CRB_CTRL_REQ) & CRB_REQ_CMD_READY == 0, "CRB not ready")?;
+            if self.regs.read32(CRB_CTRL_STS) & CRB_STS_ERROR != 0 {
+                return Err("CRB reported a fatal error");
+            }
+
+            for (index, byte) in command.iter().enumerate() {
+                self.regs.write8(CRB_DATA_BUFFER + index, *byte);
+            }
+            self.regs.write32(CRB_CTRL_CMD_SIZE, command.len() as u32);
+            self.regs.write32(CRB_CTRL_RSP_SIZE, self.buffer_size as u32);
+            self.regs.write32(CRB_CTRL_START, 1);
+            wait_for(&self.regs, |regs| regs.read32(CRB_CTRL_START) & 1 == 0, "TPM command timed out")?;
+
+            let header: Vec<u8> = (0..HEADER_SIZE).map(|index| self.regs.read8(CRB_DATA_BUFFER + index)).collect();
+            let size = u32::from_be_bytes([header[2], header[3], header[4], header[5]]) as usize;
+            if !(HEADER_SIZE..=self.buffer_size).contains(&size) {
+                return Err("Invalid TPM response size");
+            }
+            let response = (0..size).map(|index| self.regs.read8(CRB_DATA_BUFFER + index)).collect();
+            self.regs.write32(CRB_CTRL_REQ, CRB_REQ_GO_IDLE);
+            Ok(response)
+        }
+    }
+
+    struct CommandBuilder {
+        bytes: Vec<u8>,
+    }
+
+    impl CommandBuilder {
+        fn new(tag: u16, code: u32) -> Self {
+            let mut bytes = Vec::with_capacity(64);
+            bytes.extend_from_slice(&tag.to_be_bytes());
+            bytes.extend_from_slice(&0u32.to_be_bytes());
+            bytes.extend_from_slice(&code.to_be_bytes());
+            CommandBuilder { bytes }
+        }
+
+        fn u8(mut self, value: u8) -> Self {
+            self.bytes.push(value);
+            self
+        }
+
+        fn u16(mut self, value: u16) -> Self {
+            self.bytes.extend_from_slice(&value.to_be_bytes());
+            self
+        }
+
+        fn u32(mut self, value: u32) -> Self {
+            self.bytes.extend_from_slice(&value.to_be_bytes());
+            self
+        }
+
+        fn bytes(mut self, value: &[u8]) -> Self {
+            self.bytes.extend_from_slice(value);
+            self
+        }
+
+        fn sized(self, value: &[u8]) -> Self {
+            self.u16(value.len() as u16).bytes(value)
+        }
+
+        // Empty password authorization for a single handle
+        fn password_session(self) -> Self {
+            self.u32(9).u32(TPM_RS_PW).u16(0).u8(0).u16(0)
+        }
+
+        fn pcr_selection(self, pcrs: &[u32]) -> Self {
+            let mut bitmap = [0u8; 3];
+            for pcr in pcrs {
+                bitmap[(*pcr / 8) as usize] |= 1 << (pcr % 8);
+            }
+            self.u32(1).u16(TPM_ALG_SHA256).u8(3).bytes(&bitmap)
+        }
+
+        fn finish(mut self) -> Vec<u8> {
+            let size = (self.bytes.len() as u32).to_be_bytes();
+            self.bytes[2..6].copy_from_slice(&size);
+            self.bytes
+        }
+    }
+
+    struct ResponseReader<'a> {
+        bytes: &'a [u8],
+        position: usize,
+    }
+
+    impl<'a> ResponseReader<'a> {
+        fn new(bytes: &'a [u8]) -> Self {
+            ResponseReader { bytes, position: HEADER_SIZE }
+        }
+
+        fn take(&mut self, length: usize) -> Result<&'a [u8], &'static str> {
+            let end = self.position + length;
+            let slice = self.bytes.get(self.position..end).ok_or("Truncated TPM response")?;
+            self.position = end;
+            Ok(slice)
+        }
+
+        fn u16(&mut self) -> Result<u16, &'static str> {
+            let bytes = self.take(2)?;
+            Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+        }
+
+        fn u32(&mut self) -> Result<u32, &'static str> {
+            let bytes = self.take(4)?;
+            Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        }
+
+        fn sized(&mut self) -> Result<&'a [u8], &'static str> {
+            let length = self.u16()? as usize;
+            self.take(length)
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Quote {
+        // Marshalled TPMS_ATTEST structure covered by the signature
+        pub attest: Vec<u8>,
+        // Marshalled TPMT_SIGNATURE
+        pub signature: Vec<u8>,
+    }
+
+    // Data sealed under a storage key, in the two halves TPM2_Create hands
+    // back: the encrypted sensitive part and the public area. Only the TPM
+    // that made it can load it again.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct SealedObject {
+        pub private: Vec<u8>,
+        pub public: Vec<u8>,
+    }
+
+    pub struct Tpm2<T: TpmTransport> {
+        transport: T,
+        lock: Mutex<()>,
+    }
+
+    impl<T: TpmTransport> Tpm2<T> {
+        pub fn new(transport: T) -> Self {
+            Tpm2 {
+                transport,
+                lock: Mutex::new(()),
+            }
+        }
+
+        fn execute(&self, command: &[u8]) -> Result<Vec<u8>, &'static str> {
+            let _guard = self.lock.lock().unwrap();
+            let response = self.transport.transmit(command)?;
+            if response.len() < HEADER_SIZE {
+                return Err("TPM response too short");
+            }
+            let code = u32::from_be_bytes([response[6], response[7], response[8], response[9]]);
+            if code != 0 {
+                log::warn!("TPM command failed with response code {:#x}", code);
+                return Err("TPM command failed");
+            }
+            Ok(response)
+        }
+
+        pub fn startup(&self) -> Result<(), &'static str> {
+            let command = CommandBuilder::new(TPM_ST_NO_SESSIONS, TPM_CC_STARTUP).u16(TPM_SU_CLEAR).finish();
+            let _guard = self.lock.lock().unwrap();
+            let response = self.transport.transmit(&command)?;
+            // Firmware normally already issued Startup; TPM_RC_INITIALIZE is fine
+            match response.get(6..10).map(|code| u32::from_be_bytes([code[0], code[1], code[2], code[3]])) {
+                Some(0) | Some(TPM_RC_INITIALIZE) => Ok(()),
+                _ => Err("TPM startup failed"),
+            }
+        }
+
+        pub fn pcr_extend(&self, pcr: u32, digest: &[u8; SHA256_SIZE]) -> Result<(), &'static str> {
+            if pcr >= PCR_COUNT {
+                return Err("PCR index out of range");
+            }
+            let command = CommandBuilder::new(TPM_ST_SESSIONS, TPM_CC_PCR_EXTEND)
+                .u32(pcr)
+                .password_session()
+                .u32(1)
+                .u16(TPM_ALG_SHA256)
+                .bytes(digest)
+                .finish();
+            self.execute(&command).map(|_| ())
+        }
+
+        pub fn pcr_read(&self, pcr: u32) -> Result<[u8; SHA256_SIZE], &'static str> {
+            if pcr >= PCR_COUNT {
+                return Err("PCR index out of range");
+            }
+            let command = CommandBuilder::new(TPM_ST_NO_SESSIONS, TPM_CC_PCR_READ).pcr_selection(&[pcr]).finish();
+            let response = self.execute(&command)?;
+            let mut reader = ResponseReader::new(&response);
+            reader.u32()?; // pcrUpdateCounter
+            let selections = reader.u32()?;
+            for _ in 0..selections {
+                reader.u16()?;
+                let size = reader.take(1)?[0] as usize;
+                reader.take(size)?;
+            }
+            if reader.u32()? != 1 {
+                return Err("Unexpected PCR digest count");
+            }
+            reader.sized()?.try_into().map_err(|_| "Unexpected PCR digest size")
+        }
+
+        pub fn quote(&self, signing_key: u32, nonce: &[u8], pcrs: &[u32]) -> Result<Quote, &'static str> {
+            if pcrs.iter().any(|pcr| *pcr >= PCR_COUNT) {
+                return Err("PCR index out of range");
+            }
+            let command = CommandBuilder::new(TPM_ST_SESSIONS, TPM_CC_QUOTE)
+                .u32(signing_key)
+                .password_session()
+                .sized(nonce)
+                .u16(TPM_ALG_NULL)
+                .pcr_selection(pcrs)
+                .finish();
+            let response = self.execute(&command)?;
+            let mut reader = ResponseReader::new(&response);
+            let parameter_size = reader.u32()? as usize;
+            let parameters_end = reader.position + parameter_size;
+            let attest = reader.sized()?.to_vec();
+            let signature = response
+                .get(reader.position..parameters_end)
+                .ok_or("Truncated TPM quote signature")?
+                .to_vec();
+            Ok(Quote { attest, signature })
+        }
+
+        pub fn get_random(&self, length: u16) -> Result<Vec<u8>, &'static str> {
+            let command = CommandBuilder::new(TPM_ST_NO_SESSIONS, TPM_CC_GET_RANDOM).u16(length).finish();
+            let response = self.execute(&command)?;
+            Ok(ResponseReader::new(&response).sized()?.to_vec())
+        }
+
+        // The command holding the data is wiped once sent
+        pub fn seal(&self, parent: u32, data: &[u8]) -> Result<SealedObject, &'static str> {
+            if data.len() > MAX_SEALED_SIZE {
+                return Err("Too much data to seal");
+            }
+            let mut sensitive = CommandBuilder { bytes: Vec::new() }.u16(0).sized(data).bytes;
+            let public = CommandBuilder { bytes: Vec::new() }
+                .u16(TPM_ALG_KEYEDHASH)
+                .u16(TPM_ALG_SHA256)
+                .u32(SEALED_OBJECT_ATTRIBUTES)
+                .u16(0)
+                .u16(TPM_ALG_NULL)
+                .u16(0)
+                .bytes;
+            let mut command = CommandBuilder::new(TPM_ST_SESSIONS, TPM_CC_CREATE)
+                .u32(parent)
+                .password_session()
+                .sized(&sensitive)
+                .sized(&public)
+                .u16(0)
+                .u32(0)
+                .finish();
+            let response = self.execute(&command);
+            sensitive.zeroize();
+            command.zeroize();
+            let response = response?;
+            let mut reader = ResponseReader::new(&response);
+            reader.u32()?; // parameterSize
+            let private = reader.sized()?.to_vec();
+            let public = reader.sized()?.to_vec();
+            Ok(SealedObject { private, public })
+        }
+
+        // Loads the object under its parent, unseals it and flushes it
+        // again. The response holding the data is wiped once copied.
+        pub fn unseal(&self, parent: u32, object: &SealedObject) -> Result<Vec<u8>, &'static str> {
+            let command = CommandBuilder::new(TPM_ST_SESSIONS, TPM_CC_LOAD)
+                .u32(parent)
+                .password_session()
+                .sized(&object.private)
+                .sized(&object.public)
+                .finish();
+            let handle = ResponseReader::new(&self.execute(&command)?).u32()?;
+            let command = CommandBuilder::new(TPM_ST_SESSIONS, TPM_CC_UNSEAL).u32(handle).password_session().finish();
+            let unsealed = self.execute(&command).and_then(|mut response| {
+                let mut reader = ResponseReader::new(&response);
+                let data = reader.u32().and_then(|_| reader.sized()).map(<[u8]>::to_vec);
+                response.zeroize();
+                data
+            });
+            let command = CommandBuilder::new(TPM_ST_NO_SESSIONS, TPM_CC_FLUSH_CONTEXT).u32(handle).finish();
+            self.execute(&command)?;
+            unsealed
+        }
+
+        pub fn measure(&self, pcr: u32, data: &[u8]) -> Result<[u8; SHA256_SIZE], &'static str> {
+            let digest: [u8; SHA256_SIZE] = Sha256::digest(data).into();
+            self.pcr_extend(pcr, &digest)?;
+            Ok(digest)
+        }
+    }
+
+    // Software model of PCR_Extend: new = SHA256(old || digest)
+    pub fn extend_digest(current: &[u8; SHA256_SIZE], digest: &[u8; SHA256_SIZE]) -> [u8; SHA256_SIZE] {
+        let mut hasher = Sha256::new();
+        hasher.update(current);
+        hasher.update(digest);
+        hasher.finalize().into()
+    }
+}
@@ -0,0 +1,108 @@
+// src/kernel/drivers/uart.rs
+
+pub mod uart {
+    use crate::drivers::port::port::PortIo;
+    use std::time::{Duration, Instant};
+
+    // Legacy PC serial ports
+    pub const COM1: u16 = 0x3F8;
+    pub const COM2: u16 = 0x2F8;
+    pub const COM3: u16 = 0x3E8;
+    pub const COM4: u16 = 0x2E8;
+    // Each takes eight from its base
+    pub const PORTS: u16 = 8;
+
+    const UART_CLOCK: u32 = 115_200;
+
+    // Offsets from the base port
+    const DATA: u16 = 0;
+    const INTERRUPT_ENABLE: u16 = 1;
+    const FIFO_CONTROL: u16 = 2;
+    const LINE_CONTROL: u16 = 3;
+    const MODEM_CONTROL: u16 = 4;
+    const LINE_STATUS: u16 = 5;
+    // With LINE_CONTROL_DLAB set, DATA and INTERRUPT_ENABLE hold the divisor
+    const DIVISOR_LOW: u16 = 0;
+    const DIVISOR_HIGH: u16 = 1;
+
+    const LINE_CONTROL_8N1: u8 = 0x03;
+    const LINE_CONTROL_DLAB: u8 = 0x80;
+    // Enabled and cleared, interrupting at 14 bytes
+    const FIFO_ENABLE: u8 = 0xC7;
+    // DTR, RTS and OUT2
+    const MODEM_READY: u8 = 0x0B;
+    const LINE_STATUS_DATA_READY: u8 = 1 << 0;
+    const LINE_STATUS_THR_EMPTY: u8 = 1 << 5;
+
+    const TRANSMIT_TIMEOUT: Duration = Duration::from_millis(10);
+
+    // A byte-at-a-time serial line, polled
+    pub trait SerialPort {
+        fn read_byte(&self) -> Option<u8>;
+        fn write_byte(&self, byte: u8) -> Result<(), &'static str>;
+
+        fn write_all(&self, data: &[u8]) -> Result<(), &'static str> {
+            data.iter().try_for_each(|byte| self.write_byte(*byte))
+        }
+    }
+
+    // ttyS0 to ttyS3, as on the kernel command line
+    pub fn port_for_tty(name: &str) -> Option<u16> {
+        match name {
+            "ttyS0" => Some(COM1),
+            "ttyS1" => Some(COM2),
+            "ttyS2" => Some(COM3),
+            "ttyS3" => Some(COM4),
+            _ => None,
+        }
+    }
+
+    // A 16550-compatible UART, with interrupts off
+    pub struct Uart16550<P: PortIo> {
+        ports: P,
+        base: u16,
+    }
+
+    impl<P: PortIo> Uart16550<P> {
+        // 8 data bits, no parity, one stop bit
+        pub fn new(ports: P, base: u16, baud: u32) -> Result<Self, &'static str> {
+            if baud == 0 || !UART_CLOCK.is_multiple_of(baud) || UART_CLOCK / baud > u16::MAX as u32 {
+                return Err("Unsupported baud rate");
+            }
+            let divisor = (UART_CLOCK / baud) as u16;
+            ports.outb(base + INTERRUPT_ENABLE, 0);
+            ports.outb(base + LINE_CONTROL, LINE_CONTROL_DLAB);
+            ports.outb(base + DIVISOR_LOW, divisor as u8);
+            ports.outb(base + DIVISOR_HIGH, (divisor >> 8) as u8);
+            ports.outb(base + LINE_CONTROL, LINE_CONTROL_8N1);
+            ports.outb(base + FIFO_CONTROL, FIFO_ENABLE);
+            ports.outb(base + MODEM_CONTROL, MODEM_READY);
+            Ok(Uart16550 { ports, base })
+        }
+
+        pub fn base(&self) -> u16 {
+            self.base
+        }
+    }
+
+    impl<P: PortIo> SerialPort for Uart16550<P> {
+        fn read_byte(&self) -> Option<u8> {
+            match self.ports.inb(self.base + LINE_STATUS) & LINE_STATUS_DATA_READY != 0 {
+                true => Some(self.ports.inb(self.base + DATA)),
+                false => None,
+            }
+        }
+
+        fn write_byte(&self, byte: u8) -> Result<(), &'static str> {
+            let start = Instant::now();
+            while self.ports.inb(self.base + LINE_STATUS) & LINE_STATUS_THR_EMPTY == 0 {
+                if start.elapsed() > TRANSMIT_TIMEOUT {
+                    return Err("UART transmit timeout");
+                }
+                std::hint::spin_loop();
+            }
+            self.ports.outb(self.base + DATA, byte);
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,260 @@
+// src/kernel/faultinject.rs
+
+pub mod faultinject {
+    use crate::metrics::metrics::{MetricType, Registry, Sample};
+    use crate::vxboot::vxboot::CommandLine;
+    use std::cell::Cell;
+    use std::fmt::Write;
+    use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU32, AtomicU64, Ordering};
+    use std::time::Duration;
+
+    // Allocations through the kernel allocator return null
+    pub static FAIL_ALLOC: FaultPoint = FaultPoint::new("fail_alloc");
+    // Data a device has transferred comes back with a bit flipped
+    pub static FAIL_DMA: FaultPoint = FaultPoint::new("fail_dma");
+    // Received frames are dropped before the stack sees them
+    pub static FAIL_PACKET: FaultPoint = FaultPoint::new("fail_packet");
+    // Interrupts are handled late by the configured delay
+    pub static FAIL_IRQ_DELAY: FaultPoint = FaultPoint::new("fail_irq_delay");
+    // Filesystem and block device I/O fails
+    pub static FAIL_IO: FaultPoint = FaultPoint::new("fail_io");
+
+    pub static POINTS: [&FaultPoint; 5] = [&FAIL_ALLOC, &FAIL_DMA, &FAIL_PACKET, &FAIL_IRQ_DELAY, &FAIL_IO];
+
+    // xorshift64, seeded for reproducible runs
+    static RANDOM: AtomicU64 = AtomicU64::new(0x9E37_79B9_7F4A_7C15);
+
+    thread_local! {
+        // Set while running inject's closure, for task-filtered points
+        static INJECTING: Cell<bool> = const { Cell::new(false) };
+    }
+
+    pub fn set_seed(seed: u64) {
+        RANDOM.store(seed.max(1), Ordering::Relaxed);
+    }
+
+    fn random() -> u64 {
+        let mut state = RANDOM.load(Ordering::Relaxed);
+        loop {
+            let mut next = state;
+            next ^= next << 13;
+            next ^= next >> 7;
+            next ^= next << 17;
+            match RANDOM.compare_exchange_weak(state, next, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => return next,
+                Err(current) => state = current,
+            }
+        }
+    }
+
+    // Runs code with task-filtered points armed on this thread only, so a
+    // test can fail its own allocations and nobody else's
+    pub fn inject<T>(code: impl FnOnce() -> T) -> T {
+        let previous = INJECTING.with(|injecting| injecting.replace(true));
+        let result = code();
+        INJECTING.with(|injecting| injecting.set(previous));
+        result
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct FaultConfig {
+        // Only every interval-th call may fail
+        pub interval: u64,
+        // Percent of those that do
+        pub probability: u32,
+        // Failures left; None for no limit
+        pub times: Option<u64>,
+        // For delaying points
+        pub delay: Duration,
+        // Only on threads inside inject
+        pub task_filter: bool,
+    }
+
+    impl FaultConfig {
+        // Every call fails
+        pub fn always() -> Self {
+            FaultConfig { interval: 1, probability: 100, times: None, delay: Duration::ZERO, task_filter: false }
+        }
+
+        // interval,probability[,times[,delay in microseconds]] as on the
+        // kernel command line; times of -1 is no limit
+        pub fn parse(text: &str) -> Result<Self, &'static str> {
+            let fields: Vec<&str> = text.split(',').collect();
+            if !(2..=4).contains(&fields.len()) {
+                return Err("Usage: interval,probability[,times[,delay]]");
+            }
+            let interval: u64 = fields[0].parse().map_err(|_| "Invalid fault interval")?;
+            let probability: u32 = fields[1].parse().ok().filter(|percent| *percent <= 100).ok_or("Invalid fault probability")?;
+            let times = match fields.get(2) {
+                None | Some(&"-1") => None,
+                Some(times) => Some(times.parse().map_err(|_| "Invalid fault count")?),
+            };
+            let delay = match fields.get(3) {
+                None => Duration::ZERO,
+                Some(micros) => Duration::from_micros(micros.parse().map_err(|_| "Invalid fault delay")?),
+            };
+            Ok(FaultConfig { interval: interval.max(1), probability, times, delay, task_filter: false })
+        }
+    }
+
+    // A place in the kernel where a failure can be made to happen, in the
+    // style of Linux's fault attributes. Lock-free, as the allocator
+    // checks one.
+    pub struct FaultPoint {
+        name: &'static str,
+        enabled: AtomicBool,
+        interval: AtomicU64,
+        probability: AtomicU32,
+        // -1 for no limit
+        times: AtomicI64,
+        delay_micros: AtomicU64,
+        task_filter: AtomicBool,
+        calls: AtomicU64,
+        injected: AtomicU64,
+    }
+
+    impl FaultPoint {
+        pub const fn new(name: &'static str) -> Self {
+            FaultPoint {
+                name,
+                enabled: AtomicBool::new(false),
+                interval: AtomicU64::new(1),
+                probability: AtomicU32::new(0),
+                times: AtomicI64::new(-1),
+                delay_micros: AtomicU64::new(0),
+                task_filter: AtomicBool::new(false),
+                calls: AtomicU64::new(0),
+                injected: AtomicU64::new(0),
+            }
+        }
+
+        pub fn name(&self) -> &'static str {
+            self.name
+        }
+
+        // Counters start again from zero
+        pub fn configure(&self, config: FaultConfig) {
+            self.enabled.store(false, Ordering::SeqCst);
+            self.interval.store(config.interval.max(1), Ordering::Relaxed);
+            self.probability.store(config.probability.min(100), Ordering::Relaxed);
+            self.times.store(config.times.map_or(-1, |times| times.min(i64::MAX as u64) as i64), Ordering::Relaxed);
+            self.delay_micros.store(config.delay.as_micros() as u64, Ordering::Relaxed);
+            self.task_filter.store(config.task_filter, Ordering::Relaxed);
+            self.calls.store(0, Ordering::Relaxed);
+            self.injected.store(0, Ordering::Relaxed);
+            self.enabled.store(true, Ordering::SeqCst);
+        }
+
+        pub fn disable(&self) {
+            self.enabled.store(false, Ordering::SeqCst);
+        }
+
+        pub fn config(&self) -> Option<FaultConfig> {
+            if !self.enabled.load(Ordering::SeqCst) {
+                return None;
+            }
+            let times = self.times.load(Ordering::Relaxed);
+            Some(FaultConfig {
+                interval: self.interval.load(Ordering::Relaxed),
+                probability: self.probability.load(Ordering::Relaxed),
+                times: (times >= 0).then_some(times as u64),
+                delay: Duration::from_micros(self.delay_micros.load(Ordering::Relaxed)),
+                task_filter: self.task_filter.load(Ordering::Relaxed),
+            })
+        }
+
+        // Calls that counted and how many of them failed
+        pub fn stats(&self) -> (u64, u64) {
+            (self.calls.load(Ordering::Relaxed), self.injected.load(Ordering::Relaxed))
+        }
+
+        // Whether this call should fail. Off, it costs one load.
+        pub fn should_fail(&self) -> bool {
+            if !self.enabled.load(Ordering::Relaxed) {
+                return false;
+            }
+            // Without allocating, as the allocator asks too; a thread on its
+            // way out is never filtered in
+            if self.task_filter.load(Ordering::Relaxed) && !INJECTING.try_with(Cell::get).unwrap_or(false) {
+                return false;
+            }
+            let call = self.calls.fetch_add(1, Ordering::Relaxed) + 1;
+            if !call.is_multiple_of(self.interval.load(Ordering::Relaxed)) {
+                return false;
+            }
+            let probability = self.probability.load(Ordering::Relaxed);
+            if probability < 100 && random() % 100 >= probability as u64 {
+                return false;
+            }
+            let left = self.times.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |times| match times {
+                0 => None,
+                -1 => Some(-1),
+                times => Some(times - 1),
+            });
+            if left.is_err() {
+                return false;
+            }
+            self.injected.fetch_add(1, Ordering::Relaxed);
+            true
+        }
+
+        // Flips a bit somewhere in data when the point fires
+        pub fn corrupt(&self, data: &mut [u8]) -> bool {
+            if data.is_empty() || !self.should_fail() {
+                return false;
+            }
+            let bit = random() % (data.len() as u64 * 8);
+            data[(bit / 8) as usize] ^= 1 << (bit % 8);
+            true
+        }
+
+        // Sleeps for the configured delay when the point fires
+        pub fn delay(&self) -> bool {
+            if !self.should_fail() {
+                return false;
+            }
+            std::thread::sleep(Duration::from_micros(self.delay_micros.load(Ordering::Relaxed)));
+            true
+        }
+    }
+
+    pub fn point(name: &str) -> Option<&'static FaultPoint> {
+        POINTS.iter().copied().find(|point| point.name == name)
+    }
+
+    // fail_alloc=100,100,5 and the like; returns how many were set up
+    pub fn configure_from_command_line(command_line: &CommandLine) -> Result<usize, &'static str> {
+        let mut count = 0;
+        for point in POINTS {
+            if let Some(setting) = command_line.get(point.name) {
+                point.configure(FaultConfig::parse(setting)?);
+                log::warn!("Fault injection armed at {}: {}", point.name, setting);
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    // A line per armed point: name, interval, probability, failures left,
+    // calls and failures so far
+    pub fn report() -> String {
+        let mut report = String::new();
+        for point in POINTS {
+            if let Some(config) = point.config() {
+                let (calls, injected) = point.stats();
+                let times = config.times.map_or("-1".to_string(), |times| times.to_string());
+                writeln!(report, "{} {} {}% {} {} {}", point.name, config.interval, config.probability, times, calls, injected).unwrap();
+            }
+        }
+        report
+    }
+
+    // Calls and failures at each armed point
+    pub fn register_metrics(registry: &Registry) -> Result<(), &'static str> {
+        let samples = |stat: fn(&FaultPoint) -> u64| {
+            move || POINTS.iter().filter(|point| point.config().is_some()).map(|point| Sample::new(stat(point) as f64).with("point", point.name)).collect()
+        };
+        registry.collect("vaelix_fault_calls_total", "Calls that reached an armed fault injection point.", MetricType::Counter, samples(|point| point.stats().0))?;
+        registry.collect("vaelix_fault_injected_total", "Failures injected at each armed point.", MetricType::Counter, samples(|point| point.stats().1))
+    }
+}
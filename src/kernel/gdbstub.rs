@@ -0,0 +1,486 @@
+// src/kernel/gdbstub.rs
+
+pub mod gdbstub {
+    use crate::drivers::port::port::PortIo;
+    use crate::drivers::resource::resource::{resources, ClaimedPorts};
+    use crate::drivers::uart::uart::{self, SerialPort, Uart16550};
+    use crate::process::signal::signal::SIGTRAP;
+    use crate::process::table::table::{ProcessTable, Tid};
+    use crate::process::task::task::{Trap, UserContext};
+    use crate::vxboot::vxboot::CommandLine;
+    use std::collections::BTreeMap;
+    use std::fmt::Write;
+
+    // The trap flag: the CPU raises a debug exception after each instruction
+    pub const RFLAGS_TF: u64 = 1 << 8;
+    pub const DEBUG_VECTOR: u8 = 1;
+    pub const BREAKPOINT_VECTOR: u8 = 3;
+    pub const INT3: u8 = 0xCC;
+    // Longest packet either way
+    pub const PACKET_SIZE: usize = 0x1000;
+    const DEFAULT_BAUD: u32 = 115_200;
+    // GDB's amd64 registers without a target description: sixteen general
+    // ones with rsp eighth, rip, then eflags and six segment registers at
+    // four bytes each
+    const REGISTERS_SIZE: usize = 17 * 8 + 7 * 4;
+
+    // What the stub debugs: threads by ID, each with its registers and
+    // address space
+    pub trait DebugTarget {
+        fn threads(&self) -> Vec<Tid>;
+        fn thread_name(&self, tid: Tid) -> Option<String>;
+        fn registers(&self, tid: Tid) -> Result<UserContext, &'static str>;
+        fn set_registers(&mut self, tid: Tid, context: UserContext) -> Result<(), &'static str>;
+        // Through the thread's address space, which has the kernel's in its
+        // upper half
+        fn read_memory(&self, tid: Tid, address: u64, buffer: &mut [u8]) -> Result<(), &'static str>;
+        fn write_memory(&mut self, tid: Tid, address: u64, data: &[u8]) -> Result<(), &'static str>;
+    }
+
+    // GDB's threads are the kernel's tasks. Breakpoints in shared text are
+    // hit by every process mapping it.
+    impl DebugTarget for ProcessTable {
+        fn threads(&self) -> Vec<Tid> {
+            self.pids().into_iter().filter_map(|pid| self.get(pid)).flat_map(|process| process.tids()).collect()
+        }
+
+        fn thread_name(&self, tid: Tid) -> Option<String> {
+            let pid = self.owner(tid)?;
+            Some(format!("{} (pid {})", self.get(pid)?.name(), pid))
+        }
+
+        fn registers(&self, tid: Tid) -> Result<UserContext, &'static str> {
+            let pid = self.owner(tid).ok_or("No such thread")?;
+            Ok(self.get(pid).and_then(|process| process.thread(tid)).ok_or("No such thread")?.context)
+        }
+
+        fn set_registers(&mut self, tid: Tid, context: UserContext) -> Result<(), &'static str> {
+            let pid = self.owner(tid).ok_or("No such thread")?;
+            self.get_mut(pid).and_then(|process| process.thread_mut(tid)).ok_or("No such thread")?.context = context;
+            Ok(())
+        }
+
+        fn read_memory(&self, tid: Tid, address: u64, buffer: &mut [u8]) -> Result<(), &'static str> {
+            let pid = self.owner(tid).ok_or("No such thread")?;
+            self.get(pid).ok_or("No such thread")?.space().read(address, buffer)
+        }
+
+        fn write_memory(&mut self, tid: Tid, address: u64, data: &[u8]) -> Result<(), &'static str> {
+            let pid = self.owner(tid).ok_or("No such thread")?;
+            self.get(pid).ok_or("No such thread")?.space().write(address, data)
+        }
+    }
+
+    // What GDB wants the kernel to do next
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Command {
+        // Run every thread
+        Continue,
+        // Run one thread for an instruction; its next debug exception goes
+        // to handle_trap
+        Step(Tid),
+        // Stop everything, for Ctrl-C, and say where with stopped
+        Interrupt,
+        // Breakpoints are gone and the kernel runs on undebugged
+        Detach,
+        Kill,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Receive {
+        Idle,
+        Packet,
+        // The checksum's first digit once it has come
+        Checksum(Option<u8>),
+    }
+
+    fn to_hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn from_hex(text: &str) -> Option<Vec<u8>> {
+        if !text.len().is_multiple_of(2) {
+            return None;
+        }
+        (0..text.len()).step_by(2).map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok()).collect()
+    }
+
+    fn number(text: &str) -> Option<u64> {
+        u64::from_str_radix(text, 16).ok()
+    }
+
+    // "-1" for all threads and "0" for any are both taken as no choice
+    fn thread_id(text: &str) -> Option<Option<Tid>> {
+        match text {
+            "-1" | "0" => Some(None),
+            _ => Tid::from_str_radix(text, 16).ok().map(Some),
+        }
+    }
+
+    fn encode_registers(context: &UserContext) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(REGISTERS_SIZE);
+        let registers = &context.registers;
+        for value in registers[..7].iter().chain([&context.rsp]).chain(&registers[7..]).chain([&context.rip]) {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&(context.rflags as u32).to_le_bytes());
+        // cs and ss, then ds, es, fs and gs, which are unused
+        for segment in [context.cs, context.ss, 0, 0, 0, 0] {
+            bytes.extend_from_slice(&(segment as u32).to_le_bytes());
+        }
+        bytes
+    }
+
+    fn decode_registers(bytes: &[u8], context: &mut UserContext) {
+        let word = |index: usize| u64::from_le_bytes(bytes[index * 8..index * 8 + 8].try_into().unwrap());
+        let half = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        for (index, register) in (0..7).chain(8..16).zip(context.registers.iter_mut()) {
+            *register = word(index);
+        }
+        context.rsp = word(7);
+        context.rip = word(16);
+        context.rflags = context.rflags & !0xFFFF_FFFF | half(17 * 8) as u64;
+        context.cs = half(17 * 8 + 4) as u16;
+        context.ss = half(17 * 8 + 8) as u16;
+    }
+
+    // The GDB remote serial protocol over a serial line. The kernel polls
+    // it while stopped for the debugger, and hands it the debug and
+    // breakpoint exceptions threads take.
+    pub struct GdbStub<S: SerialPort> {
+        serial: S,
+        receive: Receive,
+        packet: Vec<u8>,
+        // Framed, to send again when GDB asks
+        last_reply: Vec<u8>,
+        no_ack: bool,
+        // Threads chosen with Hg, for registers, memory and breakpoints,
+        // and Hc, for stepping
+        general: Option<Tid>,
+        control: Option<Tid>,
+        // The thread last reported stopped, until GDB resumes
+        stopped: Option<Tid>,
+        stepping: Option<Tid>,
+        // The bytes under each INT3, and the thread it went in through
+        breakpoints: BTreeMap<u64, (Tid, u8)>,
+        wait: bool,
+    }
+
+    impl<S: SerialPort> GdbStub<S> {
+        pub fn new(serial: S) -> Self {
+            GdbStub {
+                serial,
+                receive: Receive::Idle,
+                packet: Vec::new(),
+                last_reply: Vec::new(),
+                no_ack: false,
+                general: None,
+                control: None,
+                stopped: None,
+                stepping: None,
+                breakpoints: BTreeMap::new(),
+                wait: false,
+            }
+        }
+
+        // Whether the kernel should stop for GDB before starting init
+        pub fn waits_for_debugger(&self) -> bool {
+            self.wait
+        }
+
+        pub fn breakpoints(&self) -> Vec<u64> {
+            self.breakpoints.keys().copied().collect()
+        }
+
+        fn send(&mut self, payload: &str) {
+            let checksum = payload.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+            self.last_reply = format!("${}#{:02x}", payload, checksum).into_bytes();
+            if self.serial.write_all(&self.last_reply).is_err() {
+                log::warn!("Serial line not taking data");
+            }
+        }
+
+        fn ack(&self, byte: u8) {
+            if !self.no_ack {
+                let _ = self.serial.write_byte(byte);
+            }
+        }
+
+        // Tells GDB the kernel has stopped, e.g. once it has stopped
+        // everything for Command::Interrupt or to wait for GDB at boot
+        pub fn stopped(&mut self, tid: Tid, signal: u8) {
+            self.report(tid, signal, "");
+        }
+
+        fn report(&mut self, tid: Tid, signal: u8, reason: &str) {
+            self.stopped = Some(tid);
+            self.general = None;
+            self.control = None;
+            self.send(&format!("T{:02x}thread:{:x};{}", signal, tid, reason));
+        }
+
+        // Takes a thread's breakpoint or debug exception if it was the
+        // stub's doing, reporting the stop to GDB. Others, such as an INT3
+        // a program has of its own, are left to the caller.
+        pub fn handle_trap(&mut self, target: &mut dyn DebugTarget, tid: Tid, trap: Trap) -> bool {
+            match trap {
+                Trap::Exception { vector: BREAKPOINT_VECTOR, .. } => {
+                    let Ok(mut context) = target.registers(tid) else {
+                        return false;
+                    };
+                    // RIP is past the INT3
+                    let address = context.rip.wrapping_sub(1);
+                    if !self.breakpoints.contains_key(&address) {
+                        return false;
+                    }
+                    context.rip = address;
+                    if target.set_registers(tid, context).is_err() {
+                        return false;
+                    }
+                    self.report(tid, SIGTRAP, "swbreak:;");
+                    true
+                }
+                Trap::Exception { vector: DEBUG_VECTOR, .. } if self.stepping == Some(tid) => {
+                    self.stepping = None;
+                    if let Ok(mut context) = target.registers(tid) {
+                        context.rflags &= !RFLAGS_TF;
+                        let _ = target.set_registers(tid, context);
+                    }
+                    self.report(tid, SIGTRAP, "");
+                    true
+                }
+                _ => false,
+            }
+        }
+
+        // Reads whatever GDB has sent, answering packets, until it says to
+        // run or there is nothing more
+        pub fn poll(&mut self, target: &mut dyn DebugTarget) -> Option<Command> {
+            while let Some(byte) = self.serial.read_byte() {
+                if let Some(command) = self.take_byte(byte, target) {
+                    return Some(command);
+                }
+            }
+            None
+        }
+
+        fn take_byte(&mut self, byte: u8, target: &mut dyn DebugTarget) -> Option<Command> {
+            match (self.receive, byte) {
+                (Receive::Idle, b'$') => {
+                    self.packet.clear();
+                    self.receive = Receive::Packet;
+                }
+                (Receive::Idle, 0x03) => return Some(Command::Interrupt),
+                (Receive::Idle, b'-') if !self.no_ack => {
+                    let reply = self.last_reply.clone();
+                    let _ = self.serial.write_all(&reply);
+                }
+                // Acks and line noise
+                (Receive::Idle, _) => {}
+                (Receive::Packet, b'#') => self.receive = Receive::Checksum(None),
+                (Receive::Packet, _) if self.packet.len() == PACKET_SIZE => {
+                    self.receive = Receive::Idle;
+                    self.ack(b'-');
+                }
+                (Receive::Packet, byte) => self.packet.push(byte),
+                (Receive::Checksum(None), digit) => self.receive = Receive::Checksum(Some(digit)),
+                (Receive::Checksum(Some(first)), second) => {
+                    self.receive = Receive::Idle;
+                    let sum = self.packet.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+                    let expected = std::str::from_utf8(&[first, second]).ok().and_then(|digits| u8::from_str_radix(digits, 16).ok());
+                    if expected != Some(sum) && !self.no_ack {
+                        self.ack(b'-');
+                        return None;
+                    }
+                    self.ack(b'+');
+                    let packet = String::from_utf8_lossy(&self.packet).into_owned();
+                    return self.handle(&packet, target);
+                }
+            }
+            None
+        }
+
+        fn current(&self, target: &dyn DebugTarget, chosen: Option<Tid>) -> Result<Tid, &'static str> {
+            chosen.or(self.stopped).or_else(|| target.threads().first().copied()).ok_or("No threads")
+        }
+
+        fn set_breakpoint(&mut self, target: &mut dyn DebugTarget, address: u64) -> Result<(), &'static str> {
+            if self.breakpoints.contains_key(&address) {
+                return Ok(());
+            }
+            let tid = self.current(target, self.general)?;
+            let mut original = [0u8];
+            target.read_memory(tid, address, &mut original)?;
+            target.write_memory(tid, address, &[INT3])?;
+            self.breakpoints.insert(address, (tid, original[0]));
+            Ok(())
+        }
+
+        fn clear_breakpoint(&mut self, target: &mut dyn DebugTarget, address: u64) -> Result<(), &'static str> {
+            let (tid, original) = self.breakpoints.remove(&address).ok_or("No breakpoint there")?;
+            target.write_memory(tid, address, &[original])
+        }
+
+        fn clear_breakpoints(&mut self, target: &mut dyn DebugTarget) {
+            for address in self.breakpoints() {
+                let _ = self.clear_breakpoint(target, address);
+            }
+        }
+
+        // Resuming at another address when given one
+        fn resume(&mut self, target: &mut dyn DebugTarget, arguments: &str, step: bool) -> Result<Command, &'static str> {
+            let tid = self.current(target, self.control)?;
+            let mut context = target.registers(tid)?;
+            if !arguments.is_empty() {
+                context.rip = number(arguments).ok_or("Malformed address")?;
+            }
+            match step {
+                true => context.rflags |= RFLAGS_TF,
+                false => context.rflags &= !RFLAGS_TF,
+            }
+            target.set_registers(tid, context)?;
+            self.stopped = None;
+            self.stepping = step.then_some(tid);
+            Ok(match step {
+                true => Command::Step(tid),
+                false => Command::Continue,
+            })
+        }
+
+        fn handle(&mut self, packet: &str, target: &mut dyn DebugTarget) -> Option<Command> {
+            let Some(kind) = packet.chars().next() else {
+                self.send("");
+                return None;
+            };
+            let arguments = &packet[1..];
+            let reply: Result<String, &'static str> = match kind {
+                '?' => {
+                    let tid = self.current(target, None);
+                    match tid {
+                        Ok(tid) => self.report(tid, SIGTRAP, ""),
+                        Err(_) => self.send(&format!("S{:02x}", SIGTRAP)),
+                    }
+                    return None;
+                }
+                'g' => self.current(target, self.general).and_then(|tid| target.registers(tid)).map(|context| to_hex(&encode_registers(&context))),
+                'G' => (|| {
+                    let bytes = from_hex(arguments).filter(|bytes| bytes.len() >= REGISTERS_SIZE).ok_or("Malformed registers")?;
+                    let tid = self.current(target, self.general)?;
+                    let mut context = target.registers(tid)?;
+                    decode_registers(&bytes, &mut context);
+                    target.set_registers(tid, context)?;
+                    Ok("OK".to_string())
+                })(),
+                'm' => (|| {
+                    let (address, length) = arguments.split_once(',').ok_or("Malformed memory read")?;
+                    let (address, length) = (number(address).ok_or("Malformed address")?, number(length).ok_or("Malformed length")?);
+                    let mut buffer = vec![0u8; (length as usize).min(PACKET_SIZE / 2)];
+                    target.read_memory(self.current(target, self.general)?, address, &mut buffer)?;
+                    Ok(to_hex(&buffer))
+                })(),
+                'M' => (|| {
+                    let (range, data) = arguments.split_once(':').ok_or("Malformed memory write")?;
+                    let (address, length) = range.split_once(',').ok_or("Malformed memory write")?;
+                    let data = from_hex(data).filter(|data| Some(data.len() as u64) == number(length)).ok_or("Malformed memory write")?;
+                    target.write_memory(self.current(target, self.general)?, number(address).ok_or("Malformed address")?, &data)?;
+                    Ok("OK".to_string())
+                })(),
+                'c' | 's' => match self.resume(target, arguments, kind == 's') {
+                    Ok(command) => return Some(command),
+                    Err(error) => Err(error),
+                },
+                'Z' | 'z' => match arguments.split(',').collect::<Vec<_>>()[..] {
+                    ["0", address, _] => (|| {
+                        let address = number(address).ok_or("Malformed address")?;
+                        match kind {
+                            'Z' => self.set_breakpoint(target, address)?,
+                            _ => self.clear_breakpoint(target, address)?,
+                        }
+                        Ok("OK".to_string())
+                    })(),
+                    // Hardware breakpoints and watchpoints are not supported
+                    _ => Ok(String::new()),
+                },
+                'H' => match (arguments.get(..1), arguments.get(1..).and_then(thread_id)) {
+                    (Some(which), Some(tid)) if tid.is_none_or(|tid| target.threads().contains(&tid)) => {
+                        match which {
+                            "g" => self.general = tid,
+                            _ => self.control = tid,
+                        }
+                        Ok("OK".to_string())
+                    }
+                    _ => Err("No such thread"),
+                },
+                'T' => match thread_id(arguments).flatten() {
+                    Some(tid) if target.threads().contains(&tid) => Ok("OK".to_string()),
+                    _ => Err("No such thread"),
+                },
+                'D' => {
+                    self.clear_breakpoints(target);
+                    self.send("OK");
+                    self.stopped = None;
+                    return Some(Command::Detach);
+                }
+                'k' => {
+                    self.clear_breakpoints(target);
+                    return Some(Command::Kill);
+                }
+                'q' | 'Q' => Ok(self.query(packet, target)),
+                _ => Ok(String::new()),
+            };
+            match reply {
+                Ok(reply) => self.send(&reply),
+                Err(error) => {
+                    log::debug!("{}: {}", packet.chars().take(32).collect::<String>(), error);
+                    self.send("E01");
+                }
+            }
+            if packet == "QStartNoAckMode" {
+                self.no_ack = true;
+            }
+            None
+        }
+
+        fn query(&self, packet: &str, target: &dyn DebugTarget) -> String {
+            if packet.starts_with("qSupported") {
+                return format!("PacketSize={:x};swbreak+;QStartNoAckMode+", PACKET_SIZE);
+            }
+            if let Some(id) = packet.strip_prefix("qThreadExtraInfo,") {
+                return thread_id(id).flatten().and_then(|tid| target.thread_name(tid)).map_or_else(String::new, |name| to_hex(name.as_bytes()));
+            }
+            match packet {
+                "qfThreadInfo" => {
+                    let mut reply = String::from("m");
+                    for (index, tid) in target.threads().iter().enumerate() {
+                        write!(reply, "{}{:x}", if index > 0 { "," } else { "" }, tid).unwrap();
+                    }
+                    reply
+                }
+                "qsThreadInfo" => "l".to_string(),
+                "qC" => self.current(target, self.general).map_or_else(|_| String::new(), |tid| format!("QC{:x}", tid)),
+                // Attached to something already running, so detaching leaves it be
+                "qAttached" => "1".to_string(),
+                "QStartNoAckMode" => "OK".to_string(),
+                _ => String::new(),
+            }
+        }
+    }
+
+    // kgdboc=ttyS0,115200 puts the stub on that serial port; kgdbwait has
+    // the kernel stop for GDB before starting init. None without kgdboc.
+    pub fn from_command_line<P: PortIo>(command_line: &CommandLine, ports: P) -> Result<Option<GdbStub<Uart16550<ClaimedPorts<P>>>>, &'static str> {
+        let Some(setting) = command_line.get("kgdboc") else {
+            return Ok(None);
+        };
+        let (tty, baud) = match setting.split_once(',') {
+            Some((tty, baud)) => (tty, baud.parse().map_err(|_| "Invalid baud rate for kgdboc")?),
+            None => (setting, DEFAULT_BAUD),
+        };
+        let port = uart::port_for_tty(tty).ok_or("Unknown serial port for kgdboc")?;
+        let ports = resources().request_region(port, uart::PORTS, "serial")?.ports(ports)?;
+        let mut stub = GdbStub::new(Uart16550::new(ports, port, baud)?);
+        stub.wait = command_line.has("kgdbwait");
+        log::info!("Listening on {} at {} baud{}", tty, baud, if stub.wait { ", waiting for GDB" } else { "" });
+        Ok(Some(stub))
+    }
+}
@@ -0,0 +1,153 @@
+// src/kernel/hardening.rs
+
+pub mod hardening {
+    use crate::process::memory::memory::{AddressSpace, NO_EXECUTE, PAGE_SIZE, WRITABLE};
+    use std::ops::Add;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::OnceLock;
+
+    // Whether the kernel was built with the hardening feature. Each check
+    // costs a little, so release builds choose.
+    pub const ENABLED: bool = cfg!(feature = "hardening");
+
+    // Freed heap memory is filled with this, so what is read through a
+    // dangling pointer is never mistaken for a pointer or a length
+    pub const POISON_FREE: u8 = 0x6B;
+
+    // Each CPU's interrupt stack, in the same area of the kernel half as
+    // Linux's per-CPU entry area. Slots are a stack plus one page, which
+    // is left unmapped under it as a guard when hardened.
+    pub const IRQ_STACKS_BASE: u64 = 0xFFFF_FE00_0000_0000;
+    pub const IRQ_STACK_PAGES: u64 = 4;
+
+    static WARNINGS: AtomicU64 = AtomicU64::new(0);
+
+    // A WARN: something that should not happen did, but the kernel can
+    // carry on
+    pub fn warn(what: &str) {
+        WARNINGS.fetch_add(1, Ordering::Relaxed);
+        log::warn!("{}", what);
+    }
+
+    // Since boot
+    pub fn warnings() -> u64 {
+        WARNINGS.load(Ordering::Relaxed)
+    }
+
+    pub trait Counter: Copy + Add<Output = Self> {
+        fn checked(self, amount: Self) -> Option<Self>;
+        fn saturating(self, amount: Self) -> Self;
+    }
+
+    macro_rules! counter {
+        ($($type:ty),*) => {
+            $(impl Counter for $type {
+                fn checked(self, amount: Self) -> Option<Self> {
+                    self.checked_add(amount)
+                }
+
+                fn saturating(self, amount: Self) -> Self {
+                    self.saturating_add(amount)
+                }
+            })*
+        };
+    }
+
+    counter!(u32, u64, usize);
+
+    // For counters on hot paths. Hardened, one that would overflow warns
+    // and sticks at its maximum rather than wrapping round to values that
+    // are already in use; otherwise it is a plain addition.
+    pub fn add<T: Counter>(value: T, amount: T, what: &str) -> T {
+        if !ENABLED {
+            return value + amount;
+        }
+        value.checked(amount).unwrap_or_else(|| {
+            warn(&format!("{} overflowed", what));
+            value.saturating(amount)
+        })
+    }
+
+    // A random value for this boot, with a zero low byte so that a string
+    // copy running up the stack cannot write it back as it was
+    pub fn canary() -> u64 {
+        static CANARY: OnceLock<u64> = OnceLock::new();
+        *CANARY.get_or_init(|| {
+            let mut bytes = [0u8; 8];
+            getrandom::getrandom(&mut bytes).expect("System entropy source unavailable");
+            u64::from_le_bytes(bytes) & !0xFF
+        })
+    }
+
+    // What a failed canary check does, as __stack_chk_fail does
+    pub fn stack_check_fail(what: &str) -> ! {
+        panic!("Stack smashing detected: {}", what)
+    }
+
+    // A stack in the kernel half. Hardened, it has an unmapped guard page
+    // under it, so running off the end faults rather than overwriting
+    // whatever is below, and a canary in its lowest word, which a stack
+    // that came close shows by having clobbered.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct KernelStack {
+        bottom: u64,
+        top: u64,
+        guard: Option<u64>,
+    }
+
+    impl KernelStack {
+        // Pages of stack from the slot at base up; the slot is one page
+        // longer, for the guard
+        pub fn map(space: &mut AddressSpace, base: u64, pages: u64) -> Result<Self, &'static str> {
+            let (bottom, guard) = match ENABLED {
+                true => (base + PAGE_SIZE, Some(base)),
+                false => (base, None),
+            };
+            space.map_zeroed(bottom, pages, WRITABLE | NO_EXECUTE)?;
+            if ENABLED {
+                space.write(bottom, &canary().to_le_bytes())?;
+            }
+            Ok(KernelStack {
+                bottom,
+                top: bottom + pages * PAGE_SIZE,
+                guard,
+            })
+        }
+
+        pub fn bottom(&self) -> u64 {
+            self.bottom
+        }
+
+        // Where the stack pointer starts
+        pub fn top(&self) -> u64 {
+            self.top
+        }
+
+        pub fn guard(&self) -> Option<u64> {
+            self.guard
+        }
+
+        // Whether a fault at an address was this stack overflowing
+        pub fn overflowed_at(&self, address: u64) -> bool {
+            self.guard.is_some_and(|guard| (guard..guard + PAGE_SIZE).contains(&address))
+        }
+
+        // Always passes unhardened, as there is no canary
+        pub fn check(&self, space: &AddressSpace) -> Result<(), &'static str> {
+            if self.guard.is_none() {
+                return Ok(());
+            }
+            let mut bytes = [0u8; 8];
+            space.read(self.bottom, &mut bytes)?;
+            match u64::from_le_bytes(bytes) == canary() {
+                true => Ok(()),
+                false => Err("Kernel stack canary clobbered"),
+            }
+        }
+    }
+
+    // One per CPU, from IRQ_STACKS_BASE up
+    pub fn map_irq_stacks(space: &mut AddressSpace, cpus: usize) -> Result<Vec<KernelStack>, &'static str> {
+        (0..cpus as u64).map(|cpu| KernelStack::map(space, IRQ_STACKS_BASE + cpu * (IRQ_STACK_PAGES + 1) * PAGE_SIZE, IRQ_STACK_PAGES)).collect()
+    }
+}
@@ -0,0 +1,146 @@
+// src/kernel/input.rs
+
+pub mod input {
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::sync::{Mutex, OnceLock};
+
+    // Key and button codes follow evdev so HID usages map the same way
+    // everywhere
+    pub const BTN_LEFT: u32 = 0x110;
+    pub const BTN_RIGHT: u32 = 0x111;
+    pub const BTN_MIDDLE: u32 = 0x112;
+
+    pub const KEY_ESC: u32 = 1;
+    pub const KEY_BACKSPACE: u32 = 14;
+    pub const KEY_TAB: u32 = 15;
+    pub const KEY_ENTER: u32 = 28;
+    pub const KEY_LEFTCTRL: u32 = 29;
+    pub const KEY_F: u32 = 33;
+    pub const KEY_H: u32 = 35;
+    pub const KEY_J: u32 = 36;
+    pub const KEY_K: u32 = 37;
+    pub const KEY_L: u32 = 38;
+    pub const KEY_LEFTSHIFT: u32 = 42;
+    pub const KEY_RIGHTSHIFT: u32 = 54;
+    pub const KEY_LEFTALT: u32 = 56;
+    pub const KEY_SPACE: u32 = 57;
+    // F1 to F10 are consecutive; F11 and F12 came later
+    pub const KEY_F1: u32 = 59;
+    pub const KEY_F10: u32 = 68;
+    pub const KEY_F11: u32 = 87;
+    pub const KEY_F12: u32 = 88;
+    pub const KEY_RIGHTCTRL: u32 = 97;
+    pub const KEY_RIGHTALT: u32 = 100;
+    pub const KEY_HOME: u32 = 102;
+    pub const KEY_UP: u32 = 103;
+    pub const KEY_PAGEUP: u32 = 104;
+    pub const KEY_LEFT: u32 = 105;
+    pub const KEY_RIGHT: u32 = 106;
+    pub const KEY_END: u32 = 107;
+    pub const KEY_DOWN: u32 = 108;
+    pub const KEY_PAGEDOWN: u32 = 109;
+    pub const KEY_INSERT: u32 = 110;
+    pub const KEY_DELETE: u32 = 111;
+    pub const KEY_LEFTMETA: u32 = 125;
+    pub const KEY_RIGHTMETA: u32 = 126;
+
+    // 1 for F1 through 12 for F12
+    pub fn function_key(code: u32) -> Option<u8> {
+        match code {
+            KEY_F1..=KEY_F10 => Some((code - KEY_F1 + 1) as u8),
+            KEY_F11 => Some(11),
+            KEY_F12 => Some(12),
+            _ => None,
+        }
+    }
+
+    // Absolute positions are scaled to 0..=ABS_MAX on both axes
+    pub const ABS_MAX: u32 = 0xFFFF;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum InputEvent {
+        Key { code: u32, pressed: bool },
+        PointerMotion { dx: i32, dy: i32 },
+        // Touchscreens and tablets
+        PointerAbsolute { x: u32, y: u32 },
+        Button { code: u32, pressed: bool },
+        Scroll { dx: i32, dy: i32 },
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct InputDeviceId(pub u32);
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct InputRecord {
+        pub device: InputDeviceId,
+        pub event: InputEvent,
+    }
+
+    struct InputState {
+        devices: Vec<(InputDeviceId, String)>,
+        subscribers: Vec<Sender<InputRecord>>,
+    }
+
+    // Keyboard, touchpad and mouse drivers report here; the compositor and
+    // the idle tracker subscribe
+    pub struct InputHub {
+        state: Mutex<InputState>,
+    }
+
+    impl InputHub {
+        pub fn new() -> Self {
+            InputHub {
+                state: Mutex::new(InputState {
+                    devices: Vec::new(),
+                    subscribers: Vec::new(),
+                }),
+            }
+        }
+
+        pub fn register_device(&self, name: &str) -> InputDeviceId {
+            let mut state = self.state.lock().unwrap();
+            let id = InputDeviceId(state.devices.last().map_or(0, |(id, _)| id.0 + 1));
+            log::info!("Registering input device {} as {}", name, id.0);
+            state.devices.push((id, name.to_string()));
+            id
+        }
+
+        pub fn unregister_device(&self, device: InputDeviceId) -> Result<(), &'static str> {
+            let mut state = self.state.lock().unwrap();
+            let index = state.devices.iter().position(|(id, _)| *id == device).ok_or("Input device not found")?;
+            state.devices.remove(index);
+            Ok(())
+        }
+
+        pub fn devices(&self) -> Vec<(InputDeviceId, String)> {
+            self.state.lock().unwrap().devices.clone()
+        }
+
+        pub fn report(&self, device: InputDeviceId, event: InputEvent) -> Result<(), &'static str> {
+            let mut state = self.state.lock().unwrap();
+            if !state.devices.iter().any(|(id, _)| *id == device) {
+                return Err("Input device not found");
+            }
+            let record = InputRecord { device, event };
+            state.subscribers.retain(|subscriber| subscriber.send(record).is_ok());
+            Ok(())
+        }
+
+        pub fn subscribe(&self) -> Receiver<InputRecord> {
+            let (sender, receiver) = mpsc::channel();
+            self.state.lock().unwrap().subscribers.push(sender);
+            receiver
+        }
+    }
+
+    impl Default for InputHub {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    pub fn input() -> &'static InputHub {
+        static HUB: OnceLock<InputHub> = OnceLock::new();
+        HUB.get_or_init(InputHub::new)
+    }
+}
@@ -0,0 +1,461 @@
+// src/kernel/irq.rs
+
+pub mod irq {
+    use crate::metrics::metrics::{MetricType, Registry, Sample};
+    use crate::percpu::percpu;
+    use crate::sync::sync::{self, SpinLock};
+    use std::collections::BTreeMap;
+    use std::fmt::Write;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{Arc, OnceLock};
+    use std::time::{Duration, Instant};
+
+    // Below it the vectors are CPU exceptions
+    pub const FIRST_EXTERNAL_VECTOR: u8 = 0x20;
+    // From it up they are the local APIC's own: timer, IPIs and spurious
+    pub const FIRST_SYSTEM_VECTOR: u8 = 0xEC;
+    // IRQ threads are real-time threads, 1 to 99 as for SCHED_FIFO, and
+    // run at 50 unless asked otherwise as on Linux
+    pub const DEFAULT_THREAD_PRIORITY: u8 = 50;
+    pub const MAX_THREAD_PRIORITY: u8 = 99;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum IrqReturn {
+        // Not from this device; another sharing the vector may claim it
+        None,
+        Handled,
+        // Handled as far as it can be with interrupts off, the rest left
+        // to the handler's thread
+        WakeThread,
+    }
+
+    type Handler = Box<dyn Fn(u8) -> IrqReturn + Send + Sync>;
+    type ThreadHandler = Box<dyn Fn(u8) + Send + Sync>;
+    type MaskHandler = Box<dyn Fn(bool) + Send + Sync>;
+
+    // When a vector is taken to be storming, and how it is handled until
+    // it calms down
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct StormConfig {
+        // More interrupts than this in a window is a storm
+        pub threshold: u64,
+        pub window: Duration,
+        // How often the handlers of a masked vector are run anyway, so its
+        // device still gets serviced
+        pub poll_interval: Duration,
+        // Masked this long, doubling with each storm, before being tried
+        // again
+        pub unmask_after: Duration,
+    }
+
+    impl Default for StormConfig {
+        // 100,000 a second, well above what any working device raises
+        fn default() -> Self {
+            StormConfig { threshold: 10_000, window: Duration::from_millis(100), poll_interval: Duration::from_millis(10), unmask_after: Duration::from_secs(1) }
+        }
+    }
+
+    // Longest a vector stays masked, as unmask_after doubles
+    const MAX_UNMASK_SHIFT: u64 = 6;
+
+    struct IrqThread {
+        handler: ThreadHandler,
+        priority: u8,
+        // Interrupts arriving before it runs are handled in the one run
+        pending: AtomicBool,
+    }
+
+    // A device's handler for a vector: a quick part run with interrupts
+    // off and, when it asks for one, a thread for the rest
+    pub struct IrqAction {
+        name: String,
+        handler: Handler,
+        thread: Option<IrqThread>,
+        affinity: Option<Vec<usize>>,
+        mask: Option<MaskHandler>,
+    }
+
+    impl IrqAction {
+        pub fn new(name: &str, handler: impl Fn(u8) -> IrqReturn + Send + Sync + 'static) -> Self {
+            IrqAction { name: name.to_string(), handler: Box::new(handler), thread: None, affinity: None, mask: None }
+        }
+
+        // Everything in the thread, for devices that need nothing done
+        // before interrupts are on again
+        pub fn threaded(name: &str, priority: u8, thread: impl Fn(u8) + Send + Sync + 'static) -> Self {
+            IrqAction::new(name, |_| IrqReturn::WakeThread).thread(priority, thread)
+        }
+
+        // The thread WakeThread runs, at a real-time priority
+        pub fn thread(mut self, priority: u8, thread: impl Fn(u8) + Send + Sync + 'static) -> Self {
+            self.thread = Some(IrqThread { handler: Box::new(thread), priority, pending: AtomicBool::new(false) });
+            self
+        }
+
+        // The CPUs the vector may be delivered to; any of them otherwise
+        pub fn affinity(mut self, cpus: &[usize]) -> Self {
+            self.affinity = Some(cpus.to_vec());
+            self
+        }
+
+        // How to stop the device raising it, and let it again, for when it
+        // storms; otherwise what it raises is dropped while it is masked
+        pub fn mask(mut self, mask: impl Fn(bool) + Send + Sync + 'static) -> Self {
+            self.mask = Some(Box::new(mask));
+            self
+        }
+
+        pub fn name(&self) -> &str {
+            &self.name
+        }
+    }
+
+    struct Line {
+        actions: Vec<Arc<IrqAction>>,
+        affinity: Option<Vec<usize>>,
+        // The one CPU of the affinity it is delivered to, as the I/O APIC
+        // and MSI deliver to one
+        target: usize,
+        // Per CPU
+        counts: BTreeMap<usize, u64>,
+        // Handled by none of its handlers
+        unhandled: u64,
+        // Taken in the current storm detection window
+        window_start: Instant,
+        window_count: u64,
+        // Since it was masked as storming, and when it was last polled
+        masked: Option<(Instant, Instant)>,
+        storms: u64,
+    }
+
+    impl Line {
+        fn new(target: usize) -> Self {
+            let now = Instant::now();
+            Line { actions: Vec::new(), affinity: None, target, counts: BTreeMap::new(), unhandled: 0, window_start: now, window_count: 0, masked: None, storms: 0 }
+        }
+
+        fn names(&self) -> String {
+            self.actions.iter().map(|action| action.name.as_str()).collect::<Vec<_>>().join(", ")
+        }
+    }
+
+    // Each vector's handlers and where it is delivered. Handlers share a
+    // vector as devices share a line, each asked in turn whether the
+    // interrupt was its own; threads run, highest priority first, on the
+    // CPU their vector goes to before it returns to what was interrupted.
+    // A vector raised faster than a working device would is masked and its
+    // handlers polled instead, so that one stuck device cannot livelock
+    // the CPU it interrupts.
+    pub struct IrqController {
+        online: AtomicUsize,
+        // Both taken from interrupt handlers
+        lines: SpinLock<BTreeMap<u8, Line>>,
+        storm: SpinLock<StormConfig>,
+    }
+
+    impl IrqController {
+        pub fn new(cpus: usize) -> Self {
+            IrqController { online: AtomicUsize::new(cpus.max(1)), lines: SpinLock::new("irq.lines", BTreeMap::new()), storm: SpinLock::new("irq.storm", StormConfig::default()) }
+        }
+
+        pub fn set_storm_config(&self, config: StormConfig) {
+            *self.storm.lock_irqsave() = config;
+        }
+
+        pub fn storm_config(&self) -> StormConfig {
+            *self.storm.lock_irqsave()
+        }
+
+        pub fn online_cpus(&self) -> usize {
+            self.online.load(Ordering::Relaxed)
+        }
+
+        fn check_affinity(&self, cpus: &[usize]) -> Result<(), &'static str> {
+            if cpus.is_empty() || cpus.iter().any(|cpu| *cpu >= self.online_cpus()) {
+                return Err("Invalid IRQ affinity");
+            }
+            Ok(())
+        }
+
+        // The CPU of those allowed with the fewest vectors, the first on a
+        // tie, so that vectors spread across cores
+        fn least_loaded(&self, lines: &BTreeMap<u8, Line>, affinity: Option<&[usize]>, skip: Option<u8>) -> usize {
+            let allowed: Vec<usize> = match affinity {
+                Some(cpus) => cpus.to_vec(),
+                None => (0..self.online_cpus()).collect(),
+            };
+            let load = |cpu: usize| lines.iter().filter(|(vector, line)| Some(**vector) != skip && line.target == cpu).count();
+            allowed.into_iter().min_by_key(|cpu| (load(*cpu), *cpu)).unwrap_or(0)
+        }
+
+        pub fn request(&self, vector: u8, action: IrqAction) -> Result<(), &'static str> {
+            self.request_locked(&mut self.lines.lock_irqsave(), vector, action)
+        }
+
+        // Registers action on the lowest vector no device has, as MSI and
+        // MSI-X can raise any; returns the vector
+        pub fn request_any(&self, action: IrqAction) -> Result<u8, &'static str> {
+            let mut lines = self.lines.lock_irqsave();
+            let vector = (FIRST_EXTERNAL_VECTOR..FIRST_SYSTEM_VECTOR).find(|vector| !lines.contains_key(vector)).ok_or("No free interrupt vectors")?;
+            self.request_locked(&mut lines, vector, action)?;
+            Ok(vector)
+        }
+
+        fn request_locked(&self, lines: &mut BTreeMap<u8, Line>, vector: u8, action: IrqAction) -> Result<(), &'static str> {
+            if vector < FIRST_EXTERNAL_VECTOR {
+                return Err("Vector reserved for CPU exceptions");
+            }
+            if vector >= FIRST_SYSTEM_VECTOR {
+                return Err("Vector reserved for the local APIC");
+            }
+            if action.thread.as_ref().is_some_and(|thread| thread.priority == 0 || thread.priority > MAX_THREAD_PRIORITY) {
+                return Err("Invalid IRQ thread priority");
+            }
+            if let Some(cpus) = &action.affinity {
+                self.check_affinity(cpus)?;
+            }
+            if lines.get(&vector).is_some_and(|line| line.actions.iter().any(|other| other.name == action.name)) {
+                return Err("Handler already registered");
+            }
+            if !lines.contains_key(&vector) {
+                let target = self.least_loaded(lines, action.affinity.as_deref(), None);
+                lines.insert(vector, Line::new(target));
+            }
+            if let Some(cpus) = &action.affinity {
+                let target = self.least_loaded(lines, Some(cpus), Some(vector));
+                let line = lines.get_mut(&vector).unwrap();
+                line.affinity = Some(cpus.clone());
+                if !cpus.contains(&line.target) {
+                    line.target = target;
+                }
+            }
+            let line = lines.get_mut(&vector).unwrap();
+            match &action.thread {
+                Some(thread) => log::info!("Registered {} on vector {:#x}, CPU {}, threaded at priority {}", action.name, vector, line.target, thread.priority),
+                None => log::info!("Registered {} on vector {:#x}, CPU {}", action.name, vector, line.target),
+            }
+            line.actions.push(Arc::new(action));
+            Ok(())
+        }
+
+        // Its thread does not run again, even if woken
+        pub fn free(&self, vector: u8, name: &str) -> Result<(), &'static str> {
+            let mut lines = self.lines.lock_irqsave();
+            let line = lines.get_mut(&vector).ok_or("Handler not found")?;
+            let index = line.actions.iter().position(|action| action.name == name).ok_or("Handler not found")?;
+            line.actions.remove(index);
+            if line.actions.is_empty() {
+                lines.remove(&vector);
+            }
+            Ok(())
+        }
+
+        pub fn names(&self, vector: u8) -> Vec<String> {
+            self.lines.lock_irqsave().get(&vector).map(|line| line.actions.iter().map(|action| action.name.clone()).collect()).unwrap_or_default()
+        }
+
+        // Moves the vector, and with it its threads; returns the CPU it now
+        // goes to
+        pub fn set_affinity(&self, vector: u8, cpus: &[usize]) -> Result<usize, &'static str> {
+            self.check_affinity(cpus)?;
+            let mut lines = self.lines.lock_irqsave();
+            let target = self.least_loaded(&lines, Some(cpus), Some(vector));
+            let line = lines.get_mut(&vector).ok_or("Vector not in use")?;
+            line.affinity = Some(cpus.to_vec());
+            if !cpus.contains(&line.target) {
+                line.target = target;
+            }
+            Ok(line.target)
+        }
+
+        pub fn target(&self, vector: u8) -> Option<usize> {
+            self.lines.lock_irqsave().get(&vector).map(|line| line.target)
+        }
+
+        // As CPUs come up or go down: every vector is spread again over
+        // those online that its affinity allows, or all of them when it
+        // allows none
+        pub fn set_online_cpus(&self, cpus: usize) {
+            let online = cpus.max(1);
+            self.online.store(online, Ordering::Relaxed);
+            let mut lines = self.lines.lock_irqsave();
+            let vectors: Vec<u8> = lines.keys().copied().collect();
+            for line in lines.values_mut() {
+                line.target = usize::MAX;
+            }
+            for vector in vectors {
+                let affinity = lines[&vector].affinity.as_ref().map(|cpus| cpus.iter().copied().filter(|cpu| *cpu < online).collect::<Vec<_>>()).filter(|cpus| !cpus.is_empty());
+                let target = self.least_loaded(&lines, affinity.as_deref(), Some(vector));
+                lines.get_mut(&vector).unwrap().target = target;
+            }
+        }
+
+        // Asks each handler in turn, noting the threads they wake
+        fn dispatch(&self, vector: u8, actions: &[Arc<IrqAction>]) -> IrqReturn {
+            let mut result = IrqReturn::None;
+            for action in actions {
+                match (action.handler)(vector) {
+                    IrqReturn::None => {}
+                    IrqReturn::Handled => result = IrqReturn::Handled,
+                    IrqReturn::WakeThread => {
+                        match &action.thread {
+                            Some(thread) => thread.pending.store(true, Ordering::Release),
+                            None => log::warn!("{} woke a thread it does not have", action.name),
+                        }
+                        result = IrqReturn::WakeThread;
+                    }
+                }
+            }
+            result
+        }
+
+        // From the interrupt entry code on the CPU that took it. Threads
+        // asked for are left for run_threads().
+        pub fn handle(&self, vector: u8, now: Instant) -> IrqReturn {
+            let config = self.storm_config();
+            let (actions, storm) = {
+                let mut lines = self.lines.lock_irqsave();
+                let Some(line) = lines.get_mut(&vector) else {
+                    log::warn!("Interrupt on vector {:#x} with no handler", vector);
+                    return IrqReturn::None;
+                };
+                *line.counts.entry(percpu::this_cpu()).or_default() += 1;
+                // Raised before the mask took effect; poll() sees to it
+                if line.masked.is_some() {
+                    return IrqReturn::None;
+                }
+                if now.saturating_duration_since(line.window_start) >= config.window {
+                    line.window_start = now;
+                    line.window_count = 0;
+                }
+                line.window_count += 1;
+                let storm = line.window_count > config.threshold;
+                if storm {
+                    log::warn!("Interrupt storm on vector {:#x} from {}: over {} in {}ms, masking it and polling", vector, line.names(), config.threshold, config.window.as_millis());
+                    line.masked = Some((now, now));
+                    line.storms += 1;
+                }
+                (line.actions.clone(), storm)
+            };
+            let result = self.dispatch(vector, &actions);
+            if result == IrqReturn::None {
+                if let Some(line) = self.lines.lock_irqsave().get_mut(&vector) {
+                    line.unhandled += 1;
+                }
+            }
+            if storm {
+                for mask in actions.iter().filter_map(|action| action.mask.as_ref()) {
+                    mask(true);
+                }
+            }
+            result
+        }
+
+        // From the timer interrupt: runs the handlers of storming vectors
+        // that are due a poll, and unmasks those masked long enough.
+        // Returns how many were polled.
+        pub fn poll(&self, now: Instant) -> usize {
+            let config = self.storm_config();
+            let mut due = Vec::new();
+            let mut calmed = Vec::new();
+            for (vector, line) in self.lines.lock_irqsave().iter_mut() {
+                let Some((since, polled)) = line.masked else {
+                    continue;
+                };
+                let backoff = config.unmask_after * (1 << (line.storms - 1).min(MAX_UNMASK_SHIFT)) as u32;
+                if now.saturating_duration_since(since) >= backoff {
+                    log::info!("Unmasking vector {:#x} from {} after {}ms", vector, line.names(), backoff.as_millis());
+                    line.masked = None;
+                    line.window_start = now;
+                    line.window_count = 0;
+                    calmed.push(line.actions.clone());
+                } else if now.saturating_duration_since(polled) >= config.poll_interval {
+                    line.masked = Some((since, now));
+                    due.push((*vector, line.actions.clone()));
+                }
+            }
+            for mask in calmed.iter().flatten().filter_map(|action| action.mask.as_ref()) {
+                mask(false);
+            }
+            for (vector, actions) in &due {
+                self.dispatch(*vector, actions);
+            }
+            due.len()
+        }
+
+        pub fn is_masked(&self, vector: u8) -> bool {
+            self.lines.lock_irqsave().get(&vector).is_some_and(|line| line.masked.is_some())
+        }
+
+        pub fn storms(&self, vector: u8) -> u64 {
+            self.lines.lock_irqsave().get(&vector).map(|line| line.storms).unwrap_or(0)
+        }
+
+        // Runs the woken threads of the vectors a CPU takes, highest
+        // priority first, with interrupts on; returns how many ran
+        pub fn run_threads(&self, cpu: usize) -> usize {
+            assert!(!sync::in_interrupt(), "IRQ threads run outside interrupt handlers");
+            let mut woken: Vec<(u8, u8, Arc<IrqAction>)> = Vec::new();
+            for (vector, line) in self.lines.lock_irqsave().iter().filter(|(_, line)| line.target == cpu) {
+                for action in &line.actions {
+                    if let Some(thread) = action.thread.as_ref().filter(|thread| thread.pending.load(Ordering::Acquire)) {
+                        woken.push((thread.priority, *vector, Arc::clone(action)));
+                    }
+                }
+            }
+            woken.sort_by_key(|(priority, vector, _)| (std::cmp::Reverse(*priority), *vector));
+            let mut ran = 0;
+            for (_, vector, action) in woken {
+                let thread = action.thread.as_ref().unwrap();
+                // Cleared first, so that an interrupt while it runs runs it again
+                if thread.pending.swap(false, Ordering::AcqRel) {
+                    (thread.handler)(vector);
+                    ran += 1;
+                }
+            }
+            ran
+        }
+
+        // Interrupts on the vector so far, on every CPU
+        pub fn count(&self, vector: u8) -> u64 {
+            self.lines.lock_irqsave().get(&vector).map(|line| line.counts.values().sum()).unwrap_or(0)
+        }
+
+        pub fn unhandled(&self, vector: u8) -> u64 {
+            self.lines.lock_irqsave().get(&vector).map(|line| line.unhandled).unwrap_or(0)
+        }
+
+        // Storms each vector has had, by the devices on it
+        pub fn register_metrics(&'static self, registry: &Registry) -> Result<(), &'static str> {
+            registry.collect("vaelix_irq_storms_total", "Times a vector was masked for interrupting too often.", MetricType::Counter, move || {
+                let lines = self.lines.lock_irqsave();
+                lines.iter().filter(|(_, line)| line.storms > 0).map(|(vector, line)| Sample::new(line.storms as f64).with("vector", &vector.to_string()).with("device", &line.names())).collect()
+            })
+        }
+
+        // As /proc/interrupts: a column of counts per CPU, then where the
+        // vector goes and its handlers
+        pub fn report(&self) -> String {
+            let cpus = self.online_cpus();
+            let mut report = " ".repeat(5);
+            for cpu in 0..cpus {
+                write!(report, "{:>11}", format!("CPU{}", cpu)).unwrap();
+            }
+            report.push('\n');
+            for (vector, line) in self.lines.lock_irqsave().iter() {
+                write!(report, "{:>4}:", vector).unwrap();
+                for cpu in 0..cpus {
+                    write!(report, "{:>11}", line.counts.get(&cpu).copied().unwrap_or(0)).unwrap();
+                }
+                writeln!(report, "  CPU{}  {}{}", line.target, line.names(), if line.masked.is_some() { "  (masked, storm)" } else { "" }).unwrap();
+            }
+            report
+        }
+    }
+
+    // The kernel's own; hosted, it runs on the one CPU
+    pub fn controller() -> &'static IrqController {
+        static CONTROLLER: OnceLock<IrqController> = OnceLock::new();
+        CONTROLLER.get_or_init(|| IrqController::new(1))
+    }
+}
@@ -0,0 +1,213 @@
+// src/kernel/kallsyms.rs
+
+pub mod kallsyms {
+    use crate::crashdump::crashdump;
+    use std::ffi::c_void;
+    use std::sync::OnceLock;
+
+    const TABLE_MAGIC: &[u8; 8] = b"VXSYMS01";
+    // At the very end of an image with a table: the table's length, then
+    // this
+    const TRAILER_MAGIC: &[u8; 8] = b"VXKSYMTB";
+    pub const MAX_FRAMES: usize = 64;
+
+    static KALLSYMS: OnceLock<SymbolTable> = OnceLock::new();
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Symbol {
+        pub address: u64,
+        // 0 when unknown, in which case it runs up to the next symbol
+        pub size: u64,
+        pub name: String,
+    }
+
+    fn put_varint(output: &mut Vec<u8>, mut value: u64) {
+        while value >= 0x80 {
+            output.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        output.push(value as u8);
+    }
+
+    fn varint(data: &[u8], index: &mut usize) -> Result<u64, &'static str> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = *data.get(*index).ok_or("Truncated symbol table")?;
+            *index += 1;
+            value |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("Corrupt symbol table")
+    }
+
+    // The kernel's functions by address, as embedded in its image
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct SymbolTable {
+        // Sorted by address, as linked
+        symbols: Vec<Symbol>,
+        // Where KASLR put the image
+        offset: u64,
+    }
+
+    impl SymbolTable {
+        pub fn new(mut symbols: Vec<Symbol>) -> Self {
+            symbols.sort_by(|a, b| a.address.cmp(&b.address).then_with(|| a.name.cmp(&b.name)));
+            symbols.dedup_by(|a, b| a.address == b.address && a.name == b.name);
+            SymbolTable { symbols, offset: 0 }
+        }
+
+        // From `nm --defined-only -S` on the linked kernel. Only text
+        // symbols are kept, as only code turns up in backtraces.
+        pub fn from_nm(text: &str) -> Result<Self, &'static str> {
+            let mut symbols = Vec::new();
+            for line in text.lines().filter(|line| !line.trim().is_empty()) {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                let (address, size, kind, name) = match fields[..] {
+                    [address, size, kind, name] => (address, Some(size), kind, name),
+                    [address, kind, name] => (address, None, kind, name),
+                    _ => return Err("Malformed nm output"),
+                };
+                if !matches!(kind, "T" | "t" | "W" | "w") {
+                    continue;
+                }
+                let parse = |field: &str| u64::from_str_radix(field, 16).map_err(|_| "Malformed nm output");
+                let size = size.map(parse).transpose()?.unwrap_or(0);
+                symbols.push(Symbol { address: parse(address)?, size, name: name.to_string() });
+            }
+            Ok(SymbolTable::new(symbols))
+        }
+
+        pub fn len(&self) -> usize {
+            self.symbols.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.symbols.is_empty()
+        }
+
+        // Addresses as deltas from the one before, names as how much they
+        // share with the one before plus the rest. Sorted symbols from the
+        // same module mostly share a long prefix.
+        pub fn encode(&self) -> Vec<u8> {
+            let mut output = TABLE_MAGIC.to_vec();
+            put_varint(&mut output, self.symbols.len() as u64);
+            let (mut address, mut name): (u64, &[u8]) = (0, b"");
+            for symbol in &self.symbols {
+                let shared = name.iter().zip(symbol.name.as_bytes()).take(u8::MAX as usize).take_while(|(a, b)| a == b).count();
+                put_varint(&mut output, symbol.address - address);
+                put_varint(&mut output, symbol.size);
+                output.push(shared as u8);
+                put_varint(&mut output, (symbol.name.len() - shared) as u64);
+                output.extend_from_slice(&symbol.name.as_bytes()[shared..]);
+                (address, name) = (symbol.address, symbol.name.as_bytes());
+            }
+            output
+        }
+
+        pub fn decode(data: &[u8]) -> Result<Self, &'static str> {
+            if !data.starts_with(TABLE_MAGIC) {
+                return Err("Not a symbol table");
+            }
+            let mut index = TABLE_MAGIC.len();
+            let count = varint(data, &mut index)?;
+            // Each takes at least four bytes
+            if count > (data.len() / 4) as u64 {
+                return Err("Corrupt symbol table");
+            }
+            let mut symbols: Vec<Symbol> = Vec::with_capacity(count as usize);
+            let mut address = 0u64;
+            for _ in 0..count {
+                address = address.checked_add(varint(data, &mut index)?).ok_or("Corrupt symbol table")?;
+                let size = varint(data, &mut index)?;
+                let shared = *data.get(index).ok_or("Truncated symbol table")? as usize;
+                index += 1;
+                let length = varint(data, &mut index)? as usize;
+                let previous = symbols.last().map_or("", |symbol| symbol.name.as_str());
+                let prefix = previous.as_bytes().get(..shared).ok_or("Corrupt symbol table")?;
+                let rest = data.get(index..index.saturating_add(length)).ok_or("Truncated symbol table")?;
+                index += length;
+                let name = String::from_utf8([prefix, rest].concat()).map_err(|_| "Corrupt symbol table")?;
+                symbols.push(Symbol { address, size, name });
+            }
+            Ok(SymbolTable { symbols, offset: 0 })
+        }
+
+        // Appended to a kernel image when it is built; measured along with
+        // the rest of it
+        pub fn embed(&self, image: &mut Vec<u8>) {
+            let table = self.encode();
+            image.extend_from_slice(&table);
+            image.extend_from_slice(&(table.len() as u64).to_le_bytes());
+            image.extend_from_slice(TRAILER_MAGIC);
+        }
+
+        pub fn from_image(image: &[u8]) -> Result<Self, &'static str> {
+            let trailer = image.len().checked_sub(16).filter(|start| image[start + 8..] == TRAILER_MAGIC[..]).ok_or("Kernel image has no symbol table")?;
+            let length = u64::from_le_bytes(image[trailer..trailer + 8].try_into().unwrap());
+            let start = usize::try_from(length).ok().and_then(|length| trailer.checked_sub(length)).ok_or("Corrupt symbol table")?;
+            Self::decode(&image[start..trailer])
+        }
+
+        // For an image relocated that far up, as relocate_kernel does
+        pub fn relocate(&mut self, offset: u64) {
+            self.offset = offset;
+        }
+
+        // The symbol an address is in, and how far into it
+        pub fn lookup(&self, address: u64) -> Option<(&Symbol, u64)> {
+            let address = address.checked_sub(self.offset)?;
+            let index = self.symbols.partition_point(|symbol| symbol.address <= address).checked_sub(1)?;
+            let symbol = &self.symbols[index];
+            let end = match symbol.size {
+                0 => self.symbols.get(index + 1).map_or(u64::MAX, |next| next.address),
+                size => symbol.address.saturating_add(size),
+            };
+            (address < end).then_some((symbol, address - symbol.address))
+        }
+
+        pub fn address_of(&self, name: &str) -> Option<u64> {
+            self.symbols.iter().find(|symbol| symbol.name == name).map(|symbol| symbol.address + self.offset)
+        }
+
+        // As name+offset/size
+        pub fn symbolize(&self, address: u64) -> Option<String> {
+            let (symbol, offset) = self.lookup(address)?;
+            Some(match symbol.size {
+                0 => format!("{}+{:#x}", symbol.name, offset),
+                size => format!("{}+{:#x}/{:#x}", symbol.name, offset, size),
+            })
+        }
+    }
+
+    // Once the image is relocated; from then on addresses in backtraces,
+    // profiles and traces get names
+    pub fn install(table: SymbolTable) -> Result<(), &'static str> {
+        KALLSYMS.set(table).map_err(|_| "Kernel symbols already installed")
+    }
+
+    pub fn installed() -> bool {
+        KALLSYMS.get().is_some()
+    }
+
+    // Kernel symbols first, then loaded modules by name and offset, else
+    // the bare address
+    pub fn symbolize(address: u64) -> String {
+        if let Some(name) = KALLSYMS.get().and_then(|table| table.symbolize(address)) {
+            return name;
+        }
+        let module = crashdump::modules().into_iter().find(|module| address.wrapping_sub(module.base) < module.size);
+        match module {
+            Some(module) => format!("[{}]+{:#x}", module.name, address - module.base),
+            None => format!("{:#x}", address),
+        }
+    }
+
+    // Return addresses of the calling thread, innermost first
+    pub fn capture_backtrace() -> Vec<u64> {
+        let mut frames = [std::ptr::null_mut::<c_void>(); MAX_FRAMES];
+        let count = unsafe { libc::backtrace(frames.as_mut_ptr(), MAX_FRAMES as i32) };
+        frames[..count.max(0) as usize].iter().map(|frame| *frame as u64).collect()
+    }
+}
@@ -0,0 +1,171 @@
+// src/kernel/kconfig.rs
+
+pub mod kconfig {
+    use crate::hardening::hardening;
+    use crate::power::policy::policy::PolicyMode;
+    use std::fmt::Write;
+
+    #[cfg(all(feature = "profile-laptop", feature = "profile-qemu"))]
+    compile_error!("Select at most one machine profile feature");
+
+    // The machine the kernel is built for, chosen with the profile-laptop
+    // and profile-qemu features; neither means generic x86_64
+    pub const PROFILE: Profile = if cfg!(feature = "profile-laptop") {
+        Profile::Laptop
+    } else if cfg!(feature = "profile-qemu") {
+        Profile::Qemu
+    } else {
+        Profile::Generic
+    };
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Profile {
+        // The Alder Lake laptop VaelixOS is developed on
+        Laptop,
+        // QEMU's q35 machine, for development and the test runner
+        Qemu,
+        Generic,
+    }
+
+    impl Profile {
+        pub const ALL: [Profile; 3] = [Profile::Laptop, Profile::Qemu, Profile::Generic];
+
+        pub fn name(&self) -> &'static str {
+            match self {
+                Profile::Laptop => "laptop",
+                Profile::Qemu => "qemu",
+                Profile::Generic => "generic",
+            }
+        }
+
+        pub fn parse(name: &str) -> Result<Self, &'static str> {
+            Profile::ALL.into_iter().find(|profile| profile.name() == name).ok_or("Unknown machine profile")
+        }
+    }
+
+    // Only drivers boot actually probes are configurable; the rest are
+    // always built
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub enum Driver {
+        Tpm,
+        Uart,
+    }
+
+    impl Driver {
+        pub const ALL: [Driver; 2] = [Driver::Tpm, Driver::Uart];
+
+        pub fn name(&self) -> &'static str {
+            match self {
+                Driver::Tpm => "tpm",
+                Driver::Uart => "uart",
+            }
+        }
+    }
+
+    // A bit per driver, so that a whole configuration is a constant
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct Drivers(u32);
+
+    impl Drivers {
+        pub const fn of(drivers: &[Driver]) -> Self {
+            let mut set = Drivers(0);
+            let mut index = 0;
+            while index < drivers.len() {
+                set.0 |= 1 << drivers[index] as u32;
+                index += 1;
+            }
+            set
+        }
+
+        pub const fn contains(&self, driver: Driver) -> bool {
+            self.0 & 1 << driver as u32 != 0
+        }
+
+        pub fn iter(&self) -> impl Iterator<Item = Driver> + '_ {
+            Driver::ALL.into_iter().filter(|driver| self.contains(*driver))
+        }
+    }
+
+    // Which drivers are started and the policy the kernel starts with, for
+    // one machine profile
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct KernelConfig {
+        pub profile: Profile,
+        pub drivers: Drivers,
+        pub power_mode: PolicyMode,
+        pub serial_console: bool,
+        // The soft lockup watchdog, left off where vCPUs are descheduled
+        // often enough to look stuck
+        pub watchdog: bool,
+        pub hardening: bool,
+    }
+
+    impl KernelConfig {
+        pub const fn for_profile(profile: Profile) -> Self {
+            let (drivers, power_mode, serial_console, watchdog): (&[Driver], _, _, _) = match profile {
+                Profile::Laptop => (&[Driver::Tpm], PolicyMode::Balanced, false, true),
+                Profile::Qemu => (&Driver::ALL, PolicyMode::Performance, true, false),
+                Profile::Generic => (&Driver::ALL, PolicyMode::Balanced, true, true),
+            };
+            KernelConfig {
+                profile,
+                drivers: Drivers::of(drivers),
+                power_mode,
+                serial_console,
+                watchdog,
+                hardening: hardening::ENABLED,
+            }
+        }
+
+        pub const fn has(&self, driver: Driver) -> bool {
+            self.drivers.contains(driver)
+        }
+
+        pub const fn enable(&mut self, driver: Driver) {
+            self.drivers.0 |= 1 << driver as u32;
+        }
+
+        pub const fn disable(&mut self, driver: Driver) {
+            self.drivers.0 &= !(1 << driver as u32);
+        }
+
+        // The first unmet dependency, as Kconfig would refuse it. Const, so
+        // that ACTIVE is checked as the kernel is built.
+        pub const fn validate(&self) -> Result<(), &'static str> {
+            if self.serial_console && !self.has(Driver::Uart) {
+                return Err("serial_console depends on uart");
+            }
+            Ok(())
+        }
+
+        // As a .config file, for /proc/config
+        pub fn render(&self) -> String {
+            let mut output = format!("CONFIG_PROFILE=\"{}\"\n", self.profile.name());
+            let flag = |output: &mut String, name: &str, set: bool| match set {
+                true => writeln!(output, "CONFIG_{}=y", name.to_uppercase()).unwrap(),
+                false => writeln!(output, "# CONFIG_{} is not set", name.to_uppercase()).unwrap(),
+            };
+            for driver in Driver::ALL {
+                flag(&mut output, &format!("driver_{}", driver.name()), self.has(driver));
+            }
+            let mode = match self.power_mode {
+                PolicyMode::Performance => "performance",
+                PolicyMode::Balanced => "balanced",
+                PolicyMode::PowerSaver => "powersaver",
+            };
+            writeln!(output, "CONFIG_POWER_MODE=\"{}\"", mode).unwrap();
+            flag(&mut output, "serial_console", self.serial_console);
+            flag(&mut output, "watchdog", self.watchdog);
+            flag(&mut output, "hardening", self.hardening);
+            output
+        }
+    }
+
+    // What this kernel was built with
+    pub const ACTIVE: KernelConfig = KernelConfig::for_profile(PROFILE);
+
+    // A profile that could not work fails the build rather than the boot
+    const _: () = if let Err(problem) = ACTIVE.validate() {
+        panic!("{}", problem)
+    };
+}
@@ -0,0 +1,410 @@
+// src/kernel/keyring.rs
+
+pub mod keyring {
+    use crate::drivers::tpm::tpm::{SealedObject, Tpm2, TpmTransport};
+    use crate::users::users::{Credentials, Gid, Uid, ACCESS_READ, ACCESS_WRITE};
+    use std::collections::BTreeMap;
+    use std::fmt;
+    use std::sync::Arc;
+    use zeroize::Zeroize;
+
+    pub type KeyId = u32;
+
+    // Longest payload of any kind of key
+    pub const MAX_KEY_SIZE: usize = 64;
+
+    // Key material. It is wiped when dropped, so nothing of it is left in
+    // freed memory, compared in constant time and never printed.
+    pub struct Secret {
+        bytes: Vec<u8>,
+    }
+
+    impl Secret {
+        // Takes the buffer over rather than copying it
+        pub fn new(bytes: Vec<u8>) -> Self {
+            Secret { bytes }
+        }
+
+        pub fn from_slice(bytes: &[u8]) -> Self {
+            Secret { bytes: bytes.to_vec() }
+        }
+
+        pub fn expose(&self) -> &[u8] {
+            &self.bytes
+        }
+
+        pub fn len(&self) -> usize {
+            self.bytes.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.bytes.is_empty()
+        }
+    }
+
+    impl Clone for Secret {
+        fn clone(&self) -> Self {
+            Secret::from_slice(&self.bytes)
+        }
+    }
+
+    impl PartialEq for Secret {
+        fn eq(&self, other: &Self) -> bool {
+            self.bytes.len() == other.bytes.len() && self.bytes.iter().zip(&other.bytes).fold(0, |difference, (a, b)| difference | (a ^ b)) == 0
+        }
+    }
+
+    impl Eq for Secret {}
+
+    impl fmt::Debug for Secret {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "Secret({} bytes)", self.bytes.len())
+        }
+    }
+
+    impl Drop for Secret {
+        fn drop(&mut self) {
+            self.bytes.zeroize();
+        }
+    }
+
+    // Numbers are the ABI, for add_key and request_key
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum KeyKind {
+        // A vxfs volume key: 256 bits, or 512 for XTS
+        Encryption,
+        // A WPA passphrase of 8 to 63 printable characters, or the 256-bit
+        // PSK itself as 64 hex digits
+        WifiPsk,
+        // A Curve25519 private key, or a preshared key
+        WireGuard,
+    }
+
+    impl KeyKind {
+        pub fn from_number(number: u64) -> Option<Self> {
+            match number {
+                0 => Some(KeyKind::Encryption),
+                1 => Some(KeyKind::WifiPsk),
+                2 => Some(KeyKind::WireGuard),
+                _ => None,
+            }
+        }
+
+        pub fn name(self) -> &'static str {
+            match self {
+                KeyKind::Encryption => "encryption",
+                KeyKind::WifiPsk => "wifi-psk",
+                KeyKind::WireGuard => "wireguard",
+            }
+        }
+
+        pub fn parse(name: &str) -> Result<Self, &'static str> {
+            match name {
+                "encryption" => Ok(KeyKind::Encryption),
+                "wifi-psk" => Ok(KeyKind::WifiPsk),
+                "wireguard" => Ok(KeyKind::WireGuard),
+                _ => Err("Unknown key kind"),
+            }
+        }
+
+        pub fn check(self, payload: &[u8]) -> Result<(), &'static str> {
+            let valid = match self {
+                KeyKind::Encryption => payload.len() == 32 || payload.len() == 64,
+                KeyKind::WifiPsk => match payload.len() {
+                    64 => payload.iter().all(u8::is_ascii_hexdigit),
+                    length => (8..=63).contains(&length) && payload.iter().all(|byte| (0x20..0x7F).contains(byte)),
+                },
+                KeyKind::WireGuard => payload.len() == 32,
+            };
+            match valid {
+                true => Ok(()),
+                false => Err("Invalid key payload"),
+            }
+        }
+    }
+
+    // Puts secrets beyond reach of anything but this machine, so they can
+    // be kept on disk
+    pub trait Sealer: Send + Sync {
+        fn seal(&self, secret: &Secret) -> Result<Vec<u8>, &'static str>;
+        fn unseal(&self, blob: &[u8]) -> Result<Secret, &'static str>;
+    }
+
+    // Seals under a storage key of the TPM. Blobs are the private and
+    // public areas, each with its size in front as the TPM gives them.
+    pub struct TpmSealer<T: TpmTransport> {
+        tpm: Arc<Tpm2<T>>,
+        parent: u32,
+    }
+
+    impl<T: TpmTransport> TpmSealer<T> {
+        pub fn new(tpm: Arc<Tpm2<T>>, parent: u32) -> Self {
+            TpmSealer { tpm, parent }
+        }
+    }
+
+    impl<T: TpmTransport> Sealer for TpmSealer<T> {
+        fn seal(&self, secret: &Secret) -> Result<Vec<u8>, &'static str> {
+            let object = self.tpm.seal(self.parent, secret.expose())?;
+            let mut blob = Vec::new();
+            for part in [&object.private, &object.public] {
+                blob.extend_from_slice(&(part.len() as u16).to_be_bytes());
+                blob.extend_from_slice(part);
+            }
+            Ok(blob)
+        }
+
+        fn unseal(&self, blob: &[u8]) -> Result<Secret, &'static str> {
+            let split = |blob: &[u8]| -> Option<(Vec<u8>, usize)> {
+                let length = u16::from_be_bytes(blob.get(..2)?.try_into().ok()?) as usize;
+                Some((blob.get(2..2 + length)?.to_vec(), 2 + length))
+            };
+            let (private, used) = split(blob).ok_or("Malformed sealed key")?;
+            let (public, rest) = split(&blob[used..]).ok_or("Malformed sealed key")?;
+            if used + rest != blob.len() {
+                return Err("Malformed sealed key");
+            }
+            self.tpm.unseal(self.parent, &SealedObject { private, public }).map(Secret::new)
+        }
+    }
+
+    struct Key {
+        kind: KeyKind,
+        description: String,
+        owner: Uid,
+        group: Gid,
+        // Read and write bits for the owner, group and others, as for files
+        mode: u32,
+        secret: Secret,
+    }
+
+    impl Key {
+        fn may_access(&self, credentials: &Credentials, access: u32) -> bool {
+            if credentials.is_root() {
+                return true;
+            }
+            let bits = if self.owner == credentials.uid {
+                self.mode >> 6
+            } else if credentials.in_group(self.group) {
+                self.mode >> 3
+            } else {
+                self.mode
+            };
+            bits & access == access
+        }
+    }
+
+    // What is known of a key without reading it
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct KeyInfo {
+        pub id: KeyId,
+        pub kind: KeyKind,
+        pub description: String,
+        pub owner: Uid,
+        pub group: Gid,
+        pub mode: u32,
+    }
+
+    // A key as kept on disk: everything about it in the open but the
+    // secret, which only the sealer that made it can get back
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct SealedKey {
+        pub kind: KeyKind,
+        pub description: String,
+        pub owner: Uid,
+        pub group: Gid,
+        pub mode: u32,
+        pub blob: Vec<u8>,
+    }
+
+    impl SealedKey {
+        // KIND OWNER GROUP MODE BLOB DESCRIPTION, with the mode in octal,
+        // the blob in hex and the description running to the end
+        pub fn entry(&self) -> String {
+            let blob: String = self.blob.iter().map(|byte| format!("{:02x}", byte)).collect();
+            format!("{} {} {} {:o} {} {}", self.kind.name(), self.owner, self.group, self.mode, blob, self.description)
+        }
+
+        pub fn parse(line: &str) -> Result<Self, &'static str> {
+            let mut fields = line.splitn(6, ' ');
+            let mut field = || fields.next().ok_or("Malformed sealed key");
+            let kind = KeyKind::parse(field()?)?;
+            let owner = field()?.parse().map_err(|_| "Malformed sealed key")?;
+            let group = field()?.parse().map_err(|_| "Malformed sealed key")?;
+            let mode = u32::from_str_radix(field()?, 8).map_err(|_| "Malformed sealed key")?;
+            let blob = field()?;
+            if blob.len() % 2 != 0 {
+                return Err("Malformed sealed key");
+            }
+            let blob = (0..blob.len())
+                .step_by(2)
+                .map(|index| blob.get(index..index + 2).and_then(|digits| u8::from_str_radix(digits, 16).ok()))
+                .collect::<Option<Vec<u8>>>()
+                .ok_or("Malformed sealed key")?;
+            Ok(SealedKey {
+                kind,
+                description: field()?.to_string(),
+                owner,
+                group,
+                mode,
+                blob,
+            })
+        }
+    }
+
+    // The kernel's keys and secrets, each owned by a user and readable and
+    // writable as its mode allows; root may do anything. A key is found by
+    // its kind and description. Revoking one wipes it straight away.
+    pub struct Keyring {
+        keys: BTreeMap<KeyId, Key>,
+        next_id: KeyId,
+        sealer: Option<Box<dyn Sealer>>,
+    }
+
+    impl Default for Keyring {
+        fn default() -> Self {
+            Keyring::new()
+        }
+    }
+
+    impl Keyring {
+        pub fn new() -> Self {
+            Keyring {
+                keys: BTreeMap::new(),
+                next_id: 1,
+                sealer: None,
+            }
+        }
+
+        // Keys can only be sealed with one, usually the TPM's
+        pub fn set_sealer(&mut self, sealer: Box<dyn Sealer>) {
+            self.sealer = Some(sealer);
+        }
+
+        pub fn can_seal(&self) -> bool {
+            self.sealer.is_some()
+        }
+
+        pub fn len(&self) -> usize {
+            self.keys.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.keys.is_empty()
+        }
+
+        // Owned by the caller. Adding a key the caller already has of the
+        // same kind and description updates it instead.
+        pub fn add(&mut self, credentials: &Credentials, kind: KeyKind, description: &str, secret: Secret, mode: u32) -> Result<KeyId, &'static str> {
+            kind.check(secret.expose())?;
+            if mode & !0o666 != 0 {
+                return Err("Invalid key mode");
+            }
+            if description.is_empty() || description.contains('\n') {
+                return Err("Invalid key description");
+            }
+            let existing = self.keys.iter().find(|(_, key)| key.owner == credentials.uid && key.kind == kind && key.description == description);
+            if let Some((&id, _)) = existing {
+                let key = self.keys.get_mut(&id).unwrap();
+                if !key.may_access(credentials, ACCESS_WRITE) {
+                    return Err("Permission denied");
+                }
+                key.secret = secret;
+                return Ok(id);
+            }
+            let id = self.next_id;
+            self.next_id += 1;
+            self.keys.insert(
+                id,
+                Key {
+                    kind,
+                    description: description.to_string(),
+                    owner: credentials.uid,
+                    group: credentials.gid,
+                    mode,
+                    secret,
+                },
+            );
+            Ok(id)
+        }
+
+        // The caller's own key if it has one, else the first it may read
+        pub fn find(&self, credentials: &Credentials, kind: KeyKind, description: &str) -> Result<KeyId, &'static str> {
+            let mut matching = self.keys.iter().filter(|(_, key)| key.kind == kind && key.description == description);
+            let own = matching.clone().find(|(_, key)| key.owner == credentials.uid);
+            own.or_else(|| matching.find(|(_, key)| key.may_access(credentials, ACCESS_READ)))
+                .map(|(id, _)| *id)
+                .ok_or("Key not found")
+        }
+
+        fn key(&self, credentials: &Credentials, id: KeyId, access: u32) -> Result<&Key, &'static str> {
+            let key = self.keys.get(&id).ok_or("Key not found")?;
+            match key.may_access(credentials, access) {
+                true => Ok(key),
+                false => Err("Permission denied"),
+            }
+        }
+
+        pub fn read(&self, credentials: &Credentials, id: KeyId) -> Result<&Secret, &'static str> {
+            self.key(credentials, id, ACCESS_READ).map(|key| &key.secret)
+        }
+
+        pub fn info(&self, id: KeyId) -> Option<KeyInfo> {
+            self.keys.get(&id).map(|key| KeyInfo {
+                id,
+                kind: key.kind,
+                description: key.description.clone(),
+                owner: key.owner,
+                group: key.group,
+                mode: key.mode,
+            })
+        }
+
+        pub fn revoke(&mut self, credentials: &Credentials, id: KeyId) -> Result<(), &'static str> {
+            self.key(credentials, id, ACCESS_WRITE)?;
+            self.keys.remove(&id);
+            Ok(())
+        }
+
+        // Only by its owner or root
+        pub fn set_mode(&mut self, credentials: &Credentials, id: KeyId, mode: u32) -> Result<(), &'static str> {
+            if mode & !0o666 != 0 {
+                return Err("Invalid key mode");
+            }
+            let key = self.keys.get_mut(&id).ok_or("Key not found")?;
+            if !credentials.is_root() && key.owner != credentials.uid {
+                return Err("Permission denied");
+            }
+            key.mode = mode;
+            Ok(())
+        }
+
+        // For keeping on disk, by someone who may read it
+        pub fn seal(&self, credentials: &Credentials, id: KeyId) -> Result<SealedKey, &'static str> {
+            let sealer = self.sealer.as_ref().ok_or("No sealer for keys")?;
+            let key = self.key(credentials, id, ACCESS_READ)?;
+            Ok(SealedKey {
+                kind: key.kind,
+                description: key.description.clone(),
+                owner: key.owner,
+                group: key.group,
+                mode: key.mode,
+                blob: sealer.seal(&key.secret)?,
+            })
+        }
+
+        // Brings a sealed key back as it was, owner and all, as the kernel
+        // does at boot
+        pub fn unseal(&mut self, sealed: &SealedKey) -> Result<KeyId, &'static str> {
+            let secret = self.sealer.as_ref().ok_or("No sealer for keys")?.unseal(&sealed.blob)?;
+            let credentials = Credentials {
+                uid: sealed.owner,
+                gid: sealed.group,
+                ..Credentials::root()
+            };
+            let id = self.add(&credentials, sealed.kind, &sealed.description, secret, sealed.mode)?;
+            self.keys.get_mut(&id).unwrap().mode = sealed.mode;
+            Ok(id)
+        }
+    }
+}
@@ -0,0 +1,199 @@
+// src/kernel/ktest.rs
+
+pub mod ktest {
+    use crate::drivers::port::port::PortIo;
+    use crate::drivers::uart::uart::SerialPort;
+    use crate::vx_tasklet::TaskletScheduler;
+    use crate::vxboot::vxboot::CommandLine;
+    use crate::vxchan::vxchan::VXChanManager;
+    use crate::vxfs::vxfs::VXFS;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    // QEMU's isa-debug-exit device, as the harness sets it up: writing a
+    // value makes QEMU exit with (value << 1) | 1
+    pub const QEMU_EXIT_PORT: u16 = 0xF4;
+    pub const QEMU_EXIT_SUCCESS: u8 = 0x10;
+    pub const QEMU_EXIT_FAILURE: u8 = 0x11;
+
+    // What QEMU exits with after value is written
+    pub fn qemu_exit_status(value: u8) -> i32 {
+        ((value as i32) << 1) | 1
+    }
+
+    // A test run inside the kernel, failing by panicking as #[test]s do
+    #[derive(Clone, Copy)]
+    pub struct TestCase {
+        pub suite: &'static str,
+        pub name: &'static str,
+        pub run: fn(),
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum Outcome {
+        Passed,
+        Failed(String),
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct TestResult {
+        pub suite: &'static str,
+        pub name: &'static str,
+        pub outcome: Outcome,
+        pub duration: Duration,
+    }
+
+    #[derive(Debug, Clone, Default, PartialEq, Eq)]
+    pub struct Report {
+        pub results: Vec<TestResult>,
+    }
+
+    impl Report {
+        pub fn passed(&self) -> usize {
+            self.results.iter().filter(|result| result.outcome == Outcome::Passed).count()
+        }
+
+        pub fn failed(&self) -> usize {
+            self.results.len() - self.passed()
+        }
+
+        // Nothing run is a failure too, as with a misspelt suite
+        pub fn success(&self) -> bool {
+            !self.results.is_empty() && self.failed() == 0
+        }
+
+        pub fn exit_value(&self) -> u8 {
+            match self.success() {
+                true => QEMU_EXIT_SUCCESS,
+                false => QEMU_EXIT_FAILURE,
+            }
+        }
+    }
+
+    // The suites asked for with vxtest=suite,suite on the kernel command
+    // line, or every one for vxtest=all or a bare vxtest. None when not
+    // testing.
+    pub fn selected_suites(command_line: &CommandLine) -> Option<Vec<String>> {
+        if !command_line.has("vxtest") {
+            return None;
+        }
+        match command_line.get("vxtest") {
+            None | Some("all") => Some(Vec::new()),
+            Some(suites) => Some(suites.split(',').filter(|suite| !suite.is_empty()).map(str::to_string).collect()),
+        }
+    }
+
+    fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+        match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+            (Some(message), _) => message.to_string(),
+            (_, Some(message)) => message.clone(),
+            _ => "panicked".to_string(),
+        }
+    }
+
+    // Runs the cases in the given suites, all of them for none, writing a
+    // line per test and a summary to the console for the harness to show
+    pub fn run(cases: &[TestCase], suites: &[String], console: &dyn SerialPort) -> Report {
+        let write = |line: String| {
+            let _ = console.write_all(format!("{}\r\n", line).as_bytes());
+        };
+        let selected: Vec<&TestCase> = cases.iter().filter(|case| suites.is_empty() || suites.iter().any(|suite| suite == case.suite)).collect();
+        write(format!("vxtest: running {} tests", selected.len()));
+        let mut report = Report::default();
+        for case in selected {
+            let start = Instant::now();
+            let outcome = match panic::catch_unwind(AssertUnwindSafe(case.run)) {
+                Ok(()) => Outcome::Passed,
+                Err(payload) => Outcome::Failed(panic_message(payload.as_ref())),
+            };
+            let duration = start.elapsed();
+            match &outcome {
+                Outcome::Passed => write(format!("vxtest: {}::{} ... ok ({}ms)", case.suite, case.name, duration.as_millis())),
+                Outcome::Failed(message) => write(format!("vxtest: {}::{} ... FAILED: {}", case.suite, case.name, message)),
+            }
+            report.results.push(TestResult { suite: case.suite, name: case.name, outcome, duration });
+        }
+        write(format!("vxtest: {} passed, {} failed", report.passed(), report.failed()));
+        report
+    }
+
+    // Under QEMU this ends the run with the report's exit value; hosted,
+    // the process exits with the status QEMU would have
+    pub fn exit(ports: &dyn PortIo, report: &Report) -> ! {
+        ports.outb(QEMU_EXIT_PORT, report.exit_value());
+        std::process::exit(qemu_exit_status(report.exit_value()))
+    }
+
+    fn allocator_large_and_small() {
+        let blocks: Vec<Vec<u8>> = (0..256).map(|index| vec![index as u8; 64 << (index % 10)]).collect();
+        for (index, block) in blocks.iter().enumerate() {
+            assert!(block.iter().all(|byte| *byte == index as u8), "Allocation {} corrupted", index);
+        }
+        let huge = vec![0xA5u8; 64 << 20];
+        assert_eq!(huge[huge.len() - 1], 0xA5);
+    }
+
+    fn allocator_reuse() {
+        for round in 0..1000 {
+            let boxed = Box::new([round as u64; 32]);
+            assert_eq!(boxed.iter().sum::<u64>(), round as u64 * 32);
+        }
+    }
+
+    fn scheduler_priority_order() {
+        let scheduler = TaskletScheduler::new();
+        let (sender, receiver) = mpsc::channel();
+        for priority in [3, 1, 2, 0] {
+            let sender = sender.clone();
+            scheduler.add_task(Box::new(move || sender.send(priority).unwrap()), priority);
+        }
+        let runner = scheduler.clone();
+        thread::spawn(move || runner.run());
+        let order: Vec<usize> = (0..4).map(|_| receiver.recv_timeout(Duration::from_secs(5)).expect("Tasklet did not run")).collect();
+        assert_eq!(order, [0, 1, 2, 3]);
+    }
+
+    fn vxchan_round_trip() {
+        let manager = VXChanManager::new();
+        manager.create_channel("ktest").unwrap();
+        assert!(manager.create_channel("ktest").is_err());
+        for index in 0..100 {
+            manager.send_message("ktest", format!("message {}", index)).unwrap();
+        }
+        for index in 0..100 {
+            assert_eq!(manager.receive_message("ktest").unwrap(), format!("message {}", index));
+        }
+        assert_eq!(manager.try_receive_message("ktest"), Ok(None));
+        assert!(manager.send_message("missing", String::new()).is_err());
+    }
+
+    // On a scratch directory, as a loopback mount of an image would be
+    fn vxfs_loopback() {
+        let root = std::env::temp_dir().join(format!("vxtest-vxfs-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let path = root.join("file");
+        let path = path.to_str().unwrap();
+        let mut vxfs = VXFS::new();
+        vxfs.write_file(path, "hello").unwrap();
+        vxfs.append_bytes(path, b", world").unwrap();
+        assert_eq!(vxfs.read_file(path).unwrap(), "hello, world");
+        assert!(vxfs.verify_integrity(path).unwrap());
+        std::fs::write(path, "tampered").unwrap();
+        assert!(!vxfs.verify_integrity(path).unwrap());
+        assert_eq!(vxfs.list_dir(root.to_str().unwrap()).unwrap(), ["file"]);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    // The kernel's own suites; other subsystems bring theirs
+    pub fn cases() -> Vec<TestCase> {
+        vec![
+            TestCase { suite: "allocator", name: "large_and_small", run: allocator_large_and_small },
+            TestCase { suite: "allocator", name: "reuse", run: allocator_reuse },
+            TestCase { suite: "scheduler", name: "priority_order", run: scheduler_priority_order },
+            TestCase { suite: "vxchan", name: "round_trip", run: vxchan_round_trip },
+            TestCase { suite: "vxfs", name: "loopback", run: vxfs_loopback },
+        ]
+    }
+}
@@ -0,0 +1,313 @@
+// src/kernel/latency.rs
+
+pub mod latency {
+    use crate::metrics::metrics::{MetricType, Registry, Sample};
+    use crate::process::table::table::Tid;
+    use crate::sync::sync::{self, SpinLock};
+    use crate::vxboot::vxboot::CommandLine;
+    use std::cell::RefCell;
+    use std::collections::{BTreeMap, VecDeque};
+    use std::fmt::Write;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::{Duration, Instant};
+
+    // Recent latencies kept per stage for the percentiles
+    pub const SAMPLES: usize = 1024;
+
+    static TRACER: LatencyTracer = LatencyTracer::new();
+
+    thread_local! {
+        // Per CPU: the traced interrupts being handled, innermost last
+        static HANDLING: RefCell<Vec<(u8, Instant)>> = const { RefCell::new(Vec::new()) };
+    }
+
+    // The path from an interrupt to the task it was for running, in parts
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub enum Stage {
+        // Entry to the handler returning
+        Handler,
+        // Entry to the handler waking a task
+        Wakeup,
+        // The task woken to it running
+        Schedule,
+        // Entry to the task running
+        Total,
+    }
+
+    impl Stage {
+        pub const ALL: [Stage; 4] = [Stage::Handler, Stage::Wakeup, Stage::Schedule, Stage::Total];
+
+        pub fn name(&self) -> &'static str {
+            match self {
+                Stage::Handler => "handler",
+                Stage::Wakeup => "wakeup",
+                Stage::Schedule => "schedule",
+                Stage::Total => "total",
+            }
+        }
+    }
+
+    // Max and min are over everything since tracing started, percentiles
+    // over the last SAMPLES
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct LatencyStats {
+        pub count: u64,
+        pub min: Duration,
+        pub max: Duration,
+        pub p50: Duration,
+        pub p90: Duration,
+        pub p99: Duration,
+    }
+
+    #[derive(Default)]
+    struct Samples {
+        recent: VecDeque<Duration>,
+        count: u64,
+        min: Duration,
+        max: Duration,
+    }
+
+    impl Samples {
+        fn record(&mut self, latency: Duration) {
+            if self.recent.len() == SAMPLES {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(latency);
+            self.min = if self.count == 0 { latency } else { self.min.min(latency) };
+            self.max = self.max.max(latency);
+            self.count += 1;
+        }
+
+        fn stats(&self) -> LatencyStats {
+            let mut sorted: Vec<Duration> = self.recent.iter().copied().collect();
+            sorted.sort_unstable();
+            // Nearest rank
+            let percentile = |percent: usize| sorted.get((sorted.len() * percent).div_ceil(100).saturating_sub(1)).copied().unwrap_or_default();
+            LatencyStats { count: self.count, min: self.min, max: self.max, p50: percentile(50), p90: percentile(90), p99: percentile(99) }
+        }
+    }
+
+    struct Vector {
+        // Interrupt to task running, for the path to count as on time
+        deadline: Option<Duration>,
+        misses: u64,
+        stages: BTreeMap<Stage, Samples>,
+    }
+
+    // A task woken by a traced interrupt, not yet running
+    struct Woken {
+        vector: u8,
+        entry: Instant,
+        woken: Instant,
+    }
+
+    struct State {
+        vectors: BTreeMap<u8, Vector>,
+        woken: BTreeMap<Tid, Woken>,
+    }
+
+    // Times interrupts on selected vectors through to the tasks they wake
+    // running, so that the audio and input paths can be shown to meet
+    // their deadlines. Untraced vectors cost an atomic load.
+    pub struct LatencyTracer {
+        enabled: AtomicBool,
+        // Taken from interrupt handlers
+        state: SpinLock<State>,
+    }
+
+    impl Default for LatencyTracer {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl LatencyTracer {
+        pub const fn new() -> Self {
+            LatencyTracer { enabled: AtomicBool::new(false), state: SpinLock::new("latency.tracer", State { vectors: BTreeMap::new(), woken: BTreeMap::new() }) }
+        }
+
+        // Starts tracing a vector afresh
+        pub fn trace(&self, vector: u8, deadline: Option<Duration>) {
+            let mut state = self.state.lock_irqsave();
+            state.vectors.insert(vector, Vector { deadline, misses: 0, stages: BTreeMap::new() });
+            self.enabled.store(true, Ordering::Relaxed);
+        }
+
+        pub fn untrace(&self, vector: u8) {
+            let mut state = self.state.lock_irqsave();
+            state.vectors.remove(&vector);
+            state.woken.retain(|_, woken| woken.vector != vector);
+            self.enabled.store(!state.vectors.is_empty(), Ordering::Relaxed);
+        }
+
+        pub fn traced(&self) -> Vec<u8> {
+            self.state.lock_irqsave().vectors.keys().copied().collect()
+        }
+
+        // latency_trace=vector[:deadline],... with vectors in decimal or
+        // 0x hex and deadlines in microseconds; returns how many are traced
+        pub fn configure_from_command_line(&self, command_line: &CommandLine) -> Result<usize, &'static str> {
+            let Some(setting) = command_line.get("latency_trace") else {
+                return Ok(0);
+            };
+            let mut vectors = Vec::new();
+            for entry in setting.split(',').filter(|entry| !entry.is_empty()) {
+                let (vector, deadline) = match entry.split_once(':') {
+                    Some((vector, deadline)) => (vector, Some(Duration::from_micros(deadline.parse().map_err(|_| "Invalid latency deadline")?))),
+                    None => (entry, None),
+                };
+                let vector = match vector.strip_prefix("0x") {
+                    Some(hex) => u8::from_str_radix(hex, 16),
+                    None => vector.parse(),
+                };
+                vectors.push((vector.map_err(|_| "Invalid interrupt vector")?, deadline));
+            }
+            for (vector, deadline) in &vectors {
+                self.trace(*vector, *deadline);
+                log::info!("Tracing interrupt latency on vector {:#x}", vector);
+            }
+            Ok(vectors.len())
+        }
+
+        fn is_traced(&self, vector: u8) -> bool {
+            self.enabled.load(Ordering::Relaxed) && self.state.lock_irqsave().vectors.contains_key(&vector)
+        }
+
+        // From interrupt entry, with interrupts off
+        pub fn irq_entry(&self, vector: u8, now: Instant) {
+            if self.is_traced(vector) {
+                HANDLING.with(|handling| handling.borrow_mut().push((vector, now)));
+            }
+        }
+
+        // As the handler returns
+        pub fn irq_exit(&self, vector: u8, now: Instant) {
+            let Some(entry) = HANDLING.with(|handling| {
+                let mut handling = handling.borrow_mut();
+                let index = handling.iter().rposition(|(handled, _)| *handled == vector)?;
+                Some(handling.remove(index).1)
+            }) else {
+                return;
+            };
+            let mut state = self.state.lock_irqsave();
+            if let Some(traced) = state.vectors.get_mut(&vector) {
+                traced.stages.entry(Stage::Handler).or_default().record(now.saturating_duration_since(entry));
+            }
+        }
+
+        // Runs an interrupt handler as sync::hardirq does, timing it when
+        // its vector is traced
+        pub fn irq<T>(&self, vector: u8, handler: impl FnOnce() -> T) -> T {
+            if !self.enabled.load(Ordering::Relaxed) {
+                return sync::hardirq(handler);
+            }
+            self.irq_entry(vector, Instant::now());
+            let result = sync::hardirq(handler);
+            self.irq_exit(vector, Instant::now());
+            result
+        }
+
+        // A task being made runnable. Only wakeups from a traced handler
+        // count, charged to the innermost one.
+        pub fn wakeup(&self, tid: Tid, now: Instant) {
+            if !self.enabled.load(Ordering::Relaxed) {
+                return;
+            }
+            let Some((vector, entry)) = HANDLING.with(|handling| handling.borrow().last().copied()) else {
+                return;
+            };
+            let mut state = self.state.lock_irqsave();
+            if let Some(traced) = state.vectors.get_mut(&vector) {
+                traced.stages.entry(Stage::Wakeup).or_default().record(now.saturating_duration_since(entry));
+                state.woken.insert(tid, Woken { vector, entry, woken: now });
+            }
+        }
+
+        // From the scheduler as it switches to a task
+        pub fn run(&self, tid: Tid, now: Instant) {
+            if !self.enabled.load(Ordering::Relaxed) {
+                return;
+            }
+            let mut state = self.state.lock_irqsave();
+            let Some(woken) = state.woken.remove(&tid) else {
+                return;
+            };
+            let Some(traced) = state.vectors.get_mut(&woken.vector) else {
+                return;
+            };
+            let total = now.saturating_duration_since(woken.entry);
+            traced.stages.entry(Stage::Schedule).or_default().record(now.saturating_duration_since(woken.woken));
+            traced.stages.entry(Stage::Total).or_default().record(total);
+            if traced.deadline.is_some_and(|deadline| total > deadline) {
+                traced.misses += 1;
+                log::warn!("Vector {:#x} missed its deadline: task {} ran {}us after the interrupt", woken.vector, tid, total.as_micros());
+            }
+        }
+
+        pub fn stats(&self, vector: u8, stage: Stage) -> Option<LatencyStats> {
+            let state = self.state.lock_irqsave();
+            let traced = state.vectors.get(&vector)?;
+            Some(traced.stages.get(&stage).map(Samples::stats).unwrap_or_default())
+        }
+
+        // Paths that took longer than the vector's deadline
+        pub fn misses(&self, vector: u8) -> Option<u64> {
+            self.state.lock_irqsave().vectors.get(&vector).map(|traced| traced.misses)
+        }
+
+        // A line per traced vector and stage seen, in microseconds
+        pub fn report(&self) -> String {
+            let mut report = format!("{:<8}{:<10}{:>10}{:>10}{:>10}{:>10}{:>10}{:>10}\n", "vector", "stage", "count", "min", "p50", "p90", "p99", "max");
+            let state = self.state.lock_irqsave();
+            for (vector, traced) in &state.vectors {
+                for (stage, samples) in &traced.stages {
+                    let stats = samples.stats();
+                    writeln!(
+                        report,
+                        "{:<8}{:<10}{:>10}{:>10}{:>10}{:>10}{:>10}{:>10}",
+                        format!("{:#x}", vector),
+                        stage.name(),
+                        stats.count,
+                        stats.min.as_micros(),
+                        stats.p50.as_micros(),
+                        stats.p90.as_micros(),
+                        stats.p99.as_micros(),
+                        stats.max.as_micros()
+                    )
+                    .unwrap();
+                }
+                if let Some(deadline) = traced.deadline {
+                    writeln!(report, "{:<8}deadline {}us, missed {} times", format!("{:#x}", vector), deadline.as_micros(), traced.misses).unwrap();
+                }
+            }
+            report
+        }
+
+        // Worst and 99th percentile latencies, and deadline misses
+        pub fn register_metrics(&'static self, registry: &Registry) -> Result<(), &'static str> {
+            let samples = move |stat: fn(&LatencyStats) -> Duration| {
+                move || {
+                    let state = self.state.lock_irqsave();
+                    let mut samples = Vec::new();
+                    for (vector, traced) in &state.vectors {
+                        for (stage, recorded) in &traced.stages {
+                            samples.push(Sample::new(stat(&recorded.stats()).as_secs_f64()).with("vector", &vector.to_string()).with("stage", stage.name()));
+                        }
+                    }
+                    samples
+                }
+            };
+            registry.collect("vaelix_irq_latency_max_seconds", "Worst interrupt latency seen, by stage.", MetricType::Gauge, samples(|stats| stats.max))?;
+            registry.collect("vaelix_irq_latency_p99_seconds", "99th percentile of recent interrupt latencies, by stage.", MetricType::Gauge, samples(|stats| stats.p99))?;
+            registry.collect("vaelix_irq_deadline_misses_total", "Interrupts whose task ran after the deadline.", MetricType::Counter, move || {
+                let state = self.state.lock_irqsave();
+                state.vectors.iter().filter(|(_, traced)| traced.deadline.is_some()).map(|(vector, traced)| Sample::new(traced.misses as f64).with("vector", &vector.to_string())).collect()
+            })
+        }
+    }
+
+    // The kernel's own tracer
+    pub fn tracer() -> &'static LatencyTracer {
+        &TRACER
+    }
+}
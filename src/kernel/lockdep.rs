@@ -0,0 +1,207 @@
+// src/kernel/lockdep.rs
+
+pub mod lockdep {
+    use crate::kallsyms::kallsyms;
+    use crate::rcu::rcu;
+    use crate::sync::sync;
+    use std::cell::RefCell;
+    use std::collections::{BTreeMap, VecDeque};
+    use std::fmt::Write;
+    use std::sync::Mutex;
+
+    // Every acquisition takes a backtrace, so only debug builds check
+    pub const ENABLED: bool = cfg!(debug_assertions);
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum LockKind {
+        Spin,
+        Sleeping,
+    }
+
+    struct Held {
+        class: &'static str,
+        id: usize,
+        kind: LockKind,
+        stack: Vec<u64>,
+    }
+
+    // What has been seen of each lock class since boot, with where it was
+    // first seen
+    struct Graph {
+        // Classes taken while holding a class
+        after: BTreeMap<&'static str, BTreeMap<&'static str, Vec<u64>>>,
+        in_irq: BTreeMap<&'static str, Vec<u64>>,
+        irqs_on: BTreeMap<&'static str, Vec<u64>>,
+    }
+
+    static GRAPH: Mutex<Graph> = Mutex::new(Graph { after: BTreeMap::new(), in_irq: BTreeMap::new(), irqs_on: BTreeMap::new() });
+
+    thread_local! {
+        // Locks this CPU holds, in the order they were taken
+        static HELD: RefCell<Vec<Held>> = const { RefCell::new(Vec::new()) };
+    }
+
+    impl Graph {
+        // The shortest chain of dependencies from one class to another
+        fn path(&self, from: &'static str, to: &'static str) -> Option<Vec<(&'static str, &'static str)>> {
+            let mut previous: BTreeMap<&'static str, &'static str> = BTreeMap::new();
+            let mut queue = VecDeque::from([from]);
+            while let Some(class) = queue.pop_front() {
+                if class == to {
+                    let mut path = Vec::new();
+                    let mut class = to;
+                    while class != from {
+                        let before = previous[class];
+                        path.push((before, class));
+                        class = before;
+                    }
+                    path.reverse();
+                    return Some(path);
+                }
+                for next in self.after.get(class).into_iter().flat_map(|after| after.keys()) {
+                    if *next != from && !previous.contains_key(next) {
+                        previous.insert(next, class);
+                        queue.push_back(next);
+                    }
+                }
+            }
+            None
+        }
+    }
+
+    fn write_stack(report: &mut String, title: &str, stack: &[u64]) {
+        writeln!(report, "{}:", title).unwrap();
+        for frame in stack {
+            writeln!(report, "    {}", kallsyms::symbolize(*frame)).unwrap();
+        }
+    }
+
+    // A report of what is wrong with taking this lock now, if anything
+    fn check(class: &'static str, kind: LockKind, stack: &[u64]) -> Option<String> {
+        let mut report = String::new();
+        let held: Vec<(&'static str, LockKind, Vec<u64>)> = HELD.with(|held| held.borrow().iter().map(|lock| (lock.class, lock.kind, lock.stack.clone())).collect());
+        let (in_irq, irqs_on) = (sync::in_interrupt(), !sync::irqs_disabled());
+
+        if kind == LockKind::Sleeping && !irqs_on {
+            let context = if in_irq { "in an interrupt handler" } else { "with interrupts disabled" };
+            writeln!(report, "lockdep: sleeping lock {} taken {}", class, context).unwrap();
+            write_stack(&mut report, "this acquisition", stack);
+            return Some(report);
+        }
+        if kind == LockKind::Sleeping && rcu::in_read_section() {
+            writeln!(report, "lockdep: sleeping lock {} taken in an RCU read-side critical section", class).unwrap();
+            write_stack(&mut report, "this acquisition", stack);
+            return Some(report);
+        }
+        if let Some((_, _, first)) = held.iter().find(|(holder, _, _)| *holder == class) {
+            writeln!(report, "lockdep: possible recursive locking of {}", class).unwrap();
+            write_stack(&mut report, "this acquisition", stack);
+            write_stack(&mut report, "already held since", first);
+            return Some(report);
+        }
+        if let Some((holder, _, first)) = held.iter().find(|(_, kind, _)| *kind == LockKind::Spin).filter(|_| kind == LockKind::Sleeping) {
+            writeln!(report, "lockdep: sleeping lock {} taken while holding spinlock {}", class, holder).unwrap();
+            write_stack(&mut report, "this acquisition", stack);
+            write_stack(&mut report, &format!("{} held since", holder), first);
+            return Some(report);
+        }
+
+        let mut graph = GRAPH.lock().unwrap();
+        // A lock an interrupt handler takes must never be held with
+        // interrupts on, or the handler can spin on its own CPU forever
+        let inconsistent = match (in_irq, irqs_on) {
+            (true, _) => graph.irqs_on.get(class).map(|first| ("taken with interrupts enabled", first)),
+            (false, true) => graph.in_irq.get(class).map(|first| ("taken in an interrupt handler", first)),
+            (false, false) => None,
+        };
+        if let Some((usage, first)) = inconsistent {
+            let context = if in_irq { "in an interrupt handler" } else { "with interrupts enabled" };
+            writeln!(report, "lockdep: inconsistent lock state: {} taken {}, but also {}", class, context, usage).unwrap();
+            write_stack(&mut report, "this acquisition", stack);
+            write_stack(&mut report, &format!("{} at", usage), first);
+            return Some(report);
+        }
+
+        for (holder, _, first) in &held {
+            if graph.after.get(holder).is_some_and(|after| after.contains_key(class)) {
+                continue;
+            }
+            if let Some(path) = graph.path(class, holder) {
+                writeln!(report, "lockdep: possible circular locking taking {} while holding {}", class, holder).unwrap();
+                let chain: Vec<&str> = std::iter::once(class).chain(path.iter().map(|(_, next)| *next)).collect();
+                writeln!(report, "{} was already taken after {}: {}", holder, class, chain.join(" -> ")).unwrap();
+                write_stack(&mut report, "this acquisition", stack);
+                write_stack(&mut report, &format!("{} held since", holder), first);
+                for (before, after) in path {
+                    write_stack(&mut report, &format!("{} -> {} first at", before, after), &graph.after[before][after]);
+                }
+                return Some(report);
+            }
+            // Held with interrupts off, B could wait on a CPU that took it
+            // with them on and is now stuck in the handler wanting A
+            if let (Some(safe), Some(unsafe_at)) = (graph.in_irq.get(holder), graph.irqs_on.get(class)) {
+                writeln!(report, "lockdep: interrupt-unsafe lock {} taken while holding interrupt-safe {}", class, holder).unwrap();
+                write_stack(&mut report, "this acquisition", stack);
+                write_stack(&mut report, &format!("{} taken in an interrupt handler at", holder), safe);
+                write_stack(&mut report, &format!("{} taken with interrupts enabled at", class), unsafe_at);
+                return Some(report);
+            }
+        }
+
+        for (holder, _, _) in &held {
+            graph.after.entry(holder).or_default().entry(class).or_insert_with(|| stack.to_vec());
+        }
+        if in_irq {
+            graph.in_irq.entry(class).or_insert_with(|| stack.to_vec());
+        } else if irqs_on {
+            graph.irqs_on.entry(class).or_insert_with(|| stack.to_vec());
+        }
+        None
+    }
+
+    // Before a lock is waited for; panics with the stacks involved when
+    // waiting could deadlock
+    pub fn acquire(class: &'static str, id: usize, kind: LockKind) {
+        if !ENABLED {
+            return;
+        }
+        let stack = kallsyms::capture_backtrace();
+        if let Some(report) = check(class, kind, &stack) {
+            panic!("{}", report);
+        }
+        HELD.with(|held| held.borrow_mut().push(Held { class, id, kind, stack }));
+    }
+
+    // After a lock is taken without waiting
+    pub fn acquire_try(class: &'static str, id: usize, kind: LockKind) {
+        if !ENABLED {
+            return;
+        }
+        let stack = kallsyms::capture_backtrace();
+        HELD.with(|held| held.borrow_mut().push(Held { class, id, kind, stack }));
+    }
+
+    // Locks can be dropped in any order
+    pub fn release(id: usize) {
+        if !ENABLED {
+            return;
+        }
+        let _ = HELD.try_with(|held| {
+            let mut held = held.borrow_mut();
+            if let Some(index) = held.iter().rposition(|lock| lock.id == id) {
+                held.remove(index);
+            }
+        });
+    }
+
+    // Classes this CPU holds, outermost first
+    pub fn held() -> Vec<&'static str> {
+        HELD.with(|held| held.borrow().iter().map(|lock| lock.class).collect())
+    }
+
+    // Each ordering seen: the class held, then the one taken under it
+    pub fn dependencies() -> Vec<(&'static str, &'static str)> {
+        let graph = GRAPH.lock().unwrap();
+        graph.after.iter().flat_map(|(before, after)| after.keys().map(move |after| (*before, *after))).collect()
+    }
+}
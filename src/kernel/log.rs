@@ -0,0 +1,364 @@
+// src/kernel/log.rs
+
+pub mod log {
+    use crate::crashdump::crashdump;
+    use crate::drivers::uart::uart::SerialPort;
+    use crate::pty::pty::PtySlave;
+    use crate::vxchan::vxchan::VXChanManager;
+    use crate::vxfs::vxfs::VXFS;
+    use ::log::{Level, LevelFilter, Metadata};
+    use std::collections::{BTreeMap, VecDeque};
+    use std::fmt;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Mutex, OnceLock};
+    use std::time::Instant;
+
+    pub const REQUEST_CHANNEL: &str = "vxlog";
+    pub const REPLY_CHANNEL: &str = "vxlog.reply";
+    // Records kept for dmesg, oldest dropped first
+    pub const LOG_ENTRIES: usize = 4096;
+    pub const DEFAULT_LEVEL: LevelFilter = LevelFilter::Info;
+
+    static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+    // Nanoseconds since the kernel started, as logs and crash dumps stamp
+    // their entries
+    pub fn uptime() -> u64 {
+        static BOOT: OnceLock<Instant> = OnceLock::new();
+        BOOT.get_or_init(Instant::now).elapsed().as_nanos() as u64
+    }
+
+    // Module paths as targets lose the crate and the module each file
+    // wraps itself in: vaelix_core::drivers::uart::uart is drivers::uart
+    fn short_target(target: &str) -> &str {
+        let target = match target.split_once("::") {
+            Some((krate, rest)) if krate.starts_with("vaelix_") => rest,
+            _ => target,
+        };
+        match target.rsplit_once("::") {
+            Some((head, last)) if head == last || head.ends_with(&format!("::{}", last)) => head,
+            _ => target,
+        }
+    }
+
+    fn parse_level(text: &str) -> Result<LevelFilter, &'static str> {
+        text.parse().map_err(|_| "Unknown log level")
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Record {
+        // Numbered from 0 since boot, so readers can pick up where they
+        // left off
+        pub sequence: u64,
+        // Nanoseconds since boot
+        pub time: u64,
+        pub level: Level,
+        pub target: String,
+        pub message: String,
+    }
+
+    impl fmt::Display for Record {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            let micros = self.time / 1000;
+            write!(f, "[{:5}.{:06}] {:<5} {}: {}", micros / 1_000_000, micros % 1_000_000, self.level, self.target, self.message)
+        }
+    }
+
+    // Somewhere records go as they are logged. Sinks must not log
+    // themselves, as the logger is busy with them.
+    pub trait Sink: Send {
+        fn write(&mut self, record: &Record) -> Result<(), &'static str>;
+    }
+
+    pub struct SerialSink<S: SerialPort + Send> {
+        serial: S,
+    }
+
+    impl<S: SerialPort + Send> SerialSink<S> {
+        pub fn new(serial: S) -> Self {
+            SerialSink { serial }
+        }
+    }
+
+    impl<S: SerialPort + Send> Sink for SerialSink<S> {
+        fn write(&mut self, record: &Record) -> Result<(), &'static str> {
+            self.serial.write_all(format!("{}\r\n", record).as_bytes())
+        }
+    }
+
+    // The text console, written to as any program on it would
+    pub struct ConsoleSink {
+        console: PtySlave,
+    }
+
+    impl ConsoleSink {
+        pub fn new(console: PtySlave) -> Self {
+            ConsoleSink { console }
+        }
+    }
+
+    impl Sink for ConsoleSink {
+        fn write(&mut self, record: &Record) -> Result<(), &'static str> {
+            self.console.write(format!("{}\n", record).as_bytes())
+        }
+    }
+
+    // Appends to a file, once the filesystem holding it is mounted
+    pub struct FileSink {
+        vxfs: VXFS,
+        path: String,
+    }
+
+    impl FileSink {
+        pub fn new(path: &str) -> Self {
+            FileSink { vxfs: VXFS::new(), path: path.to_string() }
+        }
+    }
+
+    impl Sink for FileSink {
+        fn write(&mut self, record: &Record) -> Result<(), &'static str> {
+            self.vxfs.append_bytes(&self.path, format!("{}\n", record).as_bytes()).map_err(|_| "Failed to write log file")
+        }
+    }
+
+    struct SinkEntry {
+        name: String,
+        level: LevelFilter,
+        sink: Box<dyn Sink>,
+        failures: u64,
+    }
+
+    // Keeps the most recent records in memory and passes each to the
+    // sinks that want it. Records below their target's level are dropped
+    // before they are formatted.
+    pub struct Logger {
+        records: Mutex<VecDeque<Record>>,
+        capacity: usize,
+        next_sequence: AtomicU64,
+        default_level: Mutex<LevelFilter>,
+        // By target prefix, the longest match winning: drivers covers
+        // drivers::uart
+        targets: Mutex<BTreeMap<String, LevelFilter>>,
+        sinks: Mutex<Vec<SinkEntry>>,
+    }
+
+    impl Logger {
+        pub fn new(capacity: usize) -> Self {
+            Logger {
+                records: Mutex::new(VecDeque::new()),
+                capacity,
+                next_sequence: AtomicU64::new(0),
+                default_level: Mutex::new(DEFAULT_LEVEL),
+                targets: Mutex::new(BTreeMap::new()),
+                sinks: Mutex::new(Vec::new()),
+            }
+        }
+
+        pub fn level(&self, target: &str) -> LevelFilter {
+            let targets = self.targets.lock().unwrap();
+            let found = targets
+                .iter()
+                .filter(|(prefix, _)| target == prefix.as_str() || target.strip_prefix(prefix.as_str()).is_some_and(|rest| rest.starts_with("::")))
+                .max_by_key(|(prefix, _)| prefix.len());
+            found.map_or_else(|| *self.default_level.lock().unwrap(), |(_, level)| *level)
+        }
+
+        pub fn set_level(&self, level: LevelFilter) {
+            *self.default_level.lock().unwrap() = level;
+        }
+
+        // None goes back to the default level
+        pub fn set_target_level(&self, target: &str, level: Option<LevelFilter>) {
+            let mut targets = self.targets.lock().unwrap();
+            match level {
+                Some(level) => targets.insert(target.to_string(), level),
+                None => targets.remove(target),
+            };
+        }
+
+        pub fn target_levels(&self) -> Vec<(String, LevelFilter)> {
+            self.targets.lock().unwrap().iter().map(|(target, level)| (target.clone(), *level)).collect()
+        }
+
+        // Returns the record's sequence number, or None if it was
+        // filtered out
+        pub fn record(&self, level: Level, target: &str, message: &str) -> Option<u64> {
+            let target = short_target(target);
+            if level > self.level(target) {
+                return None;
+            }
+            let record = Record {
+                sequence: self.next_sequence.fetch_add(1, Ordering::Relaxed),
+                time: uptime(),
+                level,
+                target: target.to_string(),
+                message: message.to_string(),
+            };
+            if level <= Level::Info {
+                crashdump::trace(&format!("{}: {}", record.target, record.message));
+            }
+            for entry in self.sinks.lock().unwrap().iter_mut().filter(|entry| level <= entry.level) {
+                if entry.sink.write(&record).is_err() {
+                    entry.failures += 1;
+                }
+            }
+            let mut records = self.records.lock().unwrap();
+            if records.len() == self.capacity {
+                records.pop_front();
+            }
+            let sequence = record.sequence;
+            records.push_back(record);
+            Some(sequence)
+        }
+
+        // Records from sequence number since on, at level or above
+        pub fn read(&self, since: u64, level: LevelFilter) -> Vec<Record> {
+            let records = self.records.lock().unwrap();
+            records.iter().filter(|record| record.sequence >= since && record.level <= level).cloned().collect()
+        }
+
+        pub fn clear(&self) {
+            self.records.lock().unwrap().clear();
+        }
+
+        // The sink first gets what is already in the buffer, so a console
+        // or file set up late still shows the boot
+        pub fn add_sink(&self, name: &str, level: LevelFilter, mut sink: Box<dyn Sink>) -> Result<(), &'static str> {
+            let mut sinks = self.sinks.lock().unwrap();
+            if sinks.iter().any(|entry| entry.name == name) {
+                return Err("Log sink already exists");
+            }
+            let mut failures = 0;
+            for record in self.read(0, level) {
+                if sink.write(&record).is_err() {
+                    failures += 1;
+                }
+            }
+            sinks.push(SinkEntry { name: name.to_string(), level, sink, failures });
+            Ok(())
+        }
+
+        pub fn remove_sink(&self, name: &str) -> Result<(), &'static str> {
+            let mut sinks = self.sinks.lock().unwrap();
+            let index = sinks.iter().position(|entry| entry.name == name).ok_or("No such log sink")?;
+            sinks.remove(index);
+            Ok(())
+        }
+
+        // Name, level and how many records failed to go out
+        pub fn sinks(&self) -> Vec<(String, LevelFilter, u64)> {
+            self.sinks.lock().unwrap().iter().map(|entry| (entry.name.clone(), entry.level, entry.failures)).collect()
+        }
+
+        // One request per message:
+        //   dmesg [LEVEL] [since SEQ] | level TARGET LEVEL|default |
+        //   levels | sinks | clear
+        // dmesg gives one record per line after its sequence number; a
+        // TARGET of * sets the default level.
+        pub fn execute(&self, line: &str) -> Result<String, &'static str> {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words[..] {
+                ["dmesg", ref rest @ ..] => {
+                    let (level, rest) = match rest {
+                        [level, rest @ ..] if *level != "since" => (parse_level(level)?, rest),
+                        _ => (LevelFilter::Trace, rest),
+                    };
+                    let since = match rest {
+                        [] => 0,
+                        ["since", sequence] => sequence.parse().map_err(|_| "Invalid sequence number")?,
+                        _ => return Err("Usage: dmesg [LEVEL] [since SEQ]"),
+                    };
+                    Ok(self.read(since, level).iter().map(|record| format!("{} {}", record.sequence, record)).collect::<Vec<_>>().join("\n"))
+                }
+                ["level", "*", level] => {
+                    self.set_level(parse_level(level)?);
+                    Ok(String::new())
+                }
+                ["level", target, "default"] => {
+                    self.set_target_level(target, None);
+                    Ok(String::new())
+                }
+                ["level", target, level] => {
+                    self.set_target_level(target, Some(parse_level(level)?));
+                    Ok(String::new())
+                }
+                ["levels"] => {
+                    let default = format!("* {}", self.default_level.lock().unwrap());
+                    let targets = self.target_levels().into_iter().map(|(target, level)| format!("{} {}", target, level));
+                    Ok(std::iter::once(default).chain(targets).collect::<Vec<_>>().join("\n"))
+                }
+                ["sinks"] => Ok(self.sinks().iter().map(|(name, level, failures)| format!("{} {} {}", name, level, failures)).collect::<Vec<_>>().join("\n")),
+                ["clear"] => {
+                    self.clear();
+                    Ok(String::new())
+                }
+                [] => Err("Empty request"),
+                _ => Err("Unknown command"),
+            }
+        }
+    }
+
+    impl ::log::Log for Logger {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.level() <= self.level(short_target(metadata.target()))
+        }
+
+        fn log(&self, record: &::log::Record) {
+            self.record(record.level(), record.target(), &record.args().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    // Makes the kernel logger the one the log macros go to. Anything
+    // logged before this is lost, so the boot does it first.
+    pub fn init() -> &'static Logger {
+        let mut installed = false;
+        let logger = LOGGER.get_or_init(|| {
+            installed = true;
+            Logger::new(LOG_ENTRIES)
+        });
+        if installed && ::log::set_logger(logger).is_ok() {
+            // Filtering is per target, so everything has to reach it
+            ::log::set_max_level(LevelFilter::Trace);
+        }
+        logger
+    }
+
+    pub fn logger() -> Option<&'static Logger> {
+        LOGGER.get()
+    }
+
+    // Answers dmesg and log level changes. Messages and replies are framed
+    // as in vxnetctl.
+    pub struct LogService;
+
+    impl LogService {
+        pub fn new(manager: &VXChanManager) -> Result<Self, &'static str> {
+            manager.create_channel(REQUEST_CHANNEL)?;
+            manager.create_channel(REPLY_CHANNEL)?;
+            Ok(LogService)
+        }
+
+        // Answers every queued request; returns how many were handled
+        pub fn poll(&self, manager: &VXChanManager, logger: &Logger) -> Result<usize, &'static str> {
+            let mut count = 0;
+            while let Some(message) = manager.try_receive_message(REQUEST_CHANNEL)? {
+                let (id, line) = message.split_once(' ').unwrap_or((message.as_str(), ""));
+                let result = id.parse::<u64>().map_err(|_| "Invalid request id").and_then(|_| logger.execute(line));
+                let reply = match result {
+                    Ok(body) if body.is_empty() => format!("{} ok", id),
+                    Ok(body) => format!("{} ok\n{}", id, body),
+                    Err(error) => format!("{} error {}", id, error),
+                };
+                manager.send_message(REPLY_CHANNEL, reply)?;
+                count += 1;
+            }
+            Ok(count)
+        }
+    }
+
+    pub fn send_request(manager: &VXChanManager, id: u64, request: &str) -> Result<(), &'static str> {
+        manager.send_message(REQUEST_CHANNEL, format!("{} {}", id, request))
+    }
+}
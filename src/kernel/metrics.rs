@@ -0,0 +1,350 @@
+// src/kernel/metrics.rs
+
+pub mod metrics {
+    use crate::hardening::hardening;
+    use crate::log::log::uptime;
+    use crate::sync::sync::Mutex;
+    use std::collections::BTreeMap;
+    use std::fmt::Write;
+    use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+    use std::sync::{Arc, OnceLock};
+
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+
+    // Only ever goes up, until a reboot
+    #[derive(Debug, Default)]
+    pub struct Counter(AtomicU64);
+
+    impl Counter {
+        pub const fn new() -> Self {
+            Counter(AtomicU64::new(0))
+        }
+
+        pub fn inc(&self) {
+            self.add(1);
+        }
+
+        pub fn add(&self, amount: u64) {
+            self.0.fetch_add(amount, Ordering::Relaxed);
+        }
+
+        pub fn get(&self) -> u64 {
+            self.0.load(Ordering::Relaxed)
+        }
+    }
+
+    #[derive(Debug, Default)]
+    pub struct Gauge(AtomicI64);
+
+    impl Gauge {
+        pub const fn new() -> Self {
+            Gauge(AtomicI64::new(0))
+        }
+
+        pub fn set(&self, value: i64) {
+            self.0.store(value, Ordering::Relaxed);
+        }
+
+        pub fn add(&self, amount: i64) {
+            self.0.fetch_add(amount, Ordering::Relaxed);
+        }
+
+        pub fn sub(&self, amount: i64) {
+            self.0.fetch_sub(amount, Ordering::Relaxed);
+        }
+
+        pub fn get(&self) -> i64 {
+            self.0.load(Ordering::Relaxed)
+        }
+    }
+
+    // Observations counted into buckets by upper bound, e.g. latencies in
+    // microseconds. Anything over the last bound is only in the total.
+    #[derive(Debug)]
+    pub struct Histogram {
+        bounds: Vec<u64>,
+        buckets: Vec<AtomicU64>,
+        sum: AtomicU64,
+        count: AtomicU64,
+    }
+
+    impl Histogram {
+        pub fn new(bounds: &[u64]) -> Self {
+            let mut bounds = bounds.to_vec();
+            bounds.sort_unstable();
+            bounds.dedup();
+            let buckets = bounds.iter().map(|_| AtomicU64::new(0)).collect();
+            Histogram { bounds, buckets, sum: AtomicU64::new(0), count: AtomicU64::new(0) }
+        }
+
+        pub fn observe(&self, value: u64) {
+            let bucket = self.bounds.partition_point(|bound| *bound < value);
+            if let Some(bucket) = self.buckets.get(bucket) {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+            self.sum.fetch_add(value, Ordering::Relaxed);
+            self.count.fetch_add(1, Ordering::Relaxed);
+        }
+
+        pub fn count(&self) -> u64 {
+            self.count.load(Ordering::Relaxed)
+        }
+
+        pub fn sum(&self) -> u64 {
+            self.sum.load(Ordering::Relaxed)
+        }
+
+        // Each bound with how many observations were at most it
+        pub fn cumulative(&self) -> Vec<(u64, u64)> {
+            let mut total = 0;
+            self.bounds
+                .iter()
+                .zip(&self.buckets)
+                .map(|(bound, bucket)| {
+                    total += bucket.load(Ordering::Relaxed);
+                    (*bound, total)
+                })
+                .collect()
+        }
+    }
+
+    // count bounds from start, each factor times the one before
+    pub fn exponential_buckets(start: u64, factor: u64, count: usize) -> Vec<u64> {
+        std::iter::successors(Some(start.max(1)), |bound| bound.checked_mul(factor.max(2))).take(count).collect()
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum MetricType {
+        Counter,
+        Gauge,
+        Histogram,
+    }
+
+    impl MetricType {
+        fn name(&self) -> &'static str {
+            match self {
+                MetricType::Counter => "counter",
+                MetricType::Gauge => "gauge",
+                MetricType::Histogram => "histogram",
+            }
+        }
+    }
+
+    // One value of a family a subsystem reports when scraped
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Sample {
+        pub labels: Vec<(String, String)>,
+        pub value: f64,
+    }
+
+    impl Sample {
+        pub fn new(value: f64) -> Self {
+            Sample { labels: Vec::new(), value }
+        }
+
+        pub fn with(mut self, name: &str, value: &str) -> Self {
+            self.labels.push((name.to_string(), value.to_string()));
+            self
+        }
+    }
+
+    type Collector = Box<dyn Fn() -> Vec<Sample> + Send + Sync>;
+
+    enum Member {
+        Counter(Vec<(String, String)>, Arc<Counter>),
+        Gauge(Vec<(String, String)>, Arc<Gauge>),
+        Histogram(Vec<(String, String)>, Arc<Histogram>),
+        // Read from state the subsystem keeps anyway, when scraped
+        Collector(Collector),
+    }
+
+    impl Member {
+        fn labels(&self) -> Option<&[(String, String)]> {
+            match self {
+                Member::Counter(labels, _) | Member::Gauge(labels, _) | Member::Histogram(labels, _) => Some(labels),
+                Member::Collector(_) => None,
+            }
+        }
+    }
+
+    struct Family {
+        help: String,
+        kind: MetricType,
+        members: Vec<Member>,
+    }
+
+    fn valid_name(name: &str, colons: bool) -> bool {
+        let mut characters = name.chars();
+        let valid = |character: char, first: bool| character == '_' || (colons && character == ':') || character.is_ascii_alphabetic() || (!first && character.is_ascii_digit());
+        characters.next().is_some_and(|first| valid(first, true)) && characters.all(|character| valid(character, false))
+    }
+
+    fn escape(text: &str, quotes: bool) -> String {
+        let mut escaped = String::with_capacity(text.len());
+        for character in text.chars() {
+            match character {
+                '\\' => escaped.push_str("\\\\"),
+                '\n' => escaped.push_str("\\n"),
+                '"' if quotes => escaped.push_str("\\\""),
+                _ => escaped.push(character),
+            }
+        }
+        escaped
+    }
+
+    fn write_labels(output: &mut String, labels: &[(String, String)], extra: Option<(&str, &str)>) {
+        let labels: Vec<String> = labels
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .chain(extra)
+            .map(|(name, value)| format!("{}=\"{}\"", name, escape(value, true)))
+            .collect();
+        if !labels.is_empty() {
+            write!(output, "{{{}}}", labels.join(",")).unwrap();
+        }
+    }
+
+    fn format_value(value: f64) -> String {
+        match value {
+            _ if value.is_nan() => "NaN".to_string(),
+            f64::INFINITY => "+Inf".to_string(),
+            f64::NEG_INFINITY => "-Inf".to_string(),
+            _ => value.to_string(),
+        }
+    }
+
+    // Metrics subsystems register, read by scraping /proc/metrics or the
+    // network exporter in the Prometheus text format
+    pub struct Registry {
+        families: Mutex<BTreeMap<String, Family>>,
+    }
+
+    impl Default for Registry {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl Registry {
+        pub fn new() -> Self {
+            Registry { families: Mutex::new("metrics.registry", BTreeMap::new()) }
+        }
+
+        fn add(&self, name: &str, help: &str, kind: MetricType, member: Member) -> Result<(), &'static str> {
+            if !valid_name(name, true) {
+                return Err("Invalid metric name");
+            }
+            if member.labels().unwrap_or_default().iter().any(|(label, _)| !valid_name(label, false) || label.starts_with("__") || label == "le") {
+                return Err("Invalid metric label");
+            }
+            let mut families = self.families.lock();
+            let family = families.entry(name.to_string()).or_insert_with(|| Family { help: help.to_string(), kind, members: Vec::new() });
+            if family.kind != kind {
+                return Err("Metric registered with another type");
+            }
+            if member.labels().is_some() && family.members.iter().any(|existing| existing.labels() == member.labels()) {
+                return Err("Metric already registered");
+            }
+            family.members.push(member);
+            Ok(())
+        }
+
+        fn labels(labels: &[(&str, &str)]) -> Vec<(String, String)> {
+            labels.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+        }
+
+        pub fn counter(&self, name: &str, help: &str, labels: &[(&str, &str)]) -> Result<Arc<Counter>, &'static str> {
+            let counter = Arc::new(Counter::new());
+            self.add(name, help, MetricType::Counter, Member::Counter(Self::labels(labels), counter.clone()))?;
+            Ok(counter)
+        }
+
+        pub fn gauge(&self, name: &str, help: &str, labels: &[(&str, &str)]) -> Result<Arc<Gauge>, &'static str> {
+            let gauge = Arc::new(Gauge::new());
+            self.add(name, help, MetricType::Gauge, Member::Gauge(Self::labels(labels), gauge.clone()))?;
+            Ok(gauge)
+        }
+
+        pub fn histogram(&self, name: &str, help: &str, labels: &[(&str, &str)], bounds: &[u64]) -> Result<Arc<Histogram>, &'static str> {
+            let histogram = Arc::new(Histogram::new(bounds));
+            self.add(name, help, MetricType::Histogram, Member::Histogram(Self::labels(labels), histogram.clone()))?;
+            Ok(histogram)
+        }
+
+        // A counter or gauge family read when scraped. Called with the
+        // registry locked, so it must not register anything.
+        pub fn collect(&self, name: &str, help: &str, kind: MetricType, collector: impl Fn() -> Vec<Sample> + Send + Sync + 'static) -> Result<(), &'static str> {
+            if kind == MetricType::Histogram {
+                return Err("Collected metrics are counters or gauges");
+            }
+            self.add(name, help, kind, Member::Collector(Box::new(collector)))
+        }
+
+        // For a subsystem going away
+        pub fn unregister(&self, name: &str) -> Result<(), &'static str> {
+            self.families.lock().remove(name).map(|_| ()).ok_or("Metric not registered")
+        }
+
+        pub fn names(&self) -> Vec<String> {
+            self.families.lock().keys().cloned().collect()
+        }
+
+        // The text exposition format, version 0.0.4
+        pub fn render(&self) -> String {
+            let mut output = String::new();
+            for (name, family) in self.families.lock().iter() {
+                writeln!(output, "# HELP {} {}", name, escape(&family.help, false)).unwrap();
+                writeln!(output, "# TYPE {} {}", name, family.kind.name()).unwrap();
+                for member in &family.members {
+                    match member {
+                        Member::Counter(labels, counter) => {
+                            output.push_str(name);
+                            write_labels(&mut output, labels, None);
+                            writeln!(output, " {}", counter.get()).unwrap();
+                        }
+                        Member::Gauge(labels, gauge) => {
+                            output.push_str(name);
+                            write_labels(&mut output, labels, None);
+                            writeln!(output, " {}", gauge.get()).unwrap();
+                        }
+                        Member::Histogram(labels, histogram) => {
+                            for (bound, count) in histogram.cumulative() {
+                                write!(output, "{}_bucket", name).unwrap();
+                                write_labels(&mut output, labels, Some(("le", &bound.to_string())));
+                                writeln!(output, " {}", count).unwrap();
+                            }
+                            write!(output, "{}_bucket", name).unwrap();
+                            write_labels(&mut output, labels, Some(("le", "+Inf")));
+                            writeln!(output, " {}", histogram.count()).unwrap();
+                            write!(output, "{}_sum", name).unwrap();
+                            write_labels(&mut output, labels, None);
+                            writeln!(output, " {}", histogram.sum()).unwrap();
+                            write!(output, "{}_count", name).unwrap();
+                            write_labels(&mut output, labels, None);
+                            writeln!(output, " {}", histogram.count()).unwrap();
+                        }
+                        Member::Collector(collector) => {
+                            for sample in collector() {
+                                output.push_str(name);
+                                write_labels(&mut output, &sample.labels, None);
+                                writeln!(output, " {}", format_value(sample.value)).unwrap();
+                            }
+                        }
+                    }
+                }
+            }
+            output
+        }
+    }
+
+    // The kernel's own registry
+    pub fn registry() -> &'static Registry {
+        REGISTRY.get_or_init(Registry::new)
+    }
+
+    // What the core kernel keeps track of anyway
+    pub fn register_kernel_metrics(registry: &Registry) -> Result<(), &'static str> {
+        registry.collect("vaelix_uptime_seconds", "Time since boot.", MetricType::Gauge, || vec![Sample::new(uptime() as f64 / 1e9)])?;
+        registry.collect("vaelix_kernel_warnings_total", "Kernel warnings since boot.", MetricType::Counter, || vec![Sample::new(hardening::warnings() as f64)])
+    }
+}
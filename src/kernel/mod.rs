@@ -1,11 +1,39 @@
 // src/kernel/mod.rs
 
+pub mod crashdump;
+pub mod drivers;
+pub mod faultinject;
+pub mod gdbstub;
+pub mod hardening;
+pub mod input;
+pub mod irq;
+pub mod kallsyms;
+pub mod kconfig;
+pub mod keyring;
+pub mod ktest;
+pub mod latency;
+pub mod lockdep;
+pub mod log;
+pub mod metrics;
+pub mod percpu;
+pub mod power;
+pub mod process;
+pub mod pty;
+pub mod rcu;
+pub mod sync;
+pub mod topology;
+pub mod users;
 pub mod vaelix_alloc;
+pub mod vt;
 pub mod vx_tasklet;
 pub mod vxboot;
 pub mod vxchan;
 pub mod vxfs;
+pub mod vxinit;
+pub mod vxsh;
 pub mod vxshield;
+pub mod vxtop;
+pub mod watchdog;
 
 pub use vx_tasklet::vx_tasklet_init;
 pub use vxchan::vxchan::vxchan_init;
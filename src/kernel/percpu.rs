@@ -0,0 +1,146 @@
+// src/kernel/percpu.rs
+
+pub mod percpu {
+    use crate::drivers::msr::msr::MsrIo;
+    use crate::metrics::metrics::{Counter, MetricType, Registry, Sample};
+    use std::cell::Cell;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::OnceLock;
+
+    pub const IA32_GS_BASE: u32 = 0xC000_0101;
+    // What SWAPGS exchanges GS_BASE with: the user's on entry
+    pub const IA32_KERNEL_GS_BASE: u32 = 0xC000_0102;
+    // Each CPU's area, one after another below the interrupt stacks
+    pub const PERCPU_BASE: u64 = 0xFFFF_FD00_0000_0000;
+    pub const PERCPU_AREA_SIZE: u64 = 64 << 10;
+    pub const MAX_CPUS: usize = 64;
+
+    thread_local! {
+        // Hosted, each thread running as a CPU has its own GS base, as
+        // each CPU does; 0 until it enters one
+        static GS_BASE: Cell<u64> = const { Cell::new(0) };
+    }
+
+    pub fn area(cpu: usize) -> u64 {
+        PERCPU_BASE + cpu as u64 * PERCPU_AREA_SIZE
+    }
+
+    // Points a CPU's GS base at its area, with user GS 0 until a process
+    // sets its own; from that CPU, early in its bring-up
+    pub fn init_cpu<M: MsrIo + ?Sized>(msr: &M, cpu: usize) -> Result<(), &'static str> {
+        if cpu >= MAX_CPUS {
+            return Err("CPU out of range");
+        }
+        msr.wrmsr(cpu, IA32_GS_BASE, area(cpu))?;
+        msr.wrmsr(cpu, IA32_KERNEL_GS_BASE, 0)?;
+        GS_BASE.with(|base| base.set(area(cpu)));
+        Ok(())
+    }
+
+    // The CPU this runs on, from its GS base. Code that never entered one
+    // runs as the boot CPU.
+    pub fn this_cpu() -> usize {
+        match GS_BASE.with(Cell::get) {
+            0 => 0,
+            base => ((base - PERCPU_BASE) / PERCPU_AREA_SIZE) as usize,
+        }
+    }
+
+    // A variable with a copy on each CPU, declared with define_per_cpu!.
+    // Each CPU's copy is made on first use; changing another CPU's copy
+    // needs atomics or a lock inside it like any shared data.
+    pub struct PerCpu<T> {
+        name: &'static str,
+        init: fn() -> T,
+        copies: OnceLock<Box<[T]>>,
+    }
+
+    impl<T> PerCpu<T> {
+        pub const fn new(name: &'static str, init: fn() -> T) -> Self {
+            PerCpu { name, init, copies: OnceLock::new() }
+        }
+
+        pub fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn copies(&self) -> &[T] {
+            self.copies.get_or_init(|| (0..MAX_CPUS).map(|_| (self.init)()).collect())
+        }
+
+        // This CPU's copy
+        pub fn get(&self) -> &T {
+            &self.copies()[this_cpu()]
+        }
+
+        // Another CPU's, as per_cpu() is in Linux
+        pub fn get_cpu(&self, cpu: usize) -> Option<&T> {
+            self.copies().get(cpu)
+        }
+
+        // Every CPU's, for totals
+        pub fn iter(&self) -> impl Iterator<Item = (usize, &T)> {
+            self.copies().iter().enumerate()
+        }
+    }
+
+    // define_per_cpu!(pub static NAME: Type = initial value);
+    #[macro_export]
+    macro_rules! define_per_cpu {
+        ($(#[$attribute:meta])* $visibility:vis static $name:ident: $type:ty = $init:expr $(;)?) => {
+            $(#[$attribute])*
+            $visibility static $name: $crate::percpu::percpu::PerCpu<$type> = $crate::percpu::percpu::PerCpu::new(stringify!($name), || $init);
+        };
+    }
+
+    // this_cpu!(NAME) is this CPU's copy, this_cpu!(NAME, cpu) another's
+    #[macro_export]
+    macro_rules! this_cpu {
+        ($name:path) => {
+            $name.get()
+        };
+        ($name:path, $cpu:expr) => {
+            $name.get_cpu($cpu)
+        };
+    }
+
+    // What each CPU counts of itself
+    #[derive(Debug, Default)]
+    pub struct CpuStats {
+        pub interrupts: Counter,
+        pub syscalls: Counter,
+        pub context_switches: Counter,
+    }
+
+    define_per_cpu!(pub static STATS: CpuStats = CpuStats::default());
+    // The thread each CPU is running, 0 when idle
+    define_per_cpu!(pub static CURRENT: AtomicU32 = AtomicU32::new(0));
+
+    // From the scheduler as it switches to a thread
+    pub fn switch_to(tid: u32) {
+        let previous = this_cpu!(CURRENT).swap(tid, Ordering::Relaxed);
+        if previous != tid {
+            this_cpu!(STATS).context_switches.inc();
+        }
+    }
+
+    pub fn current() -> u32 {
+        this_cpu!(CURRENT).load(Ordering::Relaxed)
+    }
+
+    // Each CPU's counts, for those that have done anything
+    pub fn register_metrics(registry: &Registry) -> Result<(), &'static str> {
+        let samples = |stat: fn(&CpuStats) -> &Counter| {
+            move || {
+                STATS
+                    .iter()
+                    .filter(|(_, stats)| stats.interrupts.get() + stats.syscalls.get() + stats.context_switches.get() > 0)
+                    .map(|(cpu, stats)| Sample::new(stat(stats).get() as f64).with("cpu", &cpu.to_string()))
+                    .collect()
+            }
+        };
+        registry.collect("vaelix_cpu_interrupts_total", "Interrupts each CPU has taken.", MetricType::Counter, samples(|stats| &stats.interrupts))?;
+        registry.collect("vaelix_cpu_syscalls_total", "System calls each CPU has handled.", MetricType::Counter, samples(|stats| &stats.syscalls))?;
+        registry.collect("vaelix_cpu_context_switches_total", "Switches between threads on each CPU.", MetricType::Counter, samples(|stats| &stats.context_switches))
+    }
+}
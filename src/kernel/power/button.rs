@@ -0,0 +1,232 @@
+// src/kernel/power/button.rs
+
+pub mod button {
+    use crate::drivers::ec::ec::EmbeddedController;
+    use crate::drivers::port::port::PortIo;
+    use crate::power::policy::policy::{LidAction, PolicyManager};
+    use crate::power::profile::profile::{ProfileManager, AC_PROFILE, BATTERY_PROFILE};
+    use crate::vxchan::vxchan::VXChanManager;
+    use std::sync::Arc;
+
+    // One message per event, e.g. "ac unplugged" or "lid closed"
+    pub const EVENT_CHANNEL: &str = "vxpower.button";
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ButtonKind {
+        AcAdapter,
+        Lid,
+        PowerButton,
+        SleepButton,
+    }
+
+    impl ButtonKind {
+        // Maps the _HID of a namespace device to the kind we handle
+        pub fn from_hid(hid: &str) -> Option<Self> {
+            match hid {
+                "ACPI0003" => Some(ButtonKind::AcAdapter),
+                "PNP0C0D" => Some(ButtonKind::Lid),
+                "PNP0C0C" => Some(ButtonKind::PowerButton),
+                "PNP0C0E" => Some(ButtonKind::SleepButton),
+                _ => None,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum PowerEvent {
+        AcPlugged,
+        AcUnplugged,
+        LidOpened,
+        LidClosed,
+        PowerButton,
+        SleepButton,
+    }
+
+    impl PowerEvent {
+        pub fn message(&self) -> &'static str {
+            match self {
+                PowerEvent::AcPlugged => "ac plugged",
+                PowerEvent::AcUnplugged => "ac unplugged",
+                PowerEvent::LidOpened => "lid open",
+                PowerEvent::LidClosed => "lid closed",
+                PowerEvent::PowerButton => "button power",
+                PowerEvent::SleepButton => "button sleep",
+            }
+        }
+
+        // For clients reading EVENT_CHANNEL
+        pub fn parse(message: &str) -> Option<Self> {
+            [
+                PowerEvent::AcPlugged,
+                PowerEvent::AcUnplugged,
+                PowerEvent::LidOpened,
+                PowerEvent::LidClosed,
+                PowerEvent::PowerButton,
+                PowerEvent::SleepButton,
+            ]
+            .into_iter()
+            .find(|event| event.message() == message)
+        }
+    }
+
+    // Current state of a switch: _PSR for the AC adapter (true when
+    // online), _LID for the lid (true when open)
+    pub trait StatusSource: Send + Sync {
+        fn read(&self) -> Result<bool, &'static str>;
+    }
+
+    // Status bit in EC RAM, which is where most laptops' _PSR and _LID
+    // methods read from
+    pub struct EcStatusBit<P: PortIo> {
+        ec: Arc<EmbeddedController<P>>,
+        address: u8,
+        mask: u8,
+    }
+
+    impl<P: PortIo> EcStatusBit<P> {
+        pub fn new(ec: Arc<EmbeddedController<P>>, address: u8, mask: u8) -> Self {
+            EcStatusBit { ec, address, mask }
+        }
+    }
+
+    impl<P: PortIo> StatusSource for EcStatusBit<P> {
+        fn read(&self) -> Result<bool, &'static str> {
+            Ok(self.ec.read(self.address)? & self.mask != 0)
+        }
+    }
+
+    struct ButtonDevice {
+        path: String,
+        kind: ButtonKind,
+        // Only the AC adapter and the lid have a state to poll
+        source: Option<Box<dyn StatusSource>>,
+        last: Option<bool>,
+    }
+
+    // Tracks the ACPI button and AC adapter devices, turns state changes and
+    // presses into events and runs the default actions for them
+    pub struct ButtonManager {
+        devices: Vec<ButtonDevice>,
+        suspend: Option<Box<dyn FnMut() + Send>>,
+    }
+
+    impl ButtonManager {
+        pub fn new(manager: &VXChanManager) -> Result<Self, &'static str> {
+            manager.create_channel(EVENT_CHANNEL)?;
+            Ok(ButtonManager {
+                devices: Vec::new(),
+                suspend: None,
+            })
+        }
+
+        // Called with each device found in the namespace; devices that are
+        // not buttons or AC adapters are ignored and return Ok(None)
+        pub fn add_device(
+            &mut self,
+            path: &str,
+            hid: &str,
+            source: Option<Box<dyn StatusSource>>,
+        ) -> Result<Option<ButtonKind>, &'static str> {
+            let Some(kind) = ButtonKind::from_hid(hid) else {
+                return Ok(None);
+            };
+            let needs_source = matches!(kind, ButtonKind::AcAdapter | ButtonKind::Lid);
+            if needs_source != source.is_some() {
+                return Err("Status source does not match the device kind");
+            }
+            // The initial state is not an event
+            let last = source.as_ref().and_then(|source| source.read().ok());
+            log::info!("ACPI {} is {:?}", path, kind);
+            self.devices.push(ButtonDevice {
+                path: path.to_string(),
+                kind,
+                source,
+                last,
+            });
+            Ok(Some(kind))
+        }
+
+        pub fn set_suspend_handler(&mut self, handler: Box<dyn FnMut() + Send>) {
+            self.suspend = Some(handler);
+        }
+
+        pub fn state(&self, kind: ButtonKind) -> Option<bool> {
+            self.devices.iter().find(|device| device.kind == kind).and_then(|device| device.last)
+        }
+
+        // Re-reads every status device, e.g. on a GPE or Notify(0x80), and
+        // returns the changes since the last read
+        pub fn poll(&mut self) -> Vec<PowerEvent> {
+            let mut events = Vec::new();
+            for device in &mut self.devices {
+                let Some(source) = &device.source else {
+                    continue;
+                };
+                let state = match source.read() {
+                    Ok(state) => state,
+                    Err(err) => {
+                        log::warn!("Failed to read {}: {}", device.path, err);
+                        continue;
+                    }
+                };
+                if device.last == Some(state) {
+                    continue;
+                }
+                device.last = Some(state);
+                events.push(match (device.kind, state) {
+                    (ButtonKind::AcAdapter, true) => PowerEvent::AcPlugged,
+                    (ButtonKind::AcAdapter, false) => PowerEvent::AcUnplugged,
+                    (_, true) => PowerEvent::LidOpened,
+                    (_, false) => PowerEvent::LidClosed,
+                });
+            }
+            events
+        }
+
+        // Momentary buttons signal a press with Notify(0x80) on the device
+        pub fn pressed(&self, path: &str) -> Option<PowerEvent> {
+            match self.devices.iter().find(|device| device.path == path)?.kind {
+                ButtonKind::PowerButton => Some(PowerEvent::PowerButton),
+                ButtonKind::SleepButton => Some(PowerEvent::SleepButton),
+                _ => None,
+            }
+        }
+
+        // Posts the event on EVENT_CHANNEL and applies the policy manager's
+        // event actions. Power and sleep buttons are left to the session.
+        pub fn handle(
+            &mut self,
+            event: PowerEvent,
+            manager: &VXChanManager,
+            policy: &PolicyManager,
+            profiles: &ProfileManager,
+        ) -> Result<(), &'static str> {
+            manager.send_message(EVENT_CHANNEL, event.message().to_string())?;
+            let actions = policy.event_actions();
+            match event {
+                PowerEvent::AcPlugged if actions.ac_profiles => profiles.switch(AC_PROFILE, policy)?,
+                PowerEvent::AcUnplugged if actions.ac_profiles => profiles.switch(BATTERY_PROFILE, policy)?,
+                PowerEvent::LidClosed if actions.lid_close == LidAction::Suspend => match self.suspend.as_mut() {
+                    Some(suspend) => suspend(),
+                    None => log::warn!("Lid closed, but no suspend handler is installed"),
+                },
+                _ => {}
+            }
+            Ok(())
+        }
+
+        // Polls and handles everything that changed
+        pub fn process(
+            &mut self,
+            manager: &VXChanManager,
+            policy: &PolicyManager,
+            profiles: &ProfileManager,
+        ) -> Result<Vec<PowerEvent>, &'static str> {
+            let events = self.poll();
+            for event in &events {
+                self.handle(*event, manager, policy, profiles)?;
+            }
+            Ok(events)
+        }
+    }
+}
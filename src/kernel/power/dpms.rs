@@ -0,0 +1,141 @@
+// src/kernel/power/dpms.rs
+
+pub mod dpms {
+    use std::time::{Duration, Instant};
+
+    // Implemented by the display driver (the i915 panel code on Intel
+    // laptops), which owns the panel power sequencing delays
+    pub trait PanelPower: Send {
+        // Backlight level, 0-100
+        fn brightness(&self) -> u8;
+        fn set_brightness(&mut self, percent: u8) -> Result<(), &'static str>;
+        // Off turns the backlight off and then the panel; on does the reverse
+        fn set_panel_power(&mut self, on: bool) -> Result<(), &'static str>;
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub enum DisplayState {
+        On,
+        Dimmed,
+        Blanked,
+        Suspended,
+    }
+
+    // Each stage counts from the last input event; None disables it
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct IdleTimeouts {
+        pub dim: Option<Duration>,
+        pub blank: Option<Duration>,
+        pub suspend: Option<Duration>,
+        // Backlight level while dimmed, 0-100
+        pub dim_brightness: u8,
+    }
+
+    impl Default for IdleTimeouts {
+        fn default() -> Self {
+            IdleTimeouts {
+                dim: Some(Duration::from_secs(120)),
+                blank: Some(Duration::from_secs(300)),
+                suspend: Some(Duration::from_secs(900)),
+                dim_brightness: 30,
+            }
+        }
+    }
+
+    // System idle tracking: the input subsystem reports activity, a
+    // periodic tick moves the display through dim, blank and suspend
+    pub struct IdleTracker {
+        panel: Box<dyn PanelPower>,
+        timeouts: IdleTimeouts,
+        state: DisplayState,
+        last_input: Instant,
+        // Brightness to restore when leaving the dimmed state
+        saved_brightness: Option<u8>,
+        suspend: Option<Box<dyn FnMut() + Send>>,
+    }
+
+    impl IdleTracker {
+        pub fn new(panel: Box<dyn PanelPower>, timeouts: IdleTimeouts, now: Instant) -> Self {
+            IdleTracker {
+                panel,
+                timeouts,
+                state: DisplayState::On,
+                last_input: now,
+                saved_brightness: None,
+                suspend: None,
+            }
+        }
+
+        pub fn set_suspend_handler(&mut self, handler: Box<dyn FnMut() + Send>) {
+            self.suspend = Some(handler);
+        }
+
+        pub fn set_timeouts(&mut self, timeouts: IdleTimeouts) {
+            self.timeouts = timeouts;
+        }
+
+        pub fn timeouts(&self) -> IdleTimeouts {
+            self.timeouts
+        }
+
+        pub fn state(&self) -> DisplayState {
+            self.state
+        }
+
+        pub fn idle_time(&self, now: Instant) -> Duration {
+            now.saturating_duration_since(self.last_input)
+        }
+
+        // Any key, pointer or touch event. Wakes the display straight back
+        // to full brightness; after a suspend the resume path calls this.
+        pub fn input_activity(&mut self, now: Instant) -> Result<(), &'static str> {
+            self.last_input = now;
+            if self.state >= DisplayState::Blanked {
+                self.panel.set_panel_power(true)?;
+            }
+            if let Some(brightness) = self.saved_brightness.take() {
+                self.panel.set_brightness(brightness)?;
+            }
+            self.state = DisplayState::On;
+            Ok(())
+        }
+
+        fn stage_due(&self, timeout: Option<Duration>, now: Instant) -> bool {
+            timeout.is_some_and(|timeout| self.idle_time(now) >= timeout)
+        }
+
+        // Advances as far as the idle time allows, one stage per call so a
+        // late tick still dims before blanking
+        pub fn tick(&mut self, now: Instant) -> Result<DisplayState, &'static str> {
+            match self.state {
+                DisplayState::On if self.stage_due(self.timeouts.dim, now) => {
+                    let brightness = self.panel.brightness();
+                    if brightness > self.timeouts.dim_brightness {
+                        self.panel.set_brightness(self.timeouts.dim_brightness)?;
+                        self.saved_brightness = Some(brightness);
+                    }
+                    self.state = DisplayState::Dimmed;
+                }
+                DisplayState::On | DisplayState::Dimmed if self.stage_due(self.timeouts.blank, now) => {
+                    self.panel.set_panel_power(false)?;
+                    self.state = DisplayState::Blanked;
+                }
+                DisplayState::On | DisplayState::Dimmed | DisplayState::Blanked
+                    if self.stage_due(self.timeouts.suspend, now) =>
+                {
+                    if self.state != DisplayState::Blanked {
+                        self.panel.set_panel_power(false)?;
+                    }
+                    self.state = DisplayState::Suspended;
+                    log::info!("System idle for {}s, suspending", self.idle_time(now).as_secs());
+                    match self.suspend.as_mut() {
+                        Some(suspend) => suspend(),
+                        None => log::warn!("No suspend handler installed"),
+                    }
+                }
+                _ => {}
+            }
+            Ok(self.state)
+        }
+    }
+}
@@ -0,0 +1,309 @@
+// src/kernel/power/fan.rs
+
+pub mod fan {
+    use crate::drivers::ec::ec::EmbeddedController;
+    use crate::drivers::port::port::PortIo;
+    use crate::power::policy::policy::policy_manager;
+    use crate::power::thermal::thermal::registry;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    const FAILSAFE_DUTY: u8 = 100;
+    const FAILSAFE_THROTTLE_LEVEL: u8 = 2;
+    const FAN_FAULT_THROTTLE_LEVEL: u8 = 4;
+
+    pub trait FanDriver: Send + Sync {
+        fn set_duty(&self, percent: u8) -> Result<(), &'static str>;
+        fn rpm(&self) -> Result<u32, &'static str>;
+    }
+
+    // Vendor specific EC register layout for a PWM fan
+    #[derive(Debug, Clone, Copy)]
+    pub struct EcFanRegisters {
+        pub mode: u8,
+        pub manual_mode: u8,
+        pub auto_mode: u8,
+        pub duty: u8,
+        pub duty_max: u8,
+        pub rpm_low: u8,
+    }
+
+    pub struct EcFan<P: PortIo> {
+        ec: Arc<EmbeddedController<P>>,
+        registers: EcFanRegisters,
+    }
+
+    impl<P: PortIo> EcFan<P> {
+        pub fn new(ec: Arc<EmbeddedController<P>>, registers: EcFanRegisters) -> Self {
+            EcFan { ec, registers }
+        }
+
+        // Hands fan control back to the EC firmware
+        pub fn release(&self) -> Result<(), &'static str> {
+            self.ec.write(self.registers.mode, self.registers.auto_mode)
+        }
+    }
+
+    impl<P: PortIo> FanDriver for EcFan<P> {
+        fn set_duty(&self, percent: u8) -> Result<(), &'static str> {
+            let percent = percent.min(100) as u32;
+            let raw = (percent * self.registers.duty_max as u32).div_ceil(100) as u8;
+            self.ec.write(self.registers.mode, self.registers.manual_mode)?;
+            self.ec.write(self.registers.duty, raw)
+        }
+
+        fn rpm(&self) -> Result<u32, &'static str> {
+            self.ec.read_u16(self.registers.rpm_low).map(|rpm| rpm as u32)
+        }
+    }
+
+    // Piecewise linear temperature (degrees Celsius) to duty (percent) curve
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct FanCurve {
+        points: Vec<(i32, u8)>,
+    }
+
+    impl FanCurve {
+        pub fn new(points: Vec<(i32, u8)>) -> Result<Self, &'static str> {
+            if points.is_empty() {
+                return Err("Fan curve needs at least one point");
+            }
+            for pair in points.windows(2) {
+                if pair[1].0 <= pair[0].0 {
+                    return Err("Fan curve temperatures must be strictly increasing");
+                }
+                if pair[1].1 < pair[0].1 {
+                    return Err("Fan curve duty must not decrease with temperature");
+                }
+            }
+            if points.iter().any(|(_, duty)| *duty > 100) {
+                return Err("Fan curve duty must be a percentage");
+            }
+            Ok(FanCurve { points })
+        }
+
+        pub fn default_curve() -> Self {
+            FanCurve {
+                points: vec![(40, 0), (50, 25), (65, 45), (75, 70), (85, 100)],
+            }
+        }
+
+        pub fn points(&self) -> &[(i32, u8)] {
+            &self.points
+        }
+
+        pub fn duty_for(&self, temperature: i32) -> u8 {
+            let first = self.points[0];
+            let last = self.points[self.points.len() - 1];
+            if temperature <= first.0 {
+                return first.1;
+            }
+            if temperature >= last.0 {
+                return last.1;
+            }
+            let pair = self
+                .points
+                .windows(2)
+                .find(|pair| temperature < pair[1].0)
+                .unwrap();
+            let ((t0, d0), (t1, d1)) = (pair[0], pair[1]);
+            let span = (d1 - d0) as i32 * (temperature - t0) / (t1 - t0);
+            d0 + span as u8
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct FanConfig {
+        pub curve: FanCurve,
+        // Degrees the temperature must fall before the duty is lowered
+        pub hysteresis: i32,
+        // Above this temperature the fan runs at full speed regardless of the curve
+        pub failsafe_temperature: i32,
+        // Thermal zone to follow; the hottest zone when None
+        pub zone: Option<String>,
+    }
+
+    impl Default for FanConfig {
+        fn default() -> Self {
+            FanConfig {
+                curve: FanCurve::default_curve(),
+                hysteresis: 4,
+                failsafe_temperature: 95,
+                zone: None,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct FanStatus {
+        pub duty: u8,
+        pub temperature: Option<i32>,
+        pub failsafe: bool,
+    }
+
+    // The component throttle the fan controller put in place, and the
+    // level it replaced
+    #[derive(Debug, Clone, Copy)]
+    struct FanThrottle {
+        previous: u8,
+        applied: u8,
+    }
+
+    struct FanState {
+        duty: u8,
+        reference_temperature: Option<i32>,
+        failsafe: bool,
+        throttle: Option<FanThrottle>,
+    }
+
+    pub struct FanControlService {
+        fan: Arc<dyn FanDriver>,
+        config: Mutex<FanConfig>,
+        state: Mutex<FanState>,
+    }
+
+    impl FanControlService {
+        pub fn new(fan: Arc<dyn FanDriver>, config: FanConfig) -> Self {
+            FanControlService {
+                fan,
+                config: Mutex::new(config),
+                state: Mutex::new(FanState {
+                    duty: 0,
+                    reference_temperature: None,
+                    failsafe: false,
+                    throttle: None,
+                }),
+            }
+        }
+
+        pub fn set_curve(&self, curve: FanCurve) {
+            self.config.lock().unwrap().curve = curve;
+            // Re-evaluate from scratch so a lower curve takes effect immediately
+            self.state.lock().unwrap().reference_temperature = None;
+        }
+
+        pub fn set_hysteresis(&self, hysteresis: i32) {
+            self.config.lock().unwrap().hysteresis = hysteresis.max(0);
+        }
+
+        pub fn config(&self) -> FanConfig {
+            self.config.lock().unwrap().clone()
+        }
+
+        pub fn status(&self) -> FanStatus {
+            let state = self.state.lock().unwrap();
+            FanStatus {
+                duty: state.duty,
+                temperature: state.reference_temperature,
+                failsafe: state.failsafe,
+            }
+        }
+
+        fn read_temperature(&self) -> Result<i32, &'static str> {
+            let zone = self.config.lock().unwrap().zone.clone();
+            let millicelsius = match zone {
+                Some(name) => registry().get(&name).ok_or("Thermal zone not found")?.temperature()?,
+                None => registry().hottest().ok_or("No readable thermal zone")?.1,
+            };
+            Ok(millicelsius / 1000)
+        }
+
+        pub fn tick(&self) -> FanStatus {
+            let temperature = self.read_temperature();
+            self.update(temperature)
+        }
+
+        // Applies one control step for a temperature reading in degrees Celsius
+        pub fn update(&self, temperature: Result<i32, &'static str>) -> FanStatus {
+            let config = self.config();
+            let mut state = self.state.lock().unwrap();
+
+            let target = match temperature {
+                Ok(temp) if temp < config.failsafe_temperature => {
+                    if state.failsafe && temp > config.failsafe_temperature - config.hysteresis {
+                        // Stay in failsafe until we are clearly below the limit
+                        FAILSAFE_DUTY
+                    } else {
+                        state.failsafe = false;
+                        let duty = config.curve.duty_for(temp);
+                        let holding = state
+                            .reference_temperature
+                            .is_some_and(|reference| temp > reference - config.hysteresis);
+                        if duty < state.duty && holding {
+                            state.duty
+                        } else {
+                            state.reference_temperature = Some(temp);
+                            duty
+                        }
+                    }
+                }
+                Ok(temp) => {
+                    log::error!("Temperature {}C above failsafe limit, fan at full speed", temp);
+                    state.failsafe = true;
+                    state.reference_temperature = Some(temp);
+                    FAILSAFE_DUTY
+                }
+                Err(error) => {
+                    log::error!("Fan control lost temperature input: {}", error);
+                    state.failsafe = true;
+                    state.reference_temperature = None;
+                    FAILSAFE_DUTY
+                }
+            };
+
+            let throttle = match self.fan.set_duty(target) {
+                Ok(()) => {
+                    state.duty = target;
+                    state.failsafe.then_some(FAILSAFE_THROTTLE_LEVEL)
+                }
+                Err(error) => {
+                    // A fan we cannot drive is only safe with the components throttled hard
+                    log::error!("Failed to set fan duty: {}", error);
+                    state.failsafe = true;
+                    Some(FAN_FAULT_THROTTLE_LEVEL)
+                }
+            };
+
+            let policy = policy_manager();
+            let current = policy.component_snapshot().throttle_level;
+            match (throttle, state.throttle) {
+                (Some(level), applied) => {
+                    // Never below what the thermal policy already asked for
+                    let previous = match applied {
+                        Some(applied) if applied.applied == current => applied.previous,
+                        _ => current,
+                    };
+                    policy.throttle_components(level.max(previous));
+                    let applied = policy.component_snapshot().throttle_level;
+                    state.throttle = Some(FanThrottle { previous, applied });
+                }
+                (None, Some(applied)) => {
+                    // A level changed since belongs to whoever changed it
+                    if applied.applied == current {
+                        policy.throttle_components(applied.previous);
+                    }
+                    state.throttle = None;
+                }
+                (None, None) => {}
+            }
+
+            FanStatus {
+                duty: state.duty,
+                temperature: temperature.ok(),
+                failsafe: state.failsafe,
+            }
+        }
+    }
+
+    pub fn fan_control_init(fan: Arc<dyn FanDriver>, config: FanConfig, interval: Duration) -> Arc<FanControlService> {
+        log::info!("Initializing fan control service...");
+        let service = Arc::new(FanControlService::new(fan, config));
+        let worker = Arc::clone(&service);
+        thread::spawn(move || loop {
+            worker.tick();
+            thread::sleep(interval);
+        });
+        service
+    }
+}
@@ -0,0 +1,257 @@
+// src/kernel/power/hibernate.rs
+
+pub mod hibernate {
+    use crate::drivers::block::block::{registry, BlockDevice};
+    use sha2::{Digest, Sha256};
+    use std::sync::Arc;
+
+    const RESUME_MAGIC: &[u8; 8] = b"VXHIBER1";
+    // magic, image size, raw size, region count, SHA-256 of the raw image
+    const HEADER_SIZE: usize = 8 + 8 + 8 + 4 + 32;
+    // Longest run either kind of compression packet can describe
+    const MAX_PACKET: usize = 128;
+    const MIN_RUN: usize = 3;
+
+    // A contiguous range of kernel memory captured in the image
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct MemoryRegion {
+        pub base: u64,
+        pub data: Vec<u8>,
+    }
+
+    // Source of the pages to save and target for restoring them; the
+    // snapshot must be taken with every other CPU and device quiesced
+    pub trait MemoryImage {
+        fn snapshot(&self) -> Vec<MemoryRegion>;
+        fn restore(&self, region: &MemoryRegion) -> Result<(), &'static str>;
+    }
+
+    // Stored in the first block of the swap area. It is written only once
+    // the whole image is on disk, so a half-written image is never resumed.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct ResumeHeader {
+        pub image_size: u64,
+        pub raw_size: u64,
+        pub regions: u32,
+        pub digest: [u8; 32],
+    }
+
+    impl ResumeHeader {
+        fn encode(&self, block: &mut [u8]) {
+            block[..8].copy_from_slice(RESUME_MAGIC);
+            block[8..16].copy_from_slice(&self.image_size.to_le_bytes());
+            block[16..24].copy_from_slice(&self.raw_size.to_le_bytes());
+            block[24..28].copy_from_slice(&self.regions.to_le_bytes());
+            block[28..60].copy_from_slice(&self.digest);
+        }
+
+        fn decode(block: &[u8]) -> Option<Self> {
+            if &block[..8] != RESUME_MAGIC {
+                return None;
+            }
+            Some(ResumeHeader {
+                image_size: u64::from_le_bytes(block[8..16].try_into().unwrap()),
+                raw_size: u64::from_le_bytes(block[16..24].try_into().unwrap()),
+                regions: u32::from_le_bytes(block[24..28].try_into().unwrap()),
+                digest: block[28..60].try_into().unwrap(),
+            })
+        }
+    }
+
+    // Run-length packets in the style of PackBits: a control byte below
+    // 0x80 is followed by that many plus one literal bytes, otherwise the
+    // next byte repeats (control - 0x7E) times. Mostly-zero kernel pages
+    // shrink to a few bytes each.
+    pub fn compress(data: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        let mut literal_start = 0;
+        let mut index = 0;
+        while index < data.len() {
+            let run = data[index..].iter().take(MAX_PACKET + 1).take_while(|byte| **byte == data[index]).count();
+            if run >= MIN_RUN {
+                flush_literals(&mut output, &data[literal_start..index]);
+                output.push((run + 0x7E) as u8);
+                output.push(data[index]);
+                index += run;
+                literal_start = index;
+            } else {
+                index += run;
+            }
+        }
+        flush_literals(&mut output, &data[literal_start..]);
+        output
+    }
+
+    fn flush_literals(output: &mut Vec<u8>, literals: &[u8]) {
+        for chunk in literals.chunks(MAX_PACKET) {
+            output.push((chunk.len() - 1) as u8);
+            output.extend_from_slice(chunk);
+        }
+    }
+
+    pub fn decompress(data: &[u8]) -> Result<Vec<u8>, &'static str> {
+        let mut output = Vec::new();
+        let mut index = 0;
+        while index < data.len() {
+            let control = data[index] as usize;
+            index += 1;
+            if control < 0x80 {
+                let literals = data.get(index..index + control + 1).ok_or("Truncated compressed image")?;
+                output.extend_from_slice(literals);
+                index += control + 1;
+            } else {
+                let byte = *data.get(index).ok_or("Truncated compressed image")?;
+                output.resize(output.len() + control - 0x7E, byte);
+                index += 1;
+            }
+        }
+        Ok(output)
+    }
+
+    fn serialize(regions: &[MemoryRegion]) -> Vec<u8> {
+        let mut raw = Vec::new();
+        for region in regions {
+            raw.extend_from_slice(&region.base.to_le_bytes());
+            raw.extend_from_slice(&(region.data.len() as u64).to_le_bytes());
+            raw.extend_from_slice(&region.data);
+        }
+        raw
+    }
+
+    fn deserialize(raw: &[u8], count: u32) -> Result<Vec<MemoryRegion>, &'static str> {
+        let mut regions = Vec::new();
+        let mut index = 0;
+        for _ in 0..count {
+            let header = raw.get(index..index + 16).ok_or("Truncated hibernation image")?;
+            let base = u64::from_le_bytes(header[..8].try_into().unwrap());
+            let length = u64::from_le_bytes(header[8..].try_into().unwrap()) as usize;
+            index += 16;
+            let data = raw.get(index..index + length).ok_or("Truncated hibernation image")?;
+            regions.push(MemoryRegion {
+                base,
+                data: data.to_vec(),
+            });
+            index += length;
+        }
+        Ok(regions)
+    }
+
+    // A range of blocks on the system drive set aside for the image. The
+    // first block holds the resume header, the image follows it.
+    pub struct SwapArea {
+        device: Arc<dyn BlockDevice>,
+        start: u64,
+        blocks: u64,
+    }
+
+    impl SwapArea {
+        pub fn new(device: Arc<dyn BlockDevice>, start: u64, blocks: u64) -> Result<Self, &'static str> {
+            if blocks < 2 || start.checked_add(blocks).is_none_or(|end| end > device.block_count()) {
+                return Err("Swap area outside the device");
+            }
+            if device.block_size() < HEADER_SIZE {
+                return Err("Block size too small for the resume header");
+            }
+            Ok(SwapArea { device, start, blocks })
+        }
+
+        // Looks the device up in the block registry, e.g. nvme0n1
+        pub fn open(name: &str, start: u64, blocks: u64) -> Result<Self, &'static str> {
+            let device = registry().get(name).ok_or("Block device not found")?;
+            Self::new(device, start, blocks)
+        }
+
+        // Bytes available for the compressed image
+        pub fn capacity(&self) -> u64 {
+            (self.blocks - 1) * self.device.block_size() as u64
+        }
+
+        pub fn read_header(&self) -> Result<Option<ResumeHeader>, &'static str> {
+            let mut block = vec![0u8; self.device.block_size()];
+            self.device.read_blocks(self.start, &mut block)?;
+            Ok(ResumeHeader::decode(&block))
+        }
+
+        fn write_header(&self, header: Option<ResumeHeader>) -> Result<(), &'static str> {
+            let mut block = vec![0u8; self.device.block_size()];
+            if let Some(header) = header {
+                header.encode(&mut block);
+            }
+            self.device.write_blocks(self.start, &block)
+        }
+
+        // Wipes the header so the image is not resumed twice
+        pub fn invalidate(&self) -> Result<(), &'static str> {
+            self.write_header(None)
+        }
+    }
+
+    // Snapshots memory and writes it to the swap area. The caller enters S4
+    // once this returns; if the machine keeps running instead (the sleep
+    // request failed) it must invalidate the area again.
+    pub fn hibernate(area: &SwapArea, memory: &dyn MemoryImage) -> Result<ResumeHeader, &'static str> {
+        if area.device.read_only() {
+            return Err("Swap device is read-only");
+        }
+        let regions = memory.snapshot();
+        let raw = serialize(&regions);
+        let mut image = compress(&raw);
+        if image.len() as u64 > area.capacity() {
+            return Err("Hibernation image does not fit the swap area");
+        }
+        let header = ResumeHeader {
+            image_size: image.len() as u64,
+            raw_size: raw.len() as u64,
+            regions: regions.len() as u32,
+            digest: Sha256::digest(&raw).into(),
+        };
+        // Any old header goes first so a crash mid-write cannot pair it
+        // with a partly overwritten image
+        area.invalidate()?;
+        let block_size = area.device.block_size();
+        image.resize(image.len().div_ceil(block_size) * block_size, 0);
+        area.device.write_blocks(area.start + 1, &image)?;
+        area.write_header(Some(header))?;
+        log::info!(
+            "Hibernation image written: {} region(s), {} bytes compressed to {}",
+            header.regions, header.raw_size, header.image_size
+        );
+        Ok(header)
+    }
+
+    // Restores a saved image if the swap area holds one. A corrupted image
+    // is discarded so the next boot starts fresh.
+    pub fn resume(area: &SwapArea, memory: &dyn MemoryImage) -> Result<Option<ResumeHeader>, &'static str> {
+        let Some(header) = area.read_header()? else {
+            return Ok(None);
+        };
+        let regions = match load_image(area, &header) {
+            Ok(regions) => regions,
+            Err(err) => {
+                area.invalidate()?;
+                return Err(err);
+            }
+        };
+        area.invalidate()?;
+        for region in &regions {
+            memory.restore(region)?;
+        }
+        log::info!("Resumed from hibernation: {} region(s) restored", regions.len());
+        Ok(Some(header))
+    }
+
+    fn load_image(area: &SwapArea, header: &ResumeHeader) -> Result<Vec<MemoryRegion>, &'static str> {
+        if header.image_size > area.capacity() {
+            return Err("Hibernation image larger than the swap area");
+        }
+        let block_size = area.device.block_size();
+        let mut image = vec![0u8; (header.image_size as usize).div_ceil(block_size) * block_size];
+        area.device.read_blocks(area.start + 1, &mut image)?;
+        image.truncate(header.image_size as usize);
+        let raw = decompress(&image)?;
+        if raw.len() as u64 != header.raw_size || Sha256::digest(&raw).as_slice() != header.digest {
+            return Err("Hibernation image checksum mismatch");
+        }
+        deserialize(&raw, header.regions)
+    }
+}
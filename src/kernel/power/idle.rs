@@ -0,0 +1,207 @@
+// src/kernel/power/idle.rs
+
+pub mod idle {
+    use std::collections::{BTreeMap, VecDeque};
+    use std::sync::{Arc, Mutex, OnceLock};
+    use std::thread;
+    use std::time::Duration;
+
+    const HISTORY_LEN: usize = 8;
+    // Longest sleep without a timer event pending; tickless idle only
+    // wakes for interrupts otherwise
+    const MAX_IDLE_US: u64 = 1_000_000;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct CState {
+        pub name: &'static str,
+        // Microseconds
+        pub exit_latency: u32,
+        // Shortest idle period for which entering the state saves energy
+        pub target_residency: u32,
+        // MWAIT hint (EAX) for the state
+        pub mwait_hint: u32,
+    }
+
+    // Alder Lake style table, shallowest first
+    pub fn default_cstates() -> Vec<CState> {
+        vec![
+            CState {
+                name: "C1",
+                exit_latency: 1,
+                target_residency: 1,
+                mwait_hint: 0x00,
+            },
+            CState {
+                name: "C1E",
+                exit_latency: 2,
+                target_residency: 4,
+                mwait_hint: 0x01,
+            },
+            CState {
+                name: "C6",
+                exit_latency: 220,
+                target_residency: 600,
+                mwait_hint: 0x20,
+            },
+        ]
+    }
+
+    // Wakeup latency limits requested by subsystems, e.g. audio streaming
+    // asking for 200 us; the strictest request wins
+    pub struct LatencyConstraints {
+        requests: Mutex<BTreeMap<String, u32>>,
+    }
+
+    impl LatencyConstraints {
+        pub fn new() -> Self {
+            LatencyConstraints {
+                requests: Mutex::new(BTreeMap::new()),
+            }
+        }
+
+        // Replaces any earlier request from the same subsystem
+        pub fn request(&self, subsystem: &str, max_latency_us: u32) {
+            self.requests.lock().unwrap().insert(subsystem.to_string(), max_latency_us);
+        }
+
+        pub fn release(&self, subsystem: &str) {
+            self.requests.lock().unwrap().remove(subsystem);
+        }
+
+        pub fn current(&self) -> Option<u32> {
+            self.requests.lock().unwrap().values().min().copied()
+        }
+    }
+
+    impl Default for LatencyConstraints {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    pub fn latency_constraints() -> Arc<LatencyConstraints> {
+        static CONSTRAINTS: OnceLock<Arc<LatencyConstraints>> = OnceLock::new();
+        Arc::clone(CONSTRAINTS.get_or_init(|| Arc::new(LatencyConstraints::new())))
+    }
+
+    // Puts a core into a C-state until the next interrupt or the deadline
+    pub trait IdleDriver: Send + Sync {
+        // Returns how long the core actually stayed idle
+        fn enter(&self, core: usize, state: &CState, deadline: Duration) -> Duration;
+    }
+
+    // Hosted stand-in that sleeps the thread for the whole deadline
+    pub struct SleepIdle;
+
+    impl IdleDriver for SleepIdle {
+        fn enter(&self, _core: usize, _state: &CState, deadline: Duration) -> Duration {
+            thread::sleep(deadline);
+            deadline
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct StateUsage {
+        pub entries: u64,
+        pub residency_us: u64,
+    }
+
+    struct CoreIdle {
+        // Recent measured idle periods, microseconds
+        history: VecDeque<u64>,
+        usage: Vec<StateUsage>,
+    }
+
+    // Menu-style governor: predicts each core's idle period from the next
+    // timer event and the recent pattern of wakeups, then picks the deepest
+    // state that pays off within that period and meets the latency limit
+    pub struct IdleGovernor {
+        states: Vec<CState>,
+        cores: Vec<Mutex<CoreIdle>>,
+        constraints: Arc<LatencyConstraints>,
+    }
+
+    impl IdleGovernor {
+        pub fn new(states: Vec<CState>, cores: usize, constraints: Arc<LatencyConstraints>) -> Self {
+            let usage = vec![StateUsage::default(); states.len()];
+            IdleGovernor {
+                states,
+                cores: (0..cores)
+                    .map(|_| {
+                        Mutex::new(CoreIdle {
+                            history: VecDeque::new(),
+                            usage: usage.clone(),
+                        })
+                    })
+                    .collect(),
+                constraints,
+            }
+        }
+
+        pub fn states(&self) -> &[CState] {
+            &self.states
+        }
+
+        // Repetitive wakeups (e.g. a periodic device interrupt) are caught
+        // by the history when it is consistent enough to trust
+        fn typical_interval(history: &VecDeque<u64>) -> Option<u64> {
+            if history.len() < HISTORY_LEN / 2 {
+                return None;
+            }
+            let count = history.len() as u64;
+            let mean = history.iter().sum::<u64>() / count;
+            let variance = history.iter().map(|interval| interval.abs_diff(mean).pow(2)).sum::<u64>() / count;
+            // Standard deviation within a quarter of the mean
+            (variance * 16 <= mean * mean).then_some(mean)
+        }
+
+        pub fn predict(&self, core: usize, next_event_us: Option<u64>) -> u64 {
+            let timer = next_event_us.unwrap_or(MAX_IDLE_US);
+            let typical = self
+                .cores
+                .get(core)
+                .and_then(|idle| Self::typical_interval(&idle.lock().unwrap().history));
+            typical.map_or(timer, |typical| typical.min(timer))
+        }
+
+        // Index into states() of the state to enter
+        pub fn select(&self, core: usize, next_event_us: Option<u64>) -> usize {
+            let predicted = self.predict(core, next_event_us);
+            let limit = self.constraints.current().unwrap_or(u32::MAX);
+            self.states
+                .iter()
+                .rposition(|state| state.target_residency as u64 <= predicted && state.exit_latency <= limit)
+                .unwrap_or(0)
+        }
+
+        // Feeds back how long the core actually slept in the chosen state
+        pub fn reflect(&self, core: usize, state: usize, idle_us: u64) {
+            let Some(idle) = self.cores.get(core) else {
+                return;
+            };
+            let mut idle = idle.lock().unwrap();
+            if idle.history.len() == HISTORY_LEN {
+                idle.history.pop_front();
+            }
+            idle.history.push_back(idle_us);
+            if let Some(usage) = idle.usage.get_mut(state) {
+                usage.entries += 1;
+                usage.residency_us += idle_us;
+            }
+        }
+
+        pub fn usage(&self, core: usize) -> Vec<StateUsage> {
+            self.cores.get(core).map_or_else(Vec::new, |idle| idle.lock().unwrap().usage.clone())
+        }
+
+        // One pass of the idle loop: called when the core has nothing to
+        // run, with the time until its next timer event if one is armed
+        pub fn idle(&self, core: usize, next_event_us: Option<u64>, driver: &dyn IdleDriver) -> Duration {
+            let state = self.select(core, next_event_us);
+            let deadline = Duration::from_micros(next_event_us.unwrap_or(MAX_IDLE_US));
+            let slept = driver.enter(core, &self.states[state], deadline);
+            self.reflect(core, state, slept.as_micros() as u64);
+            slept
+        }
+    }
+}
@@ -0,0 +1,14 @@
+// src/kernel/power/mod.rs
+
+pub mod button;
+pub mod dpms;
+pub mod fan;
+pub mod hibernate;
+pub mod idle;
+pub mod policy;
+pub mod profile;
+pub mod pstate;
+pub mod rapl;
+pub mod runtime;
+pub mod thermal;
+pub mod wake;
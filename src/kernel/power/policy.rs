@@ -0,0 +1,412 @@
+// src/kernel/power/policy.rs
+
+pub mod policy {
+    use crate::kconfig::kconfig;
+    use crate::power::thermal::thermal::{registry, ThermalZone, TripType};
+    use std::collections::BTreeMap;
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::sync::{Mutex, OnceLock};
+
+    // Only for CPUs whose CPUID leaf 0x16 reports no maximum, as under
+    // most hypervisors; init sets the real one from the topology
+    const FALLBACK_MAX_FREQUENCY_MHZ: u32 = 3000;
+    const MIN_FREQUENCY_MHZ: u32 = 400;
+    const MAX_THROTTLE_LEVEL: u8 = 4;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum PolicyMode {
+        Performance,
+        Balanced,
+        PowerSaver,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct ComponentStates {
+        pub cpu_frequency_mhz: u32,
+        pub throttle_level: u8,
+        pub turbo_enabled: bool,
+        pub efficiency_cores_parked: bool,
+        // None until a driver with package power control is installed
+        pub power_limits: Option<PowerLimits>,
+        pub throttle_reasons: ThrottleReasons,
+    }
+
+    // Package power budget: PL1 is the sustained limit averaged over the
+    // tau window, PL2 the short burst limit
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct PowerLimits {
+        pub pl1_mw: u32,
+        pub pl2_mw: u32,
+        pub tau_ms: u32,
+        // Firmware locked the limits until the next reset
+        pub locked: bool,
+    }
+
+    // Why the cores are running below the requested frequency
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct ThrottleReasons {
+        pub prochot: bool,
+        pub thermal: bool,
+        pub pl1: bool,
+        pub pl2: bool,
+        pub max_turbo: bool,
+        pub electrical: bool,
+    }
+
+    impl ThrottleReasons {
+        pub fn any(&self) -> bool {
+            self.prochot || self.thermal || self.pl1 || self.pl2 || self.max_turbo || self.electrical
+        }
+    }
+
+    // User ceiling on top of the mode and the thermal limit, e.g. from a
+    // battery profile
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct PerformanceCap {
+        pub turbo: bool,
+        // 0-100
+        pub limit_percent: u32,
+    }
+
+    impl Default for PerformanceCap {
+        fn default() -> Self {
+            PerformanceCap {
+                turbo: true,
+                limit_percent: 100,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum LidAction {
+        Suspend,
+        Ignore,
+    }
+
+    // What the system does on its own for AC and lid events
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct EventActions {
+        pub lid_close: LidAction,
+        // Switch between the AC and battery profiles on plug/unplug
+        pub ac_profiles: bool,
+    }
+
+    impl Default for EventActions {
+        fn default() -> Self {
+            EventActions {
+                lid_close: LidAction::Suspend,
+                ac_profiles: true,
+            }
+        }
+    }
+
+    // Stage of the thermal response currently in force
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ThermalAction {
+        Normal,
+        CapTurbo,
+        // Throttle level, 2 and up
+        CapFrequency(u8),
+        ParkEfficiencyCores,
+        EmergencyShutdown,
+    }
+
+    // Most severe trip of one zone at its last reading, and whether that
+    // reading was at or above the passive trip (as opposed to cooling
+    // within its hysteresis band)
+    #[derive(Clone, Copy)]
+    struct ZoneTrip {
+        trip: Option<TripType>,
+        rising: bool,
+    }
+
+    // What the policy asks of the CPUs; a scaling driver turns it into
+    // hardware requests
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct PerformanceTarget {
+        pub mode: PolicyMode,
+        // Thermal cap on the usable performance range, 0-100
+        pub limit_percent: u32,
+        pub turbo: bool,
+        // Hybrid parts only: hold the E-cores at their lowest level
+        pub park_efficiency_cores: bool,
+    }
+
+    pub trait PerformanceScaling: Send {
+        // Returns the highest frequency any core may now reach, in MHz
+        fn apply(&mut self, target: PerformanceTarget) -> Result<u32, &'static str>;
+
+        fn power_limits(&self) -> Option<PowerLimits> {
+            None
+        }
+
+        fn throttle_reasons(&self) -> ThrottleReasons {
+            ThrottleReasons::default()
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct ModeChange {
+        pub previous: PolicyMode,
+        pub current: PolicyMode,
+    }
+
+    pub struct PolicyManager {
+        mode: Mutex<PolicyMode>,
+        states: Mutex<ComponentStates>,
+        cap: Mutex<PerformanceCap>,
+        event_actions: Mutex<EventActions>,
+        subscribers: Mutex<Vec<Sender<ModeChange>>>,
+        scaling: Mutex<Option<Box<dyn PerformanceScaling>>>,
+        zones: Mutex<BTreeMap<String, ZoneTrip>>,
+        // Zone name and temperature; called once when a critical trip fires
+        critical_handler: Mutex<Option<CriticalHandler>>,
+        shutdown_requested: Mutex<bool>,
+        max_frequency_mhz: Mutex<u32>,
+    }
+
+    type CriticalHandler = Box<dyn FnMut(&str, i32) + Send>;
+
+    impl PolicyManager {
+        pub fn new(mode: PolicyMode) -> Self {
+            let manager = PolicyManager {
+                mode: Mutex::new(mode),
+                states: Mutex::new(ComponentStates {
+                    cpu_frequency_mhz: FALLBACK_MAX_FREQUENCY_MHZ,
+                    throttle_level: 0,
+                    turbo_enabled: true,
+                    efficiency_cores_parked: false,
+                    power_limits: None,
+                    throttle_reasons: ThrottleReasons::default(),
+                }),
+                cap: Mutex::new(PerformanceCap::default()),
+                event_actions: Mutex::new(EventActions::default()),
+                subscribers: Mutex::new(Vec::new()),
+                scaling: Mutex::new(None),
+                zones: Mutex::new(BTreeMap::new()),
+                critical_handler: Mutex::new(None),
+                shutdown_requested: Mutex::new(false),
+                max_frequency_mhz: Mutex::new(FALLBACK_MAX_FREQUENCY_MHZ),
+            };
+            manager.apply();
+            manager
+        }
+
+        pub fn set_mode(&self, mode: PolicyMode) {
+            let mut current = self.mode.lock().unwrap();
+            let previous = std::mem::replace(&mut *current, mode);
+            if previous == mode {
+                return;
+            }
+            log::info!("Switching power policy to {:?}", mode);
+            let change = ModeChange { previous, current: mode };
+            // Sent under the mode lock, so concurrent switches reach every
+            // subscriber in the order they were made. Receivers that went
+            // away are dropped from the list.
+            self.subscribers.lock().unwrap().retain(|subscriber| subscriber.send(change).is_ok());
+            drop(current);
+            self.apply();
+        }
+
+        pub fn current_mode(&self) -> PolicyMode {
+            *self.mode.lock().unwrap()
+        }
+
+        pub fn set_performance_cap(&self, cap: PerformanceCap) {
+            let cap = PerformanceCap {
+                limit_percent: cap.limit_percent.min(100),
+                ..cap
+            };
+            if std::mem::replace(&mut *self.cap.lock().unwrap(), cap) != cap {
+                self.apply();
+            }
+        }
+
+        pub fn performance_cap(&self) -> PerformanceCap {
+            *self.cap.lock().unwrap()
+        }
+
+        pub fn set_event_actions(&self, actions: EventActions) {
+            *self.event_actions.lock().unwrap() = actions;
+        }
+
+        pub fn event_actions(&self) -> EventActions {
+            *self.event_actions.lock().unwrap()
+        }
+
+        // Every later mode switch is delivered to the returned receiver
+        pub fn subscribe(&self) -> Receiver<ModeChange> {
+            let (sender, receiver) = mpsc::channel();
+            self.subscribers.lock().unwrap().push(sender);
+            receiver
+        }
+
+        // Without a handler a critical trip is only logged; the platform
+        // code installs one that powers the machine off
+        pub fn set_critical_handler(&self, handler: CriticalHandler) {
+            *self.critical_handler.lock().unwrap() = Some(handler);
+        }
+
+        // Temperature in degrees Celsius of the given zone. The response
+        // follows the most severe trip across all zones: above the passive
+        // trip each evaluation steps the throttle up (turbo first, then
+        // frequency), the hot trip also parks the E-cores and the critical
+        // trip requests an emergency shutdown. Once every zone has cleared
+        // its passive trip the throttle steps back down one level at a time.
+        pub fn evaluate_policy(&self, zone: &ThermalZone, temperature: i32) -> ThermalAction {
+            let trip = zone.update_trips(temperature);
+            let rising = zone
+                .trip_points()
+                .iter()
+                .any(|point| point.kind == TripType::Passive && temperature >= point.temperature);
+            let (worst, rising) = {
+                let mut zones = self.zones.lock().unwrap();
+                zones.insert(zone.name().to_string(), ZoneTrip { trip, rising });
+                zones
+                    .values()
+                    .fold((None, false), |(worst, rising), zone| (worst.max(zone.trip), rising || zone.rising))
+            };
+
+            if worst == Some(TripType::Critical) {
+                let already = std::mem::replace(&mut *self.shutdown_requested.lock().unwrap(), true);
+                if !already {
+                    log::error!("Critical temperature {}C in {}, emergency shutdown", temperature, zone.name());
+                    if let Some(handler) = self.critical_handler.lock().unwrap().as_mut() {
+                        handler(zone.name(), temperature);
+                    }
+                }
+                return ThermalAction::EmergencyShutdown;
+            }
+
+            self.park_efficiency_cores(worst == Some(TripType::Hot));
+            let level = self.states.lock().unwrap().throttle_level;
+            match worst {
+                Some(_) if rising => self.throttle_components(level.saturating_add(1)),
+                Some(_) => {}
+                None => self.throttle_components(level.saturating_sub(1)),
+            }
+            match (worst, self.states.lock().unwrap().throttle_level) {
+                (Some(TripType::Hot), _) => ThermalAction::ParkEfficiencyCores,
+                (_, 0) => ThermalAction::Normal,
+                (_, 1) => ThermalAction::CapTurbo,
+                (_, level) => ThermalAction::CapFrequency(level),
+            }
+        }
+
+        // Reads and evaluates every registered zone
+        pub fn evaluate_zones(&self) -> ThermalAction {
+            let mut action = ThermalAction::Normal;
+            for zone in registry().zones() {
+                if let Ok(millicelsius) = zone.temperature() {
+                    action = self.evaluate_policy(&zone, millicelsius / 1000);
+                }
+            }
+            action
+        }
+
+        fn park_efficiency_cores(&self, park: bool) {
+            {
+                let mut states = self.states.lock().unwrap();
+                if states.efficiency_cores_parked == park {
+                    return;
+                }
+                log::info!("{} efficiency cores", if park { "Parking" } else { "Unparking" });
+                states.efficiency_cores_parked = park;
+            }
+            self.apply();
+        }
+
+        pub fn throttle_components(&self, level: u8) {
+            let level = level.min(MAX_THROTTLE_LEVEL);
+            {
+                let mut states = self.states.lock().unwrap();
+                if states.throttle_level == level {
+                    return;
+                }
+                log::info!("Setting component throttle level {}", level);
+                states.throttle_level = level;
+            }
+            self.apply();
+        }
+
+        // The highest frequency the CPUs reach, from their topology, for the
+        // estimate used without a scaling driver
+        pub fn set_max_frequency(&self, mhz: u32) {
+            *self.max_frequency_mhz.lock().unwrap() = mhz.max(MIN_FREQUENCY_MHZ);
+            self.apply();
+        }
+
+        // Hands frequency control to a hardware driver (HWP or legacy
+        // P-states) and applies the current policy through it
+        pub fn set_scaling_driver(&self, driver: Box<dyn PerformanceScaling>) {
+            *self.scaling.lock().unwrap() = Some(driver);
+            self.apply();
+        }
+
+        // Level 1 only drops turbo, each further level removes another
+        // share of the frequency range
+        fn limit_percent(level: u8) -> u32 {
+            let steps = MAX_THROTTLE_LEVEL as u32;
+            100 * (steps + 1 - level.max(1) as u32) / steps
+        }
+
+        // Estimate used while no scaling driver is installed
+        pub fn calculate_target_frequency(&self) -> u32 {
+            let mode = *self.mode.lock().unwrap();
+            let level = self.states.lock().unwrap().throttle_level;
+            let scale = match mode {
+                PolicyMode::Performance => 100,
+                PolicyMode::Balanced => 75,
+                PolicyMode::PowerSaver => 50,
+            };
+            let frequency = *self.max_frequency_mhz.lock().unwrap() * scale / 100;
+            let limit = Self::limit_percent(level).min(self.cap.lock().unwrap().limit_percent);
+            let frequency = frequency * limit / 100;
+            frequency.max(MIN_FREQUENCY_MHZ)
+        }
+
+        fn apply(&self) {
+            let mode = *self.mode.lock().unwrap();
+            let (level, parked) = {
+                let states = self.states.lock().unwrap();
+                (states.throttle_level, states.efficiency_cores_parked)
+            };
+            let cap = *self.cap.lock().unwrap();
+            let target = PerformanceTarget {
+                mode,
+                limit_percent: Self::limit_percent(level).min(cap.limit_percent),
+                turbo: mode != PolicyMode::PowerSaver && level == 0 && cap.turbo,
+                park_efficiency_cores: parked,
+            };
+            let mut scaling = self.scaling.lock().unwrap();
+            let frequency = match scaling.as_mut().map(|driver| driver.apply(target)) {
+                Some(Ok(frequency)) => frequency,
+                Some(Err(error)) => {
+                    log::warn!("P-state update failed: {}", error);
+                    self.calculate_target_frequency()
+                }
+                None => self.calculate_target_frequency(),
+            };
+            let power_limits = scaling.as_ref().and_then(|driver| driver.power_limits());
+            drop(scaling);
+            let mut states = self.states.lock().unwrap();
+            states.cpu_frequency_mhz = frequency;
+            states.turbo_enabled = target.turbo;
+            states.power_limits = power_limits;
+        }
+
+        // Throttle reasons are read fresh from the driver
+        pub fn component_snapshot(&self) -> ComponentStates {
+            let reasons = self.scaling.lock().unwrap().as_ref().map(|driver| driver.throttle_reasons());
+            let mut states = self.states.lock().unwrap();
+            states.throttle_reasons = reasons.unwrap_or_default();
+            *states
+        }
+    }
+
+    // Starts in the power mode the kernel was configured with
+    pub fn policy_manager() -> &'static PolicyManager {
+        static MANAGER: OnceLock<PolicyManager> = OnceLock::new();
+        MANAGER.get_or_init(|| PolicyManager::new(kconfig::ACTIVE.power_mode))
+    }
+}
@@ -0,0 +1,324 @@
+// src/kernel/power/profile.rs
+
+pub mod profile {
+    use crate::power::policy::policy::{PerformanceCap, PolicyManager, PolicyMode};
+    use crate::vxchan::vxchan::VXChanManager;
+    use crate::vxfs::vxfs::VXFS;
+    use std::collections::BTreeMap;
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::sync::Mutex;
+
+    pub const REQUEST_CHANNEL: &str = "vxpower";
+    pub const REPLY_CHANNEL: &str = "vxpower.reply";
+    // "profile NAME" after every switch, for clients without a receiver
+    pub const EVENT_CHANNEL: &str = "vxpower.events";
+
+    pub const AC_PROFILE: &str = "ac";
+    pub const BATTERY_PROFILE: &str = "battery";
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct PolicyProfile {
+        pub mode: PolicyMode,
+        pub cap: PerformanceCap,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct ProfileChange {
+        pub previous: String,
+        pub current: String,
+    }
+
+    fn mode_name(mode: PolicyMode) -> &'static str {
+        match mode {
+            PolicyMode::Performance => "performance",
+            PolicyMode::Balanced => "balanced",
+            PolicyMode::PowerSaver => "powersaver",
+        }
+    }
+
+    fn parse_mode(name: &str) -> Result<PolicyMode, &'static str> {
+        match name {
+            "performance" => Ok(PolicyMode::Performance),
+            "balanced" => Ok(PolicyMode::Balanced),
+            "powersaver" => Ok(PolicyMode::PowerSaver),
+            _ => Err("Unknown policy mode"),
+        }
+    }
+
+    fn parse_bool(value: &str) -> Result<bool, &'static str> {
+        match value {
+            "on" => Ok(true),
+            "off" => Ok(false),
+            _ => Err("Expected on or off"),
+        }
+    }
+
+    fn describe(name: &str, profile: &PolicyProfile) -> String {
+        format!(
+            "{} mode={} turbo={} limit={}",
+            name,
+            mode_name(profile.mode),
+            if profile.cap.turbo { "on" } else { "off" },
+            profile.cap.limit_percent
+        )
+    }
+
+    struct ProfileState {
+        profiles: BTreeMap<String, PolicyProfile>,
+        active: String,
+        subscribers: Vec<Sender<ProfileChange>>,
+    }
+
+    // Named policy settings. The AC and battery profiles always exist so
+    // the lid/AC handler has something to switch between.
+    pub struct ProfileManager {
+        state: Mutex<ProfileState>,
+        // Where the profiles are persisted; memory only when None
+        path: Option<String>,
+    }
+
+    impl ProfileManager {
+        pub fn new(path: Option<&str>) -> Self {
+            let mut profiles = BTreeMap::new();
+            profiles.insert(
+                AC_PROFILE.to_string(),
+                PolicyProfile {
+                    mode: PolicyMode::Balanced,
+                    cap: PerformanceCap::default(),
+                },
+            );
+            profiles.insert(
+                BATTERY_PROFILE.to_string(),
+                PolicyProfile {
+                    mode: PolicyMode::PowerSaver,
+                    cap: PerformanceCap {
+                        turbo: false,
+                        limit_percent: 80,
+                    },
+                },
+            );
+            ProfileManager {
+                state: Mutex::new(ProfileState {
+                    profiles,
+                    active: AC_PROFILE.to_string(),
+                    subscribers: Vec::new(),
+                }),
+                path: path.map(str::to_string),
+            }
+        }
+
+        // Starts from the defaults when nothing has been saved yet
+        pub fn load(path: &str) -> Result<Self, &'static str> {
+            let manager = ProfileManager::new(Some(path));
+            let contents = match VXFS::new().read_file(path) {
+                Ok(contents) => contents,
+                Err(_) => return Ok(manager),
+            };
+            {
+                let mut state = manager.state.lock().unwrap();
+                let mut current: Option<String> = None;
+                for line in contents.lines().map(str::trim).filter(|line| !line.is_empty()) {
+                    if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                        state.profiles.entry(name.to_string()).or_insert(PolicyProfile {
+                            mode: PolicyMode::Balanced,
+                            cap: PerformanceCap::default(),
+                        });
+                        current = Some(name.to_string());
+                        continue;
+                    }
+                    let (key, value) = line.split_once('=').ok_or("Malformed profile line")?;
+                    let Some(name) = &current else {
+                        match key {
+                            "active" => state.active = value.to_string(),
+                            _ => return Err("Unknown profile setting"),
+                        }
+                        continue;
+                    };
+                    let profile = state.profiles.get_mut(name).unwrap();
+                    match key {
+                        "mode" => profile.mode = parse_mode(value)?,
+                        "turbo" => profile.cap.turbo = parse_bool(value)?,
+                        "limit" => profile.cap.limit_percent = value.parse().map_err(|_| "Invalid limit")?,
+                        _ => return Err("Unknown profile setting"),
+                    }
+                }
+                if !state.profiles.contains_key(&state.active) {
+                    state.active = AC_PROFILE.to_string();
+                }
+            }
+            Ok(manager)
+        }
+
+        pub fn save(&self) -> Result<(), &'static str> {
+            let Some(path) = &self.path else {
+                return Ok(());
+            };
+            let contents = {
+                let state = self.state.lock().unwrap();
+                let mut contents = format!("active={}\n", state.active);
+                for (name, profile) in &state.profiles {
+                    contents += &format!(
+                        "[{}]\nmode={}\nturbo={}\nlimit={}\n",
+                        name,
+                        mode_name(profile.mode),
+                        if profile.cap.turbo { "on" } else { "off" },
+                        profile.cap.limit_percent
+                    );
+                }
+                contents
+            };
+            VXFS::new().write_file(path, &contents).map_err(|_| "Failed to save power profiles")
+        }
+
+        pub fn names(&self) -> Vec<String> {
+            self.state.lock().unwrap().profiles.keys().cloned().collect()
+        }
+
+        pub fn get(&self, name: &str) -> Option<PolicyProfile> {
+            self.state.lock().unwrap().profiles.get(name).copied()
+        }
+
+        pub fn active(&self) -> String {
+            self.state.lock().unwrap().active.clone()
+        }
+
+        // Creates or replaces a profile; the policy is not touched until
+        // the profile is switched to
+        pub fn set_profile(&self, name: &str, profile: PolicyProfile) -> Result<(), &'static str> {
+            if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == '[' || c == ']') {
+                return Err("Invalid profile name");
+            }
+            self.state.lock().unwrap().profiles.insert(name.to_string(), profile);
+            self.save()
+        }
+
+        pub fn remove_profile(&self, name: &str) -> Result<(), &'static str> {
+            {
+                let mut state = self.state.lock().unwrap();
+                if name == AC_PROFILE || name == BATTERY_PROFILE || name == state.active {
+                    return Err("Profile in use");
+                }
+                state.profiles.remove(name).ok_or("Profile not found")?;
+            }
+            self.save()
+        }
+
+        // Applies the active profile, e.g. once at boot
+        pub fn apply(&self, policy: &PolicyManager) {
+            let state = self.state.lock().unwrap();
+            let profile = state.profiles[&state.active];
+            policy.set_mode(profile.mode);
+            policy.set_performance_cap(profile.cap);
+        }
+
+        // Re-applies the profile even when it is already active, so a
+        // switch also undoes manual policy changes
+        pub fn switch(&self, name: &str, policy: &PolicyManager) -> Result<(), &'static str> {
+            {
+                let mut state = self.state.lock().unwrap();
+                let profile = *state.profiles.get(name).ok_or("Profile not found")?;
+                policy.set_mode(profile.mode);
+                policy.set_performance_cap(profile.cap);
+                let previous = std::mem::replace(&mut state.active, name.to_string());
+                if previous == name {
+                    return Ok(());
+                }
+                log::info!("Switching power profile to {}", name);
+                let change = ProfileChange {
+                    previous,
+                    current: name.to_string(),
+                };
+                state.subscribers.retain(|subscriber| subscriber.send(change.clone()).is_ok());
+            }
+            self.save()
+        }
+
+        pub fn subscribe(&self) -> Receiver<ProfileChange> {
+            let (sender, receiver) = mpsc::channel();
+            self.state.lock().unwrap().subscribers.push(sender);
+            receiver
+        }
+    }
+
+    // Grammar, one request per message:
+    //   list | show [NAME] | current | switch NAME
+    //   set NAME MODE (on|off) LIMIT | remove NAME
+    // Messages and replies are framed as in vxnetctl
+    pub struct ProfileService {
+        events: Receiver<ProfileChange>,
+    }
+
+    impl ProfileService {
+        pub fn new(manager: &VXChanManager, profiles: &ProfileManager) -> Result<Self, &'static str> {
+            manager.create_channel(REQUEST_CHANNEL)?;
+            manager.create_channel(REPLY_CHANNEL)?;
+            manager.create_channel(EVENT_CHANNEL)?;
+            Ok(ProfileService {
+                events: profiles.subscribe(),
+            })
+        }
+
+        fn execute(line: &str, profiles: &ProfileManager, policy: &PolicyManager) -> Result<String, &'static str> {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                ["list"] => Ok(profiles.names().join("\n")),
+                ["show"] => Self::execute(&format!("show {}", profiles.active()), profiles, policy),
+                ["show", name] => Ok(describe(name, &profiles.get(name).ok_or("Profile not found")?)),
+                ["current"] => {
+                    let (cap, states) = (policy.performance_cap(), policy.component_snapshot());
+                    Ok(format!(
+                        "profile={} mode={} turbo={} limit={} frequency={} throttle={}",
+                        profiles.active(),
+                        mode_name(policy.current_mode()),
+                        if states.turbo_enabled { "on" } else { "off" },
+                        cap.limit_percent,
+                        states.cpu_frequency_mhz,
+                        states.throttle_level
+                    ))
+                }
+                ["switch", name] => profiles.switch(name, policy).map(|_| String::new()),
+                ["set", name, mode, turbo, limit] => {
+                    let profile = PolicyProfile {
+                        mode: parse_mode(mode)?,
+                        cap: PerformanceCap {
+                            turbo: parse_bool(turbo)?,
+                            limit_percent: limit.parse().map_err(|_| "Invalid limit")?,
+                        },
+                    };
+                    profiles.set_profile(name, profile).map(|_| String::new())
+                }
+                ["remove", name] => profiles.remove_profile(name).map(|_| String::new()),
+                [] => Err("Empty request"),
+                _ => Err("Unknown command"),
+            }
+        }
+
+        // Answers queued requests and forwards profile changes to the
+        // event channel; returns how many requests were handled
+        pub fn poll(&mut self, manager: &VXChanManager, profiles: &ProfileManager, policy: &PolicyManager) -> Result<usize, &'static str> {
+            let mut count = 0;
+            while let Some(message) = manager.try_receive_message(REQUEST_CHANNEL)? {
+                let (id, line) = message.split_once(' ').unwrap_or((message.as_str(), ""));
+                let result = id
+                    .parse::<u64>()
+                    .map_err(|_| "Invalid request id")
+                    .and_then(|_| Self::execute(line, profiles, policy));
+                let reply = match result {
+                    Ok(body) if body.is_empty() => format!("{} ok", id),
+                    Ok(body) => format!("{} ok\n{}", id, body),
+                    Err(error) => format!("{} error {}", id, error),
+                };
+                manager.send_message(REPLY_CHANNEL, reply)?;
+                count += 1;
+            }
+            while let Ok(change) = self.events.try_recv() {
+                manager.send_message(EVENT_CHANNEL, format!("profile {}", change.current))?;
+            }
+            Ok(count)
+        }
+    }
+
+    pub fn send_request(manager: &VXChanManager, id: u64, request: &str) -> Result<(), &'static str> {
+        manager.send_message(REQUEST_CHANNEL, format!("{} {}", id, request))
+    }
+}
@@ -0,0 +1,356 @@
+// src/kernel/power/pstate.rs
+
+pub mod pstate {
+    use crate::drivers::msr::msr::MsrIo;
+    use crate::power::policy::policy::{PerformanceScaling, PerformanceTarget, PolicyMode, PowerLimits, ThrottleReasons};
+    use crate::topology::topology::{self, CoreType};
+
+    const CPUID_THERMAL_POWER_LEAF: u32 = 6;
+    const CPUID_HWP: u32 = 1 << 7;
+    const CPUID_HWP_EPP: u32 = 1 << 10;
+
+    const MSR_PLATFORM_INFO: u32 = 0xCE;
+    const IA32_PERF_CTL: u32 = 0x199;
+    const IA32_MISC_ENABLE: u32 = 0x1A0;
+    const MSR_TURBO_RATIO_LIMIT: u32 = 0x1AD;
+    const MSR_RAPL_POWER_UNIT: u32 = 0x606;
+    const MSR_PKG_POWER_LIMIT: u32 = 0x610;
+    const MSR_CORE_PERF_LIMIT_REASONS: u32 = 0x64F;
+    const IA32_PM_ENABLE: u32 = 0x770;
+    const IA32_HWP_CAPABILITIES: u32 = 0x771;
+    const IA32_HWP_REQUEST: u32 = 0x774;
+
+    const MISC_ENABLE_TURBO_DISABLE: u64 = 1 << 38;
+
+    const POWER_LIMIT_MASK: u64 = 0x7FFF;
+    const PL1_ENABLE_CLAMP: u64 = (1 << 15) | (1 << 16);
+    const PL2_ENABLE_CLAMP: u64 = (1 << 47) | (1 << 48);
+    const PL1_WINDOW_SHIFT: u32 = 17;
+    const POWER_LIMIT_LOCK: u64 = 1 << 63;
+
+    const LIMIT_PROCHOT: u64 = 1 << 0;
+    const LIMIT_THERMAL: u64 = 1 << 1;
+    const LIMIT_VR_THERMAL: u64 = 1 << 6;
+    const LIMIT_ELECTRICAL: u64 = 1 << 8;
+    const LIMIT_PL1: u64 = 1 << 10;
+    const LIMIT_PL2: u64 = 1 << 11;
+    const LIMIT_MAX_TURBO: u64 = 1 << 12;
+    // Ratios and HWP performance levels are in units of the 100 MHz bus
+    // clock (hybrid P-cores scale slightly differently; close enough for
+    // reporting)
+    const BUS_CLOCK_MHZ: u32 = 100;
+
+    // Energy/performance preference hints; 0 favours performance
+    const EPP_PERFORMANCE: u8 = 0x00;
+    const EPP_BALANCE_PERFORMANCE: u8 = 0x80;
+    const EPP_BALANCE_POWER: u8 = 0xC0;
+    const EPP_POWER: u8 = 0xFF;
+
+    // Performance levels from IA32_HWP_CAPABILITIES
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct HwpCapabilities {
+        pub highest: u8,
+        pub guaranteed: u8,
+        pub most_efficient: u8,
+        pub lowest: u8,
+    }
+
+    impl HwpCapabilities {
+        fn decode(value: u64) -> Self {
+            HwpCapabilities {
+                highest: value as u8,
+                guaranteed: (value >> 8) as u8,
+                most_efficient: (value >> 16) as u8,
+                lowest: (value >> 24) as u8,
+            }
+        }
+    }
+
+    // One core's IA32_HWP_REQUEST; desired 0 leaves selection to hardware
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct HwpRequest {
+        pub min: u8,
+        pub max: u8,
+        pub desired: u8,
+        pub epp: u8,
+    }
+
+    impl HwpRequest {
+        pub fn encode(&self) -> u64 {
+            self.min as u64 | (self.max as u64) << 8 | (self.desired as u64) << 16 | (self.epp as u64) << 24
+        }
+
+        pub fn decode(value: u64) -> Self {
+            HwpRequest {
+                min: value as u8,
+                max: (value >> 8) as u8,
+                desired: (value >> 16) as u8,
+                epp: (value >> 24) as u8,
+            }
+        }
+    }
+
+    // Non-HWP ratios from MSR_PLATFORM_INFO and MSR_TURBO_RATIO_LIMIT
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct LegacyRatios {
+        pub min: u8,
+        pub max_non_turbo: u8,
+        pub max_turbo: u8,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum ScalingMethod {
+        // Per-core capabilities, indexed by CPU
+        Hwp { cores: Vec<HwpCapabilities>, epp: bool },
+        Legacy(LegacyRatios),
+    }
+
+    // Power and time units from MSR_RAPL_POWER_UNIT, as 1/2^n watts and
+    // seconds
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct RaplUnits {
+        power_shift: u32,
+        time_shift: u32,
+    }
+
+    impl RaplUnits {
+        fn milliwatts(&self, raw: u64) -> u32 {
+            (((raw & POWER_LIMIT_MASK) * 1000) >> self.power_shift) as u32
+        }
+
+        fn raw_power(&self, milliwatts: u32) -> u64 {
+            ((milliwatts as u64) << self.power_shift).div_ceil(1000).min(POWER_LIMIT_MASK)
+        }
+
+        // Time windows are 2^y * (1 + z/4) time units, y in bits 0-4 and z
+        // in bits 5-6 of the field
+        fn window_ms(&self, field: u64) -> u32 {
+            let (y, z) = (field & 0x1F, (field >> 5) & 0x3);
+            ((((1u64 << y) * (4 + z) * 1000) >> self.time_shift) / 4) as u32
+        }
+
+        fn window_field(&self, milliseconds: u32) -> u64 {
+            (0..32u64)
+                .flat_map(|y| (0..4u64).map(move |z| (z << 5) | y))
+                .min_by_key(|field| self.window_ms(*field).abs_diff(milliseconds))
+                .unwrap()
+        }
+    }
+
+    pub fn is_efficiency_core<M: MsrIo>(msr: &M, cpu: usize) -> bool {
+        topology::core_type(msr, cpu) == CoreType::Efficiency
+    }
+
+    // Drives CPU performance states: HWP hints when the CPU supports them,
+    // otherwise target ratios through IA32_PERF_CTL
+    pub struct PStateDriver<M: MsrIo> {
+        msr: M,
+        method: ScalingMethod,
+        // Package power control, with the firmware's limits as the baseline
+        // for the per-mode budgets; None without RAPL
+        power: Option<(RaplUnits, PowerLimits)>,
+    }
+
+    impl<M: MsrIo> PStateDriver<M> {
+        // Enabling HWP is one-way until the next reset
+        pub fn init(msr: M) -> Result<Self, &'static str> {
+            let (eax, _, _, _) = msr.cpuid(0, CPUID_THERMAL_POWER_LEAF);
+            let method = if eax & CPUID_HWP != 0 {
+                let mut cores = Vec::new();
+                for cpu in 0..msr.cpu_count() {
+                    msr.wrmsr(cpu, IA32_PM_ENABLE, 1)?;
+                    cores.push(HwpCapabilities::decode(msr.rdmsr(cpu, IA32_HWP_CAPABILITIES)?));
+                }
+                log::info!("HWP enabled on {} CPU(s)", cores.len());
+                ScalingMethod::Hwp {
+                    cores,
+                    epp: eax & CPUID_HWP_EPP != 0,
+                }
+            } else {
+                let info = msr.rdmsr(0, MSR_PLATFORM_INFO)?;
+                let max_non_turbo = (info >> 8) as u8;
+                let max_turbo = msr
+                    .rdmsr(0, MSR_TURBO_RATIO_LIMIT)
+                    .map(|limits| limits as u8)
+                    .unwrap_or(max_non_turbo)
+                    .max(max_non_turbo);
+                log::warn!("HWP unavailable, using legacy P-states");
+                ScalingMethod::Legacy(LegacyRatios {
+                    min: (info >> 40) as u8,
+                    max_non_turbo,
+                    max_turbo,
+                })
+            };
+            let units = msr.rdmsr(0, MSR_RAPL_POWER_UNIT).ok().map(|units| RaplUnits {
+                power_shift: (units & 0xF) as u32,
+                time_shift: ((units >> 16) & 0xF) as u32,
+            });
+            let mut driver = PStateDriver {
+                msr,
+                method,
+                power: None,
+            };
+            if let Some(units) = units {
+                driver.power = driver.read_power_limits(units).ok().map(|limits| (units, limits));
+            }
+            Ok(driver)
+        }
+
+        fn read_power_limits(&self, units: RaplUnits) -> Result<PowerLimits, &'static str> {
+            let raw = self.msr.rdmsr(0, MSR_PKG_POWER_LIMIT)?;
+            Ok(PowerLimits {
+                pl1_mw: units.milliwatts(raw),
+                pl2_mw: units.milliwatts(raw >> 32),
+                tau_ms: units.window_ms(raw >> PL1_WINDOW_SHIFT),
+                locked: raw & POWER_LIMIT_LOCK != 0,
+            })
+        }
+
+        // Firmware defaults read at init
+        pub fn default_power_limits(&self) -> Option<PowerLimits> {
+            self.power.map(|(_, limits)| limits)
+        }
+
+        // Programs PL1, PL2 and tau; the PL2 time window is left as set by
+        // firmware
+        pub fn set_power_limits(&self, limits: PowerLimits) -> Result<(), &'static str> {
+            let (units, _) = self.power.ok_or("Package power limits not supported")?;
+            if limits.pl1_mw == 0 || limits.pl2_mw < limits.pl1_mw {
+                return Err("Invalid package power limits");
+            }
+            let raw = self.msr.rdmsr(0, MSR_PKG_POWER_LIMIT)?;
+            if raw & POWER_LIMIT_LOCK != 0 {
+                return Err("Package power limits locked by firmware");
+            }
+            let pl2_window = raw & (0x7F << 49);
+            let value = units.raw_power(limits.pl1_mw)
+                | PL1_ENABLE_CLAMP
+                | units.window_field(limits.tau_ms) << PL1_WINDOW_SHIFT
+                | units.raw_power(limits.pl2_mw) << 32
+                | PL2_ENABLE_CLAMP
+                | pl2_window;
+            self.msr.wrmsr(0, MSR_PKG_POWER_LIMIT, value)
+        }
+
+        // Performance keeps the firmware budget, Balanced trims the burst
+        // limit halfway to PL1 and PowerSaver drops bursts altogether with a
+        // lower sustained limit
+        fn apply_power_budget(&self, mode: PolicyMode) -> Result<(), &'static str> {
+            let Some((_, defaults)) = self.power else {
+                return Ok(());
+            };
+            if defaults.locked {
+                return Ok(());
+            }
+            let (pl1_mw, pl2_mw) = match mode {
+                PolicyMode::Performance => (defaults.pl1_mw, defaults.pl2_mw),
+                PolicyMode::Balanced => (defaults.pl1_mw, (defaults.pl1_mw + defaults.pl2_mw) / 2),
+                PolicyMode::PowerSaver => (defaults.pl1_mw * 3 / 4, defaults.pl1_mw * 3 / 4),
+            };
+            self.set_power_limits(PowerLimits {
+                pl1_mw,
+                pl2_mw,
+                ..defaults
+            })
+        }
+
+        pub fn method(&self) -> &ScalingMethod {
+            &self.method
+        }
+
+        pub fn hwp_request(&self, cpu: usize) -> Result<HwpRequest, &'static str> {
+            self.msr.rdmsr(cpu, IA32_HWP_REQUEST).map(HwpRequest::decode)
+        }
+
+        fn apply_hwp(&self, cores: &[HwpCapabilities], epp: bool, target: PerformanceTarget) -> Result<u32, &'static str> {
+            let preference = match target.mode {
+                PolicyMode::Performance => EPP_PERFORMANCE,
+                PolicyMode::Balanced => EPP_BALANCE_PERFORMANCE,
+                PolicyMode::PowerSaver => EPP_BALANCE_POWER,
+            };
+            let mut peak = 0;
+            for (cpu, caps) in cores.iter().enumerate() {
+                // A parked core is held at its floor
+                if target.park_efficiency_cores && is_efficiency_core(&self.msr, cpu) {
+                    let request = HwpRequest {
+                        min: caps.lowest,
+                        max: caps.lowest,
+                        desired: 0,
+                        epp: if epp { EPP_POWER } else { 0 },
+                    };
+                    self.msr.wrmsr(cpu, IA32_HWP_REQUEST, request.encode())?;
+                    continue;
+                }
+                let ceiling = if target.turbo { caps.highest } else { caps.guaranteed };
+                let range = ceiling.saturating_sub(caps.lowest) as u32;
+                let max = caps.lowest + (range * target.limit_percent.min(100) / 100) as u8;
+                // Power saving keeps the floor at the efficient point instead
+                // of letting the core idle along at its lowest level
+                let min = match target.mode {
+                    PolicyMode::PowerSaver => caps.lowest,
+                    _ => caps.most_efficient.clamp(caps.lowest, max),
+                };
+                let request = HwpRequest {
+                    min,
+                    max,
+                    desired: 0,
+                    epp: if epp { preference } else { 0 },
+                };
+                self.msr.wrmsr(cpu, IA32_HWP_REQUEST, request.encode())?;
+                peak = peak.max(max as u32 * BUS_CLOCK_MHZ);
+            }
+            Ok(peak)
+        }
+
+        fn apply_legacy(&self, ratios: LegacyRatios, target: PerformanceTarget) -> Result<u32, &'static str> {
+            // Without EPP the mode can only scale the ratio itself. Pre-HWP
+            // parts have no E-cores, so parking does not apply.
+            let scale = match target.mode {
+                PolicyMode::Performance => 100,
+                PolicyMode::Balanced => 75,
+                PolicyMode::PowerSaver => 50,
+            };
+            let ceiling = if target.turbo { ratios.max_turbo } else { ratios.max_non_turbo };
+            let range = ceiling.saturating_sub(ratios.min) as u32;
+            let ratio = ratios.min + (range * scale / 100 * target.limit_percent.min(100) / 100) as u8;
+            for cpu in 0..self.msr.cpu_count() {
+                let misc = self.msr.rdmsr(cpu, IA32_MISC_ENABLE).unwrap_or(0);
+                let misc = if target.turbo {
+                    misc & !MISC_ENABLE_TURBO_DISABLE
+                } else {
+                    misc | MISC_ENABLE_TURBO_DISABLE
+                };
+                self.msr.wrmsr(cpu, IA32_MISC_ENABLE, misc)?;
+                self.msr.wrmsr(cpu, IA32_PERF_CTL, (ratio as u64) << 8)?;
+            }
+            Ok(ratio as u32 * BUS_CLOCK_MHZ)
+        }
+    }
+
+    impl<M: MsrIo> PerformanceScaling for PStateDriver<M> {
+        fn apply(&mut self, target: PerformanceTarget) -> Result<u32, &'static str> {
+            self.apply_power_budget(target.mode)?;
+            match &self.method {
+                ScalingMethod::Hwp { cores, epp } => self.apply_hwp(cores, *epp, target),
+                ScalingMethod::Legacy(ratios) => self.apply_legacy(*ratios, target),
+            }
+        }
+
+        fn power_limits(&self) -> Option<PowerLimits> {
+            let (units, _) = self.power?;
+            self.read_power_limits(units).ok()
+        }
+
+        fn throttle_reasons(&self) -> ThrottleReasons {
+            let status = self.msr.rdmsr(0, MSR_CORE_PERF_LIMIT_REASONS).unwrap_or(0);
+            ThrottleReasons {
+                prochot: status & LIMIT_PROCHOT != 0,
+                thermal: status & LIMIT_THERMAL != 0,
+                pl1: status & LIMIT_PL1 != 0,
+                pl2: status & LIMIT_PL2 != 0,
+                max_turbo: status & LIMIT_MAX_TURBO != 0,
+                electrical: status & (LIMIT_VR_THERMAL | LIMIT_ELECTRICAL) != 0,
+            }
+        }
+    }
+}
@@ -0,0 +1,195 @@
+// src/kernel/power/rapl.rs
+
+pub mod rapl {
+    use crate::drivers::msr::msr::MsrIo;
+    use crate::power::policy::policy::PolicyMode;
+    use crate::topology::topology::{CoreType, Topology};
+
+    const IA32_TIME_STAMP_COUNTER: u32 = 0x10;
+    const IA32_MPERF: u32 = 0xE7;
+    // Instructions retired
+    const IA32_FIXED_CTR0: u32 = 0x309;
+    const IA32_FIXED_CTR_CTRL: u32 = 0x38D;
+    const IA32_PERF_GLOBAL_CTRL: u32 = 0x38F;
+    const MSR_RAPL_POWER_UNIT: u32 = 0x606;
+    const MSR_PKG_ENERGY_STATUS: u32 = 0x611;
+    const MSR_PP0_ENERGY_STATUS: u32 = 0x639;
+
+    // Architectural performance monitoring: version in EAX[7:0], fixed
+    // counters in EDX[4:0]
+    const CPUID_PERFMON_LEAF: u32 = 0x0A;
+    // Counter 0's field of IA32_FIXED_CTR_CTRL, counting in ring 0 and 3
+    const FIXED_CTR0_FIELD: u64 = 0xF;
+    const FIXED_CTR0_OS_USR: u64 = 0x3;
+    const GLOBAL_FIXED_CTR0: u64 = 1 << 32;
+    // The fixed counters are 48 bits wide
+    const COUNTER_MASK: u64 = (1 << 48) - 1;
+
+    // Per-core activity over one sampling interval
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct CoreActivity {
+        // Share of the interval spent in C0, 0-100
+        pub utilization: u32,
+        // From fixed counter 0; zero when the PMU is not set up
+        pub instructions: u64,
+    }
+
+    #[derive(Debug, Clone, Default, PartialEq, Eq)]
+    pub struct EnergySample {
+        pub package_uj: u64,
+        // PP0 (all cores); None on parts without the domain
+        pub cores_uj: Option<u64>,
+        pub cores: Vec<CoreActivity>,
+    }
+
+    impl EnergySample {
+        pub fn instructions(&self) -> u64 {
+            self.cores.iter().map(|core| core.instructions).sum()
+        }
+
+        // Package energy per retired instruction over the interval
+        pub fn nanojoules_per_instruction(&self) -> Option<u64> {
+            let instructions = self.instructions();
+            (instructions > 0).then(|| self.package_uj * 1000 / instructions)
+        }
+
+        // The same for some of the CPUs, charging them the share of the
+        // package energy that their busy time makes up
+        pub fn nanojoules_per_instruction_on(&self, cpus: &[usize]) -> Option<u64> {
+            let busy = |cpu: &usize| self.cores.get(*cpu).map_or(0, |core| core.utilization as u64);
+            let total: u64 = (0..self.cores.len()).map(|cpu| busy(&cpu)).sum();
+            let instructions: u64 = cpus.iter().filter_map(|cpu| self.cores.get(*cpu)).map(|core| core.instructions).sum();
+            if total == 0 || instructions == 0 {
+                return None;
+            }
+            let energy_uj = self.package_uj * cpus.iter().map(busy).sum::<u64>() / total;
+            Some(energy_uj * 1000 / instructions)
+        }
+
+        fn least_busy(&self, cpus: impl IntoIterator<Item = usize>) -> Option<usize> {
+            cpus.into_iter().min_by_key(|cpu| self.cores.get(*cpu).map_or(0, |core| core.utilization))
+        }
+    }
+
+    // Where to place a task that becomes runnable. PowerSaver picks the
+    // core type that retired instructions for the least energy over the
+    // last sample, then its least busy CPU. The other modes, and
+    // PowerSaver before both types have run, go by the topology's
+    // preference order.
+    pub fn select_target_core(topology: &Topology, mode: PolicyMode, sample: Option<&EnergySample>) -> Option<usize> {
+        let order = topology.preferred_order();
+        let Some(sample) = sample else {
+            return order.first().copied();
+        };
+        if mode == PolicyMode::PowerSaver && topology.is_hybrid() {
+            let performance = topology.cpus_of_type(CoreType::Performance);
+            let efficiency = topology.cpus_of_type(CoreType::Efficiency);
+            let cpus = match (sample.nanojoules_per_instruction_on(&performance), sample.nanojoules_per_instruction_on(&efficiency)) {
+                (Some(p), Some(e)) if p < e => Some(performance),
+                (Some(_), Some(_)) => Some(efficiency),
+                _ => None,
+            };
+            if let Some(cpus) = cpus {
+                return sample.least_busy(order.into_iter().filter(|cpu| cpus.contains(cpu)));
+            }
+        }
+        sample.least_busy(order)
+    }
+
+    #[derive(Clone, Copy, Default)]
+    struct CoreCounters {
+        tsc: u64,
+        mperf: u64,
+        instructions: u64,
+    }
+
+    // Turns the RAPL energy status counters and the per-core MPERF and
+    // instruction counters into deltas between successive samples
+    pub struct RaplMeter<M: MsrIo> {
+        msr: M,
+        // Energy status units are 1/2^n joules
+        energy_shift: u32,
+        package: u32,
+        pp0: Option<u32>,
+        cores: Vec<CoreCounters>,
+    }
+
+    impl<M: MsrIo> RaplMeter<M> {
+        pub fn new(msr: M) -> Result<Self, &'static str> {
+            let units = msr.rdmsr(0, MSR_RAPL_POWER_UNIT)?;
+            let package = msr.rdmsr(0, MSR_PKG_ENERGY_STATUS)? as u32;
+            let pp0 = msr.rdmsr(0, MSR_PP0_ENERGY_STATUS).ok().map(|value| value as u32);
+            // Without the counter the instruction counts stay zero
+            for cpu in 0..msr.cpu_count() {
+                if let Err(err) = Self::enable_instruction_counter(&msr, cpu) {
+                    log::warn!("rapl: no instruction counts on CPU {}: {}", cpu, err);
+                }
+            }
+            let cores = (0..msr.cpu_count()).map(|cpu| Self::read_core(&msr, cpu)).collect();
+            Ok(RaplMeter {
+                energy_shift: ((units >> 8) & 0x1F) as u32,
+                package,
+                pp0,
+                cores,
+                msr,
+            })
+        }
+
+        // Starts fixed counter 0, leaving the other fixed counters as they
+        // were set up
+        fn enable_instruction_counter(msr: &M, cpu: usize) -> Result<(), &'static str> {
+            let (eax, _, _, edx) = msr.cpuid(cpu, CPUID_PERFMON_LEAF);
+            if eax & 0xFF < 2 || edx & 0x1F == 0 {
+                return Err("No fixed instruction counter");
+            }
+            let control = msr.rdmsr(cpu, IA32_FIXED_CTR_CTRL).unwrap_or(0);
+            msr.wrmsr(cpu, IA32_FIXED_CTR_CTRL, control & !FIXED_CTR0_FIELD | FIXED_CTR0_OS_USR)?;
+            let global = msr.rdmsr(cpu, IA32_PERF_GLOBAL_CTRL).unwrap_or(0);
+            msr.wrmsr(cpu, IA32_PERF_GLOBAL_CTRL, global | GLOBAL_FIXED_CTR0)
+        }
+
+        fn read_core(msr: &M, cpu: usize) -> CoreCounters {
+            CoreCounters {
+                tsc: msr.rdmsr(cpu, IA32_TIME_STAMP_COUNTER).unwrap_or(0),
+                mperf: msr.rdmsr(cpu, IA32_MPERF).unwrap_or(0),
+                instructions: msr.rdmsr(cpu, IA32_FIXED_CTR0).unwrap_or(0),
+            }
+        }
+
+        // The 32-bit energy counters wrap every few minutes under load, so
+        // sample at least once a minute
+        fn energy_uj(&self, previous: u32, current: u32) -> u64 {
+            (current.wrapping_sub(previous) as u64 * 1_000_000) >> self.energy_shift
+        }
+
+        pub fn sample(&mut self) -> Result<EnergySample, &'static str> {
+            let package = self.msr.rdmsr(0, MSR_PKG_ENERGY_STATUS)? as u32;
+            let package_uj = self.energy_uj(self.package, package);
+            self.package = package;
+            let cores_uj = match self.pp0 {
+                Some(previous) => {
+                    let pp0 = self.msr.rdmsr(0, MSR_PP0_ENERGY_STATUS)? as u32;
+                    self.pp0 = Some(pp0);
+                    Some(self.energy_uj(previous, pp0))
+                }
+                None => None,
+            };
+            let mut cores = Vec::new();
+            for cpu in 0..self.cores.len() {
+                let current = Self::read_core(&self.msr, cpu);
+                let previous = std::mem::replace(&mut self.cores[cpu], current);
+                let elapsed = current.tsc.wrapping_sub(previous.tsc);
+                let busy = current.mperf.wrapping_sub(previous.mperf);
+                cores.push(CoreActivity {
+                    utilization: (busy.min(elapsed) * 100).checked_div(elapsed).unwrap_or(0) as u32,
+                    instructions: current.instructions.wrapping_sub(previous.instructions) & COUNTER_MASK,
+                });
+            }
+            Ok(EnergySample {
+                package_uj,
+                cores_uj,
+                cores,
+            })
+        }
+    }
+}
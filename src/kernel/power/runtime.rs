@@ -0,0 +1,269 @@
+// src/kernel/power/runtime.rs
+
+pub mod runtime {
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex, OnceLock};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    // Deeper states order after lighter ones
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub enum DevicePowerState {
+        D0,
+        // Optional PCI states between on and off; runtime PM skips them
+        D1,
+        D2,
+        D3Hot,
+    }
+
+    // Devices with runtime PM; the class picks the default idle delay
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum DeviceClass {
+        Wifi,
+        Audio,
+        Gpu,
+    }
+
+    impl DeviceClass {
+        // Longer delays for devices that are slow to bring back up
+        pub fn autosuspend_delay(&self) -> Duration {
+            match self {
+                DeviceClass::Wifi => Duration::from_secs(2),
+                DeviceClass::Audio => Duration::from_secs(1),
+                DeviceClass::Gpu => Duration::from_secs(5),
+            }
+        }
+    }
+
+    // Implemented by drivers; moves the device between D0 and D3hot,
+    // saving and restoring whatever state the transition loses
+    pub trait RuntimePower: Send {
+        fn set_power_state(&mut self, state: DevicePowerState) -> Result<(), &'static str>;
+    }
+
+    // Shared so a transition can run with the device table unlocked; the
+    // hook's own lock keeps one device's transitions in order
+    type SharedHook = Arc<Mutex<Box<dyn RuntimePower>>>;
+
+    struct RuntimeDevice {
+        hook: SharedHook,
+        class: DeviceClass,
+        state: DevicePowerState,
+        usage: u32,
+        delay: Duration,
+        last_busy: Instant,
+        // Cleared when the user pins the device on
+        allowed: bool,
+    }
+
+    impl RuntimeDevice {
+        fn idle(&self, now: Instant) -> bool {
+            self.state == DevicePowerState::D0
+                && self.usage == 0
+                && self.allowed
+                && now.saturating_duration_since(self.last_busy) >= self.delay
+        }
+    }
+
+    // Suspends devices nobody holds a reference on once they have been
+    // idle for their autosuspend delay, and wakes them on the next access.
+    // Hooks can sleep for milliseconds, so they are never called with the
+    // device table locked.
+    pub struct RuntimePm {
+        devices: Mutex<BTreeMap<String, RuntimeDevice>>,
+    }
+
+    impl RuntimePm {
+        pub fn new() -> Self {
+            RuntimePm {
+                devices: Mutex::new(BTreeMap::new()),
+            }
+        }
+
+        // Devices start active with their idle timer running
+        pub fn register(&self, name: &str, class: DeviceClass, hook: Box<dyn RuntimePower>) -> Result<(), &'static str> {
+            let mut devices = self.devices.lock().unwrap();
+            if devices.contains_key(name) {
+                return Err("Device already registered for runtime PM");
+            }
+            devices.insert(
+                name.to_string(),
+                RuntimeDevice {
+                    hook: Arc::new(Mutex::new(hook)),
+                    class,
+                    state: DevicePowerState::D0,
+                    usage: 0,
+                    delay: class.autosuspend_delay(),
+                    last_busy: Instant::now(),
+                    allowed: true,
+                },
+            );
+            Ok(())
+        }
+
+        fn hook(&self, name: &str) -> Result<SharedHook, &'static str> {
+            let devices = self.devices.lock().unwrap();
+            Ok(devices.get(name).ok_or("Device not registered for runtime PM")?.hook.clone())
+        }
+
+        // Leaves the device in D0 so its driver can tear down normally
+        pub fn unregister(&self, name: &str) -> Result<(), &'static str> {
+            let hook = self.hook(name)?;
+            // Waits out a suspend in flight, so the state removed is current
+            let mut hook = hook.lock().unwrap();
+            let device = self.devices.lock().unwrap().remove(name).ok_or("Device not registered for runtime PM")?;
+            if device.state != DevicePowerState::D0 {
+                hook.set_power_state(DevicePowerState::D0)?;
+            }
+            Ok(())
+        }
+
+        fn resume(&self, name: &str, hook: &SharedHook) -> Result<(), &'static str> {
+            let mut hook = hook.lock().unwrap();
+            if matches!(self.state(name), None | Some(DevicePowerState::D0)) {
+                return Ok(());
+            }
+            hook.set_power_state(DevicePowerState::D0)?;
+            if let Some(device) = self.devices.lock().unwrap().get_mut(name) {
+                device.state = DevicePowerState::D0;
+                log::info!("Runtime resumed {} ({:?})", name, device.class);
+            }
+            Ok(())
+        }
+
+        // Takes a usage reference, resuming the device first if needed.
+        // Every successful get must be paired with a put.
+        pub fn get(&self, name: &str) -> Result<(), &'static str> {
+            // The reference is taken first so that poll leaves the device be
+            let hook = {
+                let mut devices = self.devices.lock().unwrap();
+                let device = devices.get_mut(name).ok_or("Device not registered for runtime PM")?;
+                device.usage += 1;
+                device.hook.clone()
+            };
+            if let Err(err) = self.resume(name, &hook) {
+                if let Some(device) = self.devices.lock().unwrap().get_mut(name) {
+                    device.usage = device.usage.saturating_sub(1);
+                }
+                return Err(err);
+            }
+            Ok(())
+        }
+
+        // Drops a usage reference; the idle timer restarts from now
+        pub fn put(&self, name: &str) -> Result<(), &'static str> {
+            let mut devices = self.devices.lock().unwrap();
+            let device = devices.get_mut(name).ok_or("Device not registered for runtime PM")?;
+            if device.usage == 0 {
+                return Err("Runtime PM usage count underflow");
+            }
+            device.usage -= 1;
+            device.last_busy = Instant::now();
+            Ok(())
+        }
+
+        // For activity that does not hold a reference, e.g. an interrupt
+        pub fn mark_busy(&self, name: &str) {
+            if let Some(device) = self.devices.lock().unwrap().get_mut(name) {
+                device.last_busy = Instant::now();
+            }
+        }
+
+        pub fn set_autosuspend_delay(&self, name: &str, delay: Duration) -> Result<(), &'static str> {
+            let mut devices = self.devices.lock().unwrap();
+            devices.get_mut(name).ok_or("Device not registered for runtime PM")?.delay = delay;
+            Ok(())
+        }
+
+        // Forbidding runtime PM resumes the device and keeps it in D0
+        pub fn set_allowed(&self, name: &str, allowed: bool) -> Result<(), &'static str> {
+            let (hook, previous) = {
+                let mut devices = self.devices.lock().unwrap();
+                let device = devices.get_mut(name).ok_or("Device not registered for runtime PM")?;
+                let previous = device.allowed;
+                device.allowed = allowed;
+                if allowed {
+                    return Ok(());
+                }
+                (device.hook.clone(), previous)
+            };
+            if let Err(err) = self.resume(name, &hook) {
+                if let Some(device) = self.devices.lock().unwrap().get_mut(name) {
+                    device.allowed = previous;
+                }
+                return Err(err);
+            }
+            Ok(())
+        }
+
+        pub fn state(&self, name: &str) -> Option<DevicePowerState> {
+            self.devices.lock().unwrap().get(name).map(|device| device.state)
+        }
+
+        pub fn usage(&self, name: &str) -> Option<u32> {
+            self.devices.lock().unwrap().get(name).map(|device| device.usage)
+        }
+
+        // Idle timer tick: suspends every device whose delay has expired
+        // and returns their names
+        pub fn poll(&self, now: Instant) -> Vec<String> {
+            let candidates: Vec<(String, SharedHook)> = self
+                .devices
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(_, device)| device.idle(now))
+                .map(|(name, device)| (name.clone(), device.hook.clone()))
+                .collect();
+            let mut suspended = Vec::new();
+            for (name, hook) in candidates {
+                let mut hook = hook.lock().unwrap();
+                // A get may have come in since; one arriving after this
+                // waits on the hook and resumes the device again
+                if !self.devices.lock().unwrap().get(&name).is_some_and(|device| device.idle(now)) {
+                    continue;
+                }
+                let result = hook.set_power_state(DevicePowerState::D3Hot);
+                let mut devices = self.devices.lock().unwrap();
+                let Some(device) = devices.get_mut(&name) else {
+                    continue;
+                };
+                match result {
+                    Ok(()) => {
+                        device.state = DevicePowerState::D3Hot;
+                        log::info!("Runtime suspended {} ({:?})", name, device.class);
+                        suspended.push(name);
+                    }
+                    // Retried on the next tick after a fresh delay
+                    Err(err) => {
+                        log::warn!("Runtime suspend of {} failed: {}", name, err);
+                        device.last_busy = now;
+                    }
+                }
+            }
+            suspended
+        }
+    }
+
+    impl Default for RuntimePm {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    pub fn runtime_pm() -> &'static RuntimePm {
+        static RUNTIME_PM: OnceLock<RuntimePm> = OnceLock::new();
+        RUNTIME_PM.get_or_init(RuntimePm::new)
+    }
+
+    // Fine enough for the shortest autosuspend delay
+    pub const IDLE_TIMER_INTERVAL: Duration = Duration::from_millis(250);
+
+    // Drives the idle timers of the global manager
+    pub fn start_idle_timer(interval: Duration) {
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            runtime_pm().poll(Instant::now());
+        });
+    }
+}
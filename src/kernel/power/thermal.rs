@@ -0,0 +1,152 @@
+// src/kernel/power/thermal.rs
+
+pub mod thermal {
+    use crate::metrics::metrics::{MetricType, Registry, Sample};
+    use std::sync::{Arc, Mutex, OnceLock};
+
+    pub trait TemperatureSensor: Send + Sync {
+        fn read_millicelsius(&self) -> Result<i32, &'static str>;
+    }
+
+    // Ordered by severity
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub enum TripType {
+        Passive,
+        Hot,
+        Critical,
+    }
+
+    // Degrees Celsius; a tripped point clears once the temperature falls
+    // hysteresis degrees below it
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct TripPoint {
+        pub kind: TripType,
+        pub temperature: i32,
+        pub hysteresis: i32,
+    }
+
+    pub fn default_trip_points() -> Vec<TripPoint> {
+        vec![
+            TripPoint {
+                kind: TripType::Passive,
+                temperature: 85,
+                hysteresis: 5,
+            },
+            TripPoint {
+                kind: TripType::Hot,
+                temperature: 95,
+                hysteresis: 5,
+            },
+            TripPoint {
+                kind: TripType::Critical,
+                temperature: 105,
+                hysteresis: 0,
+            },
+        ]
+    }
+
+    pub struct ThermalZone {
+        name: String,
+        sensor: Arc<dyn TemperatureSensor>,
+        // Each trip point with whether it is currently tripped
+        trips: Mutex<Vec<(TripPoint, bool)>>,
+    }
+
+    impl ThermalZone {
+        pub fn new(name: &str, sensor: Arc<dyn TemperatureSensor>) -> Self {
+            ThermalZone {
+                name: name.to_string(),
+                sensor,
+                trips: Mutex::new(default_trip_points().into_iter().map(|trip| (trip, false)).collect()),
+            }
+        }
+
+        // Replaces the table and clears all tripped state
+        pub fn set_trip_points(&self, trips: Vec<TripPoint>) {
+            *self.trips.lock().unwrap() = trips.into_iter().map(|trip| (trip, false)).collect();
+        }
+
+        pub fn trip_points(&self) -> Vec<TripPoint> {
+            self.trips.lock().unwrap().iter().map(|(trip, _)| *trip).collect()
+        }
+
+        // Updates the tripped state for a reading in degrees Celsius and
+        // returns the most severe trip still in effect
+        pub fn update_trips(&self, temperature: i32) -> Option<TripType> {
+            let mut trips = self.trips.lock().unwrap();
+            for (trip, tripped) in trips.iter_mut() {
+                if temperature >= trip.temperature {
+                    *tripped = true;
+                } else if temperature < trip.temperature - trip.hysteresis {
+                    *tripped = false;
+                }
+            }
+            trips.iter().filter(|(_, tripped)| *tripped).map(|(trip, _)| trip.kind).max()
+        }
+
+        pub fn name(&self) -> &str {
+            &self.name
+        }
+
+        pub fn temperature(&self) -> Result<i32, &'static str> {
+            self.sensor.read_millicelsius()
+        }
+    }
+
+    pub struct ThermalRegistry {
+        zones: Mutex<Vec<Arc<ThermalZone>>>,
+    }
+
+    impl ThermalRegistry {
+        pub fn new() -> Self {
+            ThermalRegistry {
+                zones: Mutex::new(Vec::new()),
+            }
+        }
+
+        pub fn register(&self, zone: ThermalZone) -> Result<Arc<ThermalZone>, &'static str> {
+            let mut zones = self.zones.lock().unwrap();
+            if zones.iter().any(|existing| existing.name == zone.name) {
+                return Err("Thermal zone already registered");
+            }
+            log::info!("Registering thermal zone {}", zone.name);
+            let zone = Arc::new(zone);
+            zones.push(Arc::clone(&zone));
+            Ok(zone)
+        }
+
+        pub fn get(&self, name: &str) -> Option<Arc<ThermalZone>> {
+            self.zones.lock().unwrap().iter().find(|zone| zone.name == name).cloned()
+        }
+
+        pub fn zones(&self) -> Vec<Arc<ThermalZone>> {
+            self.zones.lock().unwrap().clone()
+        }
+
+        // Hottest readable zone, in millidegrees Celsius
+        pub fn hottest(&self) -> Option<(String, i32)> {
+            self.zones()
+                .iter()
+                .filter_map(|zone| zone.temperature().ok().map(|temp| (zone.name.clone(), temp)))
+                .max_by_key(|(_, temp)| *temp)
+        }
+    }
+
+    impl Default for ThermalRegistry {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    pub fn registry() -> &'static ThermalRegistry {
+        static REGISTRY: OnceLock<ThermalRegistry> = OnceLock::new();
+        REGISTRY.get_or_init(ThermalRegistry::new)
+    }
+
+    // Each readable zone's temperature, as vxtop and scrapers see it
+    pub fn register_metrics(metrics: &Registry, zones: &'static ThermalRegistry) -> Result<(), &'static str> {
+        metrics.collect("vaelix_thermal_zone_celsius", "Temperature of each thermal zone.", MetricType::Gauge, move || {
+            zones.zones().iter().filter_map(|zone| zone.temperature().ok().map(|temp| Sample::new(temp as f64 / 1000.0).with("zone", zone.name()))).collect()
+        })
+    }
+}
@@ -0,0 +1,166 @@
+// src/kernel/power/wake.rs
+
+pub mod wake {
+    use crate::drivers::port::port::PortIo;
+    use std::collections::BTreeMap;
+
+    // Packet and link events a network controller can wake the system on
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct WakeOnLan {
+        pub magic_packet: bool,
+        pub unicast: bool,
+        pub multicast: bool,
+        pub broadcast: bool,
+        pub link_change: bool,
+    }
+
+    impl WakeOnLan {
+        pub fn any(&self) -> bool {
+            self.magic_packet || self.unicast || self.multicast || self.broadcast || self.link_change
+        }
+
+        // True when every event enabled here is also enabled in other
+        pub fn is_subset_of(&self, other: &WakeOnLan) -> bool {
+            (!self.magic_packet || other.magic_packet)
+                && (!self.unicast || other.unicast)
+                && (!self.multicast || other.multicast)
+                && (!self.broadcast || other.broadcast)
+                && (!self.link_change || other.link_change)
+        }
+    }
+
+    // ACPI general purpose event block (FADT GPE0_BLK): the first half of
+    // the block holds the status bits, write 1 to clear, the second half
+    // the matching enable bits
+    pub struct GpeBlock<P: PortIo> {
+        ports: P,
+        base: u16,
+        length: u16,
+    }
+
+    impl<P: PortIo> GpeBlock<P> {
+        pub fn new(ports: P, base: u16, length: u16) -> Result<Self, &'static str> {
+            if length == 0 || !length.is_multiple_of(2) {
+                return Err("GPE block length must be even");
+            }
+            Ok(GpeBlock { ports, base, length })
+        }
+
+        pub fn count(&self) -> u32 {
+            self.length as u32 / 2 * 8
+        }
+
+        // (status port, enable port, bit mask) for one GPE
+        fn locate(&self, gpe: u32) -> Result<(u16, u16, u8), &'static str> {
+            if gpe >= self.count() {
+                return Err("GPE out of range");
+            }
+            let status = self.base + (gpe / 8) as u16;
+            Ok((status, status + self.length / 2, 1 << (gpe % 8)))
+        }
+
+        pub fn enable(&self, gpe: u32) -> Result<(), &'static str> {
+            let (_, enable, mask) = self.locate(gpe)?;
+            self.ports.outb(enable, self.ports.inb(enable) | mask);
+            Ok(())
+        }
+
+        pub fn disable(&self, gpe: u32) -> Result<(), &'static str> {
+            let (_, enable, mask) = self.locate(gpe)?;
+            self.ports.outb(enable, self.ports.inb(enable) & !mask);
+            Ok(())
+        }
+
+        pub fn is_enabled(&self, gpe: u32) -> Result<bool, &'static str> {
+            let (_, enable, mask) = self.locate(gpe)?;
+            Ok(self.ports.inb(enable) & mask != 0)
+        }
+
+        pub fn is_pending(&self, gpe: u32) -> Result<bool, &'static str> {
+            let (status, _, mask) = self.locate(gpe)?;
+            Ok(self.ports.inb(status) & mask != 0)
+        }
+
+        pub fn clear_status(&self, gpe: u32) -> Result<(), &'static str> {
+            let (status, _, mask) = self.locate(gpe)?;
+            // Status bits are write-one-to-clear; the others must be written as 0
+            self.ports.outb(status, mask);
+            Ok(())
+        }
+    }
+
+    // Devices allowed to wake the system from sleep, each with the GPE its
+    // _PRW object names. The suspend path arms them last thing before
+    // entering the sleep state and asks which one fired on the way back.
+    pub struct WakeManager<P: PortIo> {
+        gpe: GpeBlock<P>,
+        sources: BTreeMap<String, u32>,
+        armed: bool,
+    }
+
+    impl<P: PortIo> WakeManager<P> {
+        pub fn new(gpe: GpeBlock<P>) -> Self {
+            WakeManager {
+                gpe,
+                sources: BTreeMap::new(),
+                armed: false,
+            }
+        }
+
+        pub fn gpe_block(&self) -> &GpeBlock<P> {
+            &self.gpe
+        }
+
+        pub fn register(&mut self, device: &str, gpe: u32) -> Result<(), &'static str> {
+            self.gpe.locate(gpe)?;
+            self.sources.insert(device.to_string(), gpe);
+            if self.armed {
+                self.gpe.clear_status(gpe)?;
+                self.gpe.enable(gpe)?;
+            }
+            Ok(())
+        }
+
+        pub fn unregister(&mut self, device: &str) -> Result<(), &'static str> {
+            let gpe = self.sources.remove(device).ok_or("Wake source not registered")?;
+            if self.armed && !self.sources.values().any(|other| *other == gpe) {
+                self.gpe.disable(gpe)?;
+            }
+            Ok(())
+        }
+
+        pub fn sources(&self) -> Vec<(&str, u32)> {
+            self.sources.iter().map(|(device, gpe)| (device.as_str(), *gpe)).collect()
+        }
+
+        pub fn is_armed(&self) -> bool {
+            self.armed
+        }
+
+        // Clears stale status first so an old event cannot wake the
+        // system straight back up
+        pub fn prepare_suspend(&mut self) -> Result<(), &'static str> {
+            for gpe in self.sources.values() {
+                self.gpe.clear_status(*gpe)?;
+                self.gpe.enable(*gpe)?;
+            }
+            self.armed = true;
+            log::info!("Armed {} wake source(s) for suspend", self.sources.len());
+            Ok(())
+        }
+
+        // Disarms the wake GPEs and returns the devices whose event fired
+        pub fn resume(&mut self) -> Result<Vec<String>, &'static str> {
+            let mut woken = Vec::new();
+            for (device, gpe) in &self.sources {
+                if self.gpe.is_pending(*gpe)? {
+                    woken.push(device.clone());
+                    self.gpe.clear_status(*gpe)?;
+                }
+                self.gpe.disable(*gpe)?;
+            }
+            self.armed = false;
+            Ok(woken)
+        }
+    }
+}
@@ -1 +0,0 @@
-// src/kernel/process.rs
@@ -0,0 +1,217 @@
+// src/kernel/process/elf.rs
+
+pub mod elf {
+    use crate::process::memory::memory::{page_align_down, page_align_up, AddressSpace, NO_EXECUTE, PAGE_SIZE, USER, USER_END, WRITABLE};
+
+    const MAGIC: &[u8; 4] = b"\x7FELF";
+    const CLASS_64: u8 = 2;
+    const LITTLE_ENDIAN: u8 = 1;
+    const ET_EXEC: u16 = 2;
+    const ET_DYN: u16 = 3;
+    const EM_X86_64: u16 = 62;
+    const HEADER_SIZE: usize = 64;
+    pub const PROGRAM_HEADER_SIZE: usize = 56;
+
+    pub const PT_LOAD: u32 = 1;
+    pub const PT_DYNAMIC: u32 = 2;
+    pub const PT_INTERP: u32 = 3;
+    pub const PT_PHDR: u32 = 6;
+    // Segment permissions
+    pub const PF_X: u32 = 1;
+    pub const PF_W: u32 = 2;
+    pub const PF_R: u32 = 4;
+
+    fn u16_at(data: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn u64_at(data: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+    }
+
+    // A PT_LOAD program header
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Segment {
+        pub address: u64,
+        pub offset: u64,
+        pub file_size: u64,
+        // Past the file's part it is zeroed, as for .bss
+        pub memory_size: u64,
+        // PF_* bits
+        pub flags: u32,
+    }
+
+    impl Segment {
+        pub fn end(&self) -> u64 {
+            self.address + self.memory_size
+        }
+
+        pub fn page_flags(&self) -> u64 {
+            let writable = if self.flags & PF_W != 0 { WRITABLE } else { 0 };
+            let no_execute = if self.flags & PF_X == 0 { NO_EXECUTE } else { 0 };
+            USER | writable | no_execute
+        }
+    }
+
+    // Addresses are as linked: position-independent files add wherever
+    // they are loaded to every one of them
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Executable {
+        pub entry: u64,
+        pub segments: Vec<Segment>,
+        // Where the program headers are once loaded, for the auxiliary
+        // vector, when a segment covers them
+        pub program_headers: Option<u64>,
+        pub program_header_count: usize,
+        // ET_DYN: a position-independent executable or a shared object
+        pub position_independent: bool,
+        // The dynamic linker PT_INTERP asks for
+        pub interpreter: Option<String>,
+        // Where PT_DYNAMIC is
+        pub dynamic: Option<u64>,
+    }
+
+    impl Executable {
+        // x86-64 executables and shared objects. Shared objects may have no
+        // entry point at all.
+        pub fn parse(data: &[u8]) -> Result<Self, &'static str> {
+            if data.len() < HEADER_SIZE || &data[..4] != MAGIC {
+                return Err("Not an ELF file");
+            }
+            if data[4] != CLASS_64 || data[5] != LITTLE_ENDIAN {
+                return Err("Not a 64-bit little-endian ELF file");
+            }
+            let position_independent = match u16_at(data, 16) {
+                ET_EXEC => false,
+                ET_DYN => true,
+                _ => return Err("Not an executable"),
+            };
+            if u16_at(data, 18) != EM_X86_64 {
+                return Err("Not an x86-64 executable");
+            }
+            let entry = u64_at(data, 24);
+            let table = u64_at(data, 32);
+            let (entry_size, count) = (u16_at(data, 54) as usize, u16_at(data, 56) as usize);
+            if entry_size != PROGRAM_HEADER_SIZE {
+                return Err("Unexpected program header size");
+            }
+            let table_end = table.checked_add((count * PROGRAM_HEADER_SIZE) as u64);
+            if table_end.is_none_or(|end| end > data.len() as u64) {
+                return Err("Program headers out of bounds");
+            }
+            let mut segments: Vec<Segment> = Vec::new();
+            let mut program_headers = None;
+            let mut interpreter = None;
+            let mut dynamic = None;
+            for index in 0..count {
+                let header = &data[table as usize + index * PROGRAM_HEADER_SIZE..][..PROGRAM_HEADER_SIZE];
+                let segment = Segment {
+                    address: u64_at(header, 16),
+                    offset: u64_at(header, 8),
+                    file_size: u64_at(header, 32),
+                    memory_size: u64_at(header, 40),
+                    flags: u32_at(header, 4),
+                };
+                match u32_at(header, 0) {
+                    PT_INTERP => {
+                        let end = segment.offset.checked_add(segment.file_size).filter(|end| *end <= data.len() as u64).ok_or("Bad interpreter path")?;
+                        let path = std::str::from_utf8(&data[segment.offset as usize..end as usize]).ok();
+                        let path = path.and_then(|path| path.strip_suffix('\0')).filter(|path| path.starts_with('/')).ok_or("Bad interpreter path")?;
+                        interpreter = Some(path.to_string());
+                        continue;
+                    }
+                    PT_DYNAMIC => {
+                        dynamic = Some(segment.address);
+                        continue;
+                    }
+                    PT_PHDR => {
+                        program_headers = Some(segment.address);
+                        continue;
+                    }
+                    PT_LOAD => {}
+                    _ => continue,
+                }
+                if segment.file_size > segment.memory_size {
+                    return Err("Segment larger in the file than in memory");
+                }
+                if segment.flags & PF_W != 0 && segment.flags & PF_X != 0 {
+                    return Err("Writable and executable segment");
+                }
+                if segment.offset.checked_add(segment.file_size).is_none_or(|end| end > data.len() as u64) {
+                    return Err("Segment out of bounds");
+                }
+                if segment.address.checked_add(segment.memory_size).is_none_or(|end| end > USER_END) {
+                    return Err("Segment outside user space");
+                }
+                if segments.iter().any(|other| segment.address < other.end() && other.address < segment.end()) {
+                    return Err("Segments overlap");
+                }
+                segments.push(segment);
+            }
+            if segments.is_empty() {
+                return Err("No loadable segments");
+            }
+            let in_code = |address: u64| segments.iter().any(|segment| segment.flags & PF_X != 0 && (segment.address..segment.end()).contains(&address));
+            if (entry != 0 || !position_independent) && !in_code(entry) {
+                return Err("Entry point outside the program");
+            }
+            let program_headers = program_headers.or_else(|| {
+                segments
+                    .iter()
+                    .find(|segment| (segment.offset..segment.offset + segment.file_size).contains(&table))
+                    .map(|segment| segment.address + (table - segment.offset))
+            });
+            Ok(Executable {
+                entry,
+                segments,
+                program_headers,
+                program_header_count: count,
+                position_independent,
+                interpreter,
+                dynamic,
+            })
+        }
+
+        // Past every segment, where the heap can start
+        pub fn end(&self) -> u64 {
+            let end = self.segments.iter().map(Segment::end).max().unwrap_or(0);
+            page_align_up(end).unwrap_or(end)
+        }
+
+        // Maps every segment and copies in its part of the file, moved up
+        // by a page-aligned base, which has to be zero for anything not
+        // position-independent. Segments sharing a page get the
+        // permissions of both, unless that would make it writable and
+        // executable.
+        pub fn load(&self, data: &[u8], space: &mut AddressSpace, base: u64) -> Result<(), &'static str> {
+            if !base.is_multiple_of(PAGE_SIZE) || (base != 0 && !self.position_independent) {
+                return Err("Bad load address");
+            }
+            if base.checked_add(self.end()).is_none_or(|end| end > USER_END) {
+                return Err("Segment outside user space");
+            }
+            for segment in &self.segments {
+                let flags = segment.page_flags();
+                let mut page = base + page_align_down(segment.address);
+                while page < base + segment.end() {
+                    match space.translate(page) {
+                        Some((_, existing)) => {
+                            let writable = (existing | flags) & WRITABLE;
+                            let no_execute = existing & flags & NO_EXECUTE;
+                            space.protect(page, USER | writable | no_execute)?;
+                        }
+                        None => space.map_zeroed(page, 1, flags)?,
+                    }
+                    page += PAGE_SIZE;
+                }
+                let start = segment.offset as usize;
+                space.write(base + segment.address, &data[start..start + segment.file_size as usize])?;
+            }
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,150 @@
+// src/kernel/process/files.rs
+
+pub mod files {
+    use crate::process::pipe::pipe::{PipeReader, PipeWriter};
+    use crate::pty::pty::PtySlave;
+    use std::fs::File;
+
+    // Descriptors a process can have open at once
+    pub const MAX_FILES: usize = 256;
+
+    // What a descriptor refers to
+    pub enum OpenFile {
+        // Reads come a line at a time in canonical mode; what did not fit
+        // the last read waits in pending
+        Terminal { slave: PtySlave, pending: Vec<u8> },
+        File { file: File, readable: bool, writable: bool },
+        // Each write sends one message and each read takes one. A message
+        // too long for the read waits in pending.
+        Channel { name: String, pending: Option<String> },
+        // Generated when opened, e.g. under /proc, and read-only
+        Snapshot { data: Vec<u8>, position: usize },
+        // Either end of a pipe or FIFO. Reads and writes that cannot go
+        // ahead wait, unless nonblocking.
+        PipeReader { reader: PipeReader, nonblocking: bool },
+        PipeWriter { writer: PipeWriter, nonblocking: bool },
+    }
+
+    impl OpenFile {
+        // The same open file for another descriptor, as children get
+        pub fn try_clone(&self) -> Option<OpenFile> {
+            Some(match self {
+                OpenFile::Terminal { slave, .. } => OpenFile::Terminal { slave: slave.clone(), pending: Vec::new() },
+                OpenFile::File { file, readable, writable } => OpenFile::File {
+                    file: file.try_clone().ok()?,
+                    readable: *readable,
+                    writable: *writable,
+                },
+                OpenFile::Channel { name, .. } => OpenFile::Channel { name: name.clone(), pending: None },
+                OpenFile::Snapshot { data, position } => OpenFile::Snapshot { data: data.clone(), position: *position },
+                OpenFile::PipeReader { reader, nonblocking } => OpenFile::PipeReader { reader: reader.clone(), nonblocking: *nonblocking },
+                OpenFile::PipeWriter { writer, nonblocking } => OpenFile::PipeWriter { writer: writer.clone(), nonblocking: *nonblocking },
+            })
+        }
+
+        // Of the pipe either end is open on
+        pub fn pipe_id(&self) -> Option<u64> {
+            match self {
+                OpenFile::PipeReader { reader, .. } => Some(reader.id()),
+                OpenFile::PipeWriter { writer, .. } => Some(writer.id()),
+                _ => None,
+            }
+        }
+    }
+
+    // A process's descriptors. New ones take the lowest free number,
+    // which has to be under the limit.
+    pub struct FileTable {
+        files: Vec<Option<OpenFile>>,
+        limit: usize,
+    }
+
+    impl Default for FileTable {
+        fn default() -> Self {
+            FileTable {
+                files: Vec::new(),
+                limit: MAX_FILES,
+            }
+        }
+    }
+
+    impl FileTable {
+        pub fn new() -> Self {
+            FileTable::default()
+        }
+
+        // Standard input, output and error on a terminal
+        pub fn with_terminal(slave: &PtySlave) -> Self {
+            let terminal = || Some(OpenFile::Terminal { slave: slave.clone(), pending: Vec::new() });
+            FileTable {
+                files: vec![terminal(), terminal(), terminal()],
+                limit: MAX_FILES,
+            }
+        }
+
+        // Standard input, output and error for a child
+        pub fn inherit(&self) -> FileTable {
+            FileTable {
+                files: self.files.iter().take(3).map(|file| file.as_ref().and_then(OpenFile::try_clone)).collect(),
+                limit: MAX_FILES,
+            }
+        }
+
+        pub fn limit(&self) -> usize {
+            self.limit
+        }
+
+        // Descriptors already open past it stay open
+        pub fn set_limit(&mut self, limit: usize) {
+            self.limit = limit.min(MAX_FILES);
+        }
+
+        pub fn insert(&mut self, file: OpenFile) -> Result<usize, &'static str> {
+            let fd = self.files.iter().position(Option::is_none).unwrap_or(self.files.len());
+            if fd >= self.limit {
+                return Err("Too many open files");
+            }
+            match self.files.get_mut(fd) {
+                Some(slot) => *slot = Some(file),
+                None => self.files.push(Some(file)),
+            }
+            Ok(fd)
+        }
+
+        // At a given number, as with dup2, closing what was there
+        pub fn install(&mut self, fd: usize, file: OpenFile) -> Result<(), &'static str> {
+            if fd >= self.limit {
+                return Err("Bad file descriptor");
+            }
+            if self.files.len() <= fd {
+                self.files.resize_with(fd + 1, || None);
+            }
+            self.files[fd] = Some(file);
+            Ok(())
+        }
+
+        pub fn get(&self, fd: usize) -> Option<&OpenFile> {
+            self.files.get(fd)?.as_ref()
+        }
+
+        pub fn get_mut(&mut self, fd: usize) -> Option<&mut OpenFile> {
+            self.files.get_mut(fd)?.as_mut()
+        }
+
+        pub fn close(&mut self, fd: usize) -> Result<OpenFile, &'static str> {
+            let file = self.files.get_mut(fd).and_then(Option::take).ok_or("Bad file descriptor")?;
+            while self.files.last().is_some_and(Option::is_none) {
+                self.files.pop();
+            }
+            Ok(file)
+        }
+
+        pub fn iter(&self) -> impl Iterator<Item = (usize, &OpenFile)> {
+            self.files.iter().enumerate().filter_map(|(fd, file)| Some((fd, file.as_ref()?)))
+        }
+
+        pub fn count(&self) -> usize {
+            self.files.iter().filter(|file| file.is_some()).count()
+        }
+    }
+}
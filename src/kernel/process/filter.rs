@@ -0,0 +1,149 @@
+// src/kernel/process/filter.rs
+
+pub mod filter {
+    use crate::process::syscall::syscall;
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Comparison {
+        Equal,
+        NotEqual,
+        Less,
+        LessOrEqual,
+        Greater,
+        GreaterOrEqual,
+    }
+
+    // One of a call's arguments, masked, against a value. Pointers are
+    // only numbers here: what they point to can change after the check.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Predicate {
+        pub argument: usize,
+        pub mask: u64,
+        pub comparison: Comparison,
+        pub value: u64,
+    }
+
+    impl Predicate {
+        pub fn matches(&self, arguments: &[u64; 6]) -> bool {
+            let argument = arguments[self.argument] & self.mask;
+            match self.comparison {
+                Comparison::Equal => argument == self.value,
+                Comparison::NotEqual => argument != self.value,
+                Comparison::Less => argument < self.value,
+                Comparison::LessOrEqual => argument <= self.value,
+                Comparison::Greater => argument > self.value,
+                Comparison::GreaterOrEqual => argument >= self.value,
+            }
+        }
+
+        // argN, with &MASK if any, then the comparison and the value, e.g.
+        // arg1&3==0. Numbers can be decimal, 0x hex or 0o octal.
+        pub fn parse(text: &str) -> Result<Self, &'static str> {
+            let split = text.find(['=', '!', '<', '>']).ok_or("Malformed syscall predicate")?;
+            let (left, right) = text.split_at(split);
+            let (comparison, value) = [
+                ("==", Comparison::Equal),
+                ("!=", Comparison::NotEqual),
+                ("<=", Comparison::LessOrEqual),
+                (">=", Comparison::GreaterOrEqual),
+                ("<", Comparison::Less),
+                (">", Comparison::Greater),
+            ]
+            .into_iter()
+            .find_map(|(operator, comparison)| right.strip_prefix(operator).map(|value| (comparison, value)))
+            .ok_or("Malformed syscall predicate")?;
+            let (argument, mask) = match left.split_once('&') {
+                Some((argument, mask)) => (argument, number(mask)?),
+                None => (left, u64::MAX),
+            };
+            let argument = argument.strip_prefix("arg").and_then(|index| index.parse().ok()).filter(|index| *index < 6).ok_or("Unknown syscall argument")?;
+            Ok(Predicate {
+                argument,
+                mask,
+                comparison,
+                value: number(value)?,
+            })
+        }
+    }
+
+    fn number(text: &str) -> Result<u64, &'static str> {
+        let parsed = match (text.strip_prefix("0x"), text.strip_prefix("0o")) {
+            (Some(hex), _) => u64::from_str_radix(hex, 16),
+            (_, Some(octal)) => u64::from_str_radix(octal, 8),
+            _ => text.parse(),
+        };
+        parsed.map_err(|_| "Invalid number")
+    }
+
+    // What happens to a call a filter does not allow. Kill is the harsher,
+    // and wins when filters disagree.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub enum Action {
+        // It fails with EPERM
+        Deny,
+        // The process is killed with SIGSYS, which cannot be ignored
+        Kill,
+    }
+
+    impl Action {
+        pub fn parse(name: &str) -> Result<Self, &'static str> {
+            match name {
+                "deny" => Ok(Action::Deny),
+                "kill" => Ok(Action::Kill),
+                _ => Err("Unknown syscall action"),
+            }
+        }
+    }
+
+    // An allow list of system calls by number. A call listed with no rules
+    // always goes ahead; otherwise one of its rules has to hold, and a
+    // rule holds when all of its predicates do.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Filter {
+        rules: BTreeMap<u64, Vec<Vec<Predicate>>>,
+        action: Action,
+    }
+
+    impl Filter {
+        pub fn new(action: Action) -> Self {
+            Filter { rules: BTreeMap::new(), action }
+        }
+
+        // Calls by name separated by whitespace, each with its predicates
+        // after a colon, separated by commas, e.g.
+        //   read write close open:arg1&3==0 kill:arg0==0
+        // Listing a call again adds another rule for it.
+        pub fn parse(text: &str, action: Action) -> Result<Self, &'static str> {
+            let mut filter = Filter::new(action);
+            for entry in text.split_whitespace() {
+                let (name, predicates) = match entry.split_once(':') {
+                    Some((name, predicates)) => (name, predicates.split(',').map(Predicate::parse).collect::<Result<_, _>>()?),
+                    None => (entry, Vec::new()),
+                };
+                filter.allow(syscall::number(name).ok_or("Unknown syscall")?, predicates);
+            }
+            Ok(filter)
+        }
+
+        pub fn action(&self) -> Action {
+            self.action
+        }
+
+        pub fn allow(&mut self, number: u64, predicates: Vec<Predicate>) {
+            self.rules.entry(number).or_default().push(predicates);
+        }
+
+        pub fn allows(&self, number: u64, arguments: &[u64; 6]) -> bool {
+            self.rules.get(&number).is_some_and(|rules| rules.iter().any(|rule| rule.iter().all(|predicate| predicate.matches(arguments))))
+        }
+    }
+
+    // A process's filters are stacked, each only narrowing what the ones
+    // before it allow: a call has to get past all of them. What happens to
+    // one that does not, if any does not.
+    pub fn check(filters: &[Arc<Filter>], number: u64, arguments: &[u64; 6]) -> Option<Action> {
+        filters.iter().filter(|filter| !filter.allows(number, arguments)).map(|filter| filter.action).max()
+    }
+}
@@ -0,0 +1,59 @@
+// src/kernel/process/kthread.rs
+
+pub mod kthread {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread::{self, JoinHandle};
+
+    // A thread running kernel code with no user address space of its own,
+    // e.g. to flush caches or poll devices. Stopped and joined when
+    // dropped.
+    pub struct KernelThread {
+        name: String,
+        stop: Arc<AtomicBool>,
+        handle: Option<JoinHandle<()>>,
+    }
+
+    impl KernelThread {
+        // Calls the body over and over until it returns false or the thread
+        // is asked to stop. The body should not block for long, so that
+        // stopping does not have to wait.
+        pub fn spawn(name: &str, mut body: impl FnMut() -> bool + Send + 'static) -> Result<Self, &'static str> {
+            let stop = Arc::new(AtomicBool::new(false));
+            let stopping = stop.clone();
+            let handle = thread::Builder::new()
+                .name(format!("k{}", name))
+                .spawn(move || while !stopping.load(Ordering::Acquire) && body() {})
+                .map_err(|_| "Failed to start kernel thread")?;
+            log::info!("Started kernel thread {}", name);
+            Ok(KernelThread {
+                name: name.to_string(),
+                stop,
+                handle: Some(handle),
+            })
+        }
+
+        pub fn name(&self) -> &str {
+            &self.name
+        }
+
+        pub fn is_running(&self) -> bool {
+            self.handle.as_ref().is_some_and(|handle| !handle.is_finished())
+        }
+
+        // Waits for the body's current call to return. Err if it panicked.
+        pub fn stop(&mut self) -> Result<(), &'static str> {
+            self.stop.store(true, Ordering::Release);
+            match self.handle.take() {
+                Some(handle) => handle.join().map_err(|_| "Kernel thread panicked"),
+                None => Ok(()),
+            }
+        }
+    }
+
+    impl Drop for KernelThread {
+        fn drop(&mut self) {
+            let _ = self.stop();
+        }
+    }
+}
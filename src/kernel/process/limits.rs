@@ -0,0 +1,146 @@
+// src/kernel/process/limits.rs
+
+pub mod limits {
+    use crate::process::files::files::MAX_FILES;
+    use std::time::Duration;
+
+    // Resource numbers are the ABI, as for getrlimit
+    pub const RLIMIT_CPU: u64 = 0;
+    pub const RLIMIT_NOFILE: u64 = 7;
+    pub const RLIMIT_AS: u64 = 9;
+    pub const RLIM_INFINITY: u64 = u64::MAX;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Resource {
+        // In seconds of user and system time together
+        CpuTime,
+        // Descriptors, by the lowest number that cannot be used
+        Files,
+        // Bytes of mapped memory
+        Memory,
+    }
+
+    impl Resource {
+        pub const ALL: [Resource; 3] = [Resource::CpuTime, Resource::Files, Resource::Memory];
+
+        pub fn from_number(number: u64) -> Option<Self> {
+            match number {
+                RLIMIT_CPU => Some(Resource::CpuTime),
+                RLIMIT_NOFILE => Some(Resource::Files),
+                RLIMIT_AS => Some(Resource::Memory),
+                _ => None,
+            }
+        }
+
+        pub fn name(self) -> &'static str {
+            match self {
+                Resource::CpuTime => "Max cpu time",
+                Resource::Files => "Max open files",
+                Resource::Memory => "Max address space",
+            }
+        }
+
+        pub fn unit(self) -> &'static str {
+            match self {
+                Resource::CpuTime => "seconds",
+                Resource::Files => "files",
+                Resource::Memory => "bytes",
+            }
+        }
+    }
+
+    // Enforced at the soft limit. Anyone may lower either, or raise the
+    // soft one up to the hard one.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Limit {
+        pub soft: u64,
+        pub hard: u64,
+    }
+
+    impl Limit {
+        pub const UNLIMITED: Limit = Limit {
+            soft: RLIM_INFINITY,
+            hard: RLIM_INFINITY,
+        };
+
+        pub fn new(soft: u64, hard: u64) -> Self {
+            Limit { soft, hard }
+        }
+
+        // Whether using this much goes past it
+        pub fn exceeded_by(&self, used: u64) -> bool {
+            self.soft != RLIM_INFINITY && used > self.soft
+        }
+    }
+
+    // A process's limits, inherited by its children
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Limits {
+        cpu_time: Limit,
+        files: Limit,
+        memory: Limit,
+    }
+
+    impl Default for Limits {
+        fn default() -> Self {
+            Limits {
+                cpu_time: Limit::UNLIMITED,
+                files: Limit::new(MAX_FILES as u64, MAX_FILES as u64),
+                memory: Limit::UNLIMITED,
+            }
+        }
+    }
+
+    impl Limits {
+        pub fn new() -> Self {
+            Limits::default()
+        }
+
+        pub fn get(&self, resource: Resource) -> Limit {
+            match resource {
+                Resource::CpuTime => self.cpu_time,
+                Resource::Files => self.files,
+                Resource::Memory => self.memory,
+            }
+        }
+
+        // Only the privileged may raise a hard limit
+        pub fn set(&mut self, resource: Resource, limit: Limit, privileged: bool) -> Result<(), &'static str> {
+            if limit.soft > limit.hard {
+                return Err("Soft limit above hard limit");
+            }
+            if resource == Resource::Files && limit.hard > MAX_FILES as u64 {
+                return Err("Limit too high");
+            }
+            let current = match resource {
+                Resource::CpuTime => &mut self.cpu_time,
+                Resource::Files => &mut self.files,
+                Resource::Memory => &mut self.memory,
+            };
+            if limit.hard > current.hard && !privileged {
+                return Err("Not permitted");
+            }
+            *current = limit;
+            Ok(())
+        }
+    }
+
+    // What a process has used over its life, all its threads together
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct Usage {
+        // Running its own code
+        pub user_time: Duration,
+        // In system calls on its behalf
+        pub system_time: Duration,
+        pub syscalls: u64,
+        // Bytes, at the most
+        pub peak_memory: u64,
+        pub files_opened: u64,
+    }
+
+    impl Usage {
+        pub fn cpu_time(&self) -> Duration {
+            self.user_time + self.system_time
+        }
+    }
+}
@@ -0,0 +1,562 @@
+// src/kernel/process/linker.rs
+
+pub mod linker {
+    use crate::process::elf::elf::Executable;
+    use crate::process::mapping::mapping::Backing;
+    use crate::process::memory::memory::{page_align_down, page_align_up, AddressSpace, NO_EXECUTE, PAGE_SIZE};
+    use crate::process::table::table::{Pid, ProcessTable};
+    use crate::users::users::ACCESS_READ;
+    use std::collections::VecDeque;
+    use std::fs::{self, File};
+    use std::os::unix::fs::MetadataExt;
+    use std::sync::Arc;
+
+    // Programs asking for this interpreter are linked by the kernel as
+    // they are loaded, rather than by an interpreter of their own
+    pub const INTERPRETER: &str = "/lib/ld-vaelix.so.1";
+    // Where libraries named without a slash are looked for, in order
+    pub const LIBRARY_PATH: [&str; 2] = ["/lib", "/usr/lib"];
+    // For symbol lookups in everything loaded with the program
+    pub const DEFAULT_HANDLE: u64 = 0;
+
+    // Dynamic section tags
+    pub const DT_NULL: u64 = 0;
+    pub const DT_NEEDED: u64 = 1;
+    pub const DT_PLTRELSZ: u64 = 2;
+    pub const DT_HASH: u64 = 4;
+    pub const DT_STRTAB: u64 = 5;
+    pub const DT_SYMTAB: u64 = 6;
+    pub const DT_RELA: u64 = 7;
+    pub const DT_RELASZ: u64 = 8;
+    pub const DT_STRSZ: u64 = 10;
+    pub const DT_SONAME: u64 = 14;
+    pub const DT_JMPREL: u64 = 23;
+    // Relocation types
+    pub const R_X86_64_NONE: u32 = 0;
+    pub const R_X86_64_64: u32 = 1;
+    pub const R_X86_64_GLOB_DAT: u32 = 6;
+    pub const R_X86_64_JUMP_SLOT: u32 = 7;
+    pub const R_X86_64_RELATIVE: u32 = 8;
+
+    const DYNAMIC_ENTRY_SIZE: u64 = 16;
+    // Dynamic sections end at DT_NULL well before this many entries
+    const MAX_DYNAMIC_ENTRIES: u64 = 1024;
+    const SYMBOL_SIZE: u64 = 24;
+    const RELOCATION_SIZE: u64 = 24;
+    const SHN_UNDEF: u16 = 0;
+    const STB_GLOBAL: u8 = 1;
+    const STB_WEAK: u8 = 2;
+
+    fn read_u32(space: &AddressSpace, address: u64) -> Result<u32, &'static str> {
+        let mut bytes = [0; 4];
+        space.read(address, &mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn read_u64(space: &AddressSpace, address: u64) -> Result<u64, &'static str> {
+        let mut bytes = [0; 8];
+        space.read(address, &mut bytes)?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    // What an object's dynamic section says, with addresses moved to
+    // where it is loaded. Names are offsets into its string table.
+    #[derive(Debug, Clone, Default, PartialEq, Eq)]
+    struct Dynamic {
+        needed: Vec<u64>,
+        soname: Option<u64>,
+        hash: Option<u64>,
+        strings: u64,
+        strings_size: u64,
+        symbols: u64,
+        relocations: (u64, u64),
+        plt_relocations: (u64, u64),
+    }
+
+    impl Dynamic {
+        fn read(space: &AddressSpace, base: u64, address: u64) -> Result<Self, &'static str> {
+            let mut dynamic = Dynamic::default();
+            for index in 0..MAX_DYNAMIC_ENTRIES {
+                let entry = address + index * DYNAMIC_ENTRY_SIZE;
+                let value = read_u64(space, entry + 8)?;
+                match read_u64(space, entry)? {
+                    DT_NULL => return Ok(dynamic),
+                    DT_NEEDED => dynamic.needed.push(value),
+                    DT_SONAME => dynamic.soname = Some(value),
+                    DT_HASH => dynamic.hash = Some(base + value),
+                    DT_STRTAB => dynamic.strings = base + value,
+                    DT_STRSZ => dynamic.strings_size = value,
+                    DT_SYMTAB => dynamic.symbols = base + value,
+                    DT_RELA => dynamic.relocations.0 = base + value,
+                    DT_RELASZ => dynamic.relocations.1 = value,
+                    DT_JMPREL => dynamic.plt_relocations.0 = base + value,
+                    DT_PLTRELSZ => dynamic.plt_relocations.1 = value,
+                    _ => {}
+                }
+            }
+            Err("Dynamic section not terminated")
+        }
+
+        // Within the string table and NUL-terminated
+        fn string(&self, space: &AddressSpace, offset: u64) -> Result<String, &'static str> {
+            if offset >= self.strings_size {
+                return Err("Bad string table");
+            }
+            let mut bytes = vec![0; (self.strings_size - offset) as usize];
+            space.read(self.strings + offset, &mut bytes)?;
+            let end = bytes.iter().position(|byte| *byte == 0).ok_or("Bad string table")?;
+            bytes.truncate(end);
+            String::from_utf8(bytes).map_err(|_| "Bad string table")
+        }
+    }
+
+    struct Symbol {
+        name: String,
+        binding: u8,
+        section: u16,
+        value: u64,
+    }
+
+    impl Symbol {
+        fn is_defined(&self) -> bool {
+            self.section != SHN_UNDEF && matches!(self.binding, STB_GLOBAL | STB_WEAK)
+        }
+    }
+
+    // A program or shared object in a process's address space
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Object {
+        // What dlopen returns for it
+        pub handle: u64,
+        // Its DT_SONAME, or else the name it was asked for by
+        pub name: String,
+        pub path: String,
+        pub base: u64,
+        // What it has mapped, to be unmapped with it; nothing for the
+        // program, whose segments are part of its image
+        start: u64,
+        end: u64,
+        dynamic: Dynamic,
+        // Handles of the objects its DT_NEEDED entries name
+        pub needed: Vec<u64>,
+        // Opened with dlopen and not yet closed
+        opened: usize,
+        // Opens, objects needing it, and one more for the program's own
+        // objects, which stay until the process goes
+        references: usize,
+    }
+
+    impl Object {
+        fn symbol(&self, space: &AddressSpace, index: u64) -> Result<Symbol, &'static str> {
+            let address = self.dynamic.symbols + index * SYMBOL_SIZE;
+            let mut bytes = [0; SYMBOL_SIZE as usize];
+            space.read(address, &mut bytes)?;
+            Ok(Symbol {
+                name: self.dynamic.string(space, u32::from_le_bytes(bytes[..4].try_into().unwrap()) as u64)?,
+                binding: bytes[4] >> 4,
+                section: u16::from_le_bytes(bytes[6..8].try_into().unwrap()),
+                value: u64::from_le_bytes(bytes[8..16].try_into().unwrap()),
+            })
+        }
+
+        // Where it defines a symbol, through its DT_HASH table. Objects
+        // without one export nothing.
+        fn lookup(&self, space: &AddressSpace, name: &str) -> Result<Option<u64>, &'static str> {
+            let Some(hash) = self.dynamic.hash else {
+                return Ok(None);
+            };
+            let (buckets, chains) = (read_u32(space, hash)? as u64, read_u32(space, hash + 4)? as u64);
+            if buckets == 0 {
+                return Ok(None);
+            }
+            let mut index = read_u32(space, hash + 8 + (elf_hash(name) as u64 % buckets) * 4)? as u64;
+            // A chain longer than the table has a loop in it
+            for _ in 0..chains {
+                if index == 0 || index >= chains {
+                    break;
+                }
+                let symbol = self.symbol(space, index)?;
+                if symbol.is_defined() && symbol.name == name {
+                    return Ok(Some(self.base + symbol.value));
+                }
+                index = read_u32(space, hash + 8 + (buckets + index) * 4)? as u64;
+            }
+            Ok(None)
+        }
+    }
+
+    // The System V ABI's symbol hash
+    fn elf_hash(name: &str) -> u32 {
+        let mut hash: u32 = 0;
+        for byte in name.bytes() {
+            hash = (hash << 4).wrapping_add(byte as u32);
+            let high = hash & 0xF000_0000;
+            hash ^= high >> 24;
+            hash &= !high;
+        }
+        hash
+    }
+
+    // Every object in a process, in load order. The program and whatever it
+    // needs come first and make up the global scope symbols are looked up
+    // in; objects opened later add their own dependencies after that.
+    #[derive(Debug, Clone, Default, PartialEq, Eq)]
+    pub struct LinkMap {
+        objects: Vec<Object>,
+        global: usize,
+        next_handle: u64,
+    }
+
+    impl LinkMap {
+        // For a program loaded at a base, from its dynamic section
+        pub fn program(space: &AddressSpace, name: &str, base: u64, dynamic: u64) -> Result<Self, &'static str> {
+            let dynamic = Dynamic::read(space, base, dynamic)?;
+            Ok(LinkMap {
+                objects: vec![Object {
+                    handle: 1,
+                    name: name.to_string(),
+                    path: name.to_string(),
+                    base,
+                    start: 0,
+                    end: 0,
+                    dynamic,
+                    needed: Vec::new(),
+                    opened: 0,
+                    references: 1,
+                }],
+                global: 0,
+                next_handle: 2,
+            })
+        }
+
+        pub fn objects(&self) -> &[Object] {
+            &self.objects
+        }
+
+        pub fn get(&self, handle: u64) -> Option<&Object> {
+            self.objects.iter().find(|object| object.handle == handle)
+        }
+
+        fn get_mut(&mut self, handle: u64) -> Option<&mut Object> {
+            self.objects.iter_mut().find(|object| object.handle == handle)
+        }
+
+        // An object and everything it needs, breadth first
+        fn tree(&self, handle: u64) -> Vec<u64> {
+            let mut tree = vec![handle];
+            let mut index = 0;
+            while let Some(next) = tree.get(index).and_then(|handle| self.get(*handle)) {
+                for needed in &next.needed {
+                    if !tree.contains(needed) {
+                        tree.push(*needed);
+                    }
+                }
+                index += 1;
+            }
+            tree
+        }
+
+        // Where symbols used by objects in a tree are looked for: the
+        // global scope, then the tree itself
+        fn scope(&self, handle: Option<u64>) -> Vec<u64> {
+            let mut scope: Vec<u64> = self.objects[..self.global].iter().map(|object| object.handle).collect();
+            for handle in handle.map(|handle| self.tree(handle)).unwrap_or_default() {
+                if !scope.contains(&handle) {
+                    scope.push(handle);
+                }
+            }
+            scope
+        }
+    }
+
+    // Finds a library as a process could: a path, or a name to look for
+    // along LIBRARY_PATH
+    fn find(table: &ProcessTable, pid: Pid, name: &str) -> Result<String, &'static str> {
+        let credentials = table.get(pid).ok_or("No such process")?.credentials();
+        if name.contains('/') {
+            table.access(credentials, name, ACCESS_READ)?;
+            return Ok(name.to_string());
+        }
+        for directory in LIBRARY_PATH {
+            let path = format!("{}/{}", directory, name);
+            match table.access(credentials, &path, ACCESS_READ) {
+                Ok(_) => return Ok(path),
+                Err("File not found") => continue,
+                Err(error) => return Err(error),
+            }
+        }
+        Err("Library not found")
+    }
+
+    // Maps a shared object's segments from its file, as private mappings
+    // somewhere free, zeroing what is past the file's part of each
+    fn map_object(table: &mut ProcessTable, pid: Pid, path: &str) -> Result<(u64, u64, u64, Executable), &'static str> {
+        let host = table.resolve(path)?;
+        let data = fs::read(&host).map_err(|_| "Failed to read file")?;
+        let object = Executable::parse(&data)?;
+        if !object.position_independent || object.dynamic.is_none() {
+            return Err("Not a shared object");
+        }
+        let first = page_align_down(object.segments.iter().map(|segment| segment.address).min().unwrap());
+        let mut pages = Vec::new();
+        for segment in &object.segments {
+            if segment.offset % PAGE_SIZE != segment.address % PAGE_SIZE {
+                return Err("Segment misaligned");
+            }
+            let range = page_align_down(segment.address)..page_align_up(segment.end()).unwrap();
+            if pages.iter().any(|other: &std::ops::Range<u64>| range.start < other.end && other.start < range.end) {
+                return Err("Segments share a page");
+            }
+            pages.push(range);
+        }
+        let file = Arc::new(File::open(&host).map_err(|_| "Failed to read file")?);
+        let metadata = file.metadata().map_err(|_| "Failed to read file")?;
+        let cache = table.page_cache().clone();
+        let process = table.get_mut(pid).ok_or("No such process")?;
+        // Reserved in one piece first, so the segments stay together
+        let size = object.end() - first;
+        let start = process.map(None, size, NO_EXECUTE, false, Backing::Anonymous)?;
+        let base = start - first;
+        let mapped = object.segments.iter().try_for_each(|segment| {
+            let flags = segment.page_flags();
+            let page = base + page_align_down(segment.address);
+            let file_end = base + segment.address + segment.file_size;
+            if segment.file_size > 0 {
+                let backing = Backing::File {
+                    cache: cache.clone(),
+                    file: file.clone(),
+                    key: (metadata.dev(), metadata.ino()),
+                    offset: page_align_down(segment.offset),
+                    writable: false,
+                };
+                process.map(Some(page), file_end - page, flags, false, backing)?;
+                // The rest of the last page is the file's next bytes
+                let zeroed = page_align_up(file_end).unwrap();
+                process.space().write(file_end, &vec![0; (zeroed - file_end) as usize])?;
+            }
+            let bss = if segment.file_size > 0 { page_align_up(file_end).unwrap() } else { page };
+            let end = page_align_up(base + segment.end()).unwrap();
+            if end > bss {
+                process.map(Some(bss), end - bss, flags, false, Backing::Anonymous)?;
+            }
+            Ok(())
+        });
+        if let Err(error) = mapped {
+            let _ = process.unmap(start, size);
+            return Err(error);
+        }
+        Ok((base, start, start + size, object))
+    }
+
+    // Maps a shared object found at a path and adds it to the link map
+    fn add(table: &mut ProcessTable, pid: Pid, name: &str, path: &str) -> Result<u64, &'static str> {
+        let (base, start, end, object) = map_object(table, pid, path)?;
+        let process = table.get_mut(pid).unwrap();
+        let dynamic = Dynamic::read(process.space(), base, base + object.dynamic.unwrap());
+        let soname = dynamic.as_ref().ok().and_then(|dynamic| Some(dynamic.string(process.space(), dynamic.soname?)));
+        let (dynamic, soname) = match (dynamic, soname.transpose()) {
+            (Ok(dynamic), Ok(soname)) => (dynamic, soname),
+            (Err(error), _) | (_, Err(error)) => {
+                let _ = process.unmap(start, end - start);
+                return Err(error);
+            }
+        };
+        let link_map = process.link_map_mut();
+        let handle = link_map.next_handle.max(1);
+        link_map.next_handle = handle + 1;
+        link_map.objects.push(Object {
+            handle,
+            name: soname.unwrap_or_else(|| name.to_string()),
+            path: path.to_string(),
+            base,
+            start,
+            end,
+            dynamic,
+            needed: Vec::new(),
+            opened: 0,
+            references: 0,
+        });
+        log::info!("Loaded {} for process {} at {:#x}", path, pid, base);
+        Ok(handle)
+    }
+
+    // An object by name or path, loaded unless it already is, with a
+    // reference taken. New objects are added to the list.
+    fn load(table: &mut ProcessTable, pid: Pid, name: &str, loaded: &mut Vec<u64>) -> Result<u64, &'static str> {
+        let loaded_as = |table: &ProcessTable, matches: &dyn Fn(&Object) -> bool| {
+            table.get(pid).unwrap().link_map().objects.iter().find(|object| matches(object)).map(|object| object.handle)
+        };
+        table.get(pid).ok_or("No such process")?;
+        let handle = match loaded_as(table, &|object| object.name == name) {
+            Some(handle) => handle,
+            None => {
+                let path = find(table, pid, name)?;
+                match loaded_as(table, &|object| object.path == path) {
+                    Some(handle) => handle,
+                    None => {
+                        let handle = add(table, pid, name, &path)?;
+                        loaded.push(handle);
+                        handle
+                    }
+                }
+            }
+        };
+        table.get_mut(pid).unwrap().link_map_mut().get_mut(handle).unwrap().references += 1;
+        Ok(handle)
+    }
+
+    // Loads everything the new objects need, in breadth-first order
+    fn load_needed(table: &mut ProcessTable, pid: Pid, loaded: &mut Vec<u64>) -> Result<(), &'static str> {
+        let mut queue: VecDeque<u64> = loaded.iter().copied().collect();
+        while let Some(handle) = queue.pop_front() {
+            let process = table.get(pid).unwrap();
+            let object = process.link_map().get(handle).unwrap();
+            let names = object.dynamic.needed.iter().map(|name| object.dynamic.string(process.space(), *name)).collect::<Result<Vec<_>, _>>()?;
+            for name in names {
+                let before = loaded.len();
+                let needed = load(table, pid, &name, loaded)?;
+                table.get_mut(pid).unwrap().link_map_mut().get_mut(handle).unwrap().needed.push(needed);
+                queue.extend(&loaded[before..]);
+            }
+        }
+        Ok(())
+    }
+
+    // Applies an object's relocations, with symbols from a scope
+    fn relocate(table: &ProcessTable, pid: Pid, handle: u64, scope: &[u64]) -> Result<(), &'static str> {
+        let process = table.get(pid).unwrap();
+        let (space, link_map) = (process.space(), process.link_map());
+        let object = link_map.get(handle).unwrap();
+        for (start, size) in [object.dynamic.relocations, object.dynamic.plt_relocations] {
+            for entry in (start..start + size).step_by(RELOCATION_SIZE as usize) {
+                let offset = object.base + read_u64(space, entry)?;
+                let info = read_u64(space, entry + 8)?;
+                let addend = read_u64(space, entry + 16)?;
+                let symbol = || -> Result<u64, &'static str> {
+                    let symbol = object.symbol(space, info >> 32)?;
+                    for handle in scope {
+                        if let Some(address) = link_map.get(*handle).unwrap().lookup(space, &symbol.name)? {
+                            return Ok(address);
+                        }
+                    }
+                    if symbol.binding == STB_WEAK {
+                        return Ok(0);
+                    }
+                    log::warn!("Undefined symbol {} in {}", symbol.name, object.name);
+                    Err("Undefined symbol")
+                };
+                let value = match info as u32 {
+                    R_X86_64_NONE => continue,
+                    R_X86_64_RELATIVE => object.base.wrapping_add(addend),
+                    R_X86_64_64 => symbol()?.wrapping_add(addend),
+                    R_X86_64_GLOB_DAT | R_X86_64_JUMP_SLOT => symbol()?,
+                    _ => return Err("Unsupported relocation"),
+                };
+                space.write(offset, &value.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    // Takes away a reference, unloading the object with the last one and
+    // letting go of what it needed
+    fn release(table: &mut ProcessTable, pid: Pid, handle: u64) {
+        let process = table.get_mut(pid).unwrap();
+        let Some(object) = process.link_map_mut().get_mut(handle) else {
+            return;
+        };
+        object.references -= 1;
+        if object.references > 0 {
+            return;
+        }
+        let object = object.clone();
+        process.link_map_mut().objects.retain(|other| other.handle != handle);
+        let _ = process.unmap(object.start, object.end - object.start);
+        log::info!("Unloaded {} from process {}", object.name, pid);
+        for needed in object.needed {
+            release(table, pid, needed);
+        }
+    }
+
+    // Loads, links and relocates new objects, taking them all away again
+    // if any part fails
+    fn link_new(table: &mut ProcessTable, pid: Pid, root: Option<&str>, mut loaded: Vec<u64>) -> Result<Option<u64>, &'static str> {
+        let result = (|| {
+            let root = root.map(|name| load(table, pid, name, &mut loaded)).transpose()?;
+            load_needed(table, pid, &mut loaded)?;
+            // A program being linked is the root of its own tree
+            let scope = table.get(pid).unwrap().link_map().scope(root.or(loaded.first().copied()));
+            for handle in &loaded {
+                relocate(table, pid, *handle, &scope)?;
+            }
+            Ok(root)
+        })();
+        if result.is_err() {
+            let process = table.get_mut(pid).unwrap();
+            let link_map = process.link_map_mut();
+            let (removed, kept) = link_map.objects.drain(..).partition(|object| loaded.contains(&object.handle));
+            link_map.objects = kept;
+            for object in &removed {
+                for needed in &object.needed {
+                    if let Some(other) = link_map.get_mut(*needed) {
+                        other.references -= 1;
+                    }
+                }
+            }
+            for object in removed {
+                let _ = process.unmap(object.start, object.end - object.start);
+            }
+        }
+        result
+    }
+
+    // Does what an interpreter would for a program the kernel has just
+    // loaded: loads the libraries it needs, and theirs, then relocates
+    // it and them. They all stay for the life of the process.
+    pub fn link(table: &mut ProcessTable, pid: Pid) -> Result<(), &'static str> {
+        let program = table.get(pid).ok_or("No such process")?.link_map().objects.first().map(|object| object.handle).ok_or("Not a dynamic program")?;
+        link_new(table, pid, None, vec![program])?;
+        let link_map = table.get_mut(pid).unwrap().link_map_mut();
+        link_map.global = link_map.objects.len();
+        for object in &mut link_map.objects {
+            object.references += 1;
+        }
+        Ok(())
+    }
+
+    // Loads a shared object and whatever it needs at run time, returning
+    // a handle for it. Opening one already loaded only counts another
+    // reference.
+    pub fn open(table: &mut ProcessTable, pid: Pid, name: &str) -> Result<u64, &'static str> {
+        let handle = link_new(table, pid, Some(name), Vec::new())?.unwrap();
+        table.get_mut(pid).unwrap().link_map_mut().get_mut(handle).unwrap().opened += 1;
+        Ok(handle)
+    }
+
+    // Where a symbol is in an object or what it needs, or with
+    // DEFAULT_HANDLE, in the global scope
+    pub fn symbol(table: &ProcessTable, pid: Pid, handle: u64, name: &str) -> Result<u64, &'static str> {
+        let process = table.get(pid).ok_or("No such process")?;
+        let link_map = process.link_map();
+        let scope = match handle {
+            DEFAULT_HANDLE => link_map.scope(None),
+            _ if link_map.get(handle).is_some() => link_map.tree(handle),
+            _ => return Err("Bad handle"),
+        };
+        for handle in scope {
+            if let Some(address) = link_map.get(handle).unwrap().lookup(process.space(), name)? {
+                return Ok(address);
+            }
+        }
+        Err("Symbol not found")
+    }
+
+    // Undoes an open, unloading the object when nothing else holds it
+    pub fn close(table: &mut ProcessTable, pid: Pid, handle: u64) -> Result<(), &'static str> {
+        let link_map = table.get_mut(pid).ok_or("No such process")?.link_map_mut();
+        let object = link_map.get_mut(handle).filter(|object| object.opened > 0).ok_or("Bad handle")?;
+        object.opened -= 1;
+        release(table, pid, handle);
+        Ok(())
+    }
+}
@@ -0,0 +1,172 @@
+// src/kernel/process/mapping.rs
+
+pub mod mapping {
+    use crate::process::memory::memory::{PhysicalMemory, PAGE_SIZE};
+    use std::collections::BTreeMap;
+    use std::fs::File;
+    use std::os::unix::fs::FileExt;
+    use std::sync::{Arc, Mutex};
+
+    // A file by device and inode, however it was opened
+    pub type FileKey = (u64, u64);
+
+    // Where a mapping's pages come from when first touched
+    #[derive(Clone)]
+    pub enum Backing {
+        // Zeroed pages
+        Anonymous,
+        // Pages of a file from a page-aligned offset. Shared mappings use
+        // the page cache's frames, and write them back to the file when
+        // unmapped if it was opened for writing.
+        File {
+            cache: PageCache,
+            file: Arc<File>,
+            key: FileKey,
+            offset: u64,
+            writable: bool,
+        },
+    }
+
+    // A page-aligned range of user memory set up by mmap and filled in a
+    // page at a time as it is touched
+    #[derive(Clone)]
+    pub struct Mapping {
+        pub start: u64,
+        pub end: u64,
+        // Page table flags its pages get; without USER user code cannot
+        // touch it at all
+        pub flags: u64,
+        // Shared with every other shared mapping of the same file pages.
+        // Anonymous shared memory has nobody to share with yet, as there
+        // is no fork, and behaves as private.
+        pub shared: bool,
+        pub backing: Backing,
+    }
+
+    impl Mapping {
+        pub fn contains(&self, address: u64) -> bool {
+            (self.start..self.end).contains(&address)
+        }
+
+        pub fn length(&self) -> u64 {
+            self.end - self.start
+        }
+
+        // Whether its pages are the page cache's rather than its own
+        pub fn borrows_pages(&self) -> bool {
+            self.shared && matches!(self.backing, Backing::File { .. })
+        }
+
+        // Where a page of it is in its file
+        pub fn file_offset(&self, page: u64) -> u64 {
+            match self.backing {
+                Backing::File { offset, .. } => offset + (page - self.start),
+                Backing::Anonymous => 0,
+            }
+        }
+
+        // The part of it from one page-aligned address to another
+        pub fn slice(&self, start: u64, end: u64) -> Mapping {
+            let mut slice = self.clone();
+            if let Backing::File { offset, .. } = &mut slice.backing {
+                *offset += start - self.start;
+            }
+            slice.start = start;
+            slice.end = end;
+            slice
+        }
+    }
+
+    struct CachedPage {
+        frame: u64,
+        // Mapped pages using the frame
+        users: usize,
+    }
+
+    // Frames holding pages of files mapped shared, so every process
+    // mapping a page sees the same memory. A frame is freed when its last
+    // user unmaps it. Clones share the same cache.
+    #[derive(Clone, Default)]
+    pub struct PageCache {
+        pages: Arc<Mutex<BTreeMap<(FileKey, u64), CachedPage>>>,
+    }
+
+    impl PageCache {
+        pub fn new() -> Self {
+            PageCache::default()
+        }
+
+        pub fn len(&self) -> usize {
+            self.pages.lock().unwrap().len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.len() == 0
+        }
+
+        // The frame holding a page of a file, read in on first use, with
+        // one more user
+        pub fn acquire(&self, memory: &PhysicalMemory, file: &File, key: FileKey, offset: u64) -> Result<u64, &'static str> {
+            let mut pages = self.pages.lock().unwrap();
+            if let Some(page) = pages.get_mut(&(key, offset)) {
+                page.users += 1;
+                return Ok(page.frame);
+            }
+            let frame = memory.allocate()?;
+            read_page(memory, file, offset, frame).inspect_err(|_| memory.free(frame))?;
+            pages.insert((key, offset), CachedPage { frame, users: 1 });
+            Ok(frame)
+        }
+
+        // One user fewer, freeing the frame after the last
+        pub fn release(&self, memory: &PhysicalMemory, key: FileKey, offset: u64) {
+            let mut pages = self.pages.lock().unwrap();
+            let Some(page) = pages.get_mut(&(key, offset)) else {
+                return;
+            };
+            page.users -= 1;
+            if page.users == 0 {
+                memory.free(page.frame);
+                pages.remove(&(key, offset));
+            }
+        }
+
+        // A page's contents as they are now, into a frame of its own: from
+        // the cache if someone has it mapped shared, or else the file
+        pub fn read_into(&self, memory: &PhysicalMemory, file: &File, key: FileKey, offset: u64, frame: u64) -> Result<(), &'static str> {
+            let cached = self.pages.lock().unwrap().get(&(key, offset)).map(|page| page.frame);
+            match cached {
+                Some(cached) => {
+                    let mut buffer = vec![0; PAGE_SIZE as usize];
+                    memory.read(cached, &mut buffer)?;
+                    memory.write(frame, &buffer)
+                }
+                None => read_page(memory, file, offset, frame),
+            }
+        }
+    }
+
+    // Past the end of the file reads as zeroes
+    fn read_page(memory: &PhysicalMemory, file: &File, offset: u64, frame: u64) -> Result<(), &'static str> {
+        let mut buffer = vec![0; PAGE_SIZE as usize];
+        let mut done = 0;
+        while done < buffer.len() {
+            match file.read_at(&mut buffer[done..], offset + done as u64).map_err(|_| "Failed to read page")? {
+                0 => break,
+                read => done += read,
+            }
+        }
+        memory.write(frame, &buffer)
+    }
+
+    // Only the part inside the file: mappings never make it longer
+    pub fn write_page(memory: &PhysicalMemory, file: &File, offset: u64, frame: u64) -> Result<(), &'static str> {
+        let length = file.metadata().map_err(|_| "Failed to write back page")?.len();
+        if offset >= length {
+            return Ok(());
+        }
+        let mut buffer = vec![0; (length - offset).min(PAGE_SIZE) as usize];
+        memory.read(frame, &mut buffer)?;
+        file.write_all_at(&buffer, offset).map_err(|_| "Failed to write back page")
+    }
+}
@@ -0,0 +1,504 @@
+// src/kernel/process/memory.rs
+
+pub mod memory {
+    use crate::hardening::hardening;
+    use crate::process::mapping::mapping::{write_page, Backing, Mapping};
+    use std::collections::BTreeMap;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    pub const PAGE_SIZE: u64 = 4096;
+    // User processes get the lower half of the canonical address space,
+    // less its last page so no user range ends at the hole
+    pub const USER_END: u64 = 0x0000_7FFF_FFFF_F000;
+    pub const KERNEL_BASE: u64 = 0xFFFF_8000_0000_0000;
+
+    // Page table entry bits
+    pub const PRESENT: u64 = 1;
+    pub const WRITABLE: u64 = 1 << 1;
+    pub const USER: u64 = 1 << 2;
+    pub const NO_EXECUTE: u64 = 1 << 63;
+    const ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+    const ENTRIES: usize = 512;
+    // The first top-level entry of the kernel half
+    const KERNEL_ENTRY: usize = 256;
+    // Frames are handed out from here up, clear of what firmware uses
+    const FIRST_FRAME: u64 = 0x10_0000;
+
+    pub fn page_align_down(address: u64) -> u64 {
+        address & !(PAGE_SIZE - 1)
+    }
+
+    pub fn page_align_up(address: u64) -> Option<u64> {
+        address.checked_add(PAGE_SIZE - 1).map(page_align_down)
+    }
+
+    struct Frames {
+        frames: BTreeMap<u64, Box<[u8]>>,
+        free: Vec<u64>,
+        next: u64,
+        limit: usize,
+    }
+
+    // RAM as page frames, as many as the machine has. Clones share the
+    // same memory.
+    #[derive(Clone)]
+    pub struct PhysicalMemory {
+        frames: Arc<Mutex<Frames>>,
+    }
+
+    impl PhysicalMemory {
+        pub fn new(frames: usize) -> Self {
+            PhysicalMemory {
+                frames: Arc::new(Mutex::new(Frames {
+                    frames: BTreeMap::new(),
+                    free: Vec::new(),
+                    next: FIRST_FRAME,
+                    limit: frames,
+                })),
+            }
+        }
+
+        // A zeroed frame
+        pub fn allocate(&self) -> Result<u64, &'static str> {
+            let mut frames = self.frames.lock().unwrap();
+            if frames.frames.len() >= frames.limit {
+                return Err("Out of memory");
+            }
+            let frame = match frames.free.pop() {
+                Some(frame) => frame,
+                None => {
+                    let frame = frames.next;
+                    frames.next = hardening::add(frames.next, PAGE_SIZE, "Frame allocator");
+                    frame
+                }
+            };
+            frames.frames.insert(frame, vec![0; PAGE_SIZE as usize].into_boxed_slice());
+            Ok(frame)
+        }
+
+        pub fn free(&self, frame: u64) {
+            let mut frames = self.frames.lock().unwrap();
+            if frames.frames.remove(&frame).is_some() {
+                frames.free.push(frame);
+            }
+        }
+
+        pub fn allocated(&self) -> usize {
+            self.frames.lock().unwrap().frames.len()
+        }
+
+        pub fn capacity(&self) -> usize {
+            self.frames.lock().unwrap().limit
+        }
+
+        fn access<T>(&self, address: u64, length: usize, access: impl FnOnce(&mut [u8]) -> T) -> Result<T, &'static str> {
+            let offset = (address % PAGE_SIZE) as usize;
+            if offset + length > PAGE_SIZE as usize {
+                return Err("Access crosses a frame");
+            }
+            let mut frames = self.frames.lock().unwrap();
+            let frame = frames.frames.get_mut(&page_align_down(address)).ok_or("Frame not allocated")?;
+            Ok(access(&mut frame[offset..offset + length]))
+        }
+
+        // Both stay within one frame
+        pub fn read(&self, address: u64, buffer: &mut [u8]) -> Result<(), &'static str> {
+            self.access(address, buffer.len(), |bytes| buffer.copy_from_slice(bytes))
+        }
+
+        pub fn write(&self, address: u64, data: &[u8]) -> Result<(), &'static str> {
+            self.access(address, data.len(), |bytes| bytes.copy_from_slice(data))
+        }
+
+        fn entry(&self, table: u64, index: usize) -> u64 {
+            let mut bytes = [0; 8];
+            self.read(table + index as u64 * 8, &mut bytes).unwrap();
+            u64::from_le_bytes(bytes)
+        }
+
+        fn set_entry(&self, table: u64, index: usize, entry: u64) {
+            self.write(table + index as u64 * 8, &entry.to_le_bytes()).unwrap();
+        }
+    }
+
+    // Four-level page tables. User address spaces map the lower half and
+    // share the kernel's top-level entries for the upper half.
+    pub struct AddressSpace {
+        memory: PhysicalMemory,
+        root: u64,
+        user: bool,
+        // Pages mapped in the half it owns. Filling in a mapping's pages
+        // only needs a shared reference, as kernel reads and writes do it.
+        pages: AtomicU64,
+        // By start address, none overlapping
+        mappings: BTreeMap<u64, Mapping>,
+    }
+
+    impl AddressSpace {
+        // The kernel's own tables
+        pub fn kernel(memory: &PhysicalMemory) -> Result<Self, &'static str> {
+            Ok(AddressSpace {
+                memory: memory.clone(),
+                root: memory.allocate()?,
+                user: false,
+                pages: AtomicU64::new(0),
+                mappings: BTreeMap::new(),
+            })
+        }
+
+        // Kernel mappings made later have to go in tables that already
+        // existed when this was created
+        pub fn new_user(kernel: &AddressSpace) -> Result<Self, &'static str> {
+            let memory = kernel.memory.clone();
+            let root = memory.allocate()?;
+            for index in KERNEL_ENTRY..ENTRIES {
+                memory.set_entry(root, index, memory.entry(kernel.root, index));
+            }
+            Ok(AddressSpace {
+                memory,
+                root,
+                user: true,
+                pages: AtomicU64::new(0),
+                mappings: BTreeMap::new(),
+            })
+        }
+
+        // What goes in CR3 to switch to it
+        pub fn root(&self) -> u64 {
+            self.root
+        }
+
+        pub fn memory(&self) -> &PhysicalMemory {
+            &self.memory
+        }
+
+        pub fn is_user(&self) -> bool {
+            self.user
+        }
+
+        pub fn mapped_pages(&self) -> u64 {
+            self.pages.load(Ordering::Relaxed)
+        }
+
+        fn indices(address: u64) -> [usize; 4] {
+            [39, 30, 21, 12].map(|shift| ((address >> shift) & (ENTRIES as u64 - 1)) as usize)
+        }
+
+        fn check(&self, address: u64) -> Result<(), &'static str> {
+            if !address.is_multiple_of(PAGE_SIZE) {
+                return Err("Address not page aligned");
+            }
+            match self.user {
+                true if address >= USER_END => Err("Address outside user space"),
+                false if address < KERNEL_BASE => Err("Address outside kernel space"),
+                _ => Ok(()),
+            }
+        }
+
+        // Pages are writable or executable, never both, in either half
+        fn check_flags(flags: u64) -> Result<(), &'static str> {
+            match flags & WRITABLE != 0 && flags & NO_EXECUTE == 0 {
+                true => Err("Writable and executable"),
+                false => Ok(()),
+            }
+        }
+
+        // The last-level table for an address, made on the way down when
+        // asked to
+        fn table(&self, address: u64, create: bool) -> Result<Option<u64>, &'static str> {
+            let mut table = self.root;
+            for index in &Self::indices(address)[..3] {
+                let entry = self.memory.entry(table, *index);
+                table = if entry & PRESENT != 0 {
+                    entry & ADDRESS_MASK
+                } else if create {
+                    let next = self.memory.allocate()?;
+                    // Leaf entries decide what is allowed
+                    self.memory.set_entry(table, *index, next | PRESENT | WRITABLE | USER);
+                    next
+                } else {
+                    return Ok(None);
+                };
+            }
+            Ok(Some(table))
+        }
+
+        pub fn map(&mut self, address: u64, frame: u64, flags: u64) -> Result<(), &'static str> {
+            self.insert(address, frame, flags)
+        }
+
+        fn insert(&self, address: u64, frame: u64, flags: u64) -> Result<(), &'static str> {
+            self.check(address)?;
+            Self::check_flags(flags)?;
+            let table = self.table(address, true)?.unwrap();
+            let index = Self::indices(address)[3];
+            if self.memory.entry(table, index) & PRESENT != 0 {
+                return Err("Page already mapped");
+            }
+            self.memory.set_entry(table, index, (frame & ADDRESS_MASK) | flags | PRESENT);
+            self.pages.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+
+        // Hands back the frame, which the caller frees
+        pub fn unmap(&mut self, address: u64) -> Result<u64, &'static str> {
+            self.remove(address)
+        }
+
+        fn remove(&self, address: u64) -> Result<u64, &'static str> {
+            self.check(address)?;
+            let table = self.table(address, false)?.ok_or("Page not mapped")?;
+            let index = Self::indices(address)[3];
+            let entry = self.memory.entry(table, index);
+            if entry & PRESENT == 0 {
+                return Err("Page not mapped");
+            }
+            self.memory.set_entry(table, index, 0);
+            self.pages.fetch_sub(1, Ordering::Relaxed);
+            Ok(entry & ADDRESS_MASK)
+        }
+
+        // Changes the flags of a mapped page, keeping its frame
+        pub fn protect(&mut self, address: u64, flags: u64) -> Result<(), &'static str> {
+            self.check(address)?;
+            Self::check_flags(flags)?;
+            let table = self.table(address, false)?.ok_or("Page not mapped")?;
+            let index = Self::indices(address)[3];
+            let entry = self.memory.entry(table, index);
+            if entry & PRESENT == 0 {
+                return Err("Page not mapped");
+            }
+            self.memory.set_entry(table, index, (entry & ADDRESS_MASK) | flags | PRESENT);
+            Ok(())
+        }
+
+        // The physical address and the page's entry flags
+        pub fn translate(&self, address: u64) -> Option<(u64, u64)> {
+            let table = self.table(address, false).ok()??;
+            let entry = self.memory.entry(table, Self::indices(address)[3]);
+            (entry & PRESENT != 0).then_some(((entry & ADDRESS_MASK) | (address % PAGE_SIZE), entry & !ADDRESS_MASK))
+        }
+
+        // Fresh zeroed frames over a page-aligned range, all or none
+        pub fn map_zeroed(&mut self, address: u64, pages: u64, flags: u64) -> Result<(), &'static str> {
+            for page in 0..pages {
+                let mapped = self.memory.allocate().and_then(|frame| {
+                    self.map(address + page * PAGE_SIZE, frame, flags).inspect_err(|_| self.memory.free(frame))
+                });
+                if let Err(error) = mapped {
+                    for page in 0..page {
+                        let frame = self.unmap(address + page * PAGE_SIZE)?;
+                        self.memory.free(frame);
+                    }
+                    return Err(error);
+                }
+            }
+            Ok(())
+        }
+
+        pub fn mapping_at(&self, address: u64) -> Option<&Mapping> {
+            self.mappings.range(..=address).next_back().map(|(_, mapping)| mapping).filter(|mapping| mapping.contains(address))
+        }
+
+        pub fn mappings(&self) -> impl Iterator<Item = &Mapping> {
+            self.mappings.values()
+        }
+
+        // Bytes in mappings, whether their pages have been touched or not
+        pub fn mapped_length(&self) -> u64 {
+            self.mappings.values().map(Mapping::length).sum()
+        }
+
+        // The highest page-aligned address with room for that many bytes
+        // between two others, clear of every mapping
+        pub fn find_free(&self, size: u64, bottom: u64, top: u64) -> Option<u64> {
+            let mut top = top;
+            for mapping in self.mappings.values().rev() {
+                if mapping.start >= top {
+                    continue;
+                }
+                if let Some(address) = top.checked_sub(size).filter(|address| *address >= mapping.end.max(bottom)) {
+                    return Some(address);
+                }
+                top = mapping.start;
+            }
+            top.checked_sub(size).filter(|address| *address >= bottom)
+        }
+
+        // Nothing is mapped in it until a page is touched
+        pub fn add_mapping(&mut self, mapping: Mapping) -> Result<(), &'static str> {
+            self.check(mapping.start)?;
+            self.check(mapping.end - PAGE_SIZE)?;
+            Self::check_flags(mapping.flags)?;
+            if self.mappings.range(..mapping.end).any(|(_, other)| other.end > mapping.start) {
+                return Err("Mapping overlaps");
+            }
+            self.mappings.insert(mapping.start, mapping);
+            Ok(())
+        }
+
+        // Cuts the mapping containing a page-aligned address in two there
+        fn split(&mut self, address: u64) {
+            let Some(mapping) = self.mapping_at(address).filter(|mapping| mapping.start != address).cloned() else {
+                return;
+            };
+            self.mappings.insert(mapping.start, mapping.slice(mapping.start, address));
+            self.mappings.insert(address, mapping.slice(address, mapping.end));
+        }
+
+        // Whatever parts of mappings lie in a page-aligned range, with
+        // their pages
+        pub fn remove_mappings(&mut self, start: u64, end: u64) {
+            self.split(start);
+            self.split(end);
+            let starts: Vec<u64> = self.mappings.range(start..end).map(|(start, _)| *start).collect();
+            for start in starts {
+                let mapping = self.mappings.remove(&start).unwrap();
+                self.release(&mapping);
+            }
+        }
+
+        // Gives new page table flags to every mapping in a page-aligned
+        // range, which has to be mapped throughout. Shared file mappings
+        // can only be made writable if the file was opened for writing.
+        pub fn protect_mappings(&mut self, start: u64, end: u64, flags: u64) -> Result<(), &'static str> {
+            Self::check_flags(flags)?;
+            let mut next = start;
+            for mapping in self.mappings.range(..end).map(|(_, mapping)| mapping).filter(|mapping| mapping.end > start) {
+                if mapping.start > next {
+                    return Err("Range not mapped");
+                }
+                if flags & WRITABLE != 0 && mapping.borrows_pages() && matches!(mapping.backing, Backing::File { writable: false, .. }) {
+                    return Err("Permission denied");
+                }
+                next = mapping.end;
+            }
+            if next < end {
+                return Err("Range not mapped");
+            }
+            self.split(start);
+            self.split(end);
+            let ranges: Vec<(u64, u64)> = self.mappings.range(start..end).map(|(start, mapping)| (*start, mapping.end)).collect();
+            for (start, end) in ranges {
+                self.mappings.get_mut(&start).unwrap().flags = flags;
+                for page in (start..end).step_by(PAGE_SIZE as usize) {
+                    if self.translate(page).is_some() {
+                        self.protect(page, flags)?;
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        // Fills in the page of a mapping an address is in, unless it
+        // already is: zeroed for anonymous memory, a copy of the file's
+        // page for private mappings, or the page cache's own frame for
+        // shared ones
+        pub fn populate(&self, address: u64) -> Result<(), &'static str> {
+            let page = page_align_down(address);
+            if self.translate(page).is_some() {
+                return Ok(());
+            }
+            let mapping = self.mapping_at(page).ok_or("Page not mapped")?;
+            let offset = mapping.file_offset(page);
+            let frame = match &mapping.backing {
+                Backing::File { cache, file, key, .. } if mapping.shared => cache.acquire(&self.memory, file, *key, offset)?,
+                backing => {
+                    let frame = self.memory.allocate()?;
+                    if let Backing::File { cache, file, key, .. } = backing {
+                        cache.read_into(&self.memory, file, *key, offset, frame).inspect_err(|_| self.memory.free(frame))?;
+                    }
+                    frame
+                }
+            };
+            self.insert(page, frame, mapping.flags).inspect_err(|_| self.free_page(mapping, page, frame))
+        }
+
+        // Unmaps what has been filled in of a mapping
+        fn release(&self, mapping: &Mapping) {
+            for page in (mapping.start..mapping.end).step_by(PAGE_SIZE as usize) {
+                if let Ok(frame) = self.remove(page) {
+                    self.free_page(mapping, page, frame);
+                }
+            }
+        }
+
+        // Writes shared pages back to a file opened for writing before
+        // letting go of them. Errors cannot go anywhere, as with any
+        // writeback.
+        fn free_page(&self, mapping: &Mapping, page: u64, frame: u64) {
+            match &mapping.backing {
+                Backing::File { cache, file, key, writable, .. } if mapping.shared => {
+                    let offset = mapping.file_offset(page);
+                    if *writable {
+                        let _ = write_page(&self.memory, file, offset, frame);
+                    }
+                    cache.release(&self.memory, *key, offset);
+                }
+                _ => self.memory.free(frame),
+            }
+        }
+
+        // Goes page by page through mapped memory, whatever its protection,
+        // filling in mappings on the way
+        fn copy(&self, address: u64, length: usize, mut copy: impl FnMut(u64, usize, usize)) -> Result<(), &'static str> {
+            let mut done = 0;
+            while done < length {
+                let virtual_address = address.checked_add(done as u64).ok_or("Page not mapped")?;
+                self.populate(virtual_address)?;
+                let (physical, _) = self.translate(virtual_address).ok_or("Page not mapped")?;
+                let chunk = (PAGE_SIZE - virtual_address % PAGE_SIZE).min((length - done) as u64) as usize;
+                copy(physical, done, chunk);
+                done += chunk;
+            }
+            Ok(())
+        }
+
+        // Kernel access, e.g. for loading programs
+        pub fn read(&self, address: u64, buffer: &mut [u8]) -> Result<(), &'static str> {
+            let length = buffer.len();
+            self.copy(address, length, |physical, offset, chunk| {
+                self.memory.read(physical, &mut buffer[offset..offset + chunk]).unwrap();
+            })
+        }
+
+        pub fn write(&self, address: u64, data: &[u8]) -> Result<(), &'static str> {
+            self.copy(address, data.len(), |physical, offset, chunk| {
+                self.memory.write(physical, &data[offset..offset + chunk]).unwrap();
+            })
+        }
+
+        fn free_table(&self, table: u64, level: usize) {
+            for index in 0..ENTRIES {
+                let entry = self.memory.entry(table, index);
+                if entry & PRESENT == 0 {
+                    continue;
+                }
+                match level {
+                    0 => self.memory.free(entry & ADDRESS_MASK),
+                    _ => self.free_table(entry & ADDRESS_MASK, level - 1),
+                }
+            }
+            self.memory.free(table);
+        }
+    }
+
+    // Frees the half it owns: every table and mapped frame below it, less
+    // the page cache's, which are written back and let go of
+    impl Drop for AddressSpace {
+        fn drop(&mut self) {
+            for mapping in std::mem::take(&mut self.mappings).values() {
+                self.release(mapping);
+            }
+            let owned = if self.user { 0..KERNEL_ENTRY } else { KERNEL_ENTRY..ENTRIES };
+            for index in owned {
+                let entry = self.memory.entry(self.root, index);
+                if entry & PRESENT != 0 {
+                    self.free_table(entry & ADDRESS_MASK, 2);
+                }
+            }
+            self.memory.free(self.root);
+        }
+    }
+}
@@ -0,0 +1,17 @@
+// src/kernel/process/mod.rs
+
+pub mod elf;
+pub mod files;
+pub mod filter;
+pub mod kthread;
+pub mod limits;
+pub mod linker;
+pub mod mapping;
+pub mod memory;
+pub mod pipe;
+pub mod procfs;
+pub mod signal;
+pub mod syscall;
+pub mod table;
+pub mod task;
+pub mod uaccess;
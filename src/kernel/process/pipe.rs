@@ -0,0 +1,226 @@
+// src/kernel/process/pipe.rs
+
+pub mod pipe {
+    use crate::hardening::hardening;
+    use crate::process::mapping::mapping::FileKey;
+    use crate::process::syscall::syscall::{POLLERR, POLLHUP, POLLIN, POLLOUT};
+    use std::collections::{BTreeMap, VecDeque};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    // Bytes a pipe holds before writers have to wait
+    pub const PIPE_CAPACITY: usize = 64 * 1024;
+    // Writes of up to this much go in whole or not at all, so they never
+    // interleave with other writers'
+    pub const PIPE_ATOMIC: usize = 4096;
+
+    static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+    struct Buffer {
+        // A ring of at most PIPE_CAPACITY bytes
+        data: VecDeque<u8>,
+        // Ends open on it
+        readers: usize,
+        writers: usize,
+        // Whether a writer has ever opened it; until then a FIFO's readers
+        // wait rather than see the end
+        connected: bool,
+    }
+
+    // The buffer both ends share. Threads waiting on one are told apart by
+    // its ID.
+    #[derive(Clone)]
+    pub struct Pipe {
+        id: u64,
+        buffer: Arc<Mutex<Buffer>>,
+    }
+
+    impl Pipe {
+        pub fn new() -> Self {
+            Pipe {
+                id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+                buffer: Arc::new(Mutex::new(Buffer {
+                    data: VecDeque::with_capacity(PIPE_CAPACITY),
+                    readers: 0,
+                    writers: 0,
+                    connected: false,
+                })),
+            }
+        }
+
+        pub fn id(&self) -> u64 {
+            self.id
+        }
+
+        pub fn reader(&self) -> PipeReader {
+            let mut buffer = self.buffer.lock().unwrap();
+            buffer.readers = hardening::add(buffer.readers, 1, "Pipe reader count");
+            PipeReader { pipe: self.clone() }
+        }
+
+        pub fn writer(&self) -> PipeWriter {
+            let mut buffer = self.buffer.lock().unwrap();
+            buffer.writers = hardening::add(buffer.writers, 1, "Pipe writer count");
+            buffer.connected = true;
+            PipeWriter { pipe: self.clone() }
+        }
+
+        pub fn readers(&self) -> usize {
+            self.buffer.lock().unwrap().readers
+        }
+
+        pub fn writers(&self) -> usize {
+            self.buffer.lock().unwrap().writers
+        }
+
+        // Bytes waiting to be read
+        pub fn len(&self) -> usize {
+            self.buffer.lock().unwrap().data.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.len() == 0
+        }
+    }
+
+    impl Default for Pipe {
+        fn default() -> Self {
+            Pipe::new()
+        }
+    }
+
+    // An anonymous pipe's two ends
+    pub fn pipe() -> (PipeReader, PipeWriter) {
+        let pipe = Pipe::new();
+        (pipe.reader(), pipe.writer())
+    }
+
+    // The end bytes come out of. Clones are more handles on it, as for
+    // descriptors children inherit.
+    pub struct PipeReader {
+        pipe: Pipe,
+    }
+
+    pub struct PipeWriter {
+        pipe: Pipe,
+    }
+
+    impl PipeReader {
+        pub fn id(&self) -> u64 {
+            self.pipe.id
+        }
+
+        // Up to count bytes of whatever is there. None means nothing yet;
+        // an empty read is the end, once every write end is closed.
+        pub fn read(&self, count: usize) -> Option<Vec<u8>> {
+            let mut buffer = self.pipe.buffer.lock().unwrap();
+            if buffer.data.is_empty() && (buffer.writers > 0 || !buffer.connected) {
+                return None;
+            }
+            let count = count.min(buffer.data.len());
+            Some(buffer.data.drain(..count).collect())
+        }
+
+        // POLLIN with something to read, and POLLHUP once the writers
+        // have all gone
+        pub fn poll(&self) -> u16 {
+            let buffer = self.pipe.buffer.lock().unwrap();
+            let mut events = 0;
+            if !buffer.data.is_empty() {
+                events |= POLLIN;
+            }
+            if buffer.writers == 0 && buffer.connected {
+                events |= POLLHUP;
+            }
+            events
+        }
+    }
+
+    impl Clone for PipeReader {
+        fn clone(&self) -> Self {
+            self.pipe.reader()
+        }
+    }
+
+    impl Drop for PipeReader {
+        fn drop(&mut self) {
+            self.pipe.buffer.lock().unwrap().readers -= 1;
+        }
+    }
+
+    impl PipeWriter {
+        pub fn id(&self) -> u64 {
+            self.pipe.id
+        }
+
+        // As much as fits, which is nothing when the pipe is full or an
+        // atomic write does not fit whole
+        pub fn write(&self, data: &[u8]) -> Result<usize, &'static str> {
+            let mut buffer = self.pipe.buffer.lock().unwrap();
+            if buffer.readers == 0 {
+                return Err("Broken pipe");
+            }
+            let room = PIPE_CAPACITY - buffer.data.len();
+            if data.len() <= PIPE_ATOMIC && data.len() > room {
+                return Ok(0);
+            }
+            let count = data.len().min(room);
+            buffer.data.extend(&data[..count]);
+            Ok(count)
+        }
+
+        // POLLOUT with room for an atomic write, and POLLERR once the
+        // readers have all gone
+        pub fn poll(&self) -> u16 {
+            let buffer = self.pipe.buffer.lock().unwrap();
+            if buffer.readers == 0 {
+                return POLLERR;
+            }
+            match PIPE_CAPACITY - buffer.data.len() >= PIPE_ATOMIC {
+                true => POLLOUT,
+                false => 0,
+            }
+        }
+    }
+
+    impl Clone for PipeWriter {
+        fn clone(&self) -> Self {
+            self.pipe.writer()
+        }
+    }
+
+    impl Drop for PipeWriter {
+        fn drop(&mut self) {
+            self.pipe.buffer.lock().unwrap().writers -= 1;
+        }
+    }
+
+    // The pipes behind named FIFOs, by the device and inode of their node
+    // so every path to one reaches the same pipe. A pipe no end is open on
+    // is forgotten, with whatever was left in it. Clones share them.
+    #[derive(Clone, Default)]
+    pub struct Fifos {
+        pipes: Arc<Mutex<BTreeMap<FileKey, Pipe>>>,
+    }
+
+    impl Fifos {
+        pub fn new() -> Self {
+            Fifos::default()
+        }
+
+        pub fn get(&self, key: FileKey) -> Pipe {
+            let mut pipes = self.pipes.lock().unwrap();
+            pipes.retain(|_, pipe| pipe.readers() > 0 || pipe.writers() > 0);
+            pipes.entry(key).or_default().clone()
+        }
+
+        // Of those with an end open
+        pub fn len(&self) -> usize {
+            self.pipes.lock().unwrap().values().filter(|pipe| pipe.readers() > 0 || pipe.writers() > 0).count()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.len() == 0
+        }
+    }
+}
@@ -0,0 +1,139 @@
+// src/kernel/process/procfs.rs
+
+pub mod procfs {
+    use crate::drivers::resource::resource::{resources, ResourceKind};
+    use crate::irq::irq;
+    use crate::kconfig::kconfig;
+    use crate::metrics::metrics;
+    use crate::process::limits::limits::{Resource, RLIM_INFINITY};
+    use crate::process::memory::memory::PAGE_SIZE;
+    use crate::process::table::table::{Pid, ProcessTable};
+
+    // Files generated from the process table when read, one directory per
+    // live process:
+    //   /proc/PID/status  name, state, IDs, threads, descriptors, memory,
+    //                     reserved and resident, and as on Linux, 2 for
+    //                     Seccomp when system calls are filtered
+    //   /proc/PID/limits  each resource limit with its units
+    //   /proc/PID/usage   CPU time, system calls and files opened so far
+    // /proc/self is whoever is reading, /proc/meminfo physical memory in
+    // use, /proc/metrics the kernel's metrics in the Prometheus text format,
+    // /proc/config what it was built with, /proc/interrupts how many of
+    // each vector each CPU has taken and /proc/ioports and /proc/iomem
+    // which driver claimed each range of ports and physical addresses.
+    pub const MOUNT: &str = "/proc";
+    const FILES: [&str; 7] = ["config", "interrupts", "iomem", "ioports", "meminfo", "metrics", "self"];
+    const PROCESS_FILES: [&str; 3] = ["limits", "status", "usage"];
+
+    // An absolute path's components, with ".." stopping at the root
+    fn components(path: &str) -> Vec<&str> {
+        let mut components = Vec::new();
+        for component in path.split('/') {
+            match component {
+                "" | "." => {}
+                ".." => {
+                    components.pop();
+                }
+                _ => components.push(component),
+            }
+        }
+        components
+    }
+
+    pub fn is_proc(path: &str) -> bool {
+        path.starts_with('/') && components(path).first() == Some(&&MOUNT[1..])
+    }
+
+    // The generated contents of a file, as seen by a process
+    pub fn read(table: &ProcessTable, reader: Pid, path: &str) -> Result<String, &'static str> {
+        let (directory, file) = match components(path).as_slice() {
+            ["proc", "config"] => return Ok(kconfig::ACTIVE.render()),
+            ["proc", "interrupts"] => return Ok(irq::controller().report()),
+            ["proc", "iomem"] => return Ok(resources().report(ResourceKind::Memory)),
+            ["proc", "ioports"] => return Ok(resources().report(ResourceKind::Io)),
+            ["proc", "meminfo"] => {
+                let memory = table.physical_memory();
+                let kilobytes = |frames: usize| frames as u64 * PAGE_SIZE / 1024;
+                return Ok(format!(
+                    "MemTotal:\t{} kB\nMemFree:\t{} kB\nMemUsed:\t{} kB\n",
+                    kilobytes(memory.capacity()),
+                    kilobytes(memory.capacity() - memory.allocated()),
+                    kilobytes(memory.allocated()),
+                ));
+            }
+            ["proc", "metrics"] => return Ok(metrics::registry().render()),
+            ["proc"] | ["proc", _] => return Err("Is a directory"),
+            ["proc", directory, file] => (*directory, *file),
+            _ => return Err("File not found"),
+        };
+        let pid = match directory {
+            "self" => reader,
+            _ => directory.parse().map_err(|_| "File not found")?,
+        };
+        let process = table.get(pid).ok_or("File not found")?;
+        match file {
+            "status" => {
+                let tids = process.tids();
+                let state = match tids.iter().all(|tid| table.blocker(*tid).is_some()) {
+                    _ if table.is_stopped(pid) => "T (stopped)",
+                    true => "S (sleeping)",
+                    false => "R (running)",
+                };
+                let credentials = process.credentials();
+                Ok(format!(
+                    "Name:\t{}\nState:\t{}\nPid:\t{}\nPPid:\t{}\nUid:\t{}\nGid:\t{}\nThreads:\t{}\nFDSize:\t{}\nVmSize:\t{} kB\nVmPeak:\t{} kB\nVmRSS:\t{} kB\nSeccomp:\t{}\n",
+                    process.name(),
+                    state,
+                    pid,
+                    table.parent(pid).unwrap_or(0),
+                    credentials.uid,
+                    credentials.gid,
+                    tids.len(),
+                    process.files().count(),
+                    process.memory() / 1024,
+                    process.usage().peak_memory / 1024,
+                    process.space().mapped_pages() * PAGE_SIZE / 1024,
+                    if process.filters().is_empty() { 0 } else { 2 },
+                ))
+            }
+            "limits" => {
+                let value = |value: u64| if value == RLIM_INFINITY { "unlimited".to_string() } else { value.to_string() };
+                let mut contents = format!("{:<26}{:<21}{:<21}{}\n", "Limit", "Soft Limit", "Hard Limit", "Units");
+                for resource in Resource::ALL {
+                    let limit = process.limits().get(resource);
+                    contents += &format!("{:<26}{:<21}{:<21}{}\n", resource.name(), value(limit.soft), value(limit.hard), resource.unit());
+                }
+                Ok(contents)
+            }
+            "usage" => {
+                let usage = process.usage();
+                Ok(format!(
+                    "UserTime:\t{} us\nSystemTime:\t{} us\nSyscalls:\t{}\nFilesOpened:\t{}\n",
+                    usage.user_time.as_micros(),
+                    usage.system_time.as_micros(),
+                    usage.syscalls,
+                    usage.files_opened,
+                ))
+            }
+            _ => Err("File not found"),
+        }
+    }
+
+    // The entries of a directory, for tools walking /proc
+    pub fn list(table: &ProcessTable, reader: Pid, path: &str) -> Result<Vec<String>, &'static str> {
+        match components(path).as_slice() {
+            ["proc"] => Ok(table.pids().iter().map(Pid::to_string).chain(FILES.iter().map(|file| file.to_string())).collect()),
+            ["proc", "config" | "interrupts" | "iomem" | "ioports" | "meminfo" | "metrics"] => Err("Not a directory"),
+            ["proc", directory] => {
+                let pid = match *directory {
+                    "self" => reader,
+                    _ => directory.parse().map_err(|_| "File not found")?,
+                };
+                table.get(pid).ok_or("File not found")?;
+                Ok(PROCESS_FILES.iter().map(|file| file.to_string()).collect())
+            }
+            ["proc", _, _] => Err("Not a directory"),
+            _ => Err("File not found"),
+        }
+    }
+}
@@ -0,0 +1,95 @@
+// src/kernel/process/signal.rs
+
+pub mod signal {
+    use crate::pty::pty::Signal;
+
+    // Numbers are the ABI, as on Linux
+    pub const SIGHUP: u8 = 1;
+    pub const SIGINT: u8 = 2;
+    pub const SIGQUIT: u8 = 3;
+    // Breakpoints and single steps, as a debugger sees them
+    pub const SIGTRAP: u8 = 5;
+    pub const SIGKILL: u8 = 9;
+    // What processes touching memory they may not are killed with
+    pub const SIGSEGV: u8 = 11;
+    // Writers to a pipe nobody reads
+    pub const SIGPIPE: u8 = 13;
+    pub const SIGTERM: u8 = 15;
+    pub const SIGCHLD: u8 = 17;
+    pub const SIGCONT: u8 = 18;
+    pub const SIGSTOP: u8 = 19;
+    pub const SIGTSTP: u8 = 20;
+    // Background jobs touching their terminal
+    pub const SIGTTIN: u8 = 21;
+    pub const SIGTTOU: u8 = 22;
+    // What processes over their CPU time limit are killed with
+    pub const SIGXCPU: u8 = 24;
+    pub const SIGWINCH: u8 = 28;
+    // What processes making calls their filters forbid are killed with
+    pub const SIGSYS: u8 = 31;
+    // One past the highest
+    pub const NSIG: u8 = 65;
+
+    // What a signal does to a process not ignoring it. There are no
+    // handlers in user code yet.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Action {
+        Terminate,
+        Stop,
+        Continue,
+        Ignore,
+    }
+
+    pub fn is_valid(signal: u8) -> bool {
+        (1..NSIG).contains(&signal)
+    }
+
+    pub fn default_action(signal: u8) -> Action {
+        match signal {
+            SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => Action::Stop,
+            SIGCONT => Action::Continue,
+            SIGCHLD | SIGWINCH => Action::Ignore,
+            _ => Action::Terminate,
+        }
+    }
+
+    // What the line discipline raises, for the terminal's foreground job
+    pub fn from_terminal(signal: Signal) -> u8 {
+        match signal {
+            Signal::Interrupt => SIGINT,
+            Signal::Quit => SIGQUIT,
+            Signal::Suspend => SIGTSTP,
+            Signal::WindowChange => SIGWINCH,
+        }
+    }
+
+    // Which signals a process ignores. SIGKILL and SIGSTOP cannot be,
+    // and SIGCONT continues a stopped process either way. Children start
+    // with none ignored.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct Dispositions {
+        ignored: u64,
+    }
+
+    impl Dispositions {
+        pub fn is_ignored(&self, signal: u8) -> bool {
+            is_valid(signal) && self.ignored & 1 << (signal - 1) != 0
+        }
+
+        // Returns whether it was ignored before
+        pub fn set_ignored(&mut self, signal: u8, ignored: bool) -> Result<bool, &'static str> {
+            if !is_valid(signal) {
+                return Err("Invalid signal");
+            }
+            if signal == SIGKILL || signal == SIGSTOP {
+                return Err("Signal cannot be ignored");
+            }
+            let was = self.is_ignored(signal);
+            match ignored {
+                true => self.ignored |= 1 << (signal - 1),
+                false => self.ignored &= !(1 << (signal - 1)),
+            }
+            Ok(was)
+        }
+    }
+}
@@ -0,0 +1,1292 @@
+// src/kernel/process/syscall.rs
+
+pub mod syscall {
+    use crate::drivers::msr::msr::MsrIo;
+    use crate::hardening::hardening;
+    use crate::keyring::keyring::{KeyKind, Secret, MAX_KEY_SIZE};
+    use crate::process::files::files::{OpenFile, MAX_FILES};
+    use crate::process::filter::filter::{self, Action};
+    use crate::process::limits::limits::{Limit, Resource};
+    use crate::process::linker::linker;
+    use crate::process::mapping::mapping::Backing;
+    use crate::process::pipe::pipe;
+    use crate::process::memory::memory::{page_align_down, AddressSpace, KERNEL_BASE, NO_EXECUTE, PAGE_SIZE, USER, USER_END, WRITABLE};
+    use crate::process::procfs::procfs;
+    use crate::process::signal::signal::{self, SIGPIPE, SIGSYS, SIGTTIN, SIGTTOU};
+    use crate::process::table::table::{Blocker, ExitStatus, Pid, ProcessTable, Tid, KERNEL_PID};
+    use crate::process::task::task::{Thread, UserContext, R10, R11, R8, R9, RAX, RCX, RDI, RDX, RFLAGS_IF, RFLAGS_RESERVED, RSI, USER_DATA_SELECTOR};
+    use crate::process::uaccess::uaccess;
+    use crate::pty::pty::PtySlave;
+    use crate::users::users::{Credentials, Gid, Uid, ACCESS_EXECUTE, ACCESS_READ, ACCESS_WRITE};
+    use crate::vxchan::vxchan::VXChanManager;
+    use std::ffi::CString;
+    use std::fs::{self, Metadata, OpenOptions, Permissions};
+    use std::io::{self, ErrorKind, Read, Write};
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::{self as unix_fs, FileTypeExt, MetadataExt, PermissionsExt};
+    use std::path::Path;
+    use std::sync::Arc;
+
+    pub const IA32_EFER: u32 = 0xC000_0080;
+    pub const IA32_STAR: u32 = 0xC000_0081;
+    pub const IA32_LSTAR: u32 = 0xC000_0082;
+    pub const IA32_FMASK: u32 = 0xC000_0084;
+    pub const EFER_SCE: u64 = 1;
+    pub const KERNEL_CODE_SELECTOR: u16 = 0x08;
+    // SYSRET loads SS from this plus 8 and CS from it plus 16
+    const SYSRET_SELECTOR: u16 = USER_DATA_SELECTOR - 8;
+    // Cleared on entry: trap, interrupts, direction and alignment check
+    pub const SYSCALL_FLAGS_MASK: u64 = 1 << 8 | 1 << 9 | 1 << 10 | 1 << 18;
+    // Flags user code may set for itself: the arithmetic ones, trap,
+    // direction and alignment check. Never IOPL.
+    const USER_FLAGS: u64 = 0x4_0DD5;
+
+    // Numbers are the ABI: never reuse or reorder them, only add more
+    pub const SYS_READ: u64 = 0;
+    pub const SYS_WRITE: u64 = 1;
+    pub const SYS_OPEN: u64 = 2;
+    pub const SYS_CLOSE: u64 = 3;
+    pub const SYS_MMAP: u64 = 4;
+    pub const SYS_SPAWN: u64 = 5;
+    pub const SYS_EXIT: u64 = 6;
+    pub const SYS_CHANNEL_CREATE: u64 = 7;
+    pub const SYS_CHANNEL_OPEN: u64 = 8;
+    pub const SYS_WAIT: u64 = 9;
+    pub const SYS_GETPID: u64 = 10;
+    pub const SYS_GETPPID: u64 = 11;
+    pub const SYS_THREAD_CREATE: u64 = 12;
+    pub const SYS_THREAD_EXIT: u64 = 13;
+    pub const SYS_GETTID: u64 = 14;
+    pub const SYS_FUTEX: u64 = 15;
+    pub const SYS_SET_TLS: u64 = 16;
+    pub const SYS_GETUID: u64 = 17;
+    pub const SYS_GETGID: u64 = 18;
+    pub const SYS_SETUID: u64 = 19;
+    pub const SYS_SETGID: u64 = 20;
+    pub const SYS_CHMOD: u64 = 21;
+    pub const SYS_CHOWN: u64 = 22;
+    pub const SYS_GETRLIMIT: u64 = 23;
+    pub const SYS_SETRLIMIT: u64 = 24;
+    pub const SYS_MUNMAP: u64 = 25;
+    pub const SYS_MPROTECT: u64 = 26;
+    pub const SYS_DLOPEN: u64 = 27;
+    pub const SYS_DLSYM: u64 = 28;
+    pub const SYS_DLCLOSE: u64 = 29;
+    pub const SYS_KILL: u64 = 30;
+    pub const SYS_SIGNAL: u64 = 31;
+    pub const SYS_SETPGID: u64 = 32;
+    pub const SYS_GETPGID: u64 = 33;
+    pub const SYS_SETSID: u64 = 34;
+    pub const SYS_GETSID: u64 = 35;
+    pub const SYS_TCGETPGRP: u64 = 36;
+    pub const SYS_TCSETPGRP: u64 = 37;
+    pub const SYS_SETCTTY: u64 = 38;
+    pub const SYS_PIPE: u64 = 39;
+    pub const SYS_MKFIFO: u64 = 40;
+    pub const SYS_POLL: u64 = 41;
+    pub const SYS_ADD_KEY: u64 = 42;
+    pub const SYS_REQUEST_KEY: u64 = 43;
+    pub const SYS_READ_KEY: u64 = 44;
+    pub const SYS_REVOKE_KEY: u64 = 45;
+
+    // open flags
+    pub const O_RDONLY: u64 = 0;
+    pub const O_WRONLY: u64 = 1;
+    pub const O_RDWR: u64 = 2;
+    const O_ACCMODE: u64 = 3;
+    pub const O_CREAT: u64 = 0o100;
+    pub const O_EXCL: u64 = 0o200;
+    pub const O_TRUNC: u64 = 0o1000;
+    pub const O_APPEND: u64 = 0o2000;
+    pub const O_NONBLOCK: u64 = 0o4000;
+
+    // mmap protection and flags
+    pub const PROT_READ: u64 = 1;
+    pub const PROT_WRITE: u64 = 2;
+    pub const PROT_EXEC: u64 = 4;
+    pub const MAP_SHARED: u64 = 1;
+    pub const MAP_PRIVATE: u64 = 2;
+    pub const MAP_FIXED: u64 = 0x10;
+    pub const MAP_ANONYMOUS: u64 = 0x20;
+
+    // wait flags
+    pub const WNOHANG: u64 = 1;
+    pub const WUNTRACED: u64 = 2;
+    // wait for any child
+    pub const ANY_CHILD: u64 = u64::MAX;
+
+    // signal actions
+    pub const SIG_DFL: u64 = 0;
+    pub const SIG_IGN: u64 = 1;
+
+    // poll events, in a struct pollfd { int fd; short events; short
+    // revents; }
+    pub const POLLIN: u16 = 1;
+    pub const POLLOUT: u16 = 4;
+    pub const POLLERR: u16 = 8;
+    pub const POLLHUP: u16 = 0x10;
+    pub const POLLNVAL: u16 = 0x20;
+    pub const POLLFD_SIZE: usize = 8;
+
+    // futex operations
+    pub const FUTEX_WAIT: u64 = 0;
+    pub const FUTEX_WAKE: u64 = 1;
+
+    // Longest path or argument, not counting the NUL
+    pub const MAX_STRING: usize = 4096;
+    pub const MAX_ARGUMENTS: usize = 1024;
+    // Reads and writes move at most this much at once, returning a short
+    // count past it
+    pub const MAX_TRANSFER: usize = 1 << 20;
+
+    // Errors go back to user code as their negated number in RAX
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Errno {
+        NotPermitted = 1,
+        NoEntry = 2,
+        NoProcess = 3,
+        Interrupted = 4,
+        Io = 5,
+        NoDeviceOrAddress = 6,
+        TooBig = 7,
+        NotExecutable = 8,
+        BadFile = 9,
+        NoChild = 10,
+        Again = 11,
+        NoMemory = 12,
+        Access = 13,
+        Fault = 14,
+        Exists = 17,
+        NoDevice = 19,
+        IsDirectory = 21,
+        Invalid = 22,
+        TooManyFiles = 24,
+        NotTerminal = 25,
+        Pipe = 32,
+        NameTooLong = 36,
+        NoSys = 38,
+        MessageSize = 90,
+        NoKey = 126,
+    }
+
+    impl Errno {
+        pub fn as_return(self) -> u64 {
+            (-(self as i64)) as u64
+        }
+
+        // For people, as strerror gives it
+        pub fn message(self) -> &'static str {
+            match self {
+                Errno::NotPermitted => "Operation not permitted",
+                Errno::NoEntry => "No such file or directory",
+                Errno::NoProcess => "No such process",
+                Errno::Interrupted => "Interrupted system call",
+                Errno::Io => "Input/output error",
+                Errno::NoDeviceOrAddress => "No such device or address",
+                Errno::TooBig => "Argument list too long",
+                Errno::NotExecutable => "Exec format error",
+                Errno::BadFile => "Bad file descriptor",
+                Errno::NoChild => "No child processes",
+                Errno::Again => "Resource temporarily unavailable",
+                Errno::NoMemory => "Cannot allocate memory",
+                Errno::Access => "Permission denied",
+                Errno::Fault => "Bad address",
+                Errno::Exists => "File exists",
+                Errno::NoDevice => "No such device",
+                Errno::IsDirectory => "Is a directory",
+                Errno::Invalid => "Invalid argument",
+                Errno::TooManyFiles => "Too many open files",
+                Errno::NotTerminal => "Inappropriate ioctl for device",
+                Errno::Pipe => "Broken pipe",
+                Errno::NameTooLong => "File name too long",
+                Errno::NoSys => "Function not implemented",
+                Errno::MessageSize => "Message too long",
+                Errno::NoKey => "Required key not available",
+            }
+        }
+
+        fn from_io(error: io::Error) -> Self {
+            match error.kind() {
+                ErrorKind::NotFound => Errno::NoEntry,
+                ErrorKind::PermissionDenied => Errno::Access,
+                ErrorKind::AlreadyExists => Errno::Exists,
+                ErrorKind::IsADirectory => Errno::IsDirectory,
+                _ => Errno::Io,
+            }
+        }
+    }
+
+    // Points SYSCALL at the kernel's entry stub on one CPU
+    pub fn enable(msr: &dyn MsrIo, cpu: usize, entry: u64) -> Result<(), &'static str> {
+        if entry < KERNEL_BASE {
+            return Err("Entry point outside kernel space");
+        }
+        let efer = msr.rdmsr(cpu, IA32_EFER)?;
+        msr.wrmsr(cpu, IA32_STAR, (SYSRET_SELECTOR as u64) << 48 | (KERNEL_CODE_SELECTOR as u64) << 32)?;
+        msr.wrmsr(cpu, IA32_LSTAR, entry)?;
+        msr.wrmsr(cpu, IA32_FMASK, SYSCALL_FLAGS_MASK)?;
+        msr.wrmsr(cpu, IA32_EFER, efer | EFER_SCE)
+    }
+
+    // Whether user code could make the same access itself: all of it in
+    // user space, mapped for user mode, and writable to be written
+    pub fn check_user(space: &AddressSpace, address: u64, length: usize, write: bool) -> Result<(), Errno> {
+        if length == 0 {
+            return Ok(());
+        }
+        let end = address.checked_add(length as u64).filter(|end| *end <= USER_END).ok_or(Errno::Fault)?;
+        let mut page = page_align_down(address);
+        while page < end {
+            space.populate(page).map_err(|_| Errno::Fault)?;
+            let (_, flags) = space.translate(page).ok_or(Errno::Fault)?;
+            if flags & USER == 0 || (write && flags & WRITABLE == 0) {
+                return Err(Errno::Fault);
+            }
+            page += PAGE_SIZE;
+        }
+        Ok(())
+    }
+
+    // The only ways into user memory from a system call, with SMAP lifted
+    // for just the range checked
+    pub fn copy_from_user(space: &AddressSpace, address: u64, length: usize) -> Result<Vec<u8>, Errno> {
+        check_user(space, address, length, false)?;
+        let mut buffer = vec![0; length];
+        uaccess::with_user_access(|| space.read(address, &mut buffer)).map_err(|_| Errno::Fault)?;
+        Ok(buffer)
+    }
+
+    // Nothing is written unless all of it can be
+    pub fn copy_to_user(space: &AddressSpace, address: u64, data: &[u8]) -> Result<(), Errno> {
+        check_user(space, address, data.len(), true)?;
+        uaccess::with_user_access(|| space.write(address, data)).map_err(|_| Errno::Fault)
+    }
+
+    // A NUL-terminated string, read a page at a time so one ending just
+    // before an unmapped page is fine
+    pub fn copy_string_from_user(space: &AddressSpace, address: u64, max: usize) -> Result<String, Errno> {
+        let mut bytes = Vec::new();
+        let mut next = address;
+        loop {
+            let chunk = copy_from_user(space, next, (PAGE_SIZE - next % PAGE_SIZE) as usize)?;
+            if let Some(end) = chunk.iter().position(|byte| *byte == 0) {
+                bytes.extend_from_slice(&chunk[..end]);
+                break;
+            }
+            bytes.extend_from_slice(&chunk);
+            if bytes.len() > max {
+                return Err(Errno::NameTooLong);
+            }
+            next += chunk.len() as u64;
+        }
+        if bytes.len() > max {
+            return Err(Errno::NameTooLong);
+        }
+        String::from_utf8(bytes).map_err(|_| Errno::Invalid)
+    }
+
+    // A null-terminated array of string pointers, as for argv. A null
+    // array is empty.
+    pub fn copy_strings_from_user(space: &AddressSpace, address: u64) -> Result<Vec<String>, Errno> {
+        let mut strings = Vec::new();
+        if address == 0 {
+            return Ok(strings);
+        }
+        for index in 0..=MAX_ARGUMENTS as u64 {
+            let slot = address.checked_add(index * 8).ok_or(Errno::Fault)?;
+            let pointer = u64::from_le_bytes(copy_from_user(space, slot, 8)?.try_into().unwrap());
+            if pointer == 0 {
+                return Ok(strings);
+            }
+            strings.push(copy_string_from_user(space, pointer, MAX_STRING)?);
+        }
+        Err(Errno::TooBig)
+    }
+
+    // With the calling process and thread
+    type Handler = fn(&mut ProcessTable, Pid, Tid, [u64; 6]) -> Result<u64, Errno>;
+
+    // By number
+    const SYSCALLS: [(&str, Handler); 46] = [
+        ("read", sys_read),
+        ("write", sys_write),
+        ("open", sys_open),
+        ("close", sys_close),
+        ("mmap", sys_mmap),
+        ("spawn", sys_spawn),
+        ("exit", sys_exit),
+        ("channel_create", sys_channel_create),
+        ("channel_open", sys_channel_open),
+        ("wait", sys_wait),
+        ("getpid", sys_getpid),
+        ("getppid", sys_getppid),
+        ("thread_create", sys_thread_create),
+        ("thread_exit", sys_thread_exit),
+        ("gettid", sys_gettid),
+        ("futex", sys_futex),
+        ("set_tls", sys_set_tls),
+        ("getuid", sys_getuid),
+        ("getgid", sys_getgid),
+        ("setuid", sys_setuid),
+        ("setgid", sys_setgid),
+        ("chmod", sys_chmod),
+        ("chown", sys_chown),
+        ("getrlimit", sys_getrlimit),
+        ("setrlimit", sys_setrlimit),
+        ("munmap", sys_munmap),
+        ("mprotect", sys_mprotect),
+        ("dlopen", sys_dlopen),
+        ("dlsym", sys_dlsym),
+        ("dlclose", sys_dlclose),
+        ("kill", sys_kill),
+        ("signal", sys_signal),
+        ("setpgid", sys_setpgid),
+        ("getpgid", sys_getpgid),
+        ("setsid", sys_setsid),
+        ("getsid", sys_getsid),
+        ("tcgetpgrp", sys_tcgetpgrp),
+        ("tcsetpgrp", sys_tcsetpgrp),
+        ("setctty", sys_setctty),
+        ("pipe", sys_pipe),
+        ("mkfifo", sys_mkfifo),
+        ("poll", sys_poll),
+        ("add_key", sys_add_key),
+        ("request_key", sys_request_key),
+        ("read_key", sys_read_key),
+        ("revoke_key", sys_revoke_key),
+    ];
+
+    pub fn name(number: u64) -> Option<&'static str> {
+        SYSCALLS.get(usize::try_from(number).ok()?).map(|(name, _)| *name)
+    }
+
+    pub fn number(name: &str) -> Option<u64> {
+        SYSCALLS.iter().position(|(other, _)| *other == name).map(|number| number as u64)
+    }
+
+    // The number is in RAX and the arguments in RDI, RSI, RDX, R10, R8 and
+    // R9; RCX and R11 hold what SYSCALL saved
+    pub fn arguments(context: &UserContext) -> [u64; 6] {
+        [RDI, RSI, RDX, R10, R8, R9].map(|register| context.registers[register])
+    }
+
+    // Returns from a system call as SYSRET would, with the result in RAX
+    pub fn finish(context: &mut UserContext, result: Result<u64, Errno>) {
+        context.registers[RAX] = result.unwrap_or_else(Errno::as_return);
+        context.rip = context.registers[RCX];
+        context.rflags = context.registers[R11] & USER_FLAGS | RFLAGS_IF | RFLAGS_RESERVED;
+    }
+
+    // Handles the system call a process trapped on, unless its filters
+    // stop it. Blocking calls return later, when whatever blocked them
+    // finishes them.
+    pub fn dispatch(table: &mut ProcessTable, pid: Pid, tid: Tid) -> Result<(), &'static str> {
+        let process = table.get(pid).ok_or("No such thread")?;
+        let context = process.thread(tid).ok_or("No such thread")?.context;
+        let number = context.registers[RAX];
+        let arguments = arguments(&context);
+        let result = match filter::check(process.filters(), number, &arguments) {
+            Some(Action::Kill) => {
+                log::warn!("Process {} killed for system call {}", pid, number);
+                table.exit(pid, ExitStatus::Killed(SIGSYS));
+                return Ok(());
+            }
+            Some(Action::Deny) => Err(Errno::NotPermitted),
+            None => match usize::try_from(number).ok().and_then(|number| SYSCALLS.get(number)) {
+                Some((_, handler)) => handler(table, pid, tid, arguments),
+                None => Err(Errno::NoSys),
+            },
+        };
+        if table.blocker(tid).is_some() {
+            return Ok(());
+        }
+        // Unless it exited
+        if let Some(thread) = table.get_mut(pid).and_then(|process| process.thread_mut(tid)) {
+            finish(&mut thread.context, result);
+        }
+        Ok(())
+    }
+
+    fn descriptor(value: u64) -> Result<usize, Errno> {
+        usize::try_from(value).map_err(|_| Errno::BadFile)
+    }
+
+    pub fn read_from(file: &mut OpenFile, channels: &VXChanManager, count: usize) -> Result<Vec<u8>, Errno> {
+        match file {
+            OpenFile::Terminal { slave, pending } => {
+                if pending.is_empty() {
+                    // An empty line is end of file
+                    *pending = slave.read().map_err(|_| Errno::Io)?.ok_or(Errno::Again)?;
+                }
+                Ok(pending.drain(..count.min(pending.len())).collect())
+            }
+            OpenFile::File { file, readable, .. } => {
+                if !*readable {
+                    return Err(Errno::BadFile);
+                }
+                let mut buffer = vec![0; count];
+                let read = file.read(&mut buffer).map_err(Errno::from_io)?;
+                buffer.truncate(read);
+                Ok(buffer)
+            }
+            OpenFile::Channel { name, pending } => {
+                let message = match pending.take() {
+                    Some(message) => message,
+                    None => channels.try_receive_message(name).map_err(|_| Errno::NoEntry)?.ok_or(Errno::Again)?,
+                };
+                if message.len() > count {
+                    *pending = Some(message);
+                    return Err(Errno::MessageSize);
+                }
+                Ok(message.into_bytes())
+            }
+            OpenFile::Snapshot { data, position } => {
+                let end = (*position + count).min(data.len());
+                let chunk = data[*position..end].to_vec();
+                *position = end;
+                Ok(chunk)
+            }
+            OpenFile::PipeReader { reader, .. } => reader.read(count).ok_or(Errno::Again),
+            OpenFile::PipeWriter { .. } => Err(Errno::BadFile),
+        }
+    }
+
+    pub fn write_to(file: &mut OpenFile, channels: &VXChanManager, data: Vec<u8>) -> Result<u64, Errno> {
+        let length = data.len() as u64;
+        match file {
+            OpenFile::Terminal { slave, .. } => slave.write(&data).map_err(|_| Errno::Io)?,
+            OpenFile::File { file, writable, .. } => {
+                if !*writable {
+                    return Err(Errno::BadFile);
+                }
+                return Ok(file.write(&data).map_err(Errno::from_io)? as u64);
+            }
+            OpenFile::Channel { name, .. } => {
+                let message = String::from_utf8(data).map_err(|_| Errno::Invalid)?;
+                channels.send_message(name, message).map_err(|_| Errno::NoEntry)?;
+            }
+            OpenFile::Snapshot { .. } | OpenFile::PipeReader { .. } => return Err(Errno::BadFile),
+            OpenFile::PipeWriter { writer, .. } => {
+                return match writer.write(&data).map_err(|_| Errno::Pipe)? {
+                    0 if length > 0 => Err(Errno::Again),
+                    written => Ok(written as u64),
+                };
+            }
+        }
+        Ok(length)
+    }
+
+    // Which events poll would report. Channels cannot be looked into, so
+    // they only show as readable with a message held back.
+    fn poll_file(file: &OpenFile) -> u16 {
+        match file {
+            OpenFile::Terminal { slave, pending } => {
+                let readable = if !pending.is_empty() || slave.is_readable() { POLLIN } else { 0 };
+                let hung_up = if slave.is_hung_up() { POLLHUP } else { 0 };
+                POLLOUT | readable | hung_up
+            }
+            OpenFile::File { readable, writable, .. } => (if *readable { POLLIN } else { 0 }) | (if *writable { POLLOUT } else { 0 }),
+            OpenFile::Channel { pending, .. } => POLLOUT | if pending.is_some() { POLLIN } else { 0 },
+            OpenFile::Snapshot { .. } => POLLIN,
+            OpenFile::PipeReader { reader, .. } => reader.poll(),
+            OpenFile::PipeWriter { writer, .. } => writer.poll(),
+        }
+    }
+
+    // The pipe a descriptor is open on, when reads or writes that cannot
+    // go ahead wait for it
+    fn blocking_pipe(file: &OpenFile) -> Option<u64> {
+        match file {
+            OpenFile::PipeReader { nonblocking: false, .. } | OpenFile::PipeWriter { nonblocking: false, .. } => file.pipe_id(),
+            _ => None,
+        }
+    }
+
+    // The terminal a descriptor is open on
+    fn terminal(table: &ProcessTable, pid: Pid, fd: u64) -> Result<PtySlave, Errno> {
+        match table.get(pid).unwrap().files().get(descriptor(fd)?) {
+            Some(OpenFile::Terminal { slave, .. }) => Ok(slave.clone()),
+            Some(_) => Err(Errno::NotTerminal),
+            None => Err(Errno::BadFile),
+        }
+    }
+
+    fn terminal_errno(error: &'static str) -> Errno {
+        match error {
+            "Interrupted" => Errno::Interrupted,
+            "Not a controlling terminal" => Errno::NotTerminal,
+            "Not permitted" => Errno::NotPermitted,
+            _ => Errno::Io,
+        }
+    }
+
+    // Background jobs reading their terminal are stopped first. Reading an
+    // empty pipe waits for a writer, or the last one to close it.
+    fn sys_read(table: &mut ProcessTable, pid: Pid, tid: Tid, [fd, buffer, count, ..]: [u64; 6]) -> Result<u64, Errno> {
+        if let Ok(slave) = terminal(table, pid, fd) {
+            table.terminal_access(pid, &slave, SIGTTIN).map_err(terminal_errno)?;
+        }
+        let channels = table.channels().clone();
+        let process = table.get_mut(pid).unwrap();
+        let count = count.min(MAX_TRANSFER as u64) as usize;
+        // Before taking anything that would be lost
+        check_user(process.space(), buffer, count, true)?;
+        let file = process.files_mut().get_mut(descriptor(fd)?).ok_or(Errno::BadFile)?;
+        let (pipe, blocking) = (file.pipe_id(), blocking_pipe(file));
+        let data = match (read_from(file, &channels, count), blocking) {
+            (Err(Errno::Again), Some(id)) => {
+                table.block(tid, Blocker::Pipe(id));
+                return Ok(0);
+            }
+            (result, _) => result?,
+        };
+        copy_to_user(process.space(), buffer, &data)?;
+        if let Some(id) = pipe.filter(|_| !data.is_empty()) {
+            table.wake_pipe(id);
+        }
+        Ok(data.len() as u64)
+    }
+
+    // Writing a full pipe waits for room, and writes what fits. Writing
+    // one nobody reads raises SIGPIPE, failing if that is ignored.
+    fn sys_write(table: &mut ProcessTable, pid: Pid, tid: Tid, [fd, buffer, count, ..]: [u64; 6]) -> Result<u64, Errno> {
+        let channels = table.channels().clone();
+        let process = table.get_mut(pid).unwrap();
+        let data = copy_from_user(process.space(), buffer, count.min(MAX_TRANSFER as u64) as usize)?;
+        let file = process.files_mut().get_mut(descriptor(fd)?).ok_or(Errno::BadFile)?;
+        let (pipe, blocking) = (file.pipe_id(), blocking_pipe(file));
+        match (write_to(file, &channels, data), blocking) {
+            (Err(Errno::Again), Some(id)) => {
+                table.block(tid, Blocker::Pipe(id));
+                Ok(0)
+            }
+            (Err(Errno::Pipe), _) => {
+                let _ = table.signal(pid, SIGPIPE);
+                Err(Errno::Pipe)
+            }
+            (Ok(written), _) => {
+                if let Some(id) = pipe {
+                    table.wake_pipe(id);
+                }
+                Ok(written)
+            }
+            (Err(error), _) => Err(error),
+        }
+    }
+
+    // Opening a FIFO for writing waits for it to have a reader, which
+    // those waiting are told of
+    fn sys_open(table: &mut ProcessTable, pid: Pid, tid: Tid, [path, flags, ..]: [u64; 6]) -> Result<u64, Errno> {
+        let path = copy_string_from_user(table.get(pid).unwrap().space(), path, MAX_STRING)?;
+        let file = match open(table, pid, &path, flags) {
+            Err(Errno::Again) if flags & O_NONBLOCK == 0 => {
+                table.block(tid, Blocker::FifoOpen);
+                return Ok(0);
+            }
+            result => result?,
+        };
+        let reader = matches!(file, OpenFile::PipeReader { .. });
+        let fd = open_file(table, pid, file)?;
+        if reader {
+            table.wake_fifo_openers();
+        }
+        Ok(fd)
+    }
+
+    // A path as the process would open it, not yet given a descriptor
+    pub fn open(table: &ProcessTable, pid: Pid, path: &str, flags: u64) -> Result<OpenFile, Errno> {
+        if flags & !(O_ACCMODE | O_CREAT | O_EXCL | O_TRUNC | O_APPEND | O_NONBLOCK) != 0 {
+            return Err(Errno::Invalid);
+        }
+        let (readable, writable) = match flags & O_ACCMODE {
+            O_RDONLY => (true, false),
+            O_WRONLY => (false, true),
+            O_RDWR => (true, true),
+            _ => return Err(Errno::Invalid),
+        };
+        // The kernel opens files as root
+        let credentials = match pid {
+            KERNEL_PID => Credentials::root(),
+            _ => table.get(pid).ok_or(Errno::NoProcess)?.credentials().clone(),
+        };
+        if procfs::is_proc(path) {
+            if writable || flags & (O_CREAT | O_TRUNC | O_APPEND) != 0 {
+                return Err(Errno::Access);
+            }
+            let data = procfs::read(table, pid, path).map_err(|error| match error {
+                "Is a directory" => Errno::IsDirectory,
+                _ => Errno::NoEntry,
+            })?;
+            return Ok(OpenFile::Snapshot { data: data.into_bytes(), position: 0 });
+        }
+        let mut access = if readable { ACCESS_READ } else { 0 };
+        if writable || flags & O_TRUNC != 0 {
+            access |= ACCESS_WRITE;
+        }
+        // New files need a directory that can be written to, and belong to
+        // whoever made them
+        let (host, created) = match table.access(&credentials, path, access) {
+            Err("File not found") if flags & O_CREAT != 0 => {
+                let directory = Path::new(path).parent().and_then(Path::to_str).ok_or(Errno::Invalid)?;
+                table.access(&credentials, directory, ACCESS_WRITE | ACCESS_EXECUTE).map_err(access_errno)?;
+                (table.resolve(path).map_err(access_errno)?, true)
+            }
+            result => (result.map_err(access_errno)?, false),
+        };
+        if let Some(metadata) = fs::metadata(&host).ok().filter(|metadata| metadata.file_type().is_fifo()) {
+            return open_fifo(table, &metadata, readable, writable, flags & O_NONBLOCK != 0);
+        }
+        let file = OpenOptions::new()
+            .read(readable)
+            .write(writable)
+            .append(flags & O_APPEND != 0)
+            .truncate(flags & O_TRUNC != 0)
+            .create(flags & O_CREAT != 0 && flags & O_EXCL == 0)
+            .create_new(flags & O_CREAT != 0 && flags & O_EXCL != 0)
+            .open(&host)
+            .map_err(Errno::from_io)?;
+        if created {
+            unix_fs::chown(&host, Some(credentials.uid), Some(credentials.gid)).map_err(Errno::from_io)?;
+        }
+        Ok(OpenFile::File { file, readable, writable })
+    }
+
+    // Either end of a FIFO's pipe, which cannot be both. Opening it for
+    // writing with no reader fails with EAGAIN for the caller to wait, or
+    // ENXIO when nonblocking. Readers go ahead, and wait in read for the
+    // first writer.
+    fn open_fifo(table: &ProcessTable, metadata: &Metadata, readable: bool, writable: bool, nonblocking: bool) -> Result<OpenFile, Errno> {
+        let pipe = table.fifos().get((metadata.dev(), metadata.ino()));
+        match (readable, writable) {
+            (true, false) => Ok(OpenFile::PipeReader { reader: pipe.reader(), nonblocking }),
+            (false, true) if pipe.readers() == 0 && nonblocking => Err(Errno::NoDeviceOrAddress),
+            (false, true) if pipe.readers() == 0 => Err(Errno::Again),
+            (false, true) => Ok(OpenFile::PipeWriter { writer: pipe.writer(), nonblocking }),
+            _ => Err(Errno::Invalid),
+        }
+    }
+
+    fn open_file(table: &mut ProcessTable, pid: Pid, file: OpenFile) -> Result<u64, Errno> {
+        let process = table.get_mut(pid).unwrap();
+        let fd = process.files_mut().insert(file).map_err(|_| Errno::TooManyFiles)?;
+        let usage = process.usage_mut();
+        usage.files_opened = hardening::add(usage.files_opened, 1, "Open count");
+        Ok(fd as u64)
+    }
+
+    fn sys_close(table: &mut ProcessTable, pid: Pid, _: Tid, [fd, ..]: [u64; 6]) -> Result<u64, Errno> {
+        let files = table.get_mut(pid).unwrap().files_mut();
+        let pipe = files.close(descriptor(fd)?).map_err(|_| Errno::BadFile)?.pipe_id();
+        if let Some(id) = pipe {
+            table.wake_pipe(id);
+        }
+        Ok(0)
+    }
+
+    // Both ends of a new pipe, the read end's descriptor first as two
+    // ints. Only O_NONBLOCK may be given.
+    fn sys_pipe(table: &mut ProcessTable, pid: Pid, _: Tid, [fds, flags, ..]: [u64; 6]) -> Result<u64, Errno> {
+        if flags & !O_NONBLOCK != 0 {
+            return Err(Errno::Invalid);
+        }
+        check_user(table.get(pid).unwrap().space(), fds, 8, true)?;
+        let nonblocking = flags & O_NONBLOCK != 0;
+        let (reader, writer) = pipe::pipe();
+        let read_fd = open_file(table, pid, OpenFile::PipeReader { reader, nonblocking })?;
+        let write_fd = match open_file(table, pid, OpenFile::PipeWriter { writer, nonblocking }) {
+            Ok(fd) => fd,
+            Err(error) => {
+                let _ = table.get_mut(pid).unwrap().files_mut().close(read_fd as usize);
+                return Err(error);
+            }
+        };
+        let mut pair = (read_fd as u32).to_le_bytes().to_vec();
+        pair.extend_from_slice(&(write_fd as u32).to_le_bytes());
+        copy_to_user(table.get(pid).unwrap().space(), fds, &pair)?;
+        Ok(0)
+    }
+
+    // A FIFO node, made as O_CREAT makes files. The host keeps the node and
+    // the kernel the pipe behind it.
+    fn sys_mkfifo(table: &mut ProcessTable, pid: Pid, _: Tid, [path, mode, ..]: [u64; 6]) -> Result<u64, Errno> {
+        if mode & !0o777 != 0 {
+            return Err(Errno::Invalid);
+        }
+        let process = table.get(pid).unwrap();
+        let path = copy_string_from_user(process.space(), path, MAX_STRING)?;
+        if procfs::is_proc(&path) {
+            return Err(Errno::Access);
+        }
+        let credentials = process.credentials().clone();
+        let directory = Path::new(&path).parent().and_then(Path::to_str).ok_or(Errno::Exists)?;
+        table.access(&credentials, directory, ACCESS_WRITE | ACCESS_EXECUTE).map_err(access_errno)?;
+        let host = table.resolve(&path).map_err(access_errno)?;
+        if host.symlink_metadata().is_ok() {
+            return Err(Errno::Exists);
+        }
+        let name = CString::new(host.as_os_str().as_bytes()).map_err(|_| Errno::Invalid)?;
+        // SAFETY: name is a NUL-terminated path that outlives the call
+        if unsafe { libc::mkfifo(name.as_ptr(), mode as libc::mode_t) } != 0 {
+            return Err(Errno::from_io(io::Error::last_os_error()));
+        }
+        // Past the host's umask
+        fs::set_permissions(&host, Permissions::from_mode(mode as u32)).map_err(Errno::from_io)?;
+        unix_fs::chown(&host, Some(credentials.uid), Some(credentials.gid)).map_err(Errno::from_io)?;
+        Ok(0)
+    }
+
+    // Fills in revents for each struct pollfd, returning how many have
+    // any. POLLERR, POLLHUP and POLLNVAL come whether asked for or not,
+    // and negative descriptors are skipped. The timeout is 0, to return
+    // straight away, or -1 to wait for one to be ready; waits end with
+    // pipe or terminal activity.
+    fn sys_poll(table: &mut ProcessTable, pid: Pid, tid: Tid, [fds, count, timeout, ..]: [u64; 6]) -> Result<u64, Errno> {
+        let wait = match timeout as i64 {
+            0 => false,
+            -1 => true,
+            _ => return Err(Errno::Invalid),
+        };
+        if count > MAX_FILES as u64 {
+            return Err(Errno::Invalid);
+        }
+        let process = table.get_mut(pid).unwrap();
+        let mut entries = copy_from_user(process.space(), fds, count as usize * POLLFD_SIZE)?;
+        let mut ready = 0;
+        for entry in entries.chunks_mut(POLLFD_SIZE) {
+            let fd = i32::from_le_bytes(entry[..4].try_into().unwrap());
+            let events = u16::from_le_bytes(entry[4..6].try_into().unwrap());
+            let revents = match usize::try_from(fd) {
+                Err(_) => 0,
+                Ok(fd) => match process.files().get(fd) {
+                    Some(file) => poll_file(file) & (events | POLLERR | POLLHUP),
+                    None => POLLNVAL,
+                },
+            };
+            entry[6..].copy_from_slice(&revents.to_le_bytes());
+            ready += (revents != 0) as u64;
+        }
+        if ready == 0 && wait {
+            table.block(tid, Blocker::Poll);
+            return Ok(0);
+        }
+        copy_to_user(table.get(pid).unwrap().space(), fds, &entries)?;
+        Ok(ready)
+    }
+
+    // Page table flags for mmap protection. Pages cannot be write-only,
+    // and with no protection at all user code cannot touch them. Nor can
+    // they be writable and executable at once.
+    fn page_flags(prot: u64) -> Result<u64, Errno> {
+        if prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+            return Err(Errno::Invalid);
+        }
+        if prot & PROT_WRITE != 0 && prot & PROT_EXEC != 0 {
+            return Err(Errno::Access);
+        }
+        if prot == 0 {
+            return Ok(NO_EXECUTE);
+        }
+        let writable = if prot & PROT_WRITE != 0 { WRITABLE } else { 0 };
+        let no_execute = if prot & PROT_EXEC == 0 { NO_EXECUTE } else { 0 };
+        Ok(USER | writable | no_execute)
+    }
+
+    // Memory filled in as it is touched, placed by the kernel or, with
+    // MAP_FIXED, exactly at the address in place of any mappings there.
+    // Files are mapped from a page-aligned offset: privately as copies of
+    // their pages, or shared through the page cache so that writes reach
+    // the file and everyone else mapping it.
+    fn sys_mmap(table: &mut ProcessTable, pid: Pid, _: Tid, [address, length, prot, flags, fd, offset]: [u64; 6]) -> Result<u64, Errno> {
+        if flags & !(MAP_SHARED | MAP_PRIVATE | MAP_FIXED | MAP_ANONYMOUS) != 0 {
+            return Err(Errno::Invalid);
+        }
+        let shared = match flags & (MAP_SHARED | MAP_PRIVATE) {
+            MAP_SHARED => true,
+            MAP_PRIVATE => false,
+            _ => return Err(Errno::Invalid),
+        };
+        let page_flags = page_flags(prot)?;
+        let backing = if flags & MAP_ANONYMOUS != 0 {
+            Backing::Anonymous
+        } else {
+            if !offset.is_multiple_of(PAGE_SIZE) || offset.checked_add(length).is_none() {
+                return Err(Errno::Invalid);
+            }
+            let OpenFile::File { file, readable, writable } = table.get(pid).unwrap().files().get(descriptor(fd)?).ok_or(Errno::BadFile)? else {
+                return Err(Errno::NoDevice);
+            };
+            if !readable || (shared && prot & PROT_WRITE != 0 && !writable) {
+                return Err(Errno::Access);
+            }
+            let metadata = file.metadata().map_err(Errno::from_io)?;
+            Backing::File {
+                cache: table.page_cache().clone(),
+                file: Arc::new(file.try_clone().map_err(Errno::from_io)?),
+                key: (metadata.dev(), metadata.ino()),
+                offset,
+                writable: *writable,
+            }
+        };
+        let fixed = (flags & MAP_FIXED != 0).then_some(address);
+        let process = table.get_mut(pid).unwrap();
+        process.map(fixed, length, page_flags, shared, backing).map_err(|error| match error {
+            "Invalid length" | "Invalid address" => Errno::Invalid,
+            _ => Errno::NoMemory,
+        })
+    }
+
+    fn sys_munmap(table: &mut ProcessTable, pid: Pid, _: Tid, [address, length, ..]: [u64; 6]) -> Result<u64, Errno> {
+        table.get_mut(pid).unwrap().unmap(address, length).map_err(|_| Errno::Invalid)?;
+        Ok(0)
+    }
+
+    // All of the range has to be mapped
+    fn sys_mprotect(table: &mut ProcessTable, pid: Pid, _: Tid, [address, length, prot, ..]: [u64; 6]) -> Result<u64, Errno> {
+        let flags = page_flags(prot)?;
+        table.get_mut(pid).unwrap().protect(address, length, flags).map_err(|error| match error {
+            "Range not mapped" => Errno::NoMemory,
+            "Permission denied" => Errno::Access,
+            _ => Errno::Invalid,
+        })?;
+        Ok(0)
+    }
+
+    // Runs an executable as a new process with the caller's standard input,
+    // output and error, returning its PID
+    fn sys_spawn(table: &mut ProcessTable, pid: Pid, _: Tid, [path, argv, envp, ..]: [u64; 6]) -> Result<u64, Errno> {
+        let process = table.get(pid).unwrap();
+        let path = copy_string_from_user(process.space(), path, MAX_STRING)?;
+        let args = copy_strings_from_user(process.space(), argv)?;
+        let env = copy_strings_from_user(process.space(), envp)?;
+        let files = process.files().inherit();
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let env: Vec<&str> = env.iter().map(String::as_str).collect();
+        let child = table.spawn(pid, &path, &args, &env, files).map_err(|error| match error {
+            "Path not absolute" => Errno::Invalid,
+            "File not found" | "Library not found" => Errno::NoEntry,
+            "Permission denied" => Errno::Access,
+            "Failed to read file" => Errno::Io,
+            "Out of memory" | "Memory limit exceeded" => Errno::NoMemory,
+            "Arguments too long" => Errno::TooBig,
+            _ => Errno::NotExecutable,
+        })?;
+        Ok(child as u64)
+    }
+
+    fn sys_exit(table: &mut ProcessTable, pid: Pid, _: Tid, [status, ..]: [u64; 6]) -> Result<u64, Errno> {
+        table.exit(pid, ExitStatus::Exited(status as u8));
+        Ok(0)
+    }
+
+    // Reaps a child, or any with ANY_CHILD, returning its PID and writing
+    // its encoded status unless the pointer is null. Blocks until one
+    // exits, unless WNOHANG asks for 0 instead. WUNTRACED reports children
+    // stopping too.
+    fn sys_wait(table: &mut ProcessTable, pid: Pid, tid: Tid, [child, status, flags, ..]: [u64; 6]) -> Result<u64, Errno> {
+        if flags & !(WNOHANG | WUNTRACED) != 0 {
+            return Err(Errno::Invalid);
+        }
+        let untraced = flags & WUNTRACED != 0;
+        let child = match child {
+            ANY_CHILD => None,
+            child => Some(Pid::try_from(child).map_err(|_| Errno::NoChild)?),
+        };
+        if status != 0 {
+            check_user(table.get(pid).unwrap().space(), status, 4, true)?;
+        }
+        match table.wait_untraced(pid, child, untraced).map_err(|_| Errno::NoChild)? {
+            Some((child, exit)) => {
+                if status != 0 {
+                    copy_to_user(table.get(pid).unwrap().space(), status, &exit.encode().to_le_bytes())?;
+                }
+                Ok(child as u64)
+            }
+            None if flags & WNOHANG != 0 => Ok(0),
+            None => {
+                table.block(tid, Blocker::Wait { child, status, untraced });
+                Ok(0)
+            }
+        }
+    }
+
+    fn sys_getpid(_: &mut ProcessTable, pid: Pid, _: Tid, _: [u64; 6]) -> Result<u64, Errno> {
+        Ok(pid as u64)
+    }
+
+    fn sys_getppid(table: &mut ProcessTable, pid: Pid, _: Tid, _: [u64; 6]) -> Result<u64, Errno> {
+        Ok(table.parent(pid).unwrap_or(0) as u64)
+    }
+
+    // Starts a thread at a function taking one argument, on a stack the
+    // caller mapped, returning its thread ID. It gets its own thread-local
+    // storage, and clear_tid works as described on Thread.
+    fn sys_thread_create(table: &mut ProcessTable, pid: Pid, _: Tid, [entry, stack, argument, tls, clear_tid, _]: [u64; 6]) -> Result<u64, Errno> {
+        if tls >= USER_END || clear_tid % 4 != 0 {
+            return Err(Errno::Invalid);
+        }
+        let space = table.get(pid).unwrap().space();
+        let _ = space.populate(entry);
+        match space.translate(entry) {
+            Some((_, flags)) if entry < USER_END && flags & USER != 0 && flags & NO_EXECUTE == 0 => {}
+            _ => return Err(Errno::Fault),
+        }
+        if clear_tid != 0 {
+            check_user(space, clear_tid, 4, true)?;
+        }
+        // As if the function had been called, with a null return address
+        // so returning from it faults
+        let stack = (stack & !15).checked_sub(8).ok_or(Errno::Fault)?;
+        copy_to_user(space, stack, &0u64.to_le_bytes())?;
+        let mut context = UserContext::new(entry, stack);
+        context.registers[RDI] = argument;
+        context.fs_base = tls;
+        let tid = table.add_thread(pid, Thread { context, clear_tid }).map_err(|_| Errno::NoMemory)?;
+        Ok(tid as u64)
+    }
+
+    fn sys_thread_exit(table: &mut ProcessTable, _: Pid, tid: Tid, [status, ..]: [u64; 6]) -> Result<u64, Errno> {
+        table.exit_thread(tid, ExitStatus::Exited(status as u8));
+        Ok(0)
+    }
+
+    fn sys_gettid(_: &mut ProcessTable, _: Pid, tid: Tid, _: [u64; 6]) -> Result<u64, Errno> {
+        Ok(tid as u64)
+    }
+
+    // FUTEX_WAIT blocks the thread while the 32-bit word still holds the
+    // value, or fails with EAGAIN; FUTEX_WAKE wakes up to that many waiters
+    // and returns how many it did. Futexes are known by physical address,
+    // so they work across processes sharing memory. The table is locked
+    // throughout, so no wake can slip between the check and the wait.
+    // There are no timeouts yet.
+    fn sys_futex(table: &mut ProcessTable, pid: Pid, tid: Tid, [address, op, value, timeout, ..]: [u64; 6]) -> Result<u64, Errno> {
+        if address % 4 != 0 {
+            return Err(Errno::Invalid);
+        }
+        let space = table.get(pid).unwrap().space();
+        let word = u32::from_le_bytes(copy_from_user(space, address, 4)?.try_into().unwrap());
+        let (key, _) = space.translate(address).ok_or(Errno::Fault)?;
+        match op {
+            FUTEX_WAIT if timeout != 0 => Err(Errno::Invalid),
+            FUTEX_WAIT if word != value as u32 => Err(Errno::Again),
+            FUTEX_WAIT => {
+                table.block(tid, Blocker::Futex(key));
+                Ok(0)
+            }
+            FUTEX_WAKE => Ok(table.futex_wake(key, usize::try_from(value).unwrap_or(usize::MAX)) as u64),
+            _ => Err(Errno::NoSys),
+        }
+    }
+
+    // Where FS points for the calling thread
+    fn sys_set_tls(table: &mut ProcessTable, pid: Pid, tid: Tid, [address, ..]: [u64; 6]) -> Result<u64, Errno> {
+        if address >= USER_END {
+            return Err(Errno::Invalid);
+        }
+        table.get_mut(pid).unwrap().thread_mut(tid).unwrap().context.fs_base = address;
+        Ok(0)
+    }
+
+    fn access_errno(error: &'static str) -> Errno {
+        match error {
+            "File not found" => Errno::NoEntry,
+            "Permission denied" => Errno::Access,
+            "Path not absolute" => Errno::Invalid,
+            _ => Errno::Io,
+        }
+    }
+
+    fn sys_getuid(table: &mut ProcessTable, pid: Pid, _: Tid, _: [u64; 6]) -> Result<u64, Errno> {
+        Ok(table.get(pid).unwrap().credentials().uid as u64)
+    }
+
+    fn sys_getgid(table: &mut ProcessTable, pid: Pid, _: Tid, _: [u64; 6]) -> Result<u64, Errno> {
+        Ok(table.get(pid).unwrap().credentials().gid as u64)
+    }
+
+    // Root may become anyone, and after that cannot come back
+    fn sys_setuid(table: &mut ProcessTable, pid: Pid, _: Tid, [uid, ..]: [u64; 6]) -> Result<u64, Errno> {
+        let uid = Uid::try_from(uid).map_err(|_| Errno::Invalid)?;
+        let process = table.get_mut(pid).unwrap();
+        let mut credentials = process.credentials().clone();
+        if !credentials.is_root() && credentials.uid != uid {
+            return Err(Errno::NotPermitted);
+        }
+        credentials.uid = uid;
+        process.set_credentials(credentials);
+        Ok(0)
+    }
+
+    // To any group for root, otherwise only to a supplementary one
+    fn sys_setgid(table: &mut ProcessTable, pid: Pid, _: Tid, [gid, ..]: [u64; 6]) -> Result<u64, Errno> {
+        let gid = Gid::try_from(gid).map_err(|_| Errno::Invalid)?;
+        let process = table.get_mut(pid).unwrap();
+        let mut credentials = process.credentials().clone();
+        if !credentials.is_root() && !credentials.in_group(gid) {
+            return Err(Errno::NotPermitted);
+        }
+        credentials.gid = gid;
+        process.set_credentials(credentials);
+        Ok(0)
+    }
+
+    // The owner or root may change the permission bits
+    fn sys_chmod(table: &mut ProcessTable, pid: Pid, _: Tid, [path, mode, ..]: [u64; 6]) -> Result<u64, Errno> {
+        let process = table.get(pid).unwrap();
+        let path = copy_string_from_user(process.space(), path, MAX_STRING)?;
+        let credentials = process.credentials();
+        let host = table.access(credentials, &path, 0).map_err(access_errno)?;
+        let owner = fs::metadata(&host).map_err(Errno::from_io)?.uid();
+        if !credentials.is_root() && owner != credentials.uid {
+            return Err(Errno::NotPermitted);
+        }
+        fs::set_permissions(&host, Permissions::from_mode((mode & 0o7777) as u32)).map_err(Errno::from_io)?;
+        Ok(0)
+    }
+
+    // Root alone may give files away
+    fn sys_chown(table: &mut ProcessTable, pid: Pid, _: Tid, [path, uid, gid, ..]: [u64; 6]) -> Result<u64, Errno> {
+        let process = table.get(pid).unwrap();
+        let path = copy_string_from_user(process.space(), path, MAX_STRING)?;
+        let credentials = process.credentials();
+        let uid = Uid::try_from(uid).map_err(|_| Errno::Invalid)?;
+        let gid = Gid::try_from(gid).map_err(|_| Errno::Invalid)?;
+        let host = table.access(credentials, &path, 0).map_err(access_errno)?;
+        if !credentials.is_root() {
+            return Err(Errno::NotPermitted);
+        }
+        unix_fs::chown(&host, Some(uid), Some(gid)).map_err(Errno::from_io)?;
+        Ok(0)
+    }
+
+    // Both take a pointer to the soft limit followed by the hard one
+    fn sys_getrlimit(table: &mut ProcessTable, pid: Pid, _: Tid, [resource, limit, ..]: [u64; 6]) -> Result<u64, Errno> {
+        let resource = Resource::from_number(resource).ok_or(Errno::Invalid)?;
+        let process = table.get(pid).unwrap();
+        let Limit { soft, hard } = process.limits().get(resource);
+        let bytes: Vec<u8> = [soft, hard].iter().flat_map(|value| value.to_le_bytes()).collect();
+        copy_to_user(process.space(), limit, &bytes)?;
+        Ok(0)
+    }
+
+    // Only root may raise a hard limit
+    fn sys_setrlimit(table: &mut ProcessTable, pid: Pid, _: Tid, [resource, limit, ..]: [u64; 6]) -> Result<u64, Errno> {
+        let resource = Resource::from_number(resource).ok_or(Errno::Invalid)?;
+        let process = table.get_mut(pid).unwrap();
+        let bytes = copy_from_user(process.space(), limit, 16)?;
+        let value = |offset: usize| u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+        let mut limits = *process.limits();
+        limits.set(resource, Limit::new(value(0), value(8)), process.credentials().is_root()).map_err(|error| match error {
+            "Not permitted" => Errno::NotPermitted,
+            _ => Errno::Invalid,
+        })?;
+        process.set_limits(limits);
+        Ok(0)
+    }
+
+    fn linker_errno(error: &'static str) -> Errno {
+        match error {
+            "File not found" | "Library not found" | "Symbol not found" => Errno::NoEntry,
+            "Permission denied" => Errno::Access,
+            "Path not absolute" | "Bad handle" => Errno::Invalid,
+            "Failed to read file" => Errno::Io,
+            "Out of memory" | "Memory limit exceeded" | "Out of address space" => Errno::NoMemory,
+            _ => Errno::NotExecutable,
+        }
+    }
+
+    // The built-in linker's run-time loading: a library by path, or by name
+    // along its search path, returning a handle for dlsym and dlclose
+    fn sys_dlopen(table: &mut ProcessTable, pid: Pid, _: Tid, [name, ..]: [u64; 6]) -> Result<u64, Errno> {
+        let name = copy_string_from_user(table.get(pid).unwrap().space(), name, MAX_STRING)?;
+        linker::open(table, pid, &name).map_err(linker_errno)
+    }
+
+    fn sys_dlsym(table: &mut ProcessTable, pid: Pid, _: Tid, [handle, name, ..]: [u64; 6]) -> Result<u64, Errno> {
+        let name = copy_string_from_user(table.get(pid).unwrap().space(), name, MAX_STRING)?;
+        linker::symbol(table, pid, handle, &name).map_err(linker_errno)
+    }
+
+    fn sys_dlclose(table: &mut ProcessTable, pid: Pid, _: Tid, [handle, ..]: [u64; 6]) -> Result<u64, Errno> {
+        linker::close(table, pid, handle).map_err(linker_errno)?;
+        Ok(0)
+    }
+
+    // A process by PID, the caller's group with 0, or a group by its
+    // negated ID. Signal 0 only checks they could be sent one. Root may
+    // signal anyone, others only their own processes.
+    fn sys_kill(table: &mut ProcessTable, pid: Pid, _: Tid, [target, number, ..]: [u64; 6]) -> Result<u64, Errno> {
+        let number = u8::try_from(number).ok().filter(|number| *number == 0 || signal::is_valid(*number)).ok_or(Errno::Invalid)?;
+        let id = |value: i64| Pid::try_from(value).map_err(|_| Errno::NoProcess);
+        let targets = match target as i64 {
+            0 => table.group_members(table.get(pid).unwrap().group()),
+            target if target > 0 => table.get(id(target)?).map(|process| vec![process.pid()]).unwrap_or_default(),
+            -1 => return Err(Errno::Invalid),
+            target => table.group_members(id(-target)?),
+        };
+        if targets.is_empty() {
+            return Err(Errno::NoProcess);
+        }
+        let sender = table.get(pid).unwrap().credentials().clone();
+        let targets: Vec<Pid> = targets.into_iter().filter(|target| sender.is_root() || table.get(*target).unwrap().credentials().uid == sender.uid).collect();
+        if targets.is_empty() {
+            return Err(Errno::NotPermitted);
+        }
+        if number != 0 {
+            for target in targets {
+                // Earlier ones may have taken later ones with them
+                let _ = table.signal(target, number);
+            }
+        }
+        Ok(0)
+    }
+
+    // SIG_DFL or SIG_IGN, returning which it was before
+    fn sys_signal(table: &mut ProcessTable, pid: Pid, _: Tid, [number, action, ..]: [u64; 6]) -> Result<u64, Errno> {
+        let number = u8::try_from(number).map_err(|_| Errno::Invalid)?;
+        let ignored = match action {
+            SIG_DFL => false,
+            SIG_IGN => true,
+            _ => return Err(Errno::Invalid),
+        };
+        let dispositions = table.get_mut(pid).unwrap().dispositions_mut();
+        let was = dispositions.set_ignored(number, ignored).map_err(|_| Errno::Invalid)?;
+        Ok(if was { SIG_IGN } else { SIG_DFL })
+    }
+
+    // 0 for the caller
+    fn process_id(pid: Pid, value: u64) -> Result<Pid, Errno> {
+        match value {
+            0 => Ok(pid),
+            value => Pid::try_from(value).map_err(|_| Errno::NoProcess),
+        }
+    }
+
+    // For the caller or a child, with 0 for either meaning the caller;
+    // a group ID of 0 makes a new group led by the process
+    fn sys_setpgid(table: &mut ProcessTable, pid: Pid, _: Tid, [target, group, ..]: [u64; 6]) -> Result<u64, Errno> {
+        let target = process_id(pid, target)?;
+        let group = process_id(target, group).map_err(|_| Errno::Invalid)?;
+        table.set_group(pid, target, group).map_err(|error| match error {
+            "Not permitted" => Errno::NotPermitted,
+            _ => Errno::NoProcess,
+        })?;
+        Ok(0)
+    }
+
+    fn sys_getpgid(table: &mut ProcessTable, pid: Pid, _: Tid, [target, ..]: [u64; 6]) -> Result<u64, Errno> {
+        let process = table.get(process_id(pid, target)?).ok_or(Errno::NoProcess)?;
+        Ok(process.group() as u64)
+    }
+
+    fn sys_setsid(table: &mut ProcessTable, pid: Pid, _: Tid, _: [u64; 6]) -> Result<u64, Errno> {
+        Ok(table.new_session(pid).map_err(|_| Errno::NotPermitted)? as u64)
+    }
+
+    fn sys_getsid(table: &mut ProcessTable, pid: Pid, _: Tid, [target, ..]: [u64; 6]) -> Result<u64, Errno> {
+        let process = table.get(process_id(pid, target)?).ok_or(Errno::NoProcess)?;
+        Ok(process.session() as u64)
+    }
+
+    // Only for the caller's controlling terminal
+    fn sys_tcgetpgrp(table: &mut ProcessTable, pid: Pid, _: Tid, [fd, ..]: [u64; 6]) -> Result<u64, Errno> {
+        let slave = terminal(table, pid, fd)?;
+        if slave.session() != Some(table.get(pid).unwrap().session()) {
+            return Err(Errno::NotTerminal);
+        }
+        Ok(slave.foreground().ok_or(Errno::NotTerminal)? as u64)
+    }
+
+    // From the background, only once SIGTTOU is ignored
+    fn sys_tcsetpgrp(table: &mut ProcessTable, pid: Pid, _: Tid, [fd, group, ..]: [u64; 6]) -> Result<u64, Errno> {
+        let slave = terminal(table, pid, fd)?;
+        let group = Pid::try_from(group).map_err(|_| Errno::Invalid)?;
+        table.terminal_access(pid, &slave, SIGTTOU).map_err(terminal_errno)?;
+        table.set_foreground(pid, &slave, group).map_err(terminal_errno)?;
+        Ok(0)
+    }
+
+    // Makes a terminal the controlling terminal of the session the caller
+    // leads, as after setsid
+    fn sys_setctty(table: &mut ProcessTable, pid: Pid, _: Tid, [fd, ..]: [u64; 6]) -> Result<u64, Errno> {
+        let slave = terminal(table, pid, fd)?;
+        table.set_controlling_terminal(pid, &slave).map_err(|_| Errno::NotPermitted)?;
+        Ok(0)
+    }
+
+    fn channel_name(table: &ProcessTable, pid: Pid, name: u64) -> Result<String, Errno> {
+        let name = copy_string_from_user(table.get(pid).unwrap().space(), name, MAX_STRING)?;
+        if name.is_empty() {
+            return Err(Errno::Invalid);
+        }
+        Ok(name)
+    }
+
+    // Channels are descriptors: each write sends a message and each read
+    // takes one
+    fn sys_channel_create(table: &mut ProcessTable, pid: Pid, _: Tid, [name, ..]: [u64; 6]) -> Result<u64, Errno> {
+        let name = channel_name(table, pid, name)?;
+        table.channels().create_channel(&name).map_err(|_| Errno::Exists)?;
+        let files = table.get_mut(pid).unwrap().files_mut();
+        Ok(files.insert(OpenFile::Channel { name, pending: None }).map_err(|_| Errno::TooManyFiles)? as u64)
+    }
+
+    fn sys_channel_open(table: &mut ProcessTable, pid: Pid, _: Tid, [name, ..]: [u64; 6]) -> Result<u64, Errno> {
+        let name = channel_name(table, pid, name)?;
+        if !table.channels().has_channel(&name) {
+            return Err(Errno::NoEntry);
+        }
+        let files = table.get_mut(pid).unwrap().files_mut();
+        Ok(files.insert(OpenFile::Channel { name, pending: None }).map_err(|_| Errno::TooManyFiles)? as u64)
+    }
+
+    fn key_errno(error: &'static str) -> Errno {
+        match error {
+            "Key not found" => Errno::NoKey,
+            "Permission denied" => Errno::Access,
+            _ => Errno::Invalid,
+        }
+    }
+
+    // Adds a key to the kernel keyring, or updates the caller's own of the
+    // same kind and description, returning its ID. The payload never sits
+    // anywhere but in a Secret, so it is wiped when done with.
+    fn sys_add_key(table: &mut ProcessTable, pid: Pid, _: Tid, [kind, description, payload, length, mode, ..]: [u64; 6]) -> Result<u64, Errno> {
+        let kind = KeyKind::from_number(kind).ok_or(Errno::Invalid)?;
+        if length > MAX_KEY_SIZE as u64 {
+            return Err(Errno::Invalid);
+        }
+        let process = table.get(pid).unwrap();
+        let description = copy_string_from_user(process.space(), description, MAX_STRING)?;
+        let secret = Secret::new(copy_from_user(process.space(), payload, length as usize)?);
+        let credentials = process.credentials().clone();
+        let mode = u32::try_from(mode).map_err(|_| Errno::Invalid)?;
+        Ok(table.keyring_mut().add(&credentials, kind, &description, secret, mode).map_err(key_errno)? as u64)
+    }
+
+    fn sys_request_key(table: &mut ProcessTable, pid: Pid, _: Tid, [kind, description, ..]: [u64; 6]) -> Result<u64, Errno> {
+        let kind = KeyKind::from_number(kind).ok_or(Errno::Invalid)?;
+        let process = table.get(pid).unwrap();
+        let description = copy_string_from_user(process.space(), description, MAX_STRING)?;
+        Ok(table.keyring().find(process.credentials(), kind, &description).map_err(key_errno)? as u64)
+    }
+
+    // Returns the key's length, copying it out only when the buffer can
+    // take all of it
+    fn sys_read_key(table: &mut ProcessTable, pid: Pid, _: Tid, [id, buffer, length, ..]: [u64; 6]) -> Result<u64, Errno> {
+        let process = table.get(pid).unwrap();
+        let id = u32::try_from(id).map_err(|_| Errno::NoKey)?;
+        let secret = table.keyring().read(process.credentials(), id).map_err(key_errno)?;
+        if secret.len() as u64 <= length {
+            copy_to_user(process.space(), buffer, secret.expose())?;
+        }
+        Ok(secret.len() as u64)
+    }
+
+    fn sys_revoke_key(table: &mut ProcessTable, pid: Pid, _: Tid, [id, ..]: [u64; 6]) -> Result<u64, Errno> {
+        let credentials = table.get(pid).unwrap().credentials().clone();
+        let id = u32::try_from(id).map_err(|_| Errno::NoKey)?;
+        table.keyring_mut().revoke(&credentials, id).map_err(key_errno)?;
+        Ok(0)
+    }
+}
@@ -0,0 +1,843 @@
+// src/kernel/process/table.rs
+
+pub mod table {
+    use crate::crashdump::crashdump;
+    use crate::faultinject::faultinject::FAIL_IRQ_DELAY;
+    use crate::hardening::hardening::{self, KernelStack};
+    use crate::keyring::keyring::Keyring;
+    use crate::irq::irq;
+    use crate::latency::latency;
+    use crate::percpu::percpu::{self, STATS};
+    use crate::rcu::rcu;
+    use crate::process::elf::elf::Executable;
+    use crate::process::files::files::{FileTable, OpenFile};
+    use crate::process::limits::limits::{Limits, Resource, RLIM_INFINITY};
+    use crate::process::linker::linker::{self, INTERPRETER};
+    use crate::process::mapping::mapping::PageCache;
+    use crate::process::pipe::pipe::Fifos;
+    use crate::process::memory::memory::{AddressSpace, PhysicalMemory, NO_EXECUTE, USER, WRITABLE};
+    use crate::process::signal::signal::{self, Action, SIGCONT, SIGHUP, SIGSEGV, SIGSTOP, SIGTTOU, SIGXCPU};
+    use crate::process::syscall::syscall::{self, Errno};
+    use crate::process::task::task::{Layout, Process, Thread, Trap, UserCpu, FAULT_FETCH, FAULT_PRESENT, FAULT_USER, FAULT_WRITE};
+    use crate::process::uaccess::uaccess;
+    use crate::pty::pty::PtySlave;
+    use crate::users::users::{Credentials, ACCESS_EXECUTE};
+    use crate::vxchan::vxchan::VXChanManager;
+    use crate::vxfs::vxfs::VXFS;
+    use std::collections::{BTreeMap, BTreeSet, VecDeque};
+    use std::fs;
+    use std::io::ErrorKind;
+    use std::path::{Component, Path, PathBuf};
+    use std::time::{Duration, Instant};
+
+    pub type Pid = u32;
+    // Thread IDs share the numbering of PIDs
+    pub type Tid = u32;
+
+    // The parent of processes the kernel started itself
+    pub const KERNEL_PID: Pid = 0;
+    // Adopts orphans, and is the first process spawned
+    pub const INIT_PID: Pid = 1;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ExitStatus {
+        // The low byte of what the process passed to exit
+        Exited(u8),
+        Killed(u8),
+        // Not an exit: what wait reports for a child a signal has stopped,
+        // when asked to
+        Stopped(u8),
+    }
+
+    impl ExitStatus {
+        // As wait writes it: the code in the second byte, or the signal in
+        // the first, or for a stop the signal in the second and 0x7F in
+        // the first
+        pub fn encode(self) -> u32 {
+            match self {
+                ExitStatus::Exited(code) => (code as u32) << 8,
+                ExitStatus::Killed(signal) => signal as u32,
+                ExitStatus::Stopped(signal) => (signal as u32) << 8 | 0x7F,
+            }
+        }
+    }
+
+    // Why a thread cannot run
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Blocker {
+        // Until a child exits, or stops too when untraced, any of them when
+        // None, then writes its status where asked unless that is null
+        Wait { child: Option<Pid>, status: u64, untraced: bool },
+        // Until woken on the futex at a physical address
+        Futex(u64),
+        // These make the call again when something changes, and may block
+        // again: reading or writing a pipe, by its ID, opening a FIFO for
+        // writing before it has a reader, and polling
+        Pipe(u64),
+        FifoOpen,
+        Poll,
+    }
+
+    // Every user process, by PID, and what they share: the kernel half of
+    // the address space, the channels, the filesystem and the page cache
+    // of files mapped shared. Exited processes stay as zombies with their
+    // status until their parent waits for them.
+    //
+    // Processes are in process groups, which are in sessions; each session
+    // can have a controlling terminal, whose foreground group gets the
+    // signals typed at it and may read from it. Groups in the background
+    // are stopped when they try.
+    pub struct ProcessTable {
+        kernel: AddressSpace,
+        // By CPU
+        irq_stacks: Vec<KernelStack>,
+        channels: VXChanManager,
+        // Host directory standing in for the root filesystem
+        root: PathBuf,
+        page_cache: PageCache,
+        fifos: Fifos,
+        keyring: Keyring,
+        processes: BTreeMap<Pid, Process>,
+        // The process each live thread belongs to
+        threads: BTreeMap<Tid, Pid>,
+        // Of live processes and zombies
+        parents: BTreeMap<Pid, Pid>,
+        zombies: BTreeMap<Pid, ExitStatus>,
+        blocked: BTreeMap<Tid, Blocker>,
+        // Threads waiting on each futex, first come first woken
+        futexes: BTreeMap<u64, VecDeque<Tid>>,
+        // Processes stopped by a signal, and the signal for those whose
+        // parent has not been told yet
+        stopped: BTreeSet<Pid>,
+        stops: BTreeMap<Pid, u8>,
+        // Controlling terminals by session
+        terminals: BTreeMap<Pid, PtySlave>,
+        // Whether processes get randomized layouts, as they do unless
+        // turned off, say to debug something that depends on addresses
+        randomize_layout: bool,
+        // What CPU set-up turned on in CR4, which decides what a fault the
+        // kernel takes on a user page means
+        cr4: u64,
+        next_pid: Pid,
+    }
+
+    impl ProcessTable {
+        pub fn new(memory: &PhysicalMemory, root: &str, channels: &VXChanManager) -> Result<Self, &'static str> {
+            Ok(ProcessTable {
+                kernel: AddressSpace::kernel(memory)?,
+                irq_stacks: Vec::new(),
+                channels: channels.clone(),
+                root: PathBuf::from(root),
+                page_cache: PageCache::new(),
+                fifos: Fifos::new(),
+                keyring: Keyring::new(),
+                processes: BTreeMap::new(),
+                threads: BTreeMap::new(),
+                parents: BTreeMap::new(),
+                zombies: BTreeMap::new(),
+                blocked: BTreeMap::new(),
+                futexes: BTreeMap::new(),
+                stopped: BTreeSet::new(),
+                stops: BTreeMap::new(),
+                terminals: BTreeMap::new(),
+                randomize_layout: true,
+                cr4: 0,
+                next_pid: INIT_PID,
+            })
+        }
+
+        pub fn channels(&self) -> &VXChanManager {
+            &self.channels
+        }
+
+        pub fn page_cache(&self) -> &PageCache {
+            &self.page_cache
+        }
+
+        pub fn fifos(&self) -> &Fifos {
+            &self.fifos
+        }
+
+        // One interrupt stack per CPU. Like any kernel mapping, before the
+        // first process is spawned, so that every address space has them.
+        pub fn map_irq_stacks(&mut self, cpus: usize) -> Result<(), &'static str> {
+            if !self.processes.is_empty() || !self.irq_stacks.is_empty() {
+                return Err("Too late to map interrupt stacks");
+            }
+            self.irq_stacks = hardening::map_irq_stacks(&mut self.kernel, cpus)?;
+            Ok(())
+        }
+
+        pub fn irq_stack(&self, cpu: usize) -> Option<&KernelStack> {
+            self.irq_stacks.get(cpu)
+        }
+
+        pub fn check_irq_stacks(&self) -> Result<(), &'static str> {
+            self.irq_stacks.iter().try_for_each(|stack| stack.check(&self.kernel))
+        }
+
+        pub fn keyring(&self) -> &Keyring {
+            &self.keyring
+        }
+
+        pub fn keyring_mut(&mut self) -> &mut Keyring {
+            &mut self.keyring
+        }
+
+        pub fn randomize_layout(&self) -> bool {
+            self.randomize_layout
+        }
+
+        // Only for processes spawned from then on
+        pub fn set_randomize_layout(&mut self, randomize: bool) {
+            self.randomize_layout = randomize;
+        }
+
+        // As uaccess::enable leaves it, the same on every CPU
+        pub fn set_cr4(&mut self, cr4: u64) {
+            self.cr4 = cr4;
+        }
+
+        // Where an absolute path is on the host. ".." stops at the root.
+        pub fn resolve(&self, path: &str) -> Result<PathBuf, &'static str> {
+            if !path.starts_with('/') {
+                return Err("Path not absolute");
+            }
+            let mut resolved = self.root.clone();
+            for component in Path::new(path).components() {
+                match component {
+                    Component::Normal(name) => resolved.push(name),
+                    Component::ParentDir if resolved != self.root => {
+                        resolved.pop();
+                    }
+                    _ => {}
+                }
+            }
+            Ok(resolved)
+        }
+
+        // Resolves a path as someone with the credentials: every directory
+        // on the way must be searchable, and the file must allow the
+        // access asked for, a mask of the ACCESS_ bits
+        pub fn access(&self, credentials: &Credentials, path: &str, access: u32) -> Result<PathBuf, &'static str> {
+            let host = self.resolve(path)?;
+            let mut directories: Vec<&Path> = host.ancestors().skip(1).take_while(|directory| directory.starts_with(&self.root)).collect();
+            directories.reverse();
+            for directory in directories {
+                match fs::metadata(directory) {
+                    Ok(metadata) if metadata.is_dir() => {
+                        if !credentials.may_access(&metadata, ACCESS_EXECUTE) {
+                            return Err("Permission denied");
+                        }
+                    }
+                    _ => return Err("File not found"),
+                }
+            }
+            let metadata = fs::metadata(&host).map_err(|error| match error.kind() {
+                ErrorKind::NotFound => "File not found",
+                _ => "Failed to read file",
+            })?;
+            if !credentials.may_access(&metadata, access) {
+                return Err("Permission denied");
+            }
+            Ok(host)
+        }
+
+        fn read_file(&self, credentials: &Credentials, path: &str, access: u32) -> Result<Vec<u8>, &'static str> {
+            let host = self.access(credentials, path, access)?;
+            // Such as a FIFO, which would never be read to its end
+            if !host.is_file() {
+                return Err("Permission denied");
+            }
+            VXFS::new().read_bytes(&host.to_string_lossy()).map_err(|error| match error.kind() {
+                ErrorKind::NotFound => "File not found",
+                _ => "Failed to read file",
+            })
+        }
+
+        // Loads an executable from the filesystem as a new child of a
+        // process, or of the kernel, running with its parent's credentials,
+        // limits and system call filters in its parent's group. Dynamic
+        // programs get the interpreter they ask for, or are linked straight
+        // away when that is the built-in one. What the kernel starts leads
+        // a session of its own, controlled by the first terminal it is
+        // given that no other session has.
+        pub fn spawn(&mut self, parent: Pid, path: &str, args: &[&str], env: &[&str], files: FileTable) -> Result<Pid, &'static str> {
+            let pid = self.next_pid;
+            let (credentials, limits, filters, group, session) = match parent {
+                KERNEL_PID => (Credentials::root(), Limits::new(), Vec::new(), pid, pid),
+                _ => {
+                    let parent = self.processes.get(&parent).ok_or("No such process")?;
+                    (parent.credentials().clone(), *parent.limits(), parent.filters().to_vec(), parent.group(), parent.session())
+                }
+            };
+            let data = self.read_file(&credentials, path, ACCESS_EXECUTE)?;
+            let interpreter = match Executable::parse(&data)?.interpreter {
+                Some(interpreter) if interpreter != INTERPRETER => Some(self.read_file(&credentials, &interpreter, ACCESS_EXECUTE)?),
+                _ => None,
+            };
+            let name = path.rsplit('/').next().unwrap_or(path);
+            let layout = match self.randomize_layout {
+                true => Layout::randomized()?,
+                false => Layout::fixed(),
+            };
+            let mut process = Process::load_with_interpreter(pid, name, &self.kernel, &data, interpreter.as_deref(), layout, args, env)?;
+            process.set_credentials(credentials);
+            process.set_limits(limits);
+            process.set_files(files);
+            process.set_group(group);
+            process.set_session(session);
+            for filter in filters {
+                process.add_filter(filter);
+            }
+            if limits.get(Resource::Memory).exceeded_by(process.memory()) {
+                return Err("Memory limit exceeded");
+            }
+            let dynamic = !process.link_map().objects().is_empty();
+            self.processes.insert(pid, process);
+            if dynamic {
+                if let Err(error) = linker::link(self, pid) {
+                    self.processes.remove(&pid);
+                    return Err(error);
+                }
+            }
+            self.next_pid = hardening::add(self.next_pid, 1, "PID counter");
+            self.threads.insert(pid, pid);
+            self.parents.insert(pid, parent);
+            if session == pid {
+                let files = self.processes[&pid].files();
+                let terminal = (0..3).find_map(|fd| match files.get(fd) {
+                    Some(OpenFile::Terminal { slave, .. }) if slave.session().is_none() => Some(slave.clone()),
+                    _ => None,
+                });
+                if let Some(slave) = terminal {
+                    self.set_controlling_terminal(pid, &slave)?;
+                }
+            }
+            log::info!("Spawned {} as process {}", path, pid);
+            Ok(pid)
+        }
+
+        pub fn get(&self, pid: Pid) -> Option<&Process> {
+            self.processes.get(&pid)
+        }
+
+        pub fn get_mut(&mut self, pid: Pid) -> Option<&mut Process> {
+            self.processes.get_mut(&pid)
+        }
+
+        // The frames every address space is allocated from
+        pub fn physical_memory(&self) -> &PhysicalMemory {
+            self.kernel.memory()
+        }
+
+        // Live processes
+        pub fn pids(&self) -> Vec<Pid> {
+            self.processes.keys().copied().collect()
+        }
+
+        // The process a live thread belongs to
+        pub fn owner(&self, tid: Tid) -> Option<Pid> {
+            self.threads.get(&tid).copied()
+        }
+
+        // Another thread in a process, with its own registers
+        pub fn add_thread(&mut self, pid: Pid, thread: Thread) -> Result<Tid, &'static str> {
+            let process = self.processes.get_mut(&pid).ok_or("No such process")?;
+            let tid = self.next_pid;
+            self.next_pid = hardening::add(self.next_pid, 1, "PID counter");
+            process.add_thread(tid, thread);
+            self.threads.insert(tid, pid);
+            Ok(tid)
+        }
+
+        fn forget_thread(&mut self, tid: Tid) {
+            self.threads.remove(&tid);
+            if let Some(Blocker::Futex(key)) = self.blocked.remove(&tid) {
+                self.dequeue(key, tid);
+            }
+        }
+
+        fn dequeue(&mut self, key: u64, tid: Tid) {
+            if let Some(queue) = self.futexes.get_mut(&key) {
+                queue.retain(|waiter| *waiter != tid);
+                if queue.is_empty() {
+                    self.futexes.remove(&key);
+                }
+            }
+        }
+
+        // Ends one thread. Its clear_tid word is zeroed and one waiter on
+        // it woken; the last thread out takes the process with it.
+        pub fn exit_thread(&mut self, tid: Tid, status: ExitStatus) {
+            let Some(pid) = self.owner(tid) else {
+                return;
+            };
+            let process = self.processes.get_mut(&pid).unwrap();
+            let thread = process.remove_thread(tid).unwrap();
+            let last = process.tids().is_empty();
+            self.forget_thread(tid);
+            if last {
+                self.exit(pid, status);
+                return;
+            }
+            let space = self.processes[&pid].space();
+            if thread.clear_tid == 0 || syscall::copy_to_user(space, thread.clear_tid, &0u32.to_le_bytes()).is_err() {
+                return;
+            }
+            if let Some((key, _)) = space.translate(thread.clear_tid) {
+                self.futex_wake(key, 1);
+            }
+        }
+
+        pub fn parent(&self, pid: Pid) -> Option<Pid> {
+            self.parents.get(&pid).copied()
+        }
+
+        // Live or not yet waited for
+        pub fn children(&self, pid: Pid) -> Vec<Pid> {
+            self.parents.iter().filter(|(_, parent)| **parent == pid).map(|(child, _)| *child).collect()
+        }
+
+        pub fn is_zombie(&self, pid: Pid) -> bool {
+            self.zombies.contains_key(&pid)
+        }
+
+        // Frees everything the process had, keeping its status for its
+        // parent. Its children go to init, or to the kernel when init is
+        // the one exiting. A session leader takes its terminal's
+        // foreground job with it, and groups this leaves orphaned with
+        // stopped processes in them are hung up, as nothing in their
+        // session could continue them.
+        pub fn exit(&mut self, pid: Pid, status: ExitStatus) {
+            let Some(process) = self.processes.remove(&pid) else {
+                return;
+            };
+            log::info!("Process {} exited: {:?}", pid, status);
+            let tids: Vec<Tid> = self.threads.iter().filter(|(_, owner)| **owner == pid).map(|(tid, _)| *tid).collect();
+            for tid in tids {
+                self.forget_thread(tid);
+            }
+            self.stopped.remove(&pid);
+            self.stops.remove(&pid);
+            if let Some(slave) = self.terminals.remove(&pid) {
+                self.hang_up(slave, None);
+            }
+            let adopter = if pid != INIT_PID && self.processes.contains_key(&INIT_PID) { INIT_PID } else { KERNEL_PID };
+            let children = self.children(pid);
+            for child in &children {
+                self.parents.insert(*child, adopter);
+            }
+            self.zombies.insert(pid, status);
+            let pipes: BTreeSet<u64> = process.files().iter().filter_map(|(_, file)| file.pipe_id()).collect();
+            let mut groups: Vec<Pid> = children.iter().filter_map(|child| self.processes.get(child)).map(Process::group).collect();
+            groups.push(process.group());
+            groups.sort_unstable();
+            groups.dedup();
+            drop(process);
+            for group in groups {
+                if self.is_orphaned(group) && self.group_members(group).iter().any(|member| self.stopped.contains(member)) {
+                    let _ = self.signal_group(group, SIGHUP);
+                    let _ = self.signal_group(group, SIGCONT);
+                }
+            }
+            self.wake_waiters();
+            for id in pipes {
+                self.wake_pipe(id);
+            }
+        }
+
+        // Reaps an exited child, any when None. Ok(None) means none has
+        // exited yet.
+        pub fn wait(&mut self, parent: Pid, child: Option<Pid>) -> Result<Option<(Pid, ExitStatus)>, &'static str> {
+            self.wait_untraced(parent, child, false)
+        }
+
+        // The same, also reporting each stop of a child once when untraced
+        pub fn wait_untraced(&mut self, parent: Pid, child: Option<Pid>, untraced: bool) -> Result<Option<(Pid, ExitStatus)>, &'static str> {
+            let children = self.children(parent);
+            let candidates: Vec<Pid> = children.into_iter().filter(|pid| child.is_none_or(|child| *pid == child)).collect();
+            if candidates.is_empty() {
+                return Err("No child processes");
+            }
+            for pid in candidates {
+                if let Some(status) = self.zombies.remove(&pid) {
+                    self.parents.remove(&pid);
+                    return Ok(Some((pid, status)));
+                }
+                if untraced {
+                    if let Some(signal) = self.stops.remove(&pid) {
+                        return Ok(Some((pid, ExitStatus::Stopped(signal))));
+                    }
+                }
+            }
+            Ok(None)
+        }
+
+        pub fn is_stopped(&self, pid: Pid) -> bool {
+            self.stopped.contains(&pid)
+        }
+
+        // Live processes in a group
+        pub fn group_members(&self, group: Pid) -> Vec<Pid> {
+            self.processes.values().filter(|process| process.group() == group).map(Process::pid).collect()
+        }
+
+        // When no member has a parent in the same session but another
+        // group, which could stop and continue it as a job
+        pub fn is_orphaned(&self, group: Pid) -> bool {
+            self.processes.values().filter(|process| process.group() == group).all(|member| {
+                match self.parent(member.pid()).and_then(|parent| self.processes.get(&parent)) {
+                    Some(parent) => parent.group() == group || parent.session() != member.session(),
+                    None => true,
+                }
+            })
+        }
+
+        // Acts on a signal straight away, as there are no handlers in user
+        // code: SIGCONT continues a stopped process even when ignored.
+        // Stop signals other than SIGSTOP do nothing to orphaned groups.
+        pub fn signal(&mut self, pid: Pid, signal: u8) -> Result<(), &'static str> {
+            if !signal::is_valid(signal) {
+                return Err("Invalid signal");
+            }
+            let process = self.processes.get(&pid).ok_or("No such process")?;
+            let (ignored, group) = (process.dispositions().is_ignored(signal), process.group());
+            if signal == SIGCONT && self.stopped.remove(&pid) {
+                self.stops.remove(&pid);
+                log::info!("Process {} continued", pid);
+            }
+            if ignored {
+                return Ok(());
+            }
+            match signal::default_action(signal) {
+                Action::Terminate => self.exit(pid, ExitStatus::Killed(signal)),
+                Action::Stop if signal != SIGSTOP && self.is_orphaned(group) => {}
+                Action::Stop => {
+                    if self.stopped.insert(pid) {
+                        log::info!("Process {} stopped by signal {}", pid, signal);
+                        self.stops.insert(pid, signal);
+                        self.wake_waiters();
+                    }
+                }
+                Action::Continue | Action::Ignore => {}
+            }
+            Ok(())
+        }
+
+        // Every member, which there has to be one of
+        pub fn signal_group(&mut self, group: Pid, signal: u8) -> Result<(), &'static str> {
+            let members = self.group_members(group);
+            if members.is_empty() {
+                return Err("No such process");
+            }
+            for pid in members {
+                self.signal(pid, signal)?;
+            }
+            Ok(())
+        }
+
+        // Moves the caller or one of its children into another group in
+        // the caller's session, a new one when it is the process's own
+        // PID. Session leaders stay where they are.
+        pub fn set_group(&mut self, caller: Pid, pid: Pid, group: Pid) -> Result<(), &'static str> {
+            let session = self.processes.get(&caller).ok_or("No such process")?.session();
+            if pid != caller && self.parent(pid) != Some(caller) {
+                return Err("No such process");
+            }
+            let process = self.processes.get(&pid).ok_or("No such process")?;
+            if process.session() != session || session == pid {
+                return Err("Not permitted");
+            }
+            if group != pid && !self.processes.values().any(|other| other.group() == group && other.session() == session) {
+                return Err("Not permitted");
+            }
+            self.processes.get_mut(&pid).unwrap().set_group(group);
+            Ok(())
+        }
+
+        // Starts a session, without a terminal, led by a process that does
+        // not lead a group already
+        pub fn new_session(&mut self, pid: Pid) -> Result<Pid, &'static str> {
+            if self.processes.values().any(|process| process.group() == pid) {
+                return Err("Not permitted");
+            }
+            let process = self.processes.get_mut(&pid).ok_or("No such process")?;
+            process.set_group(pid);
+            process.set_session(pid);
+            log::info!("Process {} started a session", pid);
+            Ok(pid)
+        }
+
+        // For a session leader without one, a terminal no other session has
+        pub fn set_controlling_terminal(&mut self, pid: Pid, slave: &PtySlave) -> Result<(), &'static str> {
+            let session = self.processes.get(&pid).ok_or("No such process")?.session();
+            if session != pid || self.terminals.contains_key(&pid) || slave.session().is_some() {
+                return Err("Not permitted");
+            }
+            slave.set_session(Some(pid));
+            self.terminals.insert(pid, slave.clone());
+            Ok(())
+        }
+
+        // Of the process's session
+        pub fn controlling_terminal(&self, pid: Pid) -> Option<&PtySlave> {
+            self.terminals.get(&self.processes.get(&pid)?.session())
+        }
+
+        // Puts a group of the caller's session in the foreground of its
+        // controlling terminal
+        pub fn set_foreground(&mut self, pid: Pid, slave: &PtySlave, group: Pid) -> Result<(), &'static str> {
+            let session = self.processes.get(&pid).ok_or("No such process")?.session();
+            if slave.session() != Some(session) {
+                return Err("Not a controlling terminal");
+            }
+            if !self.processes.values().any(|process| process.group() == group && process.session() == session) {
+                return Err("Not permitted");
+            }
+            slave.set_foreground(group);
+            Ok(())
+        }
+
+        // Whether a process may go on with reading its controlling terminal,
+        // for SIGTTIN, or changing it, for SIGTTOU. From the background its
+        // group is sent the signal and it should try again once continued.
+        // Reads fail instead when it is ignored or nothing could continue
+        // the group; changes go ahead when it is ignored.
+        pub fn terminal_access(&mut self, pid: Pid, slave: &PtySlave, signal: u8) -> Result<(), &'static str> {
+            let process = self.processes.get(&pid).ok_or("No such process")?;
+            let group = process.group();
+            if slave.session() != Some(process.session()) || slave.foreground() == Some(group) {
+                return Ok(());
+            }
+            let ignored = process.dispositions().is_ignored(signal);
+            if signal == SIGTTOU && ignored {
+                return Ok(());
+            }
+            if ignored || self.is_orphaned(group) {
+                return Err("Background job");
+            }
+            self.signal_group(group, signal)?;
+            Err("Interrupted")
+        }
+
+        // Hangs up on the foreground job, and the session leader when it is
+        // still there, which lose the terminal
+        fn hang_up(&mut self, slave: PtySlave, leader: Option<Pid>) {
+            let foreground = slave.foreground();
+            log::info!("Hanging up {}", slave.name());
+            slave.set_session(None);
+            if let Some(group) = foreground {
+                let _ = self.signal_group(group, SIGHUP);
+                let _ = self.signal_group(group, SIGCONT);
+            }
+            if let Some(pid) = leader {
+                let _ = self.signal(pid, SIGHUP);
+                let _ = self.signal(pid, SIGCONT);
+            }
+        }
+
+        // Sends what the line discipline raised on each controlling
+        // terminal to its foreground job, and hangs up those whose
+        // terminal side has gone
+        pub fn poll_terminals(&mut self) {
+            let terminals: Vec<(Pid, PtySlave)> = self.terminals.iter().map(|(session, slave)| (*session, slave.clone())).collect();
+            for (session, slave) in terminals {
+                if slave.is_hung_up() {
+                    self.terminals.remove(&session);
+                    self.hang_up(slave, Some(session));
+                    continue;
+                }
+                for raised in slave.take_signals() {
+                    if let Some(group) = slave.foreground() {
+                        let _ = self.signal_group(group, signal::from_terminal(raised));
+                    }
+                }
+            }
+            self.retry(|blocker| blocker == Blocker::Poll);
+        }
+
+        pub fn block(&mut self, tid: Tid, blocker: Blocker) {
+            if !self.threads.contains_key(&tid) {
+                return;
+            }
+            if let Blocker::Futex(key) = blocker {
+                self.futexes.entry(key).or_default().push_back(tid);
+            }
+            self.blocked.insert(tid, blocker);
+        }
+
+        pub fn blocker(&self, tid: Tid) -> Option<Blocker> {
+            self.blocked.get(&tid).copied()
+        }
+
+        // Live threads that are neither blocked nor stopped
+        pub fn runnable(&self) -> Vec<Tid> {
+            self.threads.iter().filter(|(tid, pid)| !self.blocked.contains_key(tid) && !self.stopped.contains(pid)).map(|(tid, _)| *tid).collect()
+        }
+
+        // Returns a blocked thread from its system call
+        fn unblock(&mut self, tid: Tid, result: Result<u64, Errno>) {
+            self.blocked.remove(&tid);
+            latency::tracer().wakeup(tid, Instant::now());
+            let process = self.processes.get_mut(&self.threads[&tid]).unwrap();
+            syscall::finish(&mut process.thread_mut(tid).unwrap().context, result);
+        }
+
+        // Makes the calls of the threads blocked as matched again, which
+        // finish them unless they block again
+        fn retry(&mut self, matches: impl Fn(Blocker) -> bool) {
+            let tids: Vec<Tid> = self.blocked.iter().filter(|(_, blocker)| matches(**blocker)).map(|(tid, _)| *tid).collect();
+            for tid in tids {
+                // One retried before may have finished it or taken it with it
+                if !self.blocked.get(&tid).is_some_and(|blocker| matches(*blocker)) {
+                    continue;
+                }
+                self.blocked.remove(&tid);
+                latency::tracer().wakeup(tid, Instant::now());
+                let _ = syscall::dispatch(self, self.threads[&tid], tid);
+            }
+        }
+
+        // After a pipe was read or written or one of its ends closed: the
+        // threads reading or writing it, or polling anything
+        pub fn wake_pipe(&mut self, id: u64) {
+            self.retry(|blocker| blocker == Blocker::Pipe(id) || blocker == Blocker::Poll);
+        }
+
+        // After a FIFO was opened for reading
+        pub fn wake_fifo_openers(&mut self) {
+            self.retry(|blocker| blocker == Blocker::FifoOpen);
+        }
+
+        // Wakes up to count threads waiting on a futex, by physical
+        // address, returning how many were
+        pub fn futex_wake(&mut self, key: u64, count: usize) -> usize {
+            let Some(queue) = self.futexes.get_mut(&key) else {
+                return 0;
+            };
+            let woken: Vec<Tid> = queue.drain(..count.min(queue.len())).collect();
+            if queue.is_empty() {
+                self.futexes.remove(&key);
+            }
+            for tid in &woken {
+                self.unblock(*tid, Ok(0));
+            }
+            woken.len()
+        }
+
+        // Finishes the waits that can be, returning from their system calls
+        fn wake_waiters(&mut self) {
+            let waiting: Vec<(Tid, Option<Pid>, u64, bool)> = self
+                .blocked
+                .iter()
+                .filter_map(|(tid, blocker)| match blocker {
+                    Blocker::Wait { child, status, untraced } => Some((*tid, *child, *status, *untraced)),
+                    _ => None,
+                })
+                .collect();
+            for (tid, child, status, untraced) in waiting {
+                let Some(&pid) = self.threads.get(&tid) else {
+                    continue;
+                };
+                let result = match self.wait_untraced(pid, child, untraced) {
+                    Ok(None) => continue,
+                    Ok(Some((child, exit))) => {
+                        let space = self.processes[&pid].space();
+                        match status {
+                            0 => Ok(child as u64),
+                            _ => syscall::copy_to_user(space, status, &exit.encode().to_le_bytes()).map(|_| child as u64),
+                        }
+                    }
+                    Err(_) => Err(Errno::NoChild),
+                };
+                self.unblock(tid, result);
+            }
+        }
+
+        // Fills in the page a process faulted on, if a mapping allows the
+        // access. A fault on a page already there is always a violation,
+        // as is one the kernel takes on a user page that SMEP or SMAP
+        // forbid it.
+        pub fn fault(&self, pid: Pid, address: u64, error: u64) -> Result<(), &'static str> {
+            let space = self.processes.get(&pid).ok_or("No such process")?.space();
+            let flags = space.mapping_at(address).ok_or("Page not mapped")?.flags;
+            if error & FAULT_USER == 0 && flags & USER != 0 {
+                uaccess::check_kernel_fault(self.cr4, error)?;
+            }
+            if error & FAULT_PRESENT != 0
+                || error & FAULT_USER != 0 && flags & USER == 0
+                || error & FAULT_WRITE != 0 && flags & WRITABLE == 0
+                || error & FAULT_FETCH != 0 && flags & NO_EXECUTE != 0
+            {
+                return Err("Access not allowed");
+            }
+            space.populate(address)
+        }
+
+        // Runs a thread until it traps, handling system calls and page
+        // faults; a process faulting where it may not is killed. Anything
+        // else is left to the caller. A process's main thread has its PID.
+        // Time spent either way is charged to the process, which is killed
+        // instead of run once it has had its CPU time limit. Signals typed
+        // at terminals are acted on first.
+        pub fn run(&mut self, tid: Tid, cpu: &mut dyn UserCpu) -> Result<Trap, &'static str> {
+            self.poll_terminals();
+            if self.blocked.contains_key(&tid) {
+                return Err("Thread is blocked");
+            }
+            let pid = self.owner(tid).ok_or("No such thread")?;
+            if self.stopped.contains(&pid) {
+                return Err("Process is stopped");
+            }
+            let process = self.processes.get_mut(&pid).unwrap();
+            let limit = process.limits().get(Resource::CpuTime);
+            if limit.soft != RLIM_INFINITY && process.usage().cpu_time() >= Duration::from_secs(limit.soft) {
+                log::warn!("Process {} is over its CPU time limit", pid);
+                self.exit(pid, ExitStatus::Killed(SIGXCPU));
+                return Err("CPU time limit exceeded");
+            }
+            let start = Instant::now();
+            latency::tracer().run(tid, start);
+            percpu::switch_to(tid);
+            rcu::note_context_switch();
+            let trap = process.run(tid, cpu)?;
+            process.usage_mut().user_time += start.elapsed();
+            let _frame = process.thread(tid).map(|thread| crashdump::enter_trap(tid, thread.context));
+            if let Trap::PageFault { address, error } = trap {
+                if self.fault(pid, address, error).is_err() {
+                    log::warn!("Process {} faulted at {:#x}", pid, address);
+                    crashdump::trace(&format!("process {} faulted at {:#x}", pid, address));
+                    self.exit(pid, ExitStatus::Killed(SIGSEGV));
+                    return Err("Segmentation fault");
+                }
+            }
+            if let Trap::Interrupt(vector) = trap {
+                crate::this_cpu!(STATS).interrupts.inc();
+                latency::tracer().irq(vector, || {
+                    FAIL_IRQ_DELAY.delay();
+                    irq::controller().handle(vector, Instant::now())
+                });
+                // The threads it woke outrank whatever it interrupted
+                irq::controller().run_threads(percpu::this_cpu());
+            }
+            if trap == Trap::Syscall {
+                crate::this_cpu!(STATS).syscalls.inc();
+                let start = Instant::now();
+                syscall::dispatch(self, pid, tid)?;
+                if let Some(process) = self.processes.get_mut(&pid) {
+                    let usage = process.usage_mut();
+                    usage.system_time += start.elapsed();
+                    usage.syscalls = hardening::add(usage.syscalls, 1, "System call count");
+                }
+            }
+            // Interrupts taken while it ran went on these
+            if hardening::ENABLED {
+                if let Err(error) = self.check_irq_stacks() {
+                    hardening::stack_check_fail(error);
+                }
+            }
+            Ok(trap)
+        }
+    }
+}
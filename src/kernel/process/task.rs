@@ -0,0 +1,519 @@
+// src/kernel/process/task.rs
+
+pub mod task {
+    use crate::process::elf::elf::{Executable, PROGRAM_HEADER_SIZE};
+    use crate::process::files::files::FileTable;
+    use crate::process::filter::filter::Filter;
+    use crate::process::limits::limits::{Limits, Resource, Usage};
+    use crate::process::linker::linker::{LinkMap, INTERPRETER};
+    use crate::process::mapping::mapping::{Backing, Mapping};
+    use crate::process::memory::memory::{page_align_up, AddressSpace, NO_EXECUTE, PAGE_SIZE, USER, USER_END, WRITABLE};
+    use crate::process::signal::signal::Dispositions;
+    use crate::users::users::Credentials;
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    // Laid out for SYSRET: user data, then user code, both at ring 3
+    pub const USER_DATA_SELECTOR: u16 = 0x1B;
+    pub const USER_CODE_SELECTOR: u16 = 0x23;
+    pub const RFLAGS_IF: u64 = 1 << 9;
+    // Bit 1 always reads as set
+    pub const RFLAGS_RESERVED: u64 = 1 << 1;
+
+    // The main stack sits at the top of user space with an unmapped page
+    // under it, so running off the end faults instead of corrupting
+    // whatever is below
+    pub const STACK_TOP: u64 = USER_END;
+    pub const STACK_SIZE: u64 = 32 * PAGE_SIZE;
+    // Mappings are placed from here down, well clear of the stack, and
+    // an interpreter goes just under it
+    pub const MMAP_TOP: u64 = STACK_TOP - (1 << 30);
+    // Where position-independent programs are loaded
+    pub const PROGRAM_BASE: u64 = 0x5555_5555_4000;
+    // How far each part of a randomized layout can move, in pages: the
+    // stack and mappings down, the program and heap up
+    pub const STACK_RANDOM_PAGES: u64 = 1 << 12;
+    pub const MMAP_RANDOM_PAGES: u64 = 1 << 16;
+    pub const PROGRAM_RANDOM_PAGES: u64 = 1 << 16;
+    pub const HEAP_RANDOM_PAGES: u64 = 1 << 13;
+
+    // Where a process's program, heap, mappings and stack go. Randomized,
+    // each moves by a whole number of pages, so addresses in one process
+    // tell nothing about another's.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Layout {
+        pub program_base: u64,
+        // Left between the end of the program and the start of the heap
+        pub heap_gap: u64,
+        pub mmap_top: u64,
+        pub stack_top: u64,
+    }
+
+    impl Layout {
+        pub fn fixed() -> Self {
+            Layout {
+                program_base: PROGRAM_BASE,
+                heap_gap: 0,
+                mmap_top: MMAP_TOP,
+                stack_top: STACK_TOP,
+            }
+        }
+
+        pub fn randomized() -> Result<Self, &'static str> {
+            let mut bytes = [0u8; 32];
+            getrandom::getrandom(&mut bytes).map_err(|_| "System entropy source unavailable")?;
+            let random = |index: usize, pages: u64| u64::from_le_bytes(bytes[index * 8..index * 8 + 8].try_into().unwrap()) % pages * PAGE_SIZE;
+            Ok(Layout {
+                program_base: PROGRAM_BASE + random(0, PROGRAM_RANDOM_PAGES),
+                heap_gap: random(1, HEAP_RANDOM_PAGES),
+                mmap_top: MMAP_TOP - random(2, MMAP_RANDOM_PAGES),
+                stack_top: STACK_TOP - random(3, STACK_RANDOM_PAGES),
+            })
+        }
+    }
+
+    impl Default for Layout {
+        fn default() -> Self {
+            Layout::fixed()
+        }
+    }
+
+    // Indices into UserContext::registers
+    pub const RAX: usize = 0;
+    pub const RCX: usize = 2;
+    pub const RDX: usize = 3;
+    pub const RSI: usize = 4;
+    pub const RDI: usize = 5;
+    pub const R8: usize = 7;
+    pub const R9: usize = 8;
+    pub const R10: usize = 9;
+    pub const R11: usize = 10;
+
+    // Auxiliary vector keys
+    pub const AT_NULL: u64 = 0;
+    pub const AT_PHDR: u64 = 3;
+    pub const AT_PHENT: u64 = 4;
+    pub const AT_PHNUM: u64 = 5;
+    pub const AT_PAGESZ: u64 = 6;
+    pub const AT_BASE: u64 = 7;
+    pub const AT_ENTRY: u64 = 9;
+
+    // What user code runs with, saved whenever it traps into the kernel
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct UserContext {
+        pub rip: u64,
+        pub rsp: u64,
+        pub rflags: u64,
+        pub cs: u16,
+        pub ss: u16,
+        // rax, rbx, rcx, rdx, rsi, rdi, rbp and r8 to r15, in that order
+        pub registers: [u64; 15],
+        // Where thread-local storage is, loaded into FS_BASE
+        pub fs_base: u64,
+    }
+
+    impl UserContext {
+        // Ring 3 at the entry point with interrupts on and every other
+        // register cleared, so nothing leaks from the kernel
+        pub fn new(entry: u64, stack: u64) -> Self {
+            UserContext {
+                rip: entry,
+                rsp: stack,
+                rflags: RFLAGS_IF | RFLAGS_RESERVED,
+                cs: USER_CODE_SELECTOR,
+                ss: USER_DATA_SELECTOR,
+                registers: [0; 15],
+                fs_base: 0,
+            }
+        }
+
+        pub fn is_user_mode(&self) -> bool {
+            self.cs & 3 == 3 && self.ss & 3 == 3
+        }
+    }
+
+    // Page fault error code bits
+    pub const FAULT_PRESENT: u64 = 1;
+    pub const FAULT_WRITE: u64 = 1 << 1;
+    pub const FAULT_USER: u64 = 1 << 2;
+    pub const FAULT_FETCH: u64 = 1 << 4;
+
+    // Why user code stopped and the kernel has the CPU back
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Trap {
+        // The error code's bits say whether it was a write, from user
+        // mode, or on a page that was present
+        PageFault { address: u64, error: u64 },
+        // Any other exception, by vector
+        Exception { vector: u8, error: Option<u64> },
+        // The timer or a device
+        Interrupt(u8),
+        // The SYSCALL instruction, with the return address in RCX and the
+        // flags in R11
+        Syscall,
+    }
+
+    // Runs user code on a CPU: switches CR3 to the address space and IRETs
+    // to the context, then saves the context back when something traps
+    pub trait UserCpu {
+        fn run(&mut self, space: &AddressSpace, context: &mut UserContext) -> Trap;
+    }
+
+    // One of a process's threads. They share everything but their
+    // registers, stack and thread-local storage.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Thread {
+        pub context: UserContext,
+        // Zeroed when the thread exits, with waiters on it as a futex
+        // woken, so others can join it. Null for none.
+        pub clear_tid: u64,
+    }
+
+    impl Thread {
+        pub fn new(context: UserContext) -> Self {
+            Thread { context, clear_tid: 0 }
+        }
+    }
+
+    // A program in its own address space. The kernel half of the tables
+    // is shared with every other process.
+    pub struct Process {
+        pid: u32,
+        name: String,
+        space: AddressSpace,
+        // By thread ID, which comes from the same numbers as PIDs. The
+        // main thread's is the PID.
+        threads: BTreeMap<u32, Thread>,
+        // Where the heap can start, past the program's segments
+        break_start: u64,
+        // Bytes of the program's segments, its interpreter's and its stack
+        image_size: u64,
+        layout: Layout,
+        // Where mappings go below, and fixed ones have to end by
+        mmap_top: u64,
+        // What the built-in linker has loaded
+        link_map: LinkMap,
+        files: FileTable,
+        credentials: Credentials,
+        limits: Limits,
+        usage: Usage,
+        // For job control: the process group it is in and the session the
+        // group is in, each known by its leader's PID
+        group: u32,
+        session: u32,
+        dispositions: Dispositions,
+        // System call filters, oldest first
+        filters: Vec<Arc<Filter>>,
+    }
+
+    impl Process {
+        // Loads an executable into a fresh address space, with a stack
+        // holding the arguments and environment, ready to enter at its
+        // entry point. It runs as root, leading a group and session of its
+        // own, until given other credentials and placed otherwise.
+        // Programs for the built-in linker are left for it to link. The
+        // layout is the fixed one.
+        pub fn load(pid: u32, name: &str, kernel: &AddressSpace, data: &[u8], args: &[&str], env: &[&str]) -> Result<Self, &'static str> {
+            Self::load_with_interpreter(pid, name, kernel, data, None, Layout::fixed(), args, env)
+        }
+
+        // The same for a program with an interpreter of its own, which is
+        // loaded under the mappings and entered instead, with the program's
+        // entry point and where the interpreter is in the auxiliary vector
+        #[allow(clippy::too_many_arguments)]
+        pub fn load_with_interpreter(
+            pid: u32,
+            name: &str,
+            kernel: &AddressSpace,
+            data: &[u8],
+            interpreter: Option<&[u8]>,
+            layout: Layout,
+            args: &[&str],
+            env: &[&str],
+        ) -> Result<Self, &'static str> {
+            let executable = Executable::parse(data)?;
+            if executable.entry == 0 {
+                return Err("No entry point");
+            }
+            let builtin = executable.interpreter.as_deref() == Some(INTERPRETER);
+            if executable.interpreter.is_some() && !builtin && interpreter.is_none() {
+                return Err("Interpreter not loaded");
+            }
+            let base = if executable.position_independent { layout.program_base } else { 0 };
+            let mut space = AddressSpace::new_user(kernel)?;
+            executable.load(data, &mut space, base)?;
+            let break_start = base + executable.end() + layout.heap_gap;
+            let (entry, interpreter_base) = match interpreter {
+                Some(data) => {
+                    let interpreter = Executable::parse(data)?;
+                    if !interpreter.position_independent || interpreter.interpreter.is_some() || interpreter.entry == 0 {
+                        return Err("Bad interpreter");
+                    }
+                    let interpreter_base = layout.mmap_top.checked_sub(interpreter.end()).filter(|base| *base >= break_start).ok_or("Out of address space")?;
+                    interpreter.load(data, &mut space, interpreter_base)?;
+                    (interpreter_base + interpreter.entry, Some(interpreter_base))
+                }
+                None => (base + executable.entry, None),
+            };
+            let stack = build_stack(&mut space, &executable, base, interpreter_base, layout.stack_top, args, env)?;
+            let link_map = match executable.dynamic {
+                Some(dynamic) if builtin => LinkMap::program(&space, name, base, base + dynamic)?,
+                _ => LinkMap::default(),
+            };
+            let image_size = space.mapped_pages() * PAGE_SIZE;
+            log::info!("Loaded {} with entry point {:#x}", name, entry);
+            Ok(Process {
+                pid,
+                name: name.to_string(),
+                space,
+                threads: BTreeMap::from([(pid, Thread::new(UserContext::new(entry, stack)))]),
+                break_start,
+                image_size,
+                layout,
+                mmap_top: interpreter_base.unwrap_or(layout.mmap_top),
+                link_map,
+                files: FileTable::new(),
+                credentials: Credentials::root(),
+                limits: Limits::new(),
+                usage: Usage {
+                    peak_memory: image_size,
+                    ..Usage::default()
+                },
+                group: pid,
+                session: pid,
+                dispositions: Dispositions::default(),
+                filters: Vec::new(),
+            })
+        }
+
+        pub fn pid(&self) -> u32 {
+            self.pid
+        }
+
+        pub fn name(&self) -> &str {
+            &self.name
+        }
+
+        pub fn space(&self) -> &AddressSpace {
+            &self.space
+        }
+
+        pub fn space_mut(&mut self) -> &mut AddressSpace {
+            &mut self.space
+        }
+
+        // The main thread's, or the oldest left once it has exited
+        pub fn context(&self) -> &UserContext {
+            &self.threads.values().next().unwrap().context
+        }
+
+        pub fn context_mut(&mut self) -> &mut UserContext {
+            &mut self.threads.values_mut().next().unwrap().context
+        }
+
+        pub fn tids(&self) -> Vec<u32> {
+            self.threads.keys().copied().collect()
+        }
+
+        pub fn thread(&self, tid: u32) -> Option<&Thread> {
+            self.threads.get(&tid)
+        }
+
+        pub fn thread_mut(&mut self, tid: u32) -> Option<&mut Thread> {
+            self.threads.get_mut(&tid)
+        }
+
+        pub fn add_thread(&mut self, tid: u32, thread: Thread) {
+            self.threads.insert(tid, thread);
+        }
+
+        pub fn remove_thread(&mut self, tid: u32) -> Option<Thread> {
+            self.threads.remove(&tid)
+        }
+
+        pub fn break_start(&self) -> u64 {
+            self.break_start
+        }
+
+        pub fn layout(&self) -> &Layout {
+            &self.layout
+        }
+
+        pub fn link_map(&self) -> &LinkMap {
+            &self.link_map
+        }
+
+        pub fn link_map_mut(&mut self) -> &mut LinkMap {
+            &mut self.link_map
+        }
+
+        pub fn files(&self) -> &FileTable {
+            &self.files
+        }
+
+        pub fn files_mut(&mut self) -> &mut FileTable {
+            &mut self.files
+        }
+
+        pub fn set_files(&mut self, mut files: FileTable) {
+            files.set_limit(self.limits.get(Resource::Files).soft as usize);
+            self.files = files;
+        }
+
+        pub fn credentials(&self) -> &Credentials {
+            &self.credentials
+        }
+
+        pub fn set_credentials(&mut self, credentials: Credentials) {
+            self.credentials = credentials;
+        }
+
+        pub fn limits(&self) -> &Limits {
+            &self.limits
+        }
+
+        // Applies the open files limit to the descriptors straight away
+        pub fn set_limits(&mut self, limits: Limits) {
+            self.limits = limits;
+            self.files.set_limit(limits.get(Resource::Files).soft as usize);
+        }
+
+        pub fn usage(&self) -> &Usage {
+            &self.usage
+        }
+
+        pub fn usage_mut(&mut self) -> &mut Usage {
+            &mut self.usage
+        }
+
+        pub fn group(&self) -> u32 {
+            self.group
+        }
+
+        // The process table keeps groups within sessions
+        pub fn set_group(&mut self, group: u32) {
+            self.group = group;
+        }
+
+        pub fn session(&self) -> u32 {
+            self.session
+        }
+
+        pub fn set_session(&mut self, session: u32) {
+            self.session = session;
+        }
+
+        pub fn dispositions(&self) -> &Dispositions {
+            &self.dispositions
+        }
+
+        pub fn dispositions_mut(&mut self) -> &mut Dispositions {
+            &mut self.dispositions
+        }
+
+        pub fn filters(&self) -> &[Arc<Filter>] {
+            &self.filters
+        }
+
+        // There is no taking one away again
+        pub fn add_filter(&mut self, filter: Arc<Filter>) {
+            self.filters.push(filter);
+        }
+
+        // Bytes of its address space in use, counting mappings in full
+        // whether touched yet or not
+        pub fn memory(&self) -> u64 {
+            self.image_size + self.space.mapped_length()
+        }
+
+        // Page-aligned start and end of a range given by user code
+        fn range(address: u64, length: u64) -> Result<(u64, u64), &'static str> {
+            let size = page_align_up(length).filter(|size| *size > 0).ok_or("Invalid length")?;
+            let end = address.checked_add(size).filter(|end| address.is_multiple_of(PAGE_SIZE) && *end <= USER_END).ok_or("Invalid address")?;
+            Ok((address, end))
+        }
+
+        // Memory with page table flags that is filled in as it is touched,
+        // within the memory limit. It goes below the previous mappings, or
+        // exactly at a fixed address in place of whatever mappings were
+        // there; never over the program, its interpreter or its stack.
+        pub fn map(&mut self, address: Option<u64>, length: u64, flags: u64, shared: bool, backing: Backing) -> Result<u64, &'static str> {
+            let size = page_align_up(length).filter(|size| *size > 0).ok_or("Invalid length")?;
+            if self.limits.get(Resource::Memory).exceeded_by(self.memory().saturating_add(size)) {
+                return Err("Memory limit exceeded");
+            }
+            let start = match address {
+                Some(address) => {
+                    let (start, end) = Self::range(address, size)?;
+                    if start < self.break_start || end > self.mmap_top {
+                        return Err("Address in use");
+                    }
+                    self.space.remove_mappings(start, end);
+                    start
+                }
+                None => self.space.find_free(size, self.break_start, self.mmap_top).ok_or("Out of address space")?,
+            };
+            self.space.add_mapping(Mapping {
+                start,
+                end: start + size,
+                flags,
+                shared,
+                backing,
+            })?;
+            self.usage.peak_memory = self.usage.peak_memory.max(self.memory());
+            Ok(start)
+        }
+
+        // Only takes away mappings; the program and its stack stay
+        pub fn unmap(&mut self, address: u64, length: u64) -> Result<(), &'static str> {
+            let (start, end) = Self::range(address, length)?;
+            self.space.remove_mappings(start, end);
+            Ok(())
+        }
+
+        pub fn protect(&mut self, address: u64, length: u64, flags: u64) -> Result<(), &'static str> {
+            let (start, end) = Self::range(address, length)?;
+            self.space.protect_mappings(start, end, flags)
+        }
+
+        // One thread, until the next trap
+        pub fn run(&mut self, tid: u32, cpu: &mut dyn UserCpu) -> Result<Trap, &'static str> {
+            let thread = self.threads.get_mut(&tid).ok_or("No such thread")?;
+            Ok(cpu.run(&self.space, &mut thread.context))
+        }
+    }
+
+    // The System V start-up stack, from the stack pointer up: argc, the
+    // argument pointers and a null, the environment pointers and a null,
+    // then the auxiliary vector. The strings are above it all.
+    fn build_stack(space: &mut AddressSpace, executable: &Executable, base: u64, interpreter: Option<u64>, stack_top: u64, args: &[&str], env: &[&str]) -> Result<u64, &'static str> {
+        let bottom = stack_top - STACK_SIZE;
+        space.map_zeroed(bottom, STACK_SIZE / PAGE_SIZE, USER | WRITABLE | NO_EXECUTE)?;
+        let mut top = stack_top;
+        let mut push_string = |text: &str| -> Result<u64, &'static str> {
+            top = top.checked_sub(text.len() as u64 + 1).filter(|top| *top >= bottom).ok_or("Arguments too long")?;
+            space.write(top, text.as_bytes())?;
+            Ok(top)
+        };
+        let args: Vec<u64> = args.iter().map(|arg| push_string(arg)).collect::<Result<_, _>>()?;
+        let env: Vec<u64> = env.iter().map(|var| push_string(var)).collect::<Result<_, _>>()?;
+        let mut words = vec![args.len() as u64];
+        words.extend(&args);
+        words.push(0);
+        words.extend(&env);
+        words.push(0);
+        if let Some(headers) = executable.program_headers {
+            words.extend([AT_PHDR, base + headers, AT_PHENT, PROGRAM_HEADER_SIZE as u64, AT_PHNUM, executable.program_header_count as u64]);
+        }
+        if let Some(interpreter) = interpreter {
+            words.extend([AT_BASE, interpreter]);
+        }
+        words.extend([AT_PAGESZ, PAGE_SIZE, AT_ENTRY, base + executable.entry, AT_NULL, 0]);
+        // 16-byte aligned where argc is, as the ABI asks
+        let size = words.len() as u64 * 8;
+        let stack = top.checked_sub(size).map(|stack| stack & !15).filter(|stack| *stack >= bottom).ok_or("Arguments too long")?;
+        let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        space.write(stack, &bytes)?;
+        Ok(stack)
+    }
+}
@@ -0,0 +1,77 @@
+// src/kernel/process/uaccess.rs
+
+pub mod uaccess {
+    use crate::drivers::msr::msr::MsrIo;
+    use crate::process::task::task::FAULT_FETCH;
+    use std::cell::Cell;
+
+    // CR4 bits
+    pub const CR4_UMIP: u64 = 1 << 11;
+    pub const CR4_SMEP: u64 = 1 << 20;
+    pub const CR4_SMAP: u64 = 1 << 21;
+
+    // Structured extended features, subleaf 0
+    pub const CPUID_EXTENDED_FEATURES: u32 = 7;
+    pub const CPUID_SMEP: u32 = 1 << 7;
+    pub const CPUID_SMAP: u32 = 1 << 20;
+    pub const CPUID_UMIP: u32 = 1 << 2;
+
+    // Turns on whichever of SMEP, SMAP and UMIP one CPU has, returning
+    // the CR4 bits set: the kernel may then neither run user pages, nor
+    // touch them but through copy_from_user and copy_to_user, and user
+    // code may not read descriptor table addresses with SGDT and the like
+    pub fn enable(msr: &dyn MsrIo, cpu: usize) -> Result<u64, &'static str> {
+        let (_, ebx, ecx, _) = msr.cpuid(cpu, CPUID_EXTENDED_FEATURES);
+        let bits = [(ebx & CPUID_SMEP, CR4_SMEP), (ebx & CPUID_SMAP, CR4_SMAP), (ecx & CPUID_UMIP, CR4_UMIP)]
+            .iter()
+            .filter(|(feature, _)| *feature != 0)
+            .fold(0, |bits, (_, bit)| bits | bit);
+        let cr4 = msr.read_cr4(cpu)?;
+        msr.write_cr4(cpu, cr4 | bits)?;
+        Ok(bits)
+    }
+
+    thread_local! {
+        // RFLAGS.AC on the CPU this runs on, which lets the kernel at user
+        // pages despite SMAP
+        static USER_ACCESS: Cell<bool> = const { Cell::new(false) };
+    }
+
+    // Open between STAC and CLAC, and closed again if the access panics
+    struct Window {
+        was_open: bool,
+    }
+
+    impl Drop for Window {
+        fn drop(&mut self) {
+            USER_ACCESS.with(|open| open.set(self.was_open));
+        }
+    }
+
+    // Runs an access to user memory with SMAP lifted, for the user copy
+    // helpers once they have checked the range. Nothing else should.
+    pub fn with_user_access<T>(access: impl FnOnce() -> T) -> T {
+        let _window = Window { was_open: USER_ACCESS.with(|open| open.replace(true)) };
+        access()
+    }
+
+    pub fn user_access_open() -> bool {
+        USER_ACCESS.with(Cell::get)
+    }
+
+    // What a fault the kernel took on a user page means, with what CR4
+    // had on: executing it is always a bug under SMEP, and touching it is
+    // one under SMAP unless a user copy was under way
+    pub fn check_kernel_fault(cr4: u64, error: u64) -> Result<(), &'static str> {
+        if error & FAULT_FETCH != 0 {
+            return match cr4 & CR4_SMEP != 0 {
+                true => Err("SMEP: kernel executed user memory"),
+                false => Ok(()),
+            };
+        }
+        match cr4 & CR4_SMAP != 0 && !user_access_open() {
+            true => Err("SMAP: kernel accessed user memory directly"),
+            false => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,330 @@
+// src/kernel/pty.rs
+
+pub mod pty {
+    use std::collections::VecDeque;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct PtySize {
+        pub rows: u16,
+        pub cols: u16,
+    }
+
+    // The subset of termios the line discipline honours
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct LineMode {
+        // Input is handed over a line at a time, with erase and kill
+        pub canonical: bool,
+        pub echo: bool,
+        // ^C, ^Z and ^\ raise signals instead of being passed through
+        pub signals: bool,
+    }
+
+    impl Default for LineMode {
+        fn default() -> Self {
+            LineMode {
+                canonical: true,
+                echo: true,
+                signals: true,
+            }
+        }
+    }
+
+    // For the slave side's foreground process
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Signal {
+        Interrupt,
+        Quit,
+        Suspend,
+        WindowChange,
+    }
+
+    const CTRL_C: u8 = 0x03;
+    const CTRL_D: u8 = 0x04;
+    const CTRL_U: u8 = 0x15;
+    const CTRL_Z: u8 = 0x1A;
+    const CTRL_BACKSLASH: u8 = 0x1C;
+    const BACKSPACE: u8 = 0x08;
+    const DELETE: u8 = 0x7F;
+
+    struct PtyState {
+        size: PtySize,
+        mode: LineMode,
+        // Master to slave, ready to read
+        input: VecDeque<u8>,
+        // The canonical line being edited
+        line: Vec<u8>,
+        // Slave to master
+        output: VecDeque<u8>,
+        signals: Vec<Signal>,
+        // ^D on an empty line; the next slave read returns nothing
+        end_of_file: bool,
+        master_open: bool,
+        slave_open: bool,
+        // The session it is the controlling terminal of, and which of that
+        // session's process groups is in the foreground
+        session: Option<u32>,
+        foreground: Option<u32>,
+    }
+
+    impl PtyState {
+        // Output post-processing: newlines go out as CR LF
+        fn emit(&mut self, bytes: &[u8]) {
+            for &byte in bytes {
+                if byte == b'\n' {
+                    self.output.push_back(b'\r');
+                }
+                self.output.push_back(byte);
+            }
+        }
+
+        fn echo(&mut self, bytes: &[u8]) {
+            if self.mode.echo {
+                self.emit(bytes);
+            }
+        }
+
+        fn signal(&mut self, byte: u8) -> bool {
+            let signal = match byte {
+                CTRL_C => Signal::Interrupt,
+                CTRL_BACKSLASH => Signal::Quit,
+                CTRL_Z => Signal::Suspend,
+                _ => return false,
+            };
+            self.signals.push(signal);
+            self.line.clear();
+            self.echo(&[b'^', byte + b'@', b'\n']);
+            true
+        }
+
+        // The line discipline, for bytes typed at the terminal
+        fn receive(&mut self, byte: u8) {
+            if self.mode.signals && self.signal(byte) {
+                return;
+            }
+            if !self.mode.canonical {
+                self.input.push_back(byte);
+                self.echo(&[byte]);
+                return;
+            }
+            match byte {
+                b'\r' | b'\n' => {
+                    self.line.push(b'\n');
+                    self.input.extend(self.line.drain(..));
+                    self.echo(b"\n");
+                }
+                BACKSPACE | DELETE => {
+                    // Back over a whole UTF-8 character
+                    while let Some(removed) = self.line.pop() {
+                        if removed & 0xC0 != 0x80 {
+                            self.echo(b"\x08 \x08");
+                            break;
+                        }
+                    }
+                }
+                CTRL_U => {
+                    let erased = self.line.iter().filter(|byte| *byte & 0xC0 != 0x80).count();
+                    self.line.clear();
+                    for _ in 0..erased {
+                        self.echo(b"\x08 \x08");
+                    }
+                }
+                CTRL_D if self.line.is_empty() => self.end_of_file = true,
+                CTRL_D => self.input.extend(self.line.drain(..)),
+                byte => {
+                    self.line.push(byte);
+                    self.echo(&[byte]);
+                }
+            }
+        }
+    }
+
+    static NEXT_INDEX: AtomicU32 = AtomicU32::new(0);
+
+    // The terminal side: the emulator or console writes keystrokes and
+    // reads what programs print
+    pub struct PtyMaster {
+        index: u32,
+        state: Arc<Mutex<PtyState>>,
+    }
+
+    // The program side, which a shell gets as its controlling terminal.
+    // Clones share the terminal, as after fork.
+    #[derive(Clone)]
+    pub struct PtySlave {
+        index: u32,
+        state: Arc<Mutex<PtyState>>,
+    }
+
+    // Allocates the next /dev/pts/N pair
+    pub fn open(size: PtySize) -> (PtyMaster, PtySlave) {
+        let index = NEXT_INDEX.fetch_add(1, Ordering::Relaxed);
+        let state = Arc::new(Mutex::new(PtyState {
+            size,
+            mode: LineMode::default(),
+            input: VecDeque::new(),
+            line: Vec::new(),
+            output: VecDeque::new(),
+            signals: Vec::new(),
+            end_of_file: false,
+            master_open: true,
+            slave_open: true,
+            session: None,
+            foreground: None,
+        }));
+        (
+            PtyMaster {
+                index,
+                state: state.clone(),
+            },
+            PtySlave { index, state },
+        )
+    }
+
+    impl PtyMaster {
+        pub fn index(&self) -> u32 {
+            self.index
+        }
+
+        pub fn write(&self, bytes: &[u8]) -> Result<(), &'static str> {
+            let mut state = self.state.lock().unwrap();
+            if !state.slave_open {
+                return Err("Terminal hung up");
+            }
+            for &byte in bytes {
+                state.receive(byte);
+            }
+            Ok(())
+        }
+
+        // Everything printed since the last read
+        pub fn read(&self) -> Vec<u8> {
+            self.state.lock().unwrap().output.drain(..).collect()
+        }
+
+        pub fn size(&self) -> PtySize {
+            self.state.lock().unwrap().size
+        }
+
+        // The foreground process hears about it through WindowChange
+        pub fn resize(&self, size: PtySize) {
+            let mut state = self.state.lock().unwrap();
+            if state.size != size {
+                state.size = size;
+                state.signals.push(Signal::WindowChange);
+            }
+        }
+
+        // False once every slave handle has been dropped
+        pub fn is_connected(&self) -> bool {
+            self.state.lock().unwrap().slave_open
+        }
+
+        // The job keystrokes go to, e.g. for a title
+        pub fn foreground(&self) -> Option<u32> {
+            self.state.lock().unwrap().foreground
+        }
+    }
+
+    impl Drop for PtyMaster {
+        fn drop(&mut self) {
+            self.state.lock().unwrap().master_open = false;
+        }
+    }
+
+    impl PtySlave {
+        pub fn index(&self) -> u32 {
+            self.index
+        }
+
+        pub fn name(&self) -> String {
+            format!("/dev/pts/{}", self.index)
+        }
+
+        // Whatever input is ready: whole lines in canonical mode. Ok(None)
+        // means nothing yet; an empty read is end of file.
+        pub fn read(&self) -> Result<Option<Vec<u8>>, &'static str> {
+            let mut state = self.state.lock().unwrap();
+            if !state.input.is_empty() {
+                return Ok(Some(state.input.drain(..).collect()));
+            }
+            if std::mem::take(&mut state.end_of_file) {
+                return Ok(Some(Vec::new()));
+            }
+            if !state.master_open {
+                return Err("Terminal hung up");
+            }
+            Ok(None)
+        }
+
+        // Whether read would return straight away, for poll
+        pub fn is_readable(&self) -> bool {
+            let state = self.state.lock().unwrap();
+            !state.input.is_empty() || state.end_of_file || !state.master_open
+        }
+
+        pub fn write(&self, bytes: &[u8]) -> Result<(), &'static str> {
+            let mut state = self.state.lock().unwrap();
+            if !state.master_open {
+                return Err("Terminal hung up");
+            }
+            state.emit(bytes);
+            Ok(())
+        }
+
+        pub fn size(&self) -> PtySize {
+            self.state.lock().unwrap().size
+        }
+
+        pub fn mode(&self) -> LineMode {
+            self.state.lock().unwrap().mode
+        }
+
+        // Switching to raw mode hands over a half-typed line as is
+        pub fn set_mode(&self, mode: LineMode) {
+            let mut state = self.state.lock().unwrap();
+            if !mode.canonical {
+                let line: Vec<u8> = state.line.drain(..).collect();
+                state.input.extend(line);
+            }
+            state.mode = mode;
+        }
+
+        pub fn take_signals(&self) -> Vec<Signal> {
+            std::mem::take(&mut self.state.lock().unwrap().signals)
+        }
+
+        // Once the terminal side has gone away
+        pub fn is_hung_up(&self) -> bool {
+            !self.state.lock().unwrap().master_open
+        }
+
+        pub fn session(&self) -> Option<u32> {
+            self.state.lock().unwrap().session
+        }
+
+        pub fn foreground(&self) -> Option<u32> {
+            self.state.lock().unwrap().foreground
+        }
+
+        // Becoming or ceasing to be a session's controlling terminal, with
+        // the session leader's group in the foreground
+        pub fn set_session(&self, session: Option<u32>) {
+            let mut state = self.state.lock().unwrap();
+            state.session = session;
+            state.foreground = session;
+        }
+
+        // The process table checks the group is in the session
+        pub fn set_foreground(&self, group: u32) {
+            self.state.lock().unwrap().foreground = Some(group);
+        }
+
+        // Hangs up for every clone at once, as when the session leader
+        // exits
+        pub fn close(self) {
+            self.state.lock().unwrap().slave_open = false;
+        }
+    }
+}
@@ -0,0 +1,210 @@
+// src/kernel/rcu.rs
+
+pub mod rcu {
+    use crate::metrics::metrics::{MetricType, Registry, Sample};
+    use crate::sync::sync::{Mutex, SpinLock};
+    use std::cell::Cell;
+    use std::marker::PhantomData;
+    use std::ops::Deref;
+    use std::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    type Callback = Box<dyn FnOnce() + Send>;
+
+    // Grace periods started since boot, plus one so that a snapshot of it
+    // is never 0
+    static GP_SEQ: AtomicU64 = AtomicU64::new(1);
+    static COMPLETED: AtomicU64 = AtomicU64::new(0);
+    // Every CPU that has ever read, taken from interrupt handlers
+    static READERS: SpinLock<Vec<Arc<Reader>>> = SpinLock::new("rcu.readers", Vec::new());
+    // call_rcu() callbacks with the grace period each waits for
+    static CALLBACKS: SpinLock<Vec<(u64, Callback)>> = SpinLock::new("rcu.callbacks", Vec::new());
+
+    // GP_SEQ when the CPU's outermost read-side critical section began,
+    // 0 when it is in none
+    struct Reader {
+        snapshot: AtomicU64,
+    }
+
+    thread_local! {
+        // Per CPU: how deeply nested in read-side critical sections
+        static NESTING: Cell<u32> = const { Cell::new(0) };
+        static READER: Arc<Reader> = {
+            let reader = Arc::new(Reader { snapshot: AtomicU64::new(0) });
+            READERS.lock_irqsave().push(Arc::clone(&reader));
+            reader
+        };
+    }
+
+    pub fn in_read_section() -> bool {
+        NESTING.with(Cell::get) > 0
+    }
+
+    // Until it is dropped, nothing read through an Rcu is freed. Sections
+    // nest, cost no locks or shared writes and must not sleep.
+    pub struct RcuReadGuard {
+        _not_send: PhantomData<*const ()>,
+    }
+
+    pub fn read_lock() -> RcuReadGuard {
+        let nesting = NESTING.with(|nesting| nesting.replace(nesting.get() + 1));
+        if nesting == 0 {
+            READER.with(|reader| reader.snapshot.store(GP_SEQ.load(Ordering::SeqCst), Ordering::SeqCst));
+        }
+        RcuReadGuard { _not_send: PhantomData }
+    }
+
+    impl Drop for RcuReadGuard {
+        fn drop(&mut self) {
+            let nesting = NESTING.with(|nesting| nesting.replace(nesting.get() - 1));
+            if nesting == 1 {
+                READER.with(|reader| reader.snapshot.store(0, Ordering::SeqCst));
+            }
+        }
+    }
+
+    fn start_grace_period() -> u64 {
+        GP_SEQ.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    // The earliest grace period a CPU's read-side critical section may
+    // have begun in. Readers of CPUs that are gone are dropped on the way.
+    fn oldest_reader() -> u64 {
+        let mut readers = READERS.lock_irqsave();
+        readers.retain(|reader| Arc::strong_count(reader) > 1);
+        readers.iter().map(|reader| reader.snapshot.load(Ordering::SeqCst)).filter(|snapshot| *snapshot != 0).min().unwrap_or(u64::MAX)
+    }
+
+    // Waits until every read-side critical section that could have seen
+    // what was unpublished before the call has ended
+    pub fn synchronize_rcu() {
+        assert!(!in_read_section(), "synchronize_rcu() in an RCU read-side critical section");
+        let target = start_grace_period();
+        while oldest_reader() < target {
+            std::thread::yield_now();
+        }
+        COMPLETED.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Runs callback after a grace period, from the scheduler, without
+    // waiting for it; safe from interrupt handlers
+    pub fn call_rcu(callback: impl FnOnce() + Send + 'static) {
+        let target = start_grace_period();
+        CALLBACKS.lock_irqsave().push((target, Box::new(callback)));
+    }
+
+    // Runs the callbacks whose grace period has ended, returning how many
+    pub fn process_callbacks() -> usize {
+        let ready: Vec<Callback> = {
+            let mut callbacks = CALLBACKS.lock_irqsave();
+            let oldest = oldest_reader();
+            let (ready, waiting) = std::mem::take(&mut *callbacks).into_iter().partition(|(target, _)| *target <= oldest);
+            *callbacks = waiting;
+            ready.into_iter().map(|(_, callback)| callback).collect()
+        };
+        let count = ready.len();
+        for callback in ready {
+            callback();
+        }
+        count
+    }
+
+    // Waits for every callback queued so far to have run
+    pub fn rcu_barrier() {
+        synchronize_rcu();
+        process_callbacks();
+    }
+
+    pub fn pending_callbacks() -> usize {
+        CALLBACKS.lock_irqsave().len()
+    }
+
+    // From the scheduler as it switches threads: a CPU that switches is
+    // in no read-side critical section, unless it went to sleep in one
+    pub fn note_context_switch() {
+        if in_read_section() {
+            log::warn!("rcu: context switch in an RCU read-side critical section");
+            return;
+        }
+        process_callbacks();
+    }
+
+    // A value readers reach without locks. Updates copy it, publish the
+    // copy and free the old one once a grace period has passed, so each
+    // costs a clone and a wait; for data read far more than written.
+    pub struct Rcu<T> {
+        current: AtomicPtr<T>,
+        // Updates are serialised, reads never wait for them
+        writer: Mutex<()>,
+        // Freed from whichever CPU updates, read from any
+        _owns: PhantomData<Arc<T>>,
+    }
+
+    impl<T> Rcu<T> {
+        pub fn new(class: &'static str, value: T) -> Self {
+            Rcu { current: AtomicPtr::new(Box::into_raw(Box::new(value))), writer: Mutex::new(class, ()), _owns: PhantomData }
+        }
+
+        // The value as it is now; later updates are not seen through it
+        pub fn read(&self) -> RcuRef<'_, T> {
+            let guard = read_lock();
+            let value = unsafe { &*self.current.load(Ordering::SeqCst) };
+            RcuRef { value, _guard: guard }
+        }
+
+        // Publishes value and waits for a grace period to free the old one
+        pub fn replace(&self, value: T) {
+            let old = {
+                let _writer = self.writer.lock();
+                self.current.swap(Box::into_raw(Box::new(value)), Ordering::SeqCst)
+            };
+            synchronize_rcu();
+            drop(unsafe { Box::from_raw(old) });
+        }
+
+        // Changes a copy, publishing it only when update succeeds
+        pub fn update<R>(&self, update: impl FnOnce(&mut T) -> Result<R, &'static str>) -> Result<R, &'static str>
+        where
+            T: Clone,
+        {
+            let (result, old) = {
+                let _writer = self.writer.lock();
+                // Only an update frees the current value, and none can
+                // while this holds the writer lock
+                let mut copy = unsafe { &*self.current.load(Ordering::SeqCst) }.clone();
+                let result = update(&mut copy)?;
+                (result, self.current.swap(Box::into_raw(Box::new(copy)), Ordering::SeqCst))
+            };
+            synchronize_rcu();
+            drop(unsafe { Box::from_raw(old) });
+            Ok(result)
+        }
+    }
+
+    impl<T> Drop for Rcu<T> {
+        fn drop(&mut self) {
+            drop(unsafe { Box::from_raw(*self.current.get_mut()) });
+        }
+    }
+
+    // A read of an Rcu, in a read-side critical section until dropped
+    pub struct RcuRef<'a, T> {
+        value: &'a T,
+        _guard: RcuReadGuard,
+    }
+
+    impl<T> Deref for RcuRef<'_, T> {
+        type Target = T;
+
+        fn deref(&self) -> &T {
+            self.value
+        }
+    }
+
+    pub fn register_metrics(registry: &Registry) -> Result<(), &'static str> {
+        registry.collect("vaelix_rcu_grace_periods_total", "Grace periods waited for by synchronize_rcu().", MetricType::Counter, || {
+            vec![Sample::new(COMPLETED.load(Ordering::Relaxed) as f64)]
+        })?;
+        registry.collect("vaelix_rcu_callbacks_pending", "call_rcu() callbacks waiting for their grace period.", MetricType::Gauge, || vec![Sample::new(pending_callbacks() as f64)])
+    }
+}
@@ -1 +1,214 @@
 // src/kernel/sync.rs
+
+pub mod sync {
+    use crate::lockdep::lockdep::{self, LockKind};
+    use std::cell::{Cell, UnsafeCell};
+    use std::ops::{Deref, DerefMut};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::PoisonError;
+
+    thread_local! {
+        // Per CPU: how deep in interrupt handlers, and how many times
+        // interrupts have been disabled without being enabled again
+        static HARDIRQ: Cell<u32> = const { Cell::new(0) };
+        static IRQS_OFF: Cell<u32> = const { Cell::new(0) };
+    }
+
+    pub fn in_interrupt() -> bool {
+        HARDIRQ.with(Cell::get) > 0
+    }
+
+    // Handlers run with interrupts off too
+    pub fn irqs_disabled() -> bool {
+        in_interrupt() || IRQS_OFF.with(Cell::get) > 0
+    }
+
+    // Interrupts on this CPU stay off until it is dropped. Hosted, only
+    // the bookkeeping lockdep checks against is kept.
+    pub struct IrqGuard {
+        _not_send: std::marker::PhantomData<*const ()>,
+    }
+
+    pub fn local_irq_disable() -> IrqGuard {
+        IRQS_OFF.with(|off| off.set(off.get() + 1));
+        IrqGuard { _not_send: std::marker::PhantomData }
+    }
+
+    impl Drop for IrqGuard {
+        fn drop(&mut self) {
+            IRQS_OFF.with(|off| off.set(off.get() - 1));
+        }
+    }
+
+    // Runs an interrupt handler, as the entry code does on a vector
+    pub fn hardirq<T>(handler: impl FnOnce() -> T) -> T {
+        struct Exit;
+        impl Drop for Exit {
+            fn drop(&mut self) {
+                HARDIRQ.with(|depth| depth.set(depth.get() - 1));
+            }
+        }
+        HARDIRQ.with(|depth| depth.set(depth.get() + 1));
+        let _exit = Exit;
+        handler()
+    }
+
+    // A busy-waiting lock, for data interrupt handlers share. Locks with
+    // the same class name are one class to lockdep.
+    pub struct SpinLock<T> {
+        class: &'static str,
+        locked: AtomicBool,
+        value: UnsafeCell<T>,
+    }
+
+    unsafe impl<T: Send> Sync for SpinLock<T> {}
+    unsafe impl<T: Send> Send for SpinLock<T> {}
+
+    impl<T> SpinLock<T> {
+        pub const fn new(class: &'static str, value: T) -> Self {
+            SpinLock { class, locked: AtomicBool::new(false), value: UnsafeCell::new(value) }
+        }
+
+        pub fn class(&self) -> &'static str {
+            self.class
+        }
+
+        fn id(&self) -> usize {
+            self as *const Self as usize
+        }
+
+        fn spin(&self) {
+            while self.locked.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+                std::hint::spin_loop();
+            }
+        }
+
+        // Only safe against interrupt handlers taking it if they never do;
+        // lockdep complains when one does
+        pub fn lock(&self) -> SpinLockGuard<'_, T> {
+            lockdep::acquire(self.class, self.id(), LockKind::Spin);
+            self.spin();
+            SpinLockGuard { lock: self, _irq: None }
+        }
+
+        // With interrupts off on this CPU until the guard is dropped
+        pub fn lock_irqsave(&self) -> SpinLockGuard<'_, T> {
+            let irq = local_irq_disable();
+            lockdep::acquire(self.class, self.id(), LockKind::Spin);
+            self.spin();
+            SpinLockGuard { lock: self, _irq: Some(irq) }
+        }
+
+        // Never waits, so it cannot deadlock and adds no ordering
+        pub fn try_lock(&self) -> Option<SpinLockGuard<'_, T>> {
+            self.locked.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).ok()?;
+            lockdep::acquire_try(self.class, self.id(), LockKind::Spin);
+            Some(SpinLockGuard { lock: self, _irq: None })
+        }
+
+        pub fn get_mut(&mut self) -> &mut T {
+            self.value.get_mut()
+        }
+
+        pub fn into_inner(self) -> T {
+            self.value.into_inner()
+        }
+    }
+
+    pub struct SpinLockGuard<'a, T> {
+        lock: &'a SpinLock<T>,
+        // Dropped after the lock is released
+        _irq: Option<IrqGuard>,
+    }
+
+    impl<T> Deref for SpinLockGuard<'_, T> {
+        type Target = T;
+
+        fn deref(&self) -> &T {
+            unsafe { &*self.lock.value.get() }
+        }
+    }
+
+    impl<T> DerefMut for SpinLockGuard<'_, T> {
+        fn deref_mut(&mut self) -> &mut T {
+            unsafe { &mut *self.lock.value.get() }
+        }
+    }
+
+    impl<T> Drop for SpinLockGuard<'_, T> {
+        fn drop(&mut self) {
+            self.lock.locked.store(false, Ordering::Release);
+            lockdep::release(self.lock.id());
+        }
+    }
+
+    // A sleeping lock, for process context only. A panic while holding
+    // one does not poison it, as the kernel carries on regardless.
+    pub struct Mutex<T> {
+        class: &'static str,
+        inner: std::sync::Mutex<T>,
+    }
+
+    impl<T> Mutex<T> {
+        pub const fn new(class: &'static str, value: T) -> Self {
+            Mutex { class, inner: std::sync::Mutex::new(value) }
+        }
+
+        pub fn class(&self) -> &'static str {
+            self.class
+        }
+
+        fn id(&self) -> usize {
+            self as *const Self as usize
+        }
+
+        pub fn lock(&self) -> MutexGuard<'_, T> {
+            lockdep::acquire(self.class, self.id(), LockKind::Sleeping);
+            let guard = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+            MutexGuard { guard, id: self.id() }
+        }
+
+        pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+            let guard = match self.inner.try_lock() {
+                Ok(guard) => guard,
+                Err(std::sync::TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+                Err(std::sync::TryLockError::WouldBlock) => return None,
+            };
+            lockdep::acquire_try(self.class, self.id(), LockKind::Sleeping);
+            Some(MutexGuard { guard, id: self.id() })
+        }
+
+        pub fn get_mut(&mut self) -> &mut T {
+            self.inner.get_mut().unwrap_or_else(PoisonError::into_inner)
+        }
+
+        pub fn into_inner(self) -> T {
+            self.inner.into_inner().unwrap_or_else(PoisonError::into_inner)
+        }
+    }
+
+    pub struct MutexGuard<'a, T> {
+        guard: std::sync::MutexGuard<'a, T>,
+        id: usize,
+    }
+
+    impl<T> Deref for MutexGuard<'_, T> {
+        type Target = T;
+
+        fn deref(&self) -> &T {
+            &self.guard
+        }
+    }
+
+    impl<T> DerefMut for MutexGuard<'_, T> {
+        fn deref_mut(&mut self) -> &mut T {
+            &mut self.guard
+        }
+    }
+
+    impl<T> Drop for MutexGuard<'_, T> {
+        fn drop(&mut self) {
+            lockdep::release(self.id);
+        }
+    }
+}
@@ -0,0 +1,213 @@
+// src/kernel/topology.rs
+
+pub mod topology {
+    use crate::drivers::msr::msr::MsrIo;
+    use std::collections::{BTreeMap, BTreeSet};
+
+    const CPUID_MAX_LEAF: u32 = 0;
+    const CPUID_FEATURES: u32 = 1;
+    const CPUID_CACHE_LEAF: u32 = 4;
+    const CPUID_EXTENDED_TOPOLOGY: u32 = 0x0B;
+    const CPUID_FREQUENCY_LEAF: u32 = 0x16;
+    const CPUID_HYBRID_LEAF: u32 = 0x1A;
+    // Adds module, tile and die levels to 0x0B
+    const CPUID_V2_TOPOLOGY: u32 = 0x1F;
+
+    const CORE_TYPE_ATOM: u32 = 0x20;
+
+    // Level types in ECX[15:8] of the topology leaves
+    const LEVEL_INVALID: u32 = 0;
+    const LEVEL_SMT: u32 = 1;
+    // No CPU has more than a handful of levels
+    const MAX_LEVELS: u32 = 8;
+    const MAX_CACHES: u32 = 16;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub enum CoreType {
+        // P-cores, or every core of a part that is not hybrid
+        Performance,
+        // E-cores
+        Efficiency,
+    }
+
+    // Hybrid core type from CPUID leaf 0x1A, read on the CPU itself
+    pub fn core_type<M: MsrIo + ?Sized>(msr: &M, cpu: usize) -> CoreType {
+        let (eax, _, _, _) = msr.cpuid(cpu, CPUID_HYBRID_LEAF);
+        match eax >> 24 {
+            CORE_TYPE_ATOM => CoreType::Efficiency,
+            _ => CoreType::Performance,
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct LogicalCpu {
+        pub cpu: usize,
+        pub apic_id: u32,
+        pub package: u32,
+        // Unique across packages
+        pub core: u32,
+        // Which hardware thread of its core
+        pub thread: u32,
+        pub core_type: CoreType,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub enum CacheKind {
+        Data,
+        Instruction,
+        Unified,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Cache {
+        pub level: u8,
+        pub kind: CacheKind,
+        // Bytes
+        pub size: u64,
+        pub line_size: u32,
+        // The logical CPUs sharing this one instance
+        pub cpus: Vec<usize>,
+    }
+
+    // How the CPUs are laid out, from CPUID on each of them rather than
+    // assumed, so that the scheduler and the power policy fit any x86_64
+    // machine
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Topology {
+        pub cpus: Vec<LogicalCpu>,
+        pub caches: Vec<Cache>,
+        // From leaf 0x16, where the CPU has it
+        pub base_frequency_mhz: Option<u32>,
+        pub max_frequency_mhz: Option<u32>,
+    }
+
+    // Bits an APIC ID field needs for count IDs
+    fn id_bits(count: u32) -> u32 {
+        count.max(1).next_power_of_two().trailing_zeros()
+    }
+
+    // A CPU's x2APIC ID and the shifts to its core and package IDs
+    fn apic_layout<M: MsrIo + ?Sized>(msr: &M, cpu: usize, max_leaf: u32) -> (u32, u32, u32) {
+        for leaf in [CPUID_V2_TOPOLOGY, CPUID_EXTENDED_TOPOLOGY] {
+            if leaf > max_leaf || msr.cpuid_count(cpu, leaf, 0).1 == 0 {
+                continue;
+            }
+            let (mut apic_id, mut smt_shift, mut package_shift) = (0, 0, 0);
+            for subleaf in 0..MAX_LEVELS {
+                let (eax, _, ecx, edx) = msr.cpuid_count(cpu, leaf, subleaf);
+                let level = (ecx >> 8) & 0xFF;
+                if level == LEVEL_INVALID {
+                    break;
+                }
+                apic_id = edx;
+                // Each level's shift takes in those below it, so the last
+                // is the package's
+                package_shift = eax & 0x1F;
+                if level == LEVEL_SMT {
+                    smt_shift = package_shift;
+                }
+            }
+            return (apic_id, smt_shift, package_shift);
+        }
+        // Before either leaf: the initial APIC ID, and every CPU a core of
+        // the one package
+        let (_, ebx, _, _) = msr.cpuid(cpu, CPUID_FEATURES);
+        (ebx >> 24, 0, 8)
+    }
+
+    impl Topology {
+        pub fn discover<M: MsrIo + ?Sized>(msr: &M) -> Self {
+            let (max_leaf, _, _, _) = msr.cpuid(0, CPUID_MAX_LEAF);
+            let hybrid = max_leaf >= CPUID_HYBRID_LEAF;
+            let mut cpus = Vec::new();
+            let mut caches: BTreeMap<(u8, CacheKind, u32, u64), Cache> = BTreeMap::new();
+            for cpu in 0..msr.cpu_count() {
+                let (apic_id, smt_shift, package_shift) = apic_layout(msr, cpu, max_leaf);
+                cpus.push(LogicalCpu {
+                    cpu,
+                    apic_id,
+                    package: apic_id >> package_shift,
+                    core: apic_id >> smt_shift,
+                    thread: apic_id & ((1 << smt_shift) - 1),
+                    core_type: if hybrid { core_type(msr, cpu) } else { CoreType::Performance },
+                });
+                if max_leaf < CPUID_CACHE_LEAF {
+                    continue;
+                }
+                for subleaf in 0..MAX_CACHES {
+                    let (eax, ebx, ecx, _) = msr.cpuid_count(cpu, CPUID_CACHE_LEAF, subleaf);
+                    let kind = match eax & 0x1F {
+                        1 => CacheKind::Data,
+                        2 => CacheKind::Instruction,
+                        3 => CacheKind::Unified,
+                        _ => break,
+                    };
+                    let level = ((eax >> 5) & 0x7) as u8;
+                    let line_size = (ebx & 0xFFF) + 1;
+                    let size = ((ebx >> 22) + 1) as u64 * (((ebx >> 12) & 0x3FF) + 1) as u64 * line_size as u64 * (ecx as u64 + 1);
+                    // CPUs whose APIC IDs only differ below the sharing
+                    // field have the same instance
+                    let group = apic_id >> id_bits(((eax >> 14) & 0xFFF) + 1);
+                    caches.entry((level, kind, group, size)).or_insert_with(|| Cache { level, kind, size, line_size, cpus: Vec::new() }).cpus.push(cpu);
+                }
+            }
+            let (base, max, _, _) = if max_leaf >= CPUID_FREQUENCY_LEAF { msr.cpuid(0, CPUID_FREQUENCY_LEAF) } else { (0, 0, 0, 0) };
+            let topology = Topology {
+                cpus,
+                caches: caches.into_values().collect(),
+                base_frequency_mhz: (base & 0xFFFF != 0).then_some(base & 0xFFFF),
+                max_frequency_mhz: (max & 0xFFFF != 0).then_some(max & 0xFFFF),
+            };
+            log::info!("CPU topology: {}", topology.summary());
+            topology
+        }
+
+        pub fn packages(&self) -> usize {
+            self.cpus.iter().map(|cpu| cpu.package).collect::<BTreeSet<_>>().len()
+        }
+
+        pub fn cores(&self) -> usize {
+            self.cpus.iter().map(|cpu| cpu.core).collect::<BTreeSet<_>>().len()
+        }
+
+        pub fn is_hybrid(&self) -> bool {
+            self.cpus.iter().any(|cpu| cpu.core_type == CoreType::Efficiency) && self.cpus.iter().any(|cpu| cpu.core_type == CoreType::Performance)
+        }
+
+        pub fn cpus_of_type(&self, core_type: CoreType) -> Vec<usize> {
+            self.cpus.iter().filter(|cpu| cpu.core_type == core_type).map(|cpu| cpu.cpu).collect()
+        }
+
+        // Hardware threads of the same core, itself included
+        pub fn siblings(&self, cpu: usize) -> Vec<usize> {
+            let Some(core) = self.cpus.get(cpu).map(|cpu| cpu.core) else {
+                return Vec::new();
+            };
+            self.cpus.iter().filter(|other| other.core == core).map(|other| other.cpu).collect()
+        }
+
+        // The data or unified cache of a level a CPU uses
+        pub fn cache(&self, cpu: usize, level: u8) -> Option<&Cache> {
+            self.caches.iter().find(|cache| cache.level == level && cache.kind != CacheKind::Instruction && cache.cpus.contains(&cpu))
+        }
+
+        // CPUs in the order to place work on them: P-cores before E-cores,
+        // and a thread on each core before doubling up on siblings
+        pub fn preferred_order(&self) -> Vec<usize> {
+            let mut cpus: Vec<&LogicalCpu> = self.cpus.iter().collect();
+            cpus.sort_by_key(|cpu| (cpu.core_type, cpu.thread, cpu.package, cpu.core));
+            cpus.into_iter().map(|cpu| cpu.cpu).collect()
+        }
+
+        // e.g. "2 P-cores and 8 E-cores, 12 threads in 1 package"
+        pub fn summary(&self) -> String {
+            let cores = |core_type: CoreType| self.cpus.iter().filter(|cpu| cpu.core_type == core_type).map(|cpu| cpu.core).collect::<BTreeSet<_>>().len();
+            let cores = match self.is_hybrid() {
+                true => format!("{} P-cores and {} E-cores", cores(CoreType::Performance), cores(CoreType::Efficiency)),
+                false => format!("{} cores", self.cores()),
+            };
+            let packages = self.packages();
+            format!("{}, {} threads in {} package{}", cores, self.cpus.len(), packages, if packages == 1 { "" } else { "s" })
+        }
+    }
+}
@@ -0,0 +1,420 @@
+// src/kernel/users.rs
+
+pub mod users {
+    use crate::vxchan::vxchan::VXChanManager;
+    use crate::vxfs::vxfs::VXFS;
+    use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+    use argon2::Argon2;
+    use std::collections::BTreeMap;
+    use std::fs::{self, Metadata, Permissions};
+    use std::io::ErrorKind;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    use std::path::Path;
+
+    pub type Uid = u32;
+    pub type Gid = u32;
+
+    pub const ROOT_UID: Uid = 0;
+    pub const ROOT_GID: Gid = 0;
+    // Accounts people log in to are numbered from here; those below are
+    // for the system
+    pub const FIRST_USER_ID: u32 = 1000;
+
+    // Under the root filesystem
+    pub const PASSWD_PATH: &str = "etc/passwd";
+    pub const GROUP_PATH: &str = "etc/group";
+    // Password hashes, readable by root alone
+    pub const SHADOW_PATH: &str = "etc/shadow";
+
+    pub const REQUEST_CHANNEL: &str = "vxauth";
+    pub const REPLY_CHANNEL: &str = "vxauth.reply";
+
+    // Permission bits asked for, as in the mode's "other" triplet
+    pub const ACCESS_READ: u32 = 4;
+    pub const ACCESS_WRITE: u32 = 2;
+    pub const ACCESS_EXECUTE: u32 = 1;
+
+    const SALT_LENGTH: usize = 16;
+
+    // Who a process acts as
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Credentials {
+        pub uid: Uid,
+        pub gid: Gid,
+        // Supplementary groups
+        pub groups: Vec<Gid>,
+    }
+
+    impl Credentials {
+        pub fn root() -> Self {
+            Credentials {
+                uid: ROOT_UID,
+                gid: ROOT_GID,
+                groups: Vec::new(),
+            }
+        }
+
+        pub fn is_root(&self) -> bool {
+            self.uid == ROOT_UID
+        }
+
+        pub fn in_group(&self, gid: Gid) -> bool {
+            self.gid == gid || self.groups.contains(&gid)
+        }
+
+        // Checks the owner's, the group's or everyone else's bits, the
+        // first that apply. Root may do anything but execute files nobody
+        // can.
+        pub fn may_access(&self, metadata: &Metadata, access: u32) -> bool {
+            let mode = metadata.mode();
+            if self.is_root() {
+                return access & ACCESS_EXECUTE == 0 || metadata.is_dir() || mode & 0o111 != 0;
+            }
+            let bits = if metadata.uid() == self.uid {
+                mode >> 6
+            } else if self.in_group(metadata.gid()) {
+                mode >> 3
+            } else {
+                mode
+            };
+            bits & access == access
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct User {
+        pub name: String,
+        pub uid: Uid,
+        // Primary group
+        pub gid: Gid,
+        pub full_name: String,
+        pub home: String,
+        pub shell: String,
+    }
+
+    impl User {
+        // As a line of /etc/passwd: name:x:uid:gid:full name:home:shell
+        pub fn entry(&self) -> String {
+            format!("{}:x:{}:{}:{}:{}:{}", self.name, self.uid, self.gid, self.full_name, self.home, self.shell)
+        }
+
+        pub fn parse(line: &str) -> Result<Self, &'static str> {
+            let fields: Vec<&str> = line.split(':').collect();
+            let [name, _, uid, gid, full_name, home, shell] = fields.as_slice() else {
+                return Err("Malformed passwd line");
+            };
+            Ok(User {
+                name: name.to_string(),
+                uid: uid.parse().map_err(|_| "Malformed passwd line")?,
+                gid: gid.parse().map_err(|_| "Malformed passwd line")?,
+                full_name: full_name.to_string(),
+                home: home.to_string(),
+                shell: shell.to_string(),
+            })
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Group {
+        pub name: String,
+        pub gid: Gid,
+        // Users with it as a supplementary group
+        pub members: Vec<String>,
+    }
+
+    impl Group {
+        // As a line of /etc/group: name:x:gid:member,member
+        pub fn entry(&self) -> String {
+            format!("{}:x:{}:{}", self.name, self.gid, self.members.join(","))
+        }
+
+        pub fn parse(line: &str) -> Result<Self, &'static str> {
+            let fields: Vec<&str> = line.split(':').collect();
+            let [name, _, gid, members] = fields.as_slice() else {
+                return Err("Malformed group line");
+            };
+            Ok(Group {
+                name: name.to_string(),
+                gid: gid.parse().map_err(|_| "Malformed group line")?,
+                members: members.split(',').filter(|member| !member.is_empty()).map(String::from).collect(),
+            })
+        }
+    }
+
+    fn valid_name(name: &str) -> bool {
+        !name.is_empty() && name.len() <= 32 && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    }
+
+    fn hash_password(password: &str) -> Result<String, &'static str> {
+        let mut salt = [0u8; SALT_LENGTH];
+        getrandom::getrandom(&mut salt).map_err(|_| "System entropy source unavailable")?;
+        let salt = SaltString::encode_b64(&salt).map_err(|_| "Failed to hash password")?;
+        let hash = Argon2::default().hash_password(password.as_bytes(), &salt).map_err(|_| "Failed to hash password")?;
+        Ok(hash.to_string())
+    }
+
+    // Users and groups, with a salted Argon2 hash of each password, kept
+    // in passwd, group and shadow files on the root filesystem
+    #[derive(Default)]
+    pub struct UserDatabase {
+        users: BTreeMap<Uid, User>,
+        groups: BTreeMap<Gid, Group>,
+        // By user name; accounts without one cannot log in
+        hashes: BTreeMap<String, String>,
+    }
+
+    impl UserDatabase {
+        // Only root, with no password
+        pub fn new() -> Self {
+            let mut database = UserDatabase::default();
+            database.users.insert(
+                ROOT_UID,
+                User {
+                    name: "root".to_string(),
+                    uid: ROOT_UID,
+                    gid: ROOT_GID,
+                    full_name: String::new(),
+                    home: "/root".to_string(),
+                    shell: "/bin/vxsh".to_string(),
+                },
+            );
+            database.groups.insert(
+                ROOT_GID,
+                Group {
+                    name: "root".to_string(),
+                    gid: ROOT_GID,
+                    members: Vec::new(),
+                },
+            );
+            database
+        }
+
+        // From a root filesystem on the host, or a fresh database if it
+        // has none yet
+        pub fn load(root: &str) -> Result<Self, &'static str> {
+            let mut fs = VXFS::new();
+            let mut read = |path: &str| match fs.read_file(&Path::new(root).join(path).to_string_lossy()) {
+                Ok(contents) => Ok(Some(contents)),
+                Err(error) if error.kind() == ErrorKind::NotFound => Ok(None),
+                Err(_) => Err("Failed to read user database"),
+            };
+            let Some(passwd) = read(PASSWD_PATH)? else {
+                return Ok(UserDatabase::new());
+            };
+            let mut database = UserDatabase::default();
+            for line in passwd.lines().filter(|line| !line.trim().is_empty()) {
+                let user = User::parse(line)?;
+                database.users.insert(user.uid, user);
+            }
+            for line in read(GROUP_PATH)?.unwrap_or_default().lines().filter(|line| !line.trim().is_empty()) {
+                let group = Group::parse(line)?;
+                database.groups.insert(group.gid, group);
+            }
+            for line in read(SHADOW_PATH)?.unwrap_or_default().lines().filter(|line| !line.trim().is_empty()) {
+                let (name, hash) = line.split_once(':').ok_or("Malformed shadow line")?;
+                database.hashes.insert(name.to_string(), hash.to_string());
+            }
+            Ok(database)
+        }
+
+        pub fn save(&self, root: &str) -> Result<(), &'static str> {
+            let root = Path::new(root);
+            fs::create_dir_all(root.join("etc")).map_err(|_| "Failed to write user database")?;
+            let lines = |entries: Vec<String>| entries.iter().map(|entry| format!("{}\n", entry)).collect::<String>();
+            let shadow = root.join(SHADOW_PATH);
+            let mut fs = VXFS::new();
+            let mut write = |path: &Path, contents: String| fs.write_file(&path.to_string_lossy(), &contents).map_err(|_| "Failed to write user database");
+            write(&root.join(PASSWD_PATH), lines(self.users.values().map(User::entry).collect()))?;
+            write(&root.join(GROUP_PATH), lines(self.groups.values().map(Group::entry).collect()))?;
+            write(&shadow, lines(self.hashes.iter().map(|(name, hash)| format!("{}:{}", name, hash)).collect()))?;
+            fs::set_permissions(&shadow, Permissions::from_mode(0o600)).map_err(|_| "Failed to write user database")
+        }
+
+        pub fn users(&self) -> Vec<&User> {
+            self.users.values().collect()
+        }
+
+        pub fn user(&self, name: &str) -> Option<&User> {
+            self.users.values().find(|user| user.name == name)
+        }
+
+        pub fn user_by_uid(&self, uid: Uid) -> Option<&User> {
+            self.users.get(&uid)
+        }
+
+        pub fn groups(&self) -> Vec<&Group> {
+            self.groups.values().collect()
+        }
+
+        pub fn group(&self, name: &str) -> Option<&Group> {
+            self.groups.values().find(|group| group.name == name)
+        }
+
+        pub fn group_by_gid(&self, gid: Gid) -> Option<&Group> {
+            self.groups.get(&gid)
+        }
+
+        // A group of its own, numbered like users
+        pub fn add_group(&mut self, name: &str) -> Result<Gid, &'static str> {
+            if !valid_name(name) {
+                return Err("Invalid name");
+            }
+            if self.group(name).is_some() {
+                return Err("Group already exists");
+            }
+            let gid = (FIRST_USER_ID..).find(|gid| !self.groups.contains_key(gid)).unwrap();
+            self.groups.insert(
+                gid,
+                Group {
+                    name: name.to_string(),
+                    gid,
+                    members: Vec::new(),
+                },
+            );
+            Ok(gid)
+        }
+
+        // An account with a primary group of the same name and no password
+        // yet
+        pub fn add_user(&mut self, name: &str, full_name: &str, home: &str, shell: &str) -> Result<Uid, &'static str> {
+            if !valid_name(name) || full_name.contains([':', '\n']) || home.contains([':', '\n']) || shell.contains([':', '\n']) {
+                return Err("Invalid name");
+            }
+            if self.user(name).is_some() {
+                return Err("User already exists");
+            }
+            if self.group(name).is_some() {
+                return Err("Group already exists");
+            }
+            let uid = (FIRST_USER_ID..).find(|uid| !self.users.contains_key(uid) && !self.groups.contains_key(uid)).unwrap();
+            self.groups.insert(
+                uid,
+                Group {
+                    name: name.to_string(),
+                    gid: uid,
+                    members: Vec::new(),
+                },
+            );
+            self.users.insert(
+                uid,
+                User {
+                    name: name.to_string(),
+                    uid,
+                    gid: uid,
+                    full_name: full_name.to_string(),
+                    home: home.to_string(),
+                    shell: shell.to_string(),
+                },
+            );
+            log::info!("Added user {} as {}", name, uid);
+            Ok(uid)
+        }
+
+        pub fn add_member(&mut self, group: &str, user: &str) -> Result<(), &'static str> {
+            if self.user(user).is_none() {
+                return Err("User not found");
+            }
+            let group = self.groups.values_mut().find(|entry| entry.name == group).ok_or("Group not found")?;
+            if !group.members.iter().any(|member| member == user) {
+                group.members.push(user.to_string());
+            }
+            Ok(())
+        }
+
+        pub fn set_password(&mut self, name: &str, password: &str) -> Result<(), &'static str> {
+            if self.user(name).is_none() {
+                return Err("User not found");
+            }
+            self.hashes.insert(name.to_string(), hash_password(password)?);
+            Ok(())
+        }
+
+        // Until a password is set again
+        pub fn lock(&mut self, name: &str) -> Result<(), &'static str> {
+            self.user(name).ok_or("User not found")?;
+            self.hashes.remove(name);
+            Ok(())
+        }
+
+        // The same error whether or not the user exists, after as long
+        pub fn authenticate(&self, name: &str, password: &str) -> Result<&User, &'static str> {
+            let stored = self.user(name).and(self.hashes.get(name)).and_then(|hash| PasswordHash::new(hash).ok());
+            let Some(stored) = stored else {
+                let _ = hash_password(password);
+                return Err("Authentication failed");
+            };
+            Argon2::default().verify_password(password.as_bytes(), &stored).map_err(|_| "Authentication failed")?;
+            Ok(self.user(name).unwrap())
+        }
+
+        // What a process the user logs in to runs with
+        pub fn credentials(&self, name: &str) -> Result<Credentials, &'static str> {
+            let user = self.user(name).ok_or("User not found")?;
+            let groups = self.groups.values().filter(|group| group.gid != user.gid && group.members.contains(&user.name)).map(|group| group.gid).collect();
+            Ok(Credentials {
+                uid: user.uid,
+                gid: user.gid,
+                groups,
+            })
+        }
+
+        fn execute(&self, line: &str) -> Result<String, &'static str> {
+            let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+            match command {
+                // The password is the rest of the line, spaces and all
+                "authenticate" => {
+                    let (name, password) = rest.split_once(' ').ok_or("Missing password")?;
+                    self.authenticate(name, password).map(User::entry)
+                }
+                "users" if rest.is_empty() => {
+                    Ok(self.users.values().filter(|user| user.uid >= FIRST_USER_ID).map(User::entry).collect::<Vec<_>>().join("\n"))
+                }
+                "user" if !rest.is_empty() => self.user(rest).map(User::entry).ok_or("User not found"),
+                "groups" if !rest.is_empty() => {
+                    let credentials = self.credentials(rest)?;
+                    let gids = std::iter::once(credentials.gid).chain(credentials.groups);
+                    Ok(gids.filter_map(|gid| self.groups.get(&gid)).map(|group| group.name.as_str()).collect::<Vec<_>>().join(" "))
+                }
+                "" => Err("Empty request"),
+                _ => Err("Unknown command"),
+            }
+        }
+    }
+
+    // Answers the greeter and lock screen. Grammar, one request per
+    // message:
+    //   authenticate USER PASSWORD | users | user USER | groups USER
+    // Users come back as passwd lines, those people log in to for users,
+    // and groups as names with the primary one first. Messages and replies
+    // are framed as in vxnetctl.
+    pub struct AuthService;
+
+    impl AuthService {
+        pub fn new(manager: &VXChanManager) -> Result<Self, &'static str> {
+            manager.create_channel(REQUEST_CHANNEL)?;
+            manager.create_channel(REPLY_CHANNEL)?;
+            Ok(AuthService)
+        }
+
+        // Answers every queued request; returns how many were handled
+        pub fn poll(&self, manager: &VXChanManager, database: &UserDatabase) -> Result<usize, &'static str> {
+            let mut count = 0;
+            while let Some(message) = manager.try_receive_message(REQUEST_CHANNEL)? {
+                let (id, line) = message.split_once(' ').unwrap_or((message.as_str(), ""));
+                let result = id.parse::<u64>().map_err(|_| "Invalid request id").and_then(|_| database.execute(line));
+                let reply = match result {
+                    Ok(body) if body.is_empty() => format!("{} ok", id),
+                    Ok(body) => format!("{} ok\n{}", id, body),
+                    Err(error) => format!("{} error {}", id, error),
+                };
+                manager.send_message(REPLY_CHANNEL, reply)?;
+                count += 1;
+            }
+            Ok(count)
+        }
+    }
+
+    pub fn send_request(manager: &VXChanManager, id: u64, request: &str) -> Result<(), &'static str> {
+        manager.send_message(REQUEST_CHANNEL, format!("{} {}", id, request))
+    }
+}
@@ -1,3 +1,5 @@
+use crate::faultinject::faultinject::FAIL_ALLOC;
+use crate::hardening::hardening;
 use core::alloc::{GlobalAlloc, Layout};
 use std::alloc::System;
 
@@ -5,7 +7,11 @@ struct VaelixAllocator;
 
 unsafe impl GlobalAlloc for VaelixAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        // Implement memory allocation with failure detection
+        // Implement memory allocation with failure detection. An injected
+        // failure looks like running out, for fallible callers to handle.
+        if FAIL_ALLOC.should_fail() {
+            return std::ptr::null_mut();
+        }
         let ptr = System.alloc(layout);
         if ptr.is_null() {
             panic!("Memory allocation failed");
@@ -15,6 +21,9 @@ unsafe impl GlobalAlloc for VaelixAllocator {
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         // Implement memory deallocation
+        if hardening::ENABLED {
+            std::ptr::write_bytes(ptr, hardening::POISON_FREE, layout.size());
+        }
         System.dealloc(ptr, layout);
     }
 }
@@ -0,0 +1,159 @@
+// src/kernel/vt.rs
+
+pub mod vt {
+    use crate::input::input::{function_key, InputEvent, KEY_LEFTALT, KEY_LEFTCTRL, KEY_RIGHTALT, KEY_RIGHTCTRL};
+    use crate::pty::pty::{self, PtyMaster, PtySize, PtySlave};
+    use std::sync::mpsc::{self, Receiver, Sender};
+
+    pub const TEXT_CONSOLE_SIZE: PtySize = PtySize { rows: 25, cols: 80 };
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum VtKind {
+        // A console terminal on a PTY, for a getty or shell
+        Text,
+        // Owned by the compositor, which stops scanning out while away
+        Graphical,
+    }
+
+    // Sent to subscribers on every switch, numbered from 1 as in ttyN
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct VtSwitch {
+        pub from: u8,
+        pub to: u8,
+    }
+
+    struct Console {
+        kind: VtKind,
+        // Until the console renderer takes it
+        master: Option<PtyMaster>,
+        slave: Option<PtySlave>,
+    }
+
+    // Multiplexes the screen and keyboard between virtual consoles.
+    // Ctrl-Alt-Fn switches to console N from anywhere; on a text console
+    // Alt-Fn is enough, as on Linux.
+    pub struct VtManager {
+        consoles: Vec<Console>,
+        active: u8,
+        ctrl: u8,
+        alt: u8,
+        // Releases of keys whose press switched consoles
+        swallowed: Vec<u32>,
+        subscribers: Vec<Sender<VtSwitch>>,
+    }
+
+    impl VtManager {
+        // Consoles 1..=count; `graphical` is the session's console and
+        // starts out active
+        pub fn new(count: u8, graphical: u8) -> Result<Self, &'static str> {
+            if !(1..=count).contains(&graphical) {
+                return Err("Graphical console out of range");
+            }
+            let consoles = (1..=count)
+                .map(|vt| {
+                    if vt == graphical {
+                        return Console {
+                            kind: VtKind::Graphical,
+                            master: None,
+                            slave: None,
+                        };
+                    }
+                    let (master, slave) = pty::open(TEXT_CONSOLE_SIZE);
+                    Console {
+                        kind: VtKind::Text,
+                        master: Some(master),
+                        slave: Some(slave),
+                    }
+                })
+                .collect();
+            Ok(VtManager {
+                consoles,
+                active: graphical,
+                ctrl: 0,
+                alt: 0,
+                swallowed: Vec::new(),
+                subscribers: Vec::new(),
+            })
+        }
+
+        pub fn count(&self) -> u8 {
+            self.consoles.len() as u8
+        }
+
+        pub fn active(&self) -> u8 {
+            self.active
+        }
+
+        fn console(&self, vt: u8) -> Result<&Console, &'static str> {
+            vt.checked_sub(1)
+                .and_then(|index| self.consoles.get(index as usize))
+                .ok_or("No such console")
+        }
+
+        pub fn kind(&self, vt: u8) -> Option<VtKind> {
+            self.console(vt).ok().map(|console| console.kind)
+        }
+
+        // For the terminal that draws the console; taken once
+        pub fn take_master(&mut self, vt: u8) -> Option<PtyMaster> {
+            self.console(vt).ok()?;
+            self.consoles[vt as usize - 1].master.take()
+        }
+
+        // For the getty or shell running on the console
+        pub fn slave(&self, vt: u8) -> Option<PtySlave> {
+            self.console(vt).ok()?.slave.clone()
+        }
+
+        pub fn subscribe(&mut self) -> Receiver<VtSwitch> {
+            let (sender, receiver) = mpsc::channel();
+            self.subscribers.push(sender);
+            receiver
+        }
+
+        pub fn switch_to(&mut self, vt: u8) -> Result<(), &'static str> {
+            self.console(vt)?;
+            if vt == self.active {
+                return Ok(());
+            }
+            let switch = VtSwitch { from: self.active, to: vt };
+            log::info!("Switching from console {} to {}", switch.from, switch.to);
+            self.active = vt;
+            self.subscribers.retain(|subscriber| subscriber.send(switch).is_ok());
+            Ok(())
+        }
+
+        // Sees every input event first. Switch combinations are taken out;
+        // everything else is for whoever owns the active console.
+        pub fn handle_input(&mut self, event: InputEvent) -> Option<InputEvent> {
+            let InputEvent::Key { code, pressed } = event else {
+                return Some(event);
+            };
+            let count = |held: &mut u8| {
+                *held = if pressed { held.saturating_add(1) } else { held.saturating_sub(1) };
+            };
+            match code {
+                KEY_LEFTCTRL | KEY_RIGHTCTRL => count(&mut self.ctrl),
+                KEY_LEFTALT | KEY_RIGHTALT => count(&mut self.alt),
+                _ => {}
+            }
+            if !pressed {
+                if let Some(index) = self.swallowed.iter().position(|swallowed| *swallowed == code) {
+                    self.swallowed.remove(index);
+                    return None;
+                }
+                return Some(event);
+            }
+            let on_text = self.kind(self.active) == Some(VtKind::Text);
+            let chord = self.alt > 0 && (self.ctrl > 0 || on_text);
+            match function_key(code).filter(|_| chord) {
+                Some(vt) if vt <= self.count() => {
+                    self.switch_to(vt).unwrap();
+                    self.swallowed.push(code);
+                    None
+                }
+                _ => Some(event),
+            }
+        }
+    }
+}
@@ -11,14 +11,19 @@ pub struct Tasklet {
     task: Box<dyn FnOnce() + Send + 'static>,
 }
 
+type IdleHandler = Box<dyn FnMut() + Send + 'static>;
+
 pub struct TaskletScheduler {
     task_queue: Arc<Mutex<VecDeque<Tasklet>>>,
+    // Runs whenever the queue is empty, e.g. the C-state governor
+    idle: Arc<Mutex<Option<IdleHandler>>>,
 }
 
 impl Clone for TaskletScheduler {
     fn clone(&self) -> Self {
         TaskletScheduler {
             task_queue: Arc::clone(&self.task_queue),
+            idle: Arc::clone(&self.idle),
         }
     }
 }
@@ -27,9 +32,16 @@ impl TaskletScheduler {
     pub fn new() -> Self {
         TaskletScheduler {
             task_queue: Arc::new(Mutex::new(VecDeque::new())),
+            idle: Arc::new(Mutex::new(None)),
         }
     }
 
+    // The handler should return on the next interrupt or timer event so
+    // newly queued tasklets are picked up
+    pub fn set_idle_handler(&self, handler: IdleHandler) {
+        *self.idle.lock().unwrap() = Some(handler);
+    }
+
     pub fn add_task(&self, task: Box<dyn FnOnce() + Send + 'static>, priority: usize) {
         let mut queue = self.task_queue.lock().unwrap();
         let tasklet = Tasklet {
@@ -48,7 +60,11 @@ impl TaskletScheduler {
                 drop(queue);
                 (tasklet.task)();
             } else {
-                thread::sleep(Duration::from_millis(10));
+                drop(queue);
+                match self.idle.lock().unwrap().as_mut() {
+                    Some(handler) => handler(),
+                    None => thread::sleep(Duration::from_millis(10)),
+                }
             }
         }
     }
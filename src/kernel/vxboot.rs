@@ -1,34 +1,430 @@
 // src/kernel/vxboot.rs
 
 pub mod vxboot {
+    use crate::drivers::mmio::mmio::MmioRegion;
+    use crate::drivers::tpm::tpm::{extend_digest, TisTransport, Tpm2, TpmTransport, SHA256_SIZE};
+    use crate::kallsyms::kallsyms::{self, SymbolTable};
+    use crate::power::hibernate::hibernate::{self, MemoryImage, SwapArea};
+    use std::fs;
     use std::io;
+    use std::path::Path;
+
+    // PCR assignment follows the common boot loader convention
+    pub const KERNEL_PCR: u32 = 8;
+    pub const INITRAMFS_PCR: u32 = 9;
+    // TIS registers for localities 0 to 4
+    pub const TPM_TIS_BASE: usize = 0xFED4_0000;
+    const TPM_TIS_SIZE: usize = 0x5000;
+
+    // The kernel is linked to run at the start of its region and is loaded
+    // somewhere in it at random, by a multiple of the alignment
+    pub const KERNEL_IMAGE_BASE: u64 = 0xFFFF_FFFF_8000_0000;
+    pub const KERNEL_REGION_SIZE: u64 = 1 << 30;
+    pub const KERNEL_ALIGN: u64 = 2 * 1024 * 1024;
+
+    // A/B slot state, under the root filesystem
+    pub const SLOT_CONTROL_PATH: &str = "boot/slots";
+    // Boots a slot gets to be marked successful before it is given up on
+    pub const MAX_BOOT_TRIES: u8 = 3;
+    pub const MAX_SLOT_PRIORITY: u8 = 15;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub enum Slot {
+        A,
+        B,
+    }
+
+    impl Slot {
+        pub fn other(self) -> Slot {
+            match self {
+                Slot::A => Slot::B,
+                Slot::B => Slot::A,
+            }
+        }
+
+        pub fn name(self) -> &'static str {
+            match self {
+                Slot::A => "a",
+                Slot::B => "b",
+            }
+        }
+
+        fn index(self) -> usize {
+            self as usize
+        }
+    }
+
+    // Priority 0 means the slot cannot be booted, e.g. while it is being
+    // written or once it has run out of tries
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct SlotState {
+        pub priority: u8,
+        pub tries_remaining: u8,
+        pub successful: bool,
+    }
+
+    impl SlotState {
+        pub fn is_bootable(&self) -> bool {
+            self.priority > 0 && (self.successful || self.tries_remaining > 0)
+        }
+    }
+
+    // Which of two system slots boots. A freshly updated slot gets a few
+    // tries at the highest priority; each boot of it uses one up until
+    // the system marks it successful, and when they run out the boot falls
+    // back to the other slot.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct BootControl {
+        slots: [SlotState; 2],
+    }
+
+    impl Default for BootControl {
+        fn default() -> Self {
+            BootControl::new()
+        }
+    }
+
+    impl BootControl {
+        // As installed: everything in A, and B empty
+        pub fn new() -> Self {
+            BootControl {
+                slots: [
+                    SlotState {
+                        priority: MAX_SLOT_PRIORITY,
+                        tries_remaining: 0,
+                        successful: true,
+                    },
+                    SlotState {
+                        priority: 0,
+                        tries_remaining: 0,
+                        successful: false,
+                    },
+                ],
+            }
+        }
+
+        // One line per slot: NAME PRIORITY TRIES SUCCESSFUL
+        pub fn parse(text: &str) -> io::Result<Self> {
+            let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Malformed slot control");
+            let mut control = BootControl::new();
+            let mut seen = [false; 2];
+            for line in text.lines().filter(|line| !line.trim().is_empty()) {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                let [name, priority, tries, successful] = fields[..] else {
+                    return Err(invalid());
+                };
+                let slot = match name {
+                    "a" => Slot::A,
+                    "b" => Slot::B,
+                    _ => return Err(invalid()),
+                };
+                control.slots[slot.index()] = SlotState {
+                    priority: priority.parse().ok().filter(|priority| *priority <= MAX_SLOT_PRIORITY).ok_or_else(invalid)?,
+                    tries_remaining: tries.parse().ok().filter(|tries| *tries <= MAX_BOOT_TRIES).ok_or_else(invalid)?,
+                    successful: match successful {
+                        "0" => false,
+                        "1" => true,
+                        _ => return Err(invalid()),
+                    },
+                };
+                seen[slot.index()] = true;
+            }
+            match seen {
+                [true, true] => Ok(control),
+                _ => Err(invalid()),
+            }
+        }
+
+        pub fn entries(&self) -> String {
+            [Slot::A, Slot::B]
+                .iter()
+                .map(|slot| {
+                    let state = self.slots[slot.index()];
+                    format!("{} {} {} {}\n", slot.name(), state.priority, state.tries_remaining, state.successful as u8)
+                })
+                .collect()
+        }
+
+        // A fresh install's when there is no file yet
+        pub fn load(root: &str) -> io::Result<Self> {
+            match fs::read_to_string(Path::new(root).join(SLOT_CONTROL_PATH)) {
+                Ok(text) => BootControl::parse(&text),
+                Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(BootControl::new()),
+                Err(error) => Err(error),
+            }
+        }
+
+        // Replaced in one rename, so a crash leaves the old state or the new
+        pub fn save(&self, root: &str) -> io::Result<()> {
+            let path = Path::new(root).join(SLOT_CONTROL_PATH);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let staging = path.with_extension("new");
+            fs::write(&staging, self.entries())?;
+            fs::rename(&staging, &path)
+        }
+
+        pub fn state(&self, slot: Slot) -> SlotState {
+            self.slots[slot.index()]
+        }
+
+        // The bootable slot with the highest priority, A on a tie
+        pub fn active(&self) -> Option<Slot> {
+            [Slot::A, Slot::B].into_iter().filter(|slot| self.state(*slot).is_bootable()).max_by_key(|slot| (self.state(*slot).priority, std::cmp::Reverse(*slot)))
+        }
+
+        // Picks the slot to boot and, until it has been marked successful,
+        // uses up one of its tries. The state has to be saved before
+        // booting it, so a boot that hangs still counts.
+        pub fn choose_boot_slot(&mut self) -> io::Result<Slot> {
+            let slot = self.active().ok_or_else(|| io::Error::other("No bootable slot"))?;
+            let state = &mut self.slots[slot.index()];
+            if !state.successful {
+                state.tries_remaining -= 1;
+                log::info!("Booting slot {}, {} tries left", slot.name(), state.tries_remaining);
+            }
+            Ok(slot)
+        }
+
+        // Once the system came up properly in it
+        pub fn mark_successful(&mut self, slot: Slot) {
+            let state = &mut self.slots[slot.index()];
+            state.successful = true;
+            state.tries_remaining = 0;
+        }
+
+        // Before a slot is written, so nothing boots a half-written one
+        pub fn mark_unbootable(&mut self, slot: Slot) {
+            self.slots[slot.index()] = SlotState {
+                priority: 0,
+                tries_remaining: 0,
+                successful: false,
+            };
+        }
+
+        // Once a new system is written to a slot: it goes ahead of the
+        // other, which stays as it is to fall back to
+        pub fn set_active(&mut self, slot: Slot) {
+            let other = self.state(slot.other());
+            if other.priority >= MAX_SLOT_PRIORITY {
+                self.slots[slot.other().index()].priority = MAX_SLOT_PRIORITY - 1;
+            }
+            self.slots[slot.index()] = SlotState {
+                priority: MAX_SLOT_PRIORITY,
+                tries_remaining: MAX_BOOT_TRIES,
+                successful: false,
+            };
+        }
+    }
+
+    // What the bootloader passes the kernel: words separated by spaces,
+    // each a flag or key=value, with double quotes around values that have
+    // spaces in them. Later settings win over earlier ones.
+    #[derive(Debug, Clone, Default, PartialEq, Eq)]
+    pub struct CommandLine {
+        parameters: Vec<(String, Option<String>)>,
+    }
+
+    impl CommandLine {
+        pub fn parse(text: &str) -> io::Result<Self> {
+            let mut parameters = Vec::new();
+            let (mut word, mut quoted, mut in_word) = (String::new(), false, false);
+            for c in text.chars().chain(std::iter::once(' ')) {
+                match c {
+                    '"' => {
+                        quoted = !quoted;
+                        in_word = true;
+                    }
+                    c if c.is_whitespace() && !quoted => {
+                        if in_word {
+                            let parameter = match word.split_once('=') {
+                                Some((key, value)) => (key.to_string(), Some(value.to_string())),
+                                None => (word.clone(), None),
+                            };
+                            parameters.push(parameter);
+                            word.clear();
+                        }
+                        in_word = false;
+                    }
+                    c => {
+                        word.push(c);
+                        in_word = true;
+                    }
+                }
+            }
+            if quoted {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "Unterminated quote on the kernel command line"));
+            }
+            Ok(CommandLine { parameters })
+        }
+
+        // The value of a key=value setting
+        pub fn get(&self, key: &str) -> Option<&str> {
+            self.parameters.iter().rev().find(|(name, _)| name == key).and_then(|(_, value)| value.as_deref())
+        }
+
+        // Whether a flag or setting is there at all
+        pub fn has(&self, key: &str) -> bool {
+            self.parameters.iter().any(|(name, _)| name == key)
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct MeasurementEvent {
+        pub pcr: u32,
+        pub description: String,
+        pub digest: [u8; SHA256_SIZE],
+    }
+
+    // Event log kept alongside the PCRs so a verifier can replay them
+    #[derive(Debug, Clone, Default)]
+    pub struct MeasurementLog {
+        events: Vec<MeasurementEvent>,
+    }
+
+    impl MeasurementLog {
+        pub fn events(&self) -> &[MeasurementEvent] {
+            &self.events
+        }
+
+        pub fn record(&mut self, pcr: u32, description: &str, digest: [u8; SHA256_SIZE]) {
+            self.events.push(MeasurementEvent {
+                pcr,
+                description: description.to_string(),
+                digest,
+            });
+        }
+
+        // Expected PCR value if the PCR started at zero and only saw logged events
+        pub fn replay(&self, pcr: u32) -> [u8; SHA256_SIZE] {
+            self.events
+                .iter()
+                .filter(|event| event.pcr == pcr)
+                .fold([0u8; SHA256_SIZE], |value, event| extend_digest(&value, &event.digest))
+        }
+    }
+
+    // How far up to load an image of that size, leaving all of it inside
+    // the region
+    pub fn kaslr_offset(image_size: u64) -> io::Result<u64> {
+        let slots = image_size
+            .checked_next_multiple_of(KERNEL_ALIGN)
+            .and_then(|size| KERNEL_REGION_SIZE.checked_sub(size))
+            .map(|room| room / KERNEL_ALIGN + 1)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Kernel image too large"))?;
+        let mut bytes = [0u8; 8];
+        getrandom::getrandom(&mut bytes).map_err(|_| io::Error::other("System entropy source unavailable"))?;
+        Ok(u64::from_le_bytes(bytes) % slots * KERNEL_ALIGN)
+    }
+
+    // Moves a kernel image to run that far up: relocations are where in it
+    // an absolute address is stored, which gets the offset added. The
+    // image is measured before this, so its digest does not change.
+    pub fn relocate_kernel(image: &mut [u8], relocations: &[u64], offset: u64) -> io::Result<()> {
+        for &relocation in relocations {
+            let field = usize::try_from(relocation)
+                .ok()
+                .and_then(|start| image.get_mut(start..start.checked_add(8)?))
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Relocation outside the kernel image"))?;
+            let address = u64::from_le_bytes(field[..].try_into().unwrap()).wrapping_add(offset);
+            field.copy_from_slice(&address.to_le_bytes());
+        }
+        Ok(())
+    }
+
+    // Installs the symbol table built into the image, once it has been
+    // relocated that far up
+    pub fn load_kernel_symbols(image: &[u8], offset: u64) -> io::Result<()> {
+        let mut table = SymbolTable::from_image(image).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        table.relocate(offset);
+        log::info!("Loaded {} kernel symbols", table.len());
+        kallsyms::install(table).map_err(io::Error::other)
+    }
 
     pub fn initialize_hardware() -> io::Result<()> {
         // Probe and initialize hardware components
-        println!("Initializing hardware...");
+        log::info!("Initializing hardware...");
         // Placeholder for actual hardware initialization logic
         Ok(())
     }
 
     pub fn load_essential_drivers() -> io::Result<()> {
         // Load essential drivers required for boot
-        println!("Loading essential drivers...");
+        log::info!("Loading essential drivers...");
         // Placeholder for actual driver loading logic
         Ok(())
     }
 
     pub fn fail_safe_recovery() -> io::Result<()> {
         // Implement fail-safe recovery mechanism
-        println!("Setting up fail-safe recovery...");
+        log::info!("Setting up fail-safe recovery...");
         // Placeholder for actual fail-safe recovery logic
         Ok(())
     }
 
+    pub fn measure_boot_components<T: TpmTransport>(
+        tpm: &Tpm2<T>,
+        kernel_image: &[u8],
+        initramfs: &[u8],
+    ) -> io::Result<MeasurementLog> {
+        log::info!("Measuring boot components into the TPM...");
+        tpm.startup().map_err(io::Error::other)?;
+        let mut log = MeasurementLog::default();
+        let digest = tpm.measure(KERNEL_PCR, kernel_image).map_err(io::Error::other)?;
+        log.record(KERNEL_PCR, "kernel", digest);
+        let digest = tpm.measure(INITRAMFS_PCR, initramfs).map_err(io::Error::other)?;
+        log.record(INITRAMFS_PCR, "initramfs", digest);
+        Ok(log)
+    }
+
+    // The TPM to measure into, on machines that have one
+    pub fn probe_tpm() -> Option<Tpm2<TisTransport<MmioRegion>>> {
+        match TisTransport::new(MmioRegion::new(TPM_TIS_BASE, TPM_TIS_SIZE)) {
+            Ok(transport) => Some(Tpm2::new(transport)),
+            Err(error) => {
+                log::warn!("Booting without measurements: {}", error);
+                None
+            }
+        }
+    }
+
+    // The kernel image as it was loaded, and the initramfs named by initrd=
+    pub fn load_boot_images(command_line: &CommandLine) -> io::Result<(Vec<u8>, Vec<u8>)> {
+        let kernel_image = fs::read(std::env::current_exe()?)?;
+        let initramfs = match command_line.get("initrd") {
+            Some(path) => fs::read(path)?,
+            None => Vec::new(),
+        };
+        Ok((kernel_image, initramfs))
+    }
+
+    pub fn measured_boot<T: TpmTransport>(
+        tpm: &Tpm2<T>,
+        kernel_image: &[u8],
+        initramfs: &[u8],
+    ) -> io::Result<MeasurementLog> {
+        // Measure before anything from the images runs
+        let log = measure_boot_components(tpm, kernel_image, initramfs)?;
+        boot()?;
+        Ok(log)
+    }
+
+    // Runs once the drivers are loaded so the swap device is reachable.
+    // Returns true when a hibernation image was restored, in which case
+    // the caller jumps back into the saved kernel instead of booting on.
+    pub fn resume_from_hibernation(area: &SwapArea, memory: &dyn MemoryImage) -> io::Result<bool> {
+        log::info!("Checking swap area for a hibernation image...");
+        let resumed = hibernate::resume(area, memory).map_err(io::Error::other)?;
+        Ok(resumed.is_some())
+    }
+
     pub fn boot() -> io::Result<()> {
+        crate::log::log::init();
         initialize_hardware()?;
         load_essential_drivers()?;
         fail_safe_recovery()?;
-        println!("Boot process completed successfully.");
+        log::info!("Boot process completed successfully.");
         Ok(())
     }
 }
@@ -22,8 +22,15 @@ pub mod vxchan {
         pub fn receive(&self) -> Result<String, &'static str> {
             self.receiver.recv().map_err(|_| "Failed to receive message")
         }
+
+        // Non-blocking receive for services polled from a main loop
+        pub fn try_receive(&self) -> Option<String> {
+            self.receiver.try_recv().ok()
+        }
     }
 
+    // Clones share the same channels
+    #[derive(Clone)]
     pub struct VXChanManager {
         channels: Arc<Mutex<HashMap<String, Arc<Mutex<VXChan>>>>>,
     }
@@ -45,6 +52,10 @@ pub mod vxchan {
             Ok(())
         }
 
+        pub fn has_channel(&self, name: &str) -> bool {
+            self.channels.lock().unwrap().contains_key(name)
+        }
+
         pub fn send_message(&self, name: &str, message: String) -> Result<(), &'static str> {
             let channels = self.channels.lock().unwrap();
             if let Some(vxchan) = channels.get(name) {
@@ -64,11 +75,21 @@ pub mod vxchan {
                 Err("Channel not found")
             }
         }
+
+        pub fn try_receive_message(&self, name: &str) -> Result<Option<String>, &'static str> {
+            let channels = self.channels.lock().unwrap();
+            if let Some(vxchan) = channels.get(name) {
+                let vxchan = vxchan.lock().unwrap();
+                Ok(vxchan.try_receive())
+            } else {
+                Err("Channel not found")
+            }
+        }
     }
 
     pub fn vxchan_init() -> Result<VXChanManager, &'static str> {
         // Initialize the VXChan module with detailed functionality
-        println!("Initializing VXChan module...");
+        log::info!("Initializing VXChan module...");
         Ok(VXChanManager::new())
     }
 }
@@ -1,13 +1,21 @@
 // src/kernel/vxfs.rs
 
 use std::fs;
-use std::io;
+use std::io::{self, Write};
 use std::collections::HashMap;
 use sha2::{Sha256, Digest};
+use crate::faultinject::faultinject::FAIL_IO;
 
 pub mod vxfs {
     use super::*;
 
+    fn injected_fault() -> io::Result<()> {
+        match FAIL_IO.should_fail() {
+            true => Err(io::Error::other("Injected I/O error")),
+            false => Ok(()),
+        }
+    }
+
     pub struct VXFS {
         journal: HashMap<String, String>,
     }
@@ -21,12 +29,13 @@ pub mod vxfs {
 
         pub fn initialize(&self) -> io::Result<()> {
             // Initialize the filesystem with journaling and integrity checking
-            println!("Initializing VXFS...");
+            log::info!("Initializing VXFS...");
             // Placeholder for actual initialization logic
             Ok(())
         }
 
 pub fn read_file(&mut self, path: &str) -> io::Result<String> {
+    injected_fault()?;
     // Read a file from the filesystem
     let contents = fs::read_to_string(path)?;
     let checksum = self.calculate_checksum(&contents);
@@ -34,7 +43,17 @@ pub fn read_file(&mut self, path: &str) -> io::Result<String> {
     Ok(contents)
 }
 
+// Binary files such as fonts and images
+pub fn read_bytes(&mut self, path: &str) -> io::Result<Vec<u8>> {
+    injected_fault()?;
+    let contents = fs::read(path)?;
+    let checksum = self.calculate_checksum(&contents);
+    self.journal.insert(path.to_string(), checksum);
+    Ok(contents)
+}
+
 pub fn write_file(&mut self, path: &str, contents: &str) -> io::Result<()> {
+    injected_fault()?;
     // Write to a file in the filesystem
     fs::write(path, contents)?;
     let checksum = self.calculate_checksum(contents);
@@ -42,7 +61,34 @@ pub fn write_file(&mut self, path: &str, contents: &str) -> io::Result<()> {
     Ok(())
 }
 
-        fn calculate_checksum(&self, contents: &str) -> String {
+pub fn write_bytes(&mut self, path: &str, contents: &[u8]) -> io::Result<()> {
+    injected_fault()?;
+    fs::write(path, contents)?;
+    let checksum = self.calculate_checksum(contents);
+    self.journal.insert(path.to_string(), checksum);
+    Ok(())
+}
+
+        // Creating the file if need be, as logs are written
+        pub fn append_bytes(&mut self, path: &str, contents: &[u8]) -> io::Result<()> {
+            injected_fault()?;
+            fs::OpenOptions::new().create(true).append(true).open(path)?.write_all(contents)?;
+            let checksum = self.calculate_checksum(fs::read(path)?);
+            self.journal.insert(path.to_string(), checksum);
+            Ok(())
+        }
+
+        // Names of the entries in a directory, sorted
+        pub fn list_dir(&self, path: &str) -> io::Result<Vec<String>> {
+            let mut names = Vec::new();
+            for entry in fs::read_dir(path)? {
+                names.push(entry?.file_name().to_string_lossy().into_owned());
+            }
+            names.sort();
+            Ok(names)
+        }
+
+        fn calculate_checksum(&self, contents: impl AsRef<[u8]>) -> String {
             let mut hasher = Sha256::new();
             hasher.update(contents);
             let result = hasher.finalize();
@@ -52,7 +98,7 @@ pub fn write_file(&mut self, path: &str, contents: &str) -> io::Result<()> {
         pub fn verify_integrity(&self, path: &str) -> io::Result<bool> {
             // Verify the integrity of a file using the journal
             if let Some(expected_checksum) = self.journal.get(path) {
-                let contents = fs::read_to_string(path)?;
+                let contents = fs::read(path)?;
                 let actual_checksum = self.calculate_checksum(&contents);
                 Ok(expected_checksum == &actual_checksum)
             } else {
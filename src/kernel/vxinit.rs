@@ -0,0 +1,575 @@
+// src/kernel/vxinit.rs
+
+pub mod vxinit {
+    use crate::process::files::files::FileTable;
+    use crate::process::filter::filter::{Action, Filter};
+    use crate::process::signal::signal::SIGTERM;
+    use crate::process::syscall::syscall::{self, Errno, O_APPEND, O_CREAT, O_RDONLY, O_WRONLY};
+    use crate::process::table::table::{ExitStatus, Pid, ProcessTable, KERNEL_PID};
+    use crate::vxchan::vxchan::VXChanManager;
+    use crate::vxfs::vxfs::VXFS;
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    pub const REQUEST_CHANNEL: &str = "vxinit";
+    pub const REPLY_CHANNEL: &str = "vxinit.reply";
+    pub const MANIFEST_DIR: &str = "/etc/vxinit";
+
+    // A service restarting more often than this within the window is
+    // given up on
+    pub const RESTART_LIMIT: usize = 5;
+    pub const RESTART_WINDOW: Duration = Duration::from_secs(60);
+    pub const DEFAULT_RESTART_DELAY: Duration = Duration::from_millis(500);
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Restart {
+        Never,
+        // Unless it exited with status 0
+        OnFailure,
+        Always,
+    }
+
+    impl Restart {
+        pub fn name(self) -> &'static str {
+            match self {
+                Restart::Never => "never",
+                Restart::OnFailure => "on-failure",
+                Restart::Always => "always",
+            }
+        }
+
+        pub fn parse(name: &str) -> Result<Self, &'static str> {
+            match name {
+                "never" => Ok(Restart::Never),
+                "on-failure" => Ok(Restart::OnFailure),
+                "always" => Ok(Restart::Always),
+                _ => Err("Unknown restart policy"),
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum UnitKind {
+        // A program to run
+        Service,
+        // A set of units to bring up together, e.g. rescue or graphical
+        Target,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Manifest {
+        pub name: String,
+        pub kind: UnitKind,
+        pub description: String,
+        // The executable's path and its arguments
+        pub exec: Vec<String>,
+        pub env: Vec<String>,
+        // Started first; the unit stops if one of them fails
+        pub requires: Vec<String>,
+        // Only ordering: started first when both are being started
+        pub after: Vec<String>,
+        // Targets that pull the unit in
+        pub wanted_by: Vec<String>,
+        pub restart: Restart,
+        pub restart_delay: Duration,
+        // Paths opened as standard input, output and error: input for
+        // reading, the others appended to and created if need be. A FIFO
+        // to write to has to have its reader already.
+        pub stdin: Option<String>,
+        pub stdout: Option<String>,
+        pub stderr: Option<String>,
+        // What system calls it may make, installed before it runs
+        pub filter: Option<Arc<Filter>>,
+    }
+
+    impl Manifest {
+        // One [service] or [target] section of key=value lines. Lists are
+        // separated by spaces, and env may be given more than once:
+        //   name=network
+        //   description=Network manager
+        //   exec=/bin/vxnetd --foreground
+        //   env=RUST_LOG=info
+        //   requires=devices
+        //   after=log
+        //   wanted-by=rescue graphical
+        //   restart=never|on-failure|always
+        //   restart-delay=MILLISECONDS
+        //   stdout=/var/log/vxnetd.log
+        //   syscalls=read write close poll open:arg1&3==0
+        //   syscall-action=kill|deny
+        // Targets take name, description, requires, after and wanted-by.
+        pub fn parse(contents: &str) -> Result<Self, &'static str> {
+            let mut kind = None;
+            let mut manifest = Manifest {
+                name: String::new(),
+                kind: UnitKind::Service,
+                description: String::new(),
+                exec: Vec::new(),
+                env: Vec::new(),
+                requires: Vec::new(),
+                after: Vec::new(),
+                wanted_by: Vec::new(),
+                restart: Restart::OnFailure,
+                restart_delay: DEFAULT_RESTART_DELAY,
+                stdin: None,
+                stdout: None,
+                stderr: None,
+                filter: None,
+            };
+            let (mut syscalls, mut action) = (None, Action::Kill);
+            for line in contents.lines().map(str::trim) {
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                if let Some(section) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                    if kind.is_some() {
+                        return Err("More than one unit in a manifest");
+                    }
+                    kind = Some(match section {
+                        "service" => UnitKind::Service,
+                        "target" => UnitKind::Target,
+                        _ => return Err("Unknown unit kind"),
+                    });
+                    continue;
+                }
+                let kind = kind.ok_or("Setting outside a unit")?;
+                let (key, value) = line.split_once('=').ok_or("Malformed manifest line")?;
+                let (key, value) = (key.trim(), value.trim());
+                let list = || value.split_whitespace().map(String::from);
+                match (kind, key) {
+                    (_, "name") => manifest.name = value.to_string(),
+                    (_, "description") => manifest.description = value.to_string(),
+                    (_, "requires") => manifest.requires.extend(list()),
+                    (_, "after") => manifest.after.extend(list()),
+                    (_, "wanted-by") => manifest.wanted_by.extend(list()),
+                    (UnitKind::Service, "exec") => manifest.exec = list().collect(),
+                    (UnitKind::Service, "env") => manifest.env.push(value.to_string()),
+                    (UnitKind::Service, "restart") => manifest.restart = Restart::parse(value)?,
+                    (UnitKind::Service, "stdin") => manifest.stdin = Some(value.to_string()),
+                    (UnitKind::Service, "stdout") => manifest.stdout = Some(value.to_string()),
+                    (UnitKind::Service, "stderr") => manifest.stderr = Some(value.to_string()),
+                    (UnitKind::Service, "syscalls") => syscalls = Some(value),
+                    (UnitKind::Service, "syscall-action") => action = Action::parse(value)?,
+                    (UnitKind::Service, "restart-delay") => {
+                        manifest.restart_delay = Duration::from_millis(value.parse().map_err(|_| "Invalid number")?)
+                    }
+                    _ => return Err("Unknown manifest setting"),
+                }
+            }
+            manifest.kind = kind.ok_or("No unit in manifest")?;
+            if manifest.name.is_empty() || manifest.name.contains(char::is_whitespace) {
+                return Err("Missing unit name");
+            }
+            if manifest.kind == UnitKind::Service && manifest.exec.is_empty() {
+                return Err("Service has nothing to run");
+            }
+            manifest.filter = syscalls.map(|syscalls| Filter::parse(syscalls, action).map(Arc::new)).transpose()?;
+            Ok(manifest)
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum UnitState {
+        Inactive,
+        // A target whose units have been started
+        Active,
+        Running(Pid),
+        // To start again at the given time
+        Restarting(Instant),
+        // And not to be restarted
+        Exited(ExitStatus),
+        Failed(&'static str),
+    }
+
+    impl UnitState {
+        pub fn name(self) -> &'static str {
+            match self {
+                UnitState::Inactive => "inactive",
+                UnitState::Active => "active",
+                UnitState::Running(_) => "running",
+                UnitState::Restarting(_) => "restarting",
+                UnitState::Exited(_) => "exited",
+                UnitState::Failed(_) => "failed",
+            }
+        }
+
+        fn is_up(self) -> bool {
+            matches!(self, UnitState::Active | UnitState::Running(_) | UnitState::Restarting(_))
+        }
+    }
+
+    struct Unit {
+        manifest: Manifest,
+        state: UnitState,
+        // Within RESTART_WINDOW
+        restarts: Vec<Instant>,
+    }
+
+    // Starts services from their manifests in dependency order, restarts
+    // them as their policy says, and brings whole targets up or down. Runs
+    // in the kernel and owns the kernel's children, so it also reaps
+    // orphans left to the kernel.
+    #[derive(Default)]
+    pub struct Init {
+        units: BTreeMap<String, Unit>,
+        target: Option<String>,
+    }
+
+    impl Init {
+        pub fn new() -> Self {
+            Init::default()
+        }
+
+        pub fn add(&mut self, manifest: Manifest) -> Result<(), &'static str> {
+            if self.units.contains_key(&manifest.name) {
+                return Err("Unit already exists");
+            }
+            let unit = Unit {
+                manifest,
+                state: UnitState::Inactive,
+                restarts: Vec::new(),
+            };
+            self.units.insert(unit.manifest.name.clone(), unit);
+            Ok(())
+        }
+
+        // Every *.service and *.target manifest in a directory, returning
+        // how many were added
+        pub fn load_dir(&mut self, dir: &str) -> Result<usize, &'static str> {
+            let mut fs = VXFS::new();
+            let names = fs.list_dir(dir).map_err(|_| "Failed to read manifest directory")?;
+            let mut count = 0;
+            for name in names.iter().filter(|name| name.ends_with(".service") || name.ends_with(".target")) {
+                let contents = fs.read_file(&format!("{}/{}", dir, name)).map_err(|_| "Failed to read manifest")?;
+                self.add(Manifest::parse(&contents)?)?;
+                count += 1;
+            }
+            Ok(count)
+        }
+
+        pub fn units(&self) -> Vec<&str> {
+            self.units.keys().map(String::as_str).collect()
+        }
+
+        pub fn manifest(&self, name: &str) -> Option<&Manifest> {
+            self.units.get(name).map(|unit| &unit.manifest)
+        }
+
+        pub fn state(&self, name: &str) -> Option<UnitState> {
+            self.units.get(name).map(|unit| unit.state)
+        }
+
+        // Restarts within RESTART_WINDOW
+        pub fn restarts(&self, name: &str) -> usize {
+            self.units.get(name).map_or(0, |unit| unit.restarts.len())
+        }
+
+        // The target last isolated
+        pub fn target(&self) -> Option<&str> {
+            self.target.as_deref()
+        }
+
+        fn unit(&self, name: &str) -> Result<&Unit, &'static str> {
+            self.units.get(name).ok_or("Unit not found")
+        }
+
+        // What a unit pulls in: what it requires, and for a target what is
+        // wanted by it
+        fn pulls(&self, name: &str) -> Result<Vec<String>, &'static str> {
+            let unit = self.unit(name)?;
+            let mut pulled = unit.manifest.requires.clone();
+            if unit.manifest.kind == UnitKind::Target {
+                pulled.extend(
+                    self.units
+                        .values()
+                        .filter(|other| other.manifest.wanted_by.contains(&unit.manifest.name))
+                        .map(|other| other.manifest.name.clone()),
+                );
+            }
+            Ok(pulled)
+        }
+
+        // The unit and everything it pulls in, in the order to start them
+        pub fn closure(&self, name: &str) -> Result<Vec<String>, &'static str> {
+            let mut members = BTreeSet::new();
+            let mut pending = vec![name.to_string()];
+            while let Some(name) = pending.pop() {
+                if members.insert(name.clone()) {
+                    pending.extend(self.pulls(&name)?);
+                }
+            }
+            // Depth-first, each unit after whatever it requires or is
+            // ordered after, among the members
+            let mut order = Vec::new();
+            let mut visiting = BTreeSet::new();
+            fn visit(
+                init: &Init,
+                name: &str,
+                members: &BTreeSet<String>,
+                visiting: &mut BTreeSet<String>,
+                order: &mut Vec<String>,
+            ) -> Result<(), &'static str> {
+                if order.iter().any(|done| done == name) {
+                    return Ok(());
+                }
+                if !visiting.insert(name.to_string()) {
+                    return Err("Dependency cycle");
+                }
+                let manifest = &init.units[name].manifest;
+                let mut before: Vec<String> = manifest.requires.iter().chain(&manifest.after).cloned().collect();
+                if manifest.kind == UnitKind::Target {
+                    before.extend(init.pulls(name)?);
+                }
+                for dependency in before.iter().filter(|dependency| members.contains(*dependency)) {
+                    visit(init, dependency, members, visiting, order)?;
+                }
+                visiting.remove(name);
+                order.push(name.to_string());
+                Ok(())
+            }
+            for member in &members {
+                visit(self, member, &members, &mut visiting, &mut order)?;
+            }
+            Ok(order)
+        }
+
+        fn set_state(&mut self, name: &str, state: UnitState) {
+            self.units.get_mut(name).unwrap().state = state;
+        }
+
+        fn stdio(manifest: &Manifest, table: &ProcessTable) -> Result<FileTable, &'static str> {
+            let mut files = FileTable::new();
+            let output = O_WRONLY | O_CREAT | O_APPEND;
+            for (fd, path, flags) in [(0, &manifest.stdin, O_RDONLY), (1, &manifest.stdout, output), (2, &manifest.stderr, output)] {
+                if let Some(path) = path {
+                    let file = syscall::open(table, KERNEL_PID, path, flags).map_err(Errno::message)?;
+                    files.install(fd, file)?;
+                }
+            }
+            Ok(files)
+        }
+
+        fn start_unit(&mut self, name: &str, table: &mut ProcessTable) {
+            let unit = &self.units[name];
+            if unit.manifest.requires.iter().any(|dependency| !self.units.get(dependency).is_some_and(|other| other.state.is_up())) {
+                self.set_state(name, UnitState::Failed("Dependency failed"));
+                return;
+            }
+            let state = match unit.manifest.kind {
+                UnitKind::Target => UnitState::Active,
+                UnitKind::Service => {
+                    let exec: Vec<&str> = unit.manifest.exec.iter().map(String::as_str).collect();
+                    let env: Vec<&str> = unit.manifest.env.iter().map(String::as_str).collect();
+                    match Self::stdio(&unit.manifest, table).and_then(|files| table.spawn(KERNEL_PID, exec[0], &exec, &env, files)) {
+                        Ok(pid) => {
+                            if let Some(filter) = &unit.manifest.filter {
+                                table.get_mut(pid).unwrap().add_filter(filter.clone());
+                            }
+                            UnitState::Running(pid)
+                        }
+                        Err(error) => UnitState::Failed(error),
+                    }
+                }
+            };
+            log::info!("Unit {} is {}", name, state.name());
+            self.set_state(name, state);
+        }
+
+        // Starts a unit and everything it pulls in that is not up already
+        pub fn start(&mut self, name: &str, table: &mut ProcessTable) -> Result<(), &'static str> {
+            for member in self.closure(name)? {
+                if !self.units[&member].state.is_up() {
+                    self.start_unit(&member, table);
+                }
+            }
+            match self.units[name].state {
+                UnitState::Failed(error) => Err(error),
+                _ => Ok(()),
+            }
+        }
+
+        // Units that require this one, and then those that require them
+        fn dependents(&self, name: &str) -> Vec<String> {
+            let mut found: Vec<String> = Vec::new();
+            let mut pending = vec![name.to_string()];
+            while let Some(name) = pending.pop() {
+                for unit in self.units.values() {
+                    if unit.manifest.requires.contains(&name) && !found.contains(&unit.manifest.name) {
+                        found.push(unit.manifest.name.clone());
+                        pending.push(unit.manifest.name.clone());
+                    }
+                }
+            }
+            found
+        }
+
+        fn stop_unit(&mut self, name: &str, table: &mut ProcessTable, state: UnitState) {
+            if let UnitState::Running(pid) = self.units[name].state {
+                table.exit(pid, ExitStatus::Killed(SIGTERM));
+                let _ = table.wait(KERNEL_PID, Some(pid));
+            }
+            if self.units[name].state.is_up() || state != UnitState::Inactive {
+                log::info!("Unit {} is {}", name, state.name());
+            }
+            self.set_state(name, state);
+        }
+
+        // Stops a unit, after whatever requires it
+        pub fn stop(&mut self, name: &str, table: &mut ProcessTable) -> Result<(), &'static str> {
+            self.unit(name)?;
+            for dependent in self.dependents(name).iter().rev() {
+                if self.units[dependent].state.is_up() {
+                    self.stop_unit(dependent, table, UnitState::Inactive);
+                }
+            }
+            self.stop_unit(name, table, UnitState::Inactive);
+            Ok(())
+        }
+
+        pub fn restart(&mut self, name: &str, table: &mut ProcessTable) -> Result<(), &'static str> {
+            self.stop(name, table)?;
+            self.start(name, table)
+        }
+
+        // Brings up a target and stops everything it does not pull in,
+        // e.g. dropping from graphical to rescue
+        pub fn isolate(&mut self, target: &str, table: &mut ProcessTable) -> Result<(), &'static str> {
+            if self.unit(target)?.manifest.kind != UnitKind::Target {
+                return Err("Not a target");
+            }
+            let keep = self.closure(target)?;
+            let names: Vec<String> = self.units.keys().cloned().collect();
+            for name in names.iter().rev().filter(|name| !keep.contains(name)) {
+                if self.units[name].state.is_up() {
+                    self.stop_unit(name, table, UnitState::Inactive);
+                }
+            }
+            log::info!("Isolating {}", target);
+            self.target = Some(target.to_string());
+            self.start(target, table)
+        }
+
+        fn exited(&mut self, name: &str, status: ExitStatus, table: &mut ProcessTable, now: Instant) {
+            let unit = self.units.get_mut(name).unwrap();
+            let failed = status != ExitStatus::Exited(0);
+            let restart = match unit.manifest.restart {
+                Restart::Never => false,
+                Restart::OnFailure => failed,
+                Restart::Always => true,
+            };
+            unit.restarts.retain(|at| now.duration_since(*at) < RESTART_WINDOW);
+            let state = if !restart {
+                UnitState::Exited(status)
+            } else if unit.restarts.len() >= RESTART_LIMIT {
+                UnitState::Failed("Restarting too quickly")
+            } else {
+                unit.restarts.push(now);
+                UnitState::Restarting(now + unit.manifest.restart_delay)
+            };
+            log::info!("Unit {} exited: {:?}, now {}", name, status, state.name());
+            unit.state = state;
+            if failed && !restart || matches!(state, UnitState::Failed(_)) {
+                for dependent in self.dependents(name).iter().rev() {
+                    if self.units[dependent].state.is_up() {
+                        self.stop_unit(dependent, table, UnitState::Failed("Dependency failed"));
+                    }
+                }
+            }
+        }
+
+        // Reaps the kernel's exited children and restarts the services due
+        // to be. Returns how many processes were reaped.
+        pub fn poll(&mut self, table: &mut ProcessTable, now: Instant) -> usize {
+            let mut reaped = 0;
+            while let Ok(Some((pid, status))) = table.wait(KERNEL_PID, None) {
+                reaped += 1;
+                let name = self.units.iter().find(|(_, unit)| unit.state == UnitState::Running(pid)).map(|(name, _)| name.clone());
+                match name {
+                    Some(name) => self.exited(&name, status, table, now),
+                    None => log::info!("Reaped orphan {}: {:?}", pid, status),
+                }
+            }
+            let due: Vec<String> = self
+                .units
+                .iter()
+                .filter(|(_, unit)| matches!(unit.state, UnitState::Restarting(at) if at <= now))
+                .map(|(name, _)| name.clone())
+                .collect();
+            for name in due {
+                self.start_unit(&name, table);
+            }
+            reaped
+        }
+
+        fn describe(&self, name: &str) -> Result<String, &'static str> {
+            let unit = self.unit(name)?;
+            let mut body = format!("name={}\nstate={}\n", name, unit.state.name());
+            match unit.state {
+                UnitState::Running(pid) => body += &format!("pid={}\n", pid),
+                UnitState::Exited(status) => body += &format!("status={}\n", status.encode()),
+                UnitState::Failed(reason) => body += &format!("reason={}\n", reason),
+                _ => {}
+            }
+            body += &format!("restarts={}\ndescription={}", unit.restarts.len(), unit.manifest.description);
+            Ok(body)
+        }
+
+        fn execute(&mut self, line: &str, table: &mut ProcessTable) -> Result<String, &'static str> {
+            match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+                ["list"] => Ok(self
+                    .units
+                    .iter()
+                    .map(|(name, unit)| match unit.state {
+                        UnitState::Running(pid) => format!("{} running {}", name, pid),
+                        state => format!("{} {}", name, state.name()),
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")),
+                ["status", name] => self.describe(name),
+                ["start", name] => self.start(name, table).map(|_| String::new()),
+                ["stop", name] => self.stop(name, table).map(|_| String::new()),
+                ["restart", name] => self.restart(name, table).map(|_| String::new()),
+                ["isolate", target] => self.isolate(target, table).map(|_| String::new()),
+                ["target"] => Ok(self.target.clone().unwrap_or_default()),
+                [] => Err("Empty request"),
+                _ => Err("Unknown command"),
+            }
+        }
+    }
+
+    // Grammar, one request per message:
+    //   list | status UNIT | target
+    //   start UNIT | stop UNIT | restart UNIT | isolate TARGET
+    // Messages and replies are framed as in vxnetctl
+    pub struct InitService;
+
+    impl InitService {
+        pub fn new(manager: &VXChanManager) -> Result<Self, &'static str> {
+            manager.create_channel(REQUEST_CHANNEL)?;
+            manager.create_channel(REPLY_CHANNEL)?;
+            Ok(InitService)
+        }
+
+        // Answers every queued request; returns how many were handled
+        pub fn poll(&self, manager: &VXChanManager, init: &mut Init, table: &mut ProcessTable) -> Result<usize, &'static str> {
+            let mut count = 0;
+            while let Some(message) = manager.try_receive_message(REQUEST_CHANNEL)? {
+                let (id, line) = message.split_once(' ').unwrap_or((message.as_str(), ""));
+                let result = id.parse::<u64>().map_err(|_| "Invalid request id").and_then(|_| init.execute(line, table));
+                let reply = match result {
+                    Ok(body) if body.is_empty() => format!("{} ok", id),
+                    Ok(body) => format!("{} ok\n{}", id, body),
+                    Err(error) => format!("{} error {}", id, error),
+                };
+                manager.send_message(REPLY_CHANNEL, reply)?;
+                count += 1;
+            }
+            Ok(count)
+        }
+    }
+
+    pub fn send_request(manager: &VXChanManager, id: u64, request: &str) -> Result<(), &'static str> {
+        manager.send_message(REQUEST_CHANNEL, format!("{} {}", id, request))
+    }
+}
@@ -0,0 +1,988 @@
+// src/kernel/vxsh.rs
+
+pub mod vxsh {
+    use crate::process::files::files::{FileTable, OpenFile};
+    use crate::process::pipe::pipe::{self, PipeReader};
+    use crate::process::signal::signal::{self, SIGCONT, SIGHUP, SIGINT, SIGKILL, SIGQUIT, SIGSTOP, SIGTERM, SIGTSTP, SIGTTIN, SIGTTOU};
+    use crate::process::syscall::syscall::{self, Errno, O_APPEND, O_CREAT, O_RDONLY, O_TRUNC, O_WRONLY};
+    use crate::process::table::table::{ExitStatus, Pid, ProcessTable};
+    use crate::pty::pty::PtySlave;
+    use crate::users::users::ACCESS_EXECUTE;
+    use crate::vxchan::vxchan::VXChanManager;
+    use std::collections::{BTreeMap, BTreeSet, VecDeque};
+    use std::ops::Range;
+
+    pub const DEFAULT_PATH: &str = "/bin:/usr/bin";
+    // Exit statuses, as other shells give them
+    pub const STATUS_USAGE: u8 = 2;
+    pub const STATUS_NOT_EXECUTABLE: u8 = 126;
+    pub const STATUS_NOT_FOUND: u8 = 127;
+    // Plus the signal, for commands killed or stopped by one
+    pub const STATUS_SIGNALED: u8 = 128;
+
+    const BUILTINS: [&str; 8] = ["bg", "echo", "exit", "export", "fg", "jobs", "kill", "unset"];
+    // Read from standard input at once
+    const INPUT_CHUNK: usize = 4096;
+
+    // Part of a word as typed. Variables are expanded when the command
+    // runs, so `false; echo $?` sees the first one's status.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum Part {
+        // Unquoted, the only place assignments are recognised
+        Text(String),
+        Quoted(String),
+        // $NAME or ${NAME}, and the specials $?, $$ and $!
+        Variable(String),
+    }
+
+    pub type Word = Vec<Part>;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum Redirection {
+        // fd<path
+        Input(usize, Word),
+        // fd>path, or fd>>path when appending
+        Output(usize, Word, bool),
+        // fd>&other
+        Duplicate(usize, usize),
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Default)]
+    pub struct Command {
+        // NAME=value words in front, setting shell variables when there is
+        // nothing else and the command's environment otherwise
+        pub assignments: Vec<(String, Word)>,
+        pub words: Vec<Word>,
+        pub redirections: Vec<Redirection>,
+    }
+
+    impl Command {
+        fn is_empty(&self) -> bool {
+            self.assignments.is_empty() && self.words.is_empty() && self.redirections.is_empty()
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Pipeline {
+        pub commands: Vec<Command>,
+        // Ended with &
+        pub background: bool,
+        // As typed, for jobs
+        pub text: String,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    enum Token {
+        Word(Word),
+        Pipe,
+        Background,
+        Separator,
+        // With the descriptor it is for
+        Input(usize),
+        Output(usize),
+        Append(usize),
+        Duplicate(usize),
+    }
+
+    fn is_name(name: &str) -> bool {
+        let mut chars = name.chars();
+        chars.next().is_some_and(|first| first.is_ascii_alphabetic() || first == '_') && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    }
+
+    fn ends_word(c: char) -> bool {
+        c.is_whitespace() || matches!(c, '|' | '&' | ';' | '<' | '>')
+    }
+
+    // A $ expansion starting at chars[at], and the index after it. None
+    // when the $ is just a dollar sign.
+    fn variable(chars: &[char], at: usize) -> Result<Option<(String, usize)>, &'static str> {
+        match chars.get(at + 1) {
+            Some('{') => {
+                let end = chars[at + 2..].iter().position(|c| *c == '}').ok_or("Bad substitution")? + at + 2;
+                let name: String = chars[at + 2..end].iter().collect();
+                if !is_name(&name) && !matches!(name.as_str(), "?" | "$" | "!") {
+                    return Err("Bad substitution");
+                }
+                Ok(Some((name, end + 1)))
+            }
+            Some(special @ ('?' | '$' | '!')) => Ok(Some((special.to_string(), at + 2))),
+            Some(c) if c.is_ascii_alphabetic() || *c == '_' => {
+                let end = chars[at + 1..].iter().position(|c| !c.is_ascii_alphanumeric() && *c != '_').map_or(chars.len(), |end| end + at + 1);
+                Ok(Some((chars[at + 1..end].iter().collect(), end)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    // One word from chars[at], and the index after it
+    fn word(chars: &[char], mut at: usize) -> Result<(Word, usize), &'static str> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        while let Some(&c) = chars.get(at) {
+            if ends_word(c) {
+                break;
+            }
+            match c {
+                '\\' => {
+                    text.push(*chars.get(at + 1).unwrap_or(&'\\'));
+                    at += 2;
+                }
+                '\'' => {
+                    let end = chars[at + 1..].iter().position(|c| *c == '\'').ok_or("Unterminated quote")? + at + 1;
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Quoted(chars[at + 1..end].iter().collect()));
+                    at = end + 1;
+                }
+                '"' => {
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    let before = parts.len();
+                    let mut quoted = String::new();
+                    at += 1;
+                    loop {
+                        match chars.get(at) {
+                            None => return Err("Unterminated quote"),
+                            Some('"') => break,
+                            Some('\\') if matches!(chars.get(at + 1), Some('"' | '\\' | '$')) => {
+                                quoted.push(chars[at + 1]);
+                                at += 2;
+                            }
+                            Some('$') => match variable(chars, at)? {
+                                Some((name, next)) => {
+                                    if !quoted.is_empty() {
+                                        parts.push(Part::Quoted(std::mem::take(&mut quoted)));
+                                    }
+                                    parts.push(Part::Variable(name));
+                                    at = next;
+                                }
+                                None => {
+                                    quoted.push('$');
+                                    at += 1;
+                                }
+                            },
+                            Some(&c) => {
+                                quoted.push(c);
+                                at += 1;
+                            }
+                        }
+                    }
+                    // Even when empty, so "" is still a word
+                    if !quoted.is_empty() || parts.len() == before {
+                        parts.push(Part::Quoted(quoted));
+                    }
+                    at += 1;
+                }
+                '$' => match variable(chars, at)? {
+                    Some((name, next)) => {
+                        if !text.is_empty() {
+                            parts.push(Part::Text(std::mem::take(&mut text)));
+                        }
+                        parts.push(Part::Variable(name));
+                        at = next;
+                    }
+                    None => {
+                        text.push('$');
+                        at += 1;
+                    }
+                },
+                _ => {
+                    text.push(c);
+                    at += 1;
+                }
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok((parts, at))
+    }
+
+    // A redirection operator at chars[at] for a descriptor, and the index
+    // after it
+    fn redirection(chars: &[char], at: usize, fd: Option<usize>) -> (Token, usize) {
+        match (chars[at], chars.get(at + 1)) {
+            ('<', _) => (Token::Input(fd.unwrap_or(0)), at + 1),
+            ('>', Some('>')) => (Token::Append(fd.unwrap_or(1)), at + 2),
+            ('>', Some('&')) => (Token::Duplicate(fd.unwrap_or(1)), at + 2),
+            _ => (Token::Output(fd.unwrap_or(1)), at + 1),
+        }
+    }
+
+    // With where each token is in the line. A # starting a word comments
+    // out the rest.
+    fn tokenize(line: &str) -> Result<Vec<(Token, Range<usize>)>, &'static str> {
+        let chars: Vec<char> = line.chars().collect();
+        let mut tokens = Vec::new();
+        let mut at = 0;
+        while let Some(&c) = chars.get(at) {
+            let start = at;
+            let token = match c {
+                _ if c.is_whitespace() => {
+                    at += 1;
+                    continue;
+                }
+                '#' => break,
+                '|' => Token::Pipe,
+                '&' => Token::Background,
+                ';' => Token::Separator,
+                '<' | '>' => {
+                    let (token, next) = redirection(&chars, at, None);
+                    tokens.push((token, start..next));
+                    at = next;
+                    continue;
+                }
+                _ => {
+                    let (word, next) = word(&chars, at)?;
+                    at = next;
+                    // Digits right before one are the descriptor, as in 2>
+                    let fd = match word.as_slice() {
+                        [Part::Text(digits)] if matches!(chars.get(at), Some('<' | '>')) => digits.parse::<usize>().ok(),
+                        _ => None,
+                    };
+                    if fd.is_some() {
+                        let (token, next) = redirection(&chars, at, fd);
+                        at = next;
+                        token
+                    } else {
+                        Token::Word(word)
+                    }
+                }
+            };
+            if matches!(token, Token::Pipe | Token::Background | Token::Separator) {
+                at += 1;
+            }
+            tokens.push((token, start..at));
+        }
+        Ok(tokens)
+    }
+
+    // NAME=value, unquoted up to the =
+    fn assignment(word: &Word) -> Option<(String, Word)> {
+        let Some(Part::Text(text)) = word.first() else {
+            return None;
+        };
+        let (name, value) = text.split_once('=')?;
+        if !is_name(name) {
+            return None;
+        }
+        let mut rest = Vec::new();
+        if !value.is_empty() {
+            rest.push(Part::Text(value.to_string()));
+        }
+        rest.extend_from_slice(&word[1..]);
+        Some((name.to_string(), rest))
+    }
+
+    // Grammar:
+    //   line     := pipeline ((';' | '&') pipeline)* [';' | '&']
+    //   pipeline := command ('|' command)*
+    //   command  := (NAME=value)* (word | redirection)*
+    //   redirection := [fd] ('<' | '>' | '>>') word | [fd] '>&' fd
+    // There is no field splitting or globbing: a variable expands to one
+    // word however many spaces it has.
+    pub fn parse(line: &str) -> Result<Vec<Pipeline>, &'static str> {
+        let chars: Vec<char> = line.chars().collect();
+        let mut tokens = tokenize(line)?.into_iter().peekable();
+        let mut pipelines = Vec::new();
+        let mut commands = Vec::new();
+        let mut command = Command::default();
+        let mut span: Option<Range<usize>> = None;
+        while let Some((token, range)) = tokens.next() {
+            if !matches!(token, Token::Separator | Token::Background) {
+                span = Some(span.map_or(range.clone(), |span| span.start..range.end));
+            }
+            match token {
+                Token::Word(word) => match assignment(&word) {
+                    Some(assignment) if command.words.is_empty() => command.assignments.push(assignment),
+                    _ => command.words.push(word),
+                },
+                Token::Input(fd) | Token::Output(fd) | Token::Append(fd) | Token::Duplicate(fd) => {
+                    let Some((Token::Word(target), target_range)) = tokens.next() else {
+                        return Err("Missing redirection target");
+                    };
+                    span = span.map(|span| span.start..target_range.end);
+                    command.redirections.push(match token {
+                        Token::Input(_) => Redirection::Input(fd, target),
+                        Token::Output(_) => Redirection::Output(fd, target, false),
+                        Token::Append(_) => Redirection::Output(fd, target, true),
+                        _ => match target.as_slice() {
+                            [Part::Text(digits)] => Redirection::Duplicate(fd, digits.parse().map_err(|_| "Bad file descriptor")?),
+                            _ => return Err("Bad file descriptor"),
+                        },
+                    });
+                }
+                Token::Pipe => {
+                    if command.is_empty() {
+                        return Err("Empty command");
+                    }
+                    commands.push(std::mem::take(&mut command));
+                }
+                Token::Separator | Token::Background => {
+                    if command.is_empty() {
+                        return Err("Empty command");
+                    }
+                    commands.push(std::mem::take(&mut command));
+                    let span = span.take().unwrap();
+                    pipelines.push(Pipeline {
+                        commands: std::mem::take(&mut commands),
+                        background: token == Token::Background,
+                        text: chars[span].iter().collect(),
+                    });
+                }
+            }
+        }
+        if !command.is_empty() {
+            commands.push(command);
+            pipelines.push(Pipeline {
+                commands,
+                background: false,
+                text: chars[span.unwrap()].iter().collect(),
+            });
+        } else if !commands.is_empty() {
+            return Err("Empty command");
+        }
+        Ok(pipelines)
+    }
+
+    // Variables are looked up by name, the specials included
+    pub fn expand(word: &Word, lookup: impl Fn(&str) -> Option<String>) -> String {
+        word.iter()
+            .map(|part| match part {
+                Part::Text(text) | Part::Quoted(text) => text.clone(),
+                Part::Variable(name) => lookup(name).unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    // $? for how a command ended
+    pub fn status_code(status: ExitStatus) -> u8 {
+        match status {
+            ExitStatus::Exited(code) => code,
+            ExitStatus::Killed(signal) | ExitStatus::Stopped(signal) => STATUS_SIGNALED.wrapping_add(signal),
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum JobState {
+        Running,
+        // By the signal
+        Stopped(u8),
+        // How its last command ended
+        Done(ExitStatus),
+    }
+
+    impl JobState {
+        pub fn name(self) -> String {
+            match self {
+                JobState::Running => "Running".to_string(),
+                JobState::Stopped(_) => "Stopped".to_string(),
+                JobState::Done(ExitStatus::Exited(0)) => "Done".to_string(),
+                JobState::Done(ExitStatus::Exited(code)) => format!("Exit {}", code),
+                JobState::Done(ExitStatus::Killed(SIGHUP)) => "Hangup".to_string(),
+                JobState::Done(ExitStatus::Killed(SIGINT)) => "Interrupt".to_string(),
+                JobState::Done(ExitStatus::Killed(SIGKILL)) => "Killed".to_string(),
+                JobState::Done(ExitStatus::Killed(SIGTERM)) => "Terminated".to_string(),
+                JobState::Done(ExitStatus::Killed(signal) | ExitStatus::Stopped(signal)) => format!("Signal {}", signal),
+            }
+        }
+    }
+
+    // A pipeline's processes, in a group of their own when the shell has
+    // job control
+    #[derive(Debug, Clone)]
+    pub struct Job {
+        pub id: usize,
+        pub group: Pid,
+        pub pids: Vec<Pid>,
+        pub text: String,
+        pub state: JobState,
+        // Not yet reaped, and of those stopped
+        live: BTreeSet<Pid>,
+        stopped: BTreeSet<Pid>,
+        // Of the last command, when it has ended or never started
+        last: Option<ExitStatus>,
+    }
+
+    // How one command of a pipeline went
+    enum Outcome {
+        Spawned(Pid),
+        // A builtin, or what could not be started
+        Finished(ExitStatus),
+    }
+
+    // Runs on behalf of a process that stands for it: reading commands
+    // from its standard input, starting their programs as its children
+    // and reaping them. With the process's controlling terminal on
+    // standard input it is interactive, with prompts and job control.
+    pub struct Shell {
+        pid: Pid,
+        terminal: Option<PtySlave>,
+        variables: BTreeMap<String, String>,
+        exported: BTreeSet<String>,
+        jobs: Vec<Job>,
+        // The job waited for before reading more
+        foreground: Option<usize>,
+        status: u8,
+        last_background: Option<Pid>,
+        // Read but not yet a whole line, and parsed but not yet run
+        input: Vec<u8>,
+        pending: VecDeque<Pipeline>,
+        prompted: bool,
+        finished: bool,
+    }
+
+    fn write_file(files: &mut FileTable, channels: &VXChanManager, fd: usize, text: &str) {
+        if let Some(file) = files.get_mut(fd) {
+            let _ = syscall::write_to(file, channels, text.as_bytes().to_vec());
+        }
+    }
+
+    fn parse_signal(name: &str) -> Option<u8> {
+        let signal = match name.trim_start_matches("SIG") {
+            "HUP" => SIGHUP,
+            "INT" => SIGINT,
+            "QUIT" => SIGQUIT,
+            "KILL" => SIGKILL,
+            "TERM" => SIGTERM,
+            "CONT" => SIGCONT,
+            "STOP" => SIGSTOP,
+            "TSTP" => SIGTSTP,
+            number => number.parse().ok()?,
+        };
+        signal::is_valid(signal).then_some(signal)
+    }
+
+    impl Shell {
+        // Takes over a process, with its environment as KEY=value strings,
+        // all of them exported. An interactive shell ignores what the
+        // terminal sends its jobs and leads a group of its own in the
+        // foreground.
+        pub fn new(table: &mut ProcessTable, pid: Pid, env: &[&str]) -> Result<Self, &'static str> {
+            let process = table.get(pid).ok_or("No such process")?;
+            let terminal = match (process.files().get(0), table.controlling_terminal(pid)) {
+                (Some(OpenFile::Terminal { slave, .. }), Some(controlling)) if slave.index() == controlling.index() => Some(slave.clone()),
+                _ => None,
+            };
+            if let Some(slave) = &terminal {
+                let process = table.get_mut(pid).unwrap();
+                for ignored in [SIGINT, SIGQUIT, SIGTSTP, SIGTTIN, SIGTTOU] {
+                    process.dispositions_mut().set_ignored(ignored, true)?;
+                }
+                if process.group() != pid {
+                    table.set_group(pid, pid, pid)?;
+                }
+                table.set_foreground(pid, slave, pid)?;
+            }
+            let mut variables = BTreeMap::new();
+            for entry in env {
+                let (name, value) = entry.split_once('=').ok_or("Bad environment entry")?;
+                variables.insert(name.to_string(), value.to_string());
+            }
+            variables.entry("PATH".to_string()).or_insert_with(|| DEFAULT_PATH.to_string());
+            let exported = variables.keys().cloned().collect();
+            log::info!("Shell running as process {}{}", pid, if terminal.is_some() { " on a terminal" } else { "" });
+            Ok(Shell {
+                pid,
+                terminal,
+                variables,
+                exported,
+                jobs: Vec::new(),
+                foreground: None,
+                status: 0,
+                last_background: None,
+                input: Vec::new(),
+                pending: VecDeque::new(),
+                prompted: false,
+                finished: false,
+            })
+        }
+
+        pub fn pid(&self) -> Pid {
+            self.pid
+        }
+
+        pub fn is_interactive(&self) -> bool {
+            self.terminal.is_some()
+        }
+
+        // $?
+        pub fn status(&self) -> u8 {
+            self.status
+        }
+
+        // A shell variable or one of the specials
+        pub fn variable(&self, name: &str) -> Option<String> {
+            match name {
+                "?" => Some(self.status.to_string()),
+                "$" => Some(self.pid.to_string()),
+                "!" => self.last_background.map(|pid| pid.to_string()),
+                _ => self.variables.get(name).cloned(),
+            }
+        }
+
+        pub fn jobs(&self) -> &[Job] {
+            &self.jobs
+        }
+
+        pub fn foreground(&self) -> Option<&Job> {
+            self.jobs.iter().find(|job| Some(job.id) == self.foreground)
+        }
+
+        // After exit, end of input, or the process going away
+        pub fn is_finished(&self) -> bool {
+            self.finished
+        }
+
+        // Reaps children and, with no job in the foreground, runs whatever
+        // whole lines there are to read, prompting once there are none.
+        // Returns whether the shell is still running.
+        pub fn poll(&mut self, table: &mut ProcessTable) -> bool {
+            if table.get(self.pid).is_none() {
+                self.finished = true;
+            }
+            while !self.finished {
+                self.reap(table);
+                if self.foreground.is_some() {
+                    break;
+                }
+                if let Some(pipeline) = self.pending.pop_front() {
+                    self.run(table, pipeline);
+                    continue;
+                }
+                if let Some(end) = self.input.iter().position(|byte| *byte == b'\n') {
+                    let line: Vec<u8> = self.input.drain(..=end).collect();
+                    self.prompted = false;
+                    match parse(&String::from_utf8_lossy(&line)) {
+                        Ok(pipelines) => self.pending.extend(pipelines),
+                        Err(error) => {
+                            self.report(table, error);
+                            self.status = STATUS_USAGE;
+                        }
+                    }
+                    continue;
+                }
+                match self.read_input(table) {
+                    // A last line without a newline still runs
+                    Ok(data) if data.is_empty() && !self.input.is_empty() => self.input.push(b'\n'),
+                    Ok(data) if data.is_empty() => {
+                        if self.is_interactive() {
+                            self.print(table, 2, "exit\n");
+                        }
+                        self.exit(table, self.status);
+                    }
+                    Ok(data) => self.input.extend(data),
+                    Err(Errno::Again) => {
+                        self.prompt(table);
+                        break;
+                    }
+                    Err(error) => {
+                        self.report(table, error.message());
+                        self.exit(table, 1);
+                    }
+                }
+            }
+            !self.finished
+        }
+
+        fn read_input(&mut self, table: &mut ProcessTable) -> Result<Vec<u8>, Errno> {
+            let channels = table.channels().clone();
+            let file = table.get_mut(self.pid).unwrap().files_mut().get_mut(0).ok_or(Errno::BadFile)?;
+            syscall::read_from(file, &channels, INPUT_CHUNK)
+        }
+
+        fn prompt(&mut self, table: &mut ProcessTable) {
+            if self.terminal.is_none() || self.prompted || !self.input.is_empty() {
+                return;
+            }
+            let prompt = match self.variables.get("PS1") {
+                Some(prompt) => prompt.clone(),
+                None if table.get(self.pid).unwrap().credentials().is_root() => "# ".to_string(),
+                None => "$ ".to_string(),
+            };
+            self.print(table, 2, &prompt);
+            self.prompted = true;
+        }
+
+        // On one of the shell's own descriptors
+        fn print(&self, table: &mut ProcessTable, fd: usize, text: &str) {
+            let channels = table.channels().clone();
+            if let Some(process) = table.get_mut(self.pid) {
+                write_file(process.files_mut(), &channels, fd, text);
+            }
+        }
+
+        fn report(&self, table: &mut ProcessTable, message: &str) {
+            self.print(table, 2, &format!("vxsh: {}\n", message));
+        }
+
+        fn exit(&mut self, table: &mut ProcessTable, status: u8) {
+            log::info!("Shell {} exiting with status {}", self.pid, status);
+            self.finished = true;
+            table.exit(self.pid, ExitStatus::Exited(status));
+        }
+
+        fn expand(&self, word: &Word) -> String {
+            expand(word, |name| self.variable(name))
+        }
+
+        // Processes have no working directory yet, so relative paths are
+        // from the root
+        fn path(&self, word: &Word) -> String {
+            let path = self.expand(word);
+            match path.starts_with('/') {
+                true => path,
+                false => format!("/{}", path),
+            }
+        }
+
+        fn take_terminal(&self, table: &mut ProcessTable) {
+            if let Some(slave) = &self.terminal {
+                let _ = table.set_foreground(self.pid, slave, self.pid);
+            }
+        }
+
+        fn reap(&mut self, table: &mut ProcessTable) {
+            while let Ok(Some((pid, status))) = table.wait_untraced(self.pid, None, true) {
+                let Some(index) = self.jobs.iter().position(|job| job.pids.contains(&pid)) else {
+                    continue;
+                };
+                let job = &mut self.jobs[index];
+                match status {
+                    ExitStatus::Stopped(_) => {
+                        job.stopped.insert(pid);
+                    }
+                    _ => {
+                        job.live.remove(&pid);
+                        job.stopped.remove(&pid);
+                        if job.pids.last() == Some(&pid) {
+                            job.last = Some(status);
+                        }
+                    }
+                }
+                let state = match status {
+                    _ if job.live.is_empty() => JobState::Done(job.last.unwrap_or(status)),
+                    ExitStatus::Stopped(signal) if job.live == job.stopped => JobState::Stopped(signal),
+                    _ => continue,
+                };
+                if job.state != state {
+                    self.changed(table, index, state);
+                }
+            }
+        }
+
+        // Foreground jobs give the terminal back and set $? once they end
+        // or stop; background ones are reported when they end
+        fn changed(&mut self, table: &mut ProcessTable, index: usize, state: JobState) {
+            let job = &mut self.jobs[index];
+            job.state = state;
+            let (id, text) = (job.id, job.text.clone());
+            let foreground = self.foreground == Some(id);
+            if foreground {
+                self.foreground = None;
+                self.take_terminal(table);
+                self.status = match state {
+                    JobState::Stopped(signal) => STATUS_SIGNALED.wrapping_add(signal),
+                    JobState::Done(status) => status_code(status),
+                    JobState::Running => self.status,
+                };
+            }
+            match state {
+                JobState::Stopped(_) => self.print(table, 2, &format!("[{}]+  {:<24}{}\n", id, state.name(), text)),
+                JobState::Done(_) if !foreground => self.print(table, 2, &format!("[{}]+  {:<24}{}\n", id, state.name(), text)),
+                _ => {}
+            }
+            if matches!(state, JobState::Done(_)) {
+                self.jobs.remove(index);
+            }
+        }
+
+        // Each command gets the shell's standard descriptors, with pipes
+        // between them and then its own redirections
+        fn run(&mut self, table: &mut ProcessTable, pipeline: Pipeline) {
+            if let [command] = pipeline.commands.as_slice() {
+                if command.words.is_empty() {
+                    for (name, value) in &command.assignments {
+                        let value = self.expand(value);
+                        self.variables.insert(name.clone(), value);
+                    }
+                }
+            }
+            let mut pids = Vec::new();
+            let mut last = ExitStatus::Exited(0);
+            let mut last_spawned = false;
+            let mut previous: Option<PipeReader> = None;
+            let count = pipeline.commands.len();
+            for (index, command) in pipeline.commands.iter().enumerate() {
+                let mut files = table.get(self.pid).unwrap().files().inherit();
+                if let Some(reader) = previous.take() {
+                    let _ = files.install(0, OpenFile::PipeReader { reader, nonblocking: false });
+                }
+                if index + 1 < count {
+                    let (reader, writer) = pipe::pipe();
+                    let _ = files.install(1, OpenFile::PipeWriter { writer, nonblocking: false });
+                    previous = Some(reader);
+                }
+                let outcome = match self.redirect(table, &mut files, command) {
+                    Ok(()) => self.command(table, command, files, pids.first().copied()),
+                    Err(error) => {
+                        self.report(table, &error);
+                        Outcome::Finished(ExitStatus::Exited(1))
+                    }
+                };
+                if self.finished {
+                    return;
+                }
+                last_spawned = matches!(outcome, Outcome::Spawned(_));
+                match outcome {
+                    Outcome::Spawned(pid) => pids.push(pid),
+                    Outcome::Finished(status) => last = status,
+                }
+            }
+            let (Some(&group), Some(&final_pid)) = (pids.first(), pids.last()) else {
+                self.status = status_code(last);
+                return;
+            };
+            let id = self.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+            self.jobs.push(Job {
+                id,
+                group,
+                pids: pids.clone(),
+                text: pipeline.text.clone(),
+                state: JobState::Running,
+                live: pids.iter().copied().collect(),
+                stopped: BTreeSet::new(),
+                last: (!last_spawned).then_some(last),
+            });
+            if pipeline.background {
+                self.last_background = Some(final_pid);
+                self.status = 0;
+                self.print(table, 2, &format!("[{}] {}\n", id, final_pid));
+            } else {
+                self.foreground = Some(id);
+                if let Some(slave) = &self.terminal {
+                    let _ = table.set_foreground(self.pid, slave, group);
+                }
+            }
+        }
+
+        fn redirect(&self, table: &ProcessTable, files: &mut FileTable, command: &Command) -> Result<(), String> {
+            for redirection in &command.redirections {
+                let (fd, file) = match redirection {
+                    Redirection::Input(fd, target) => {
+                        let path = self.path(target);
+                        let file = syscall::open(table, self.pid, &path, O_RDONLY).map_err(|error| format!("{}: {}", path, error.message()))?;
+                        (*fd, file)
+                    }
+                    Redirection::Output(fd, target, append) => {
+                        let path = self.path(target);
+                        let flags = O_WRONLY | O_CREAT | if *append { O_APPEND } else { O_TRUNC };
+                        let file = syscall::open(table, self.pid, &path, flags).map_err(|error| format!("{}: {}", path, error.message()))?;
+                        (*fd, file)
+                    }
+                    Redirection::Duplicate(fd, other) => {
+                        let file = files.get(*other).and_then(OpenFile::try_clone).ok_or(format!("{}: {}", other, Errno::BadFile.message()))?;
+                        (*fd, file)
+                    }
+                };
+                files.install(fd, file).map_err(|error| format!("{}: {}", fd, error))?;
+            }
+            Ok(())
+        }
+
+        // Spawned into the job's group, led by its first process
+        fn command(&mut self, table: &mut ProcessTable, command: &Command, mut files: FileTable, leader: Option<Pid>) -> Outcome {
+            let words: Vec<String> = command.words.iter().map(|word| self.expand(word)).collect();
+            let Some(name) = words.first() else {
+                return Outcome::Finished(ExitStatus::Exited(0));
+            };
+            if BUILTINS.contains(&name.as_str()) {
+                let channels = table.channels().clone();
+                let status = match self.builtin(table, &words) {
+                    Ok(output) => {
+                        write_file(&mut files, &channels, 1, &output);
+                        0
+                    }
+                    Err(error) => {
+                        write_file(&mut files, &channels, 2, &format!("vxsh: {}: {}\n", name, error));
+                        1
+                    }
+                };
+                return Outcome::Finished(ExitStatus::Exited(status));
+            }
+            let path = match self.find(table, name) {
+                Ok(path) => path,
+                Err((error, status)) => {
+                    self.report(table, &format!("{}: {}", name, error));
+                    return Outcome::Finished(ExitStatus::Exited(status));
+                }
+            };
+            let mut environment: BTreeMap<String, String> = self.exported.iter().filter_map(|name| Some((name.clone(), self.variables.get(name)?.clone()))).collect();
+            for (name, value) in &command.assignments {
+                environment.insert(name.clone(), self.expand(value));
+            }
+            let env: Vec<String> = environment.iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+            let env: Vec<&str> = env.iter().map(String::as_str).collect();
+            let args: Vec<&str> = words.iter().map(String::as_str).collect();
+            match table.spawn(self.pid, &path, &args, &env, files) {
+                Ok(pid) => {
+                    if self.terminal.is_some() {
+                        let _ = table.set_group(self.pid, pid, leader.unwrap_or(pid));
+                    }
+                    Outcome::Spawned(pid)
+                }
+                Err(error) => {
+                    self.report(table, &format!("{}: {}", name, error));
+                    let status = if error == "File not found" { STATUS_NOT_FOUND } else { STATUS_NOT_EXECUTABLE };
+                    Outcome::Finished(ExitStatus::Exited(status))
+                }
+            }
+        }
+
+        // Names with a slash are paths; others are looked for in $PATH
+        fn find(&self, table: &ProcessTable, name: &str) -> Result<String, (&'static str, u8)> {
+            if name.contains('/') {
+                return Ok(self.path(&vec![Part::Text(name.to_string())]));
+            }
+            let credentials = table.get(self.pid).unwrap().credentials().clone();
+            let mut denied = false;
+            for directory in self.variables.get("PATH").map_or("", String::as_str).split(':').filter(|directory| !directory.is_empty()) {
+                let path = format!("{}/{}", directory.trim_end_matches('/'), name);
+                match table.access(&credentials, &path, ACCESS_EXECUTE) {
+                    Ok(host) if host.is_file() => return Ok(path),
+                    Err("Permission denied") => denied = true,
+                    _ => {}
+                }
+            }
+            match denied {
+                true => Err(("Permission denied", STATUS_NOT_EXECUTABLE)),
+                false => Err(("command not found", STATUS_NOT_FOUND)),
+            }
+        }
+
+        // %N, or the most recent job when there is no spec or it is %% or %+
+        fn job(&self, spec: Option<&String>) -> Result<usize, String> {
+            let index = match spec.map(String::as_str) {
+                None | Some("%%") | Some("%+") => self.jobs.len().checked_sub(1),
+                Some(spec) => {
+                    let id: usize = spec.strip_prefix('%').unwrap_or(spec).parse().map_err(|_| format!("{}: no such job", spec))?;
+                    self.jobs.iter().position(|job| job.id == id)
+                }
+            };
+            index.ok_or_else(|| match spec {
+                Some(spec) => format!("{}: no such job", spec),
+                None => "no current job".to_string(),
+            })
+        }
+
+        // Continues a job, stopped or not, in the foreground or background
+        fn resume(&mut self, table: &mut ProcessTable, index: usize, foreground: bool) -> Result<String, String> {
+            if self.terminal.is_none() {
+                return Err("no job control".to_string());
+            }
+            let job = &mut self.jobs[index];
+            job.state = JobState::Running;
+            job.stopped.clear();
+            let (id, group, text) = (job.id, job.group, job.text.clone());
+            if foreground {
+                self.foreground = Some(id);
+                let _ = table.set_foreground(self.pid, self.terminal.as_ref().unwrap(), group);
+            }
+            let _ = table.signal_group(group, SIGCONT);
+            Ok(match foreground {
+                true => format!("{}\n", text),
+                false => format!("[{}]+ {} &\n", id, text),
+            })
+        }
+
+        // Output for standard output, or the error
+        fn builtin(&mut self, table: &mut ProcessTable, words: &[String]) -> Result<String, String> {
+            let arguments = &words[1..];
+            match words[0].as_str() {
+                "echo" => match arguments.first().map(String::as_str) {
+                    Some("-n") => Ok(arguments[1..].join(" ")),
+                    _ => Ok(format!("{}\n", arguments.join(" "))),
+                },
+                "exit" => {
+                    let status = match arguments.first() {
+                        Some(status) => status.parse::<u8>().map_err(|_| format!("{}: numeric argument required", status))?,
+                        None => self.status,
+                    };
+                    self.exit(table, status);
+                    Ok(String::new())
+                }
+                "export" if arguments.is_empty() => Ok(self
+                    .exported
+                    .iter()
+                    .filter_map(|name| Some(format!("export {}={}\n", name, self.variables.get(name)?)))
+                    .collect()),
+                "export" => {
+                    for argument in arguments {
+                        let name = match argument.split_once('=') {
+                            Some((name, value)) if is_name(name) => {
+                                self.variables.insert(name.to_string(), value.to_string());
+                                name
+                            }
+                            None if is_name(argument) => argument,
+                            _ => return Err(format!("{}: not a valid identifier", argument)),
+                        };
+                        self.exported.insert(name.to_string());
+                    }
+                    Ok(String::new())
+                }
+                "unset" => {
+                    for name in arguments {
+                        self.variables.remove(name);
+                        self.exported.remove(name);
+                    }
+                    Ok(String::new())
+                }
+                "jobs" => Ok(self
+                    .jobs
+                    .iter()
+                    .enumerate()
+                    .map(|(index, job)| {
+                        let current = if index + 1 == self.jobs.len() { '+' } else { ' ' };
+                        format!("[{}]{}  {:<24}{}\n", job.id, current, job.state.name(), job.text)
+                    })
+                    .collect()),
+                "fg" => {
+                    let index = self.job(arguments.first())?;
+                    self.resume(table, index, true)
+                }
+                "bg" => {
+                    let index = self.job(arguments.first())?;
+                    self.resume(table, index, false)
+                }
+                "kill" => {
+                    let (signal, targets) = match arguments.first().and_then(|first| first.strip_prefix('-')) {
+                        Some(name) => (parse_signal(name).ok_or(format!("{}: invalid signal specification", name))?, &arguments[1..]),
+                        None => (SIGTERM, arguments),
+                    };
+                    if targets.is_empty() {
+                        return Err("usage: kill [-signal] pid | %job ...".to_string());
+                    }
+                    // Only what the shell's user could signal itself
+                    let sender = table.get(self.pid).unwrap().credentials().clone();
+                    for target in targets {
+                        let pids = match target.strip_prefix('%') {
+                            Some(_) => table.group_members(self.jobs[self.job(Some(target))?].group),
+                            None => vec![target.parse::<Pid>().map_err(|_| format!("{}: arguments must be process or job IDs", target))?],
+                        };
+                        for pid in pids {
+                            let process = table.get(pid).ok_or(format!("({}) - No such process", pid))?;
+                            if !sender.is_root() && process.credentials().uid != sender.uid {
+                                return Err(format!("({}) - Operation not permitted", pid));
+                            }
+                            let _ = table.signal(pid, signal);
+                        }
+                    }
+                    Ok(String::new())
+                }
+                _ => Err("not a builtin".to_string()),
+            }
+        }
+    }
+}
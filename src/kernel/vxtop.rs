@@ -0,0 +1,532 @@
+// src/kernel/vxtop.rs
+
+pub mod vxtop {
+    use crate::process::procfs::procfs;
+    use crate::process::table::table::{Pid, ProcessTable};
+    use crate::pty::pty::{LineMode, PtySlave};
+    use std::cmp::Ordering;
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+
+    // Clears the screen and homes the cursor before each frame
+    pub const CLEAR: &str = "\x1b[H\x1b[2J";
+
+    // Where vxtop reads its files from: the local /proc, or another
+    // machine's over RPC
+    pub trait ProcSource {
+        fn read(&self, path: &str) -> Result<String, &'static str>;
+        fn list(&self, path: &str) -> Result<Vec<String>, &'static str>;
+    }
+
+    // This kernel's /proc, read as the given process
+    pub struct Procfs<'a> {
+        pub table: &'a ProcessTable,
+        pub reader: Pid,
+    }
+
+    impl ProcSource for Procfs<'_> {
+        fn read(&self, path: &str) -> Result<String, &'static str> {
+            procfs::read(self.table, self.reader, path)
+        }
+
+        fn list(&self, path: &str) -> Result<Vec<String>, &'static str> {
+            procfs::list(self.table, self.reader, path)
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Task {
+        pub pid: Pid,
+        pub name: String,
+        pub state: String,
+        pub uid: u32,
+        pub threads: u32,
+        // Kilobytes
+        pub resident: u64,
+        pub reserved: u64,
+        // User and system time together
+        pub cpu_time: Duration,
+        pub syscalls: u64,
+    }
+
+    // Kilobytes
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct MemoryInfo {
+        pub total: u64,
+        pub free: u64,
+        pub used: u64,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Default)]
+    pub struct InterfaceCounters {
+        pub name: String,
+        pub rx_bytes: f64,
+        pub tx_bytes: f64,
+        pub rx_packets: f64,
+        pub tx_packets: f64,
+        // Dropped and failed, either way
+        pub errors: f64,
+    }
+
+    // Everything read from the source in one refresh
+    #[derive(Debug, Clone, PartialEq, Default)]
+    pub struct Snapshot {
+        pub taken: Duration,
+        pub uptime: Option<f64>,
+        pub tasks: Vec<Task>,
+        pub memory: Option<MemoryInfo>,
+        pub interfaces: Vec<InterfaceCounters>,
+        // Degrees Celsius by zone
+        pub thermal: Vec<(String, f64)>,
+    }
+
+    // "Key:\tvalue" lines, as the /proc files have them
+    fn fields(text: &str) -> BTreeMap<&str, &str> {
+        text.lines().filter_map(|line| line.split_once(':')).map(|(key, value)| (key.trim(), value.trim())).collect()
+    }
+
+    // The number in front of a unit, as in "120 kB"
+    fn number(fields: &BTreeMap<&str, &str>, key: &str) -> Option<u64> {
+        fields.get(key)?.split_whitespace().next()?.parse().ok()
+    }
+
+    fn task(source: &dyn ProcSource, pid: Pid) -> Option<Task> {
+        let status = source.read(&format!("/proc/{}/status", pid)).ok()?;
+        let usage = source.read(&format!("/proc/{}/usage", pid)).ok()?;
+        let (status, usage) = (fields(&status), fields(&usage));
+        let micros = number(&usage, "UserTime")? + number(&usage, "SystemTime")?;
+        Some(Task {
+            pid,
+            name: status.get("Name")?.to_string(),
+            state: status.get("State")?.to_string(),
+            uid: number(&status, "Uid")? as u32,
+            threads: number(&status, "Threads")? as u32,
+            resident: number(&status, "VmRSS")?,
+            reserved: number(&status, "VmSize")?,
+            cpu_time: Duration::from_micros(micros),
+            syscalls: number(&usage, "Syscalls")?,
+        })
+    }
+
+    pub type Labels = Vec<(String, String)>;
+
+    // A sample line of the Prometheus text format: name, labels and value
+    pub fn parse_sample(line: &str) -> Option<(&str, Labels, f64)> {
+        if line.starts_with('#') || line.trim().is_empty() {
+            return None;
+        }
+        let (series, value) = line.rsplit_once(' ')?;
+        let value = match value {
+            "+Inf" => f64::INFINITY,
+            "-Inf" => f64::NEG_INFINITY,
+            _ => value.parse().ok()?,
+        };
+        let Some((name, rest)) = series.split_once('{') else {
+            return Some((series, Vec::new(), value));
+        };
+        let mut labels = Vec::new();
+        let mut characters = rest.strip_suffix('}')?.chars();
+        loop {
+            let label: String = characters.by_ref().take_while(|character| *character != '=').collect();
+            if label.is_empty() {
+                break;
+            }
+            if characters.next() != Some('"') {
+                return None;
+            }
+            let mut text = String::new();
+            loop {
+                match characters.next()? {
+                    '"' => break,
+                    '\\' => match characters.next()? {
+                        'n' => text.push('\n'),
+                        escaped => text.push(escaped),
+                    },
+                    character => text.push(character),
+                }
+            }
+            labels.push((label.trim_start_matches(',').to_string(), text));
+        }
+        Some((name, labels, value))
+    }
+
+    impl Snapshot {
+        // Processes that exit while being read are left out
+        pub fn read(source: &dyn ProcSource, now: Duration) -> Result<Self, &'static str> {
+            let mut snapshot = Snapshot { taken: now, ..Default::default() };
+            for entry in source.list("/proc")? {
+                if let Some(task) = entry.parse().ok().and_then(|pid| task(source, pid)) {
+                    snapshot.tasks.push(task);
+                }
+            }
+            if let Ok(meminfo) = source.read("/proc/meminfo") {
+                let meminfo = fields(&meminfo);
+                snapshot.memory = Some(MemoryInfo {
+                    total: number(&meminfo, "MemTotal").unwrap_or(0),
+                    free: number(&meminfo, "MemFree").unwrap_or(0),
+                    used: number(&meminfo, "MemUsed").unwrap_or(0),
+                });
+            }
+            let metrics = source.read("/proc/metrics").unwrap_or_default();
+            let mut interfaces: BTreeMap<String, InterfaceCounters> = BTreeMap::new();
+            for (name, labels, value) in metrics.lines().filter_map(parse_sample) {
+                let label = |wanted: &str| labels.iter().find(|(label, _)| label == wanted).map(|(_, value)| value.clone());
+                match name {
+                    "vaelix_uptime_seconds" => snapshot.uptime = Some(value),
+                    "vaelix_thermal_zone_celsius" => snapshot.thermal.push((label("zone").unwrap_or_default(), value)),
+                    _ if name.starts_with("vaelix_net_") => {
+                        let Some(interface) = label("interface") else {
+                            continue;
+                        };
+                        let counters = interfaces.entry(interface.clone()).or_insert_with(|| InterfaceCounters { name: interface, ..Default::default() });
+                        match name {
+                            "vaelix_net_receive_bytes_total" => counters.rx_bytes = value,
+                            "vaelix_net_transmit_bytes_total" => counters.tx_bytes = value,
+                            "vaelix_net_receive_packets_total" => counters.rx_packets = value,
+                            "vaelix_net_transmit_packets_total" => counters.tx_packets = value,
+                            _ if name.ends_with("_dropped_total") || name.ends_with("_errors_total") => counters.errors += value,
+                            _ => {}
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            snapshot.interfaces = interfaces.into_values().collect();
+            Ok(snapshot)
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum SortKey {
+        Cpu,
+        Memory,
+        Pid,
+        Name,
+        Time,
+    }
+
+    impl SortKey {
+        fn name(&self) -> &'static str {
+            match self {
+                SortKey::Cpu => "%CPU",
+                SortKey::Memory => "RSS",
+                SortKey::Pid => "PID",
+                SortKey::Name => "NAME",
+                SortKey::Time => "TIME",
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Key {
+        Char(char),
+        Up,
+        Down,
+        Enter,
+        Escape,
+    }
+
+    // Keys in what a raw mode terminal sends; unknown escape sequences are
+    // skipped
+    pub fn parse_keys(bytes: &[u8]) -> Vec<Key> {
+        let text = String::from_utf8_lossy(bytes);
+        let mut characters = text.chars().peekable();
+        let mut keys = Vec::new();
+        while let Some(character) = characters.next() {
+            match character {
+                '\x1b' if characters.peek() == Some(&'[') => {
+                    characters.next();
+                    let Some(last) = characters.by_ref().find(|character| ('\x40'..='\x7e').contains(character)) else {
+                        break;
+                    };
+                    match last {
+                        'A' => keys.push(Key::Up),
+                        'B' => keys.push(Key::Down),
+                        _ => {}
+                    }
+                }
+                '\x1b' => keys.push(Key::Escape),
+                '\r' | '\n' => keys.push(Key::Enter),
+                _ => keys.push(Key::Char(character)),
+            }
+        }
+        keys
+    }
+
+    fn format_time(time: Duration) -> String {
+        let hundredths = time.as_millis() / 10;
+        format!("{}:{:02}.{:02}", hundredths / 6000, hundredths / 100 % 60, hundredths % 100)
+    }
+
+    fn format_rate(bytes: f64) -> String {
+        match bytes {
+            _ if bytes >= 1024.0 * 1024.0 => format!("{:.1} MiB/s", bytes / (1024.0 * 1024.0)),
+            _ if bytes >= 1024.0 => format!("{:.1} KiB/s", bytes / 1024.0),
+            _ => format!("{:.0} B/s", bytes),
+        }
+    }
+
+    // A top: the busiest tasks, memory, network and temperatures, refreshed
+    // from a ProcSource. Frames are plain lines so that a graphical front
+    // end can draw them too.
+    pub struct Top {
+        sort: SortKey,
+        reverse: bool,
+        selected: Option<Pid>,
+        // The task whose details are shown instead of the list
+        detail: Option<Pid>,
+        limits: Option<String>,
+        previous: Option<Snapshot>,
+        current: Option<Snapshot>,
+        running: bool,
+        // What the terminal was in before, once it is in raw mode
+        saved_mode: Option<LineMode>,
+    }
+
+    impl Default for Top {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl Top {
+        pub fn new() -> Self {
+            Top { sort: SortKey::Cpu, reverse: false, selected: None, detail: None, limits: None, previous: None, current: None, running: true, saved_mode: None }
+        }
+
+        pub fn sort(&self) -> SortKey {
+            self.sort
+        }
+
+        pub fn selected(&self) -> Option<Pid> {
+            self.selected
+        }
+
+        pub fn detail(&self) -> Option<Pid> {
+            self.detail
+        }
+
+        pub fn is_running(&self) -> bool {
+            self.running
+        }
+
+        pub fn snapshot(&self) -> Option<&Snapshot> {
+            self.current.as_ref()
+        }
+
+        pub fn refresh(&mut self, source: &dyn ProcSource, now: Duration) -> Result<(), &'static str> {
+            let snapshot = Snapshot::read(source, now)?;
+            self.previous = self.current.replace(snapshot);
+            self.limits = self.detail.and_then(|pid| source.read(&format!("/proc/{}/limits", pid)).ok());
+            if self.selected.is_none_or(|pid| !self.tasks().iter().any(|task| task.pid == pid)) {
+                self.selected = self.tasks().first().map(|task| task.pid);
+            }
+            Ok(())
+        }
+
+        // Seconds between the last two refreshes
+        fn interval(&self) -> Option<f64> {
+            let (previous, current) = (self.previous.as_ref()?, self.current.as_ref()?);
+            let interval = current.taken.checked_sub(previous.taken)?.as_secs_f64();
+            (interval > 0.0).then_some(interval)
+        }
+
+        // Of one CPU, over the last refresh
+        pub fn cpu_percent(&self, pid: Pid) -> f64 {
+            let Some(interval) = self.interval() else {
+                return 0.0;
+            };
+            let time = |snapshot: &Option<Snapshot>| snapshot.as_ref().and_then(|snapshot| snapshot.tasks.iter().find(|task| task.pid == pid)).map(|task| task.cpu_time);
+            match (time(&self.previous), time(&self.current)) {
+                (Some(before), Some(after)) => after.saturating_sub(before).as_secs_f64() / interval * 100.0,
+                _ => 0.0,
+            }
+        }
+
+        // Bytes a second received and sent, over the last refresh
+        pub fn interface_rate(&self, name: &str) -> (f64, f64) {
+            let Some(interval) = self.interval() else {
+                return (0.0, 0.0);
+            };
+            let counters = |snapshot: &Option<Snapshot>| snapshot.as_ref().and_then(|snapshot| snapshot.interfaces.iter().find(|interface| interface.name == name)).cloned();
+            match (counters(&self.previous), counters(&self.current)) {
+                (Some(before), Some(after)) => ((after.rx_bytes - before.rx_bytes).max(0.0) / interval, (after.tx_bytes - before.tx_bytes).max(0.0) / interval),
+                _ => (0.0, 0.0),
+            }
+        }
+
+        // In the order they are listed
+        pub fn tasks(&self) -> Vec<&Task> {
+            let mut tasks: Vec<&Task> = self.current.iter().flat_map(|snapshot| &snapshot.tasks).collect();
+            tasks.sort_by(|a, b| {
+                let order = match self.sort {
+                    SortKey::Cpu => self.cpu_percent(b.pid).partial_cmp(&self.cpu_percent(a.pid)).unwrap_or(Ordering::Equal),
+                    SortKey::Memory => b.resident.cmp(&a.resident),
+                    SortKey::Pid => Ordering::Equal,
+                    SortKey::Name => a.name.cmp(&b.name),
+                    SortKey::Time => b.cpu_time.cmp(&a.cpu_time),
+                };
+                let order = order.then(a.pid.cmp(&b.pid));
+                if self.reverse {
+                    order.reverse()
+                } else {
+                    order
+                }
+            });
+            tasks
+        }
+
+        fn select(&mut self, offset: isize) {
+            let pids: Vec<Pid> = self.tasks().iter().map(|task| task.pid).collect();
+            let Some(last) = pids.len().checked_sub(1) else {
+                return;
+            };
+            let index = self.selected.and_then(|pid| pids.iter().position(|other| *other == pid)).unwrap_or(0);
+            self.selected = Some(pids[index.saturating_add_signed(offset).min(last)]);
+        }
+
+        // P, M, N and T sort by CPU, memory, name and time, and r reverses;
+        // Enter shows the selected task and Escape goes back to the list
+        pub fn handle_key(&mut self, key: Key) {
+            match key {
+                Key::Char('q') => self.running = false,
+                Key::Char('P') => self.sort = SortKey::Cpu,
+                Key::Char('M') => self.sort = SortKey::Memory,
+                Key::Char('N') => self.sort = SortKey::Name,
+                Key::Char('T') => self.sort = SortKey::Time,
+                Key::Char('#') => self.sort = SortKey::Pid,
+                Key::Char('r') => self.reverse = !self.reverse,
+                Key::Up | Key::Char('k') if self.detail.is_none() => self.select(-1),
+                Key::Down | Key::Char('j') if self.detail.is_none() => self.select(1),
+                Key::Enter if self.detail.is_none() => self.detail = self.selected,
+                Key::Escape => {
+                    self.detail = None;
+                    self.limits = None;
+                }
+                _ => {}
+            }
+        }
+
+        fn summary(&self, snapshot: &Snapshot) -> Vec<String> {
+            let uptime = snapshot.uptime.map(|seconds| seconds as u64).unwrap_or(0);
+            let running = snapshot.tasks.iter().filter(|task| task.state.starts_with('R')).count();
+            let mut lines = vec![format!(
+                "vxtop - up {}:{:02}:{:02}, {} tasks, {} running, sorted by {}{}",
+                uptime / 3600,
+                uptime / 60 % 60,
+                uptime % 60,
+                snapshot.tasks.len(),
+                running,
+                self.sort.name(),
+                if self.reverse { " (reversed)" } else { "" }
+            )];
+            if let Some(memory) = snapshot.memory {
+                lines.push(format!("Mem: {} kB total, {} kB used, {} kB free", memory.total, memory.used, memory.free));
+            }
+            let interfaces: Vec<String> = snapshot
+                .interfaces
+                .iter()
+                .map(|interface| {
+                    let (rx, tx) = self.interface_rate(&interface.name);
+                    format!("{} rx {} tx {}", interface.name, format_rate(rx), format_rate(tx))
+                })
+                .collect();
+            if !interfaces.is_empty() {
+                lines.push(format!("Net: {}", interfaces.join(", ")));
+            }
+            let zones: Vec<String> = snapshot.thermal.iter().map(|(zone, celsius)| format!("{} {:.1}C", zone, celsius)).collect();
+            if !zones.is_empty() {
+                lines.push(format!("Thermal: {}", zones.join(", ")));
+            }
+            lines
+        }
+
+        fn task_lines(&self, height: usize) -> Vec<String> {
+            let mut lines = vec![format!("  {:>5} {:>5} {:<2} {:>6} {:>8} {:>9} {:>3} {}", "PID", "UID", "S", "%CPU", "RSS", "TIME", "THR", "NAME")];
+            let tasks = self.tasks();
+            let rows = height.saturating_sub(1).max(1);
+            // Scrolled so that the selection stays in view
+            let index = self.selected.and_then(|pid| tasks.iter().position(|task| task.pid == pid)).unwrap_or(0);
+            let first = (index + 1).saturating_sub(rows);
+            for task in tasks.iter().skip(first).take(rows) {
+                let marker = if Some(task.pid) == self.selected { '>' } else { ' ' };
+                lines.push(format!(
+                    "{} {:>5} {:>5} {:<2} {:>6.1} {:>8} {:>9} {:>3} {}",
+                    marker,
+                    task.pid,
+                    task.uid,
+                    task.state.chars().next().unwrap_or('?'),
+                    self.cpu_percent(task.pid),
+                    task.resident,
+                    format_time(task.cpu_time),
+                    task.threads,
+                    task.name
+                ));
+            }
+            lines
+        }
+
+        fn detail_lines(&self, snapshot: &Snapshot, pid: Pid) -> Vec<String> {
+            let Some(task) = snapshot.tasks.iter().find(|task| task.pid == pid) else {
+                return vec![format!("vxtop - process {} has exited", pid), String::new(), "Escape to go back".to_string()];
+            };
+            let mut lines = vec![
+                format!("vxtop - process {} ({})", task.pid, task.name),
+                format!("State: {}", task.state),
+                format!("User: {}", task.uid),
+                format!("Threads: {}", task.threads),
+                format!("CPU: {:.1}%, {} in all, {} system calls", self.cpu_percent(pid), format_time(task.cpu_time), task.syscalls),
+                format!("Memory: {} kB resident, {} kB reserved", task.resident, task.reserved),
+                String::new(),
+            ];
+            lines.extend(self.limits.iter().flat_map(|limits| limits.lines().map(str::to_string)));
+            lines
+        }
+
+        // One frame, at most height lines of at most width characters
+        pub fn render(&self, width: usize, height: usize) -> Vec<String> {
+            let Some(snapshot) = &self.current else {
+                return vec!["vxtop - reading /proc".to_string()];
+            };
+            let mut lines = match self.detail {
+                Some(pid) => self.detail_lines(snapshot, pid),
+                None => {
+                    let mut lines = self.summary(snapshot);
+                    lines.push(String::new());
+                    let rows = height.saturating_sub(lines.len());
+                    lines.extend(self.task_lines(rows));
+                    lines
+                }
+            };
+            lines.truncate(height);
+            lines.iter().map(|line| line.chars().take(width).collect()).collect()
+        }
+
+        // Takes input, refreshes and redraws, putting the terminal in raw
+        // mode first and back once q is pressed or input ends. Returns
+        // whether to carry on.
+        pub fn frame(&mut self, terminal: &PtySlave, source: &dyn ProcSource, now: Duration) -> Result<bool, &'static str> {
+            if self.saved_mode.is_none() {
+                self.saved_mode = Some(terminal.mode());
+                terminal.set_mode(LineMode { canonical: false, echo: false, signals: true });
+            }
+            match terminal.read()? {
+                Some(input) if input.is_empty() => self.running = false,
+                Some(input) => parse_keys(&input).into_iter().for_each(|key| self.handle_key(key)),
+                None => {}
+            }
+            if !self.running {
+                terminal.write(CLEAR.as_bytes())?;
+                if let Some(mode) = self.saved_mode.take() {
+                    terminal.set_mode(mode);
+                }
+                return Ok(false);
+            }
+            self.refresh(source, now)?;
+            let size = terminal.size();
+            let lines = self.render(size.cols as usize, size.rows as usize);
+            terminal.write(format!("{}{}", CLEAR, lines.join("\r\n")).as_bytes())?;
+            Ok(true)
+        }
+    }
+}
@@ -0,0 +1,278 @@
+// src/kernel/watchdog.rs
+
+pub mod watchdog {
+    use crate::kallsyms::kallsyms;
+    use crate::lockdep::lockdep;
+    use crate::metrics::metrics::{Counter, MetricType, Registry, Sample};
+    use crate::process::kthread::kthread::KernelThread;
+    use crate::sync::sync::SpinLock;
+    use crate::vxboot::vxboot::CommandLine;
+    use std::fmt::Write;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    // How long a watchdog task sleeps at most between touches, so that
+    // stopping it is quick
+    const TOUCH_INTERVAL: Duration = Duration::from_millis(100);
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct WatchdogConfig {
+        // The CPU's watchdog task not having run for this long: nothing is
+        // being scheduled there
+        pub soft_lockup: Duration,
+        // One task having had the CPU this long without switching away, as
+        // an RCU stall would show
+        pub stall: Duration,
+        // Interrupts a second on one CPU
+        pub irq_storm: u64,
+        // Panic on a lockup, and so write a crash dump, rather than carry on
+        pub panic: bool,
+    }
+
+    // As Linux with its default watchdog_thresh of 10
+    impl Default for WatchdogConfig {
+        fn default() -> Self {
+            WatchdogConfig { soft_lockup: Duration::from_secs(20), stall: Duration::from_secs(21), irq_storm: 100_000, panic: false }
+        }
+    }
+
+    impl WatchdogConfig {
+        // watchdog_thresh=seconds, softlockup_panic=1 and
+        // watchdog_irq_storm=rate; None for nowatchdog or a threshold of 0
+        pub fn from_command_line(command_line: &CommandLine) -> Result<Option<Self>, &'static str> {
+            if command_line.has("nowatchdog") {
+                return Ok(None);
+            }
+            let mut config = WatchdogConfig::default();
+            if let Some(threshold) = command_line.get("watchdog_thresh") {
+                let seconds: u64 = threshold.parse().map_err(|_| "Invalid watchdog threshold")?;
+                if seconds == 0 {
+                    return Ok(None);
+                }
+                config.soft_lockup = Duration::from_secs(seconds * 2);
+                config.stall = Duration::from_secs(seconds * 2 + 1);
+            }
+            if let Some(panic) = command_line.get("softlockup_panic") {
+                config.panic = panic != "0";
+            }
+            if let Some(rate) = command_line.get("watchdog_irq_storm") {
+                config.irq_storm = rate.parse().map_err(|_| "Invalid interrupt storm rate")?;
+            }
+            Ok(Some(config))
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum LockupKind {
+        SoftLockup,
+        Stall,
+        IrqStorm { rate: u64 },
+    }
+
+    // What the watchdog found on a CPU, with what that CPU was doing
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Lockup {
+        pub cpu: usize,
+        pub kind: LockupKind,
+        pub duration: Duration,
+        pub task: Option<String>,
+        pub locks: Vec<&'static str>,
+        pub backtrace: Vec<String>,
+    }
+
+    impl Lockup {
+        pub fn headline(&self) -> String {
+            match self.kind {
+                LockupKind::SoftLockup => format!("watchdog: BUG: soft lockup - CPU#{} stuck for {}s!", self.cpu, self.duration.as_secs()),
+                LockupKind::Stall => format!("watchdog: CPU#{} stalled: no context switch for {}s", self.cpu, self.duration.as_secs()),
+                LockupKind::IrqStorm { rate } => format!("watchdog: interrupt storm on CPU#{}: {} a second", self.cpu, rate),
+            }
+        }
+
+        pub fn report(&self) -> String {
+            let mut report = self.headline();
+            writeln!(report).unwrap();
+            writeln!(report, "task: {}", self.task.as_deref().unwrap_or("idle")).unwrap();
+            match self.locks.is_empty() {
+                true => writeln!(report, "locks held: none").unwrap(),
+                false => writeln!(report, "locks held: {}", self.locks.join(", ")).unwrap(),
+            }
+            writeln!(report, "backtrace:").unwrap();
+            for frame in &self.backtrace {
+                writeln!(report, "    {}", frame).unwrap();
+            }
+            report
+        }
+    }
+
+    struct CpuState {
+        // When its watchdog task last ran
+        touched: Instant,
+        current: Option<String>,
+        switched: Instant,
+        irqs: u64,
+        window: Instant,
+        // Each is reported once until the CPU recovers
+        soft_lockup_reported: bool,
+        stall_reported: bool,
+        storm_reported: bool,
+    }
+
+    // Notices CPUs that have stopped scheduling, tasks that will not let go
+    // of a CPU and interrupt storms, rather than leaving the machine to hang
+    // silently
+    pub struct Watchdog {
+        config: WatchdogConfig,
+        // Taken from interrupt handlers
+        cpus: Vec<SpinLock<CpuState>>,
+        soft_lockups: Counter,
+        stalls: Counter,
+        irq_storms: Counter,
+    }
+
+    impl Watchdog {
+        pub fn new(config: WatchdogConfig, cpus: usize, now: Instant) -> Self {
+            let cpus = (0..cpus)
+                .map(|_| {
+                    SpinLock::new(
+                        "watchdog.cpu",
+                        CpuState {
+                            touched: now,
+                            current: None,
+                            switched: now,
+                            irqs: 0,
+                            window: now,
+                            soft_lockup_reported: false,
+                            stall_reported: false,
+                            storm_reported: false,
+                        },
+                    )
+                })
+                .collect();
+            Watchdog { config, cpus, soft_lockups: Counter::new(), stalls: Counter::new(), irq_storms: Counter::new() }
+        }
+
+        pub fn config(&self) -> WatchdogConfig {
+            self.config
+        }
+
+        pub fn cpus(&self) -> usize {
+            self.cpus.len()
+        }
+
+        // From the CPU's watchdog task, whenever it gets to run
+        pub fn touch(&self, cpu: usize, now: Instant) {
+            if let Some(state) = self.cpus.get(cpu) {
+                let mut state = state.lock_irqsave();
+                state.touched = now;
+                state.soft_lockup_reported = false;
+            }
+        }
+
+        // From the scheduler on a context switch, None when going idle. A
+        // switch is a quiescent state.
+        pub fn switch_to(&self, cpu: usize, task: Option<&str>, now: Instant) {
+            if let Some(state) = self.cpus.get(cpu) {
+                let mut state = state.lock_irqsave();
+                state.current = task.map(str::to_string);
+                state.switched = now;
+                state.stall_reported = false;
+            }
+        }
+
+        // From interrupt entry
+        pub fn interrupt(&self, cpu: usize) {
+            if let Some(state) = self.cpus.get(cpu) {
+                state.lock_irqsave().irqs += 1;
+            }
+        }
+
+        // From the CPU's own timer interrupt, so that the locks and the
+        // backtrace are those of the code it interrupted. Lockups are
+        // logged, and panic when configured to.
+        pub fn timer_interrupt(&self, cpu: usize, now: Instant) -> Vec<Lockup> {
+            let Some(state) = self.cpus.get(cpu) else {
+                return Vec::new();
+            };
+            let locks = lockdep::held();
+            let mut found = Vec::new();
+            let task = {
+                let mut state = state.lock_irqsave();
+                let stuck = now.saturating_duration_since(state.touched);
+                if stuck >= self.config.soft_lockup && !state.soft_lockup_reported {
+                    state.soft_lockup_reported = true;
+                    found.push((LockupKind::SoftLockup, stuck));
+                }
+                let running = now.saturating_duration_since(state.switched);
+                if state.current.is_some() && running >= self.config.stall && !state.stall_reported {
+                    state.stall_reported = true;
+                    found.push((LockupKind::Stall, running));
+                }
+                let window = now.saturating_duration_since(state.window);
+                if window >= Duration::from_secs(1) {
+                    let rate = state.irqs * 1000 / window.as_millis() as u64;
+                    if rate >= self.config.irq_storm && !state.storm_reported {
+                        found.push((LockupKind::IrqStorm { rate }, window));
+                    }
+                    state.storm_reported = rate >= self.config.irq_storm;
+                    state.irqs = 0;
+                    state.window = now;
+                }
+                state.current.clone()
+            };
+            if found.is_empty() {
+                return Vec::new();
+            }
+
+            let backtrace: Vec<String> = kallsyms::capture_backtrace().into_iter().map(kallsyms::symbolize).collect();
+            let lockups: Vec<Lockup> = found
+                .into_iter()
+                .map(|(kind, duration)| Lockup { cpu, kind, duration, task: task.clone(), locks: locks.clone(), backtrace: backtrace.clone() })
+                .collect();
+            for lockup in &lockups {
+                match lockup.kind {
+                    LockupKind::SoftLockup => self.soft_lockups.inc(),
+                    LockupKind::Stall => self.stalls.inc(),
+                    LockupKind::IrqStorm { .. } => self.irq_storms.inc(),
+                }
+                log::error!("{}", lockup.report());
+            }
+            // A storm is survivable; a CPU that has stopped is not
+            if self.config.panic {
+                if let Some(lockup) = lockups.iter().find(|lockup| !matches!(lockup.kind, LockupKind::IrqStorm { .. })) {
+                    panic!("{}", lockup.headline());
+                }
+            }
+            lockups
+        }
+
+        // Lockups found since boot, by kind
+        pub fn register_metrics(self: &Arc<Self>, registry: &Registry) -> Result<(), &'static str> {
+            let watchdog = self.clone();
+            registry.collect("vaelix_watchdog_lockups_total", "Lockups the watchdog has found.", MetricType::Counter, move || {
+                vec![
+                    Sample::new(watchdog.soft_lockups.get() as f64).with("kind", "soft_lockup"),
+                    Sample::new(watchdog.stalls.get() as f64).with("kind", "stall"),
+                    Sample::new(watchdog.irq_storms.get() as f64).with("kind", "irq_storm"),
+                ]
+            })
+        }
+
+        // A watchdog task per core. Each is starved along with everything
+        // else on a CPU that stops scheduling.
+        pub fn start(self: &Arc<Self>) -> Result<Vec<KernelThread>, &'static str> {
+            let interval = (self.config.soft_lockup / 5).min(TOUCH_INTERVAL);
+            (0..self.cpus.len())
+                .map(|cpu| {
+                    let watchdog = self.clone();
+                    KernelThread::spawn(&format!("watchdog/{}", cpu), move || {
+                        watchdog.touch(cpu, Instant::now());
+                        thread::sleep(interval);
+                        true
+                    })
+                })
+                .collect()
+        }
+    }
+}
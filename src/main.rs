@@ -1,16 +1,143 @@
+use std::io::Write;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use vaelix_core::drivers::uart::uart::SerialPort;
+
+// The serial console, which QEMU puts on its standard output
+struct Console;
+
+impl SerialPort for Console {
+    fn read_byte(&self) -> Option<u8> {
+        None
+    }
+
+    fn write_byte(&self, byte: u8) -> Result<(), &'static str> {
+        self.write_all(&[byte])
+    }
+
+    fn write_all(&self, data: &[u8]) -> Result<(), &'static str> {
+        let mut stdout = std::io::stdout();
+        stdout.write_all(data).and_then(|_| stdout.flush()).map_err(|_| "Console write failed")
+    }
+}
+
 fn main() {
+    use vaelix_core::drivers::mmio::mmio::MmioRegion;
+    use vaelix_core::drivers::msr::msr::BootCpu;
+    use vaelix_core::drivers::pci::pci;
+    use vaelix_core::drivers::pci_pm::pci_pm;
+    use vaelix_core::drivers::port::port::PortSpace;
+    use vaelix_core::faultinject::faultinject;
+    use vaelix_core::irq::irq;
+    use vaelix_core::kconfig::kconfig::{self, Driver};
+    use vaelix_core::ktest::ktest;
+    use vaelix_core::latency::latency;
+    use vaelix_core::log::log::{self, SerialSink};
+    use vaelix_core::metrics::metrics;
+    use vaelix_core::percpu::percpu;
+    use vaelix_core::power::policy::policy;
+    use vaelix_core::power::runtime::runtime;
+    use vaelix_core::power::thermal::thermal;
+    use vaelix_core::rcu::rcu;
+    use vaelix_core::sync::sync;
+    use vaelix_core::topology::topology::Topology;
     use vaelix_core::vx_tasklet::vx_tasklet_init;
     use vaelix_core::vxchan::vxchan::vxchan_init;
-    use vaelix_core::vxboot::vxboot::boot;
+    use vaelix_core::vxboot::vxboot::{self, boot, measured_boot, CommandLine};
+    use vaelix_core::watchdog::watchdog::{Watchdog, WatchdogConfig};
+
+    // As the bootloader passes it
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    let command_line = CommandLine::parse(&arguments.join(" ")).expect("Invalid kernel command line");
+
+    // Checked when it was built
+    let config = kconfig::ACTIVE;
+
+    // Kernel messages on the serial console, from the start of the boot
+    let logger = log::init();
+    if config.serial_console {
+        logger.add_sink("console", ::log::LevelFilter::Info, Box::new(SerialSink::new(Console))).expect("Failed to set up the console");
+    }
+    ::log::info!("Built for the {} profile", config.profile.name());
+
+    // Error paths to exercise, as asked for with fail_alloc= and the like
+    faultinject::configure_from_command_line(&command_line).expect("Invalid fault injection setting");
+    // And the interrupts whose path to their tasks to time, with latency_trace=
+    latency::tracer().configure_from_command_line(&command_line).expect("Invalid latency trace setting");
 
     // Initialize the tasklet scheduler
-    let scheduler = vx_tasklet_init();
+    let _scheduler = vx_tasklet_init();
 
     // Initialize the VXChan module
-    let vxchan_manager = vxchan_init().expect("Failed to initialize VXChan");
+    let _vxchan_manager = vxchan_init().expect("Failed to initialize VXChan");
+
+    // Start the boot process, measuring the kernel and initramfs into the
+    // TPM first where its driver is configured and there is one
+    let (kernel_image, initramfs) = vxboot::load_boot_images(&command_line).expect("Failed to read the boot images");
+    match config.has(Driver::Tpm).then(vxboot::probe_tpm).flatten() {
+        Some(tpm) => {
+            let log = measured_boot(&tpm, &kernel_image, &initramfs).expect("Failed to boot the system");
+            ::log::info!("Measured {} boot components", log.events().len());
+        }
+        None => boot().expect("Failed to boot the system"),
+    }
+
+    // vxtest on the command line runs the in-kernel suites instead of the
+    // system, then powers off with the result
+    if let Some(suites) = ktest::selected_suites(&command_line) {
+        let mut cases = ktest::cases();
+        cases.extend(vaelix_networking::ktest::ktest::cases());
+        let report = ktest::run(&cases, &suites, &Console);
+        ktest::exit(&PortSpace::new(), &report);
+    }
+
+    // The power policy's frequency estimate scales from the maximum the
+    // CPU reports
+    let topology = Topology::discover(&BootCpu);
+    if let Some(mhz) = topology.max_frequency_mhz {
+        policy::policy_manager().set_max_frequency(mhz);
+    }
+
+    // WiFi, audio and GPU functions drop to D3hot while nothing uses them
+    for (name, config) in pci::scan_bus(0, |offset| MmioRegion::new(pci::ECAM_BASE + offset, pci::ECAM_FUNCTION_SIZE)) {
+        match pci_pm::register_runtime(runtime::runtime_pm(), &name, config) {
+            Ok(Some(class)) => ::log::info!("{}: runtime PM as {:?}", name, class),
+            Ok(None) => {}
+            Err(error) => ::log::warn!("{}: no runtime PM: {}", name, error),
+        }
+    }
+    runtime::start_idle_timer(runtime::IDLE_TIMER_INTERVAL);
+
+    // Where the profile has it or the command line asks for it. Hosted,
+    // the kernel runs on the one CPU.
+    let watchdog = WatchdogConfig::from_command_line(&command_line)
+        .expect("Invalid watchdog setting")
+        .filter(|_| config.watchdog || command_line.has("watchdog"))
+        .map(|config| Arc::new(Watchdog::new(config, 1, Instant::now())));
+    let _watchdog_tasks = watchdog.as_ref().map(|watchdog| watchdog.start().expect("Failed to start the watchdog"));
 
-    // Start the boot process
-    boot().expect("Failed to boot the system");
+    // Scraped through /proc/metrics, or over the network by the exporter
+    let registry = metrics::registry();
+    metrics::register_kernel_metrics(registry).expect("Failed to register kernel metrics");
+    faultinject::register_metrics(registry).expect("Failed to register fault injection metrics");
+    thermal::register_metrics(registry, thermal::registry()).expect("Failed to register thermal metrics");
+    latency::tracer().register_metrics(registry).expect("Failed to register latency metrics");
+    percpu::register_metrics(registry).expect("Failed to register per-CPU metrics");
+    rcu::register_metrics(registry).expect("Failed to register RCU metrics");
+    irq::controller().register_metrics(registry).expect("Failed to register interrupt metrics");
+    if let Some(watchdog) = &watchdog {
+        watchdog.register_metrics(registry).expect("Failed to register watchdog metrics");
+    }
 
-    loop {} // Kernel main loop
+    loop {
+        // Kernel main loop
+        thread::sleep(Duration::from_millis(10));
+        if let Some(watchdog) = &watchdog {
+            sync::hardirq(|| watchdog.timer_interrupt(0, Instant::now()));
+        }
+        // Devices whose vectors were masked for storming still get seen to
+        sync::hardirq(|| irq::controller().poll(Instant::now()));
+        irq::controller().run_threads(0);
+    }
 }
@@ -0,0 +1,526 @@
+// src/networking/bridge.rs
+
+pub mod bridge {
+    use crate::netdev::netdev::{MacAddress, NetDevice};
+    use crate::packet::packet::ETHERNET_HEADER_LEN;
+    use std::collections::{BTreeMap, VecDeque};
+    use std::sync::{Arc, Mutex};
+
+    pub const DEFAULT_AGEING_MS: u64 = 300_000;
+    pub const DEFAULT_PRIORITY: u16 = 0x8000;
+    pub const DEFAULT_PORT_COST: u32 = 19;
+    pub const HELLO_MS: u64 = 2_000;
+    pub const MAX_AGE_MS: u64 = 20_000;
+    pub const FORWARD_DELAY_MS: u64 = 15_000;
+
+    const STP_GROUP: MacAddress = MacAddress([0x01, 0x80, 0xC2, 0x00, 0x00, 0x00]);
+    // 01:80:c2:00:00:0x is link-local and never forwarded
+    const LINK_LOCAL_PREFIX: [u8; 5] = [0x01, 0x80, 0xC2, 0x00, 0x00];
+    const LLC_STP: [u8; 3] = [0x42, 0x42, 0x03];
+    const BPDU_CONFIG: u8 = 0x00;
+    const BPDU_LEN: usize = 35;
+    const PORT_PRIORITY: u16 = 0x80;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct PortId(pub usize);
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum PortState {
+        Disabled,
+        Blocking,
+        // Learns addresses but forwards nothing until the forward delay ends
+        Learning,
+        Forwarding,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum PortRole {
+        Root,
+        Designated,
+        // Another bridge offers a better path to this segment
+        Blocked,
+    }
+
+    // 802.1D priority vector; lower is better, compared field by field
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    struct PriorityVector {
+        root: u64,
+        cost: u32,
+        bridge: u64,
+        port: u16,
+    }
+
+    struct Port {
+        device: Box<dyn NetDevice>,
+        number: u16,
+        cost: u32,
+        role: PortRole,
+        state: PortState,
+        state_since: u64,
+        // Best BPDU heard on the segment and when
+        received: Option<(PriorityVector, u64)>,
+    }
+
+    impl Port {
+        fn id(&self) -> u16 {
+            PORT_PRIORITY << 8 | self.number
+        }
+
+        fn forwarding(&self) -> bool {
+            self.state == PortState::Forwarding
+        }
+    }
+
+    struct BridgeState {
+        name: String,
+        mac: MacAddress,
+        priority: u16,
+        stp: bool,
+        ageing: u64,
+        ports: BTreeMap<PortId, Port>,
+        next_port: usize,
+        fdb: BTreeMap<MacAddress, (PortId, u64)>,
+        root: PriorityVector,
+        root_port: Option<PortId>,
+        last_hello: Option<u64>,
+        to_host: VecDeque<Vec<u8>>,
+        now: u64,
+    }
+
+    impl BridgeState {
+        fn bridge_id(&self) -> u64 {
+            let mut id = [0u8; 8];
+            id[..2].copy_from_slice(&self.priority.to_be_bytes());
+            id[2..].copy_from_slice(&self.mac.0);
+            u64::from_be_bytes(id)
+        }
+
+        fn set_state(port: &mut Port, state: PortState, now: u64) {
+            if port.state != state {
+                port.state = state;
+                port.state_since = now;
+            }
+        }
+
+        // Elects the root and assigns port roles from the BPDUs heard
+        fn recompute(&mut self) {
+            let own = self.bridge_id();
+            let now = self.now;
+            let mut root = PriorityVector {
+                root: own,
+                cost: 0,
+                bridge: own,
+                port: 0,
+            };
+            let mut root_port = None;
+            let mut root_port_id = u16::MAX;
+            if self.stp {
+                for (id, port) in &self.ports {
+                    let Some((heard, _)) = port.received else {
+                        continue;
+                    };
+                    if port.state == PortState::Disabled || heard.bridge == own {
+                        continue;
+                    }
+                    let candidate = PriorityVector {
+                        cost: heard.cost + port.cost,
+                        ..heard
+                    };
+                    // Ties between ports on the same path go to the lower port
+                    if candidate.root < own && (candidate, port.id()) < (root, root_port_id) {
+                        root = candidate;
+                        root_port = Some(*id);
+                        root_port_id = port.id();
+                    }
+                }
+            }
+            let mut changed = Vec::new();
+            for (id, port) in &mut self.ports {
+                let role = if Some(*id) == root_port {
+                    PortRole::Root
+                } else {
+                    let ours = PriorityVector {
+                        root: root.root,
+                        cost: root.cost,
+                        bridge: own,
+                        port: port.id(),
+                    };
+                    match port.received {
+                        Some((heard, _)) if self.stp && heard < ours => PortRole::Blocked,
+                        _ => PortRole::Designated,
+                    }
+                };
+                if role != port.role {
+                    port.role = role;
+                    changed.push(*id);
+                }
+                match (port.state, role) {
+                    (PortState::Disabled, _) => {}
+                    (_, PortRole::Blocked) => Self::set_state(port, PortState::Blocking, now),
+                    (PortState::Blocking, _) => {
+                        let next = if self.stp { PortState::Learning } else { PortState::Forwarding };
+                        Self::set_state(port, next, now);
+                    }
+                    _ => {}
+                }
+            }
+            if root.root != self.root.root || root_port != self.root_port {
+                log::info!("Bridge {}: root {:016x} via {:?}", self.name, root.root, root_port);
+            }
+            self.root = root;
+            self.root_port = root_port;
+            // Stations behind a port that changed role may now be elsewhere
+            self.fdb.retain(|_, (port, _)| !changed.contains(port));
+        }
+
+        fn send_bpdus(&mut self) {
+            let own = self.bridge_id();
+            let (root, cost) = (self.root.root, self.root.cost);
+            let mac = self.mac;
+            for port in self.ports.values_mut() {
+                if port.role != PortRole::Designated || port.state == PortState::Disabled {
+                    continue;
+                }
+                let mut frame = Vec::with_capacity(ETHERNET_HEADER_LEN + LLC_STP.len() + BPDU_LEN);
+                frame.extend_from_slice(&STP_GROUP.0);
+                frame.extend_from_slice(&mac.0);
+                frame.extend_from_slice(&((LLC_STP.len() + BPDU_LEN) as u16).to_be_bytes());
+                frame.extend_from_slice(&LLC_STP);
+                frame.extend_from_slice(&[0, 0, 0, BPDU_CONFIG, 0]);
+                frame.extend_from_slice(&root.to_be_bytes());
+                frame.extend_from_slice(&cost.to_be_bytes());
+                frame.extend_from_slice(&own.to_be_bytes());
+                frame.extend_from_slice(&port.id().to_be_bytes());
+                // Ages and timers, in 1/256 s
+                frame.extend_from_slice(&0u16.to_be_bytes());
+                for timer in [MAX_AGE_MS, HELLO_MS, FORWARD_DELAY_MS] {
+                    frame.extend_from_slice(&((timer * 256 / 1000) as u16).to_be_bytes());
+                }
+                let _ = port.device.transmit(&frame);
+            }
+        }
+
+        fn receive_bpdu(&mut self, id: PortId, frame: &[u8]) {
+            let Some(bpdu) = frame.get(ETHERNET_HEADER_LEN..) else {
+                return;
+            };
+            if !self.stp || bpdu.len() < LLC_STP.len() + BPDU_LEN || bpdu[..3] != LLC_STP || bpdu[6] != BPDU_CONFIG {
+                return;
+            }
+            let field = |offset: usize, len: usize| {
+                bpdu[3 + offset..3 + offset + len]
+                    .iter()
+                    .fold(0u64, |value, byte| value << 8 | *byte as u64)
+            };
+            let heard = PriorityVector {
+                root: field(5, 8),
+                cost: field(13, 4) as u32,
+                bridge: field(17, 8),
+                port: field(25, 2) as u16,
+            };
+            let now = self.now;
+            let Some(port) = self.ports.get_mut(&id) else {
+                return;
+            };
+            // Keep the best vector on the segment, refreshing it when the
+            // same designated port repeats itself
+            let better = match port.received {
+                Some((current, _)) => heard <= current || (heard.bridge, heard.port) == (current.bridge, current.port),
+                None => true,
+            };
+            if better {
+                port.received = Some((heard, now));
+                self.recompute();
+            }
+        }
+
+        fn transmit_to(&mut self, id: PortId, frame: &[u8]) {
+            if let Some(port) = self.ports.get_mut(&id) {
+                if port.forwarding() {
+                    let _ = port.device.transmit(frame);
+                }
+            }
+        }
+
+        fn flood(&mut self, except: Option<PortId>, frame: &[u8]) {
+            for (id, port) in &mut self.ports {
+                if Some(*id) != except && port.forwarding() {
+                    let _ = port.device.transmit(frame);
+                }
+            }
+        }
+
+        fn poll(&mut self, now: u64) {
+            self.now = now;
+            let ageing = self.ageing;
+            self.fdb.retain(|_, (_, seen)| now.saturating_sub(*seen) < ageing);
+            let mut dirty = false;
+            for port in self.ports.values_mut() {
+                let up = port.device.link_up();
+                match (port.state, up) {
+                    (PortState::Disabled, true) => {
+                        Self::set_state(port, PortState::Blocking, now);
+                        dirty = true;
+                    }
+                    (PortState::Disabled, false) => {}
+                    (_, false) => {
+                        Self::set_state(port, PortState::Disabled, now);
+                        port.received = None;
+                        dirty = true;
+                    }
+                    (PortState::Learning, true) if now.saturating_sub(port.state_since) >= FORWARD_DELAY_MS => {
+                        Self::set_state(port, PortState::Forwarding, now);
+                    }
+                    _ => {}
+                }
+                if port.received.is_some_and(|(_, heard_at)| now.saturating_sub(heard_at) >= MAX_AGE_MS) {
+                    port.received = None;
+                    dirty = true;
+                }
+            }
+            if dirty {
+                self.recompute();
+                let disabled: Vec<PortId> = self
+                    .ports
+                    .iter()
+                    .filter(|(_, port)| port.state == PortState::Disabled)
+                    .map(|(id, _)| *id)
+                    .collect();
+                self.fdb.retain(|_, (port, _)| !disabled.contains(port));
+            }
+            if self.stp && self.last_hello.is_none_or(|last| now.saturating_sub(last) >= HELLO_MS) {
+                self.last_hello = Some(now);
+                self.send_bpdus();
+            }
+        }
+    }
+
+    // Learning L2 bridge with spanning-tree loop avoidance. Port drivers
+    // hand received frames to receive(); the bridge itself joins the stack
+    // through device() and its host-bound frames are drained with
+    // take_host_frame() into NetStack::receive().
+    #[derive(Clone)]
+    pub struct Bridge {
+        state: Arc<Mutex<BridgeState>>,
+    }
+
+    impl Bridge {
+        pub fn new(name: &str, mac: MacAddress) -> Self {
+            Bridge {
+                state: Arc::new(Mutex::new(BridgeState {
+                    name: name.to_string(),
+                    mac,
+                    priority: DEFAULT_PRIORITY,
+                    stp: true,
+                    ageing: DEFAULT_AGEING_MS,
+                    ports: BTreeMap::new(),
+                    next_port: 0,
+                    fdb: BTreeMap::new(),
+                    root: PriorityVector {
+                        root: u64::MAX,
+                        cost: 0,
+                        bridge: u64::MAX,
+                        port: 0,
+                    },
+                    root_port: None,
+                    last_hello: None,
+                    to_host: VecDeque::new(),
+                    now: 0,
+                })),
+            }
+        }
+
+        pub fn device(&self) -> BridgeDevice {
+            BridgeDevice {
+                name: self.state.lock().unwrap().name.clone(),
+                state: Arc::clone(&self.state),
+            }
+        }
+
+        // Ports start out learning; they forward once the forward delay
+        // has passed without the spanning tree blocking them
+        pub fn add_port(&self, mut device: Box<dyn NetDevice>) -> Result<PortId, &'static str> {
+            if !device.bridgeable() {
+                return Err("Device cannot be bridged");
+            }
+            let mut state = self.state.lock().unwrap();
+            if state.ports.len() >= 0xFF {
+                return Err("Too many bridge ports");
+            }
+            device.set_promiscuous(true)?;
+            let id = PortId(state.next_port);
+            state.next_port += 1;
+            log::info!("Bridge {}: adding port {}", state.name, device.name());
+            let now = state.now;
+            let initial = if state.stp { PortState::Learning } else { PortState::Forwarding };
+            state.ports.insert(
+                id,
+                Port {
+                    device,
+                    number: (id.0 % 0xFF) as u16 + 1,
+                    cost: DEFAULT_PORT_COST,
+                    role: PortRole::Designated,
+                    state: initial,
+                    state_since: now,
+                    received: None,
+                },
+            );
+            state.recompute();
+            Ok(id)
+        }
+
+        pub fn remove_port(&self, id: PortId) -> Result<Box<dyn NetDevice>, &'static str> {
+            let mut state = self.state.lock().unwrap();
+            let mut port = state.ports.remove(&id).ok_or("Port not found")?;
+            state.fdb.retain(|_, (port, _)| *port != id);
+            state.recompute();
+            port.device.set_promiscuous(false)?;
+            Ok(port.device)
+        }
+
+        // Without STP every port forwards at once; only safe on loop-free
+        // topologies
+        pub fn set_stp(&self, enabled: bool) {
+            let mut state = self.state.lock().unwrap();
+            state.stp = enabled;
+            let now = state.now;
+            for port in state.ports.values_mut() {
+                port.received = None;
+                if port.state != PortState::Disabled {
+                    let initial = if enabled { PortState::Learning } else { PortState::Forwarding };
+                    BridgeState::set_state(port, initial, now);
+                }
+            }
+            state.recompute();
+        }
+
+        pub fn set_priority(&self, priority: u16) {
+            let mut state = self.state.lock().unwrap();
+            state.priority = priority;
+            state.recompute();
+        }
+
+        pub fn set_port_cost(&self, id: PortId, cost: u32) -> Result<(), &'static str> {
+            let mut state = self.state.lock().unwrap();
+            state.ports.get_mut(&id).ok_or("Port not found")?.cost = cost.max(1);
+            state.recompute();
+            Ok(())
+        }
+
+        pub fn set_ageing(&self, ageing: u64) {
+            self.state.lock().unwrap().ageing = ageing;
+        }
+
+        pub fn port_state(&self, id: PortId) -> Option<(PortRole, PortState)> {
+            let state = self.state.lock().unwrap();
+            state.ports.get(&id).map(|port| (port.role, port.state))
+        }
+
+        pub fn is_root(&self) -> bool {
+            self.state.lock().unwrap().root_port.is_none()
+        }
+
+        // Learned stations and the port each was last seen on
+        pub fn fdb(&self) -> Vec<(MacAddress, PortId)> {
+            let state = self.state.lock().unwrap();
+            state.fdb.iter().map(|(mac, (port, _))| (*mac, *port)).collect()
+        }
+
+        pub fn receive(&self, id: PortId, frame: &[u8], now: u64) -> Result<(), &'static str> {
+            if frame.len() < ETHERNET_HEADER_LEN {
+                return Err("Truncated frame");
+            }
+            let destination = MacAddress(frame[..6].try_into().unwrap());
+            let source = MacAddress(frame[6..12].try_into().unwrap());
+            let mut state = self.state.lock().unwrap();
+            state.now = now;
+            let port_state = state.ports.get(&id).ok_or("Port not found")?.state;
+            if destination.0[..5] == LINK_LOCAL_PREFIX {
+                if destination == STP_GROUP && port_state != PortState::Disabled {
+                    state.receive_bpdu(id, frame);
+                }
+                return Ok(());
+            }
+            if matches!(port_state, PortState::Disabled | PortState::Blocking) {
+                return Ok(());
+            }
+            // Our own address coming back in means a loop, not a station
+            if !source.is_multicast() && source != state.mac {
+                state.fdb.insert(source, (id, now));
+            }
+            if port_state == PortState::Learning {
+                return Ok(());
+            }
+            if destination.is_multicast() {
+                state.flood(Some(id), frame);
+                state.to_host.push_back(frame.to_vec());
+            } else if destination == state.mac {
+                state.to_host.push_back(frame.to_vec());
+            } else {
+                match state.fdb.get(&destination).map(|(port, _)| *port) {
+                    Some(port) if port == id => {}
+                    Some(port) => state.transmit_to(port, frame),
+                    None => state.flood(Some(id), frame),
+                }
+            }
+            Ok(())
+        }
+
+        pub fn take_host_frame(&self) -> Option<Vec<u8>> {
+            self.state.lock().unwrap().to_host.pop_front()
+        }
+
+        // Ages the forwarding database, advances port states, tracks port
+        // carrier and sends hello BPDUs
+        pub fn poll(&self, now: u64) {
+            self.state.lock().unwrap().poll(now);
+        }
+    }
+
+    // The bridge as seen by the stack: frames it transmits are switched
+    // to the ports like any other station's
+    pub struct BridgeDevice {
+        name: String,
+        state: Arc<Mutex<BridgeState>>,
+    }
+
+    impl NetDevice for BridgeDevice {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        fn mac_address(&self) -> MacAddress {
+            self.state.lock().unwrap().mac
+        }
+
+        fn mtu(&self) -> usize {
+            let state = self.state.lock().unwrap();
+            state.ports.values().map(|port| port.device.mtu()).min().unwrap_or(1500)
+        }
+
+        fn transmit(&mut self, frame: &[u8]) -> Result<(), &'static str> {
+            if frame.len() < ETHERNET_HEADER_LEN {
+                return Err("Truncated frame");
+            }
+            let destination = MacAddress(frame[..6].try_into().unwrap());
+            let mut state = self.state.lock().unwrap();
+            match state.fdb.get(&destination).map(|(port, _)| *port) {
+                Some(port) if !destination.is_multicast() => state.transmit_to(port, frame),
+                _ => state.flood(None, frame),
+            }
+            Ok(())
+        }
+
+        // Carrier follows the ports: up while any of them forwards
+        fn link_up(&self) -> bool {
+            self.state.lock().unwrap().ports.values().any(Port::forwarding)
+        }
+
+        fn set_mac_address(&mut self, mac: MacAddress) -> Result<(), &'static str> {
+            let mut state = self.state.lock().unwrap();
+            state.mac = mac;
+            state.recompute();
+            Ok(())
+        }
+    }
+}
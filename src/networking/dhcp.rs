@@ -0,0 +1,672 @@
+// src/networking/dhcp.rs
+
+pub mod dhcp {
+    use crate::netdev::netdev::MacAddress;
+    use crate::packet::packet::IP_PROTO_UDP;
+    use crate::udp::udp::{build_udp, UdpHeader, UdpSocketHandle, UdpStack};
+    use crate::vxnet_core::vxnet_core::{InterfaceId, IpCidr, LinkEvent, NetStack, Route, RouteProtocol};
+    use std::collections::BTreeMap;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    pub const DHCP_SERVER_PORT: u16 = 67;
+    pub const DHCP_CLIENT_PORT: u16 = 68;
+
+    pub const DHCPDISCOVER: u8 = 1;
+    pub const DHCPOFFER: u8 = 2;
+    pub const DHCPREQUEST: u8 = 3;
+    pub const DHCPDECLINE: u8 = 4;
+    pub const DHCPACK: u8 = 5;
+    pub const DHCPNAK: u8 = 6;
+    pub const DHCPRELEASE: u8 = 7;
+
+    const BOOTREQUEST: u8 = 1;
+    const BOOTREPLY: u8 = 2;
+    const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+    const FIXED_LEN: usize = 236;
+    const BROADCAST_FLAG: u16 = 0x8000;
+
+    const OPTION_PAD: u8 = 0;
+    const OPTION_SUBNET_MASK: u8 = 1;
+    const OPTION_ROUTER: u8 = 3;
+    const OPTION_DNS: u8 = 6;
+    const OPTION_HOSTNAME: u8 = 12;
+    const OPTION_MTU: u8 = 26;
+    const OPTION_REQUESTED_IP: u8 = 50;
+    const OPTION_LEASE_TIME: u8 = 51;
+    const OPTION_MESSAGE_TYPE: u8 = 53;
+    const OPTION_SERVER_ID: u8 = 54;
+    const OPTION_PARAMETER_LIST: u8 = 55;
+    const OPTION_RENEWAL_TIME: u8 = 58;
+    const OPTION_REBINDING_TIME: u8 = 59;
+    const OPTION_CLIENT_ID: u8 = 61;
+    const OPTION_CLASSLESS_ROUTES: u8 = 121;
+    const OPTION_END: u8 = 255;
+
+    const INITIAL_RETRY_MS: u64 = 4_000;
+    const MAX_RETRY_MS: u64 = 64_000;
+    const MIN_RENEW_RETRY_MS: u64 = 60_000;
+    pub const DEFAULT_ROUTE_METRIC: u32 = 100;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct DhcpMessage {
+        pub op: u8,
+        pub xid: u32,
+        pub secs: u16,
+        pub flags: u16,
+        pub ciaddr: Ipv4Addr,
+        pub yiaddr: Ipv4Addr,
+        pub siaddr: Ipv4Addr,
+        pub giaddr: Ipv4Addr,
+        pub chaddr: MacAddress,
+        pub options: BTreeMap<u8, Vec<u8>>,
+    }
+
+    impl DhcpMessage {
+        fn request(xid: u32, chaddr: MacAddress, kind: u8) -> Self {
+            let mut options = BTreeMap::new();
+            options.insert(OPTION_MESSAGE_TYPE, vec![kind]);
+            let mut client_id = vec![1];
+            client_id.extend_from_slice(&chaddr.0);
+            options.insert(OPTION_CLIENT_ID, client_id);
+            DhcpMessage {
+                op: BOOTREQUEST,
+                xid,
+                secs: 0,
+                flags: 0,
+                ciaddr: Ipv4Addr::UNSPECIFIED,
+                yiaddr: Ipv4Addr::UNSPECIFIED,
+                siaddr: Ipv4Addr::UNSPECIFIED,
+                giaddr: Ipv4Addr::UNSPECIFIED,
+                chaddr,
+                options,
+            }
+        }
+
+        pub fn message_type(&self) -> Option<u8> {
+            self.options.get(&OPTION_MESSAGE_TYPE).and_then(|value| value.first().copied())
+        }
+
+        fn address_option(&self, code: u8) -> Option<Ipv4Addr> {
+            self.address_list(code).into_iter().next()
+        }
+
+        fn address_list(&self, code: u8) -> Vec<Ipv4Addr> {
+            self.options.get(&code).map_or_else(Vec::new, |value| {
+                value
+                    .chunks_exact(4)
+                    .map(|chunk| Ipv4Addr::new(chunk[0], chunk[1], chunk[2], chunk[3]))
+                    .collect()
+            })
+        }
+
+        // Classless static routes (RFC 3442): prefix width, the significant
+        // destination octets, then the router; 0.0.0.0 means on-link
+        fn classless_routes(&self) -> Vec<(IpCidr, Option<Ipv4Addr>)> {
+            let mut routes = Vec::new();
+            let mut rest = match self.options.get(&OPTION_CLASSLESS_ROUTES) {
+                Some(value) => value.as_slice(),
+                None => return routes,
+            };
+            while let Some(&width) = rest.first() {
+                let significant = (width as usize).div_ceil(8);
+                if width > 32 || rest.len() < 1 + significant + 4 {
+                    return Vec::new();
+                }
+                let mut destination = [0u8; 4];
+                destination[..significant].copy_from_slice(&rest[1..1 + significant]);
+                let router = &rest[1 + significant..5 + significant];
+                let router = Ipv4Addr::new(router[0], router[1], router[2], router[3]);
+                if let Ok(cidr) = IpCidr::new(IpAddr::V4(Ipv4Addr::from(destination)), width) {
+                    routes.push((cidr.network(), Some(router).filter(|router| !router.is_unspecified())));
+                }
+                rest = &rest[5 + significant..];
+            }
+            routes
+        }
+
+        fn u32_option(&self, code: u8) -> Option<u32> {
+            let value = self.options.get(&code)?;
+            Some(u32::from_be_bytes(value.get(..4)?.try_into().ok()?))
+        }
+    }
+
+    pub fn parse_dhcp(data: &[u8]) -> Result<DhcpMessage, &'static str> {
+        if data.len() < FIXED_LEN + MAGIC_COOKIE.len() || data[FIXED_LEN..FIXED_LEN + 4] != MAGIC_COOKIE {
+            return Err("Not a DHCP message");
+        }
+        if data[1] != 1 || data[2] != 6 {
+            return Err("Unsupported DHCP hardware type");
+        }
+        let address = |offset: usize| Ipv4Addr::new(data[offset], data[offset + 1], data[offset + 2], data[offset + 3]);
+
+        let mut options = BTreeMap::new();
+        let mut rest = &data[FIXED_LEN + 4..];
+        while let Some(&code) = rest.first() {
+            match code {
+                OPTION_PAD => rest = &rest[1..],
+                OPTION_END => break,
+                _ => {
+                    let length = *rest.get(1).ok_or("Truncated DHCP option")? as usize;
+                    let value = rest.get(2..2 + length).ok_or("Truncated DHCP option")?;
+                    // Repeated options concatenate (RFC 3396)
+                    options.entry(code).or_insert_with(Vec::new).extend_from_slice(value);
+                    rest = &rest[2 + length..];
+                }
+            }
+        }
+
+        let mut chaddr = [0u8; 6];
+        chaddr.copy_from_slice(&data[28..34]);
+        Ok(DhcpMessage {
+            op: data[0],
+            xid: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            secs: u16::from_be_bytes([data[8], data[9]]),
+            flags: u16::from_be_bytes([data[10], data[11]]),
+            ciaddr: address(12),
+            yiaddr: address(16),
+            siaddr: address(20),
+            giaddr: address(24),
+            chaddr: MacAddress(chaddr),
+            options,
+        })
+    }
+
+    pub fn build_dhcp(message: &DhcpMessage) -> Vec<u8> {
+        let mut data = vec![0u8; FIXED_LEN];
+        data[0] = message.op;
+        data[1] = 1;
+        data[2] = 6;
+        data[4..8].copy_from_slice(&message.xid.to_be_bytes());
+        data[8..10].copy_from_slice(&message.secs.to_be_bytes());
+        data[10..12].copy_from_slice(&message.flags.to_be_bytes());
+        data[12..16].copy_from_slice(&message.ciaddr.octets());
+        data[16..20].copy_from_slice(&message.yiaddr.octets());
+        data[20..24].copy_from_slice(&message.siaddr.octets());
+        data[24..28].copy_from_slice(&message.giaddr.octets());
+        data[28..34].copy_from_slice(&message.chaddr.0);
+        data.extend_from_slice(&MAGIC_COOKIE);
+        // Message type goes first for servers that expect it there
+        let message_type = message.options.get(&OPTION_MESSAGE_TYPE);
+        let others = message.options.iter().filter(|(code, _)| **code != OPTION_MESSAGE_TYPE);
+        for (code, value) in message_type.map(|value| (&OPTION_MESSAGE_TYPE, value)).into_iter().chain(others) {
+            for chunk in value.chunks(255) {
+                data.push(*code);
+                data.push(chunk.len() as u8);
+                data.extend_from_slice(chunk);
+            }
+        }
+        data.push(OPTION_END);
+        // BOOTP minimum message size
+        if data.len() < 300 {
+            data.resize(300, 0);
+        }
+        data
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct DhcpLease {
+        pub address: Ipv4Addr,
+        pub prefix_len: u8,
+        pub router: Option<Ipv4Addr>,
+        pub dns_servers: Vec<Ipv4Addr>,
+        pub mtu: Option<u16>,
+        pub routes: Vec<(IpCidr, Option<Ipv4Addr>)>,
+        pub server: Ipv4Addr,
+        // Times in milliseconds relative to acquired_at
+        pub lease_time: u64,
+        pub renewal_time: u64,
+        pub rebinding_time: u64,
+        pub acquired_at: u64,
+    }
+
+    impl DhcpLease {
+        fn from_ack(message: &DhcpMessage, server: Ipv4Addr, now: u64) -> Self {
+            let lease_time = message.u32_option(OPTION_LEASE_TIME).unwrap_or(3600) as u64 * 1000;
+            let renewal_time = message
+                .u32_option(OPTION_RENEWAL_TIME)
+                .map_or(lease_time / 2, |seconds| seconds as u64 * 1000);
+            let rebinding_time = message
+                .u32_option(OPTION_REBINDING_TIME)
+                .map_or(lease_time * 7 / 8, |seconds| seconds as u64 * 1000);
+            let prefix_len = message
+                .address_option(OPTION_SUBNET_MASK)
+                .map_or(24, |mask| u32::from(mask).leading_ones() as u8);
+            let mtu = message
+                .options
+                .get(&OPTION_MTU)
+                .filter(|value| value.len() == 2)
+                .map(|value| u16::from_be_bytes([value[0], value[1]]))
+                .filter(|mtu| *mtu >= 576);
+            DhcpLease {
+                address: message.yiaddr,
+                prefix_len,
+                router: message.address_option(OPTION_ROUTER),
+                dns_servers: message.address_list(OPTION_DNS),
+                mtu,
+                routes: message.classless_routes(),
+                server,
+                lease_time,
+                renewal_time,
+                rebinding_time,
+                acquired_at: now,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum DhcpState {
+        Stopped,
+        Selecting,
+        Requesting,
+        Bound,
+        Renewing,
+        Rebinding,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum DhcpEvent {
+        Bound(DhcpLease),
+        Lost,
+    }
+
+    // Per-interface DHCP state machine (RFC 2131)
+    pub struct DhcpClient {
+        interface: InterfaceId,
+        mac: MacAddress,
+        hostname: Option<String>,
+        state: DhcpState,
+        xid: u32,
+        offer: Option<(Ipv4Addr, Ipv4Addr)>,
+        lease: Option<DhcpLease>,
+        started_at: u64,
+        retry_at: Option<u64>,
+        retry_interval: u64,
+        route_metric: u32,
+    }
+
+    impl DhcpClient {
+        pub fn new(interface: InterfaceId, mac: MacAddress) -> Self {
+            DhcpClient {
+                interface,
+                mac,
+                hostname: None,
+                state: DhcpState::Stopped,
+                xid: 0,
+                offer: None,
+                lease: None,
+                started_at: 0,
+                retry_at: None,
+                retry_interval: INITIAL_RETRY_MS,
+                route_metric: DEFAULT_ROUTE_METRIC,
+            }
+        }
+
+        pub fn set_hostname(&mut self, hostname: &str) {
+            self.hostname = Some(hostname.to_string());
+        }
+
+        // Applies to routes installed from the next lease
+        pub fn set_route_metric(&mut self, metric: u32) {
+            self.route_metric = metric;
+        }
+
+        pub fn state(&self) -> DhcpState {
+            self.state
+        }
+
+        pub fn lease(&self) -> Option<&DhcpLease> {
+            self.lease.as_ref()
+        }
+
+        fn next_xid(&mut self, now: u64) {
+            let [a, b, c, d, e, f] = self.mac.0;
+            let seed = u32::from_be_bytes([a ^ e, b ^ f, c, d]);
+            self.xid = seed.rotate_left(self.xid.count_ones() + 7) ^ (now as u32).wrapping_mul(0x9E37_79B9) ^ self.xid;
+        }
+
+        fn message(&self, kind: u8, now: u64) -> DhcpMessage {
+            let mut message = DhcpMessage::request(self.xid, self.mac, kind);
+            message.secs = (now.saturating_sub(self.started_at) / 1000).min(u16::MAX as u64) as u16;
+            if let Some(hostname) = &self.hostname {
+                message.options.insert(OPTION_HOSTNAME, hostname.as_bytes().to_vec());
+            }
+            if kind == DHCPDISCOVER || kind == DHCPREQUEST {
+                message.options.insert(
+                    OPTION_PARAMETER_LIST,
+                    vec![
+                        OPTION_SUBNET_MASK,
+                        OPTION_ROUTER,
+                        OPTION_DNS,
+                        OPTION_MTU,
+                        OPTION_LEASE_TIME,
+                        OPTION_RENEWAL_TIME,
+                        OPTION_REBINDING_TIME,
+                        OPTION_CLASSLESS_ROUTES,
+                    ],
+                );
+            }
+            message
+        }
+
+        fn transmit(
+            &self,
+            net: &mut NetStack,
+            message: &DhcpMessage,
+            source: Ipv4Addr,
+            destination: Ipv4Addr,
+        ) -> Result<(), &'static str> {
+            let (source, destination) = (IpAddr::V4(source), IpAddr::V4(destination));
+            let header = UdpHeader {
+                source_port: DHCP_CLIENT_PORT,
+                destination_port: DHCP_SERVER_PORT,
+            };
+            let data = build_udp(source, destination, &header, &build_dhcp(message));
+            if destination == IpAddr::V4(Ipv4Addr::BROADCAST) {
+                net.send_on_interface(self.interface, source, destination, IP_PROTO_UDP, &data, 64)
+            } else {
+                net.send_from(source, destination, IP_PROTO_UDP, &data, 64)
+            }
+        }
+
+        fn schedule_retry(&mut self, now: u64) {
+            self.retry_at = Some(now + self.retry_interval);
+            self.retry_interval = (self.retry_interval * 2).min(MAX_RETRY_MS);
+        }
+
+        fn send_discover(&mut self, net: &mut NetStack, now: u64) -> Result<(), &'static str> {
+            let mut message = self.message(DHCPDISCOVER, now);
+            message.flags = BROADCAST_FLAG;
+            if let Some(lease) = &self.lease {
+                message.options.insert(OPTION_REQUESTED_IP, lease.address.octets().to_vec());
+            }
+            self.schedule_retry(now);
+            self.transmit(net, &message, Ipv4Addr::UNSPECIFIED, Ipv4Addr::BROADCAST)
+        }
+
+        fn send_request(&mut self, net: &mut NetStack, now: u64) -> Result<(), &'static str> {
+            let mut message = self.message(DHCPREQUEST, now);
+            match self.state {
+                DhcpState::Requesting => {
+                    let (address, server) = self.offer.ok_or("No offer to request")?;
+                    message.flags = BROADCAST_FLAG;
+                    message.options.insert(OPTION_REQUESTED_IP, address.octets().to_vec());
+                    message.options.insert(OPTION_SERVER_ID, server.octets().to_vec());
+                    self.schedule_retry(now);
+                    self.transmit(net, &message, Ipv4Addr::UNSPECIFIED, Ipv4Addr::BROADCAST)
+                }
+                DhcpState::Renewing | DhcpState::Rebinding => {
+                    let lease = self.lease.as_ref().ok_or("No lease to renew")?;
+                    message.ciaddr = lease.address;
+                    let (address, server) = (lease.address, lease.server);
+                    // Retry at half the remaining time to the next deadline (RFC 2131 4.4.5)
+                    let deadline = if self.state == DhcpState::Renewing {
+                        lease.acquired_at + lease.rebinding_time
+                    } else {
+                        lease.acquired_at + lease.lease_time
+                    };
+                    self.retry_at = Some(now + (deadline.saturating_sub(now) / 2).max(MIN_RENEW_RETRY_MS));
+                    let destination = if self.state == DhcpState::Renewing {
+                        server
+                    } else {
+                        Ipv4Addr::BROADCAST
+                    };
+                    self.transmit(net, &message, address, destination)
+                }
+                _ => Err("No request pending"),
+            }
+        }
+
+        // Begins acquisition; called on link up
+        pub fn start(&mut self, net: &mut NetStack, now: u64) -> Result<(), &'static str> {
+            log::info!("Discovering on interface {}", self.interface.0);
+            self.state = DhcpState::Selecting;
+            self.started_at = now;
+            self.offer = None;
+            self.retry_interval = INITIAL_RETRY_MS;
+            self.next_xid(now);
+            self.send_discover(net, now)
+        }
+
+        // Drops any configuration; called on link down
+        pub fn stop(&mut self, net: &mut NetStack) -> Option<DhcpEvent> {
+            self.state = DhcpState::Stopped;
+            self.retry_at = None;
+            self.unconfigure(net)
+        }
+
+        pub fn release(&mut self, net: &mut NetStack, now: u64) -> Result<Option<DhcpEvent>, &'static str> {
+            if let Some(lease) = &self.lease {
+                let mut message = self.message(DHCPRELEASE, now);
+                message.ciaddr = lease.address;
+                message.options.insert(OPTION_SERVER_ID, lease.server.octets().to_vec());
+                self.transmit(net, &message, lease.address, lease.server)?;
+            }
+            Ok(self.stop(net))
+        }
+
+        fn configure(&mut self, net: &mut NetStack, lease: DhcpLease) -> Result<(), &'static str> {
+            if self.lease.as_ref().is_some_and(|current| current.address != lease.address) {
+                self.unconfigure(net);
+            }
+            let cidr = IpCidr::new(IpAddr::V4(lease.address), lease.prefix_len)?;
+            let configured = net
+                .interface(self.interface)
+                .is_some_and(|iface| iface.addresses().contains(&cidr));
+            if !configured {
+                net.add_address(self.interface, cidr)?;
+                log::info!(
+                    "Bound {}/{} on interface {} (router {:?}, dns {:?}, mtu {:?})",
+                    lease.address, lease.prefix_len, self.interface.0, lease.router, lease.dns_servers, lease.mtu
+                );
+            }
+            // Renewals may change these, so they are applied on every ack.
+            // Without option 26 the interface goes back to the link's MTU.
+            if let Err(err) = net.set_mtu(self.interface, lease.mtu.map(usize::from)) {
+                log::warn!("Ignoring leased MTU {:?}: {}", lease.mtu, err);
+            }
+            self.reconcile_routes(net, &lease)?;
+            net.set_dns_servers(self.interface, lease.dns_servers.iter().copied().map(IpAddr::V4).collect())?;
+            self.lease = Some(lease);
+            Ok(())
+        }
+
+        // Installs the lease's routes and removes DHCP routes it no longer
+        // has. Servers sending classless routes include the default route
+        // there; the router option is ignored (RFC 3442).
+        fn reconcile_routes(&self, net: &mut NetStack, lease: &DhcpLease) -> Result<(), &'static str> {
+            let leased = if lease.routes.is_empty() {
+                let default = IpCidr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0)?;
+                lease.router.map(|router| (default, Some(router))).into_iter().collect()
+            } else {
+                lease.routes.clone()
+            };
+            let routes: Vec<Route> = leased
+                .into_iter()
+                .map(|(destination, router)| Route {
+                    metric: self.route_metric,
+                    protocol: RouteProtocol::Dhcp,
+                    ..Route::new(destination.network(), router.map(IpAddr::V4), self.interface)
+                })
+                .collect();
+            let interface = self.interface;
+            net.fib_mut().retain_routes(|_, route| {
+                !(route.interface == interface && route.protocol == RouteProtocol::Dhcp) || routes.contains(route)
+            });
+            for route in routes {
+                if net.routes().contains(&route) {
+                    continue;
+                }
+                if let Err(err) = net.add_route(route) {
+                    log::warn!("Failed to install route to {:?}: {}", route.destination, err);
+                }
+            }
+            Ok(())
+        }
+
+        fn unconfigure(&mut self, net: &mut NetStack) -> Option<DhcpEvent> {
+            let lease = self.lease.take()?;
+            if let Ok(cidr) = IpCidr::new(IpAddr::V4(lease.address), lease.prefix_len) {
+                let _ = net.remove_address(self.interface, cidr);
+            }
+            if lease.mtu.is_some() {
+                let _ = net.set_mtu(self.interface, None);
+            }
+            let _ = net.set_dns_servers(self.interface, Vec::new());
+            let interface = self.interface;
+            net.fib_mut()
+                .retain_routes(|_, route| !(route.interface == interface && route.protocol == RouteProtocol::Dhcp));
+            log::warn!("Lease on interface {} lost", self.interface.0);
+            Some(DhcpEvent::Lost)
+        }
+
+        pub fn handle_packet(
+            &mut self,
+            net: &mut NetStack,
+            payload: &[u8],
+            now: u64,
+        ) -> Result<Option<DhcpEvent>, &'static str> {
+            let message = parse_dhcp(payload)?;
+            if message.op != BOOTREPLY || message.xid != self.xid || message.chaddr != self.mac {
+                return Ok(None);
+            }
+            let server = message.address_option(OPTION_SERVER_ID);
+            match (self.state, message.message_type()) {
+                (DhcpState::Selecting, Some(DHCPOFFER)) => {
+                    let server = server.ok_or("Offer without server identifier")?;
+                    self.offer = Some((message.yiaddr, server));
+                    self.state = DhcpState::Requesting;
+                    self.retry_interval = INITIAL_RETRY_MS;
+                    self.send_request(net, now)?;
+                    Ok(None)
+                }
+                (DhcpState::Requesting | DhcpState::Renewing | DhcpState::Rebinding, Some(DHCPACK)) => {
+                    let server = server
+                        .or(self.offer.map(|(_, server)| server))
+                        .or(self.lease.as_ref().map(|lease| lease.server))
+                        .ok_or("Ack without server identifier")?;
+                    let lease = DhcpLease::from_ack(&message, server, now);
+                    self.configure(net, lease.clone())?;
+                    self.state = DhcpState::Bound;
+                    self.offer = None;
+                    self.retry_at = None;
+                    Ok(Some(DhcpEvent::Bound(lease)))
+                }
+                (DhcpState::Requesting | DhcpState::Renewing | DhcpState::Rebinding, Some(DHCPNAK)) => {
+                    let lost = self.unconfigure(net);
+                    self.start(net, now)?;
+                    Ok(lost)
+                }
+                _ => Ok(None),
+            }
+        }
+
+        // Drives retransmission and the T1/T2/expiry lease timers
+        pub fn poll(&mut self, net: &mut NetStack, now: u64) -> Result<Option<DhcpEvent>, &'static str> {
+            if let Some(lease) = &self.lease {
+                let elapsed = now.saturating_sub(lease.acquired_at);
+                if elapsed >= lease.lease_time {
+                    let lost = self.unconfigure(net);
+                    self.start(net, now)?;
+                    return Ok(lost);
+                }
+                let next = match self.state {
+                    DhcpState::Bound | DhcpState::Renewing if elapsed >= lease.rebinding_time => {
+                        Some(DhcpState::Rebinding)
+                    }
+                    DhcpState::Bound if elapsed >= lease.renewal_time => Some(DhcpState::Renewing),
+                    _ => None,
+                };
+                if let Some(state) = next {
+                    self.state = state;
+                    self.next_xid(now);
+                    self.send_request(net, now)?;
+                    return Ok(None);
+                }
+            }
+            if self.retry_at.is_some_and(|retry_at| now >= retry_at) {
+                match self.state {
+                    DhcpState::Selecting => self.send_discover(net, now)?,
+                    DhcpState::Requesting if self.retry_interval > MAX_RETRY_MS / 2 => {
+                        // The offer went stale; start over
+                        self.start(net, now)?;
+                    }
+                    DhcpState::Requesting | DhcpState::Renewing | DhcpState::Rebinding => {
+                        self.send_request(net, now)?
+                    }
+                    _ => self.retry_at = None,
+                }
+            }
+            Ok(None)
+        }
+    }
+
+    // Runs a DHCP client on every interface whose link comes up
+    pub struct DhcpService {
+        socket: UdpSocketHandle,
+        clients: BTreeMap<InterfaceId, DhcpClient>,
+    }
+
+    impl DhcpService {
+        pub fn new(udp: &mut UdpStack) -> Result<Self, &'static str> {
+            Ok(DhcpService {
+                socket: udp.bind(None, DHCP_CLIENT_PORT)?,
+                clients: BTreeMap::new(),
+            })
+        }
+
+        pub fn client(&self, interface: InterfaceId) -> Option<&DhcpClient> {
+            self.clients.get(&interface)
+        }
+
+        pub fn client_mut(&mut self, interface: InterfaceId) -> Option<&mut DhcpClient> {
+            self.clients.get_mut(&interface)
+        }
+
+        pub fn handle_link_event(
+            &mut self,
+            net: &mut NetStack,
+            event: LinkEvent,
+            now: u64,
+        ) -> Result<Option<DhcpEvent>, &'static str> {
+            if event.up {
+                let mac = net.interface(event.interface).ok_or("Interface not found")?.mac_address();
+                let client = self
+                    .clients
+                    .entry(event.interface)
+                    .or_insert_with(|| DhcpClient::new(event.interface, mac));
+                client.start(net, now)?;
+                Ok(None)
+            } else {
+                Ok(self.clients.get_mut(&event.interface).and_then(|client| client.stop(net)))
+            }
+        }
+
+        // Feeds received replies to their clients and runs timers
+        pub fn poll(
+            &mut self,
+            net: &mut NetStack,
+            udp: &mut UdpStack,
+            now: u64,
+        ) -> Result<Vec<(InterfaceId, DhcpEvent)>, &'static str> {
+            let mut events = Vec::new();
+            while let Some(message) = udp.recv_from(self.socket)? {
+                if message.source_port != DHCP_SERVER_PORT {
+                    continue;
+                }
+                if let Some(client) = self.clients.get_mut(&message.interface) {
+                    match client.handle_packet(net, &message.payload, now) {
+                        Ok(Some(event)) => events.push((message.interface, event)),
+                        Ok(None) => {}
+                        Err(error) => log::debug!("Ignoring reply: {}", error),
+                    }
+                }
+            }
+            for (interface, client) in &mut self.clients {
+                if let Some(event) = client.poll(net, now)? {
+                    events.push((*interface, event));
+                }
+            }
+            Ok(events)
+        }
+    }
+}
@@ -0,0 +1,171 @@
+// src/networking/exporter.rs
+
+pub mod exporter {
+    use crate::socket::socket::{SocketHandle, SocketKind, SocketSet};
+    use crate::vxnet_core::vxnet_core::InterfaceStats;
+    use std::io;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+    use std::task::Waker;
+    use vaelix_core::metrics::metrics::{MetricType, Registry, Sample};
+
+    // node_exporter's, which scrape configurations expect
+    pub const METRICS_PORT: u16 = 9100;
+    pub const METRICS_PATH: &str = "/metrics";
+    pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+    // Longer request heads are refused
+    const MAX_REQUEST: usize = 8192;
+
+    struct Connection {
+        handle: SocketHandle,
+        request: Vec<u8>,
+        // Once the request is in, what is left of the response
+        response: Option<Vec<u8>>,
+    }
+
+    fn response(status: &str, body: &[u8], head_only: bool) -> Vec<u8> {
+        let mut response = format!("HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", status, CONTENT_TYPE, body.len()).into_bytes();
+        if !head_only {
+            response.extend_from_slice(body);
+        }
+        response
+    }
+
+    // Serves the registry over HTTP for Prometheus to scrape, a connection
+    // per scrape
+    pub struct MetricsExporter {
+        listener: SocketHandle,
+        connections: Vec<Connection>,
+        scrapes: u64,
+    }
+
+    impl MetricsExporter {
+        pub fn bind(set: &mut SocketSet, address: SocketAddr) -> io::Result<Self> {
+            let listener = set.socket(SocketKind::Tcp)?;
+            let listening = set.bind(listener, address).and_then(|_| set.listen(listener, 8));
+            if let Err(error) = listening {
+                let _ = set.close(listener);
+                return Err(error);
+            }
+            log::info!("Exporting metrics on {}", address);
+            Ok(MetricsExporter { listener, connections: Vec::new(), scrapes: 0 })
+        }
+
+        pub fn local_addr(&self, set: &SocketSet) -> io::Result<Option<SocketAddr>> {
+            set.local_addr(self.listener)
+        }
+
+        // Successful scrapes so far
+        pub fn scrapes(&self) -> u64 {
+            self.scrapes
+        }
+
+        // Answers what the set has received; call after polling it. The
+        // set is unlocked while the registry renders, as the interface
+        // metrics read it too.
+        pub fn poll(&mut self, set: &Mutex<SocketSet>, registry: &Registry) -> io::Result<()> {
+            let mut done = self.receive(&mut set.lock().unwrap())?;
+            for connection in self.connections.iter_mut().filter(|connection| connection.response.is_none()) {
+                connection.response = answer(&connection.request, registry, &mut self.scrapes);
+            }
+            let mut set = set.lock().unwrap();
+            for (index, connection) in self.connections.iter_mut().enumerate() {
+                let Some(response) = &mut connection.response else {
+                    continue;
+                };
+                match set.send(connection.handle, response) {
+                    Ok(sent) => {
+                        response.drain(..sent);
+                    }
+                    Err(error) if error.kind() == io::ErrorKind::WouldBlock => {}
+                    Err(_) => response.clear(),
+                }
+                if response.is_empty() {
+                    done.push(index);
+                }
+            }
+            done.sort_unstable();
+            done.dedup();
+            for index in done.into_iter().rev() {
+                let connection = self.connections.remove(index);
+                let _ = set.close(connection.handle);
+            }
+            let wakers = set.take_wakers();
+            drop(set);
+            wakers.into_iter().for_each(Waker::wake);
+            Ok(())
+        }
+
+        // New connections and requests; returns connections that hung up
+        fn receive(&mut self, set: &mut SocketSet) -> io::Result<Vec<usize>> {
+            loop {
+                match set.accept(self.listener) {
+                    Ok((handle, _)) => self.connections.push(Connection { handle, request: Vec::new(), response: None }),
+                    Err(error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(error) => return Err(error),
+                }
+            }
+            let mut closed = Vec::new();
+            for (index, connection) in self.connections.iter_mut().enumerate().filter(|(_, connection)| connection.response.is_none()) {
+                let mut buffer = [0u8; 1024];
+                match set.recv(connection.handle, &mut buffer) {
+                    Ok(0) => closed.push(index),
+                    Ok(count) => connection.request.extend_from_slice(&buffer[..count]),
+                    Err(error) if error.kind() == io::ErrorKind::WouldBlock => {}
+                    Err(_) => closed.push(index),
+                }
+            }
+            Ok(closed)
+        }
+
+        pub fn close(self, set: &mut SocketSet) -> io::Result<()> {
+            for connection in self.connections {
+                let _ = set.close(connection.handle);
+            }
+            set.close(self.listener)
+        }
+    }
+
+    // The response once the request head is complete
+    fn answer(request: &[u8], registry: &Registry, scrapes: &mut u64) -> Option<Vec<u8>> {
+        let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") else {
+            return (request.len() > MAX_REQUEST).then(|| response("431 Request Header Fields Too Large", b"", false));
+        };
+        let head = String::from_utf8_lossy(&request[..end]);
+        let mut words = head.lines().next().unwrap_or("").split(' ');
+        let (method, target) = (words.next().unwrap_or(""), words.next().unwrap_or(""));
+        let path = target.split('?').next().unwrap_or("");
+        Some(match (method, path) {
+            ("GET" | "HEAD", METRICS_PATH) => {
+                *scrapes += 1;
+                response("200 OK", registry.render().as_bytes(), method == "HEAD")
+            }
+            ("GET" | "HEAD", _) => response("404 Not Found", b"Not found\n", method == "HEAD"),
+            _ => response("405 Method Not Allowed", b"Method not allowed\n", false),
+        })
+    }
+
+    type InterfaceStat = fn(&InterfaceStats) -> u64;
+
+    // Each interface's counters, read from the stack when scraped
+    pub fn register_interface_metrics(registry: &Registry, set: Arc<Mutex<SocketSet>>) -> Result<(), &'static str> {
+        let families: [(&str, &str, InterfaceStat); 8] = [
+            ("vaelix_net_receive_packets_total", "Packets received.", |stats| stats.rx_packets),
+            ("vaelix_net_receive_bytes_total", "Bytes received.", |stats| stats.rx_bytes),
+            ("vaelix_net_receive_dropped_total", "Received packets dropped.", |stats| stats.rx_dropped),
+            ("vaelix_net_receive_errors_total", "Received packets that could not be processed.", |stats| stats.rx_errors),
+            ("vaelix_net_transmit_packets_total", "Packets transmitted.", |stats| stats.tx_packets),
+            ("vaelix_net_transmit_bytes_total", "Bytes transmitted.", |stats| stats.tx_bytes),
+            ("vaelix_net_transmit_dropped_total", "Packets the queueing discipline dropped.", |stats| stats.tx_dropped),
+            ("vaelix_net_transmit_errors_total", "Packets the device failed to send.", |stats| stats.tx_errors),
+        ];
+        for (name, help, stat) in families {
+            let set = set.clone();
+            registry.collect(name, help, MetricType::Counter, move || {
+                let set = set.lock().unwrap();
+                set.net().interfaces().iter().map(|interface| Sample::new(stat(&interface.stats()) as f64).with("interface", interface.name())).collect()
+            })?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,265 @@
+// src/networking/fib.rs
+
+pub mod fib {
+    use crate::vxnet_core::vxnet_core::{InterfaceId, IpCidr, Route};
+    use std::collections::BTreeMap;
+    use std::net::IpAddr;
+
+    pub const TABLE_DEFAULT: u32 = 253;
+    pub const TABLE_MAIN: u32 = 254;
+
+    const PRIORITY_MAIN: u32 = 32766;
+    const PRIORITY_DEFAULT: u32 = 32767;
+
+    // Addresses are left-aligned in 128 bits so both families share the walk
+    fn address_bits(address: &IpAddr) -> u128 {
+        match address {
+            IpAddr::V4(address) => (u32::from(*address) as u128) << 96,
+            IpAddr::V6(address) => u128::from(*address),
+        }
+    }
+
+    fn bit(bits: u128, index: u8) -> usize {
+        ((bits >> (127 - index as u32)) & 1) as usize
+    }
+
+    #[derive(Default)]
+    struct TrieNode {
+        routes: Vec<Route>,
+        children: [Option<Box<TrieNode>>; 2],
+    }
+
+    impl TrieNode {
+        // Lowest metric wins; among equals the route installed first
+        fn best(&self, filter: &dyn Fn(&Route) -> bool) -> Option<Route> {
+            self.routes.iter().filter(|route| filter(route)).min_by_key(|route| route.metric).copied()
+        }
+
+        fn retain(&mut self, keep: &mut dyn FnMut(&Route) -> bool) -> usize {
+            let before = self.routes.len();
+            self.routes.retain(|route| keep(route));
+            let mut removed = before - self.routes.len();
+            for child in &mut self.children {
+                if let Some(node) = child {
+                    removed += node.retain(keep);
+                    if node.routes.is_empty() && node.children.iter().all(Option::is_none) {
+                        *child = None;
+                    }
+                }
+            }
+            removed
+        }
+
+        fn collect(&self, out: &mut Vec<Route>) {
+            out.extend_from_slice(&self.routes);
+            for child in self.children.iter().flatten() {
+                child.collect(out);
+            }
+        }
+    }
+
+    // Binary trie over the network bits of one address family
+    #[derive(Default)]
+    struct PrefixTrie {
+        root: TrieNode,
+    }
+
+    impl PrefixTrie {
+        fn insert(&mut self, route: Route) -> Result<(), &'static str> {
+            let bits = address_bits(&route.destination.address);
+            let mut node = &mut self.root;
+            for index in 0..route.destination.prefix_len {
+                node = node.children[bit(bits, index)].get_or_insert_with(Default::default);
+            }
+            let duplicate = node.routes.iter().any(|existing| {
+                existing.interface == route.interface
+                    && existing.gateway == route.gateway
+                    && existing.metric == route.metric
+            });
+            if duplicate {
+                return Err("Route already exists");
+            }
+            node.routes.push(route);
+            Ok(())
+        }
+
+        fn lookup(&self, address: &IpAddr, filter: &dyn Fn(&Route) -> bool) -> Option<Route> {
+            let bits = address_bits(address);
+            let depth = if address.is_ipv4() { 32 } else { 128 };
+            let mut node = &self.root;
+            let mut best = node.best(filter);
+            for index in 0..depth {
+                match &node.children[bit(bits, index)] {
+                    Some(child) => node = child,
+                    None => break,
+                }
+                if let Some(route) = node.best(filter) {
+                    best = Some(route);
+                }
+            }
+            best
+        }
+    }
+
+    #[derive(Default)]
+    struct RouteTable {
+        ipv4: PrefixTrie,
+        ipv6: PrefixTrie,
+    }
+
+    impl RouteTable {
+        fn family(&self, address: &IpAddr) -> &PrefixTrie {
+            if address.is_ipv4() {
+                &self.ipv4
+            } else {
+                &self.ipv6
+            }
+        }
+
+        fn family_mut(&mut self, address: &IpAddr) -> &mut PrefixTrie {
+            if address.is_ipv4() {
+                &mut self.ipv4
+            } else {
+                &mut self.ipv6
+            }
+        }
+    }
+
+    // Policy rule: traffic matching every selector is looked up in table
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct RoutingRule {
+        pub priority: u32,
+        pub source: Option<IpCidr>,
+        pub destination: Option<IpCidr>,
+        pub interface: Option<InterfaceId>,
+        pub table: u32,
+    }
+
+    impl RoutingRule {
+        pub fn new(priority: u32, table: u32) -> Self {
+            RoutingRule {
+                priority,
+                source: None,
+                destination: None,
+                interface: None,
+                table,
+            }
+        }
+
+        // Source selectors never match locally generated traffic that has
+        // not picked a source address yet
+        fn matches(&self, destination: &IpAddr, source: Option<&IpAddr>, incoming: Option<InterfaceId>) -> bool {
+            self.source.is_none_or(|cidr| source.is_some_and(|source| cidr.contains(source)))
+                && self.destination.is_none_or(|cidr| cidr.contains(destination))
+                && self.interface.is_none_or(|id| incoming == Some(id))
+        }
+    }
+
+    pub struct Fib {
+        tables: BTreeMap<u32, RouteTable>,
+        rules: Vec<RoutingRule>,
+    }
+
+    impl Fib {
+        pub fn new() -> Self {
+            let mut tables = BTreeMap::new();
+            tables.insert(TABLE_MAIN, RouteTable::default());
+            Fib {
+                tables,
+                rules: vec![
+                    RoutingRule::new(PRIORITY_MAIN, TABLE_MAIN),
+                    RoutingRule::new(PRIORITY_DEFAULT, TABLE_DEFAULT),
+                ],
+            }
+        }
+
+        pub fn add_route(&mut self, table: u32, mut route: Route) -> Result<(), &'static str> {
+            route.destination = route.destination.network();
+            self.tables
+                .entry(table)
+                .or_default()
+                .family_mut(&route.destination.address)
+                .insert(route)
+        }
+
+        pub fn contains(&self, table: u32, route: &Route) -> bool {
+            self.routes(table).iter().any(|existing| {
+                existing.destination == route.destination.network()
+                    && existing.interface == route.interface
+                    && existing.gateway == route.gateway
+            })
+        }
+
+        // Removes every route for which keep returns false
+        pub fn retain_routes(&mut self, mut keep: impl FnMut(u32, &Route) -> bool) -> usize {
+            let mut removed = 0;
+            for (table, routes) in &mut self.tables {
+                let mut keep = |route: &Route| keep(*table, route);
+                removed += routes.ipv4.root.retain(&mut keep);
+                removed += routes.ipv6.root.retain(&mut keep);
+            }
+            removed
+        }
+
+        pub fn routes(&self, table: u32) -> Vec<Route> {
+            let mut routes = Vec::new();
+            if let Some(table) = self.tables.get(&table) {
+                table.ipv4.root.collect(&mut routes);
+                table.ipv6.root.collect(&mut routes);
+            }
+            routes
+        }
+
+        pub fn tables(&self) -> Vec<u32> {
+            self.tables.keys().copied().collect()
+        }
+
+        pub fn add_rule(&mut self, rule: RoutingRule) -> Result<(), &'static str> {
+            if self.rules.iter().any(|existing| existing.priority == rule.priority) {
+                return Err("Rule priority already in use");
+            }
+            let index = self.rules.partition_point(|existing| existing.priority < rule.priority);
+            self.rules.insert(index, rule);
+            Ok(())
+        }
+
+        pub fn remove_rule(&mut self, priority: u32) -> Result<RoutingRule, &'static str> {
+            let index = self
+                .rules
+                .iter()
+                .position(|rule| rule.priority == priority)
+                .ok_or("Rule not found")?;
+            Ok(self.rules.remove(index))
+        }
+
+        pub fn rules(&self) -> &[RoutingRule] {
+            &self.rules
+        }
+
+        pub fn lookup(&self, destination: &IpAddr, source: Option<&IpAddr>, incoming: Option<InterfaceId>) -> Option<Route> {
+            self.lookup_filtered(destination, source, incoming, |_| true)
+        }
+
+        // Walks the rules in priority order; the first table holding a
+        // matching route decides
+        pub fn lookup_filtered(
+            &self,
+            destination: &IpAddr,
+            source: Option<&IpAddr>,
+            incoming: Option<InterfaceId>,
+            filter: impl Fn(&Route) -> bool,
+        ) -> Option<Route> {
+            self.rules
+                .iter()
+                .filter(|rule| rule.matches(destination, source, incoming))
+                .filter_map(|rule| self.tables.get(&rule.table))
+                .find_map(|table| table.family(destination).lookup(destination, &filter))
+        }
+    }
+
+    impl Default for Fib {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+}
@@ -0,0 +1,689 @@
+// src/networking/http.rs
+
+pub mod http {
+    use crate::socket::socket::{Readiness, SocketHandle, SocketKind, SocketSet};
+    use std::collections::BTreeMap;
+    use std::fmt;
+    use std::io::{ErrorKind, Write};
+    use std::net::{IpAddr, SocketAddr};
+
+    pub const DEFAULT_PORT: u16 = 80;
+    pub const TLS_PORT: u16 = 443;
+    pub const DEFAULT_MAX_REDIRECTS: usize = 5;
+    const MAX_HEAD_LEN: usize = 16 * 1024;
+    const MAX_CHUNK_LINE_LEN: usize = 1024;
+    const MAX_RESUME_ATTEMPTS: u32 = 3;
+    const READ_CHUNK: usize = 16 * 1024;
+    const USER_AGENT: &str = "VaelixOS/0.1";
+    const WRITE_FAILED: &str = "Failed to write body";
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Url {
+        pub secure: bool,
+        pub host: String,
+        pub port: u16,
+        // Path and query, always starting with '/'
+        pub path: String,
+    }
+
+    impl Url {
+        pub fn parse(text: &str) -> Result<Url, &'static str> {
+            let (secure, rest) = if let Some(rest) = text.strip_prefix("http://") {
+                (false, rest)
+            } else if let Some(rest) = text.strip_prefix("https://") {
+                (true, rest)
+            } else {
+                return Err("Unsupported URL scheme");
+            };
+            let rest = rest.split('#').next().unwrap_or_default();
+            let (authority, path) = match rest.find(['/', '?']) {
+                Some(index) => rest.split_at(index),
+                None => (rest, "/"),
+            };
+            if authority.contains('@') {
+                return Err("Credentials in URLs are not supported");
+            }
+            let (host, port) = match authority.strip_prefix('[') {
+                Some(bracketed) => {
+                    let (host, after) = bracketed.split_once(']').ok_or("Invalid IPv6 host")?;
+                    (host, after.strip_prefix(':'))
+                }
+                None => match authority.split_once(':') {
+                    Some((host, port)) => (host, Some(port)),
+                    None => (authority, None),
+                },
+            };
+            if host.is_empty() {
+                return Err("Missing host in URL");
+            }
+            let port = match port {
+                Some(port) => port.parse().map_err(|_| "Invalid port in URL")?,
+                None if secure => TLS_PORT,
+                None => DEFAULT_PORT,
+            };
+            let path = if path.starts_with('/') {
+                path.to_string()
+            } else {
+                format!("/{}", path)
+            };
+            Ok(Url {
+                secure,
+                host: host.to_ascii_lowercase(),
+                port,
+                path,
+            })
+        }
+
+        // Resolves a Location header against this URL
+        pub fn join(&self, location: &str) -> Result<Url, &'static str> {
+            if location.contains("://") {
+                return Url::parse(location);
+            }
+            let scheme = if self.secure { "https:" } else { "http:" };
+            if location.starts_with("//") {
+                return Url::parse(&format!("{}{}", scheme, location));
+            }
+            let path = if location.starts_with('/') {
+                location.to_string()
+            } else {
+                let base = self.path.split('?').next().unwrap_or("/");
+                let directory = &base[..base.rfind('/').map_or(0, |index| index + 1)];
+                format!("{}{}", directory, location)
+            };
+            Ok(Url { path, ..self.clone() })
+        }
+
+        fn authority(&self) -> String {
+            let host = if self.host.contains(':') {
+                format!("[{}]", self.host)
+            } else {
+                self.host.clone()
+            };
+            let default = if self.secure { TLS_PORT } else { DEFAULT_PORT };
+            if self.port == default {
+                host
+            } else {
+                format!("{}:{}", host, self.port)
+            }
+        }
+    }
+
+    impl fmt::Display for Url {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let scheme = if self.secure { "https" } else { "http" };
+            write!(f, "{}://{}{}", scheme, self.authority(), self.path)
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Method {
+        Get,
+        Head,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct HttpRequest {
+        pub method: Method,
+        pub url: Url,
+        pub headers: Vec<(String, String)>,
+        // Non-zero asks for the body from this offset on
+        pub range_start: u64,
+    }
+
+    impl HttpRequest {
+        pub fn new(method: Method, url: Url) -> Self {
+            HttpRequest {
+                method,
+                url,
+                headers: Vec::new(),
+                range_start: 0,
+            }
+        }
+
+        pub fn add_header(&mut self, name: &str, value: &str) {
+            self.headers.push((name.to_string(), value.to_string()));
+        }
+
+        // One request per connection; the body must arrive unencoded so
+        // range offsets line up with the bytes written out
+        pub fn encode(&self) -> Vec<u8> {
+            let method = match self.method {
+                Method::Get => "GET",
+                Method::Head => "HEAD",
+            };
+            let mut text = format!(
+                "{} {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: {}\r\nAccept-Encoding: identity\r\nConnection: close\r\n",
+                method,
+                self.url.path,
+                self.url.authority(),
+                USER_AGENT
+            );
+            if self.range_start > 0 {
+                text.push_str(&format!("Range: bytes={}-\r\n", self.range_start));
+            }
+            for (name, value) in &self.headers {
+                text.push_str(&format!("{}: {}\r\n", name, value));
+            }
+            text.push_str("\r\n");
+            text.into_bytes()
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct HttpResponse {
+        pub status: u16,
+        pub reason: String,
+        pub headers: Vec<(String, String)>,
+    }
+
+    impl HttpResponse {
+        pub fn header(&self, name: &str) -> Option<&str> {
+            self.headers
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())
+        }
+
+        pub fn content_length(&self) -> Option<u64> {
+            self.header("Content-Length")?.trim().parse().ok()
+        }
+
+        // "bytes first-last/total"; total may be "*"
+        pub fn content_range(&self) -> Option<(u64, Option<u64>)> {
+            let range = self.header("Content-Range")?.trim().strip_prefix("bytes ")?;
+            let (span, total) = range.split_once('/')?;
+            let first = span.split_once('-')?.0.parse().ok()?;
+            Some((first, total.parse().ok()))
+        }
+
+        // Total body size from Content-Range, also present on 416 replies
+        // as "bytes */total"
+        fn complete_length(&self) -> Option<u64> {
+            self.header("Content-Range")?.rsplit_once('/')?.1.trim().parse().ok()
+        }
+
+        pub fn is_redirect(&self) -> bool {
+            matches!(self.status, 301 | 302 | 303 | 307 | 308)
+        }
+
+        fn parse(head: &str) -> Result<HttpResponse, &'static str> {
+            let mut lines = head.split("\r\n");
+            let status_line = lines.next().ok_or("Missing status line")?;
+            let mut parts = status_line.splitn(3, ' ');
+            if !parts.next().is_some_and(|version| version.starts_with("HTTP/1.")) {
+                return Err("Not an HTTP/1.x response");
+            }
+            let status = parts
+                .next()
+                .and_then(|status| status.parse().ok())
+                .ok_or("Invalid status code")?;
+            let reason = parts.next().unwrap_or_default().to_string();
+            let mut headers = Vec::new();
+            for line in lines.filter(|line| !line.is_empty()) {
+                let (name, value) = line.split_once(':').ok_or("Malformed header")?;
+                headers.push((name.trim().to_string(), value.trim().to_string()));
+            }
+            Ok(HttpResponse { status, reason, headers })
+        }
+    }
+
+    enum ChunkState {
+        Size,
+        Data(u64),
+        DataEnd,
+        Trailer,
+    }
+
+    enum Framing {
+        Length(u64),
+        Chunked(ChunkState),
+        UntilClose,
+    }
+
+    // Receives each decoded run of body bytes with the response it belongs to
+    pub type BodyCallback<'a> = dyn FnMut(&HttpResponse, &[u8]) -> Result<(), &'static str> + 'a;
+
+    // Incremental HTTP/1.1 response parser. Input may be split anywhere;
+    // decoded body bytes are handed out as they arrive.
+    pub struct ResponseParser {
+        head_only: bool,
+        buffer: Vec<u8>,
+        response: Option<HttpResponse>,
+        framing: Option<Framing>,
+        complete: bool,
+    }
+
+    impl ResponseParser {
+        // head_only is set for HEAD requests, whose responses never carry a body
+        pub fn new(head_only: bool) -> Self {
+            ResponseParser {
+                head_only,
+                buffer: Vec::new(),
+                response: None,
+                framing: None,
+                complete: false,
+            }
+        }
+
+        pub fn response(&self) -> Option<&HttpResponse> {
+            self.response.as_ref()
+        }
+
+        pub fn is_complete(&self) -> bool {
+            self.complete
+        }
+
+        pub fn feed(
+            &mut self,
+            data: &[u8],
+            body: &mut BodyCallback<'_>,
+        ) -> Result<(), &'static str> {
+            if self.complete {
+                return Ok(());
+            }
+            self.buffer.extend_from_slice(data);
+            while !self.complete {
+                let Some(framing) = &mut self.framing else {
+                    if !self.parse_head()? {
+                        return Ok(());
+                    }
+                    continue;
+                };
+                let response = self.response.as_ref().ok_or("Missing response")?;
+                match framing {
+                    Framing::Length(remaining) | Framing::Chunked(ChunkState::Data(remaining)) => {
+                        if self.buffer.is_empty() {
+                            return Ok(());
+                        }
+                        let count = self.buffer.len().min(*remaining as usize);
+                        body(response, &self.buffer[..count])?;
+                        self.buffer.drain(..count);
+                        *remaining -= count as u64;
+                        if *remaining == 0 {
+                            match framing {
+                                Framing::Chunked(state) => *state = ChunkState::DataEnd,
+                                _ => self.complete = true,
+                            }
+                        }
+                    }
+                    Framing::UntilClose => {
+                        if !self.buffer.is_empty() {
+                            body(response, &self.buffer)?;
+                            self.buffer.clear();
+                        }
+                        return Ok(());
+                    }
+                    Framing::Chunked(state) => {
+                        let Some(end) = find(&self.buffer, b"\r\n") else {
+                            if self.buffer.len() > MAX_CHUNK_LINE_LEN {
+                                return Err("Chunk header too long");
+                            }
+                            return Ok(());
+                        };
+                        let line = String::from_utf8_lossy(&self.buffer[..end]).into_owned();
+                        self.buffer.drain(..end + 2);
+                        match state {
+                            ChunkState::Size => {
+                                let size = line.split(';').next().unwrap_or_default().trim();
+                                let size = u64::from_str_radix(size, 16).map_err(|_| "Invalid chunk size")?;
+                                *state = if size == 0 {
+                                    ChunkState::Trailer
+                                } else {
+                                    ChunkState::Data(size)
+                                };
+                            }
+                            ChunkState::DataEnd if line.is_empty() => *state = ChunkState::Size,
+                            ChunkState::DataEnd => return Err("Missing CRLF after chunk"),
+                            ChunkState::Trailer if line.is_empty() => self.complete = true,
+                            ChunkState::Trailer => {}
+                            ChunkState::Data(_) => unreachable!(),
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        // Called once the peer closed its side; only close-delimited bodies
+        // may end this way
+        pub fn finish(&mut self) -> Result<(), &'static str> {
+            if matches!(self.framing, Some(Framing::UntilClose)) {
+                self.complete = true;
+            }
+            if !self.complete {
+                return Err("Connection closed before the response completed");
+            }
+            Ok(())
+        }
+
+        fn parse_head(&mut self) -> Result<bool, &'static str> {
+            let Some(end) = find(&self.buffer, b"\r\n\r\n") else {
+                if self.buffer.len() > MAX_HEAD_LEN {
+                    return Err("Response header too large");
+                }
+                return Ok(false);
+            };
+            let head = std::str::from_utf8(&self.buffer[..end]).map_err(|_| "Response header is not UTF-8")?;
+            let response = HttpResponse::parse(head)?;
+            self.buffer.drain(..end + 4);
+            // Interim responses (100 Continue) precede the real one
+            if (100..200).contains(&response.status) {
+                return Ok(true);
+            }
+            let chunked = response
+                .header("Transfer-Encoding")
+                .is_some_and(|encoding| encoding.to_ascii_lowercase().contains("chunked"));
+            let framing = if self.head_only || matches!(response.status, 204 | 304) {
+                Framing::Length(0)
+            } else if chunked {
+                Framing::Chunked(ChunkState::Size)
+            } else if let Some(length) = response.content_length() {
+                Framing::Length(length)
+            } else {
+                Framing::UntilClose
+            };
+            self.complete = matches!(framing, Framing::Length(0));
+            self.framing = Some(framing);
+            self.response = Some(response);
+            Ok(true)
+        }
+    }
+
+    fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+        haystack.windows(needle.len()).position(|window| window == needle)
+    }
+
+    // Until a DNS resolver exists, host names resolve through a static table
+    pub struct HttpClient {
+        hosts: BTreeMap<String, IpAddr>,
+        max_redirects: usize,
+    }
+
+    impl HttpClient {
+        pub fn new() -> Self {
+            HttpClient {
+                hosts: BTreeMap::new(),
+                max_redirects: DEFAULT_MAX_REDIRECTS,
+            }
+        }
+
+        pub fn add_host(&mut self, name: &str, address: IpAddr) {
+            self.hosts.insert(name.to_ascii_lowercase(), address);
+        }
+
+        pub fn set_max_redirects(&mut self, max_redirects: usize) {
+            self.max_redirects = max_redirects;
+        }
+
+        pub fn get<W: Write>(&self, set: &mut SocketSet, url: &str, sink: W) -> Result<HttpTransfer<W>, &'static str> {
+            self.start(set, HttpRequest::new(Method::Get, Url::parse(url)?), sink)
+        }
+
+        pub fn head<W: Write>(&self, set: &mut SocketSet, url: &str, sink: W) -> Result<HttpTransfer<W>, &'static str> {
+            self.start(set, HttpRequest::new(Method::Head, Url::parse(url)?), sink)
+        }
+
+        // Continues a download whose first offset bytes are already in sink
+        pub fn resume<W: Write>(
+            &self,
+            set: &mut SocketSet,
+            url: &str,
+            sink: W,
+            offset: u64,
+        ) -> Result<HttpTransfer<W>, &'static str> {
+            let mut request = HttpRequest::new(Method::Get, Url::parse(url)?);
+            request.range_start = offset;
+            self.start(set, request, sink)
+        }
+
+        pub fn start<W: Write>(
+            &self,
+            set: &mut SocketSet,
+            request: HttpRequest,
+            sink: W,
+        ) -> Result<HttpTransfer<W>, &'static str> {
+            let mut transfer = HttpTransfer {
+                written: request.range_start,
+                head_only: request.method == Method::Head,
+                request,
+                hosts: self.hosts.clone(),
+                max_redirects: self.max_redirects,
+                redirects: 0,
+                resumes: 0,
+                socket: None,
+                phase: Phase::Connecting,
+                parser: ResponseParser::new(false),
+                position: None,
+                total: None,
+                response: None,
+                sink,
+            };
+            transfer.connect(set)?;
+            Ok(transfer)
+        }
+    }
+
+    impl Default for HttpClient {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum TransferStatus {
+        InProgress,
+        Complete,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Phase {
+        Connecting,
+        Sending(usize),
+        Receiving,
+        Done,
+    }
+
+    // One request driven over a SocketSet: follows redirects, and when the
+    // connection drops mid-body reconnects with a range request for the
+    // rest. Bodies of 2xx responses stream into sink; others are discarded.
+    pub struct HttpTransfer<W: Write> {
+        request: HttpRequest,
+        head_only: bool,
+        hosts: BTreeMap<String, IpAddr>,
+        max_redirects: usize,
+        redirects: usize,
+        resumes: u32,
+        socket: Option<SocketHandle>,
+        phase: Phase,
+        parser: ResponseParser,
+        // Absolute body offset of the next byte on the current connection
+        position: Option<u64>,
+        written: u64,
+        total: Option<u64>,
+        response: Option<HttpResponse>,
+        sink: W,
+    }
+
+    impl<W: Write> HttpTransfer<W> {
+        pub fn url(&self) -> &Url {
+            &self.request.url
+        }
+
+        // Final response once the transfer completed
+        pub fn response(&self) -> Option<&HttpResponse> {
+            self.response.as_ref()
+        }
+
+        // Body bytes in sink, including any resumed-from offset
+        pub fn received(&self) -> u64 {
+            self.written
+        }
+
+        pub fn total_length(&self) -> Option<u64> {
+            self.total
+        }
+
+        pub fn is_complete(&self) -> bool {
+            self.phase == Phase::Done
+        }
+
+        pub fn into_sink(self) -> W {
+            self.sink
+        }
+
+        fn resolve(&self) -> Result<SocketAddr, &'static str> {
+            let url = &self.request.url;
+            let address = match url.host.parse::<IpAddr>() {
+                Ok(address) => address,
+                Err(_) => *self.hosts.get(&url.host).ok_or("Unknown host")?,
+            };
+            Ok(SocketAddr::new(address, url.port))
+        }
+
+        fn connect(&mut self, set: &mut SocketSet) -> Result<(), &'static str> {
+            if self.request.url.secure {
+                return Err("HTTPS requires TLS, which is not available");
+            }
+            let peer = self.resolve()?;
+            let socket = set.socket(SocketKind::Tcp).map_err(|_| "Out of sockets")?;
+            self.socket = Some(socket);
+            set.connect(socket, peer).map_err(|_| "Connection failed")?;
+            self.request.range_start = self.written;
+            self.parser = ResponseParser::new(self.head_only);
+            self.position = None;
+            self.phase = Phase::Connecting;
+            Ok(())
+        }
+
+        fn disconnect(&mut self, set: &mut SocketSet) {
+            if let Some(socket) = self.socket.take() {
+                let _ = set.close(socket);
+            }
+        }
+
+        // Retries from the current offset while attempts remain
+        fn interrupted(&mut self, set: &mut SocketSet, reason: &'static str) -> Result<TransferStatus, &'static str> {
+            self.disconnect(set);
+            if self.resumes >= MAX_RESUME_ATTEMPTS {
+                return Err(reason);
+            }
+            self.resumes += 1;
+            log::warn!("{} fetching {}, resuming at byte {}", reason, self.request.url, self.written);
+            self.connect(set)?;
+            Ok(TransferStatus::InProgress)
+        }
+
+        // Call after SocketSet::poll(); never blocks
+        pub fn poll(&mut self, set: &mut SocketSet) -> Result<TransferStatus, &'static str> {
+            loop {
+                let Some(socket) = self.socket else {
+                    return Ok(TransferStatus::Complete);
+                };
+                match self.phase {
+                    Phase::Done => return Ok(TransferStatus::Complete),
+                    Phase::Connecting => {
+                        let ready = set.readiness(socket).map_err(|_| "Socket not found")?;
+                        if ready.contains(Readiness::WRITABLE) {
+                            self.phase = Phase::Sending(0);
+                        } else if ready.intersects(Readiness::HANGUP | Readiness::ERROR) {
+                            return self.interrupted(set, "Connection failed");
+                        } else {
+                            return Ok(TransferStatus::InProgress);
+                        }
+                    }
+                    Phase::Sending(offset) => {
+                        let data = self.request.encode();
+                        match set.send(socket, &data[offset..]) {
+                            Ok(sent) if offset + sent == data.len() => self.phase = Phase::Receiving,
+                            Ok(sent) => self.phase = Phase::Sending(offset + sent),
+                            Err(error) if error.kind() == ErrorKind::WouldBlock => return Ok(TransferStatus::InProgress),
+                            Err(_) => return self.interrupted(set, "Connection lost while sending"),
+                        }
+                    }
+                    Phase::Receiving => {
+                        let mut buffer = vec![0u8; READ_CHUNK];
+                        let result = match set.recv(socket, &mut buffer) {
+                            Ok(0) => self.parser.finish(),
+                            Ok(count) => self.deliver(&buffer[..count]),
+                            Err(error) if error.kind() == ErrorKind::WouldBlock => return Ok(TransferStatus::InProgress),
+                            Err(_) => Err("Connection reset"),
+                        };
+                        if let Err(reason) = result {
+                            // Nothing the server sent can be salvaged by asking again, nor
+                            // a sink that refuses the body
+                            if reason == WRITE_FAILED || self.parser.response().is_some_and(|response| !is_success(response.status)) {
+                                self.disconnect(set);
+                                return Err(reason);
+                            }
+                            return self.interrupted(set, reason);
+                        }
+                        if self.parser.is_complete() {
+                            self.finish_response(set)?;
+                        }
+                    }
+                }
+            }
+        }
+
+        fn deliver(&mut self, data: &[u8]) -> Result<(), &'static str> {
+            let range_start = self.request.range_start;
+            let (sink, written, position, total) = (&mut self.sink, &mut self.written, &mut self.position, &mut self.total);
+            self.parser.feed(data, &mut |response, data| {
+                if !is_success(response.status) {
+                    return Ok(());
+                }
+                let start = match *position {
+                    Some(start) => start,
+                    None => {
+                        // Servers ignoring the range resend from the start
+                        let (start, length) = match response.content_range() {
+                            Some((first, length)) if response.status == 206 => (first, length),
+                            _ => (0, response.content_length()),
+                        };
+                        if start > range_start {
+                            return Err("Range response does not match the request");
+                        }
+                        *total = length.or(*total);
+                        start
+                    }
+                };
+                let skip = written.saturating_sub(start).min(data.len() as u64) as usize;
+                sink.write_all(&data[skip..]).map_err(|_| WRITE_FAILED)?;
+                *written += (data.len() - skip) as u64;
+                *position = Some(start + data.len() as u64);
+                Ok(())
+            })
+        }
+
+        fn finish_response(&mut self, set: &mut SocketSet) -> Result<(), &'static str> {
+            self.disconnect(set);
+            let response = self.parser.response().cloned().ok_or("Missing response")?;
+            if response.is_redirect() {
+                let location = response.header("Location").ok_or("Redirect without Location")?;
+                if self.redirects >= self.max_redirects {
+                    return Err("Too many redirects");
+                }
+                self.redirects += 1;
+                self.request.url = self.request.url.join(location)?;
+                return self.connect(set);
+            }
+            // Resuming exactly at the end of the body
+            let satisfied = response.status == 416
+                && self.written > 0
+                && response.complete_length() == Some(self.written);
+            if is_success(response.status) || satisfied {
+                self.sink.flush().map_err(|_| WRITE_FAILED)?;
+                if satisfied {
+                    self.total = Some(self.written);
+                }
+            }
+            self.response = Some(response);
+            self.phase = Phase::Done;
+            Ok(())
+        }
+    }
+
+    fn is_success(status: u16) -> bool {
+        (200..300).contains(&status)
+    }
+}
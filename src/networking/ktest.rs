@@ -0,0 +1,74 @@
+// src/networking/ktest.rs
+
+pub mod ktest {
+    use crate::packet::packet::IP_PROTO_TCP;
+    use crate::pktbuf::pktbuf::PacketBuffer;
+    use crate::tcp::tcp::{CongestionAlgorithm, Endpoint, TcpStack, TcpState};
+    use crate::vxnet_core::vxnet_core::{Datagram, InterfaceId};
+    use std::net::{IpAddr, Ipv4Addr};
+    use vaelix_core::ktest::ktest::TestCase;
+
+    const LOOPBACK: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    // Everything the stack sends comes straight back in, as on lo
+    fn loop_back(stack: &mut TcpStack, now: u64) {
+        while let Some(outbound) = stack.take_outbound() {
+            let datagram = Datagram {
+                interface: InterfaceId(0),
+                source: outbound.source,
+                destination: outbound.destination,
+                protocol: IP_PROTO_TCP,
+                ttl: 64,
+                payload: PacketBuffer::from_vec(outbound.segment),
+            };
+            stack.handle_datagram(&datagram, now).unwrap();
+        }
+    }
+
+    fn tcp_loopback_transfer() {
+        let mut stack = TcpStack::new(CongestionAlgorithm::NewReno);
+        stack.listen(7, 1).unwrap();
+        let client = stack.connect(LOOPBACK, Endpoint { address: LOOPBACK, port: 7 }, 0).unwrap();
+        loop_back(&mut stack, 0);
+        let server = stack.accept(7).expect("Connection not accepted");
+        assert_eq!((stack.state(client), stack.state(server)), (Some(TcpState::Established), Some(TcpState::Established)));
+
+        let data: Vec<u8> = (0..100_000).map(|index| (index % 253) as u8).collect();
+        let (mut sent, mut received) = (0, Vec::new());
+        for now in 1..1000 {
+            if sent < data.len() {
+                sent += stack.send(client, &data[sent..], now).unwrap();
+            }
+            loop_back(&mut stack, now);
+            let mut buffer = [0u8; 8192];
+            let count = stack.recv(server, &mut buffer, now).unwrap();
+            received.extend_from_slice(&buffer[..count]);
+            stack.poll(now);
+            if received.len() == data.len() {
+                break;
+            }
+        }
+        assert!(received == data, "Received {} of {} bytes intact", received.len(), data.len());
+
+        stack.close(client, 2000).unwrap();
+        loop_back(&mut stack, 2000);
+        assert!(stack.is_eof(server));
+        stack.close(server, 2000).unwrap();
+        loop_back(&mut stack, 2000);
+        assert_eq!(stack.state(client), Some(TcpState::TimeWait));
+    }
+
+    fn tcp_loopback_refused() {
+        let mut stack = TcpStack::new(CongestionAlgorithm::Cubic);
+        let client = stack.connect(LOOPBACK, Endpoint { address: LOOPBACK, port: 9 }, 0).unwrap();
+        loop_back(&mut stack, 0);
+        assert!(stack.was_reset(client));
+    }
+
+    pub fn cases() -> Vec<TestCase> {
+        vec![
+            TestCase { suite: "tcp", name: "loopback_transfer", run: tcp_loopback_transfer },
+            TestCase { suite: "tcp", name: "loopback_refused", run: tcp_loopback_refused },
+        ]
+    }
+}
@@ -1,5 +1,23 @@
 // src/networking/mod.rs
 
+pub mod bridge;
+pub mod dhcp;
+pub mod exporter;
+pub mod fib;
+pub mod http;
+pub mod ktest;
+pub mod neighbor;
+pub mod netdev;
+pub mod packet;
+pub mod pktbuf;
+pub mod qdisc;
+pub mod socket;
+pub mod tcp;
+pub mod udp;
+pub mod vxcap;
+pub mod vxdiag;
 pub mod vxnet_core;
+pub mod vxnetctl;
+pub mod vxupdate;
 pub mod vxwall;
 pub mod vxvpn;
@@ -0,0 +1,299 @@
+// src/networking/neighbor.rs
+
+pub mod neighbor {
+    use crate::netdev::netdev::MacAddress;
+    use crate::pktbuf::pktbuf::PacketBuffer;
+    use crate::vxnet_core::vxnet_core::InterfaceId;
+    use std::collections::{BTreeMap, VecDeque};
+    use std::net::IpAddr;
+
+    // Reachability states as in RFC 4861, shared by ARP and NDP
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum NeighborState {
+        // Solicited, no answer yet; packets queue behind it
+        Incomplete,
+        Reachable,
+        // Address known but unconfirmed; still used for sending
+        Stale,
+        // Stale entry in use, being confirmed with unicast probes
+        Probe,
+        // Resolution gave up; sends fail fast until the entry expires
+        Failed,
+    }
+
+    // Times in milliseconds
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct NeighborConfig {
+        pub reachable_time: u64,
+        pub retrans_time: u64,
+        // Grace period before a stale entry in use gets probed
+        pub delay_first_probe: u64,
+        pub multicast_probes: u32,
+        pub unicast_probes: u32,
+        pub failed_hold: u64,
+        // Unused stale entries are forgotten after this long
+        pub gc_stale_time: u64,
+        pub queue_len: usize,
+    }
+
+    impl Default for NeighborConfig {
+        fn default() -> Self {
+            NeighborConfig {
+                reachable_time: 30_000,
+                retrans_time: 1_000,
+                delay_first_probe: 5_000,
+                multicast_probes: 3,
+                unicast_probes: 3,
+                failed_hold: 20_000,
+                gc_stale_time: 60_000,
+                queue_len: 16,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Neighbor {
+        pub address: IpAddr,
+        pub interface: InterfaceId,
+        pub mac: Option<MacAddress>,
+        pub state: NeighborState,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Resolution {
+        Resolved(MacAddress),
+        // The caller queues the packet; solicit is set when a first
+        // solicitation has to go out
+        Incomplete { solicit: bool },
+        Failed,
+    }
+
+    // A solicitation due: broadcast/multicast when mac is None, unicast to
+    // the cached address when confirming a stale entry
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Probe {
+        pub interface: InterfaceId,
+        pub address: IpAddr,
+        pub mac: Option<MacAddress>,
+    }
+
+    pub type QueuedPacket = (u16, PacketBuffer);
+
+    struct Entry {
+        interface: InterfaceId,
+        mac: Option<MacAddress>,
+        state: NeighborState,
+        // When the current state was entered, or last confirmed
+        updated: u64,
+        probes: u32,
+        next_probe: u64,
+        queue: VecDeque<QueuedPacket>,
+    }
+
+    impl Entry {
+        fn enter(&mut self, state: NeighborState, now: u64) {
+            self.state = state;
+            self.updated = now;
+            self.probes = 0;
+        }
+    }
+
+    pub struct NeighborTable {
+        entries: BTreeMap<IpAddr, Entry>,
+        config: NeighborConfig,
+    }
+
+    impl NeighborTable {
+        pub fn new(config: NeighborConfig) -> Self {
+            NeighborTable {
+                entries: BTreeMap::new(),
+                config,
+            }
+        }
+
+        pub fn config(&self) -> NeighborConfig {
+            self.config
+        }
+
+        pub fn set_config(&mut self, config: NeighborConfig) {
+            self.config = config;
+        }
+
+        // Cached link address, in any state that has one
+        pub fn lookup(&self, address: &IpAddr) -> Option<MacAddress> {
+            self.entries.get(address).and_then(|entry| entry.mac)
+        }
+
+        pub fn contains(&self, address: &IpAddr) -> bool {
+            self.entries.contains_key(address)
+        }
+
+        pub fn get(&self, address: &IpAddr) -> Option<Neighbor> {
+            self.entries.get(address).map(|entry| Neighbor {
+                address: *address,
+                interface: entry.interface,
+                mac: entry.mac,
+                state: entry.state,
+            })
+        }
+
+        pub fn entries(&self) -> Vec<Neighbor> {
+            self.entries.keys().filter_map(|address| self.get(address)).collect()
+        }
+
+        // Called for every unicast send; a stale entry in use starts the
+        // delay towards unicast probing
+        pub fn resolve(&mut self, interface: InterfaceId, address: IpAddr, now: u64) -> Resolution {
+            let config = self.config;
+            let entry = self.entries.entry(address).or_insert_with(|| Entry {
+                interface,
+                mac: None,
+                state: NeighborState::Incomplete,
+                updated: now,
+                probes: 0,
+                next_probe: now,
+                queue: VecDeque::new(),
+            });
+            match (entry.state, entry.mac) {
+                (NeighborState::Failed, _) => Resolution::Failed,
+                (NeighborState::Stale, Some(mac)) => {
+                    entry.enter(NeighborState::Probe, now);
+                    entry.next_probe = now + config.delay_first_probe;
+                    Resolution::Resolved(mac)
+                }
+                (_, Some(mac)) => Resolution::Resolved(mac),
+                (_, None) => {
+                    let solicit = entry.probes == 0;
+                    if solicit {
+                        entry.probes = 1;
+                        entry.next_probe = now + config.retrans_time;
+                    }
+                    Resolution::Incomplete { solicit }
+                }
+            }
+        }
+
+        // Parks a packet behind an incomplete entry; returns false when the
+        // oldest queued packet had to be dropped to make room
+        pub fn enqueue(&mut self, address: &IpAddr, packet: QueuedPacket) -> bool {
+            let limit = self.config.queue_len;
+            let Some(entry) = self.entries.get_mut(address) else {
+                return false;
+            };
+            entry.queue.push_back(packet);
+            if entry.queue.len() > limit {
+                entry.queue.pop_front();
+                return false;
+            }
+            true
+        }
+
+        // Records a link address heard from the neighbor. Confirmed
+        // answers (ARP replies, solicited adverts) make it reachable;
+        // anything else only refreshes a changed or new entry as stale.
+        // Returns the packets that were waiting for it.
+        pub fn learn(
+            &mut self,
+            interface: InterfaceId,
+            address: IpAddr,
+            mac: MacAddress,
+            confirmed: bool,
+            now: u64,
+        ) -> Vec<QueuedPacket> {
+            let entry = self.entries.entry(address).or_insert_with(|| Entry {
+                interface,
+                mac: None,
+                state: NeighborState::Stale,
+                updated: now,
+                probes: 0,
+                next_probe: now,
+                queue: VecDeque::new(),
+            });
+            let changed = entry.mac != Some(mac);
+            entry.interface = interface;
+            entry.mac = Some(mac);
+            if confirmed {
+                entry.enter(NeighborState::Reachable, now);
+            } else if changed || matches!(entry.state, NeighborState::Incomplete | NeighborState::Failed) {
+                entry.enter(NeighborState::Stale, now);
+            }
+            entry.queue.drain(..).collect()
+        }
+
+        // Upper-layer proof of progress (e.g. new TCP ACKs) counts as a
+        // confirmation without another solicitation
+        pub fn confirm(&mut self, address: &IpAddr, now: u64) {
+            if let Some(entry) = self.entries.get_mut(address) {
+                if entry.mac.is_some() && entry.state != NeighborState::Failed {
+                    entry.enter(NeighborState::Reachable, now);
+                }
+            }
+        }
+
+        pub fn remove(&mut self, address: &IpAddr) -> bool {
+            self.entries.remove(address).is_some()
+        }
+
+        // Forgets everything learned on an interface; returns how many
+        // queued packets were dropped with it
+        pub fn flush_interface(&mut self, interface: InterfaceId) -> usize {
+            let mut dropped = 0;
+            self.entries.retain(|_, entry| {
+                if entry.interface == interface {
+                    dropped += entry.queue.len();
+                }
+                entry.interface != interface
+            });
+            dropped
+        }
+
+        // Advances the state machine; returns the solicitations to send and,
+        // per interface, the queued packets dropped by failed resolutions
+        pub fn poll(&mut self, now: u64) -> (Vec<Probe>, Vec<(InterfaceId, usize)>) {
+            let config = self.config;
+            let mut probes = Vec::new();
+            let mut dropped = Vec::new();
+            self.entries.retain(|address, entry| {
+                let age = now.saturating_sub(entry.updated);
+                match entry.state {
+                    NeighborState::Reachable if age >= config.reachable_time => {
+                        entry.enter(NeighborState::Stale, now);
+                    }
+                    NeighborState::Stale if age >= config.gc_stale_time => return false,
+                    NeighborState::Failed if age >= config.failed_hold => return false,
+                    NeighborState::Incomplete | NeighborState::Probe if now >= entry.next_probe => {
+                        let (limit, mac) = match entry.state {
+                            NeighborState::Incomplete => (config.multicast_probes, None),
+                            _ => (config.unicast_probes, entry.mac),
+                        };
+                        if entry.probes >= limit {
+                            log::info!("Neighbor {} unreachable", address);
+                            if !entry.queue.is_empty() {
+                                dropped.push((entry.interface, entry.queue.len()));
+                                entry.queue.clear();
+                            }
+                            entry.enter(NeighborState::Failed, now);
+                        } else {
+                            entry.probes += 1;
+                            entry.next_probe = now + config.retrans_time;
+                            probes.push(Probe {
+                                interface: entry.interface,
+                                address: *address,
+                                mac,
+                            });
+                        }
+                    }
+                    _ => {}
+                }
+                true
+            });
+            (probes, dropped)
+        }
+    }
+
+    impl Default for NeighborTable {
+        fn default() -> Self {
+            Self::new(NeighborConfig::default())
+        }
+    }
+}
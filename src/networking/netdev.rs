@@ -0,0 +1,187 @@
+// src/networking/netdev.rs
+
+pub mod netdev {
+    use crate::pktbuf::pktbuf::PacketBuffer;
+    use vaelix_core::power::wake::wake::WakeOnLan;
+    use std::collections::VecDeque;
+    use std::fmt;
+    use std::str::FromStr;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+    pub struct MacAddress(pub [u8; 6]);
+
+    impl MacAddress {
+        pub const BROADCAST: MacAddress = MacAddress([0xFF; 6]);
+        pub const ZERO: MacAddress = MacAddress([0; 6]);
+
+        pub fn is_broadcast(&self) -> bool {
+            *self == Self::BROADCAST
+        }
+
+        pub fn is_multicast(&self) -> bool {
+            self.0[0] & 0x01 != 0
+        }
+    }
+
+    impl FromStr for MacAddress {
+        type Err = &'static str;
+
+        fn from_str(text: &str) -> Result<Self, Self::Err> {
+            let mut mac = [0u8; 6];
+            let mut octets = text.split([':', '-']);
+            for octet in &mut mac {
+                let part = octets.next().ok_or("Invalid MAC address")?;
+                if part.len() != 2 {
+                    return Err("Invalid MAC address");
+                }
+                *octet = u8::from_str_radix(part, 16).map_err(|_| "Invalid MAC address")?;
+            }
+            if octets.next().is_some() {
+                return Err("Invalid MAC address");
+            }
+            Ok(MacAddress(mac))
+        }
+    }
+
+    impl fmt::Display for MacAddress {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let [a, b, c, d, e, g] = self.0;
+            write!(f, "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}", a, b, c, d, e, g)
+        }
+    }
+
+    // Errors only the hardware sees: frames lost before they reach the stack,
+    // or that failed after transmit() accepted them. The stack keeps its
+    // own per-interface packet and byte counters.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct DeviceStats {
+        pub rx_errors: u64,
+        pub rx_crc_errors: u64,
+        // Frames lost to a full RX ring
+        pub rx_missed: u64,
+        pub tx_errors: u64,
+        pub tx_carrier_errors: u64,
+        pub collisions: u64,
+    }
+
+    // Implemented by ethernet, WiFi and virtual drivers. The stack pushes
+    // frames through transmit(); drivers hand received frames to
+    // NetStack::receive() from their RX path.
+    pub trait NetDevice: Send {
+        fn name(&self) -> &str;
+        fn mac_address(&self) -> MacAddress;
+        fn mtu(&self) -> usize;
+        fn transmit(&mut self, frame: &[u8]) -> Result<(), &'static str>;
+
+        // Scatter-gather capable drivers override this to hand the chunks to
+        // hardware directly; the default flattens the chain first
+        fn transmit_buffer(&mut self, packet: &PacketBuffer) -> Result<(), &'static str> {
+            if packet.fragments().is_empty() {
+                self.transmit(packet.data())
+            } else {
+                self.transmit(&packet.to_vec())
+            }
+        }
+
+        fn link_up(&self) -> bool {
+            true
+        }
+
+        // False while the TX ring is full; frames then wait in the
+        // interface's queueing discipline instead of the driver
+        fn tx_ready(&self) -> bool {
+            true
+        }
+
+        // Drivers with a unicast RX filter reprogram it when the stack
+        // overrides the address; the default accepts the change as is
+        fn set_mac_address(&mut self, _mac: MacAddress) -> Result<(), &'static str> {
+            Ok(())
+        }
+
+        // Tunnels have no neighbors to resolve; the stack still hands them
+        // ethernet-framed packets, which they strip
+        fn point_to_point(&self) -> bool {
+            false
+        }
+
+        // Whether frames for other stations can be sent and received, as a
+        // bridge port needs. WiFi drivers answer true only in 4-address mode.
+        fn bridgeable(&self) -> bool {
+            !self.point_to_point()
+        }
+
+        // Bridge ports accept unicast frames for any destination
+        fn set_promiscuous(&mut self, _enabled: bool) -> Result<(), &'static str> {
+            Ok(())
+        }
+
+        fn device_stats(&self) -> DeviceStats {
+            DeviceStats::default()
+        }
+
+        // Events the controller can still see while the system sleeps;
+        // WiFi drivers report what their WoWLAN firmware supports
+        fn wake_capabilities(&self) -> WakeOnLan {
+            WakeOnLan::default()
+        }
+
+        // Programs the wake filters; they take effect when the system
+        // suspends and the device drops to D3
+        fn set_wake_on_lan(&mut self, config: WakeOnLan) -> Result<(), &'static str> {
+            if config.any() {
+                return Err("Wake-on-LAN not supported");
+            }
+            Ok(())
+        }
+    }
+
+    // Software device that keeps transmitted frames in memory; used for
+    // loopback-style setups and for exercising the stack without hardware
+    pub struct QueueDevice {
+        name: String,
+        mac: MacAddress,
+        mtu: usize,
+        transmitted: Arc<Mutex<VecDeque<Vec<u8>>>>,
+    }
+
+    impl QueueDevice {
+        pub fn new(name: &str, mac: MacAddress, mtu: usize) -> Self {
+            QueueDevice {
+                name: name.to_string(),
+                mac,
+                mtu,
+                transmitted: Arc::new(Mutex::new(VecDeque::new())),
+            }
+        }
+
+        // Shared view of transmitted frames that stays valid after the
+        // device has been handed to the stack
+        pub fn tx_queue(&self) -> Arc<Mutex<VecDeque<Vec<u8>>>> {
+            Arc::clone(&self.transmitted)
+        }
+    }
+
+    impl NetDevice for QueueDevice {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        fn mac_address(&self) -> MacAddress {
+            self.mac
+        }
+
+        fn mtu(&self) -> usize {
+            self.mtu
+        }
+
+        fn transmit(&mut self, frame: &[u8]) -> Result<(), &'static str> {
+            if frame.len() > self.mtu + 14 {
+                return Err("Frame exceeds device MTU");
+            }
+            self.transmitted.lock().unwrap().push_back(frame.to_vec());
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,509 @@
+// src/networking/packet.rs
+
+pub mod packet {
+    use crate::netdev::netdev::MacAddress;
+    use crate::pktbuf::pktbuf::PacketBuffer;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    pub const ETHERNET_HEADER_LEN: usize = 14;
+    pub const ETHERTYPE_IPV4: u16 = 0x0800;
+    pub const ETHERTYPE_ARP: u16 = 0x0806;
+    pub const ETHERTYPE_IPV6: u16 = 0x86DD;
+    pub const ETHERTYPE_WAKE_ON_LAN: u16 = 0x0842;
+
+    pub const IP_PROTO_HOPOPT: u8 = 0;
+    pub const IP_PROTO_ICMP: u8 = 1;
+    pub const IP_PROTO_IGMP: u8 = 2;
+    pub const IP_PROTO_TCP: u8 = 6;
+    pub const IP_PROTO_UDP: u8 = 17;
+    pub const IP_PROTO_IPV6_ROUTING: u8 = 43;
+    pub const IP_PROTO_IPV6_FRAGMENT: u8 = 44;
+    pub const IP_PROTO_ICMPV6: u8 = 58;
+    pub const IP_PROTO_IPV6_DSTOPTS: u8 = 60;
+
+    pub const IPV4_HEADER_LEN: usize = 20;
+    pub const IPV6_HEADER_LEN: usize = 40;
+    pub const IPV6_FRAGMENT_HEADER_LEN: usize = 8;
+
+    pub const ARP_REQUEST: u16 = 1;
+    pub const ARP_REPLY: u16 = 2;
+
+    // Internet checksum helpers (RFC 1071)
+    pub fn checksum_add(mut sum: u32, data: &[u8]) -> u32 {
+        let mut chunks = data.chunks_exact(2);
+        for chunk in &mut chunks {
+            sum += u16::from_be_bytes([chunk[0], chunk[1]]) as u32;
+        }
+        if let [last] = chunks.remainder() {
+            sum += (*last as u32) << 8;
+        }
+        sum
+    }
+
+    pub fn checksum_finish(mut sum: u32) -> u16 {
+        while sum >> 16 != 0 {
+            sum = (sum & 0xFFFF) + (sum >> 16);
+        }
+        !(sum as u16)
+    }
+
+    pub fn checksum(data: &[u8]) -> u16 {
+        checksum_finish(checksum_add(0, data))
+    }
+
+    // Checksum over the IPv4/IPv6 pseudo header followed by the upper layer payload
+    pub fn pseudo_header_checksum(source: IpAddr, destination: IpAddr, protocol: u8, payload: &[u8]) -> u16 {
+        let mut sum = 0;
+        match (source, destination) {
+            (IpAddr::V4(source), IpAddr::V4(destination)) => {
+                sum = checksum_add(sum, &source.octets());
+                sum = checksum_add(sum, &destination.octets());
+                sum += protocol as u32;
+                sum += payload.len() as u32;
+            }
+            (source, destination) => {
+                sum = checksum_add(sum, &to_ipv6(source).octets());
+                sum = checksum_add(sum, &to_ipv6(destination).octets());
+                sum = checksum_add(sum, &(payload.len() as u32).to_be_bytes());
+                sum += protocol as u32;
+            }
+        }
+        checksum_finish(checksum_add(sum, payload))
+    }
+
+    fn to_ipv6(address: IpAddr) -> Ipv6Addr {
+        match address {
+            IpAddr::V4(address) => address.to_ipv6_mapped(),
+            IpAddr::V6(address) => address,
+        }
+    }
+
+    fn read_u16(data: &[u8], offset: usize) -> u16 {
+        u16::from_be_bytes([data[offset], data[offset + 1]])
+    }
+
+    fn read_u32(data: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+    }
+
+    fn read_mac(data: &[u8], offset: usize) -> MacAddress {
+        let mut mac = [0u8; 6];
+        mac.copy_from_slice(&data[offset..offset + 6]);
+        MacAddress(mac)
+    }
+
+    fn read_ipv4(data: &[u8], offset: usize) -> Ipv4Addr {
+        Ipv4Addr::new(data[offset], data[offset + 1], data[offset + 2], data[offset + 3])
+    }
+
+    fn read_ipv6(data: &[u8], offset: usize) -> Ipv6Addr {
+        let mut octets = [0u8; 16];
+        octets.copy_from_slice(&data[offset..offset + 16]);
+        Ipv6Addr::from(octets)
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct EthernetHeader {
+        pub destination: MacAddress,
+        pub source: MacAddress,
+        pub ethertype: u16,
+    }
+
+    pub fn parse_ethernet(frame: &[u8]) -> Result<(EthernetHeader, &[u8]), &'static str> {
+        if frame.len() < ETHERNET_HEADER_LEN {
+            return Err("Truncated ethernet frame");
+        }
+        let header = EthernetHeader {
+            destination: read_mac(frame, 0),
+            source: read_mac(frame, 6),
+            ethertype: read_u16(frame, 12),
+        };
+        Ok((header, &frame[ETHERNET_HEADER_LEN..]))
+    }
+
+    // Prepends the header into the buffer's headroom
+    pub fn push_ethernet(header: &EthernetHeader, packet: &mut PacketBuffer) {
+        let data = packet.push(ETHERNET_HEADER_LEN);
+        data[..6].copy_from_slice(&header.destination.0);
+        data[6..12].copy_from_slice(&header.source.0);
+        data[12..].copy_from_slice(&header.ethertype.to_be_bytes());
+    }
+
+    // Strips the header, leaving the buffer at the network layer
+    pub fn pull_ethernet(packet: &mut PacketBuffer) -> Result<EthernetHeader, &'static str> {
+        let (header, _) = parse_ethernet(packet.data())?;
+        packet.pull(ETHERNET_HEADER_LEN)?;
+        Ok(header)
+    }
+
+    pub fn build_ethernet(header: &EthernetHeader, payload: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(ETHERNET_HEADER_LEN + payload.len());
+        frame.extend_from_slice(&header.destination.0);
+        frame.extend_from_slice(&header.source.0);
+        frame.extend_from_slice(&header.ethertype.to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct ArpPacket {
+        pub operation: u16,
+        pub sender_mac: MacAddress,
+        pub sender_ip: Ipv4Addr,
+        pub target_mac: MacAddress,
+        pub target_ip: Ipv4Addr,
+    }
+
+    pub fn parse_arp(data: &[u8]) -> Result<ArpPacket, &'static str> {
+        if data.len() < 28 {
+            return Err("Truncated ARP packet");
+        }
+        // Only Ethernet/IPv4 ARP is meaningful to us
+        if read_u16(data, 0) != 1 || read_u16(data, 2) != ETHERTYPE_IPV4 || data[4] != 6 || data[5] != 4 {
+            return Err("Unsupported ARP hardware or protocol");
+        }
+        Ok(ArpPacket {
+            operation: read_u16(data, 6),
+            sender_mac: read_mac(data, 8),
+            sender_ip: read_ipv4(data, 14),
+            target_mac: read_mac(data, 18),
+            target_ip: read_ipv4(data, 24),
+        })
+    }
+
+    // Six 0xFF bytes followed by the target address sixteen times
+    pub fn build_magic_packet(target: MacAddress) -> Vec<u8> {
+        let mut data = vec![0xFF; 6];
+        for _ in 0..16 {
+            data.extend_from_slice(&target.0);
+        }
+        data
+    }
+
+    pub fn build_arp(packet: &ArpPacket) -> Vec<u8> {
+        let mut data = Vec::with_capacity(28);
+        data.extend_from_slice(&1u16.to_be_bytes());
+        data.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        data.push(6);
+        data.push(4);
+        data.extend_from_slice(&packet.operation.to_be_bytes());
+        data.extend_from_slice(&packet.sender_mac.0);
+        data.extend_from_slice(&packet.sender_ip.octets());
+        data.extend_from_slice(&packet.target_mac.0);
+        data.extend_from_slice(&packet.target_ip.octets());
+        data
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Ipv4Header {
+        pub tos: u8,
+        pub identification: u16,
+        pub dont_fragment: bool,
+        pub more_fragments: bool,
+        // Fragment offset in bytes (always a multiple of 8)
+        pub fragment_offset: usize,
+        pub ttl: u8,
+        pub protocol: u8,
+        pub source: Ipv4Addr,
+        pub destination: Ipv4Addr,
+    }
+
+    impl Ipv4Header {
+        pub fn new(source: Ipv4Addr, destination: Ipv4Addr, protocol: u8) -> Self {
+            Ipv4Header {
+                tos: 0,
+                identification: 0,
+                dont_fragment: false,
+                more_fragments: false,
+                fragment_offset: 0,
+                ttl: 64,
+                protocol,
+                source,
+                destination,
+            }
+        }
+
+        pub fn is_fragment(&self) -> bool {
+            self.more_fragments || self.fragment_offset != 0
+        }
+    }
+
+    pub fn parse_ipv4(data: &[u8]) -> Result<(Ipv4Header, &[u8]), &'static str> {
+        let (header, header_len, total_len) = parse_ipv4_header(data, data.len())?;
+        Ok((header, &data[header_len..total_len]))
+    }
+
+    // Leaves the buffer holding the payload, trimmed to the total length
+    pub fn pull_ipv4(packet: &mut PacketBuffer) -> Result<Ipv4Header, &'static str> {
+        let (header, header_len, total_len) = parse_ipv4_header(packet.data(), packet.len())?;
+        packet.pull(header_len)?;
+        packet.trim(total_len - header_len);
+        Ok(header)
+    }
+
+    // The header must be in data; packet_len also counts any fragments
+    fn parse_ipv4_header(data: &[u8], packet_len: usize) -> Result<(Ipv4Header, usize, usize), &'static str> {
+        if data.len() < IPV4_HEADER_LEN || data[0] >> 4 != 4 {
+            return Err("Not an IPv4 packet");
+        }
+        let header_len = ((data[0] & 0xF) as usize) * 4;
+        let total_len = read_u16(data, 2) as usize;
+        if header_len < IPV4_HEADER_LEN || header_len > data.len() || total_len < header_len || total_len > packet_len {
+            return Err("Malformed IPv4 header");
+        }
+        if checksum(&data[..header_len]) != 0 {
+            return Err("Bad IPv4 header checksum");
+        }
+        let flags = read_u16(data, 6);
+        let header = Ipv4Header {
+            tos: data[1],
+            identification: read_u16(data, 4),
+            dont_fragment: flags & 0x4000 != 0,
+            more_fragments: flags & 0x2000 != 0,
+            fragment_offset: ((flags & 0x1FFF) as usize) * 8,
+            ttl: data[8],
+            protocol: data[9],
+            source: read_ipv4(data, 12),
+            destination: read_ipv4(data, 16),
+        };
+        Ok((header, header_len, total_len))
+    }
+
+    fn write_ipv4_header(header: &Ipv4Header, payload_len: usize, data: &mut [u8]) {
+        let mut flags = (header.fragment_offset / 8) as u16 & 0x1FFF;
+        if header.dont_fragment {
+            flags |= 0x4000;
+        }
+        if header.more_fragments {
+            flags |= 0x2000;
+        }
+        data[0] = 0x45;
+        data[1] = header.tos;
+        data[2..4].copy_from_slice(&((IPV4_HEADER_LEN + payload_len) as u16).to_be_bytes());
+        data[4..6].copy_from_slice(&header.identification.to_be_bytes());
+        data[6..8].copy_from_slice(&flags.to_be_bytes());
+        data[8] = header.ttl;
+        data[9] = header.protocol;
+        data[10..12].fill(0);
+        data[12..16].copy_from_slice(&header.source.octets());
+        data[16..20].copy_from_slice(&header.destination.octets());
+        let sum = checksum(&data[..IPV4_HEADER_LEN]);
+        data[10..12].copy_from_slice(&sum.to_be_bytes());
+    }
+
+    // Prepends the header into the buffer's headroom
+    pub fn push_ipv4(header: &Ipv4Header, packet: &mut PacketBuffer) {
+        let payload_len = packet.len();
+        write_ipv4_header(header, payload_len, packet.push(IPV4_HEADER_LEN));
+    }
+
+    pub fn build_ipv4(header: &Ipv4Header, payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![0; IPV4_HEADER_LEN];
+        write_ipv4_header(header, payload.len(), &mut data);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Ipv6Header {
+        pub traffic_class: u8,
+        pub flow_label: u32,
+        pub next_header: u8,
+        pub hop_limit: u8,
+        pub source: Ipv6Addr,
+        pub destination: Ipv6Addr,
+    }
+
+    impl Ipv6Header {
+        pub fn new(source: Ipv6Addr, destination: Ipv6Addr, next_header: u8) -> Self {
+            Ipv6Header {
+                traffic_class: 0,
+                flow_label: 0,
+                next_header,
+                hop_limit: 64,
+                source,
+                destination,
+            }
+        }
+    }
+
+    pub fn parse_ipv6(data: &[u8]) -> Result<(Ipv6Header, &[u8]), &'static str> {
+        let (header, payload_len) = parse_ipv6_header(data, data.len())?;
+        Ok((header, &data[IPV6_HEADER_LEN..IPV6_HEADER_LEN + payload_len]))
+    }
+
+    // Leaves the buffer holding the payload, trimmed to the payload length
+    pub fn pull_ipv6(packet: &mut PacketBuffer) -> Result<Ipv6Header, &'static str> {
+        let (header, payload_len) = parse_ipv6_header(packet.data(), packet.len())?;
+        packet.pull(IPV6_HEADER_LEN)?;
+        packet.trim(payload_len);
+        Ok(header)
+    }
+
+    // The header must be in data; packet_len also counts any fragments
+    fn parse_ipv6_header(data: &[u8], packet_len: usize) -> Result<(Ipv6Header, usize), &'static str> {
+        if data.len() < IPV6_HEADER_LEN || data[0] >> 4 != 6 {
+            return Err("Not an IPv6 packet");
+        }
+        let payload_len = read_u16(data, 4) as usize;
+        if IPV6_HEADER_LEN + payload_len > packet_len {
+            return Err("Truncated IPv6 packet");
+        }
+        let first = read_u32(data, 0);
+        let header = Ipv6Header {
+            traffic_class: ((first >> 20) & 0xFF) as u8,
+            flow_label: first & 0xF_FFFF,
+            next_header: data[6],
+            hop_limit: data[7],
+            source: read_ipv6(data, 8),
+            destination: read_ipv6(data, 24),
+        };
+        Ok((header, payload_len))
+    }
+
+    fn write_ipv6_header(header: &Ipv6Header, payload_len: usize, data: &mut [u8]) {
+        let first = (6u32 << 28) | ((header.traffic_class as u32) << 20) | (header.flow_label & 0xF_FFFF);
+        data[0..4].copy_from_slice(&first.to_be_bytes());
+        data[4..6].copy_from_slice(&(payload_len as u16).to_be_bytes());
+        data[6] = header.next_header;
+        data[7] = header.hop_limit;
+        data[8..24].copy_from_slice(&header.source.octets());
+        data[24..40].copy_from_slice(&header.destination.octets());
+    }
+
+    // Prepends the header into the buffer's headroom
+    pub fn push_ipv6(header: &Ipv6Header, packet: &mut PacketBuffer) {
+        let payload_len = packet.len();
+        write_ipv6_header(header, payload_len, packet.push(IPV6_HEADER_LEN));
+    }
+
+    pub fn build_ipv6(header: &Ipv6Header, payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![0; IPV6_HEADER_LEN];
+        write_ipv6_header(header, payload.len(), &mut data);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Ipv6Fragment {
+        pub next_header: u8,
+        pub offset: usize,
+        pub more_fragments: bool,
+        pub identification: u32,
+    }
+
+    pub fn parse_ipv6_fragment(data: &[u8]) -> Result<(Ipv6Fragment, &[u8]), &'static str> {
+        if data.len() < IPV6_FRAGMENT_HEADER_LEN {
+            return Err("Truncated IPv6 fragment header");
+        }
+        let offset_flags = read_u16(data, 2);
+        let fragment = Ipv6Fragment {
+            next_header: data[0],
+            offset: (offset_flags & !0x7) as usize,
+            more_fragments: offset_flags & 1 != 0,
+            identification: read_u32(data, 4),
+        };
+        Ok((fragment, &data[IPV6_FRAGMENT_HEADER_LEN..]))
+    }
+
+    pub fn build_ipv6_fragment(fragment: &Ipv6Fragment) -> [u8; IPV6_FRAGMENT_HEADER_LEN] {
+        let mut data = [0u8; IPV6_FRAGMENT_HEADER_LEN];
+        data[0] = fragment.next_header;
+        let offset_flags = (fragment.offset as u16 & !0x7) | fragment.more_fragments as u16;
+        data[2..4].copy_from_slice(&offset_flags.to_be_bytes());
+        data[4..8].copy_from_slice(&fragment.identification.to_be_bytes());
+        data
+    }
+
+    // Skips hop-by-hop, routing and destination option headers. Returns the
+    // next header value and the remaining data, stopping at fragment headers.
+    pub fn skip_ipv6_extensions(mut next_header: u8, mut data: &[u8]) -> Result<(u8, &[u8]), &'static str> {
+        loop {
+            match next_header {
+                IP_PROTO_HOPOPT | IP_PROTO_IPV6_ROUTING | IP_PROTO_IPV6_DSTOPTS => {
+                    if data.len() < 8 {
+                        return Err("Truncated IPv6 extension header");
+                    }
+                    let length = (data[1] as usize + 1) * 8;
+                    if data.len() < length {
+                        return Err("Truncated IPv6 extension header");
+                    }
+                    next_header = data[0];
+                    data = &data[length..];
+                }
+                _ => return Ok((next_header, data)),
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct IcmpMessage {
+        pub kind: u8,
+        pub code: u8,
+        // The 4 bytes after the checksum (identifier/sequence for echo)
+        pub rest: [u8; 4],
+        pub data: Vec<u8>,
+    }
+
+    pub fn parse_icmp(data: &[u8]) -> Result<IcmpMessage, &'static str> {
+        if data.len() < 8 {
+            return Err("Truncated ICMP message");
+        }
+        Ok(IcmpMessage {
+            kind: data[0],
+            code: data[1],
+            rest: [data[4], data[5], data[6], data[7]],
+            data: data[8..].to_vec(),
+        })
+    }
+
+    fn build_icmp_unchecked(message: &IcmpMessage) -> Vec<u8> {
+        let mut data = Vec::with_capacity(8 + message.data.len());
+        data.push(message.kind);
+        data.push(message.code);
+        data.extend_from_slice(&[0, 0]);
+        data.extend_from_slice(&message.rest);
+        data.extend_from_slice(&message.data);
+        data
+    }
+
+    pub fn build_icmpv4(message: &IcmpMessage) -> Vec<u8> {
+        let mut data = build_icmp_unchecked(message);
+        let sum = checksum(&data);
+        data[2..4].copy_from_slice(&sum.to_be_bytes());
+        data
+    }
+
+    pub fn build_icmpv6(source: Ipv6Addr, destination: Ipv6Addr, message: &IcmpMessage) -> Vec<u8> {
+        let mut data = build_icmp_unchecked(message);
+        let sum = pseudo_header_checksum(IpAddr::V6(source), IpAddr::V6(destination), IP_PROTO_ICMPV6, &data);
+        data[2..4].copy_from_slice(&sum.to_be_bytes());
+        data
+    }
+
+    // 33:33 + low 32 bits of the IPv6 multicast address
+    pub fn ipv6_multicast_mac(address: &Ipv6Addr) -> MacAddress {
+        let octets = address.octets();
+        MacAddress([0x33, 0x33, octets[12], octets[13], octets[14], octets[15]])
+    }
+
+    // 01:00:5e + low 23 bits of the IPv4 multicast address
+    pub fn ipv4_multicast_mac(address: &Ipv4Addr) -> MacAddress {
+        let octets = address.octets();
+        MacAddress([0x01, 0x00, 0x5E, octets[1] & 0x7F, octets[2], octets[3]])
+    }
+
+    pub fn solicited_node_multicast(address: &Ipv6Addr) -> Ipv6Addr {
+        let octets = address.octets();
+        Ipv6Addr::new(
+            0xFF02,
+            0,
+            0,
+            0,
+            0,
+            1,
+            0xFF00 | octets[13] as u16,
+            ((octets[14] as u16) << 8) | octets[15] as u16,
+        )
+    }
+}
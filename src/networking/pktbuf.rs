@@ -0,0 +1,291 @@
+// src/networking/pktbuf.rs
+
+pub mod pktbuf {
+    use std::sync::Arc;
+
+    // Room reserved in front of received or locally built packets for the
+    // headers lower layers prepend (ethernet, VLAN, IPv6, tunnel overhead)
+    pub const DEFAULT_HEADROOM: usize = 128;
+
+    // Read-only view into shared storage; used for scatter-gather fragments
+    #[derive(Debug, Clone)]
+    pub struct PacketSlice {
+        storage: Arc<Vec<u8>>,
+        offset: usize,
+        len: usize,
+    }
+
+    impl PacketSlice {
+        pub fn new(data: Vec<u8>) -> Self {
+            let len = data.len();
+            PacketSlice {
+                storage: Arc::new(data),
+                offset: 0,
+                len,
+            }
+        }
+
+        // Narrows the view without copying
+        pub fn slice(&self, offset: usize, len: usize) -> Result<PacketSlice, &'static str> {
+            if offset + len > self.len {
+                return Err("Slice out of range");
+            }
+            Ok(PacketSlice {
+                storage: Arc::clone(&self.storage),
+                offset: self.offset + offset,
+                len,
+            })
+        }
+
+        pub fn len(&self) -> usize {
+            self.len
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.len == 0
+        }
+
+        pub fn as_slice(&self) -> &[u8] {
+            &self.storage[self.offset..self.offset + self.len]
+        }
+    }
+
+    // Reference-counted packet buffer modelled on the skb: a linear area
+    // with headroom and tailroom plus an optional chain of fragments.
+    // Clones share storage; the linear area is copied only when a shared
+    // buffer is written to.
+    #[derive(Debug, Clone)]
+    pub struct PacketBuffer {
+        storage: Arc<Vec<u8>>,
+        head: usize,
+        tail: usize,
+        fragments: Vec<PacketSlice>,
+    }
+
+    impl PacketBuffer {
+        pub fn with_capacity(headroom: usize, capacity: usize) -> Self {
+            PacketBuffer {
+                storage: Arc::new(vec![0; headroom + capacity]),
+                head: headroom,
+                tail: headroom,
+                fragments: Vec::new(),
+            }
+        }
+
+        pub fn from_slice(headroom: usize, data: &[u8]) -> Self {
+            let mut buffer = Self::with_capacity(headroom, data.len());
+            buffer.put(data.len()).copy_from_slice(data);
+            buffer
+        }
+
+        // Takes ownership of a driver's receive buffer without copying
+        pub fn from_vec(data: Vec<u8>) -> Self {
+            let tail = data.len();
+            PacketBuffer {
+                storage: Arc::new(data),
+                head: 0,
+                tail,
+                fragments: Vec::new(),
+            }
+        }
+
+        pub fn headroom(&self) -> usize {
+            self.head
+        }
+
+        pub fn tailroom(&self) -> usize {
+            self.storage.len() - self.tail
+        }
+
+        // Length of the linear area only
+        pub fn linear_len(&self) -> usize {
+            self.tail - self.head
+        }
+
+        // Linear area plus all fragments
+        pub fn len(&self) -> usize {
+            self.linear_len() + self.fragments.iter().map(PacketSlice::len).sum::<usize>()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.len() == 0
+        }
+
+        pub fn is_shared(&self) -> bool {
+            Arc::strong_count(&self.storage) > 1
+        }
+
+        pub fn data(&self) -> &[u8] {
+            &self.storage[self.head..self.tail]
+        }
+
+        pub fn data_mut(&mut self) -> &mut [u8] {
+            let (head, tail) = (self.head, self.tail);
+            &mut self.unshare()[head..tail]
+        }
+
+        pub fn fragments(&self) -> &[PacketSlice] {
+            &self.fragments
+        }
+
+        // Copy-on-write: gives exclusive access to the storage, duplicating
+        // it if another clone still references it
+        fn unshare(&mut self) -> &mut Vec<u8> {
+            Arc::make_mut(&mut self.storage)
+        }
+
+        // Grows the storage so at least headroom/tailroom bytes are free
+        pub fn reserve(&mut self, headroom: usize, tailroom: usize) {
+            if self.headroom() >= headroom && self.tailroom() >= tailroom {
+                return;
+            }
+            let extra_head = headroom.saturating_sub(self.head);
+            let extra_tail = tailroom.saturating_sub(self.tailroom());
+            let mut storage = vec![0; self.storage.len() + extra_head + extra_tail];
+            storage[extra_head..extra_head + self.storage.len()].copy_from_slice(&self.storage);
+            self.storage = Arc::new(storage);
+            self.head += extra_head;
+            self.tail += extra_head;
+        }
+
+        // Prepends len bytes (skb_push) and returns them for the header
+        pub fn push(&mut self, len: usize) -> &mut [u8] {
+            if self.headroom() < len {
+                self.reserve(len.max(DEFAULT_HEADROOM), 0);
+            }
+            self.head -= len;
+            let (head, tail) = (self.head, self.head + len);
+            &mut self.unshare()[head..tail]
+        }
+
+        // Strips len bytes from the front (skb_pull) and returns them
+        pub fn pull(&mut self, len: usize) -> Result<&[u8], &'static str> {
+            if len > self.linear_len() {
+                return Err("Pull beyond linear data");
+            }
+            self.head += len;
+            Ok(&self.storage[self.head - len..self.head])
+        }
+
+        // Appends len bytes to the linear area (skb_put)
+        pub fn put(&mut self, len: usize) -> &mut [u8] {
+            if !self.fragments.is_empty() {
+                // Linear data must stay ahead of the fragment chain
+                self.linearize();
+            }
+            if self.tailroom() < len {
+                self.reserve(0, len);
+            }
+            self.tail += len;
+            let (start, end) = (self.tail - len, self.tail);
+            &mut self.unshare()[start..end]
+        }
+
+        // Cuts the packet down to len bytes, dropping trailing fragments
+        pub fn trim(&mut self, len: usize) {
+            let mut remaining = len;
+            if remaining <= self.linear_len() {
+                self.tail = self.head + remaining;
+                self.fragments.clear();
+                return;
+            }
+            remaining -= self.linear_len();
+            let mut keep = 0;
+            for fragment in &mut self.fragments {
+                if remaining == 0 {
+                    break;
+                }
+                if fragment.len > remaining {
+                    fragment.len = remaining;
+                }
+                remaining -= fragment.len;
+                keep += 1;
+            }
+            self.fragments.truncate(keep);
+        }
+
+        pub fn append_fragment(&mut self, fragment: PacketSlice) {
+            if !fragment.is_empty() {
+                self.fragments.push(fragment);
+            }
+        }
+
+        // Splits off everything from offset onward as a new buffer sharing
+        // this one's storage; used when segmenting large sends
+        pub fn split_off(&mut self, offset: usize) -> Result<PacketBuffer, &'static str> {
+            if offset > self.len() {
+                return Err("Split beyond packet end");
+            }
+            let mut rest = PacketBuffer::with_capacity(DEFAULT_HEADROOM, 0);
+            for chunk in self.chunks_from(offset) {
+                rest.fragments.push(chunk);
+            }
+            self.trim(offset);
+            Ok(rest)
+        }
+
+        fn chunks_from(&self, mut offset: usize) -> Vec<PacketSlice> {
+            let linear = PacketSlice {
+                storage: Arc::clone(&self.storage),
+                offset: self.head,
+                len: self.linear_len(),
+            };
+            let mut chunks = Vec::new();
+            for chunk in std::iter::once(&linear).chain(self.fragments.iter()) {
+                if offset >= chunk.len {
+                    offset -= chunk.len;
+                    continue;
+                }
+                chunks.push(PacketSlice {
+                    storage: Arc::clone(&chunk.storage),
+                    offset: chunk.offset + offset,
+                    len: chunk.len - offset,
+                });
+                offset = 0;
+            }
+            chunks
+        }
+
+        // Scatter-gather list for DMA-capable drivers: linear area first
+        pub fn chunks(&self) -> impl Iterator<Item = &[u8]> {
+            std::iter::once(self.data()).chain(self.fragments.iter().map(PacketSlice::as_slice))
+        }
+
+        // Pulls the fragments into the linear area for code that needs
+        // contiguous bytes (header parsing, non-SG drivers)
+        pub fn linearize(&mut self) {
+            if self.fragments.is_empty() {
+                return;
+            }
+            let extra: usize = self.fragments.iter().map(PacketSlice::len).sum();
+            if self.tailroom() < extra {
+                self.reserve(0, extra);
+            }
+            let fragments = std::mem::take(&mut self.fragments);
+            let mut tail = self.tail;
+            let storage = self.unshare();
+            for fragment in &fragments {
+                storage[tail..tail + fragment.len].copy_from_slice(fragment.as_slice());
+                tail += fragment.len;
+            }
+            self.tail = tail;
+        }
+
+        pub fn to_vec(&self) -> Vec<u8> {
+            let mut data = Vec::with_capacity(self.len());
+            for chunk in self.chunks() {
+                data.extend_from_slice(chunk);
+            }
+            data
+        }
+    }
+
+    // Buffers compare by content, however it is split into chunks
+    impl PartialEq for PacketBuffer {
+        fn eq(&self, other: &PacketBuffer) -> bool {
+            self.len() == other.len() && self.chunks().flatten().eq(other.chunks().flatten())
+        }
+    }
+
+    impl Eq for PacketBuffer {}
+}
@@ -0,0 +1,457 @@
+// src/networking/qdisc.rs
+
+pub mod qdisc {
+    use crate::packet::packet::{
+        parse_ethernet, ETHERTYPE_ARP, ETHERTYPE_IPV4, ETHERTYPE_IPV6, IP_PROTO_ICMP, IP_PROTO_ICMPV6,
+        IP_PROTO_TCP, IP_PROTO_UDP,
+    };
+    use crate::pktbuf::pktbuf::PacketBuffer;
+    use std::collections::VecDeque;
+
+    pub const DEFAULT_QUEUE_LIMIT: usize = 1000;
+    pub const CODEL_TARGET_MS: u64 = 5;
+    pub const CODEL_INTERVAL_MS: u64 = 100;
+
+    const PRIO_BANDS: usize = 3;
+    const DSCP_CS1: u8 = 8;
+    const DSCP_CS5: u8 = 40;
+    // TCP ACKs, DNS queries and keystrokes all fit in one of these
+    const INTERACTIVE_FRAME_LEN: usize = 128;
+    const FQ_FLOWS: usize = 1024;
+    const FQ_QUANTUM: i64 = 1514;
+
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct QdiscStats {
+        pub enqueued: u64,
+        pub dequeued: u64,
+        pub dropped: u64,
+        pub backlog: usize,
+    }
+
+    // Egress queueing discipline between the stack and a device. Frames
+    // are complete ethernet frames with their headers in the linear area;
+    // times are the stack's milliseconds.
+    pub trait Qdisc: Send {
+        // Err means the frame was dropped
+        fn enqueue(&mut self, frame: PacketBuffer, now: u64) -> Result<(), &'static str>;
+        fn dequeue(&mut self, now: u64) -> Option<PacketBuffer>;
+        fn stats(&self) -> QdiscStats;
+
+        // Shapers holding back a frame report when it becomes sendable
+        fn next_release(&self, _now: u64) -> Option<u64> {
+            None
+        }
+    }
+
+    struct FrameInfo {
+        ethertype: u16,
+        protocol: Option<u8>,
+        dscp: u8,
+        // Addresses and ports, for hashing into flows
+        flow: Vec<u8>,
+    }
+
+    fn inspect(frame: &[u8]) -> Option<FrameInfo> {
+        let (ethernet, packet) = parse_ethernet(frame).ok()?;
+        let (protocol, dscp, addresses, transport) = match ethernet.ethertype {
+            ETHERTYPE_IPV4 if packet.len() >= 20 => {
+                let header_len = (packet[0] & 0xF) as usize * 4;
+                let first_fragment = u16::from_be_bytes([packet[6], packet[7]]) & 0x1FFF == 0;
+                let transport = packet.get(header_len..).filter(|_| first_fragment);
+                (Some(packet[9]), packet[1] >> 2, &packet[12..20], transport)
+            }
+            ETHERTYPE_IPV6 if packet.len() >= 40 => {
+                let traffic_class = (u16::from_be_bytes([packet[0], packet[1]]) >> 4) as u8;
+                (Some(packet[6]), traffic_class >> 2, &packet[8..40], packet.get(40..))
+            }
+            _ => (None, 0, &[][..], None),
+        };
+        let mut flow = addresses.to_vec();
+        flow.extend(protocol);
+        if matches!(protocol, Some(IP_PROTO_TCP | IP_PROTO_UDP)) {
+            if let Some(ports) = transport.and_then(|data| data.get(..4)) {
+                flow.extend_from_slice(ports);
+            }
+        }
+        flow.extend_from_slice(&ethernet.ethertype.to_be_bytes());
+        Some(FrameInfo {
+            ethertype: ethernet.ethertype,
+            protocol,
+            dscp,
+            flow,
+        })
+    }
+
+    fn flow_hash(data: &[u8]) -> u64 {
+        data.iter()
+            .fold(0xCBF2_9CE4_8422_2325u64, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01B3))
+    }
+
+    // Plain tail-drop FIFO; the default child of the other disciplines
+    pub struct Fifo {
+        frames: VecDeque<PacketBuffer>,
+        limit: usize,
+        stats: QdiscStats,
+    }
+
+    impl Fifo {
+        pub fn new(limit: usize) -> Self {
+            Fifo {
+                frames: VecDeque::new(),
+                limit,
+                stats: QdiscStats::default(),
+            }
+        }
+    }
+
+    impl Qdisc for Fifo {
+        fn enqueue(&mut self, frame: PacketBuffer, _now: u64) -> Result<(), &'static str> {
+            if self.frames.len() >= self.limit {
+                self.stats.dropped += 1;
+                return Err("Queue full");
+            }
+            self.frames.push_back(frame);
+            self.stats.enqueued += 1;
+            self.stats.backlog = self.frames.len();
+            Ok(())
+        }
+
+        fn dequeue(&mut self, _now: u64) -> Option<PacketBuffer> {
+            let frame = self.frames.pop_front()?;
+            self.stats.dequeued += 1;
+            self.stats.backlog = self.frames.len();
+            Some(frame)
+        }
+
+        fn stats(&self) -> QdiscStats {
+            self.stats
+        }
+    }
+
+    // Strict priority over three bands: 0 for control and latency
+    // sensitive traffic, 1 best effort, 2 bulk (DSCP CS1)
+    pub struct Prio {
+        bands: Vec<Box<dyn Qdisc>>,
+    }
+
+    impl Prio {
+        pub fn new(limit: usize) -> Self {
+            Prio {
+                bands: (0..PRIO_BANDS).map(|_| Box::new(Fifo::new(limit)) as Box<dyn Qdisc>).collect(),
+            }
+        }
+
+        pub fn band(frame: &PacketBuffer) -> usize {
+            let Some(info) = inspect(frame.data()) else {
+                return 1;
+            };
+            let control = info.ethertype == ETHERTYPE_ARP || matches!(info.protocol, Some(IP_PROTO_ICMP | IP_PROTO_ICMPV6));
+            if control || info.dscp >= DSCP_CS5 {
+                0
+            } else if info.dscp == DSCP_CS1 {
+                2
+            } else if frame.len() <= INTERACTIVE_FRAME_LEN {
+                0
+            } else {
+                1
+            }
+        }
+    }
+
+    impl Qdisc for Prio {
+        fn enqueue(&mut self, frame: PacketBuffer, now: u64) -> Result<(), &'static str> {
+            let band = Prio::band(&frame);
+            self.bands[band].enqueue(frame, now)
+        }
+
+        fn dequeue(&mut self, now: u64) -> Option<PacketBuffer> {
+            self.bands.iter_mut().find_map(|band| band.dequeue(now))
+        }
+
+        fn stats(&self) -> QdiscStats {
+            self.bands.iter().fold(QdiscStats::default(), |total, band| {
+                let stats = band.stats();
+                QdiscStats {
+                    enqueued: total.enqueued + stats.enqueued,
+                    dequeued: total.dequeued + stats.dequeued,
+                    dropped: total.dropped + stats.dropped,
+                    backlog: total.backlog + stats.backlog,
+                }
+            })
+        }
+
+        fn next_release(&self, now: u64) -> Option<u64> {
+            self.bands.iter().filter_map(|band| band.next_release(now)).min()
+        }
+    }
+
+    // Rate limiter in front of a child discipline. Tokens are kept in
+    // byte-milliseconds so slow rates do not lose precision.
+    pub struct TokenBucket {
+        rate: u64,
+        burst: u64,
+        tokens: u64,
+        last_refill: Option<u64>,
+        held: Option<PacketBuffer>,
+        child: Box<dyn Qdisc>,
+    }
+
+    impl TokenBucket {
+        // rate in bytes per second; burst in bytes, at least one frame
+        pub fn new(rate: u64, burst: usize, child: Box<dyn Qdisc>) -> Result<Self, &'static str> {
+            if rate == 0 || burst == 0 {
+                return Err("Rate and burst must be non-zero");
+            }
+            Ok(TokenBucket {
+                rate,
+                burst: burst as u64 * 1000,
+                tokens: burst as u64 * 1000,
+                last_refill: None,
+                held: None,
+                child,
+            })
+        }
+
+        fn refill(&mut self, now: u64) {
+            let elapsed = self.last_refill.map_or(0, |last| now.saturating_sub(last));
+            self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+            self.last_refill = Some(now);
+        }
+    }
+
+    impl Qdisc for TokenBucket {
+        fn enqueue(&mut self, frame: PacketBuffer, now: u64) -> Result<(), &'static str> {
+            self.child.enqueue(frame, now)
+        }
+
+        fn dequeue(&mut self, now: u64) -> Option<PacketBuffer> {
+            self.refill(now);
+            if self.held.is_none() {
+                self.held = self.child.dequeue(now);
+            }
+            let cost = self.held.as_ref()?.len() as u64 * 1000;
+            if cost > self.tokens && cost <= self.burst {
+                return None;
+            }
+            self.tokens = self.tokens.saturating_sub(cost);
+            self.held.take()
+        }
+
+        fn stats(&self) -> QdiscStats {
+            let stats = self.child.stats();
+            QdiscStats {
+                backlog: stats.backlog + self.held.is_some() as usize,
+                ..stats
+            }
+        }
+
+        fn next_release(&self, now: u64) -> Option<u64> {
+            let Some(held) = &self.held else {
+                return self.child.next_release(now);
+            };
+            let missing = (held.len() as u64 * 1000).saturating_sub(self.tokens);
+            let last = self.last_refill.unwrap_or(now);
+            Some(last + missing.div_ceil(self.rate))
+        }
+    }
+
+    #[derive(Default)]
+    struct Codel {
+        first_above: Option<u64>,
+        dropping: bool,
+        drop_next: u64,
+        count: u32,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum FlowList {
+        Inactive,
+        New,
+        Old,
+    }
+
+    struct Flow {
+        frames: VecDeque<(u64, PacketBuffer)>,
+        bytes: usize,
+        deficit: i64,
+        list: FlowList,
+        codel: Codel,
+    }
+
+    // Flow-queueing with CoDel per flow (RFC 8290): deficit round robin
+    // across hashed flows, new flows first, and each flow's standing
+    // queue kept near the target delay
+    pub struct FqCodel {
+        flows: Vec<Flow>,
+        new_flows: VecDeque<usize>,
+        old_flows: VecDeque<usize>,
+        limit: usize,
+        target: u64,
+        interval: u64,
+        stats: QdiscStats,
+    }
+
+    impl FqCodel {
+        pub fn new(limit: usize) -> Self {
+            FqCodel::with_params(limit, CODEL_TARGET_MS, CODEL_INTERVAL_MS)
+        }
+
+        pub fn with_params(limit: usize, target: u64, interval: u64) -> Self {
+            FqCodel {
+                flows: (0..FQ_FLOWS)
+                    .map(|_| Flow {
+                        frames: VecDeque::new(),
+                        bytes: 0,
+                        deficit: 0,
+                        list: FlowList::Inactive,
+                        codel: Codel::default(),
+                    })
+                    .collect(),
+                new_flows: VecDeque::new(),
+                old_flows: VecDeque::new(),
+                limit,
+                target,
+                interval,
+                stats: QdiscStats::default(),
+            }
+        }
+
+        fn control_law(&self, time: u64, count: u32) -> u64 {
+            time + (self.interval as f64 / (count.max(1) as f64).sqrt()) as u64
+        }
+
+        // Drops from the head of the largest flow when over the limit
+        fn drop_fattest(&mut self) {
+            let Some(index) = (0..self.flows.len()).max_by_key(|index| self.flows[*index].bytes) else {
+                return;
+            };
+            let flow = &mut self.flows[index];
+            if let Some((_, frame)) = flow.frames.pop_front() {
+                flow.bytes -= frame.len();
+                self.stats.dropped += 1;
+                self.stats.backlog -= 1;
+            }
+        }
+
+        // CoDel dequeue for one flow (RFC 8289)
+        fn codel_dequeue(&mut self, index: usize, now: u64) -> Option<PacketBuffer> {
+            loop {
+                let (target, interval) = (self.target, self.interval);
+                let flow = &mut self.flows[index];
+                let (enqueued, frame) = flow.frames.pop_front()?;
+                flow.bytes -= frame.len();
+                self.stats.backlog -= 1;
+                let sojourn = now.saturating_sub(enqueued);
+                let ok_to_drop = if sojourn < target || flow.bytes <= FQ_QUANTUM as usize {
+                    flow.codel.first_above = None;
+                    false
+                } else {
+                    match flow.codel.first_above {
+                        Some(deadline) => now >= deadline,
+                        None => {
+                            flow.codel.first_above = Some(now + interval);
+                            false
+                        }
+                    }
+                };
+                let codel = &mut flow.codel;
+                if codel.dropping {
+                    if !ok_to_drop {
+                        codel.dropping = false;
+                        return Some(frame);
+                    }
+                    if now < codel.drop_next {
+                        return Some(frame);
+                    }
+                    codel.count += 1;
+                    let (count, drop_next) = (codel.count, codel.drop_next);
+                    self.flows[index].codel.drop_next = self.control_law(drop_next, count);
+                    self.stats.dropped += 1;
+                    continue;
+                }
+                if !ok_to_drop {
+                    return Some(frame);
+                }
+                // Re-entering soon after the last episode resumes near the
+                // previous drop rate
+                codel.dropping = true;
+                codel.count = if codel.count > 2 && now.saturating_sub(codel.drop_next) < 16 * interval {
+                    codel.count - 2
+                } else {
+                    1
+                };
+                let count = codel.count;
+                self.flows[index].codel.drop_next = self.control_law(now, count);
+                self.stats.dropped += 1;
+            }
+        }
+    }
+
+    impl Qdisc for FqCodel {
+        fn enqueue(&mut self, frame: PacketBuffer, now: u64) -> Result<(), &'static str> {
+            let hash = inspect(frame.data()).map_or(0, |info| flow_hash(&info.flow));
+            let index = (hash % FQ_FLOWS as u64) as usize;
+            let flow = &mut self.flows[index];
+            flow.bytes += frame.len();
+            flow.frames.push_back((now, frame));
+            if flow.list == FlowList::Inactive {
+                flow.list = FlowList::New;
+                flow.deficit = FQ_QUANTUM;
+                self.new_flows.push_back(index);
+            }
+            self.stats.enqueued += 1;
+            self.stats.backlog += 1;
+            if self.stats.backlog > self.limit {
+                self.drop_fattest();
+            }
+            Ok(())
+        }
+
+        fn dequeue(&mut self, now: u64) -> Option<PacketBuffer> {
+            loop {
+                let (index, list) = match self.new_flows.front() {
+                    Some(index) => (*index, FlowList::New),
+                    None => (*self.old_flows.front()?, FlowList::Old),
+                };
+                let queue = if list == FlowList::New {
+                    &mut self.new_flows
+                } else {
+                    &mut self.old_flows
+                };
+                if self.flows[index].deficit <= 0 {
+                    queue.pop_front();
+                    self.flows[index].deficit += FQ_QUANTUM;
+                    self.flows[index].list = FlowList::Old;
+                    self.old_flows.push_back(index);
+                    continue;
+                }
+                match self.codel_dequeue(index, now) {
+                    Some(frame) => {
+                        self.flows[index].deficit -= frame.len() as i64;
+                        self.stats.dequeued += 1;
+                        return Some(frame);
+                    }
+                    None => {
+                        // An emptied new flow goes behind the old ones once,
+                        // so a flow cannot stay "new" by trickling
+                        if list == FlowList::New {
+                            self.new_flows.pop_front();
+                            if !self.old_flows.is_empty() {
+                                self.flows[index].list = FlowList::Old;
+                                self.old_flows.push_back(index);
+                                continue;
+                            }
+                        } else {
+                            self.old_flows.pop_front();
+                        }
+                        self.flows[index].list = FlowList::Inactive;
+                    }
+                }
+            }
+        }
+
+        fn stats(&self) -> QdiscStats {
+            self.stats
+        }
+    }
+}
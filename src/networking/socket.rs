@@ -0,0 +1,868 @@
+// src/networking/socket.rs
+
+pub mod socket {
+    use crate::packet::packet::{IP_PROTO_TCP, IP_PROTO_UDP};
+    use crate::tcp::tcp::{CongestionAlgorithm, Endpoint, TcpInfo, TcpSocketHandle, TcpStack, TcpState};
+    use crate::udp::udp::{UdpSocketHandle, UdpStack};
+    use crate::vxnet_core::vxnet_core::{NetStack, SocketOwner};
+    use std::collections::{BTreeMap, VecDeque};
+    use std::future::Future;
+    use std::io;
+    use std::net::{IpAddr, SocketAddr};
+    use std::ops::BitOr;
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll, Waker};
+
+    pub const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+    pub const MAX_BUFFER_SIZE: usize = 4 * 1024 * 1024;
+    pub const DEFAULT_MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+    const MAX_RAW_QUEUED: usize = 256;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum SocketKind {
+        Tcp,
+        Udp,
+        // Raw IP socket for one protocol number
+        Raw(u8),
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct SocketHandle(pub usize);
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct Readiness(u8);
+
+    impl Readiness {
+        pub const EMPTY: Readiness = Readiness(0);
+        pub const READABLE: Readiness = Readiness(1);
+        pub const WRITABLE: Readiness = Readiness(2);
+        pub const HANGUP: Readiness = Readiness(4);
+        pub const ERROR: Readiness = Readiness(8);
+
+        pub fn contains(&self, other: Readiness) -> bool {
+            self.0 & other.0 == other.0
+        }
+
+        pub fn intersects(&self, other: Readiness) -> bool {
+            self.0 & other.0 != 0
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.0 == 0
+        }
+    }
+
+    impl BitOr for Readiness {
+        type Output = Readiness;
+
+        fn bitor(self, other: Readiness) -> Readiness {
+            Readiness(self.0 | other.0)
+        }
+    }
+
+    // Traffic through one socket; TCP sockets report segments and payload
+    // bytes from the connection along with its RTT estimate
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct SocketStats {
+        pub packets_sent: u64,
+        pub bytes_sent: u64,
+        pub packets_received: u64,
+        pub bytes_received: u64,
+        // Datagrams dropped because the receive buffer was full
+        pub dropped: u64,
+        pub tcp: Option<TcpInfo>,
+    }
+
+    struct RawQueue {
+        messages: VecDeque<(IpAddr, Vec<u8>)>,
+        bytes: usize,
+    }
+
+    enum Binding {
+        Unbound,
+        TcpListener(u16),
+        Tcp(TcpSocketHandle),
+        Udp(UdpSocketHandle),
+        Raw(RawQueue),
+    }
+
+    struct SocketEntry {
+        kind: SocketKind,
+        binding: Binding,
+        local: Option<SocketAddr>,
+        peer: Option<SocketAddr>,
+        send_buffer: usize,
+        recv_buffer: usize,
+        wakers: Vec<(Readiness, Waker)>,
+        owner: Option<SocketOwner>,
+        // (protocol, port) this socket claimed in the stack's owner table
+        owned_port: Option<(u8, u16)>,
+        // Datagram sockets only; TCP counters live in the connection
+        stats: SocketStats,
+    }
+
+    impl SocketEntry {
+        fn reserved(&self) -> usize {
+            self.send_buffer + self.recv_buffer
+        }
+    }
+
+    fn net_error(error: &'static str) -> io::Error {
+        match error {
+            "Connection reset by peer" => io::Error::new(io::ErrorKind::ConnectionReset, error),
+            "Port already in use" => io::Error::new(io::ErrorKind::AddrInUse, error),
+            "No route to host" => io::Error::new(io::ErrorKind::HostUnreachable, error),
+            "Operation not permitted by firewall" => io::Error::new(io::ErrorKind::PermissionDenied, error),
+            _ => io::Error::other(error),
+        }
+    }
+
+    fn would_block() -> io::Error {
+        io::Error::from(io::ErrorKind::WouldBlock)
+    }
+
+    fn not_found() -> io::Error {
+        io::Error::new(io::ErrorKind::NotFound, "Socket not found")
+    }
+
+    fn invalid(message: &'static str) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidInput, message)
+    }
+
+    // Unified TCP/UDP/raw socket table over one NetStack. Every call is
+    // non-blocking; AsyncSocket layers futures on top through wakers.
+    // Socket buffers are charged against a shared memory budget so a
+    // single connection cannot exhaust kernel memory. A TCP socket's
+    // out-of-order queue is held inside its receive reservation.
+    pub struct SocketSet {
+        net: NetStack,
+        tcp: TcpStack,
+        udp: UdpStack,
+        sockets: BTreeMap<SocketHandle, SocketEntry>,
+        // Closed TCP sockets still finishing their shutdown handshake
+        closing: Vec<TcpSocketHandle>,
+        woken: Vec<Waker>,
+        next_handle: usize,
+        memory_limit: usize,
+        memory_used: usize,
+        now: u64,
+    }
+
+    impl SocketSet {
+        pub fn new(net: NetStack) -> Self {
+            SocketSet {
+                net,
+                tcp: TcpStack::new(CongestionAlgorithm::Cubic),
+                udp: UdpStack::new(),
+                sockets: BTreeMap::new(),
+                closing: Vec::new(),
+                woken: Vec::new(),
+                next_handle: 0,
+                memory_limit: DEFAULT_MEMORY_LIMIT,
+                memory_used: 0,
+                now: 0,
+            }
+        }
+
+        pub fn with_memory_limit(net: NetStack, limit: usize) -> Self {
+            let mut set = Self::new(net);
+            set.memory_limit = limit;
+            set
+        }
+
+        pub fn net(&self) -> &NetStack {
+            &self.net
+        }
+
+        pub fn net_mut(&mut self) -> &mut NetStack {
+            &mut self.net
+        }
+
+        pub fn tcp_mut(&mut self) -> &mut TcpStack {
+            &mut self.tcp
+        }
+
+        pub fn udp_mut(&mut self) -> &mut UdpStack {
+            &mut self.udp
+        }
+
+        pub fn memory_used(&self) -> usize {
+            self.memory_used
+        }
+
+        fn charge(&mut self, amount: usize, refund: usize) -> io::Result<()> {
+            let used = self.memory_used - refund + amount;
+            if used > self.memory_limit {
+                return Err(io::Error::new(io::ErrorKind::OutOfMemory, "Socket memory limit reached"));
+            }
+            self.memory_used = used;
+            Ok(())
+        }
+
+        fn entry(&self, handle: SocketHandle) -> io::Result<&SocketEntry> {
+            self.sockets.get(&handle).ok_or_else(not_found)
+        }
+
+        fn entry_mut(&mut self, handle: SocketHandle) -> io::Result<&mut SocketEntry> {
+            self.sockets.get_mut(&handle).ok_or_else(not_found)
+        }
+
+        fn insert(&mut self, kind: SocketKind, binding: Binding) -> io::Result<SocketHandle> {
+            self.charge(2 * DEFAULT_BUFFER_SIZE, 0)?;
+            let handle = SocketHandle(self.next_handle);
+            self.next_handle += 1;
+            self.sockets.insert(
+                handle,
+                SocketEntry {
+                    kind,
+                    binding,
+                    local: None,
+                    peer: None,
+                    send_buffer: DEFAULT_BUFFER_SIZE,
+                    recv_buffer: DEFAULT_BUFFER_SIZE,
+                    wakers: Vec::new(),
+                    owner: None,
+                    owned_port: None,
+                    stats: SocketStats::default(),
+                },
+            );
+            Ok(handle)
+        }
+
+        pub fn socket(&mut self, kind: SocketKind) -> io::Result<SocketHandle> {
+            let binding = match kind {
+                SocketKind::Raw(_) => Binding::Raw(RawQueue {
+                    messages: VecDeque::new(),
+                    bytes: 0,
+                }),
+                _ => Binding::Unbound,
+            };
+            self.insert(kind, binding)
+        }
+
+        pub fn handles(&self) -> Vec<SocketHandle> {
+            self.sockets.keys().copied().collect()
+        }
+
+        pub fn socket_stats(&self, handle: SocketHandle) -> io::Result<SocketStats> {
+            let entry = self.entry(handle)?;
+            Ok(match entry.binding {
+                Binding::Tcp(connection) => {
+                    let info = self.tcp.info(connection).ok_or_else(not_found)?;
+                    SocketStats {
+                        packets_sent: info.segments_sent,
+                        bytes_sent: info.bytes_sent,
+                        packets_received: info.segments_received,
+                        bytes_received: info.bytes_received,
+                        dropped: 0,
+                        tcp: Some(info),
+                    }
+                }
+                Binding::Udp(udp) => SocketStats {
+                    dropped: self.udp.dropped(udp),
+                    ..entry.stats
+                },
+                _ => entry.stats,
+            })
+        }
+
+        pub fn kind(&self, handle: SocketHandle) -> io::Result<SocketKind> {
+            Ok(self.entry(handle)?.kind)
+        }
+
+        // Tags the socket with the task and service that opened it, so
+        // vxwall rules can match on them. Best set before bind or connect;
+        // accepted connections inherit their listener's owner.
+        pub fn set_owner(&mut self, handle: SocketHandle, owner: SocketOwner) -> io::Result<()> {
+            let entry = self.entry_mut(handle)?;
+            entry.owner = Some(owner.clone());
+            if let Some((protocol, port)) = entry.owned_port {
+                self.net.set_port_owner(protocol, port, owner);
+            }
+            Ok(())
+        }
+
+        pub fn owner(&self, handle: SocketHandle) -> io::Result<Option<&SocketOwner>> {
+            Ok(self.entry(handle)?.owner.as_ref())
+        }
+
+        fn claim_port(&mut self, handle: SocketHandle, protocol: u8, port: u16) -> io::Result<()> {
+            let entry = self.entry_mut(handle)?;
+            entry.owned_port = Some((protocol, port));
+            if let Some(owner) = entry.owner.clone() {
+                self.net.set_port_owner(protocol, port, owner);
+            }
+            Ok(())
+        }
+
+        pub fn local_addr(&self, handle: SocketHandle) -> io::Result<Option<SocketAddr>> {
+            Ok(self.entry(handle)?.local)
+        }
+
+        pub fn peer_addr(&self, handle: SocketHandle) -> io::Result<Option<SocketAddr>> {
+            Ok(self.entry(handle)?.peer)
+        }
+
+        fn apply_buffer_sizes(&mut self, handle: SocketHandle) -> io::Result<()> {
+            let entry = self.entry(handle)?;
+            let (send, recv) = (entry.send_buffer, entry.recv_buffer);
+            match entry.binding {
+                Binding::Tcp(tcp) => self.tcp.set_buffer_sizes(tcp, send, recv).map_err(net_error),
+                Binding::Udp(udp) => self.udp.set_recv_buffer(udp, recv).map_err(net_error),
+                _ => Ok(()),
+            }
+        }
+
+        pub fn set_buffer_sizes(&mut self, handle: SocketHandle, send: usize, recv: usize) -> io::Result<()> {
+            if send == 0 || recv == 0 || send > MAX_BUFFER_SIZE || recv > MAX_BUFFER_SIZE {
+                return Err(invalid("Buffer size out of range"));
+            }
+            let previous = self.entry(handle)?.reserved();
+            self.charge(send + recv, previous)?;
+            let entry = self.entry_mut(handle)?;
+            entry.send_buffer = send;
+            entry.recv_buffer = recv;
+            self.apply_buffer_sizes(handle)
+        }
+
+        pub fn buffer_sizes(&self, handle: SocketHandle) -> io::Result<(usize, usize)> {
+            let entry = self.entry(handle)?;
+            Ok((entry.send_buffer, entry.recv_buffer))
+        }
+
+        // Receive memory a TCP socket holds, out-of-order segments included
+        pub fn receive_memory(&self, handle: SocketHandle) -> io::Result<usize> {
+            match self.entry(handle)?.binding {
+                Binding::Tcp(connection) => Ok(self.tcp.receive_memory(connection)),
+                _ => Err(invalid("Socket is not a TCP connection")),
+            }
+        }
+
+        pub fn bind(&mut self, handle: SocketHandle, address: SocketAddr) -> io::Result<()> {
+            let entry = self.entry(handle)?;
+            if entry.local.is_some() {
+                return Err(invalid("Socket already bound"));
+            }
+            match entry.kind {
+                SocketKind::Udp => {
+                    let ip = Some(address.ip()).filter(|ip| !ip.is_unspecified());
+                    let udp = self.udp.bind(ip, address.port()).map_err(net_error)?;
+                    let port = self.udp.local_port(udp).unwrap_or(address.port());
+                    let entry = self.entry_mut(handle)?;
+                    entry.binding = Binding::Udp(udp);
+                    entry.local = Some(SocketAddr::new(address.ip(), port));
+                    self.claim_port(handle, IP_PROTO_UDP, port)?;
+                    self.apply_buffer_sizes(handle)
+                }
+                SocketKind::Tcp => {
+                    let port = self.tcp.bind(address.port()).map_err(net_error)?;
+                    self.entry_mut(handle)?.local = Some(SocketAddr::new(address.ip(), port));
+                    self.claim_port(handle, IP_PROTO_TCP, port)
+                }
+                SocketKind::Raw(_) => {
+                    self.entry_mut(handle)?.local = Some(address);
+                    Ok(())
+                }
+            }
+        }
+
+        pub fn listen(&mut self, handle: SocketHandle, backlog: usize) -> io::Result<()> {
+            let entry = self.entry(handle)?;
+            let local = match (entry.kind, &entry.binding, entry.local) {
+                (SocketKind::Tcp, Binding::Unbound, Some(local)) => local,
+                (SocketKind::Tcp, _, _) => return Err(invalid("Socket must be bound and unconnected")),
+                _ => return Err(invalid("Only TCP sockets listen")),
+            };
+            // The reservation made by bind passes to the listener
+            self.tcp.unbind(local.port()).map_err(net_error)?;
+            if let Err(error) = self.tcp.listen(local.port(), backlog) {
+                let _ = self.tcp.bind(local.port());
+                return Err(net_error(error));
+            }
+            self.entry_mut(handle)?.binding = Binding::TcpListener(local.port());
+            Ok(())
+        }
+
+        pub fn accept(&mut self, handle: SocketHandle) -> io::Result<(SocketHandle, SocketAddr)> {
+            let entry = self.entry(handle)?;
+            let Binding::TcpListener(port) = entry.binding else {
+                return Err(invalid("Socket is not listening"));
+            };
+            let (send, recv) = (entry.send_buffer, entry.recv_buffer);
+            let owner = entry.owner.clone();
+            let connection = self.tcp.accept(port).ok_or_else(would_block)?;
+            let (local, remote) = self.tcp.endpoints(connection).ok_or_else(not_found)?;
+            let accepted = match self.insert(SocketKind::Tcp, Binding::Tcp(connection)) {
+                Ok(accepted) => accepted,
+                Err(error) => {
+                    // Over budget: refuse the connection rather than queue it
+                    let _ = self.tcp.abort(connection);
+                    self.closing.push(connection);
+                    return Err(error);
+                }
+            };
+            let peer = SocketAddr::new(remote.address, remote.port);
+            let entry = self.entry_mut(accepted)?;
+            entry.local = Some(SocketAddr::new(local.address, local.port));
+            entry.peer = Some(peer);
+            entry.owner = owner;
+            // Inherit the listener's buffer sizes when the budget allows
+            if self.set_buffer_sizes(accepted, send, recv).is_err() {
+                self.apply_buffer_sizes(accepted)?;
+            }
+            Ok((accepted, peer))
+        }
+
+        // TCP connects start the handshake and return immediately; UDP and
+        // raw sockets only record the default destination
+        pub fn connect(&mut self, handle: SocketHandle, peer: SocketAddr) -> io::Result<()> {
+            let entry = self.entry(handle)?;
+            match entry.kind {
+                SocketKind::Tcp => {
+                    if !matches!(entry.binding, Binding::Unbound) {
+                        return Err(invalid("Socket already connected or listening"));
+                    }
+                    let bound = entry.local;
+                    let source = match bound.map(|local| local.ip()).filter(|ip| !ip.is_unspecified()) {
+                        Some(ip) => ip,
+                        None => self
+                            .net
+                            .source_address(&peer.ip())
+                            .ok_or_else(|| net_error("No route to host"))?,
+                    };
+                    let remote = Endpoint {
+                        address: peer.ip(),
+                        port: peer.port(),
+                    };
+                    // A bound socket connects from the port it reserved
+                    let connection = match bound {
+                        Some(bound) => {
+                            self.tcp.unbind(bound.port()).map_err(net_error)?;
+                            let local = Endpoint {
+                                address: source,
+                                port: bound.port(),
+                            };
+                            self.tcp.connect_from(local, remote, self.now).inspect_err(|_| {
+                                let _ = self.tcp.bind(bound.port());
+                            })
+                        }
+                        None => self.tcp.connect(source, remote, self.now),
+                    }
+                    .map_err(net_error)?;
+                    let (local, _) = self.tcp.endpoints(connection).ok_or_else(not_found)?;
+                    let entry = self.entry_mut(handle)?;
+                    entry.binding = Binding::Tcp(connection);
+                    entry.local = Some(SocketAddr::new(local.address, local.port));
+                    entry.peer = Some(peer);
+                    self.claim_port(handle, IP_PROTO_TCP, local.port)?;
+                    self.apply_buffer_sizes(handle)?;
+                    // A SYN rejected by an output rule fails the connect
+                    // instead of leaving it to time out
+                    match self.tcp.flush(&mut self.net) {
+                        Err(error @ "Operation not permitted by firewall") => {
+                            let _ = self.tcp.abort(connection);
+                            let entry = self.entry_mut(handle)?;
+                            entry.binding = Binding::Unbound;
+                            entry.local = bound;
+                            entry.peer = None;
+                            if bound.is_some() {
+                                // Hand the port back to the socket that bound it
+                                let _ = self.tcp.release(connection);
+                                self.tcp.bind(local.port).map_err(net_error)?;
+                            } else {
+                                entry.owned_port = None;
+                                self.closing.push(connection);
+                                self.net.clear_port_owner(IP_PROTO_TCP, local.port);
+                            }
+                            Err(net_error(error))
+                        }
+                        Err(error) => {
+                            log::debug!("Dropping TCP segment: {}", error);
+                            Ok(())
+                        }
+                        Ok(()) => Ok(()),
+                    }
+                }
+                SocketKind::Udp => {
+                    self.autobind(handle, &peer)?;
+                    self.entry_mut(handle)?.peer = Some(peer);
+                    Ok(())
+                }
+                SocketKind::Raw(_) => {
+                    self.entry_mut(handle)?.peer = Some(peer);
+                    Ok(())
+                }
+            }
+        }
+
+        fn autobind(&mut self, handle: SocketHandle, peer: &SocketAddr) -> io::Result<()> {
+            if self.entry(handle)?.local.is_none() {
+                let any = match peer {
+                    SocketAddr::V4(_) => IpAddr::from([0u8; 4]),
+                    SocketAddr::V6(_) => IpAddr::from([0u16; 8]),
+                };
+                self.bind(handle, SocketAddr::new(any, 0))?;
+            }
+            Ok(())
+        }
+
+        pub fn send(&mut self, handle: SocketHandle, data: &[u8]) -> io::Result<usize> {
+            let entry = self.entry(handle)?;
+            match entry.binding {
+                Binding::Tcp(connection) => {
+                    let sent = self.tcp.send(connection, data, self.now).map_err(net_error)?;
+                    self.flush();
+                    if sent == 0 && !data.is_empty() {
+                        return Err(would_block());
+                    }
+                    Ok(sent)
+                }
+                _ => {
+                    let peer = entry
+                        .peer
+                        .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "No destination"))?;
+                    self.send_to(handle, data, peer)
+                }
+            }
+        }
+
+        pub fn send_to(&mut self, handle: SocketHandle, data: &[u8], destination: SocketAddr) -> io::Result<usize> {
+            let entry = self.entry(handle)?;
+            if data.len() > entry.send_buffer {
+                return Err(invalid("Message too long"));
+            }
+            match entry.kind {
+                SocketKind::Udp => {
+                    self.autobind(handle, &destination)?;
+                    let Binding::Udp(udp) = self.entry(handle)?.binding else {
+                        return Err(not_found());
+                    };
+                    self.udp
+                        .send_to(&mut self.net, udp, destination.ip(), destination.port(), data)
+                        .map_err(net_error)?;
+                }
+                SocketKind::Raw(protocol) => {
+                    self.net.send(destination.ip(), protocol, data).map_err(net_error)?;
+                }
+                SocketKind::Tcp => return Err(invalid("TCP sockets are connection oriented")),
+            }
+            let stats = &mut self.entry_mut(handle)?.stats;
+            stats.packets_sent += 1;
+            stats.bytes_sent += data.len() as u64;
+            Ok(data.len())
+        }
+
+        pub fn recv(&mut self, handle: SocketHandle, buffer: &mut [u8]) -> io::Result<usize> {
+            match self.entry(handle)?.binding {
+                Binding::Tcp(connection) => {
+                    let count = self.tcp.recv(connection, buffer, self.now).map_err(net_error)?;
+                    self.flush();
+                    if count == 0 && !buffer.is_empty() && !self.tcp.is_eof(connection) {
+                        return Err(would_block());
+                    }
+                    Ok(count)
+                }
+                _ => self.recv_from(handle, buffer).map(|(count, _)| count),
+            }
+        }
+
+        // Datagrams larger than buffer are truncated, as with recvfrom(2)
+        pub fn recv_from(&mut self, handle: SocketHandle, buffer: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+            let entry = self.sockets.get_mut(&handle).ok_or_else(not_found)?;
+            let (source, payload) = match &mut entry.binding {
+                Binding::Udp(udp) => {
+                    let message = self.udp.recv_from(*udp).map_err(net_error)?.ok_or_else(would_block)?;
+                    (SocketAddr::new(message.source, message.source_port), message.payload)
+                }
+                Binding::Raw(queue) => {
+                    let (source, payload) = queue.messages.pop_front().ok_or_else(would_block)?;
+                    queue.bytes -= payload.len();
+                    (SocketAddr::new(source, 0), payload)
+                }
+                Binding::Unbound => return Err(would_block()),
+                _ => return Err(invalid("Socket is not datagram oriented")),
+            };
+            entry.stats.packets_received += 1;
+            entry.stats.bytes_received += payload.len() as u64;
+            let count = payload.len().min(buffer.len());
+            buffer[..count].copy_from_slice(&payload[..count]);
+            Ok((count, source))
+        }
+
+        pub fn close(&mut self, handle: SocketHandle) -> io::Result<()> {
+            let entry = self.sockets.remove(&handle).ok_or_else(not_found)?;
+            self.memory_used -= entry.reserved();
+            self.woken.extend(entry.wakers.into_iter().map(|(_, waker)| waker));
+            if let Some((protocol, port)) = entry.owned_port {
+                self.net.clear_port_owner(protocol, port);
+            }
+            match entry.binding {
+                Binding::Tcp(connection) => {
+                    self.tcp.close(connection, self.now).map_err(net_error)?;
+                    self.closing.push(connection);
+                    self.flush();
+                }
+                Binding::TcpListener(port) => self.tcp.unlisten(port).map_err(net_error)?,
+                Binding::Udp(udp) => self.udp.close(&mut self.net, udp).map_err(net_error)?,
+                Binding::Unbound => {
+                    if let (SocketKind::Tcp, Some(local)) = (entry.kind, entry.local) {
+                        let _ = self.tcp.unbind(local.port());
+                    }
+                }
+                Binding::Raw(_) => {}
+            }
+            Ok(())
+        }
+
+        pub fn readiness(&self, handle: SocketHandle) -> io::Result<Readiness> {
+            let entry = self.entry(handle)?;
+            let mut ready = Readiness::EMPTY;
+            match &entry.binding {
+                Binding::Tcp(connection) => {
+                    let connection = *connection;
+                    let state = self.tcp.state(connection).unwrap_or(TcpState::Closed);
+                    if self.tcp.readable(connection) > 0 || self.tcp.is_eof(connection) {
+                        ready = ready | Readiness::READABLE;
+                    }
+                    if matches!(state, TcpState::Established | TcpState::CloseWait) && self.tcp.send_space(connection) > 0 {
+                        ready = ready | Readiness::WRITABLE;
+                    }
+                    if state == TcpState::Closed || self.tcp.is_eof(connection) {
+                        ready = ready | Readiness::HANGUP;
+                    }
+                    if self.tcp.was_reset(connection) {
+                        ready = ready | Readiness::ERROR;
+                    }
+                }
+                Binding::TcpListener(port) => {
+                    if self.tcp.backlog_len(*port) > 0 {
+                        ready = ready | Readiness::READABLE;
+                    }
+                }
+                Binding::Udp(udp) => {
+                    ready = ready | Readiness::WRITABLE;
+                    if self.udp.queued(*udp) > 0 {
+                        ready = ready | Readiness::READABLE;
+                    }
+                }
+                Binding::Raw(queue) => {
+                    ready = ready | Readiness::WRITABLE;
+                    if !queue.messages.is_empty() {
+                        ready = ready | Readiness::READABLE;
+                    }
+                }
+                Binding::Unbound => {
+                    if entry.kind == SocketKind::Udp {
+                        ready = ready | Readiness::WRITABLE;
+                    }
+                }
+            }
+            Ok(ready)
+        }
+
+        // poll(2)-style readiness check over several sockets; errors and
+        // hangups are always reported
+        pub fn select(&self, interests: &[(SocketHandle, Readiness)]) -> Vec<(SocketHandle, Readiness)> {
+            interests
+                .iter()
+                .filter_map(|(handle, interest)| {
+                    let ready = self.readiness(*handle).ok()?;
+                    let wanted = *interest | Readiness::HANGUP | Readiness::ERROR;
+                    ready.intersects(wanted).then_some((*handle, ready))
+                })
+                .collect()
+        }
+
+        // Arranges for waker to fire once the socket becomes ready for interest
+        pub fn register_waker(&mut self, handle: SocketHandle, interest: Readiness, waker: Waker) -> io::Result<()> {
+            let ready = self.readiness(handle)?;
+            if ready.intersects(interest | Readiness::HANGUP | Readiness::ERROR) {
+                self.woken.push(waker);
+                return Ok(());
+            }
+            let entry = self.entry_mut(handle)?;
+            entry.wakers.retain(|(_, existing)| !existing.will_wake(&waker));
+            entry.wakers.push((interest, waker));
+            Ok(())
+        }
+
+        fn wake_ready(&mut self) {
+            let handles: Vec<SocketHandle> = self
+                .sockets
+                .iter()
+                .filter(|(_, entry)| !entry.wakers.is_empty())
+                .map(|(handle, _)| *handle)
+                .collect();
+            for handle in handles {
+                let Ok(ready) = self.readiness(handle) else {
+                    continue;
+                };
+                let entry = self.sockets.get_mut(&handle).unwrap();
+                let (wake, keep): (Vec<_>, Vec<_>) = entry
+                    .wakers
+                    .drain(..)
+                    .partition(|(interest, _)| ready.intersects(*interest | Readiness::HANGUP | Readiness::ERROR));
+                entry.wakers = keep;
+                self.woken.extend(wake.into_iter().map(|(_, waker)| waker));
+            }
+        }
+
+        // Wakers whose sockets became ready. They are handed out rather than
+        // called so they run after the set's lock is released; a woken task
+        // locks the set straight away.
+        pub fn take_wakers(&mut self) -> Vec<Waker> {
+            std::mem::take(&mut self.woken)
+        }
+
+        // Polls a shared set and runs the wakers once it is unlocked
+        pub fn poll_shared(set: &Mutex<SocketSet>, now: u64) {
+            let wakers = {
+                let mut set = set.lock().unwrap();
+                set.poll(now);
+                set.take_wakers()
+            };
+            for waker in wakers {
+                waker.wake();
+            }
+        }
+
+        fn flush(&mut self) {
+            if let Err(error) = self.tcp.flush(&mut self.net) {
+                log::debug!("Dropping TCP segment: {}", error);
+            }
+        }
+
+        // Drives the stack: delivers received datagrams to sockets, runs
+        // protocol timers and collects the wakers of tasks waiting on
+        // readiness for take_wakers
+        pub fn poll(&mut self, now: u64) {
+            self.now = now;
+            self.net.poll_timers(now);
+            while let Some(datagram) = self.net.take_inbound() {
+                for entry in self.sockets.values_mut() {
+                    let (SocketKind::Raw(protocol), Binding::Raw(queue)) = (entry.kind, &mut entry.binding) else {
+                        continue;
+                    };
+                    let fits = queue.messages.len() < MAX_RAW_QUEUED
+                        && queue.bytes + datagram.payload.len() <= entry.recv_buffer;
+                    if protocol != datagram.protocol {
+                        continue;
+                    }
+                    if fits {
+                        queue.bytes += datagram.payload.len();
+                        queue.messages.push_back((datagram.source, datagram.payload.to_vec()));
+                    } else {
+                        entry.stats.dropped += 1;
+                    }
+                }
+                let result = match datagram.protocol {
+                    IP_PROTO_TCP => self.tcp.handle_datagram(&datagram, now),
+                    IP_PROTO_UDP => self.udp.handle_datagram(&datagram).map(|_| ()),
+                    _ => Ok(()),
+                };
+                if let Err(error) = result {
+                    log::debug!("Dropping datagram from {}: {}", datagram.source, error);
+                }
+            }
+            self.tcp.poll(now);
+            self.flush();
+            let tcp = &mut self.tcp;
+            self.closing.retain(|connection| tcp.release(*connection).is_err());
+            self.wake_ready();
+        }
+    }
+
+    // Resolves once the socket is ready for the requested interest
+    pub struct ReadinessFuture {
+        set: Arc<Mutex<SocketSet>>,
+        handle: SocketHandle,
+        interest: Readiness,
+    }
+
+    impl Future for ReadinessFuture {
+        type Output = io::Result<Readiness>;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            let mut set = self.set.lock().unwrap();
+            let ready = match set.readiness(self.handle) {
+                Ok(ready) => ready,
+                Err(error) => return Poll::Ready(Err(error)),
+            };
+            if ready.intersects(self.interest | Readiness::HANGUP | Readiness::ERROR) {
+                return Poll::Ready(Ok(ready));
+            }
+            match set.register_waker(self.handle, self.interest, cx.waker().clone()) {
+                Ok(()) => Poll::Pending,
+                Err(error) => Poll::Ready(Err(error)),
+            }
+        }
+    }
+
+    // Async view of a socket in a shared SocketSet
+    #[derive(Clone)]
+    pub struct AsyncSocket {
+        set: Arc<Mutex<SocketSet>>,
+        handle: SocketHandle,
+    }
+
+    impl AsyncSocket {
+        pub fn new(set: Arc<Mutex<SocketSet>>, handle: SocketHandle) -> Self {
+            AsyncSocket { set, handle }
+        }
+
+        pub fn handle(&self) -> SocketHandle {
+            self.handle
+        }
+
+        pub fn ready(&self, interest: Readiness) -> ReadinessFuture {
+            ReadinessFuture {
+                set: Arc::clone(&self.set),
+                handle: self.handle,
+                interest,
+            }
+        }
+
+        pub async fn recv(&self, buffer: &mut [u8]) -> io::Result<usize> {
+            loop {
+                self.ready(Readiness::READABLE).await?;
+                match self.set.lock().unwrap().recv(self.handle, buffer) {
+                    Err(error) if error.kind() == io::ErrorKind::WouldBlock => continue,
+                    result => return result,
+                }
+            }
+        }
+
+        pub async fn recv_from(&self, buffer: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+            loop {
+                self.ready(Readiness::READABLE).await?;
+                match self.set.lock().unwrap().recv_from(self.handle, buffer) {
+                    Err(error) if error.kind() == io::ErrorKind::WouldBlock => continue,
+                    result => return result,
+                }
+            }
+        }
+
+        pub async fn send(&self, data: &[u8]) -> io::Result<usize> {
+            loop {
+                self.ready(Readiness::WRITABLE).await?;
+                match self.set.lock().unwrap().send(self.handle, data) {
+                    Err(error) if error.kind() == io::ErrorKind::WouldBlock => continue,
+                    result => return result,
+                }
+            }
+        }
+
+        pub async fn accept(&self) -> io::Result<(AsyncSocket, SocketAddr)> {
+            loop {
+                self.ready(Readiness::READABLE).await?;
+                let accepted = self.set.lock().unwrap().accept(self.handle);
+                match accepted {
+                    Ok((handle, peer)) => return Ok((AsyncSocket::new(Arc::clone(&self.set), handle), peer)),
+                    Err(error) if error.kind() == io::ErrorKind::WouldBlock => continue,
+                    Err(error) => return Err(error),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,1385 @@
+// src/networking/tcp.rs
+
+pub mod tcp {
+    use crate::packet::packet::{pseudo_header_checksum, IP_PROTO_TCP};
+    use crate::vxnet_core::vxnet_core::{Datagram, NetStack};
+    use std::collections::{BTreeMap, BTreeSet, VecDeque};
+    use std::net::IpAddr;
+
+    const FLAG_FIN: u8 = 0x01;
+    const FLAG_SYN: u8 = 0x02;
+    const FLAG_RST: u8 = 0x04;
+    const FLAG_PSH: u8 = 0x08;
+    const FLAG_ACK: u8 = 0x10;
+
+    const TCP_HEADER_LEN: usize = 20;
+    const DEFAULT_MSS: usize = 536;
+    const LOCAL_MSS: usize = 1460;
+    const LOCAL_WINDOW_SHIFT: u8 = 7;
+    const DEFAULT_BUFFER_SIZE: usize = 256 * 1024;
+
+    const INITIAL_RTO_MS: u64 = 1000;
+    const MIN_RTO_MS: u64 = 200;
+    const MAX_RTO_MS: u64 = 60_000;
+    const MAX_RETRANSMISSIONS: u32 = 12;
+    const DELAYED_ACK_MS: u64 = 40;
+    const TIME_WAIT_MS: u64 = 60_000;
+    const DUP_ACK_THRESHOLD: u32 = 3;
+
+    const EPHEMERAL_PORT_START: u16 = 49152;
+    // Half-open connections a listener holds before dropping new SYNs
+    const MAX_SYN_BACKLOG: usize = 64;
+    // Bookkeeping charged per out-of-order segment, so a flood of tiny
+    // segments cannot hold more than the receive reservation
+    const OUT_OF_ORDER_ENTRY_COST: usize = 64;
+
+    // Sequence number comparisons modulo 2^32
+    fn seq_lt(a: u32, b: u32) -> bool {
+        (a.wrapping_sub(b) as i32) < 0
+    }
+
+    fn seq_le(a: u32, b: u32) -> bool {
+        a == b || seq_lt(a, b)
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct TcpSegment {
+        pub source_port: u16,
+        pub destination_port: u16,
+        pub sequence: u32,
+        pub acknowledgment: u32,
+        pub flags: u8,
+        pub window: u16,
+        pub mss: Option<u16>,
+        pub window_shift: Option<u8>,
+        pub payload: Vec<u8>,
+    }
+
+    impl TcpSegment {
+        fn has(&self, flag: u8) -> bool {
+            self.flags & flag != 0
+        }
+
+        // Sequence space consumed by the segment (SYN and FIN count as one)
+        fn length(&self) -> u32 {
+            self.payload.len() as u32 + self.has(FLAG_SYN) as u32 + self.has(FLAG_FIN) as u32
+        }
+    }
+
+    pub fn parse_segment(source: IpAddr, destination: IpAddr, data: &[u8]) -> Result<TcpSegment, &'static str> {
+        if data.len() < TCP_HEADER_LEN {
+            return Err("Truncated TCP segment");
+        }
+        if pseudo_header_checksum(source, destination, IP_PROTO_TCP, data) != 0 {
+            return Err("Bad TCP checksum");
+        }
+        let header_len = ((data[12] >> 4) as usize) * 4;
+        if header_len < TCP_HEADER_LEN || header_len > data.len() {
+            return Err("Malformed TCP header");
+        }
+
+        let mut mss = None;
+        let mut window_shift = None;
+        let mut options = &data[TCP_HEADER_LEN..header_len];
+        while let Some(&kind) = options.first() {
+            match kind {
+                0 => break,
+                1 => options = &options[1..],
+                _ => {
+                    let length = *options.get(1).ok_or("Truncated TCP option")? as usize;
+                    if length < 2 || length > options.len() {
+                        return Err("Malformed TCP option");
+                    }
+                    match (kind, length) {
+                        (2, 4) => mss = Some(u16::from_be_bytes([options[2], options[3]])),
+                        (3, 3) => window_shift = Some(options[2].min(14)),
+                        _ => {}
+                    }
+                    options = &options[length..];
+                }
+            }
+        }
+
+        Ok(TcpSegment {
+            source_port: u16::from_be_bytes([data[0], data[1]]),
+            destination_port: u16::from_be_bytes([data[2], data[3]]),
+            sequence: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            acknowledgment: u32::from_be_bytes([data[8], data[9], data[10], data[11]]),
+            flags: data[13],
+            window: u16::from_be_bytes([data[14], data[15]]),
+            mss,
+            window_shift,
+            payload: data[header_len..].to_vec(),
+        })
+    }
+
+    pub fn build_segment(source: IpAddr, destination: IpAddr, segment: &TcpSegment) -> Vec<u8> {
+        let mut options = Vec::new();
+        if let Some(mss) = segment.mss {
+            options.extend_from_slice(&[2, 4]);
+            options.extend_from_slice(&mss.to_be_bytes());
+        }
+        if let Some(shift) = segment.window_shift {
+            options.extend_from_slice(&[1, 3, 3, shift]);
+        }
+        let header_len = TCP_HEADER_LEN + options.len();
+
+        let mut data = Vec::with_capacity(header_len + segment.payload.len());
+        data.extend_from_slice(&segment.source_port.to_be_bytes());
+        data.extend_from_slice(&segment.destination_port.to_be_bytes());
+        data.extend_from_slice(&segment.sequence.to_be_bytes());
+        data.extend_from_slice(&segment.acknowledgment.to_be_bytes());
+        data.push(((header_len / 4) as u8) << 4);
+        data.push(segment.flags);
+        data.extend_from_slice(&segment.window.to_be_bytes());
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        data.extend_from_slice(&options);
+        data.extend_from_slice(&segment.payload);
+        let sum = pseudo_header_checksum(source, destination, IP_PROTO_TCP, &data);
+        data[16..18].copy_from_slice(&sum.to_be_bytes());
+        data
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum CongestionEvent {
+        // Three duplicate ACKs
+        FastRetransmit,
+        // Retransmission timer expired
+        Timeout,
+    }
+
+    pub trait CongestionControl: Send {
+        fn name(&self) -> &'static str;
+        // Congestion window in bytes
+        fn window(&self) -> usize;
+        fn on_ack(&mut self, acked: usize, now: u64);
+        fn on_congestion(&mut self, event: CongestionEvent, in_flight: usize, now: u64);
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum CongestionAlgorithm {
+        NewReno,
+        Cubic,
+    }
+
+    impl CongestionAlgorithm {
+        pub fn create(&self, mss: usize) -> Box<dyn CongestionControl> {
+            match self {
+                CongestionAlgorithm::NewReno => Box::new(NewReno::new(mss)),
+                CongestionAlgorithm::Cubic => Box::new(Cubic::new(mss)),
+            }
+        }
+    }
+
+    fn initial_window(mss: usize) -> usize {
+        // RFC 6928
+        (10 * mss).min(14600.max(2 * mss))
+    }
+
+    pub struct NewReno {
+        mss: usize,
+        cwnd: usize,
+        ssthresh: usize,
+    }
+
+    impl NewReno {
+        pub fn new(mss: usize) -> Self {
+            NewReno {
+                mss,
+                cwnd: initial_window(mss),
+                ssthresh: usize::MAX,
+            }
+        }
+    }
+
+    impl CongestionControl for NewReno {
+        fn name(&self) -> &'static str {
+            "newreno"
+        }
+
+        fn window(&self) -> usize {
+            self.cwnd
+        }
+
+        fn on_ack(&mut self, acked: usize, _now: u64) {
+            if self.cwnd < self.ssthresh {
+                // Slow start with appropriate byte counting
+                self.cwnd += acked.min(self.mss);
+            } else {
+                self.cwnd += (self.mss * self.mss / self.cwnd).max(1);
+            }
+        }
+
+        fn on_congestion(&mut self, event: CongestionEvent, in_flight: usize, _now: u64) {
+            self.ssthresh = (in_flight / 2).max(2 * self.mss);
+            self.cwnd = match event {
+                CongestionEvent::FastRetransmit => self.ssthresh,
+                CongestionEvent::Timeout => self.mss,
+            };
+        }
+    }
+
+    // RFC 8312 CUBIC; windows are tracked in segments internally
+    pub struct Cubic {
+        mss: usize,
+        cwnd: f64,
+        ssthresh: f64,
+        w_max: f64,
+        k: f64,
+        epoch_start: Option<u64>,
+        // Reno-friendly estimate
+        w_est: f64,
+    }
+
+    impl Cubic {
+        const C: f64 = 0.4;
+        const BETA: f64 = 0.7;
+
+        pub fn new(mss: usize) -> Self {
+            Cubic {
+                mss,
+                cwnd: (initial_window(mss) / mss) as f64,
+                ssthresh: f64::MAX,
+                w_max: 0.0,
+                k: 0.0,
+                epoch_start: None,
+                w_est: 0.0,
+            }
+        }
+    }
+
+    impl CongestionControl for Cubic {
+        fn name(&self) -> &'static str {
+            "cubic"
+        }
+
+        fn window(&self) -> usize {
+            (self.cwnd * self.mss as f64) as usize
+        }
+
+        fn on_ack(&mut self, acked: usize, now: u64) {
+            let acked_segments = acked as f64 / self.mss as f64;
+            if self.cwnd < self.ssthresh {
+                self.cwnd += acked_segments.min(1.0);
+                return;
+            }
+            let start = *self.epoch_start.get_or_insert_with(|| {
+                self.w_est = self.cwnd;
+                if self.w_max <= self.cwnd {
+                    self.k = 0.0;
+                    self.w_max = self.cwnd;
+                } else {
+                    self.k = ((self.w_max - self.cwnd) / Self::C).cbrt();
+                }
+                now
+            });
+            let t = (now - start) as f64 / 1000.0;
+            let target = Self::C * (t - self.k).powi(3) + self.w_max;
+            self.w_est += 3.0 * (1.0 - Self::BETA) / (1.0 + Self::BETA) * acked_segments / self.cwnd;
+            let target = target.max(self.w_est);
+            if target > self.cwnd {
+                self.cwnd += ((target - self.cwnd) / self.cwnd).min(1.0) * acked_segments;
+            } else {
+                self.cwnd += 0.01 * acked_segments / self.cwnd;
+            }
+        }
+
+        fn on_congestion(&mut self, event: CongestionEvent, _in_flight: usize, _now: u64) {
+            self.epoch_start = None;
+            self.w_max = self.cwnd;
+            self.ssthresh = (self.cwnd * Self::BETA).max(2.0);
+            self.cwnd = match event {
+                CongestionEvent::FastRetransmit => self.ssthresh,
+                CongestionEvent::Timeout => 1.0,
+            };
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum TcpState {
+        Closed,
+        SynSent,
+        SynReceived,
+        Established,
+        FinWait1,
+        FinWait2,
+        CloseWait,
+        Closing,
+        LastAck,
+        TimeWait,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct TcpSocketHandle(pub usize);
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct Endpoint {
+        pub address: IpAddr,
+        pub port: u16,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct TcpOutbound {
+        pub source: IpAddr,
+        pub destination: IpAddr,
+        pub segment: Vec<u8>,
+    }
+
+    // Per-connection counters and timing, for monitoring
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct TcpInfo {
+        pub segments_sent: u64,
+        pub segments_received: u64,
+        // Payload bytes; retransmitted data counts again
+        pub bytes_sent: u64,
+        pub bytes_acked: u64,
+        pub bytes_received: u64,
+        pub retransmits: u64,
+        pub timeouts: u64,
+        // Milliseconds; srtt stays None until the first sample
+        pub srtt: Option<u64>,
+        pub rttvar: u64,
+        pub rto: u64,
+        pub congestion_window: usize,
+    }
+
+    struct TcpConnection {
+        local: Endpoint,
+        remote: Endpoint,
+        state: TcpState,
+        listener: Option<u16>,
+        reset: bool,
+
+        iss: u32,
+        snd_una: u32,
+        snd_nxt: u32,
+        snd_max: u32,
+        snd_wnd: usize,
+        snd_shift: u8,
+        // Sequence number of the first byte in send_buffer
+        buffer_seq: u32,
+        send_buffer: VecDeque<u8>,
+        send_capacity: usize,
+        fin_queued: bool,
+
+        rcv_nxt: u32,
+        rcv_shift: u8,
+        recv_buffer: VecDeque<u8>,
+        recv_capacity: usize,
+        // Never overlapping, and never more than the receive window holds
+        out_of_order: BTreeMap<u32, Vec<u8>>,
+        out_of_order_bytes: usize,
+        fin_received: bool,
+
+        mss: usize,
+        srtt: Option<u64>,
+        rttvar: u64,
+        rto: u64,
+        rtt_sample: Option<(u32, u64)>,
+        retransmit_deadline: Option<u64>,
+        retransmissions: u32,
+        dup_acks: u32,
+        recovery_point: Option<u32>,
+        unacked_segments: u32,
+        delayed_ack_deadline: Option<u64>,
+        time_wait_deadline: Option<u64>,
+        algorithm: CongestionAlgorithm,
+        congestion: Box<dyn CongestionControl>,
+        // Counters only; timing fields are filled in by TcpStack::info()
+        info: TcpInfo,
+    }
+
+    impl TcpConnection {
+        fn new(local: Endpoint, remote: Endpoint, iss: u32, algorithm: CongestionAlgorithm) -> Self {
+            TcpConnection {
+                local,
+                remote,
+                state: TcpState::Closed,
+                listener: None,
+                reset: false,
+                iss,
+                snd_una: iss,
+                snd_nxt: iss,
+                snd_max: iss,
+                snd_wnd: DEFAULT_MSS,
+                snd_shift: 0,
+                buffer_seq: iss.wrapping_add(1),
+                send_buffer: VecDeque::new(),
+                send_capacity: DEFAULT_BUFFER_SIZE,
+                fin_queued: false,
+                rcv_nxt: 0,
+                rcv_shift: 0,
+                recv_buffer: VecDeque::new(),
+                recv_capacity: DEFAULT_BUFFER_SIZE,
+                out_of_order: BTreeMap::new(),
+                out_of_order_bytes: 0,
+                fin_received: false,
+                mss: DEFAULT_MSS,
+                srtt: None,
+                rttvar: 0,
+                rto: INITIAL_RTO_MS,
+                rtt_sample: None,
+                retransmit_deadline: None,
+                retransmissions: 0,
+                dup_acks: 0,
+                recovery_point: None,
+                unacked_segments: 0,
+                delayed_ack_deadline: None,
+                time_wait_deadline: None,
+                algorithm,
+                congestion: algorithm.create(DEFAULT_MSS),
+                info: TcpInfo::default(),
+            }
+        }
+
+        fn in_flight(&self) -> usize {
+            self.snd_nxt.wrapping_sub(self.snd_una) as usize
+        }
+
+        fn receive_window(&self) -> usize {
+            self.recv_capacity.saturating_sub(self.recv_buffer.len())
+        }
+
+        // Memory held on the receive side, queued segments included
+        fn receive_memory(&self) -> usize {
+            self.recv_buffer.len() + self.out_of_order_bytes + self.out_of_order.len() * OUT_OF_ORDER_ENTRY_COST
+        }
+
+        // Drops queued segments from the far end until the receive side
+        // fits in its reservation again
+        fn trim_out_of_order(&mut self) {
+            while self.receive_memory() > self.recv_capacity {
+                let rcv_nxt = self.rcv_nxt;
+                let Some(last) = self.out_of_order.keys().copied().max_by_key(|start| start.wrapping_sub(rcv_nxt)) else {
+                    break;
+                };
+                let data = self.out_of_order.remove(&last).unwrap_or_default();
+                self.out_of_order_bytes -= data.len();
+            }
+        }
+
+        fn advertised_window(&self) -> u16 {
+            (self.receive_window() >> self.rcv_shift).min(u16::MAX as usize) as u16
+        }
+
+        fn fin_sequence(&self) -> u32 {
+            self.buffer_seq.wrapping_add(self.send_buffer.len() as u32)
+        }
+
+        fn segment(&self, sequence: u32, flags: u8, payload: Vec<u8>) -> TcpSegment {
+            TcpSegment {
+                source_port: self.local.port,
+                destination_port: self.remote.port,
+                sequence,
+                acknowledgment: if flags & FLAG_ACK != 0 { self.rcv_nxt } else { 0 },
+                flags,
+                window: self.advertised_window(),
+                mss: None,
+                window_shift: None,
+                payload,
+            }
+        }
+
+        fn syn_segment(&self) -> TcpSegment {
+            let flags = if self.state == TcpState::SynReceived {
+                FLAG_SYN | FLAG_ACK
+            } else {
+                FLAG_SYN
+            };
+            let mut segment = self.segment(self.iss, flags, Vec::new());
+            // Window in SYN segments is never scaled
+            segment.window = self.receive_window().min(u16::MAX as usize) as u16;
+            segment.mss = Some(LOCAL_MSS as u16);
+            segment.window_shift = Some(self.rcv_shift);
+            segment
+        }
+
+        fn ack_segment(&mut self) -> TcpSegment {
+            self.unacked_segments = 0;
+            self.delayed_ack_deadline = None;
+            self.segment(self.snd_nxt, FLAG_ACK, Vec::new())
+        }
+
+        fn arm_retransmit(&mut self, now: u64) {
+            if self.retransmit_deadline.is_none() {
+                self.retransmit_deadline = Some(now + self.rto);
+            }
+        }
+
+        fn update_rtt(&mut self, sample: u64) {
+            // RFC 6298
+            match self.srtt {
+                None => {
+                    self.srtt = Some(sample);
+                    self.rttvar = sample / 2;
+                }
+                Some(srtt) => {
+                    let delta = srtt.abs_diff(sample);
+                    self.rttvar = (3 * self.rttvar + delta) / 4;
+                    self.srtt = Some((7 * srtt + sample) / 8);
+                }
+            }
+            let rto = self.srtt.unwrap() + (4 * self.rttvar).max(1);
+            self.rto = rto.clamp(MIN_RTO_MS, MAX_RTO_MS);
+        }
+
+        // Emits new data (and a FIN) allowed by the send and congestion windows
+        fn output(&mut self, now: u64, out: &mut Vec<TcpSegment>) {
+            if !matches!(
+                self.state,
+                TcpState::Established | TcpState::CloseWait | TcpState::FinWait1 | TcpState::Closing | TcpState::LastAck
+            ) {
+                return;
+            }
+            let window = self.snd_wnd.min(self.congestion.window());
+            loop {
+                let offset = self.snd_nxt.wrapping_sub(self.buffer_seq) as usize;
+                let unsent = self.send_buffer.len().saturating_sub(offset);
+                let room = window.saturating_sub(self.in_flight());
+                if unsent > 0 && room > 0 {
+                    let length = unsent.min(room).min(self.mss);
+                    // Avoid silly-window segments while more data is in flight
+                    if length < self.mss && length < unsent && self.in_flight() > 0 {
+                        break;
+                    }
+                    let payload: Vec<u8> = self.send_buffer.range(offset..offset + length).copied().collect();
+                    let flags = if length == unsent { FLAG_ACK | FLAG_PSH } else { FLAG_ACK };
+                    if self.rtt_sample.is_none() && self.snd_nxt == self.snd_max {
+                        self.rtt_sample = Some((self.snd_nxt.wrapping_add(length as u32), now));
+                    }
+                    out.push(self.segment(self.snd_nxt, flags, payload));
+                    self.snd_nxt = self.snd_nxt.wrapping_add(length as u32);
+                    self.unacked_segments = 0;
+                    self.delayed_ack_deadline = None;
+                    self.arm_retransmit(now);
+                    continue;
+                }
+                if unsent == 0 && self.fin_queued && self.snd_nxt == self.fin_sequence() {
+                    out.push(self.segment(self.snd_nxt, FLAG_FIN | FLAG_ACK, Vec::new()));
+                    self.snd_nxt = self.snd_nxt.wrapping_add(1);
+                    self.arm_retransmit(now);
+                }
+                break;
+            }
+            if seq_lt(self.snd_max, self.snd_nxt) {
+                self.snd_max = self.snd_nxt;
+            }
+        }
+
+        // Resends the first unacknowledged segment
+        fn retransmit_head(&mut self, out: &mut Vec<TcpSegment>) {
+            self.info.retransmits += 1;
+            match self.state {
+                TcpState::SynSent | TcpState::SynReceived => {
+                    out.push(self.syn_segment());
+                    return;
+                }
+                _ => {}
+            }
+            let offset = self.snd_una.wrapping_sub(self.buffer_seq) as usize;
+            if offset < self.send_buffer.len() {
+                let length = (self.send_buffer.len() - offset).min(self.mss);
+                let payload = self.send_buffer.range(offset..offset + length).copied().collect();
+                out.push(self.segment(self.snd_una, FLAG_ACK, payload));
+            } else if self.fin_queued {
+                out.push(self.segment(self.fin_sequence(), FLAG_FIN | FLAG_ACK, Vec::new()));
+            }
+            // Karn's algorithm: no RTT samples across retransmissions
+            self.rtt_sample = None;
+        }
+
+        fn on_timer(&mut self, now: u64, out: &mut Vec<TcpSegment>) {
+            if let Some(deadline) = self.delayed_ack_deadline {
+                if now >= deadline {
+                    let ack = self.ack_segment();
+                    out.push(ack);
+                }
+            }
+            if let Some(deadline) = self.time_wait_deadline {
+                if now >= deadline {
+                    self.state = TcpState::Closed;
+                }
+            }
+            let Some(deadline) = self.retransmit_deadline else {
+                return;
+            };
+            if now < deadline {
+                return;
+            }
+            self.retransmissions += 1;
+            self.info.timeouts += 1;
+            if self.retransmissions > MAX_RETRANSMISSIONS {
+                log::info!("TCP connection to {:?} timed out", self.remote);
+                self.state = TcpState::Closed;
+                self.reset = true;
+                self.retransmit_deadline = None;
+                return;
+            }
+            if !matches!(self.state, TcpState::SynSent | TcpState::SynReceived) {
+                self.congestion.on_congestion(CongestionEvent::Timeout, self.in_flight(), now);
+            }
+            self.recovery_point = None;
+            self.dup_acks = 0;
+            self.rto = (self.rto * 2).min(MAX_RTO_MS);
+            self.retransmit_head(out);
+            // Go back N: everything after the head is resent as the window reopens
+            if !matches!(self.state, TcpState::SynSent | TcpState::SynReceived) {
+                let head = self.send_buffer.len().saturating_sub(self.snd_una.wrapping_sub(self.buffer_seq) as usize);
+                let resent = if head > 0 { head.min(self.mss) as u32 } else { 1 };
+                self.snd_nxt = self.snd_una.wrapping_add(resent);
+            }
+            self.retransmit_deadline = Some(now + self.rto);
+        }
+
+        fn schedule_ack(&mut self, now: u64, immediate: bool, out: &mut Vec<TcpSegment>) {
+            self.unacked_segments += 1;
+            if immediate || self.unacked_segments >= 2 {
+                let ack = self.ack_segment();
+                out.push(ack);
+            } else if self.delayed_ack_deadline.is_none() {
+                self.delayed_ack_deadline = Some(now + DELAYED_ACK_MS);
+            }
+        }
+
+        fn process_ack(&mut self, segment: &TcpSegment, now: u64, out: &mut Vec<TcpSegment>) {
+            let ack = segment.acknowledgment;
+            if seq_lt(self.snd_max, ack) {
+                // Acknowledges something we never sent
+                let reply = self.ack_segment();
+                out.push(reply);
+                return;
+            }
+
+            if seq_lt(self.snd_una, ack) {
+                let acked = ack.wrapping_sub(self.snd_una) as usize;
+                self.info.bytes_acked += acked as u64;
+                if let Some((sequence, sent_at)) = self.rtt_sample {
+                    if seq_le(sequence, ack) {
+                        self.update_rtt(now.saturating_sub(sent_at));
+                        self.rtt_sample = None;
+                    }
+                }
+                self.snd_una = ack;
+                if seq_lt(self.snd_nxt, ack) {
+                    self.snd_nxt = ack;
+                }
+                if seq_lt(self.buffer_seq, ack) {
+                    let drop = (ack.wrapping_sub(self.buffer_seq) as usize).min(self.send_buffer.len());
+                    self.send_buffer.drain(..drop);
+                    self.buffer_seq = self.buffer_seq.wrapping_add(drop as u32);
+                }
+                self.retransmissions = 0;
+                self.dup_acks = 0;
+                self.retransmit_deadline = None;
+                if self.in_flight() > 0 {
+                    self.arm_retransmit(now);
+                }
+
+                match self.recovery_point {
+                    Some(point) if seq_lt(ack, point) => {
+                        // NewReno partial ACK: the next hole is lost too
+                        self.retransmit_head(out);
+                    }
+                    Some(_) => self.recovery_point = None,
+                    None => self.congestion.on_ack(acked, now),
+                }
+            } else if ack == self.snd_una
+                && segment.payload.is_empty()
+                && !segment.has(FLAG_FIN)
+                && self.in_flight() > 0
+            {
+                self.dup_acks += 1;
+                if self.dup_acks == DUP_ACK_THRESHOLD && self.recovery_point.is_none() {
+                    self.congestion
+                        .on_congestion(CongestionEvent::FastRetransmit, self.in_flight(), now);
+                    self.recovery_point = Some(self.snd_nxt);
+                    self.retransmit_head(out);
+                }
+            }
+
+            self.snd_wnd = (segment.window as usize) << self.snd_shift;
+
+            let fin_acked = self.fin_queued && self.snd_una == self.fin_sequence().wrapping_add(1);
+            match self.state {
+                TcpState::FinWait1 if fin_acked => self.state = TcpState::FinWait2,
+                TcpState::Closing if fin_acked => self.enter_time_wait(now),
+                TcpState::LastAck if fin_acked => self.state = TcpState::Closed,
+                _ => {}
+            }
+        }
+
+        fn enter_time_wait(&mut self, now: u64) {
+            self.state = TcpState::TimeWait;
+            self.retransmit_deadline = None;
+            self.time_wait_deadline = Some(now + TIME_WAIT_MS);
+        }
+
+        fn process_payload(&mut self, segment: &TcpSegment, now: u64, out: &mut Vec<TcpSegment>) {
+            let mut sequence = segment.sequence;
+            let mut payload = &segment.payload[..];
+            // Trim bytes we already have
+            if seq_lt(sequence, self.rcv_nxt) {
+                let duplicate = (self.rcv_nxt.wrapping_sub(sequence) as usize).min(payload.len());
+                payload = &payload[duplicate..];
+                sequence = sequence.wrapping_add(duplicate as u32);
+            }
+            // and bytes past the window, which the peer may not send
+            if !payload.is_empty() {
+                let room = self.receive_window().saturating_sub(sequence.wrapping_sub(self.rcv_nxt) as usize);
+                payload = &payload[..payload.len().min(room)];
+            }
+
+            let mut out_of_order = false;
+            if !payload.is_empty() {
+                if sequence == self.rcv_nxt {
+                    self.deliver(payload);
+                    self.drain_out_of_order();
+                } else {
+                    out_of_order = true;
+                    self.queue_out_of_order(sequence, payload);
+                }
+            }
+
+            let fin_sequence = segment.sequence.wrapping_add(segment.payload.len() as u32);
+            let fin = segment.has(FLAG_FIN) && fin_sequence == self.rcv_nxt;
+            if fin {
+                self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+                self.fin_received = true;
+                match self.state {
+                    TcpState::Established => self.state = TcpState::CloseWait,
+                    TcpState::FinWait1 => self.state = TcpState::Closing,
+                    TcpState::FinWait2 => self.enter_time_wait(now),
+                    _ => {}
+                }
+            }
+
+            if !segment.payload.is_empty() || segment.has(FLAG_FIN) {
+                // Out of order data and FINs are acknowledged right away
+                self.schedule_ack(now, out_of_order || fin, out);
+            }
+        }
+
+        fn deliver(&mut self, data: &[u8]) {
+            let accepted = data.len().min(self.receive_window());
+            self.recv_buffer.extend(&data[..accepted]);
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(accepted as u32);
+            self.info.bytes_received += accepted as u64;
+        }
+
+        // Queued segments the in-order data has reached are delivered from
+        // rcv_nxt on, or freed if it has passed them entirely
+        fn drain_out_of_order(&mut self) {
+            while let Some(start) = self.out_of_order.keys().copied().find(|start| seq_le(*start, self.rcv_nxt)) {
+                let data = self.out_of_order.remove(&start).unwrap_or_default();
+                self.out_of_order_bytes -= data.len();
+                let covered = self.rcv_nxt.wrapping_sub(start) as usize;
+                if covered < data.len() {
+                    self.deliver(&data[covered..]);
+                }
+            }
+        }
+
+        // Stores only the bytes no queued segment covers yet, so where
+        // segments overlap the ones that arrived first are kept. Offsets are
+        // from rcv_nxt, which every queued segment starts after.
+        fn queue_out_of_order(&mut self, sequence: u32, payload: &[u8]) {
+            let mut queued: Vec<(usize, usize)> = self
+                .out_of_order
+                .iter()
+                .map(|(start, data)| (start.wrapping_sub(self.rcv_nxt) as usize, data.len()))
+                .collect();
+            queued.sort_unstable();
+            let start = sequence.wrapping_sub(self.rcv_nxt) as usize;
+            let end = start + payload.len();
+            let mut pieces = Vec::new();
+            let mut offset = start;
+            for (queued_start, length) in queued {
+                if queued_start >= end {
+                    break;
+                }
+                if queued_start > offset {
+                    pieces.push((offset, queued_start));
+                }
+                offset = offset.max(queued_start + length);
+            }
+            if offset < end {
+                pieces.push((offset, end));
+            }
+            for (from, to) in pieces {
+                // Out of order data is charged against the receive reservation
+                if self.receive_memory() + (to - from) + OUT_OF_ORDER_ENTRY_COST > self.recv_capacity {
+                    break;
+                }
+                self.out_of_order.insert(self.rcv_nxt.wrapping_add(from as u32), payload[from - start..to - start].to_vec());
+                self.out_of_order_bytes += to - from;
+            }
+        }
+
+        fn acceptable(&self, segment: &TcpSegment) -> bool {
+            let window = self.receive_window() as u32;
+            let length = segment.length();
+            let start = segment.sequence;
+            let end = start.wrapping_add(length.saturating_sub(1));
+            let window_end = self.rcv_nxt.wrapping_add(window);
+            match (length, window) {
+                (0, 0) => start == self.rcv_nxt,
+                (0, _) => seq_le(self.rcv_nxt, start) && seq_lt(start, window_end),
+                (_, 0) => false,
+                _ => {
+                    (seq_le(self.rcv_nxt, start) && seq_lt(start, window_end))
+                        || (seq_le(self.rcv_nxt, end) && seq_lt(end, window_end))
+                }
+            }
+        }
+
+        fn process(&mut self, segment: &TcpSegment, now: u64, out: &mut Vec<TcpSegment>) {
+            if self.state == TcpState::SynSent {
+                if segment.has(FLAG_ACK) && segment.acknowledgment != self.iss.wrapping_add(1) {
+                    if !segment.has(FLAG_RST) {
+                        out.push(self.segment(segment.acknowledgment, FLAG_RST, Vec::new()));
+                    }
+                    return;
+                }
+                if segment.has(FLAG_RST) {
+                    if segment.has(FLAG_ACK) {
+                        self.state = TcpState::Closed;
+                        self.reset = true;
+                    }
+                    return;
+                }
+                if segment.has(FLAG_SYN) && segment.has(FLAG_ACK) {
+                    self.apply_syn_options(segment);
+                    self.rcv_nxt = segment.sequence.wrapping_add(1);
+                    self.snd_una = segment.acknowledgment;
+                    self.retransmit_deadline = None;
+                    self.retransmissions = 0;
+                    self.state = TcpState::Established;
+                    let ack = self.ack_segment();
+                    out.push(ack);
+                    self.output(now, out);
+                }
+                return;
+            }
+
+            if !self.acceptable(segment) {
+                if !segment.has(FLAG_RST) {
+                    let ack = self.ack_segment();
+                    out.push(ack);
+                }
+                return;
+            }
+            if segment.has(FLAG_RST) {
+                self.state = TcpState::Closed;
+                self.reset = true;
+                return;
+            }
+            if segment.has(FLAG_SYN) {
+                // Retransmitted SYN-ACK or a peer restart; re-acknowledge
+                let ack = self.ack_segment();
+                out.push(ack);
+                return;
+            }
+            if !segment.has(FLAG_ACK) {
+                return;
+            }
+
+            if self.state == TcpState::SynReceived {
+                if segment.acknowledgment != self.iss.wrapping_add(1) {
+                    out.push(self.segment(segment.acknowledgment, FLAG_RST, Vec::new()));
+                    return;
+                }
+                self.state = TcpState::Established;
+                self.retransmit_deadline = None;
+                self.retransmissions = 0;
+            }
+
+            self.process_ack(segment, now, out);
+            if self.state != TcpState::Closed && self.state != TcpState::TimeWait {
+                self.process_payload(segment, now, out);
+            } else if self.state == TcpState::TimeWait && segment.has(FLAG_FIN) {
+                let ack = self.ack_segment();
+                out.push(ack);
+            }
+            self.output(now, out);
+        }
+
+        fn apply_syn_options(&mut self, segment: &TcpSegment) {
+            self.mss = segment.mss.map(|mss| mss as usize).unwrap_or(DEFAULT_MSS).min(LOCAL_MSS);
+            // Window scaling only applies when both sides offer it
+            match segment.window_shift {
+                Some(shift) => self.snd_shift = shift,
+                None => {
+                    self.snd_shift = 0;
+                    self.rcv_shift = 0;
+                }
+            }
+            // The window in a SYN is never scaled
+            self.snd_wnd = segment.window as usize;
+            // Nothing has been sent yet, so the window starts over sized for the agreed MSS
+            self.congestion = self.algorithm.create(self.mss);
+        }
+    }
+
+    struct Listener {
+        backlog: VecDeque<TcpSocketHandle>,
+        max_backlog: usize,
+    }
+
+    type ConnectionKey = (Endpoint, Endpoint);
+
+    // Keys the initial sequence numbers, so that they cannot be predicted
+    // from outside
+    fn random_secret() -> u64 {
+        let mut bytes = [0u8; 8];
+        getrandom::getrandom(&mut bytes).expect("System entropy source unavailable");
+        u64::from_ne_bytes(bytes)
+    }
+
+    // Socket-style TCP endpoint table. Incoming segments arrive through
+    // handle_datagram(); generated segments queue until flush().
+    pub struct TcpStack {
+        connections: BTreeMap<TcpSocketHandle, TcpConnection>,
+        demux: BTreeMap<ConnectionKey, TcpSocketHandle>,
+        listeners: BTreeMap<u16, Listener>,
+        // Ports reserved by bind() and not yet listening or connected
+        bound: BTreeSet<u16>,
+        outbound: VecDeque<TcpOutbound>,
+        algorithm: CongestionAlgorithm,
+        next_handle: usize,
+        next_port: u16,
+        iss_secret: u64,
+    }
+
+    impl TcpStack {
+        pub fn new(algorithm: CongestionAlgorithm) -> Self {
+            TcpStack {
+                connections: BTreeMap::new(),
+                demux: BTreeMap::new(),
+                listeners: BTreeMap::new(),
+                bound: BTreeSet::new(),
+                outbound: VecDeque::new(),
+                algorithm,
+                next_handle: 0,
+                next_port: EPHEMERAL_PORT_START,
+                iss_secret: random_secret(),
+            }
+        }
+
+        pub fn set_congestion_algorithm(&mut self, algorithm: CongestionAlgorithm) {
+            // Applies to connections opened from now on
+            self.algorithm = algorithm;
+        }
+
+        fn initial_sequence(&mut self, local: &Endpoint, remote: &Endpoint, now: u64) -> u32 {
+            // RFC 6528 style: keyed hash of the 4-tuple plus a 4us clock
+            let mut hash = self.iss_secret;
+            let mut mix = |value: u64| {
+                hash ^= value;
+                hash = hash.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+                hash ^= hash >> 31;
+            };
+            for endpoint in [local, remote] {
+                match endpoint.address {
+                    IpAddr::V4(address) => mix(u32::from(address) as u64),
+                    IpAddr::V6(address) => {
+                        let value = u128::from(address);
+                        mix(value as u64);
+                        mix((value >> 64) as u64);
+                    }
+                }
+                mix(endpoint.port as u64);
+            }
+            self.iss_secret = self.iss_secret.rotate_left(7) ^ hash;
+            (hash as u32).wrapping_add((now * 250) as u32)
+        }
+
+        fn allocate_handle(&mut self) -> TcpSocketHandle {
+            let handle = TcpSocketHandle(self.next_handle);
+            self.next_handle += 1;
+            handle
+        }
+
+        // Claimed by a listener or a bind; connections lingering on a port
+        // do not block rebinding it, as they are told apart by the peer
+        fn port_reserved(&self, port: u16) -> bool {
+            self.listeners.contains_key(&port) || self.bound.contains(&port)
+        }
+
+        fn ephemeral_port(&mut self) -> Result<u16, &'static str> {
+            for _ in EPHEMERAL_PORT_START..=u16::MAX {
+                let port = self.next_port;
+                self.next_port = if port == u16::MAX { EPHEMERAL_PORT_START } else { port + 1 };
+                if !self.port_reserved(port) && !self.demux.keys().any(|(local, _)| local.port == port) {
+                    return Ok(port);
+                }
+            }
+            Err("No free ephemeral ports")
+        }
+
+        // Reserves a local port until unbind(); port 0 picks an ephemeral one
+        pub fn bind(&mut self, port: u16) -> Result<u16, &'static str> {
+            let port = match port {
+                0 => self.ephemeral_port()?,
+                port if self.port_reserved(port) => return Err("Port already in use"),
+                port => port,
+            };
+            self.bound.insert(port);
+            Ok(port)
+        }
+
+        pub fn unbind(&mut self, port: u16) -> Result<(), &'static str> {
+            self.bound.remove(&port).then_some(()).ok_or("Port not bound")
+        }
+
+        pub fn listen(&mut self, port: u16, max_backlog: usize) -> Result<(), &'static str> {
+            if self.port_reserved(port) {
+                return Err("Port already in use");
+            }
+            self.listeners.insert(
+                port,
+                Listener {
+                    backlog: VecDeque::new(),
+                    max_backlog: max_backlog.max(1),
+                },
+            );
+            Ok(())
+        }
+
+        pub fn unlisten(&mut self, port: u16) -> Result<(), &'static str> {
+            self.listeners.remove(&port).map(|_| ()).ok_or("Port not listening")
+        }
+
+        pub fn accept(&mut self, port: u16) -> Option<TcpSocketHandle> {
+            self.listeners.get_mut(&port)?.backlog.pop_front()
+        }
+
+        pub fn connect(
+            &mut self,
+            local_address: IpAddr,
+            remote: Endpoint,
+            now: u64,
+        ) -> Result<TcpSocketHandle, &'static str> {
+            let local = Endpoint {
+                address: local_address,
+                port: self.ephemeral_port()?,
+            };
+            self.connect_from(local, remote, now)
+        }
+
+        // Connects from a chosen local port, typically one just released
+        // with unbind()
+        pub fn connect_from(&mut self, local: Endpoint, remote: Endpoint, now: u64) -> Result<TcpSocketHandle, &'static str> {
+            if local.address.is_ipv4() != remote.address.is_ipv4() {
+                return Err("Address families differ");
+            }
+            if self.port_reserved(local.port) {
+                return Err("Port already in use");
+            }
+            if self.demux.contains_key(&(local, remote)) {
+                return Err("Connection already exists");
+            }
+            let iss = self.initial_sequence(&local, &remote, now);
+            let mut connection = TcpConnection::new(local, remote, iss, self.algorithm);
+            connection.state = TcpState::SynSent;
+            connection.rcv_shift = LOCAL_WINDOW_SHIFT;
+            connection.snd_nxt = iss.wrapping_add(1);
+            connection.snd_max = connection.snd_nxt;
+            connection.arm_retransmit(now);
+            let syn = connection.syn_segment();
+
+            let handle = self.allocate_handle();
+            self.demux.insert((local, remote), handle);
+            self.connections.insert(handle, connection);
+            self.queue(handle, vec![syn]);
+            Ok(handle)
+        }
+
+        pub fn state(&self, handle: TcpSocketHandle) -> Option<TcpState> {
+            self.connections.get(&handle).map(|connection| connection.state)
+        }
+
+        pub fn was_reset(&self, handle: TcpSocketHandle) -> bool {
+            self.connections.get(&handle).is_some_and(|connection| connection.reset)
+        }
+
+        pub fn endpoints(&self, handle: TcpSocketHandle) -> Option<(Endpoint, Endpoint)> {
+            self.connections.get(&handle).map(|connection| (connection.local, connection.remote))
+        }
+
+        pub fn congestion_window(&self, handle: TcpSocketHandle) -> Option<usize> {
+            self.connections.get(&handle).map(|connection| connection.congestion.window())
+        }
+
+        pub fn smoothed_rtt(&self, handle: TcpSocketHandle) -> Option<u64> {
+            self.connections.get(&handle).and_then(|connection| connection.srtt)
+        }
+
+        pub fn info(&self, handle: TcpSocketHandle) -> Option<TcpInfo> {
+            self.connections.get(&handle).map(|connection| TcpInfo {
+                srtt: connection.srtt,
+                rttvar: connection.rttvar,
+                rto: connection.rto,
+                congestion_window: connection.congestion.window(),
+                ..connection.info
+            })
+        }
+
+        // Queues data for transmission; returns how many bytes fit in the send buffer
+        pub fn send(&mut self, handle: TcpSocketHandle, data: &[u8], now: u64) -> Result<usize, &'static str> {
+            let connection = self.connections.get_mut(&handle).ok_or("Socket not found")?;
+            match connection.state {
+                TcpState::SynSent | TcpState::SynReceived | TcpState::Established | TcpState::CloseWait => {}
+                _ => return Err("Socket is not open for sending"),
+            }
+            if connection.fin_queued {
+                return Err("Socket already shut down for writing");
+            }
+            let room = connection.send_capacity.saturating_sub(connection.send_buffer.len());
+            let accepted = data.len().min(room);
+            connection.send_buffer.extend(&data[..accepted]);
+            let mut out = Vec::new();
+            connection.output(now, &mut out);
+            self.queue(handle, out);
+            Ok(accepted)
+        }
+
+        pub fn recv(&mut self, handle: TcpSocketHandle, buffer: &mut [u8], now: u64) -> Result<usize, &'static str> {
+            let connection = self.connections.get_mut(&handle).ok_or("Socket not found")?;
+            let window_before = connection.advertised_window();
+            let count = buffer.len().min(connection.recv_buffer.len());
+            for (slot, byte) in buffer.iter_mut().zip(connection.recv_buffer.drain(..count)) {
+                *slot = byte;
+            }
+            if count == 0 && connection.reset {
+                return Err("Connection reset by peer");
+            }
+            // Window update once the reader frees a meaningful amount of space
+            let mut out = Vec::new();
+            if window_before == 0 && connection.advertised_window() > 0 && connection.state == TcpState::Established {
+                out.push(connection.ack_segment());
+            }
+            connection.output(now, &mut out);
+            self.queue(handle, out);
+            Ok(count)
+        }
+
+        // Resizes the socket buffers; never shrinks below data already queued
+        pub fn set_buffer_sizes(&mut self, handle: TcpSocketHandle, send: usize, recv: usize) -> Result<(), &'static str> {
+            let connection = self.connections.get_mut(&handle).ok_or("Socket not found")?;
+            connection.send_capacity = send.max(connection.send_buffer.len());
+            connection.recv_capacity = recv.max(connection.recv_buffer.len());
+            connection.trim_out_of_order();
+            Ok(())
+        }
+
+        pub fn send_space(&self, handle: TcpSocketHandle) -> usize {
+            self.connections.get(&handle).map_or(0, |connection| {
+                connection.send_capacity.saturating_sub(connection.send_buffer.len())
+            })
+        }
+
+        pub fn backlog_len(&self, port: u16) -> usize {
+            self.listeners.get(&port).map_or(0, |listener| listener.backlog.len())
+        }
+
+        pub fn readable(&self, handle: TcpSocketHandle) -> usize {
+            self.connections.get(&handle).map_or(0, |connection| connection.recv_buffer.len())
+        }
+
+        // Received past a hole, waiting for it to be filled
+        pub fn out_of_order_bytes(&self, handle: TcpSocketHandle) -> usize {
+            self.connections.get(&handle).map_or(0, |connection| connection.out_of_order_bytes)
+        }
+
+        // Buffered and out-of-order bytes plus queue bookkeeping; never
+        // more than the receive buffer size
+        pub fn receive_memory(&self, handle: TcpSocketHandle) -> usize {
+            self.connections.get(&handle).map_or(0, |connection| connection.receive_memory())
+        }
+
+        pub fn is_eof(&self, handle: TcpSocketHandle) -> bool {
+            self.connections
+                .get(&handle)
+                .is_some_and(|connection| connection.fin_received && connection.recv_buffer.is_empty())
+        }
+
+        pub fn close(&mut self, handle: TcpSocketHandle, now: u64) -> Result<(), &'static str> {
+            let connection = self.connections.get_mut(&handle).ok_or("Socket not found")?;
+            match connection.state {
+                TcpState::Established | TcpState::SynReceived => connection.state = TcpState::FinWait1,
+                TcpState::CloseWait => connection.state = TcpState::LastAck,
+                TcpState::SynSent => {
+                    connection.state = TcpState::Closed;
+                    return Ok(());
+                }
+                _ => return Ok(()),
+            }
+            connection.fin_queued = true;
+            let mut out = Vec::new();
+            connection.output(now, &mut out);
+            self.queue(handle, out);
+            Ok(())
+        }
+
+        pub fn abort(&mut self, handle: TcpSocketHandle) -> Result<(), &'static str> {
+            let connection = self.connections.get_mut(&handle).ok_or("Socket not found")?;
+            if connection.state != TcpState::Closed {
+                let reset = connection.segment(connection.snd_nxt, FLAG_RST | FLAG_ACK, Vec::new());
+                connection.state = TcpState::Closed;
+                self.queue(handle, vec![reset]);
+            }
+            Ok(())
+        }
+
+        // Frees a closed socket's resources
+        pub fn release(&mut self, handle: TcpSocketHandle) -> Result<(), &'static str> {
+            match self.connections.get(&handle) {
+                Some(connection) if connection.state == TcpState::Closed => {
+                    let key = (connection.local, connection.remote);
+                    self.demux.remove(&key);
+                    self.connections.remove(&handle);
+                    Ok(())
+                }
+                Some(_) => Err("Socket still open"),
+                None => Err("Socket not found"),
+            }
+        }
+
+        fn queue(&mut self, handle: TcpSocketHandle, segments: Vec<TcpSegment>) {
+            let Some(connection) = self.connections.get_mut(&handle) else {
+                return;
+            };
+            let (source, destination) = (connection.local.address, connection.remote.address);
+            for segment in segments {
+                connection.info.segments_sent += 1;
+                connection.info.bytes_sent += segment.payload.len() as u64;
+                self.outbound.push_back(TcpOutbound {
+                    source,
+                    destination,
+                    segment: build_segment(source, destination, &segment),
+                });
+            }
+        }
+
+        fn reply_reset(&mut self, datagram: &Datagram, segment: &TcpSegment) {
+            if segment.has(FLAG_RST) {
+                return;
+            }
+            let (sequence, acknowledgment, flags) = if segment.has(FLAG_ACK) {
+                (segment.acknowledgment, 0, FLAG_RST)
+            } else {
+                (0, segment.sequence.wrapping_add(segment.length()), FLAG_RST | FLAG_ACK)
+            };
+            let reset = TcpSegment {
+                source_port: segment.destination_port,
+                destination_port: segment.source_port,
+                sequence,
+                acknowledgment,
+                flags,
+                window: 0,
+                mss: None,
+                window_shift: None,
+                payload: Vec::new(),
+            };
+            self.outbound.push_back(TcpOutbound {
+                source: datagram.destination,
+                destination: datagram.source,
+                segment: build_segment(datagram.destination, datagram.source, &reset),
+            });
+        }
+
+        pub fn handle_datagram(&mut self, datagram: &Datagram, now: u64) -> Result<(), &'static str> {
+            let segment = parse_segment(datagram.source, datagram.destination, datagram.payload.data())?;
+            let local = Endpoint {
+                address: datagram.destination,
+                port: segment.destination_port,
+            };
+            let remote = Endpoint {
+                address: datagram.source,
+                port: segment.source_port,
+            };
+
+            if let Some(handle) = self.demux.get(&(local, remote)).copied() {
+                let connection = self.connections.get_mut(&handle).unwrap();
+                connection.info.segments_received += 1;
+                let was_established = connection.state == TcpState::Established;
+                let mut out = Vec::new();
+                connection.process(&segment, now, &mut out);
+                let newly_established = !was_established && connection.state == TcpState::Established;
+                let listener = connection.listener;
+                self.queue(handle, out);
+                if newly_established {
+                    if let Some(port) = listener {
+                        if let Some(listener) = self.listeners.get_mut(&port) {
+                            listener.backlog.push_back(handle);
+                        }
+                    }
+                }
+                return Ok(());
+            }
+
+            let half_open = self
+                .connections
+                .values()
+                .filter(|connection| connection.state == TcpState::SynReceived && connection.listener == Some(local.port))
+                .count();
+            let backlog_full = match self.listeners.get(&local.port) {
+                Some(listener) => listener.backlog.len() >= listener.max_backlog || half_open >= MAX_SYN_BACKLOG,
+                None => {
+                    self.reply_reset(datagram, &segment);
+                    return Ok(());
+                }
+            };
+            if !segment.has(FLAG_SYN) || segment.has(FLAG_ACK) || segment.has(FLAG_RST) {
+                self.reply_reset(datagram, &segment);
+                return Ok(());
+            }
+            if backlog_full {
+                // Drop the SYN; the peer retries once the application catches up
+                return Ok(());
+            }
+
+            let iss = self.initial_sequence(&local, &remote, now);
+            let mut connection = TcpConnection::new(local, remote, iss, self.algorithm);
+            connection.state = TcpState::SynReceived;
+            connection.listener = Some(local.port);
+            connection.rcv_shift = if segment.window_shift.is_some() { LOCAL_WINDOW_SHIFT } else { 0 };
+            connection.apply_syn_options(&segment);
+            connection.rcv_nxt = segment.sequence.wrapping_add(1);
+            connection.snd_nxt = iss.wrapping_add(1);
+            connection.snd_max = connection.snd_nxt;
+            connection.arm_retransmit(now);
+            let syn_ack = connection.syn_segment();
+
+            let handle = self.allocate_handle();
+            self.demux.insert((local, remote), handle);
+            self.connections.insert(handle, connection);
+            self.queue(handle, vec![syn_ack]);
+            Ok(())
+        }
+
+        // Drives retransmission, delayed ACK and TIME-WAIT timers
+        pub fn poll(&mut self, now: u64) {
+            let handles: Vec<TcpSocketHandle> = self.connections.keys().copied().collect();
+            for handle in handles {
+                let connection = self.connections.get_mut(&handle).unwrap();
+                let mut out = Vec::new();
+                connection.on_timer(now, &mut out);
+                connection.output(now, &mut out);
+                self.queue(handle, out);
+            }
+        }
+
+        pub fn take_outbound(&mut self) -> Option<TcpOutbound> {
+            self.outbound.pop_front()
+        }
+
+        // Hands queued segments to the IP layer
+        pub fn flush(&mut self, net: &mut NetStack) -> Result<(), &'static str> {
+            while let Some(outbound) = self.outbound.pop_front() {
+                net.send_from(outbound.source, outbound.destination, IP_PROTO_TCP, &outbound.segment, 64)?;
+            }
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,311 @@
+// src/networking/udp.rs
+
+pub mod udp {
+    use crate::packet::packet::{pseudo_header_checksum, IP_PROTO_UDP};
+    use crate::vxnet_core::vxnet_core::{Datagram, InterfaceId, NetStack};
+    use std::collections::{BTreeMap, VecDeque};
+    use std::net::IpAddr;
+
+    pub const UDP_HEADER_LEN: usize = 8;
+    const MAX_QUEUED_PER_SOCKET: usize = 256;
+    pub const DEFAULT_RECV_BUFFER: usize = 212_992;
+    const EPHEMERAL_PORT_START: u16 = 49152;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct UdpHeader {
+        pub source_port: u16,
+        pub destination_port: u16,
+    }
+
+    pub fn parse_udp(source: IpAddr, destination: IpAddr, data: &[u8]) -> Result<(UdpHeader, &[u8]), &'static str> {
+        if data.len() < UDP_HEADER_LEN {
+            return Err("Truncated UDP datagram");
+        }
+        let length = u16::from_be_bytes([data[4], data[5]]) as usize;
+        if length < UDP_HEADER_LEN || length > data.len() {
+            return Err("Bad UDP length");
+        }
+        let sum = u16::from_be_bytes([data[6], data[7]]);
+        // A zero checksum means "none" over IPv4; IPv6 makes it mandatory
+        if (sum != 0 || destination.is_ipv6())
+            && pseudo_header_checksum(source, destination, IP_PROTO_UDP, &data[..length]) != 0
+        {
+            return Err("Bad UDP checksum");
+        }
+        let header = UdpHeader {
+            source_port: u16::from_be_bytes([data[0], data[1]]),
+            destination_port: u16::from_be_bytes([data[2], data[3]]),
+        };
+        Ok((header, &data[UDP_HEADER_LEN..length]))
+    }
+
+    pub fn build_udp(source: IpAddr, destination: IpAddr, header: &UdpHeader, payload: &[u8]) -> Vec<u8> {
+        let length = (UDP_HEADER_LEN + payload.len()) as u16;
+        let mut data = Vec::with_capacity(length as usize);
+        data.extend_from_slice(&header.source_port.to_be_bytes());
+        data.extend_from_slice(&header.destination_port.to_be_bytes());
+        data.extend_from_slice(&length.to_be_bytes());
+        data.extend_from_slice(&[0, 0]);
+        data.extend_from_slice(payload);
+        let sum = match pseudo_header_checksum(source, destination, IP_PROTO_UDP, &data) {
+            0 => 0xFFFF,
+            sum => sum,
+        };
+        data[6..8].copy_from_slice(&sum.to_be_bytes());
+        data
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct UdpSocketHandle(pub usize);
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct UdpMessage {
+        pub interface: InterfaceId,
+        pub source: IpAddr,
+        pub source_port: u16,
+        pub destination: IpAddr,
+        pub payload: Vec<u8>,
+    }
+
+    struct UdpSocket {
+        // None binds every local address
+        address: Option<IpAddr>,
+        port: u16,
+        groups: Vec<(InterfaceId, IpAddr)>,
+        queue: VecDeque<UdpMessage>,
+        queued_bytes: usize,
+        recv_buffer: usize,
+        dropped: u64,
+    }
+
+    impl UdpSocket {
+        fn accepts(&self, destination: &IpAddr, interface: InterfaceId) -> bool {
+            if destination.is_multicast() {
+                return self.groups.contains(&(interface, *destination));
+            }
+            match self.address {
+                None => true,
+                Some(address) => address == *destination || is_broadcast(destination),
+            }
+        }
+    }
+
+    fn is_broadcast(address: &IpAddr) -> bool {
+        match address {
+            IpAddr::V4(address) => address.is_broadcast(),
+            IpAddr::V6(_) => false,
+        }
+    }
+
+    pub struct UdpStack {
+        sockets: BTreeMap<UdpSocketHandle, UdpSocket>,
+        next_handle: usize,
+        next_port: u16,
+    }
+
+    impl UdpStack {
+        pub fn new() -> Self {
+            UdpStack {
+                sockets: BTreeMap::new(),
+                next_handle: 0,
+                next_port: EPHEMERAL_PORT_START,
+            }
+        }
+
+        fn port_in_use(&self, address: Option<IpAddr>, port: u16) -> bool {
+            self.sockets.values().any(|socket| {
+                socket.port == port && (socket.address.is_none() || address.is_none() || socket.address == address)
+            })
+        }
+
+        fn ephemeral_port(&mut self) -> Result<u16, &'static str> {
+            for _ in EPHEMERAL_PORT_START..=u16::MAX {
+                let port = self.next_port;
+                self.next_port = if port == u16::MAX { EPHEMERAL_PORT_START } else { port + 1 };
+                if !self.sockets.values().any(|socket| socket.port == port) {
+                    return Ok(port);
+                }
+            }
+            Err("No free ephemeral ports")
+        }
+
+        // Port 0 picks an ephemeral port
+        pub fn bind(&mut self, address: Option<IpAddr>, port: u16) -> Result<UdpSocketHandle, &'static str> {
+            let port = match port {
+                0 => self.ephemeral_port()?,
+                port if self.port_in_use(address, port) => return Err("Port already in use"),
+                port => port,
+            };
+            let handle = UdpSocketHandle(self.next_handle);
+            self.next_handle += 1;
+            self.sockets.insert(
+                handle,
+                UdpSocket {
+                    address,
+                    port,
+                    groups: Vec::new(),
+                    queue: VecDeque::new(),
+                    queued_bytes: 0,
+                    recv_buffer: DEFAULT_RECV_BUFFER,
+                    dropped: 0,
+                },
+            );
+            Ok(handle)
+        }
+
+        pub fn local_port(&self, handle: UdpSocketHandle) -> Option<u16> {
+            self.sockets.get(&handle).map(|socket| socket.port)
+        }
+
+        pub fn close(&mut self, net: &mut NetStack, handle: UdpSocketHandle) -> Result<(), &'static str> {
+            let socket = self.sockets.remove(&handle).ok_or("Socket not found")?;
+            for (interface, group) in socket.groups {
+                self.release_group(net, interface, group)?;
+            }
+            Ok(())
+        }
+
+        pub fn send_to(
+            &mut self,
+            net: &mut NetStack,
+            handle: UdpSocketHandle,
+            destination: IpAddr,
+            port: u16,
+            payload: &[u8],
+        ) -> Result<(), &'static str> {
+            let socket = self.sockets.get(&handle).ok_or("Socket not found")?;
+            if UDP_HEADER_LEN + payload.len() > u16::MAX as usize {
+                return Err("UDP payload too large");
+            }
+            let source = match socket.address {
+                Some(address) if !address.is_unspecified() => address,
+                _ => net
+                    .source_address(&destination)
+                    .ok_or("No source address for destination")?,
+            };
+            let header = UdpHeader {
+                source_port: socket.port,
+                destination_port: port,
+            };
+            let data = build_udp(source, destination, &header, payload);
+            // Multicast stays on the local link unless a caller routes it
+            let ttl = if destination.is_multicast() { 1 } else { 64 };
+            net.send_from(source, destination, IP_PROTO_UDP, &data, ttl)
+        }
+
+        pub fn recv_from(&mut self, handle: UdpSocketHandle) -> Result<Option<UdpMessage>, &'static str> {
+            let socket = self.sockets.get_mut(&handle).ok_or("Socket not found")?;
+            let message = socket.queue.pop_front();
+            if let Some(message) = &message {
+                socket.queued_bytes -= message.payload.len();
+            }
+            Ok(message)
+        }
+
+        pub fn queued(&self, handle: UdpSocketHandle) -> usize {
+            self.sockets.get(&handle).map_or(0, |socket| socket.queue.len())
+        }
+
+        // Caps the bytes of payload waiting to be read
+        pub fn set_recv_buffer(&mut self, handle: UdpSocketHandle, bytes: usize) -> Result<(), &'static str> {
+            let socket = self.sockets.get_mut(&handle).ok_or("Socket not found")?;
+            socket.recv_buffer = bytes;
+            Ok(())
+        }
+
+        pub fn dropped(&self, handle: UdpSocketHandle) -> u64 {
+            self.sockets.get(&handle).map_or(0, |socket| socket.dropped)
+        }
+
+        pub fn join_multicast(
+            &mut self,
+            net: &mut NetStack,
+            handle: UdpSocketHandle,
+            interface: InterfaceId,
+            group: IpAddr,
+        ) -> Result<(), &'static str> {
+            if !group.is_multicast() {
+                return Err("Not a multicast address");
+            }
+            let socket = self.sockets.get_mut(&handle).ok_or("Socket not found")?;
+            if socket.groups.contains(&(interface, group)) {
+                return Ok(());
+            }
+            net.join_multicast(interface, group)?;
+            socket.groups.push((interface, group));
+            Ok(())
+        }
+
+        pub fn leave_multicast(
+            &mut self,
+            net: &mut NetStack,
+            handle: UdpSocketHandle,
+            interface: InterfaceId,
+            group: IpAddr,
+        ) -> Result<(), &'static str> {
+            let socket = self.sockets.get_mut(&handle).ok_or("Socket not found")?;
+            let before = socket.groups.len();
+            socket.groups.retain(|membership| *membership != (interface, group));
+            if socket.groups.len() == before {
+                return Err("Socket is not a member of group");
+            }
+            self.release_group(net, interface, group)
+        }
+
+        // The interface stays in the group while any socket still needs it
+        fn release_group(&mut self, net: &mut NetStack, interface: InterfaceId, group: IpAddr) -> Result<(), &'static str> {
+            let still_used = self
+                .sockets
+                .values()
+                .any(|socket| socket.groups.contains(&(interface, group)));
+            if still_used {
+                return Ok(());
+            }
+            net.leave_multicast(interface, group)
+        }
+
+        // Delivers an inbound UDP datagram; returns false when no socket wants it
+        pub fn handle_datagram(&mut self, datagram: &Datagram) -> Result<bool, &'static str> {
+            let (header, payload) = parse_udp(datagram.source, datagram.destination, datagram.payload.data())?;
+            let message = UdpMessage {
+                interface: datagram.interface,
+                source: datagram.source,
+                source_port: header.source_port,
+                destination: datagram.destination,
+                payload: payload.to_vec(),
+            };
+            let mut matching: Vec<&mut UdpSocket> = self
+                .sockets
+                .values_mut()
+                .filter(|socket| {
+                    socket.port == header.destination_port && socket.accepts(&datagram.destination, datagram.interface)
+                })
+                .collect();
+            // Multicast and broadcast fan out to every matching socket; unicast
+            // prefers a socket bound to the exact address over a wildcard one
+            let fan_out = datagram.destination.is_multicast() || is_broadcast(&datagram.destination);
+            if !fan_out {
+                matching.sort_by_key(|socket| socket.address.is_none());
+                matching.truncate(1);
+            }
+            let delivered = !matching.is_empty();
+            for socket in matching {
+                let full = socket.queue.len() >= MAX_QUEUED_PER_SOCKET
+                    || socket.queued_bytes + message.payload.len() > socket.recv_buffer;
+                if full {
+                    socket.dropped += 1;
+                } else {
+                    socket.queued_bytes += message.payload.len();
+                    socket.queue.push_back(message.clone());
+                }
+            }
+            Ok(delivered)
+        }
+    }
+
+    impl Default for UdpStack {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+}
@@ -0,0 +1,303 @@
+// src/networking/vxcap.rs
+
+pub mod vxcap {
+    use crate::packet::packet::{
+        parse_ethernet, parse_ipv4, parse_ipv6, ETHERTYPE_ARP, ETHERTYPE_IPV4, ETHERTYPE_IPV6, IP_PROTO_ICMP,
+        IP_PROTO_ICMPV6, IP_PROTO_TCP, IP_PROTO_UDP,
+    };
+    use crate::vxnet_core::vxnet_core::{Direction, InterfaceId, IpCidr, NetStack, PacketTap};
+    use std::collections::{BTreeMap, VecDeque};
+    use std::net::IpAddr;
+    use std::sync::{Arc, Mutex, OnceLock};
+
+    pub const DEFAULT_SNAPLEN: usize = 65_535;
+    pub const DEFAULT_RING_BYTES: usize = 4 * 1024 * 1024;
+
+    const PCAP_MAGIC: u32 = 0xA1B2_C3D4;
+    const PCAP_VERSION: (u16, u16) = (2, 4);
+    const LINKTYPE_ETHERNET: u32 = 1;
+    // Per-record bookkeeping charged against the ring on top of the data
+    const RECORD_OVERHEAD: usize = 16;
+
+    // Every selector that is set must match; an empty filter takes everything
+    #[derive(Debug, Clone, Default, PartialEq, Eq)]
+    pub struct CaptureFilter {
+        pub interface: Option<InterfaceId>,
+        pub direction: Option<Direction>,
+        pub ethertype: Option<u16>,
+        pub protocol: Option<u8>,
+        pub host: Option<IpCidr>,
+        pub port: Option<u16>,
+    }
+
+    impl CaptureFilter {
+        // Words: iface N, rx, tx, arp, ip, ip6, tcp, udp, icmp, icmp6,
+        // host CIDR, port N
+        pub fn parse(text: &str) -> Result<CaptureFilter, &'static str> {
+            let mut filter = CaptureFilter::default();
+            let mut words = text.split_whitespace();
+            while let Some(word) = words.next() {
+                let mut value = || words.next().ok_or("Missing value in capture filter");
+                match word {
+                    "iface" => filter.interface = Some(InterfaceId(value()?.parse().map_err(|_| "Invalid interface")?)),
+                    "rx" => filter.direction = Some(Direction::Rx),
+                    "tx" => filter.direction = Some(Direction::Tx),
+                    "arp" => filter.ethertype = Some(ETHERTYPE_ARP),
+                    "ip" => filter.ethertype = Some(ETHERTYPE_IPV4),
+                    "ip6" => filter.ethertype = Some(ETHERTYPE_IPV6),
+                    "tcp" => filter.protocol = Some(IP_PROTO_TCP),
+                    "udp" => filter.protocol = Some(IP_PROTO_UDP),
+                    "icmp" => filter.protocol = Some(IP_PROTO_ICMP),
+                    "icmp6" => filter.protocol = Some(IP_PROTO_ICMPV6),
+                    "host" => filter.host = Some(value()?.parse()?),
+                    "port" => filter.port = Some(value()?.parse().map_err(|_| "Invalid port")?),
+                    _ => return Err("Unknown capture filter keyword"),
+                }
+            }
+            Ok(filter)
+        }
+
+        pub fn matches(&self, interface: InterfaceId, direction: Direction, frame: &[u8]) -> bool {
+            if self.interface.is_some_and(|id| id != interface) || self.direction.is_some_and(|dir| dir != direction) {
+                return false;
+            }
+            let Ok((ethernet, payload)) = parse_ethernet(frame) else {
+                return false;
+            };
+            if self.ethertype.is_some_and(|ethertype| ethertype != ethernet.ethertype) {
+                return false;
+            }
+            if self.protocol.is_none() && self.host.is_none() && self.port.is_none() {
+                return true;
+            }
+            // Ports are only visible in the first fragment
+            let (source, destination, protocol, transport) = match ethernet.ethertype {
+                ETHERTYPE_IPV4 => match parse_ipv4(payload) {
+                    Ok((header, data)) => (
+                        IpAddr::V4(header.source),
+                        IpAddr::V4(header.destination),
+                        header.protocol,
+                        (header.fragment_offset == 0).then_some(data),
+                    ),
+                    Err(_) => return false,
+                },
+                ETHERTYPE_IPV6 => match parse_ipv6(payload) {
+                    Ok((header, data)) => (
+                        IpAddr::V6(header.source),
+                        IpAddr::V6(header.destination),
+                        header.next_header,
+                        Some(data),
+                    ),
+                    Err(_) => return false,
+                },
+                _ => return false,
+            };
+            if self.protocol.is_some_and(|wanted| wanted != protocol) {
+                return false;
+            }
+            if self.host.is_some_and(|cidr| !cidr.contains(&source) && !cidr.contains(&destination)) {
+                return false;
+            }
+            match self.port {
+                None => true,
+                Some(port) => {
+                    let ports = transport
+                        .filter(|data| data.len() >= 4 && matches!(protocol, IP_PROTO_TCP | IP_PROTO_UDP))
+                        .map(|data| [u16::from_be_bytes([data[0], data[1]]), u16::from_be_bytes([data[2], data[3]])]);
+                    ports.is_some_and(|ports| ports.contains(&port))
+                }
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct CapturedPacket {
+        // Milliseconds, on the stack's clock
+        pub timestamp: u64,
+        pub interface: InterfaceId,
+        pub direction: Direction,
+        // Length on the wire; data may be shorter when cut at the snaplen
+        pub original_len: usize,
+        pub data: Vec<u8>,
+    }
+
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct CaptureStats {
+        pub captured: u64,
+        // Oldest packets overwritten because the reader fell behind
+        pub overwritten: u64,
+        pub queued: usize,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct CaptureId(pub u32);
+
+    struct Session {
+        filter: CaptureFilter,
+        snaplen: usize,
+        capacity: usize,
+        bytes: usize,
+        packets: VecDeque<CapturedPacket>,
+        stats: CaptureStats,
+    }
+
+    impl Session {
+        fn record(&mut self, interface: InterfaceId, direction: Direction, frame: &[u8], now: u64) {
+            let data = frame[..frame.len().min(self.snaplen)].to_vec();
+            let size = data.len() + RECORD_OVERHEAD;
+            while self.bytes + size > self.capacity {
+                let Some(oldest) = self.packets.pop_front() else {
+                    break;
+                };
+                self.bytes -= oldest.data.len() + RECORD_OVERHEAD;
+                self.stats.overwritten += 1;
+            }
+            self.bytes += size;
+            self.stats.captured += 1;
+            self.packets.push_back(CapturedPacket {
+                timestamp: now,
+                interface,
+                direction,
+                original_len: frame.len(),
+                data,
+            });
+        }
+    }
+
+    // Capture sessions, each with its own filter and ring buffer. Rings
+    // overwrite their oldest packets rather than stall the data path.
+    pub struct PacketCapture {
+        sessions: BTreeMap<CaptureId, Session>,
+        next_id: u32,
+    }
+
+    impl PacketCapture {
+        pub fn new() -> Self {
+            PacketCapture {
+                sessions: BTreeMap::new(),
+                next_id: 1,
+            }
+        }
+
+        pub fn start(&mut self, filter: CaptureFilter, snaplen: usize, capacity: usize) -> Result<CaptureId, &'static str> {
+            // A full snaplen record must always fit
+            if snaplen == 0 || capacity < snaplen + RECORD_OVERHEAD {
+                return Err("Capture buffer too small");
+            }
+            let id = CaptureId(self.next_id);
+            self.next_id += 1;
+            self.sessions.insert(
+                id,
+                Session {
+                    filter,
+                    snaplen,
+                    capacity,
+                    bytes: 0,
+                    packets: VecDeque::new(),
+                    stats: CaptureStats::default(),
+                },
+            );
+            log::info!("Capture {} started", id.0);
+            Ok(id)
+        }
+
+        pub fn stop(&mut self, id: CaptureId) -> Result<CaptureStats, &'static str> {
+            let session = self.sessions.remove(&id).ok_or("Capture not found")?;
+            log::info!("Capture {} stopped", id.0);
+            Ok(CaptureStats {
+                queued: session.packets.len(),
+                ..session.stats
+            })
+        }
+
+        pub fn stats(&self, id: CaptureId) -> Result<CaptureStats, &'static str> {
+            let session = self.sessions.get(&id).ok_or("Capture not found")?;
+            Ok(CaptureStats {
+                queued: session.packets.len(),
+                ..session.stats
+            })
+        }
+
+        // Drains up to max packets, oldest first
+        pub fn read(&mut self, id: CaptureId, max: usize) -> Result<Vec<CapturedPacket>, &'static str> {
+            let session = self.sessions.get_mut(&id).ok_or("Capture not found")?;
+            let count = max.min(session.packets.len());
+            let packets: Vec<CapturedPacket> = session.packets.drain(..count).collect();
+            session.bytes -= packets.iter().map(|packet| packet.data.len() + RECORD_OVERHEAD).sum::<usize>();
+            Ok(packets)
+        }
+
+        // Drains everything queued as a pcap file
+        pub fn export_pcap(&mut self, id: CaptureId) -> Result<Vec<u8>, &'static str> {
+            let snaplen = self.sessions.get(&id).ok_or("Capture not found")?.snaplen;
+            let packets = self.read(id, usize::MAX)?;
+            Ok(write_pcap(&packets, snaplen))
+        }
+
+        fn capture(&mut self, interface: InterfaceId, direction: Direction, frame: &[u8], now: u64) {
+            for session in self.sessions.values_mut() {
+                if session.filter.matches(interface, direction, frame) {
+                    session.record(interface, direction, frame, now);
+                }
+            }
+        }
+    }
+
+    impl Default for PacketCapture {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl PacketTap for Arc<Mutex<PacketCapture>> {
+        fn tap(&mut self, interface: InterfaceId, direction: Direction, frame: &[u8], now: u64) {
+            let mut capture = self.lock().unwrap();
+            if !capture.sessions.is_empty() {
+                capture.capture(interface, direction, frame, now);
+            }
+        }
+    }
+
+    // Classic libpcap format, little endian, microsecond timestamps
+    pub fn write_pcap(packets: &[CapturedPacket], snaplen: usize) -> Vec<u8> {
+        let mut out = Vec::with_capacity(24 + packets.iter().map(|packet| 16 + packet.data.len()).sum::<usize>());
+        out.extend_from_slice(&PCAP_MAGIC.to_le_bytes());
+        out.extend_from_slice(&PCAP_VERSION.0.to_le_bytes());
+        out.extend_from_slice(&PCAP_VERSION.1.to_le_bytes());
+        out.extend_from_slice(&0i32.to_le_bytes());
+        out.extend_from_slice(&0u32.to_le_bytes());
+        out.extend_from_slice(&(snaplen as u32).to_le_bytes());
+        out.extend_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+        for packet in packets {
+            out.extend_from_slice(&((packet.timestamp / 1000) as u32).to_le_bytes());
+            out.extend_from_slice(&((packet.timestamp % 1000 * 1000) as u32).to_le_bytes());
+            out.extend_from_slice(&(packet.data.len() as u32).to_le_bytes());
+            out.extend_from_slice(&(packet.original_len as u32).to_le_bytes());
+            out.extend_from_slice(&packet.data);
+        }
+        out
+    }
+
+    pub fn packet_capture() -> &'static Arc<Mutex<PacketCapture>> {
+        static CAPTURE: OnceLock<Arc<Mutex<PacketCapture>>> = OnceLock::new();
+        CAPTURE.get_or_init(|| Arc::new(Mutex::new(PacketCapture::new())))
+    }
+
+    // Attaches the global capture tap; sessions cost nothing until started
+    pub fn init(net: &mut NetStack) {
+        log::info!("Initializing VXCap...");
+        net.set_packet_tap(Box::new(Arc::clone(packet_capture())));
+    }
+
+    pub fn start_capture(filter: &str) -> Result<CaptureId, &'static str> {
+        let filter = CaptureFilter::parse(filter)?;
+        packet_capture()
+            .lock()
+            .unwrap()
+            .start(filter, DEFAULT_SNAPLEN, DEFAULT_RING_BYTES)
+    }
+
+    pub fn stop_capture(id: CaptureId) -> Result<CaptureStats, &'static str> {
+        packet_capture().lock().unwrap().stop(id)
+    }
+}
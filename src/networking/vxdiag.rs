@@ -0,0 +1,608 @@
+// src/networking/vxdiag.rs
+
+pub mod vxdiag {
+    use crate::packet::packet::{build_icmpv4, build_icmpv6, parse_icmp, IcmpMessage, IP_PROTO_ICMP, IP_PROTO_ICMPV6};
+    use crate::vxnet_core::vxnet_core::{Datagram, NetStack};
+    use std::collections::BTreeMap;
+    use std::net::IpAddr;
+    use vaelix_core::vxchan::vxchan::VXChanManager;
+
+    pub const REQUEST_CHANNEL: &str = "vxdiag";
+    pub const REPLY_CHANNEL: &str = "vxdiag.reply";
+
+    const ICMP_ECHO_REPLY: u8 = 0;
+    const ICMP_DEST_UNREACHABLE: u8 = 3;
+    const ICMP_ECHO_REQUEST: u8 = 8;
+    const ICMP_TIME_EXCEEDED: u8 = 11;
+    const ICMPV6_DEST_UNREACHABLE: u8 = 1;
+    const ICMPV6_TIME_EXCEEDED: u8 = 3;
+    const ICMPV6_ECHO_REQUEST: u8 = 128;
+    const ICMPV6_ECHO_REPLY: u8 = 129;
+    const MAX_PAYLOAD: usize = 1400;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct PingOptions {
+        pub count: u16,
+        // Milliseconds between echo requests
+        pub interval: u64,
+        pub timeout: u64,
+        pub payload_len: usize,
+        pub ttl: u8,
+    }
+
+    impl Default for PingOptions {
+        fn default() -> Self {
+            PingOptions {
+                count: 4,
+                interval: 1000,
+                timeout: 1000,
+                payload_len: 56,
+                ttl: 64,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct TracerouteOptions {
+        pub max_hops: u8,
+        pub probes_per_hop: u8,
+        pub timeout: u64,
+    }
+
+    impl Default for TracerouteOptions {
+        fn default() -> Self {
+            TracerouteOptions {
+                max_hops: 30,
+                probes_per_hop: 3,
+                timeout: 1000,
+            }
+        }
+    }
+
+    // Doubles as the ICMP echo identifier of the session's probes
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct ProbeId(pub u16);
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ProbeOutcome {
+        Reply { from: IpAddr, rtt: u64, ttl: u8 },
+        TimeExceeded { from: IpAddr, rtt: u64 },
+        Unreachable { from: IpAddr, rtt: u64, code: u8 },
+        Timeout,
+        SendFailed(&'static str),
+    }
+
+    impl ProbeOutcome {
+        pub fn responder(&self) -> Option<IpAddr> {
+            match self {
+                ProbeOutcome::Reply { from, .. }
+                | ProbeOutcome::TimeExceeded { from, .. }
+                | ProbeOutcome::Unreachable { from, .. } => Some(*from),
+                _ => None,
+            }
+        }
+
+        pub fn rtt(&self) -> Option<u64> {
+            match self {
+                ProbeOutcome::Reply { rtt, .. }
+                | ProbeOutcome::TimeExceeded { rtt, .. }
+                | ProbeOutcome::Unreachable { rtt, .. } => Some(*rtt),
+                _ => None,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct ProbeResult {
+        pub sequence: u16,
+        // TTL the probe was sent with; the hop number for traceroute
+        pub hop_limit: u8,
+        pub outcome: ProbeOutcome,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct ProbeReport {
+        pub destination: IpAddr,
+        pub sent: usize,
+        // In the order the probes were sent
+        pub results: Vec<ProbeResult>,
+        pub reached: bool,
+        pub done: bool,
+    }
+
+    impl ProbeReport {
+        pub fn received(&self) -> usize {
+            self.results
+                .iter()
+                .filter(|result| matches!(result.outcome, ProbeOutcome::Reply { .. }))
+                .count()
+        }
+
+        pub fn loss_percent(&self) -> u32 {
+            if self.sent == 0 {
+                return 0;
+            }
+            ((self.sent - self.received()) * 100 / self.sent) as u32
+        }
+
+        // min, average and max over the echo replies
+        pub fn rtt_summary(&self) -> Option<(u64, u64, u64)> {
+            let rtts: Vec<u64> = self
+                .results
+                .iter()
+                .filter(|result| matches!(result.outcome, ProbeOutcome::Reply { .. }))
+                .filter_map(|result| result.outcome.rtt())
+                .collect();
+            let (min, max) = (*rtts.iter().min()?, *rtts.iter().max()?);
+            Some((min, rtts.iter().sum::<u64>() / rtts.len() as u64, max))
+        }
+
+        // Traceroute results grouped by hop
+        pub fn hops(&self) -> Vec<(u8, Vec<ProbeOutcome>)> {
+            let mut hops: BTreeMap<u8, Vec<ProbeOutcome>> = BTreeMap::new();
+            for result in &self.results {
+                hops.entry(result.hop_limit).or_default().push(result.outcome);
+            }
+            hops.into_iter().collect()
+        }
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    enum Mode {
+        Ping(PingOptions),
+        Traceroute(TracerouteOptions),
+    }
+
+    struct Session {
+        mode: Mode,
+        destination: IpAddr,
+        next_sequence: u16,
+        next_send: u64,
+        hop: u8,
+        // sequence -> (sent at, hop limit)
+        outstanding: BTreeMap<u16, (u64, u8)>,
+        results: Vec<ProbeResult>,
+        reached: bool,
+        done: bool,
+    }
+
+    impl Session {
+        fn timeout(&self) -> u64 {
+            match self.mode {
+                Mode::Ping(options) => options.timeout,
+                Mode::Traceroute(options) => options.timeout,
+            }
+        }
+
+        fn finish_probe(&mut self, sequence: u16, outcome: impl FnOnce(u64) -> ProbeOutcome, now: u64) -> bool {
+            let Some((sent_at, hop_limit)) = self.outstanding.remove(&sequence) else {
+                return false;
+            };
+            let outcome = outcome(now.saturating_sub(sent_at));
+            if matches!(outcome, ProbeOutcome::Reply { .. } | ProbeOutcome::Unreachable { .. })
+                && outcome.responder() == Some(self.destination)
+            {
+                self.reached = true;
+            }
+            self.results.push(ProbeResult {
+                sequence,
+                hop_limit,
+                outcome,
+            });
+            true
+        }
+    }
+
+    // Echo-based ping and traceroute. Inbound ICMP reaches it through
+    // handle_datagram(); poll() sends probes and expires lost ones.
+    pub struct Diagnostics {
+        sessions: BTreeMap<ProbeId, Session>,
+        next_id: u16,
+    }
+
+    impl Diagnostics {
+        pub fn new() -> Self {
+            Diagnostics {
+                sessions: BTreeMap::new(),
+                next_id: 1,
+            }
+        }
+
+        pub fn ping(&mut self, net: &mut NetStack, destination: IpAddr, options: PingOptions, now: u64) -> Result<ProbeId, &'static str> {
+            if options.count == 0 || options.ttl == 0 {
+                return Err("Count and TTL must be non-zero");
+            }
+            if options.payload_len > MAX_PAYLOAD {
+                return Err("Payload too large");
+            }
+            self.start(net, destination, Mode::Ping(options), now)
+        }
+
+        pub fn traceroute(
+            &mut self,
+            net: &mut NetStack,
+            destination: IpAddr,
+            options: TracerouteOptions,
+            now: u64,
+        ) -> Result<ProbeId, &'static str> {
+            if options.max_hops == 0 || options.probes_per_hop == 0 {
+                return Err("Hops and probes must be non-zero");
+            }
+            self.start(net, destination, Mode::Traceroute(options), now)
+        }
+
+        fn start(&mut self, net: &mut NetStack, destination: IpAddr, mode: Mode, now: u64) -> Result<ProbeId, &'static str> {
+            if destination.is_unspecified() || destination.is_multicast() {
+                return Err("Invalid probe destination");
+            }
+            net.source_address(&destination).ok_or("No route to host")?;
+            let id = (0..u16::MAX)
+                .map(|offset| ProbeId(self.next_id.wrapping_add(offset).max(1)))
+                .find(|id| !self.sessions.contains_key(id))
+                .ok_or("Too many probe sessions")?;
+            self.next_id = id.0.wrapping_add(1);
+            self.sessions.insert(
+                id,
+                Session {
+                    mode,
+                    destination,
+                    next_sequence: 0,
+                    next_send: now,
+                    hop: 0,
+                    outstanding: BTreeMap::new(),
+                    results: Vec::new(),
+                    reached: false,
+                    done: false,
+                },
+            );
+            self.poll_session(net, id, now);
+            Ok(id)
+        }
+
+        pub fn report(&self, id: ProbeId) -> Result<ProbeReport, &'static str> {
+            let session = self.sessions.get(&id).ok_or("Probe session not found")?;
+            let mut results = session.results.clone();
+            results.sort_by_key(|result| result.sequence);
+            Ok(ProbeReport {
+                destination: session.destination,
+                sent: session.next_sequence as usize,
+                results,
+                reached: session.reached,
+                done: session.done,
+            })
+        }
+
+        // Drops a session, finished or not, and returns its last report
+        pub fn remove(&mut self, id: ProbeId) -> Result<ProbeReport, &'static str> {
+            let report = self.report(id)?;
+            self.sessions.remove(&id);
+            Ok(report)
+        }
+
+        pub fn poll(&mut self, net: &mut NetStack, now: u64) {
+            let ids: Vec<ProbeId> = self.sessions.keys().copied().collect();
+            for id in ids {
+                self.poll_session(net, id, now);
+            }
+        }
+
+        fn poll_session(&mut self, net: &mut NetStack, id: ProbeId, now: u64) {
+            let Some(session) = self.sessions.get_mut(&id) else {
+                return;
+            };
+            if session.done {
+                return;
+            }
+            let timeout = session.timeout();
+            let expired: Vec<u16> = session
+                .outstanding
+                .iter()
+                .filter(|(_, (sent_at, _))| now.saturating_sub(*sent_at) >= timeout)
+                .map(|(sequence, _)| *sequence)
+                .collect();
+            for sequence in expired {
+                session.finish_probe(sequence, |_| ProbeOutcome::Timeout, now);
+            }
+            match session.mode {
+                Mode::Ping(options) => {
+                    while session.next_sequence < options.count && now >= session.next_send {
+                        send_probe(net, id, session, options.ttl, options.payload_len, now);
+                        session.next_send += options.interval;
+                    }
+                    session.done = session.next_sequence == options.count && session.outstanding.is_empty();
+                }
+                Mode::Traceroute(options) => {
+                    // One hop at a time, so replies from the destination stop
+                    // the walk before probes go out past it
+                    if !session.outstanding.is_empty() {
+                        return;
+                    }
+                    if session.reached || session.hop == options.max_hops {
+                        session.done = true;
+                        return;
+                    }
+                    session.hop += 1;
+                    for _ in 0..options.probes_per_hop {
+                        send_probe(net, id, session, session.hop, 0, now);
+                    }
+                }
+            }
+        }
+
+        // Consumes echo replies and ICMP errors quoting our probes
+        pub fn handle_datagram(&mut self, datagram: &Datagram, now: u64) -> bool {
+            let ipv6 = match datagram.protocol {
+                IP_PROTO_ICMP => false,
+                IP_PROTO_ICMPV6 => true,
+                _ => return false,
+            };
+            let Ok(message) = parse_icmp(datagram.payload.data()) else {
+                return false;
+            };
+            let from = datagram.source;
+            let (echo, quoted) = match (ipv6, message.kind) {
+                (false, ICMP_ECHO_REPLY) | (true, ICMPV6_ECHO_REPLY) => (Some(message.rest), None),
+                (false, ICMP_TIME_EXCEEDED | ICMP_DEST_UNREACHABLE)
+                | (true, ICMPV6_TIME_EXCEEDED | ICMPV6_DEST_UNREACHABLE) => (None, quoted_echo(ipv6, &message.data)),
+                _ => return false,
+            };
+            let Some(rest) = echo.or(quoted) else {
+                return false;
+            };
+            let id = ProbeId(u16::from_be_bytes([rest[0], rest[1]]));
+            let sequence = u16::from_be_bytes([rest[2], rest[3]]);
+            let Some(session) = self.sessions.get_mut(&id) else {
+                return false;
+            };
+            let time_exceeded = matches!((ipv6, message.kind), (false, ICMP_TIME_EXCEEDED) | (true, ICMPV6_TIME_EXCEEDED));
+            let ttl = datagram.ttl;
+            session.finish_probe(
+                sequence,
+                |rtt| match echo {
+                    Some(_) => ProbeOutcome::Reply { from, rtt, ttl },
+                    None if time_exceeded => ProbeOutcome::TimeExceeded { from, rtt },
+                    None => ProbeOutcome::Unreachable {
+                        from,
+                        rtt,
+                        code: message.code,
+                    },
+                },
+                now,
+            )
+        }
+    }
+
+    impl Default for Diagnostics {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    fn send_probe(net: &mut NetStack, id: ProbeId, session: &mut Session, ttl: u8, payload_len: usize, now: u64) {
+        let sequence = session.next_sequence;
+        session.next_sequence = session.next_sequence.wrapping_add(1);
+        session.outstanding.insert(sequence, (now, ttl));
+        let [id_high, id_low] = id.0.to_be_bytes();
+        let [sequence_high, sequence_low] = sequence.to_be_bytes();
+        let payload: Vec<u8> = (0..payload_len).map(|index| index as u8).collect();
+        let destination = session.destination;
+        let sent = net.source_address(&destination).ok_or("No route to host").and_then(|source| {
+            let (protocol, message) = match (source, destination) {
+                (IpAddr::V4(_), IpAddr::V4(_)) => (
+                    IP_PROTO_ICMP,
+                    build_icmpv4(&IcmpMessage {
+                        kind: ICMP_ECHO_REQUEST,
+                        code: 0,
+                        rest: [id_high, id_low, sequence_high, sequence_low],
+                        data: payload,
+                    }),
+                ),
+                (IpAddr::V6(source), IpAddr::V6(destination)) => (
+                    IP_PROTO_ICMPV6,
+                    build_icmpv6(
+                        source,
+                        destination,
+                        &IcmpMessage {
+                            kind: ICMPV6_ECHO_REQUEST,
+                            code: 0,
+                            rest: [id_high, id_low, sequence_high, sequence_low],
+                            data: payload,
+                        },
+                    ),
+                ),
+                _ => return Err("Source and destination address families differ"),
+            };
+            net.send_from(source, destination, protocol, &message, ttl)
+        });
+        if let Err(error) = sent {
+            session.finish_probe(sequence, |_| ProbeOutcome::SendFailed(error), now);
+        }
+    }
+
+    // Identifier and sequence of the echo request quoted in an ICMP error
+    fn quoted_echo(ipv6: bool, data: &[u8]) -> Option<[u8; 4]> {
+        let echo = if ipv6 {
+            (data.get(6) == Some(&IP_PROTO_ICMPV6)).then(|| data.get(40..))??
+        } else {
+            let header_len = (data.first()? & 0xF) as usize * 4;
+            (data.get(9) == Some(&IP_PROTO_ICMP)).then(|| data.get(header_len..))??
+        };
+        let request = if ipv6 { ICMPV6_ECHO_REQUEST } else { ICMP_ECHO_REQUEST };
+        if echo.len() < 8 || echo[0] != request {
+            return None;
+        }
+        Some([echo[4], echo[5], echo[6], echo[7]])
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum DiagRequest {
+        Ping(IpAddr, PingOptions),
+        Traceroute(IpAddr, TracerouteOptions),
+    }
+
+    // Grammar, one request per message:
+    //   ping ADDRESS [count N] [interval MS] [timeout MS] [size N] [ttl N]
+    //   traceroute ADDRESS [hops N] [probes N] [timeout MS]
+    pub fn parse_request(line: &str) -> Result<DiagRequest, &'static str> {
+        let mut words = line.split_whitespace();
+        let command = words.next().ok_or("Empty request")?;
+        let destination: IpAddr = words
+            .next()
+            .ok_or("Missing argument")?
+            .parse()
+            .map_err(|_| "Invalid address")?;
+        let mut ping = PingOptions::default();
+        let mut traceroute = TracerouteOptions::default();
+        while let Some(option) = words.next() {
+            let value = words.next().ok_or("Missing argument")?;
+            let number = || value.parse::<u64>().map_err(|_| "Invalid number");
+            let small = || value.parse::<u8>().map_err(|_| "Invalid number");
+            match (command, option) {
+                ("ping", "count") => ping.count = value.parse().map_err(|_| "Invalid number")?,
+                ("ping", "interval") => ping.interval = number()?,
+                ("ping", "size") => ping.payload_len = number()? as usize,
+                ("ping", "ttl") => ping.ttl = small()?,
+                ("ping", "timeout") => ping.timeout = number()?,
+                ("traceroute", "hops") => traceroute.max_hops = small()?,
+                ("traceroute", "probes") => traceroute.probes_per_hop = small()?,
+                ("traceroute", "timeout") => traceroute.timeout = number()?,
+                ("ping" | "traceroute", _) => return Err("Unknown option"),
+                _ => return Err("Unknown command"),
+            }
+        }
+        match command {
+            "ping" => Ok(DiagRequest::Ping(destination, ping)),
+            "traceroute" => Ok(DiagRequest::Traceroute(destination, traceroute)),
+            _ => Err("Unknown command"),
+        }
+    }
+
+    fn format_ping(report: &ProbeReport) -> String {
+        let mut lines: Vec<String> = report
+            .results
+            .iter()
+            .map(|result| match result.outcome {
+                ProbeOutcome::Reply { from, rtt, ttl } => {
+                    format!("seq={} from={} ttl={} rtt={}", result.sequence, from, ttl, rtt)
+                }
+                ProbeOutcome::TimeExceeded { from, .. } => format!("seq={} from={} time-exceeded", result.sequence, from),
+                ProbeOutcome::Unreachable { from, code, .. } => {
+                    format!("seq={} from={} unreachable code={}", result.sequence, from, code)
+                }
+                ProbeOutcome::Timeout => format!("seq={} timeout", result.sequence),
+                ProbeOutcome::SendFailed(error) => format!("seq={} failed {}", result.sequence, error),
+            })
+            .collect();
+        let mut summary = format!(
+            "sent={} received={} loss={}%",
+            report.sent,
+            report.received(),
+            report.loss_percent()
+        );
+        if let Some((min, avg, max)) = report.rtt_summary() {
+            summary.push_str(&format!(" rtt={}/{}/{}", min, avg, max));
+        }
+        lines.push(summary);
+        lines.join("\n")
+    }
+
+    // "<hop> <responder|*> <rtt|*>..." per hop, then whether it got there
+    fn format_traceroute(report: &ProbeReport) -> String {
+        let mut lines: Vec<String> = report
+            .hops()
+            .iter()
+            .map(|(hop, outcomes)| {
+                let responder = outcomes.iter().find_map(ProbeOutcome::responder);
+                let mut line = format!("{} {}", hop, responder.map_or("*".to_string(), |address| address.to_string()));
+                for outcome in outcomes {
+                    line.push(' ');
+                    line.push_str(&outcome.rtt().map_or("*".to_string(), |rtt| rtt.to_string()));
+                }
+                line
+            })
+            .collect();
+        lines.push(if report.reached { "reached" } else { "unreached" }.to_string());
+        lines.join("\n")
+    }
+
+    // Same framing as vxnetctl: "<id> <request>" in, "<id> ok\n<body>" or
+    // "<id> error <reason>" out, but the reply waits for the probes
+    pub struct DiagService {
+        diagnostics: Diagnostics,
+        pending: Vec<(u64, ProbeId, bool)>,
+        handled: u64,
+    }
+
+    impl DiagService {
+        pub fn new(manager: &VXChanManager) -> Result<Self, &'static str> {
+            manager.create_channel(REQUEST_CHANNEL)?;
+            manager.create_channel(REPLY_CHANNEL)?;
+            log::info!("vxdiag listening on {}", REQUEST_CHANNEL);
+            Ok(DiagService {
+                diagnostics: Diagnostics::new(),
+                pending: Vec::new(),
+                handled: 0,
+            })
+        }
+
+        pub fn handled(&self) -> u64 {
+            self.handled
+        }
+
+        pub fn diagnostics(&mut self) -> &mut Diagnostics {
+            &mut self.diagnostics
+        }
+
+        pub fn handle_datagram(&mut self, datagram: &Datagram, now: u64) -> bool {
+            self.diagnostics.handle_datagram(datagram, now)
+        }
+
+        // Starts queued requests, advances the probes and answers the
+        // requests that finished; returns how many replies went out
+        pub fn poll(&mut self, manager: &VXChanManager, net: &mut NetStack, now: u64) -> Result<usize, &'static str> {
+            let mut replies = 0;
+            while let Some(message) = manager.try_receive_message(REQUEST_CHANNEL)? {
+                let (id, line) = message.split_once(' ').unwrap_or((message.as_str(), ""));
+                let started = id
+                    .parse::<u64>()
+                    .map_err(|_| "Invalid request id")
+                    .and_then(|request_id| {
+                        let (probe, traceroute) = match parse_request(line)? {
+                            DiagRequest::Ping(destination, options) => {
+                                (self.diagnostics.ping(net, destination, options, now)?, false)
+                            }
+                            DiagRequest::Traceroute(destination, options) => {
+                                (self.diagnostics.traceroute(net, destination, options, now)?, true)
+                            }
+                        };
+                        self.pending.push((request_id, probe, traceroute));
+                        Ok(())
+                    });
+                if let Err(error) = started {
+                    manager.send_message(REPLY_CHANNEL, format!("{} error {}", id, error))?;
+                    replies += 1;
+                }
+            }
+            self.diagnostics.poll(net, now);
+            let mut index = 0;
+            while index < self.pending.len() {
+                let (request_id, probe, traceroute) = self.pending[index];
+                if !self.diagnostics.report(probe)?.done {
+                    index += 1;
+                    continue;
+                }
+                self.pending.remove(index);
+                let report = self.diagnostics.remove(probe)?;
+                let body = if traceroute { format_traceroute(&report) } else { format_ping(&report) };
+                manager.send_message(REPLY_CHANNEL, format!("{} ok\n{}", request_id, body))?;
+                replies += 1;
+            }
+            self.handled += replies as u64;
+            Ok(replies)
+        }
+    }
+
+    pub fn send_request(manager: &VXChanManager, id: u64, request: &str) -> Result<(), &'static str> {
+        manager.send_message(REQUEST_CHANNEL, format!("{} {}", id, request))
+    }
+}
@@ -1,22 +1,1892 @@
 pub mod vxnet_core {
-    pub fn init() {
-        println!("Initializing VXNet Core...");
-        // Initialize the VXNet Core system
+    use crate::fib::fib::{Fib, TABLE_MAIN};
+    use crate::neighbor::neighbor::{Neighbor, NeighborConfig, NeighborTable, Resolution};
+    use crate::netdev::netdev::{DeviceStats, MacAddress, NetDevice};
+    use crate::packet::packet::*;
+    use crate::pktbuf::pktbuf::{PacketBuffer, DEFAULT_HEADROOM};
+    use crate::qdisc::qdisc::{Qdisc, QdiscStats};
+    use vaelix_core::faultinject::faultinject::FAIL_PACKET;
+    use vaelix_core::power::wake::wake::WakeOnLan;
+    use std::collections::{BTreeMap, VecDeque};
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+    use std::str::FromStr;
+
+    const REASSEMBLY_TIMEOUT_MS: u64 = 30_000;
+    const MAX_REASSEMBLY_SIZE: usize = 65_535;
+    // Packets waiting for fragments at once; the oldest is dropped for a new one
+    const MAX_REASSEMBLY_ENTRIES: usize = 64;
+    const NDP_HOP_LIMIT: u8 = 255;
+    const MULTICAST_METRIC_BASE: u32 = 256;
+    const MIN_IPV4_MTU: usize = 68;
+    const MIN_IPV6_MTU: usize = 1280;
+
+    const ICMP_ECHO_REPLY: u8 = 0;
+    const ICMP_ECHO_REQUEST: u8 = 8;
+    const ICMP_DEST_UNREACHABLE: u8 = 3;
+    const ICMP_ADMIN_PROHIBITED: u8 = 13;
+    const ICMPV6_DEST_UNREACHABLE: u8 = 1;
+    const ICMPV6_ADMIN_PROHIBITED: u8 = 1;
+    const ICMP_NET_UNREACHABLE: u8 = 0;
+    const ICMP_TIME_EXCEEDED: u8 = 11;
+    const ICMPV6_NO_ROUTE: u8 = 0;
+    const ICMPV6_TIME_EXCEEDED: u8 = 3;
+    const ICMPV6_ECHO_REQUEST: u8 = 128;
+    const ICMPV6_ECHO_REPLY: u8 = 129;
+    const ICMPV6_NEIGHBOR_SOLICIT: u8 = 135;
+    const ICMPV6_NEIGHBOR_ADVERT: u8 = 136;
+    const NDP_OPTION_SOURCE_LL: u8 = 1;
+    const NDP_OPTION_TARGET_LL: u8 = 2;
+    const NDP_FLAG_SOLICITED: u8 = 0x40;
+
+    const IGMP_MEMBERSHIP_QUERY: u8 = 0x11;
+    const IGMPV2_MEMBERSHIP_REPORT: u8 = 0x16;
+    const IGMP_LEAVE_GROUP: u8 = 0x17;
+    const MLD_LISTENER_QUERY: u8 = 130;
+    const MLD_LISTENER_REPORT: u8 = 131;
+    const MLD_LISTENER_DONE: u8 = 132;
+
+    const ALL_SYSTEMS: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 1);
+    const ALL_ROUTERS: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 2);
+    const ALL_NODES: Ipv6Addr = Ipv6Addr::new(0xFF02, 0, 0, 0, 0, 0, 0, 1);
+    const ALL_ROUTERS_V6: Ipv6Addr = Ipv6Addr::new(0xFF02, 0, 0, 0, 0, 0, 0, 2);
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct InterfaceId(pub usize);
+
+    // Raised by poll_timers() when a device's carrier changes
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct LinkEvent {
+        pub interface: InterfaceId,
+        pub up: bool,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct IpCidr {
+        pub address: IpAddr,
+        pub prefix_len: u8,
+    }
+
+    impl IpCidr {
+        pub fn new(address: IpAddr, prefix_len: u8) -> Result<Self, &'static str> {
+            let max = if address.is_ipv4() { 32 } else { 128 };
+            if prefix_len > max {
+                return Err("Prefix length too long for address family");
+            }
+            Ok(IpCidr { address, prefix_len })
+        }
+
+        pub fn contains(&self, address: &IpAddr) -> bool {
+            match (self.address, address) {
+                (IpAddr::V4(network), IpAddr::V4(address)) => {
+                    let mask = u32::MAX.checked_shl(32 - self.prefix_len as u32).unwrap_or(0);
+                    u32::from(network) & mask == u32::from(*address) & mask
+                }
+                (IpAddr::V6(network), IpAddr::V6(address)) => {
+                    let mask = u128::MAX.checked_shl(128 - self.prefix_len as u32).unwrap_or(0);
+                    u128::from(network) & mask == u128::from(*address) & mask
+                }
+                _ => false,
+            }
+        }
+
+        pub fn network(&self) -> IpCidr {
+            let address = match self.address {
+                IpAddr::V4(address) => {
+                    let mask = u32::MAX.checked_shl(32 - self.prefix_len as u32).unwrap_or(0);
+                    IpAddr::V4(Ipv4Addr::from(u32::from(address) & mask))
+                }
+                IpAddr::V6(address) => {
+                    let mask = u128::MAX.checked_shl(128 - self.prefix_len as u32).unwrap_or(0);
+                    IpAddr::V6(Ipv6Addr::from(u128::from(address) & mask))
+                }
+            };
+            IpCidr {
+                address,
+                prefix_len: self.prefix_len,
+            }
+        }
+
+        pub fn broadcast(&self) -> Option<Ipv4Addr> {
+            match self.address {
+                IpAddr::V4(address) => {
+                    let host_mask = u32::MAX.checked_shr(self.prefix_len as u32).unwrap_or(0);
+                    Some(Ipv4Addr::from(u32::from(address) | host_mask))
+                }
+                IpAddr::V6(_) => None,
+            }
+        }
+    }
+
+    // Bare addresses are host prefixes
+    impl FromStr for IpCidr {
+        type Err = &'static str;
+
+        fn from_str(text: &str) -> Result<Self, Self::Err> {
+            let (address, prefix) = match text.split_once('/') {
+                Some((address, prefix)) => (address, Some(prefix)),
+                None => (text, None),
+            };
+            let address: IpAddr = address.parse().map_err(|_| "Invalid address")?;
+            let prefix_len = match prefix {
+                Some(prefix) => prefix.parse().map_err(|_| "Invalid prefix length")?,
+                None if address.is_ipv4() => 32,
+                None => 128,
+            };
+            IpCidr::new(address, prefix_len)
+        }
+    }
+
+    // Counters the stack keeps as frames cross an interface
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct InterfaceStats {
+        pub rx_packets: u64,
+        pub rx_bytes: u64,
+        pub rx_dropped: u64,
+        // Frames the stack could not parse or process
+        pub rx_errors: u64,
+        // Broadcast frames count as multicast too
+        pub rx_multicast: u64,
+        pub tx_packets: u64,
+        pub tx_bytes: u64,
+        pub tx_errors: u64,
+        // Frames the queueing discipline refused or flushed
+        pub tx_dropped: u64,
+    }
+
+    pub struct Interface {
+        id: InterfaceId,
+        device: Box<dyn NetDevice>,
+        addresses: Vec<IpCidr>,
+        multicast_groups: Vec<IpAddr>,
+        link_up: bool,
+        admin_up: bool,
+        mtu: Option<usize>,
+        mac: Option<MacAddress>,
+        stats: InterfaceStats,
+        qdisc: Option<Box<dyn Qdisc>>,
+        wake: WakeOnLan,
+        dns_servers: Vec<IpAddr>,
+    }
+
+    impl Interface {
+        pub fn id(&self) -> InterfaceId {
+            self.id
+        }
+
+        pub fn name(&self) -> &str {
+            self.device.name()
+        }
+
+        pub fn mac_address(&self) -> MacAddress {
+            self.mac.unwrap_or_else(|| self.device.mac_address())
+        }
+
+        // Address burned into the device, regardless of any override
+        pub fn permanent_mac_address(&self) -> MacAddress {
+            self.device.mac_address()
+        }
+
+        pub fn mtu(&self) -> usize {
+            self.mtu.unwrap_or_else(|| self.device.mtu())
+        }
+
+        // Carrier as last reported by the driver
+        pub fn link_up(&self) -> bool {
+            self.link_up
+        }
+
+        // Administrative state; a down interface neither sends nor receives
+        pub fn is_up(&self) -> bool {
+            self.admin_up
+        }
+
+        pub fn is_point_to_point(&self) -> bool {
+            self.device.point_to_point()
+        }
+
+        pub fn stats(&self) -> InterfaceStats {
+            self.stats
+        }
+
+        pub fn device_stats(&self) -> DeviceStats {
+            self.device.device_stats()
+        }
+
+        pub fn wake_on_lan(&self) -> WakeOnLan {
+            self.wake
+        }
+
+        // Name servers learned on this link, most preferred first
+        pub fn dns_servers(&self) -> &[IpAddr] {
+            &self.dns_servers
+        }
+
+        pub fn addresses(&self) -> &[IpCidr] {
+            &self.addresses
+        }
+
+        pub fn has_address(&self, address: &IpAddr) -> bool {
+            self.addresses.iter().any(|cidr| cidr.address == *address)
+        }
+
+        pub fn multicast_groups(&self) -> &[IpAddr] {
+            &self.multicast_groups
+        }
+
+        pub fn is_member(&self, group: &IpAddr) -> bool {
+            self.multicast_groups.contains(group)
+        }
+
+        fn accepts_ipv4(&self, destination: &Ipv4Addr) -> bool {
+            if destination.is_multicast() {
+                return *destination == ALL_SYSTEMS || self.is_member(&IpAddr::V4(*destination));
+            }
+            destination.is_broadcast()
+                || self.addresses.iter().any(|cidr| {
+                    cidr.address == IpAddr::V4(*destination) || cidr.broadcast() == Some(*destination)
+                })
+        }
+
+        fn accepts_ipv6(&self, destination: &Ipv6Addr) -> bool {
+            if destination.is_multicast() {
+                return *destination == ALL_NODES
+                    || self.is_member(&IpAddr::V6(*destination))
+                    || self.ipv6_addresses().any(|address| solicited_node_multicast(&address) == *destination);
+            }
+            self.has_address(&IpAddr::V6(*destination))
+        }
+
+        fn ipv4_addresses(&self) -> impl Iterator<Item = Ipv4Addr> + '_ {
+            self.addresses.iter().filter_map(|cidr| match cidr.address {
+                IpAddr::V4(address) => Some(address),
+                IpAddr::V6(_) => None,
+            })
+        }
+
+        fn ipv6_addresses(&self) -> impl Iterator<Item = Ipv6Addr> + '_ {
+            self.addresses.iter().filter_map(|cidr| match cidr.address {
+                IpAddr::V6(address) => Some(address),
+                IpAddr::V4(_) => None,
+            })
+        }
+    }
+
+    // Who installed a route; lets owners withdraw their routes without
+    // touching anyone else's
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum RouteProtocol {
+        Kernel,
+        Static,
+        Dhcp,
+        WireGuard,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Route {
+        pub destination: IpCidr,
+        pub gateway: Option<IpAddr>,
+        pub interface: InterfaceId,
+        pub metric: u32,
+        pub protocol: RouteProtocol,
+    }
+
+    impl Route {
+        pub fn new(destination: IpCidr, gateway: Option<IpAddr>, interface: InterfaceId) -> Self {
+            Route {
+                destination,
+                gateway,
+                interface,
+                metric: 0,
+                protocol: RouteProtocol::Static,
+            }
+        }
+    }
+
+    // A reassembled IP payload handed to the transport layers
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Datagram {
+        pub interface: InterfaceId,
+        pub source: IpAddr,
+        pub destination: IpAddr,
+        pub protocol: u8,
+        pub ttl: u8,
+        // Headers already pulled off; always linear, so it reads as one
+        // slice through data()
+        pub payload: PacketBuffer,
+    }
+
+    // Points in the datagram path where a PacketFilter runs
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub enum Hook {
+        // Received, before the routing decision (destination NAT)
+        Prerouting,
+        // Received and addressed to this host
+        Input,
+        // Generated locally, before fragmentation
+        Output,
+        // Routed through this host
+        Forward,
+        // Leaving through the chosen interface (source NAT)
+        Postrouting,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Verdict {
+        Accept,
+        Drop,
+        // Drop and tell the sender (ICMP administratively prohibited)
+        Reject,
+    }
+
+    // Who holds a local TCP/UDP port: the scheduler's task id and the
+    // service it runs as
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct SocketOwner {
+        pub task: usize,
+        pub service: String,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct FilterContext<'a> {
+        pub now: u64,
+        // Address of datagram.interface in the datagram's family, which is
+        // what masquerading rewrites the source to
+        pub interface_address: Option<IpAddr>,
+        // Owner of the local socket for input and output datagrams
+        pub owner: Option<&'a SocketOwner>,
+    }
+
+    // Installed by vxwall; may rewrite the datagram (NAT) as well as judge it
+    pub trait PacketFilter: Send {
+        fn filter(&mut self, hook: Hook, datagram: &mut Datagram, context: &FilterContext) -> Verdict;
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub enum Direction {
+        Rx,
+        Tx,
+    }
+
+    // Sees every frame crossing an interface as the driver saw it; cannot
+    // alter or drop anything
+    pub trait PacketTap: Send {
+        fn tap(&mut self, interface: InterfaceId, direction: Direction, frame: &[u8], now: u64);
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    enum FragmentKey {
+        V4(Ipv4Addr, Ipv4Addr, u8, u16),
+        V6(Ipv6Addr, Ipv6Addr, u32),
+    }
+
+    struct Reassembly {
+        // Never overlapping
+        fragments: BTreeMap<usize, Vec<u8>>,
+        total_len: Option<usize>,
+        first_seen: u64,
+        // IPv6 drops the whole packet on any overlap (RFC 5722), and keeps
+        // dropping its fragments until the entry times out
+        discard_overlaps: bool,
+        discarded: bool,
+    }
+
+    impl Reassembly {
+        fn insert(&mut self, offset: usize, data: &[u8], last: bool) -> Result<(), &'static str> {
+            let end = offset + data.len();
+            if end > MAX_REASSEMBLY_SIZE {
+                return Err("Reassembled packet too large");
+            }
+            if self.discarded {
+                return Ok(());
+            }
+            let overlapping = self.fragments.range(..end).filter(|(start, queued)| *start + queued.len() > offset);
+            if self.discard_overlaps && overlapping.clone().next().is_some() {
+                self.discarded = true;
+                self.fragments.clear();
+                self.total_len = None;
+                return Ok(());
+            }
+            // Only bytes no earlier fragment covers are stored, so where
+            // IPv4 fragments overlap the ones that arrived first are kept
+            let mut pieces = Vec::new();
+            let mut cursor = offset;
+            for (start, queued) in overlapping {
+                if *start > cursor {
+                    pieces.push((cursor, *start));
+                }
+                cursor = cursor.max(start + queued.len());
+            }
+            if cursor < end {
+                pieces.push((cursor, end));
+            }
+            for (from, to) in pieces {
+                self.fragments.entry(from).or_insert_with(|| data[from - offset..to - offset].to_vec());
+            }
+            if last {
+                self.total_len = Some(end);
+            }
+            Ok(())
+        }
+
+        fn assemble(&self) -> Option<Vec<u8>> {
+            let total = self.total_len?;
+            let mut payload = Vec::with_capacity(total);
+            for (offset, data) in &self.fragments {
+                if *offset > payload.len() {
+                    return None;
+                }
+                payload.extend_from_slice(data);
+            }
+            (payload.len() >= total).then(|| {
+                payload.truncate(total);
+                payload
+            })
+        }
+    }
+
+    pub struct NetStack {
+        interfaces: Vec<Interface>,
+        fib: Fib,
+        neighbors: NeighborTable,
+        reassembly: BTreeMap<FragmentKey, Reassembly>,
+        inbound: VecDeque<Datagram>,
+        link_events: VecDeque<LinkEvent>,
+        filter: Option<Box<dyn PacketFilter>>,
+        // (protocol, local port) -> socket owner, for owner-matching filter rules
+        owners: BTreeMap<(u8, u16), SocketOwner>,
+        tap: Option<Box<dyn PacketTap>>,
+        forwarding: bool,
+        next_ipv4_id: u16,
+        next_ipv6_id: u32,
+        now: u64,
+    }
+
+    impl NetStack {
+        pub fn new() -> Self {
+            NetStack {
+                interfaces: Vec::new(),
+                fib: Fib::new(),
+                neighbors: NeighborTable::default(),
+                reassembly: BTreeMap::new(),
+                inbound: VecDeque::new(),
+                link_events: VecDeque::new(),
+                filter: None,
+                owners: BTreeMap::new(),
+                tap: None,
+                forwarding: false,
+                next_ipv4_id: 1,
+                next_ipv6_id: 1,
+                now: 0,
+            }
+        }
+
+        pub fn add_interface(&mut self, device: Box<dyn NetDevice>) -> Result<InterfaceId, &'static str> {
+            if device.mtu() < MIN_IPV4_MTU {
+                return Err("Device MTU below the IPv4 minimum");
+            }
+            let id = InterfaceId(self.interfaces.len());
+            log::info!("Adding interface {} ({})", device.name(), device.mac_address());
+            self.interfaces.push(Interface {
+                id,
+                device,
+                addresses: Vec::new(),
+                multicast_groups: Vec::new(),
+                link_up: false,
+                admin_up: true,
+                mtu: None,
+                mac: None,
+                stats: InterfaceStats::default(),
+                qdisc: None,
+                wake: WakeOnLan::default(),
+                dns_servers: Vec::new(),
+            });
+            Ok(id)
+        }
+
+        pub fn interface(&self, id: InterfaceId) -> Option<&Interface> {
+            self.interfaces.get(id.0)
+        }
+
+        pub fn interfaces(&self) -> &[Interface] {
+            &self.interfaces
+        }
+
+        pub fn interface_by_name(&self, name: &str) -> Option<InterfaceId> {
+            self.interfaces.iter().find(|iface| iface.name() == name).map(|iface| iface.id)
+        }
+
+        // Assigns an address and installs the matching connected route, plus
+        // a multicast route for the first address of each family
+        pub fn add_address(&mut self, id: InterfaceId, cidr: IpCidr) -> Result<(), &'static str> {
+            let iface = self.interfaces.get_mut(id.0).ok_or("Interface not found")?;
+            if iface.addresses.contains(&cidr) {
+                return Err("Address already assigned");
+            }
+            if cidr.address.is_ipv6() && iface.mtu() < MIN_IPV6_MTU {
+                return Err("MTU below the IPv6 minimum");
+            }
+            let first_of_family = !iface.addresses.iter().any(|existing| existing.address.is_ipv4() == cidr.address.is_ipv4());
+            iface.addresses.push(cidr);
+            let connected = Route {
+                protocol: RouteProtocol::Kernel,
+                ..Route::new(cidr.network(), None, id)
+            };
+            if !self.fib.contains(TABLE_MAIN, &connected) {
+                self.fib.add_route(TABLE_MAIN, connected)?;
+            }
+            if first_of_family {
+                self.fib.add_route(TABLE_MAIN, multicast_route(id, cidr.address.is_ipv4()))?;
+            }
+            Ok(())
+        }
+
+        pub fn remove_address(&mut self, id: InterfaceId, cidr: IpCidr) -> Result<(), &'static str> {
+            let iface = self.interfaces.get_mut(id.0).ok_or("Interface not found")?;
+            let before = iface.addresses.len();
+            iface.addresses.retain(|existing| *existing != cidr);
+            if iface.addresses.len() == before {
+                return Err("Address not assigned");
+            }
+            let network = cidr.network();
+            let network_in_use = iface.addresses.iter().any(|existing| existing.network() == network);
+            let family_in_use = iface.addresses.iter().any(|existing| existing.address.is_ipv4() == cidr.address.is_ipv4());
+            let multicast = multicast_route(id, cidr.address.is_ipv4());
+            self.fib.retain_routes(|table, route| {
+                let kernel = table == TABLE_MAIN && route.interface == id && route.protocol == RouteProtocol::Kernel;
+                let stale = (!network_in_use && route.destination == network && route.gateway.is_none())
+                    || (!family_in_use && *route == multicast);
+                !(kernel && stale)
+            });
+            Ok(())
+        }
+
+        pub fn add_route(&mut self, route: Route) -> Result<(), &'static str> {
+            self.add_route_to_table(TABLE_MAIN, route)
+        }
+
+        pub fn add_route_to_table(&mut self, table: u32, route: Route) -> Result<(), &'static str> {
+            if self.interfaces.get(route.interface.0).is_none() {
+                return Err("Interface not found");
+            }
+            self.fib.add_route(table, route)
+        }
+
+        // Removes every route to destination from the main table
+        pub fn remove_route(&mut self, destination: IpCidr) -> Result<(), &'static str> {
+            let network = destination.network();
+            let removed = self
+                .fib
+                .retain_routes(|table, route| table != TABLE_MAIN || route.destination != network);
+            if removed == 0 {
+                return Err("Route not found");
+            }
+            Ok(())
+        }
+
+        pub fn routes(&self) -> Vec<Route> {
+            self.fib.routes(TABLE_MAIN)
+        }
+
+        pub fn fib(&self) -> &Fib {
+            &self.fib
+        }
+
+        pub fn fib_mut(&mut self) -> &mut Fib {
+            &mut self.fib
+        }
+
+        // Routes through administratively down interfaces are skipped
+        pub fn lookup_route(&self, destination: &IpAddr) -> Option<Route> {
+            self.route_for(destination, None, None)
+        }
+
+        // Multicast sent from an assigned address leaves through the interface
+        // owning it; everything else goes through the policy rules
+        fn route_for(&self, destination: &IpAddr, source: Option<&IpAddr>, incoming: Option<InterfaceId>) -> Option<Route> {
+            if destination.is_multicast() {
+                let owner = source.and_then(|source| self.interfaces.iter().find(|iface| iface.has_address(source)));
+                if let Some(iface) = owner {
+                    let host = IpCidr::new(*destination, if destination.is_ipv4() { 32 } else { 128 }).ok()?;
+                    return Some(Route {
+                        protocol: RouteProtocol::Kernel,
+                        ..Route::new(host, None, iface.id)
+                    });
+                }
+            }
+            self.fib
+                .lookup_filtered(destination, source, incoming, |route| self.interfaces[route.interface.0].admin_up)
+        }
+
+        pub fn set_admin_state(&mut self, id: InterfaceId, up: bool) -> Result<(), &'static str> {
+            let iface = self.interfaces.get_mut(id.0).ok_or("Interface not found")?;
+            if iface.admin_up == up {
+                return Ok(());
+            }
+            iface.admin_up = up;
+            log::info!("Interface {} set {}", iface.device.name(), if up { "up" } else { "down" });
+            // Consumers such as DHCP only see the combined state
+            if iface.link_up {
+                self.link_events.push_back(LinkEvent { interface: id, up });
+            }
+            if !up {
+                iface.stats.tx_dropped += self.neighbors.flush_interface(id) as u64;
+                if let Some(qdisc) = &mut iface.qdisc {
+                    while qdisc.dequeue(self.now).is_some() {
+                        iface.stats.tx_dropped += 1;
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        // Egress frames then queue in the discipline and reach the device
+        // as it has room and the discipline releases them
+        pub fn set_qdisc(&mut self, id: InterfaceId, qdisc: Box<dyn Qdisc>) -> Result<(), &'static str> {
+            let mut qdisc = qdisc;
+            let iface = self.interfaces.get_mut(id.0).ok_or("Interface not found")?;
+            // Frames already queued keep their place in the new discipline
+            if let Some(mut previous) = iface.qdisc.take() {
+                while let Some(frame) = previous.dequeue(self.now) {
+                    if qdisc.enqueue(frame, self.now).is_err() {
+                        iface.stats.tx_dropped += 1;
+                    }
+                }
+            }
+            iface.qdisc = Some(qdisc);
+            self.service_qdisc(id);
+            Ok(())
+        }
+
+        // Back to handing frames straight to the device
+        pub fn clear_qdisc(&mut self, id: InterfaceId) -> Result<(), &'static str> {
+            let iface = self.interfaces.get_mut(id.0).ok_or("Interface not found")?;
+            if let Some(mut qdisc) = iface.qdisc.take() {
+                while let Some(frame) = qdisc.dequeue(self.now) {
+                    let _ = Self::hand_to_device(iface, &mut self.tap, &frame, self.now);
+                }
+            }
+            Ok(())
+        }
+
+        pub fn qdisc_stats(&self, id: InterfaceId) -> Option<QdiscStats> {
+            self.interfaces.get(id.0)?.qdisc.as_ref().map(|qdisc| qdisc.stats())
+        }
+
+        // Earliest time a shaper on any interface will release a frame
+        pub fn next_qdisc_release(&self) -> Option<u64> {
+            self.interfaces
+                .iter()
+                .filter_map(|iface| iface.qdisc.as_ref()?.next_release(self.now))
+                .min()
+        }
+
+        fn service_qdisc(&mut self, id: InterfaceId) {
+            let now = self.now;
+            let iface = &mut self.interfaces[id.0];
+            while iface.admin_up && iface.device.tx_ready() {
+                let Some(frame) = iface.qdisc.as_mut().and_then(|qdisc| qdisc.dequeue(now)) else {
+                    break;
+                };
+                // Errors are already counted against the interface
+                let _ = Self::hand_to_device(iface, &mut self.tap, &frame, now);
+            }
+        }
+
+        fn hand_to_device(
+            iface: &mut Interface,
+            tap: &mut Option<Box<dyn PacketTap>>,
+            packet: &PacketBuffer,
+            now: u64,
+        ) -> Result<(), &'static str> {
+            if let Some(tap) = tap {
+                // Fragments split off a large send arrive as a chain
+                if packet.fragments().is_empty() {
+                    tap.tap(iface.id, Direction::Tx, packet.data(), now);
+                } else {
+                    tap.tap(iface.id, Direction::Tx, &packet.to_vec(), now);
+                }
+            }
+            let result = iface.device.transmit_buffer(packet);
+            if result.is_ok() {
+                iface.stats.tx_packets += 1;
+                iface.stats.tx_bytes += packet.len() as u64;
+            } else {
+                iface.stats.tx_errors += 1;
+            }
+            result
+        }
+
+        // The device MTU is the ceiling; None restores it
+        pub fn set_mtu(&mut self, id: InterfaceId, mtu: Option<usize>) -> Result<(), &'static str> {
+            let iface = self.interfaces.get_mut(id.0).ok_or("Interface not found")?;
+            if let Some(mtu) = mtu {
+                if !(MIN_IPV4_MTU..=iface.device.mtu()).contains(&mtu) {
+                    return Err("MTU out of range");
+                }
+                if mtu < MIN_IPV6_MTU && iface.ipv6_addresses().next().is_some() {
+                    return Err("MTU below the IPv6 minimum");
+                }
+            }
+            iface.mtu = mtu;
+            Ok(())
+        }
+
+        // Replaces the name servers learned on an interface; the resolver
+        // queries them through dns_servers
+        pub fn set_dns_servers(&mut self, id: InterfaceId, servers: Vec<IpAddr>) -> Result<(), &'static str> {
+            let iface = self.interfaces.get_mut(id.0).ok_or("Interface not found")?;
+            iface.dns_servers = servers;
+            Ok(())
+        }
+
+        // Name servers of every interface that is up, in interface order
+        pub fn dns_servers(&self) -> Vec<IpAddr> {
+            let mut servers: Vec<IpAddr> = Vec::new();
+            for server in self.interfaces.iter().filter(|iface| iface.admin_up).flat_map(|iface| &iface.dns_servers) {
+                if !servers.contains(server) {
+                    servers.push(*server);
+                }
+            }
+            servers
+        }
+
+        // None restores the permanent address
+        pub fn set_mac_address(&mut self, id: InterfaceId, mac: Option<MacAddress>) -> Result<(), &'static str> {
+            let iface = self.interfaces.get_mut(id.0).ok_or("Interface not found")?;
+            if iface.device.point_to_point() {
+                return Err("Interface has no link-layer address");
+            }
+            if mac.is_some_and(|mac| mac.is_multicast() || mac == MacAddress::ZERO) {
+                return Err("Invalid MAC address");
+            }
+            let permanent = iface.device.mac_address();
+            iface.device.set_mac_address(mac.unwrap_or(permanent))?;
+            iface.mac = mac.filter(|mac| *mac != permanent);
+            Ok(())
+        }
+
+        // Arms the device's wake filters for the next suspend; the power
+        // code separately enables the GPE the device signals wake on
+        pub fn set_wake_on_lan(&mut self, id: InterfaceId, config: WakeOnLan) -> Result<(), &'static str> {
+            let iface = self.interfaces.get_mut(id.0).ok_or("Interface not found")?;
+            if !config.is_subset_of(&iface.device.wake_capabilities()) {
+                return Err("Wake event not supported by device");
+            }
+            iface.device.set_wake_on_lan(config)?;
+            iface.wake = config;
+            Ok(())
+        }
+
+        // Wakes a sleeping machine on the interface's segment
+        pub fn send_magic_packet(&mut self, id: InterfaceId, target: MacAddress) -> Result<(), &'static str> {
+            let packet = PacketBuffer::from_slice(DEFAULT_HEADROOM, &build_magic_packet(target));
+            self.transmit_frame(id, MacAddress::BROADCAST, ETHERTYPE_WAKE_ON_LAN, packet)
+        }
+
+        pub fn neighbor(&self, address: &IpAddr) -> Option<MacAddress> {
+            self.neighbors.lookup(address)
+        }
+
+        pub fn neighbor_entry(&self, address: &IpAddr) -> Option<Neighbor> {
+            self.neighbors.get(address)
+        }
+
+        pub fn neighbors(&self) -> Vec<Neighbor> {
+            self.neighbors.entries()
+        }
+
+        pub fn neighbor_config(&self) -> NeighborConfig {
+            self.neighbors.config()
+        }
+
+        pub fn set_neighbor_config(&mut self, config: NeighborConfig) {
+            self.neighbors.set_config(config);
+        }
+
+        // For transports that see forward progress to the neighbor
+        pub fn confirm_neighbor(&mut self, address: &IpAddr) {
+            self.neighbors.confirm(address, self.now);
+        }
+
+        pub fn remove_neighbor(&mut self, address: &IpAddr) -> Result<(), &'static str> {
+            if !self.neighbors.remove(address) {
+                return Err("Neighbor not found");
+            }
+            Ok(())
+        }
+
+        pub fn take_inbound(&mut self) -> Option<Datagram> {
+            self.inbound.pop_front()
+        }
+
+        pub fn set_packet_filter(&mut self, filter: Box<dyn PacketFilter>) {
+            self.filter = Some(filter);
+        }
+
+        pub fn clear_packet_filter(&mut self) {
+            self.filter = None;
+        }
+
+        pub fn set_packet_tap(&mut self, tap: Box<dyn PacketTap>) {
+            self.tap = Some(tap);
+        }
+
+        pub fn clear_packet_tap(&mut self) {
+            self.tap = None;
+        }
+
+        // Records which task and service hold a local port so filter rules
+        // can match on them; the socket layer registers on bind and connect
+        pub fn set_port_owner(&mut self, protocol: u8, port: u16, owner: SocketOwner) {
+            self.owners.insert((protocol, port), owner);
+        }
+
+        pub fn clear_port_owner(&mut self, protocol: u8, port: u16) -> Option<SocketOwner> {
+            self.owners.remove(&(protocol, port))
+        }
+
+        pub fn port_owner(&self, protocol: u8, port: u16) -> Option<&SocketOwner> {
+            self.owners.get(&(protocol, port))
+        }
+
+        fn run_filter(&mut self, hook: Hook, datagram: &mut Datagram) -> Verdict {
+            let local_port = match (datagram.protocol, datagram.payload.data().get(..4)) {
+                (IP_PROTO_TCP | IP_PROTO_UDP, Some(ports)) => match hook {
+                    Hook::Output | Hook::Postrouting => Some(u16::from_be_bytes([ports[0], ports[1]])),
+                    Hook::Input => Some(u16::from_be_bytes([ports[2], ports[3]])),
+                    _ => None,
+                },
+                _ => None,
+            };
+            let context = FilterContext {
+                now: self.now,
+                interface_address: self.select_source(datagram.interface, &datagram.destination),
+                owner: local_port.and_then(|port| self.owners.get(&(datagram.protocol, port))),
+            };
+            match &mut self.filter {
+                Some(filter) => filter.filter(hook, datagram, &context),
+                None => Verdict::Accept,
+            }
+        }
+
+        // Routes datagrams that are not addressed to this host
+        pub fn set_forwarding(&mut self, enabled: bool) {
+            self.forwarding = enabled;
+        }
+
+        pub fn forwarding(&self) -> bool {
+            self.forwarding
+        }
+
+        fn is_local_address(&self, address: &IpAddr) -> bool {
+            self.interfaces.iter().any(|iface| iface.has_address(address))
+        }
+
+        fn forwardable(&self, source: &IpAddr, destination: &IpAddr) -> bool {
+            let broadcast = matches!(destination, IpAddr::V4(address) if address.is_broadcast());
+            self.forwarding && !destination.is_multicast() && !broadcast && !source.is_unspecified()
+        }
+
+        pub fn take_link_event(&mut self) -> Option<LinkEvent> {
+            self.link_events.pop_front()
+        }
+
+        // Drops reassembly state that has waited too long for missing
+        // fragments, picks up carrier changes from the drivers, runs the
+        // neighbor state machine and drains the queueing disciplines
+        pub fn poll_timers(&mut self, now: u64) {
+            self.now = now;
+            self.reassembly
+                .retain(|_, entry| now.saturating_sub(entry.first_seen) < REASSEMBLY_TIMEOUT_MS);
+            for iface in &mut self.interfaces {
+                let up = iface.device.link_up();
+                if up != iface.link_up {
+                    iface.link_up = up;
+                    log::info!("Link {} on {}", if up { "up" } else { "down" }, iface.device.name());
+                    if iface.admin_up {
+                        self.link_events.push_back(LinkEvent { interface: iface.id, up });
+                    }
+                }
+            }
+            let (probes, dropped) = self.neighbors.poll(now);
+            for (id, count) in dropped {
+                self.interfaces[id.0].stats.tx_dropped += count as u64;
+            }
+            for probe in probes {
+                // A probe that cannot be sent simply counts as unanswered
+                let _ = self.solicit(probe.interface, probe.address, probe.mac);
+            }
+            for index in 0..self.interfaces.len() {
+                if self.interfaces[index].qdisc.is_some() {
+                    self.service_qdisc(InterfaceId(index));
+                }
+            }
+        }
+
+        fn select_source(&self, id: InterfaceId, destination: &IpAddr) -> Option<IpAddr> {
+            let iface = self.interfaces.get(id.0)?;
+            let same_family = |cidr: &&IpCidr| cidr.address.is_ipv4() == destination.is_ipv4();
+            iface
+                .addresses
+                .iter()
+                .filter(same_family)
+                .find(|cidr| cidr.contains(destination))
+                .or_else(|| iface.addresses.iter().find(same_family))
+                .map(|cidr| cidr.address)
+        }
+
+        // Source address the stack would pick for traffic to destination
+        pub fn source_address(&self, destination: &IpAddr) -> Option<IpAddr> {
+            let route = self.route_for(destination, None, None)?;
+            self.select_source(route.interface, destination)
+        }
+
+        pub fn send(&mut self, destination: IpAddr, protocol: u8, payload: &[u8]) -> Result<(), &'static str> {
+            let source = self
+                .source_address(&destination)
+                .ok_or("No source address for destination")?;
+            self.send_from(source, destination, protocol, payload, 64)
+        }
+
+        pub fn join_multicast(&mut self, id: InterfaceId, group: IpAddr) -> Result<(), &'static str> {
+            if !group.is_multicast() {
+                return Err("Not a multicast address");
+            }
+            let iface = self.interfaces.get_mut(id.0).ok_or("Interface not found")?;
+            if iface.is_member(&group) {
+                return Ok(());
+            }
+            iface.multicast_groups.push(group);
+            self.send_membership(id, group, true)
+        }
+
+        pub fn leave_multicast(&mut self, id: InterfaceId, group: IpAddr) -> Result<(), &'static str> {
+            let iface = self.interfaces.get_mut(id.0).ok_or("Interface not found")?;
+            if !iface.is_member(&group) {
+                return Err("Not a member of group");
+            }
+            iface.multicast_groups.retain(|existing| *existing != group);
+            self.send_membership(id, group, false)
+        }
+
+        // IGMPv2 / MLDv1 report or leave for a single group
+        fn send_membership(&mut self, id: InterfaceId, group: IpAddr, join: bool) -> Result<(), &'static str> {
+            let iface = &self.interfaces[id.0];
+            match group {
+                IpAddr::V4(group) => {
+                    if group == ALL_SYSTEMS {
+                        return Ok(());
+                    }
+                    let Some(source) = iface.ipv4_addresses().next() else {
+                        return Ok(());
+                    };
+                    let (kind, destination) = if join {
+                        (IGMPV2_MEMBERSHIP_REPORT, group)
+                    } else {
+                        (IGMP_LEAVE_GROUP, ALL_ROUTERS)
+                    };
+                    let mut message = vec![kind, 0, 0, 0];
+                    message.extend_from_slice(&group.octets());
+                    let sum = checksum(&message);
+                    message[2..4].copy_from_slice(&sum.to_be_bytes());
+                    let mut header = Ipv4Header::new(source, destination, IP_PROTO_IGMP);
+                    header.ttl = 1;
+                    let mut packet = PacketBuffer::from_slice(DEFAULT_HEADROOM, &message);
+                    push_ipv4(&header, &mut packet);
+                    self.transmit_frame(id, ipv4_multicast_mac(&destination), ETHERTYPE_IPV4, packet)
+                }
+                IpAddr::V6(group) => {
+                    if group == ALL_NODES {
+                        return Ok(());
+                    }
+                    // MLD must come from a link-local address
+                    let Some(source) = iface
+                        .ipv6_addresses()
+                        .find(|address| address.segments()[0] & 0xFFC0 == 0xFE80)
+                    else {
+                        return Ok(());
+                    };
+                    let (kind, destination) = if join {
+                        (MLD_LISTENER_REPORT, group)
+                    } else {
+                        (MLD_LISTENER_DONE, ALL_ROUTERS_V6)
+                    };
+                    let message = IcmpMessage {
+                        kind,
+                        code: 0,
+                        rest: [0; 4],
+                        data: group.octets().to_vec(),
+                    };
+                    let icmp = build_icmpv6(source, destination, &message);
+                    let mut header = Ipv6Header::new(source, destination, IP_PROTO_ICMPV6);
+                    header.hop_limit = 1;
+                    let mut packet = PacketBuffer::from_slice(DEFAULT_HEADROOM, &icmp);
+                    push_ipv6(&header, &mut packet);
+                    self.transmit_frame(id, ipv6_multicast_mac(&destination), ETHERTYPE_IPV6, packet)
+                }
+            }
+        }
+
+        // Answers a general (unspecified group) or group-specific query
+        fn answer_membership_query(&mut self, id: InterfaceId, queried: IpAddr) -> Result<(), &'static str> {
+            let groups: Vec<IpAddr> = self.interfaces[id.0]
+                .multicast_groups
+                .iter()
+                .filter(|group| group.is_ipv4() == queried.is_ipv4())
+                .filter(|group| queried.is_unspecified() || **group == queried)
+                .copied()
+                .collect();
+            for group in groups {
+                self.send_membership(id, group, true)?;
+            }
+            Ok(())
+        }
+
+        pub fn send_from(
+            &mut self,
+            source: IpAddr,
+            destination: IpAddr,
+            protocol: u8,
+            payload: &[u8],
+            ttl: u8,
+        ) -> Result<(), &'static str> {
+            let route = self
+                .route_for(&destination, Some(&source), None)
+                .ok_or("No route to host")?;
+            // The one copy on the way out; lower layers push their headers
+            // into the headroom
+            let payload = PacketBuffer::from_slice(DEFAULT_HEADROOM, payload);
+            if self.filter.is_none() {
+                let next_hop = route.gateway.unwrap_or(destination);
+                return self.transmit_datagram(route.interface, next_hop, source, destination, protocol, payload, ttl);
+            }
+
+            let mut datagram = Datagram {
+                interface: route.interface,
+                source,
+                destination,
+                protocol,
+                ttl,
+                payload,
+            };
+            match self.run_filter(Hook::Output, &mut datagram) {
+                Verdict::Accept => {}
+                Verdict::Drop => return Ok(()),
+                Verdict::Reject => return Err("Operation not permitted by firewall"),
+            }
+            // The filter may have rewritten the destination
+            let route = if datagram.destination == destination {
+                route
+            } else {
+                self.route_for(&datagram.destination, Some(&datagram.source), None)
+                    .ok_or("No route to host")?
+            };
+            datagram.interface = route.interface;
+            if self.run_filter(Hook::Postrouting, &mut datagram) != Verdict::Accept {
+                return Ok(());
+            }
+            let next_hop = route.gateway.unwrap_or(datagram.destination);
+            self.transmit_datagram(
+                route.interface,
+                next_hop,
+                datagram.source,
+                datagram.destination,
+                datagram.protocol,
+                datagram.payload,
+                datagram.ttl,
+            )
+        }
+
+        // Bypasses routing; used before an interface has addresses or routes
+        // (DHCP, router solicitation)
+        pub fn send_on_interface(
+            &mut self,
+            id: InterfaceId,
+            source: IpAddr,
+            destination: IpAddr,
+            protocol: u8,
+            payload: &[u8],
+            ttl: u8,
+        ) -> Result<(), &'static str> {
+            if self.interfaces.get(id.0).is_none() {
+                return Err("Interface not found");
+            }
+            let payload = PacketBuffer::from_slice(DEFAULT_HEADROOM, payload);
+            self.transmit_datagram(id, destination, source, destination, protocol, payload, ttl)
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        fn transmit_datagram(
+            &mut self,
+            id: InterfaceId,
+            next_hop: IpAddr,
+            source: IpAddr,
+            destination: IpAddr,
+            protocol: u8,
+            payload: PacketBuffer,
+            ttl: u8,
+        ) -> Result<(), &'static str> {
+            let mtu = self.interfaces[id.0].mtu();
+            match (source, destination) {
+                (IpAddr::V4(source), IpAddr::V4(destination)) => {
+                    let mut header = Ipv4Header::new(source, destination, protocol);
+                    header.ttl = ttl;
+                    header.identification = self.next_ipv4_id;
+                    self.next_ipv4_id = self.next_ipv4_id.wrapping_add(1);
+                    for packet in fragment_ipv4(&header, payload, mtu)? {
+                        self.transmit_ip(id, next_hop, ETHERTYPE_IPV4, packet)?;
+                    }
+                    Ok(())
+                }
+                (IpAddr::V6(source), IpAddr::V6(destination)) => {
+                    let mut header = Ipv6Header::new(source, destination, protocol);
+                    header.hop_limit = ttl;
+                    let identification = self.next_ipv6_id;
+                    self.next_ipv6_id = self.next_ipv6_id.wrapping_add(1);
+                    for packet in fragment_ipv6(&header, payload, mtu, identification)? {
+                        self.transmit_ip(id, next_hop, ETHERTYPE_IPV6, packet)?;
+                    }
+                    Ok(())
+                }
+                _ => Err("Source and destination address families differ"),
+            }
+        }
+
+        fn transmit_frame(
+            &mut self,
+            id: InterfaceId,
+            destination: MacAddress,
+            ethertype: u16,
+            mut packet: PacketBuffer,
+        ) -> Result<(), &'static str> {
+            let iface = self.interfaces.get_mut(id.0).ok_or("Interface not found")?;
+            if !iface.admin_up {
+                return Err("Interface is down");
+            }
+            let header = EthernetHeader {
+                destination,
+                source: iface.mac_address(),
+                ethertype,
+            };
+            push_ethernet(&header, &mut packet);
+            // A full queue drops like a lossy link; the sender is not told
+            if let Some(qdisc) = &mut iface.qdisc {
+                if qdisc.enqueue(packet, self.now).is_err() {
+                    iface.stats.tx_dropped += 1;
+                }
+                self.service_qdisc(id);
+                return Ok(());
+            }
+            Self::hand_to_device(iface, &mut self.tap, &packet, self.now)
+        }
+
+        fn transmit_ip(
+            &mut self,
+            id: InterfaceId,
+            next_hop: IpAddr,
+            ethertype: u16,
+            packet: PacketBuffer,
+        ) -> Result<(), &'static str> {
+            if self.interfaces[id.0].device.point_to_point() {
+                return self.transmit_frame(id, MacAddress::ZERO, ethertype, packet);
+            }
+            let destination = match next_hop {
+                IpAddr::V4(address) if address.is_broadcast() => Some(MacAddress::BROADCAST),
+                IpAddr::V4(address) if address.is_multicast() => Some(ipv4_multicast_mac(&address)),
+                IpAddr::V4(address)
+                    if self.interfaces[id.0]
+                        .addresses
+                        .iter()
+                        .any(|cidr| cidr.broadcast() == Some(address)) =>
+                {
+                    Some(MacAddress::BROADCAST)
+                }
+                IpAddr::V6(address) if address.is_multicast() => Some(ipv6_multicast_mac(&address)),
+                _ => None,
+            };
+            if let Some(mac) = destination {
+                return self.transmit_frame(id, mac, ethertype, packet);
+            }
+
+            match self.neighbors.resolve(id, next_hop, self.now) {
+                Resolution::Resolved(mac) => self.transmit_frame(id, mac, ethertype, packet),
+                Resolution::Failed => Err("Neighbor unreachable"),
+                // Park the packet until the neighbor answers; one
+                // solicitation covers everything queued behind it, and
+                // poll_timers() retransmits it
+                Resolution::Incomplete { solicit } => {
+                    if !self.neighbors.enqueue(&next_hop, (ethertype, packet)) {
+                        self.interfaces[id.0].stats.tx_dropped += 1;
+                    }
+                    if solicit {
+                        self.solicit(id, next_hop, None)?;
+                    }
+                    Ok(())
+                }
+            }
+        }
+
+        // Broadcast/multicast solicitation, or a unicast probe to the
+        // cached address when confirming a stale entry
+        fn solicit(&mut self, id: InterfaceId, address: IpAddr, unicast: Option<MacAddress>) -> Result<(), &'static str> {
+            let iface = &self.interfaces[id.0];
+            let mac = iface.mac_address();
+            match address {
+                IpAddr::V4(target) => {
+                    let sender = iface.ipv4_addresses().next().ok_or("Interface has no IPv4 address")?;
+                    let request = build_arp(&ArpPacket {
+                        operation: ARP_REQUEST,
+                        sender_mac: mac,
+                        sender_ip: sender,
+                        target_mac: MacAddress::ZERO,
+                        target_ip: target,
+                    });
+                    let packet = PacketBuffer::from_slice(DEFAULT_HEADROOM, &request);
+                    self.transmit_frame(id, unicast.unwrap_or(MacAddress::BROADCAST), ETHERTYPE_ARP, packet)
+                }
+                IpAddr::V6(target) => {
+                    let source = iface.ipv6_addresses().next().ok_or("Interface has no IPv6 address")?;
+                    let destination = match unicast {
+                        Some(_) => target,
+                        None => solicited_node_multicast(&target),
+                    };
+                    let mut data = target.octets().to_vec();
+                    data.extend_from_slice(&[NDP_OPTION_SOURCE_LL, 1]);
+                    data.extend_from_slice(&mac.0);
+                    let message = IcmpMessage {
+                        kind: ICMPV6_NEIGHBOR_SOLICIT,
+                        code: 0,
+                        rest: [0; 4],
+                        data,
+                    };
+                    let icmp = build_icmpv6(source, destination, &message);
+                    let mut header = Ipv6Header::new(source, destination, IP_PROTO_ICMPV6);
+                    header.hop_limit = NDP_HOP_LIMIT;
+                    let mut packet = PacketBuffer::from_slice(DEFAULT_HEADROOM, &icmp);
+                    push_ipv6(&header, &mut packet);
+                    let next_hop = unicast.unwrap_or_else(|| ipv6_multicast_mac(&destination));
+                    self.transmit_frame(id, next_hop, ETHERTYPE_IPV6, packet)
+                }
+            }
+        }
+
+        fn learn_neighbor(
+            &mut self,
+            id: InterfaceId,
+            address: IpAddr,
+            mac: MacAddress,
+            confirmed: bool,
+        ) -> Result<(), &'static str> {
+            for (ethertype, packet) in self.neighbors.learn(id, address, mac, confirmed, self.now) {
+                self.transmit_frame(id, mac, ethertype, packet)?;
+            }
+            Ok(())
+        }
+
+        // RX entry point called by drivers for every received frame
+        pub fn receive(&mut self, id: InterfaceId, frame: &[u8], now: u64) -> Result<(), &'static str> {
+            self.receive_buffer(id, PacketBuffer::from_slice(0, frame), now)
+        }
+
+        // Entry point for drivers that receive into PacketBuffers; headers
+        // are pulled off in place and the payload is handed up as is
+        pub fn receive_buffer(&mut self, id: InterfaceId, mut packet: PacketBuffer, now: u64) -> Result<(), &'static str> {
+            self.now = now;
+            let iface = self.interfaces.get_mut(id.0).ok_or("Interface not found")?;
+            if !iface.admin_up || FAIL_PACKET.should_fail() {
+                iface.stats.rx_dropped += 1;
+                return Ok(());
+            }
+            iface.stats.rx_packets += 1;
+            iface.stats.rx_bytes += packet.len() as u64;
+            // Protocol handlers read payloads as one slice. A driver's single
+            // receive buffer already is one; only a scatter-gather chain is
+            // flattened here.
+            packet.linearize();
+            if let Some(tap) = &mut self.tap {
+                tap.tap(id, Direction::Rx, packet.data(), now);
+            }
+            let result = self.receive_frame(id, packet);
+            if result.is_err() {
+                self.interfaces[id.0].stats.rx_errors += 1;
+            }
+            result
+        }
+
+        fn receive_frame(&mut self, id: InterfaceId, mut packet: PacketBuffer) -> Result<(), &'static str> {
+            let ethernet = pull_ethernet(&mut packet)?;
+            let iface = &mut self.interfaces[id.0];
+            if ethernet.destination.is_multicast() {
+                iface.stats.rx_multicast += 1;
+            } else if ethernet.destination != iface.mac_address() {
+                return Ok(());
+            }
+            match ethernet.ethertype {
+                ETHERTYPE_ARP => self.receive_arp(id, packet.data()),
+                ETHERTYPE_IPV4 => self.receive_ipv4(id, packet),
+                ETHERTYPE_IPV6 => self.receive_ipv6(id, packet),
+                _ => Ok(()),
+            }
+        }
+
+        fn receive_arp(&mut self, id: InterfaceId, data: &[u8]) -> Result<(), &'static str> {
+            let arp = parse_arp(data)?;
+            let iface = &self.interfaces[id.0];
+            let for_us = iface.has_address(&IpAddr::V4(arp.target_ip));
+            let known = self.neighbors.contains(&IpAddr::V4(arp.sender_ip));
+            if !for_us && !known {
+                return Ok(());
+            }
+            let mac = iface.mac_address();
+            // Only a reply to us proves the neighbor can hear us
+            let confirmed = for_us && arp.operation == ARP_REPLY;
+            self.learn_neighbor(id, IpAddr::V4(arp.sender_ip), arp.sender_mac, confirmed)?;
+            if for_us && arp.operation == ARP_REQUEST {
+                let reply = build_arp(&ArpPacket {
+                    operation: ARP_REPLY,
+                    sender_mac: mac,
+                    sender_ip: arp.target_ip,
+                    target_mac: arp.sender_mac,
+                    target_ip: arp.sender_ip,
+                });
+                let packet = PacketBuffer::from_slice(DEFAULT_HEADROOM, &reply);
+                self.transmit_frame(id, arp.sender_mac, ETHERTYPE_ARP, packet)?;
+            }
+            Ok(())
+        }
+
+        fn receive_ipv4(&mut self, id: InterfaceId, mut packet: PacketBuffer) -> Result<(), &'static str> {
+            // Shares the storage; kept for quoting in ICMP errors
+            let original = packet.clone();
+            let header = pull_ipv4(&mut packet)?;
+            let (source, destination) = (IpAddr::V4(header.source), IpAddr::V4(header.destination));
+            let local = self.interfaces[id.0].accepts_ipv4(&header.destination) || self.is_local_address(&destination);
+            if !local && !self.forwardable(&source, &destination) {
+                return Ok(());
+            }
+
+            let payload = if header.is_fragment() {
+                let key = FragmentKey::V4(header.source, header.destination, header.protocol, header.identification);
+                match self.reassemble(key, header.fragment_offset, packet.data(), !header.more_fragments)? {
+                    Some(payload) => PacketBuffer::from_vec(payload),
+                    None => return Ok(()),
+                }
+            } else {
+                packet
+            };
+
+            let mut datagram = Datagram {
+                interface: id,
+                source,
+                destination,
+                protocol: header.protocol,
+                ttl: header.ttl,
+                payload,
+            };
+            match self.prerouting(&mut datagram, local) {
+                Some(true) => {}
+                Some(false) => return self.forward(datagram, original),
+                None => return Ok(()),
+            }
+            if !self.input_allowed(&mut datagram, original.data())? {
+                return Ok(());
+            }
+            if header.protocol == IP_PROTO_ICMP && self.handle_icmpv4(&datagram)? {
+                return Ok(());
+            }
+            if header.protocol == IP_PROTO_IGMP {
+                return self.handle_igmp(&datagram);
+            }
+            self.inbound.push_back(datagram);
+            Ok(())
+        }
+
+        fn receive_ipv6(&mut self, id: InterfaceId, mut packet: PacketBuffer) -> Result<(), &'static str> {
+            // Shares the storage; kept for quoting in ICMP errors
+            let original = packet.clone();
+            let header = pull_ipv6(&mut packet)?;
+            let (source, destination) = (IpAddr::V6(header.source), IpAddr::V6(header.destination));
+            let local = self.interfaces[id.0].accepts_ipv6(&header.destination) || self.is_local_address(&destination);
+            if !local && !self.forwardable(&source, &destination) {
+                return Ok(());
+            }
+
+            let (mut next_header, rest) = skip_ipv6_extensions(header.next_header, packet.data())?;
+            packet.pull(packet.len() - rest.len())?;
+            if next_header == IP_PROTO_IPV6_FRAGMENT {
+                let (fragment, data) = parse_ipv6_fragment(packet.data())?;
+                let key = FragmentKey::V6(header.source, header.destination, fragment.identification);
+                let reassembled = match self.reassemble(key, fragment.offset, data, !fragment.more_fragments)? {
+                    Some(payload) => payload,
+                    None => return Ok(()),
+                };
+                let (inner, rest) = skip_ipv6_extensions(fragment.next_header, &reassembled)?;
+                let skipped = reassembled.len() - rest.len();
+                next_header = inner;
+                packet = PacketBuffer::from_vec(reassembled);
+                packet.pull(skipped)?;
+            }
+
+            let mut datagram = Datagram {
+                interface: id,
+                source,
+                destination,
+                protocol: next_header,
+                ttl: header.hop_limit,
+                payload: packet,
+            };
+            // Neighbor discovery and MLD are link plumbing, never filtered
+            let link_control = next_header == IP_PROTO_ICMPV6
+                && matches!(datagram.payload.data().first(), Some(&(MLD_LISTENER_QUERY..=ICMPV6_NEIGHBOR_ADVERT)));
+            if !link_control {
+                match self.prerouting(&mut datagram, local) {
+                    Some(true) => {}
+                    Some(false) => return self.forward(datagram, original),
+                    None => return Ok(()),
+                }
+            }
+            if !link_control && !self.input_allowed(&mut datagram, original.data())? {
+                return Ok(());
+            }
+            if next_header == IP_PROTO_ICMPV6 && self.handle_icmpv6(&datagram)? {
+                return Ok(());
+            }
+            self.inbound.push_back(datagram);
+            Ok(())
+        }
+
+        // Runs the prerouting hook and returns whether the datagram, possibly
+        // with a translated destination, is for this host; None if dropped
+        fn prerouting(&mut self, datagram: &mut Datagram, local: bool) -> Option<bool> {
+            if self.filter.is_none() {
+                return Some(local);
+            }
+            let destination = datagram.destination;
+            if self.run_filter(Hook::Prerouting, datagram) != Verdict::Accept {
+                return None;
+            }
+            if datagram.destination == destination {
+                Some(local)
+            } else {
+                Some(self.is_local_address(&datagram.destination))
+            }
+        }
+
+        fn forward(&mut self, mut datagram: Datagram, original: PacketBuffer) -> Result<(), &'static str> {
+            if !self.forwardable(&datagram.source, &datagram.destination) {
+                return Ok(());
+            }
+            // Zero can only come from a broken or hostile sender; it must
+            // not wrap round to 255 on the way out
+            if datagram.ttl <= 1 {
+                return self.send_icmp_error(&datagram, original.data(), (ICMP_TIME_EXCEEDED, 0), (ICMPV6_TIME_EXCEEDED, 0));
+            }
+            let Some(route) = self.route_for(&datagram.destination, Some(&datagram.source), Some(datagram.interface)) else {
+                return self.send_icmp_error(
+                    &datagram,
+                    original.data(),
+                    (ICMP_DEST_UNREACHABLE, ICMP_NET_UNREACHABLE),
+                    (ICMPV6_DEST_UNREACHABLE, ICMPV6_NO_ROUTE),
+                );
+            };
+            datagram.ttl -= 1;
+            datagram.interface = route.interface;
+            if self.filter.is_some() {
+                match self.run_filter(Hook::Forward, &mut datagram) {
+                    Verdict::Accept => {}
+                    Verdict::Drop => return Ok(()),
+                    Verdict::Reject => return self.send_prohibited(&datagram, original.data()),
+                }
+                if self.run_filter(Hook::Postrouting, &mut datagram) != Verdict::Accept {
+                    return Ok(());
+                }
+            }
+            // The new headers go where the received ones were pulled from;
+            // with the copy gone the storage is no longer shared
+            drop(original);
+            let next_hop = route.gateway.unwrap_or(datagram.destination);
+            self.transmit_datagram(
+                route.interface,
+                next_hop,
+                datagram.source,
+                datagram.destination,
+                datagram.protocol,
+                datagram.payload,
+                datagram.ttl,
+            )
+        }
+
+        // Runs the input hook; rejected packets get an ICMP error quoting
+        // the start of the original packet
+        fn input_allowed(&mut self, datagram: &mut Datagram, original: &[u8]) -> Result<bool, &'static str> {
+            if self.filter.is_none() {
+                return Ok(true);
+            }
+            match self.run_filter(Hook::Input, datagram) {
+                Verdict::Accept => Ok(true),
+                Verdict::Drop => Ok(false),
+                Verdict::Reject => {
+                    self.send_prohibited(datagram, original)?;
+                    Ok(false)
+                }
+            }
+        }
+
+        fn send_prohibited(&mut self, datagram: &Datagram, original: &[u8]) -> Result<(), &'static str> {
+            self.send_icmp_error(
+                datagram,
+                original,
+                (ICMP_DEST_UNREACHABLE, ICMP_ADMIN_PROHIBITED),
+                (ICMPV6_DEST_UNREACHABLE, ICMPV6_ADMIN_PROHIBITED),
+            )
+        }
+
+        // Reports a problem with a received datagram to its sender; (type,
+        // code) pairs are given for both families
+        fn send_icmp_error(
+            &mut self,
+            datagram: &Datagram,
+            original: &[u8],
+            icmpv4: (u8, u8),
+            icmpv6: (u8, u8),
+        ) -> Result<(), &'static str> {
+            if datagram.destination.is_multicast() || datagram.source.is_unspecified() {
+                return Ok(());
+            }
+            // Routers answer from their own address, not the one the
+            // datagram was headed to
+            let local = if self.is_local_address(&datagram.destination) {
+                datagram.destination
+            } else {
+                match self.source_address(&datagram.source) {
+                    Some(address) => address,
+                    None => return Ok(()),
+                }
+            };
+            match (local, datagram.source) {
+                (IpAddr::V4(local), IpAddr::V4(_)) => {
+                    if matches!(datagram.destination, IpAddr::V4(address) if address.is_broadcast()) {
+                        return Ok(());
+                    }
+                    let quoted = original.len().min(IPV4_HEADER_LEN + 8);
+                    let message = build_icmpv4(&IcmpMessage {
+                        kind: icmpv4.0,
+                        code: icmpv4.1,
+                        rest: [0; 4],
+                        data: original[..quoted].to_vec(),
+                    });
+                    self.send_from(IpAddr::V4(local), datagram.source, IP_PROTO_ICMP, &message, 64)
+                }
+                (IpAddr::V6(local), IpAddr::V6(remote)) => {
+                    let quoted = original.len().min(1232);
+                    let message = build_icmpv6(
+                        local,
+                        remote,
+                        &IcmpMessage {
+                            kind: icmpv6.0,
+                            code: icmpv6.1,
+                            rest: [0; 4],
+                            data: original[..quoted].to_vec(),
+                        },
+                    );
+                    self.send_from(IpAddr::V6(local), datagram.source, IP_PROTO_ICMPV6, &message, 64)
+                }
+                _ => Ok(()),
+            }
+        }
+
+        fn reassemble(
+            &mut self,
+            key: FragmentKey,
+            offset: usize,
+            data: &[u8],
+            last: bool,
+        ) -> Result<Option<Vec<u8>>, &'static str> {
+            let now = self.now;
+            if !self.reassembly.contains_key(&key) && self.reassembly.len() >= MAX_REASSEMBLY_ENTRIES {
+                let oldest = self.reassembly.iter().min_by_key(|(_, entry)| entry.first_seen).map(|(key, _)| *key);
+                if let Some(oldest) = oldest {
+                    self.reassembly.remove(&oldest);
+                }
+            }
+            let entry = self.reassembly.entry(key).or_insert_with(|| Reassembly {
+                fragments: BTreeMap::new(),
+                total_len: None,
+                first_seen: now,
+                discard_overlaps: matches!(key, FragmentKey::V6(..)),
+                discarded: false,
+            });
+            if let Err(error) = entry.insert(offset, data, last) {
+                self.reassembly.remove(&key);
+                return Err(error);
+            }
+            match entry.assemble() {
+                Some(payload) => {
+                    self.reassembly.remove(&key);
+                    Ok(Some(payload))
+                }
+                None => Ok(None),
+            }
+        }
+
+        // Answers echo requests; returns true when the message was consumed
+        fn handle_icmpv4(&mut self, datagram: &Datagram) -> Result<bool, &'static str> {
+            if checksum(datagram.payload.data()) != 0 {
+                return Err("Bad ICMP checksum");
+            }
+            let message = parse_icmp(datagram.payload.data())?;
+            if message.kind != ICMP_ECHO_REQUEST {
+                return Ok(false);
+            }
+            let reply = build_icmpv4(&IcmpMessage {
+                kind: ICMP_ECHO_REPLY,
+                code: 0,
+                rest: message.rest,
+                data: message.data,
+            });
+            let IpAddr::V4(destination) = datagram.destination else {
+                return Ok(true);
+            };
+            // Replies to broadcast pings come from the interface address
+            let source = if destination.is_broadcast() || destination.is_multicast() {
+                match self.select_source(datagram.interface, &datagram.source) {
+                    Some(source) => source,
+                    None => return Ok(true),
+                }
+            } else {
+                datagram.destination
+            };
+            self.send_from(source, datagram.source, IP_PROTO_ICMP, &reply, 64)?;
+            Ok(true)
+        }
+
+        fn handle_igmp(&mut self, datagram: &Datagram) -> Result<(), &'static str> {
+            let message = datagram.payload.data();
+            if message.len() < 8 || checksum(message) != 0 {
+                return Err("Bad IGMP message");
+            }
+            if message[0] == IGMP_MEMBERSHIP_QUERY {
+                let group = Ipv4Addr::new(message[4], message[5], message[6], message[7]);
+                self.answer_membership_query(datagram.interface, IpAddr::V4(group))?;
+            }
+            Ok(())
+        }
+
+        fn handle_icmpv6(&mut self, datagram: &Datagram) -> Result<bool, &'static str> {
+            if pseudo_header_checksum(datagram.source, datagram.destination, IP_PROTO_ICMPV6, datagram.payload.data()) != 0 {
+                return Err("Bad ICMPv6 checksum");
+            }
+            let message = parse_icmp(datagram.payload.data())?;
+            let (IpAddr::V6(source), IpAddr::V6(destination)) = (datagram.source, datagram.destination) else {
+                return Ok(false);
+            };
+            match message.kind {
+                ICMPV6_ECHO_REQUEST => {
+                    if destination.is_multicast() {
+                        return Ok(true);
+                    }
+                    let reply = build_icmpv6(
+                        destination,
+                        source,
+                        &IcmpMessage {
+                            kind: ICMPV6_ECHO_REPLY,
+                            code: 0,
+                            rest: message.rest,
+                            data: message.data,
+                        },
+                    );
+                    self.send_from(datagram.destination, datagram.source, IP_PROTO_ICMPV6, &reply, 64)?;
+                    Ok(true)
+                }
+                ICMPV6_NEIGHBOR_SOLICIT => {
+                    if datagram.ttl != NDP_HOP_LIMIT || message.data.len() < 16 {
+                        return Ok(true);
+                    }
+                    let target = Ipv6Addr::from(<[u8; 16]>::try_from(&message.data[..16]).unwrap());
+                    if let Some(mac) = ndp_link_layer_option(&message.data[16..], NDP_OPTION_SOURCE_LL) {
+                        if !source.is_unspecified() {
+                            self.learn_neighbor(datagram.interface, datagram.source, mac, false)?;
+                        }
+                    }
+                    let iface = &self.interfaces[datagram.interface.0];
+                    if iface.has_address(&IpAddr::V6(target)) {
+                        self.send_neighbor_advert(datagram.interface, target, source)?;
+                    }
+                    Ok(true)
+                }
+                ICMPV6_NEIGHBOR_ADVERT => {
+                    if datagram.ttl != NDP_HOP_LIMIT || message.data.len() < 16 {
+                        return Ok(true);
+                    }
+                    let target = IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(&message.data[..16]).unwrap()));
+                    // Answers to unicast probes may leave the address out
+                    let mac = ndp_link_layer_option(&message.data[16..], NDP_OPTION_TARGET_LL)
+                        .or_else(|| self.neighbors.lookup(&target));
+                    if let Some(mac) = mac {
+                        let solicited = message.rest[0] & NDP_FLAG_SOLICITED != 0;
+                        self.learn_neighbor(datagram.interface, target, mac, solicited)?;
+                    }
+                    Ok(true)
+                }
+                MLD_LISTENER_QUERY => {
+                    if datagram.ttl != 1 || message.data.len() < 16 {
+                        return Ok(true);
+                    }
+                    let group = Ipv6Addr::from(<[u8; 16]>::try_from(&message.data[..16]).unwrap());
+                    self.answer_membership_query(datagram.interface, IpAddr::V6(group))?;
+                    Ok(true)
+                }
+                MLD_LISTENER_REPORT | MLD_LISTENER_DONE => Ok(true),
+                _ => Ok(false),
+            }
+        }
+
+        fn send_neighbor_advert(
+            &mut self,
+            id: InterfaceId,
+            target: Ipv6Addr,
+            solicitor: Ipv6Addr,
+        ) -> Result<(), &'static str> {
+            let mac = self.interfaces[id.0].mac_address();
+            let (destination, flags) = if solicitor.is_unspecified() {
+                // Duplicate address detection probe: answer all nodes, not solicited
+                (ALL_NODES, 0x20)
+            } else {
+                (solicitor, 0x60)
+            };
+            let mut data = target.octets().to_vec();
+            data.extend_from_slice(&[NDP_OPTION_TARGET_LL, 1]);
+            data.extend_from_slice(&mac.0);
+            let icmp = build_icmpv6(
+                target,
+                destination,
+                &IcmpMessage {
+                    kind: ICMPV6_NEIGHBOR_ADVERT,
+                    code: 0,
+                    rest: [flags, 0, 0, 0],
+                    data,
+                },
+            );
+            let mut header = Ipv6Header::new(target, destination, IP_PROTO_ICMPV6);
+            header.hop_limit = NDP_HOP_LIMIT;
+            let mut packet = PacketBuffer::from_slice(DEFAULT_HEADROOM, &icmp);
+            push_ipv6(&header, &mut packet);
+            let next_hop = match self.neighbors.lookup(&IpAddr::V6(destination)) {
+                Some(mac) => mac,
+                None => ipv6_multicast_mac(&destination),
+            };
+            self.transmit_frame(id, next_hop, ETHERTYPE_IPV6, packet)
+        }
+    }
+
+    impl Default for NetStack {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    // Lower interface ids win, so multicast without an owned source address
+    // leaves through the first interface configured for the family
+    fn multicast_route(id: InterfaceId, ipv4: bool) -> Route {
+        let destination = if ipv4 {
+            IpCidr { address: IpAddr::V4(Ipv4Addr::new(224, 0, 0, 0)), prefix_len: 4 }
+        } else {
+            IpCidr { address: IpAddr::V6(Ipv6Addr::new(0xff00, 0, 0, 0, 0, 0, 0, 0)), prefix_len: 8 }
+        };
+        Route {
+            metric: MULTICAST_METRIC_BASE + id.0 as u32,
+            protocol: RouteProtocol::Kernel,
+            ..Route::new(destination, None, id)
+        }
+    }
+
+    fn ndp_link_layer_option(mut options: &[u8], kind: u8) -> Option<MacAddress> {
+        while options.len() >= 8 {
+            let length = options[1] as usize * 8;
+            if length == 0 || length > options.len() {
+                return None;
+            }
+            if options[0] == kind {
+                let mut mac = [0u8; 6];
+                mac.copy_from_slice(&options[2..8]);
+                return Some(MacAddress(mac));
+            }
+            options = &options[length..];
+        }
+        None
     }
 
-    pub fn send_packet(packet: &str) {
-        println!("Sending packet: {}", packet);
-        // Send a network packet
+    // Headers are pushed into each piece's headroom. Pieces after a split
+    // reference the payload's storage instead of copying it, and reach
+    // the device as fragment chains.
+    pub fn fragment_ipv4(header: &Ipv4Header, mut payload: PacketBuffer, mtu: usize) -> Result<Vec<PacketBuffer>, &'static str> {
+        if mtu < MIN_IPV4_MTU {
+            return Err("MTU below the IPv4 minimum");
+        }
+        if IPV4_HEADER_LEN + payload.len() <= mtu {
+            push_ipv4(header, &mut payload);
+            return Ok(vec![payload]);
+        }
+        if header.dont_fragment {
+            return Err("Packet exceeds MTU with DF set");
+        }
+        let chunk = (mtu - IPV4_HEADER_LEN) & !7;
+        let total = payload.len();
+        let mut rest = payload.split_off(0)?;
+        let mut packets = Vec::new();
+        let mut offset = 0;
+        while offset < total {
+            let mut piece = rest;
+            rest = piece.split_off(chunk.min(total - offset))?;
+            let mut fragment = *header;
+            fragment.fragment_offset = offset;
+            offset += piece.len();
+            fragment.more_fragments = offset < total;
+            push_ipv4(&fragment, &mut piece);
+            packets.push(piece);
+        }
+        Ok(packets)
     }
 
-    pub fn receive_packet() -> String {
-        println!("Receiving packet...");
-        // Receive a network packet
-        String::from("Received packet")
+    pub fn fragment_ipv6(
+        header: &Ipv6Header,
+        mut payload: PacketBuffer,
+        mtu: usize,
+        identification: u32,
+    ) -> Result<Vec<PacketBuffer>, &'static str> {
+        if mtu < MIN_IPV6_MTU {
+            return Err("MTU below the IPv6 minimum");
+        }
+        if IPV6_HEADER_LEN + payload.len() <= mtu {
+            push_ipv6(header, &mut payload);
+            return Ok(vec![payload]);
+        }
+        let chunk = (mtu - IPV6_HEADER_LEN - IPV6_FRAGMENT_HEADER_LEN) & !7;
+        let mut outer = *header;
+        outer.next_header = IP_PROTO_IPV6_FRAGMENT;
+        let total = payload.len();
+        let mut rest = payload.split_off(0)?;
+        let mut packets = Vec::new();
+        let mut offset = 0;
+        while offset < total {
+            let mut piece = rest;
+            rest = piece.split_off(chunk.min(total - offset))?;
+            let fragment = build_ipv6_fragment(&Ipv6Fragment {
+                next_header: header.next_header,
+                offset,
+                more_fragments: offset + piece.len() < total,
+                identification,
+            });
+            offset += piece.len();
+            piece.push(IPV6_FRAGMENT_HEADER_LEN).copy_from_slice(&fragment);
+            push_ipv6(&outer, &mut piece);
+            packets.push(piece);
+        }
+        Ok(packets)
     }
 
-    pub fn update() {
-        println!("Updating VXNet Core...");
-        // Update the VXNet Core system
+    pub fn init() -> NetStack {
+        log::info!("Initializing VXNet Core...");
+        NetStack::new()
     }
 }
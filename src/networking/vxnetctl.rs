@@ -0,0 +1,207 @@
+// src/networking/vxnetctl.rs
+
+pub mod vxnetctl {
+    use crate::netdev::netdev::MacAddress;
+    use crate::vxnet_core::vxnet_core::{Interface, InterfaceId, IpCidr, NetStack};
+    use vaelix_core::vxchan::vxchan::VXChanManager;
+
+    pub const REQUEST_CHANNEL: &str = "vxnetctl";
+    pub const REPLY_CHANNEL: &str = "vxnetctl.reply";
+
+    // Interfaces are named by device name or by index
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum NetCtlRequest {
+        List,
+        Show(String),
+        SetUp(String, bool),
+        AddAddress(String, IpCidr),
+        RemoveAddress(String, IpCidr),
+        SetMtu(String, Option<usize>),
+        SetMac(String, Option<MacAddress>),
+        Stats(String),
+    }
+
+    // Grammar, one request per message:
+    //   list | show IF | stats IF | up IF | down IF
+    //   addr add IF CIDR | addr del IF CIDR
+    //   mtu IF (N|default) | mac IF (XX:XX:XX:XX:XX:XX|default)
+    pub fn parse_request(line: &str) -> Result<NetCtlRequest, &'static str> {
+        let mut words = line.split_whitespace();
+        let command = words.next().ok_or("Empty request")?;
+        let mut value = || words.next().map(str::to_string).ok_or("Missing argument");
+        let request = match command {
+            "list" => NetCtlRequest::List,
+            "show" => NetCtlRequest::Show(value()?),
+            "stats" => NetCtlRequest::Stats(value()?),
+            "up" => NetCtlRequest::SetUp(value()?, true),
+            "down" => NetCtlRequest::SetUp(value()?, false),
+            "addr" => {
+                let action = value()?;
+                let (name, cidr) = (value()?, value()?.parse()?);
+                match action.as_str() {
+                    "add" => NetCtlRequest::AddAddress(name, cidr),
+                    "del" => NetCtlRequest::RemoveAddress(name, cidr),
+                    _ => return Err("Unknown address action"),
+                }
+            }
+            "mtu" => {
+                let name = value()?;
+                let mtu = match value()?.as_str() {
+                    "default" => None,
+                    mtu => Some(mtu.parse().map_err(|_| "Invalid MTU")?),
+                };
+                NetCtlRequest::SetMtu(name, mtu)
+            }
+            "mac" => {
+                let name = value()?;
+                let mac = match value()?.as_str() {
+                    "default" => None,
+                    mac => Some(mac.parse()?),
+                };
+                NetCtlRequest::SetMac(name, mac)
+            }
+            _ => return Err("Unknown command"),
+        };
+        if words.next().is_some() {
+            return Err("Unexpected trailing argument");
+        }
+        Ok(request)
+    }
+
+    fn resolve(net: &NetStack, name: &str) -> Result<InterfaceId, &'static str> {
+        if let Some(id) = net.interface_by_name(name) {
+            return Ok(id);
+        }
+        let index: usize = name.parse().map_err(|_| "Interface not found")?;
+        net.interface(InterfaceId(index)).map(Interface::id).ok_or("Interface not found")
+    }
+
+    fn describe(iface: &Interface) -> String {
+        let addresses: Vec<String> = iface
+            .addresses()
+            .iter()
+            .map(|cidr| format!("{}/{}", cidr.address, cidr.prefix_len))
+            .collect();
+        format!(
+            "{} index={} kind={} admin={} carrier={} mtu={} mac={} addresses={}",
+            iface.name(),
+            iface.id().0,
+            if iface.is_point_to_point() { "tunnel" } else { "ether" },
+            if iface.is_up() { "up" } else { "down" },
+            if iface.link_up() { "up" } else { "down" },
+            iface.mtu(),
+            iface.mac_address(),
+            addresses.join(","),
+        )
+    }
+
+    // Runs one request against the stack and renders the reply body
+    pub fn execute(net: &mut NetStack, request: &NetCtlRequest) -> Result<String, &'static str> {
+        match request {
+            NetCtlRequest::List => Ok(net.interfaces().iter().map(describe).collect::<Vec<_>>().join("\n")),
+            NetCtlRequest::Show(name) => {
+                let id = resolve(net, name)?;
+                Ok(describe(&net.interfaces()[id.0]))
+            }
+            NetCtlRequest::Stats(name) => {
+                let id = resolve(net, name)?;
+                let iface = &net.interfaces()[id.0];
+                let (stats, device) = (iface.stats(), iface.device_stats());
+                Ok(format!(
+                    "rx_packets={} rx_bytes={} rx_dropped={} rx_errors={} rx_multicast={} tx_packets={} tx_bytes={} \
+                     tx_errors={} tx_dropped={} rx_crc_errors={} rx_missed={} tx_carrier_errors={} collisions={}",
+                    stats.rx_packets,
+                    stats.rx_bytes,
+                    stats.rx_dropped,
+                    stats.rx_errors + device.rx_errors,
+                    stats.rx_multicast,
+                    stats.tx_packets,
+                    stats.tx_bytes,
+                    stats.tx_errors + device.tx_errors,
+                    stats.tx_dropped,
+                    device.rx_crc_errors,
+                    device.rx_missed,
+                    device.tx_carrier_errors,
+                    device.collisions
+                ))
+            }
+            NetCtlRequest::SetUp(name, up) => {
+                let id = resolve(net, name)?;
+                net.set_admin_state(id, *up).map(|_| String::new())
+            }
+            NetCtlRequest::AddAddress(name, cidr) => {
+                let id = resolve(net, name)?;
+                net.add_address(id, *cidr).map(|_| String::new())
+            }
+            NetCtlRequest::RemoveAddress(name, cidr) => {
+                let id = resolve(net, name)?;
+                net.remove_address(id, *cidr).map(|_| String::new())
+            }
+            NetCtlRequest::SetMtu(name, mtu) => {
+                let id = resolve(net, name)?;
+                net.set_mtu(id, *mtu).map(|_| String::new())
+            }
+            NetCtlRequest::SetMac(name, mac) => {
+                let id = resolve(net, name)?;
+                net.set_mac_address(id, *mac).map(|_| String::new())
+            }
+        }
+    }
+
+    // Messages on REQUEST_CHANNEL are "<id> <request>"; replies on
+    // REPLY_CHANNEL are "<id> ok[\n<body>]" or "<id> error <reason>"
+    pub struct NetCtlService {
+        handled: u64,
+    }
+
+    impl NetCtlService {
+        pub fn new(manager: &VXChanManager) -> Result<Self, &'static str> {
+            manager.create_channel(REQUEST_CHANNEL)?;
+            manager.create_channel(REPLY_CHANNEL)?;
+            log::info!("vxnetctl listening on {}", REQUEST_CHANNEL);
+            Ok(NetCtlService { handled: 0 })
+        }
+
+        pub fn handled(&self) -> u64 {
+            self.handled
+        }
+
+        // Answers every queued request; returns how many were handled
+        pub fn poll(&mut self, manager: &VXChanManager, net: &mut NetStack) -> Result<usize, &'static str> {
+            let mut count = 0;
+            while let Some(message) = manager.try_receive_message(REQUEST_CHANNEL)? {
+                let (id, line) = message.split_once(' ').unwrap_or((message.as_str(), ""));
+                let result = id
+                    .parse::<u64>()
+                    .map_err(|_| "Invalid request id")
+                    .and_then(|_| parse_request(line))
+                    .and_then(|request| execute(net, &request));
+                let reply = match result {
+                    Ok(body) if body.is_empty() => format!("{} ok", id),
+                    Ok(body) => format!("{} ok\n{}", id, body),
+                    Err(error) => format!("{} error {}", id, error),
+                };
+                manager.send_message(REPLY_CHANNEL, reply)?;
+                count += 1;
+            }
+            self.handled += count as u64;
+            Ok(count)
+        }
+    }
+
+    pub fn send_request(manager: &VXChanManager, id: u64, request: &str) -> Result<(), &'static str> {
+        manager.send_message(REQUEST_CHANNEL, format!("{} {}", id, request))
+    }
+
+    // Splits a reply into its request id and either the body or the error
+    pub fn parse_reply(message: &str) -> Result<(u64, Result<String, String>), &'static str> {
+        let (head, body) = message.split_once('\n').unwrap_or((message, ""));
+        let (id, status) = head.split_once(' ').ok_or("Malformed reply")?;
+        let id = id.parse().map_err(|_| "Malformed reply")?;
+        match status.split_once(' ') {
+            None if status == "ok" => Ok((id, Ok(body.to_string()))),
+            Some(("error", reason)) => Ok((id, Err(reason.to_string()))),
+            _ => Err("Malformed reply"),
+        }
+    }
+}
@@ -0,0 +1,350 @@
+// src/networking/vxupdate.rs
+
+pub mod vxupdate {
+    use crate::http::http::{HttpClient, HttpTransfer, TransferStatus};
+    use crate::socket::socket::SocketSet;
+    use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+    use sha2::{Digest, Sha256};
+    use std::fmt;
+    use std::io::{self, Write};
+    use std::str::FromStr;
+    use std::sync::Arc;
+    use vaelix_core::drivers::block::block::BlockDevice;
+    use vaelix_core::vxboot::vxboot::{BootControl, Slot};
+
+    pub const BUNDLE_MAGIC: &str = "VXUPDATE 1";
+    // The header is read whole before anything is written
+    pub const MAX_HEADER_LEN: usize = 4096;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct Version(pub u32, pub u32, pub u32);
+
+    impl FromStr for Version {
+        type Err = &'static str;
+
+        fn from_str(text: &str) -> Result<Self, Self::Err> {
+            let parts: Vec<u32> = text.split('.').map(|part| part.parse().map_err(|_| "Invalid version")).collect::<Result<_, _>>()?;
+            match parts[..] {
+                [major, minor, patch] => Ok(Version(major, minor, patch)),
+                _ => Err("Invalid version"),
+            }
+        }
+    }
+
+    impl fmt::Display for Version {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{}.{}.{}", self.0, self.1, self.2)
+        }
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn unhex<const N: usize>(text: &str) -> Result<[u8; N], &'static str> {
+        if text.len() != 2 * N {
+            return Err("Invalid hex length");
+        }
+        let mut bytes = [0u8; N];
+        for (index, byte) in bytes.iter_mut().enumerate() {
+            *byte = text.get(2 * index..2 * index + 2).and_then(|digits| u8::from_str_radix(digits, 16).ok()).ok_or("Invalid hex")?;
+        }
+        Ok(bytes)
+    }
+
+    // What a bundle says about the system image after it. It is signed
+    // over its text, so the image is only trusted through the hash.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct BundleHeader {
+        pub version: Version,
+        pub size: u64,
+        pub sha256: [u8; 32],
+    }
+
+    impl BundleHeader {
+        // The signed part
+        fn text(&self) -> String {
+            format!("{}\nversion {}\nsize {}\nsha256 {}\n", BUNDLE_MAGIC, self.version, self.size, hex(&self.sha256))
+        }
+
+        // The header, ending in its signature line and a blank line, then
+        // the image
+        pub fn parse(text: &str, keys: &[VerifyingKey]) -> Result<Self, &'static str> {
+            let (signed, signature) = text.rsplit_once("signature ").ok_or("Unsigned update bundle")?;
+            let signature = Signature::from_bytes(&unhex(signature.trim_end_matches('\n'))?);
+            if !keys.iter().any(|key| key.verify(signed.as_bytes(), &signature).is_ok()) {
+                return Err("Bad update signature");
+            }
+            let mut lines = signed.lines();
+            if lines.next() != Some(BUNDLE_MAGIC) {
+                return Err("Not an update bundle");
+            }
+            let mut field = |name: &str| lines.next().and_then(|line| line.strip_prefix(name)).and_then(|line| line.strip_prefix(' ')).ok_or("Malformed update header");
+            let header = BundleHeader {
+                version: field("version")?.parse()?,
+                size: field("size")?.parse().map_err(|_| "Malformed update header")?,
+                sha256: unhex(field("sha256")?)?,
+            };
+            match header.text() == signed {
+                true => Ok(header),
+                false => Err("Malformed update header"),
+            }
+        }
+    }
+
+    // How release tooling makes a bundle
+    pub fn build_bundle(key: &SigningKey, version: Version, image: &[u8]) -> Vec<u8> {
+        let header = BundleHeader {
+            version,
+            size: image.len() as u64,
+            sha256: Sha256::digest(image).into(),
+        };
+        let text = header.text();
+        let signature = key.sign(text.as_bytes());
+        let mut bundle = format!("{}signature {}\n\n", text, hex(&signature.to_bytes())).into_bytes();
+        bundle.extend_from_slice(image);
+        bundle
+    }
+
+    // Takes a bundle as it downloads: checks the header's signature and
+    // version before writing a single byte to the slot, then streams the
+    // image to it block by block, hashing as it goes
+    pub struct SlotWriter {
+        device: Arc<dyn BlockDevice>,
+        keys: Vec<VerifyingKey>,
+        // Bundles have to be newer than the running system
+        running: Version,
+        head: Vec<u8>,
+        header: Option<BundleHeader>,
+        hasher: Sha256,
+        pending: Vec<u8>,
+        written: u64,
+        next_block: u64,
+        // Why the last write failed; HttpTransfer only says it did
+        error: Option<&'static str>,
+    }
+
+    impl SlotWriter {
+        pub fn new(device: Arc<dyn BlockDevice>, keys: Vec<VerifyingKey>, running: Version) -> Self {
+            SlotWriter {
+                device,
+                keys,
+                running,
+                head: Vec::new(),
+                header: None,
+                hasher: Sha256::new(),
+                pending: Vec::new(),
+                written: 0,
+                next_block: 0,
+                error: None,
+            }
+        }
+
+        pub fn header(&self) -> Option<&BundleHeader> {
+            self.header.as_ref()
+        }
+
+        pub fn error(&self) -> Option<&'static str> {
+            self.error
+        }
+
+        fn take_header(&mut self, data: &[u8]) -> Result<usize, &'static str> {
+            let before = self.head.len();
+            self.head.extend_from_slice(data);
+            let Some(end) = self.head.windows(2).position(|window| window == b"\n\n") else {
+                return match self.head.len() > MAX_HEADER_LEN {
+                    true => Err("Update header too long"),
+                    false => Ok(data.len()),
+                };
+            };
+            let text = std::str::from_utf8(&self.head[..end + 1]).map_err(|_| "Malformed update header")?;
+            let header = BundleHeader::parse(text, &self.keys)?;
+            if header.version <= self.running {
+                return Err("Update is not newer than the running system");
+            }
+            let capacity = self.device.block_count() * self.device.block_size() as u64;
+            if header.size > capacity {
+                return Err("Update does not fit in the slot");
+            }
+            self.header = Some(header);
+            self.head.clear();
+            Ok(end + 2 - before)
+        }
+
+        fn take_image(&mut self, data: &[u8]) -> Result<(), &'static str> {
+            let size = self.header.as_ref().unwrap().size;
+            if self.written + data.len() as u64 > size {
+                return Err("Update image longer than its header says");
+            }
+            self.hasher.update(data);
+            self.written += data.len() as u64;
+            self.pending.extend_from_slice(data);
+            let block_size = self.device.block_size();
+            let whole = self.pending.len() / block_size * block_size;
+            if whole > 0 {
+                self.device.write_blocks(self.next_block, &self.pending[..whole])?;
+                self.next_block += (whole / block_size) as u64;
+                self.pending.drain(..whole);
+            }
+            Ok(())
+        }
+
+        fn accept(&mut self, data: &[u8]) -> Result<(), &'static str> {
+            let used = match self.header {
+                Some(_) => 0,
+                None => self.take_header(data)?,
+            };
+            match self.header.is_some() && used < data.len() {
+                true => self.take_image(&data[used..]),
+                false => Ok(()),
+            }
+        }
+
+        // Once the download is done: writes what is left, padded out to a
+        // block, and checks the image against its hash both as received
+        // and as read back from the slot
+        pub fn finish(mut self) -> Result<BundleHeader, &'static str> {
+            let header = self.header.clone().ok_or("Incomplete update header")?;
+            if self.written != header.size {
+                return Err("Update image shorter than its header says");
+            }
+            if <[u8; 32]>::from(self.hasher.finalize_reset()) != header.sha256 {
+                return Err("Update image hash mismatch");
+            }
+            let block_size = self.device.block_size();
+            if !self.pending.is_empty() {
+                self.pending.resize(block_size, 0);
+                self.device.write_blocks(self.next_block, &self.pending)?;
+            }
+            let mut block = vec![0u8; block_size];
+            let mut remaining = header.size;
+            for lba in 0..header.size.div_ceil(block_size as u64) {
+                self.device.read_blocks(lba, &mut block)?;
+                let used = remaining.min(block_size as u64) as usize;
+                self.hasher.update(&block[..used]);
+                remaining -= used as u64;
+            }
+            match <[u8; 32]>::from(self.hasher.finalize()) == header.sha256 {
+                true => Ok(header),
+                false => Err("Slot read back does not match the update"),
+            }
+        }
+    }
+
+    impl Write for SlotWriter {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            if let Some(error) = self.error {
+                return Err(io::Error::other(error));
+            }
+            self.accept(data).map_err(|error| {
+                self.error = Some(error);
+                io::Error::other(error)
+            })?;
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum UpdateStatus {
+        Idle,
+        Downloading { received: u64, total: Option<u64> },
+        // Written and set to boot next, on trial until confirmed
+        Ready(Version),
+    }
+
+    // Downloads signed bundles into whichever slot is not running and
+    // hands them to vxboot to try on the next boot. Bundles are checked
+    // end to end, so plain HTTP mirrors are as safe as HTTPS ones, which
+    // work once the HTTP client has TLS.
+    pub struct UpdateService {
+        root: String,
+        keys: Vec<VerifyingKey>,
+        running: Slot,
+        version: Version,
+        slots: [Arc<dyn BlockDevice>; 2],
+        transfer: Option<HttpTransfer<SlotWriter>>,
+        ready: Option<Version>,
+    }
+
+    impl UpdateService {
+        // The slot control lives under root; slots are the A and B
+        // system partitions
+        pub fn new(root: &str, keys: Vec<VerifyingKey>, running: Slot, version: Version, slots: [Arc<dyn BlockDevice>; 2]) -> Self {
+            UpdateService {
+                root: root.to_string(),
+                keys,
+                running,
+                version,
+                slots,
+                transfer: None,
+                ready: None,
+            }
+        }
+
+        pub fn target(&self) -> Slot {
+            self.running.other()
+        }
+
+        pub fn status(&self) -> UpdateStatus {
+            match (&self.transfer, self.ready) {
+                (Some(transfer), _) => UpdateStatus::Downloading {
+                    received: transfer.received(),
+                    total: transfer.total_length(),
+                },
+                (None, Some(version)) => UpdateStatus::Ready(version),
+                (None, None) => UpdateStatus::Idle,
+            }
+        }
+
+        // The other slot is what a failed boot falls back to, so it is only
+        // overwritten once the running one has proved itself
+        pub fn start(&mut self, client: &HttpClient, set: &mut SocketSet, url: &str) -> Result<(), &'static str> {
+            if self.transfer.is_some() {
+                return Err("Update already in progress");
+            }
+            let mut control = BootControl::load(&self.root).map_err(|_| "Failed to read slot control")?;
+            if !control.state(self.running).successful {
+                return Err("Running slot not yet marked successful");
+            }
+            control.mark_unbootable(self.target());
+            control.save(&self.root).map_err(|_| "Failed to write slot control")?;
+            self.ready = None;
+            let writer = SlotWriter::new(Arc::clone(&self.slots[self.target() as usize]), self.keys.clone(), self.version);
+            self.transfer = Some(client.get(set, url, writer)?);
+            log::info!("Downloading update from {} to slot {}", url, self.target().name());
+            Ok(())
+        }
+
+        // A failed update leaves the target slot unbootable and the
+        // running one as it was
+        pub fn poll(&mut self, set: &mut SocketSet) -> Result<UpdateStatus, &'static str> {
+            let Some(transfer) = self.transfer.as_mut() else {
+                return Ok(self.status());
+            };
+            let status = transfer.poll(set);
+            if let Ok(TransferStatus::InProgress) = status {
+                return Ok(self.status());
+            }
+            let transfer = self.transfer.take().unwrap();
+            let response = transfer.response().map(|response| response.status);
+            let writer = transfer.into_sink();
+            if let Err(error) = status {
+                return Err(writer.error().unwrap_or(error));
+            }
+            if !response.is_some_and(|status| (200..300).contains(&status)) {
+                return Err("Update server refused the download");
+            }
+            let header = writer.finish()?;
+            let mut control = BootControl::load(&self.root).map_err(|_| "Failed to read slot control")?;
+            control.set_active(self.target());
+            control.save(&self.root).map_err(|_| "Failed to write slot control")?;
+            log::info!("Update to {} written to slot {}, reboot to try it", header.version, self.target().name());
+            self.ready = Some(header.version);
+            Ok(self.status())
+        }
+    }
+}
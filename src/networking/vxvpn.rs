@@ -1,27 +1,1304 @@
 pub mod vxvpn {
-    pub fn init() {
-        println!("Initializing VXVPN...");
-        // Initialize the VXVPN system
+    use crate::fib::fib::TABLE_MAIN;
+    use crate::netdev::netdev::{MacAddress, NetDevice};
+    use crate::packet::packet::{build_ethernet, EthernetHeader, ETHERNET_HEADER_LEN, ETHERTYPE_IPV4, ETHERTYPE_IPV6};
+    use crate::udp::udp::{UdpSocketHandle, UdpStack};
+    use crate::vxnet_core::vxnet_core::{InterfaceId, IpCidr, NetStack, Route, RouteProtocol};
+    use blake2::digest::consts::U16;
+    use blake2::digest::{Digest, Mac};
+    use blake2::{Blake2s256, Blake2sMac};
+    use chacha20poly1305::aead::{Aead, Payload};
+    use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce, XChaCha20Poly1305, XNonce};
+    use hmac::SimpleHmac;
+    use vaelix_core::keyring::keyring::Secret;
+    use std::collections::{BTreeMap, VecDeque};
+    use std::fmt;
+    use std::net::{IpAddr, SocketAddr};
+    use std::sync::{Arc, Mutex};
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use x25519_dalek::{PublicKey, StaticSecret};
+
+    pub const DEFAULT_PORT: u16 = 51820;
+    // Below DHCP's default so a 0.0.0.0/0 allowed-ip takes over the uplink
+    pub const ROUTE_METRIC: u32 = 50;
+    // 1500 minus the outer IPv6 (40), UDP (8) and transport header (32)
+    pub const WG_MTU: usize = 1420;
+
+    const CONSTRUCTION: &[u8] = b"Noise_IKpsk2_25519_ChaChaPoly_BLAKE2s";
+    const IDENTIFIER: &[u8] = b"WireGuard v1 zx2c4 Jason@zx2c4.com";
+    const LABEL_MAC1: &[u8] = b"mac1----";
+    const LABEL_COOKIE: &[u8] = b"cookie--";
+
+    const MESSAGE_INITIATION: u8 = 1;
+    const MESSAGE_RESPONSE: u8 = 2;
+    const MESSAGE_COOKIE_REPLY: u8 = 3;
+    const MESSAGE_TRANSPORT: u8 = 4;
+    const INITIATION_LEN: usize = 148;
+    const RESPONSE_LEN: usize = 92;
+    const COOKIE_REPLY_LEN: usize = 64;
+    const TRANSPORT_HEADER_LEN: usize = 16;
+    const AEAD_TAG_LEN: usize = 16;
+
+    // Protocol timers from the WireGuard paper, in milliseconds
+    const REKEY_AFTER_MESSAGES: u64 = 1 << 60;
+    const REJECT_AFTER_MESSAGES: u64 = u64::MAX - (1 << 13);
+    const REKEY_AFTER_TIME_MS: u64 = 120_000;
+    const REJECT_AFTER_TIME_MS: u64 = 180_000;
+    const REKEY_ATTEMPT_TIME_MS: u64 = 90_000;
+    const REKEY_TIMEOUT_MS: u64 = 5_000;
+    const REKEY_JITTER_MS: u64 = 334;
+    const KEEPALIVE_TIMEOUT_MS: u64 = 10_000;
+    const COOKIE_SECRET_LIFETIME_MS: u64 = 120_000;
+
+    const MAX_QUEUED_PACKETS: usize = 1024;
+    // Handshakes per second accepted before initiators must prove their
+    // address with a cookie
+    const DEFAULT_HANDSHAKE_LIMIT: u32 = 64;
+    const REPLAY_WORDS: u64 = 32;
+
+    fn hash(parts: &[&[u8]]) -> [u8; 32] {
+        let mut hasher = Blake2s256::new();
+        for part in parts {
+            hasher.update(part);
+        }
+        hasher.finalize().into()
+    }
+
+    // Keyed BLAKE2s with a 128-bit output
+    fn mac(key: &[u8], parts: &[&[u8]]) -> [u8; 16] {
+        let mut mac = <Blake2sMac<U16> as Mac>::new_from_slice(key).expect("BLAKE2s key is at most 32 bytes");
+        for part in parts {
+            Mac::update(&mut mac, part);
+        }
+        mac.finalize().into_bytes().into()
+    }
+
+    fn hmac(key: &[u8], data: &[u8]) -> [u8; 32] {
+        let mut mac = <SimpleHmac<Blake2s256> as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+        Mac::update(&mut mac, data);
+        mac.finalize().into_bytes().into()
+    }
+
+    // HKDF over HMAC-BLAKE2s producing N chained outputs
+    fn kdf<const N: usize>(key: &[u8; 32], input: &[u8]) -> [[u8; 32]; N] {
+        let secret = hmac(key, input);
+        let mut outputs = [[0u8; 32]; N];
+        let mut previous = Vec::new();
+        for (index, output) in outputs.iter_mut().enumerate() {
+            previous.push(index as u8 + 1);
+            *output = hmac(&secret, &previous);
+            previous = output.to_vec();
+        }
+        outputs
+    }
+
+    fn counter_nonce(counter: u64) -> Nonce {
+        let mut nonce = [0u8; 12];
+        nonce[4..].copy_from_slice(&counter.to_le_bytes());
+        Nonce::clone_from_slice(&nonce)
+    }
+
+    fn aead_seal(key: &[u8; 32], counter: u64, plaintext: &[u8], aad: &[u8]) -> Vec<u8> {
+        ChaCha20Poly1305::new(Key::from_slice(key))
+            .encrypt(&counter_nonce(counter), Payload { msg: plaintext, aad })
+            .expect("ChaCha20-Poly1305 sealing cannot fail")
+    }
+
+    fn aead_open(key: &[u8; 32], counter: u64, ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>, &'static str> {
+        ChaCha20Poly1305::new(Key::from_slice(key))
+            .decrypt(&counter_nonce(counter), Payload { msg: ciphertext, aad })
+            .map_err(|_| "Handshake authentication failed")
+    }
+
+    fn dh(secret: &StaticSecret, public: &[u8; 32]) -> Result<[u8; 32], &'static str> {
+        let shared = secret.diffie_hellman(&PublicKey::from(*public));
+        if !shared.was_contributory() {
+            return Err("Invalid public key");
+        }
+        Ok(shared.to_bytes())
+    }
+
+    fn static_secret(private_key: &Secret) -> Result<StaticSecret, &'static str> {
+        let bytes: [u8; 32] = private_key.expose().try_into().map_err(|_| "Invalid key length")?;
+        Ok(StaticSecret::from(bytes))
+    }
+
+    fn random_bytes<const N: usize>() -> [u8; N] {
+        let mut bytes = [0u8; N];
+        getrandom::getrandom(&mut bytes).expect("System entropy source unavailable");
+        bytes
+    }
+
+    fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+    }
+
+    fn read_u32(data: &[u8]) -> u32 {
+        u32::from_le_bytes([data[0], data[1], data[2], data[3]])
+    }
+
+    // TAI64N label of the current wall-clock time
+    fn tai64n() -> [u8; 12] {
+        let elapsed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut timestamp = [0u8; 12];
+        timestamp[..8].copy_from_slice(&(0x4000_0000_0000_000A + elapsed.as_secs()).to_be_bytes());
+        timestamp[8..].copy_from_slice(&elapsed.subsec_nanos().to_be_bytes());
+        timestamp
+    }
+
+    fn packet_source(packet: &[u8]) -> Option<IpAddr> {
+        match packet.first()? >> 4 {
+            4 if packet.len() >= 20 => Some(IpAddr::from(<[u8; 4]>::try_from(&packet[12..16]).ok()?)),
+            6 if packet.len() >= 40 => Some(IpAddr::from(<[u8; 16]>::try_from(&packet[8..24]).ok()?)),
+            _ => None,
+        }
+    }
+
+    fn packet_destination(packet: &[u8]) -> Option<IpAddr> {
+        match packet.first()? >> 4 {
+            4 if packet.len() >= 20 => Some(IpAddr::from(<[u8; 4]>::try_from(&packet[16..20]).ok()?)),
+            6 if packet.len() >= 40 => Some(IpAddr::from(<[u8; 16]>::try_from(&packet[24..40]).ok()?)),
+            _ => None,
+        }
+    }
+
+    // Length from the IP header; anything past it is transport padding
+    fn packet_len(packet: &[u8]) -> Option<usize> {
+        match packet.first()? >> 4 {
+            4 if packet.len() >= 20 => Some(u16::from_be_bytes([packet[2], packet[3]]) as usize),
+            6 if packet.len() >= 40 => Some(40 + u16::from_be_bytes([packet[4], packet[5]]) as usize),
+            _ => None,
+        }
+    }
+
+    const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    // Curve25519 public key in the form wg(8) prints and accepts. Private
+    // keys are only ever held as a Secret, so they are wiped when dropped.
+    #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct WgKey(pub [u8; 32]);
+
+    impl WgKey {
+        pub fn generate_private() -> Secret {
+            Secret::from_slice(StaticSecret::from(random_bytes::<32>()).as_bytes())
+        }
+
+        // The public half of a private key
+        pub fn from_private(private_key: &Secret) -> Result<WgKey, &'static str> {
+            Ok(WgKey(PublicKey::from(&static_secret(private_key)?).to_bytes()))
+        }
+
+        pub fn to_base64(&self) -> String {
+            let mut text = String::with_capacity(44);
+            for chunk in self.0.chunks(3) {
+                let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+                let value = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+                for position in 0..4 {
+                    if position <= chunk.len() {
+                        text.push(BASE64[(value >> (18 - 6 * position) & 0x3F) as usize] as char);
+                    } else {
+                        text.push('=');
+                    }
+                }
+            }
+            text
+        }
+
+        pub fn from_base64(text: &str) -> Result<WgKey, &'static str> {
+            let text = text.trim().as_bytes();
+            if text.len() != 44 || text[43] != b'=' {
+                return Err("Key must be 44 characters of base64");
+            }
+            let mut value: u64 = 0;
+            let mut bits = 0;
+            let mut key = Vec::with_capacity(33);
+            for &character in &text[..43] {
+                let digit = BASE64.iter().position(|&c| c == character).ok_or("Invalid base64 in key")?;
+                value = (value << 6) | digit as u64;
+                bits += 6;
+                if bits >= 8 {
+                    bits -= 8;
+                    key.push((value >> bits) as u8);
+                }
+            }
+            key.truncate(32);
+            Ok(WgKey(key.try_into().map_err(|_| "Invalid key length")?))
+        }
+    }
+
+    impl fmt::Debug for WgKey {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "WgKey({})", self.to_base64())
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct PeerConfig {
+        pub public_key: WgKey,
+        pub preshared_key: Option<Secret>,
+        // Learned from authenticated traffic when not configured
+        pub endpoint: Option<SocketAddr>,
+        // Cryptokey routing: what the peer may send from and is sent to
+        pub allowed_ips: Vec<IpCidr>,
+        // Seconds between keepalives for peers behind NAT
+        pub persistent_keepalive: Option<u64>,
+    }
+
+    impl PeerConfig {
+        pub fn new(public_key: WgKey) -> Self {
+            PeerConfig {
+                public_key,
+                preshared_key: None,
+                endpoint: None,
+                allowed_ips: Vec::new(),
+                persistent_keepalive: None,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct PeerStatus {
+        pub public_key: WgKey,
+        pub endpoint: Option<SocketAddr>,
+        pub allowed_ips: Vec<IpCidr>,
+        pub last_handshake: Option<u64>,
+        pub rx_bytes: u64,
+        pub tx_bytes: u64,
+        pub has_session: bool,
+    }
+
+    // Sliding window over received counters (RFC 6479)
+    struct ReplayWindow {
+        greatest: Option<u64>,
+        bitmap: [u64; REPLAY_WORDS as usize],
+    }
+
+    impl ReplayWindow {
+        fn new() -> Self {
+            ReplayWindow {
+                greatest: None,
+                bitmap: [0; REPLAY_WORDS as usize],
+            }
+        }
+
+        fn slot(counter: u64) -> (usize, u64) {
+            (((counter / 64) % REPLAY_WORDS) as usize, 1 << (counter % 64))
+        }
+
+        fn check(&self, counter: u64) -> bool {
+            if counter >= REJECT_AFTER_MESSAGES {
+                return false;
+            }
+            match self.greatest {
+                None => true,
+                Some(greatest) if counter > greatest => true,
+                Some(greatest) if greatest - counter >= (REPLAY_WORDS - 1) * 64 => false,
+                Some(_) => {
+                    let (word, bit) = Self::slot(counter);
+                    self.bitmap[word] & bit == 0
+                }
+            }
+        }
+
+        fn update(&mut self, counter: u64) -> bool {
+            if !self.check(counter) {
+                return false;
+            }
+            if self.greatest.is_none_or(|greatest| counter > greatest) {
+                if let Some(greatest) = self.greatest {
+                    let current = greatest / 64;
+                    let advance = (counter / 64 - current).min(REPLAY_WORDS);
+                    for step in 1..=advance {
+                        self.bitmap[((current + step) % REPLAY_WORDS) as usize] = 0;
+                    }
+                }
+                self.greatest = Some(counter);
+            }
+            let (word, bit) = Self::slot(counter);
+            self.bitmap[word] |= bit;
+            true
+        }
+    }
+
+    // Transport keys from one completed handshake
+    struct Session {
+        local_index: u32,
+        remote_index: u32,
+        sender: ChaCha20Poly1305,
+        receiver: ChaCha20Poly1305,
+        sending_counter: u64,
+        replay: ReplayWindow,
+        created: u64,
+        initiator: bool,
+    }
+
+    impl Session {
+        fn new(local_index: u32, remote_index: u32, keys: (&[u8; 32], &[u8; 32]), now: u64, initiator: bool) -> Self {
+            Session {
+                local_index,
+                remote_index,
+                sender: ChaCha20Poly1305::new(Key::from_slice(keys.0)),
+                receiver: ChaCha20Poly1305::new(Key::from_slice(keys.1)),
+                sending_counter: 0,
+                replay: ReplayWindow::new(),
+                created: now,
+                initiator,
+            }
+        }
+
+        fn expired(&self, now: u64) -> bool {
+            now.saturating_sub(self.created) >= REJECT_AFTER_TIME_MS
+        }
+
+        fn can_send(&self, now: u64) -> bool {
+            !self.expired(now) && self.sending_counter < REJECT_AFTER_MESSAGES
+        }
+    }
+
+    // Initiator state between sending an initiation and its response
+    struct Handshake {
+        local_index: u32,
+        chaining_key: [u8; 32],
+        hash: [u8; 32],
+        ephemeral: StaticSecret,
+        // Cookie replies are bound to the mac1 of the message they answer
+        mac1: [u8; 16],
+    }
+
+    // Our static key plus the values derived from it
+    struct Identity {
+        private_key: StaticSecret,
+        public_key: WgKey,
+        mac1_key: [u8; 32],
+        cookie_key: [u8; 32],
+    }
+
+    impl Identity {
+        fn new(private_key: &Secret) -> Result<Self, &'static str> {
+            let public_key = WgKey::from_private(private_key)?;
+            Ok(Identity {
+                private_key: static_secret(private_key)?,
+                public_key,
+                mac1_key: hash(&[LABEL_MAC1, &public_key.0]),
+                cookie_key: hash(&[LABEL_COOKIE, &public_key.0]),
+            })
+        }
+
+        // Noise IK state after mixing in the responder's static key
+        fn initial_state(responder: &WgKey) -> ([u8; 32], [u8; 32]) {
+            let chaining_key = hash(&[CONSTRUCTION]);
+            let hash_value = hash(&[&hash(&[&chaining_key, IDENTIFIER]), &responder.0]);
+            (chaining_key, hash_value)
+        }
+    }
+
+    struct Peer {
+        config: PeerConfig,
+        static_shared: [u8; 32],
+        mac1_key: [u8; 32],
+        cookie_key: [u8; 32],
+        handshake: Option<Handshake>,
+        handshake_started: Option<u64>,
+        last_initiation: Option<u64>,
+        retry_jitter: u64,
+        last_timestamp_sent: [u8; 12],
+        greatest_timestamp: [u8; 12],
+        cookie: Option<([u8; 16], u64)>,
+        current: Option<Session>,
+        previous: Option<Session>,
+        // Responder session waiting for the initiator's first message
+        next: Option<Session>,
+        queue: VecDeque<Vec<u8>>,
+        last_sent: Option<u64>,
+        keepalive_due: Option<u64>,
+        awaiting_reply: Option<u64>,
+        last_handshake: Option<u64>,
+        rx_bytes: u64,
+        tx_bytes: u64,
+        // Indices no longer in use, for the device to unregister
+        retired: Vec<u32>,
     }
 
-    pub fn connect_vpn(server: &str) {
-        println!("Connecting to VPN server: {}", server);
-        // Connect to a VPN server
+    impl Peer {
+        fn new(identity: &Identity, config: PeerConfig) -> Result<Self, &'static str> {
+            let static_shared = dh(&identity.private_key, &config.public_key.0)?;
+            Ok(Peer {
+                static_shared,
+                mac1_key: hash(&[LABEL_MAC1, &config.public_key.0]),
+                cookie_key: hash(&[LABEL_COOKIE, &config.public_key.0]),
+                config,
+                handshake: None,
+                handshake_started: None,
+                last_initiation: None,
+                retry_jitter: 0,
+                last_timestamp_sent: [0; 12],
+                greatest_timestamp: [0; 12],
+                cookie: None,
+                current: None,
+                previous: None,
+                next: None,
+                queue: VecDeque::new(),
+                last_sent: None,
+                keepalive_due: None,
+                awaiting_reply: None,
+                last_handshake: None,
+                rx_bytes: 0,
+                tx_bytes: 0,
+                retired: Vec::new(),
+            })
+        }
+
+        fn status(&self) -> PeerStatus {
+            PeerStatus {
+                public_key: self.config.public_key,
+                endpoint: self.config.endpoint,
+                allowed_ips: self.config.allowed_ips.clone(),
+                last_handshake: self.last_handshake,
+                rx_bytes: self.rx_bytes,
+                tx_bytes: self.tx_bytes,
+                has_session: self.current.is_some(),
+            }
+        }
+
+        fn preshared_key(&self) -> &[u8] {
+            self.config.preshared_key.as_ref().map_or(&[0; 32], |key| key.expose())
+        }
+
+        fn allows(&self, address: &IpAddr) -> bool {
+            self.config.allowed_ips.iter().any(|cidr| cidr.contains(address))
+        }
+
+        fn retire(&mut self, session: Option<Session>) {
+            if let Some(session) = session {
+                self.retired.push(session.local_index);
+            }
+        }
+
+        fn install_current(&mut self, session: Session) {
+            let previous = self.previous.take();
+            self.retire(previous);
+            self.previous = self.current.replace(session);
+        }
+
+        // Timestamps must strictly increase even if the clock does not
+        fn next_timestamp(&mut self) -> [u8; 12] {
+            let mut timestamp = tai64n();
+            if timestamp <= self.last_timestamp_sent {
+                timestamp = self.last_timestamp_sent;
+                let nanos = u32::from_be_bytes([timestamp[8], timestamp[9], timestamp[10], timestamp[11]]);
+                timestamp[8..].copy_from_slice(&(nanos + 1).to_be_bytes());
+            }
+            self.last_timestamp_sent = timestamp;
+            timestamp
+        }
+
+        // mac1 proves knowledge of the recipient's public key; mac2 echoes
+        // a cookie the recipient handed out, if we hold a fresh one
+        fn append_macs(&self, message: &mut Vec<u8>, now: u64) -> [u8; 16] {
+            let mac1 = mac(&self.mac1_key, &[message]);
+            message.extend_from_slice(&mac1);
+            let mac2 = match self.cookie {
+                Some((cookie, received)) if now.saturating_sub(received) < COOKIE_SECRET_LIFETIME_MS => {
+                    mac(&cookie, &[message])
+                }
+                _ => [0; 16],
+            };
+            message.extend_from_slice(&mac2);
+            mac1
+        }
+
+        fn create_initiation(&mut self, identity: &Identity, local_index: u32, now: u64) -> Result<Vec<u8>, &'static str> {
+            let ephemeral = StaticSecret::from(random_bytes::<32>());
+            let ephemeral_public = PublicKey::from(&ephemeral).to_bytes();
+            let (chaining_key, hash_value) = Identity::initial_state(&self.config.public_key);
+
+            let [chaining_key] = kdf(&chaining_key, &ephemeral_public);
+            let hash_value = hash(&[&hash_value, &ephemeral_public]);
+            let [chaining_key, key] = kdf(&chaining_key, &dh(&ephemeral, &self.config.public_key.0)?);
+            let encrypted_static = aead_seal(&key, 0, &identity.public_key.0, &hash_value);
+            let hash_value = hash(&[&hash_value, &encrypted_static]);
+            let [chaining_key, key] = kdf(&chaining_key, &self.static_shared);
+            let encrypted_timestamp = aead_seal(&key, 0, &self.next_timestamp(), &hash_value);
+            let hash_value = hash(&[&hash_value, &encrypted_timestamp]);
+
+            let mut message = Vec::with_capacity(INITIATION_LEN);
+            message.extend_from_slice(&[MESSAGE_INITIATION, 0, 0, 0]);
+            message.extend_from_slice(&local_index.to_le_bytes());
+            message.extend_from_slice(&ephemeral_public);
+            message.extend_from_slice(&encrypted_static);
+            message.extend_from_slice(&encrypted_timestamp);
+            let mac1 = self.append_macs(&mut message, now);
+
+            if let Some(old) = self.handshake.replace(Handshake {
+                local_index,
+                chaining_key,
+                hash: hash_value,
+                ephemeral,
+                mac1,
+            }) {
+                self.retired.push(old.local_index);
+            }
+            Ok(message)
+        }
+
+        // Builds a transport message with the current session; None when
+        // there is no usable session
+        fn encrypt(&mut self, packet: &[u8], now: u64) -> Option<Vec<u8>> {
+            let session = self.current.as_mut().filter(|session| session.can_send(now))?;
+            let mut plaintext = packet.to_vec();
+            plaintext.resize(packet.len().div_ceil(16) * 16, 0);
+            let counter = session.sending_counter;
+            session.sending_counter += 1;
+            let ciphertext = session.sender.encrypt(&counter_nonce(counter), plaintext.as_slice()).ok()?;
+
+            let mut message = Vec::with_capacity(TRANSPORT_HEADER_LEN + ciphertext.len());
+            message.extend_from_slice(&[MESSAGE_TRANSPORT, 0, 0, 0]);
+            message.extend_from_slice(&session.remote_index.to_le_bytes());
+            message.extend_from_slice(&counter.to_le_bytes());
+            message.extend_from_slice(&ciphertext);
+
+            self.last_sent = Some(now);
+            self.keepalive_due = None;
+            if !packet.is_empty() {
+                self.awaiting_reply.get_or_insert(now);
+            }
+            self.tx_bytes += message.len() as u64;
+            Some(message)
+        }
+
+        fn needs_rekey(&self, now: u64) -> bool {
+            self.handshake.is_none()
+                && self.current.as_ref().is_some_and(|session| {
+                    (session.initiator && now.saturating_sub(session.created) >= REKEY_AFTER_TIME_MS)
+                        || session.sending_counter >= REKEY_AFTER_MESSAGES
+                })
+        }
+
+        // Keys are erased once no handshake has refreshed them for a while
+        fn expire_sessions(&mut self, now: u64) {
+            let stale = |session: &Option<Session>| {
+                session
+                    .as_ref()
+                    .is_some_and(|session| now.saturating_sub(session.created) >= REJECT_AFTER_TIME_MS * 3)
+            };
+            for slot in [&mut self.current, &mut self.previous, &mut self.next] {
+                if stale(slot) {
+                    if let Some(session) = slot.take() {
+                        self.retired.push(session.local_index);
+                    }
+                }
+            }
+        }
+    }
+
+    // WireGuard protocol engine for one interface: handshakes, transport
+    // encryption, cryptokey routing and timers. UDP I/O happens outside,
+    // through receive() and take_outbound().
+    pub struct WireGuard {
+        identity: Identity,
+        listen_port: u16,
+        peers: BTreeMap<WgKey, Peer>,
+        indices: BTreeMap<u32, WgKey>,
+        cookie_secret: [u8; 32],
+        cookie_secret_created: u64,
+        handshake_limit: u32,
+        load_window: u64,
+        handshakes_in_window: u32,
+        outbound: VecDeque<(SocketAddr, Vec<u8>)>,
+        now: u64,
+    }
+
+    impl WireGuard {
+        // The private key is usually one from the kernel keyring
+        pub fn new(private_key: &Secret, listen_port: u16) -> Result<Self, &'static str> {
+            Ok(WireGuard {
+                identity: Identity::new(private_key)?,
+                listen_port,
+                peers: BTreeMap::new(),
+                indices: BTreeMap::new(),
+                cookie_secret: random_bytes(),
+                cookie_secret_created: 0,
+                handshake_limit: DEFAULT_HANDSHAKE_LIMIT,
+                load_window: 0,
+                handshakes_in_window: 0,
+                outbound: VecDeque::new(),
+                now: 0,
+            })
+        }
+
+        pub fn public_key(&self) -> WgKey {
+            self.identity.public_key
+        }
+
+        pub fn listen_port(&self) -> u16 {
+            self.listen_port
+        }
+
+        pub fn add_peer(&mut self, config: PeerConfig) -> Result<(), &'static str> {
+            if config.public_key == self.identity.public_key {
+                return Err("Peer key is the interface's own key");
+            }
+            if self.peers.contains_key(&config.public_key) {
+                return Err("Peer already exists");
+            }
+            if config.preshared_key.as_ref().is_some_and(|key| key.len() != 32) {
+                return Err("Invalid preshared key length");
+            }
+            let peer = Peer::new(&self.identity, config)?;
+            self.peers.insert(peer.config.public_key, peer);
+            Ok(())
+        }
+
+        pub fn remove_peer(&mut self, public_key: &WgKey) -> Result<(), &'static str> {
+            self.peers.remove(public_key).ok_or("Peer not found")?;
+            self.indices.retain(|_, owner| owner != public_key);
+            Ok(())
+        }
+
+        pub fn peer(&self, public_key: &WgKey) -> Option<PeerStatus> {
+            self.peers.get(public_key).map(Peer::status)
+        }
+
+        pub fn peers(&self) -> Vec<PeerStatus> {
+            self.peers.values().map(Peer::status).collect()
+        }
+
+        // Handshake messages per second before cookies are required
+        pub fn set_handshake_limit(&mut self, per_second: u32) {
+            self.handshake_limit = per_second;
+        }
+
+        pub fn take_outbound(&mut self) -> Option<(SocketAddr, Vec<u8>)> {
+            self.outbound.pop_front()
+        }
+
+        // Longest allowed-ips match across all peers
+        fn peer_for(&self, destination: &IpAddr) -> Option<WgKey> {
+            self.peers
+                .values()
+                .flat_map(|peer| {
+                    peer.config
+                        .allowed_ips
+                        .iter()
+                        .filter(|cidr| cidr.contains(destination))
+                        .map(move |cidr| (cidr.prefix_len, peer.config.public_key))
+                })
+                .max_by_key(|(prefix_len, _)| *prefix_len)
+                .map(|(_, key)| key)
+        }
+
+        fn allocate_index(&mut self, owner: WgKey) -> u32 {
+            loop {
+                let index = u32::from_le_bytes(random_bytes());
+                if let std::collections::btree_map::Entry::Vacant(entry) = self.indices.entry(index) {
+                    entry.insert(owner);
+                    return index;
+                }
+            }
+        }
+
+        fn release_retired(&mut self, key: &WgKey) {
+            if let Some(peer) = self.peers.get_mut(key) {
+                for index in peer.retired.drain(..) {
+                    self.indices.remove(&index);
+                }
+            }
+        }
+
+        // Encrypts an IP packet from the stack for the peer that owns its
+        // destination, queueing it behind a handshake if needed
+        pub fn send_packet(&mut self, packet: &[u8]) -> Result<(), &'static str> {
+            let now = self.now;
+            let destination = packet_destination(packet).ok_or("Malformed IP packet")?;
+            let key = self.peer_for(&destination).ok_or("No peer for destination")?;
+            let peer = self.peers.get_mut(&key).ok_or("Peer not found")?;
+            let endpoint = peer.config.endpoint.ok_or("Peer has no endpoint")?;
+            if let Some(message) = peer.encrypt(packet, now) {
+                let rekey = peer.needs_rekey(now);
+                self.outbound.push_back((endpoint, message));
+                if rekey {
+                    self.start_handshake(&key, now)?;
+                }
+                return Ok(());
+            }
+            if peer.queue.len() >= MAX_QUEUED_PACKETS {
+                peer.queue.pop_front();
+            }
+            peer.queue.push_back(packet.to_vec());
+            self.start_handshake(&key, now)
+        }
+
+        // Starts a handshake unless one is already in flight; the retry
+        // timer takes care of lost messages
+        fn start_handshake(&mut self, key: &WgKey, now: u64) -> Result<(), &'static str> {
+            let peer = self.peers.get_mut(key).ok_or("Peer not found")?;
+            if peer.handshake.is_some() {
+                return Ok(());
+            }
+            peer.handshake_started = Some(now);
+            self.send_initiation(key, now)
+        }
+
+        fn send_initiation(&mut self, key: &WgKey, now: u64) -> Result<(), &'static str> {
+            let endpoint = self
+                .peers
+                .get(key)
+                .ok_or("Peer not found")?
+                .config
+                .endpoint
+                .ok_or("Peer has no endpoint")?;
+            let index = self.allocate_index(*key);
+            let peer = self.peers.get_mut(key).ok_or("Peer not found")?;
+            let message = peer.create_initiation(&self.identity, index, now)?;
+            peer.last_initiation = Some(now);
+            peer.retry_jitter = u16::from_le_bytes(random_bytes()) as u64 % REKEY_JITTER_MS;
+            self.outbound.push_back((endpoint, message));
+            self.release_retired(key);
+            Ok(())
+        }
+
+        fn send_keepalive(&mut self, key: &WgKey, now: u64) {
+            let Some(peer) = self.peers.get_mut(key) else {
+                return;
+            };
+            let Some(endpoint) = peer.config.endpoint else {
+                return;
+            };
+            if let Some(message) = peer.encrypt(&[], now) {
+                self.outbound.push_back((endpoint, message));
+            }
+        }
+
+        fn flush_queue(&mut self, key: &WgKey, now: u64) {
+            let Some(peer) = self.peers.get_mut(key) else {
+                return;
+            };
+            let Some(endpoint) = peer.config.endpoint else {
+                return;
+            };
+            while let Some(packet) = peer.queue.pop_front() {
+                match peer.encrypt(&packet, now) {
+                    Some(message) => self.outbound.push_back((endpoint, message)),
+                    None => break,
+                }
+            }
+        }
+
+        // Handles one UDP payload from the network; returns the decrypted
+        // IP packet when it carried one
+        pub fn receive(&mut self, source: SocketAddr, data: &[u8], now: u64) -> Result<Option<Vec<u8>>, &'static str> {
+            self.now = now;
+            if data.len() < 4 || data[1..4] != [0, 0, 0] {
+                return Err("Malformed WireGuard message");
+            }
+            match (data[0], data.len()) {
+                (MESSAGE_INITIATION, INITIATION_LEN) => {
+                    if self.check_macs(source, data, now)? {
+                        self.handle_initiation(source, data, now)?;
+                    }
+                    Ok(None)
+                }
+                (MESSAGE_RESPONSE, RESPONSE_LEN) => {
+                    if self.check_macs(source, data, now)? {
+                        self.handle_response(source, data, now)?;
+                    }
+                    Ok(None)
+                }
+                (MESSAGE_COOKIE_REPLY, COOKIE_REPLY_LEN) => {
+                    self.handle_cookie_reply(data, now)?;
+                    Ok(None)
+                }
+                (MESSAGE_TRANSPORT, length) if length >= TRANSPORT_HEADER_LEN + AEAD_TAG_LEN => {
+                    self.handle_transport(source, data, now)
+                }
+                _ => Err("Malformed WireGuard message"),
+            }
+        }
+
+        fn under_load(&mut self, now: u64) -> bool {
+            if now.saturating_sub(self.load_window) >= 1000 {
+                self.load_window = now;
+                self.handshakes_in_window = 0;
+            }
+            self.handshakes_in_window = self.handshakes_in_window.saturating_add(1);
+            self.handshakes_in_window > self.handshake_limit
+        }
+
+        // Cookies bind a handshake to the source address it came from
+        fn cookie_for(&mut self, source: &SocketAddr, now: u64) -> [u8; 16] {
+            if now.saturating_sub(self.cookie_secret_created) >= COOKIE_SECRET_LIFETIME_MS {
+                self.cookie_secret = random_bytes();
+                self.cookie_secret_created = now;
+            }
+            let address = match source.ip() {
+                IpAddr::V4(address) => address.octets().to_vec(),
+                IpAddr::V6(address) => address.octets().to_vec(),
+            };
+            mac(&self.cookie_secret, &[&address, &source.port().to_le_bytes()])
+        }
+
+        // Verifies mac1 and, when under load, mac2. Handshakes without a
+        // valid cookie are answered with one instead of being processed.
+        fn check_macs(&mut self, source: SocketAddr, message: &[u8], now: u64) -> Result<bool, &'static str> {
+            let mac1_offset = message.len() - 32;
+            let mac1 = mac(&self.identity.mac1_key, &[&message[..mac1_offset]]);
+            if !constant_time_eq(&mac1, &message[mac1_offset..mac1_offset + 16]) {
+                return Err("Bad handshake mac1");
+            }
+            if !self.under_load(now) {
+                return Ok(true);
+            }
+            let cookie = self.cookie_for(&source, now);
+            let mac2 = mac(&cookie, &[&message[..mac1_offset + 16]]);
+            if constant_time_eq(&mac2, &message[mac1_offset + 16..]) {
+                return Ok(true);
+            }
+            let nonce: [u8; 24] = random_bytes();
+            let encrypted = XChaCha20Poly1305::new(Key::from_slice(&self.identity.cookie_key))
+                .encrypt(
+                    XNonce::from_slice(&nonce),
+                    Payload {
+                        msg: &cookie,
+                        aad: &message[mac1_offset..mac1_offset + 16],
+                    },
+                )
+                .map_err(|_| "Cookie encryption failed")?;
+            let mut reply = Vec::with_capacity(COOKIE_REPLY_LEN);
+            reply.extend_from_slice(&[MESSAGE_COOKIE_REPLY, 0, 0, 0]);
+            reply.extend_from_slice(&message[4..8]);
+            reply.extend_from_slice(&nonce);
+            reply.extend_from_slice(&encrypted);
+            self.outbound.push_back((source, reply));
+            Ok(false)
+        }
+
+        fn handle_initiation(&mut self, source: SocketAddr, message: &[u8], now: u64) -> Result<(), &'static str> {
+            let sender = read_u32(&message[4..8]);
+            let ephemeral: [u8; 32] = message[8..40].try_into().map_err(|_| "Malformed initiation")?;
+            let encrypted_static = &message[40..88];
+            let encrypted_timestamp = &message[88..116];
+
+            let (chaining_key, hash_value) = Identity::initial_state(&self.identity.public_key);
+            let [chaining_key] = kdf(&chaining_key, &ephemeral);
+            let hash_value = hash(&[&hash_value, &ephemeral]);
+            let [chaining_key, key] = kdf(&chaining_key, &dh(&self.identity.private_key, &ephemeral)?);
+            let peer_public = aead_open(&key, 0, encrypted_static, &hash_value)?;
+            let hash_value = hash(&[&hash_value, encrypted_static]);
+            let peer_key = WgKey(peer_public.try_into().map_err(|_| "Malformed initiation")?);
+            let peer = self.peers.get(&peer_key).ok_or("Handshake from unknown peer")?;
+            let [chaining_key, key] = kdf(&chaining_key, &peer.static_shared);
+            let timestamp = aead_open(&key, 0, encrypted_timestamp, &hash_value)?;
+            let hash_value = hash(&[&hash_value, encrypted_timestamp]);
+            let timestamp: [u8; 12] = timestamp.try_into().map_err(|_| "Malformed initiation")?;
+            if timestamp <= peer.greatest_timestamp {
+                return Err("Replayed handshake initiation");
+            }
+
+            // Response
+            let local_index = self.allocate_index(peer_key);
+            let peer = self.peers.get_mut(&peer_key).ok_or("Peer not found")?;
+            peer.greatest_timestamp = timestamp;
+            let responder_ephemeral = StaticSecret::from(random_bytes::<32>());
+            let responder_public = PublicKey::from(&responder_ephemeral).to_bytes();
+            let [chaining_key] = kdf(&chaining_key, &responder_public);
+            let hash_value = hash(&[&hash_value, &responder_public]);
+            let [chaining_key] = kdf(&chaining_key, &dh(&responder_ephemeral, &ephemeral)?);
+            let [chaining_key] = kdf(&chaining_key, &dh(&responder_ephemeral, &peer_key.0)?);
+            let [chaining_key, tau, key] = kdf(&chaining_key, peer.preshared_key());
+            let hash_value = hash(&[&hash_value, &tau]);
+            let encrypted_empty = aead_seal(&key, 0, &[], &hash_value);
+            let [receiving_key, sending_key] = kdf(&chaining_key, &[]);
+
+            let mut response = Vec::with_capacity(RESPONSE_LEN);
+            response.extend_from_slice(&[MESSAGE_RESPONSE, 0, 0, 0]);
+            response.extend_from_slice(&local_index.to_le_bytes());
+            response.extend_from_slice(&sender.to_le_bytes());
+            response.extend_from_slice(&responder_public);
+            response.extend_from_slice(&encrypted_empty);
+            peer.append_macs(&mut response, now);
+
+            let session = Session::new(local_index, sender, (&sending_key, &receiving_key), now, false);
+            let replaced = peer.next.replace(session);
+            peer.retire(replaced);
+            peer.config.endpoint = Some(source);
+            peer.tx_bytes += response.len() as u64;
+            self.outbound.push_back((source, response));
+            self.release_retired(&peer_key);
+            Ok(())
+        }
+
+        fn handle_response(&mut self, source: SocketAddr, message: &[u8], now: u64) -> Result<(), &'static str> {
+            let sender = read_u32(&message[4..8]);
+            let receiver = read_u32(&message[8..12]);
+            let responder_public: [u8; 32] = message[12..44].try_into().map_err(|_| "Malformed response")?;
+            let encrypted_empty = &message[44..60];
+
+            let key = *self.indices.get(&receiver).ok_or("Unknown receiver index")?;
+            let peer = self.peers.get_mut(&key).ok_or("Peer not found")?;
+            let handshake = peer
+                .handshake
+                .as_ref()
+                .filter(|handshake| handshake.local_index == receiver)
+                .ok_or("No handshake awaiting this response")?;
+            let [chaining_key] = kdf(&handshake.chaining_key, &responder_public);
+            let hash_value = hash(&[&handshake.hash, &responder_public]);
+            let [chaining_key] = kdf(&chaining_key, &dh(&handshake.ephemeral, &responder_public)?);
+            let [chaining_key] = kdf(&chaining_key, &dh(&self.identity.private_key, &responder_public)?);
+            let [chaining_key, tau, aead_key] = kdf(&chaining_key, peer.preshared_key());
+            let hash_value = hash(&[&hash_value, &tau]);
+            aead_open(&aead_key, 0, encrypted_empty, &hash_value)?;
+            let [sending_key, receiving_key] = kdf(&chaining_key, &[]);
+
+            peer.handshake = None;
+            peer.handshake_started = None;
+            peer.install_current(Session::new(receiver, sender, (&sending_key, &receiving_key), now, true));
+            peer.last_handshake = Some(now);
+            peer.config.endpoint = Some(source);
+            peer.awaiting_reply = None;
+            peer.rx_bytes += message.len() as u64;
+            self.release_retired(&key);
+
+            // The responder may only use the session once it hears from us
+            let sent = self.outbound.len();
+            self.flush_queue(&key, now);
+            if self.outbound.len() == sent {
+                self.send_keepalive(&key, now);
+            }
+            Ok(())
+        }
+
+        fn handle_cookie_reply(&mut self, message: &[u8], now: u64) -> Result<(), &'static str> {
+            let receiver = read_u32(&message[4..8]);
+            let key = *self.indices.get(&receiver).ok_or("Unknown receiver index")?;
+            let peer = self.peers.get_mut(&key).ok_or("Peer not found")?;
+            let mac1 = peer
+                .handshake
+                .as_ref()
+                .filter(|handshake| handshake.local_index == receiver)
+                .map(|handshake| handshake.mac1)
+                .ok_or("No handshake awaiting a cookie")?;
+            let cookie = XChaCha20Poly1305::new(Key::from_slice(&peer.cookie_key))
+                .decrypt(
+                    XNonce::from_slice(&message[8..32]),
+                    Payload {
+                        msg: &message[32..64],
+                        aad: &mac1,
+                    },
+                )
+                .map_err(|_| "Bad cookie reply")?;
+            peer.cookie = Some((cookie.try_into().map_err(|_| "Bad cookie reply")?, now));
+            Ok(())
+        }
+
+        fn handle_transport(
+            &mut self,
+            source: SocketAddr,
+            message: &[u8],
+            now: u64,
+        ) -> Result<Option<Vec<u8>>, &'static str> {
+            let receiver = read_u32(&message[4..8]);
+            let counter = u64::from_le_bytes(message[8..16].try_into().map_err(|_| "Malformed transport message")?);
+            let key = *self.indices.get(&receiver).ok_or("Unknown receiver index")?;
+            let peer = self.peers.get_mut(&key).ok_or("Peer not found")?;
+
+            let confirming = peer.next.as_ref().is_some_and(|session| session.local_index == receiver);
+            let session = [&mut peer.current, &mut peer.previous, &mut peer.next]
+                .into_iter()
+                .flatten()
+                .find(|session| session.local_index == receiver)
+                .ok_or("Unknown receiver index")?;
+            if session.expired(now) {
+                return Err("Session expired");
+            }
+            if !session.replay.check(counter) {
+                return Err("Replayed transport message");
+            }
+            let mut packet = session
+                .receiver
+                .decrypt(&counter_nonce(counter), &message[TRANSPORT_HEADER_LEN..])
+                .map_err(|_| "Transport authentication failed")?;
+            if !session.replay.update(counter) {
+                return Err("Replayed transport message");
+            }
+
+            if confirming {
+                let session = peer.next.take();
+                if let Some(session) = session {
+                    peer.install_current(session);
+                }
+                peer.last_handshake = Some(now);
+            }
+            peer.config.endpoint = Some(source);
+            peer.awaiting_reply = None;
+            peer.rx_bytes += message.len() as u64;
+            let rekey = peer.handshake.is_none()
+                && peer.current.as_ref().is_some_and(|session| {
+                    session.initiator
+                        && now.saturating_sub(session.created)
+                            >= REJECT_AFTER_TIME_MS - KEEPALIVE_TIMEOUT_MS - REKEY_TIMEOUT_MS
+                });
+
+            let result = if packet.is_empty() {
+                // Keepalive
+                Ok(None)
+            } else {
+                let length = packet_len(&packet)
+                    .filter(|length| *length <= packet.len())
+                    .ok_or("Malformed inner packet")?;
+                packet.truncate(length);
+                let allowed = packet_source(&packet).is_some_and(|address| peer.allows(&address));
+                if !allowed {
+                    return Err("Packet source not allowed for peer");
+                }
+                peer.keepalive_due.get_or_insert(now);
+                Ok(Some(packet))
+            };
+            self.release_retired(&key);
+            if rekey {
+                self.start_handshake(&key, now)?;
+            }
+            result
+        }
+
+        // Drives retransmission, keepalives, rekeying and key expiry
+        pub fn update_timers(&mut self, now: u64) {
+            self.now = now;
+            let keys: Vec<WgKey> = self.peers.keys().copied().collect();
+            for key in keys {
+                let Some(peer) = self.peers.get_mut(&key) else {
+                    continue;
+                };
+                peer.expire_sessions(now);
+
+                let retry_due = peer.handshake.is_some()
+                    && peer
+                        .last_initiation
+                        .is_some_and(|sent| now >= sent + REKEY_TIMEOUT_MS + peer.retry_jitter);
+                if retry_due {
+                    let started = peer.handshake_started.unwrap_or(now);
+                    if now.saturating_sub(started) >= REKEY_ATTEMPT_TIME_MS {
+                        log::warn!("WireGuard handshake with {:?} timed out", key);
+                        if let Some(handshake) = peer.handshake.take() {
+                            peer.retired.push(handshake.local_index);
+                        }
+                        peer.handshake_started = None;
+                        peer.queue.clear();
+                    } else if let Err(error) = self.send_initiation(&key, now) {
+                        log::warn!("WireGuard handshake retry failed: {}", error);
+                    }
+                }
+
+                let Some(peer) = self.peers.get_mut(&key) else {
+                    continue;
+                };
+                let keepalive_due = peer.keepalive_due.is_some_and(|since| now >= since + KEEPALIVE_TIMEOUT_MS);
+                let persistent_due = peer.config.persistent_keepalive.is_some_and(|interval| {
+                    now >= peer.last_sent.unwrap_or(0) + interval * 1000
+                });
+                let unanswered = peer
+                    .awaiting_reply
+                    .is_some_and(|since| now >= since + KEEPALIVE_TIMEOUT_MS + REKEY_TIMEOUT_MS);
+                if unanswered {
+                    peer.awaiting_reply = None;
+                }
+                let rekey = unanswered || peer.needs_rekey(now);
+                if keepalive_due || persistent_due {
+                    self.send_keepalive(&key, now);
+                }
+                if rekey {
+                    if let Err(error) = self.start_handshake(&key, now) {
+                        log::warn!("WireGuard rekey failed: {}", error);
+                    }
+                }
+                self.release_retired(&key);
+            }
+        }
+    }
+
+    // Virtual point-to-point interface; packets routed to it are encrypted
+    // for the peer whose allowed-ips cover the destination
+    pub struct WgDevice {
+        name: String,
+        engine: Arc<Mutex<WireGuard>>,
+    }
+
+    impl WgDevice {
+        pub fn new(name: &str, engine: Arc<Mutex<WireGuard>>) -> Self {
+            WgDevice {
+                name: name.to_string(),
+                engine,
+            }
+        }
+    }
+
+    impl NetDevice for WgDevice {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        fn mac_address(&self) -> MacAddress {
+            MacAddress::ZERO
+        }
+
+        fn mtu(&self) -> usize {
+            WG_MTU
+        }
+
+        fn transmit(&mut self, frame: &[u8]) -> Result<(), &'static str> {
+            let packet = frame.get(ETHERNET_HEADER_LEN..).ok_or("Truncated frame")?;
+            self.engine.lock().unwrap().send_packet(packet)
+        }
+
+        fn point_to_point(&self) -> bool {
+            true
+        }
     }
 
-    pub fn disconnect_vpn() {
-        println!("Disconnecting from VPN...");
-        // Disconnect from the VPN
+    // A WireGuard interface attached to the stack: the wg0 device plus the
+    // UDP socket carrying its encrypted traffic
+    pub struct WgTunnel {
+        interface: InterfaceId,
+        socket: UdpSocketHandle,
+        engine: Arc<Mutex<WireGuard>>,
+        installed: Vec<Route>,
     }
 
-    pub fn status() -> String {
-        println!("Checking VPN status...");
-        // Check the VPN status
-        String::from("VPN is connected")
+    impl WgTunnel {
+        pub fn attach(
+            net: &mut NetStack,
+            udp: &mut UdpStack,
+            name: &str,
+            engine: WireGuard,
+        ) -> Result<WgTunnel, &'static str> {
+            let socket = udp.bind(None, engine.listen_port())?;
+            let engine = Arc::new(Mutex::new(engine));
+            let interface = net.add_interface(Box::new(WgDevice::new(name, Arc::clone(&engine))))?;
+            log::info!("Attached WireGuard interface {}", name);
+            let mut tunnel = WgTunnel {
+                interface,
+                socket,
+                engine,
+                installed: Vec::new(),
+            };
+            tunnel.sync_routes(net);
+            Ok(tunnel)
+        }
+
+        pub fn interface(&self) -> InterfaceId {
+            self.interface
+        }
+
+        pub fn engine(&self) -> &Arc<Mutex<WireGuard>> {
+            &self.engine
+        }
+
+        // Routes the tunnel installed in the main table
+        pub fn routes(&self) -> &[Route] {
+            &self.installed
+        }
+
+        // Installs a route per allowed-ip and, for endpoints that would
+        // otherwise route into the tunnel, a host route over the underlay
+        pub fn sync_routes(&mut self, net: &mut NetStack) {
+            let peers = self.engine.lock().unwrap().peers();
+            let wg = self.interface;
+            let route = |destination: IpCidr, gateway: Option<IpAddr>, interface: InterfaceId| Route {
+                metric: ROUTE_METRIC,
+                protocol: RouteProtocol::WireGuard,
+                ..Route::new(destination.network(), gateway, interface)
+            };
+            let mut desired: Vec<Route> = Vec::new();
+            for allowed in peers.iter().flat_map(|peer| &peer.allowed_ips) {
+                let allowed = route(*allowed, None, wg);
+                if !desired.contains(&allowed) {
+                    desired.push(allowed);
+                }
+            }
+            // Existing pins stay until the endpoints are re-checked against
+            // the updated allowed-ips
+            let pins: Vec<Route> = self.installed.iter().filter(|route| route.interface != wg).copied().collect();
+            self.apply(net, desired.iter().chain(&pins).copied().collect());
+            for endpoint in peers.iter().filter_map(|peer| peer.endpoint) {
+                let address = endpoint.ip();
+                let through = net.fib().lookup_filtered(&address, None, None, |route| !pins.contains(route));
+                if through.is_none_or(|route| route.interface != wg) {
+                    continue;
+                }
+                let underlay = net.fib().lookup_filtered(&address, None, None, |route| {
+                    route.interface != wg && !pins.contains(route)
+                });
+                let Some(underlay) = underlay else {
+                    continue;
+                };
+                let host = IpCidr::new(address, if address.is_ipv4() { 32 } else { 128 }).unwrap();
+                let pin = route(host, underlay.gateway, underlay.interface);
+                if !desired.contains(&pin) {
+                    desired.push(pin);
+                }
+            }
+            self.apply(net, desired);
+        }
+
+        fn apply(&mut self, net: &mut NetStack, desired: Vec<Route>) {
+            let stale: Vec<Route> = self.installed.iter().filter(|route| !desired.contains(route)).copied().collect();
+            if !stale.is_empty() {
+                net.fib_mut()
+                    .retain_routes(|table, route| table != TABLE_MAIN || !stale.contains(route));
+            }
+            self.installed.retain(|route| desired.contains(route));
+            for route in desired {
+                if self.installed.contains(&route) {
+                    continue;
+                }
+                match net.add_route(route) {
+                    Ok(()) => self.installed.push(route),
+                    Err(error) => log::warn!("WireGuard failed to install route to {:?}: {}", route.destination, error),
+                }
+            }
+        }
+
+        // Decrypts datagrams waiting on the UDP socket into the stack, runs
+        // the timers and sends whatever the engine produced
+        pub fn poll(&mut self, net: &mut NetStack, udp: &mut UdpStack, now: u64) -> Result<(), &'static str> {
+            while let Some(message) = udp.recv_from(self.socket)? {
+                let source = SocketAddr::new(message.source, message.source_port);
+                // The lock must be released before the stack can transmit on wg0
+                let result = self.engine.lock().unwrap().receive(source, &message.payload, now);
+                let packet = match result {
+                    Ok(Some(packet)) => packet,
+                    Ok(None) => continue,
+                    Err(error) => {
+                        log::debug!("WireGuard dropped packet from {}: {}", source, error);
+                        continue;
+                    }
+                };
+                let ethertype = if packet[0] >> 4 == 6 { ETHERTYPE_IPV6 } else { ETHERTYPE_IPV4 };
+                let frame = build_ethernet(
+                    &EthernetHeader {
+                        destination: MacAddress::ZERO,
+                        source: MacAddress::ZERO,
+                        ethertype,
+                    },
+                    &packet,
+                );
+                if let Err(error) = net.receive(self.interface, &frame, now) {
+                    log::debug!("WireGuard inner packet rejected: {}", error);
+                }
+            }
+            self.engine.lock().unwrap().update_timers(now);
+            self.sync_routes(net);
+            loop {
+                let outbound = self.engine.lock().unwrap().take_outbound();
+                let Some((endpoint, data)) = outbound else {
+                    break;
+                };
+                udp.send_to(net, self.socket, endpoint.ip(), endpoint.port(), &data)?;
+            }
+            Ok(())
+        }
     }
 
-    pub fn update() {
-        println!("Updating VXVPN...");
-        // Update the VXVPN system
+    pub fn init() {
+        log::info!("Initializing VXVPN...");
     }
 }
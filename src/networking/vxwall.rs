@@ -1,26 +1,998 @@
 pub mod vxwall {
-    pub fn init() {
-        println!("Initializing VXWall...");
-        // Initialize the VXWall system
+    use crate::packet::packet::{checksum, pseudo_header_checksum, IP_PROTO_ICMP, IP_PROTO_ICMPV6, IP_PROTO_TCP, IP_PROTO_UDP};
+    use crate::vxnet_core::vxnet_core::{
+        Datagram, FilterContext, Hook, InterfaceId, IpCidr, NetStack, PacketFilter, SocketOwner, Verdict,
+    };
+    use std::collections::BTreeMap;
+    use std::net::{IpAddr, SocketAddr};
+    use std::str::SplitWhitespace;
+    use std::sync::{Arc, Mutex, OnceLock};
+
+    const MAX_CONNECTIONS: usize = 65_536;
+    const GC_INTERVAL_MS: u64 = 1_000;
+    const TCP_ESTABLISHED_TIMEOUT_MS: u64 = 2 * 60 * 60 * 1000;
+    const TCP_HANDSHAKE_TIMEOUT_MS: u64 = 120_000;
+    const TCP_CLOSING_TIMEOUT_MS: u64 = 10_000;
+    const UDP_TIMEOUT_MS: u64 = 30_000;
+    const UDP_STREAM_TIMEOUT_MS: u64 = 180_000;
+    const ICMP_TIMEOUT_MS: u64 = 30_000;
+    // Source ports masquerading may move a flow to when its own is taken
+    const NAT_PORT_START: u16 = 1024;
+
+    const TCP_FIN: u8 = 0x01;
+    const TCP_SYN: u8 = 0x02;
+    const TCP_RST: u8 = 0x04;
+    const TCP_ACK: u8 = 0x10;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub enum ConnState {
+        New,
+        Established,
+        // ICMP errors about a tracked flow
+        Related,
+        Invalid,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct FlowKey {
+        pub protocol: u8,
+        pub source: IpAddr,
+        pub source_port: u16,
+        pub destination: IpAddr,
+        pub destination_port: u16,
+    }
+
+    impl FlowKey {
+        pub fn reversed(&self) -> FlowKey {
+            FlowKey {
+                protocol: self.protocol,
+                source: self.destination,
+                source_port: self.destination_port,
+                destination: self.source,
+                destination_port: self.source_port,
+            }
+        }
+    }
+
+    fn is_icmp(protocol: u8) -> bool {
+        protocol == IP_PROTO_ICMP || protocol == IP_PROTO_ICMPV6
+    }
+
+    fn is_icmp_echo(kind: u8) -> bool {
+        matches!(kind, 0 | 8 | 128 | 129)
+    }
+
+    fn is_icmp_error(protocol: u8, kind: u8) -> bool {
+        match protocol {
+            IP_PROTO_ICMP => matches!(kind, 3 | 4 | 5 | 11 | 12),
+            IP_PROTO_ICMPV6 => kind < 128,
+            _ => false,
+        }
+    }
+
+    // Ports for TCP/UDP; ICMP echo uses its identifier on both sides so the
+    // reply maps onto the reversed request
+    fn flow_ports(protocol: u8, payload: &[u8]) -> Option<(u16, u16)> {
+        match protocol {
+            IP_PROTO_TCP | IP_PROTO_UDP if payload.len() >= 4 => Some((
+                u16::from_be_bytes([payload[0], payload[1]]),
+                u16::from_be_bytes([payload[2], payload[3]]),
+            )),
+            IP_PROTO_ICMP | IP_PROTO_ICMPV6 if payload.len() >= 8 && is_icmp_echo(payload[0]) => {
+                let id = u16::from_be_bytes([payload[4], payload[5]]);
+                Some((id, id))
+            }
+            IP_PROTO_ICMP | IP_PROTO_ICMPV6 => None,
+            _ => Some((0, 0)),
+        }
+    }
+
+    pub fn flow_key(datagram: &Datagram) -> Option<FlowKey> {
+        let (source_port, destination_port) = flow_ports(datagram.protocol, datagram.payload.data())?;
+        Some(FlowKey {
+            protocol: datagram.protocol,
+            source: datagram.source,
+            source_port,
+            destination: datagram.destination,
+            destination_port,
+        })
+    }
+
+    // Flow of the packet quoted inside an ICMP error
+    fn quoted_flow(datagram: &Datagram) -> Option<FlowKey> {
+        let quoted = datagram.payload.data().get(8..)?;
+        let (source, destination, protocol, payload) = match datagram.protocol {
+            // Quotes are truncated, so read the header fields directly
+            IP_PROTO_ICMP => {
+                let header_len = ((*quoted.first()? & 0x0F) as usize) * 4;
+                let header = quoted.get(..header_len.max(20))?;
+                let source: [u8; 4] = header[12..16].try_into().ok()?;
+                let destination: [u8; 4] = header[16..20].try_into().ok()?;
+                (IpAddr::from(source), IpAddr::from(destination), header[9], &quoted[header.len()..])
+            }
+            _ => {
+                let header = quoted.get(..40)?;
+                let source: [u8; 16] = header[8..24].try_into().ok()?;
+                let destination: [u8; 16] = header[24..40].try_into().ok()?;
+                (IpAddr::from(source), IpAddr::from(destination), header[6], &quoted[40..])
+            }
+        };
+        let (source_port, destination_port) = flow_ports(protocol, payload)?;
+        Some(FlowKey {
+            protocol,
+            source,
+            source_port,
+            destination,
+            destination_port,
+        })
+    }
+
+    // Rewrites one end of a datagram and fixes up the transport checksum.
+    // ICMP echo carries its identifier in place of both ports.
+    fn rewrite_endpoint(datagram: &mut Datagram, source: bool, address: IpAddr, port: u16) {
+        let current = match flow_ports(datagram.protocol, datagram.payload.data()) {
+            Some((source_port, _)) if source => (datagram.source, source_port),
+            Some((_, destination_port)) => (datagram.destination, destination_port),
+            None => return,
+        };
+        if current == (address, port) {
+            return;
+        }
+        if source {
+            datagram.source = address;
+        } else {
+            datagram.destination = address;
+        }
+        let payload = datagram.payload.data_mut();
+        match datagram.protocol {
+            IP_PROTO_TCP | IP_PROTO_UDP => {
+                let offset = if source { 0 } else { 2 };
+                payload[offset..offset + 2].copy_from_slice(&port.to_be_bytes());
+            }
+            IP_PROTO_ICMP | IP_PROTO_ICMPV6 => payload[4..6].copy_from_slice(&port.to_be_bytes()),
+            _ => {}
+        }
+        update_checksum(datagram);
+    }
+
+    fn update_checksum(datagram: &mut Datagram) {
+        let offset = match datagram.protocol {
+            IP_PROTO_TCP => 16,
+            IP_PROTO_UDP => 6,
+            IP_PROTO_ICMP | IP_PROTO_ICMPV6 => 2,
+            _ => return,
+        };
+        let payload = datagram.payload.data_mut();
+        if payload.len() < offset + 2 {
+            return;
+        }
+        // UDP over IPv4 may go without a checksum; keep it that way
+        if datagram.protocol == IP_PROTO_UDP && datagram.source.is_ipv4() && payload[6..8] == [0, 0] {
+            return;
+        }
+        payload[offset..offset + 2].copy_from_slice(&[0, 0]);
+        let sum = match datagram.protocol {
+            IP_PROTO_ICMP => checksum(payload),
+            protocol => pseudo_header_checksum(datagram.source, datagram.destination, protocol, payload),
+        };
+        let sum = if datagram.protocol == IP_PROTO_UDP && sum == 0 { 0xFFFF } else { sum };
+        payload[offset..offset + 2].copy_from_slice(&sum.to_be_bytes());
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum TcpPhase {
+        Handshake,
+        Established,
+        Closing,
+    }
+
+    // A tracked flow. Without NAT the reply tuple is the reversed original;
+    // with NAT it is what the peer sees and answers to, and packets are
+    // rewritten between the two.
+    #[derive(Debug, Clone)]
+    pub struct Connection {
+        pub original: FlowKey,
+        pub reply: FlowKey,
+        pub seen_reply: bool,
+        pub packets: u64,
+        pub bytes: u64,
+        tcp_phase: TcpPhase,
+        last_seen: u64,
+        timeout: u64,
+        // Source NAT rules are consulted once, on the first packet out
+        source_nat_checked: bool,
+    }
+
+    impl Connection {
+        pub fn is_translated(&self) -> bool {
+            self.reply != self.original.reversed()
+        }
+
+        // Destination after prerouting translation
+        fn nat_destination(&self, reply: bool) -> (IpAddr, u16) {
+            if reply {
+                (self.original.source, self.original.source_port)
+            } else {
+                (self.reply.source, self.reply.source_port)
+            }
+        }
+
+        // Source after postrouting translation
+        fn nat_source(&self, reply: bool) -> (IpAddr, u16) {
+            if reply {
+                (self.original.destination, self.original.destination_port)
+            } else {
+                (self.reply.destination, self.reply.destination_port)
+            }
+        }
+
+        // Every form a packet of this flow takes on its way through: as
+        // received, after destination NAT and after source NAT, for the
+        // original direction first and then the reply direction
+        fn keys(&self) -> [FlowKey; 6] {
+            let translated = |key: FlowKey, (destination, destination_port): (IpAddr, u16)| FlowKey {
+                destination,
+                destination_port,
+                ..key
+            };
+            [
+                self.original,
+                translated(self.original, self.nat_destination(false)),
+                self.reply.reversed(),
+                self.reply,
+                translated(self.reply, self.nat_destination(true)),
+                self.original.reversed(),
+            ]
+        }
+
+        fn is_reply(&self, key: &FlowKey) -> bool {
+            !self.keys()[..3].contains(key)
+        }
+
+        fn refresh(&mut self, reply: bool, datagram: &Datagram, now: u64) {
+            self.last_seen = now;
+            self.packets += 1;
+            self.bytes += datagram.payload.len() as u64;
+            if reply {
+                self.seen_reply = true;
+            }
+            self.timeout = match self.original.protocol {
+                IP_PROTO_TCP => {
+                    let flags = datagram.payload.data().get(13).copied().unwrap_or(0);
+                    if flags & (TCP_FIN | TCP_RST) != 0 {
+                        self.tcp_phase = TcpPhase::Closing;
+                    } else if self.seen_reply && self.tcp_phase == TcpPhase::Handshake && flags & TCP_ACK != 0 && !reply {
+                        self.tcp_phase = TcpPhase::Established;
+                    }
+                    match self.tcp_phase {
+                        TcpPhase::Handshake => TCP_HANDSHAKE_TIMEOUT_MS,
+                        TcpPhase::Established => TCP_ESTABLISHED_TIMEOUT_MS,
+                        TcpPhase::Closing => TCP_CLOSING_TIMEOUT_MS,
+                    }
+                }
+                IP_PROTO_UDP if self.seen_reply => UDP_STREAM_TIMEOUT_MS,
+                IP_PROTO_UDP => UDP_TIMEOUT_MS,
+                _ => ICMP_TIMEOUT_MS,
+            };
+        }
+
+        fn expired(&self, now: u64) -> bool {
+            now.saturating_sub(self.last_seen) >= self.timeout
+        }
+    }
+
+    // Connection tracker; every flow is indexed under both directions, in
+    // each of their translated forms
+    pub struct ConnTracker {
+        connections: BTreeMap<u64, Connection>,
+        index: BTreeMap<FlowKey, u64>,
+        next_id: u64,
+        last_gc: u64,
+    }
+
+    impl ConnTracker {
+        pub fn new() -> Self {
+            ConnTracker {
+                connections: BTreeMap::new(),
+                index: BTreeMap::new(),
+                next_id: 0,
+                last_gc: 0,
+            }
+        }
+
+        pub fn len(&self) -> usize {
+            self.connections.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.connections.is_empty()
+        }
+
+        pub fn connections(&self) -> impl Iterator<Item = &Connection> {
+            self.connections.values()
+        }
+
+        pub fn lookup(&self, key: &FlowKey) -> Option<&Connection> {
+            self.index.get(key).and_then(|id| self.connections.get(id))
+        }
+
+        // Classifies a packet without changing the table
+        pub fn classify(&self, datagram: &Datagram) -> ConnState {
+            if is_icmp(datagram.protocol) {
+                let kind = datagram.payload.data().first().copied().unwrap_or(0);
+                if is_icmp_error(datagram.protocol, kind) {
+                    let related = quoted_flow(datagram).is_some_and(|key| self.index.contains_key(&key));
+                    return if related { ConnState::Related } else { ConnState::Invalid };
+                }
+            }
+            let Some(key) = flow_key(datagram) else {
+                return ConnState::Invalid;
+            };
+            match self.lookup(&key) {
+                Some(connection) if connection.seen_reply || connection.is_reply(&key) => ConnState::Established,
+                Some(_) => ConnState::New,
+                None => {
+                    let flags = datagram.payload.data().get(13).copied().unwrap_or(0);
+                    // Only a SYN may open a TCP flow
+                    if datagram.protocol == IP_PROTO_TCP && flags & (TCP_SYN | TCP_ACK | TCP_RST) != TCP_SYN {
+                        ConnState::Invalid
+                    } else {
+                        ConnState::New
+                    }
+                }
+            }
+        }
+
+        // Records an accepted packet, creating the flow if needed
+        pub fn confirm(&mut self, datagram: &Datagram, now: u64) -> Option<&Connection> {
+            let key = flow_key(datagram)?;
+            let id = match self.index.get(&key) {
+                Some(id) => *id,
+                None => self.create(key, key.reversed(), now)?,
+            };
+            let connection = self.connections.get_mut(&id)?;
+            let reply = connection.is_reply(&key);
+            connection.refresh(reply, datagram, now);
+            Some(connection)
+        }
+
+        fn create(&mut self, original: FlowKey, reply: FlowKey, now: u64) -> Option<u64> {
+            if self.connections.len() >= MAX_CONNECTIONS {
+                self.collect(now);
+                if self.connections.len() >= MAX_CONNECTIONS {
+                    return None;
+                }
+            }
+            let id = self.next_id;
+            self.next_id += 1;
+            let connection = Connection {
+                original,
+                reply,
+                seen_reply: false,
+                packets: 0,
+                bytes: 0,
+                tcp_phase: TcpPhase::Handshake,
+                last_seen: now,
+                timeout: UDP_TIMEOUT_MS,
+                source_nat_checked: false,
+            };
+            for key in connection.keys() {
+                self.index.insert(key, id);
+            }
+            self.connections.insert(id, connection);
+            Some(id)
+        }
+
+        fn unindex(&mut self, id: u64, connection: &Connection) {
+            for key in connection.keys() {
+                if self.index.get(&key) == Some(&id) {
+                    self.index.remove(&key);
+                }
+            }
+        }
+
+        // Changes the tuple the peer answers to (source NAT decided after
+        // the flow was created)
+        fn rebind(&mut self, id: u64, reply: FlowKey) {
+            let Some(connection) = self.connections.get(&id).cloned() else {
+                return;
+            };
+            self.unindex(id, &connection);
+            let connection = self.connections.get_mut(&id).unwrap();
+            connection.reply = reply;
+            for key in connection.keys() {
+                self.index.insert(key, id);
+            }
+        }
+
+        // Picks the port a flow leaves with when its source becomes address:
+        // the original port if no other flow uses the resulting reply tuple
+        fn nat_reply(&self, id: u64, address: IpAddr) -> Option<FlowKey> {
+            let original = self.connections.get(&id)?.original;
+            let echo = is_icmp(original.protocol);
+            let reply_for = |port: u16| FlowKey {
+                protocol: original.protocol,
+                source: original.destination,
+                source_port: if echo { port } else { original.destination_port },
+                destination: address,
+                destination_port: port,
+            };
+            let free = |reply: &FlowKey| {
+                [*reply, reply.reversed()]
+                    .iter()
+                    .all(|key| self.index.get(key).is_none_or(|owner| *owner == id))
+            };
+            let first = reply_for(original.source_port);
+            if free(&first) {
+                return Some(first);
+            }
+            if !echo && original.source_port == 0 && original.destination_port == 0 {
+                // Portless protocols cannot be told apart
+                return None;
+            }
+            (NAT_PORT_START..=u16::MAX).map(reply_for).find(free)
+        }
+
+        // Drops expired flows
+        pub fn collect(&mut self, now: u64) {
+            self.last_gc = now;
+            let expired: Vec<u64> = self
+                .connections
+                .iter()
+                .filter(|(_, connection)| connection.expired(now))
+                .map(|(id, _)| *id)
+                .collect();
+            for id in expired {
+                if let Some(connection) = self.connections.remove(&id) {
+                    self.unindex(id, &connection);
+                }
+            }
+        }
+
+        fn maybe_collect(&mut self, now: u64) {
+            if now.saturating_sub(self.last_gc) >= GC_INTERVAL_MS {
+                self.collect(now);
+            }
+        }
+    }
+
+    impl Default for ConnTracker {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    #[derive(Debug, Clone, Default, PartialEq, Eq)]
+    pub struct RuleMatch {
+        pub protocol: Option<u8>,
+        pub source: Option<IpCidr>,
+        pub destination: Option<IpCidr>,
+        pub source_ports: Option<(u16, u16)>,
+        pub destination_ports: Option<(u16, u16)>,
+        pub interface: Option<InterfaceId>,
+        // Empty matches every state
+        pub states: Vec<ConnState>,
+        // Owner of the local socket; datagrams without one never match
+        pub service: Option<String>,
+        pub task: Option<usize>,
+    }
+
+    impl RuleMatch {
+        fn matches(&self, datagram: &Datagram, state: ConnState, owner: Option<&SocketOwner>) -> bool {
+            if self.protocol.is_some_and(|protocol| protocol != datagram.protocol) {
+                return false;
+            }
+            if self.source.is_some_and(|cidr| !cidr.contains(&datagram.source)) {
+                return false;
+            }
+            if self.destination.is_some_and(|cidr| !cidr.contains(&datagram.destination)) {
+                return false;
+            }
+            if self.interface.is_some_and(|interface| interface != datagram.interface) {
+                return false;
+            }
+            if !self.states.is_empty() && !self.states.contains(&state) {
+                return false;
+            }
+            if self.service.as_ref().is_some_and(|service| owner.is_none_or(|owner| &owner.service != service)) {
+                return false;
+            }
+            if self.task.is_some_and(|task| owner.is_none_or(|owner| owner.task != task)) {
+                return false;
+            }
+            if self.source_ports.is_some() || self.destination_ports.is_some() {
+                let ports = match datagram.protocol {
+                    IP_PROTO_TCP | IP_PROTO_UDP => flow_ports(datagram.protocol, datagram.payload.data()),
+                    _ => None,
+                };
+                let Some((source_port, destination_port)) = ports else {
+                    return false;
+                };
+                let in_range = |range: Option<(u16, u16)>, port: u16| range.is_none_or(|(low, high)| (low..=high).contains(&port));
+                if !in_range(self.source_ports, source_port) || !in_range(self.destination_ports, destination_port) {
+                    return false;
+                }
+            }
+            true
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Rule {
+        pub hook: Hook,
+        pub matcher: RuleMatch,
+        pub verdict: Verdict,
+        pub comment: Option<String>,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct RuleId(pub u64);
+
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct RuleCounters {
+        pub packets: u64,
+        pub bytes: u64,
+    }
+
+    struct RuleEntry {
+        id: RuleId,
+        rule: Rule,
+        counters: RuleCounters,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum NatAction {
+        // Source becomes the outgoing interface's current address
+        Masquerade,
+        Snat(IpAddr),
+        // Destination becomes address, and port when given (port forwarding)
+        Dnat { address: IpAddr, port: Option<u16> },
+    }
+
+    // Applies to the first packet of a flow; the rest of the flow, replies
+    // included, follows the binding kept in the connection tracker. The
+    // matcher's interface is the incoming one for Dnat and the outgoing one
+    // for source NAT.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct NatRule {
+        pub matcher: RuleMatch,
+        pub action: NatAction,
+        pub comment: Option<String>,
+    }
+
+    struct NatEntry {
+        id: RuleId,
+        rule: NatRule,
+        counters: RuleCounters,
+    }
+
+    // Ordered rule chains per hook with first-match semantics; packets that
+    // match nothing take the hook's policy. Established and related traffic
+    // of accepted flows passes unless a rule says otherwise.
+    pub struct Firewall {
+        rules: Vec<RuleEntry>,
+        nat_rules: Vec<NatEntry>,
+        policies: BTreeMap<Hook, Verdict>,
+        conntrack: ConnTracker,
+        next_id: u64,
+    }
+
+    impl Firewall {
+        pub fn new() -> Self {
+            let mut policies = BTreeMap::new();
+            policies.insert(Hook::Input, Verdict::Accept);
+            policies.insert(Hook::Output, Verdict::Accept);
+            policies.insert(Hook::Forward, Verdict::Drop);
+            Firewall {
+                rules: Vec::new(),
+                nat_rules: Vec::new(),
+                policies,
+                conntrack: ConnTracker::new(),
+                next_id: 0,
+            }
+        }
+
+        pub fn set_policy(&mut self, hook: Hook, verdict: Verdict) {
+            self.policies.insert(hook, verdict);
+        }
+
+        pub fn policy(&self, hook: Hook) -> Verdict {
+            self.policies.get(&hook).copied().unwrap_or(Verdict::Accept)
+        }
+
+        pub fn add_rule(&mut self, rule: Rule) -> RuleId {
+            let position = self.rules.len();
+            self.insert_rule(position, rule)
+        }
+
+        pub fn insert_rule(&mut self, position: usize, rule: Rule) -> RuleId {
+            let id = RuleId(self.next_id);
+            self.next_id += 1;
+            let entry = RuleEntry {
+                id,
+                rule,
+                counters: RuleCounters::default(),
+            };
+            self.rules.insert(position.min(self.rules.len()), entry);
+            id
+        }
+
+        pub fn remove_rule(&mut self, id: RuleId) -> Result<Rule, &'static str> {
+            let position = self.rules.iter().position(|entry| entry.id == id).ok_or("Rule not found")?;
+            Ok(self.rules.remove(position).rule)
+        }
+
+        pub fn flush_rules(&mut self) {
+            self.rules.clear();
+        }
+
+        pub fn rules(&self) -> Vec<(RuleId, Rule, RuleCounters)> {
+            self.rules
+                .iter()
+                .map(|entry| (entry.id, entry.rule.clone(), entry.counters))
+                .collect()
+        }
+
+        pub fn counters(&self, id: RuleId) -> Option<RuleCounters> {
+            self.rules.iter().find(|entry| entry.id == id).map(|entry| entry.counters)
+        }
+
+        pub fn reset_counters(&mut self) {
+            for entry in &mut self.rules {
+                entry.counters = RuleCounters::default();
+            }
+            for entry in &mut self.nat_rules {
+                entry.counters = RuleCounters::default();
+            }
+        }
+
+        // NAT rules are evaluated in the order they were added
+        pub fn add_nat_rule(&mut self, rule: NatRule) -> RuleId {
+            let id = RuleId(self.next_id);
+            self.next_id += 1;
+            self.nat_rules.push(NatEntry {
+                id,
+                rule,
+                counters: RuleCounters::default(),
+            });
+            id
+        }
+
+        // Existing flows keep their bindings until they expire
+        pub fn remove_nat_rule(&mut self, id: RuleId) -> Result<NatRule, &'static str> {
+            let position = self
+                .nat_rules
+                .iter()
+                .position(|entry| entry.id == id)
+                .ok_or("Rule not found")?;
+            Ok(self.nat_rules.remove(position).rule)
+        }
+
+        pub fn flush_nat_rules(&mut self) {
+            self.nat_rules.clear();
+        }
+
+        pub fn nat_rules(&self) -> Vec<(RuleId, NatRule, RuleCounters)> {
+            self.nat_rules
+                .iter()
+                .map(|entry| (entry.id, entry.rule.clone(), entry.counters))
+                .collect()
+        }
+
+        pub fn conntrack(&self) -> &ConnTracker {
+            &self.conntrack
+        }
+
+        pub fn conntrack_mut(&mut self) -> &mut ConnTracker {
+            &mut self.conntrack
+        }
+
+        // Prerouting and postrouting only translate; the other hooks filter
+        pub fn evaluate(&mut self, hook: Hook, datagram: &mut Datagram, context: &FilterContext) -> Verdict {
+            let now = context.now;
+            self.conntrack.maybe_collect(now);
+            match hook {
+                Hook::Prerouting => {
+                    self.translate_destination(datagram, now);
+                    return Verdict::Accept;
+                }
+                Hook::Postrouting => {
+                    self.translate_source(datagram, context);
+                    return Verdict::Accept;
+                }
+                _ => {}
+            }
+            let state = self.conntrack.classify(datagram);
+
+            let mut verdict = None;
+            for entry in &mut self.rules {
+                if entry.rule.hook == hook && entry.rule.matcher.matches(datagram, state, context.owner) {
+                    entry.counters.packets += 1;
+                    entry.counters.bytes += datagram.payload.len() as u64;
+                    verdict = Some(entry.rule.verdict);
+                    break;
+                }
+            }
+            let verdict = verdict.unwrap_or_else(|| match state {
+                ConnState::Established | ConnState::Related => Verdict::Accept,
+                _ => self.policy(hook),
+            });
+
+            if verdict == Verdict::Accept && state != ConnState::Related && state != ConnState::Invalid {
+                self.conntrack.confirm(datagram, now);
+            }
+            verdict
+        }
+
+        fn translate_destination(&mut self, datagram: &mut Datagram, now: u64) {
+            let Some(key) = flow_key(datagram) else {
+                return;
+            };
+            if let Some(connection) = self.conntrack.lookup(&key) {
+                let (address, port) = connection.nat_destination(connection.is_reply(&key));
+                rewrite_endpoint(datagram, false, address, port);
+                return;
+            }
+            if self.conntrack.classify(datagram) != ConnState::New {
+                return;
+            }
+            let length = datagram.payload.len() as u64;
+            let target = self.nat_rules.iter_mut().find_map(|entry| match entry.rule.action {
+                NatAction::Dnat { address, port }
+                    if address.is_ipv4() == key.destination.is_ipv4()
+                        && entry.rule.matcher.matches(datagram, ConnState::New, None) =>
+                {
+                    entry.counters.packets += 1;
+                    entry.counters.bytes += length;
+                    Some((address, port.unwrap_or(key.destination_port)))
+                }
+                _ => None,
+            });
+            let Some((address, port)) = target else {
+                return;
+            };
+            let reply = FlowKey {
+                protocol: key.protocol,
+                source: address,
+                source_port: port,
+                destination: key.source,
+                destination_port: key.source_port,
+            };
+            if self.conntrack.create(key, reply, now).is_some() {
+                rewrite_endpoint(datagram, false, address, port);
+            }
+        }
+
+        fn translate_source(&mut self, datagram: &mut Datagram, context: &FilterContext) {
+            let Some(key) = flow_key(datagram) else {
+                return;
+            };
+            let id = self.conntrack.index.get(&key).copied();
+            let undecided = match id.and_then(|id| self.conntrack.connections.get(&id)) {
+                Some(connection) => !connection.source_nat_checked && !connection.is_reply(&key),
+                None => true,
+            };
+            let source = if undecided { self.match_source_nat(datagram, context) } else { None };
+            let id = match (id, source) {
+                (Some(id), _) => id,
+                (None, Some(_)) => match self.conntrack.create(key, key.reversed(), context.now) {
+                    Some(id) => id,
+                    None => return,
+                },
+                (None, None) => return,
+            };
+            if undecided {
+                if let Some(connection) = self.conntrack.connections.get_mut(&id) {
+                    connection.source_nat_checked = true;
+                }
+                if let Some(reply) = source.and_then(|address| self.conntrack.nat_reply(id, address)) {
+                    self.conntrack.rebind(id, reply);
+                }
+            }
+            if let Some(connection) = self.conntrack.connections.get(&id) {
+                let (address, port) = connection.nat_source(connection.is_reply(&key));
+                rewrite_endpoint(datagram, true, address, port);
+            }
+        }
+
+        fn match_source_nat(&mut self, datagram: &Datagram, context: &FilterContext) -> Option<IpAddr> {
+            let length = datagram.payload.len() as u64;
+            for entry in &mut self.nat_rules {
+                let address = match entry.rule.action {
+                    NatAction::Masquerade => context.interface_address,
+                    NatAction::Snat(address) => Some(address),
+                    NatAction::Dnat { .. } => continue,
+                };
+                let Some(address) = address.filter(|address| address.is_ipv4() == datagram.source.is_ipv4()) else {
+                    continue;
+                };
+                if entry.rule.matcher.matches(datagram, ConnState::New, context.owner) {
+                    entry.counters.packets += 1;
+                    entry.counters.bytes += length;
+                    return Some(address);
+                }
+            }
+            None
+        }
+    }
+
+    impl Default for Firewall {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl PacketFilter for Arc<Mutex<Firewall>> {
+        fn filter(&mut self, hook: Hook, datagram: &mut Datagram, context: &FilterContext) -> Verdict {
+            self.lock().unwrap().evaluate(hook, datagram, context)
+        }
+    }
+
+    fn parse_ports(text: &str) -> Result<(u16, u16), &'static str> {
+        let (low, high) = text.split_once('-').unwrap_or((text, text));
+        let low = low.parse().map_err(|_| "Invalid port")?;
+        let high = high.parse().map_err(|_| "Invalid port")?;
+        if low > high {
+            return Err("Invalid port range");
+        }
+        Ok((low, high))
+    }
+
+    // Handles the match keywords shared by filter and NAT rules; returns
+    // false for any other word
+    fn parse_match(matcher: &mut RuleMatch, word: &str, words: &mut SplitWhitespace) -> Result<bool, &'static str> {
+        let mut value = || words.next().ok_or("Missing value in rule");
+        match word {
+            "proto" => {
+                matcher.protocol = Some(match value()? {
+                    "tcp" => IP_PROTO_TCP,
+                    "udp" => IP_PROTO_UDP,
+                    "icmp" => IP_PROTO_ICMP,
+                    "icmpv6" => IP_PROTO_ICMPV6,
+                    number => number.parse().map_err(|_| "Unknown protocol")?,
+                })
+            }
+            "from" => matcher.source = Some(value()?.parse()?),
+            "to" => matcher.destination = Some(value()?.parse()?),
+            "sport" => matcher.source_ports = Some(parse_ports(value()?)?),
+            "dport" => matcher.destination_ports = Some(parse_ports(value()?)?),
+            "iface" => matcher.interface = Some(InterfaceId(value()?.parse().map_err(|_| "Invalid interface")?)),
+            "service" => matcher.service = Some(value()?.to_string()),
+            "task" => matcher.task = Some(value()?.parse().map_err(|_| "Invalid task")?),
+            "state" => {
+                for state in value()?.split(',') {
+                    matcher.states.push(match state {
+                        "new" => ConnState::New,
+                        "established" => ConnState::Established,
+                        "related" => ConnState::Related,
+                        "invalid" => ConnState::Invalid,
+                        _ => return Err("Unknown connection state"),
+                    });
+                }
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    // Parses the textual rule form used by configuration files and
+    // vxnetctl, for example:
+    //   input proto tcp from 10.0.0.0/8 dport 22 accept
+    //   input state established,related accept
+    //   forward iface 1 drop
+    //   output proto tcp dport 443 service vxupdate accept
+    pub fn parse_rule(text: &str) -> Result<Rule, &'static str> {
+        let mut words = text.split_whitespace();
+        let hook = match words.next() {
+            Some("input") => Hook::Input,
+            Some("output") => Hook::Output,
+            Some("forward") => Hook::Forward,
+            _ => return Err("Rule must start with input, output or forward"),
+        };
+        let mut matcher = RuleMatch::default();
+        let mut verdict = None;
+        let mut comment = None;
+        while let Some(word) = words.next() {
+            if parse_match(&mut matcher, word, &mut words)? {
+                continue;
+            }
+            match word {
+                "comment" => comment = Some(words.by_ref().collect::<Vec<_>>().join(" ")),
+                "accept" => verdict = Some(Verdict::Accept),
+                "drop" => verdict = Some(Verdict::Drop),
+                "reject" => verdict = Some(Verdict::Reject),
+                _ => return Err("Unknown rule keyword"),
+            }
+        }
+        Ok(Rule {
+            hook,
+            matcher,
+            verdict: verdict.ok_or("Rule has no verdict")?,
+            comment,
+        })
+    }
+
+    // NAT counterpart of parse_rule:
+    //   masquerade iface 1 from 192.168.1.0/24
+    //   snat iface 1 to-source 203.0.113.5
+    //   dnat iface 1 proto tcp dport 8080 to-destination 192.168.1.20:80
+    pub fn parse_nat_rule(text: &str) -> Result<NatRule, &'static str> {
+        let mut words = text.split_whitespace();
+        let kind = words.next().ok_or("Empty NAT rule")?;
+        let mut matcher = RuleMatch::default();
+        let mut target = None;
+        let mut comment = None;
+        while let Some(word) = words.next() {
+            if parse_match(&mut matcher, word, &mut words)? {
+                continue;
+            }
+            match word {
+                "to-source" | "to-destination" if target.is_none() => {
+                    target = Some((word, words.next().ok_or("Missing value in rule")?))
+                }
+                "comment" => comment = Some(words.by_ref().collect::<Vec<_>>().join(" ")),
+                _ => return Err("Unknown rule keyword"),
+            }
+        }
+        let action = match (kind, target) {
+            ("masquerade", None) => NatAction::Masquerade,
+            ("snat", Some(("to-source", address))) => {
+                NatAction::Snat(address.parse().map_err(|_| "Invalid address")?)
+            }
+            ("dnat", Some(("to-destination", target))) => match target.parse::<SocketAddr>() {
+                Ok(socket) => NatAction::Dnat {
+                    address: socket.ip(),
+                    port: Some(socket.port()),
+                },
+                Err(_) => NatAction::Dnat {
+                    address: target.parse().map_err(|_| "Invalid address")?,
+                    port: None,
+                },
+            },
+            ("masquerade" | "snat" | "dnat", _) => return Err("Missing or misplaced NAT target"),
+            _ => return Err("NAT rule must start with masquerade, snat or dnat"),
+        };
+        Ok(NatRule {
+            matcher,
+            action,
+            comment,
+        })
+    }
+
+    pub fn firewall() -> &'static Arc<Mutex<Firewall>> {
+        static FIREWALL: OnceLock<Arc<Mutex<Firewall>>> = OnceLock::new();
+        FIREWALL.get_or_init(|| Arc::new(Mutex::new(Firewall::new())))
+    }
+
+    // Hooks the global firewall into the stack's RX and TX paths
+    pub fn init(net: &mut NetStack) {
+        log::info!("Initializing VXWall...");
+        net.set_packet_filter(Box::new(Arc::clone(firewall())));
+    }
+
+    pub fn add_rule(rule: &str) -> Result<RuleId, &'static str> {
+        let rule = parse_rule(rule)?;
+        Ok(firewall().lock().unwrap().add_rule(rule))
+    }
+
+    pub fn remove_rule(id: RuleId) -> Result<(), &'static str> {
+        firewall().lock().unwrap().remove_rule(id).map(|_| ())
     }
 
-    pub fn add_rule(rule: &str) {
-        println!("Adding firewall rule: {}", rule);
-        // Add a firewall rule
+    pub fn list_rules() -> Vec<(RuleId, Rule, RuleCounters)> {
+        firewall().lock().unwrap().rules()
     }
 
-    pub fn remove_rule(rule: &str) {
-        println!("Removing firewall rule: {}", rule);
-        // Remove a firewall rule
+    pub fn add_nat_rule(rule: &str) -> Result<RuleId, &'static str> {
+        let rule = parse_nat_rule(rule)?;
+        Ok(firewall().lock().unwrap().add_nat_rule(rule))
     }
 
-    pub fn list_rules() {
-        println!("Listing firewall rules...");
-        // List all firewall rules
+    pub fn remove_nat_rule(id: RuleId) -> Result<(), &'static str> {
+        firewall().lock().unwrap().remove_nat_rule(id).map(|_| ())
     }
 
-    pub fn update() {
-        println!("Updating VXWall...");
-        // Update the VXWall system
+    pub fn list_nat_rules() -> Vec<(RuleId, NatRule, RuleCounters)> {
+        firewall().lock().unwrap().nat_rules()
     }
 }
@@ -1,5 +1,6 @@
 // src/package/mod.rs
 
 pub mod vxp_installer;
-pub mod vxtoml;
 pub mod vxp_security;
+pub mod vxpkg;
+pub mod vxtoml;
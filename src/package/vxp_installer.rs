@@ -1,26 +1,216 @@
+// src/package/vxp_installer.rs
+
 pub mod vxp_installer {
-    pub fn init() {
-        println!("Initializing VXP Installer...");
-        // Initialize the VXP Installer system
-    }
+    use crate::vxpkg::vxpkg::{self, Manifest, Package, Plan, Version};
+    use ed25519_dalek::VerifyingKey;
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::fs;
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use vaelix_core::vxfs::vxfs::VXFS;
 
-    pub fn install_package(package: &str) {
-        println!("Installing package: {}", package);
-        // Install a package
+    // One manifest per installed package, named after it
+    pub const DATABASE_DIR: &str = "var/lib/vxpkg";
+
+    // Every file a transaction touches, as it was before, to put back if
+    // a later step fails
+    struct Transaction<'a> {
+        vxfs: &'a mut VXFS,
+        undo: Vec<(PathBuf, Option<Vec<u8>>)>,
+        saved: BTreeSet<PathBuf>,
     }
 
-    pub fn uninstall_package(package: &str) {
-        println!("Uninstalling package: {}", package);
-        // Uninstall a package
+    impl Transaction<'_> {
+        fn save(&mut self, path: &Path) -> io::Result<()> {
+            if !self.saved.insert(path.to_path_buf()) {
+                return Ok(());
+            }
+            let old = match fs::read(path) {
+                Ok(data) => Some(data),
+                Err(error) if error.kind() == io::ErrorKind::NotFound => None,
+                Err(error) => return Err(error),
+            };
+            self.undo.push((path.to_path_buf(), old));
+            Ok(())
+        }
+
+        fn write(&mut self, path: &Path, data: &[u8]) -> io::Result<()> {
+            self.save(path)?;
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            self.vxfs.write_bytes(path.to_str().ok_or(io::ErrorKind::InvalidInput)?, data)
+        }
+
+        fn delete(&mut self, path: &Path) -> io::Result<()> {
+            self.save(path)?;
+            match fs::remove_file(path) {
+                Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+                _ => Ok(()),
+            }
+        }
+
+        // Best effort: a file that cannot be put back is reported and the
+        // rest still are
+        fn rollback(self) {
+            for (path, old) in self.undo.into_iter().rev() {
+                let result = match old {
+                    Some(data) => path.parent().map_or(Ok(()), fs::create_dir_all).and_then(|_| fs::write(&path, data)),
+                    None => fs::remove_file(&path).or_else(|error| match error.kind() {
+                        io::ErrorKind::NotFound => Ok(()),
+                        _ => Err(error),
+                    }),
+                };
+                if let Err(error) = result {
+                    log::error!("Could not restore {}: {}", path.display(), error);
+                }
+            }
+        }
     }
 
-    pub fn list_installed_packages() {
-        println!("Listing installed packages...");
-        // List all installed packages
+    // Installs packages under a root, keeping track of what is there in
+    // the database
+    pub struct Installer {
+        root: PathBuf,
+        vxfs: VXFS,
+        keys: Vec<VerifyingKey>,
+        installed: BTreeMap<String, Manifest>,
+        // Packages that have been checked and can be installed
+        available: BTreeMap<(String, Version), Package>,
     }
 
-    pub fn update() {
-        println!("Updating VXP Installer...");
-        // Update the VXP Installer system
+    impl Installer {
+        // Packages are only accepted signed by one of keys
+        pub fn open(root: &str, keys: Vec<VerifyingKey>) -> Result<Self, &'static str> {
+            let mut vxfs = VXFS::new();
+            let mut installed = BTreeMap::new();
+            let database = Path::new(root).join(DATABASE_DIR);
+            let names = match vxfs.list_dir(database.to_str().ok_or("Invalid root")?) {
+                Ok(names) => names,
+                Err(error) if error.kind() == io::ErrorKind::NotFound => Vec::new(),
+                Err(_) => return Err("Failed to read package database"),
+            };
+            for name in names.iter().filter_map(|name| name.strip_suffix(".toml")) {
+                let path = database.join(format!("{}.toml", name));
+                let text = vxfs.read_file(path.to_str().unwrap()).map_err(|_| "Failed to read package database")?;
+                let manifest = Manifest::parse(&text)?;
+                if manifest.name != name {
+                    return Err("Corrupt package database");
+                }
+                installed.insert(manifest.name.clone(), manifest);
+            }
+            Ok(Installer {
+                root: PathBuf::from(root),
+                vxfs,
+                keys,
+                installed,
+                available: BTreeMap::new(),
+            })
+        }
+
+        pub fn installed(&self) -> &BTreeMap<String, Manifest> {
+            &self.installed
+        }
+
+        // Checks a package archive and makes it available to install
+        pub fn add_package(&mut self, archive: &[u8]) -> Result<Manifest, &'static str> {
+            let package = Package::open(archive, &self.keys)?;
+            let manifest = package.manifest.clone();
+            self.available.insert((manifest.name.clone(), manifest.version), package);
+            Ok(manifest)
+        }
+
+        pub fn plan(&self, install: &[&str], remove: &[&str]) -> Result<Plan, &'static str> {
+            let available: Vec<Manifest> = self.available.values().map(|package| package.manifest.clone()).collect();
+            vxpkg::resolve(&self.installed, &available, install, remove)
+        }
+
+        pub fn install(&mut self, names: &[&str]) -> Result<Plan, &'static str> {
+            let plan = self.plan(names, &[])?;
+            self.apply(&plan)?;
+            Ok(plan)
+        }
+
+        // Every installed package with a newer version available
+        pub fn upgrade(&mut self) -> Result<Plan, &'static str> {
+            let names: Vec<&str> = self
+                .installed
+                .values()
+                .filter(|manifest| self.available.keys().any(|(name, version)| *name == manifest.name && *version > manifest.version))
+                .map(|manifest| manifest.name.as_str())
+                .collect();
+            let plan = self.plan(&names, &[])?;
+            self.apply(&plan)?;
+            Ok(plan)
+        }
+
+        pub fn remove(&mut self, names: &[&str]) -> Result<Plan, &'static str> {
+            let plan = self.plan(&[], names)?;
+            self.apply(&plan)?;
+            Ok(plan)
+        }
+
+        // All of the plan or, if any step fails, none of it
+        pub fn apply(&mut self, plan: &Plan) -> Result<(), &'static str> {
+            let replaced: BTreeSet<&str> = plan.remove.iter().map(String::as_str).chain(plan.install.iter().map(|manifest| manifest.name.as_str())).collect();
+            let mut owners: BTreeMap<&str, &str> = BTreeMap::new();
+            for manifest in self.installed.values().filter(|manifest| !replaced.contains(manifest.name.as_str())) {
+                owners.extend(manifest.files.keys().map(|path| (path.as_str(), manifest.name.as_str())));
+            }
+            for manifest in &plan.install {
+                if !self.available.contains_key(&(manifest.name.clone(), manifest.version)) {
+                    return Err("Package not available");
+                }
+                for path in manifest.files.keys() {
+                    if let Some(owner) = owners.insert(path, &manifest.name) {
+                        log::warn!("{} from {} belongs to {}", path, manifest.name, owner);
+                        return Err("File belongs to another package");
+                    }
+                }
+            }
+
+            let mut transaction = Transaction {
+                vxfs: &mut self.vxfs,
+                undo: Vec::new(),
+                saved: BTreeSet::new(),
+            };
+            let database = self.root.join(DATABASE_DIR);
+            let result = (|| -> io::Result<()> {
+                for name in &plan.remove {
+                    for path in self.installed[name].files.keys() {
+                        transaction.delete(&self.root.join(path))?;
+                    }
+                    transaction.delete(&database.join(format!("{}.toml", name)))?;
+                    log::info!("Removed {}", name);
+                }
+                for manifest in &plan.install {
+                    let package = &self.available[&(manifest.name.clone(), manifest.version)];
+                    if let Some(old) = self.installed.get(&manifest.name) {
+                        for path in old.files.keys().filter(|path| !manifest.files.contains_key(*path)) {
+                            transaction.delete(&self.root.join(path))?;
+                        }
+                    }
+                    for (path, data) in &package.files {
+                        transaction.write(&self.root.join(path), data)?;
+                    }
+                    transaction.write(&database.join(format!("{}.toml", manifest.name)), manifest.to_text().as_bytes())?;
+                    log::info!("Installed {} {}", manifest.name, manifest.version);
+                }
+                Ok(())
+            })();
+            if let Err(error) = result {
+                log::warn!("Transaction failed, rolling back: {}", error);
+                transaction.rollback();
+                return Err("Package transaction failed");
+            }
+
+            for name in &plan.remove {
+                self.installed.remove(name);
+            }
+            for manifest in &plan.install {
+                self.installed.insert(manifest.name.clone(), manifest.clone());
+            }
+            Ok(())
+        }
     }
 }
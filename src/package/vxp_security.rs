@@ -1,29 +1,32 @@
+// src/package/vxp_security.rs
+
 pub mod vxp_security {
-    pub fn init() {
-        println!("Initializing VXP Security...");
-        // Initialize the VXP Security system
-    }
+    use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+    use sha2::{Digest, Sha256};
+
+    pub const SIGNATURE_SIZE: usize = 64;
 
-    pub fn verify_checksum(package: &str, _checksum: &str) -> bool {
-        println!("Verifying checksum for package: {}", package);
-        // Verify the checksum of the package
-        true
+    // SHA-256 in lowercase hex, as manifests list file contents
+    pub fn checksum(data: &[u8]) -> String {
+        format!("{:x}", Sha256::digest(data))
     }
 
-    pub fn sign_package(package: &str) -> Result<(), String> {
-        println!("Signing package: {}", package);
-        // Sign the package
-        Ok(())
+    pub fn verify_checksum(data: &[u8], expected: &str) -> bool {
+        checksum(data) == expected
     }
 
-    pub fn verify_signature(package: &str) -> Result<(), String> {
-        println!("Verifying signature for package: {}", package);
-        // Verify the signature of the package
-        Ok(())
+    // Packages are signed over their manifest, which in turn pins every
+    // file by hash
+    pub fn sign_manifest(key: &SigningKey, manifest: &str) -> [u8; SIGNATURE_SIZE] {
+        key.sign(manifest.as_bytes()).to_bytes()
     }
 
-    pub fn update() {
-        println!("Updating VXP Security...");
-        // Update the VXP Security system
+    // Good if any of the trusted keys made the signature
+    pub fn verify_signature(keys: &[VerifyingKey], manifest: &str, signature: &[u8; SIGNATURE_SIZE]) -> Result<(), &'static str> {
+        let signature = Signature::from_bytes(signature);
+        match keys.iter().any(|key| key.verify(manifest.as_bytes(), &signature).is_ok()) {
+            true => Ok(()),
+            false => Err("Bad package signature"),
+        }
     }
 }
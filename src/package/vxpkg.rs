@@ -0,0 +1,400 @@
+// src/package/vxpkg.rs
+
+pub mod vxpkg {
+    use crate::vxp_security::vxp_security::{self, SIGNATURE_SIZE};
+    use crate::vxtoml::vxtoml::{self, Table, Value};
+    use ed25519_dalek::{SigningKey, VerifyingKey};
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::fmt;
+    use std::path::{Component, Path};
+    use std::str::FromStr;
+    use vaelix_core::power::hibernate::hibernate::{compress, decompress};
+
+    pub const PACKAGE_MAGIC: &[u8] = b"VXPKG 1\n";
+    pub const MAX_MANIFEST_LEN: usize = 64 * 1024;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct Version(pub u32, pub u32, pub u32);
+
+    impl FromStr for Version {
+        type Err = &'static str;
+
+        fn from_str(text: &str) -> Result<Self, Self::Err> {
+            let parts: Vec<u32> = text.split('.').map(|part| part.parse().map_err(|_| "Invalid version")).collect::<Result<_, _>>()?;
+            match parts[..] {
+                [major, minor, patch] => Ok(Version(major, minor, patch)),
+                _ => Err("Invalid version"),
+            }
+        }
+    }
+
+    impl fmt::Display for Version {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{}.{}.{}", self.0, self.1, self.2)
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Comparison {
+        Less,
+        LessOrEqual,
+        Equal,
+        GreaterOrEqual,
+        Greater,
+    }
+
+    impl Comparison {
+        fn symbol(self) -> &'static str {
+            match self {
+                Comparison::Less => "<",
+                Comparison::LessOrEqual => "<=",
+                Comparison::Equal => "=",
+                Comparison::GreaterOrEqual => ">=",
+                Comparison::Greater => ">",
+            }
+        }
+    }
+
+    // A package name, and maybe which versions of it: "vxfont",
+    // "libvx >= 1.2.0". Dependencies and conflicts are both lists of them.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Constraint {
+        pub name: String,
+        pub version: Option<(Comparison, Version)>,
+    }
+
+    impl Constraint {
+        pub fn parse(text: &str) -> Result<Self, &'static str> {
+            let words: Vec<&str> = text.split_whitespace().collect();
+            let (name, version) = match words[..] {
+                [name] => (name, None),
+                [name, comparison, version] => {
+                    let comparison = match comparison {
+                        "<" => Comparison::Less,
+                        "<=" => Comparison::LessOrEqual,
+                        "=" => Comparison::Equal,
+                        ">=" => Comparison::GreaterOrEqual,
+                        ">" => Comparison::Greater,
+                        _ => return Err("Invalid version comparison"),
+                    };
+                    (name, Some((comparison, version.parse()?)))
+                }
+                _ => return Err("Malformed package constraint"),
+            };
+            check_name(name)?;
+            Ok(Constraint { name: name.to_string(), version })
+        }
+
+        pub fn allows(&self, version: Version) -> bool {
+            match self.version {
+                None => true,
+                Some((Comparison::Less, bound)) => version < bound,
+                Some((Comparison::LessOrEqual, bound)) => version <= bound,
+                Some((Comparison::Equal, bound)) => version == bound,
+                Some((Comparison::GreaterOrEqual, bound)) => version >= bound,
+                Some((Comparison::Greater, bound)) => version > bound,
+            }
+        }
+
+        pub fn matches(&self, manifest: &Manifest) -> bool {
+            self.name == manifest.name && self.allows(manifest.version)
+        }
+    }
+
+    impl fmt::Display for Constraint {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self.version {
+                Some((comparison, version)) => write!(f, "{} {} {}", self.name, comparison.symbol(), version),
+                None => write!(f, "{}", self.name),
+            }
+        }
+    }
+
+    // Names double as database file names
+    fn check_name(name: &str) -> Result<(), &'static str> {
+        match !name.is_empty() && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_') {
+            true => Ok(()),
+            false => Err("Invalid package name"),
+        }
+    }
+
+    // Files go under the root they are installed to and nowhere else
+    fn check_path(path: &str) -> Result<(), &'static str> {
+        let path = Path::new(path);
+        match path.components().next().is_some() && path.components().all(|component| matches!(component, Component::Normal(_))) {
+            true => Ok(()),
+            false => Err("Invalid file path in package"),
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct FileEntry {
+        pub size: u64,
+        pub sha256: String,
+    }
+
+    // What a package is, needs and holds:
+    //   name = "vxterm"
+    //   version = "1.2.0"
+    //   description = "Terminal emulator"
+    //   depends = ["libvx >= 1.0.0", "vxfont"]
+    //   conflicts = ["oldterm"]
+    //
+    //   [files]
+    //   "usr/bin/vxterm" = [SIZE, "SHA256"]
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Manifest {
+        pub name: String,
+        pub version: Version,
+        pub description: String,
+        pub depends: Vec<Constraint>,
+        pub conflicts: Vec<Constraint>,
+        // By path under the root
+        pub files: BTreeMap<String, FileEntry>,
+    }
+
+    impl Manifest {
+        pub fn new(name: &str, version: Version) -> Self {
+            Manifest {
+                name: name.to_string(),
+                version,
+                description: String::new(),
+                depends: Vec::new(),
+                conflicts: Vec::new(),
+                files: BTreeMap::new(),
+            }
+        }
+
+        pub fn parse(text: &str) -> Result<Self, &'static str> {
+            let table = vxtoml::parse(text)?;
+            let string = |key: &str| table.get(key).map(|value| value.as_str().ok_or("Expected a string in manifest")).transpose();
+            let constraints = |key: &str| -> Result<Vec<Constraint>, &'static str> {
+                let Some(value) = table.get(key) else {
+                    return Ok(Vec::new());
+                };
+                let values = value.as_array().ok_or("Expected a list in manifest")?;
+                values.iter().map(|value| Constraint::parse(value.as_str().ok_or("Expected a string in manifest")?)).collect()
+            };
+            let name = string("name")?.ok_or("Missing package name")?;
+            check_name(name)?;
+            let mut manifest = Manifest::new(name, string("version")?.ok_or("Missing package version")?.parse()?);
+            manifest.description = string("description")?.unwrap_or_default().to_string();
+            manifest.depends = constraints("depends")?;
+            manifest.conflicts = constraints("conflicts")?;
+            let files = match table.get("files") {
+                Some(value) => value.as_table().ok_or("Expected a [files] section")?.clone(),
+                None => Table::new(),
+            };
+            for (path, value) in files {
+                check_path(&path)?;
+                let entry = match value.as_array() {
+                    Some([size, sha256]) => FileEntry {
+                        size: size.as_integer().and_then(|size| u64::try_from(size).ok()).ok_or("Invalid file size")?,
+                        sha256: sha256.as_str().ok_or("Invalid file hash")?.to_string(),
+                    },
+                    _ => return Err("Malformed file entry"),
+                };
+                manifest.files.insert(path, entry);
+            }
+            if let Some(key) = table.keys().find(|key| !["name", "version", "description", "depends", "conflicts", "files"].contains(&key.as_str())) {
+                log::warn!("Unknown manifest key {}", key);
+                return Err("Unknown manifest key");
+            }
+            Ok(manifest)
+        }
+
+        pub fn to_text(&self) -> String {
+            let list = |constraints: &[Constraint]| Value::Array(constraints.iter().map(|constraint| Value::String(constraint.to_string())).collect());
+            let mut table = Table::new();
+            table.insert("name".to_string(), Value::String(self.name.clone()));
+            table.insert("version".to_string(), Value::String(self.version.to_string()));
+            if !self.description.is_empty() {
+                table.insert("description".to_string(), Value::String(self.description.clone()));
+            }
+            if !self.depends.is_empty() {
+                table.insert("depends".to_string(), list(&self.depends));
+            }
+            if !self.conflicts.is_empty() {
+                table.insert("conflicts".to_string(), list(&self.conflicts));
+            }
+            let files = self
+                .files
+                .iter()
+                .map(|(path, entry)| (path.clone(), Value::Array(vec![Value::Integer(entry.size as i64), Value::String(entry.sha256.clone())])))
+                .collect();
+            table.insert("files".to_string(), Value::Table(files));
+            vxtoml::serialize(&table).unwrap()
+        }
+    }
+
+    // A package whose signature and file hashes have checked out
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Package {
+        pub manifest: Manifest,
+        pub files: BTreeMap<String, Vec<u8>>,
+    }
+
+    impl Package {
+        // PACKAGE_MAGIC, the manifest's length (u32, little-endian), the
+        // manifest, its signature, then every file's contents one after the
+        // other in path order, compressed as a whole
+        pub fn open(archive: &[u8], keys: &[VerifyingKey]) -> Result<Self, &'static str> {
+            let rest = archive.strip_prefix(PACKAGE_MAGIC).ok_or("Not a package")?;
+            let length = rest.get(..4).map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()) as usize).ok_or("Truncated package")?;
+            if length > MAX_MANIFEST_LEN {
+                return Err("Package manifest too long");
+            }
+            let text = rest.get(4..4 + length).ok_or("Truncated package")?;
+            let text = std::str::from_utf8(text).map_err(|_| "Malformed package manifest")?;
+            let signature: &[u8; SIGNATURE_SIZE] = rest.get(4 + length..4 + length + SIGNATURE_SIZE).ok_or("Truncated package")?.try_into().unwrap();
+            vxp_security::verify_signature(keys, text, signature)?;
+            let manifest = Manifest::parse(text)?;
+
+            let payload = decompress(&rest[4 + length + SIGNATURE_SIZE..])?;
+            if payload.len() as u64 != manifest.files.values().map(|entry| entry.size).sum::<u64>() {
+                return Err("Package contents do not match its manifest");
+            }
+            let mut files = BTreeMap::new();
+            let mut offset = 0;
+            for (path, entry) in &manifest.files {
+                let data = &payload[offset..offset + entry.size as usize];
+                if !vxp_security::verify_checksum(data, &entry.sha256) {
+                    return Err("Package file hash mismatch");
+                }
+                files.insert(path.clone(), data.to_vec());
+                offset += entry.size as usize;
+            }
+            Ok(Package { manifest, files })
+        }
+    }
+
+    // How packaging tooling makes one: the manifest's file list is filled
+    // in from files
+    pub fn build_package(key: &SigningKey, mut manifest: Manifest, files: &[(&str, &[u8])]) -> Result<Vec<u8>, &'static str> {
+        let files: BTreeMap<&str, &[u8]> = files.iter().copied().collect();
+        manifest.files.clear();
+        let mut payload = Vec::new();
+        for (path, data) in &files {
+            check_path(path)?;
+            manifest.files.insert(
+                path.to_string(),
+                FileEntry {
+                    size: data.len() as u64,
+                    sha256: vxp_security::checksum(data),
+                },
+            );
+            payload.extend_from_slice(data);
+        }
+        let text = manifest.to_text();
+        let mut archive = PACKAGE_MAGIC.to_vec();
+        archive.extend_from_slice(&(text.len() as u32).to_le_bytes());
+        archive.extend_from_slice(text.as_bytes());
+        archive.extend_from_slice(&vxp_security::sign_manifest(key, &text));
+        archive.extend_from_slice(&compress(&payload));
+        Ok(archive)
+    }
+
+    // What a transaction does to get from what is installed to what was
+    // asked for
+    #[derive(Debug, Clone, Default, PartialEq, Eq)]
+    pub struct Plan {
+        // New packages and new versions of installed ones, each after
+        // what it depends on
+        pub install: Vec<Manifest>,
+        // Each before what it depends on
+        pub remove: Vec<String>,
+    }
+
+    impl Plan {
+        pub fn is_empty(&self) -> bool {
+            self.install.is_empty() && self.remove.is_empty()
+        }
+    }
+
+    // What the other packages there ask of name
+    fn required<'a>(packages: &'a BTreeMap<String, Manifest>, name: &str) -> Vec<&'a Constraint> {
+        packages.values().filter(|manifest| manifest.name != name).flat_map(|manifest| &manifest.depends).filter(|constraint| constraint.name == name).collect()
+    }
+
+    fn newest<'a>(available: &'a [Manifest], name: &str, constraints: &[&Constraint]) -> Option<&'a Manifest> {
+        available.iter().filter(|manifest| manifest.name == name && constraints.iter().all(|constraint| constraint.allows(manifest.version))).max_by_key(|manifest| manifest.version)
+    }
+
+    // Names in the set, each after those of its dependencies also in it
+    fn dependency_order(packages: &BTreeMap<String, Manifest>, names: &BTreeSet<String>) -> Vec<String> {
+        fn visit(packages: &BTreeMap<String, Manifest>, names: &BTreeSet<String>, name: &str, seen: &mut BTreeSet<String>, order: &mut Vec<String>) {
+            if !names.contains(name) || !seen.insert(name.to_string()) {
+                return;
+            }
+            for constraint in &packages[name].depends {
+                visit(packages, names, &constraint.name, seen, order);
+            }
+            order.push(name.to_string());
+        }
+        let (mut seen, mut order) = (BTreeSet::new(), Vec::new());
+        for name in names {
+            visit(packages, names, name, &mut seen, &mut order);
+        }
+        order
+    }
+
+    // Installs the newest allowed version of each package in install, and
+    // of whatever it needs that is missing or too old, and removes those in
+    // remove. Choices are not revisited: when the newest version of one
+    // package rules out every version of another, resolution fails rather
+    // than trying older ones.
+    pub fn resolve(installed: &BTreeMap<String, Manifest>, available: &[Manifest], install: &[&str], remove: &[&str]) -> Result<Plan, &'static str> {
+        let mut target = installed.clone();
+        for name in remove {
+            target.remove(*name).ok_or("Package not installed")?;
+        }
+        let mut queue = Vec::new();
+        for name in install {
+            let choice = newest(available, name, &required(&target, name)).ok_or("No installable version of package")?;
+            if target.get(*name) != Some(choice) {
+                target.insert(name.to_string(), choice.clone());
+                queue.push(name.to_string());
+            }
+        }
+        while let Some(name) = queue.pop() {
+            for constraint in target[&name].depends.clone() {
+                if target.get(&constraint.name).is_some_and(|manifest| constraint.matches(manifest)) {
+                    continue;
+                }
+                let Some(choice) = newest(available, &constraint.name, &required(&target, &constraint.name)) else {
+                    log::info!("Nothing satisfies {} for {}", constraint, name);
+                    return Err("Unsatisfiable dependency");
+                };
+                target.insert(constraint.name.clone(), choice.clone());
+                queue.push(constraint.name.clone());
+            }
+        }
+
+        for manifest in target.values() {
+            for constraint in &manifest.depends {
+                if !target.get(&constraint.name).is_some_and(|other| constraint.matches(other)) {
+                    log::info!("{} needs {}", manifest.name, constraint);
+                    return Err(match remove.contains(&constraint.name.as_str()) {
+                        true => "Package is needed by another",
+                        false => "Unsatisfiable dependency",
+                    });
+                }
+            }
+            for constraint in &manifest.conflicts {
+                if target.get(&constraint.name).is_some_and(|other| other.name != manifest.name && constraint.matches(other)) {
+                    log::info!("{} conflicts with {}", manifest.name, constraint);
+                    return Err("Conflicting packages");
+                }
+            }
+        }
+
+        let changed = target.iter().filter(|(name, manifest)| installed.get(*name) != Some(*manifest)).map(|(name, _)| name.clone()).collect();
+        let removed = installed.keys().filter(|name| !target.contains_key(*name)).cloned().collect();
+        let mut remove = dependency_order(installed, &removed);
+        remove.reverse();
+        Ok(Plan {
+            install: dependency_order(&target, &changed).iter().map(|name| target[name].clone()).collect(),
+            remove,
+        })
+    }
+}
@@ -1,23 +1,261 @@
+// src/package/vxtoml.rs
+
 pub mod vxtoml {
-    pub fn init() {
-        println!("Initializing VXTOML...");
-        // Initialize the VXTOML system
+    use std::collections::BTreeMap;
+    use std::fmt::Write;
+
+    // The part of TOML package manifests use: strings, integers, booleans
+    // and arrays of them, on one line each, and one level of [sections].
+    // Keys are bare (letters, digits, - and _) or quoted.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum Value {
+        String(String),
+        Integer(i64),
+        Boolean(bool),
+        Array(Vec<Value>),
+        Table(Table),
+    }
+
+    pub type Table = BTreeMap<String, Value>;
+
+    impl Value {
+        pub fn as_str(&self) -> Option<&str> {
+            match self {
+                Value::String(text) => Some(text),
+                _ => None,
+            }
+        }
+
+        pub fn as_integer(&self) -> Option<i64> {
+            match self {
+                Value::Integer(number) => Some(*number),
+                _ => None,
+            }
+        }
+
+        pub fn as_array(&self) -> Option<&[Value]> {
+            match self {
+                Value::Array(values) => Some(values),
+                _ => None,
+            }
+        }
+
+        pub fn as_table(&self) -> Option<&Table> {
+            match self {
+                Value::Table(table) => Some(table),
+                _ => None,
+            }
+        }
+    }
+
+    fn is_bare_key(key: &str) -> bool {
+        !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    }
+
+    struct Cursor<'a> {
+        text: &'a str,
+    }
+
+    impl<'a> Cursor<'a> {
+        fn skip_spaces(&mut self) {
+            self.text = self.text.trim_start_matches([' ', '\t']);
+        }
+
+        fn eat(&mut self, c: char) -> bool {
+            self.skip_spaces();
+            match self.text.strip_prefix(c) {
+                Some(rest) => {
+                    self.text = rest;
+                    true
+                }
+                None => false,
+            }
+        }
+
+        // Only a comment may follow
+        fn end(&mut self) -> Result<(), &'static str> {
+            self.skip_spaces();
+            match self.text.is_empty() || self.text.starts_with('#') {
+                true => Ok(()),
+                false => Err("Unexpected text after value"),
+            }
+        }
+
+        fn string(&mut self) -> Result<String, &'static str> {
+            let mut chars = self.text.char_indices();
+            let mut text = String::new();
+            while let Some((index, c)) = chars.next() {
+                match c {
+                    '"' => {
+                        self.text = &self.text[index + 1..];
+                        return Ok(text);
+                    }
+                    '\\' => text.push(match chars.next().map(|(_, c)| c) {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        _ => return Err("Invalid escape in string"),
+                    }),
+                    c => text.push(c),
+                }
+            }
+            Err("Unterminated string")
+        }
+
+        fn key(&mut self) -> Result<String, &'static str> {
+            self.skip_spaces();
+            if let Some(rest) = self.text.strip_prefix('"') {
+                self.text = rest;
+                return self.string();
+            }
+            let end = self.text.find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_')).unwrap_or(self.text.len());
+            let (key, rest) = self.text.split_at(end);
+            if key.is_empty() {
+                return Err("Missing key");
+            }
+            self.text = rest;
+            Ok(key.to_string())
+        }
+
+        fn value(&mut self) -> Result<Value, &'static str> {
+            self.skip_spaces();
+            if let Some(rest) = self.text.strip_prefix('"') {
+                self.text = rest;
+                return self.string().map(Value::String);
+            }
+            if let Some(rest) = self.text.strip_prefix('[') {
+                self.text = rest;
+                let mut values = Vec::new();
+                // A trailing comma is allowed
+                while !self.eat(']') {
+                    values.push(self.value()?);
+                    if !self.eat(',') {
+                        if !self.eat(']') {
+                            return Err("Unterminated array");
+                        }
+                        break;
+                    }
+                }
+                return Ok(Value::Array(values));
+            }
+            let end = self.text.find([',', ']', ' ', '\t', '#']).unwrap_or(self.text.len());
+            let (word, rest) = self.text.split_at(end);
+            self.text = rest;
+            match word {
+                "true" => Ok(Value::Boolean(true)),
+                "false" => Ok(Value::Boolean(false)),
+                _ => word.replace('_', "").parse().map(Value::Integer).map_err(|_| "Invalid value"),
+            }
+        }
     }
 
-    pub fn parse_toml(_content: &str) -> Result<(), String> {
-        println!("Parsing TOML content...");
-        // Parse the TOML content
+    pub fn parse(text: &str) -> Result<Table, &'static str> {
+        let mut root = Table::new();
+        let mut section: Option<String> = None;
+        for line in text.lines() {
+            let mut cursor = Cursor { text: line };
+            cursor.skip_spaces();
+            if cursor.text.is_empty() || cursor.text.starts_with('#') {
+                continue;
+            }
+            if cursor.eat('[') {
+                let name = cursor.key()?;
+                if !cursor.eat(']') {
+                    return Err("Malformed section header");
+                }
+                cursor.end()?;
+                if root.insert(name.clone(), Value::Table(Table::new())).is_some() {
+                    return Err("Duplicate key");
+                }
+                section = Some(name);
+                continue;
+            }
+            let key = cursor.key()?;
+            if !cursor.eat('=') {
+                return Err("Missing = after key");
+            }
+            let value = cursor.value()?;
+            cursor.end()?;
+            let table = match &section {
+                Some(name) => match root.get_mut(name) {
+                    Some(Value::Table(table)) => table,
+                    _ => unreachable!(),
+                },
+                None => &mut root,
+            };
+            if table.insert(key, value).is_some() {
+                return Err("Duplicate key");
+            }
+        }
+        Ok(root)
+    }
+
+    fn write_key(output: &mut String, key: &str) {
+        match is_bare_key(key) {
+            true => output.push_str(key),
+            false => write_string(output, key),
+        }
+    }
+
+    fn write_string(output: &mut String, text: &str) {
+        output.push('"');
+        for c in text.chars() {
+            match c {
+                '"' => output.push_str("\\\""),
+                '\\' => output.push_str("\\\\"),
+                '\n' => output.push_str("\\n"),
+                '\t' => output.push_str("\\t"),
+                c => output.push(c),
+            }
+        }
+        output.push('"');
+    }
+
+    fn write_value(output: &mut String, value: &Value) -> Result<(), &'static str> {
+        match value {
+            Value::String(text) => write_string(output, text),
+            Value::Integer(number) => write!(output, "{}", number).unwrap(),
+            Value::Boolean(flag) => write!(output, "{}", flag).unwrap(),
+            Value::Array(values) => {
+                output.push('[');
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        output.push_str(", ");
+                    }
+                    write_value(output, value)?;
+                }
+                output.push(']');
+            }
+            // Only sections hold tables
+            Value::Table(_) => return Err("Tables nest one level deep"),
+        }
         Ok(())
     }
 
-    pub fn serialize_toml(_data: &str) -> Result<String, String> {
-        println!("Serializing TOML data...");
-        // Serialize the TOML data
-        Ok(String::from("Serialized TOML"))
+    fn write_entry(output: &mut String, key: &str, value: &Value) -> Result<(), &'static str> {
+        write_key(output, key);
+        output.push_str(" = ");
+        write_value(output, value)?;
+        output.push('\n');
+        Ok(())
     }
 
-    pub fn update() {
-        println!("Updating VXTOML...");
-        // Update the VXTOML system
+    // Keys in order, plain ones before sections, so the same table always
+    // comes out as the same text
+    pub fn serialize(table: &Table) -> Result<String, &'static str> {
+        let mut output = String::new();
+        for (key, value) in table.iter().filter(|(_, value)| value.as_table().is_none()) {
+            write_entry(&mut output, key, value)?;
+        }
+        for (name, section) in table.iter().filter_map(|(name, value)| value.as_table().map(|section| (name, section))) {
+            output.push_str("\n[");
+            write_key(&mut output, name);
+            output.push_str("]\n");
+            for (key, value) in section {
+                write_entry(&mut output, key, value)?;
+            }
+        }
+        Ok(output)
     }
 }
@@ -4,3 +4,10 @@ pub mod vxui_toolkit;
 pub mod vxde;
 pub mod vxanim;
 pub mod vxnotification;
+pub mod vxterm;
+pub mod vxpanel;
+pub mod vxwallpaper;
+pub mod vxlock;
+pub mod vxhud;
+pub mod vxime;
+pub mod vxsnapshot;
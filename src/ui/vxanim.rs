@@ -1,26 +1,347 @@
+// src/ui/vxanim.rs
+
 pub mod vxanim {
-    pub fn init() {
-        println!("Initializing VXAnim...");
-        // Initialize the VXAnim system
+    use crate::vxui_toolkit::vxui_toolkit::{WidgetId, WidgetTree};
+    use vaelix_graphics::vxwin::vxwin::{Compositor, Transform, WindowId};
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Easing {
+        Linear,
+        // Control points of a CSS-style curve from (0, 0) to (1, 1)
+        CubicBezier(f32, f32, f32, f32),
+        // A unit mass pulled to the target; time runs 0..1 over the
+        // animation, so stiffness and damping are per duration.
+        // Underdamped springs overshoot.
+        Spring { stiffness: f32, damping: f32 },
+    }
+
+    pub const EASE: Easing = Easing::CubicBezier(0.25, 0.1, 0.25, 1.0);
+    pub const EASE_IN: Easing = Easing::CubicBezier(0.42, 0.0, 1.0, 1.0);
+    pub const EASE_OUT: Easing = Easing::CubicBezier(0.0, 0.0, 0.58, 1.0);
+    pub const EASE_IN_OUT: Easing = Easing::CubicBezier(0.42, 0.0, 0.58, 1.0);
+
+    impl Easing {
+        fn bezier(a: f32, b: f32, s: f32) -> f32 {
+            let inverse = 1.0 - s;
+            3.0 * inverse * inverse * s * a + 3.0 * inverse * s * s * b + s * s * s
+        }
+
+        fn bezier_slope(a: f32, b: f32, s: f32) -> f32 {
+            let inverse = 1.0 - s;
+            3.0 * inverse * inverse * a + 6.0 * inverse * s * (b - a) + 3.0 * s * s * (1.0 - b)
+        }
+
+        // Maps linear progress to eased progress; 0 and 1 are exact
+        pub fn apply(&self, t: f32) -> f32 {
+            if t <= 0.0 {
+                return 0.0;
+            }
+            if t >= 1.0 {
+                return 1.0;
+            }
+            match *self {
+                Easing::Linear => t,
+                Easing::CubicBezier(x1, y1, x2, y2) => {
+                    // Newton's method for the curve parameter at x = t,
+                    // falling back to bisection where the slope is flat
+                    let mut s = t;
+                    for _ in 0..8 {
+                        let slope = Self::bezier_slope(x1, x2, s);
+                        if slope.abs() < 1e-6 {
+                            break;
+                        }
+                        s -= (Self::bezier(x1, x2, s) - t) / slope;
+                    }
+                    if !(0.0..=1.0).contains(&s) || (Self::bezier(x1, x2, s) - t).abs() > 1e-4 {
+                        let (mut low, mut high) = (0.0, 1.0);
+                        for _ in 0..32 {
+                            s = (low + high) / 2.0;
+                            if Self::bezier(x1, x2, s) < t {
+                                low = s;
+                            } else {
+                                high = s;
+                            }
+                        }
+                    }
+                    Self::bezier(y1, y2, s)
+                }
+                Easing::Spring { stiffness, damping } => {
+                    let omega = stiffness.max(0.0).sqrt();
+                    let zeta = damping / (2.0 * omega.max(f32::EPSILON));
+                    if zeta < 1.0 {
+                        let damped = omega * (1.0 - zeta * zeta).sqrt();
+                        let decay = (-zeta * omega * t).exp();
+                        1.0 - decay * ((damped * t).cos() + zeta * omega / damped * (damped * t).sin())
+                    } else if zeta == 1.0 {
+                        1.0 - (1.0 + omega * t) * (-omega * t).exp()
+                    } else {
+                        let root = (zeta * zeta - 1.0).sqrt();
+                        let (fast, slow) = (-omega * (zeta + root), -omega * (zeta - root));
+                        1.0 - (fast * (slow * t).exp() - slow * (fast * t).exp()) / (fast - slow)
+                    }
+                }
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub enum Property {
+        TranslateX,
+        TranslateY,
+        Scale,
+        Opacity,
+    }
+
+    impl Property {
+        fn set(&self, transform: &mut Transform, value: f32) {
+            match self {
+                Property::TranslateX => transform.dx = value,
+                Property::TranslateY => transform.dy = value,
+                Property::Scale => transform.scale = value,
+                Property::Opacity => transform.opacity = value,
+            }
+        }
     }
 
-    pub fn start_animation(name: &str) {
-        println!("Starting animation: {}", name);
-        // Start an animation
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Keyframe {
+        // Fraction of the duration, 0..=1
+        pub offset: f32,
+        pub value: f32,
+        // Shapes the segment that ends at this keyframe
+        pub easing: Easing,
     }
 
-    pub fn stop_animation(name: &str) {
-        println!("Stopping animation: {}", name);
-        // Stop an animation
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Timeline {
+        duration: u64,
+        delay: u64,
+        // 0 repeats forever
+        iterations: u32,
+        alternate: bool,
+        tracks: BTreeMap<Property, Vec<Keyframe>>,
+    }
+
+    impl Timeline {
+        // Durations are in microseconds, like vblank timestamps
+        pub fn new(duration: u64) -> Self {
+            Timeline {
+                duration: duration.max(1),
+                delay: 0,
+                iterations: 1,
+                alternate: false,
+                tracks: BTreeMap::new(),
+            }
+        }
+
+        pub fn keyframe(mut self, property: Property, offset: f32, value: f32, easing: Easing) -> Self {
+            let track = self.tracks.entry(property).or_default();
+            let keyframe = Keyframe {
+                offset: offset.clamp(0.0, 1.0),
+                value,
+                easing,
+            };
+            let index = track.partition_point(|existing| existing.offset <= keyframe.offset);
+            track.insert(index, keyframe);
+            self
+        }
+
+        // Shorthand for a two-keyframe track
+        pub fn animate(self, property: Property, from: f32, to: f32, easing: Easing) -> Self {
+            self.keyframe(property, 0.0, from, Easing::Linear).keyframe(property, 1.0, to, easing)
+        }
+
+        pub fn delay(mut self, delay: u64) -> Self {
+            self.delay = delay;
+            self
+        }
+
+        // Every other iteration runs backwards when alternating
+        pub fn repeat(mut self, iterations: u32, alternate: bool) -> Self {
+            (self.iterations, self.alternate) = (iterations, alternate);
+            self
+        }
+
+        pub fn duration(&self) -> u64 {
+            self.duration
+        }
+
+        fn value(keyframes: &[Keyframe], progress: f32) -> Option<f32> {
+            let first = keyframes.first()?;
+            if progress <= first.offset {
+                return Some(first.value);
+            }
+            for pair in keyframes.windows(2) {
+                let (from, to) = (pair[0], pair[1]);
+                if progress <= to.offset {
+                    let span = to.offset - from.offset;
+                    let local = if span > 0.0 { (progress - from.offset) / span } else { 1.0 };
+                    return Some(from.value + (to.value - from.value) * to.easing.apply(local));
+                }
+            }
+            keyframes.last().map(|last| last.value)
+        }
+
+        // Progress through the current iteration at `elapsed`, and whether
+        // the timeline has run out
+        fn progress(&self, elapsed: u64) -> (f32, bool) {
+            let Some(active) = elapsed.checked_sub(self.delay) else {
+                return (0.0, false);
+            };
+            let iteration = active / self.duration;
+            let finished = self.iterations > 0 && iteration >= self.iterations as u64;
+            let (iteration, fraction) = if finished {
+                (self.iterations as u64 - 1, 1.0)
+            } else {
+                (iteration, (active % self.duration) as f32 / self.duration as f32)
+            };
+            let backwards = self.alternate && iteration % 2 == 1;
+            (if backwards { 1.0 - fraction } else { fraction }, finished)
+        }
+
+        pub fn sample(&self, elapsed: u64) -> Vec<(Property, f32)> {
+            let (progress, _) = self.progress(elapsed);
+            self.tracks
+                .iter()
+                .filter_map(|(property, keyframes)| Some((*property, Self::value(keyframes, progress)?)))
+                .collect()
+        }
     }
 
-    pub fn update_animation(name: &str) {
-        println!("Updating animation: {}", name);
-        // Update an animation
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub enum Target {
+        Window(WindowId),
+        Widget(WidgetId),
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct AnimationId(pub u32);
+
+    struct Animation {
+        target: Target,
+        timeline: Timeline,
+        // Taken from the first tick after starting
+        start: Option<u64>,
+    }
+
+    // Every transform that changed on one tick, to hand to the compositor
+    // and toolkit before the frame is composed
+    #[derive(Debug, Clone, Default, PartialEq)]
+    pub struct AnimationFrame {
+        pub windows: Vec<(WindowId, Transform)>,
+        pub widgets: Vec<(WidgetId, Transform)>,
+        pub finished: Vec<AnimationId>,
+    }
+
+    impl AnimationFrame {
+        pub fn is_empty(&self) -> bool {
+            self.windows.is_empty() && self.widgets.is_empty() && self.finished.is_empty()
+        }
+
+        // Windows that went away mid-animation are skipped
+        pub fn apply(&self, compositor: &mut Compositor) {
+            for (window, transform) in &self.windows {
+                let _ = compositor.set_transform(*window, *transform);
+            }
+        }
+
+        // Widgets only move; scale and opacity are left to windows
+        pub fn apply_widgets(&self, tree: &mut WidgetTree) {
+            for (widget, transform) in &self.widgets {
+                let _ = tree.set_translation(*widget, transform.dx.round() as i32, transform.dy.round() as i32);
+            }
+        }
+    }
+
+    // Advanced by the frame clock: each compositor frame ticks it with the
+    // vblank timestamp. Finished animations leave their last values in
+    // place.
+    pub struct Animator {
+        animations: BTreeMap<AnimationId, Animation>,
+        transforms: BTreeMap<Target, Transform>,
+        next_id: u32,
+    }
+
+    impl Animator {
+        pub fn new() -> Self {
+            Animator {
+                animations: BTreeMap::new(),
+                transforms: BTreeMap::new(),
+                next_id: 1,
+            }
+        }
+
+        // Animations started later win where they touch the same property
+        pub fn start(&mut self, target: Target, timeline: Timeline) -> AnimationId {
+            let id = AnimationId(self.next_id);
+            self.next_id += 1;
+            self.animations.insert(
+                id,
+                Animation {
+                    target,
+                    timeline,
+                    start: None,
+                },
+            );
+            id
+        }
+
+        // Freezes the target where the animation left it
+        pub fn stop(&mut self, id: AnimationId) -> Result<(), &'static str> {
+            self.animations.remove(&id).map(|_| ()).ok_or("Animation not found")
+        }
+
+        pub fn is_running(&self, id: AnimationId) -> bool {
+            self.animations.contains_key(&id)
+        }
+
+        pub fn is_idle(&self) -> bool {
+            self.animations.is_empty()
+        }
+
+        pub fn transform(&self, target: Target) -> Transform {
+            self.transforms.get(&target).copied().unwrap_or_default()
+        }
+
+        // Drops a target's animations and remembered transform, e.g. once
+        // its window is destroyed
+        pub fn forget(&mut self, target: Target) {
+            self.animations.retain(|_, animation| animation.target != target);
+            self.transforms.remove(&target);
+        }
+
+        pub fn tick(&mut self, now: u64) -> AnimationFrame {
+            let mut frame = AnimationFrame::default();
+            let mut changed = BTreeMap::new();
+            for (id, animation) in self.animations.iter_mut() {
+                let start = *animation.start.get_or_insert(now);
+                let elapsed = now.saturating_sub(start);
+                let transform = self.transforms.entry(animation.target).or_default();
+                for (property, value) in animation.timeline.sample(elapsed) {
+                    property.set(transform, value);
+                }
+                changed.insert(animation.target, *transform);
+                if animation.timeline.progress(elapsed).1 {
+                    frame.finished.push(*id);
+                }
+            }
+            for id in &frame.finished {
+                self.animations.remove(id);
+            }
+            for (target, transform) in changed {
+                match target {
+                    Target::Window(window) => frame.windows.push((window, transform)),
+                    Target::Widget(widget) => frame.widgets.push((widget, transform)),
+                }
+            }
+            frame
+        }
     }
 
-    pub fn update() {
-        println!("Updating VXAnim...");
-        // Update the VXAnim system
+    impl Default for Animator {
+        fn default() -> Self {
+            Self::new()
+        }
     }
 }
@@ -1,26 +1,648 @@
+// src/ui/vxde.rs
+
 pub mod vxde {
-    pub fn init() {
-        println!("Initializing VXDE...");
-        // Initialize the VXDE system
+    use crate::vxanim::vxanim::{AnimationId, Animator, Property, Target, Timeline, EASE_OUT};
+    use crate::vxui_toolkit::vxui_toolkit::{Align, Direction, Element, WidgetEvent, WidgetTree};
+    use vaelix_core::users::users::{self, User};
+    use vaelix_core::vxchan::vxchan::VXChanManager;
+    use vaelix_graphics::vxfont::vxfont::TextRenderer;
+    use vaelix_graphics::vxtheme::vxtheme::{Theme, Variant};
+    use vaelix_graphics::vxwin::vxwin::{Buffer, BufferStorage, Compositor, Transform, WindowId};
+    use vaelix_graphics::vxwm::vxwm::{SurfaceId, WindowManager, WindowState};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    pub const REQUEST_CHANNEL: &str = "vxde";
+    pub const REPLY_CHANNEL: &str = "vxde.reply";
+    // "started USER", "ended USER", "restarted COMPONENT" and
+    // "failed COMPONENT"
+    pub const EVENT_CHANNEL: &str = "vxde.events";
+
+    // A component that crashes more often than this within the window is
+    // given up on
+    pub const MAX_RESTARTS: usize = 3;
+    pub const RESTART_WINDOW: u64 = 60_000_000;
+    // Doubled for each crash within the window
+    pub const RESTART_DELAY: u64 = 500_000;
+    // Failed logins in a row before the greeter is locked for a while
+    pub const MAX_ATTEMPTS: u32 = 3;
+    pub const LOCKOUT: u64 = 30_000_000;
+    // How long workspaces take to slide past
+    pub const SWITCH_DURATION: u64 = 150_000;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Account {
+        pub user: String,
+        // Shown by the greeter
+        pub name: String,
+        pub uid: u32,
+        pub home: String,
+    }
+
+    // Implemented by the account service
+    pub trait AccountService: Send {
+        // Those the greeter offers, in display order
+        fn accounts(&self) -> Vec<Account>;
+        fn authenticate(&mut self, user: &str, password: &str) -> Result<Account, &'static str>;
+    }
+
+    // How long to wait for the account service before giving up
+    pub const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+    // The kernel's account service, over its vxauth channels. Replies that
+    // are not this client's are dropped, so the greeter and lock screen
+    // should share one.
+    pub struct AuthClient {
+        manager: VXChanManager,
+        next_id: AtomicU64,
+    }
+
+    impl AuthClient {
+        pub fn new(manager: &VXChanManager) -> Self {
+            AuthClient {
+                manager: manager.clone(),
+                next_id: AtomicU64::new(1),
+            }
+        }
+
+        fn request(&self, request: &str) -> Result<String, &'static str> {
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            users::send_request(&self.manager, id, request)?;
+            let deadline = Instant::now() + AUTH_TIMEOUT;
+            loop {
+                let Some(reply) = self.manager.try_receive_message(users::REPLY_CHANNEL)? else {
+                    if Instant::now() >= deadline {
+                        return Err("Account service not responding");
+                    }
+                    thread::sleep(Duration::from_millis(1));
+                    continue;
+                };
+                let (header, body) = reply.split_once('\n').unwrap_or((&reply, ""));
+                match header.split_once(' ') {
+                    Some((reply_id, "ok")) if reply_id == id.to_string() => return Ok(body.to_string()),
+                    Some((reply_id, status)) if reply_id == id.to_string() => {
+                        let error = status.strip_prefix("error ").unwrap_or(status);
+                        let known = ["Authentication failed", "User not found"];
+                        return Err(known.into_iter().find(|known| *known == error).unwrap_or("Account service error"));
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        fn account(user: User) -> Account {
+            Account {
+                name: if user.full_name.is_empty() { user.name.clone() } else { user.full_name },
+                user: user.name,
+                uid: user.uid,
+                home: user.home,
+            }
+        }
+    }
+
+    impl AccountService for AuthClient {
+        fn accounts(&self) -> Vec<Account> {
+            let Ok(body) = self.request("users") else {
+                return Vec::new();
+            };
+            body.lines().filter_map(|line| User::parse(line).ok()).map(AuthClient::account).collect()
+        }
+
+        fn authenticate(&mut self, user: &str, password: &str) -> Result<Account, &'static str> {
+            let body = self.request(&format!("authenticate {} {}", user, password))?;
+            User::parse(&body).map(AuthClient::account)
+        }
+    }
+
+    // A part of the session, such as the compositor, the panel or a
+    // background service, which the manager starts, watches and stops
+    pub trait Component: Send {
+        fn name(&self) -> &str;
+        // The session cannot go on without it once it stops restarting
+        fn essential(&self) -> bool {
+            false
+        }
+        fn start(&mut self, account: &Account) -> Result<(), &'static str>;
+        // Polled to notice crashes
+        fn is_running(&mut self) -> bool;
+        fn stop(&mut self);
     }
 
-    pub fn start_session() {
-        println!("Starting VXDE session...");
-        // Start a new VXDE session
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ComponentState {
+        Stopped,
+        Running,
+        // Crashed; started again at the given time
+        Restarting { at: u64 },
+        // Crashed too often to restart
+        Failed,
     }
 
-    pub fn stop_session() {
-        println!("Stopping VXDE session...");
-        // Stop the VXDE session
+    impl ComponentState {
+        pub fn name(&self) -> &'static str {
+            match self {
+                ComponentState::Stopped => "stopped",
+                ComponentState::Running => "running",
+                ComponentState::Restarting { .. } => "restarting",
+                ComponentState::Failed => "failed",
+            }
+        }
     }
 
-    pub fn manage_windows() {
-        println!("Managing VXDE windows...");
-        // Manage VXDE windows
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum SessionEvent {
+        Started(String),
+        Ended(String),
+        Restarted(String),
+        Failed(String),
     }
 
-    pub fn update() {
-        println!("Updating VXDE...");
-        // Update the VXDE system
+    impl SessionEvent {
+        fn message(&self) -> String {
+            match self {
+                SessionEvent::Started(user) => format!("started {}", user),
+                SessionEvent::Ended(user) => format!("ended {}", user),
+                SessionEvent::Restarted(name) => format!("restarted {}", name),
+                SessionEvent::Failed(name) => format!("failed {}", name),
+            }
+        }
+    }
+
+    struct Slot {
+        component: Box<dyn Component>,
+        state: ComponentState,
+        // Within the restart window
+        crashes: Vec<u64>,
+    }
+
+    // One graphical session at a time: logs a user in against the account
+    // service, starts the session's components in order, restarts the
+    // ones that crash and tears everything down in reverse on logout
+    pub struct SessionManager {
+        accounts: Box<dyn AccountService>,
+        slots: Vec<Slot>,
+        session: Option<Account>,
+        failures: u32,
+        locked_until: Option<u64>,
+        subscribers: Vec<Sender<SessionEvent>>,
+    }
+
+    impl SessionManager {
+        pub fn new(accounts: Box<dyn AccountService>) -> Self {
+            SessionManager {
+                accounts,
+                slots: Vec::new(),
+                session: None,
+                failures: 0,
+                locked_until: None,
+                subscribers: Vec::new(),
+            }
+        }
+
+        // Components start in the order they were added
+        pub fn add_component(&mut self, component: Box<dyn Component>) {
+            self.slots.push(Slot {
+                component,
+                state: ComponentState::Stopped,
+                crashes: Vec::new(),
+            });
+        }
+
+        pub fn accounts(&self) -> Vec<Account> {
+            self.accounts.accounts()
+        }
+
+        pub fn session(&self) -> Option<&Account> {
+            self.session.as_ref()
+        }
+
+        pub fn components(&self) -> Vec<(&str, ComponentState)> {
+            self.slots.iter().map(|slot| (slot.component.name(), slot.state)).collect()
+        }
+
+        pub fn component_state(&self, name: &str) -> Option<ComponentState> {
+            self.slots.iter().find(|slot| slot.component.name() == name).map(|slot| slot.state)
+        }
+
+        pub fn subscribe(&mut self) -> Receiver<SessionEvent> {
+            let (sender, receiver) = mpsc::channel();
+            self.subscribers.push(sender);
+            receiver
+        }
+
+        fn publish(&mut self, event: SessionEvent) {
+            self.subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+        }
+
+        // Failures in a row lock everyone out for a while
+        fn authenticate(&mut self, user: &str, password: &str, now: u64) -> Result<Account, &'static str> {
+            if self.locked_until.is_some_and(|until| now < until) {
+                return Err("Too many failed attempts");
+            }
+            match self.accounts.authenticate(user, password) {
+                Ok(account) => {
+                    self.failures = 0;
+                    Ok(account)
+                }
+                Err(error) => {
+                    self.failures += 1;
+                    if self.failures >= MAX_ATTEMPTS {
+                        self.failures = 0;
+                        self.locked_until = Some(now + LOCKOUT);
+                    }
+                    Err(error)
+                }
+            }
+        }
+
+        // Checks the password of whoever is logged in, e.g. to unlock the
+        // screen
+        pub fn verify(&mut self, password: &str, now: u64) -> Result<(), &'static str> {
+            let user = self.session.as_ref().ok_or("No session")?.user.clone();
+            self.authenticate(&user, password, now).map(|_| ())
+        }
+
+        pub fn login(&mut self, user: &str, password: &str, now: u64) -> Result<(), &'static str> {
+            if self.session.is_some() {
+                return Err("Session already running");
+            }
+            let account = self.authenticate(user, password, now)?;
+            log::info!("Starting session for {}", account.user);
+            for index in 0..self.slots.len() {
+                if let Err(error) = self.slots[index].component.start(&account) {
+                    log::warn!("Session component {} failed to start: {}", self.slots[index].component.name(), error);
+                    for started in self.slots[..index].iter_mut().rev() {
+                        started.component.stop();
+                        started.state = ComponentState::Stopped;
+                    }
+                    return Err("Session component failed to start");
+                }
+                self.slots[index].state = ComponentState::Running;
+            }
+            self.publish(SessionEvent::Started(account.user.clone()));
+            self.session = Some(account);
+            Ok(())
+        }
+
+        pub fn logout(&mut self) -> Result<(), &'static str> {
+            let account = self.session.take().ok_or("No session")?;
+            log::info!("Ending session for {}", account.user);
+            for slot in self.slots.iter_mut().rev() {
+                if slot.state == ComponentState::Running {
+                    slot.component.stop();
+                }
+                slot.state = ComponentState::Stopped;
+                slot.crashes.clear();
+            }
+            self.publish(SessionEvent::Ended(account.user));
+            Ok(())
+        }
+
+        // Restarts crashed components after a growing delay. An essential
+        // component that keeps crashing ends the session.
+        pub fn tick(&mut self, now: u64) {
+            let Some(account) = self.session.clone() else {
+                return;
+            };
+            let mut events = Vec::new();
+            let mut end = false;
+            for slot in &mut self.slots {
+                let crashed = match slot.state {
+                    ComponentState::Running => !slot.component.is_running(),
+                    ComponentState::Restarting { at } if now >= at => match slot.component.start(&account) {
+                        Ok(()) => {
+                            slot.state = ComponentState::Running;
+                            events.push(SessionEvent::Restarted(slot.component.name().to_string()));
+                            false
+                        }
+                        Err(_) => true,
+                    },
+                    _ => false,
+                };
+                if !crashed {
+                    continue;
+                }
+                slot.crashes.retain(|crash| now - crash < RESTART_WINDOW);
+                slot.crashes.push(now);
+                if slot.crashes.len() > MAX_RESTARTS {
+                    log::error!("Session component {} keeps crashing, giving up", slot.component.name());
+                    slot.state = ComponentState::Failed;
+                    events.push(SessionEvent::Failed(slot.component.name().to_string()));
+                    end |= slot.component.essential();
+                } else {
+                    let delay = RESTART_DELAY << (slot.crashes.len() - 1);
+                    log::warn!("Session component {} crashed, restarting", slot.component.name());
+                    slot.state = ComponentState::Restarting { at: now + delay };
+                }
+            }
+            for event in events {
+                self.publish(event);
+            }
+            if end {
+                let _ = self.logout();
+            }
+        }
+    }
+
+    // Grammar, one request per message:
+    //   logout | status
+    // Messages and replies are framed as in vxnetctl; status replies with
+    // "session USER" (or "session none") and a "NAME STATE" line per
+    // component
+    pub struct SessionService {
+        events: Receiver<SessionEvent>,
+    }
+
+    impl SessionService {
+        pub fn new(manager: &VXChanManager, sessions: &mut SessionManager) -> Result<Self, &'static str> {
+            manager.create_channel(REQUEST_CHANNEL)?;
+            manager.create_channel(REPLY_CHANNEL)?;
+            manager.create_channel(EVENT_CHANNEL)?;
+            Ok(SessionService {
+                events: sessions.subscribe(),
+            })
+        }
+
+        fn execute(request: &str, sessions: &mut SessionManager) -> Result<String, &'static str> {
+            match request.split_whitespace().collect::<Vec<&str>>().as_slice() {
+                ["logout"] => sessions.logout().map(|_| String::new()),
+                ["status"] => {
+                    let user = sessions.session().map_or("none", |account| account.user.as_str());
+                    let mut lines = vec![format!("session {}", user)];
+                    for (name, state) in sessions.components() {
+                        lines.push(format!("{} {}", name, state.name()));
+                    }
+                    Ok(lines.join("\n"))
+                }
+                [] => Err("Empty request"),
+                _ => Err("Unknown command"),
+            }
+        }
+
+        // Watches the components, answers queued requests and announces
+        // what happened to the session
+        pub fn poll(&mut self, manager: &VXChanManager, sessions: &mut SessionManager, now: u64) -> Result<usize, &'static str> {
+            sessions.tick(now);
+            let mut count = 0;
+            while let Some(message) = manager.try_receive_message(REQUEST_CHANNEL)? {
+                let (id, request) = message.split_once(' ').unwrap_or((message.as_str(), ""));
+                let result = id
+                    .parse::<u64>()
+                    .map_err(|_| "Invalid request id")
+                    .and_then(|_| Self::execute(request, sessions));
+                let reply = match result {
+                    Ok(body) if body.is_empty() => format!("{} ok", id),
+                    Ok(body) => format!("{} ok\n{}", id, body),
+                    Err(error) => format!("{} error {}", id, error),
+                };
+                manager.send_message(REPLY_CHANNEL, reply)?;
+                count += 1;
+            }
+            while let Ok(event) = self.events.try_recv() {
+                manager.send_message(EVENT_CHANNEL, event.message())?;
+            }
+            Ok(count)
+        }
+    }
+
+    pub fn send_request(manager: &VXChanManager, id: u64, request: &str) -> Result<(), &'static str> {
+        manager.send_message(REQUEST_CHANNEL, format!("{} {}", id, request))
+    }
+
+    // The login screen: a full-screen window with the user list and a
+    // password field, shown whenever no session is running
+    pub struct Greeter {
+        window: WindowId,
+        tree: WidgetTree,
+        users: Vec<String>,
+        buffer: Option<Buffer>,
+        shown: bool,
+        submits: Receiver<()>,
+    }
+
+    impl Greeter {
+        pub fn new(compositor: &mut Compositor, sessions: &SessionManager) -> Self {
+            let accounts = sessions.accounts();
+            let names: Vec<&str> = accounts.iter().map(|account| account.name.as_str()).collect();
+            let (submit, submits) = mpsc::channel();
+            let on_click = submit.clone();
+            let card = Element::container(Direction::Column)
+                .padding(16)
+                .child(Element::label("Welcome").name("title"))
+                .child(Element::list(&names).name("users"))
+                .child(Element::secret_input("Password").name("password").on_event(move |_, _, event| {
+                    if let WidgetEvent::Submitted(_) = event {
+                        let _ = submit.send(());
+                    }
+                }))
+                .child(Element::button("Log in").name("login").on_event(move |_, _, _| {
+                    let _ = on_click.send(());
+                }))
+                .child(Element::label("").name("message"));
+            let root = Element::container(Direction::Column)
+                .align(Align::Center)
+                .child(Element::container(Direction::Column).grow(1))
+                .child(card)
+                .child(Element::container(Direction::Column).grow(1));
+            let (width, height) = compositor.resolution();
+            let mut tree = WidgetTree::new(root, width, height);
+            let (list, password) = (tree.find("users").unwrap(), tree.find("password").unwrap());
+            if !accounts.is_empty() {
+                tree.select(list, Some(0)).unwrap();
+            }
+            tree.set_focus(Some(password)).unwrap();
+            Greeter {
+                window: compositor.create_window(0, 0),
+                tree,
+                users: accounts.into_iter().map(|account| account.user).collect(),
+                buffer: None,
+                shown: false,
+                submits,
+            }
+        }
+
+        pub fn window(&self) -> WindowId {
+            self.window
+        }
+
+        pub fn tree(&self) -> &WidgetTree {
+            &self.tree
+        }
+
+        pub fn set_font(&mut self, font: Option<Arc<TextRenderer>>) {
+            self.tree.set_font(font);
+        }
+
+        pub fn set_theme(&mut self, theme: Theme, variant: Variant) {
+            self.tree.set_theme(theme, variant);
+        }
+
+        // Input goes here while no session is running
+        pub fn pointer_motion(&mut self, x: i32, y: i32) {
+            self.tree.pointer_motion(x, y);
+        }
+
+        pub fn pointer_button(&mut self, x: i32, y: i32, pressed: bool) {
+            self.tree.pointer_button(x, y, pressed);
+        }
+
+        pub fn key(&mut self, code: u32, pressed: bool) {
+            self.tree.key(code, pressed);
+        }
+
+        pub fn text_input(&mut self, character: char) {
+            self.tree.text_input(character);
+        }
+
+        fn set_message(&mut self, message: &str) -> Result<(), &'static str> {
+            let label = self.tree.find("message").unwrap();
+            self.tree.set_text(label, message)
+        }
+
+        // Tries submitted logins, then shows or hides the screen as the
+        // session comes and goes. The password is never kept.
+        pub fn update(&mut self, sessions: &mut SessionManager, compositor: &mut Compositor, now: u64) -> Result<(), &'static str> {
+            while self.submits.try_recv().is_ok() {
+                let password_input = self.tree.find("password").unwrap();
+                let password = self.tree.text(password_input).unwrap_or("").to_string();
+                self.tree.set_text(password_input, "")?;
+                let list = self.tree.find("users").unwrap();
+                let Some(user) = self.tree.selected(list).and_then(|index| self.users.get(index)).cloned() else {
+                    self.set_message("Choose a user")?;
+                    continue;
+                };
+                match sessions.login(&user, &password, now) {
+                    Ok(()) => self.set_message("")?,
+                    Err(error) => self.set_message(error)?,
+                }
+            }
+            let show = sessions.session().is_none();
+            if !show {
+                if self.shown {
+                    self.shown = false;
+                    compositor.set_visible(self.window, false)?;
+                }
+                return Ok(());
+            }
+            let (width, height) = self.tree.buffer_size();
+            let attach = self.buffer.is_none();
+            let buffer = self
+                .buffer
+                .get_or_insert_with(|| Buffer::new(width, height, BufferStorage::SharedMemory))
+                .clone();
+            let damage = self.tree.render(&buffer)?;
+            if attach {
+                compositor.attach(self.window, buffer)?;
+            }
+            let dirty = !damage.is_empty();
+            for area in damage {
+                compositor.damage(self.window, area)?;
+            }
+            if attach || dirty {
+                compositor.commit(self.window)?;
+            }
+            if !self.shown {
+                self.shown = true;
+                compositor.set_visible(self.window, true)?;
+                compositor.raise(self.window)?;
+            }
+            Ok(())
+        }
+    }
+
+    // Switches workspaces in the window manager, sliding the windows of
+    // the one left out of the desktop while those of the next slide in
+    // from the other side. Higher workspaces come in from the right.
+    pub struct WorkspaceSwitcher {
+        duration: u64,
+        // Windows on the move, shown or hidden as the window manager has
+        // them once their animation ends
+        sliding: Vec<(SurfaceId, WindowId, AnimationId)>,
+    }
+
+    impl Default for WorkspaceSwitcher {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl WorkspaceSwitcher {
+        pub fn new() -> Self {
+            WorkspaceSwitcher {
+                duration: SWITCH_DURATION,
+                sliding: Vec::new(),
+            }
+        }
+
+        // 0 switches at once
+        pub fn set_duration(&mut self, duration: u64) {
+            self.duration = duration;
+        }
+
+        pub fn is_animating(&self) -> bool {
+            !self.sliding.is_empty()
+        }
+
+        fn minimized(wm: &WindowManager, surface: SurfaceId) -> bool {
+            wm.state(surface) == Some(WindowState::Minimized)
+        }
+
+        pub fn switch(&mut self, wm: &mut WindowManager, animator: &mut Animator, workspace: usize) -> Result<(), &'static str> {
+            self.finish(wm, animator);
+            let from = wm.current_workspace();
+            let leaving: Vec<SurfaceId> = wm.surfaces_on(from).into_iter().filter(|surface| !Self::minimized(wm, *surface)).collect();
+            wm.switch_workspace(workspace)?;
+            if workspace == from || self.duration == 0 {
+                return Ok(());
+            }
+            let width = wm.compositor().resolution().0 as f32;
+            let offset = if workspace > from { width } else { -width };
+            let entering: Vec<SurfaceId> = wm.surfaces_on(workspace).into_iter().filter(|surface| !Self::minimized(wm, *surface)).collect();
+            for (surfaces, from, to) in [(leaving, 0.0, -offset), (entering, offset, 0.0)] {
+                for surface in surfaces {
+                    let (frame, window) = wm.windows(surface).unwrap();
+                    for window in [frame, window] {
+                        // Kept in sight until it is out of the way
+                        wm.compositor_mut().set_visible(window, true)?;
+                        wm.compositor_mut().set_transform(window, Transform { dx: from, ..Transform::IDENTITY })?;
+                        let timeline = Timeline::new(self.duration).animate(Property::TranslateX, from, to, EASE_OUT);
+                        self.sliding.push((surface, window, animator.start(Target::Window(window), timeline)));
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        fn settle(wm: &mut WindowManager, animator: &mut Animator, (surface, window, _): (SurfaceId, WindowId, AnimationId)) {
+            animator.forget(Target::Window(window));
+            // Gone with its surface in the meantime
+            let Some(workspace) = wm.workspace_of(surface) else {
+                return;
+            };
+            let visible = workspace == wm.current_workspace() && !Self::minimized(wm, surface);
+            let compositor = wm.compositor_mut();
+            let _ = compositor.set_transform(window, Transform::IDENTITY);
+            let _ = compositor.set_visible(window, visible);
+        }
+
+        // Ends every slide at once
+        pub fn finish(&mut self, wm: &mut WindowManager, animator: &mut Animator) {
+            for entry in std::mem::take(&mut self.sliding) {
+                Self::settle(wm, animator, entry);
+            }
+        }
+
+        // Puts windows whose slide has ended where they belong; call after
+        // applying the animator's frame
+        pub fn update(&mut self, wm: &mut WindowManager, animator: &mut Animator) {
+            let (done, sliding) = std::mem::take(&mut self.sliding).into_iter().partition(|(_, _, id)| !animator.is_running(*id));
+            self.sliding = sliding;
+            for entry in done {
+                Self::settle(wm, animator, entry);
+            }
+        }
     }
 }
@@ -0,0 +1,145 @@
+// src/ui/vxhud.rs
+
+pub mod vxhud {
+    use vaelix_graphics::vegagx::vegagx::{Color, Paint};
+    use vaelix_graphics::vxfont::vxfont::TextRenderer;
+    use vaelix_graphics::vxwin::vxwin::{Buffer, BufferStorage, Compositor, Rect, WindowId};
+    use std::sync::Arc;
+
+    pub const HUD_WIDTH: u32 = 240;
+    pub const HUD_HEIGHT: u32 = 120;
+    // Redrawing on every frame would keep the compositor from ever going
+    // idle, and put the HUD's own frames in the figures
+    pub const HUD_INTERVAL: u64 = 500_000;
+    const MARGIN: i32 = 8;
+    const TEXT_SIZE: u32 = 12;
+    const LINE_HEIGHT: u32 = 16;
+    const GRAPH_HEIGHT: u32 = 48;
+
+    // Frame timing figures in a corner of the desktop, over everything
+    // else: averages and worst cases as text, and a bar per recent frame
+    // of how long it took from commit to flip. Bars for frames that came
+    // after dropped ones are red; the line is one refresh interval.
+    pub struct PerformanceHud {
+        window: WindowId,
+        buffer: Option<Buffer>,
+        font: Option<Arc<TextRenderer>>,
+        scale: f32,
+        enabled: bool,
+        shown: bool,
+        last_update: Option<u64>,
+    }
+
+    impl PerformanceHud {
+        pub fn new(compositor: &mut Compositor) -> Self {
+            PerformanceHud {
+                window: compositor.create_window(0, 0),
+                buffer: None,
+                font: None,
+                scale: 1.0,
+                enabled: false,
+                shown: false,
+                last_update: None,
+            }
+        }
+
+        pub fn window(&self) -> WindowId {
+            self.window
+        }
+
+        // Without a font only the graph is drawn
+        pub fn set_font(&mut self, font: Option<Arc<TextRenderer>>) {
+            self.font = font;
+            self.last_update = None;
+        }
+
+        pub fn set_scale(&mut self, scale: f32) {
+            self.scale = scale;
+            self.buffer = None;
+            self.last_update = None;
+        }
+
+        pub fn is_enabled(&self) -> bool {
+            self.enabled
+        }
+
+        pub fn set_enabled(&mut self, enabled: bool) {
+            self.enabled = enabled;
+            self.last_update = None;
+        }
+
+        pub fn toggle(&mut self) {
+            self.set_enabled(!self.enabled);
+        }
+
+        fn draw(&self, buffer: &Buffer, compositor: &Compositor) {
+            let (width, height) = (buffer.width(), buffer.height());
+            let physical = |value: u32| (value as f32 * self.scale).round() as u32;
+            let lines = compositor.timing_summary().lines();
+            let timings = compositor.frame_timings();
+            let interval = compositor.refresh_interval();
+            buffer.draw(|canvas| {
+                canvas.fill_rect(Rect::new(0, 0, width, height), &Paint::Solid(Color::argb(0xC010_1010)));
+                if let Some(font) = &self.font {
+                    let paint = Paint::Solid(Color::argb(0xFFE0_E0E0));
+                    for (index, line) in lines.iter().enumerate() {
+                        let baseline = physical(LINE_HEIGHT * (index as u32 + 1)) as f32 - self.scale * 4.0;
+                        let _ = font.draw(canvas, line, physical(TEXT_SIZE), physical(MARGIN as u32) as f32, baseline, &paint);
+                    }
+                }
+                // Two refresh intervals tall, newest on the right
+                let graph = Rect::new(0, (height - physical(GRAPH_HEIGHT)) as i32, width, physical(GRAPH_HEIGHT));
+                let bar_width = physical(2).max(1);
+                let bars = (width / bar_width) as usize;
+                let recent = &timings[timings.len().saturating_sub(bars)..];
+                for (index, timing) in recent.iter().enumerate() {
+                    let time = timing.commit_latency().unwrap_or_else(|| timing.composite_time());
+                    let bar_height = ((time as f32 / (2 * interval) as f32).min(1.0) * graph.height as f32).round() as u32;
+                    let x = width as i32 - ((recent.len() - index) as u32 * bar_width) as i32;
+                    let color = if timing.dropped > 0 { 0xFFE0_4040 } else { 0xFF40_C060 };
+                    let bar = Rect::new(x, graph.bottom() - bar_height as i32, bar_width, bar_height);
+                    canvas.fill_rect(bar, &Paint::Solid(Color::argb(color)));
+                }
+                let line = Rect::new(0, graph.y + graph.height as i32 / 2, width, physical(1).max(1));
+                canvas.fill_rect(line, &Paint::Solid(Color::argb(0xFFE0_C040)));
+            });
+        }
+
+        // Redraws at most every HUD_INTERVAL, in the top right corner of
+        // the desktop
+        pub fn update(&mut self, compositor: &mut Compositor, now: u64) -> Result<(), &'static str> {
+            if !self.enabled {
+                if self.shown {
+                    self.shown = false;
+                    compositor.set_visible(self.window, false)?;
+                }
+                return Ok(());
+            }
+            if self.last_update.is_some_and(|last| now.saturating_sub(last) < HUD_INTERVAL) {
+                return Ok(());
+            }
+            self.last_update = Some(now);
+            let (width, height) = ((HUD_WIDTH as f32 * self.scale).round() as u32, (HUD_HEIGHT as f32 * self.scale).round() as u32);
+            let attach = self.buffer.is_none();
+            let buffer = self
+                .buffer
+                .get_or_insert_with(|| Buffer::new(width, height, BufferStorage::SharedMemory))
+                .clone();
+            self.draw(&buffer, compositor);
+            if attach {
+                compositor.attach(self.window, buffer)?;
+                compositor.set_destination(self.window, (self.scale != 1.0).then_some((HUD_WIDTH, HUD_HEIGHT)))?;
+            }
+            compositor.damage(self.window, Rect::new(0, 0, width, height))?;
+            compositor.commit(self.window)?;
+            let (desktop_width, _) = compositor.resolution();
+            let x = (desktop_width as i32 - HUD_WIDTH as i32 - MARGIN).max(0);
+            compositor.move_window(self.window, x, MARGIN)?;
+            if !self.shown {
+                self.shown = true;
+                compositor.set_visible(self.window, true)?;
+            }
+            compositor.raise(self.window)
+        }
+    }
+}
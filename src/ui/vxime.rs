@@ -0,0 +1,301 @@
+// src/ui/vxime.rs
+
+pub mod vxime {
+    use crate::vxui_toolkit::vxui_toolkit::{Element, WidgetTree};
+    use vaelix_core::vxchan::vxchan::VXChanManager;
+    use vaelix_graphics::vxfont::vxfont::TextRenderer;
+    use vaelix_graphics::vxtheme::vxtheme::{Theme, Variant};
+    use vaelix_graphics::vxwin::vxwin::{Buffer, BufferStorage, Compositor, Rect, WindowId};
+    use std::sync::Arc;
+
+    // From the input method process
+    pub const REQUEST_CHANNEL: &str = "vxime";
+    // To it
+    pub const EVENT_CHANNEL: &str = "vxime.events";
+
+    const CANDIDATE_MIN_WIDTH: u32 = 80;
+
+    // What the focused text input gets from the input method
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum ImeEvent {
+        // Empty text clears the composition; the cursor counts characters
+        Preedit { text: String, cursor: usize },
+        Commit(String),
+        // A key the input method had no use for, to handle as usual
+        Key { code: u32, pressed: bool },
+    }
+
+    // Hands an event to the tree's focused input
+    pub fn apply(tree: &mut WidgetTree, event: &ImeEvent) -> Result<(), &'static str> {
+        match event {
+            ImeEvent::Preedit { text, cursor } => tree.set_preedit(text, *cursor),
+            ImeEvent::Commit(text) => tree.commit_text(text),
+            ImeEvent::Key { code, pressed } => {
+                tree.key(*code, *pressed);
+                Ok(())
+            }
+        }
+    }
+
+    fn parse<T: std::str::FromStr>(value: &str) -> Result<T, &'static str> {
+        value.parse().map_err(|_| "Invalid number")
+    }
+
+    fn parse_pressed(value: &str) -> Result<bool, &'static str> {
+        match value {
+            "1" => Ok(true),
+            "0" => Ok(false),
+            _ => Err("Invalid key state"),
+        }
+    }
+
+    // Grammar, input method to compositor, one message each:
+    //   register NAME | unregister
+    //   preedit CURSOR [TEXT...] (no text clears it)
+    //   commit TEXT...
+    //   candidates SELECTED|none [ITEM<tab>ITEM...] (no items hides them)
+    //   forward CODE 1|0
+    // and compositor to input method on EVENT_CHANNEL:
+    //   activate | deactivate | reset | key CODE 1|0
+    // While active, keys go to the input method instead of the focused
+    // input; it answers with compositions, commits and the keys it passes
+    // back. Only one input method is registered at a time.
+    pub struct InputMethod {
+        name: Option<String>,
+        // Caret of the focused input in desktop coordinates, while one
+        // that takes composed text has focus
+        caret: Option<Rect>,
+        candidates: Vec<String>,
+        selected: Option<usize>,
+        // The list needs the candidates again
+        dirty: bool,
+        window: WindowId,
+        tree: WidgetTree,
+        buffer: Option<Buffer>,
+        scale: f32,
+        shown: bool,
+        position: Option<(i32, i32)>,
+    }
+
+    impl InputMethod {
+        pub fn new(manager: &VXChanManager, compositor: &mut Compositor) -> Result<Self, &'static str> {
+            manager.create_channel(REQUEST_CHANNEL)?;
+            manager.create_channel(EVENT_CHANNEL)?;
+            Ok(InputMethod {
+                name: None,
+                caret: None,
+                candidates: Vec::new(),
+                selected: None,
+                dirty: false,
+                window: compositor.create_window(0, 0),
+                tree: WidgetTree::new(Element::list(&[]).name("candidates"), CANDIDATE_MIN_WIDTH, 1),
+                buffer: None,
+                scale: 1.0,
+                shown: false,
+                position: None,
+            })
+        }
+
+        // The registered input method
+        pub fn name(&self) -> Option<&str> {
+            self.name.as_deref()
+        }
+
+        // Whether keys go to the input method
+        pub fn is_active(&self) -> bool {
+            self.name.is_some() && self.caret.is_some()
+        }
+
+        pub fn candidates(&self) -> (&[String], Option<usize>) {
+            (&self.candidates, self.selected)
+        }
+
+        pub fn window(&self) -> WindowId {
+            self.window
+        }
+
+        pub fn tree(&self) -> &WidgetTree {
+            &self.tree
+        }
+
+        pub fn set_font(&mut self, font: Option<Arc<TextRenderer>>) {
+            self.tree.set_font(font);
+            self.buffer = None;
+        }
+
+        pub fn set_theme(&mut self, theme: Theme, variant: Variant) {
+            self.tree.set_theme(theme, variant);
+            self.buffer = None;
+        }
+
+        pub fn set_scale(&mut self, scale: f32) {
+            self.scale = scale;
+            self.tree.set_scale(scale);
+            self.buffer = None;
+        }
+
+        fn send(&self, manager: &VXChanManager, message: &str) -> Result<(), &'static str> {
+            if self.name.is_none() {
+                return Ok(());
+            }
+            manager.send_message(EVENT_CHANNEL, message.to_string())
+        }
+
+        // Follows keyboard focus: the caret of a focused input that takes
+        // composed text, as from WidgetTree::caret_area moved onto the
+        // desktop, or None
+        pub fn set_caret(&mut self, manager: &VXChanManager, caret: Option<Rect>) -> Result<(), &'static str> {
+            let was_active = self.is_active();
+            self.caret = caret;
+            match (was_active, self.is_active()) {
+                (false, true) => self.send(manager, "activate"),
+                (true, false) => {
+                    self.candidates.clear();
+                    self.send(manager, "deactivate")
+                }
+                _ => Ok(()),
+            }
+        }
+
+        // Drops the composition in progress, as when focus moves to another
+        // input
+        pub fn reset(&mut self, manager: &VXChanManager) -> Result<(), &'static str> {
+            self.candidates.clear();
+            self.send(manager, "reset")
+        }
+
+        // Keys from the keyboard. Returns whether the input method took the
+        // key; if so, neither it nor the text it types goes to the focused
+        // input.
+        pub fn key(&mut self, manager: &VXChanManager, code: u32, pressed: bool) -> Result<bool, &'static str> {
+            if !self.is_active() {
+                return Ok(false);
+            }
+            self.send(manager, &format!("key {} {}", code, if pressed { 1 } else { 0 }))?;
+            Ok(true)
+        }
+
+        fn execute(&mut self, manager: &VXChanManager, message: &str) -> Result<Option<ImeEvent>, &'static str> {
+            let (command, rest) = message.split_once(' ').unwrap_or((message, ""));
+            if command == "register" {
+                if rest.is_empty() {
+                    return Err("Missing argument");
+                }
+                log::info!("Input method {} registered", rest);
+                self.name = Some(rest.to_string());
+                self.candidates.clear();
+                if self.caret.is_some() {
+                    self.send(manager, "activate")?;
+                }
+                return Ok(None);
+            }
+            if self.name.is_none() {
+                return Err("No input method registered");
+            }
+            match command {
+                "unregister" => {
+                    self.name = None;
+                    self.candidates.clear();
+                    Ok(Some(ImeEvent::Preedit { text: String::new(), cursor: 0 }))
+                }
+                "preedit" => {
+                    let (cursor, text) = rest.split_once(' ').unwrap_or((rest, ""));
+                    Ok(Some(ImeEvent::Preedit { text: text.to_string(), cursor: parse(cursor)? }))
+                }
+                "commit" if !rest.is_empty() => Ok(Some(ImeEvent::Commit(rest.to_string()))),
+                "candidates" => {
+                    let (selected, items) = rest.split_once(' ').unwrap_or((rest, ""));
+                    let items: Vec<String> = items.split('\t').filter(|item| !item.is_empty()).map(String::from).collect();
+                    let selected = match selected {
+                        "none" => None,
+                        index => Some(parse::<usize>(index)?).filter(|index| *index < items.len()),
+                    };
+                    (self.candidates, self.selected) = (items, selected);
+                    self.dirty = true;
+                    Ok(None)
+                }
+                "forward" => match rest.split_whitespace().collect::<Vec<_>>().as_slice() {
+                    [code, pressed] => Ok(Some(ImeEvent::Key { code: parse(code)?, pressed: parse_pressed(pressed)? })),
+                    _ => Err("Missing argument"),
+                },
+                _ => Err("Unknown command"),
+            }
+        }
+
+        // Reads what the input method sent, returning what the focused
+        // input should get, in order
+        pub fn poll(&mut self, manager: &VXChanManager) -> Result<Vec<ImeEvent>, &'static str> {
+            let mut events = Vec::new();
+            while let Some(message) = manager.try_receive_message(REQUEST_CHANNEL)? {
+                match self.execute(manager, &message) {
+                    Ok(event) => events.extend(event),
+                    Err(error) => log::warn!("Dropping input method message '{}': {}", message, error),
+                }
+            }
+            Ok(events)
+        }
+
+        // Shows the candidates under the caret, or over it near the bottom
+        // of the desktop
+        pub fn update(&mut self, compositor: &mut Compositor) -> Result<(), &'static str> {
+            let Some(caret) = self.caret.filter(|_| self.is_active() && !self.candidates.is_empty()) else {
+                if self.shown {
+                    self.shown = false;
+                    compositor.set_visible(self.window, false)?;
+                }
+                return Ok(());
+            };
+            if self.dirty {
+                self.dirty = false;
+                let list = self.tree.find("candidates").unwrap();
+                let items: Vec<&str> = self.candidates.iter().map(String::as_str).collect();
+                self.tree.set_items(list, &items)?;
+                self.tree.select(list, self.selected)?;
+            }
+            let (width, height) = self.tree.preferred_size();
+            let size = (width.max(CANDIDATE_MIN_WIDTH), height);
+            if self.tree.size() != size {
+                self.tree.resize(size.0, size.1);
+                self.buffer = None;
+            }
+            let (buffer_width, buffer_height) = self.tree.buffer_size();
+            let attach = self.buffer.is_none();
+            let buffer = self
+                .buffer
+                .get_or_insert_with(|| Buffer::new(buffer_width, buffer_height, BufferStorage::SharedMemory))
+                .clone();
+            let damage = self.tree.render(&buffer)?;
+            if attach {
+                compositor.attach(self.window, buffer)?;
+                compositor.set_destination(self.window, (self.scale != 1.0).then_some(size))?;
+            }
+            let dirty = !damage.is_empty();
+            for area in damage {
+                compositor.damage(self.window, area)?;
+            }
+            if attach || dirty {
+                compositor.commit(self.window)?;
+            }
+            let (desktop_width, desktop_height) = compositor.resolution();
+            let x = caret.x.min(desktop_width as i32 - size.0 as i32).max(0);
+            let y = if caret.bottom() + size.1 as i32 > desktop_height as i32 { caret.y - size.1 as i32 } else { caret.bottom() };
+            if self.position != Some((x, y)) {
+                self.position = Some((x, y));
+                compositor.move_window(self.window, x, y)?;
+            }
+            if !self.shown {
+                self.shown = true;
+                compositor.set_visible(self.window, true)?;
+            }
+            if compositor.stacking_order().last() != Some(&self.window) {
+                compositor.raise(self.window)?;
+            }
+            Ok(())
+        }
+    }
+
+    // For input method processes
+    pub fn send_message(manager: &VXChanManager, message: &str) -> Result<(), &'static str> {
+        manager.send_message(REQUEST_CHANNEL, message.to_string())
+    }
+}
@@ -0,0 +1,275 @@
+// src/ui/vxlock.rs
+
+pub mod vxlock {
+    use crate::vxde::vxde::SessionManager;
+    use crate::vxui_toolkit::vxui_toolkit::{Align, Direction, Element, WidgetEvent, WidgetTree};
+    use vaelix_core::power::button::button::PowerEvent;
+    use vaelix_graphics::vxfont::vxfont::TextRenderer;
+    use vaelix_graphics::vxtheme::vxtheme::{Theme, Variant};
+    use vaelix_graphics::vxwin::vxwin::{Buffer, BufferStorage, Compositor, Rect, Viewport, WindowId};
+    use std::collections::BTreeMap;
+    use std::sync::mpsc::{self, Receiver};
+    use std::sync::Arc;
+
+    pub const PROMPT_WIDTH: u32 = 320;
+    pub const PROMPT_HEIGHT: u32 = 160;
+    // Idle time before the screen locks by itself
+    pub const DEFAULT_IDLE_TIMEOUT: u64 = 300_000_000;
+
+    // Covers every output with an opaque window and asks for the session
+    // user's password or PIN before showing the desktop again. Locks on
+    // request, after a period without input and when the lid closes.
+    pub struct LockScreen {
+        // One per output, stretched from a single pixel
+        covers: BTreeMap<String, (WindowId, Viewport)>,
+        prompt: WindowId,
+        prompt_position: Option<(i32, i32)>,
+        tree: WidgetTree,
+        buffer: Option<Buffer>,
+        scale: f32,
+        color: u32,
+        locked: bool,
+        shown: bool,
+        idle_timeout: Option<u64>,
+        last_activity: u64,
+        submits: Receiver<()>,
+    }
+
+    impl LockScreen {
+        pub fn new(compositor: &mut Compositor) -> Self {
+            let (submit, submits) = mpsc::channel();
+            let on_click = submit.clone();
+            let root = Element::container(Direction::Column)
+                .align(Align::Stretch)
+                .padding(16)
+                .child(Element::label("Locked").name("user"))
+                .child(Element::secret_input("Password or PIN").name("password").on_event(move |_, _, event| {
+                    if let WidgetEvent::Submitted(_) = event {
+                        let _ = submit.send(());
+                    }
+                }))
+                .child(Element::button("Unlock").name("unlock").on_event(move |_, _, _| {
+                    let _ = on_click.send(());
+                }))
+                .child(Element::label("").name("message"));
+            LockScreen {
+                covers: BTreeMap::new(),
+                prompt: compositor.create_window(0, 0),
+                prompt_position: None,
+                tree: WidgetTree::new(root, PROMPT_WIDTH, PROMPT_HEIGHT),
+                buffer: None,
+                scale: 1.0,
+                color: 0xFF00_0000,
+                locked: false,
+                shown: false,
+                idle_timeout: Some(DEFAULT_IDLE_TIMEOUT),
+                last_activity: 0,
+                submits,
+            }
+        }
+
+        pub fn prompt_window(&self) -> WindowId {
+            self.prompt
+        }
+
+        pub fn tree(&self) -> &WidgetTree {
+            &self.tree
+        }
+
+        pub fn set_font(&mut self, font: Option<Arc<TextRenderer>>) {
+            self.tree.set_font(font);
+        }
+
+        pub fn set_theme(&mut self, theme: Theme, variant: Variant) {
+            self.tree.set_theme(theme, variant);
+        }
+
+        // The scale of the output the prompt is shown on
+        pub fn set_scale(&mut self, scale: f32) {
+            self.scale = scale;
+            self.tree.set_scale(scale);
+            self.buffer = None;
+        }
+
+        // None never locks on idle
+        pub fn set_idle_timeout(&mut self, timeout: Option<u64>) {
+            self.idle_timeout = timeout;
+        }
+
+        pub fn is_locked(&self) -> bool {
+            self.locked
+        }
+
+        pub fn lock(&mut self) {
+            if !self.locked {
+                log::info!("Locking the screen");
+                self.locked = true;
+                let password = self.tree.find("password").unwrap();
+                let _ = self.tree.set_focus(Some(password));
+            }
+        }
+
+        // Any input event restarts the idle timer
+        pub fn activity(&mut self, now: u64) {
+            self.last_activity = now;
+        }
+
+        pub fn power_event(&mut self, event: PowerEvent) {
+            if event == PowerEvent::LidClosed {
+                self.lock();
+            }
+        }
+
+        // Input goes here instead of the session while locked; prompt-local
+        // coordinates
+        pub fn pointer_motion(&mut self, x: i32, y: i32) {
+            self.tree.pointer_motion(x, y);
+        }
+
+        pub fn pointer_button(&mut self, x: i32, y: i32, pressed: bool) {
+            self.tree.pointer_button(x, y, pressed);
+        }
+
+        pub fn key(&mut self, code: u32, pressed: bool) {
+            self.tree.key(code, pressed);
+        }
+
+        pub fn text_input(&mut self, character: char) {
+            self.tree.text_input(character);
+        }
+
+        fn set_message(&mut self, message: &str) -> Result<(), &'static str> {
+            let label = self.tree.find("message").unwrap();
+            self.tree.set_text(label, message)
+        }
+
+        fn hide(&mut self, compositor: &mut Compositor) -> Result<(), &'static str> {
+            if !self.shown {
+                return Ok(());
+            }
+            self.shown = false;
+            for (cover, _) in std::mem::take(&mut self.covers).into_values() {
+                compositor.destroy_window(cover)?;
+            }
+            compositor.set_visible(self.prompt, false)
+        }
+
+        // Locks on idle, checks submitted passwords against the session
+        // user's account, and keeps the covers on top of every output while
+        // locked. There is nothing to lock without a session.
+        pub fn update(
+            &mut self,
+            sessions: &mut SessionManager,
+            outputs: &[(&str, Viewport)],
+            compositor: &mut Compositor,
+            now: u64,
+        ) -> Result<(), &'static str> {
+            let Some(name) = sessions.session().map(|account| account.name.clone()) else {
+                self.locked = false;
+                self.last_activity = now;
+                return self.hide(compositor);
+            };
+            if self.idle_timeout.is_some_and(|timeout| now.saturating_sub(self.last_activity) >= timeout) {
+                self.lock();
+            }
+            while self.submits.try_recv().is_ok() {
+                if !self.locked {
+                    continue;
+                }
+                let password_input = self.tree.find("password").unwrap();
+                let password = self.tree.text(password_input).unwrap_or("").to_string();
+                self.tree.set_text(password_input, "")?;
+                match sessions.verify(&password, now) {
+                    Ok(()) => {
+                        log::info!("Unlocking the screen");
+                        self.locked = false;
+                        self.last_activity = now;
+                        self.set_message("")?;
+                    }
+                    Err(error) => self.set_message(error)?,
+                }
+            }
+            if !self.locked {
+                return self.hide(compositor);
+            }
+            let user = self.tree.find("user").unwrap();
+            if self.tree.text(user) != Some(name.as_str()) {
+                self.tree.set_text(user, &name)?;
+            }
+            let gone: Vec<String> = self
+                .covers
+                .keys()
+                .filter(|name| !outputs.iter().any(|(output, _)| output == name))
+                .cloned()
+                .collect();
+            for name in gone {
+                compositor.destroy_window(self.covers.remove(&name).unwrap().0)?;
+            }
+            for (name, viewport) in outputs {
+                let current = self.covers.get(*name).copied();
+                if current.is_some_and(|(_, covered)| covered == *viewport) {
+                    continue;
+                }
+                let cover = match current {
+                    Some((cover, _)) => cover,
+                    None => {
+                        let cover = compositor.create_window(0, 0);
+                        let buffer = Buffer::new(1, 1, BufferStorage::SharedMemory);
+                        buffer.fill(Rect::new(0, 0, 1, 1), self.color);
+                        compositor.attach(cover, buffer)?;
+                        compositor.damage(cover, Rect::new(0, 0, 1, 1))?;
+                        compositor.commit(cover)?;
+                        compositor.set_visible(cover, true)?;
+                        cover
+                    }
+                };
+                let area = viewport.area;
+                compositor.set_destination(cover, Some((area.width, area.height)))?;
+                compositor.move_window(cover, area.x, area.y)?;
+                self.covers.insert(name.to_string(), (cover, *viewport));
+            }
+            let (buffer_width, buffer_height) = self.tree.buffer_size();
+            let attach = self.buffer.is_none();
+            let buffer = self
+                .buffer
+                .get_or_insert_with(|| Buffer::new(buffer_width, buffer_height, BufferStorage::SharedMemory))
+                .clone();
+            let damage = self.tree.render(&buffer)?;
+            if attach {
+                compositor.attach(self.prompt, buffer)?;
+                compositor.set_destination(self.prompt, (self.scale != 1.0).then_some((PROMPT_WIDTH, PROMPT_HEIGHT)))?;
+            }
+            let dirty = !damage.is_empty();
+            for area in damage {
+                compositor.damage(self.prompt, area)?;
+            }
+            if attach || dirty {
+                compositor.commit(self.prompt)?;
+            }
+            // Centred on the first output
+            if let Some((_, viewport)) = outputs.first() {
+                let area = viewport.area;
+                let x = area.x + (area.width as i32 - PROMPT_WIDTH as i32) / 2;
+                let y = area.y + (area.height as i32 - PROMPT_HEIGHT as i32) / 2;
+                if self.prompt_position != Some((x, y)) {
+                    self.prompt_position = Some((x, y));
+                    compositor.move_window(self.prompt, x, y)?;
+                }
+            }
+            // Anything raised since the last update goes back under the
+            // covers, with the prompt on top
+            let order = compositor.stacking_order();
+            let top = &order[order.len().saturating_sub(self.covers.len() + 1)..];
+            let on_top = top.last() == Some(&self.prompt) && self.covers.values().all(|(cover, _)| top.contains(cover));
+            if !self.shown || !on_top {
+                for (cover, _) in self.covers.values() {
+                    compositor.raise(*cover)?;
+                }
+                compositor.set_visible(self.prompt, true)?;
+                compositor.raise(self.prompt)?;
+            }
+            self.shown = true;
+            Ok(())
+        }
+    }
+}
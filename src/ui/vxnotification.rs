@@ -1,26 +1,686 @@
+// src/ui/vxnotification.rs
+
 pub mod vxnotification {
-    pub fn init() {
-        println!("Initializing VXNotification...");
-        // Initialize the VXNotification system
+    use crate::vxui_toolkit::vxui_toolkit::{Direction, Element, WidgetTree};
+    use vaelix_core::vxchan::vxchan::VXChanManager;
+    use vaelix_core::vxfs::vxfs::VXFS;
+    use vaelix_graphics::vxfont::vxfont::TextRenderer;
+    use vaelix_graphics::vxtheme::vxtheme::{StyleOverride, Theme, Variant};
+    use vaelix_graphics::vxwin::vxwin::{Buffer, BufferStorage, Compositor, WindowId};
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::sync::Arc;
+
+    pub const REQUEST_CHANNEL: &str = "vxnotify";
+    pub const REPLY_CHANNEL: &str = "vxnotify.reply";
+
+    // On screen at once; the rest wait their turn
+    pub const MAX_VISIBLE: usize = 3;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct NotificationId(pub u32);
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub enum Urgency {
+        Low,
+        Normal,
+        Critical,
+    }
+
+    impl Urgency {
+        pub fn name(&self) -> &'static str {
+            match self {
+                Urgency::Low => "low",
+                Urgency::Normal => "normal",
+                Urgency::Critical => "critical",
+            }
+        }
+
+        pub fn parse(name: &str) -> Result<Self, &'static str> {
+            match name {
+                "low" => Ok(Urgency::Low),
+                "normal" => Ok(Urgency::Normal),
+                "critical" => Ok(Urgency::Critical),
+                _ => Err("Unknown urgency"),
+            }
+        }
+
+        // Microseconds on screen; critical notifications stay until
+        // dismissed
+        pub fn default_timeout(&self) -> Option<u64> {
+            match self {
+                Urgency::Low => Some(4_000_000),
+                Urgency::Normal => Some(8_000_000),
+                Urgency::Critical => None,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum CloseReason {
+        Expired,
+        // By the user, including through an action
+        Dismissed,
+        // By the application
+        Closed,
+    }
+
+    impl CloseReason {
+        pub fn name(&self) -> &'static str {
+            match self {
+                CloseReason::Expired => "expired",
+                CloseReason::Dismissed => "dismissed",
+                CloseReason::Closed => "closed",
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Action {
+        pub key: String,
+        pub label: String,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Notification {
+        pub app: String,
+        pub summary: String,
+        pub body: String,
+        pub urgency: Urgency,
+        pub actions: Vec<Action>,
+        // Microseconds once shown; None stays until dismissed
+        pub timeout: Option<u64>,
+        // Channel that hears "action ID KEY" and "closed ID REASON"
+        pub sender: Option<String>,
     }
 
-    pub fn show_notification(message: &str) {
-        println!("Showing notification: {}", message);
-        // Show a notification
+    impl Notification {
+        pub fn new(app: &str, summary: &str, body: &str, urgency: Urgency) -> Self {
+            Notification {
+                app: app.to_string(),
+                summary: summary.to_string(),
+                body: body.to_string(),
+                urgency,
+                actions: Vec::new(),
+                timeout: urgency.default_timeout(),
+                sender: None,
+            }
+        }
+
+        pub fn action(mut self, key: &str, label: &str) -> Self {
+            self.actions.push(Action {
+                key: key.to_string(),
+                label: label.to_string(),
+            });
+            self
+        }
+
+        pub fn timeout(mut self, timeout: Option<u64>) -> Self {
+            self.timeout = timeout;
+            self
+        }
+
+        pub fn sender(mut self, channel: &str) -> Self {
+            self.sender = Some(channel.to_string());
+            self
+        }
+    }
+
+    struct Entry {
+        id: NotificationId,
+        notification: Notification,
+        // When it went on screen; timeouts run from here
+        shown_at: Option<u64>,
     }
 
-    pub fn hide_notification() {
-        println!("Hiding notification...");
-        // Hide the notification
+    fn escape(text: &str) -> String {
+        text.replace('\\', "\\\\").replace('\n', "\\n")
     }
 
-    pub fn update_notification(message: &str) {
-        println!("Updating notification: {}", message);
-        // Update the notification
+    fn unescape(text: &str) -> String {
+        let mut result = String::new();
+        let mut characters = text.chars();
+        while let Some(character) = characters.next() {
+            match (character, character == '\\') {
+                (_, true) => match characters.next() {
+                    Some('n') => result.push('\n'),
+                    Some(other) => result.push(other),
+                    None => {}
+                },
+                (character, false) => result.push(character),
+            }
+        }
+        result
     }
 
-    pub fn update() {
-        println!("Updating VXNotification...");
-        // Update the VXNotification system
+    // Keeps notifications until they expire or are dismissed, decides
+    // which are on screen and queues messages back to their senders.
+    // Pending notifications are saved so they survive a restart.
+    pub struct NotificationDaemon {
+        entries: Vec<Entry>,
+        next_id: u32,
+        do_not_disturb: bool,
+        now: u64,
+        // Bumped whenever what is on screen changes
+        revision: u64,
+        outbox: Vec<(String, String)>,
+        path: Option<String>,
+    }
+
+    impl NotificationDaemon {
+        pub fn new(path: Option<&str>) -> Self {
+            NotificationDaemon {
+                entries: Vec::new(),
+                next_id: 1,
+                do_not_disturb: false,
+                now: 0,
+                revision: 0,
+                outbox: Vec::new(),
+                path: path.map(str::to_string),
+            }
+        }
+
+        // Restored notifications are queued afresh, with their timeouts
+        // restarting once shown
+        pub fn load(path: &str) -> Result<Self, &'static str> {
+            let mut daemon = NotificationDaemon::new(Some(path));
+            let contents = match VXFS::new().read_file(path) {
+                Ok(contents) => contents,
+                Err(_) => return Ok(daemon),
+            };
+            for line in contents.lines().filter(|line| !line.is_empty()) {
+                if let Some(id) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                    let id = NotificationId(id.parse().map_err(|_| "Invalid notification id")?);
+                    daemon.next_id = daemon.next_id.max(id.0 + 1);
+                    daemon.entries.push(Entry {
+                        id,
+                        notification: Notification::new("", "", "", Urgency::Normal),
+                        shown_at: None,
+                    });
+                    continue;
+                }
+                let (key, value) = line.split_once('=').ok_or("Malformed notification line")?;
+                let Some(entry) = daemon.entries.last_mut() else {
+                    match key {
+                        "dnd" => daemon.do_not_disturb = value == "on",
+                        _ => return Err("Unknown notification setting"),
+                    }
+                    continue;
+                };
+                let notification = &mut entry.notification;
+                match key {
+                    "app" => notification.app = value.to_string(),
+                    "urgency" => notification.urgency = Urgency::parse(value)?,
+                    "timeout" if value == "never" => notification.timeout = None,
+                    "timeout" => notification.timeout = Some(value.parse().map_err(|_| "Invalid timeout")?),
+                    "sender" => notification.sender = Some(value.to_string()),
+                    "summary" => notification.summary = unescape(value),
+                    "body" => notification.body = unescape(value),
+                    "action" => {
+                        let (key, label) = value.split_once(' ').ok_or("Malformed action")?;
+                        notification.actions.push(Action {
+                            key: key.to_string(),
+                            label: unescape(label),
+                        });
+                    }
+                    _ => return Err("Unknown notification setting"),
+                }
+            }
+            daemon.promote();
+            Ok(daemon)
+        }
+
+        fn save(&self) -> Result<(), &'static str> {
+            let Some(path) = &self.path else {
+                return Ok(());
+            };
+            let mut contents = format!("dnd={}\n", if self.do_not_disturb { "on" } else { "off" });
+            for entry in &self.entries {
+                let notification = &entry.notification;
+                contents += &format!(
+                    "[{}]\napp={}\nurgency={}\ntimeout={}\nsummary={}\nbody={}\n",
+                    entry.id.0,
+                    notification.app,
+                    notification.urgency.name(),
+                    notification.timeout.map_or("never".to_string(), |timeout| timeout.to_string()),
+                    escape(&notification.summary),
+                    escape(&notification.body)
+                );
+                if let Some(sender) = &notification.sender {
+                    contents += &format!("sender={}\n", sender);
+                }
+                for action in &notification.actions {
+                    contents += &format!("action={} {}\n", action.key, escape(&action.label));
+                }
+            }
+            VXFS::new().write_file(path, &contents).map_err(|_| "Failed to save notifications")
+        }
+
+        fn save_or_log(&self) {
+            if let Err(error) = self.save() {
+                log::warn!("{}", error);
+            }
+        }
+
+        // Fills free slots, most urgent first. Do-not-disturb holds back
+        // everything but critical notifications, including ones already up.
+        fn promote(&mut self) {
+            let mut changed = false;
+            for entry in &mut self.entries {
+                if self.do_not_disturb && entry.shown_at.is_some() && entry.notification.urgency != Urgency::Critical {
+                    entry.shown_at = None;
+                    changed = true;
+                }
+            }
+            loop {
+                let shown = self.entries.iter().filter(|entry| entry.shown_at.is_some()).count();
+                let next = self
+                    .entries
+                    .iter_mut()
+                    .filter(|entry| entry.shown_at.is_none())
+                    .filter(|entry| !self.do_not_disturb || entry.notification.urgency == Urgency::Critical)
+                    .max_by_key(|entry| (entry.notification.urgency, std::cmp::Reverse(entry.id)));
+                match next {
+                    Some(entry) if shown < MAX_VISIBLE => {
+                        entry.shown_at = Some(self.now);
+                        changed = true;
+                    }
+                    _ => break,
+                }
+            }
+            if changed {
+                self.revision += 1;
+            }
+        }
+
+        pub fn notify(&mut self, notification: Notification) -> NotificationId {
+            let id = NotificationId(self.next_id);
+            self.next_id += 1;
+            log::info!("Notification {} from {}: {}", id.0, notification.app, notification.summary);
+            self.entries.push(Entry {
+                id,
+                notification,
+                shown_at: None,
+            });
+            self.promote();
+            self.save_or_log();
+            id
+        }
+
+        // Updates in place; a notification on screen restarts its timeout
+        pub fn replace(&mut self, id: NotificationId, notification: Notification) -> Result<(), &'static str> {
+            let now = self.now;
+            let entry = self.entries.iter_mut().find(|entry| entry.id == id).ok_or("Notification not found")?;
+            entry.notification = notification;
+            if entry.shown_at.is_some() {
+                entry.shown_at = Some(now);
+                self.revision += 1;
+            }
+            self.save_or_log();
+            Ok(())
+        }
+
+        pub fn get(&self, id: NotificationId) -> Option<&Notification> {
+            self.entries.iter().find(|entry| entry.id == id).map(|entry| &entry.notification)
+        }
+
+        // Oldest first, as stacked on screen
+        pub fn visible(&self) -> Vec<NotificationId> {
+            self.entries.iter().filter(|entry| entry.shown_at.is_some()).map(|entry| entry.id).collect()
+        }
+
+        pub fn queued(&self) -> Vec<NotificationId> {
+            self.entries.iter().filter(|entry| entry.shown_at.is_none()).map(|entry| entry.id).collect()
+        }
+
+        pub fn revision(&self) -> u64 {
+            self.revision
+        }
+
+        fn tell_sender(&mut self, notification: &Notification, message: String) {
+            if let Some(sender) = &notification.sender {
+                self.outbox.push((sender.clone(), message));
+            }
+        }
+
+        pub fn close(&mut self, id: NotificationId, reason: CloseReason) -> Result<(), &'static str> {
+            let index = self.entries.iter().position(|entry| entry.id == id).ok_or("Notification not found")?;
+            let entry = self.entries.remove(index);
+            self.tell_sender(&entry.notification, format!("closed {} {}", id.0, reason.name()));
+            if entry.shown_at.is_some() {
+                self.revision += 1;
+            }
+            self.promote();
+            self.save_or_log();
+            Ok(())
+        }
+
+        // Routes the action back to the sender, then dismisses
+        pub fn invoke(&mut self, id: NotificationId, key: &str) -> Result<(), &'static str> {
+            let notification = self.get(id).ok_or("Notification not found")?.clone();
+            if !notification.actions.iter().any(|action| action.key == key) {
+                return Err("Unknown action");
+            }
+            self.tell_sender(&notification, format!("action {} {}", id.0, key));
+            self.close(id, CloseReason::Dismissed)
+        }
+
+        pub fn do_not_disturb(&self) -> bool {
+            self.do_not_disturb
+        }
+
+        pub fn set_do_not_disturb(&mut self, enabled: bool) {
+            self.do_not_disturb = enabled;
+            self.promote();
+            self.save_or_log();
+        }
+
+        // Expires notifications whose time on screen is up and brings
+        // queued ones forward; `now` is in microseconds
+        pub fn tick(&mut self, now: u64) {
+            self.now = now;
+            let expired: Vec<NotificationId> = self
+                .entries
+                .iter()
+                .filter(|entry| {
+                    let deadline = entry.shown_at.zip(entry.notification.timeout);
+                    deadline.is_some_and(|(shown, timeout)| now >= shown.saturating_add(timeout))
+                })
+                .map(|entry| entry.id)
+                .collect();
+            for id in expired {
+                let _ = self.close(id, CloseReason::Expired);
+            }
+            self.promote();
+        }
+
+        // (channel, message) pairs for senders, in order
+        pub fn take_outbox(&mut self) -> Vec<(String, String)> {
+            std::mem::take(&mut self.outbox)
+        }
+    }
+
+    fn parse_id(value: &str) -> Result<NotificationId, &'static str> {
+        value.parse().map(NotificationId).map_err(|_| "Invalid notification id")
+    }
+
+    // Grammar, one request per message:
+    //   notify APP URGENCY (MILLISECONDS|default|never) (CHANNEL|-)
+    //   SUMMARY
+    //   [BODY]
+    //   [action KEY LABEL]...
+    //   close ID | invoke ID KEY | dnd (on|off) | list
+    // Messages and replies are framed as in vxnetctl; notify replies with
+    // the new id
+    pub struct NotificationService;
+
+    impl NotificationService {
+        pub fn new(manager: &VXChanManager) -> Result<Self, &'static str> {
+            manager.create_channel(REQUEST_CHANNEL)?;
+            manager.create_channel(REPLY_CHANNEL)?;
+            Ok(NotificationService)
+        }
+
+        fn parse_notify(request: &str) -> Result<Notification, &'static str> {
+            let mut lines = request.lines();
+            let header: Vec<&str> = lines.next().unwrap_or("").split_whitespace().collect();
+            let ["notify", app, urgency, timeout, sender] = header.as_slice() else {
+                return Err("Malformed notify request");
+            };
+            let urgency = Urgency::parse(urgency)?;
+            let summary = lines.next().ok_or("Missing summary")?;
+            let body = lines.next().unwrap_or("");
+            let mut notification = Notification::new(app, summary, body, urgency);
+            notification.timeout = match *timeout {
+                "default" => urgency.default_timeout(),
+                "never" => None,
+                milliseconds => Some(milliseconds.parse::<u64>().map_err(|_| "Invalid timeout")? * 1000),
+            };
+            if *sender != "-" {
+                notification.sender = Some(sender.to_string());
+            }
+            for line in lines {
+                let (key, label) = line.strip_prefix("action ").and_then(|line| line.split_once(' ')).ok_or("Malformed action")?;
+                notification = notification.action(key, label);
+            }
+            Ok(notification)
+        }
+
+        fn execute(request: &str, daemon: &mut NotificationDaemon) -> Result<String, &'static str> {
+            if request.starts_with("notify ") {
+                return Ok(daemon.notify(Self::parse_notify(request)?).0.to_string());
+            }
+            let words: Vec<&str> = request.split_whitespace().collect();
+            match words.as_slice() {
+                ["close", id] => daemon.close(parse_id(id)?, CloseReason::Closed).map(|_| String::new()),
+                ["invoke", id, key] => daemon.invoke(parse_id(id)?, key).map(|_| String::new()),
+                ["dnd", state @ ("on" | "off")] => {
+                    daemon.set_do_not_disturb(*state == "on");
+                    Ok(String::new())
+                }
+                ["list"] => {
+                    let visible = daemon.visible();
+                    let lines: Vec<String> = daemon
+                        .entries
+                        .iter()
+                        .map(|entry| {
+                            format!(
+                                "{} {} {} {} {}",
+                                entry.id.0,
+                                if visible.contains(&entry.id) { "shown" } else { "queued" },
+                                entry.notification.urgency.name(),
+                                entry.notification.app,
+                                entry.notification.summary
+                            )
+                        })
+                        .collect();
+                    Ok(lines.join("\n"))
+                }
+                [] => Err("Empty request"),
+                _ => Err("Unknown command"),
+            }
+        }
+
+        // Advances timeouts, answers queued requests and delivers action
+        // and close messages to senders; returns how many requests were
+        // handled
+        pub fn poll(&mut self, manager: &VXChanManager, daemon: &mut NotificationDaemon, now: u64) -> Result<usize, &'static str> {
+            daemon.tick(now);
+            let mut count = 0;
+            while let Some(message) = manager.try_receive_message(REQUEST_CHANNEL)? {
+                let (id, request) = message.split_once(' ').unwrap_or((message.as_str(), ""));
+                let result = id
+                    .parse::<u64>()
+                    .map_err(|_| "Invalid request id")
+                    .and_then(|_| Self::execute(request, daemon));
+                let reply = match result {
+                    Ok(body) if body.is_empty() => format!("{} ok", id),
+                    Ok(body) => format!("{} ok\n{}", id, body),
+                    Err(error) => format!("{} error {}", id, error),
+                };
+                manager.send_message(REPLY_CHANNEL, reply)?;
+                count += 1;
+            }
+            for (channel, message) in daemon.take_outbox() {
+                // Senders that have gone away just miss the message
+                if manager.send_message(&channel, message).is_err() {
+                    log::info!("Notification sender {} is gone", channel);
+                }
+            }
+            Ok(count)
+        }
+    }
+
+    pub fn send_request(manager: &VXChanManager, id: u64, request: &str) -> Result<(), &'static str> {
+        manager.send_message(REQUEST_CHANNEL, format!("{} {}", id, request))
+    }
+
+    enum Click {
+        Action(NotificationId, String),
+        Dismiss(NotificationId),
+    }
+
+    const CRITICAL_COLOR: u32 = 0xFFE0_4040;
+
+    // The visible notifications as a stack of toolkit cards in a window
+    // pinned to the top-right corner of the screen
+    pub struct NotificationOverlay {
+        window: WindowId,
+        width: u32,
+        margin: i32,
+        tree: Option<WidgetTree>,
+        buffer: Option<Buffer>,
+        revision: Option<u64>,
+        scale: f32,
+        font: Option<Arc<TextRenderer>>,
+        theme: Theme,
+        variant: Variant,
+        clicks: Sender<Click>,
+        clicked: Receiver<Click>,
+    }
+
+    impl NotificationOverlay {
+        pub fn new(compositor: &mut Compositor, width: u32) -> Self {
+            let (clicks, clicked) = mpsc::channel();
+            NotificationOverlay {
+                window: compositor.create_window(0, 0),
+                width,
+                margin: 8,
+                tree: None,
+                buffer: None,
+                revision: None,
+                scale: 1.0,
+                font: None,
+                theme: Theme::default(),
+                variant: Variant::Dark,
+                clicks,
+                clicked,
+            }
+        }
+
+        pub fn window(&self) -> WindowId {
+            self.window
+        }
+
+        pub fn set_font(&mut self, font: Option<Arc<TextRenderer>>) {
+            self.font = font;
+            self.revision = None;
+        }
+
+        pub fn set_theme(&mut self, theme: Theme, variant: Variant) {
+            (self.theme, self.variant) = (theme, variant);
+            self.revision = None;
+        }
+
+        // The scale of the output the stack is shown on
+        pub fn set_scale(&mut self, scale: f32) {
+            self.scale = scale;
+            self.revision = None;
+        }
+
+        // Window-local coordinates, as routed by the session
+        pub fn pointer_motion(&mut self, x: i32, y: i32) {
+            if let Some(tree) = &mut self.tree {
+                tree.pointer_motion(x, y);
+            }
+        }
+
+        pub fn pointer_button(&mut self, x: i32, y: i32, pressed: bool) {
+            if let Some(tree) = &mut self.tree {
+                tree.pointer_button(x, y, pressed);
+            }
+        }
+
+        fn card(&self, id: NotificationId, notification: &Notification) -> Element {
+            let dismiss = self.clicks.clone();
+            let mut summary = Element::label(&notification.summary).grow(1);
+            if notification.urgency == Urgency::Critical {
+                summary = summary.style(StyleOverride {
+                    foreground: Some(CRITICAL_COLOR),
+                    ..StyleOverride::default()
+                });
+            }
+            let header = Element::container(Direction::Row)
+                .child(summary)
+                .child(Element::button("x").on_event(move |_, _, _| {
+                    let _ = dismiss.send(Click::Dismiss(id));
+                }));
+            let mut card = Element::container(Direction::Column).padding(8).child(header);
+            if !notification.body.is_empty() {
+                card = card.child(Element::label(&notification.body));
+            }
+            if !notification.actions.is_empty() {
+                let mut actions = Element::container(Direction::Row);
+                for action in &notification.actions {
+                    let (clicks, key) = (self.clicks.clone(), action.key.clone());
+                    actions = actions.child(Element::button(&action.label).on_event(move |_, _, _| {
+                        let _ = clicks.send(Click::Action(id, key.clone()));
+                    }));
+                }
+                card = card.child(actions);
+            }
+            card
+        }
+
+        fn rebuild(&mut self, daemon: &NotificationDaemon) {
+            let visible = daemon.visible();
+            if visible.is_empty() {
+                self.tree = None;
+                return;
+            }
+            let mut stack = Element::container(Direction::Column).size(Some(self.width), None);
+            for id in visible {
+                stack = stack.child(self.card(id, daemon.get(id).unwrap()));
+            }
+            let mut tree = WidgetTree::new(stack, self.width, 1);
+            tree.set_font(self.font.clone());
+            tree.set_theme(self.theme.clone(), self.variant);
+            tree.set_scale(self.scale);
+            let (_, height) = tree.preferred_size();
+            tree.resize(self.width, height.max(1));
+            self.tree = Some(tree);
+        }
+
+        // Hands clicks to the daemon, then redraws and repositions the
+        // stack; hidden while nothing is on screen
+        pub fn update(&mut self, daemon: &mut NotificationDaemon, compositor: &mut Compositor) -> Result<(), &'static str> {
+            while let Ok(click) = self.clicked.try_recv() {
+                // The notification may have expired in the meantime
+                let _ = match click {
+                    Click::Action(id, key) => daemon.invoke(id, &key),
+                    Click::Dismiss(id) => daemon.close(id, CloseReason::Dismissed),
+                };
+            }
+            if self.revision != Some(daemon.revision()) {
+                self.revision = Some(daemon.revision());
+                self.rebuild(daemon);
+                self.buffer = None;
+            }
+            let Some(tree) = &mut self.tree else {
+                return compositor.set_visible(self.window, false);
+            };
+            let ((width, height), (buffer_width, buffer_height)) = (tree.size(), tree.buffer_size());
+            let attach = self.buffer.is_none();
+            let buffer = self
+                .buffer
+                .get_or_insert_with(|| Buffer::new(buffer_width, buffer_height, BufferStorage::SharedMemory))
+                .clone();
+            let damage = tree.render(&buffer)?;
+            if attach {
+                compositor.attach(self.window, buffer)?;
+                compositor.set_destination(self.window, (self.scale != 1.0).then_some((width, height)))?;
+                let (screen_width, _) = compositor.resolution();
+                compositor.move_window(self.window, screen_width as i32 - width as i32 - self.margin, self.margin)?;
+                compositor.set_visible(self.window, true)?;
+                compositor.raise(self.window)?;
+            }
+            let dirty = !damage.is_empty();
+            for area in damage {
+                compositor.damage(self.window, area)?;
+            }
+            if attach || dirty {
+                compositor.commit(self.window)?;
+            }
+            Ok(())
+        }
     }
 }
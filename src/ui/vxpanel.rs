@@ -0,0 +1,793 @@
+// src/ui/vxpanel.rs
+
+pub mod vxpanel {
+    use crate::vxui_toolkit::vxui_toolkit::{Align, Direction, Element, WidgetEvent, WidgetTree};
+    use vaelix_core::input::input::{KEY_DOWN, KEY_ENTER, KEY_ESC, KEY_UP};
+    use vaelix_core::power::button::button::{self, PowerEvent};
+    use vaelix_core::power::profile::profile::{self, AC_PROFILE, BATTERY_PROFILE};
+    use vaelix_core::vxchan::vxchan::VXChanManager;
+    use vaelix_core::vxfs::vxfs::VXFS;
+    use vaelix_graphics::vxfont::vxfont::TextRenderer;
+    use vaelix_graphics::vxtheme::vxtheme::{StyleOverride, Theme, Variant};
+    use vaelix_graphics::vxwin::vxwin::{Buffer, BufferStorage, Compositor, WindowId};
+    use vaelix_graphics::vxwm::vxwm::{SurfaceId, WindowManager, WindowState};
+    use vaelix_networking::vxnetctl::vxnetctl;
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::sync::Arc;
+
+    pub const PANEL_HEIGHT: u32 = 32;
+    pub const LAUNCHER_WIDTH: u32 = 360;
+    pub const LAUNCHER_HEIGHT: u32 = 400;
+    // How often vxnetctl is asked for the interfaces
+    pub const NETWORK_POLL: u64 = 5_000_000;
+    // Request ids the panel uses with vxnetctl, well clear of other clients
+    const REQUEST_BASE: u64 = 1 << 48;
+
+    // An application as described by its manifest
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct AppEntry {
+        // The manifest's file name without ".desktop"
+        pub id: String,
+        pub name: String,
+        // Field codes such as %f and %U are dropped
+        pub exec: String,
+        pub icon: Option<String>,
+        pub comment: String,
+        pub keywords: Vec<String>,
+        pub categories: Vec<String>,
+    }
+
+    fn split_list(value: &str) -> Vec<String> {
+        value.split(';').map(str::trim).filter(|item| !item.is_empty()).map(str::to_string).collect()
+    }
+
+    fn strip_field_codes(exec: &str) -> String {
+        let words: Vec<String> = exec
+            .split_whitespace()
+            .filter(|word| !(word.len() == 2 && word.starts_with('%') && word != &"%%"))
+            .map(|word| word.replace("%%", "%"))
+            .collect();
+        words.join(" ")
+    }
+
+    impl AppEntry {
+        // Desktop-entry style: key=value lines in a [Desktop Entry] group.
+        // Other groups, localised keys and comments are ignored. Entries
+        // that are hidden or not applications parse to None.
+        pub fn parse(id: &str, text: &str) -> Result<Option<Self>, &'static str> {
+            let mut entry = AppEntry {
+                id: id.to_string(),
+                name: String::new(),
+                exec: String::new(),
+                icon: None,
+                comment: String::new(),
+                keywords: Vec::new(),
+                categories: Vec::new(),
+            };
+            let mut in_entry = false;
+            let mut shown = true;
+            for line in text.lines().map(str::trim) {
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                if line.starts_with('[') {
+                    in_entry = line == "[Desktop Entry]";
+                    continue;
+                }
+                if !in_entry {
+                    continue;
+                }
+                let (key, value) = line.split_once('=').ok_or("Expected key=value")?;
+                let value = value.trim();
+                match key.trim() {
+                    "Type" if value != "Application" => return Ok(None),
+                    "Name" => entry.name = value.to_string(),
+                    "Exec" => entry.exec = strip_field_codes(value),
+                    "Icon" => entry.icon = Some(value.to_string()),
+                    "Comment" => entry.comment = value.to_string(),
+                    "Keywords" => entry.keywords = split_list(value),
+                    "Categories" => entry.categories = split_list(value),
+                    "NoDisplay" | "Hidden" if value == "true" => shown = false,
+                    _ => {}
+                }
+            }
+            if entry.name.is_empty() {
+                return Err("Missing Name");
+            }
+            if entry.exec.is_empty() {
+                return Err("Missing Exec");
+            }
+            Ok(shown.then_some(entry))
+        }
+
+        // How well every word of a lowercased query matches; lower is
+        // better, None when some word does not match at all
+        fn rank(&self, words: &[String]) -> Option<u32> {
+            let name = self.name.to_lowercase();
+            let lower = |values: &[String]| values.iter().map(|value| value.to_lowercase()).collect::<Vec<_>>();
+            let (keywords, categories) = (lower(&self.keywords), lower(&self.categories));
+            let mut total = 0;
+            for word in words {
+                total += if name.starts_with(word.as_str()) {
+                    0
+                } else if name.split_whitespace().any(|part| part.starts_with(word.as_str())) {
+                    1
+                } else if name.contains(word.as_str()) {
+                    2
+                } else if keywords.iter().chain([&self.id, &self.exec]).any(|value| value.starts_with(word.as_str())) {
+                    3
+                } else if self.comment.to_lowercase().contains(word.as_str()) || categories.contains(word) {
+                    4
+                } else {
+                    return None;
+                };
+            }
+            Some(total)
+        }
+    }
+
+    // The applications the launcher offers, sorted by name
+    pub struct AppCatalog {
+        entries: Vec<AppEntry>,
+    }
+
+    impl AppCatalog {
+        pub fn new(mut entries: Vec<AppEntry>) -> Self {
+            entries.sort_by_key(|entry| entry.name.to_lowercase());
+            AppCatalog { entries }
+        }
+
+        // Every *.desktop manifest in the directories. Missing directories
+        // and broken manifests are skipped; an id seen twice keeps the
+        // first, so per-user directories go first.
+        pub fn load(directories: &[&str]) -> Self {
+            let mut vxfs = VXFS::new();
+            let mut entries: Vec<AppEntry> = Vec::new();
+            for directory in directories {
+                let Ok(names) = vxfs.list_dir(directory) else {
+                    continue;
+                };
+                for name in names {
+                    let Some(id) = name.strip_suffix(".desktop") else {
+                        continue;
+                    };
+                    if entries.iter().any(|entry| entry.id == id) {
+                        continue;
+                    }
+                    let path = format!("{}/{}", directory, name);
+                    let parsed = vxfs
+                        .read_file(&path)
+                        .map_err(|_| "Failed to read manifest")
+                        .and_then(|text| AppEntry::parse(id, &text));
+                    match parsed {
+                        Ok(Some(entry)) => entries.push(entry),
+                        Ok(None) => {}
+                        Err(error) => log::warn!("Skipping {}: {}", path, error),
+                    }
+                }
+            }
+            Self::new(entries)
+        }
+
+        pub fn entries(&self) -> &[AppEntry] {
+            &self.entries
+        }
+
+        pub fn get(&self, id: &str) -> Option<&AppEntry> {
+            self.entries.iter().find(|entry| entry.id == id)
+        }
+
+        // Best matches first: name prefixes, then word prefixes and
+        // substrings of the name, then keywords and the command, then the
+        // comment and categories. An empty query lists everything.
+        pub fn search(&self, query: &str) -> Vec<&AppEntry> {
+            let words: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+            let mut ranked: Vec<(u32, &AppEntry)> =
+                self.entries.iter().filter_map(|entry| Some((entry.rank(&words)?, entry))).collect();
+            ranked.sort_by_key(|(rank, _)| *rank);
+            ranked.into_iter().map(|(_, entry)| entry).collect()
+        }
+    }
+
+    // What the panel reports on besides the windows
+    #[derive(Debug, Clone, Default, PartialEq, Eq)]
+    pub struct SystemStatus {
+        // Unknown until the first AC event
+        pub ac: Option<bool>,
+        pub profile: Option<String>,
+        // Interfaces that are up with a carrier and an address; unknown
+        // until vxnetctl first answers
+        pub network: Option<Vec<String>>,
+    }
+
+    impl SystemStatus {
+        pub fn power_text(&self) -> String {
+            let source = match self.ac {
+                Some(true) => "AC",
+                Some(false) => "Battery",
+                None => "",
+            };
+            match self.profile.as_deref() {
+                Some(profile) if profile != AC_PROFILE && profile != BATTERY_PROFILE && source.is_empty() => profile.to_string(),
+                Some(profile) if profile != AC_PROFILE && profile != BATTERY_PROFILE => format!("{} ({})", source, profile),
+                _ => source.to_string(),
+            }
+        }
+
+        pub fn network_text(&self) -> String {
+            match &self.network {
+                None => String::new(),
+                Some(names) if names.is_empty() => "Offline".to_string(),
+                Some(names) => names.join(", "),
+            }
+        }
+    }
+
+    // Follows the power and network services over vxchan. Services that
+    // are not running are simply not reported on.
+    pub struct StatusMonitor {
+        status: SystemStatus,
+        next_id: u64,
+        pending: Option<u64>,
+        next_poll: u64,
+    }
+
+    impl Default for StatusMonitor {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl StatusMonitor {
+        pub fn new() -> Self {
+            StatusMonitor {
+                status: SystemStatus::default(),
+                next_id: REQUEST_BASE,
+                pending: None,
+                next_poll: 0,
+            }
+        }
+
+        pub fn status(&self) -> &SystemStatus {
+            &self.status
+        }
+
+        // The interfaces in a vxnetctl list reply that carry traffic
+        fn connected(body: &str) -> Vec<String> {
+            body.lines()
+                .filter(|line| {
+                    let fields: Vec<&str> = line.split_whitespace().collect();
+                    fields.contains(&"admin=up") && fields.contains(&"carrier=up") && !fields.contains(&"addresses=")
+                })
+                .filter_map(|line| line.split_whitespace().next().map(str::to_string))
+                .collect()
+        }
+
+        // Power events are handed back as they are read here, for whoever
+        // else cares about the lid and buttons
+        pub fn poll(&mut self, manager: &VXChanManager, now: u64) -> Result<Vec<PowerEvent>, &'static str> {
+            let mut events = Vec::new();
+            while let Ok(Some(message)) = manager.try_receive_message(button::EVENT_CHANNEL) {
+                let Some(event) = PowerEvent::parse(&message) else {
+                    continue;
+                };
+                match event {
+                    PowerEvent::AcPlugged => self.status.ac = Some(true),
+                    PowerEvent::AcUnplugged => self.status.ac = Some(false),
+                    _ => {}
+                }
+                events.push(event);
+            }
+            while let Ok(Some(message)) = manager.try_receive_message(profile::EVENT_CHANNEL) {
+                if let Some(name) = message.strip_prefix("profile ") {
+                    self.status.profile = Some(name.to_string());
+                }
+            }
+            if let Some(pending) = self.pending {
+                // Replies to other clients go back on the channel
+                let mut others = Vec::new();
+                while let Ok(Some(reply)) = manager.try_receive_message(vxnetctl::REPLY_CHANNEL) {
+                    let (header, body) = reply.split_once('\n').unwrap_or((reply.as_str(), ""));
+                    if header.split_whitespace().next() != Some(pending.to_string().as_str()) {
+                        others.push(reply);
+                        continue;
+                    }
+                    self.pending = None;
+                    if header.ends_with(" ok") {
+                        self.status.network = Some(Self::connected(body));
+                    }
+                }
+                for reply in others {
+                    manager.send_message(vxnetctl::REPLY_CHANNEL, reply)?;
+                }
+            }
+            if now >= self.next_poll {
+                self.next_poll = now + NETWORK_POLL;
+                let id = self.next_id;
+                if vxnetctl::send_request(manager, id, "list").is_ok() {
+                    self.next_id += 1;
+                    self.pending = Some(id);
+                }
+            }
+            Ok(events)
+        }
+    }
+
+    // "HH:MM" for microseconds since the Unix epoch, shifted by
+    // `utc_offset` seconds
+    pub fn clock_text(time: u64, utc_offset: i32) -> String {
+        let seconds = (time / 1_000_000) as i64 + utc_offset as i64;
+        let minutes = seconds.div_euclid(60).rem_euclid(24 * 60);
+        format!("{:02}:{:02}", minutes / 60, minutes % 60)
+    }
+
+    enum LauncherEvent {
+        Query(String),
+        Launch(usize),
+    }
+
+    // A centred search box over the application list. Chosen entries are
+    // queued for the session to start.
+    pub struct Launcher {
+        window: WindowId,
+        catalog: AppCatalog,
+        tree: WidgetTree,
+        // Ids of the listed entries
+        results: Vec<String>,
+        buffer: Option<Buffer>,
+        scale: f32,
+        open: bool,
+        shown: bool,
+        events: Receiver<LauncherEvent>,
+        launches: Vec<AppEntry>,
+    }
+
+    impl Launcher {
+        pub fn new(compositor: &mut Compositor, catalog: AppCatalog) -> Self {
+            let (sender, events) = mpsc::channel();
+            let clicks = sender.clone();
+            let root = Element::container(Direction::Column)
+                .padding(8)
+                .child(Element::text_input("Search applications").name("search").on_event(move |_, _, event| {
+                    if let WidgetEvent::Changed(query) = event {
+                        let _ = sender.send(LauncherEvent::Query(query.clone()));
+                    }
+                }))
+                .child(Element::list(&[]).name("results").grow(1).on_event(move |_, _, event| {
+                    if let WidgetEvent::Selected(index) = event {
+                        let _ = clicks.send(LauncherEvent::Launch(*index));
+                    }
+                }));
+            let mut launcher = Launcher {
+                window: compositor.create_window(0, 0),
+                catalog,
+                tree: WidgetTree::new(root, LAUNCHER_WIDTH, LAUNCHER_HEIGHT),
+                results: Vec::new(),
+                buffer: None,
+                scale: 1.0,
+                open: false,
+                shown: false,
+                events,
+                launches: Vec::new(),
+            };
+            launcher.search("");
+            launcher
+        }
+
+        pub fn window(&self) -> WindowId {
+            self.window
+        }
+
+        pub fn tree(&self) -> &WidgetTree {
+            &self.tree
+        }
+
+        pub fn catalog(&self) -> &AppCatalog {
+            &self.catalog
+        }
+
+        pub fn set_font(&mut self, font: Option<Arc<TextRenderer>>) {
+            self.tree.set_font(font);
+        }
+
+        pub fn set_theme(&mut self, theme: Theme, variant: Variant) {
+            self.tree.set_theme(theme, variant);
+        }
+
+        pub fn set_scale(&mut self, scale: f32) {
+            self.scale = scale;
+            self.tree.set_scale(scale);
+            self.buffer = None;
+        }
+
+        pub fn is_open(&self) -> bool {
+            self.open
+        }
+
+        // Opens with an empty query and the search box focused
+        pub fn open(&mut self) {
+            let search = self.tree.find("search").unwrap();
+            let _ = self.tree.set_text(search, "");
+            let _ = self.tree.set_focus(Some(search));
+            self.search("");
+            self.open = true;
+        }
+
+        pub fn close(&mut self) {
+            self.open = false;
+        }
+
+        pub fn toggle(&mut self) {
+            if self.open {
+                self.close();
+            } else {
+                self.open();
+            }
+        }
+
+        fn search(&mut self, query: &str) {
+            let found = self.catalog.search(query);
+            let names: Vec<&str> = found.iter().map(|entry| entry.name.as_str()).collect();
+            self.results = found.iter().map(|entry| entry.id.clone()).collect();
+            let list = self.tree.find("results").unwrap();
+            let _ = self.tree.set_items(list, &names);
+            let _ = self.tree.select(list, (!names.is_empty()).then_some(0));
+        }
+
+        fn launch(&mut self, index: usize) {
+            let Some(entry) = self.results.get(index).and_then(|id| self.catalog.get(id)) else {
+                return;
+            };
+            log::info!("Launching {}", entry.exec);
+            self.launches.push(entry.clone());
+            self.close();
+        }
+
+        // The entries chosen since the last call
+        pub fn take_launches(&mut self) -> Vec<AppEntry> {
+            std::mem::take(&mut self.launches)
+        }
+
+        // Window-local coordinates
+        pub fn pointer_motion(&mut self, x: i32, y: i32) {
+            self.tree.pointer_motion(x, y);
+        }
+
+        pub fn pointer_button(&mut self, x: i32, y: i32, pressed: bool) {
+            self.tree.pointer_button(x, y, pressed);
+        }
+
+        // Up and Down move through the results while typing, Enter starts
+        // the selected one and Escape closes
+        pub fn key(&mut self, code: u32, pressed: bool) {
+            let list = self.tree.find("results").unwrap();
+            let count = self.results.len();
+            match code {
+                KEY_ESC if pressed => self.close(),
+                KEY_ENTER if pressed => {
+                    if let Some(index) = self.tree.selected(list) {
+                        self.launch(index);
+                    }
+                }
+                KEY_UP | KEY_DOWN if pressed && count > 0 => {
+                    let next = match (code, self.tree.selected(list)) {
+                        (KEY_UP, Some(index)) => index.saturating_sub(1),
+                        (KEY_DOWN, Some(index)) => (index + 1).min(count - 1),
+                        _ => 0,
+                    };
+                    let _ = self.tree.select(list, Some(next));
+                }
+                KEY_ESC | KEY_ENTER | KEY_UP | KEY_DOWN => {}
+                _ => self.tree.key(code, pressed),
+            }
+        }
+
+        pub fn text_input(&mut self, character: char) {
+            self.tree.text_input(character);
+        }
+
+        pub fn update(&mut self, compositor: &mut Compositor) -> Result<(), &'static str> {
+            while let Ok(event) = self.events.try_recv() {
+                match event {
+                    LauncherEvent::Query(query) => self.search(&query),
+                    LauncherEvent::Launch(index) => self.launch(index),
+                }
+            }
+            if !self.open {
+                if self.shown {
+                    self.shown = false;
+                    compositor.set_visible(self.window, false)?;
+                }
+                return Ok(());
+            }
+            let (buffer_width, buffer_height) = self.tree.buffer_size();
+            let attach = self.buffer.is_none();
+            let buffer = self
+                .buffer
+                .get_or_insert_with(|| Buffer::new(buffer_width, buffer_height, BufferStorage::SharedMemory))
+                .clone();
+            let damage = self.tree.render(&buffer)?;
+            if attach {
+                compositor.attach(self.window, buffer)?;
+                compositor.set_destination(self.window, (self.scale != 1.0).then_some((LAUNCHER_WIDTH, LAUNCHER_HEIGHT)))?;
+            }
+            let dirty = !damage.is_empty();
+            for area in damage {
+                compositor.damage(self.window, area)?;
+            }
+            if attach || dirty {
+                compositor.commit(self.window)?;
+            }
+            if !self.shown {
+                self.shown = true;
+                let (width, height) = compositor.resolution();
+                let x = (width as i32 - LAUNCHER_WIDTH as i32) / 2;
+                let y = (height as i32 - LAUNCHER_HEIGHT as i32) / 2;
+                compositor.move_window(self.window, x, y)?;
+                compositor.set_visible(self.window, true)?;
+                compositor.raise(self.window)?;
+            }
+            Ok(())
+        }
+    }
+
+    enum Click {
+        Launcher,
+        Task(SurfaceId),
+        Workspace(usize),
+    }
+
+    // A taskbar entry as last drawn
+    #[derive(Clone, PartialEq, Eq)]
+    struct Task {
+        surface: SurfaceId,
+        title: String,
+        focused: bool,
+        minimized: bool,
+    }
+
+    // The bar along the bottom of the screen: the launcher button, a
+    // button per window on the current workspace, then the workspaces,
+    // network, power and the clock
+    pub struct Panel {
+        window: WindowId,
+        launcher: Launcher,
+        tree: Option<WidgetTree>,
+        buffer: Option<Buffer>,
+        tasks: Vec<Task>,
+        // Names, and which is current, as last drawn
+        workspaces: (Vec<String>, usize),
+        // Chosen from the panel, for the session to switch to
+        switch: Option<usize>,
+        width: u32,
+        scale: f32,
+        utc_offset: i32,
+        font: Option<Arc<TextRenderer>>,
+        theme: Theme,
+        variant: Variant,
+        clicks: Sender<Click>,
+        clicked: Receiver<Click>,
+    }
+
+    impl Panel {
+        pub fn new(compositor: &mut Compositor, catalog: AppCatalog) -> Self {
+            let (clicks, clicked) = mpsc::channel();
+            Panel {
+                window: compositor.create_window(0, 0),
+                launcher: Launcher::new(compositor, catalog),
+                tree: None,
+                buffer: None,
+                tasks: Vec::new(),
+                workspaces: (Vec::new(), 0),
+                switch: None,
+                width: 0,
+                scale: 1.0,
+                utc_offset: 0,
+                font: None,
+                theme: Theme::default(),
+                variant: Variant::Dark,
+                clicks,
+                clicked,
+            }
+        }
+
+        pub fn window(&self) -> WindowId {
+            self.window
+        }
+
+        pub fn tree(&self) -> Option<&WidgetTree> {
+            self.tree.as_ref()
+        }
+
+        pub fn tree_mut(&mut self) -> Option<&mut WidgetTree> {
+            self.tree.as_mut()
+        }
+
+        pub fn launcher(&self) -> &Launcher {
+            &self.launcher
+        }
+
+        pub fn launcher_mut(&mut self) -> &mut Launcher {
+            &mut self.launcher
+        }
+
+        pub fn set_font(&mut self, font: Option<Arc<TextRenderer>>) {
+            self.launcher.set_font(font.clone());
+            self.font = font;
+            self.tree = None;
+        }
+
+        pub fn set_theme(&mut self, theme: Theme, variant: Variant) {
+            self.launcher.set_theme(theme.clone(), variant);
+            (self.theme, self.variant) = (theme, variant);
+            self.tree = None;
+        }
+
+        pub fn set_scale(&mut self, scale: f32) {
+            self.launcher.set_scale(scale);
+            self.scale = scale;
+            self.tree = None;
+        }
+
+        // The workspace last chosen from the panel since the last call, to
+        // switch to with a WorkspaceSwitcher
+        pub fn take_workspace_switch(&mut self) -> Option<usize> {
+            self.switch.take()
+        }
+
+        // Seconds east of UTC for the clock
+        pub fn set_utc_offset(&mut self, utc_offset: i32) {
+            self.utc_offset = utc_offset;
+        }
+
+        // Window-local coordinates
+        pub fn pointer_motion(&mut self, x: i32, y: i32) {
+            if let Some(tree) = &mut self.tree {
+                tree.pointer_motion(x, y);
+            }
+        }
+
+        pub fn pointer_button(&mut self, x: i32, y: i32, pressed: bool) {
+            if let Some(tree) = &mut self.tree {
+                tree.pointer_button(x, y, pressed);
+            }
+        }
+
+        fn tasks(wm: &WindowManager) -> Vec<Task> {
+            wm.surfaces_on(wm.current_workspace())
+                .into_iter()
+                .map(|surface| Task {
+                    surface,
+                    title: wm.title(surface).unwrap_or("").to_string(),
+                    focused: wm.focused() == Some(surface),
+                    minimized: wm.state(surface) == Some(WindowState::Minimized),
+                })
+                .collect()
+        }
+
+        fn rebuild(&mut self) {
+            let launcher = self.clicks.clone();
+            let mut bar = Element::container(Direction::Row)
+                .align(Align::Center)
+                .padding(4)
+                .child(Element::button("Apps").name("apps").on_event(move |_, _, _| {
+                    let _ = launcher.send(Click::Launcher);
+                }));
+            let accent = self.theme.colors(self.variant).accent;
+            for task in &self.tasks {
+                let (clicks, surface) = (self.clicks.clone(), task.surface);
+                let label = if task.minimized { format!("[{}]", task.title) } else { task.title.clone() };
+                let mut button = Element::button(&label).on_event(move |_, _, _| {
+                    let _ = clicks.send(Click::Task(surface));
+                });
+                if task.focused {
+                    button = button.style(StyleOverride {
+                        background: Some(accent),
+                        ..StyleOverride::default()
+                    });
+                }
+                bar = bar.child(button);
+            }
+            let mut pager = Element::container(Direction::Row).name("workspaces");
+            let (names, current) = &self.workspaces;
+            for (index, name) in names.iter().enumerate() {
+                let clicks = self.clicks.clone();
+                let mut button = Element::button(name).on_event(move |_, _, _| {
+                    let _ = clicks.send(Click::Workspace(index));
+                });
+                if index == *current {
+                    button = button.style(StyleOverride {
+                        background: Some(accent),
+                        ..StyleOverride::default()
+                    });
+                }
+                pager = pager.child(button);
+            }
+            bar = bar
+                .child(Element::container(Direction::Row).grow(1))
+                .child(pager)
+                .child(Element::label("").name("network"))
+                .child(Element::label("").name("power"))
+                .child(Element::label("").name("clock"));
+            let mut tree = WidgetTree::new(bar, self.width, PANEL_HEIGHT);
+            tree.set_font(self.font.clone());
+            tree.set_theme(self.theme.clone(), self.variant);
+            tree.set_scale(self.scale);
+            self.tree = Some(tree);
+            self.buffer = None;
+        }
+
+        // Clicking a window's button focuses it, or minimizes it when it
+        // already has focus
+        fn activate(wm: &mut WindowManager, surface: SurfaceId) -> Result<(), &'static str> {
+            match wm.state(surface) {
+                Some(WindowState::Minimized) => {
+                    wm.restore(surface)?;
+                    wm.focus(surface)
+                }
+                Some(_) if wm.focused() == Some(surface) => wm.minimize(surface),
+                Some(_) => wm.focus(surface),
+                None => Err("No such surface"),
+            }
+        }
+
+        // Acts on clicks, follows the window list and status, and redraws.
+        // `time` is wall-clock time in microseconds since the Unix epoch.
+        pub fn update(&mut self, wm: &mut WindowManager, status: &SystemStatus, time: u64) -> Result<(), &'static str> {
+            while let Ok(click) = self.clicked.try_recv() {
+                match click {
+                    Click::Launcher => self.launcher.toggle(),
+                    // The window may have gone in the meantime
+                    Click::Task(surface) => {
+                        let _ = Self::activate(wm, surface);
+                    }
+                    Click::Workspace(index) => self.switch = Some(index),
+                }
+            }
+            let (screen_width, screen_height) = wm.compositor().resolution();
+            let tasks = Self::tasks(wm);
+            let workspaces = (wm.workspaces().into_iter().map(String::from).collect(), wm.current_workspace());
+            if tasks != self.tasks || workspaces != self.workspaces || screen_width != self.width {
+                (self.tasks, self.workspaces, self.width) = (tasks, workspaces, screen_width);
+                self.tree = None;
+            }
+            if self.tree.is_none() {
+                self.rebuild();
+            }
+            let tree = self.tree.as_mut().unwrap();
+            for (name, text) in [
+                ("network", status.network_text()),
+                ("power", status.power_text()),
+                ("clock", clock_text(time, self.utc_offset)),
+            ] {
+                let label = tree.find(name).unwrap();
+                if tree.text(label) != Some(text.as_str()) {
+                    tree.set_text(label, &text)?;
+                }
+            }
+            let compositor = wm.compositor_mut();
+            let (buffer_width, buffer_height) = tree.buffer_size();
+            let attach = self.buffer.is_none();
+            let buffer = self
+                .buffer
+                .get_or_insert_with(|| Buffer::new(buffer_width, buffer_height, BufferStorage::SharedMemory))
+                .clone();
+            let damage = tree.render(&buffer)?;
+            if attach {
+                compositor.attach(self.window, buffer)?;
+                compositor.set_destination(self.window, (self.scale != 1.0).then_some((self.width, PANEL_HEIGHT)))?;
+                compositor.move_window(self.window, 0, screen_height as i32 - PANEL_HEIGHT as i32)?;
+                compositor.set_visible(self.window, true)?;
+                compositor.raise(self.window)?;
+            }
+            let dirty = !damage.is_empty();
+            for area in damage {
+                compositor.damage(self.window, area)?;
+            }
+            if attach || dirty {
+                compositor.commit(self.window)?;
+            }
+            self.launcher.update(compositor)
+        }
+    }
+}
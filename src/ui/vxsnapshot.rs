@@ -0,0 +1,189 @@
+// src/ui/vxsnapshot.rs
+
+pub mod vxsnapshot {
+    use crate::vxui_toolkit::vxui_toolkit::WidgetTree;
+    use vaelix_core::vxfs::vxfs::VXFS;
+    use vaelix_graphics::vegagx::vegagx::Image;
+    use vaelix_graphics::vxwin::vxwin::{Buffer, BufferStorage, Rect};
+    use std::io::ErrorKind;
+
+    // Set to rewrite the golden images with what is rendered now
+    pub const UPDATE_VARIABLE: &str = "VAELIX_UPDATE_SNAPSHOTS";
+
+    const DIFFERENT: u32 = 0xFFFF_0000;
+    // pixelmatch's largest possible YIQ delta
+    const MAX_DELTA: f32 = 35215.0;
+
+    // Renders the whole tree into memory at its scale, with no window or
+    // display behind it
+    pub fn render_offscreen(tree: &mut WidgetTree) -> Result<Image, &'static str> {
+        let (width, height) = tree.size();
+        tree.resize(width, height);
+        let (buffer_width, buffer_height) = tree.buffer_size();
+        let buffer = Buffer::new(buffer_width, buffer_height, BufferStorage::SharedMemory);
+        tree.render(&buffer)?;
+        Ok(buffer.to_image())
+    }
+
+    fn yiq(pixel: u32) -> (f32, f32, f32) {
+        let (r, g, b) = (((pixel >> 16) & 0xFF) as f32, ((pixel >> 8) & 0xFF) as f32, (pixel & 0xFF) as f32);
+        (
+            r * 0.298_895_3 + g * 0.586_622_5 + b * 0.114_482_23,
+            r * 0.595_977_99 - g * 0.274_176_1 - b * 0.321_801_9,
+            r * 0.211_470_17 - g * 0.522_617_1 + b * 0.311_146_94,
+        )
+    }
+
+    // How different two pixels look, 0 to 1, weighting brightness over
+    // hue as the eye does. Pixels are taken as over black, as in PPM.
+    pub fn perceptual_delta(a: u32, b: u32) -> f32 {
+        if a & 0xFF_FFFF == b & 0xFF_FFFF {
+            return 0.0;
+        }
+        let ((y1, i1, q1), (y2, i2, q2)) = (yiq(a), yiq(b));
+        let delta = 0.5053 * (y1 - y2).powi(2) + 0.299 * (i1 - i2).powi(2) + 0.1957 * (q1 - q2).powi(2);
+        (delta / MAX_DELTA).sqrt().min(1.0)
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct DiffOptions {
+        // Smallest perceptual delta that counts a pixel as changed
+        pub threshold: f32,
+        // Changed pixels allowed before images differ, for noise such as
+        // anti-aliasing
+        pub max_differing: usize,
+    }
+
+    impl Default for DiffOptions {
+        fn default() -> Self {
+            DiffOptions { threshold: 0.1, max_differing: 0 }
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Diff {
+        pub differing: usize,
+        pub max_delta: f32,
+        // Around every changed pixel
+        pub bounds: Option<Rect>,
+        // The expected image faded, with changed pixels in red
+        pub image: Image,
+    }
+
+    impl Diff {
+        pub fn matches(&self, options: &DiffOptions) -> bool {
+            self.differing <= options.max_differing
+        }
+    }
+
+    pub fn diff(expected: &Image, actual: &Image, options: &DiffOptions) -> Result<Diff, &'static str> {
+        if (expected.width(), expected.height()) != (actual.width(), actual.height()) {
+            return Err("Images differ in size");
+        }
+        let width = expected.width();
+        let (mut differing, mut max_delta, mut bounds) = (0, 0.0f32, None::<Rect>);
+        let mut pixels = Vec::with_capacity(expected.pixels().len());
+        for (index, (&before, &after)) in expected.pixels().iter().zip(actual.pixels()).enumerate() {
+            let delta = perceptual_delta(before, after);
+            max_delta = max_delta.max(delta);
+            if delta >= options.threshold && delta > 0.0 {
+                differing += 1;
+                let pixel = Rect::new((index as u32 % width) as i32, (index as u32 / width) as i32, 1, 1);
+                bounds = Some(bounds.map_or(pixel, |bounds| bounds.union(&pixel)));
+                pixels.push(DIFFERENT);
+            } else {
+                let faded = 255 - ((255.0 - yiq(before).0.clamp(0.0, 255.0)) * 0.1) as u32;
+                pixels.push(0xFF00_0000 | faded << 16 | faded << 8 | faded);
+            }
+        }
+        let image = Image::new(width, expected.height(), pixels)?;
+        Ok(Diff { differing, max_delta, bounds, image })
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum SnapshotOutcome {
+        Matched,
+        // There was no golden image yet
+        Created,
+        Updated,
+    }
+
+    // Golden images for widget trees, as NAME.ppm in a directory. A
+    // mismatch leaves NAME.actual.ppm and NAME.diff.ppm beside the golden
+    // image to look at.
+    pub struct Snapshots {
+        dir: String,
+        options: DiffOptions,
+        update: bool,
+    }
+
+    impl Snapshots {
+        // Updates the golden images when UPDATE_VARIABLE is set
+        pub fn new(dir: &str) -> Self {
+            Snapshots {
+                dir: dir.trim_end_matches('/').to_string(),
+                options: DiffOptions::default(),
+                update: std::env::var_os(UPDATE_VARIABLE).is_some(),
+            }
+        }
+
+        pub fn set_options(&mut self, options: DiffOptions) {
+            self.options = options;
+        }
+
+        pub fn set_update(&mut self, update: bool) {
+            self.update = update;
+        }
+
+        pub fn path(&self, name: &str) -> String {
+            format!("{}/{}.ppm", self.dir, name)
+        }
+
+        fn write(path: &str, image: &Image) -> Result<(), &'static str> {
+            VXFS::new().write_bytes(path, &image.encode_ppm()).map_err(|_| "Failed to write snapshot")
+        }
+
+        pub fn check(&self, name: &str, tree: &mut WidgetTree) -> Result<SnapshotOutcome, &'static str> {
+            self.check_image(name, &render_offscreen(tree)?)
+        }
+
+        pub fn check_image(&self, name: &str, actual: &Image) -> Result<SnapshotOutcome, &'static str> {
+            let path = self.path(name);
+            // Compared as stored, without alpha
+            let actual = Image::decode_ppm(&actual.encode_ppm())?;
+            let expected = match VXFS::new().read_bytes(&path) {
+                Ok(data) => Image::decode_ppm(&data)?,
+                Err(error) if error.kind() == ErrorKind::NotFound => {
+                    log::info!("Writing new snapshot {}", path);
+                    Self::write(&path, &actual)?;
+                    return Ok(SnapshotOutcome::Created);
+                }
+                Err(_) => return Err("Failed to read snapshot"),
+            };
+            let mismatch = match diff(&expected, &actual, &self.options) {
+                Ok(diff) if diff.matches(&self.options) => return Ok(SnapshotOutcome::Matched),
+                Ok(diff) => {
+                    log::info!(
+                        "Snapshot {} differs in {} pixels within {:?}, by up to {:.3}",
+                        name, diff.differing, diff.bounds, diff.max_delta
+                    );
+                    Some(diff)
+                }
+                Err(error) => {
+                    log::warn!("Snapshot {}: {}", name, error);
+                    None
+                }
+            };
+            if self.update {
+                log::info!("Updating snapshot {}", path);
+                Self::write(&path, &actual)?;
+                return Ok(SnapshotOutcome::Updated);
+            }
+            Self::write(&format!("{}/{}.actual.ppm", self.dir, name), &actual)?;
+            if let Some(diff) = mismatch {
+                Self::write(&format!("{}/{}.diff.ppm", self.dir, name), &diff.image)?;
+            }
+            Err("Snapshot does not match its golden image")
+        }
+    }
+}
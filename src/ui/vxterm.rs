@@ -0,0 +1,1133 @@
+// src/ui/vxterm.rs
+
+pub mod vxterm {
+    use vaelix_core::input::input::{
+        function_key, KEY_BACKSPACE, KEY_DELETE, KEY_DOWN, KEY_END, KEY_ENTER, KEY_ESC, KEY_HOME, KEY_INSERT, KEY_LEFT,
+        KEY_LEFTALT, KEY_LEFTCTRL, KEY_LEFTSHIFT, KEY_PAGEDOWN, KEY_PAGEUP, KEY_RIGHT, KEY_RIGHTALT, KEY_RIGHTCTRL,
+        KEY_RIGHTSHIFT, KEY_TAB, KEY_UP,
+    };
+    use vaelix_core::pty::pty::{PtyMaster, PtySize};
+    use vaelix_graphics::vegagx::vegagx::{Canvas, Color, Paint};
+    use vaelix_graphics::vxfont::vxfont::TextRenderer;
+    use vaelix_graphics::vxtheme::vxtheme::{Theme, Variant};
+    use vaelix_graphics::vxwin::vxwin::{Buffer, Rect};
+    use std::collections::VecDeque;
+    use std::sync::Arc;
+
+    pub const SCROLLBACK_LINES: usize = 1000;
+    const TAB_WIDTH: usize = 8;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum CellColor {
+        Default,
+        Indexed(u8),
+        Rgb(u32),
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Attributes {
+        pub foreground: CellColor,
+        pub background: CellColor,
+        pub bold: bool,
+        pub underline: bool,
+        pub inverse: bool,
+    }
+
+    impl Default for Attributes {
+        fn default() -> Self {
+            Attributes {
+                foreground: CellColor::Default,
+                background: CellColor::Default,
+                bold: false,
+                underline: false,
+                inverse: false,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Cell {
+        pub character: char,
+        pub attributes: Attributes,
+    }
+
+    impl Default for Cell {
+        fn default() -> Self {
+            Cell {
+                character: ' ',
+                attributes: Attributes::default(),
+            }
+        }
+    }
+
+    // xterm's defaults for the first 16 colours
+    const ANSI: [u32; 16] = [
+        0xFF00_0000, 0xFFCD_0000, 0xFF00_CD00, 0xFFCD_CD00, 0xFF00_00EE, 0xFFCD_00CD, 0xFF00_CDCD, 0xFFE5_E5E5,
+        0xFF7F_7F7F, 0xFFFF_0000, 0xFF00_FF00, 0xFFFF_FF00, 0xFF5C_5CFF, 0xFFFF_00FF, 0xFF00_FFFF, 0xFFFF_FFFF,
+    ];
+
+    // The 256-colour palette: ANSI, a 6x6x6 cube, then 24 greys
+    pub fn palette(index: u8) -> u32 {
+        match index {
+            0..=15 => ANSI[index as usize],
+            16..=231 => {
+                let level = |value: u8| if value == 0 { 0 } else { 55 + value as u32 * 40 };
+                let index = index - 16;
+                0xFF00_0000 | level(index / 36) << 16 | level(index / 6 % 6) << 8 | level(index % 6)
+            }
+            232..=255 => {
+                let grey = 8 + (index - 232) as u32 * 10;
+                0xFF00_0000 | grey << 16 | grey << 8 | grey
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    enum State {
+        Ground,
+        Escape,
+        // Designating a character set; the next byte is dropped
+        Charset,
+        Csi { private: Option<u8>, params: Vec<u32>, current: Option<u32> },
+        Osc(Vec<u8>),
+        OscEscape(Vec<u8>),
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    struct SavedCursor {
+        row: usize,
+        col: usize,
+        attributes: Attributes,
+    }
+
+    // The screen model and VT100/xterm parser, without any I/O. Lines are
+    // numbered from the first line ever written so selections survive the
+    // scrollback being trimmed.
+    pub struct Terminal {
+        cols: usize,
+        rows: usize,
+        lines: Vec<Vec<Cell>>,
+        // The main screen while the alternate one is up
+        main_screen: Option<(Vec<Vec<Cell>>, SavedCursor)>,
+        scrollback: VecDeque<Vec<Cell>>,
+        // Lines dropped off the front of the scrollback
+        trimmed: u64,
+        row: usize,
+        col: usize,
+        // The cursor sits past the last column until the next character
+        pending_wrap: bool,
+        attributes: Attributes,
+        saved: Option<SavedCursor>,
+        scroll_top: usize,
+        scroll_bottom: usize,
+        autowrap: bool,
+        cursor_visible: bool,
+        app_cursor: bool,
+        bracketed_paste: bool,
+        title: String,
+        state: State,
+        utf8: Vec<u8>,
+        responses: Vec<u8>,
+        dirty: Vec<bool>,
+    }
+
+    impl Terminal {
+        pub fn new(cols: usize, rows: usize) -> Self {
+            let (cols, rows) = (cols.max(1), rows.max(1));
+            Terminal {
+                cols,
+                rows,
+                lines: vec![vec![Cell::default(); cols]; rows],
+                main_screen: None,
+                scrollback: VecDeque::new(),
+                trimmed: 0,
+                row: 0,
+                col: 0,
+                pending_wrap: false,
+                attributes: Attributes::default(),
+                saved: None,
+                scroll_top: 0,
+                scroll_bottom: rows - 1,
+                autowrap: true,
+                cursor_visible: true,
+                app_cursor: false,
+                bracketed_paste: false,
+                title: String::new(),
+                state: State::Ground,
+                utf8: Vec::new(),
+                responses: Vec::new(),
+                dirty: vec![true; rows],
+            }
+        }
+
+        pub fn size(&self) -> (usize, usize) {
+            (self.cols, self.rows)
+        }
+
+        // Row and column, from 0
+        pub fn cursor(&self) -> (usize, usize) {
+            (self.row, self.col)
+        }
+
+        pub fn cursor_visible(&self) -> bool {
+            self.cursor_visible
+        }
+
+        pub fn app_cursor(&self) -> bool {
+            self.app_cursor
+        }
+
+        pub fn bracketed_paste(&self) -> bool {
+            self.bracketed_paste
+        }
+
+        pub fn is_alternate_screen(&self) -> bool {
+            self.main_screen.is_some()
+        }
+
+        pub fn title(&self) -> &str {
+            &self.title
+        }
+
+        pub fn cell(&self, row: usize, col: usize) -> Option<Cell> {
+            self.lines.get(row)?.get(col).copied()
+        }
+
+        // A screen row as text, without trailing blanks
+        pub fn row_text(&self, row: usize) -> String {
+            self.lines.get(row).map_or(String::new(), |line| Self::text(line))
+        }
+
+        fn text(line: &[Cell]) -> String {
+            let text: String = line.iter().map(|cell| cell.character).collect();
+            text.trim_end().to_string()
+        }
+
+        pub fn scrollback_len(&self) -> usize {
+            self.scrollback.len()
+        }
+
+        // Number of the first line still kept; the screen starts at
+        // first_line() + scrollback_len()
+        pub fn first_line(&self) -> u64 {
+            self.trimmed
+        }
+
+        pub fn line(&self, number: u64) -> Option<&[Cell]> {
+            let index = number.checked_sub(self.trimmed)? as usize;
+            match index.checked_sub(self.scrollback.len()) {
+                None => Some(&self.scrollback[index]),
+                Some(row) => self.lines.get(row).map(|line| line.as_slice()),
+            }
+        }
+
+        // Replies to status queries, to be written back to the PTY
+        pub fn take_responses(&mut self) -> Vec<u8> {
+            std::mem::take(&mut self.responses)
+        }
+
+        // Screen rows changed since the last call
+        pub fn take_dirty(&mut self) -> Vec<usize> {
+            let rows = self.dirty.iter().enumerate().filter(|(_, dirty)| **dirty).map(|(row, _)| row).collect();
+            self.dirty.fill(false);
+            rows
+        }
+
+        fn touch(&mut self, row: usize) {
+            if let Some(dirty) = self.dirty.get_mut(row) {
+                *dirty = true;
+            }
+        }
+
+        fn touch_all(&mut self) {
+            self.dirty.fill(true);
+        }
+
+        // Keeps the bottom of the screen, which is where the cursor
+        // usually is
+        pub fn resize(&mut self, cols: usize, rows: usize) {
+            let (cols, rows) = (cols.max(1), rows.max(1));
+            for line in self.lines.iter_mut().chain(self.scrollback.iter_mut()) {
+                line.resize(cols, Cell::default());
+            }
+            while self.lines.len() > rows {
+                let line = self.lines.remove(0);
+                if self.row > 0 {
+                    self.row -= 1;
+                }
+                self.push_scrollback(line);
+            }
+            while self.lines.len() < rows {
+                self.lines.push(vec![Cell::default(); cols]);
+            }
+            (self.cols, self.rows) = (cols, rows);
+            (self.row, self.col) = (self.row.min(rows - 1), self.col.min(cols - 1));
+            (self.scroll_top, self.scroll_bottom) = (0, rows - 1);
+            self.pending_wrap = false;
+            self.dirty = vec![true; rows];
+        }
+
+        fn push_scrollback(&mut self, line: Vec<Cell>) {
+            if self.main_screen.is_some() {
+                return;
+            }
+            self.scrollback.push_back(line);
+            if self.scrollback.len() > SCROLLBACK_LINES {
+                self.scrollback.pop_front();
+                self.trimmed += 1;
+            }
+        }
+
+        fn blank(&self) -> Cell {
+            // Erasing fills with the current background, as xterm does
+            Cell {
+                character: ' ',
+                attributes: Attributes {
+                    background: self.attributes.background,
+                    ..Attributes::default()
+                },
+            }
+        }
+
+        pub fn feed(&mut self, bytes: &[u8]) {
+            for &byte in bytes {
+                self.byte(byte);
+            }
+        }
+
+        fn byte(&mut self, byte: u8) {
+            match std::mem::replace(&mut self.state, State::Ground) {
+                State::Ground => self.ground(byte),
+                State::Escape => self.escape(byte),
+                State::Charset => {}
+                State::Csi { private, mut params, current } => match byte {
+                    b'0'..=b'9' => {
+                        let digit = (byte - b'0') as u32;
+                        let current = Some(current.unwrap_or(0).saturating_mul(10).saturating_add(digit));
+                        self.state = State::Csi { private, params, current };
+                    }
+                    b';' | b':' => {
+                        params.push(current.unwrap_or(0));
+                        self.state = State::Csi { private, params, current: None };
+                    }
+                    b'<'..=b'?' if params.is_empty() && current.is_none() => {
+                        self.state = State::Csi { private: Some(byte), params, current };
+                    }
+                    0x40..=0x7E => {
+                        if let Some(current) = current {
+                            params.push(current);
+                        }
+                        self.csi(private, &params, byte);
+                    }
+                    0x1B => self.state = State::Escape,
+                    // Intermediates and anything odd are skipped
+                    _ => self.state = State::Csi { private, params, current },
+                },
+                State::Osc(mut data) => match byte {
+                    0x07 => self.osc(&data),
+                    0x1B => self.state = State::OscEscape(data),
+                    _ => {
+                        data.push(byte);
+                        self.state = State::Osc(data);
+                    }
+                },
+                State::OscEscape(data) => {
+                    self.osc(&data);
+                    if byte != b'\\' {
+                        self.byte(byte);
+                    }
+                }
+            }
+        }
+
+        fn ground(&mut self, byte: u8) {
+            match byte {
+                0x07 => {}
+                0x08 => {
+                    self.col = self.col.saturating_sub(1);
+                    self.pending_wrap = false;
+                }
+                b'\t' => {
+                    self.col = ((self.col / TAB_WIDTH + 1) * TAB_WIDTH).min(self.cols - 1);
+                    self.pending_wrap = false;
+                }
+                b'\n' | 0x0B | 0x0C => self.linefeed(),
+                b'\r' => {
+                    self.col = 0;
+                    self.pending_wrap = false;
+                }
+                0x1B => {
+                    self.utf8.clear();
+                    self.state = State::Escape;
+                }
+                0x00..=0x1F | 0x7F => {}
+                0x20..=0x7E => self.print(byte as char),
+                _ => self.utf8_byte(byte),
+            }
+        }
+
+        fn utf8_byte(&mut self, byte: u8) {
+            if byte & 0xC0 != 0x80 {
+                if !self.utf8.is_empty() {
+                    self.print(char::REPLACEMENT_CHARACTER);
+                }
+                self.utf8.clear();
+            }
+            self.utf8.push(byte);
+            let expected = match self.utf8[0] {
+                0xC0..=0xDF => 2,
+                0xE0..=0xEF => 3,
+                0xF0..=0xF7 => 4,
+                _ => 1,
+            };
+            if self.utf8.len() < expected {
+                return;
+            }
+            let character = std::str::from_utf8(&self.utf8)
+                .ok()
+                .and_then(|text| text.chars().next())
+                .unwrap_or(char::REPLACEMENT_CHARACTER);
+            self.utf8.clear();
+            self.print(character);
+        }
+
+        fn print(&mut self, character: char) {
+            if self.pending_wrap && self.autowrap {
+                self.col = 0;
+                self.linefeed();
+            }
+            self.pending_wrap = false;
+            self.lines[self.row][self.col] = Cell {
+                character,
+                attributes: self.attributes,
+            };
+            self.touch(self.row);
+            if self.col + 1 < self.cols {
+                self.col += 1;
+            } else {
+                self.pending_wrap = true;
+            }
+        }
+
+        fn linefeed(&mut self) {
+            self.pending_wrap = false;
+            if self.row == self.scroll_bottom {
+                self.scroll_up(1);
+            } else if self.row + 1 < self.rows {
+                self.row += 1;
+            }
+        }
+
+        fn reverse_index(&mut self) {
+            self.pending_wrap = false;
+            if self.row == self.scroll_top {
+                self.scroll_down(1);
+            } else {
+                self.row = self.row.saturating_sub(1);
+            }
+        }
+
+        // Within the scroll region; lines leaving the top of a full-screen
+        // region go to the scrollback
+        fn scroll_up(&mut self, count: usize) {
+            for _ in 0..count.min(self.scroll_bottom - self.scroll_top + 1) {
+                let line = self.lines.remove(self.scroll_top);
+                if self.scroll_top == 0 {
+                    self.push_scrollback(line);
+                }
+                self.lines.insert(self.scroll_bottom, vec![self.blank(); self.cols]);
+            }
+            for row in self.scroll_top..=self.scroll_bottom {
+                self.touch(row);
+            }
+        }
+
+        fn scroll_down(&mut self, count: usize) {
+            for _ in 0..count.min(self.scroll_bottom - self.scroll_top + 1) {
+                self.lines.remove(self.scroll_bottom);
+                self.lines.insert(self.scroll_top, vec![self.blank(); self.cols]);
+            }
+            for row in self.scroll_top..=self.scroll_bottom {
+                self.touch(row);
+            }
+        }
+
+        fn save_cursor(&self) -> SavedCursor {
+            SavedCursor {
+                row: self.row,
+                col: self.col,
+                attributes: self.attributes,
+            }
+        }
+
+        fn restore_cursor(&mut self, saved: SavedCursor) {
+            (self.row, self.col) = (saved.row.min(self.rows - 1), saved.col.min(self.cols - 1));
+            self.attributes = saved.attributes;
+            self.pending_wrap = false;
+        }
+
+        fn escape(&mut self, byte: u8) {
+            match byte {
+                b'[' => {
+                    self.state = State::Csi {
+                        private: None,
+                        params: Vec::new(),
+                        current: None,
+                    }
+                }
+                b']' => self.state = State::Osc(Vec::new()),
+                b'(' | b')' | b'*' | b'+' => self.state = State::Charset,
+                b'7' => self.saved = Some(self.save_cursor()),
+                b'8' => {
+                    let saved = self.saved.unwrap_or(SavedCursor {
+                        row: 0,
+                        col: 0,
+                        attributes: Attributes::default(),
+                    });
+                    self.restore_cursor(saved);
+                }
+                b'D' => self.linefeed(),
+                b'E' => {
+                    self.col = 0;
+                    self.linefeed();
+                }
+                b'M' => self.reverse_index(),
+                b'c' => self.reset(),
+                _ => {}
+            }
+        }
+
+        fn reset(&mut self) {
+            let (cols, rows) = (self.cols, self.rows);
+            let (scrollback, trimmed) = (std::mem::take(&mut self.scrollback), self.trimmed);
+            *self = Terminal::new(cols, rows);
+            (self.scrollback, self.trimmed) = (scrollback, trimmed);
+        }
+
+        fn osc(&mut self, data: &[u8]) {
+            let text = String::from_utf8_lossy(data);
+            if let Some((kind, title)) = text.split_once(';') {
+                if kind == "0" || kind == "2" {
+                    self.title = title.to_string();
+                }
+            }
+        }
+
+        fn erase(&mut self, row: usize, columns: std::ops::Range<usize>) {
+            let blank = self.blank();
+            let end = columns.end.min(self.cols);
+            self.lines[row][columns.start.min(end)..end].fill(blank);
+            self.touch(row);
+        }
+
+        fn set_mode(&mut self, private: Option<u8>, params: &[u32], enabled: bool) {
+            if private != Some(b'?') {
+                return;
+            }
+            for mode in params {
+                match mode {
+                    1 => self.app_cursor = enabled,
+                    7 => self.autowrap = enabled,
+                    25 => {
+                        self.cursor_visible = enabled;
+                        self.touch(self.row);
+                    }
+                    47 | 1047 | 1049 => self.alternate_screen(enabled),
+                    2004 => self.bracketed_paste = enabled,
+                    _ => {}
+                }
+            }
+        }
+
+        fn alternate_screen(&mut self, enabled: bool) {
+            if enabled == self.main_screen.is_some() {
+                return;
+            }
+            if enabled {
+                let main = std::mem::replace(&mut self.lines, vec![vec![Cell::default(); self.cols]; self.rows]);
+                self.main_screen = Some((main, self.save_cursor()));
+            } else if let Some((main, saved)) = self.main_screen.take() {
+                self.lines = main;
+                self.restore_cursor(saved);
+            }
+            self.touch_all();
+        }
+
+        fn sgr(&mut self, params: &[u32]) {
+            let params = if params.is_empty() { &[0][..] } else { params };
+            let mut index = 0;
+            while index < params.len() {
+                let extended = |index: &mut usize| -> Option<CellColor> {
+                    match params.get(*index + 1) {
+                        Some(5) => {
+                            *index += 2;
+                            params.get(*index).map(|value| CellColor::Indexed(*value as u8))
+                        }
+                        Some(2) => {
+                            *index += 4;
+                            let channel = |offset: usize| params.get(*index - offset).copied().unwrap_or(0).min(255);
+                            Some(CellColor::Rgb(0xFF00_0000 | channel(2) << 16 | channel(1) << 8 | channel(0)))
+                        }
+                        _ => None,
+                    }
+                };
+                let attributes = &mut self.attributes;
+                match params[index] {
+                    0 => *attributes = Attributes::default(),
+                    1 => attributes.bold = true,
+                    4 => attributes.underline = true,
+                    7 => attributes.inverse = true,
+                    22 => attributes.bold = false,
+                    24 => attributes.underline = false,
+                    27 => attributes.inverse = false,
+                    value @ 30..=37 => attributes.foreground = CellColor::Indexed((value - 30) as u8),
+                    38 => {
+                        if let Some(color) = extended(&mut index) {
+                            self.attributes.foreground = color;
+                        }
+                    }
+                    39 => attributes.foreground = CellColor::Default,
+                    value @ 40..=47 => attributes.background = CellColor::Indexed((value - 40) as u8),
+                    48 => {
+                        if let Some(color) = extended(&mut index) {
+                            self.attributes.background = color;
+                        }
+                    }
+                    49 => attributes.background = CellColor::Default,
+                    value @ 90..=97 => attributes.foreground = CellColor::Indexed((value - 90 + 8) as u8),
+                    value @ 100..=107 => attributes.background = CellColor::Indexed((value - 100 + 8) as u8),
+                    _ => {}
+                }
+                index += 1;
+            }
+        }
+
+        fn csi(&mut self, private: Option<u8>, params: &[u32], command: u8) {
+            // Missing or zero counts mean 1
+            let count = |index: usize| params.get(index).copied().filter(|value| *value > 0).unwrap_or(1) as usize;
+            let previous = self.row;
+            if !matches!(command, b'm' | b'n' | b'c') {
+                self.pending_wrap = false;
+            }
+            match (private, command) {
+                (None, b'A') => {
+                    let top = if self.row >= self.scroll_top { self.scroll_top } else { 0 };
+                    self.row = self.row.saturating_sub(count(0)).max(top);
+                }
+                (None, b'B') => {
+                    let bottom = if self.row <= self.scroll_bottom { self.scroll_bottom } else { self.rows - 1 };
+                    self.row = (self.row + count(0)).min(bottom);
+                }
+                (None, b'C') => self.col = (self.col + count(0)).min(self.cols - 1),
+                (None, b'D') => self.col = self.col.saturating_sub(count(0)),
+                (None, b'E') => (self.row, self.col) = ((self.row + count(0)).min(self.rows - 1), 0),
+                (None, b'F') => (self.row, self.col) = (self.row.saturating_sub(count(0)), 0),
+                (None, b'G') => self.col = (count(0) - 1).min(self.cols - 1),
+                (None, b'H' | b'f') => {
+                    self.row = (count(0) - 1).min(self.rows - 1);
+                    self.col = (count(1) - 1).min(self.cols - 1);
+                }
+                (None, b'd') => self.row = (count(0) - 1).min(self.rows - 1),
+                (None, b'J') => {
+                    let (row, col) = (self.row, self.col);
+                    match params.first().copied().unwrap_or(0) {
+                        0 => {
+                            self.erase(row, col..self.cols);
+                            for row in row + 1..self.rows {
+                                self.erase(row, 0..self.cols);
+                            }
+                        }
+                        1 => {
+                            for row in 0..row {
+                                self.erase(row, 0..self.cols);
+                            }
+                            self.erase(row, 0..col + 1);
+                        }
+                        2 => {
+                            for row in 0..self.rows {
+                                self.erase(row, 0..self.cols);
+                            }
+                        }
+                        3 => {
+                            self.trimmed += self.scrollback.len() as u64;
+                            self.scrollback.clear();
+                        }
+                        _ => {}
+                    }
+                }
+                (None, b'K') => {
+                    let (row, col) = (self.row, self.col);
+                    match params.first().copied().unwrap_or(0) {
+                        0 => self.erase(row, col..self.cols),
+                        1 => self.erase(row, 0..col + 1),
+                        2 => self.erase(row, 0..self.cols),
+                        _ => {}
+                    }
+                }
+                (None, b'L' | b'M') if (self.scroll_top..=self.scroll_bottom).contains(&self.row) => {
+                    let top = std::mem::replace(&mut self.scroll_top, self.row);
+                    if command == b'L' {
+                        self.scroll_down(count(0));
+                    } else {
+                        self.scroll_up_without_history(count(0));
+                    }
+                    self.scroll_top = top;
+                    self.col = 0;
+                }
+                (None, b'@') => {
+                    let (row, col, blank) = (self.row, self.col, self.blank());
+                    for _ in 0..count(0).min(self.cols - col) {
+                        self.lines[row].insert(col, blank);
+                        self.lines[row].pop();
+                    }
+                    self.touch(row);
+                }
+                (None, b'P') => {
+                    let (row, col, blank) = (self.row, self.col, self.blank());
+                    for _ in 0..count(0).min(self.cols - col) {
+                        self.lines[row].remove(col);
+                        self.lines[row].push(blank);
+                    }
+                    self.touch(row);
+                }
+                (None, b'X') => {
+                    let (row, col) = (self.row, self.col);
+                    self.erase(row, col..col + count(0));
+                }
+                (None, b'S') => self.scroll_up(count(0)),
+                (None, b'T') => self.scroll_down(count(0)),
+                (None, b'm') => self.sgr(params),
+                (None, b'r') => {
+                    let top = count(0) - 1;
+                    let bottom = params.get(1).copied().filter(|value| *value > 0).map_or(self.rows, |value| value as usize);
+                    if top < bottom.min(self.rows) - 1 {
+                        (self.scroll_top, self.scroll_bottom) = (top, bottom.min(self.rows) - 1);
+                        (self.row, self.col) = (0, 0);
+                    }
+                }
+                (None, b's') => self.saved = Some(self.save_cursor()),
+                (None, b'u') => {
+                    if let Some(saved) = self.saved {
+                        self.restore_cursor(saved);
+                    }
+                }
+                (None, b'n') => match params.first() {
+                    Some(5) => self.responses.extend_from_slice(b"\x1b[0n"),
+                    Some(6) => self.responses.extend(format!("\x1b[{};{}R", self.row + 1, self.col + 1).bytes()),
+                    _ => {}
+                },
+                (None, b'c') => self.responses.extend_from_slice(b"\x1b[?6c"),
+                (_, b'h') => self.set_mode(private, params, true),
+                (_, b'l') => self.set_mode(private, params, false),
+                _ => {}
+            }
+            // The cursor is drawn on its row
+            self.touch(previous);
+            self.touch(self.row);
+        }
+
+        // Deleting lines never feeds the scrollback
+        fn scroll_up_without_history(&mut self, count: usize) {
+            for _ in 0..count.min(self.scroll_bottom - self.scroll_top + 1) {
+                self.lines.remove(self.scroll_top);
+                self.lines.insert(self.scroll_bottom, vec![self.blank(); self.cols]);
+            }
+            for row in self.scroll_top..=self.scroll_bottom {
+                self.touch(row);
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct Modifiers {
+        pub shift: bool,
+        pub ctrl: bool,
+        pub alt: bool,
+    }
+
+    // What a key sends, as xterm encodes it; printable keys arrive
+    // through text input instead
+    pub fn encode_key(code: u32, modifiers: Modifiers, app_cursor: bool) -> Option<Vec<u8>> {
+        let parameter = 1 + modifiers.shift as u8 + 2 * modifiers.alt as u8 + 4 * modifiers.ctrl as u8;
+        let cursor = |final_byte: char| {
+            if parameter > 1 {
+                format!("\x1b[1;{}{}", parameter, final_byte)
+            } else if app_cursor {
+                format!("\x1bO{}", final_byte)
+            } else {
+                format!("\x1b[{}", final_byte)
+            }
+        };
+        let tilde = |number: u8| {
+            if parameter > 1 {
+                format!("\x1b[{};{}~", number, parameter)
+            } else {
+                format!("\x1b[{}~", number)
+            }
+        };
+        let sequence = match code {
+            KEY_ENTER => "\r".to_string(),
+            KEY_BACKSPACE => "\x7f".to_string(),
+            KEY_TAB if modifiers.shift => "\x1b[Z".to_string(),
+            KEY_TAB => "\t".to_string(),
+            KEY_ESC => "\x1b".to_string(),
+            KEY_UP => cursor('A'),
+            KEY_DOWN => cursor('B'),
+            KEY_RIGHT => cursor('C'),
+            KEY_LEFT => cursor('D'),
+            KEY_HOME => cursor('H'),
+            KEY_END => cursor('F'),
+            KEY_INSERT => tilde(2),
+            KEY_DELETE => tilde(3),
+            KEY_PAGEUP => tilde(5),
+            KEY_PAGEDOWN => tilde(6),
+            _ => match function_key(code)? {
+                number @ 1..=4 if parameter == 1 => format!("\x1bO{}", (b'P' + number - 1) as char),
+                number @ 1..=4 => format!("\x1b[1;{}{}", parameter, (b'P' + number - 1) as char),
+                number => tilde([15, 17, 18, 19, 20, 21, 23, 24][number as usize - 5]),
+            },
+        };
+        Some(sequence.into_bytes())
+    }
+
+    // Typed text: Ctrl makes control characters, Alt prefixes ESC
+    pub fn encode_text(character: char, modifiers: Modifiers) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        if modifiers.alt {
+            bytes.push(0x1B);
+        }
+        match character {
+            '@'..='_' | 'a'..='z' if modifiers.ctrl => bytes.push(character.to_ascii_uppercase() as u8 & 0x1F),
+            ' ' if modifiers.ctrl => bytes.push(0),
+            _ => bytes.extend(character.to_string().bytes()),
+        }
+        bytes
+    }
+
+    // A terminal window: the emulator on a PTY, drawn with the theme's
+    // colours and the toolkit's font, with scrollback and mouse selection
+    pub struct TerminalApp {
+        terminal: Terminal,
+        pty: PtyMaster,
+        font: Option<Arc<TextRenderer>>,
+        theme: Theme,
+        variant: Variant,
+        width: u32,
+        height: u32,
+        modifiers: Modifiers,
+        // Lines scrolled back from the live screen
+        view_offset: usize,
+        // Anchor and head as (line number, column)
+        selection: Option<((u64, usize), (u64, usize))>,
+        selecting: bool,
+        repaint: bool,
+        cursor: (usize, usize),
+    }
+
+    impl TerminalApp {
+        pub fn new(pty: PtyMaster, width: u32, height: u32) -> Self {
+            let mut app = TerminalApp {
+                terminal: Terminal::new(1, 1),
+                pty,
+                font: None,
+                theme: Theme::default(),
+                variant: Variant::Dark,
+                width,
+                height,
+                modifiers: Modifiers::default(),
+                view_offset: 0,
+                selection: None,
+                selecting: false,
+                repaint: true,
+                cursor: (0, 0),
+            };
+            app.resize(width, height);
+            app
+        }
+
+        pub fn terminal(&self) -> &Terminal {
+            &self.terminal
+        }
+
+        pub fn set_font(&mut self, font: Option<Arc<TextRenderer>>) {
+            self.font = font;
+            self.resize(self.width, self.height);
+        }
+
+        pub fn set_theme(&mut self, theme: Theme, variant: Variant) {
+            (self.theme, self.variant) = (theme, variant);
+            self.resize(self.width, self.height);
+        }
+
+        fn text_size(&self) -> u32 {
+            self.theme.typography.scaled_size()
+        }
+
+        // Monospace cells sized from the font's 'M', or the toolkit's
+        // fallback metrics
+        pub fn cell_size(&self) -> (u32, u32) {
+            let size = self.text_size();
+            match &self.font {
+                Some(font) => {
+                    let font = font.font();
+                    let width = font.advance(font.glyph_index('M'), size);
+                    (width.ceil().max(1.0) as u32, font.line_height(size).ceil().max(1.0) as u32)
+                }
+                None => ((size / 2).max(1), (size * 3 / 2).max(1)),
+            }
+        }
+
+        // Fits the grid to the window and tells the program
+        pub fn resize(&mut self, width: u32, height: u32) {
+            (self.width, self.height) = (width, height);
+            let (cell_width, cell_height) = self.cell_size();
+            let (cols, rows) = ((width / cell_width).max(1) as usize, (height / cell_height).max(1) as usize);
+            if self.terminal.size() != (cols, rows) {
+                self.terminal.resize(cols, rows);
+                self.pty.resize(PtySize {
+                    rows: rows as u16,
+                    cols: cols as u16,
+                });
+            }
+            self.repaint = true;
+        }
+
+        pub fn size(&self) -> (u32, u32) {
+            (self.width, self.height)
+        }
+
+        // Feeds the emulator whatever programs printed and answers their
+        // queries; returns whether anything arrived
+        pub fn pump(&mut self) -> Result<bool, &'static str> {
+            let output = self.pty.read();
+            if output.is_empty() {
+                return Ok(false);
+            }
+            let history = self.terminal.first_line() + self.terminal.scrollback_len() as u64;
+            self.terminal.feed(&output);
+            // Stay on the same lines while scrolled back
+            if self.view_offset > 0 {
+                let grown = self.terminal.first_line() + self.terminal.scrollback_len() as u64 - history;
+                self.view_offset = (self.view_offset + grown as usize).min(self.terminal.scrollback_len());
+                self.repaint = true;
+            }
+            let responses = self.terminal.take_responses();
+            if !responses.is_empty() {
+                self.pty.write(&responses)?;
+            }
+            Ok(true)
+        }
+
+        fn send(&mut self, bytes: &[u8]) -> Result<(), &'static str> {
+            if self.view_offset > 0 {
+                self.view_offset = 0;
+                self.repaint = true;
+            }
+            self.pty.write(bytes)
+        }
+
+        pub fn key(&mut self, code: u32, pressed: bool) -> Result<(), &'static str> {
+            match code {
+                KEY_LEFTSHIFT | KEY_RIGHTSHIFT => self.modifiers.shift = pressed,
+                KEY_LEFTCTRL | KEY_RIGHTCTRL => self.modifiers.ctrl = pressed,
+                KEY_LEFTALT | KEY_RIGHTALT => self.modifiers.alt = pressed,
+                _ => {}
+            }
+            if !pressed {
+                return Ok(());
+            }
+            // Shift-PageUp and PageDown page through the scrollback
+            let page = self.terminal.size().1 as i32;
+            match code {
+                KEY_PAGEUP if self.modifiers.shift => self.scroll(page),
+                KEY_PAGEDOWN if self.modifiers.shift => self.scroll(-page),
+                _ => {
+                    if let Some(bytes) = encode_key(code, self.modifiers, self.terminal.app_cursor()) {
+                        self.send(&bytes)?;
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        pub fn text_input(&mut self, character: char) -> Result<(), &'static str> {
+            let bytes = encode_text(character, self.modifiers);
+            self.send(&bytes)
+        }
+
+        // Bracketed when the program asked for it, so pasted text is not
+        // run as typed commands
+        pub fn paste(&mut self, text: &str) -> Result<(), &'static str> {
+            let mut bytes = Vec::new();
+            let bracketed = self.terminal.bracketed_paste();
+            if bracketed {
+                bytes.extend_from_slice(b"\x1b[200~");
+            }
+            bytes.extend(text.replace('\n', "\r").bytes());
+            if bracketed {
+                bytes.extend_from_slice(b"\x1b[201~");
+            }
+            self.send(&bytes)
+        }
+
+        // Positive lines go back into the scrollback
+        pub fn scroll(&mut self, lines: i32) {
+            let offset = (self.view_offset as i64 + lines as i64).clamp(0, self.terminal.scrollback_len() as i64) as usize;
+            if offset != self.view_offset {
+                self.view_offset = offset;
+                self.repaint = true;
+            }
+        }
+
+        pub fn view_offset(&self) -> usize {
+            self.view_offset
+        }
+
+        fn top_line(&self) -> u64 {
+            self.terminal.first_line() + (self.terminal.scrollback_len() - self.view_offset) as u64
+        }
+
+        fn cell_at(&self, x: i32, y: i32) -> (u64, usize) {
+            let (cell_width, cell_height) = self.cell_size();
+            let (cols, rows) = self.terminal.size();
+            let col = (x.max(0) as u32 / cell_width).min(cols as u32 - 1) as usize;
+            let row = (y.max(0) as u32 / cell_height).min(rows as u32 - 1) as u64;
+            (self.top_line() + row, col)
+        }
+
+        // Left button drags select, by character and across lines
+        pub fn pointer_button(&mut self, x: i32, y: i32, pressed: bool) {
+            if pressed {
+                let cell = self.cell_at(x, y);
+                self.selection = Some((cell, cell));
+                self.selecting = true;
+            } else {
+                self.selecting = false;
+                if self.selection.is_some_and(|(anchor, head)| anchor == head) {
+                    self.selection = None;
+                }
+            }
+            self.repaint = true;
+        }
+
+        pub fn pointer_motion(&mut self, x: i32, y: i32) {
+            if !self.selecting {
+                return;
+            }
+            let head = self.cell_at(x, y);
+            if let Some((anchor, _)) = self.selection {
+                self.selection = Some((anchor, head));
+                self.repaint = true;
+            }
+        }
+
+        fn selection_range(&self) -> Option<((u64, usize), (u64, usize))> {
+            let (anchor, head) = self.selection?;
+            Some(if anchor <= head { (anchor, head) } else { (head, anchor) })
+        }
+
+        fn is_selected(&self, line: u64, col: usize) -> bool {
+            self.selection_range().is_some_and(|(start, end)| (line, col) >= start && (line, col) <= end)
+        }
+
+        // Trailing blanks are dropped from every line
+        pub fn selection_text(&self) -> Option<String> {
+            let ((start_line, start_col), (end_line, end_col)) = self.selection_range()?;
+            let lines: Vec<String> = (start_line..=end_line)
+                .filter_map(|number| {
+                    let line = self.terminal.line(number)?;
+                    let from = if number == start_line { start_col } else { 0 };
+                    let to = if number == end_line { end_col + 1 } else { line.len() };
+                    Some(Terminal::text(&line[from.min(line.len())..to.min(line.len())]))
+                })
+                .collect();
+            Some(lines.join("\n"))
+        }
+
+        fn color(&self, color: CellColor, bold: bool, default: u32) -> u32 {
+            match color {
+                CellColor::Default => default,
+                CellColor::Indexed(index) if bold && index < 8 => palette(index + 8),
+                CellColor::Indexed(index) => palette(index),
+                CellColor::Rgb(rgb) => rgb,
+            }
+        }
+
+        fn paint_row(&self, canvas: &mut Canvas, row: usize) {
+            let (cell_width, cell_height) = self.cell_size();
+            let colors = self.theme.colors(self.variant);
+            let number = self.top_line() + row as u64;
+            let Some(line) = self.terminal.line(number) else {
+                return;
+            };
+            let cursor = self.terminal.cursor();
+            let show_cursor = self.view_offset == 0 && self.terminal.cursor_visible() && cursor.0 == row;
+            let y = (row as u32 * cell_height) as i32;
+            let size = self.text_size();
+            for (col, cell) in line.iter().enumerate() {
+                let attributes = cell.attributes;
+                let mut foreground = self.color(attributes.foreground, attributes.bold, colors.text);
+                let mut background = self.color(attributes.background, false, colors.background);
+                if attributes.inverse != (show_cursor && cursor.1 == col) {
+                    (foreground, background) = (background, foreground);
+                }
+                if self.is_selected(number, col) {
+                    background = colors.accent;
+                }
+                let area = Rect::new((col as u32 * cell_width) as i32, y, cell_width, cell_height);
+                canvas.fill_rect(area, &Paint::Solid(Color::argb(background)));
+                let paint = Paint::Solid(Color::argb(foreground));
+                if let (Some(font), false) = (&self.font, cell.character == ' ') {
+                    let metrics = font.font();
+                    let baseline = y as f32 + (cell_height as f32 + metrics.ascent(size) - metrics.descent(size)) / 2.0;
+                    let _ = font.draw(canvas, &cell.character.to_string(), size, area.x as f32, baseline, &paint);
+                }
+                if attributes.underline {
+                    canvas.fill_rect(Rect::new(area.x, y + cell_height as i32 - 1, cell_width, 1), &paint);
+                }
+            }
+        }
+
+        // Redraws changed rows into a window-sized buffer and returns the
+        // areas to damage
+        pub fn render(&mut self, buffer: &Buffer) -> Result<Vec<Rect>, &'static str> {
+            if (buffer.width(), buffer.height()) != (self.width, self.height) {
+                return Err("Buffer size does not match the terminal");
+            }
+            let (_, rows) = self.terminal.size();
+            let mut dirty = self.terminal.take_dirty();
+            let cursor = self.terminal.cursor();
+            if cursor != self.cursor {
+                dirty.extend([self.cursor.0, cursor.0]);
+                self.cursor = cursor;
+            }
+            if std::mem::take(&mut self.repaint) || (self.view_offset > 0 && !dirty.is_empty()) {
+                dirty = (0..rows).collect();
+            }
+            dirty.sort_unstable();
+            dirty.dedup();
+            dirty.retain(|row| *row < rows);
+            if dirty.is_empty() {
+                return Ok(Vec::new());
+            }
+            let (_, cell_height) = self.cell_size();
+            let background = self.theme.colors(self.variant).background;
+            let damage: Vec<Rect> = dirty
+                .iter()
+                .map(|row| Rect::new(0, (*row as u32 * cell_height) as i32, self.width, cell_height))
+                .collect();
+            buffer.draw(|canvas| {
+                if dirty.len() == rows {
+                    canvas.clear(Color::argb(background));
+                }
+                for row in &dirty {
+                    self.paint_row(canvas, *row);
+                }
+            });
+            if dirty.len() == rows {
+                return Ok(vec![Rect::new(0, 0, self.width, self.height)]);
+            }
+            Ok(damage)
+        }
+    }
+}
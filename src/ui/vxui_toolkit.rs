@@ -1,26 +1,1256 @@
 pub mod vxui_toolkit {
+    use vaelix_core::input::input::{
+        KEY_BACKSPACE, KEY_DELETE, KEY_DOWN, KEY_END, KEY_ENTER, KEY_HOME, KEY_LEFT, KEY_LEFTSHIFT, KEY_RIGHT,
+        KEY_RIGHTSHIFT, KEY_SPACE, KEY_TAB, KEY_UP,
+    };
+    use vaelix_graphics::vegagx::vegagx::{Canvas, Color, Path, Paint};
+    use vaelix_graphics::vxfont::vxfont::TextRenderer;
+    use vaelix_graphics::vxtheme::vxtheme::{StyleOverride, Theme, Variant, WidgetClass, WidgetState, WidgetStyle};
+    use vaelix_graphics::vxwin::vxwin::{Buffer, Rect, Viewport};
+    use vaelix_graphics::vxwm::vxwm::{
+        Decorations, FrameArea, WindowState, EDGE_BOTTOM, EDGE_LEFT, EDGE_RIGHT, EDGE_TOP,
+    };
+    use std::collections::BTreeMap;
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::sync::Arc;
+
     pub fn init() {
-        println!("Initializing VXUI Toolkit...");
+        log::info!("Initializing VXUI Toolkit...");
         // Initialize the VXUI Toolkit system
     }
 
     pub fn create_window(title: &str) {
-        println!("Creating window: {}", title);
+        log::info!("Creating window: {}", title);
         // Create a new window
     }
 
-    pub fn draw_button(label: &str) {
-        println!("Drawing button: {}", label);
-        // Draw a button
+    pub fn update() {
+        log::info!("Updating VXUI Toolkit...");
+        // Update the VXUI Toolkit system
     }
 
-    pub fn handle_event(event: &str) {
-        println!("Handling event: {}", event);
-        // Handle a UI event
+    // Server-side window frames for vxwin: a border, a title bar and close,
+    // maximize and minimize buttons from right to left
+    pub struct WindowDecorations {
+        pub title_height: u32,
+        pub border_width: u32,
+        pub active: u32,
+        pub inactive: u32,
+        // Titles are only drawn once a font has been loaded
+        pub font: Option<Arc<TextRenderer>>,
     }
 
-    pub fn update() {
-        println!("Updating VXUI Toolkit...");
-        // Update the VXUI Toolkit system
+    impl Default for WindowDecorations {
+        fn default() -> Self {
+            WindowDecorations {
+                title_height: 24,
+                border_width: 4,
+                active: 0xFF3A_6EA5,
+                inactive: 0xFF50_5050,
+                font: None,
+            }
+        }
+    }
+
+    const BUTTONS: [(FrameArea, u32); 3] = [
+        (FrameArea::Close, 0xFFE0_4040),
+        (FrameArea::Maximize, 0xFF60_A060),
+        (FrameArea::Minimize, 0xFFD0_A030),
+    ];
+
+    impl WindowDecorations {
+        // Button slots are title_height wide, counted from the right border
+        fn button_at(&self, width: u32, x: i32) -> Option<FrameArea> {
+            let right = width as i32 - self.border_width as i32;
+            let slot = (right - 1 - x) / self.title_height as i32;
+            (x < right && (slot as usize) < BUTTONS.len()).then(|| BUTTONS[slot as usize].0)
+        }
+    }
+
+    impl Decorations for WindowDecorations {
+        fn title_height(&self) -> u32 {
+            self.title_height
+        }
+
+        fn border_width(&self) -> u32 {
+            self.border_width
+        }
+
+        fn render(&self, frame: &Buffer, title: &str, focused: bool, _state: WindowState) {
+            let color = if focused { self.active } else { self.inactive };
+            let size = self.title_height as f32;
+            let right = (frame.width() - self.border_width) as f32;
+            frame.draw(|canvas| {
+                canvas.clear(Color::argb(color));
+                for (slot, (_, color)) in BUTTONS.iter().enumerate() {
+                    let center = right - (slot as f32 + 0.5) * size;
+                    let button = Path::circle(center, size / 2.0, size / 2.0 - 5.0);
+                    canvas.fill_path(&button, &Paint::Solid(Color::argb(*color)));
+                }
+                if let Some(font) = &self.font {
+                    // Vertically centred in the title bar, clipped short of the buttons
+                    let text_size = self.title_height * 2 / 3;
+                    let metrics = font.font();
+                    let baseline = (size + metrics.ascent(text_size) - metrics.descent(text_size)) / 2.0;
+                    let buttons = (right as u32).saturating_sub(BUTTONS.len() as u32 * self.title_height);
+                    canvas.save();
+                    canvas.clip_rect(Rect::new(0, 0, buttons, self.title_height));
+                    let text = Paint::Solid(Color::argb(0xFFFF_FFFF));
+                    let _ = font.draw(canvas, title, text_size, 8.0 + self.border_width as f32, baseline, &text);
+                    canvas.restore();
+                }
+            });
+        }
+
+        fn hit_test(&self, width: u32, height: u32, x: i32, y: i32) -> FrameArea {
+            let border = self.border_width as i32;
+            let mut edges = 0;
+            if x < border {
+                edges |= EDGE_LEFT;
+            } else if x >= width as i32 - border {
+                edges |= EDGE_RIGHT;
+            }
+            if y < border {
+                edges |= EDGE_TOP;
+            } else if y >= height as i32 - border {
+                edges |= EDGE_BOTTOM;
+            }
+            if edges != 0 {
+                return FrameArea::Edge(edges);
+            }
+            if y < self.title_height as i32 {
+                return self.button_at(width, x).unwrap_or(FrameArea::TitleBar);
+            }
+            FrameArea::Client
+        }
+    }
+
+    const MIN_INPUT_WIDTH: u32 = 120;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct WidgetId(pub u32);
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum WidgetKind {
+        Container,
+        Label { text: String },
+        Button { label: String },
+        // The cursor counts characters, not bytes. Secret inputs show a
+        // mask in place of each character.
+        TextInput { text: String, placeholder: String, cursor: usize, secret: bool },
+        List { items: Vec<String>, selected: Option<usize> },
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Direction {
+        Row,
+        Column,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Align {
+        Start,
+        Center,
+        End,
+        Stretch,
+    }
+
+    // Flexbox-like: a container lines its children up along `direction`,
+    // shares leftover space out by `grow` and places them across the line
+    // by `align`
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Layout {
+        pub direction: Direction,
+        pub align: Align,
+        pub padding: u32,
+        // The theme's gap when None
+        pub spacing: Option<u32>,
+        pub grow: u32,
+        // Fixed sizes override the measured ones
+        pub width: Option<u32>,
+        pub height: Option<u32>,
+    }
+
+    impl Default for Layout {
+        fn default() -> Self {
+            Layout {
+                direction: Direction::Column,
+                align: Align::Stretch,
+                padding: 0,
+                spacing: None,
+                grow: 0,
+                width: None,
+                height: None,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum WidgetEvent {
+        Clicked,
+        Changed(String),
+        Submitted(String),
+        Selected(usize),
+    }
+
+    // Events bubble up to the nearest widget with a callback
+    pub type Callback = Box<dyn FnMut(&mut WidgetTree, WidgetId, &WidgetEvent)>;
+
+    // What a widget is to assistive technology
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Role {
+        Group,
+        Label,
+        Button,
+        TextInput,
+        PasswordInput,
+        List,
+    }
+
+    // A widget as a screen reader would present it. Secret inputs report
+    // their mask, never their text; lists report the selected item.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct AccessibleNode {
+        pub id: WidgetId,
+        pub role: Role,
+        pub name: Option<String>,
+        pub text: String,
+        pub focused: bool,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum AccessibilityEvent {
+        // None when nothing has focus any more
+        FocusChanged(Option<AccessibleNode>),
+        TextChanged(AccessibleNode),
+        SelectionChanged(AccessibleNode),
+    }
+
+    // Declarative description of a subtree, mounted with WidgetTree::new
+    // or WidgetTree::append
+    pub struct Element {
+        kind: WidgetKind,
+        layout: Layout,
+        name: Option<String>,
+        style: StyleOverride,
+        callback: Option<Callback>,
+        children: Vec<Element>,
+    }
+
+    impl Element {
+        fn new(kind: WidgetKind) -> Self {
+            Element {
+                kind,
+                layout: Layout::default(),
+                name: None,
+                style: StyleOverride::default(),
+                callback: None,
+                children: Vec::new(),
+            }
+        }
+
+        pub fn container(direction: Direction) -> Self {
+            let mut element = Self::new(WidgetKind::Container);
+            element.layout.direction = direction;
+            element
+        }
+
+        pub fn label(text: &str) -> Self {
+            Self::new(WidgetKind::Label { text: text.to_string() })
+        }
+
+        pub fn button(label: &str) -> Self {
+            Self::new(WidgetKind::Button { label: label.to_string() })
+        }
+
+        pub fn text_input(placeholder: &str) -> Self {
+            Self::new(WidgetKind::TextInput {
+                text: String::new(),
+                placeholder: placeholder.to_string(),
+                cursor: 0,
+                secret: false,
+            })
+        }
+
+        // For passwords
+        pub fn secret_input(placeholder: &str) -> Self {
+            Self::new(WidgetKind::TextInput {
+                text: String::new(),
+                placeholder: placeholder.to_string(),
+                cursor: 0,
+                secret: true,
+            })
+        }
+
+        pub fn list(items: &[&str]) -> Self {
+            Self::new(WidgetKind::List {
+                items: items.iter().map(|item| item.to_string()).collect(),
+                selected: None,
+            })
+        }
+
+        pub fn child(mut self, child: Element) -> Self {
+            self.children.push(child);
+            self
+        }
+
+        // Lets the application find the widget again with WidgetTree::find
+        pub fn name(mut self, name: &str) -> Self {
+            self.name = Some(name.to_string());
+            self
+        }
+
+        // Takes precedence over the theme
+        pub fn style(mut self, style: StyleOverride) -> Self {
+            self.style = style;
+            self
+        }
+
+        pub fn align(mut self, align: Align) -> Self {
+            self.layout.align = align;
+            self
+        }
+
+        pub fn padding(mut self, padding: u32) -> Self {
+            self.layout.padding = padding;
+            self
+        }
+
+        pub fn spacing(mut self, spacing: u32) -> Self {
+            self.layout.spacing = Some(spacing);
+            self
+        }
+
+        pub fn grow(mut self, grow: u32) -> Self {
+            self.layout.grow = grow;
+            self
+        }
+
+        pub fn size(mut self, width: Option<u32>, height: Option<u32>) -> Self {
+            (self.layout.width, self.layout.height) = (width, height);
+            self
+        }
+
+        pub fn on_event(mut self, callback: impl FnMut(&mut WidgetTree, WidgetId, &WidgetEvent) + 'static) -> Self {
+            self.callback = Some(Box::new(callback));
+            self
+        }
+    }
+
+    struct Widget {
+        kind: WidgetKind,
+        layout: Layout,
+        name: Option<String>,
+        style: StyleOverride,
+        callback: Option<Callback>,
+        parent: Option<WidgetId>,
+        children: Vec<WidgetId>,
+        // Window coordinates, from the last layout pass
+        bounds: Rect,
+        // Shifts the widget and its children after layout, for animations
+        translation: (i32, i32),
+    }
+
+    // A window's widgets. Changes mark the areas they touch; render()
+    // repaints only those and returns them for the compositor. Layout and
+    // input are in logical pixels; the scale sets how many buffer pixels
+    // each one takes.
+    pub struct WidgetTree {
+        widgets: BTreeMap<WidgetId, Widget>,
+        root: WidgetId,
+        next_id: u32,
+        width: u32,
+        height: u32,
+        scale: f32,
+        font: Option<Arc<TextRenderer>>,
+        theme: Theme,
+        variant: Variant,
+        needs_layout: bool,
+        damage: Vec<Rect>,
+        focus: Option<WidgetId>,
+        hovered: Option<WidgetId>,
+        pressed: Option<WidgetId>,
+        // Held, for Shift-Tab
+        shift: bool,
+        accessibility: Vec<Sender<AccessibilityEvent>>,
+        // Text an input method is composing in the focused input, with its
+        // own cursor; not part of the input's text until committed
+        preedit: Option<(String, usize)>,
+    }
+
+    impl WidgetTree {
+        pub fn new(root: Element, width: u32, height: u32) -> Self {
+            let mut tree = WidgetTree {
+                widgets: BTreeMap::new(),
+                root: WidgetId(1),
+                next_id: 1,
+                width,
+                height,
+                scale: 1.0,
+                font: None,
+                theme: Theme::default(),
+                variant: Variant::Dark,
+                needs_layout: true,
+                damage: vec![Rect::new(0, 0, width, height)],
+                focus: None,
+                hovered: None,
+                pressed: None,
+                shift: false,
+                accessibility: Vec::new(),
+                preedit: None,
+            };
+            tree.root = tree.mount(root, None);
+            tree
+        }
+
+        fn mount(&mut self, element: Element, parent: Option<WidgetId>) -> WidgetId {
+            let id = WidgetId(self.next_id);
+            self.next_id += 1;
+            self.widgets.insert(
+                id,
+                Widget {
+                    kind: element.kind,
+                    layout: element.layout,
+                    name: element.name,
+                    style: element.style,
+                    callback: element.callback,
+                    parent,
+                    children: Vec::new(),
+                    bounds: Rect::new(0, 0, 0, 0),
+                    translation: (0, 0),
+                },
+            );
+            for child in element.children {
+                let child = self.mount(child, Some(id));
+                self.widgets.get_mut(&id).unwrap().children.push(child);
+            }
+            id
+        }
+
+        pub fn root(&self) -> WidgetId {
+            self.root
+        }
+
+        pub fn find(&self, name: &str) -> Option<WidgetId> {
+            self.widgets.iter().find(|(_, widget)| widget.name.as_deref() == Some(name)).map(|(id, _)| *id)
+        }
+
+        pub fn kind(&self, id: WidgetId) -> Option<&WidgetKind> {
+            self.widgets.get(&id).map(|widget| &widget.kind)
+        }
+
+        pub fn children(&self, id: WidgetId) -> &[WidgetId] {
+            self.widgets.get(&id).map_or(&[], |widget| &widget.children)
+        }
+
+        // Lays out first if anything changed since the last pass
+        pub fn bounds(&mut self, id: WidgetId) -> Option<Rect> {
+            self.layout();
+            self.widgets.get(&id).map(|widget| widget.bounds)
+        }
+
+        pub fn append(&mut self, parent: WidgetId, element: Element) -> Result<WidgetId, &'static str> {
+            match self.widgets.get(&parent).map(|widget| &widget.kind) {
+                Some(WidgetKind::Container) => {}
+                Some(_) => return Err("Only containers have children"),
+                None => return Err("Widget not found"),
+            }
+            let id = self.mount(element, Some(parent));
+            self.widgets.get_mut(&parent).unwrap().children.push(id);
+            self.needs_layout = true;
+            Ok(id)
+        }
+
+        pub fn remove(&mut self, id: WidgetId) -> Result<(), &'static str> {
+            if id == self.root {
+                return Err("Cannot remove the root widget");
+            }
+            let widget = self.widgets.remove(&id).ok_or("Widget not found")?;
+            self.add_damage(widget.bounds);
+            if let Some(parent) = widget.parent.and_then(|parent| self.widgets.get_mut(&parent)) {
+                parent.children.retain(|child| *child != id);
+            }
+            let mut orphans = widget.children;
+            while let Some(orphan) = orphans.pop() {
+                if let Some(widget) = self.widgets.remove(&orphan) {
+                    orphans.extend(widget.children);
+                }
+            }
+            for state in [&mut self.focus, &mut self.hovered, &mut self.pressed] {
+                if state.is_some_and(|widget| !self.widgets.contains_key(&widget)) {
+                    *state = None;
+                }
+            }
+            self.needs_layout = true;
+            Ok(())
+        }
+
+        pub fn set_translation(&mut self, id: WidgetId, dx: i32, dy: i32) -> Result<(), &'static str> {
+            self.widget_mut(id)?.translation = (dx, dy);
+            self.needs_layout = true;
+            Ok(())
+        }
+
+        pub fn size(&self) -> (u32, u32) {
+            (self.width, self.height)
+        }
+
+        pub fn resize(&mut self, width: u32, height: u32) {
+            (self.width, self.height) = (width, height);
+            self.needs_layout = true;
+            self.damage = vec![Rect::new(0, 0, width, height)];
+        }
+
+        pub fn scale(&self) -> f32 {
+            self.scale
+        }
+
+        // Follows the "scale" suggestion for the output the window is on
+        pub fn set_scale(&mut self, scale: f32) {
+            self.scale = scale.max(1.0);
+            self.damage = vec![Rect::new(0, 0, self.width, self.height)];
+        }
+
+        // What render() draws into
+        pub fn buffer_size(&self) -> (u32, u32) {
+            self.viewport().physical_size()
+        }
+
+        fn viewport(&self) -> Viewport {
+            Viewport::new(Rect::new(0, 0, self.width, self.height), self.scale)
+        }
+
+        // Without a font, text is measured at an average advance and not
+        // drawn
+        pub fn set_font(&mut self, font: Option<Arc<TextRenderer>>) {
+            self.font = font;
+            self.needs_layout = true;
+            self.add_damage(Rect::new(0, 0, self.width, self.height));
+        }
+
+        // Restyles everything; typography and spacing may change the layout
+        pub fn set_theme(&mut self, theme: Theme, variant: Variant) {
+            (self.theme, self.variant) = (theme, variant);
+            self.needs_layout = true;
+            self.add_damage(Rect::new(0, 0, self.width, self.height));
+        }
+
+        pub fn theme(&self) -> (&Theme, Variant) {
+            (&self.theme, self.variant)
+        }
+
+        // What the widget paints with in its current state
+        pub fn style(&self, id: WidgetId) -> Option<WidgetStyle> {
+            let widget = self.widgets.get(&id)?;
+            let class = match widget.kind {
+                WidgetKind::Container => return None,
+                WidgetKind::Label { .. } => WidgetClass::Label,
+                WidgetKind::Button { .. } => WidgetClass::Button,
+                WidgetKind::TextInput { .. } => WidgetClass::TextInput,
+                WidgetKind::List { .. } => WidgetClass::List,
+            };
+            let state = WidgetState {
+                hovered: self.hovered == Some(id),
+                pressed: self.pressed == Some(id),
+                focused: self.focus == Some(id),
+            };
+            Some(self.theme.resolve(self.variant, class, state, &widget.style))
+        }
+
+        pub fn set_style(&mut self, id: WidgetId, style: StyleOverride) -> Result<(), &'static str> {
+            self.widget_mut(id)?.style = style;
+            self.damage_widget(id);
+            Ok(())
+        }
+
+        fn add_damage(&mut self, rect: Rect) {
+            let Some(rect) = rect.intersect(&Rect::new(0, 0, self.width, self.height)) else {
+                return;
+            };
+            if let Some(existing) = self.damage.iter_mut().find(|existing| existing.intersect(&rect).is_some()) {
+                *existing = existing.union(&rect);
+            } else {
+                self.damage.push(rect);
+            }
+        }
+
+        fn damage_widget(&mut self, id: WidgetId) {
+            if let Some(bounds) = self.widgets.get(&id).map(|widget| widget.bounds) {
+                self.add_damage(bounds);
+            }
+        }
+
+        pub fn pending_damage(&self) -> &[Rect] {
+            &self.damage
+        }
+
+        fn widget_mut(&mut self, id: WidgetId) -> Result<&mut Widget, &'static str> {
+            self.widgets.get_mut(&id).ok_or("Widget not found")
+        }
+
+        // Label text, button label or input contents
+        pub fn text(&self, id: WidgetId) -> Option<&str> {
+            match &self.widgets.get(&id)?.kind {
+                WidgetKind::Label { text } | WidgetKind::Button { label: text } | WidgetKind::TextInput { text, .. } => {
+                    Some(text)
+                }
+                _ => None,
+            }
+        }
+
+        pub fn set_text(&mut self, id: WidgetId, value: &str) -> Result<(), &'static str> {
+            match &mut self.widget_mut(id)?.kind {
+                WidgetKind::Label { text } | WidgetKind::Button { label: text } => *text = value.to_string(),
+                WidgetKind::TextInput { text, cursor, .. } => {
+                    *text = value.to_string();
+                    *cursor = value.chars().count();
+                }
+                _ => return Err("Widget has no text"),
+            }
+            self.damage_widget(id);
+            self.needs_layout = true;
+            self.announce(id, AccessibilityEvent::TextChanged);
+            Ok(())
+        }
+
+        pub fn set_items(&mut self, id: WidgetId, values: &[&str]) -> Result<(), &'static str> {
+            let WidgetKind::List { items, selected } = &mut self.widget_mut(id)?.kind else {
+                return Err("Widget is not a list");
+            };
+            *items = values.iter().map(|item| item.to_string()).collect();
+            *selected = selected.filter(|index| *index < values.len());
+            self.damage_widget(id);
+            self.needs_layout = true;
+            Ok(())
+        }
+
+        pub fn selected(&self, id: WidgetId) -> Option<usize> {
+            match &self.widgets.get(&id)?.kind {
+                WidgetKind::List { selected, .. } => *selected,
+                _ => None,
+            }
+        }
+
+        pub fn select(&mut self, id: WidgetId, index: Option<usize>) -> Result<(), &'static str> {
+            let WidgetKind::List { items, selected } = &mut self.widget_mut(id)?.kind else {
+                return Err("Widget is not a list");
+            };
+            if index.is_some_and(|index| index >= items.len()) {
+                return Err("List index out of range");
+            }
+            let changed = std::mem::replace(selected, index) != index;
+            self.damage_widget(id);
+            if changed {
+                self.announce(id, AccessibilityEvent::SelectionChanged);
+            }
+            Ok(())
+        }
+
+        pub fn focus(&self) -> Option<WidgetId> {
+            self.focus
+        }
+
+        // Buttons, inputs and lists take keyboard focus
+        pub fn set_focus(&mut self, id: Option<WidgetId>) -> Result<(), &'static str> {
+            if let Some(id) = id {
+                match &self.widgets.get(&id).ok_or("Widget not found")?.kind {
+                    WidgetKind::Button { .. } | WidgetKind::TextInput { .. } | WidgetKind::List { .. } => {}
+                    _ => return Err("Widget cannot take focus"),
+                }
+            }
+            for widget in [self.focus, id].into_iter().flatten() {
+                self.damage_widget(widget);
+            }
+            let changed = std::mem::replace(&mut self.focus, id) != id;
+            if changed {
+                self.preedit = None;
+            }
+            if changed && !self.accessibility.is_empty() {
+                let node = id.and_then(|id| self.accessible(id));
+                self.accessibility.retain(|subscriber| subscriber.send(AccessibilityEvent::FocusChanged(node.clone())).is_ok());
+            }
+            Ok(())
+        }
+
+        fn collect_focusable(&self, id: WidgetId, order: &mut Vec<WidgetId>) {
+            let widget = &self.widgets[&id];
+            if matches!(widget.kind, WidgetKind::Button { .. } | WidgetKind::TextInput { .. } | WidgetKind::List { .. }) {
+                order.push(id);
+            }
+            for child in &widget.children {
+                self.collect_focusable(*child, order);
+            }
+        }
+
+        // Tab order is tree order, wrapping around at either end
+        pub fn focus_next(&mut self, forward: bool) {
+            let mut order = Vec::new();
+            self.collect_focusable(self.root, &mut order);
+            let count = order.len();
+            if count == 0 {
+                return;
+            }
+            let next = match (self.focus.and_then(|focus| order.iter().position(|id| *id == focus)), forward) {
+                (Some(index), true) => (index + 1) % count,
+                (Some(index), false) => (index + count - 1) % count,
+                (None, true) => 0,
+                (None, false) => count - 1,
+            };
+            let _ = self.set_focus(Some(order[next]));
+        }
+
+        pub fn accessible(&self, id: WidgetId) -> Option<AccessibleNode> {
+            let widget = self.widgets.get(&id)?;
+            let (role, text) = match &widget.kind {
+                WidgetKind::Container => (Role::Group, String::new()),
+                WidgetKind::Label { text } => (Role::Label, text.clone()),
+                WidgetKind::Button { label } => (Role::Button, label.clone()),
+                WidgetKind::TextInput { text, secret: true, .. } => (Role::PasswordInput, Self::shown(text, true)),
+                WidgetKind::TextInput { text, .. } => (Role::TextInput, text.clone()),
+                WidgetKind::List { items, selected } => {
+                    (Role::List, selected.and_then(|index| items.get(index)).cloned().unwrap_or_default())
+                }
+            };
+            Some(AccessibleNode {
+                id,
+                role,
+                name: widget.name.clone(),
+                text,
+                focused: self.focus == Some(id),
+            })
+        }
+
+        // Every widget with its depth, parents before children, so a
+        // screen reader can read out the whole window
+        pub fn accessibility_tree(&self) -> Vec<(usize, AccessibleNode)> {
+            let mut nodes = Vec::new();
+            let mut stack = vec![(0, self.root)];
+            while let Some((depth, id)) = stack.pop() {
+                nodes.push((depth, self.accessible(id).unwrap()));
+                stack.extend(self.widgets[&id].children.iter().rev().map(|child| (depth + 1, *child)));
+            }
+            nodes
+        }
+
+        // Focus moves and text and selection changes, whether from input
+        // or the application
+        pub fn subscribe_accessibility(&mut self) -> Receiver<AccessibilityEvent> {
+            let (sender, receiver) = mpsc::channel();
+            self.accessibility.push(sender);
+            receiver
+        }
+
+        fn announce(&mut self, id: WidgetId, event: fn(AccessibleNode) -> AccessibilityEvent) {
+            if self.accessibility.is_empty() {
+                return;
+            }
+            if let Some(node) = self.accessible(id) {
+                let event = event(node);
+                self.accessibility.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+            }
+        }
+
+        fn text_width(&self, text: &str) -> u32 {
+            match &self.font {
+                Some(font) => font.font().measure(text, self.text_size()) as u32 + 1,
+                None => text.chars().count() as u32 * self.text_size() / 2,
+            }
+        }
+
+        fn line_height(&self) -> u32 {
+            match &self.font {
+                Some(font) => font.font().line_height(self.text_size()) as u32 + 1,
+                None => self.text_size() * 3 / 2,
+            }
+        }
+
+        fn text_size(&self) -> u32 {
+            self.theme.typography.scaled_size()
+        }
+
+        // Preferred size before grow and stretch
+        fn measure(&self, id: WidgetId) -> (u32, u32) {
+            let widget = &self.widgets[&id];
+            let (line, inset, spacing) = (self.line_height(), self.theme.spacing.inset, self.spacing(&widget.layout));
+            let (width, height) = match &widget.kind {
+                WidgetKind::Container => {
+                    let (mut main, mut cross) = (0, 0);
+                    for (index, child) in widget.children.iter().enumerate() {
+                        let (width, height) = self.measure(*child);
+                        let (child_main, child_cross) = match widget.layout.direction {
+                            Direction::Row => (width, height),
+                            Direction::Column => (height, width),
+                        };
+                        main += child_main + if index > 0 { spacing } else { 0 };
+                        cross = cross.max(child_cross);
+                    }
+                    let padding = 2 * widget.layout.padding;
+                    match widget.layout.direction {
+                        Direction::Row => (main + padding, cross + padding),
+                        Direction::Column => (cross + padding, main + padding),
+                    }
+                }
+                WidgetKind::Label { text } => (self.text_width(text), line),
+                WidgetKind::Button { label } => (self.text_width(label) + 4 * inset, line + 2 * inset),
+                WidgetKind::TextInput { text, placeholder, secret, .. } => {
+                    let content = self.text_width(&Self::shown(text, *secret)).max(self.text_width(placeholder));
+                    (content.max(MIN_INPUT_WIDTH) + 2 * inset, line + 2 * inset)
+                }
+                WidgetKind::List { items, .. } => {
+                    let widest = items.iter().map(|item| self.text_width(item)).max().unwrap_or(0);
+                    (widest + 2 * inset, items.len() as u32 * line + 2 * inset)
+                }
+            };
+            (widget.layout.width.unwrap_or(width), widget.layout.height.unwrap_or(height))
+        }
+
+        fn spacing(&self, layout: &Layout) -> u32 {
+            layout.spacing.unwrap_or(self.theme.spacing.gap)
+        }
+
+        fn arrange(&mut self, id: WidgetId, bounds: Rect) {
+            let widget = self.widgets.get_mut(&id).unwrap();
+            let bounds = Rect::new(bounds.x + widget.translation.0, bounds.y + widget.translation.1, bounds.width, bounds.height);
+            let old = std::mem::replace(&mut widget.bounds, bounds);
+            if old != bounds {
+                self.add_damage(old);
+                self.add_damage(bounds);
+            }
+            let widget = &self.widgets[&id];
+            let (layout, children) = (widget.layout, widget.children.clone());
+            let spacing = self.spacing(&layout);
+            if children.is_empty() {
+                return;
+            }
+            let padding = layout.padding as i32;
+            let inner = Rect::new(
+                bounds.x + padding,
+                bounds.y + padding,
+                bounds.width.saturating_sub(2 * layout.padding),
+                bounds.height.saturating_sub(2 * layout.padding),
+            );
+            let (inner_main, inner_cross) = match layout.direction {
+                Direction::Row => (inner.width, inner.height),
+                Direction::Column => (inner.height, inner.width),
+            };
+            let sizes: Vec<(u32, u32)> = children
+                .iter()
+                .map(|child| {
+                    let (width, height) = self.measure(*child);
+                    match layout.direction {
+                        Direction::Row => (width, height),
+                        Direction::Column => (height, width),
+                    }
+                })
+                .collect();
+            let used: u32 = sizes.iter().map(|(main, _)| main).sum::<u32>() + spacing * (children.len() as u32 - 1);
+            let extra = inner_main.saturating_sub(used);
+            let grow: u32 = children.iter().map(|child| self.widgets[child].layout.grow).sum();
+            let mut position = 0;
+            for (child, (main, cross)) in children.into_iter().zip(sizes) {
+                let child_grow = self.widgets[&child].layout.grow;
+                let main = main + (extra * child_grow).checked_div(grow).unwrap_or(0);
+                let cross = if layout.align == Align::Stretch { inner_cross } else { cross.min(inner_cross) };
+                let offset = match layout.align {
+                    Align::Start | Align::Stretch => 0,
+                    Align::Center => (inner_cross - cross) / 2,
+                    Align::End => inner_cross - cross,
+                } as i32;
+                let rect = match layout.direction {
+                    Direction::Row => Rect::new(inner.x + position, inner.y + offset, main, cross),
+                    Direction::Column => Rect::new(inner.x + offset, inner.y + position, cross, main),
+                };
+                self.arrange(child, rect);
+                position += (main + spacing) as i32;
+            }
+        }
+
+        // What the whole tree measures at, to size a window to fit
+        pub fn preferred_size(&self) -> (u32, u32) {
+            self.measure(self.root)
+        }
+
+        // The root always fills the window
+        pub fn layout(&mut self) {
+            if self.needs_layout {
+                self.needs_layout = false;
+                self.arrange(self.root, Rect::new(0, 0, self.width, self.height));
+            }
+        }
+
+        // Innermost widget under a window position
+        pub fn widget_at(&mut self, x: i32, y: i32) -> Option<WidgetId> {
+            self.layout();
+            let point = Rect::new(x, y, 1, 1);
+            let mut found = None;
+            let mut candidates = vec![self.root];
+            while let Some(id) = candidates.pop() {
+                if self.widgets[&id].bounds.intersect(&point).is_some() {
+                    found = Some(id);
+                    candidates = self.widgets[&id].children.clone();
+                }
+            }
+            found
+        }
+
+        // Hands the event to the widget's callback, or its nearest
+        // ancestor's. Callbacks may change the tree, including removing
+        // the widget they belong to.
+        fn emit(&mut self, id: WidgetId, event: WidgetEvent) {
+            let mut handler = Some(id);
+            while let Some(current) = handler {
+                let widget = self.widgets.get_mut(&current).unwrap();
+                if let Some(mut callback) = widget.callback.take() {
+                    callback(self, id, &event);
+                    if let Some(widget) = self.widgets.get_mut(&current) {
+                        widget.callback.get_or_insert(callback);
+                    }
+                    return;
+                }
+                handler = widget.parent;
+            }
+        }
+
+        pub fn pointer_motion(&mut self, x: i32, y: i32) {
+            let hovered = self
+                .widget_at(x, y)
+                .filter(|id| matches!(self.widgets[id].kind, WidgetKind::Button { .. }));
+            if hovered != self.hovered {
+                for widget in [self.hovered, hovered].into_iter().flatten() {
+                    self.damage_widget(widget);
+                }
+                self.hovered = hovered;
+            }
+        }
+
+        // Buttons click on release over the button they were pressed on
+        pub fn pointer_button(&mut self, x: i32, y: i32, pressed: bool) {
+            if !pressed {
+                if let Some(button) = self.pressed.take() {
+                    self.damage_widget(button);
+                    if self.widget_at(x, y) == Some(button) {
+                        self.emit(button, WidgetEvent::Clicked);
+                    }
+                }
+                return;
+            }
+            let Some(id) = self.widget_at(x, y) else {
+                return;
+            };
+            let line = self.line_height() as i32;
+            let widget = &self.widgets[&id];
+            match &widget.kind {
+                WidgetKind::Button { .. } => {
+                    self.pressed = Some(id);
+                    let _ = self.set_focus(Some(id));
+                }
+                WidgetKind::TextInput { .. } => {
+                    let _ = self.set_focus(Some(id));
+                }
+                WidgetKind::List { items, .. } => {
+                    let row = (y - widget.bounds.y - self.theme.spacing.inset as i32).div_euclid(line);
+                    let count = items.len();
+                    let _ = self.set_focus(Some(id));
+                    if (0..count as i32).contains(&row) && self.select(id, Some(row as usize)).is_ok() {
+                        self.emit(id, WidgetEvent::Selected(row as usize));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // What an input displays for its text
+        fn shown(text: &str, secret: bool) -> String {
+            if secret {
+                "*".repeat(text.chars().count())
+            } else {
+                text.to_string()
+            }
+        }
+
+        // Tab and Shift-Tab move focus; editing and navigation keys go to
+        // the focused widget
+        pub fn key(&mut self, code: u32, pressed: bool) {
+            match code {
+                KEY_LEFTSHIFT | KEY_RIGHTSHIFT => self.shift = pressed,
+                KEY_TAB if pressed => return self.focus_next(!self.shift),
+                _ => {}
+            }
+            let Some(id) = self.focus.filter(|_| pressed) else {
+                return;
+            };
+            let event = match &mut self.widgets.get_mut(&id).unwrap().kind {
+                WidgetKind::Button { .. } if code == KEY_ENTER || code == KEY_SPACE => Some(WidgetEvent::Clicked),
+                WidgetKind::TextInput { text, cursor, .. } => {
+                    let length = text.chars().count();
+                    let byte = |index: usize| text.char_indices().nth(index).map_or(text.len(), |(byte, _)| byte);
+                    match code {
+                        KEY_BACKSPACE if *cursor > 0 => {
+                            *cursor -= 1;
+                            text.remove(byte(*cursor));
+                            Some(WidgetEvent::Changed(text.clone()))
+                        }
+                        KEY_DELETE if *cursor < length => {
+                            text.remove(byte(*cursor));
+                            Some(WidgetEvent::Changed(text.clone()))
+                        }
+                        KEY_LEFT => {
+                            *cursor = cursor.saturating_sub(1);
+                            None
+                        }
+                        KEY_RIGHT => {
+                            *cursor = (*cursor + 1).min(length);
+                            None
+                        }
+                        KEY_HOME => {
+                            *cursor = 0;
+                            None
+                        }
+                        KEY_END => {
+                            *cursor = length;
+                            None
+                        }
+                        KEY_ENTER => Some(WidgetEvent::Submitted(text.clone())),
+                        _ => None,
+                    }
+                }
+                WidgetKind::List { items, selected } if !items.is_empty() => {
+                    let next = match (code, *selected) {
+                        (KEY_UP, Some(index)) => index.saturating_sub(1),
+                        (KEY_DOWN, Some(index)) => (index + 1).min(items.len() - 1),
+                        (KEY_UP | KEY_DOWN, None) => 0,
+                        _ => return,
+                    };
+                    *selected = Some(next);
+                    Some(WidgetEvent::Selected(next))
+                }
+                _ => None,
+            };
+            self.damage_widget(id);
+            if let Some(event) = event {
+                match event {
+                    WidgetEvent::Changed(_) => {
+                        self.needs_layout = true;
+                        self.announce(id, AccessibilityEvent::TextChanged);
+                    }
+                    WidgetEvent::Selected(_) => self.announce(id, AccessibilityEvent::SelectionChanged),
+                    _ => {}
+                }
+                self.emit(id, event);
+            }
+        }
+
+        // Characters typed into the focused input
+        pub fn text_input(&mut self, character: char) {
+            if !character.is_control() {
+                self.insert(&character.to_string());
+            }
+        }
+
+        fn insert(&mut self, value: &str) {
+            let Some(id) = self.focus else {
+                return;
+            };
+            let WidgetKind::TextInput { text, cursor, .. } = &mut self.widgets.get_mut(&id).unwrap().kind else {
+                return;
+            };
+            let byte = text.char_indices().nth(*cursor).map_or(text.len(), |(byte, _)| byte);
+            text.insert_str(byte, value);
+            *cursor += value.chars().count();
+            let changed = WidgetEvent::Changed(text.clone());
+            self.damage_widget(id);
+            self.needs_layout = true;
+            self.announce(id, AccessibilityEvent::TextChanged);
+            self.emit(id, changed);
+        }
+
+        fn focused_input(&self, allow_secret: bool) -> Option<WidgetId> {
+            let id = self.focus?;
+            match &self.widgets[&id].kind {
+                WidgetKind::TextInput { secret, .. } if allow_secret || !secret => Some(id),
+                _ => None,
+            }
+        }
+
+        pub fn preedit(&self) -> Option<(&str, usize)> {
+            self.preedit.as_ref().map(|(text, cursor)| (text.as_str(), *cursor))
+        }
+
+        // Shows an input method's composition at the cursor of the focused
+        // input, underlined; empty text clears it. Secret inputs take no
+        // composed text.
+        pub fn set_preedit(&mut self, text: &str, cursor: usize) -> Result<(), &'static str> {
+            let id = self.focused_input(false).ok_or("No text input has focus")?;
+            self.preedit = (!text.is_empty()).then(|| (text.to_string(), cursor.min(text.chars().count())));
+            self.damage_widget(id);
+            Ok(())
+        }
+
+        // Text an input method settled on, in place of the composition
+        pub fn commit_text(&mut self, value: &str) -> Result<(), &'static str> {
+            let id = self.focused_input(false).ok_or("No text input has focus")?;
+            self.preedit = None;
+            self.damage_widget(id);
+            let value: String = value.chars().filter(|character| !character.is_control()).collect();
+            if !value.is_empty() {
+                self.insert(&value);
+            }
+            Ok(())
+        }
+
+        // The focused input's text with the composition spliced in at the
+        // cursor, and where the composition starts and the caret is, in
+        // characters
+        fn composed(&self, text: &str, cursor: usize) -> (String, usize, usize) {
+            let byte = text.char_indices().nth(cursor).map_or(text.len(), |(byte, _)| byte);
+            match &self.preedit {
+                Some((preedit, preedit_cursor)) => {
+                    (format!("{}{}{}", &text[..byte], preedit, &text[byte..]), cursor, cursor + preedit_cursor)
+                }
+                None => (text.to_string(), cursor, cursor),
+            }
+        }
+
+        // The caret of the focused input in tree coordinates, for placing
+        // an input method's candidates. None unless an input that takes
+        // composed text has focus.
+        pub fn caret_area(&mut self) -> Option<Rect> {
+            self.layout();
+            let id = self.focused_input(false)?;
+            let widget = &self.widgets[&id];
+            let WidgetKind::TextInput { text, cursor, .. } = &widget.kind else {
+                return None;
+            };
+            let (shown, _, caret) = self.composed(text, *cursor);
+            let before: String = shown.chars().take(caret).collect();
+            let (bounds, inset) = (widget.bounds, self.theme.spacing.inset);
+            let offset = self.text_width(&before).min(bounds.width.saturating_sub(2 * inset));
+            Some(Rect::new(bounds.x + (inset + offset) as i32, bounds.y + inset as i32, 1, bounds.height.saturating_sub(2 * inset)))
+        }
+
+        // `area` is in buffer pixels
+        fn draw_text(&self, canvas: &mut Canvas, text: &str, area: Rect, color: u32) {
+            let Some(font) = &self.font else {
+                return;
+            };
+            let (metrics, size) = (font.font(), (self.text_size() as f32 * self.scale).round() as u32);
+            let baseline = area.y as f32 + (area.height as f32 + metrics.ascent(size) - metrics.descent(size)) / 2.0;
+            canvas.save();
+            canvas.clip_rect(area);
+            let _ = font.draw(canvas, text, size, area.x as f32, baseline, &Paint::Solid(Color::argb(color)));
+            canvas.restore();
+        }
+
+        // Lays out in logical pixels and draws in buffer pixels
+        fn paint(&self, id: WidgetId, canvas: &mut Canvas) {
+            let widget = &self.widgets[&id];
+            let viewport = self.viewport();
+            let (scale, px) = (self.scale, |rect: Rect| viewport.to_physical(rect));
+            let bounds = px(widget.bounds);
+            let solid = |color: u32| Paint::Solid(Color::argb(color));
+            let focused = self.focus == Some(id);
+            let Some(style) = self.style(id) else {
+                if id == self.root {
+                    canvas.fill_rect(bounds, &solid(self.theme.colors(self.variant).background));
+                }
+                for child in &widget.children {
+                    self.paint(*child, canvas);
+                }
+                return;
+            };
+            let (logical, inset) = (widget.bounds, style.inset);
+            let inner = px(Rect::new(
+                logical.x + inset as i32,
+                logical.y + inset as i32,
+                logical.width.saturating_sub(2 * inset),
+                logical.height.saturating_sub(2 * inset),
+            ));
+            let (x, y, width, height) = (bounds.x as f32, bounds.y as f32, bounds.width as f32, bounds.height as f32);
+            let (radius, hairline) = (style.radius * scale, scale.round().max(1.0));
+            let line = self.line_height();
+            match &widget.kind {
+                WidgetKind::Container => {}
+                WidgetKind::Label { text } => self.draw_text(canvas, text, bounds, style.foreground),
+                WidgetKind::Button { label } => {
+                    canvas.fill_path(&Path::rounded_rect(x, y, width, height, radius), &solid(style.background));
+                    if focused {
+                        let half = hairline / 2.0;
+                        let outline = Path::rounded_rect(x + half, y + half, width - hairline, height - hairline, radius);
+                        canvas.stroke_path(&outline, hairline, &solid(style.border));
+                    }
+                    let text_width = ((self.text_width(label) as f32 * scale).round() as u32).min(inner.width);
+                    let centered = Rect::new(bounds.x + (bounds.width - text_width) as i32 / 2, inner.y, text_width, inner.height);
+                    self.draw_text(canvas, label, centered, style.foreground);
+                }
+                WidgetKind::TextInput { text, placeholder, cursor, secret } => {
+                    canvas.fill_rect(bounds, &solid(style.background));
+                    canvas.stroke_rect(bounds, hairline as u32, &solid(style.border));
+                    let (shown, start, caret) = if focused && !*secret {
+                        self.composed(text, *cursor)
+                    } else {
+                        (Self::shown(text, *secret), *cursor, *cursor)
+                    };
+                    if shown.is_empty() {
+                        self.draw_text(canvas, placeholder, inner, style.placeholder);
+                    } else {
+                        self.draw_text(canvas, &shown, inner, style.foreground);
+                    }
+                    let offset = |characters: usize| {
+                        let before: String = shown.chars().take(characters).collect();
+                        ((self.text_width(&before) as f32 * scale).round() as u32).min(inner.width)
+                    };
+                    if let Some((preedit, _)) = self.preedit.as_ref().filter(|_| focused && !*secret) {
+                        let (left, right) = (offset(start), offset(start + preedit.chars().count()));
+                        let underline = Rect::new(inner.x + left as i32, inner.bottom() - hairline as i32, right - left, hairline as u32);
+                        canvas.fill_rect(underline, &solid(style.foreground));
+                    }
+                    if focused {
+                        let x = inner.x + offset(caret) as i32;
+                        canvas.fill_rect(Rect::new(x, inner.y, hairline as u32, inner.height), &solid(style.foreground));
+                    }
+                }
+                WidgetKind::List { items, selected } => {
+                    canvas.fill_rect(bounds, &solid(style.background));
+                    canvas.save();
+                    canvas.clip_rect(bounds);
+                    let top = logical.y + inset as i32;
+                    for (index, item) in items.iter().enumerate() {
+                        let row = px(Rect::new(logical.x, top + (index as u32 * line) as i32, logical.width, line));
+                        if *selected == Some(index) {
+                            canvas.fill_rect(row, &solid(style.accent));
+                        }
+                        self.draw_text(canvas, item, Rect::new(inner.x, row.y, inner.width, row.height), style.foreground);
+                    }
+                    canvas.restore();
+                }
+            }
+            for child in &widget.children {
+                self.paint(*child, canvas);
+            }
+        }
+
+        // Repaints what changed into the window's buffer, sized by
+        // buffer_size(), and returns the areas to damage on the surface in
+        // buffer pixels
+        pub fn render(&mut self, buffer: &Buffer) -> Result<Vec<Rect>, &'static str> {
+            if (buffer.width(), buffer.height()) != self.buffer_size() {
+                return Err("Buffer size does not match the widget tree");
+            }
+            self.layout();
+            let viewport = self.viewport();
+            let damage: Vec<Rect> = std::mem::take(&mut self.damage)
+                .into_iter()
+                .map(|area| viewport.to_physical(area))
+                .collect();
+            buffer.draw(|canvas| {
+                for area in &damage {
+                    canvas.save();
+                    canvas.clip_rect(*area);
+                    self.paint(self.root, canvas);
+                    canvas.restore();
+                }
+            });
+            Ok(damage)
+        }
     }
 }
@@ -0,0 +1,187 @@
+// src/ui/vxwallpaper.rs
+
+pub mod vxwallpaper {
+    use vaelix_core::vxfs::vxfs::VXFS;
+    use vaelix_graphics::vegagx::vegagx::{Color, Image, Paint};
+    use vaelix_graphics::vxwin::vxwin::{Buffer, BufferStorage, Compositor, Rect, Viewport, WindowId};
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum WallpaperMode {
+        // Covers the output, cropping what does not fit
+        Fill,
+        // Shows all of the image, with the colour around it
+        Fit,
+        Stretch,
+        // At its own size in the middle
+        Center,
+        Tile,
+    }
+
+    impl WallpaperMode {
+        pub fn name(&self) -> &'static str {
+            match self {
+                WallpaperMode::Fill => "fill",
+                WallpaperMode::Fit => "fit",
+                WallpaperMode::Stretch => "stretch",
+                WallpaperMode::Center => "center",
+                WallpaperMode::Tile => "tile",
+            }
+        }
+
+        pub fn parse(name: &str) -> Result<Self, &'static str> {
+            match name {
+                "fill" => Ok(WallpaperMode::Fill),
+                "fit" => Ok(WallpaperMode::Fit),
+                "stretch" => Ok(WallpaperMode::Stretch),
+                "center" => Ok(WallpaperMode::Center),
+                "tile" => Ok(WallpaperMode::Tile),
+                _ => Err("Unknown wallpaper mode"),
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct WallpaperConfig {
+        pub image: Option<Arc<Image>>,
+        pub mode: WallpaperMode,
+        // Behind and around the image
+        pub color: u32,
+    }
+
+    impl Default for WallpaperConfig {
+        fn default() -> Self {
+            WallpaperConfig {
+                image: None,
+                mode: WallpaperMode::Fill,
+                color: 0xFF20_2830,
+            }
+        }
+    }
+
+    // Images are binary PPM files
+    pub fn load_image(path: &str) -> Result<Image, &'static str> {
+        let data = VXFS::new().read_bytes(path).map_err(|_| "Failed to read wallpaper")?;
+        Image::decode_ppm(&data)
+    }
+
+    // Draws the wallpaper for an output at its physical size
+    pub fn render(config: &WallpaperConfig, viewport: &Viewport) -> Buffer {
+        let (width, height) = viewport.physical_size();
+        let buffer = Buffer::new(width.max(1), height.max(1), BufferStorage::SharedMemory);
+        buffer.draw(|canvas| {
+            canvas.fill_rect(Rect::new(0, 0, width, height), &Paint::Solid(Color::argb(config.color)));
+            let Some(image) = &config.image else {
+                return;
+            };
+            let (image_width, image_height) = (image.width() as f32, image.height() as f32);
+            let (horizontal, vertical) = (width as f32 / image_width, height as f32 / image_height);
+            let (scale_x, scale_y) = match config.mode {
+                WallpaperMode::Fill => (horizontal.max(vertical), horizontal.max(vertical)),
+                WallpaperMode::Fit => (horizontal.min(vertical), horizontal.min(vertical)),
+                WallpaperMode::Stretch => (horizontal, vertical),
+                WallpaperMode::Center | WallpaperMode::Tile => (viewport.scale, viewport.scale),
+            };
+            let size = ((image_width * scale_x).round() as u32, (image_height * scale_y).round() as u32);
+            let scaled = if size == (image.width(), image.height()) { (**image).clone() } else { image.scaled(size.0, size.1) };
+            if config.mode != WallpaperMode::Tile {
+                let (x, y) = ((width as i32 - size.0 as i32) / 2, (height as i32 - size.1 as i32) / 2);
+                canvas.draw_image(&scaled, x, y, 255);
+                return;
+            }
+            for y in (0..height).step_by(size.1.max(1) as usize) {
+                for x in (0..width).step_by(size.0.max(1) as usize) {
+                    canvas.draw_image(&scaled, x as i32, y as i32, 255);
+                }
+            }
+        });
+        buffer
+    }
+
+    // A background window under everything else on each output. Outputs
+    // without their own configuration use the default one.
+    pub struct Wallpaper {
+        default: WallpaperConfig,
+        configs: BTreeMap<String, WallpaperConfig>,
+        backgrounds: BTreeMap<String, (WindowId, Viewport)>,
+        // Redraw every output on the next update
+        dirty: bool,
+    }
+
+    impl Default for Wallpaper {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl Wallpaper {
+        pub fn new() -> Self {
+            Wallpaper {
+                default: WallpaperConfig::default(),
+                configs: BTreeMap::new(),
+                backgrounds: BTreeMap::new(),
+                dirty: true,
+            }
+        }
+
+        pub fn set_default(&mut self, config: WallpaperConfig) {
+            self.default = config;
+            self.dirty = true;
+        }
+
+        // None goes back to the default
+        pub fn set_output(&mut self, name: &str, config: Option<WallpaperConfig>) {
+            match config {
+                Some(config) => self.configs.insert(name.to_string(), config),
+                None => self.configs.remove(name),
+            };
+            self.dirty = true;
+        }
+
+        pub fn config(&self, name: &str) -> &WallpaperConfig {
+            self.configs.get(name).unwrap_or(&self.default)
+        }
+
+        pub fn window(&self, name: &str) -> Option<WindowId> {
+            self.backgrounds.get(name).map(|(window, _)| *window)
+        }
+
+        // Follows the output layout, as from OutputManager::outputs()
+        pub fn update(&mut self, outputs: &[(&str, Viewport)], compositor: &mut Compositor) -> Result<(), &'static str> {
+            let gone: Vec<String> = self
+                .backgrounds
+                .keys()
+                .filter(|name| !outputs.iter().any(|(output, _)| output == name))
+                .cloned()
+                .collect();
+            for name in gone {
+                let (window, _) = self.backgrounds.remove(&name).unwrap();
+                compositor.destroy_window(window)?;
+            }
+            for (name, viewport) in outputs {
+                let current = self.backgrounds.get(*name).copied();
+                if !self.dirty && current.is_some_and(|(_, drawn)| drawn == *viewport) {
+                    continue;
+                }
+                let window = match current {
+                    Some((window, _)) => window,
+                    None => compositor.create_window(0, 0),
+                };
+                let buffer = render(self.config(name), viewport);
+                let (width, height) = (buffer.width(), buffer.height());
+                let area = viewport.area;
+                compositor.attach(window, buffer)?;
+                compositor.set_destination(window, (viewport.scale != 1.0).then_some((area.width, area.height)))?;
+                compositor.move_window(window, area.x, area.y)?;
+                compositor.damage(window, Rect::new(0, 0, width, height))?;
+                compositor.commit(window)?;
+                compositor.set_visible(window, true)?;
+                compositor.lower(window)?;
+                self.backgrounds.insert(name.to_string(), (window, *viewport));
+            }
+            self.dirty = false;
+            Ok(())
+        }
+    }
+}
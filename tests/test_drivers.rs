@@ -0,0 +1,1210 @@
+#[cfg(test)]
+pub mod tests {
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+    use vaelix_core::drivers::dma::dma::{self, CacheFlush, DmaBuffer, DmaController, DmaDirection, DmaOwner, FlushInstruction, MemoryType, SgTable, SimulatedCache, StreamingDma};
+    use vaelix_core::drivers::dw_i2c::dw_i2c::{scl_counts, DesignWareI2c, I2cSpeed};
+    use vaelix_core::drivers::gpio::gpio::{Direction, GpioController, IntelGpio, IntelGpioCommunity};
+    use vaelix_core::drivers::hpet::hpet::{self, ClockEventSource, Hpet, HpetTable};
+    use vaelix_core::drivers::i2c::i2c::{I2cBus, I2cMessage, I2cRegistry};
+    use vaelix_core::drivers::mmio::mmio::{MmioRegion, RegisterIo};
+    use vaelix_core::drivers::msix::msix::{self, Msix};
+    use vaelix_core::drivers::msr::msr::{MsrIo, MsrSpace};
+    use vaelix_core::drivers::nvme::nvme::{self, NvmeInterrupts};
+    use vaelix_core::drivers::pci::pci::{self, CAP_MSIX, CAP_POWER_MANAGEMENT};
+    use vaelix_core::drivers::pci_pm::pci_pm::{self, PciPm};
+    use vaelix_core::drivers::port::port::{PortIo, PortSpace};
+    use vaelix_core::drivers::resource::resource::{ResourceKind, ResourceRegistry};
+    use vaelix_core::drivers::rtl8168::rtl8168::Rtl8168Wake;
+    use vaelix_core::drivers::tpm::tpm::{extend_digest, Tpm2, TpmTransport, SRK_HANDLE};
+    use vaelix_core::irq::irq::{IrqAction, IrqController, IrqReturn, StormConfig, DEFAULT_THREAD_PRIORITY};
+    use vaelix_core::keyring::keyring::{KeyKind, Keyring, SealedKey, Secret, TpmSealer};
+    use vaelix_core::lockdep::lockdep;
+    use vaelix_core::metrics::metrics::Registry;
+    use vaelix_core::power::runtime::runtime::{DeviceClass, DevicePowerState, RuntimePm, RuntimePower};
+    use vaelix_core::power::wake::wake::{GpeBlock, WakeManager, WakeOnLan};
+    use vaelix_core::rcu::rcu::{self, Rcu};
+    use vaelix_core::sync::sync::{self, SpinLock};
+    use vaelix_core::topology::topology::{CoreType, LogicalCpu, Topology};
+    use vaelix_core::users::users::Credentials;
+    use vaelix_core::vxboot::vxboot::{self, measure_boot_components, BootControl, Slot, INITRAMFS_PCR, KERNEL_ALIGN, KERNEL_IMAGE_BASE, KERNEL_PCR, KERNEL_REGION_SIZE};
+
+    struct EchoRegisterBus {
+        registers: Mutex<[u8; 256]>,
+    }
+
+    impl I2cBus for EchoRegisterBus {
+        fn transfer(&self, messages: &mut [I2cMessage]) -> Result<(), &'static str> {
+            let mut registers = self.registers.lock().unwrap();
+            let mut pointer = 0usize;
+            for message in messages.iter_mut() {
+                match message {
+                    I2cMessage::Write { data, .. } => {
+                        pointer = data[0] as usize;
+                        for byte in &data[1..] {
+                            registers[pointer] = *byte;
+                            pointer += 1;
+                        }
+                    }
+                    I2cMessage::Read { buffer, .. } => {
+                        for byte in buffer.iter_mut() {
+                            *byte = registers[pointer];
+                            pointer += 1;
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    pub fn test_i2c_register_helpers() {
+        let bus = EchoRegisterBus {
+            registers: Mutex::new([0; 256]),
+        };
+        bus.write_register(0x50, 0x10, 0xAB).unwrap();
+        assert_eq!(bus.read_register(0x50, 0x10), Ok(0xAB));
+    }
+
+    #[test]
+    pub fn test_dw_i2c_scl_counts() {
+        // 133 MHz LPSS input clock
+        assert_eq!(scl_counts(133_000_000, I2cSpeed::Fast), (72, 172));
+        let (high, low) = scl_counts(100_000_000, I2cSpeed::Standard);
+        assert!(high >= 392 && low >= 469);
+    }
+
+    // A DesignWare controller with one device, at 0x50, which answers reads
+    // with consecutive bytes from 0xA0. Its FIFOs drain at once; commands
+    // to any other address abort as unacknowledged.
+    struct FakeDwI2c {
+        registers: MmioRegion,
+        commands: Arc<Mutex<Vec<u32>>>,
+        rx: Mutex<VecDeque<u8>>,
+    }
+
+    impl RegisterIo for FakeDwI2c {
+        fn read32(&self, offset: usize) -> u32 {
+            match offset {
+                // IC_DATA_CMD
+                0x10 => self.rx.lock().unwrap().pop_front().unwrap_or(0) as u32,
+                // IC_STATUS: TX FIFO not full and empty, RX FIFO not empty
+                0x70 => 0b110 | ((!self.rx.lock().unwrap().is_empty() as u32) << 3),
+                // IC_ENABLE_STATUS
+                0x9C => self.registers.read32(0x6C) & 1,
+                // IC_CLR_TX_ABRT
+                0x54 => {
+                    self.registers.write32(0x34, 0);
+                    0
+                }
+                _ => self.registers.read32(offset),
+            }
+        }
+
+        fn write32(&self, offset: usize, value: u32) {
+            if offset != 0x10 {
+                self.registers.write32(offset, value);
+                return;
+            }
+            let mut commands = self.commands.lock().unwrap();
+            commands.push(value);
+            if self.registers.read32(0x04) != 0x50 {
+                // TX_ABRT from ABRT_7BIT_ADDR_NOACK
+                self.registers.write32(0x34, 1 << 6);
+                self.registers.write32(0x80, 1);
+            } else if value & (1 << 8) != 0 {
+                let reads = commands.iter().filter(|command| *command & (1 << 8) != 0).count();
+                self.rx.lock().unwrap().push_back(0xA0 + reads as u8 - 1);
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_dw_i2c_transfers() {
+        let registers = MmioRegion::new(0xFE02_0000, 0x400);
+        // A two-entry TX FIFO, so reads are requested a pair at a time
+        registers.write32(0xF4, 1 << 16);
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        let fake = FakeDwI2c { registers: registers.clone(), commands: Arc::clone(&commands), rx: Mutex::new(VecDeque::new()) };
+        let bus = DesignWareI2c::new(fake, 133_000_000, I2cSpeed::Fast);
+        bus.initialize().unwrap();
+        assert_eq!(registers.read32(0x00), 0x65);
+        assert_eq!((registers.read32(0x1C), registers.read32(0x20)), (72, 172));
+
+        // The register pointer, then a repeated start into reads with a stop on the last
+        let mut buffer = [0u8; 3];
+        bus.write_read(0x50, &[0x10], &mut buffer).unwrap();
+        assert_eq!(buffer, [0xA0, 0xA1, 0xA2]);
+        assert_eq!(*commands.lock().unwrap(), [0x10, 0x500, 0x100, 0x300]);
+        assert_eq!((registers.read32(0x04), registers.read32(0x6C)), (0x50, 0));
+
+        assert_eq!(bus.write(0x51, &[0x01, 0x02]), Err("I2C address not acknowledged"));
+        bus.write(0x50, &[0x01, 0x02]).unwrap();
+        assert_eq!(commands.lock().unwrap()[6..], [0x01, 0x202]);
+
+        // Zero-length messages never reach the controller
+        commands.lock().unwrap().clear();
+        assert_eq!(bus.write(0x50, &[]), Err("Zero-length I2C messages are not supported"));
+        assert_eq!(bus.write_read(0x50, &[0x10], &mut []), Err("Zero-length I2C messages are not supported"));
+        assert!(commands.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    pub fn test_intel_gpio_output() {
+        let regs = MmioRegion::new(0xFD6E_0000, 0x1000);
+        let gpio = IntelGpio::new(
+            regs.clone(),
+            IntelGpioCommunity {
+                padcfg_offset: 0x600,
+                pad_ownership_offset: 0x20,
+                gpi_is_offset: 0x100,
+                gpi_ie_offset: 0x120,
+                pin_count: 48,
+            },
+        );
+        gpio.set_direction(5, Direction::Output).unwrap();
+        gpio.set(5, true).unwrap();
+        assert_eq!(gpio.get(5), Ok(true));
+        assert_eq!(regs.read32(0x600 + 5 * 0x10) & 1, 1);
+        assert!(gpio.set(48, true).is_err());
+    }
+
+    // Understands just enough of TPM2_Startup, PCR_Extend and PCR_Read,
+    // and of Create, Load, Unseal and FlushContext for one sealed object
+    // at a time, "encrypted" by flipping its bits
+    struct FakeTpm {
+        pcrs: Mutex<[[u8; 32]; 24]>,
+        loaded: Mutex<Option<Vec<u8>>>,
+    }
+
+    impl FakeTpm {
+        fn response(body: &[u8]) -> Vec<u8> {
+            let mut response = vec![0x80, 0x01];
+            response.extend_from_slice(&((10 + body.len()) as u32).to_be_bytes());
+            response.extend_from_slice(&0u32.to_be_bytes());
+            response.extend_from_slice(body);
+            response
+        }
+    }
+
+    impl TpmTransport for FakeTpm {
+        fn transmit(&self, command: &[u8]) -> Result<Vec<u8>, &'static str> {
+            let code = u32::from_be_bytes(command[6..10].try_into().unwrap());
+            let mut pcrs = self.pcrs.lock().unwrap();
+            match code {
+                0x144 => Ok(Self::response(&[])),
+                0x182 => {
+                    let pcr = u32::from_be_bytes(command[10..14].try_into().unwrap()) as usize;
+                    // handle, auth size, 9 byte password session, count, algorithm
+                    let digest: [u8; 32] = command[33..65].try_into().unwrap();
+                    pcrs[pcr] = extend_digest(&pcrs[pcr], &digest);
+                    Ok(Self::response(&[0, 0, 0, 0]))
+                }
+                0x17E => {
+                    let bitmap = &command[17..20];
+                    let pcr = (0..24).find(|pcr| bitmap[pcr / 8] & (1 << (pcr % 8)) != 0).unwrap();
+                    let mut body = vec![0, 0, 0, 1];
+                    body.extend_from_slice(&command[10..20]);
+                    body.extend_from_slice(&[0, 0, 0, 1, 0, 32]);
+                    body.extend_from_slice(&pcrs[pcr]);
+                    Ok(Self::response(&body))
+                }
+                0x153 => {
+                    // parent, session, then the sensitive area's size and
+                    // empty auth before the data
+                    let length = u16::from_be_bytes(command[31..33].try_into().unwrap()) as usize;
+                    let private: Vec<u8> = command[33..33 + length].iter().map(|byte| !byte).collect();
+                    let public = &command[35 + length..];
+                    let public = &public[..u16::from_be_bytes(public[..2].try_into().unwrap()) as usize];
+                    let mut body = vec![0; 4];
+                    body.extend_from_slice(&(private.len() as u16).to_be_bytes());
+                    body.extend_from_slice(&private);
+                    body.extend_from_slice(&(public.len() as u16).to_be_bytes());
+                    body.extend_from_slice(public);
+                    Ok(Self::response(&body))
+                }
+                0x157 => {
+                    let length = u16::from_be_bytes(command[27..29].try_into().unwrap()) as usize;
+                    *self.loaded.lock().unwrap() = Some(command[29..29 + length].to_vec());
+                    Ok(Self::response(&0x8000_0000u32.to_be_bytes()))
+                }
+                0x15E => {
+                    let private = self.loaded.lock().unwrap().clone().ok_or("Nothing loaded")?;
+                    let mut body = vec![0; 4];
+                    body.extend_from_slice(&(private.len() as u16).to_be_bytes());
+                    body.extend(private.iter().map(|byte| !byte));
+                    Ok(Self::response(&body))
+                }
+                0x165 => {
+                    *self.loaded.lock().unwrap() = None;
+                    Ok(Self::response(&[]))
+                }
+                _ => Err("Unsupported command"),
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_tpm_measured_boot_replay() {
+        let tpm = Tpm2::new(FakeTpm {
+            pcrs: Mutex::new([[0; 32]; 24]),
+            loaded: Mutex::new(None),
+        });
+        let log = measure_boot_components(&tpm, b"kernel image", b"initramfs image").unwrap();
+        assert_eq!(log.events().len(), 2);
+        assert_eq!(tpm.pcr_read(KERNEL_PCR).unwrap(), log.replay(KERNEL_PCR));
+        assert_eq!(tpm.pcr_read(INITRAMFS_PCR).unwrap(), log.replay(INITRAMFS_PCR));
+        assert_ne!(log.replay(KERNEL_PCR), [0; 32]);
+        assert!(tpm.pcr_extend(24, &[0; 32]).is_err());
+
+        // Booting measures the same images again on top
+        let log = vxboot::measured_boot(&tpm, b"kernel image", b"initramfs image").unwrap();
+        assert_eq!(tpm.pcr_read(KERNEL_PCR).unwrap(), extend_digest(&log.replay(KERNEL_PCR), &log.events()[0].digest));
+        // No TPM behind the TIS window when hosted
+        assert!(vxboot::probe_tpm().is_none());
+    }
+
+    #[test]
+    pub fn test_tpm_sealed_keys() {
+        let tpm = Arc::new(Tpm2::new(FakeTpm {
+            pcrs: Mutex::new([[0; 32]; 24]),
+            loaded: Mutex::new(None),
+        }));
+        let user = Credentials { uid: 1000, gid: 1000, groups: Vec::new() };
+        let mut keyring = Keyring::new();
+        let id = keyring.add(&user, KeyKind::Encryption, "vxfs:home", Secret::new(vec![7; 32]), 0o600).unwrap();
+        assert_eq!(keyring.seal(&user, id).unwrap_err(), "No sealer for keys");
+        keyring.set_sealer(Box::new(TpmSealer::new(Arc::clone(&tpm), SRK_HANDLE)));
+        let sealed = keyring.seal(&user, id).unwrap();
+        assert!(!sealed.blob.windows(32).any(|window| window == [7; 32]));
+        let other = Credentials { uid: 1001, gid: 1001, groups: Vec::new() };
+        assert!(keyring.seal(&other, id).is_err());
+
+        // As read back from disk at the next boot
+        let sealed = SealedKey::parse(&sealed.entry()).unwrap();
+        assert_eq!(sealed.description, "vxfs:home");
+        let mut restored = Keyring::new();
+        restored.set_sealer(Box::new(TpmSealer::new(tpm, SRK_HANDLE)));
+        let id = restored.unseal(&sealed).unwrap();
+        assert_eq!(restored.read(&user, id).unwrap().expose(), [7; 32]);
+        assert_eq!(restored.info(id).unwrap().owner, 1000);
+        assert!(restored.read(&other, id).is_err());
+        let mut corrupt = sealed.clone();
+        corrupt.blob.pop();
+        assert!(restored.unseal(&corrupt).is_err());
+    }
+
+    #[test]
+    pub fn test_kernel_address_randomization() {
+        let size = 5 * 1024 * 1024;
+        let offset = vxboot::kaslr_offset(size).unwrap();
+        assert_eq!(offset % KERNEL_ALIGN, 0);
+        assert!(offset + size <= KERNEL_REGION_SIZE);
+        assert_eq!(vxboot::kaslr_offset(KERNEL_REGION_SIZE).unwrap(), 0);
+        assert!(vxboot::kaslr_offset(KERNEL_REGION_SIZE + 1).is_err());
+
+        // Absolute addresses in the image move with it
+        let mut image = vec![0u8; 24];
+        image[8..16].copy_from_slice(&(KERNEL_IMAGE_BASE + 0x1234).to_le_bytes());
+        vxboot::relocate_kernel(&mut image, &[8], 2 * KERNEL_ALIGN).unwrap();
+        assert_eq!(u64::from_le_bytes(image[8..16].try_into().unwrap()), KERNEL_IMAGE_BASE + 2 * KERNEL_ALIGN + 0x1234);
+        assert!(vxboot::relocate_kernel(&mut image, &[20], KERNEL_ALIGN).is_err());
+        assert!(vxboot::relocate_kernel(&mut image, &[u64::MAX], KERNEL_ALIGN).is_err());
+    }
+
+    #[test]
+    pub fn test_ab_slot_rollback() {
+        let root = std::env::temp_dir().join(format!("vaelix-slots-{}", std::process::id()));
+        let root = root.to_str().unwrap();
+        let mut control = BootControl::load(root).unwrap();
+        assert_eq!(control.active(), Some(Slot::A));
+        control.mark_unbootable(Slot::B);
+        control.set_active(Slot::B);
+        control.save(root).unwrap();
+
+        // The new slot goes first until its tries run out, then A is back
+        for _ in 0..3 {
+            let mut control = BootControl::load(root).unwrap();
+            assert_eq!(control.choose_boot_slot().unwrap(), Slot::B);
+            control.save(root).unwrap();
+        }
+        let mut control = BootControl::load(root).unwrap();
+        assert_eq!(control.choose_boot_slot().unwrap(), Slot::A);
+        assert_eq!(control.state(Slot::A).tries_remaining, 0);
+
+        // One that is marked successful in time stays
+        control.set_active(Slot::B);
+        assert_eq!(control.choose_boot_slot().unwrap(), Slot::B);
+        control.mark_successful(Slot::B);
+        for _ in 0..5 {
+            assert_eq!(control.choose_boot_slot().unwrap(), Slot::B);
+        }
+        assert_eq!(BootControl::parse(&control.entries()).unwrap().entries(), control.entries());
+        assert!(BootControl::parse("a 15 0 1\n").is_err());
+        assert!(BootControl::parse("a 16 0 1\nb 0 0 0\n").is_err());
+        control.mark_unbootable(Slot::A);
+        control.mark_unbootable(Slot::B);
+        assert!(control.choose_boot_slot().is_err());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    // GPE0 block at 0x420: two status bytes (write one to clear), then two
+    // enable bytes
+    #[derive(Clone)]
+    struct GpePorts {
+        ports: Arc<Mutex<[u8; 4]>>,
+    }
+
+    impl PortIo for GpePorts {
+        fn inb(&self, port: u16) -> u8 {
+            self.ports.lock().unwrap()[(port - 0x420) as usize]
+        }
+
+        fn outb(&self, port: u16, value: u8) {
+            let index = (port - 0x420) as usize;
+            let mut ports = self.ports.lock().unwrap();
+            ports[index] = if index < 2 { ports[index] & !value } else { value };
+        }
+    }
+
+    #[test]
+    pub fn test_rtl8168_wake_filters_and_gpe_arming() {
+        let registers = MmioRegion::new(0, 0x100);
+        let nic = Rtl8168Wake::new(registers.clone());
+        let config = WakeOnLan {
+            magic_packet: true,
+            link_change: true,
+            ..WakeOnLan::default()
+        };
+        assert!(config.is_subset_of(&nic.capabilities()));
+        nic.configure(config);
+        assert_eq!(nic.configuration(), config);
+        // Config1 PM enable, Config3 magic + link, Config5 LanWake; Cfg9346 relocked
+        assert_eq!(registers.read8(0x52) & 0x01, 0x01);
+        assert_eq!(registers.read8(0x59), 0x30);
+        assert_eq!(registers.read8(0x56), 0x02);
+        assert_eq!(registers.read8(0x50), 0x00);
+        nic.configure(WakeOnLan::default());
+        assert_eq!((registers.read8(0x52), registers.read8(0x59)), (0, 0));
+
+        let ports = GpePorts {
+            ports: Arc::new(Mutex::new([0x01, 0, 0, 0])),
+        };
+        assert!(GpeBlock::new(ports.clone(), 0x420, 3).is_err());
+        let mut wake = WakeManager::new(GpeBlock::new(ports.clone(), 0x420, 4).unwrap());
+        wake.register("eth0", 0x08).unwrap();
+        wake.register("lid", 0x00).unwrap();
+        assert!(wake.register("wlan0", 16).is_err());
+
+        // Arming clears the stale lid event and enables both GPEs
+        wake.prepare_suspend().unwrap();
+        assert!(wake.is_armed());
+        assert!(!wake.gpe_block().is_pending(0x00).unwrap());
+        assert!(wake.gpe_block().is_enabled(0x08).unwrap() && wake.gpe_block().is_enabled(0x00).unwrap());
+
+        // The NIC asserts PME while asleep
+        ports.ports.lock().unwrap()[1] |= 0x01;
+        assert_eq!(wake.resume().unwrap(), vec!["eth0".to_string()]);
+        assert!(!wake.gpe_block().is_pending(0x08).unwrap());
+        assert!(!wake.gpe_block().is_enabled(0x08).unwrap());
+    }
+
+    fn lockdep_report(code: impl FnOnce()) -> String {
+        let payload = std::panic::catch_unwind(std::panic::AssertUnwindSafe(code)).expect_err("Expected a lockdep report");
+        payload.downcast_ref::<String>().cloned().unwrap()
+    }
+
+    #[test]
+    pub fn test_lockdep_lock_ordering() {
+        // Release builds do not check
+        if !lockdep::ENABLED {
+            return;
+        }
+        let table = sync::Mutex::new("lockdep.table", 0);
+        let entry = sync::Mutex::new("lockdep.entry", 0);
+        let cache = sync::Mutex::new("lockdep.cache", 0);
+        {
+            let _table = table.lock();
+            let _entry = entry.lock();
+            assert_eq!(lockdep::held(), ["lockdep.table", "lockdep.entry"]);
+        }
+        assert!(lockdep::held().is_empty());
+        {
+            let _entry = entry.lock();
+            *cache.lock() += 1;
+        }
+        assert!(lockdep::dependencies().contains(&("lockdep.table", "lockdep.entry")));
+        assert!(lockdep::dependencies().contains(&("lockdep.entry", "lockdep.cache")));
+
+        // cache then table closes table -> entry -> cache, even though
+        // the three never deadlocked
+        let report = lockdep_report(|| {
+            let _cache = cache.lock();
+            let _table = table.lock();
+        });
+        assert!(report.starts_with("lockdep: possible circular locking taking lockdep.table while holding lockdep.cache"), "{}", report);
+        assert!(report.contains("lockdep.table -> lockdep.entry -> lockdep.cache"));
+        // This acquisition and where the other order was seen, symbolized
+        assert!(report.contains("this acquisition:\n    ") && report.contains("lockdep.table -> lockdep.entry first at:\n    "));
+        assert!(lockdep::held().is_empty());
+        assert!(!lockdep::dependencies().contains(&("lockdep.cache", "lockdep.table")));
+        assert_eq!(*table.lock(), 0);
+
+        // A lock of the same class, as two siblings without a nesting order
+        let left = SpinLock::new("lockdep.sibling", ());
+        let right = SpinLock::new("lockdep.sibling", ());
+        let report = lockdep_report(|| {
+            let _left = left.lock_irqsave();
+            let _right = right.lock_irqsave();
+        });
+        assert!(report.starts_with("lockdep: possible recursive locking of lockdep.sibling"));
+        assert!(report.contains("already held since:\n    "));
+        assert!(right.try_lock().is_some());
+
+        // Sleeping where it cannot
+        let report = lockdep_report(|| {
+            let _left = left.lock_irqsave();
+            let _table = table.lock();
+        });
+        assert!(report.starts_with("lockdep: sleeping lock lockdep.table taken with interrupts disabled"));
+        let report = lockdep_report(|| sync::hardirq(|| *table.lock() += 1));
+        assert!(report.starts_with("lockdep: sleeping lock lockdep.table taken in an interrupt handler"));
+        assert!(!sync::in_interrupt() && !sync::irqs_disabled());
+
+        // Taken by a handler, so elsewhere only with interrupts off
+        let counter = SpinLock::new("lockdep.irq_counter", 0);
+        sync::hardirq(|| *counter.lock() += 1);
+        *counter.lock_irqsave() += 1;
+        let report = lockdep_report(|| *counter.lock() += 1);
+        assert!(report.starts_with("lockdep: inconsistent lock state: lockdep.irq_counter taken with interrupts enabled, but also taken in an interrupt handler"));
+        assert!(report.contains("taken in an interrupt handler at:\n    "));
+        assert_eq!(*counter.lock_irqsave(), 2);
+
+        // Nor may a handler's lock be held while taking one that is not
+        // safe against it
+        let status = SpinLock::new("lockdep.irq_status", ());
+        drop(status.lock());
+        let report = lockdep_report(|| {
+            let _counter = counter.lock_irqsave();
+            let _status = status.lock();
+        });
+        assert!(report.starts_with("lockdep: interrupt-unsafe lock lockdep.irq_status taken while holding interrupt-safe lockdep.irq_counter"));
+    }
+
+    #[test]
+    pub fn test_rcu_read_copy_update() {
+        let shared = Arc::new(Rcu::new("test.rcu", vec![1]));
+        let before = shared.read();
+        let updater = {
+            let shared = Arc::clone(&shared);
+            std::thread::spawn(move || shared.update(|values| {
+                values.push(2);
+                Ok(values.len())
+            }))
+        };
+        // New readers see the update as soon as it is published, while the
+        // updater waits for the old one to be done with
+        while *shared.read() != [1, 2] {
+            std::thread::yield_now();
+        }
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(!updater.is_finished());
+        assert_eq!(*before, [1]);
+        drop(before);
+        assert_eq!(updater.join().unwrap(), Ok(2));
+
+        // A failed update publishes nothing
+        assert_eq!(shared.update(|values| if values.len() > 1 { Err("Too many") } else { Ok(()) }), Err("Too many"));
+        shared.replace(vec![3]);
+        assert_eq!(*shared.read(), [3]);
+
+        // Callbacks wait for the readers of the time they were queued
+        let ran = Arc::new(Mutex::new(false));
+        let guard = rcu::read_lock();
+        {
+            let ran = Arc::clone(&ran);
+            rcu::call_rcu(move || *ran.lock().unwrap() = true);
+        }
+        assert!(rcu::in_read_section());
+        rcu::process_callbacks();
+        assert!(!*ran.lock().unwrap());
+        drop(guard);
+        assert!(!rcu::in_read_section());
+        rcu::rcu_barrier();
+        assert!(*ran.lock().unwrap());
+
+        // Waiting for a grace period from inside one never ends
+        let payload = std::panic::catch_unwind(|| {
+            let _guard = rcu::read_lock();
+            rcu::synchronize_rcu();
+        })
+        .expect_err("Expected synchronize_rcu() to refuse");
+        assert!(payload.downcast_ref::<&str>().unwrap().contains("read-side critical section"));
+        assert!(!rcu::in_read_section());
+
+        // Lookups in the driver registries do not take a lock
+        let registry = I2cRegistry::new();
+        registry.register("i2c0", Arc::new(EchoRegisterBus { registers: Mutex::new([0; 256]) })).unwrap();
+        let _guard = rcu::read_lock();
+        assert!(registry.get("i2c0").is_some());
+        assert_eq!(registry.names(), ["i2c0"]);
+    }
+
+    #[test]
+    pub fn test_lockdep_sleeping_in_rcu_read_section() {
+        if !lockdep::ENABLED {
+            return;
+        }
+        let mutex = sync::Mutex::new("lockdep.rcu_sleeper", 0);
+        let report = lockdep_report(|| {
+            let _guard = rcu::read_lock();
+            let _value = mutex.lock();
+        });
+        assert!(report.starts_with("lockdep: sleeping lock lockdep.rcu_sleeper taken in an RCU read-side critical section"));
+        // Outside one it is fine
+        drop(mutex.lock());
+    }
+
+    #[test]
+    pub fn test_threaded_interrupts() {
+        let controller = IrqController::new(4);
+        let ran = Arc::new(Mutex::new(Vec::new()));
+        let record = |name: &'static str| {
+            let ran = Arc::clone(&ran);
+            move |vector: u8| ran.lock().unwrap().push(format!("{} {:#x}", name, vector))
+        };
+        assert_eq!(controller.request(0x0E, IrqAction::new("fault", |_| IrqReturn::Handled)), Err("Vector reserved for CPU exceptions"));
+        assert_eq!(controller.request(0x40, IrqAction::threaded("audio", 0, record("audio"))), Err("Invalid IRQ thread priority"));
+        assert_eq!(controller.request(0x40, IrqAction::new("nvme", |_| IrqReturn::Handled).affinity(&[4])), Err("Invalid IRQ affinity"));
+
+        // Vectors spread over the CPUs, unless told where to go
+        controller.request(0x40, IrqAction::new("nvme", |_| IrqReturn::Handled)).unwrap();
+        controller.request(0x41, IrqAction::threaded("audio", 80, record("audio"))).unwrap();
+        let wake = Arc::new(Mutex::new(true));
+        let waking = Arc::clone(&wake);
+        controller
+            .request(0x42, IrqAction::new("eth0", move |_| if *waking.lock().unwrap() { IrqReturn::WakeThread } else { IrqReturn::Handled }).thread(DEFAULT_THREAD_PRIORITY, record("eth0")))
+            .unwrap();
+        controller.request(0x43, IrqAction::new("i8042", |_| IrqReturn::Handled).affinity(&[0])).unwrap();
+        assert_eq!([0x40, 0x41, 0x42, 0x43].map(|vector| controller.target(vector)), [Some(0), Some(1), Some(2), Some(0)]);
+
+        // Devices share a vector, each asked in turn
+        controller.request(0x40, IrqAction::new("ahci", |_| IrqReturn::None)).unwrap();
+        assert_eq!(controller.request(0x40, IrqAction::new("ahci", |_| IrqReturn::None)), Err("Handler already registered"));
+        assert_eq!(controller.names(0x40), ["nvme", "ahci"]);
+        assert_eq!(controller.handle(0x40, Instant::now()), IrqReturn::Handled);
+
+        // Threads only run on their vector's CPU, once however many times
+        // they were woken
+        assert_eq!(controller.handle(0x41, Instant::now()), IrqReturn::WakeThread);
+        assert_eq!(controller.handle(0x41, Instant::now()), IrqReturn::WakeThread);
+        assert_eq!(controller.run_threads(0), 0);
+        assert_eq!(controller.run_threads(1), 1);
+        assert_eq!(controller.run_threads(1), 0);
+        assert_eq!(*ran.lock().unwrap(), ["audio 0x41"]);
+
+        // Moved next to the audio thread, the network thread runs after it
+        assert_eq!(controller.set_affinity(0x42, &[1]), Ok(1));
+        assert_eq!(controller.set_affinity(0x44, &[1]), Err("Vector not in use"));
+        assert_eq!(controller.handle(0x42, Instant::now()), IrqReturn::WakeThread);
+        *wake.lock().unwrap() = false;
+        assert_eq!(controller.handle(0x42, Instant::now()), IrqReturn::Handled);
+        controller.handle(0x41, Instant::now());
+        assert_eq!(controller.run_threads(1), 2);
+        assert_eq!(ran.lock().unwrap()[1..], ["audio 0x41", "eth0 0x42"]);
+
+        // Interrupts nobody claims are counted
+        controller.request(0x50, IrqAction::new("gpio", |_| IrqReturn::None)).unwrap();
+        assert_eq!(controller.handle(0x50, Instant::now()), IrqReturn::None);
+        assert_eq!(controller.unhandled(0x50), 1);
+        assert_eq!(controller.handle(0x51, Instant::now()), IrqReturn::None);
+        assert_eq!(controller.count(0x41), 3);
+
+        let report = controller.report();
+        assert!(report.lines().next().unwrap().ends_with("CPU3"), "{}", report);
+        assert!(report.contains("  64:          1          0          0          0  CPU0  nvme, ahci\n"), "{}", report);
+
+        // With CPUs 2 and 3 gone, everything is spread over the other two
+        controller.set_online_cpus(2);
+        for vector in [0x40, 0x41, 0x42, 0x43, 0x50] {
+            assert!(controller.target(vector).unwrap() < 2);
+        }
+        assert_eq!(controller.target(0x42), Some(1));
+        assert_eq!(controller.target(0x43), Some(0));
+
+        controller.free(0x40, "nvme").unwrap();
+        assert_eq!(controller.free(0x40, "nvme"), Err("Handler not found"));
+        controller.free(0x40, "ahci").unwrap();
+        assert_eq!(controller.target(0x40), None);
+    }
+
+    // A function with power management at 0x40 and an MSI-X table of
+    // entries entries at 0x2000 in BAR 0
+    fn msix_function(entries: u16) -> (MmioRegion, MmioRegion) {
+        let config = MmioRegion::new(0, 4096);
+        config.write16(pci::STATUS, pci::STATUS_CAPABILITIES);
+        config.write8(pci::CAPABILITIES_POINTER, 0x40);
+        config.write16(0x40, 0x5000 | CAP_POWER_MANAGEMENT as u16);
+        config.write16(0x50, CAP_MSIX as u16);
+        config.write16(0x52, (entries - 1) | 1 << 14);
+        config.write32(0x54, 0x2000);
+        let bar = MmioRegion::new(0, 0x3000);
+        for entry in 0..entries as usize {
+            bar.write32(0x2000 + entry * 16 + 12, 1);
+        }
+        (config, bar)
+    }
+
+    #[test]
+    pub fn test_nvme_msix_queue_vectors() {
+        let (config, bar) = msix_function(4);
+        assert_eq!(pci::capabilities(&config), [(CAP_POWER_MANAGEMENT, 0x40), (CAP_MSIX, 0x50)]);
+        let table = Msix::probe(config.clone(), bar.clone()).unwrap();
+        assert_eq!(table.table_size(), 4);
+        assert_eq!(table.table_location(), (0, 0x2000));
+        assert_eq!(msix::message(3, 0x41), Ok((0xFEE0_3000, 0x41)));
+        assert_eq!(msix::message(0x100, 0x41), Err("APIC ID needs interrupt remapping"));
+        assert_eq!(table.program(4, 0, 0x41), Err("MSI-X entry out of range"));
+        assert!(Msix::probe(MmioRegion::new(0, 4096), bar.clone()).is_err());
+
+        // Six CPUs, two of them E-cores, APIC IDs twice their number
+        let cpus = (0..6)
+            .map(|cpu| LogicalCpu { cpu, apic_id: cpu as u32 * 2, package: 0, core: cpu as u32, thread: 0, core_type: if cpu < 2 { CoreType::Efficiency } else { CoreType::Performance } })
+            .collect();
+        let topology = Topology { cpus, caches: Vec::new(), base_frequency_mhz: None, max_frequency_mhz: None };
+        let irq: &'static IrqController = Box::leak(Box::new(IrqController::new(6)));
+        let completed = Arc::new(Mutex::new(Vec::new()));
+        let completion = {
+            let completed = Arc::clone(&completed);
+            Arc::new(move |queue: u16| completed.lock().unwrap().push(queue))
+        };
+
+        // Eight queues asked for, three fit beside the admin queue, each on
+        // a P-core with its own vector
+        let interrupts = NvmeInterrupts::setup("nvme0", table, irq, &topology, 8, completion).unwrap();
+        assert_eq!(interrupts.io_queues(), 3);
+        let queues = interrupts.queues().to_vec();
+        assert_eq!(queues.iter().map(|queue| (queue.queue, queue.entry, queue.cpu)).collect::<Vec<_>>(), [(0, 0, 2), (1, 1, 2), (2, 2, 3), (3, 3, 4)]);
+        for queue in &queues {
+            assert_eq!(irq.target(queue.vector), Some(queue.cpu));
+            assert_eq!(irq.names(queue.vector), [format!("nvme0q{}", queue.queue)]);
+            assert_eq!(interrupts.msix().destination(queue.entry as usize), Ok((queue.cpu as u32 * 2, queue.vector)));
+            assert_eq!(interrupts.msix().is_masked(queue.entry as usize), Ok(false));
+        }
+        assert_eq!(queues.iter().map(|queue| queue.vector).collect::<std::collections::BTreeSet<_>>().len(), 4);
+        assert!(interrupts.msix().is_enabled());
+        assert_ne!(config.read16(pci::COMMAND) & pci::COMMAND_INTX_DISABLE, 0);
+        assert_eq!(config.read16(0x52) & 1 << 14, 0);
+
+        // Completions are handled on the queue's own vector
+        assert_eq!(irq.handle(queues[2].vector, Instant::now()), IrqReturn::Handled);
+        assert_eq!(irq.handle(queues[3].vector, Instant::now()), IrqReturn::Handled);
+        assert_eq!(*completed.lock().unwrap(), [2, 3]);
+        assert_eq!([2, 3, 4, 0, 5].map(|cpu| interrupts.queue_for_cpu(cpu)), [1, 2, 3, 1, 3]);
+
+        let command = nvme::create_io_cq_command(7, queues[2], 1024, 0x1_2345_6000);
+        assert_eq!(command[0], 0x0007_0005);
+        assert_eq!((command[6], command[7]), (0x2345_6000, 1));
+        assert_eq!(command[10], 1023 << 16 | 2);
+        assert_eq!(command[11], 2 << 16 | 0b11);
+
+        // Dropped, the vectors are free and the table off
+        drop(interrupts);
+        assert!(queues.iter().all(|queue| irq.target(queue.vector).is_none()));
+        assert_eq!(config.read16(0x52) & 1 << 15, 0);
+    }
+
+    #[test]
+    pub fn test_interrupt_storm_mitigation() {
+        let irq: &'static IrqController = Box::leak(Box::new(IrqController::new(2)));
+        irq.set_storm_config(StormConfig { threshold: 100, window: Duration::from_millis(10), poll_interval: Duration::from_millis(5), unmask_after: Duration::from_millis(100) });
+        let serviced = Arc::new(Mutex::new(0));
+        let masked = Arc::new(Mutex::new(Vec::new()));
+        let action = {
+            let serviced = Arc::clone(&serviced);
+            let masked = Arc::clone(&masked);
+            IrqAction::new("iwlwifi", move |_| {
+                *serviced.lock().unwrap() += 1;
+                IrqReturn::Handled
+            })
+            .mask(move |mask| masked.lock().unwrap().push(mask))
+        };
+        irq.request(0x60, action).unwrap();
+        irq.request(0x61, IrqAction::new("xhci", |_| IrqReturn::Handled)).unwrap();
+
+        // A steady 100 a window is fine, however long it goes on
+        let start = Instant::now();
+        let at = |millis: u64| start + Duration::from_millis(millis);
+        for window in 0..5 {
+            for _ in 0..100 {
+                irq.handle(0x61, at(window * 10));
+            }
+        }
+        assert!(!irq.is_masked(0x61));
+
+        // One more in a window is a storm: masked at the device and what
+        // still arrives dropped
+        for _ in 0..101 {
+            assert_eq!(irq.handle(0x60, at(1)), IrqReturn::Handled);
+        }
+        assert!(irq.is_masked(0x60));
+        assert_eq!(irq.storms(0x60), 1);
+        assert_eq!(*masked.lock().unwrap(), [true]);
+        assert_eq!(irq.handle(0x60, at(2)), IrqReturn::None);
+        assert_eq!(*serviced.lock().unwrap(), 101);
+        assert_eq!(irq.count(0x60), 102);
+        assert_eq!(irq.unhandled(0x60), 0);
+        assert!(irq.report().contains("iwlwifi  (masked, storm)"));
+
+        // Polled every interval while masked
+        assert_eq!(irq.poll(at(3)), 0);
+        assert_eq!(irq.poll(at(6)), 1);
+        assert_eq!(irq.poll(at(8)), 0);
+        assert_eq!(irq.poll(at(11)), 1);
+        assert_eq!(*serviced.lock().unwrap(), 103);
+
+        // Unmasked after a while, then masked for twice as long when it
+        // storms again
+        assert_eq!(irq.poll(at(101)), 0);
+        assert!(!irq.is_masked(0x60));
+        assert_eq!(*masked.lock().unwrap(), [true, false]);
+        for _ in 0..101 {
+            irq.handle(0x60, at(105));
+        }
+        assert_eq!(irq.storms(0x60), 2);
+        irq.poll(at(205));
+        assert!(irq.is_masked(0x60));
+        irq.poll(at(305));
+        assert!(!irq.is_masked(0x60));
+        assert!(!irq.is_masked(0x61));
+
+        let registry = Registry::new();
+        irq.register_metrics(&registry).unwrap();
+        assert!(registry.render().contains("vaelix_irq_storms_total{vector=\"96\",device=\"iwlwifi\"} 2\n"), "{}", registry.render());
+    }
+
+    // An HPET whose main counter moves on by step ticks each time it is read
+    struct TickingHpet {
+        registers: MmioRegion,
+        step: u32,
+    }
+
+    impl RegisterIo for TickingHpet {
+        fn read32(&self, offset: usize) -> u32 {
+            let value = self.registers.read32(offset);
+            if offset == 0xF0 && self.registers.read32(0x10) & 1 != 0 {
+                let (low, carry) = value.overflowing_add(self.step);
+                self.registers.write32(0xF0, low);
+                self.registers.write32(0xF4, self.registers.read32(0xF4) + carry as u32);
+            }
+            value
+        }
+
+        fn write32(&self, offset: usize, value: u32) {
+            self.registers.write32(offset, value);
+        }
+    }
+
+    #[test]
+    pub fn test_hpet_calibration_and_timers() {
+        let mut table = vec![0u8; 56];
+        table[0..4].copy_from_slice(b"HPET");
+        table[4..8].copy_from_slice(&56u32.to_le_bytes());
+        table[36..40].copy_from_slice(&0x8086_A201u32.to_le_bytes());
+        table[44..52].copy_from_slice(&0xFED0_0000u64.to_le_bytes());
+        table[53..55].copy_from_slice(&128u16.to_le_bytes());
+        table[9] = 0u8.wrapping_sub(table.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)));
+        assert_eq!(HpetTable::parse(&table), Ok(HpetTable { block_id: 0x8086_A201, address: 0xFED0_0000, number: 0, minimum_tick: 128 }));
+        table[52] = 1;
+        assert_eq!(HpetTable::parse(&table), Err("Bad HPET table checksum"));
+        assert_eq!(HpetTable::parse(&table[..40]), Err("Not an HPET table"));
+
+        // 14.31818 MHz, three timers, a 64-bit counter; timer 0 periodic
+        // capable and routable to inputs 2 and 8, timer 1 only to 8
+        let registers = MmioRegion::new(0xFED0_0000, 0x400);
+        registers.write32(0x00, 2 << 8 | 1 << 13);
+        registers.write32(0x04, 69_841_279);
+        registers.write32(0x100, 1 << 4);
+        registers.write32(0x104, 1 << 2 | 1 << 8);
+        registers.write32(0x124, 1 << 8);
+        let hpet = Hpet::probe(TickingHpet { registers: registers.clone(), step: 1432 }).unwrap();
+        assert_eq!(hpet.frequency_hz(), 14_318_179);
+        assert_eq!(hpet.timers(), 3);
+        assert!(hpet.is_64bit());
+        assert_eq!(hpet.ticks(Duration::from_millis(1)), 14_318);
+        assert!(Hpet::probe(MmioRegion::new(0, 0x400)).is_err());
+
+        // The TSC moving 300,000 cycles for every 1,432 HPET ticks is a
+        // 3 GHz TSC
+        let tsc = std::sync::atomic::AtomicU64::new(0);
+        let read_tsc = || tsc.fetch_add(300_000, std::sync::atomic::Ordering::Relaxed);
+        assert_eq!(hpet.calibrate_tsc(read_tsc, Duration::from_millis(10)), Err("HPET is not enabled"));
+        hpet.enable();
+        assert_eq!(hpet.calibrate_tsc(read_tsc, Duration::from_millis(10)), Ok((300_000u128 * 14_318_179 / 1432) as u64));
+        hpet.disable();
+        let stopped = Hpet::probe(registers.clone()).unwrap();
+        stopped.enable();
+        assert_eq!(stopped.calibrate_tsc(read_tsc, Duration::from_millis(1)), Err("HPET counter is not running"));
+
+        // Comparators, routed to an I/O APIC input each
+        hpet.enable();
+        hpet.arm_oneshot(1, 8, Duration::from_millis(1)).unwrap();
+        let counter = registers.read32(0xF0) as u64 | (registers.read32(0xF4) as u64) << 32;
+        let comparator = registers.read32(0x128) as u64 | (registers.read32(0x12C) as u64) << 32;
+        assert_eq!(comparator, counter - 1432 + 14_318);
+        assert_eq!(registers.read32(0x120) & 0x3E0E, 8 << 9 | 1 << 2);
+        assert_eq!(hpet.arm_oneshot(1, 2, Duration::from_millis(1)), Err("HPET timer cannot be routed there"));
+        assert_eq!(hpet.arm_periodic(1, 8, Duration::from_millis(1)), Err("HPET timer is not periodic capable"));
+        assert_eq!(hpet.arm_oneshot(3, 8, Duration::from_millis(1)), Err("No such HPET timer"));
+        hpet.arm_periodic(0, 2, Duration::from_millis(1)).unwrap();
+        assert_eq!(registers.read32(0x108), 14_318);
+        assert_eq!(registers.read32(0x100) & 0x3E4E, 2 << 9 | 1 << 6 | 1 << 3 | 1 << 2);
+        hpet.disarm(0).unwrap();
+        assert_eq!(registers.read32(0x100) & (1 << 2 | 1 << 3), 0);
+
+        // The local APIC timer where its frequency is known and it keeps
+        // going in deep C-states, the HPET otherwise
+        let msr = MsrSpace::new(1);
+        assert_eq!(hpet::lapic_timer_hz(&msr), None);
+        msr.set_cpuid(0, (0x1F, 0, 0, 0));
+        msr.set_cpuid(0x15, (2, 250, 38_400_000, 0));
+        assert_eq!(hpet::lapic_timer_hz(&msr), Some(38_400_000));
+        assert!(!hpet::lapic_always_running(&msr));
+        msr.set_cpuid(0x06, (1 << 2, 0, 0, 0));
+        assert!(hpet::lapic_always_running(&msr));
+        assert_eq!(hpet::choose_clock_event(Some(38_400_000), true, true), Ok(ClockEventSource::LocalApic(38_400_000)));
+        assert_eq!(hpet::choose_clock_event(Some(38_400_000), false, true), Ok(ClockEventSource::Hpet));
+        assert_eq!(hpet::choose_clock_event(None, true, true), Ok(ClockEventSource::Hpet));
+        assert_eq!(hpet::choose_clock_event(Some(38_400_000), false, false), Ok(ClockEventSource::LocalApic(38_400_000)));
+        assert_eq!(hpet::choose_clock_event(None, false, false), Err("No usable clock event source"));
+    }
+
+    // A DMA engine's registers: starting a channel makes it busy, and its
+    // status bits clear when 1 is written to them
+    struct FakeDmaEngine {
+        registers: MmioRegion,
+    }
+
+    impl RegisterIo for FakeDmaEngine {
+        fn read32(&self, offset: usize) -> u32 {
+            self.registers.read32(offset)
+        }
+
+        fn write32(&self, offset: usize, value: u32) {
+            let register = offset & 0x1F;
+            if offset >= 0x100 && register == dma::CHANNEL_STATUS {
+                self.registers.write32(offset, self.registers.read32(offset) & !value);
+                return;
+            }
+            if offset >= 0x100 && register == dma::CHANNEL_CONTROL && value & dma::CONTROL_START != 0 {
+                self.registers.write32(offset + dma::CHANNEL_STATUS, dma::STATUS_BUSY);
+            }
+            self.registers.write32(offset, value);
+        }
+    }
+
+    #[test]
+    pub fn test_dma_scatter_gather() {
+        let aligned = DmaBuffer::from_slice(b"header", 64).unwrap();
+        assert_eq!(aligned.bus_address() % 64, 0);
+        assert_eq!(aligned.as_slice(), b"header");
+        assert!(DmaBuffer::new(0, 8).is_err());
+        assert!(DmaBuffer::new(8, 3).is_err());
+
+        // Runs longer than one descriptor moves are split
+        let buffers = vec![DmaBuffer::new(100, 8).unwrap(), DmaBuffer::new(dma::MAX_SEGMENT + 10, 4096).unwrap()];
+        let (first, second) = (buffers[0].bus_address(), buffers[1].bus_address());
+        let table = SgTable::build(&buffers).unwrap();
+        assert_eq!(
+            table.descriptors(),
+            [(first, 100, 0), (second, dma::MAX_SEGMENT as u32, 0), (second + dma::MAX_SEGMENT as u64, 10, dma::DESCRIPTOR_LAST | dma::DESCRIPTOR_INTERRUPT)]
+        );
+        assert_eq!(table.len(), 3);
+        assert!(SgTable::build(&[]).is_err());
+
+        // Registers where BAR 0, a 64-bit one, points
+        let config = MmioRegion::new(0, 4096);
+        config.write32(pci::BAR0, 0xFE10_0004);
+        config.write32(pci::BAR0 + 4, 0x1);
+        assert_eq!(pci::bar_address(&config, 0), Ok(0x1_FE10_0000));
+        assert!(pci::bar_address(&config, 6).is_err());
+        let registers = MmioRegion::new(0x1_FE10_0000, dma::REGISTERS_SIZE);
+        registers.write32(0x00, 2);
+        let mut mapped = None;
+        let controller = DmaController::probe(&config, |address, size| {
+            mapped = Some((address, size));
+            Ok(FakeDmaEngine { registers: registers.clone() })
+        })
+        .unwrap();
+        assert_eq!(mapped, Some((0x1_FE10_0000, dma::REGISTERS_SIZE)));
+        assert_eq!(config.read16(pci::COMMAND) & (pci::COMMAND_MEMORY | pci::COMMAND_BUS_MASTER), pci::COMMAND_MEMORY | pci::COMMAND_BUS_MASTER);
+        assert_eq!(controller.channels(), 2);
+        assert!(DmaController::probe(&MmioRegion::new(0, 4096), |_, _| Ok(FakeDmaEngine { registers: registers.clone() })).is_err());
+
+        // The device owns the buffers until the transfer completes
+        let transfer = controller.submit(1, DmaDirection::FromDevice, buffers).unwrap();
+        let channel = 0x120;
+        assert_eq!(registers.read32(channel + dma::CHANNEL_CONTROL), dma::CONTROL_START);
+        assert_eq!(registers.read32(channel + dma::CHANNEL_TABLE_LOW) as u64 | (registers.read32(channel + dma::CHANNEL_TABLE_HIGH) as u64) << 32, transfer.table().bus_address());
+        assert_eq!(registers.read32(channel + dma::CHANNEL_TABLE_ENTRIES), 3);
+        assert_eq!(controller.submit(1, DmaDirection::ToDevice, vec![DmaBuffer::new(8, 8).unwrap()]).err(), Some("DMA channel is busy"));
+        assert_eq!(controller.submit(2, DmaDirection::ToDevice, vec![DmaBuffer::new(8, 8).unwrap()]).err(), Some("No such DMA channel"));
+        assert!(!transfer.is_done());
+        registers.write32(channel + dma::CHANNEL_STATUS, dma::STATUS_DONE);
+        let buffers = transfer.wait(Duration::from_millis(10)).unwrap();
+        assert_eq!(buffers[0].bus_address(), first);
+        assert_eq!(registers.read32(channel + dma::CHANNEL_STATUS), 0);
+
+        // Errors and timeouts stop the channel and free it for reuse
+        let transfer = controller.submit(1, DmaDirection::ToDevice, buffers).unwrap();
+        assert_eq!(registers.read32(channel + dma::CHANNEL_CONTROL), dma::CONTROL_START | dma::CONTROL_TO_DEVICE);
+        registers.write32(channel + dma::CHANNEL_STATUS, dma::STATUS_ERROR);
+        assert_eq!(transfer.wait(Duration::from_millis(10)).err(), Some("DMA transfer failed"));
+        assert_eq!(registers.read32(channel + dma::CHANNEL_CONTROL), 0);
+        let transfer = controller.submit(1, DmaDirection::ToDevice, vec![DmaBuffer::new(8, 8).unwrap()]).unwrap();
+        assert_eq!(transfer.wait(Duration::from_millis(1)).err(), Some("DMA transfer timed out"));
+
+        // A channel that will not stop keeps its buffers and stays claimed
+        assert_eq!(registers.read32(channel + dma::CHANNEL_CONTROL), dma::CONTROL_ABORT);
+        assert_eq!(controller.submit(1, DmaDirection::ToDevice, vec![DmaBuffer::new(8, 8).unwrap()]).err(), Some("DMA channel is busy"));
+        assert!(controller.submit(0, DmaDirection::ToDevice, vec![DmaBuffer::new(8, 8).unwrap()]).is_ok());
+    }
+
+    fn dma_debug_report(code: impl FnOnce()) -> String {
+        let payload = std::panic::catch_unwind(std::panic::AssertUnwindSafe(code)).expect_err("Expected a missing DMA sync");
+        payload.downcast_ref::<String>().cloned().or_else(|| payload.downcast_ref::<&str>().map(|report| report.to_string())).unwrap()
+    }
+
+    #[test]
+    pub fn test_streaming_dma_cache_maintenance() {
+        // 64-byte lines, CLFLUSHOPT where the CPU has it, and write-combining
+        // in the PAT slot PWT alone selects
+        let msr = MsrSpace::new(1);
+        assert_eq!(CacheFlush::detect(&msr), Err("CPU has no CLFLUSH"));
+        assert_eq!(dma::init_pat(&msr, 0), Err("CPU has no PAT"));
+        msr.set_cpuid(1, (0, 8 << 8, 0, 1 << 19 | 1 << 16));
+        assert_eq!(CacheFlush::detect(&msr), Ok(CacheFlush { instruction: FlushInstruction::Clflush, line_size: 64 }));
+        msr.set_cpuid(7, (0, 1 << 23, 0, 0));
+        let flush = CacheFlush::detect(&msr).unwrap();
+        assert_eq!(flush.instruction, FlushInstruction::Clflushopt);
+        assert_eq!(flush.lines(0x1030, 0x50).collect::<Vec<_>>(), [0x1000, 0x1040]);
+        dma::init_pat(&msr, 0).unwrap();
+        assert_eq!(msr.rdmsr(0, dma::IA32_PAT), Ok(0x0407_0506_0007_0106));
+        assert_eq!(dma::page_cache_bits(MemoryType::WriteBack), 0);
+        assert_eq!(dma::page_cache_bits(MemoryType::WriteCombining), dma::PAGE_PWT);
+        assert_eq!(dma::page_cache_bits(MemoryType::WriteThrough), dma::PAGE_PAT | dma::PAGE_PCD | dma::PAGE_PWT);
+
+        // Handing a buffer to a device that does not snoop writes back each
+        // of its lines, fenced; handing it back drops them again
+        let cache = SimulatedCache::new();
+        let streaming = StreamingDma::new(cache.clone(), flush, false);
+        let buffer = DmaBuffer::new(130, 64).unwrap();
+        let lines: Vec<_> = (0..3).map(|line| (FlushInstruction::Clflushopt, buffer.bus_address() + line * 64)).collect();
+        let mut mapped = streaming.map_single(buffer, DmaDirection::FromDevice);
+        assert_eq!(mapped.owner(), DmaOwner::Device);
+        assert_eq!(cache.take_flushed(), lines);
+        assert_eq!(cache.fences(), 1);
+        streaming.sync_single_for_cpu(&mut mapped);
+        assert_eq!(cache.take_flushed(), lines);
+        mapped.as_mut_slice()[0] = 0x55;
+        streaming.sync_single_for_device(&mut mapped);
+        assert_eq!(cache.take_flushed(), lines);
+        let buffer = streaming.unmap_single(mapped);
+        assert_eq!(cache.take_flushed(), lines);
+        assert_eq!(buffer.as_slice()[0], 0x55);
+
+        // Nothing is left to drop after a transfer to the device
+        let mapped = streaming.map_single(buffer, DmaDirection::ToDevice);
+        assert_eq!(cache.take_flushed(), lines);
+        streaming.unmap_single(mapped);
+        assert!(cache.take_flushed().is_empty());
+
+        // Write-combined stores only need fencing, and a snooping device
+        // nothing at all
+        let fences = cache.fences();
+        let framebuffer = streaming.map_write_combining(DmaBuffer::new(4096, 4096).unwrap());
+        assert_eq!(framebuffer.memory_type(), MemoryType::WriteCombining);
+        assert!(cache.take_flushed().is_empty());
+        assert_eq!(cache.fences(), fences + 1);
+        streaming.unmap_single(framebuffer);
+        let coherent = StreamingDma::new(cache.clone(), flush, true);
+        let mapped = coherent.map_single(DmaBuffer::new(64, 64).unwrap(), DmaDirection::FromDevice);
+        coherent.unmap_single(mapped);
+        assert!(cache.take_flushed().is_empty());
+
+        // Missing syncs are caught in debug builds
+        if cfg!(debug_assertions) {
+            let mut mapped = streaming.map_single(DmaBuffer::new(64, 64).unwrap(), DmaDirection::FromDevice);
+            assert!(dma_debug_report(|| { mapped.as_slice(); }).contains("read while the device owns it"));
+            assert!(dma_debug_report(|| streaming.sync_single_for_device(&mut mapped)).contains("synced for the device twice"));
+            assert!(dma_debug_report(|| drop(mapped)).contains("freed while the device owns it"));
+        }
+    }
+
+    #[test]
+    pub fn test_resource_regions() {
+        let resources: &'static ResourceRegistry = Box::leak(Box::new(ResourceRegistry::new()));
+        let ec = resources.request_region(0x62, 1, "EC data").unwrap();
+        let _ec_command = resources.request_region(0x66, 1, "EC command").unwrap();
+        let serial = resources.request_region(0x3F8, 8, "serial").unwrap();
+        assert_eq!((serial.start(), serial.end()), (0x3F8, 0x3FF));
+
+        // Two drivers for the same ports fail at init
+        assert_eq!(resources.request_region(0x3FC, 2, "gdbstub").err(), Some("Resource busy"));
+        assert_eq!(resources.request_region(0x3F0, 9, "floppy").err(), Some("Resource busy"));
+        assert!(resources.request_region(0x3F0, 8, "floppy").is_ok());
+        assert_eq!(resources.request_region(0xFFFF, 2, "wraps").err(), Some("Bad resource range"));
+        assert_eq!(resources.request_region(0x80, 0, "empty").err(), Some("Bad resource range"));
+        assert_eq!(resources.owner(ResourceKind::Io, 0x3FA).as_deref(), Some("serial"));
+        assert_eq!(resources.report(ResourceKind::Io), "0062-0062 : EC data\n0066-0066 : EC command\n03f8-03ff : serial\n");
+
+        // Ports and memory are separate spaces
+        let hpet = resources.request_mem_region(0xFED0_0000, 0x400, "HPET").unwrap();
+        assert!(resources.request_mem_region(0x62, 1, "low memory").is_ok());
+        assert_eq!(resources.request_mem_region(0xFED0_03FF, 1, "HPET again").err(), Some("Resource busy"));
+        assert_eq!(resources.request_mem_region(u64::MAX, 2, "wraps").err(), Some("Bad resource range"));
+        assert_eq!(resources.report(ResourceKind::Memory), "fed00000-fed003ff : HPET\n");
+        drop(hpet);
+        assert!(resources.claims(ResourceKind::Memory).is_empty());
+
+        // Released when dropped, and reachable only inside the claim
+        drop(ec);
+        let ports = PortSpace::new();
+        let ec = resources.request_region(0x62, 1, "EC data").unwrap().ports(ports.clone()).unwrap();
+        ec.outb(0x62, 0x80);
+        ec.outb(0x66, 0x81);
+        assert_eq!((ports.inb(0x62), ports.inb(0x66)), (0x80, 0xFF));
+        assert_eq!(ec.inb(0x3F8), 0xFF);
+        assert_eq!(ec.region().kind(), ResourceKind::Io);
+        assert!(resources.request_mem_region(0x1000, 1, "memory").unwrap().ports(ports).is_err());
+    }
+
+    // A function's configuration space with its PM capability at 0x40:
+    // PME status clears when 1 is written to it, and coming out of D3hot
+    // resets the header unless the function says it does not
+    struct PmFunction {
+        config: MmioRegion,
+    }
+
+    impl RegisterIo for PmFunction {
+        fn read32(&self, offset: usize) -> u32 {
+            self.config.read32(offset)
+        }
+
+        fn write32(&self, offset: usize, value: u32) {
+            if offset != 0x44 {
+                self.config.write32(offset, value);
+                return;
+            }
+            let old = self.config.read32(0x44);
+            let status = old & !value & 1 << 15;
+            self.config.write32(0x44, value & !(1 << 15) | status);
+            if old & 0x3 == 3 && value & 0x3 == 0 && old & 1 << 3 == 0 {
+                for dword in 1..16 {
+                    self.config.write32(dword * 4, 0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_pci_power_management() {
+        let config = MmioRegion::new(0, 4096);
+        config.write16(pci::STATUS, pci::STATUS_CAPABILITIES);
+        config.write8(pci::CAPABILITIES_POINTER, 0x40);
+        // D2 but not D1, and PME from D0 and D3hot
+        config.write16(0x40, CAP_POWER_MANAGEMENT as u16);
+        config.write16(0x42, 1 << 10 | 1 << 11 | 1 << 14 | 0x3);
+        config.write32(pci::BAR0, 0xFE20_0000);
+        config.write16(pci::COMMAND, pci::COMMAND_MEMORY | pci::COMMAND_BUS_MASTER);
+        let mut pm = PciPm::probe(PmFunction { config: config.clone() }).unwrap();
+        assert!(PciPm::probe(MmioRegion::new(0, 4096)).is_err());
+        assert!(pm.supports(DevicePowerState::D2) && !pm.supports(DevicePowerState::D1));
+        assert!(pm.can_wake_from(DevicePowerState::D3Hot) && !pm.can_wake_from(DevicePowerState::D2));
+        assert_eq!(pm.state(), DevicePowerState::D0);
+        assert_eq!(pm.set_state(DevicePowerState::D1), Err("Power state not supported"));
+
+        // Deeper only, except back to D0
+        pm.set_state(DevicePowerState::D2).unwrap();
+        assert_eq!(config.read16(0x44) & 0x3, 2);
+        pm.set_state(DevicePowerState::D3Hot).unwrap();
+        assert_eq!(pm.set_state(DevicePowerState::D2), Err("Invalid power state transition"));
+
+        // D3hot to D0 waits out the delay and puts back what the reset lost
+        let started = Instant::now();
+        pm.set_state(DevicePowerState::D0).unwrap();
+        assert!(started.elapsed() >= vaelix_core::drivers::pci_pm::pci_pm::D3HOT_DELAY);
+        assert_eq!(pm.state(), DevicePowerState::D0);
+        assert_eq!(config.read32(pci::BAR0), 0xFE20_0000);
+        assert_eq!(config.read16(pci::COMMAND), pci::COMMAND_MEMORY | pci::COMMAND_BUS_MASTER);
+
+        // PME armed through the GPE _PRW names, old status cleared first
+        let ports = PortSpace::new();
+        let mut wake = WakeManager::new(GpeBlock::new(ports.clone(), 0x20, 4).unwrap());
+        config.write16(0x44, 1 << 15);
+        assert_eq!(pm.arm_wake(&mut wake, "eth0", 0x0D, DevicePowerState::D2), Err("Device cannot wake from that state"));
+        pm.arm_wake(&mut wake, "eth0", 0x0D, DevicePowerState::D3Hot).unwrap();
+        assert!(pm.is_wake_enabled() && !pm.pme_status());
+        assert_eq!(wake.sources(), [("eth0", 0x0D)]);
+        RuntimePower::set_power_state(&mut pm, DevicePowerState::D3Hot).unwrap();
+        assert!(pm.is_wake_enabled());
+
+        // The device signals PME in D3hot
+        config.write16(0x44, config.read16(0x44) | 1 << 15);
+        pm.set_state(DevicePowerState::D0).unwrap();
+        assert!(pm.pme_status());
+        assert_eq!(pm.disarm_wake(&mut wake, "eth0"), Ok(true));
+        assert!(!pm.is_wake_enabled() && !pm.pme_status());
+        assert!(wake.sources().is_empty());
+    }
+
+    #[test]
+    pub fn test_pci_scan_registers_runtime_pm_devices() {
+        // 00:02.0 a GPU, 00:1f a multi-function device with HD audio at .3
+        // and an ISA bridge at .0, 00:14.0 a WiFi card without PM
+        let ecam = MmioRegion::new(0, 32 * 8 * pci::ECAM_FUNCTION_SIZE);
+        let function = |device, function, class: u32, header: u8, pm: bool| {
+            let base = pci::ecam_offset(0, device, function);
+            ecam.write32(base + pci::VENDOR_ID, 0x1234_8086);
+            ecam.write32(base + pci::CLASS_REVISION, class << 8 | 0x01);
+            ecam.write8(base + pci::HEADER_TYPE, header);
+            if pm {
+                ecam.write16(base + pci::STATUS, pci::STATUS_CAPABILITIES);
+                ecam.write8(base + pci::CAPABILITIES_POINTER, 0x40);
+                ecam.write16(base + 0x40, CAP_POWER_MANAGEMENT as u16);
+                ecam.write16(base + 0x42, 0x3);
+            }
+        };
+        function(2, 0, 0x03_00_00, 0, true);
+        function(0x14, 0, 0x02_80_00, 0, false);
+        function(0x1F, 0, 0x06_01_00, pci::HEADER_MULTI_FUNCTION, false);
+        function(0x1F, 3, 0x04_03_00, 0, true);
+
+        // Windows onto the one region, as ECAM maps them
+        struct Window(MmioRegion, usize);
+        impl RegisterIo for Window {
+            fn read32(&self, offset: usize) -> u32 {
+                self.0.read32(self.1 + offset)
+            }
+            fn write32(&self, offset: usize, value: u32) {
+                self.0.write32(self.1 + offset, value)
+            }
+        }
+        let functions = pci::scan_bus(0, |offset| Window(ecam.clone(), offset));
+        let names: Vec<&str> = functions.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["0000:00:02.0", "0000:00:14.0", "0000:00:1f.0", "0000:00:1f.3"]);
+
+        let runtime = RuntimePm::new();
+        let classes: Vec<_> = functions.into_iter().map(|(name, config)| pci_pm::register_runtime(&runtime, &name, config)).collect();
+        assert_eq!(classes, [Ok(Some(DeviceClass::Gpu)), Err("No power management capability"), Ok(None), Ok(Some(DeviceClass::Audio))]);
+        assert_eq!(runtime.state("0000:00:02.0"), Some(DevicePowerState::D0));
+        assert_eq!(runtime.state("0000:00:1f.0"), None);
+
+        // Idle long enough, the audio function goes to D3hot in its PMCSR
+        let later = Instant::now() + Duration::from_secs(2);
+        assert_eq!(runtime.poll(later), vec!["0000:00:1f.3".to_string()]);
+        assert_eq!(ecam.read16(pci::ecam_offset(0, 0x1F, 3) + 0x44) & 0x3, 3);
+    }
+}
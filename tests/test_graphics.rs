@@ -0,0 +1,2527 @@
+#[cfg(test)]
+pub mod tests {
+    use vaelix_core::input::input::{
+        InputEvent, InputHub, BTN_LEFT, BTN_RIGHT, KEY_BACKSPACE, KEY_DELETE, KEY_DOWN, KEY_ENTER, KEY_ESC, KEY_F1,
+        KEY_F, KEY_HOME, KEY_J, KEY_L, KEY_LEFT, KEY_LEFTCTRL, KEY_LEFTMETA, KEY_LEFTSHIFT, KEY_PAGEUP, KEY_SPACE,
+        KEY_TAB, KEY_UP,
+    };
+    use vaelix_core::power::button::button::{self as power_button, PowerEvent};
+    use vaelix_core::power::profile::profile as power_profile;
+    use vaelix_core::pty::pty::{self, LineMode, PtySize};
+    use vaelix_core::process::kthread::kthread::KernelThread;
+    use vaelix_core::users::users::{AuthService, UserDatabase};
+    use vaelix_core::vxchan::vxchan::VXChanManager;
+    use vaelix_graphics::vxcursor::vxcursor::{self, CursorShape};
+    use vaelix_graphics::vegagx::vegagx::{Canvas, Color, GradientStop, Image, Paint, Path, Point};
+    use vaelix_graphics::vxtheme::vxtheme::{
+        self as vxtheme, ColorScheme, CursorStyle, StyleOverride, Theme, ThemeManager, ThemeService, Variant, WidgetClass, WidgetState,
+    };
+    use vaelix_graphics::vxfont::vxfont::{visual_order, Font, GlyphAtlas, TextRenderer};
+    use vaelix_graphics::vxwin::vxwin::{
+        BlitOp, Blitter, Buffer, BufferStorage, Clock, Compositor, Display, Mode, Output, Rect, SoftwareBlitter,
+        Transform, Viewport, TIMING_HISTORY,
+    };
+    use vaelix_graphics::vxoutput::vxoutput::{
+        detect_scale, physical_size, Hotplug, LayoutMode, OutputConfig, OutputManager,
+    };
+    use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use vaelix_graphics::vxwm::vxwm::{
+        event_channel, send_request, Layout, SurfaceId, TileAction, WindowManager, WindowState, WmService, REPLY_CHANNEL,
+    };
+    use vaelix_ui::vxde::vxde::{
+        self, Account, AccountService, AuthClient, Component, ComponentState, Greeter, SessionManager, SessionService,
+        WorkspaceSwitcher,
+    };
+    use vaelix_ui::vxanim::vxanim::{Animator, Easing, Property, Target, Timeline, EASE_IN, EASE_IN_OUT, EASE_OUT};
+    use vaelix_ui::vxnotification::vxnotification::{
+        self as vxnotify, CloseReason, Notification, NotificationDaemon, NotificationId, NotificationOverlay,
+        NotificationService, Urgency,
+    };
+    use vaelix_ui::vxpanel::vxpanel::{self, AppCatalog, AppEntry, Panel, StatusMonitor, SystemStatus};
+    use vaelix_ui::vxhud::vxhud::PerformanceHud;
+    use vaelix_ui::vxime::vxime::{self, ImeEvent, InputMethod};
+    use vaelix_ui::vxsnapshot::vxsnapshot::{self, DiffOptions, SnapshotOutcome, Snapshots};
+    use vaelix_ui::vxlock::vxlock::LockScreen;
+    use vaelix_ui::vxwallpaper::vxwallpaper::{self, Wallpaper, WallpaperConfig, WallpaperMode};
+    use vaelix_ui::vxterm::vxterm::{encode_key, encode_text, palette, CellColor, Modifiers, Terminal, TerminalApp};
+    use vaelix_ui::vxui_toolkit::vxui_toolkit::{
+        AccessibilityEvent, Align, Direction, Element, Role, WidgetEvent, WidgetId, WidgetKind, WidgetTree,
+        WindowDecorations,
+    };
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // Records what was scanned out and counts vblanks
+    struct FakeDisplay {
+        width: u32,
+        height: u32,
+        scanout: Vec<u32>,
+        damage: Vec<Vec<Rect>>,
+        vblank: u64,
+        // Largest cursor image, if there is a cursor plane
+        cursor_plane: Option<(u32, u32)>,
+        cursor: Option<Buffer>,
+        cursor_position: (i32, i32),
+        cursor_loads: usize,
+    }
+
+    impl FakeDisplay {
+        fn new(width: u32, height: u32) -> Self {
+            FakeDisplay {
+                width,
+                height,
+                scanout: vec![0; (width * height) as usize],
+                damage: Vec::new(),
+                vblank: 0,
+                cursor_plane: None,
+                cursor: None,
+                cursor_position: (0, 0),
+                cursor_loads: 0,
+            }
+        }
+
+        fn pixel(&self, x: u32, y: u32) -> u32 {
+            self.scanout[(y * self.width + x) as usize]
+        }
+    }
+
+    impl Display for FakeDisplay {
+        fn resolution(&self) -> (u32, u32) {
+            (self.width, self.height)
+        }
+
+        fn scanout(&mut self, frame: &[u32], damage: &[Rect]) -> Result<(), &'static str> {
+            for rect in damage {
+                for y in rect.y..rect.y + rect.height as i32 {
+                    let start = (y as u32 * self.width) as usize + rect.x as usize;
+                    let end = start + rect.width as usize;
+                    self.scanout[start..end].copy_from_slice(&frame[start..end]);
+                }
+            }
+            self.damage.push(damage.to_vec());
+            Ok(())
+        }
+
+        fn wait_vblank(&mut self) -> Result<u64, &'static str> {
+            self.vblank += 16_667;
+            Ok(self.vblank)
+        }
+
+        fn cursor_size(&self) -> Option<(u32, u32)> {
+            self.cursor_plane
+        }
+
+        fn set_cursor(&mut self, image: Option<&Buffer>) -> Result<(), &'static str> {
+            self.cursor_plane.ok_or("No cursor plane")?;
+            self.cursor = image.cloned();
+            self.cursor_loads += 1;
+            Ok(())
+        }
+
+        fn move_cursor(&mut self, x: i32, y: i32) -> Result<(), &'static str> {
+            self.cursor_plane.ok_or("No cursor plane")?;
+            self.cursor_position = (x, y);
+            Ok(())
+        }
+    }
+
+    #[test]
+    pub fn test_compositor_stacking_and_blending() {
+        let mut display = FakeDisplay::new(64, 48);
+        let mut compositor = Compositor::new(64, 48);
+        let background = compositor.run_frame(&mut display).unwrap().unwrap();
+        assert_eq!(background.pixels, 64 * 48);
+
+        let bottom = compositor.create_window(0, 0);
+        let bottom_buffer = Buffer::new(32, 32, BufferStorage::SharedMemory);
+        bottom_buffer.fill(Rect::new(0, 0, 32, 32), 0xFFFF_0000);
+        compositor.attach(bottom, bottom_buffer).unwrap();
+        compositor.commit(bottom).unwrap();
+
+        let top = compositor.create_window(16, 16);
+        let top_buffer = Buffer::new(16, 16, BufferStorage::GpuObject { handle: 7 });
+        // Half-transparent premultiplied blue
+        top_buffer.fill(Rect::new(0, 0, 16, 16), 0x8000_0080);
+        compositor.attach(top, top_buffer).unwrap();
+        compositor.commit(top).unwrap();
+
+        compositor.run_frame(&mut display).unwrap().unwrap();
+        assert_eq!(display.pixel(0, 0), 0xFFFF_0000);
+        assert_eq!(display.pixel(20, 20), 0xFF7F_0080);
+        assert_eq!(compositor.window_at(20, 20), Some(top));
+
+        // Raising the bottom window covers the overlap with opaque red
+        compositor.raise(bottom).unwrap();
+        assert_eq!(compositor.stacking_order(), &[top, bottom]);
+        compositor.run_frame(&mut display).unwrap().unwrap();
+        assert_eq!(display.pixel(20, 20), 0xFFFF_0000);
+        assert_eq!(display.pixel(40, 20), 0xFF20_2020);
+        assert_eq!(compositor.window_at(20, 20), Some(bottom));
+
+        compositor.destroy_window(bottom).unwrap();
+        compositor.run_frame(&mut display).unwrap().unwrap();
+        assert_eq!(display.pixel(0, 0), 0xFF20_2020);
+        assert_eq!(display.pixel(20, 20), 0xFF0F_0F8F);
+    }
+
+    #[test]
+    pub fn test_compositor_damage_and_frame_callbacks() {
+        let mut display = FakeDisplay::new(64, 48);
+        let mut compositor = Compositor::new(64, 48);
+        let window = compositor.create_window(8, 8);
+        let buffer = Buffer::new(16, 16, BufferStorage::SharedMemory);
+        compositor.attach(window, buffer.clone()).unwrap();
+        compositor.commit(window).unwrap();
+        compositor.run_frame(&mut display).unwrap().unwrap();
+
+        // Nothing changed, so nothing is scanned out
+        assert_eq!(compositor.run_frame(&mut display).unwrap(), None);
+        assert_eq!(display.damage.len(), 1);
+
+        // Drawing without damage is not picked up until the client says so
+        buffer.fill(Rect::new(0, 0, 4, 4), 0xFF00_FF00);
+        let done = compositor.request_frame(window).unwrap();
+        compositor.damage(window, Rect::new(0, 0, 4, 4)).unwrap();
+        assert!(compositor.pending_damage().is_empty());
+        compositor.commit(window).unwrap();
+        assert_eq!(compositor.pending_damage(), &[Rect::new(8, 8, 4, 4)]);
+
+        let stats = compositor.run_frame(&mut display).unwrap().unwrap();
+        assert_eq!(stats.pixels, 16);
+        assert_eq!(display.pixel(8, 8), 0xFF00_FF00);
+        assert_eq!(done.try_recv().unwrap(), stats.vblank);
+        assert_eq!(compositor.frames(), 3);
+
+        // Moving damages both the old and the new position
+        compositor.move_window(window, 40, 8).unwrap();
+        assert_eq!(compositor.pending_damage(), &[Rect::new(8, 8, 16, 16), Rect::new(40, 8, 16, 16)]);
+        compositor.run_frame(&mut display).unwrap().unwrap();
+        assert_eq!(display.pixel(8, 8), 0xFF20_2020);
+        assert_eq!(display.pixel(40, 8), 0xFF00_FF00);
+
+        assert!(compositor.compose(&mut FakeDisplay::new(32, 32)).is_err());
+    }
+
+    // Counts submissions and does the work in software, failing once the
+    // budget of good submissions runs out
+    struct FakeBlitter {
+        submitted: Arc<AtomicUsize>,
+        working: usize,
+    }
+
+    impl Blitter for FakeBlitter {
+        fn name(&self) -> &'static str {
+            "fake"
+        }
+
+        fn submit(&mut self, target: &mut [u32], stride: u32, ops: &[BlitOp]) -> Result<(), &'static str> {
+            if self.submitted.fetch_add(1, Ordering::SeqCst) >= self.working {
+                return Err("GPU hang");
+            }
+            SoftwareBlitter.submit(target, stride, ops)
+        }
+    }
+
+    #[test]
+    pub fn test_accelerated_composition_and_fallback() {
+        let mut display = FakeDisplay::new(64, 48);
+        let mut compositor = Compositor::new(64, 48);
+        let submitted = Arc::new(AtomicUsize::new(0));
+        compositor.set_accelerator(Box::new(FakeBlitter { submitted: submitted.clone(), working: 2 }));
+        assert_eq!(compositor.accelerator(), Some("fake"));
+
+        // A 2x2 checker stretched to 16x16 scales each pixel to 8x8
+        let window = compositor.create_window(8, 8);
+        let buffer = Buffer::new(2, 2, BufferStorage::GpuObject { handle: 3 });
+        buffer.fill(Rect::new(0, 0, 2, 2), 0xFFFF_FFFF);
+        buffer.fill(Rect::new(1, 0, 1, 1), 0xFF00_00FF);
+        compositor.attach(window, buffer.clone()).unwrap();
+        compositor.commit(window).unwrap();
+        compositor.set_destination(window, Some((16, 16))).unwrap();
+        assert!(compositor.set_destination(window, Some((0, 16))).is_err());
+        let stats = compositor.run_frame(&mut display).unwrap().unwrap();
+        assert!(stats.accelerated);
+        assert_eq!(display.pixel(8, 8), 0xFFFF_FFFF);
+        assert_eq!(display.pixel(16, 8), 0xFF00_00FF);
+        assert_eq!(display.pixel(23, 15), 0xFF00_00FF);
+        assert_eq!(display.pixel(23, 16), 0xFFFF_FFFF);
+        assert_eq!(display.pixel(24, 8), 0xFF20_2020);
+        assert_eq!(compositor.window_at(23, 23), Some(window));
+
+        // Unscaling damages the larger area it used to cover
+        compositor.set_destination(window, None).unwrap();
+        assert_eq!(compositor.pending_damage(), &[Rect::new(8, 8, 16, 16)]);
+        assert!(compositor.run_frame(&mut display).unwrap().unwrap().accelerated);
+        assert_eq!(display.pixel(9, 8), 0xFF00_00FF);
+        assert_eq!(display.pixel(16, 8), 0xFF20_2020);
+
+        // A failed submission still produces the frame, in software, and
+        // the GPU is not tried again
+        buffer.fill(Rect::new(0, 0, 1, 1), 0xFF00_FF00);
+        compositor.damage(window, Rect::new(0, 0, 1, 1)).unwrap();
+        compositor.commit(window).unwrap();
+        let stats = compositor.run_frame(&mut display).unwrap().unwrap();
+        assert!(!stats.accelerated);
+        assert_eq!(display.pixel(8, 8), 0xFF00_FF00);
+        assert_eq!(compositor.accelerator(), None);
+        compositor.move_window(window, 0, 0).unwrap();
+        compositor.run_frame(&mut display).unwrap().unwrap();
+        assert_eq!(submitted.load(Ordering::SeqCst), 3);
+        assert_eq!(display.pixel(0, 0), 0xFF00_FF00);
+    }
+
+    fn drain(manager: &VXChanManager, channel: &str) -> Vec<String> {
+        let mut messages = Vec::new();
+        while let Some(message) = manager.try_receive_message(channel).unwrap() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    fn click(wm: &mut WindowManager, x: i32, y: i32) {
+        wm.pointer_motion(x, y);
+        wm.pointer_button(BTN_LEFT, true).unwrap();
+    }
+
+    #[test]
+    pub fn test_window_manager_protocol() {
+        let manager = VXChanManager::new();
+        let mut service = WmService::new(&manager).unwrap();
+        let mut wm = WindowManager::new(320, 240, Box::new(WindowDecorations::default()));
+
+        send_request(&manager, 1, "create 100 80 Editor").unwrap();
+        service.poll(&manager, &mut wm).unwrap();
+        let editor_buffer = wm.register_buffer(Buffer::new(100, 80, BufferStorage::SharedMemory));
+        send_request(&manager, 2, &format!("attach 1 {}", editor_buffer.0)).unwrap();
+        send_request(&manager, 3, "commit 1").unwrap();
+        send_request(&manager, 4, "create 60 40 Terminal").unwrap();
+        let terminal_buffer = wm.register_buffer(Buffer::new(60, 40, BufferStorage::SharedMemory));
+        send_request(&manager, 5, &format!("attach 2 {}", terminal_buffer.0)).unwrap();
+        send_request(&manager, 6, "commit 2").unwrap();
+        assert_eq!(service.poll(&manager, &mut wm).unwrap(), 5);
+        assert_eq!(
+            drain(&manager, REPLY_CHANNEL),
+            vec!["1 ok\n1", "2 ok", "3 ok", "4 ok\n2", "5 ok", "6 ok"]
+        );
+        assert_eq!(drain(&manager, "vxwin.1"), vec!["configure 100 80 normal", "focus in", "focus out"]);
+        assert_eq!(drain(&manager, "vxwin.2"), vec!["configure 60 40 normal", "focus in"]);
+        // The terminal cascades below the editor and its frame is mapped
+        assert_eq!(wm.geometry(SurfaceId(2)), Some((32, 32, 60, 40)));
+        assert!(wm.compositor().is_mapped(wm.compositor().stacking_order()[2]));
+
+        // Keys follow focus
+        assert_eq!(wm.key(30, true), Some(SurfaceId(2)));
+
+        // Dragging the editor's title bar focuses and moves it
+        click(&mut wm, 10, 10);
+        wm.pointer_motion(60, 50);
+        wm.pointer_button(BTN_LEFT, false).unwrap();
+        assert_eq!(wm.focused(), Some(SurfaceId(1)));
+        assert_eq!(wm.geometry(SurfaceId(1)), Some((50, 40, 100, 80)));
+
+        // Its bottom-right corner resizes it; the client is asked to follow
+        click(&mut wm, 157, 147);
+        wm.pointer_motion(177, 157);
+        wm.pointer_button(BTN_LEFT, false).unwrap();
+        assert_eq!(wm.geometry(SurfaceId(1)), Some((50, 40, 120, 90)));
+
+        // The maximize button fills the screen inside the decorations
+        click(&mut wm, 110, 50);
+        wm.pointer_button(BTN_LEFT, false).unwrap();
+        assert_eq!(wm.state(SurfaceId(1)), Some(WindowState::Maximized));
+        assert_eq!(wm.geometry(SurfaceId(1)), Some((0, 0, 312, 212)));
+        service.poll(&manager, &mut wm).unwrap();
+        assert_eq!(
+            drain(&manager, "vxwin.1"),
+            vec!["focus in", "configure 120 90 normal", "configure 312 212 maximized"]
+        );
+
+        send_request(&manager, 7, "restore 1").unwrap();
+        send_request(&manager, 8, "minimize 1").unwrap();
+        send_request(&manager, 9, "focus 9").unwrap();
+        service.poll(&manager, &mut wm).unwrap();
+        assert_eq!(drain(&manager, REPLY_CHANNEL), vec!["7 ok", "8 ok", "9 error Surface not found"]);
+        assert_eq!(wm.geometry(SurfaceId(1)), Some((50, 40, 120, 90)));
+        assert_eq!(wm.state(SurfaceId(1)), Some(WindowState::Minimized));
+        assert_eq!(wm.focused(), Some(SurfaceId(2)));
+        assert_eq!(
+            drain(&manager, "vxwin.1"),
+            vec!["configure 120 90 normal", "configure 120 90 minimized", "focus out"]
+        );
+        assert_eq!(drain(&manager, "vxwin.2"), vec!["key 30 down", "focus out", "focus in"]);
+
+        // The close button only asks the client; it destroys the surface
+        click(&mut wm, 82, 42);
+        wm.pointer_button(BTN_LEFT, false).unwrap();
+        send_request(&manager, 10, "destroy 2").unwrap();
+        service.poll(&manager, &mut wm).unwrap();
+        assert_eq!(drain(&manager, "vxwin.2"), vec!["close"]);
+        assert_eq!(wm.surfaces(), vec![SurfaceId(1)]);
+        // Minimized surfaces do not take focus until focused explicitly
+        assert_eq!(wm.focused(), None);
+        wm.focus(SurfaceId(1)).unwrap();
+        assert_eq!(wm.state(SurfaceId(1)), Some(WindowState::Normal));
+    }
+
+    // Creates a surface over the protocol and commits a buffer of its size
+    fn map_surface(
+        manager: &VXChanManager,
+        service: &mut WmService,
+        wm: &mut WindowManager,
+        width: u32,
+        height: u32,
+    ) -> SurfaceId {
+        send_request(manager, 1, &format!("create {} {} Client", width, height)).unwrap();
+        service.poll(manager, wm).unwrap();
+        let reply = drain(manager, REPLY_CHANNEL).pop().unwrap();
+        let surface = SurfaceId(reply.rsplit('\n').next().unwrap().parse().unwrap());
+        let buffer = wm.register_buffer(Buffer::new(width, height, BufferStorage::SharedMemory));
+        wm.attach(surface, buffer).unwrap();
+        wm.commit(surface).unwrap();
+        surface
+    }
+
+    #[test]
+    pub fn test_input_routing_and_grabs() {
+        let manager = VXChanManager::new();
+        let mut service = WmService::new(&manager).unwrap();
+        let mut wm = WindowManager::new(320, 240, Box::new(WindowDecorations::default()));
+        let hub = InputHub::new();
+        wm.connect_input(&hub);
+        let touchpad = hub.register_device("i2c-touchpad");
+        let keyboard = hub.register_device("ps2-keyboard");
+        // Client areas at (4, 24) 100x80 and (36, 56) 60x40
+        let editor = map_surface(&manager, &mut service, &mut wm, 100, 80);
+        let terminal = map_surface(&manager, &mut service, &mut wm, 60, 40);
+        service.poll(&manager, &mut wm).unwrap();
+        drain(&manager, "vxwin.1");
+        drain(&manager, "vxwin.2");
+
+        // Pointer focus follows the client area under the pointer, and a
+        // press keeps events on its surface until release
+        for event in [
+            InputEvent::PointerMotion { dx: 10, dy: 30 },
+            InputEvent::PointerMotion { dx: 40, dy: 40 },
+            InputEvent::Button { code: BTN_RIGHT, pressed: true },
+            InputEvent::PointerMotion { dx: 100, dy: 0 },
+            InputEvent::Button { code: BTN_RIGHT, pressed: false },
+            InputEvent::Scroll { dx: 0, dy: 1 },
+        ] {
+            hub.report(touchpad, event).unwrap();
+        }
+        hub.report(keyboard, InputEvent::Key { code: 30, pressed: true }).unwrap();
+        service.poll(&manager, &mut wm).unwrap();
+        assert_eq!(wm.pointer_position(), (150, 70));
+        assert_eq!(drain(&manager, "vxwin.1"), vec!["enter 6 6", "motion 6 6", "leave"]);
+        assert_eq!(
+            drain(&manager, "vxwin.2"),
+            vec![
+                "enter 14 14",
+                "motion 14 14",
+                "button 273 down",
+                "motion 114 14",
+                "button 273 up",
+                "leave",
+                "key 30 down"
+            ]
+        );
+
+        // A popup keeps the keyboard even when another surface is focused
+        // and is dismissed by a press outside it, which goes no further
+        send_request(&manager, 2, &format!("grab {} drag", terminal.0)).unwrap();
+        service.poll(&manager, &mut wm).unwrap();
+        assert_eq!(drain(&manager, REPLY_CHANNEL), vec!["2 error No button held in the surface"]);
+        let menu = map_surface(&manager, &mut service, &mut wm, 40, 40);
+        send_request(&manager, 3, &format!("grab {} popup", menu.0)).unwrap();
+        send_request(&manager, 4, &format!("focus {}", terminal.0)).unwrap();
+        service.poll(&manager, &mut wm).unwrap();
+        hub.report(keyboard, InputEvent::Key { code: 31, pressed: true }).unwrap();
+        hub.report(touchpad, InputEvent::Button { code: BTN_LEFT, pressed: true }).unwrap();
+        hub.report(touchpad, InputEvent::Button { code: BTN_LEFT, pressed: false }).unwrap();
+        service.poll(&manager, &mut wm).unwrap();
+        assert_eq!(drain(&manager, REPLY_CHANNEL), vec!["3 ok", "4 ok"]);
+        assert_eq!(
+            drain(&manager, "vxwin.3"),
+            vec!["configure 40 40 normal", "focus in", "focus out", "key 31 down", "popup_done"]
+        );
+        assert_eq!(drain(&manager, "vxwin.2"), vec!["focus out", "focus in"]);
+
+        // A drag grab keeps the pointer on its surface past the screen edge
+        // and ends with the button
+        hub.report(touchpad, InputEvent::PointerMotion { dx: -100, dy: 0 }).unwrap();
+        hub.report(touchpad, InputEvent::Button { code: BTN_LEFT, pressed: true }).unwrap();
+        send_request(&manager, 5, &format!("grab {} drag", terminal.0)).unwrap();
+        service.poll(&manager, &mut wm).unwrap();
+        hub.report(touchpad, InputEvent::PointerMotion { dx: 300, dy: 300 }).unwrap();
+        hub.report(touchpad, InputEvent::Button { code: BTN_LEFT, pressed: false }).unwrap();
+        service.poll(&manager, &mut wm).unwrap();
+        assert_eq!(drain(&manager, REPLY_CHANNEL), vec!["5 ok"]);
+        assert_eq!(
+            drain(&manager, "vxwin.2"),
+            vec!["enter 14 14", "motion 14 14", "button 272 down", "motion 283 183", "button 272 up", "leave"]
+        );
+        assert_eq!(wm.pointer_focus(), None);
+        assert!(drain(&manager, &event_channel(editor)).is_empty());
+        assert_eq!(wm.focused(), Some(terminal));
+    }
+
+    #[test]
+    pub fn test_tiling_layouts() {
+        let manager = VXChanManager::new();
+        let mut service = WmService::new(&manager).unwrap();
+        let mut wm = WindowManager::new(320, 240, Box::new(WindowDecorations::default()));
+        let surfaces: Vec<SurfaceId> = (0..3).map(|_| map_surface(&manager, &mut service, &mut wm, 100, 80)).collect();
+        let (editor, terminal, viewer) = (surfaces[0], surfaces[1], surfaces[2]);
+        service.poll(&manager, &mut wm).unwrap();
+        for surface in &surfaces {
+            drain(&manager, &event_channel(*surface));
+        }
+
+        // The first window takes the left half and the others share the
+        // right, with gaps around them
+        send_request(&manager, 2, "layout split").unwrap();
+        send_request(&manager, 3, "layout").unwrap();
+        send_request(&manager, 4, &format!("move {}", editor.0)).unwrap();
+        service.poll(&manager, &mut wm).unwrap();
+        assert_eq!(drain(&manager, REPLY_CHANNEL), vec!["2 ok", "3 ok\nsplit", "4 error Surface is tiled"]);
+        assert_eq!(wm.tiled(), surfaces);
+        assert_eq!(wm.geometry(editor), Some((8, 8, 140, 196)));
+        assert_eq!(wm.geometry(terminal), Some((164, 8, 140, 80)));
+        assert_eq!(wm.geometry(viewer), Some((164, 124, 140, 80)));
+        assert_eq!(drain(&manager, &event_channel(editor)), vec!["configure 140 196 normal"]);
+        assert_eq!(drain(&manager, &event_channel(terminal)), vec!["configure 140 80 normal"]);
+
+        // Title bars no longer drag tiled windows
+        click(&mut wm, 40, 14);
+        wm.pointer_motion(80, 60);
+        wm.pointer_button(BTN_LEFT, false).unwrap();
+        assert_eq!(wm.focused(), Some(editor));
+        assert_eq!(wm.geometry(editor), Some((8, 8, 140, 196)));
+
+        // Super bindings are taken from the keyboard, releases included;
+        // the modifiers themselves still reach the client
+        assert_eq!(wm.key(KEY_LEFTMETA, true), Some(editor));
+        assert_eq!(wm.key(KEY_J, true), None);
+        assert_eq!(wm.key(KEY_J, false), None);
+        assert_eq!(wm.focused(), Some(terminal));
+        wm.key(KEY_LEFTSHIFT, true);
+        wm.key(KEY_J, true);
+        wm.key(KEY_LEFTSHIFT, false);
+        assert_eq!(wm.tiled(), vec![editor, viewer, terminal]);
+        assert_eq!(wm.geometry(terminal), Some((164, 124, 140, 80)));
+        wm.key(KEY_ENTER, true);
+        assert_eq!(wm.tiled(), vec![terminal, editor, viewer]);
+        assert_eq!(wm.geometry(terminal), Some((8, 8, 140, 196)));
+        wm.key(KEY_L, true);
+        assert_eq!(wm.geometry(terminal), Some((8, 8, 155, 196)));
+        assert_eq!(wm.geometry(editor), Some((179, 8, 125, 80)));
+
+        // A floating window goes back to where it was before tiling
+        wm.key(KEY_F, true);
+        assert_eq!(wm.is_floating(terminal), Some(true));
+        assert_eq!(wm.geometry(terminal), Some((32, 32, 100, 80)));
+        assert_eq!(wm.tiled(), vec![editor, viewer]);
+        assert_eq!(wm.geometry(editor), Some((8, 8, 155, 196)));
+        assert_eq!(wm.key(KEY_LEFTMETA, false), Some(terminal));
+        assert_eq!(wm.key(KEY_J, true), Some(terminal));
+
+        // Stacked windows each fill the output
+        wm.bind(KEY_SPACE, false, None);
+        wm.bind(KEY_SPACE, true, Some(TileAction::NextLayout));
+        wm.key(KEY_LEFTMETA, true);
+        assert_eq!(wm.key(KEY_SPACE, true), Some(terminal));
+        wm.key(KEY_LEFTSHIFT, true);
+        wm.key(KEY_SPACE, true);
+        assert_eq!(wm.layout(), Layout::Stack);
+        assert_eq!(wm.geometry(editor), Some((0, 0, 312, 212)));
+        assert_eq!(wm.geometry(viewer), Some((0, 0, 312, 212)));
+
+        // Back to floating, everything is where it started
+        send_request(&manager, 5, "tile next-layout").unwrap();
+        send_request(&manager, 6, "tile sideways").unwrap();
+        service.poll(&manager, &mut wm).unwrap();
+        assert_eq!(drain(&manager, REPLY_CHANNEL), vec!["5 ok", "6 error Unknown tile action"]);
+        assert_eq!(wm.layout(), Layout::Floating);
+        assert!(wm.tiled().is_empty());
+        assert_eq!(wm.geometry(editor), Some((0, 0, 100, 80)));
+        assert_eq!(wm.geometry(viewer), Some((64, 64, 100, 80)));
+        assert_eq!(
+            drain(&manager, &event_channel(viewer)),
+            vec![
+                "configure 140 80 normal",
+                "focus out",
+                "configure 125 80 normal",
+                "configure 125 196 normal",
+                "configure 312 212 normal",
+                "configure 100 80 normal"
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_workspaces() {
+        let manager = VXChanManager::new();
+        let mut service = WmService::new(&manager).unwrap();
+        let mut wm = WindowManager::new(640, 240, Box::new(WindowDecorations::default()));
+        let editor = map_surface(&manager, &mut service, &mut wm, 100, 80);
+        let terminal = map_surface(&manager, &mut service, &mut wm, 100, 80);
+        assert_eq!(wm.workspaces(), vec!["1", "2", "3", "4"]);
+        let (terminal_frame, terminal_window) = wm.windows(terminal).unwrap();
+
+        // Switching hides the windows of the workspace left, which keeps
+        // its focus for when it is shown again
+        send_request(&manager, 2, "workspace 1").unwrap();
+        send_request(&manager, 3, "workspace").unwrap();
+        send_request(&manager, 4, "workspace 9").unwrap();
+        service.poll(&manager, &mut wm).unwrap();
+        assert_eq!(drain(&manager, REPLY_CHANNEL), vec!["2 ok", "3 ok\n1", "4 error Workspace not found"]);
+        assert_eq!(wm.compositor().window_at(40, 40), None);
+        assert_eq!(wm.focused(), None);
+        assert_eq!(drain(&manager, &event_channel(terminal)).last().map(String::as_str), Some("focus out"));
+        let viewer = map_surface(&manager, &mut service, &mut wm, 100, 80);
+        assert_eq!((wm.workspace_of(viewer), wm.surfaces_on(1)), (Some(1), vec![viewer]));
+
+        // Each workspace has its own layout
+        send_request(&manager, 5, "layout split").unwrap();
+        service.poll(&manager, &mut wm).unwrap();
+        assert_eq!(wm.geometry(viewer), Some((8, 8, 616, 196)));
+        wm.switch_workspace(0).unwrap();
+        assert_eq!(wm.layout(), Layout::Floating);
+        assert_eq!(wm.focused(), Some(terminal));
+        assert_eq!(wm.compositor().window_at(40, 60), Some(terminal_window));
+
+        // Focusing a window on another workspace shows that workspace; a
+        // window sent away takes its floating geometry on a floating one
+        wm.focus(viewer).unwrap();
+        assert_eq!(wm.current_workspace(), 1);
+        send_request(&manager, 6, &format!("send {} 0", viewer.0)).unwrap();
+        service.poll(&manager, &mut wm).unwrap();
+        assert_eq!(drain(&manager, REPLY_CHANNEL), vec!["5 ok", "6 ok"]);
+        assert_eq!(wm.focused(), None);
+        assert_eq!(wm.surfaces_on(0), vec![editor, terminal, viewer]);
+        assert_eq!(wm.geometry(viewer), Some((64, 64, 100, 80)));
+
+        // The switcher slides the workspace left out and the next one in;
+        // switching again midway settles the first slide at once
+        let mut animator = Animator::new();
+        let mut switcher = WorkspaceSwitcher::new();
+        wm.switch_workspace(0).unwrap();
+        switcher.switch(&mut wm, &mut animator, 0).unwrap();
+        assert!(!switcher.is_animating());
+        switcher.switch(&mut wm, &mut animator, 2).unwrap();
+        assert!(switcher.is_animating());
+        assert_eq!(wm.current_workspace(), 2);
+        animator.tick(0).apply(wm.compositor_mut());
+        animator.tick(75_000).apply(wm.compositor_mut());
+        let dx = wm.compositor().transform(terminal_frame).unwrap().dx;
+        assert!(dx < -320.0 && dx > -640.0, "{}", dx);
+        assert_eq!(wm.compositor().window_at(40 + dx as i32, 60), Some(terminal_window));
+        switcher.switch(&mut wm, &mut animator, 0).unwrap();
+        assert_eq!(wm.compositor().transform(terminal_frame).unwrap().dx, -640.0);
+        animator.tick(80_000).apply(wm.compositor_mut());
+        switcher.update(&mut wm, &mut animator);
+        assert!(switcher.is_animating());
+        let frame = animator.tick(230_000);
+        assert_eq!(frame.finished.len(), 6);
+        frame.apply(wm.compositor_mut());
+        switcher.update(&mut wm, &mut animator);
+        assert!(!switcher.is_animating() && animator.is_idle());
+        assert_eq!(wm.compositor().transform(terminal_frame), Some(Transform::IDENTITY));
+        assert_eq!(wm.compositor().window_at(40, 60), Some(terminal_window));
+
+        // The panel shows the workspaces and queues the one chosen
+        let mut panel = Panel::new(wm.compositor_mut(), AppCatalog::new(Vec::new()));
+        let status = SystemStatus::default();
+        wm.set_workspace_name(1, "Web").unwrap();
+        panel.update(&mut wm, &status, 0).unwrap();
+        let tree = panel.tree_mut().unwrap();
+        let pager = tree.find("workspaces").unwrap();
+        let buttons: Vec<WidgetId> = tree.children(pager).to_vec();
+        assert_eq!(buttons.iter().map(|button| tree.text(*button).unwrap()).collect::<Vec<_>>(), vec!["1", "Web", "3", "4"]);
+        assert_eq!(tree.children(tree.root()).len(), 9);
+        let bounds = tree.bounds(buttons[1]).unwrap();
+        let (x, y) = (bounds.x + bounds.width as i32 / 2, bounds.y + bounds.height as i32 / 2);
+        panel.pointer_button(x, y, true);
+        panel.pointer_button(x, y, false);
+        panel.update(&mut wm, &status, 0).unwrap();
+        assert_eq!(panel.take_workspace_switch(), Some(1));
+        assert_eq!(panel.take_workspace_switch(), None);
+        assert_eq!(wm.current_workspace(), 0);
+
+        // Windows on workspaces taken away move to the last one left
+        wm.move_to_workspace(viewer, 3).unwrap();
+        wm.set_workspace_count(2).unwrap();
+        assert_eq!(wm.workspaces(), vec!["1", "Web"]);
+        assert_eq!(wm.workspace_of(viewer), Some(1));
+        assert_eq!(wm.set_workspace_count(0), Err("At least one workspace is needed"));
+    }
+
+    fn alpha(pixel: u32) -> u32 {
+        pixel >> 24
+    }
+
+    #[test]
+    pub fn test_vegagx_rasterization() {
+        let white = Paint::Solid(Color::argb(0xFFFF_FFFF));
+        let mut pixels = vec![0u32; 32 * 32];
+        let mut canvas = Canvas::new(&mut pixels, 32, 32).unwrap();
+
+        // Clipping is scoped by save and restore
+        canvas.save();
+        canvas.clip_rect(Rect::new(4, 4, 8, 8));
+        canvas.fill_rect(Rect::new(0, 0, 32, 32), &Paint::Solid(Color::argb(0xFFFF_0000)));
+        canvas.restore();
+        assert_eq!(canvas.pixel(3, 3), Some(0));
+        assert_eq!(canvas.pixel(4, 4), Some(0xFFFF_0000));
+        assert_eq!(canvas.pixel(12, 12), Some(0));
+        assert_eq!(canvas.clip(), Some(Rect::new(0, 0, 32, 32)));
+
+        // Pixel-aligned edges are exact, half-covered pixels get half alpha
+        canvas.clear(Color::argb(0));
+        canvas.fill_path(&Path::rect(2.0, 2.0, 4.5, 4.0), &white);
+        assert_eq!(canvas.pixel(2, 2), Some(0xFFFF_FFFF));
+        assert_eq!(canvas.pixel(5, 5), Some(0xFFFF_FFFF));
+        assert_eq!(alpha(canvas.pixel(6, 3).unwrap()), 128);
+        assert_eq!(canvas.pixel(1, 1), Some(0));
+        assert_eq!(canvas.pixel(3, 6), Some(0));
+
+        // A circle's antialiased coverage adds up to its area; paths
+        // partly off the canvas are clipped
+        canvas.clear(Color::argb(0));
+        canvas.fill_path(&Path::circle(16.0, 16.0, 10.0), &white);
+        let covered: u32 = (0..32)
+            .flat_map(|y| (0..32).map(move |x| (x, y)))
+            .map(|(x, y)| alpha(canvas.pixel(x, y).unwrap()))
+            .sum();
+        let area = std::f32::consts::PI * 100.0 * 255.0;
+        assert!((covered as f32 - area).abs() < area * 0.01);
+        assert_eq!(canvas.pixel(16, 16), Some(0xFFFF_FFFF));
+        assert!((1..255).contains(&alpha(canvas.pixel(16, 6).unwrap())));
+        canvas.clear(Color::argb(0));
+        canvas.fill_path(&Path::circle(0.0, 16.0, 8.0), &white);
+        assert_eq!(canvas.pixel(0, 16), Some(0xFFFF_FFFF));
+        assert_eq!(canvas.pixel(9, 16), Some(0));
+
+        // Strokes are centered on the path
+        canvas.clear(Color::argb(0));
+        let mut line = Path::new();
+        line.move_to(4.0, 10.0).line_to(28.0, 10.0);
+        canvas.stroke_path(&line, 2.0, &white);
+        assert_eq!(canvas.pixel(16, 9), Some(0xFFFF_FFFF));
+        assert_eq!(canvas.pixel(16, 10), Some(0xFFFF_FFFF));
+        assert_eq!(canvas.pixel(16, 11), Some(0));
+        canvas.stroke_rect(Rect::new(0, 20, 10, 10), 2, &white);
+        assert_eq!(canvas.pixel(1, 25), Some(0xFFFF_FFFF));
+        assert_eq!(canvas.pixel(5, 25), Some(0));
+
+        // Gradients run between their stops and extend past the ends
+        let stops = vec![
+            GradientStop { offset: 0.0, color: Color::argb(0xFF00_0000) },
+            GradientStop { offset: 1.0, color: Color::argb(0xFFFF_FFFF) },
+        ];
+        let linear = Paint::LinearGradient {
+            start: Point::new(0.0, 0.0),
+            end: Point::new(32.0, 0.0),
+            stops: stops.clone(),
+        };
+        assert_eq!(linear.sample(-4, 0), 0xFF00_0000);
+        assert!(linear.sample(8, 0) < linear.sample(24, 0));
+        assert_eq!(linear.sample(40, 0), 0xFFFF_FFFF);
+        let radial = Paint::RadialGradient {
+            center: Point::new(16.0, 16.0),
+            radius: 8.0,
+            stops,
+        };
+        assert_eq!(radial.sample(15, 15) & 0xFF, 0x17);
+        assert_eq!(radial.sample(28, 16), 0xFFFF_FFFF);
+
+        // Images blend with their own alpha scaled by the opacity
+        canvas.clear(Color::argb(0xFF00_0000));
+        let image = Image::new(2, 1, vec![0xFFFF_FFFF, 0x8080_8080]).unwrap();
+        canvas.draw_image(&image, 30, 0, 255);
+        assert_eq!(canvas.pixel(30, 0), Some(0xFFFF_FFFF));
+        assert_eq!(canvas.pixel(31, 0), Some(0xFF80_8080));
+        canvas.draw_image(&image, -1, 1, 128);
+        assert_eq!(canvas.pixel(0, 1), Some(0xFF40_4040));
+        assert!(Image::new(2, 2, vec![0]).is_err());
+    }
+
+    // Simple glyph with every coordinate stored in whichever form is
+    // smallest, so the short, repeated and long encodings all get used
+    fn simple_glyph(contours: &[&[(i16, i16, bool)]]) -> Vec<u8> {
+        let points: Vec<(i16, i16, bool)> = contours.iter().flat_map(|contour| contour.iter().copied()).collect();
+        let mut glyph = Vec::new();
+        glyph.extend_from_slice(&(contours.len() as i16).to_be_bytes());
+        glyph.extend_from_slice(&[0; 8]);
+        let mut end = 0;
+        for contour in contours {
+            end += contour.len();
+            glyph.extend_from_slice(&(end as u16 - 1).to_be_bytes());
+        }
+        glyph.extend_from_slice(&[0, 0]);
+        let (mut flags, mut xs, mut ys) = (Vec::new(), Vec::new(), Vec::new());
+        let mut previous = (0i16, 0i16);
+        for &(x, y, on) in &points {
+            let mut flag = on as u8;
+            for (delta, short, same, out) in [(x - previous.0, 0x02, 0x10, &mut xs), (y - previous.1, 0x04, 0x20, &mut ys)] {
+                if delta == 0 {
+                    flag |= same;
+                } else if delta.abs() < 256 {
+                    flag |= short | if delta > 0 { same } else { 0 };
+                    out.push(delta.unsigned_abs() as u8);
+                } else {
+                    out.extend_from_slice(&delta.to_be_bytes());
+                }
+            }
+            flags.push(flag);
+            previous = (x, y);
+        }
+        let mut index = 0;
+        while index < flags.len() {
+            let run = flags[index..].iter().take_while(|flag| **flag == flags[index]).count();
+            if run > 1 {
+                glyph.extend_from_slice(&[flags[index] | 0x08, run as u8 - 1]);
+            } else {
+                glyph.push(flags[index]);
+            }
+            index += run;
+        }
+        glyph.extend(xs);
+        glyph.extend(ys);
+        glyph
+    }
+
+    // A minimal TrueType font at 1000 units per em: 'A' is a square, 'O'
+    // is made only of control points, 'C' is 'A' moved right as a
+    // composite, 'V' is a triangle kerned against 'A', and alef and bet
+    // reuse 'A' and 'O' through a glyph id array
+    fn test_font() -> Vec<u8> {
+        let glyphs = [
+            Vec::new(),
+            simple_glyph(&[&[(100, 0, true), (100, 700, true), (600, 700, true), (600, 0, true)]]),
+            simple_glyph(&[&[(350, 0, false), (0, 350, false), (350, 700, false), (700, 350, false)]]),
+            [(-1i16).to_be_bytes(), [0; 2], [0; 2], [0; 2], [0; 2], 0x0003u16.to_be_bytes(), 1u16.to_be_bytes(), 300i16.to_be_bytes(), [0; 2]].concat(),
+            Vec::new(),
+            simple_glyph(&[&[(0, 700, true), (700, 700, true), (350, 0, true)]]),
+        ];
+        let advances = [500u16, 700, 700, 1000, 300, 700];
+        let be16 = |values: &[u16]| values.iter().flat_map(|value| value.to_be_bytes()).collect::<Vec<u8>>();
+
+        let mut glyf = Vec::new();
+        let mut loca = Vec::new();
+        for glyph in &glyphs {
+            loca.extend_from_slice(&(glyf.len() as u32).to_be_bytes());
+            glyf.extend_from_slice(glyph);
+        }
+        loca.extend_from_slice(&(glyf.len() as u32).to_be_bytes());
+
+        // Single-character segments map through idDelta; the Hebrew one
+        // goes through the glyph id array
+        let segments: [(u16, u16, u16); 6] = [(0x20, 0x20, 4), (0x41, 0x41, 1), (0x43, 0x43, 3), (0x4F, 0x4F, 2), (0x56, 0x56, 5), (0x5D0, 0x5D1, 0)];
+        let count = segments.len() as u16 + 1;
+        let mut ends: Vec<u16> = segments.iter().map(|segment| segment.1).collect();
+        let mut starts: Vec<u16> = segments.iter().map(|segment| segment.0).collect();
+        let mut deltas: Vec<u16> = segments.iter().map(|segment| if segment.2 == 0 { 0 } else { segment.2.wrapping_sub(segment.0) }).collect();
+        let mut ranges: Vec<u16> = segments.iter().map(|segment| if segment.2 == 0 { 4 } else { 0 }).collect();
+        ends.push(0xFFFF);
+        starts.push(0xFFFF);
+        deltas.push(1);
+        ranges.push(0);
+        let subtable = [be16(&[4, 0, 0, count * 2, 0, 0, 0]), be16(&ends), be16(&[0]), be16(&starts), be16(&deltas), be16(&ranges), be16(&[1, 2])].concat();
+        let mut cmap = [be16(&[0, 1, 3, 1]), 12u32.to_be_bytes().to_vec(), subtable].concat();
+        let length = cmap.len() as u16 - 12;
+        cmap[14..16].copy_from_slice(&length.to_be_bytes());
+
+        let mut head = vec![0u8; 54];
+        head[18..20].copy_from_slice(&1000u16.to_be_bytes());
+        head[50..52].copy_from_slice(&1u16.to_be_bytes());
+        let mut hhea = vec![0u8; 36];
+        hhea[4..6].copy_from_slice(&800i16.to_be_bytes());
+        hhea[6..8].copy_from_slice(&(-200i16).to_be_bytes());
+        hhea[34..36].copy_from_slice(&(advances.len() as u16).to_be_bytes());
+        let hmtx = be16(&advances.iter().flat_map(|advance| [*advance, 0]).collect::<Vec<u16>>());
+        let kern = be16(&[0, 1, 0, 20, 1, 1, 6, 0, 0, 1, 5, (-200i16) as u16]);
+        let maxp = [0x0000_5000u32.to_be_bytes().to_vec(), be16(&[glyphs.len() as u16])].concat();
+
+        let tables: [(&[u8; 4], Vec<u8>); 8] = [
+            (b"cmap", cmap),
+            (b"glyf", glyf),
+            (b"head", head),
+            (b"hhea", hhea),
+            (b"hmtx", hmtx),
+            (b"kern", kern),
+            (b"loca", loca),
+            (b"maxp", maxp),
+        ];
+        let mut font = [0x0001_0000u32.to_be_bytes().to_vec(), be16(&[tables.len() as u16, 0, 0, 0])].concat();
+        let mut offset = 12 + tables.len() * 16;
+        for (tag, table) in &tables {
+            font.extend_from_slice(*tag);
+            font.extend_from_slice(&[0; 4]);
+            font.extend_from_slice(&(offset as u32).to_be_bytes());
+            font.extend_from_slice(&(table.len() as u32).to_be_bytes());
+            offset += table.len();
+        }
+        for (_, table) in tables {
+            font.extend(table);
+        }
+        font
+    }
+
+    #[test]
+    pub fn test_font_parsing_and_shaping() {
+        let font = Font::parse(test_font()).unwrap();
+        assert_eq!(font.units_per_em(), 1000);
+        assert_eq!(font.glyph_count(), 6);
+        assert_eq!(font.glyph_index('A'), 1);
+        assert_eq!(font.glyph_index('V'), 5);
+        assert_eq!(font.glyph_index('\u{5D1}'), 2);
+        assert_eq!(font.glyph_index('Z'), 0);
+        assert_eq!(font.glyph_index('\u{1F600}'), 0);
+        assert_eq!(font.advance(1, 100), 70.0);
+        assert_eq!(font.kerning(1, 5), -200);
+        assert_eq!(font.kerning(5, 1), 0);
+        assert_eq!(font.ascent(100), 80.0);
+        assert_eq!(font.descent(100), 20.0);
+        assert_eq!(font.line_height(100), 100.0);
+        assert!(Font::parse(vec![0; 8]).is_err());
+        assert!(Font::parse(test_font()[..200].to_vec()).is_err());
+
+        // Kerning pulls 'V' under 'A'
+        let glyphs = font.shape("AV A", 100);
+        let positions: Vec<f32> = glyphs.iter().map(|glyph| glyph.x).collect();
+        assert_eq!(positions, vec![0.0, 50.0, 120.0, 150.0]);
+        assert_eq!(font.measure("AV A", 100), 220.0);
+        assert_eq!(font.measure("", 100), 0.0);
+
+        // Right-to-left runs are reversed, and in a right-to-left
+        // paragraph so is the order of the runs
+        let order = |text: &str| visual_order(text).into_iter().map(|(character, _)| character).collect::<String>();
+        assert_eq!(order("AC \u{5D0}\u{5D1} O"), "AC \u{5D1}\u{5D0} O");
+        assert_eq!(order("\u{5D0}\u{5D1} AC"), "AC \u{5D1}\u{5D0}");
+        assert_eq!(order("\u{5D0} \u{5D1}!"), "!\u{5D1} \u{5D0}");
+        let hebrew = font.shape("\u{5D0}\u{5D1}", 100);
+        assert_eq!(hebrew.iter().map(|glyph| glyph.glyph).collect::<Vec<u16>>(), vec![2, 1]);
+
+        // Fonts load from VXFS
+        let path = std::env::temp_dir().join(format!("vxfont-{}.ttf", std::process::id()));
+        std::fs::write(&path, test_font()).unwrap();
+        let loaded = Font::load(path.to_str().unwrap()).unwrap();
+        assert_eq!(loaded.glyph_index('O'), 2);
+        std::fs::remove_file(&path).unwrap();
+        assert!(Font::load(path.to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    pub fn test_glyph_rasterization_and_text_drawing() {
+        let font = Font::parse(test_font()).unwrap();
+
+        // The square covers x 10..60 and 70 pixels above the baseline
+        let square = font.rasterize(1, 100).unwrap();
+        assert_eq!((square.left, square.top), (9, -71));
+        let coverage = |x: i32, y: i32| square.coverage[((y - square.top) as u32 * square.width + (x - square.left) as u32) as usize];
+        assert_eq!(coverage(35, -35), 255);
+        assert_eq!(coverage(10, -1), 255);
+        assert_eq!(coverage(9, -35), 0);
+        assert_eq!(coverage(35, 0), 0);
+
+        // Components keep their offset; off-curve-only contours still fill
+        let composite = font.rasterize(3, 100).unwrap();
+        assert_eq!(composite.left, square.left + 30);
+        assert_eq!(composite.coverage, square.coverage);
+        let round = font.rasterize(2, 100).unwrap();
+        let middle = ((-35 - round.top) as u32 * round.width + (35 - round.left) as u32) as usize;
+        assert_eq!(round.coverage[middle], 255);
+        assert_eq!(round.coverage[0], 0);
+        assert!(font.rasterize(4, 100).unwrap().coverage.is_empty());
+        assert!(font.rasterize(6, 100).is_err());
+
+        // The atlas rasterizes once per glyph and size, and starts over
+        // when full
+        let mut atlas = GlyphAtlas::new(64, 64);
+        let first = atlas.get(&font, 1, 10).unwrap();
+        assert_eq!(atlas.get(&font, 1, 10).unwrap(), first);
+        let second = atlas.get(&font, 5, 10).unwrap();
+        assert!(second.area.intersect(&first.area).is_none());
+        assert_eq!(atlas.len(), 2);
+        atlas.get(&font, 2, 60).unwrap();
+        atlas.get(&font, 2, 61).unwrap();
+        assert_eq!(atlas.len(), 1);
+        assert!(atlas.get(&font, 1, 200).is_err());
+
+        // Drawn text lands where it was shaped
+        let renderer = TextRenderer::new(font);
+        let mut pixels = vec![0u32; 64 * 32];
+        let mut canvas = Canvas::new(&mut pixels, 64, 32).unwrap();
+        let width = renderer.draw(&mut canvas, "AC", 20, 2.0, 20.0, &Paint::Solid(Color::argb(0xFFFF_FFFF))).unwrap();
+        assert_eq!(width, 34.0);
+        assert_eq!(canvas.pixel(8, 15), Some(0xFFFF_FFFF));
+        assert_eq!(canvas.pixel(28, 15), Some(0xFFFF_FFFF));
+        assert_eq!(canvas.pixel(18, 15), Some(0));
+        assert_eq!(canvas.pixel(8, 21), Some(0));
+    }
+
+    #[test]
+    pub fn test_widget_tree_layout_and_events() {
+        let events: Rc<RefCell<Vec<(WidgetId, WidgetEvent)>>> = Rc::new(RefCell::new(Vec::new()));
+        let record = events.clone();
+        let root = Element::container(Direction::Column)
+            .padding(10)
+            .spacing(5)
+            .child(Element::label("Name").name("title"))
+            .child(Element::text_input("Type here").name("input"))
+            .child(
+                Element::container(Direction::Row)
+                    .name("row")
+                    .spacing(4)
+                    .align(Align::Center)
+                    .child(Element::list(&["a", "b", "c"]).name("list").grow(1))
+                    .child(Element::button("OK").name("ok").on_event(|tree, _, _| {
+                        let title = tree.find("title").unwrap();
+                        tree.set_text(title, "Clicked").unwrap();
+                    })),
+            )
+            .on_event(move |_, id, event| record.borrow_mut().push((id, event.clone())));
+        let mut tree = WidgetTree::new(root, 200, 150);
+        let [title, input, row, list, ok] = ["title", "input", "row", "list", "ok"].map(|name| tree.find(name).unwrap());
+
+        // Without a font, text measures 7 pixels a character on 21 pixel
+        // lines; controls add a 6 pixel inset
+        assert_eq!(tree.bounds(title), Some(Rect::new(10, 10, 180, 21)));
+        assert_eq!(tree.bounds(input), Some(Rect::new(10, 36, 180, 33)));
+        assert_eq!(tree.bounds(row), Some(Rect::new(10, 74, 180, 75)));
+        assert_eq!(tree.bounds(list), Some(Rect::new(10, 74, 138, 75)));
+        assert_eq!(tree.bounds(ok), Some(Rect::new(152, 95, 38, 33)));
+
+        let palette = ColorScheme::DARK;
+        let buffer = Buffer::new(200, 150, BufferStorage::SharedMemory);
+        assert_eq!(tree.render(&buffer).unwrap(), vec![Rect::new(0, 0, 200, 150)]);
+        assert_eq!(buffer.pixel(0, 0), Some(palette.background));
+        assert_eq!(buffer.pixel(100, 50), Some(palette.surface));
+        assert_eq!(buffer.pixel(10, 50), Some(palette.border));
+        assert_eq!(buffer.pixel(170, 110), Some(palette.button));
+        assert!(tree.render(&buffer).unwrap().is_empty());
+
+        // Hovering and clicking only repaint the button
+        tree.pointer_motion(170, 110);
+        tree.pointer_button(170, 110, true);
+        tree.pointer_button(170, 110, false);
+        assert_eq!(tree.text(title), Some("Clicked"));
+        assert_eq!(tree.focus(), Some(ok));
+        let damage = tree.render(&buffer).unwrap();
+        assert!(damage.contains(&Rect::new(152, 95, 38, 33)));
+        assert!(!damage.contains(&Rect::new(0, 0, 200, 150)));
+        assert_eq!(buffer.pixel(170, 110), Some(palette.button_hover));
+        tree.pointer_button(170, 110, true);
+        tree.pointer_button(20, 20, false);
+        tree.set_text(title, "Name").unwrap();
+        tree.key(KEY_ENTER, true);
+        assert_eq!(tree.text(title), Some("Clicked"));
+
+        // List events bubble to the root's callback
+        tree.pointer_button(50, 106, true);
+        tree.pointer_button(50, 106, false);
+        tree.key(KEY_DOWN, true);
+        assert_eq!(tree.selected(list), Some(2));
+        assert_eq!(*events.borrow(), vec![(list, WidgetEvent::Selected(1)), (list, WidgetEvent::Selected(2))]);
+        tree.render(&buffer).unwrap();
+        assert_eq!(buffer.pixel(50, 124), Some(palette.accent));
+        assert_eq!(buffer.pixel(50, 103), Some(palette.surface));
+
+        // Editing works in characters, whatever their encoding
+        events.borrow_mut().clear();
+        tree.pointer_button(100, 50, true);
+        assert_eq!(tree.focus(), Some(input));
+        for character in ['h', 'i', '\n'] {
+            tree.text_input(character);
+        }
+        tree.key(KEY_LEFT, true);
+        tree.text_input('!');
+        tree.key(KEY_BACKSPACE, true);
+        tree.key(KEY_HOME, true);
+        tree.text_input('é');
+        tree.key(KEY_DELETE, true);
+        tree.key(KEY_ENTER, true);
+        let changes: Vec<WidgetEvent> = events.borrow().iter().map(|(_, event)| event.clone()).collect();
+        assert_eq!(
+            changes,
+            ["h", "hi", "h!i", "hi", "éhi", "éi"]
+                .iter()
+                .map(|text| WidgetEvent::Changed(text.to_string()))
+                .chain([WidgetEvent::Submitted("éi".to_string())])
+                .collect::<Vec<WidgetEvent>>()
+        );
+
+        // Structural changes relayout; only containers take children
+        let extra = tree.append(row, Element::label("More")).unwrap();
+        assert_eq!(tree.bounds(list), Some(Rect::new(10, 74, 106, 75)));
+        assert_eq!(tree.bounds(extra), Some(Rect::new(162, 101, 28, 21)));
+        assert!(tree.append(ok, Element::label("x")).is_err());
+        tree.remove(row).unwrap();
+        assert!(tree.kind(list).is_none());
+        assert!(tree.remove(tree.root()).is_err());
+        assert_eq!(tree.children(tree.root()), &[title, input]);
+        tree.resize(100, 80);
+        assert_eq!(tree.bounds(input), Some(Rect::new(10, 36, 80, 33)));
+        assert!(tree.render(&buffer).is_err());
+        assert!(tree.set_focus(Some(title)).is_err());
+    }
+
+    #[test]
+    pub fn test_animation_timelines() {
+        // Easing curves keep their ends and bend the middle
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-3;
+        assert!(close(Easing::Linear.apply(0.25), 0.25));
+        assert!(close(EASE_IN_OUT.apply(0.5), 0.5));
+        assert!(EASE_IN.apply(0.5) < 0.4 && EASE_OUT.apply(0.5) > 0.6);
+        let bouncy = Easing::Spring { stiffness: 400.0, damping: 10.0 };
+        let stiff = Easing::Spring { stiffness: 400.0, damping: 100.0 };
+        let samples = |easing: Easing| (1..100).map(move |step| easing.apply(step as f32 / 100.0));
+        assert!(samples(bouncy).any(|value| value > 1.05));
+        assert!(samples(stiff).all(|value| (0.0..1.0).contains(&value)));
+        assert!(samples(stiff).zip(samples(stiff).skip(1)).all(|(a, b)| b >= a));
+        for easing in [EASE_IN, bouncy, stiff] {
+            assert_eq!((easing.apply(0.0), easing.apply(1.0)), (0.0, 1.0));
+        }
+
+        // Keyframes interpolate in order of offset; delay holds the first
+        // value and alternating repeats run back
+        let timeline = Timeline::new(1000)
+            .keyframe(Property::Opacity, 1.0, 0.5, Easing::Linear)
+            .keyframe(Property::Opacity, 0.0, 0.0, Easing::Linear)
+            .keyframe(Property::Opacity, 0.5, 1.0, Easing::Linear)
+            .delay(100)
+            .repeat(2, true);
+        let opacity = |elapsed: u64| timeline.sample(elapsed)[0].1;
+        assert_eq!(opacity(50), 0.0);
+        assert_eq!(opacity(350), 0.5);
+        assert_eq!(opacity(600), 1.0);
+        assert_eq!(opacity(850), 0.75);
+        assert_eq!(opacity(1350), 0.75);
+        assert_eq!(opacity(5000), 0.0);
+
+        // Window animations are applied by the compositor as it composes
+        let mut display = FakeDisplay::new(32, 32);
+        let mut compositor = Compositor::new(32, 32);
+        let window = compositor.create_window(8, 8);
+        let buffer = Buffer::new(16, 16, BufferStorage::SharedMemory);
+        buffer.fill(Rect::new(0, 0, 16, 16), 0xFFFF_0000);
+        compositor.attach(window, buffer).unwrap();
+        compositor.commit(window).unwrap();
+        let mut animator = Animator::new();
+        let open = Timeline::new(100_000)
+            .animate(Property::Scale, 0.5, 1.0, Easing::Linear)
+            .animate(Property::Opacity, 0.0, 1.0, Easing::Linear);
+        let animation = animator.start(Target::Window(window), open);
+
+        let frame = animator.tick(1_000);
+        assert_eq!(frame.windows, vec![(window, Transform { scale: 0.5, opacity: 0.0, ..Transform::IDENTITY })]);
+        frame.apply(&mut compositor);
+        compositor.run_frame(&mut display).unwrap();
+        assert_eq!(display.pixel(16, 16), 0xFF20_2020);
+
+        animator.tick(51_000).apply(&mut compositor);
+        compositor.run_frame(&mut display).unwrap();
+        assert_eq!(compositor.window_at(9, 9), None);
+        assert_eq!(compositor.window_at(10, 10), Some(window));
+        assert_eq!(display.pixel(10, 10), 0xFF8F_0F0F);
+        assert_eq!(display.pixel(9, 9), 0xFF20_2020);
+
+        let frame = animator.tick(200_000);
+        assert_eq!(frame.finished, vec![animation]);
+        frame.apply(&mut compositor);
+        compositor.run_frame(&mut display).unwrap();
+        assert_eq!(display.pixel(8, 8), 0xFFFF_0000);
+        assert!(animator.is_idle() && !animator.is_running(animation));
+        assert!(animator.tick(300_000).is_empty());
+        assert_eq!(compositor.transform(window), Some(Transform::IDENTITY));
+
+        // Widgets slide; a stopped animation stays where it was
+        let mut tree = WidgetTree::new(Element::container(Direction::Column).child(Element::label("Hi").name("hi")), 100, 50);
+        let label = tree.find("hi").unwrap();
+        let slide = animator.start(Target::Widget(label), Timeline::new(1_000).animate(Property::TranslateX, 0.0, 20.0, Easing::Linear));
+        animator.tick(0);
+        animator.tick(500).apply_widgets(&mut tree);
+        assert_eq!(tree.bounds(label), Some(Rect::new(10, 0, 100, 21)));
+        animator.stop(slide).unwrap();
+        assert!(animator.tick(1_000).is_empty());
+        assert_eq!(animator.transform(Target::Widget(label)).dx, 10.0);
+        assert!(animator.stop(slide).is_err());
+    }
+
+    #[test]
+    pub fn test_theme_resolution_and_hot_reload() {
+        let contents = "name=ocean\nvariant=light\n[light]\naccent=#0080C0\nbutton=#80FF0000\n[spacing]\ninset=4\ngap=8\n[typography]\nsize=20\n[button]\nforeground=#FFFFFF\nradius=10\n";
+        let theme = Theme::parse(contents).unwrap();
+        assert_eq!((theme.name.as_str(), theme.default_variant), ("ocean", Variant::Light));
+        assert_eq!(theme.light.accent, 0xFF00_80C0);
+        assert_eq!(theme.light.button, 0x80FF_0000);
+        assert_eq!(theme.dark, ColorScheme::DARK);
+        assert_eq!(Theme::parse(&theme.serialize()).unwrap(), theme);
+        for broken in ["[dark]\naccent=0080C0", "[dark]\nglow=#000000", "[window]\nradius=2", "variant=dim", "size"] {
+            assert!(Theme::parse(broken).is_err());
+        }
+
+        // Widget overrides beat the theme's class section, which beats the
+        // color scheme; state colors come last
+        let own = StyleOverride { foreground: Some(0xFF00_FF00), ..StyleOverride::default() };
+        let idle = theme.resolve(Variant::Light, WidgetClass::Button, WidgetState::default(), &StyleOverride::default());
+        assert_eq!((idle.background, idle.foreground, idle.radius, idle.inset), (0x80FF_0000, 0xFFFF_FFFF, 10.0, 4));
+        let pressed = WidgetState { pressed: true, focused: true, ..WidgetState::default() };
+        let styled = theme.resolve(Variant::Light, WidgetClass::Button, pressed, &own);
+        assert_eq!((styled.background, styled.foreground, styled.border), (ColorScheme::LIGHT.button_pressed, 0xFF00_FF00, 0xFF00_80C0));
+        assert_eq!(theme.resolve(Variant::Dark, WidgetClass::List, WidgetState::default(), &own).background, ColorScheme::DARK.surface);
+
+        // The toolkit relays out for the new typography and spacing
+        let mut tree = WidgetTree::new(
+            Element::container(Direction::Column)
+                .child(Element::label("Hi").name("hi"))
+                .child(Element::button("OK").name("ok").style(own)),
+            100,
+            100,
+        );
+        let (label, button) = (tree.find("hi").unwrap(), tree.find("ok").unwrap());
+        assert_eq!(tree.bounds(button), Some(Rect::new(0, 25, 100, 33)));
+        tree.set_theme(theme.clone(), Variant::Light);
+        assert_eq!(tree.bounds(label), Some(Rect::new(0, 0, 100, 30)));
+        assert_eq!(tree.bounds(button), Some(Rect::new(0, 38, 100, 38)));
+        assert_eq!(tree.style(button).unwrap().foreground, 0xFF00_FF00);
+        assert!(tree.style(tree.root()).is_none());
+        let buffer = Buffer::new(100, 100, BufferStorage::SharedMemory);
+        tree.render(&buffer).unwrap();
+        assert_eq!(buffer.pixel(0, 90), Some(ColorScheme::LIGHT.background));
+        assert_eq!(buffer.pixel(50, 57), Some(0xFFF9_7979));
+
+        // Edits to the file reach subscribers through the service
+        let path = std::env::temp_dir().join(format!("vxtheme-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, contents).unwrap();
+        let themes = ThemeManager::load(path).unwrap();
+        assert_eq!(themes.variant(), Variant::Light);
+        let manager = VXChanManager::new();
+        let mut service = ThemeService::new(&manager, &themes).unwrap();
+        let changes = themes.subscribe();
+        assert_eq!(service.poll(&manager, &themes).unwrap(), 0);
+        assert!(changes.try_recv().is_err());
+
+        std::fs::write(path, contents.replace("size=20", "size=16")).unwrap();
+        service.poll(&manager, &themes).unwrap();
+        let change = changes.try_recv().unwrap();
+        assert_eq!(change.theme.typography.size, 16);
+        assert_eq!(drain(&manager, vxtheme::EVENT_CHANNEL), vec!["theme ocean light"]);
+
+        // A broken edit keeps the last good theme
+        std::fs::write(path, "[light]\naccent=blue\n").unwrap();
+        service.poll(&manager, &themes).unwrap();
+        assert_eq!(themes.theme().typography.size, 16);
+        assert!(changes.try_recv().is_err());
+
+        vxtheme::send_request(&manager, 1, "variant dark").unwrap();
+        vxtheme::send_request(&manager, 2, "current").unwrap();
+        vxtheme::send_request(&manager, 3, "variant dim").unwrap();
+        assert_eq!(service.poll(&manager, &themes).unwrap(), 3);
+        assert_eq!(
+            drain(&manager, vxtheme::REPLY_CHANNEL),
+            vec!["1 ok", "2 ok\nocean dark", "3 error Unknown theme variant"]
+        );
+        assert_eq!(changes.try_recv().unwrap().variant, Variant::Dark);
+        assert_eq!(drain(&manager, vxtheme::EVENT_CHANNEL), vec!["theme ocean dark"]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    pub fn test_accessibility() {
+        // High contrast ignores the theme's class colors; the font scale
+        // enlarges every text size
+        let theme = Theme::parse("[button]\nbackground=#102030\n[high-contrast]\naccent=#FFFF00\n[typography]\nsize=12\nscale=1.5\n").unwrap();
+        assert_eq!(theme.high_contrast.accent, 0xFFFF_FF00);
+        assert_eq!(theme.typography.scaled_size(), 18);
+        assert_eq!(Theme::parse(&theme.serialize()).unwrap(), theme);
+        assert_eq!(Theme::parse("[typography]\nscale=9\n").unwrap().typography.scale, 4.0);
+        let focused = WidgetState { focused: true, ..WidgetState::default() };
+        let button = theme.resolve(Variant::HighContrast, WidgetClass::Button, focused, &StyleOverride::default());
+        assert_eq!((button.background, button.foreground, button.border), (0xFF00_0000, 0xFFFF_FFFF, 0xFFFF_FF00));
+        assert_eq!((button.text_size, theme.resolve(Variant::Dark, WidgetClass::Button, focused, &StyleOverride::default()).background), (18, 0xFF10_2030));
+
+        let themes = ThemeManager::new(Theme::default());
+        let manager = VXChanManager::new();
+        let mut service = ThemeService::new(&manager, &themes).unwrap();
+        vxtheme::send_request(&manager, 1, "variant high-contrast").unwrap();
+        vxtheme::send_request(&manager, 2, "font-scale 2").unwrap();
+        vxtheme::send_request(&manager, 3, "font-scale big").unwrap();
+        service.poll(&manager, &themes).unwrap();
+        assert_eq!(drain(&manager, vxtheme::REPLY_CHANNEL), vec!["1 ok", "2 ok", "3 error Invalid number"]);
+        assert_eq!((themes.variant(), themes.theme().typography.scaled_size()), (Variant::HighContrast, 28));
+        assert_eq!(drain(&manager, vxtheme::EVENT_CHANNEL), vec!["theme vaelix high-contrast", "theme vaelix high-contrast"]);
+
+        // Tab walks the focusable widgets in tree order and wraps around
+        let mut tree = WidgetTree::new(
+            Element::container(Direction::Column)
+                .child(Element::label("Sign in").name("title"))
+                .child(Element::text_input("User").name("user"))
+                .child(Element::container(Direction::Row).child(Element::secret_input("Password").name("password")))
+                .child(Element::list(&["Wayland", "X11"]).name("session"))
+                .child(Element::button("OK").name("ok")),
+            200,
+            200,
+        );
+        let find = |tree: &WidgetTree, name: &str| tree.find(name).unwrap();
+        let (user, password, session, ok) = (find(&tree, "user"), find(&tree, "password"), find(&tree, "session"), find(&tree, "ok"));
+        let events = tree.subscribe_accessibility();
+        tree.key(KEY_TAB, true);
+        tree.key(KEY_TAB, false);
+        assert_eq!(tree.focus(), Some(user));
+        tree.key(KEY_LEFTSHIFT, true);
+        tree.key(KEY_TAB, true);
+        assert_eq!(tree.focus(), Some(ok));
+        tree.key(KEY_TAB, true);
+        assert_eq!(tree.focus(), Some(session));
+        tree.key(KEY_LEFTSHIFT, false);
+        tree.key(KEY_TAB, true);
+        assert_eq!(tree.focus(), Some(ok));
+        tree.focus_next(true);
+        tree.focus_next(true);
+        assert_eq!(tree.focus(), Some(password));
+        let focus: Vec<Option<WidgetId>> = events
+            .try_iter()
+            .map(|event| match event {
+                AccessibilityEvent::FocusChanged(node) => node.map(|node| node.id),
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect();
+        assert_eq!(focus, vec![Some(user), Some(ok), Some(session), Some(ok), Some(user), Some(password)]);
+
+        // Text and selection changes are announced; passwords stay masked
+        for character in "hunter2".chars() {
+            tree.text_input(character);
+        }
+        tree.key(KEY_BACKSPACE, true);
+        let last = events.try_iter().last().unwrap();
+        let AccessibilityEvent::TextChanged(node) = last else {
+            panic!("expected a text change");
+        };
+        assert_eq!((node.id, node.role, node.text.as_str(), node.focused), (password, Role::PasswordInput, "******", true));
+        tree.set_focus(Some(session)).unwrap();
+        tree.key(KEY_DOWN, true);
+        tree.set_text(find(&tree, "title"), "Welcome").unwrap();
+        let announced: Vec<AccessibilityEvent> = events.try_iter().collect();
+        assert!(matches!(&announced[1], AccessibilityEvent::SelectionChanged(node) if node.text == "Wayland"));
+        assert!(matches!(&announced[2], AccessibilityEvent::TextChanged(node) if node.role == Role::Label && node.text == "Welcome"));
+        tree.select(session, Some(0)).unwrap();
+        assert!(events.try_recv().is_err());
+
+        let outline: Vec<(usize, Role, Option<String>)> =
+            tree.accessibility_tree().into_iter().map(|(depth, node)| (depth, node.role, node.name)).collect();
+        let name = |name: &str| Some(name.to_string());
+        assert_eq!(
+            outline,
+            vec![
+                (0, Role::Group, None),
+                (1, Role::Label, name("title")),
+                (1, Role::TextInput, name("user")),
+                (1, Role::Group, None),
+                (2, Role::PasswordInput, name("password")),
+                (1, Role::List, name("session")),
+                (1, Role::Button, name("ok")),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_notification_daemon_and_overlay() {
+        let path = std::env::temp_dir().join(format!("vxnotify-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+        let mut daemon = NotificationDaemon::load(path).unwrap();
+        let low = daemon.notify(Notification::new("mail", "Low", "", Urgency::Low));
+        let normal = daemon.notify(Notification::new("mail", "Normal", "", Urgency::Normal).sender("mail.notify"));
+        let third = daemon.notify(Notification::new("chat", "Third", "", Urgency::Normal));
+        let critical = daemon.notify(Notification::new("power", "Battery low", "5% left", Urgency::Critical));
+        // Three slots were already taken when the critical one arrived
+        assert_eq!(daemon.visible(), vec![low, normal, third]);
+        assert_eq!(daemon.queued(), vec![critical]);
+
+        // Expiry frees a slot and the critical one jumps the queue
+        daemon.tick(4_000_000);
+        assert_eq!(daemon.visible(), vec![normal, third, critical]);
+        daemon.tick(100_000_000);
+        assert_eq!(daemon.visible(), vec![critical]);
+
+        // Do-not-disturb holds back everything but critical notifications
+        daemon.set_do_not_disturb(true);
+        let quiet = daemon.notify(Notification::new("chat", "Quiet", "", Urgency::Normal).action("open", "Open"));
+        assert_eq!(daemon.visible(), vec![critical]);
+        assert_eq!(daemon.queued(), vec![quiet]);
+
+        // Pending notifications and the mode survive a restart
+        let mut daemon = NotificationDaemon::load(path).unwrap();
+        assert!(daemon.do_not_disturb());
+        assert_eq!(daemon.visible(), vec![critical]);
+        assert_eq!(daemon.get(critical).unwrap().body, "5% left");
+        assert_eq!(daemon.get(quiet).unwrap().actions[0].label, "Open");
+        assert_eq!(daemon.notify(Notification::new("a", "b", "", Urgency::Low)), NotificationId(quiet.0 + 1));
+
+        // Actions and close reasons are routed back to the sender
+        let manager = VXChanManager::new();
+        manager.create_channel("app.notify").unwrap();
+        let mut service = NotificationService::new(&manager).unwrap();
+        vxnotify::send_request(&manager, 1, "notify app normal never app.notify\nUpdate ready\nRestart now?\naction restart Restart now").unwrap();
+        vxnotify::send_request(&manager, 2, "dnd off").unwrap();
+        vxnotify::send_request(&manager, 3, "notify app loud default -\nx").unwrap();
+        assert_eq!(service.poll(&manager, &mut daemon, 0).unwrap(), 3);
+        let update = NotificationId(quiet.0 + 2);
+        assert_eq!(
+            drain(&manager, vxnotify::REPLY_CHANNEL),
+            vec![format!("1 ok\n{}", update.0), "2 ok".to_string(), "3 error Unknown urgency".to_string()]
+        );
+        assert_eq!(daemon.get(update).unwrap().timeout, None);
+        vxnotify::send_request(&manager, 4, &format!("invoke {} restart", update.0)).unwrap();
+        vxnotify::send_request(&manager, 5, &format!("invoke {} restart", update.0)).unwrap();
+        service.poll(&manager, &mut daemon, 0).unwrap();
+        assert_eq!(drain(&manager, vxnotify::REPLY_CHANNEL), vec!["4 ok", "5 error Notification not found"]);
+        assert_eq!(
+            drain(&manager, "app.notify"),
+            vec![format!("action {} restart", update.0), format!("closed {} dismissed", update.0)]
+        );
+
+        // The overlay stacks visible notifications in the top-right corner
+        let mut compositor = Compositor::new(640, 480);
+        let mut overlay = NotificationOverlay::new(&mut compositor, 200);
+        overlay.update(&mut daemon, &mut compositor).unwrap();
+        let (width, height) = compositor.size(overlay.window()).unwrap();
+        assert_eq!(width, 200);
+        assert!(height > 0);
+        assert_eq!(compositor.window_at(440, 10), Some(overlay.window()));
+        assert_eq!(compositor.window_at(428, 10), None);
+
+        // Clicking a card's close button dismisses it
+        let first = daemon.visible()[0];
+        overlay.pointer_motion(190, 16);
+        overlay.pointer_button(190, 16, true);
+        overlay.pointer_button(190, 16, false);
+        overlay.update(&mut daemon, &mut compositor).unwrap();
+        assert!(daemon.get(first).is_none());
+
+        for id in daemon.visible().into_iter().chain(daemon.queued()) {
+            daemon.close(id, CloseReason::Closed).unwrap();
+        }
+        overlay.update(&mut daemon, &mut compositor).unwrap();
+        assert_eq!(compositor.window_at(440, 10), None);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    struct FakeAccounts;
+
+    impl AccountService for FakeAccounts {
+        fn accounts(&self) -> Vec<Account> {
+            vec![Account { user: "ada".to_string(), name: "Ada".to_string(), uid: 1000, home: "/home/ada".to_string() }]
+        }
+
+        fn authenticate(&mut self, user: &str, password: &str) -> Result<Account, &'static str> {
+            match (user, password) {
+                ("ada", "secret") => Ok(self.accounts().remove(0)),
+                _ => Err("Wrong user or password"),
+            }
+        }
+    }
+
+    // Logs starts and stops; the test crashes it by clearing `running`
+    struct FakeComponent {
+        name: &'static str,
+        essential: bool,
+        fail_start: bool,
+        running: Arc<Mutex<bool>>,
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl FakeComponent {
+        fn new(name: &'static str, essential: bool, log: &Arc<Mutex<Vec<String>>>) -> (Box<Self>, Arc<Mutex<bool>>) {
+            let running = Arc::new(Mutex::new(false));
+            let component = FakeComponent { name, essential, fail_start: false, running: running.clone(), log: log.clone() };
+            (Box::new(component), running)
+        }
+    }
+
+    impl Component for FakeComponent {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn essential(&self) -> bool {
+            self.essential
+        }
+
+        fn start(&mut self, account: &Account) -> Result<(), &'static str> {
+            if self.fail_start {
+                return Err("Broken");
+            }
+            self.log.lock().unwrap().push(format!("start {} {}", self.name, account.user));
+            *self.running.lock().unwrap() = true;
+            Ok(())
+        }
+
+        fn is_running(&mut self) -> bool {
+            *self.running.lock().unwrap()
+        }
+
+        fn stop(&mut self) {
+            self.log.lock().unwrap().push(format!("stop {}", self.name));
+            *self.running.lock().unwrap() = false;
+        }
+    }
+
+    #[test]
+    pub fn test_auth_client() {
+        let manager = VXChanManager::new();
+        let mut database = UserDatabase::new();
+        database.add_user("ada", "Ada Lovelace", "/home/ada", "/bin/vxsh").unwrap();
+        database.add_user("grace", "", "/home/grace", "/bin/vxsh").unwrap();
+        database.set_password("ada", "correct horse").unwrap();
+        let mut client = AuthClient::new(&manager);
+        assert_eq!(client.authenticate("ada", "correct horse"), Err("Channel not found"));
+        let service = AuthService::new(&manager).unwrap();
+        let channels = manager.clone();
+        let mut auth = KernelThread::spawn("auth", move || {
+            service.poll(&channels, &database).unwrap();
+            std::thread::yield_now();
+            true
+        })
+        .unwrap();
+
+        // The greeter lists people by name and logs them in
+        let names: Vec<(String, String, u32)> = client.accounts().into_iter().map(|account| (account.user, account.name, account.uid)).collect();
+        assert_eq!(names, vec![("ada".into(), "Ada Lovelace".into(), 1000), ("grace".into(), "grace".into(), 1001)]);
+        let mut sessions = SessionManager::new(Box::new(client));
+        assert_eq!(sessions.login("ada", "wrong", 0), Err("Authentication failed"));
+        assert_eq!(sessions.login("ada", "correct horse", 0), Ok(()));
+        assert_eq!(sessions.session().unwrap().home, "/home/ada");
+        assert_eq!(sessions.verify("correct horse", 0), Ok(()));
+        auth.stop().unwrap();
+    }
+
+    #[test]
+    pub fn test_session_manager_and_greeter() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut sessions = SessionManager::new(Box::new(FakeAccounts));
+        sessions.add_component(FakeComponent::new("compositor", true, &log).0);
+        sessions.add_component(FakeComponent::new("panel", false, &log).0);
+        let (mut broken, _) = FakeComponent::new("indexer", false, &log);
+        broken.fail_start = true;
+        sessions.add_component(broken);
+
+        // A component that cannot start rolls back the ones before it
+        assert_eq!(sessions.login("ada", "secret", 0), Err("Session component failed to start"));
+        assert_eq!(*log.lock().unwrap(), vec!["start compositor ada", "start panel ada", "stop panel", "stop compositor"]);
+        assert!(sessions.session().is_none());
+
+        let mut sessions = SessionManager::new(Box::new(FakeAccounts));
+        let (compositor_component, compositor_running) = FakeComponent::new("compositor", true, &log);
+        sessions.add_component(compositor_component);
+        let (panel, panel_running) = FakeComponent::new("panel", false, &log);
+        sessions.add_component(panel);
+        log.lock().unwrap().clear();
+
+        // Repeated failures lock the greeter out for a while
+        for _ in 0..vxde::MAX_ATTEMPTS {
+            assert_eq!(sessions.login("ada", "guess", 0), Err("Wrong user or password"));
+        }
+        assert_eq!(sessions.login("ada", "secret", 1_000_000), Err("Too many failed attempts"));
+        assert!(log.lock().unwrap().is_empty());
+
+        // The greeter logs in with the selected user and hides itself
+        let manager = VXChanManager::new();
+        let mut service = SessionService::new(&manager, &mut sessions).unwrap();
+        let mut compositor = Compositor::new(640, 480);
+        let mut greeter = Greeter::new(&mut compositor, &sessions);
+        greeter.update(&mut sessions, &mut compositor, 0).unwrap();
+        assert_eq!(compositor.window_at(320, 240), Some(greeter.window()));
+        for character in "secret".chars() {
+            greeter.text_input(character);
+        }
+        let password = greeter.tree().find("password").unwrap();
+        assert_eq!(greeter.tree().text(password), Some("secret"));
+        greeter.key(KEY_ENTER, true);
+        greeter.key(KEY_ENTER, false);
+        greeter.update(&mut sessions, &mut compositor, 1_000_000).unwrap();
+        let message = greeter.tree().find("message").unwrap();
+        assert_eq!(greeter.tree().text(message), Some("Too many failed attempts"));
+        assert_eq!(greeter.tree().text(password), Some(""));
+        for character in "secret".chars() {
+            greeter.text_input(character);
+        }
+        greeter.key(KEY_ENTER, true);
+        greeter.update(&mut sessions, &mut compositor, vxde::LOCKOUT).unwrap();
+        assert_eq!(sessions.session().map(|account| account.uid), Some(1000));
+        assert_eq!(greeter.tree().text(message), Some(""));
+        assert_eq!(compositor.window_at(320, 240), None);
+        assert_eq!(*log.lock().unwrap(), vec!["start compositor ada", "start panel ada"]);
+
+        // Crashed components come back after a doubling delay until they
+        // crash too often
+        let now = vxde::LOCKOUT;
+        *panel_running.lock().unwrap() = false;
+        sessions.tick(now);
+        assert_eq!(sessions.component_state("panel"), Some(ComponentState::Restarting { at: now + vxde::RESTART_DELAY }));
+        sessions.tick(now + vxde::RESTART_DELAY);
+        assert_eq!(sessions.component_state("panel"), Some(ComponentState::Running));
+        let mut at = now + vxde::RESTART_DELAY;
+        for _ in 1..vxde::MAX_RESTARTS {
+            *panel_running.lock().unwrap() = false;
+            sessions.tick(at);
+            let Some(ComponentState::Restarting { at: next }) = sessions.component_state("panel") else {
+                panic!("panel should be restarting");
+            };
+            assert!(next - at > vxde::RESTART_DELAY);
+            at = next;
+            sessions.tick(at);
+        }
+        *panel_running.lock().unwrap() = false;
+        sessions.tick(at);
+        assert_eq!(sessions.component_state("panel"), Some(ComponentState::Failed));
+        assert!(sessions.session().is_some());
+
+        // Status over vxchan, and the events so far
+        vxde::send_request(&manager, 1, "status").unwrap();
+        vxde::send_request(&manager, 2, "reboot").unwrap();
+        assert_eq!(service.poll(&manager, &mut sessions, at).unwrap(), 2);
+        assert_eq!(
+            drain(&manager, vxde::REPLY_CHANNEL),
+            vec!["1 ok\nsession ada\ncompositor running\npanel failed", "2 error Unknown command"]
+        );
+        let events = drain(&manager, vxde::EVENT_CHANNEL);
+        assert_eq!(events[0], "started ada");
+        assert_eq!(events.iter().filter(|event| *event == "restarted panel").count(), vxde::MAX_RESTARTS);
+        assert_eq!(events.last().unwrap(), "failed panel");
+
+        // Logout stops what is still running, last started first
+        log.lock().unwrap().clear();
+        vxde::send_request(&manager, 3, "logout").unwrap();
+        service.poll(&manager, &mut sessions, at).unwrap();
+        assert_eq!(drain(&manager, vxde::REPLY_CHANNEL), vec!["3 ok"]);
+        assert_eq!(drain(&manager, vxde::EVENT_CHANNEL), vec!["ended ada"]);
+        assert_eq!(*log.lock().unwrap(), vec!["stop compositor"]);
+        assert_eq!(sessions.component_state("panel"), Some(ComponentState::Stopped));
+        greeter.update(&mut sessions, &mut compositor, at).unwrap();
+        assert_eq!(compositor.window_at(320, 240), Some(greeter.window()));
+
+        // An essential component that keeps crashing ends the session
+        sessions.login("ada", "secret", at).unwrap();
+        for _ in 0..=vxde::MAX_RESTARTS {
+            *compositor_running.lock().unwrap() = false;
+            sessions.tick(at);
+            at += 10_000_000;
+            sessions.tick(at);
+        }
+        assert!(sessions.session().is_none());
+        assert_eq!(sessions.component_state("compositor"), Some(ComponentState::Stopped));
+    }
+
+    #[test]
+    pub fn test_panel_and_launcher() {
+        // Manifests: field codes are dropped, hidden entries and other
+        // groups are skipped
+        let terminal = "[Desktop Entry]\nType=Application\nName=Terminal\nExec=vxterm %U\nKeywords=shell;console;\n\
+                        [Desktop Action new]\nName=New Window\n";
+        let terminal = AppEntry::parse("vxterm", terminal).unwrap().unwrap();
+        assert_eq!((terminal.exec.as_str(), terminal.keywords.len()), ("vxterm", 2));
+        assert_eq!(AppEntry::parse("x", "[Desktop Entry]\nName=X\nExec=x\nNoDisplay=true\n"), Ok(None));
+        assert_eq!(AppEntry::parse("x", "[Desktop Entry]\nType=Link\nName=X\n"), Ok(None));
+        assert_eq!(AppEntry::parse("x", "[Desktop Entry]\nName=X\n"), Err("Missing Exec"));
+
+        let directory = std::env::temp_dir().join(format!("vxpanel-{}", std::process::id()));
+        let user = directory.join("user");
+        std::fs::create_dir_all(&user).unwrap();
+        let system = directory.join("system");
+        std::fs::create_dir_all(&system).unwrap();
+        let write = |path: std::path::PathBuf, name: &str, exec: &str, extra: &str| {
+            std::fs::write(path, format!("[Desktop Entry]\nName={}\nExec={}\n{}", name, exec, extra)).unwrap();
+        };
+        write(system.join("vxterm.desktop"), "Terminal", "vxterm", "Keywords=shell;\n");
+        write(user.join("vxterm.desktop"), "My Terminal", "vxterm --login", "");
+        write(system.join("files.desktop"), "Files", "vxfiles", "Comment=Browse the file system\n");
+        write(system.join("monitor.desktop"), "System Monitor", "vxtop", "Categories=System;\n");
+        std::fs::write(system.join("broken.desktop"), "[Desktop Entry]\nName\n").unwrap();
+        std::fs::write(system.join("notes.txt"), "").unwrap();
+        let (user, system) = (user.to_str().unwrap().to_string(), system.to_str().unwrap().to_string());
+        let catalog = AppCatalog::load(&[&user, "/nonexistent", &system]);
+        let names = |entries: Vec<&AppEntry>| entries.iter().map(|entry| entry.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names(catalog.search("")), vec!["Files", "My Terminal", "System Monitor"]);
+        assert_eq!(names(catalog.search("ter")), vec!["My Terminal"]);
+        assert_eq!(names(catalog.search("SYS")), vec!["System Monitor", "Files"]);
+        assert_eq!(names(catalog.search("mon sys")), vec!["System Monitor"]);
+        assert!(catalog.search("zzz").is_empty());
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        // Status comes from the power services' events and vxnetctl
+        let manager = VXChanManager::new();
+        for channel in [power_button::EVENT_CHANNEL, power_profile::EVENT_CHANNEL, "vxnetctl", "vxnetctl.reply"] {
+            manager.create_channel(channel).unwrap();
+        }
+        let mut monitor = StatusMonitor::new();
+        manager.send_message(power_button::EVENT_CHANNEL, "ac unplugged".to_string()).unwrap();
+        manager.send_message(power_button::EVENT_CHANNEL, "lid closed".to_string()).unwrap();
+        manager.send_message(power_profile::EVENT_CHANNEL, "profile quiet".to_string()).unwrap();
+        assert_eq!(monitor.poll(&manager, 0).unwrap(), vec![PowerEvent::AcUnplugged, PowerEvent::LidClosed]);
+        assert_eq!(monitor.status().power_text(), "Battery (quiet)");
+        assert_eq!(monitor.status().network_text(), "");
+        let request = drain(&manager, "vxnetctl");
+        assert_eq!(request.len(), 1);
+        let (id, command) = request[0].split_once(' ').unwrap();
+        assert_eq!(command, "list");
+        // Someone else's reply is left for them
+        manager.send_message("vxnetctl.reply", "7 ok".to_string()).unwrap();
+        manager
+            .send_message(
+                "vxnetctl.reply",
+                format!(
+                    "{} ok\neth0 index=0 kind=ether admin=up carrier=up mtu=1500 mac=02:00:00:00:00:01 addresses=10.0.0.2/24\n\
+                     wlan0 index=1 kind=ether admin=up carrier=down mtu=1500 mac=02:00:00:00:00:02 addresses=",
+                    id
+                ),
+            )
+            .unwrap();
+        monitor.poll(&manager, 1_000_000).unwrap();
+        assert_eq!(monitor.status().network_text(), "eth0");
+        assert_eq!(drain(&manager, "vxnetctl.reply"), vec!["7 ok"]);
+        assert!(drain(&manager, "vxnetctl").is_empty());
+        monitor.poll(&manager, vxpanel::NETWORK_POLL).unwrap();
+        assert_eq!(drain(&manager, "vxnetctl").len(), 1);
+
+        assert_eq!(vxpanel::clock_text(1_700_000_000_000_000, 0), "22:13");
+        assert_eq!(vxpanel::clock_text(1_700_000_000_000_000, 2 * 3600), "00:13");
+
+        // The panel lists windows along the bottom of the screen
+        let mut wm = WindowManager::new(640, 480, Box::new(WindowDecorations::default()));
+        let editor = wm.create_surface("Editor", 100, 80);
+        let shell = wm.create_surface("Shell", 100, 80);
+        let mut panel = Panel::new(wm.compositor_mut(), catalog);
+        let status = SystemStatus { ac: Some(true), profile: None, network: Some(Vec::new()) };
+        panel.update(&mut wm, &status, 1_700_000_000_000_000).unwrap();
+        assert_eq!(wm.compositor().window_at(5, 470), Some(panel.window()));
+        let tree = panel.tree().unwrap();
+        let labels: Vec<&str> = ["network", "power", "clock"].iter().map(|name| tree.text(tree.find(name).unwrap()).unwrap()).collect();
+        assert_eq!(labels, vec!["Offline", "AC", "22:13"]);
+        let root = tree.root();
+        let buttons: Vec<WidgetId> = tree.children(root).to_vec();
+        assert_eq!(tree.text(buttons[1]), Some("Editor"));
+        assert_eq!(tree.text(buttons[2]), Some("Shell"));
+
+        let press = |panel: &mut Panel, wm: &mut WindowManager, widget: WidgetId| {
+            let bounds = panel.tree_mut().unwrap().bounds(widget).unwrap();
+            let (x, y) = (bounds.x + bounds.width as i32 / 2, bounds.y + bounds.height as i32 / 2);
+            panel.pointer_button(x, y, true);
+            panel.pointer_button(x, y, false);
+            panel.update(wm, &status, 1_700_000_000_000_000).unwrap();
+        };
+        wm.focus(shell).unwrap();
+        press(&mut panel, &mut wm, buttons[1]);
+        assert_eq!(wm.focused(), Some(editor));
+        // A second click minimizes it, a third brings it back
+        let buttons: Vec<WidgetId> = panel.tree().unwrap().children(root).to_vec();
+        press(&mut panel, &mut wm, buttons[1]);
+        assert_eq!(wm.state(editor), Some(WindowState::Minimized));
+        let tree = panel.tree().unwrap();
+        assert_eq!(tree.text(tree.children(tree.root())[1]), Some("[Editor]"));
+        let buttons: Vec<WidgetId> = tree.children(tree.root()).to_vec();
+        press(&mut panel, &mut wm, buttons[1]);
+        assert_eq!((wm.state(editor), wm.focused()), (Some(WindowState::Normal), Some(editor)));
+
+        // The launcher opens from the panel, filters as you type and
+        // queues the chosen entry
+        press(&mut panel, &mut wm, buttons[0]);
+        assert!(panel.launcher().is_open());
+        let launcher_window = panel.launcher().window();
+        assert_eq!(wm.compositor().window_at(320, 240), Some(launcher_window));
+        let launcher = panel.launcher_mut();
+        for character in "sys".chars() {
+            launcher.text_input(character);
+        }
+        launcher.update(wm.compositor_mut()).unwrap();
+        let results = launcher.tree().find("results").unwrap();
+        assert!(matches!(launcher.tree().kind(results), Some(WidgetKind::List { items, .. }) if items.len() == 2));
+        launcher.key(KEY_DOWN, true);
+        launcher.key(KEY_ENTER, true);
+        launcher.update(wm.compositor_mut()).unwrap();
+        assert_eq!(launcher.take_launches().iter().map(|entry| entry.exec.as_str()).collect::<Vec<_>>(), vec!["vxfiles"]);
+        assert!(!launcher.is_open());
+        assert_eq!(wm.compositor().window_at(320, 240), None);
+        launcher.open();
+        launcher.key(KEY_ESC, true);
+        assert!(!launcher.is_open() && launcher.take_launches().is_empty());
+    }
+
+    #[test]
+    pub fn test_wallpaper_and_lock_screen() {
+        let mut ppm = b"P6\n# red and blue\n2 1\n15\n".to_vec();
+        ppm.extend_from_slice(&[15, 0, 0, 0, 0, 15]);
+        let image = Image::decode_ppm(&ppm).unwrap();
+        assert_eq!(image.pixels(), &[0xFFFF_0000, 0xFF00_00FF]);
+        assert_eq!(Image::decode_ppm(b"P3\n1 1\n255\n0 0 0"), Err("Not a binary PPM"));
+        assert_eq!(Image::decode_ppm(b"P6\n2 2\n255\n\0\0\0"), Err("Truncated PPM data"));
+        assert_eq!(image.scaled(4, 1).pixels(), &[0xFFFF_0000, 0xFFBF_0040, 0xFF40_00BF, 0xFF00_00FF]);
+
+        // Fit letterboxes the image in the output's physical pixels
+        let left = Viewport::new(Rect::new(0, 0, 320, 480), 1.0);
+        let right = Viewport::new(Rect::new(320, 0, 320, 480), 2.0);
+        let fit = WallpaperConfig { image: Some(Arc::new(image)), mode: WallpaperMode::Fit, color: 0xFF10_1010 };
+        let buffer = vxwallpaper::render(&fit, &right);
+        assert_eq!((buffer.width(), buffer.height()), (640, 960));
+        assert_eq!(buffer.pixel(10, 100), Some(0xFF10_1010));
+        assert_eq!(buffer.pixel(10, 400), Some(0xFFFF_0000));
+        assert_eq!(buffer.pixel(630, 400), Some(0xFF00_00FF));
+        let tiled = vxwallpaper::render(&WallpaperConfig { mode: WallpaperMode::Tile, ..fit.clone() }, &left);
+        assert_eq!((tiled.pixel(2, 0), tiled.pixel(3, 5)), (Some(0xFFFF_0000), Some(0xFF00_00FF)));
+        assert_eq!(WallpaperMode::parse("stretch").map(|mode| mode.name()), Ok("stretch"));
+
+        // A background per output, under everything else
+        let mut wm = WindowManager::new(640, 480, Box::new(WindowDecorations::default()));
+        let surface = wm.create_surface("Editor", 100, 80);
+        let mut wallpaper = Wallpaper::new();
+        wallpaper.set_output("right", Some(fit));
+        wallpaper.update(&[("left", left), ("right", right)], wm.compositor_mut()).unwrap();
+        let compositor = wm.compositor_mut();
+        let (left_window, right_window) = (wallpaper.window("left").unwrap(), wallpaper.window("right").unwrap());
+        assert_eq!(compositor.size(right_window), Some((640, 960)));
+        assert_eq!(compositor.window_at(600, 400), Some(right_window));
+        assert_eq!(&compositor.stacking_order()[..2], &[right_window, left_window]);
+        wallpaper.update(&[("left", left)], compositor).unwrap();
+        assert_eq!(wallpaper.window("right"), None);
+        assert_eq!(compositor.window_at(600, 400), None);
+
+        // The lock screen engages after the idle timeout and covers every
+        // output until the session user's password is given
+        let mut sessions = SessionManager::new(Box::new(FakeAccounts));
+        let mut lock = LockScreen::new(compositor);
+        lock.set_idle_timeout(Some(10_000_000));
+        let outputs = [("left", left), ("right", Viewport::new(Rect::new(320, 0, 320, 480), 1.0))];
+        lock.update(&mut sessions, &outputs, compositor, 20_000_000).unwrap();
+        assert!(!lock.is_locked());
+        sessions.login("ada", "secret", 20_000_000).unwrap();
+        lock.update(&mut sessions, &outputs, compositor, 25_000_000).unwrap();
+        assert!(!lock.is_locked());
+        lock.activity(28_000_000);
+        lock.update(&mut sessions, &outputs, compositor, 37_000_000).unwrap();
+        assert!(!lock.is_locked());
+        lock.update(&mut sessions, &outputs, compositor, 38_000_000).unwrap();
+        assert!(lock.is_locked());
+        let covered = |compositor: &Compositor| [(10, 10), (600, 400)].map(|(x, y)| compositor.window_at(x, y));
+        let covers = covered(compositor);
+        assert!(covers.iter().all(|window| window.is_some() && *window != Some(left_window)));
+        assert_eq!(compositor.window_at(160, 240), Some(lock.prompt_window()));
+        let user = lock.tree().find("user").unwrap();
+        assert_eq!(lock.tree().text(user), Some("Ada"));
+
+        // Windows raised meanwhile go back under the covers
+        wm.focus(surface).unwrap();
+        let compositor = wm.compositor_mut();
+        lock.update(&mut sessions, &outputs, compositor, 38_000_000).unwrap();
+        assert_eq!(covered(compositor), covers);
+
+        for character in "1234".chars() {
+            lock.text_input(character);
+        }
+        lock.key(KEY_ENTER, true);
+        lock.update(&mut sessions, &outputs, compositor, 39_000_000).unwrap();
+        let message = lock.tree().find("message").unwrap();
+        assert_eq!(lock.tree().text(message), Some("Wrong user or password"));
+        assert!(lock.is_locked());
+        for character in "secret".chars() {
+            lock.text_input(character);
+        }
+        lock.key(KEY_ENTER, true);
+        lock.update(&mut sessions, &outputs, compositor, 40_000_000).unwrap();
+        assert!(!lock.is_locked());
+        assert_eq!(compositor.window_at(600, 400), None);
+        assert_eq!(compositor.window_at(160, 240), Some(left_window));
+
+        // Closing the lid locks at once; logging out drops the lock
+        lock.power_event(PowerEvent::LidClosed);
+        lock.update(&mut sessions, &outputs, compositor, 41_000_000).unwrap();
+        assert!(lock.is_locked());
+        sessions.logout().unwrap();
+        lock.update(&mut sessions, &outputs, compositor, 42_000_000).unwrap();
+        assert!(!lock.is_locked());
+        assert_eq!(compositor.window_at(160, 240), Some(left_window));
+    }
+
+    // A connector whose display the test keeps a handle on
+    struct FakeOutput {
+        name: &'static str,
+        modes: Vec<Mode>,
+        edid: Option<Vec<u8>>,
+        display: Arc<Mutex<FakeDisplay>>,
+    }
+
+    impl FakeOutput {
+        fn connector(
+            name: &'static str,
+            modes: &[(u32, u32)],
+            edid: Option<Vec<u8>>,
+        ) -> (Box<dyn Output>, Arc<Mutex<FakeDisplay>>) {
+            let modes: Vec<Mode> = modes
+                .iter()
+                .map(|&(width, height)| Mode { width, height, refresh: 60_000 })
+                .collect();
+            let display = Arc::new(Mutex::new(FakeDisplay::new(modes[0].width, modes[0].height)));
+            (Box::new(FakeOutput { name, modes, edid, display: display.clone() }), display)
+        }
+    }
+
+    impl Display for FakeOutput {
+        fn resolution(&self) -> (u32, u32) {
+            self.display.lock().unwrap().resolution()
+        }
+
+        fn scanout(&mut self, frame: &[u32], damage: &[Rect]) -> Result<(), &'static str> {
+            self.display.lock().unwrap().scanout(frame, damage)
+        }
+
+        fn wait_vblank(&mut self) -> Result<u64, &'static str> {
+            self.display.lock().unwrap().wait_vblank()
+        }
+
+        fn cursor_size(&self) -> Option<(u32, u32)> {
+            self.display.lock().unwrap().cursor_size()
+        }
+
+        fn set_cursor(&mut self, image: Option<&Buffer>) -> Result<(), &'static str> {
+            self.display.lock().unwrap().set_cursor(image)
+        }
+
+        fn move_cursor(&mut self, x: i32, y: i32) -> Result<(), &'static str> {
+            self.display.lock().unwrap().move_cursor(x, y)
+        }
+    }
+
+    impl Output for FakeOutput {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn modes(&self) -> Vec<Mode> {
+            self.modes.clone()
+        }
+
+        fn set_mode(&mut self, mode: Mode) -> Result<(), &'static str> {
+            *self.display.lock().unwrap() = FakeDisplay::new(mode.width, mode.height);
+            Ok(())
+        }
+
+        fn edid(&self) -> Option<Vec<u8>> {
+            self.edid.clone()
+        }
+    }
+
+    #[test]
+    pub fn test_multiple_outputs_and_hotplug() {
+        let mut wm = WindowManager::new(640, 480, Box::new(WindowDecorations::default()));
+        let mut outputs = OutputManager::new(LayoutMode::Extend);
+        let hotplug = outputs.hotplug_sender();
+        let (panel, panel_display) = FakeOutput::connector("eDP-1", &[(640, 480), (320, 240)], None);
+        let (hdmi, hdmi_display) = FakeOutput::connector("HDMI-1", &[(320, 240)], None);
+        hotplug.send(Hotplug::Connected(panel)).unwrap();
+        hotplug.send(Hotplug::Connected(hdmi)).unwrap();
+        assert_eq!(outputs.process_hotplug(&mut wm).unwrap(), 2);
+        let areas: Vec<(&str, Rect)> = outputs.outputs().into_iter().map(|(name, viewport)| (name, viewport.area)).collect();
+        assert_eq!(areas, vec![("eDP-1", Rect::new(0, 0, 640, 480)), ("HDMI-1", Rect::new(640, 0, 320, 240))]);
+        assert_eq!(wm.compositor().resolution(), (960, 480));
+
+        // A window dragged onto the second output is scanned out there
+        let surface = wm.create_surface("Editor", 100, 80);
+        let buffer = Buffer::new(100, 80, BufferStorage::SharedMemory);
+        buffer.fill(Rect::new(0, 0, 100, 80), 0xFF00_FF00);
+        let buffer = wm.register_buffer(buffer);
+        wm.attach(surface, buffer).unwrap();
+        wm.commit(surface).unwrap();
+        click(&mut wm, 10, 10);
+        wm.pointer_motion(710, 10);
+        wm.pointer_button(BTN_LEFT, false).unwrap();
+        assert_eq!(wm.geometry(surface), Some((700, 0, 100, 80)));
+        // Beneath the shorter output the pointer stays on screen
+        wm.pointer_motion(900, 400);
+        assert_eq!(wm.pointer_position(), (900, 239));
+        outputs.run_frame(wm.compositor_mut()).unwrap().unwrap();
+        assert_eq!(hdmi_display.lock().unwrap().pixel(90, 50), 0xFF00_FF00);
+        assert_ne!(panel_display.lock().unwrap().pixel(630, 50), 0xFF00_FF00);
+
+        // Unplugging it brings the window back to the panel
+        hotplug.send(Hotplug::Disconnected("HDMI-1".to_string())).unwrap();
+        outputs.process_hotplug(&mut wm).unwrap();
+        assert_eq!(wm.compositor().resolution(), (640, 480));
+        assert_eq!(wm.geometry(surface), Some((60, 0, 100, 80)));
+        outputs.run_frame(wm.compositor_mut()).unwrap().unwrap();
+        assert_eq!(panel_display.lock().unwrap().pixel(90, 50), 0xFF00_FF00);
+
+        // Mirroring drops the panel to the mode both outputs have
+        let (hdmi, hdmi_display) = FakeOutput::connector("HDMI-1", &[(320, 240)], None);
+        outputs.connect(hdmi).unwrap();
+        outputs.set_layout(LayoutMode::Mirror);
+        outputs.apply(&mut wm).unwrap();
+        assert_eq!(panel_display.lock().unwrap().resolution(), (320, 240));
+        assert_eq!(outputs.viewport("HDMI-1").map(|viewport| viewport.area), Some(Rect::new(0, 0, 320, 240)));
+        outputs.run_frame(wm.compositor_mut()).unwrap().unwrap();
+        assert_eq!(panel_display.lock().unwrap().pixel(90, 50), 0xFF00_FF00);
+        assert_eq!(hdmi_display.lock().unwrap().pixel(90, 50), 0xFF00_FF00);
+
+        // Configured modes and positions apply when extending again
+        outputs.set_layout(LayoutMode::Extend);
+        outputs.configure("HDMI-1", OutputConfig { position: Some((-320, 0)), ..OutputConfig::default() });
+        outputs.apply(&mut wm).unwrap();
+        assert_eq!(outputs.viewport("HDMI-1").map(|viewport| viewport.area), Some(Rect::new(0, 0, 320, 240)));
+        assert_eq!(outputs.viewport("eDP-1").map(|viewport| viewport.area), Some(Rect::new(320, 0, 640, 480)));
+        let unsupported = Mode { width: 1920, height: 1080, refresh: 60_000 };
+        outputs.configure("eDP-1", OutputConfig { mode: Some(unsupported), ..OutputConfig::default() });
+        assert_eq!(outputs.apply(&mut wm), Err("Output does not support the configured mode"));
+        assert_eq!(outputs.viewport("eDP-1").map(|viewport| viewport.area), Some(Rect::new(320, 0, 640, 480)));
+    }
+
+    // Base block with a detailed timing descriptor giving the image size
+    fn edid(width_mm: u32, height_mm: u32) -> Vec<u8> {
+        let mut edid = vec![0u8; 128];
+        edid[..8].copy_from_slice(&[0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00]);
+        edid[21] = (width_mm / 10) as u8;
+        edid[22] = (height_mm / 10) as u8;
+        edid[54..56].copy_from_slice(&14_850u16.to_le_bytes());
+        edid[66] = width_mm as u8;
+        edid[67] = height_mm as u8;
+        edid[68] = ((width_mm >> 8) << 4 | (height_mm >> 8)) as u8;
+        edid
+    }
+
+    #[test]
+    pub fn test_hidpi_scaling() {
+        // A 13 inch 1280x800 panel is about 227 dpi
+        assert_eq!(physical_size(&edid(286, 179)), Some((286, 179)));
+        assert_eq!(physical_size(&edid(0, 0)), None);
+        assert_eq!(physical_size(&[0; 128]), None);
+        let mode = |width, height| Mode { width, height, refresh: 60_000 };
+        assert_eq!(detect_scale(mode(2560, 1600), (286, 179)), 2.25);
+        assert_eq!(detect_scale(mode(1920, 1080), (344, 194)), 1.5);
+        assert_eq!(detect_scale(mode(1024, 768), (400, 300)), 1.0);
+
+        // The detected scale shrinks the panel's share of the desktop
+        let mut wm = WindowManager::new(640, 480, Box::new(WindowDecorations::default()));
+        let mut outputs = OutputManager::new(LayoutMode::Extend);
+        let (panel, panel_display) = FakeOutput::connector("eDP-1", &[(640, 400)], Some(edid(143, 89)));
+        outputs.connect(panel).unwrap();
+        outputs.configure("eDP-1", OutputConfig { scale: Some(2.0), ..OutputConfig::default() });
+        outputs.apply(&mut wm).unwrap();
+        assert_eq!(wm.outputs(), &[Viewport::new(Rect::new(0, 0, 320, 200), 2.0)]);
+        assert_eq!(wm.compositor().resolution(), (320, 200));
+
+        // Clients hear the output's scale and can hand in dense buffers
+        let surface = wm.create_surface("Editor", 100, 60);
+        assert!(wm.take_events().contains(&(surface, "scale 2".to_string())));
+        wm.set_buffer_scale(surface, 2.0).unwrap();
+        assert_eq!(wm.set_buffer_scale(surface, 0.5), Err("Invalid buffer scale"));
+        let buffer = Buffer::new(200, 120, BufferStorage::SharedMemory);
+        buffer.fill(Rect::new(0, 0, 100, 120), 0xFFFF_0000);
+        buffer.fill(Rect::new(100, 0, 100, 120), 0xFF00_FF00);
+        let buffer = wm.register_buffer(buffer);
+        wm.attach(surface, buffer).unwrap();
+        wm.commit(surface).unwrap();
+        assert_eq!(wm.geometry(surface), Some((0, 0, 100, 60)));
+
+        // Composition happens at the panel's resolution, one buffer pixel
+        // to one panel pixel: the client's halves meet exactly at the
+        // middle of its 200 pixel width
+        outputs.run_frame(wm.compositor_mut()).unwrap().unwrap();
+        let display = panel_display.lock().unwrap();
+        let (left, top) = (2 * 4, 2 * 24);
+        assert_eq!(display.pixel(left + 99, top + 10), 0xFFFF_0000);
+        assert_eq!(display.pixel(left + 100, top + 10), 0xFF00_FF00);
+        drop(display);
+
+        // Without a configured scale the EDID decides, here a fractional
+        // one for a 113 dpi panel
+        outputs.configure("eDP-1", OutputConfig::default());
+        outputs.apply(&mut wm).unwrap();
+        assert_eq!(outputs.viewport("eDP-1"), Some(Viewport::new(Rect::new(0, 0, 512, 320), 1.25)));
+        assert!(wm.take_events().contains(&(surface, "scale 1.25".to_string())));
+        outputs.run_frame(wm.compositor_mut()).unwrap().unwrap();
+        let display = panel_display.lock().unwrap();
+        assert_eq!(display.pixel(5 + 62, 30 + 10), 0xFFFF_0000);
+        assert_eq!(display.pixel(5 + 63, 30 + 10), 0xFF00_FF00);
+        drop(display);
+
+        // Widget trees lay out in logical pixels and draw at the scale
+        let mut tree = WidgetTree::new(Element::container(Direction::Column).padding(10).child(Element::button("OK").name("ok")), 100, 60);
+        tree.set_scale(2.0);
+        let ok = tree.find("ok").unwrap();
+        assert_eq!(tree.buffer_size(), (200, 120));
+        assert_eq!(tree.bounds(ok), Some(Rect::new(10, 10, 80, 33)));
+        assert_eq!(tree.widget_at(50, 20), Some(ok));
+        assert!(tree.render(&Buffer::new(100, 60, BufferStorage::SharedMemory)).is_err());
+        let buffer = Buffer::new(200, 120, BufferStorage::SharedMemory);
+        assert_eq!(tree.render(&buffer).unwrap(), vec![Rect::new(0, 0, 200, 120)]);
+        assert_eq!(buffer.pixel(19, 40), Some(ColorScheme::DARK.background));
+        assert_eq!(buffer.pixel(24, 40), Some(ColorScheme::DARK.button));
+        assert_eq!(buffer.pixel(175, 80), Some(ColorScheme::DARK.button));
+        assert_eq!(buffer.pixel(182, 80), Some(ColorScheme::DARK.background));
+    }
+
+    #[test]
+    pub fn test_terminal_emulator() {
+        let mut terminal = Terminal::new(10, 4);
+        terminal.feed("héllo\r\nworld".as_bytes());
+        assert_eq!(terminal.row_text(0), "héllo");
+        assert_eq!(terminal.cursor(), (1, 5));
+
+        // Cursor addressing, erasing and colours
+        terminal.feed(b"\x1b[1;3H\x1b[K\x1b[31;1mX\x1b[38;5;196mY\x1b[48;2;1;2;3mZ\x1b[0m");
+        assert_eq!(terminal.row_text(0), "héXYZ");
+        let x = terminal.cell(0, 2).unwrap();
+        assert_eq!((x.attributes.foreground, x.attributes.bold), (CellColor::Indexed(1), true));
+        assert_eq!(terminal.cell(0, 3).unwrap().attributes.foreground, CellColor::Indexed(196));
+        assert_eq!(terminal.cell(0, 4).unwrap().attributes.background, CellColor::Rgb(0xFF01_0203));
+        assert_eq!(palette(196), 0xFFFF_0000);
+        assert_eq!(palette(244), 0xFF80_8080);
+
+        // Wrapping at the right margin and scrolling into the scrollback
+        terminal.feed(b"\x1b[4;1H0123456789ab\r\nc");
+        assert_eq!(terminal.scrollback_len(), 2);
+        assert_eq!(terminal.row_text(1), "0123456789");
+        assert_eq!(terminal.row_text(2), "ab");
+        assert_eq!(terminal.row_text(3), "c");
+
+        // A scroll region keeps the lines outside it
+        terminal.feed(b"\x1b[2;3r\x1b[3;1H\n\x1b[r");
+        assert_eq!(terminal.row_text(1), "ab");
+        assert_eq!(terminal.row_text(2), "");
+        assert_eq!(terminal.row_text(3), "c");
+        assert_eq!(terminal.scrollback_len(), 2);
+
+        // Status queries, the title and the alternate screen
+        terminal.feed(b"\x1b[2;4H\x1b[6n\x1b[c\x1b]2;shell\x07");
+        assert_eq!(terminal.take_responses(), b"\x1b[2;4R\x1b[?6c");
+        assert_eq!(terminal.title(), "shell");
+        terminal.feed(b"\x1b[?1049h\x1b[2Jvi");
+        assert!(terminal.is_alternate_screen());
+        assert_eq!(terminal.row_text(0), "");
+        terminal.feed(b"\x1b[?1049l");
+        assert_eq!(terminal.row_text(1), "ab");
+        assert_eq!(terminal.cursor(), (1, 3));
+
+        // Keys as xterm sends them
+        let none = Modifiers::default();
+        let ctrl = Modifiers {
+            ctrl: true,
+            ..Modifiers::default()
+        };
+        assert_eq!(encode_key(KEY_UP, none, false), Some(b"\x1b[A".to_vec()));
+        assert_eq!(encode_key(KEY_UP, none, true), Some(b"\x1bOA".to_vec()));
+        assert_eq!(encode_key(KEY_UP, ctrl, true), Some(b"\x1b[1;5A".to_vec()));
+        assert_eq!(encode_key(KEY_F1, none, false), Some(b"\x1bOP".to_vec()));
+        assert_eq!(encode_key(KEY_F1 + 4, none, false), Some(b"\x1b[15~".to_vec()));
+        assert_eq!(encode_key(KEY_ENTER, none, false), Some(b"\r".to_vec()));
+        assert_eq!(encode_text('c', ctrl), vec![0x03]);
+
+        // The app on a PTY: a shell echoes through the line discipline
+        let (master, slave) = pty::open(PtySize { rows: 1, cols: 1 });
+        let mut app = TerminalApp::new(master, 80, 48);
+        assert_eq!(app.cell_size(), (7, 21));
+        assert_eq!(app.terminal().size(), (11, 2));
+        assert_eq!(slave.size(), PtySize { rows: 2, cols: 11 });
+        app.key(KEY_LEFTCTRL, true).unwrap();
+        app.text_input('c').unwrap();
+        app.key(KEY_LEFTCTRL, false).unwrap();
+        app.text_input('l').unwrap();
+        app.text_input('s').unwrap();
+        app.key(KEY_ENTER, true).unwrap();
+        assert_eq!(slave.read(), Ok(Some(b"ls\n".to_vec())));
+        assert!(app.pump().unwrap());
+        // The interrupt's echo has scrolled into the scrollback
+        let first: String = app.terminal().line(0).unwrap().iter().map(|cell| cell.character).collect();
+        assert_eq!(first.trim_end(), "^C");
+        assert_eq!(app.terminal().row_text(0), "ls");
+
+        // Programs asking for the cursor position get their answer
+        slave.set_mode(LineMode {
+            canonical: false,
+            echo: false,
+            signals: true,
+        });
+        slave.write(b"\x1b[6n").unwrap();
+        app.pump().unwrap();
+        assert_eq!(slave.read(), Ok(Some(b"\x1b[2;1R".to_vec())));
+
+        // Output lands in the buffer with the theme's colours
+        let theme = Theme::default();
+        let colors = theme.colors(Variant::Dark);
+        let buffer = Buffer::new(80, 48, BufferStorage::SharedMemory);
+        assert_eq!(app.render(&buffer).unwrap(), vec![Rect::new(0, 0, 80, 48)]);
+        assert_eq!(app.render(&buffer).unwrap(), Vec::new());
+        slave.write(b"\x1b[7m \x1b[0m").unwrap();
+        app.pump().unwrap();
+        assert_eq!(app.render(&buffer).unwrap(), vec![Rect::new(0, 21, 80, 21)]);
+        assert_eq!(buffer.pixel(0, 21), Some(colors.text));
+        assert_eq!(buffer.pixel(7, 30), Some(colors.text));
+        assert_eq!(buffer.pixel(14, 21), Some(colors.background));
+
+        // Scrolling back, selecting across lines and snapping back on input
+        slave.write(b"\nthree\nfour").unwrap();
+        app.pump().unwrap();
+        assert_eq!(app.terminal().scrollback_len(), 3);
+        app.key(KEY_LEFTSHIFT, true).unwrap();
+        app.key(KEY_PAGEUP, true).unwrap();
+        app.key(KEY_PAGEUP, true).unwrap();
+        app.key(KEY_LEFTSHIFT, false).unwrap();
+        assert_eq!(app.view_offset(), 3);
+        app.pointer_button(7, 5, true);
+        app.pointer_motion(20, 30);
+        app.pointer_button(20, 30, false);
+        assert_eq!(app.selection_text(), Some("C\nls".to_string()));
+        app.scroll(-1);
+        assert_eq!(app.view_offset(), 2);
+        app.text_input('q').unwrap();
+        assert_eq!(app.view_offset(), 0);
+        assert_eq!(app.selection_text(), Some("C\nls".to_string()));
+    }
+
+    #[test]
+    pub fn test_cursor_plane_and_shapes() {
+        let theme = Theme::parse("[cursor]\nsize=16\nfill=#FFFFFF\noutline=#000000\n").unwrap();
+        assert_eq!(theme.cursor, CursorStyle { size: 16, fill: 0xFFFF_FFFF, outline: 0xFF00_0000 });
+        assert_eq!(Theme::parse(&theme.serialize()).unwrap(), theme);
+        let arrow = vxcursor::render(CursorShape::Default, &theme.cursor).unwrap();
+        assert_eq!(arrow.hotspot, (3, 1));
+        assert_eq!(arrow.buffer.pixel(5, 8), Some(0xFFFF_FFFF));
+        assert!(vxcursor::render(CursorShape::Hidden, &theme.cursor).is_none());
+        assert_eq!(CursorShape::parse("nwse-resize"), Ok(CursorShape::ResizeDiagonal));
+        assert_eq!(CursorShape::for_edges(vaelix_graphics::vxwm::vxwm::EDGE_TOP), CursorShape::ResizeVertical);
+
+        let manager = VXChanManager::new();
+        let mut service = WmService::new(&manager).unwrap();
+        let mut wm = WindowManager::new(160, 120, Box::new(WindowDecorations::default()));
+        wm.set_cursor_theme(&theme.cursor);
+        let surface = wm.create_surface("Editor", 100, 80);
+        let buffer = wm.register_buffer(Buffer::new(100, 80, BufferStorage::SharedMemory));
+        wm.attach(surface, buffer).unwrap();
+        wm.commit(surface).unwrap();
+
+        // With a plane the pointer never enters the frame and moves
+        // without composition
+        let mut display = FakeDisplay::new(160, 120);
+        display.cursor_plane = Some((64, 64));
+        wm.pointer_motion(130, 20);
+        wm.compositor_mut().run_frame(&mut display).unwrap();
+        assert!(!wm.compositor().software_cursor());
+        assert_eq!((display.cursor_loads, display.cursor_position), (1, (127, 19)));
+        assert_eq!(display.cursor.as_ref().unwrap().pixel(5, 8), Some(0xFFFF_FFFF));
+        assert_eq!(display.pixel(132, 27), 0xFF20_2020);
+        wm.pointer_motion(140, 30);
+        assert!(wm.compositor().pending_damage().is_empty());
+        let desktop = Viewport::new(Rect::new(0, 0, 160, 120), 1.0);
+        wm.compositor_mut().update_cursor(&mut [(desktop, &mut display)]).unwrap();
+        assert_eq!((display.cursor_loads, display.cursor_position), (1, (137, 29)));
+
+        // Clients pick the shape over their area, the frame shows how it
+        // resizes and moving shows the move shape
+        send_request(&manager, 1, &format!("cursor {} text", surface.0)).unwrap();
+        send_request(&manager, 2, &format!("cursor {} sideways", surface.0)).unwrap();
+        service.poll(&manager, &mut wm).unwrap();
+        assert_eq!(drain(&manager, REPLY_CHANNEL), vec!["1 ok", "2 error Unknown cursor shape"]);
+        assert_eq!(wm.cursor_shape(), CursorShape::Default);
+        wm.pointer_motion(50, 50);
+        assert_eq!(wm.cursor_shape(), CursorShape::Text);
+        wm.compositor_mut().run_frame(&mut display).unwrap();
+        assert_eq!(display.cursor_loads, 2);
+        wm.pointer_motion(106, 60);
+        assert_eq!(wm.cursor_shape(), CursorShape::ResizeHorizontal);
+        wm.pointer_motion(106, 106);
+        assert_eq!(wm.cursor_shape(), CursorShape::ResizeDiagonal);
+        click(&mut wm, 10, 10);
+        assert_eq!(wm.cursor_shape(), CursorShape::Move);
+        wm.pointer_button(BTN_LEFT, false).unwrap();
+
+        // Without a plane the pointer is composed into the frame, and
+        // moving it repaints where it was
+        let mut plain = FakeDisplay::new(160, 120);
+        wm.pointer_motion(140, 100);
+        wm.compositor_mut().run_frame(&mut plain).unwrap();
+        assert!(wm.compositor().software_cursor());
+        assert_eq!(plain.pixel(137 + 5, 99 + 8), 0xFFFF_FFFF);
+        wm.pointer_motion(150, 110);
+        assert_eq!(wm.compositor().pending_damage(), &[Rect::new(137, 99, 23, 21)]);
+        wm.compositor_mut().run_frame(&mut plain).unwrap();
+        assert_eq!(plain.pixel(137 + 5, 99 + 8), 0xFF20_2020);
+        assert_eq!(plain.pixel(147 + 5, 109 + 8), 0xFFFF_FFFF);
+
+        // A new image tries the planes again, erasing the drawn pointer;
+        // hiding it empties the plane
+        wm.set_cursor(surface, CursorShape::Pointer).unwrap();
+        wm.pointer_motion(50, 50);
+        wm.compositor_mut().run_frame(&mut display).unwrap();
+        assert!(!wm.compositor().software_cursor());
+        assert_eq!(display.pixel(147 + 5, 109 + 8), 0xFF20_2020);
+        assert!(display.cursor.is_some());
+        wm.set_cursor(surface, CursorShape::Hidden).unwrap();
+        wm.compositor_mut().run_frame(&mut display).unwrap();
+        assert!(display.cursor.is_none());
+    }
+
+    struct TestClock(Arc<AtomicU64>);
+
+    impl Clock for TestClock {
+        fn now(&self) -> u64 {
+            self.0.load(Ordering::SeqCst)
+        }
+    }
+
+    #[test]
+    pub fn test_frame_timing() {
+        let time = Arc::new(AtomicU64::new(0));
+        let mut compositor = Compositor::new(320, 240);
+        compositor.set_clock(Box::new(TestClock(time.clone())));
+        let mut display = FakeDisplay::new(320, 240);
+        compositor.run_frame(&mut display).unwrap();
+        time.store(16_667, Ordering::SeqCst);
+        compositor.run_frame(&mut display).unwrap();
+        assert_eq!(compositor.frame_timings().len(), 1);
+        assert_eq!(compositor.frame_timings()[0].flip, Some(16_667));
+        assert_eq!(compositor.frame_timings()[0].commit, None);
+
+        // A frame carries the first input and commit since the last one,
+        // and flips on the vblank after it was composed
+        let window = compositor.create_window(10, 10);
+        compositor.attach(window, Buffer::new(40, 40, BufferStorage::SharedMemory)).unwrap();
+        time.store(20_000, Ordering::SeqCst);
+        compositor.note_input();
+        time.store(21_000, Ordering::SeqCst);
+        compositor.commit(window).unwrap();
+        time.store(22_000, Ordering::SeqCst);
+        compositor.note_input();
+        time.store(33_334, Ordering::SeqCst);
+        compositor.run_frame(&mut display).unwrap();
+        time.store(50_001, Ordering::SeqCst);
+        compositor.run_frame(&mut display).unwrap();
+        let timing = compositor.frame_timings()[1];
+        assert_eq!((timing.input, timing.commit, timing.composite_start), (Some(20_000), Some(21_000), 33_334));
+        assert_eq!((timing.input_latency(), timing.commit_latency()), (Some(30_001), Some(29_001)));
+        assert_eq!(timing.dropped, 0);
+
+        // Only vblanks missed between frames of an animation count as
+        // dropped
+        for (commit, vblank) in [(55_000, 66_668), (70_000, 83_335), (90_000, 133_336), (140_000, 150_003)] {
+            time.store(commit, Ordering::SeqCst);
+            compositor.damage(window, Rect::new(0, 0, 40, 40)).unwrap();
+            compositor.commit(window).unwrap();
+            time.store(vblank, Ordering::SeqCst);
+            compositor.run_frame(&mut display).unwrap();
+        }
+        let dropped: Vec<u32> = compositor.frame_timings().iter().map(|timing| timing.dropped).collect();
+        assert_eq!(dropped, vec![0, 0, 0, 2, 0]);
+        assert_eq!(compositor.dropped_frames(), 2);
+        let summary = compositor.timing_summary();
+        assert_eq!((summary.frames, summary.dropped), (5, 2));
+        assert_eq!(
+            summary.lines(),
+            vec!["fps 30.0 dropped 2", "composite 0.0 ms (worst 0.0)", "input 30.0 ms (worst 30.0)", "commit 45.2 ms (worst 63.3)"]
+        );
+
+        // The HUD sits in the top right corner, redrawn at most twice a
+        // second
+        let mut hud = PerformanceHud::new(&mut compositor);
+        hud.set_enabled(true);
+        hud.update(&mut compositor, 0).unwrap();
+        compositor.compose(&mut display).unwrap();
+        assert_eq!(compositor.window_at(300, 20), Some(hud.window()));
+        assert_eq!(display.pixel(100, 8 + 72 + 24), 0xFFE0_C040);
+        hud.update(&mut compositor, 100_000).unwrap();
+        assert!(compositor.pending_damage().is_empty());
+        hud.set_enabled(false);
+        hud.update(&mut compositor, 200_000).unwrap();
+        assert_eq!(compositor.window_at(300, 20), None);
+
+        for _ in 0..TIMING_HISTORY + 10 {
+            compositor.repaint();
+            compositor.compose(&mut display).unwrap();
+        }
+        assert_eq!(compositor.frame_timings().len(), TIMING_HISTORY);
+        compositor.reset_timings();
+        assert!(compositor.frame_timings().is_empty());
+        assert_eq!(compositor.dropped_frames(), 0);
+
+        // The window manager marks input as it arrives, and the figures
+        // are a request away
+        let manager = VXChanManager::new();
+        let mut service = WmService::new(&manager).unwrap();
+        let mut wm = WindowManager::new(320, 240, Box::new(WindowDecorations::default()));
+        wm.compositor_mut().set_clock(Box::new(TestClock(time.clone())));
+        time.store(5, Ordering::SeqCst);
+        wm.handle_input(InputEvent::PointerMotion { dx: 4, dy: 4 }).unwrap();
+        wm.compositor_mut().run_frame(&mut display).unwrap();
+        wm.compositor_mut().run_frame(&mut display).unwrap();
+        assert_eq!(wm.compositor().frame_timings()[0].input, Some(5));
+        send_request(&manager, 1, "timing").unwrap();
+        service.poll(&manager, &mut wm).unwrap();
+        assert_eq!(
+            drain(&manager, REPLY_CHANNEL),
+            vec!["1 ok\nfps 0.0 dropped 0\ncomposite 0.0 ms (worst 0.0)\ninput 0.0 ms (worst 0.0)\ncommit -"]
+        );
+    }
+
+    #[test]
+    pub fn test_input_method() {
+        let manager = VXChanManager::new();
+        let mut compositor = Compositor::new(320, 240);
+        let mut ime = InputMethod::new(&manager, &mut compositor).unwrap();
+        let mut tree = WidgetTree::new(
+            Element::container(Direction::Column)
+                .child(Element::text_input("Search").name("search"))
+                .child(Element::secret_input("Password").name("password")),
+            200,
+            80,
+        );
+        let (search, password) = (tree.find("search").unwrap(), tree.find("password").unwrap());
+        let on_desktop = |caret: Option<Rect>| caret.map(|caret| caret.translate(40, 20));
+
+        // Without an input method keys go straight to the input
+        tree.set_focus(Some(search)).unwrap();
+        let caret = tree.caret_area().unwrap();
+        ime.set_caret(&manager, on_desktop(Some(caret))).unwrap();
+        assert!(!ime.is_active());
+        assert!(!ime.key(&manager, 30, true).unwrap());
+        assert!(drain(&manager, vxime::EVENT_CHANNEL).is_empty());
+
+        // Registering activates it on the focused input, and keys go to it
+        vxime::send_message(&manager, "register pinyin").unwrap();
+        assert!(ime.poll(&manager).unwrap().is_empty());
+        assert_eq!((ime.name(), ime.is_active()), (Some("pinyin"), true));
+        assert!(ime.key(&manager, 49, true).unwrap());
+        assert!(ime.key(&manager, 49, false).unwrap());
+        assert_eq!(drain(&manager, vxime::EVENT_CHANNEL), vec!["activate", "key 49 1", "key 49 0"]);
+
+        // The composition shows in the input without being its text, and
+        // the candidates under the caret
+        vxime::send_message(&manager, "preedit 2 ni").unwrap();
+        vxime::send_message(&manager, "candidates 1 你\t尼\t泥").unwrap();
+        let events = ime.poll(&manager).unwrap();
+        assert_eq!(events, vec![ImeEvent::Preedit { text: "ni".to_string(), cursor: 2 }]);
+        for event in &events {
+            vxime::apply(&mut tree, event).unwrap();
+        }
+        assert_eq!((tree.preedit(), tree.text(search)), (Some(("ni", 2)), Some("")));
+        let composing = tree.caret_area().unwrap();
+        assert!(composing.x > caret.x);
+        let buffer = Buffer::new(200, 80, BufferStorage::SharedMemory);
+        tree.render(&buffer).unwrap();
+        let style = tree.style(search).unwrap();
+        let underline = tree.bounds(search).unwrap().bottom() - style.inset as i32 - 1;
+        assert_eq!(buffer.pixel((composing.x - 2) as u32, underline as u32), Some(style.foreground));
+        assert_eq!(ime.candidates(), (&["你".to_string(), "尼".to_string(), "泥".to_string()][..], Some(1)));
+        ime.set_caret(&manager, on_desktop(Some(composing))).unwrap();
+        ime.update(&mut compositor).unwrap();
+        let below = on_desktop(Some(composing)).unwrap();
+        assert_eq!(compositor.window_at(below.x + 2, below.bottom() + 2), Some(ime.window()));
+        let list = ime.tree().find("candidates").unwrap();
+        assert_eq!(ime.tree().selected(list), Some(1));
+
+        // Committing replaces the composition; keys the method passes back
+        // are handled as usual
+        let submitted = Rc::new(RefCell::new(Vec::new()));
+        let log = submitted.clone();
+        let mut form = WidgetTree::new(
+            Element::text_input("").name("field").on_event(move |_, _, event| log.borrow_mut().push(event.clone())),
+            200,
+            40,
+        );
+        form.set_focus(form.find("field")).unwrap();
+        vxime::send_message(&manager, "commit 你好").unwrap();
+        vxime::send_message(&manager, "candidates none").unwrap();
+        vxime::send_message(&manager, &format!("forward {} 1", KEY_ENTER)).unwrap();
+        vxime::send_message(&manager, "forward 28").unwrap();
+        for event in ime.poll(&manager).unwrap() {
+            vxime::apply(&mut form, &event).unwrap();
+        }
+        assert_eq!(form.text(form.find("field").unwrap()), Some("你好"));
+        assert_eq!(form.preedit(), None);
+        assert_eq!(
+            *submitted.borrow(),
+            vec![WidgetEvent::Changed("你好".to_string()), WidgetEvent::Submitted("你好".to_string())]
+        );
+        ime.update(&mut compositor).unwrap();
+        assert_eq!(compositor.window_at(below.x + 2, below.bottom() + 2), None);
+
+        // Secret inputs take no composed text
+        tree.set_focus(Some(password)).unwrap();
+        assert_eq!(tree.preedit(), None);
+        assert_eq!(tree.caret_area(), None);
+        assert_eq!(tree.set_preedit("mi", 2), Err("No text input has focus"));
+        ime.set_caret(&manager, on_desktop(tree.caret_area())).unwrap();
+        assert!(!ime.key(&manager, 30, true).unwrap());
+        vxime::send_message(&manager, "unregister").unwrap();
+        ime.poll(&manager).unwrap();
+        assert_eq!(ime.name(), None);
+        assert_eq!(drain(&manager, vxime::EVENT_CHANNEL), vec!["deactivate"]);
+    }
+
+    fn snapshot_form() -> WidgetTree {
+        let mut tree = WidgetTree::new(
+            Element::container(Direction::Column)
+                .padding(8)
+                .child(Element::label("Sign in"))
+                .child(Element::text_input("User").name("user"))
+                .child(Element::button("Continue"))
+                .child(Element::list(&["Wayland", "X11"]).name("session")),
+            160,
+            160,
+        );
+        let session = tree.find("session").unwrap();
+        tree.select(session, Some(0)).unwrap();
+        tree.set_focus(tree.find("user")).unwrap();
+        tree
+    }
+
+    #[test]
+    pub fn test_snapshots() {
+        // Checked-in golden images catch layout and theme regressions
+        let goldens = Snapshots::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/snapshots"));
+        assert_eq!(goldens.check("login_form", &mut snapshot_form()), Ok(SnapshotOutcome::Matched));
+        let mut scaled = snapshot_form();
+        scaled.set_scale(2.0);
+        assert_eq!(vxsnapshot::render_offscreen(&mut scaled).unwrap().width(), 320);
+
+        // Small shifts in colour are below the threshold; anything a
+        // person would notice is not
+        assert_eq!(vxsnapshot::perceptual_delta(0xFF10_2030, 0xFF10_2030), 0.0);
+        assert!(vxsnapshot::perceptual_delta(0xFF10_2030, 0xFF11_2131) < 0.01);
+        assert!(vxsnapshot::perceptual_delta(0xFF00_0000, 0xFFFF_FFFF) > 0.9);
+        assert!(vxsnapshot::perceptual_delta(0xFF3D_7EFF, 0xFFFF_4040) > 0.1);
+
+        let dir = std::env::temp_dir().join(format!("vxsnapshot-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut snapshots = Snapshots::new(dir.to_str().unwrap());
+        snapshots.set_update(false);
+        assert_eq!(snapshots.check("form", &mut snapshot_form()), Ok(SnapshotOutcome::Created));
+        assert_eq!(snapshots.check("form", &mut snapshot_form()), Ok(SnapshotOutcome::Matched));
+
+        // A new accent shows up in the selected row and the focused input's
+        // border, and the failure leaves the rendering and a diff to look
+        // at
+        let mut restyled = snapshot_form();
+        restyled.set_theme(Theme::parse("[dark]\naccent=#FF4040\n").unwrap(), Variant::Dark);
+        let expected = vxsnapshot::render_offscreen(&mut snapshot_form()).unwrap();
+        let actual = vxsnapshot::render_offscreen(&mut restyled).unwrap();
+        let diff = vxsnapshot::diff(&expected, &actual, &DiffOptions::default()).unwrap();
+        let (user, session) = (restyled.find("user").unwrap(), restyled.find("session").unwrap());
+        let (input, list) = (restyled.bounds(user).unwrap(), restyled.bounds(session).unwrap());
+        assert_eq!(diff.bounds, Some(input.union(&list.intersect(&diff.bounds.unwrap()).unwrap())));
+        let at = |x: i32, y: i32| diff.image.pixels()[(y as u32 * 160 + x as u32) as usize];
+        assert_eq!((at(80, list.y + 10), at(80, 2)), (0xFFFF_0000, 0xFFEA_EAEA));
+        assert_eq!(snapshots.check("form", &mut restyled), Err("Snapshot does not match its golden image"));
+        assert!(dir.join("form.actual.ppm").exists() && dir.join("form.diff.ppm").exists());
+        snapshots.set_options(DiffOptions { threshold: 0.1, max_differing: diff.differing });
+        assert_eq!(snapshots.check("form", &mut restyled), Ok(SnapshotOutcome::Matched));
+        snapshots.set_options(DiffOptions::default());
+        snapshots.set_update(true);
+        assert_eq!(snapshots.check("form", &mut restyled), Ok(SnapshotOutcome::Updated));
+        assert_eq!(snapshots.check("form", &mut restyled), Ok(SnapshotOutcome::Matched));
+        assert_eq!(
+            vxsnapshot::diff(&expected, &Image::new(1, 1, vec![0]).unwrap(), &DiffOptions::default()),
+            Err("Images differ in size")
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,7 +1,56 @@
 #[cfg(test)]
 pub mod tests {
-    use vaelix_core::{vx_tasklet_init, vxchan_init};
-
+    use vaelix_core::crashdump::crashdump::{self, DumpArea, Minidump};
+    use vaelix_core::drivers::block::block::BlockDevice;
+    use vaelix_core::drivers::msr::msr::{MsrIo, MsrSpace};
+    use vaelix_core::drivers::port::port::{PortIo, PortSpace};
+    use vaelix_core::drivers::sdhci::sdhci::{clock_divider, parse_csd_capacity};
+    use vaelix_core::drivers::uart::uart::{self, SerialPort, Uart16550, COM1, COM2};
+    use vaelix_core::faultinject::faultinject::{self, FaultConfig, FaultPoint, FAIL_ALLOC, FAIL_IO, FAIL_IRQ_DELAY};
+    use vaelix_core::gdbstub::gdbstub::{self, Command, DebugTarget, GdbStub, BREAKPOINT_VECTOR, DEBUG_VECTOR, INT3, RFLAGS_TF};
+    use vaelix_core::hardening::hardening::{self, KernelStack, IRQ_STACKS_BASE, IRQ_STACK_PAGES};
+    use vaelix_core::input::input::{InputEvent, KEY_F1, KEY_F10, KEY_LEFTALT, KEY_LEFTCTRL};
+    use vaelix_core::kallsyms::kallsyms::{self, Symbol, SymbolTable};
+    use vaelix_core::kconfig::kconfig::{self, Driver, KernelConfig, Profile};
+    use vaelix_core::keyring::keyring::{KeyKind, Keyring, Secret};
+    use vaelix_core::latency::latency::{LatencyTracer, Stage};
+    use vaelix_core::log::log::{self, ConsoleSink, FileSink, LogService, Logger, SerialSink};
+    use vaelix_core::metrics::metrics::{self, MetricType, Registry, Sample};
+    use vaelix_core::percpu::percpu::{self, PerCpu, IA32_GS_BASE, IA32_KERNEL_GS_BASE};
+    use vaelix_core::power::policy::policy::policy_manager;
+    use vaelix_core::process::elf::elf::{Executable, PF_R, PF_W, PF_X};
+    use vaelix_core::process::files::files::{FileTable, OpenFile};
+    use vaelix_core::process::filter::filter::{Action, Filter};
+    use vaelix_core::process::kthread::kthread::KernelThread;
+    use vaelix_core::process::limits::limits::{Limit, Limits, Resource, RLIMIT_AS, RLIMIT_CPU, RLIMIT_NOFILE, RLIM_INFINITY};
+    use vaelix_core::process::linker::linker::{self, DEFAULT_HANDLE, INTERPRETER};
+    use vaelix_core::process::mapping::mapping::Backing;
+    use vaelix_core::process::pipe::pipe;
+    use vaelix_core::process::procfs::procfs;
+    use vaelix_core::process::signal::signal::{SIGCONT, SIGHUP, SIGINT, SIGKILL, SIGPIPE, SIGSTOP, SIGSYS, SIGTSTP, SIGTTIN, SIGTTOU};
+    use vaelix_core::process::memory::memory::{AddressSpace, PhysicalMemory, KERNEL_BASE, NO_EXECUTE, PAGE_SIZE, USER, WRITABLE};
+    use vaelix_core::process::syscall::syscall::{self, Errno, IA32_EFER, IA32_LSTAR, IA32_STAR};
+    use vaelix_core::process::table::table::{Blocker, ExitStatus, Pid, ProcessTable, Tid, INIT_PID, KERNEL_PID};
+    use vaelix_core::process::task::task::{
+        Layout, Process, Trap, UserContext, UserCpu, AT_BASE, AT_ENTRY, AT_NULL, FAULT_FETCH, FAULT_PRESENT, FAULT_USER, FAULT_WRITE, HEAP_RANDOM_PAGES, MMAP_RANDOM_PAGES,
+        MMAP_TOP, PROGRAM_BASE, PROGRAM_RANDOM_PAGES, R10, R11, R8, R9, RAX, RCX, RDI, RDX, RSI, STACK_RANDOM_PAGES, STACK_SIZE, STACK_TOP,
+    };
+    use vaelix_core::process::uaccess::uaccess::{self, CPUID_EXTENDED_FEATURES, CPUID_SMAP, CPUID_SMEP, CPUID_UMIP, CR4_SMAP, CR4_SMEP, CR4_UMIP};
+    use vaelix_core::users::users::{self, AuthService, Credentials, UserDatabase, ACCESS_READ, ACCESS_WRITE, FIRST_USER_ID};
+    use vaelix_core::vxchan::vxchan::VXChanManager;
+    use vaelix_core::vxinit::vxinit::{self, Init, InitService, Manifest, Restart, UnitKind, UnitState};
+    use vaelix_core::vxboot::vxboot::{self, CommandLine};
+    use vaelix_core::vxtop::vxtop::{self, Key, ProcSource, Procfs, SortKey, Top};
+    use vaelix_core::vxsh::vxsh::{self, JobState, Part, Redirection, Shell, STATUS_NOT_EXECUTABLE, STATUS_NOT_FOUND, STATUS_USAGE};
+    use vaelix_core::pty::pty::{self, LineMode, PtySize, Signal};
+    use vaelix_core::vt::vt::{VtKind, VtManager, VtSwitch};
+    use vaelix_core::sync::sync::{self, SpinLock};
+    use vaelix_core::watchdog::watchdog::{LockupKind, Watchdog, WatchdogConfig};
+    use vaelix_core::{define_per_cpu, this_cpu, vx_tasklet_init, vxchan_init};
+    use ::log::{Level, LevelFilter};
+    use std::cell::RefCell;
+    use std::collections::{BTreeMap, VecDeque};
+    use std::sync::{Arc, Mutex};
 
     #[test]
     pub fn test_vx_tasklet_init() {
@@ -10,8 +59,2851 @@ pub mod tests {
     }
 
     #[test]
+    #[allow(unused_must_use)]
     pub fn test_vxchan_init() {
         vxchan_init();
         // Add assertions to verify the initialization
     }
+
+    #[test]
+    pub fn test_sdhci_clock_divider() {
+        assert_eq!(clock_divider(200_000_000, 400_000), 250);
+        assert_eq!(clock_divider(200_000_000, 208_000_000), 0);
+        assert_eq!(clock_divider(200_000_000, 50_000_000), 2);
+    }
+
+    #[test]
+    pub fn test_sdhci_csd_capacity() {
+        // CSD v2.0 with C_SIZE = 0x3B37 (a 8 GB card), shifted right by the stripped CRC byte
+        let c_size: u128 = 0x3B37;
+        let raw = (1u128 << 118) | (c_size << 40);
+        let response = [raw as u32, (raw >> 32) as u32, (raw >> 64) as u32, (raw >> 96) as u32];
+        assert_eq!(parse_csd_capacity(response), Ok((0x3B37 + 1) * 1024));
+    }
+
+    #[test]
+    pub fn test_pty_line_discipline() {
+        let (master, slave) = pty::open(PtySize { rows: 24, cols: 80 });
+        assert_eq!(slave.name(), format!("/dev/pts/{}", master.index()));
+
+        // Canonical input is held until the line ends, with erase and echo
+        master.write("lsé\x7f\x7fs -l".as_bytes()).unwrap();
+        assert_eq!(slave.read(), Ok(None));
+        master.write(b"\r").unwrap();
+        assert_eq!(slave.read(), Ok(Some(b"ls -l\n".to_vec())));
+        let echoed = master.read();
+        assert!(echoed.starts_with("lsé\x08 \x08\x08 \x08".as_bytes()));
+        assert!(echoed.ends_with(b"s -l\r\n"));
+
+        // Kill, interrupt and end of file
+        master.write(b"junk\x15\x03").unwrap();
+        assert_eq!(slave.take_signals(), vec![Signal::Interrupt]);
+        master.write(b"\x04").unwrap();
+        assert_eq!(slave.read(), Ok(Some(Vec::new())));
+        assert_eq!(slave.read(), Ok(None));
+
+        // Raw mode passes bytes straight through, and output gets CR LF
+        slave.set_mode(LineMode {
+            canonical: false,
+            echo: false,
+            signals: false,
+        });
+        master.read();
+        master.write(b"\x03q").unwrap();
+        assert_eq!(slave.read(), Ok(Some(b"\x03q".to_vec())));
+        slave.write(b"one\ntwo").unwrap();
+        assert_eq!(master.read(), b"one\r\ntwo");
+
+        master.resize(PtySize { rows: 30, cols: 100 });
+        assert_eq!(slave.size(), PtySize { rows: 30, cols: 100 });
+        assert_eq!(slave.take_signals(), vec![Signal::WindowChange]);
+
+        // Hanging up either side is seen by the other
+        slave.clone().close();
+        assert!(!master.is_connected());
+        assert_eq!(master.write(b"x"), Err("Terminal hung up"));
+        drop(master);
+        assert_eq!(slave.write(b"x"), Err("Terminal hung up"));
+    }
+
+    #[test]
+    pub fn test_virtual_console_switching() {
+        assert!(VtManager::new(6, 7).is_err());
+        let mut vts = VtManager::new(6, 1).unwrap();
+        assert_eq!(vts.kind(1), Some(VtKind::Graphical));
+        assert_eq!(vts.kind(2), Some(VtKind::Text));
+        assert!(vts.slave(1).is_none());
+        let master = vts.take_master(2).unwrap();
+        assert!(vts.take_master(2).is_none());
+        let switches = vts.subscribe();
+
+        let key = |code, pressed| InputEvent::Key { code, pressed };
+        // Alt-F2 alone belongs to the graphical session
+        assert_eq!(vts.handle_input(key(KEY_LEFTALT, true)), Some(key(KEY_LEFTALT, true)));
+        assert_eq!(vts.handle_input(key(KEY_F1 + 1, true)), Some(key(KEY_F1 + 1, true)));
+        vts.handle_input(key(KEY_F1 + 1, false));
+        assert_eq!(vts.active(), 1);
+
+        // Ctrl-Alt-F2 switches, and the F2 release is swallowed
+        vts.handle_input(key(KEY_LEFTCTRL, true));
+        assert_eq!(vts.handle_input(key(KEY_F1 + 1, true)), None);
+        assert_eq!(vts.handle_input(key(KEY_F1 + 1, false)), None);
+        vts.handle_input(key(KEY_LEFTCTRL, false));
+        assert_eq!(vts.active(), 2);
+        assert_eq!(switches.try_recv(), Ok(VtSwitch { from: 1, to: 2 }));
+
+        // On a text console Alt-Fn is enough; consoles past the count are
+        // left alone
+        assert_eq!(vts.handle_input(key(KEY_F10, true)), Some(key(KEY_F10, true)));
+        assert_eq!(vts.handle_input(key(KEY_F1, true)), None);
+        assert_eq!(vts.active(), 1);
+        assert_eq!(switches.try_recv(), Ok(VtSwitch { from: 2, to: 1 }));
+        assert_eq!(vts.switch_to(9), Err("No such console"));
+
+        // The console's getty talks to whoever drew it
+        let getty = vts.slave(2).unwrap();
+        getty.write(b"login: ").unwrap();
+        assert_eq!(master.read(), b"login: ");
+    }
+
+    // A static x86-64 executable with one page-aligned stretch of file per
+    // segment: (address, PF_* flags, contents, size in memory)
+    fn build_elf(entry: u64, segments: &[(u64, u32, &[u8], u64)]) -> Vec<u8> {
+        let mut data = vec![0u8; 64];
+        data[..4].copy_from_slice(b"\x7FELF");
+        data[4..7].copy_from_slice(&[2, 1, 1]);
+        data[16..18].copy_from_slice(&2u16.to_le_bytes());
+        data[18..20].copy_from_slice(&62u16.to_le_bytes());
+        data[24..32].copy_from_slice(&entry.to_le_bytes());
+        data[32..40].copy_from_slice(&64u64.to_le_bytes());
+        data[54..56].copy_from_slice(&56u16.to_le_bytes());
+        data[56..58].copy_from_slice(&(segments.len() as u16).to_le_bytes());
+        for (index, (address, flags, contents, memory_size)) in segments.iter().enumerate() {
+            let offset = (index as u64 + 1) * PAGE_SIZE + address % PAGE_SIZE;
+            let mut header = vec![0u8; 56];
+            header[..4].copy_from_slice(&1u32.to_le_bytes());
+            header[4..8].copy_from_slice(&flags.to_le_bytes());
+            header[8..16].copy_from_slice(&offset.to_le_bytes());
+            header[16..24].copy_from_slice(&address.to_le_bytes());
+            header[32..40].copy_from_slice(&(contents.len() as u64).to_le_bytes());
+            header[40..48].copy_from_slice(&memory_size.to_le_bytes());
+            data.extend(header);
+        }
+        for (index, (address, _, contents, _)) in segments.iter().enumerate() {
+            let offset = ((index as u64 + 1) * PAGE_SIZE + address % PAGE_SIZE) as usize;
+            data.resize(offset, 0);
+            data.extend_from_slice(contents);
+        }
+        data
+    }
+
+    // As an installer would: readable and executable by everyone
+    fn write_executable(path: &std::path::Path, data: &[u8]) {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::write(path, data).unwrap();
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    struct FaultingCpu;
+
+    impl UserCpu for FaultingCpu {
+        fn run(&mut self, space: &AddressSpace, context: &mut UserContext) -> Trap {
+            // Runs one instruction, a store to just under the stack
+            assert_eq!(space.translate(context.rip).map(|(_, flags)| flags & NO_EXECUTE), Some(0));
+            context.rip += 2;
+            Trap::PageFault { address: STACK_TOP - STACK_SIZE - 8, error: 0b110 }
+        }
+    }
+
+    fn read_u64(space: &AddressSpace, address: u64) -> u64 {
+        let mut bytes = [0; 8];
+        space.read(address, &mut bytes).unwrap();
+        u64::from_le_bytes(bytes)
+    }
+
+    #[test]
+    pub fn test_elf_loading() {
+        let memory = PhysicalMemory::new(256);
+        let kernel = AddressSpace::kernel(&memory).unwrap();
+        let code = [0x90u8; 32];
+        let elf = build_elf(0x40_0010, &[(0x40_0000, PF_R | PF_X, &code, 32), (0x40_1000, PF_R | PF_W, b"data", 0x1800)]);
+        let executable = Executable::parse(&elf).unwrap();
+        assert_eq!(executable.segments.len(), 2);
+        assert_eq!(executable.end(), 0x40_3000);
+
+        let mut process = Process::load(1, "init", &kernel, &elf, &["init", "-v"], &["HOME=/"]).unwrap();
+        let space = process.space();
+        assert!(space.is_user());
+        assert_eq!(process.break_start(), 0x40_3000);
+
+        // Text is executable and read-only, data writable and not executable,
+        // and the .bss past the file's part is zeroed
+        let (_, text) = space.translate(0x40_0000).unwrap();
+        assert_eq!(text & (USER | WRITABLE | NO_EXECUTE), USER);
+        let (_, data) = space.translate(0x40_2000).unwrap();
+        assert_eq!(data & (WRITABLE | NO_EXECUTE), WRITABLE | NO_EXECUTE);
+        let mut bytes = [0xFF; 8];
+        space.read(0x40_1000, &mut bytes).unwrap();
+        assert_eq!(&bytes, b"data\0\0\0\0");
+        assert!(space.translate(0x40_3000).is_none());
+
+        // Enters ring 3 at the entry point, with argc, argv and envp on an
+        // aligned stack that has a guard page under it
+        let context = *process.context();
+        assert!(context.is_user_mode());
+        assert_eq!(context.rip, 0x40_0010);
+        assert_eq!(context.rsp % 16, 0);
+        assert_eq!(read_u64(space, context.rsp), 2);
+        let mut argument = [0; 3];
+        space.read(read_u64(space, context.rsp + 16), &mut argument).unwrap();
+        assert_eq!(&argument, b"-v\0");
+        assert_eq!(read_u64(space, context.rsp + 24), 0);
+        let mut variable = [0; 6];
+        space.read(read_u64(space, context.rsp + 32), &mut variable).unwrap();
+        assert_eq!(&variable, b"HOME=/");
+        assert!(space.translate(STACK_TOP - STACK_SIZE).is_some());
+        assert!(space.translate(STACK_TOP - STACK_SIZE - PAGE_SIZE).is_none());
+        assert_eq!(
+            process.run(1, &mut FaultingCpu),
+            Ok(Trap::PageFault { address: STACK_TOP - STACK_SIZE - 8, error: 0b110 })
+        );
+        assert_eq!(process.context().rip, 0x40_0012);
+
+        // Every process has its own frames, all freed with it
+        let used = memory.allocated();
+        let other = Process::load(2, "init", &kernel, &elf, &[], &[]).unwrap();
+        let (first, _) = process.space().translate(0x40_1000).unwrap();
+        let (second, _) = other.space().translate(0x40_1000).unwrap();
+        assert_ne!(first, second);
+        drop(other);
+        assert_eq!(memory.allocated(), used);
+        drop(process);
+        assert_eq!(memory.allocated(), 1);
+
+        // Bad executables
+        assert_eq!(Executable::parse(b"#!/bin/sh").unwrap_err(), "Not an ELF file");
+        let mut relocatable = elf.clone();
+        relocatable[16] = 1;
+        assert_eq!(Executable::parse(&relocatable).unwrap_err(), "Not an executable");
+        let mut kernel_space = AddressSpace::new_user(&kernel).unwrap();
+        assert_eq!(executable.load(&elf, &mut kernel_space, PAGE_SIZE), Err("Bad load address"));
+        let wild = build_elf(0x50_0000, &[(0x40_0000, PF_R | PF_X, &code, 32)]);
+        assert_eq!(Executable::parse(&wild).unwrap_err(), "Entry point outside the program");
+        let kernel_half = build_elf(0xFFFF_8000_0000_0000, &[(0xFFFF_8000_0000_0000, PF_R | PF_X, &code, 32)]);
+        assert_eq!(Executable::parse(&kernel_half).unwrap_err(), "Segment outside user space");
+        let overlapping = build_elf(0x40_0000, &[(0x40_0000, PF_R | PF_X, &code, 0x2000), (0x40_1000, PF_R, b"x", 1)]);
+        assert_eq!(Executable::parse(&overlapping).unwrap_err(), "Segments overlap");
+
+        // Too little memory leaves nothing behind
+        let small = PhysicalMemory::new(8);
+        let small_kernel = AddressSpace::kernel(&small).unwrap();
+        assert!(Process::load(1, "init", &small_kernel, &elf, &[], &[]).is_err());
+        assert_eq!(small.allocated(), 1);
+    }
+
+    // Makes one system call, as user code would with SYSCALL
+    struct SyscallCpu {
+        number: u64,
+        arguments: [u64; 6],
+        flags: u64,
+    }
+
+    impl UserCpu for SyscallCpu {
+        fn run(&mut self, _: &AddressSpace, context: &mut UserContext) -> Trap {
+            context.registers[RAX] = self.number;
+            for (register, value) in [RDI, RSI, RDX, R10, R8, R9].into_iter().zip(self.arguments) {
+                context.registers[register] = value;
+            }
+            context.registers[RCX] = context.rip + 2;
+            context.registers[R11] = self.flags;
+            context.rip = KERNEL_BASE;
+            Trap::Syscall
+        }
+    }
+
+    // On a thread, a process's main one when given its PID
+    fn call(table: &mut ProcessTable, tid: Tid, number: u64, arguments: &[u64]) -> u64 {
+        let mut padded = [0; 6];
+        padded[..arguments.len()].copy_from_slice(arguments);
+        let mut cpu = SyscallCpu { number, arguments: padded, flags: 0x202 };
+        assert_eq!(table.run(tid, &mut cpu), Ok(Trap::Syscall));
+        let pid = table.owner(tid).unwrap_or(0);
+        table.get(pid).and_then(|process| process.thread(tid)).map_or(0, |thread| thread.context.registers[RAX])
+    }
+
+    #[test]
+    pub fn test_syscalls() {
+        // SYSCALL enters the kernel at its stub and SYSRET goes back to
+        // ring 3 selectors
+        let msr = MsrSpace::new(1);
+        assert!(syscall::enable(&msr, 0, KERNEL_BASE).is_err());
+        msr.wrmsr(0, IA32_EFER, 0x500).unwrap();
+        assert_eq!(syscall::enable(&msr, 0, 0x1000), Err("Entry point outside kernel space"));
+        syscall::enable(&msr, 0, KERNEL_BASE + 0x1000).unwrap();
+        assert_eq!(msr.rdmsr(0, IA32_EFER), Ok(0x501));
+        assert_eq!(msr.rdmsr(0, IA32_LSTAR), Ok(KERNEL_BASE + 0x1000));
+        assert_eq!(msr.rdmsr(0, IA32_STAR), Ok(0x0013_0008_0000_0000));
+        assert_eq!(syscall::name(syscall::SYS_WRITE), Some("write"));
+        assert_eq!(syscall::name(1000), None);
+
+        let root = std::env::temp_dir().join(format!("vaelix-syscalls-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("bin")).unwrap();
+        std::fs::create_dir_all(root.join("etc")).unwrap();
+        let code = [0x90u8; 32];
+        let elf = build_elf(0x40_0000, &[(0x40_0000, PF_R | PF_X, &code, 32)]);
+        write_executable(&root.join("bin/hello"), &elf);
+        std::fs::write(root.join("etc/motd"), "Welcome to VaelixOS").unwrap();
+
+        let memory = PhysicalMemory::new(512);
+        let channels = VXChanManager::new();
+        let mut table = ProcessTable::new(&memory, root.to_str().unwrap(), &channels).unwrap();
+        // Fixed addresses, for the scratch space under the stack
+        table.set_randomize_layout(false);
+        assert_eq!(table.resolve("/../etc/./motd"), Ok(root.join("etc/motd")));
+        let (master, slave) = vaelix_core::pty::pty::open(vaelix_core::pty::pty::PtySize { rows: 24, cols: 80 });
+        let pid = table.spawn(KERNEL_PID, "/bin/hello", &["hello"], &[], FileTable::with_terminal(&slave)).unwrap();
+        assert_eq!(table.spawn(KERNEL_PID, "/bin/missing", &[], &[], FileTable::new()), Err("File not found"));
+
+        // Scratch space at the bottom of the stack
+        let scratch = STACK_TOP - STACK_SIZE;
+        let poke = |table: &ProcessTable, address: u64, data: &[u8]| table.get(pid).unwrap().space().write(address, data).unwrap();
+        let peek = |table: &ProcessTable, address: u64, length: usize| {
+            let mut buffer = vec![0; length];
+            table.get(pid).unwrap().space().read(address, &mut buffer).unwrap();
+            buffer
+        };
+
+        // Returns past the SYSCALL, with the flags user code may keep
+        poke(&table, scratch, b"hello");
+        let mut cpu = SyscallCpu { number: syscall::SYS_WRITE, arguments: [1, scratch, 5, 0, 0, 0], flags: 0x3202 | 1 };
+        table.run(pid, &mut cpu).unwrap();
+        let context = *table.get(pid).unwrap().context();
+        assert_eq!(context.registers[RAX], 5);
+        assert_eq!(context.rip, 0x40_0002);
+        assert_eq!(context.rflags, 0x203);
+        assert_eq!(master.read(), b"hello");
+
+        // Arguments are checked before anything happens
+        assert_eq!(call(&mut table, pid, syscall::SYS_WRITE, &[1, 0xDEAD_0000, 5]), Errno::Fault.as_return());
+        assert_eq!(call(&mut table, pid, syscall::SYS_WRITE, &[1, KERNEL_BASE, 5]), Errno::Fault.as_return());
+        assert_eq!(call(&mut table, pid, syscall::SYS_WRITE, &[1, STACK_TOP - 2, 5]), Errno::Fault.as_return());
+        assert_eq!(call(&mut table, pid, syscall::SYS_WRITE, &[7, scratch, 5]), Errno::BadFile.as_return());
+        assert_eq!(call(&mut table, pid, 99, &[]), Errno::NoSys.as_return());
+        assert_eq!(call(&mut table, pid, syscall::SYS_READ, &[0, scratch, 16]), Errno::Again.as_return());
+
+        // Files under the root
+        poke(&table, scratch, b"/etc/motd\0");
+        assert_eq!(call(&mut table, pid, syscall::SYS_OPEN, &[scratch, syscall::O_RDONLY]), 3);
+        assert_eq!(call(&mut table, pid, syscall::SYS_READ, &[3, 0x40_0000, 7]), Errno::Fault.as_return());
+        assert_eq!(call(&mut table, pid, syscall::SYS_READ, &[3, scratch + 64, 7]), 7);
+        assert_eq!(peek(&table, scratch + 64, 7), b"Welcome");
+        assert_eq!(call(&mut table, pid, syscall::SYS_WRITE, &[3, scratch, 1]), Errno::BadFile.as_return());
+        assert_eq!(call(&mut table, pid, syscall::SYS_CLOSE, &[3]), 0);
+        assert_eq!(call(&mut table, pid, syscall::SYS_CLOSE, &[3]), Errno::BadFile.as_return());
+        assert_eq!(call(&mut table, pid, syscall::SYS_OPEN, &[scratch, 3]), Errno::Invalid.as_return());
+        poke(&table, scratch, b"/etc/none\0");
+        assert_eq!(call(&mut table, pid, syscall::SYS_OPEN, &[scratch, syscall::O_RDONLY]), Errno::NoEntry.as_return());
+        assert_eq!(call(&mut table, pid, syscall::SYS_OPEN, &[scratch, syscall::O_WRONLY | syscall::O_CREAT]), 3);
+        assert_eq!(call(&mut table, pid, syscall::SYS_WRITE, &[3, scratch, 4]), 4);
+        assert_eq!(std::fs::read(root.join("etc/none")).unwrap(), b"/etc");
+        call(&mut table, pid, syscall::SYS_CLOSE, &[3]);
+
+        // Anonymous memory
+        let rw = syscall::PROT_READ | syscall::PROT_WRITE;
+        let anonymous = syscall::MAP_PRIVATE | syscall::MAP_ANONYMOUS;
+        let mapped = call(&mut table, pid, syscall::SYS_MMAP, &[0, 5000, rw, anonymous, u64::MAX, 0]);
+        assert_eq!(mapped, MMAP_TOP - 2 * PAGE_SIZE);
+        // Pages are only there once touched
+        let space = table.get(pid).unwrap().space();
+        assert!(space.translate(mapped + PAGE_SIZE).is_none());
+        space.populate(mapped + PAGE_SIZE).unwrap();
+        let (_, flags) = space.translate(mapped + PAGE_SIZE).unwrap();
+        assert_eq!(flags & (USER | WRITABLE | NO_EXECUTE), USER | WRITABLE | NO_EXECUTE);
+        assert_eq!(call(&mut table, pid, syscall::SYS_MMAP, &[0, 4096, rw, syscall::MAP_PRIVATE, 3, 0]), Errno::BadFile.as_return());
+        assert_eq!(call(&mut table, pid, syscall::SYS_MMAP, &[0, 0, rw, anonymous, u64::MAX, 0]), Errno::Invalid.as_return());
+
+        // Channels as descriptors, a message per read or write
+        poke(&table, mapped, b"svc\0ping");
+        assert_eq!(call(&mut table, pid, syscall::SYS_CHANNEL_OPEN, &[mapped]), Errno::NoEntry.as_return());
+        assert_eq!(call(&mut table, pid, syscall::SYS_CHANNEL_CREATE, &[mapped]), 3);
+        assert_eq!(call(&mut table, pid, syscall::SYS_CHANNEL_CREATE, &[mapped]), Errno::Exists.as_return());
+        assert_eq!(call(&mut table, pid, syscall::SYS_WRITE, &[3, mapped + 4, 4]), 4);
+        assert_eq!(channels.try_receive_message("svc"), Ok(Some("ping".to_string())));
+        channels.send_message("svc", "pong".to_string()).unwrap();
+        assert_eq!(call(&mut table, pid, syscall::SYS_READ, &[3, mapped + 64, 2]), Errno::MessageSize.as_return());
+        assert_eq!(call(&mut table, pid, syscall::SYS_READ, &[3, mapped + 64, 16]), 4);
+        assert_eq!(peek(&table, mapped + 64, 4), b"pong");
+        assert_eq!(call(&mut table, pid, syscall::SYS_CHANNEL_OPEN, &[mapped]), 4);
+
+        // A child with arguments and the caller's terminal
+        poke(&table, scratch, b"/bin/hello\0-v\0");
+        let argv: Vec<u8> = [scratch, scratch + 11, 0].iter().flat_map(|word| word.to_le_bytes()).collect();
+        poke(&table, scratch + 32, &argv);
+        let child = call(&mut table, pid, syscall::SYS_SPAWN, &[scratch, scratch + 32, 0]) as Pid;
+        assert_eq!(table.pids(), vec![pid, child]);
+        let child_process = table.get(child).unwrap();
+        assert_eq!(child_process.name(), "hello");
+        assert_eq!(child_process.files().count(), 3);
+        let mut argc = [0; 8];
+        child_process.space().read(child_process.context().rsp, &mut argc).unwrap();
+        assert_eq!(u64::from_le_bytes(argc), 2);
+        poke(&table, scratch, b"/etc/motd\0");
+        assert_eq!(call(&mut table, pid, syscall::SYS_SPAWN, &[scratch, 0, 0]), Errno::Access.as_return());
+        write_executable(&root.join("bin/script"), b"#!/bin/sh\n");
+        poke(&table, scratch, b"/bin/script\0");
+        assert_eq!(call(&mut table, pid, syscall::SYS_SPAWN, &[scratch, 0, 0]), Errno::NotExecutable.as_return());
+
+        // Exiting frees the process and keeps its status
+        call(&mut table, child, syscall::SYS_EXIT, &[3]);
+        assert!(table.get(child).is_none());
+        assert!(table.is_zombie(child));
+        assert!(table.run(child, &mut SyscallCpu { number: 0, arguments: [0; 6], flags: 0 }).is_err());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    pub fn test_process_lifecycle() {
+        let root = std::env::temp_dir().join(format!("vaelix-lifecycle-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("bin")).unwrap();
+        let code = [0x90u8; 32];
+        write_executable(&root.join("bin/init"), &build_elf(0x40_0000, &[(0x40_0000, PF_R | PF_X, &code, 32)]));
+        let memory = PhysicalMemory::new(512);
+        let mut table = ProcessTable::new(&memory, root.to_str().unwrap(), &VXChanManager::new()).unwrap();
+        table.set_randomize_layout(false);
+        let init = table.spawn(KERNEL_PID, "/bin/init", &["init"], &[], FileTable::new()).unwrap();
+        assert_eq!(init, INIT_PID);
+        assert_eq!(table.spawn(42, "/bin/init", &[], &[], FileTable::new()), Err("No such process"));
+
+        // Children get their arguments and environment
+        let scratch = STACK_TOP - STACK_SIZE;
+        let poke = |table: &ProcessTable, pid: Pid, address: u64, data: &[u8]| table.get(pid).unwrap().space().write(address, data).unwrap();
+        poke(&table, init, scratch, b"/bin/init\0sh\0TERM=vt100\0");
+        let pointers: Vec<u8> = [scratch + 10, 0, scratch + 13, 0].iter().flat_map(|word| word.to_le_bytes()).collect();
+        poke(&table, init, scratch + 64, &pointers);
+        let shell = call(&mut table, init, syscall::SYS_SPAWN, &[scratch, scratch + 64, scratch + 80]) as Pid;
+        let space = table.get(shell).unwrap().space();
+        let stack = table.get(shell).unwrap().context().rsp;
+        assert_eq!(read_u64(space, stack), 1);
+        let mut variable = [0; 10];
+        space.read(read_u64(space, stack + 24), &mut variable).unwrap();
+        assert_eq!(&variable, b"TERM=vt100");
+        poke(&table, shell, scratch, b"/bin/init\0");
+        let job = call(&mut table, shell, syscall::SYS_SPAWN, &[scratch, 0, 0]) as Pid;
+        assert_eq!(table.children(init), vec![shell]);
+        assert_eq!(call(&mut table, job, syscall::SYS_GETPPID, &[]), shell as u64);
+        assert_eq!(call(&mut table, job, syscall::SYS_GETPID, &[]), job as u64);
+
+        // Waiting blocks until a child exits, then returns its PID and status
+        assert_eq!(call(&mut table, init, syscall::SYS_WAIT, &[job as u64, 0, 0]), Errno::NoChild.as_return());
+        assert_eq!(call(&mut table, init, syscall::SYS_WAIT, &[syscall::ANY_CHILD, 0, syscall::WNOHANG]), 0);
+        assert_eq!(call(&mut table, init, syscall::SYS_WAIT, &[syscall::ANY_CHILD, 0x40_0000, 0]), Errno::Fault.as_return());
+        call(&mut table, init, syscall::SYS_WAIT, &[syscall::ANY_CHILD, scratch + 128, 0]);
+        assert_eq!(table.blocker(init), Some(Blocker::Wait { child: None, status: scratch + 128, untraced: false }));
+        assert_eq!(table.runnable(), vec![shell, job]);
+        let mut cpu = SyscallCpu { number: syscall::SYS_GETPID, arguments: [0; 6], flags: 0x202 };
+        assert_eq!(table.run(init, &mut cpu), Err("Thread is blocked"));
+
+        // The shell's job is orphaned and adopted by init
+        call(&mut table, shell, syscall::SYS_EXIT, &[7]);
+        assert_eq!(table.blocker(init), None);
+        let context = *table.get(init).unwrap().context();
+        assert_eq!(context.registers[RAX], shell as u64);
+        assert_eq!(context.rip, context.registers[RCX]);
+        assert_eq!(read_u64(table.get(init).unwrap().space(), scratch + 128) as u32, 7 << 8);
+        assert!(!table.is_zombie(shell));
+        assert_eq!(table.parent(job), Some(INIT_PID));
+        assert_eq!(call(&mut table, job, syscall::SYS_GETPPID, &[]), INIT_PID as u64);
+
+        // Exited children wait to be reaped
+        call(&mut table, job, syscall::SYS_EXIT, &[300]);
+        assert!(table.is_zombie(job));
+        assert_eq!(call(&mut table, init, syscall::SYS_WAIT, &[job as u64, scratch + 128, syscall::WNOHANG]), job as u64);
+        assert_eq!(read_u64(table.get(init).unwrap().space(), scratch + 128) as u32, 44 << 8);
+        assert_eq!(call(&mut table, init, syscall::SYS_WAIT, &[syscall::ANY_CHILD, 0, 0]), Errno::NoChild.as_return());
+
+        // The kernel reaps what it started, and what init leaves behind
+        poke(&table, init, scratch, b"/bin/init\0");
+        let last = call(&mut table, init, syscall::SYS_SPAWN, &[scratch, 0, 0]) as Pid;
+        table.exit(init, ExitStatus::Killed(11));
+        assert_eq!(table.parent(last), Some(KERNEL_PID));
+        assert_eq!(table.wait(KERNEL_PID, None), Ok(Some((init, ExitStatus::Killed(11)))));
+        assert_eq!(table.wait(KERNEL_PID, None), Ok(None));
+        table.exit(last, ExitStatus::Exited(0));
+        assert_eq!(table.wait(KERNEL_PID, Some(last)), Ok(Some((last, ExitStatus::Exited(0)))));
+        assert_eq!(table.wait(KERNEL_PID, None), Err("No child processes"));
+        assert_eq!(ExitStatus::Killed(11).encode(), 11);
+        assert_eq!(memory.allocated(), 1);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    pub fn test_job_control() {
+        let root = std::env::temp_dir().join(format!("vaelix-jobs-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("bin")).unwrap();
+        let code = [0x90u8; 32];
+        write_executable(&root.join("bin/sh"), &build_elf(0x40_0000, &[(0x40_0000, PF_R | PF_X, &code, 32)]));
+        let memory = PhysicalMemory::new(512);
+        let mut table = ProcessTable::new(&memory, root.to_str().unwrap(), &VXChanManager::new()).unwrap();
+        table.set_randomize_layout(false);
+        let (master, slave) = pty::open(PtySize { rows: 24, cols: 80 });
+
+        // A shell the kernel starts leads a session on its terminal, and
+        // keeps out of the way of what it typed at its jobs
+        let shell = table.spawn(KERNEL_PID, "/bin/sh", &["sh"], &[], FileTable::with_terminal(&slave)).unwrap();
+        assert_eq!((slave.session(), slave.foreground()), (Some(shell), Some(shell)));
+        assert_eq!(table.controlling_terminal(shell).map(|terminal| terminal.index()), Some(slave.index()));
+        for number in [SIGINT, SIGTSTP, SIGTTOU] {
+            assert_eq!(call(&mut table, shell, syscall::SYS_SIGNAL, &[number as u64, syscall::SIG_IGN]), syscall::SIG_DFL);
+        }
+        assert_eq!(call(&mut table, shell, syscall::SYS_SIGNAL, &[SIGINT as u64, syscall::SIG_IGN]), syscall::SIG_IGN);
+        assert_eq!(call(&mut table, shell, syscall::SYS_SIGNAL, &[SIGKILL as u64, syscall::SIG_IGN]), Errno::Invalid.as_return());
+        assert_eq!(call(&mut table, shell, syscall::SYS_SETSID, &[]), Errno::NotPermitted.as_return());
+
+        // A job of two processes in a group of its own
+        let scratch = STACK_TOP - STACK_SIZE;
+        table.get(shell).unwrap().space().write(scratch, b"/bin/sh\0").unwrap();
+        let spawn = |table: &mut ProcessTable| call(table, shell, syscall::SYS_SPAWN, &[scratch, 0, 0]) as Pid;
+        let (job, partner, other) = (spawn(&mut table), spawn(&mut table), spawn(&mut table));
+        assert_eq!(call(&mut table, job, syscall::SYS_GETPGID, &[0]), shell as u64);
+        assert_eq!(call(&mut table, shell, syscall::SYS_SETPGID, &[job as u64, 0]), 0);
+        assert_eq!(call(&mut table, shell, syscall::SYS_SETPGID, &[partner as u64, job as u64]), 0);
+        assert_eq!(call(&mut table, shell, syscall::SYS_SETPGID, &[0, job as u64]), Errno::NotPermitted.as_return());
+        assert_eq!(call(&mut table, job, syscall::SYS_SETPGID, &[shell as u64, 0]), Errno::NoProcess.as_return());
+        assert_eq!(table.group_members(job), [job, partner]);
+        assert_eq!(call(&mut table, partner, syscall::SYS_GETSID, &[0]), shell as u64);
+
+        // In the foreground it is stopped by ^Z, which the shell hears of
+        assert_eq!(call(&mut table, shell, syscall::SYS_TCSETPGRP, &[0, job as u64]), 0);
+        assert_eq!(call(&mut table, partner, syscall::SYS_TCGETPGRP, &[0]), job as u64);
+        assert_eq!(master.foreground(), Some(job));
+        master.write(b"\x1A").unwrap();
+        table.poll_terminals();
+        assert!(table.is_stopped(job) && table.is_stopped(partner) && !table.is_stopped(shell));
+        assert_eq!(table.runnable(), [shell, other]);
+        let mut cpu = SyscallCpu { number: syscall::SYS_GETPID, arguments: [0; 6], flags: 0x202 };
+        assert_eq!(table.run(job, &mut cpu), Err("Process is stopped"));
+        assert!(procfs::read(&table, shell, &format!("/proc/{}/status", job)).unwrap().contains("State:\tT (stopped)"));
+        let status = scratch + 64;
+        let untraced = syscall::WUNTRACED | syscall::WNOHANG;
+        assert_eq!(call(&mut table, shell, syscall::SYS_WAIT, &[syscall::ANY_CHILD, status, untraced]), job as u64);
+        assert_eq!(read_u64(table.get(shell).unwrap().space(), status) as u32, ExitStatus::Stopped(SIGTSTP).encode());
+        assert_eq!(ExitStatus::Stopped(SIGTSTP).encode(), 20 << 8 | 0x7F);
+        assert_eq!(call(&mut table, shell, syscall::SYS_WAIT, &[syscall::ANY_CHILD, 0, untraced]), partner as u64);
+        assert_eq!(call(&mut table, shell, syscall::SYS_WAIT, &[syscall::ANY_CHILD, 0, untraced]), 0);
+
+        // Continued in the background, it is stopped again on reading the
+        // terminal or taking it over, though it may write to it
+        assert_eq!(call(&mut table, shell, syscall::SYS_TCSETPGRP, &[0, shell as u64]), 0);
+        assert_eq!(call(&mut table, shell, syscall::SYS_KILL, &[-(job as i64) as u64, SIGCONT as u64]), 0);
+        assert!(!table.is_stopped(job) && !table.is_stopped(partner));
+        assert_eq!(master.read(), b"^Z\r\n");
+        table.get(job).unwrap().space().write(scratch, b"done").unwrap();
+        assert_eq!(call(&mut table, job, syscall::SYS_WRITE, &[1, scratch, 4]), 4);
+        assert_eq!(master.read(), b"done");
+        for (number, arguments) in [(syscall::SYS_READ, [0, scratch, 16]), (syscall::SYS_TCSETPGRP, [0, job as u64, 0])] {
+            assert_eq!(call(&mut table, job, number, &arguments), Errno::Interrupted.as_return());
+            assert!(table.is_stopped(job) && table.is_stopped(partner));
+            let stopped_by = if number == syscall::SYS_READ { SIGTTIN } else { SIGTTOU };
+            assert_eq!(table.wait_untraced(shell, Some(partner), true), Ok(Some((partner, ExitStatus::Stopped(stopped_by)))));
+            assert_eq!(table.signal_group(job, SIGCONT), Ok(()));
+        }
+        assert_eq!(slave.foreground(), Some(shell));
+
+        // Brought back, ^C ends it while the shell carries on
+        assert_eq!(call(&mut table, shell, syscall::SYS_TCSETPGRP, &[0, job as u64]), 0);
+        master.write(b"\x03").unwrap();
+        table.poll_terminals();
+        assert_eq!(table.wait(shell, Some(job)), Ok(Some((job, ExitStatus::Killed(SIGINT)))));
+        assert_eq!(table.wait(shell, Some(partner)), Ok(Some((partner, ExitStatus::Killed(SIGINT)))));
+        assert_eq!(call(&mut table, shell, syscall::SYS_TCSETPGRP, &[0, job as u64]), Errno::NotPermitted.as_return());
+        assert_eq!(call(&mut table, shell, syscall::SYS_TCSETPGRP, &[0, shell as u64]), 0);
+
+        // Signals need the same user, or root
+        assert_eq!(call(&mut table, other, syscall::SYS_SETUID, &[1000]), 0);
+        assert_eq!(call(&mut table, other, syscall::SYS_KILL, &[shell as u64, SIGINT as u64]), Errno::NotPermitted.as_return());
+        assert_eq!(call(&mut table, other, syscall::SYS_KILL, &[other as u64, 0]), 0);
+        assert_eq!(call(&mut table, other, syscall::SYS_KILL, &[-999i64 as u64, SIGINT as u64]), Errno::NoProcess.as_return());
+        assert_eq!(call(&mut table, other, syscall::SYS_KILL, &[other as u64, 99]), Errno::Invalid.as_return());
+
+        // A session of its own has no terminal, and cannot take one in use
+        assert_eq!(call(&mut table, other, syscall::SYS_SETSID, &[]), other as u64);
+        assert_eq!(call(&mut table, other, syscall::SYS_GETPGID, &[0]), other as u64);
+        assert_eq!(call(&mut table, other, syscall::SYS_TCGETPGRP, &[0]), Errno::NotTerminal.as_return());
+        assert_eq!(call(&mut table, other, syscall::SYS_SETCTTY, &[0]), Errno::NotPermitted.as_return());
+        assert_eq!(call(&mut table, other, syscall::SYS_SETPGID, &[0, shell as u64]), Errno::NotPermitted.as_return());
+
+        // When the shell goes, a stopped job left behind is hung up, as
+        // nothing could continue it
+        let stray = spawn(&mut table);
+        assert_eq!(call(&mut table, stray, syscall::SYS_SETPGID, &[0, 0]), 0);
+        assert_eq!(call(&mut table, shell, syscall::SYS_KILL, &[stray as u64, SIGSTOP as u64]), 0);
+        assert!(table.is_stopped(stray));
+        call(&mut table, shell, syscall::SYS_EXIT, &[0]);
+        assert_eq!(table.wait(KERNEL_PID, Some(stray)), Ok(Some((stray, ExitStatus::Killed(SIGHUP)))));
+        assert_eq!(slave.session(), None);
+        assert!(table.get(other).is_some());
+
+        // So is a session whose terminal goes away
+        let (console, console_slave) = pty::open(PtySize { rows: 24, cols: 80 });
+        let login = table.spawn(KERNEL_PID, "/bin/sh", &["sh"], &[], FileTable::with_terminal(&console_slave)).unwrap();
+        drop(console);
+        table.poll_terminals();
+        assert_eq!(table.wait(KERNEL_PID, Some(login)), Ok(Some((login, ExitStatus::Killed(SIGHUP)))));
+        assert_eq!(console_slave.session(), None);
+
+        table.exit(other, ExitStatus::Exited(0));
+        assert_eq!(memory.allocated(), 1);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    pub fn test_shell_parsing() {
+        let text = |text: &str| vec![Part::Text(text.to_string())];
+        let pipelines = vxsh::parse("FOO=\"a b\" cat < in 2>&1 | sort -r >> 'out file' & echo \"$HOME/x\"${USER}$?\\$ # a comment").unwrap();
+        assert_eq!(pipelines.len(), 2);
+        assert!(pipelines[0].background && !pipelines[1].background);
+        assert_eq!(pipelines[0].text, "FOO=\"a b\" cat < in 2>&1 | sort -r >> 'out file'");
+        assert_eq!(pipelines[1].text, "echo \"$HOME/x\"${USER}$?\\$");
+        let cat = &pipelines[0].commands[0];
+        assert_eq!(cat.assignments, [("FOO".to_string(), vec![Part::Quoted("a b".to_string())])]);
+        assert_eq!(cat.words, [text("cat")]);
+        assert_eq!(cat.redirections, [Redirection::Input(0, text("in")), Redirection::Duplicate(2, 1)]);
+        let sort = &pipelines[0].commands[1];
+        assert_eq!(sort.words, [text("sort"), text("-r")]);
+        assert_eq!(sort.redirections, [Redirection::Output(1, vec![Part::Quoted("out file".to_string())], true)]);
+        let echo = &pipelines[1].commands[0].words[1];
+        let lookup = |name: &str| match name {
+            "HOME" => Some("/root".to_string()),
+            "?" => Some("1".to_string()),
+            _ => None,
+        };
+        assert_eq!(vxsh::expand(echo, lookup), "/root/x1$");
+        assert_eq!(vxsh::parse("echo \"\" ''").unwrap()[0].commands[0].words.len(), 3);
+        assert_eq!(vxsh::parse("  # nothing").unwrap(), []);
+        assert_eq!(vxsh::parse("a;b;").unwrap().len(), 2);
+        assert_eq!(vxsh::parse("a 2>err").unwrap()[0].commands[0].redirections, [Redirection::Output(2, text("err"), false)]);
+
+        for (line, error) in [
+            ("echo 'open", "Unterminated quote"),
+            ("echo \"open", "Unterminated quote"),
+            ("echo ${", "Bad substitution"),
+            ("echo ${a-b}", "Bad substitution"),
+            ("| cat", "Empty command"),
+            ("cat |", "Empty command"),
+            ("; ls", "Empty command"),
+            ("cat >", "Missing redirection target"),
+            ("cat > | x", "Missing redirection target"),
+            ("cat >&x", "Bad file descriptor"),
+        ] {
+            assert_eq!(vxsh::parse(line), Err(error), "{}", line);
+        }
+    }
+
+    #[test]
+    pub fn test_shell() {
+        let root = std::env::temp_dir().join(format!("vaelix-vxsh-{}", std::process::id()));
+        for directory in ["bin", "etc", "tmp"] {
+            std::fs::create_dir_all(root.join(directory)).unwrap();
+        }
+        let code = [0x90u8; 32];
+        let elf = build_elf(0x40_0000, &[(0x40_0000, PF_R | PF_X, &code, 32)]);
+        for program in ["vxsh", "cat", "sort"] {
+            write_executable(&root.join("bin").join(program), &elf);
+        }
+        std::fs::write(root.join("etc/motd"), "hello\n").unwrap();
+        let memory = PhysicalMemory::new(1024);
+        let mut table = ProcessTable::new(&memory, root.to_str().unwrap(), &VXChanManager::new()).unwrap();
+        table.set_randomize_layout(false);
+        let (master, slave) = pty::open(PtySize { rows: 24, cols: 80 });
+        let pid = table.spawn(KERNEL_PID, "/bin/vxsh", &["vxsh"], &[], FileTable::with_terminal(&slave)).unwrap();
+        let mut shell = Shell::new(&mut table, pid, &["PATH=/bin", "HOME=/root"]).unwrap();
+        assert!(shell.is_interactive());
+        let output = |master: &pty::PtyMaster| String::from_utf8(master.read()).unwrap();
+        let type_line = |table: &mut ProcessTable, shell: &mut Shell, line: &str| {
+            master.write(format!("{}\r", line).as_bytes()).unwrap();
+            table.poll_terminals();
+            assert!(shell.poll(table));
+        };
+
+        // Prompts once, as root, then runs what is typed
+        assert!(shell.poll(&mut table));
+        assert!(shell.poll(&mut table));
+        assert_eq!(output(&master), "# ");
+        type_line(&mut table, &mut shell, "GREETING=hi; echo \"$GREETING\" world > tmp/out; echo $?");
+        assert_eq!(std::fs::read_to_string(root.join("tmp/out")).unwrap(), "hi world\n");
+        assert!(output(&master).ends_with("0\r\n# "));
+        assert_eq!(shell.variable("GREETING").as_deref(), Some("hi"));
+        assert_eq!(shell.variable("$"), Some(pid.to_string()));
+
+        // A pipeline runs in the background as one job with pipes between
+        // its commands, the shell keeping the terminal
+        type_line(&mut table, &mut shell, "cat < /etc/motd | sort 2>&1 >> /tmp/sorted &");
+        let job = shell.jobs()[0].clone();
+        let (cat, sort) = (job.pids[0], job.pids[1]);
+        assert_eq!((job.id, job.group, job.text.as_str()), (1, cat, "cat < /etc/motd | sort 2>&1 >> /tmp/sorted"));
+        assert_eq!(table.group_members(cat), [cat, sort]);
+        assert_eq!(slave.foreground(), Some(pid));
+        assert_eq!(shell.variable("!"), Some(sort.to_string()));
+        assert!(output(&master).contains(&format!("[1] {}\r\n", sort)));
+        assert!(matches!(table.get(cat).unwrap().files().get(0), Some(OpenFile::File { readable: true, .. })));
+        assert!(matches!(table.get(cat).unwrap().files().get(1), Some(OpenFile::PipeWriter { .. })));
+        assert!(matches!(table.get(sort).unwrap().files().get(0), Some(OpenFile::PipeReader { .. })));
+        assert!(matches!(table.get(sort).unwrap().files().get(1), Some(OpenFile::File { writable: true, .. })));
+        assert!(matches!(table.get(sort).unwrap().files().get(2), Some(OpenFile::Terminal { .. })));
+
+        // What one writes the next reads, waiting for it, and to the end
+        // once the writer goes
+        let scratch = STACK_TOP - STACK_SIZE;
+        table.get(cat).unwrap().space().write(scratch, b"hello").unwrap();
+        call(&mut table, sort, syscall::SYS_READ, &[0, scratch + 64, 16]);
+        assert!(matches!(table.blocker(sort), Some(Blocker::Pipe(_))));
+        assert_eq!(call(&mut table, cat, syscall::SYS_WRITE, &[1, scratch, 5]), 5);
+        assert_eq!(table.blocker(sort), None);
+        assert_eq!(table.get(sort).unwrap().thread(sort).unwrap().context.registers[RAX], 5);
+        call(&mut table, cat, syscall::SYS_EXIT, &[0]);
+        assert_eq!(call(&mut table, sort, syscall::SYS_READ, &[0, scratch, 16]), 0);
+        assert_eq!(call(&mut table, sort, syscall::SYS_WRITE, &[1, scratch + 64, 5]), 5);
+        call(&mut table, sort, syscall::SYS_EXIT, &[0]);
+        assert!(shell.poll(&mut table));
+        assert_eq!(std::fs::read_to_string(root.join("tmp/sorted")).unwrap(), "hello");
+        assert!(output(&master).contains("[1]+  Done                    cat < /etc/motd | sort"));
+        assert!(shell.jobs().is_empty());
+
+        // In the foreground the terminal is the job's until it stops
+        type_line(&mut table, &mut shell, "cat /etc/motd");
+        let cat = shell.foreground().unwrap().group;
+        assert_eq!(slave.foreground(), Some(cat));
+        assert!(shell.poll(&mut table));
+        assert!(!output(&master).contains("# "));
+        master.write(b"\x1A").unwrap();
+        table.poll_terminals();
+        assert!(shell.poll(&mut table));
+        assert_eq!(slave.foreground(), Some(pid));
+        assert_eq!(shell.status(), 128 + SIGTSTP);
+        assert_eq!(shell.jobs()[0].state, JobState::Stopped(SIGTSTP));
+        let shown = output(&master);
+        assert!(shown.contains("[1]+  Stopped                 cat /etc/motd"));
+        assert!(shown.ends_with("# "));
+
+        type_line(&mut table, &mut shell, "jobs");
+        assert!(output(&master).contains("[1]+  Stopped                 cat /etc/motd\r\n"));
+        type_line(&mut table, &mut shell, "fg");
+        assert!(!table.is_stopped(cat));
+        assert_eq!(slave.foreground(), Some(cat));
+        assert!(output(&master).contains("cat /etc/motd\r\n"));
+        call(&mut table, cat, syscall::SYS_EXIT, &[3]);
+        assert!(shell.poll(&mut table));
+        type_line(&mut table, &mut shell, "echo $?");
+        assert!(output(&master).contains("3\r\n"));
+
+        // Background jobs are signalled by job number
+        type_line(&mut table, &mut shell, "sort &");
+        type_line(&mut table, &mut shell, "kill -STOP %1; jobs");
+        assert!(output(&master).contains("[1]+  Stopped"));
+        type_line(&mut table, &mut shell, "bg %1; kill %1");
+        assert!(shell.poll(&mut table));
+        assert!(output(&master).contains("[1]+  Terminated              sort"));
+
+        // Variables are exported to commands started after
+        type_line(&mut table, &mut shell, "export EDITOR=vi; unset GREETING; export");
+        assert_eq!(shell.variable("GREETING"), None);
+        assert!(output(&master).contains("export EDITOR=vi\r\nexport HOME=/root\r\nexport PATH=/bin\r\n"));
+
+        // Errors are reported with the status other shells give
+        for (line, status, message) in [
+            ("missing", STATUS_NOT_FOUND, "vxsh: missing: command not found"),
+            ("/etc/motd", STATUS_NOT_EXECUTABLE, "vxsh: /etc/motd:"),
+            ("cat < /etc/none", 1, "vxsh: /etc/none: No such file or directory"),
+            ("echo 'open", STATUS_USAGE, "vxsh: Unterminated quote"),
+            ("fg %9", 1, "vxsh: fg: %9: no such job"),
+        ] {
+            type_line(&mut table, &mut shell, line);
+            assert_eq!(shell.status(), status, "{}", line);
+            assert!(output(&master).contains(message), "{}", line);
+        }
+
+        // exit ends the process with its status
+        type_line(&mut table, &mut shell, "sort &");
+        master.write(b"exit 4\r").unwrap();
+        table.poll_terminals();
+        assert!(!shell.poll(&mut table));
+        assert!(shell.is_finished());
+        assert_eq!(table.wait(KERNEL_PID, Some(pid)), Ok(Some((pid, ExitStatus::Exited(4)))));
+        assert_eq!(slave.session(), None);
+
+        // Not on a terminal it runs a script to its end
+        let script = root.join("tmp/script");
+        std::fs::write(&script, "echo one > /tmp/script.out\nexit\n").unwrap();
+        let files = FileTable::new();
+        let pid = table.spawn(KERNEL_PID, "/bin/vxsh", &["vxsh"], &[], files).unwrap();
+        let input = syscall::open(&table, pid, "/tmp/script", syscall::O_RDONLY).unwrap();
+        table.get_mut(pid).unwrap().files_mut().install(0, input).unwrap();
+        let mut shell = Shell::new(&mut table, pid, &[]).unwrap();
+        assert!(!shell.is_interactive());
+        assert_eq!(shell.variable("PATH").as_deref(), Some(vxsh::DEFAULT_PATH));
+        assert!(!shell.poll(&mut table));
+        assert_eq!(std::fs::read_to_string(root.join("tmp/script.out")).unwrap(), "one\n");
+        assert_eq!(table.wait(KERNEL_PID, Some(pid)), Ok(Some((pid, ExitStatus::Exited(0)))));
+
+        for pid in table.pids() {
+            table.exit(pid, ExitStatus::Exited(0));
+        }
+        assert_eq!(memory.allocated(), 1);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    pub fn test_pipes_and_fifos() {
+        let root = std::env::temp_dir().join(format!("vaelix-pipes-{}", std::process::id()));
+        for directory in ["bin", "tmp"] {
+            std::fs::create_dir_all(root.join(directory)).unwrap();
+        }
+        let code = [0x90u8; 32];
+        write_executable(&root.join("bin/app"), &build_elf(0x40_0000, &[(0x40_0000, PF_R | PF_X, &code, 32)]));
+        let memory = PhysicalMemory::new(512);
+        let mut table = ProcessTable::new(&memory, root.to_str().unwrap(), &VXChanManager::new()).unwrap();
+        table.set_randomize_layout(false);
+        let spawn = |table: &mut ProcessTable| table.spawn(KERNEL_PID, "/bin/app", &[], &[], FileTable::new()).unwrap();
+        let (producer, consumer) = (spawn(&mut table), spawn(&mut table));
+        let scratch = STACK_TOP - STACK_SIZE;
+        let result = |table: &ProcessTable, pid: Pid| table.get(pid).unwrap().thread(pid).unwrap().context.registers[RAX];
+        let fds = |table: &ProcessTable, pid: Pid| {
+            let pair = read_u64(table.get(pid).unwrap().space(), scratch);
+            (pair & 0xFFFF_FFFF, pair >> 32)
+        };
+
+        // A nonblocking pipe fails where a blocking one would wait
+        assert_eq!(call(&mut table, producer, syscall::SYS_PIPE, &[scratch, syscall::O_APPEND]), Errno::Invalid.as_return());
+        assert_eq!(call(&mut table, producer, syscall::SYS_PIPE, &[scratch, syscall::O_NONBLOCK]), 0);
+        let (reader, writer) = fds(&table, producer);
+        assert_eq!((reader, writer), (0, 1));
+        assert_eq!(call(&mut table, producer, syscall::SYS_READ, &[reader, scratch + 64, 16]), Errno::Again.as_return());
+        assert_eq!(call(&mut table, producer, syscall::SYS_WRITE, &[reader, scratch, 1]), Errno::BadFile.as_return());
+        assert_eq!(call(&mut table, producer, syscall::SYS_WRITE, &[writer, scratch + 64, 1]), 1);
+        assert_eq!(call(&mut table, producer, syscall::SYS_READ, &[writer, scratch + 64, 1]), Errno::BadFile.as_return());
+        for fd in [reader, writer] {
+            assert_eq!(call(&mut table, producer, syscall::SYS_CLOSE, &[fd]), 0);
+        }
+
+        // Children share a pipe; a reader waits for data and is woken by
+        // the write, then waits again and sees the end once every writer
+        // has gone, even by exiting
+        assert_eq!(call(&mut table, producer, syscall::SYS_PIPE, &[scratch, 0]), 0);
+        let (reader, writer) = fds(&table, producer);
+        let mut files = FileTable::new();
+        files.install(0, table.get(producer).unwrap().files().get(reader as usize).unwrap().try_clone().unwrap()).unwrap();
+        let child = table.spawn(producer, "/bin/app", &[], &[], files).unwrap();
+        call(&mut table, child, syscall::SYS_READ, &[0, scratch, 16]);
+        assert!(matches!(table.blocker(child), Some(Blocker::Pipe(_))));
+        table.get(producer).unwrap().space().write(scratch + 64, b"ping").unwrap();
+        assert_eq!(call(&mut table, producer, syscall::SYS_WRITE, &[writer, scratch + 64, 4]), 4);
+        assert_eq!((table.blocker(child), result(&table, child)), (None, 4));
+        let mut bytes = [0; 4];
+        table.get(child).unwrap().space().read(scratch, &mut bytes).unwrap();
+        assert_eq!(&bytes, b"ping");
+        call(&mut table, child, syscall::SYS_READ, &[0, scratch, 16]);
+        assert!(table.blocker(child).is_some());
+        assert_eq!(call(&mut table, producer, syscall::SYS_CLOSE, &[reader]), 0);
+        assert!(table.blocker(child).is_some());
+        call(&mut table, producer, syscall::SYS_EXIT, &[0]);
+        assert_eq!((table.blocker(child), result(&table, child)), (None, 0));
+        table.exit(child, ExitStatus::Exited(0));
+
+        // A full pipe makes writers wait for room; small writes go in whole
+        let producer = spawn(&mut table);
+        let scratch_space = 64 * 1024;
+        let rw = syscall::PROT_READ | syscall::PROT_WRITE;
+        let buffer = call(&mut table, producer, syscall::SYS_MMAP, &[0, scratch_space, rw, syscall::MAP_PRIVATE | syscall::MAP_ANONYMOUS, u64::MAX, 0]);
+        assert_eq!(call(&mut table, producer, syscall::SYS_PIPE, &[scratch, 0]), 0);
+        let (reader, writer) = fds(&table, producer);
+        assert_eq!(call(&mut table, producer, syscall::SYS_WRITE, &[writer, buffer, pipe::PIPE_CAPACITY as u64 - 100]), pipe::PIPE_CAPACITY as u64 - 100);
+        call(&mut table, producer, syscall::SYS_WRITE, &[writer, buffer, 200]);
+        assert!(matches!(table.blocker(producer), Some(Blocker::Pipe(_))));
+        let mut files = FileTable::new();
+        files.install(0, table.get(producer).unwrap().files().get(reader as usize).unwrap().try_clone().unwrap()).unwrap();
+        let drainer = table.spawn(producer, "/bin/app", &[], &[], files).unwrap();
+        assert_eq!(call(&mut table, drainer, syscall::SYS_READ, &[0, scratch, 64]), 64);
+        assert!(table.blocker(producer).is_some());
+        assert_eq!(call(&mut table, drainer, syscall::SYS_READ, &[0, scratch, 64]), 64);
+        assert_eq!((table.blocker(producer), result(&table, producer)), (None, 200));
+
+        // Poll says which ends are ready, and that the other side is gone;
+        // writers once there is room for an atomic write
+        let poll = |table: &mut ProcessTable, pid: Pid, entries: &[(i32, u16)]| {
+            let mut data = Vec::new();
+            for (fd, events) in entries {
+                data.extend_from_slice(&fd.to_le_bytes());
+                data.extend_from_slice(&events.to_le_bytes());
+                data.extend_from_slice(&[0xFF, 0xFF]);
+            }
+            table.get(pid).unwrap().space().write(scratch + 256, &data).unwrap();
+            let ready = call(table, pid, syscall::SYS_POLL, &[scratch + 256, entries.len() as u64, 0]);
+            let mut data = vec![0; entries.len() * syscall::POLLFD_SIZE];
+            table.get(pid).unwrap().space().read(scratch + 256, &mut data).unwrap();
+            let revents: Vec<u16> = data.chunks(syscall::POLLFD_SIZE).map(|entry| u16::from_le_bytes([entry[6], entry[7]])).collect();
+            (ready, revents)
+        };
+        let (pollin, pollout) = (syscall::POLLIN, syscall::POLLOUT);
+        let both = pollin | pollout;
+        assert_eq!(poll(&mut table, producer, &[(writer as i32, both)]), (0, vec![0]));
+        assert_eq!(call(&mut table, drainer, syscall::SYS_READ, &[0, scratch, pipe::PIPE_ATOMIC as u64]), pipe::PIPE_ATOMIC as u64);
+        assert_eq!(poll(&mut table, producer, &[(reader as i32, both), (writer as i32, both), (-1, both), (9, both)]), (3, vec![pollin, pollout, 0, syscall::POLLNVAL]));
+        assert_eq!(call(&mut table, producer, syscall::SYS_CLOSE, &[writer]), 0);
+        assert_eq!(poll(&mut table, producer, &[(reader as i32, pollout)]), (1, vec![syscall::POLLHUP]));
+        assert_eq!(call(&mut table, producer, syscall::SYS_POLL, &[scratch + 256, 1, 5]), Errno::Invalid.as_return());
+        table.exit(drainer, ExitStatus::Exited(0));
+
+        // Poll can wait, woken by a pipe being written
+        assert_eq!(call(&mut table, producer, syscall::SYS_PIPE, &[scratch, 0]), 0);
+        let (second_reader, second_writer) = fds(&table, producer);
+        let entry = [(second_reader as i32).to_le_bytes().as_slice(), &pollin.to_le_bytes(), &[0, 0]].concat();
+        table.get(producer).unwrap().space().write(scratch + 256, &entry).unwrap();
+        call(&mut table, producer, syscall::SYS_POLL, &[scratch + 256, 1, u64::MAX]);
+        assert_eq!(table.blocker(producer), Some(Blocker::Poll));
+        let mut files = FileTable::new();
+        files.install(1, table.get(producer).unwrap().files().get(second_writer as usize).unwrap().try_clone().unwrap()).unwrap();
+        let helper = table.spawn(producer, "/bin/app", &[], &[], files).unwrap();
+        assert_eq!(call(&mut table, helper, syscall::SYS_WRITE, &[1, scratch, 1]), 1);
+        assert_eq!((table.blocker(producer), result(&table, producer)), (None, 1));
+
+        // Writing where nobody reads raises SIGPIPE, or fails when it is
+        // ignored
+        for fd in [reader, second_reader] {
+            assert_eq!(call(&mut table, producer, syscall::SYS_CLOSE, &[fd]), 0);
+        }
+        assert_eq!(poll(&mut table, producer, &[(second_writer as i32, pollout)]), (1, vec![syscall::POLLERR]));
+        assert_eq!(call(&mut table, producer, syscall::SYS_SIGNAL, &[SIGPIPE as u64, syscall::SIG_IGN]), syscall::SIG_DFL);
+        assert_eq!(call(&mut table, producer, syscall::SYS_WRITE, &[second_writer, scratch, 1]), Errno::Pipe.as_return());
+        call(&mut table, helper, syscall::SYS_WRITE, &[1, scratch, 1]);
+        assert_eq!(table.wait(producer, Some(helper)), Ok(Some((helper, ExitStatus::Killed(SIGPIPE)))));
+
+        // A FIFO is a node anyone may open; writers wait for a reader
+        let path = scratch + 512;
+        table.get(consumer).unwrap().space().write(path, b"/tmp/fifo\0").unwrap();
+        table.get(producer).unwrap().space().write(path, b"/tmp/fifo\0").unwrap();
+        assert_eq!(call(&mut table, consumer, syscall::SYS_MKFIFO, &[path, 0o7777]), Errno::Invalid.as_return());
+        assert_eq!(call(&mut table, consumer, syscall::SYS_MKFIFO, &[path, 0o660]), 0);
+        assert_eq!(call(&mut table, consumer, syscall::SYS_MKFIFO, &[path, 0o660]), Errno::Exists.as_return());
+        let metadata = std::fs::metadata(root.join("tmp/fifo")).unwrap();
+        assert!(std::os::unix::fs::FileTypeExt::is_fifo(&metadata.file_type()));
+        assert_eq!(std::os::unix::fs::PermissionsExt::mode(&metadata.permissions()) & 0o777, 0o660);
+        let write_only = syscall::O_WRONLY;
+        assert_eq!(call(&mut table, producer, syscall::SYS_OPEN, &[path, write_only | syscall::O_NONBLOCK]), Errno::NoDeviceOrAddress.as_return());
+        assert_eq!(call(&mut table, producer, syscall::SYS_OPEN, &[path, syscall::O_RDWR]), Errno::Invalid.as_return());
+        call(&mut table, producer, syscall::SYS_OPEN, &[path, write_only]);
+        assert_eq!(table.blocker(producer), Some(Blocker::FifoOpen));
+        let fifo_reader = call(&mut table, consumer, syscall::SYS_OPEN, &[path, syscall::O_RDONLY]);
+        assert_eq!(table.blocker(producer), None);
+        let fifo_writer = result(&table, producer);
+        assert!(matches!(table.get(producer).unwrap().files().get(fifo_writer as usize), Some(OpenFile::PipeWriter { nonblocking: false, .. })));
+        assert_eq!(table.fifos().len(), 1);
+        table.get(producer).unwrap().space().write(scratch, b"via fifo").unwrap();
+        assert_eq!(call(&mut table, producer, syscall::SYS_WRITE, &[fifo_writer, scratch, 8]), 8);
+        assert_eq!(call(&mut table, consumer, syscall::SYS_READ, &[fifo_reader, scratch, 64]), 8);
+        let mut bytes = [0; 8];
+        table.get(consumer).unwrap().space().read(scratch, &mut bytes).unwrap();
+        assert_eq!(&bytes, b"via fifo");
+
+        // A FIFO's reader waits for its first writer rather than seeing
+        // the end, and nobody may run it
+        assert_eq!(call(&mut table, producer, syscall::SYS_CLOSE, &[fifo_writer]), 0);
+        assert_eq!(call(&mut table, consumer, syscall::SYS_READ, &[fifo_reader, scratch, 64]), 0);
+        assert_eq!(call(&mut table, consumer, syscall::SYS_CLOSE, &[fifo_reader]), 0);
+        assert!(table.fifos().is_empty());
+        let late_reader = call(&mut table, consumer, syscall::SYS_OPEN, &[path, syscall::O_RDONLY | syscall::O_NONBLOCK]);
+        assert_eq!(call(&mut table, consumer, syscall::SYS_READ, &[late_reader, scratch, 64]), Errno::Again.as_return());
+        std::fs::set_permissions(root.join("tmp/fifo"), std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+        assert_eq!(table.spawn(consumer, "/tmp/fifo", &[], &[], FileTable::new()), Err("Permission denied"));
+
+        for pid in table.pids() {
+            table.exit(pid, ExitStatus::Exited(0));
+        }
+        assert_eq!(memory.allocated(), 1);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    pub fn test_threads_and_futexes() {
+        let root = std::env::temp_dir().join(format!("vaelix-threads-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("bin")).unwrap();
+        let code = [0x90u8; 32];
+        write_executable(&root.join("bin/worker"), &build_elf(0x40_0000, &[(0x40_0000, PF_R | PF_X, &code, 32)]));
+        let memory = PhysicalMemory::new(512);
+        let mut table = ProcessTable::new(&memory, root.to_str().unwrap(), &VXChanManager::new()).unwrap();
+        let pid = table.spawn(KERNEL_PID, "/bin/worker", &[], &[], FileTable::new()).unwrap();
+        let rw = syscall::PROT_READ | syscall::PROT_WRITE;
+        let heap = call(&mut table, pid, syscall::SYS_MMAP, &[0, 4 * PAGE_SIZE, rw, syscall::MAP_PRIVATE | syscall::MAP_ANONYMOUS, u64::MAX, 0]);
+        let (lock, tid_word, stack) = (heap, heap + 8, heap + 4 * PAGE_SIZE);
+        let poke = |table: &ProcessTable, address: u64, value: u32| table.get(pid).unwrap().space().write(address, &value.to_le_bytes()).unwrap();
+        let peek = |table: &ProcessTable, address: u64| read_u64(table.get(pid).unwrap().space(), address) as u32;
+
+        // A thread shares the address space, with its own stack, argument
+        // and thread-local storage
+        let create = [0x40_0010, stack, 42, 0x7000, tid_word];
+        assert_eq!(call(&mut table, pid, syscall::SYS_THREAD_CREATE, &[heap, stack, 0, 0, 0]), Errno::Fault.as_return());
+        assert_eq!(call(&mut table, pid, syscall::SYS_THREAD_CREATE, &[0x40_0010, stack, 0, KERNEL_BASE, 0]), Errno::Invalid.as_return());
+        let tid = call(&mut table, pid, syscall::SYS_THREAD_CREATE, &create) as Tid;
+        poke(&table, tid_word, tid);
+        assert_eq!(table.owner(tid), Some(pid));
+        let context = table.get(pid).unwrap().thread(tid).unwrap().context;
+        assert!(context.is_user_mode());
+        assert_eq!((context.rip, context.rsp, context.registers[RDI], context.fs_base), (0x40_0010, stack - 8, 42, 0x7000));
+        assert_eq!(read_u64(table.get(pid).unwrap().space(), stack - 8), 0);
+        assert_eq!(call(&mut table, tid, syscall::SYS_GETTID, &[]), tid as u64);
+        assert_eq!(call(&mut table, tid, syscall::SYS_GETPID, &[]), pid as u64);
+        call(&mut table, pid, syscall::SYS_SET_TLS, &[0x9000]);
+        assert_eq!(table.get(pid).unwrap().context().fs_base, 0x9000);
+
+        // A contended lock: the thread sleeps until the holder wakes it
+        poke(&table, lock, 1);
+        assert_eq!(call(&mut table, tid, syscall::SYS_FUTEX, &[lock, syscall::FUTEX_WAIT, 0]), Errno::Again.as_return());
+        assert_eq!(call(&mut table, tid, syscall::SYS_FUTEX, &[lock + 1, syscall::FUTEX_WAIT, 1]), Errno::Invalid.as_return());
+        assert_eq!(call(&mut table, tid, syscall::SYS_FUTEX, &[lock, syscall::FUTEX_WAIT, 1, 10]), Errno::Invalid.as_return());
+        call(&mut table, tid, syscall::SYS_FUTEX, &[lock, syscall::FUTEX_WAIT, 1]);
+        assert!(matches!(table.blocker(tid), Some(Blocker::Futex(_))));
+        assert_eq!(table.runnable(), vec![pid]);
+        let mut cpu = SyscallCpu { number: syscall::SYS_GETTID, arguments: [0; 6], flags: 0x202 };
+        assert_eq!(table.run(tid, &mut cpu), Err("Thread is blocked"));
+        poke(&table, lock, 0);
+        assert_eq!(call(&mut table, pid, syscall::SYS_FUTEX, &[lock, syscall::FUTEX_WAKE, 10]), 1);
+        assert_eq!(table.blocker(tid), None);
+        let context = table.get(pid).unwrap().thread(tid).unwrap().context;
+        assert_eq!((context.registers[RAX], context.rip), (0, context.registers[RCX]));
+        assert_eq!(call(&mut table, pid, syscall::SYS_FUTEX, &[lock, syscall::FUTEX_WAKE, 10]), 0);
+
+        // Joining: the exiting thread clears its ID and wakes the joiner
+        call(&mut table, pid, syscall::SYS_FUTEX, &[tid_word, syscall::FUTEX_WAIT, tid as u64]);
+        assert!(table.blocker(pid).is_some());
+        call(&mut table, tid, syscall::SYS_THREAD_EXIT, &[0]);
+        assert_eq!(table.owner(tid), None);
+        assert_eq!(table.blocker(pid), None);
+        assert_eq!(peek(&table, tid_word), 0);
+        assert_eq!(table.get(pid).unwrap().tids(), vec![pid]);
+
+        // The process lives while any thread does; exit ends all of them
+        let second = call(&mut table, pid, syscall::SYS_THREAD_CREATE, &[0x40_0010, stack, 0, 0, 0]) as Tid;
+        let third = call(&mut table, pid, syscall::SYS_THREAD_CREATE, &[0x40_0010, stack - PAGE_SIZE, 0, 0, 0]) as Tid;
+        call(&mut table, pid, syscall::SYS_THREAD_EXIT, &[1]);
+        assert_eq!(table.pids(), vec![pid]);
+        assert_eq!(table.get(pid).unwrap().context().rsp, stack - 8);
+        poke(&table, lock, 1);
+        call(&mut table, third, syscall::SYS_FUTEX, &[lock, syscall::FUTEX_WAIT, 1]);
+        call(&mut table, second, syscall::SYS_EXIT, &[5]);
+        assert!(table.pids().is_empty() && table.runnable().is_empty());
+        assert_eq!(table.owner(third), None);
+        assert_eq!(table.wait(KERNEL_PID, Some(pid)), Ok(Some((pid, ExitStatus::Exited(5)))));
+        assert_eq!(memory.allocated(), 1);
+        std::fs::remove_dir_all(&root).unwrap();
+
+        // Kernel threads run until they finish or are stopped
+        let count = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = count.clone();
+        let mut flusher = KernelThread::spawn("flush", move || {
+            counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            std::thread::yield_now();
+            true
+        })
+        .unwrap();
+        assert_eq!(flusher.name(), "flush");
+        while count.load(std::sync::atomic::Ordering::SeqCst) < 3 {
+            std::thread::yield_now();
+        }
+        assert!(flusher.is_running());
+        assert_eq!(flusher.stop(), Ok(()));
+        assert!(!flusher.is_running());
+        let mut once = KernelThread::spawn("once", || false).unwrap();
+        assert_eq!(once.stop(), Ok(()));
+    }
+
+    #[test]
+    pub fn test_init_services() {
+        let manifest = Manifest::parse("# Network\n[service]\nname=network\nexec=/bin/svc --foreground\nenv=RUST_LOG=info\nrequires=log\nwanted-by=graphical\nrestart=always\nrestart-delay=0\n").unwrap();
+        assert_eq!((manifest.kind, manifest.restart), (UnitKind::Service, Restart::Always));
+        assert_eq!(manifest.exec, vec!["/bin/svc", "--foreground"]);
+        assert_eq!(manifest.env, vec!["RUST_LOG=info"]);
+        assert_eq!(Manifest::parse("[service]\nname=network\n"), Err("Service has nothing to run"));
+        assert_eq!(Manifest::parse("[target]\nname=rescue\nexec=/bin/svc\n"), Err("Unknown manifest setting"));
+        assert_eq!(Manifest::parse("[service]\nname=log\nrestart=sometimes\n"), Err("Unknown restart policy"));
+
+        let root = std::env::temp_dir().join(format!("vaelix-init-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("bin")).unwrap();
+        std::fs::create_dir_all(root.join("etc/vxinit")).unwrap();
+        std::fs::create_dir_all(root.join("var/log")).unwrap();
+        let code = [0x90u8; 32];
+        write_executable(&root.join("bin/svc"), &build_elf(0x40_0000, &[(0x40_0000, PF_R | PF_X, &code, 32)]));
+        let units = [
+            ("rescue.target", "[target]\nname=rescue\n"),
+            ("graphical.target", "[target]\nname=graphical\nrequires=rescue\n"),
+            ("log.service", "[service]\nname=log\nexec=/bin/svc\nwanted-by=rescue\nrestart=always\nrestart-delay=0\nstdout=/var/log/log.log\n"),
+            ("network.service", "[service]\nname=network\nexec=/bin/svc\nrequires=log\nwanted-by=graphical\nrestart=on-failure\nrestart-delay=1000\n"),
+            ("compositor.service", "[service]\nname=compositor\nexec=/bin/svc\nrequires=network\nwanted-by=graphical\nrestart=never\nstdin=/var/log/log.log\nstderr=/var/log/compositor.log\n"),
+            ("audio.service", "[service]\nname=audio\nexec=/bin/missing\nafter=compositor\nwanted-by=graphical\n"),
+        ];
+        for (name, contents) in units {
+            std::fs::write(root.join("etc/vxinit").join(name), contents).unwrap();
+        }
+        let memory = PhysicalMemory::new(1024);
+        let manager = VXChanManager::new();
+        let mut table = ProcessTable::new(&memory, root.to_str().unwrap(), &manager).unwrap();
+        let mut init = Init::new();
+        assert_eq!(init.load_dir(root.join("etc/vxinit").to_str().unwrap()), Ok(6));
+
+        // Everything a target pulls in starts after what it depends on
+        let order = init.closure("graphical").unwrap();
+        let position = |name: &str| order.iter().position(|unit| unit == name).unwrap();
+        assert!(position("log") < position("network") && position("network") < position("compositor"));
+        assert!(position("compositor") < position("audio") && position("rescue") < position("graphical"));
+        assert_eq!(init.isolate("graphical", &mut table), Ok(()));
+        assert_eq!(init.target(), Some("graphical"));
+        assert_eq!(init.state("audio"), Some(UnitState::Failed("File not found")));
+        let pid = |init: &Init, name: &str| match init.state(name) {
+            Some(UnitState::Running(pid)) => pid,
+            state => panic!("{} is {:?}", name, state),
+        };
+        let (log, network) = (pid(&init, "log"), pid(&init, "network"));
+        assert_eq!(table.pids().len(), 3);
+
+        // Standard streams go where the manifest says
+        assert!(matches!(table.get(log).unwrap().files().get(1), Some(OpenFile::File { writable: true, .. })));
+        let compositor = table.get(pid(&init, "compositor")).unwrap().files();
+        assert!(matches!(compositor.get(0), Some(OpenFile::File { readable: true, writable: false, .. })));
+        assert!(compositor.get(1).is_none() && compositor.get(2).is_some());
+        assert!(root.join("var/log/compositor.log").exists());
+
+        // Crashes are restarted after the delay, clean exits only if asked
+        let now = std::time::Instant::now();
+        table.exit(network, ExitStatus::Exited(1));
+        assert_eq!(init.poll(&mut table, now), 1);
+        assert!(matches!(init.state("network"), Some(UnitState::Restarting(_))));
+        init.poll(&mut table, now + std::time::Duration::from_secs(1));
+        assert_ne!(pid(&init, "network"), network);
+        assert_eq!(init.restarts("network"), 1);
+        table.exit(pid(&init, "compositor"), ExitStatus::Exited(0));
+        init.poll(&mut table, now);
+        assert_eq!(init.state("compositor"), Some(UnitState::Exited(ExitStatus::Exited(0))));
+
+        // Giving up on a service takes down what requires it
+        for _ in 0..vxinit::RESTART_LIMIT {
+            table.exit(pid(&init, "log"), ExitStatus::Killed(11));
+            init.poll(&mut table, now);
+        }
+        assert_ne!(pid(&init, "log"), log);
+        table.exit(pid(&init, "log"), ExitStatus::Killed(11));
+        init.poll(&mut table, now);
+        assert_eq!(init.state("log"), Some(UnitState::Failed("Restarting too quickly")));
+        assert_eq!(init.state("network"), Some(UnitState::Failed("Dependency failed")));
+        assert!(table.pids().is_empty());
+
+        // Orphans left to the kernel are reaped too
+        let orphan = table.spawn(KERNEL_PID, "/bin/svc", &[], &[], FileTable::new()).unwrap();
+        table.exit(orphan, ExitStatus::Exited(0));
+        assert_eq!(init.poll(&mut table, now), 1);
+        assert!(!table.is_zombie(orphan));
+
+        // Status and control over vxchan
+        let service = InitService::new(&manager).unwrap();
+        vxinit::send_request(&manager, 1, "restart log").unwrap();
+        vxinit::send_request(&manager, 2, "isolate rescue").unwrap();
+        vxinit::send_request(&manager, 3, "status network").unwrap();
+        vxinit::send_request(&manager, 4, "stop audio").unwrap();
+        vxinit::send_request(&manager, 5, "isolate log").unwrap();
+        vxinit::send_request(&manager, 6, "start printer").unwrap();
+        assert_eq!(service.poll(&manager, &mut init, &mut table), Ok(6));
+        let log = pid(&init, "log");
+        vxinit::send_request(&manager, 7, "list").unwrap();
+        vxinit::send_request(&manager, 8, "target").unwrap();
+        assert_eq!(service.poll(&manager, &mut init, &mut table), Ok(2));
+        let mut replies = Vec::new();
+        while let Some(reply) = manager.try_receive_message(vxinit::REPLY_CHANNEL).unwrap() {
+            replies.push(reply);
+        }
+        assert_eq!(
+            replies,
+            vec![
+                "1 ok".to_string(),
+                "2 ok".to_string(),
+                "3 ok\nname=network\nstate=failed\nreason=Dependency failed\nrestarts=1\ndescription=".to_string(),
+                "4 ok".to_string(),
+                "5 error Not a target".to_string(),
+                "6 error Unit not found".to_string(),
+                format!("7 ok\naudio inactive\ncompositor exited\ngraphical inactive\nlog running {}\nnetwork failed\nrescue active", log),
+                "8 ok\nrescue".to_string(),
+            ]
+        );
+        assert_eq!(table.pids(), vec![log]);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    pub fn test_users_and_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+        let root = std::env::temp_dir().join(format!("vaelix-users-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("bin")).unwrap();
+        let root_path = root.to_str().unwrap();
+
+        // Accounts get a private group and a salted hash, and survive a reload
+        let mut database = UserDatabase::load(root_path).unwrap();
+        assert_eq!(database.users().len(), 1);
+        let ada = database.add_user("ada", "Ada Lovelace", "/home/ada", "/bin/vxsh").unwrap();
+        assert_eq!(ada, FIRST_USER_ID);
+        assert_eq!(database.add_user("ada", "", "/", "/bin/vxsh"), Err("User already exists"));
+        assert_eq!(database.add_user("a:b", "", "/", "/bin/vxsh"), Err("Invalid name"));
+        let wheel = database.add_group("wheel").unwrap();
+        database.add_member("wheel", "ada").unwrap();
+        assert_eq!(database.authenticate("ada", ""), Err("Authentication failed"));
+        database.set_password("ada", "correct horse").unwrap();
+        database.save(root_path).unwrap();
+        let shadow = std::fs::read_to_string(root.join(users::SHADOW_PATH)).unwrap();
+        assert!(shadow.starts_with("ada:$argon2id$") && !shadow.contains("correct horse"));
+        assert_eq!(std::fs::metadata(root.join(users::SHADOW_PATH)).unwrap().permissions().mode() & 0o777, 0o600);
+        let mut database = UserDatabase::load(root_path).unwrap();
+        assert_eq!(database.authenticate("ada", "correct horse").unwrap().uid, ada);
+        assert_eq!(database.authenticate("ada", "wrong"), Err("Authentication failed"));
+        assert_eq!(database.authenticate("nobody", "wrong"), Err("Authentication failed"));
+        let credentials = database.credentials("ada").unwrap();
+        assert_eq!(credentials, Credentials { uid: ada, gid: ada, groups: vec![wheel] });
+        database.lock("ada").unwrap();
+        assert_eq!(database.authenticate("ada", "correct horse"), Err("Authentication failed"));
+        database.set_password("ada", "correct horse").unwrap();
+
+        // The greeter's requests
+        let manager = VXChanManager::new();
+        let service = AuthService::new(&manager).unwrap();
+        users::send_request(&manager, 1, "authenticate ada correct horse").unwrap();
+        users::send_request(&manager, 2, "authenticate ada wrong").unwrap();
+        users::send_request(&manager, 3, "users").unwrap();
+        users::send_request(&manager, 4, "groups ada").unwrap();
+        users::send_request(&manager, 5, "user grace").unwrap();
+        assert_eq!(service.poll(&manager, &database), Ok(5));
+        let mut replies = Vec::new();
+        while let Some(reply) = manager.try_receive_message(users::REPLY_CHANNEL).unwrap() {
+            replies.push(reply);
+        }
+        let entry = "ada:x:1000:1000:Ada Lovelace:/home/ada:/bin/vxsh";
+        assert_eq!(
+            replies,
+            vec![
+                format!("1 ok\n{}", entry),
+                "2 error Authentication failed".to_string(),
+                format!("3 ok\n{}", entry),
+                "4 ok\nada wheel".to_string(),
+                "5 error User not found".to_string(),
+            ]
+        );
+
+        // Processes inherit credentials, which the filesystem checks
+        let code = [0x90u8; 32];
+        write_executable(&root.join("bin/sh"), &build_elf(0x40_0000, &[(0x40_0000, PF_R | PF_X, &code, 32)]));
+        std::fs::create_dir_all(root.join("home/ada")).unwrap();
+        std::os::unix::fs::chown(root.join("home/ada"), Some(ada), Some(ada)).unwrap();
+        std::fs::set_permissions(root.join("home/ada"), std::fs::Permissions::from_mode(0o700)).unwrap();
+        let memory = PhysicalMemory::new(512);
+        let mut table = ProcessTable::new(&memory, root_path, &manager).unwrap();
+        table.set_randomize_layout(false);
+        let shell = table.spawn(KERNEL_PID, "/bin/sh", &["sh"], &[], FileTable::new()).unwrap();
+        assert_eq!(table.get(shell).unwrap().credentials(), &Credentials::root());
+        let scratch = STACK_TOP - STACK_SIZE;
+        let poke = |table: &ProcessTable, pid: Pid, data: &[u8]| table.get(pid).unwrap().space().write(scratch, data).unwrap();
+        assert_eq!(call(&mut table, shell, syscall::SYS_SETGID, &[ada as u64]), 0);
+        assert_eq!(call(&mut table, shell, syscall::SYS_SETUID, &[ada as u64]), 0);
+        assert_eq!(call(&mut table, shell, syscall::SYS_GETUID, &[]), ada as u64);
+        assert_eq!(call(&mut table, shell, syscall::SYS_GETGID, &[]), ada as u64);
+        assert_eq!(call(&mut table, shell, syscall::SYS_SETUID, &[0]), Errno::NotPermitted.as_return());
+        assert_eq!(call(&mut table, shell, syscall::SYS_SETGID, &[0]), Errno::NotPermitted.as_return());
+        poke(&table, shell, b"/etc/shadow\0");
+        assert_eq!(call(&mut table, shell, syscall::SYS_OPEN, &[scratch, syscall::O_RDONLY]), Errno::Access.as_return());
+        poke(&table, shell, b"/etc/notes\0");
+        assert_eq!(call(&mut table, shell, syscall::SYS_OPEN, &[scratch, syscall::O_WRONLY | syscall::O_CREAT]), Errno::Access.as_return());
+        poke(&table, shell, b"/home/ada/notes\0");
+        assert!((call(&mut table, shell, syscall::SYS_OPEN, &[scratch, syscall::O_WRONLY | syscall::O_CREAT]) as i64) >= 0);
+        let metadata = std::fs::metadata(root.join("home/ada/notes")).unwrap();
+        assert_eq!((std::os::unix::fs::MetadataExt::uid(&metadata), std::os::unix::fs::MetadataExt::gid(&metadata)), (ada, ada));
+        assert_eq!(call(&mut table, shell, syscall::SYS_CHMOD, &[scratch, 0o600]), 0);
+        assert_eq!(call(&mut table, shell, syscall::SYS_CHOWN, &[scratch, 0, 0]), Errno::NotPermitted.as_return());
+
+        // Children run as their parent, and cannot see into others' homes
+        poke(&table, shell, b"/bin/sh\0");
+        let child = call(&mut table, shell, syscall::SYS_SPAWN, &[scratch, 0, 0]) as Pid;
+        assert_eq!(table.get(child).unwrap().credentials().uid, ada);
+        let root_shell = table.spawn(KERNEL_PID, "/bin/sh", &["sh"], &[], FileTable::new()).unwrap();
+        let mut stranger = Credentials::root();
+        stranger.uid = ada + 1;
+        table.get_mut(root_shell).unwrap().set_credentials(stranger.clone());
+        poke(&table, root_shell, b"/home/ada/notes\0");
+        assert_eq!(call(&mut table, root_shell, syscall::SYS_OPEN, &[scratch, syscall::O_RDONLY]), Errno::Access.as_return());
+        assert_eq!(table.access(&stranger, "/home/ada/notes", ACCESS_READ), Err("Permission denied"));
+        assert!(table.access(&Credentials::root(), "/home/ada/notes", ACCESS_READ | ACCESS_WRITE).is_ok());
+        std::fs::set_permissions(root.join("bin/sh"), std::fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(table.spawn(KERNEL_PID, "/bin/sh", &[], &[], FileTable::new()), Err("Permission denied"));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    pub fn test_limits_and_procfs() {
+        let mut limits = Limits::new();
+        assert_eq!(limits.get(Resource::Memory), Limit::UNLIMITED);
+        assert_eq!(limits.set(Resource::Files, Limit::new(8, 4), false), Err("Soft limit above hard limit"));
+        assert_eq!(limits.set(Resource::Files, Limit::new(8, 8), false), Ok(()));
+        assert_eq!(limits.set(Resource::Files, Limit::new(8, 16), false), Err("Not permitted"));
+        assert_eq!(limits.set(Resource::Files, Limit::new(8, 16), true), Ok(()));
+        assert_eq!(limits.set(Resource::Files, Limit::new(8, 1 << 20), true), Err("Limit too high"));
+
+        let root = std::env::temp_dir().join(format!("vaelix-limits-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("bin")).unwrap();
+        let code = [0x90u8; 32];
+        write_executable(&root.join("bin/worker"), &build_elf(0x40_0000, &[(0x40_0000, PF_R | PF_X, &code, 32)]));
+        let memory = PhysicalMemory::new(512);
+        let mut table = ProcessTable::new(&memory, root.to_str().unwrap(), &VXChanManager::new()).unwrap();
+        table.set_randomize_layout(false);
+        let pid = table.spawn(KERNEL_PID, "/bin/worker", &["worker"], &[], FileTable::new()).unwrap();
+        let scratch = STACK_TOP - STACK_SIZE;
+        let poke = |table: &ProcessTable, address: u64, data: &[u8]| table.get(pid).unwrap().space().write(address, data).unwrap();
+        let set_limit = |table: &mut ProcessTable, resource: u64, soft: u64, hard: u64| {
+            let bytes: Vec<u8> = [soft, hard].iter().flat_map(|value| value.to_le_bytes()).collect();
+            table.get(pid).unwrap().space().write(scratch + 64, &bytes).unwrap();
+            call(table, pid, syscall::SYS_SETRLIMIT, &[resource, scratch + 64])
+        };
+        let read_proc = |table: &mut ProcessTable, path: &str| {
+            poke(table, scratch, format!("{}\0", path).as_bytes());
+            let fd = call(table, pid, syscall::SYS_OPEN, &[scratch, syscall::O_RDONLY]);
+            let length = call(table, pid, syscall::SYS_READ, &[fd, scratch + 1024, 2048]) as usize;
+            call(table, pid, syscall::SYS_CLOSE, &[fd]);
+            let mut contents = vec![0; length];
+            table.get(pid).unwrap().space().read(scratch + 1024, &mut contents).unwrap();
+            String::from_utf8(contents).unwrap()
+        };
+
+        // Process information under /proc, for itself or any other
+        let status = read_proc(&mut table, "/proc/self/status");
+        assert!(status.starts_with("Name:\tworker\nState:\tR (running)\nPid:\t1\nPPid:\t0\nUid:\t0\n"));
+        assert!(status.contains("Threads:\t1\nFDSize:\t0\n"));
+        assert_eq!(procfs::read(&table, KERNEL_PID, "/proc/1/../1/./status").unwrap().lines().next(), Some("Name:\tworker"));
+        assert_eq!(procfs::read(&table, pid, "/proc/2/status"), Err("File not found"));
+        assert_eq!(procfs::read(&table, pid, "/proc/self"), Err("Is a directory"));
+        assert!(procfs::is_proc("/etc/../proc/self/limits") && !procfs::is_proc("/process"));
+        poke(&table, scratch, b"/proc/self/status\0");
+        assert_eq!(call(&mut table, pid, syscall::SYS_OPEN, &[scratch, syscall::O_RDWR]), Errno::Access.as_return());
+        assert_eq!(call(&mut table, pid, syscall::SYS_GETRLIMIT, &[RLIMIT_NOFILE, scratch + 64]), 0);
+        assert_eq!((read_u64(table.get(pid).unwrap().space(), scratch + 64), read_u64(table.get(pid).unwrap().space(), scratch + 72)), (256, 256));
+
+        // Descriptors stop at the open files limit, which only root may
+        // raise past the hard one
+        table.get_mut(pid).unwrap().set_credentials(Credentials { uid: FIRST_USER_ID, gid: FIRST_USER_ID, groups: Vec::new() });
+        assert_eq!(set_limit(&mut table, RLIMIT_NOFILE, 2, 4), 0);
+        assert_eq!(set_limit(&mut table, RLIMIT_NOFILE, 2, 8), Errno::NotPermitted.as_return());
+        assert_eq!(set_limit(&mut table, 99, 2, 4), Errno::Invalid.as_return());
+        poke(&table, scratch, b"/proc/self/limits\0");
+        assert_eq!(call(&mut table, pid, syscall::SYS_OPEN, &[scratch, syscall::O_RDONLY]), 0);
+        assert_eq!(call(&mut table, pid, syscall::SYS_OPEN, &[scratch, syscall::O_RDONLY]), 1);
+        assert_eq!(call(&mut table, pid, syscall::SYS_OPEN, &[scratch, syscall::O_RDONLY]), Errno::TooManyFiles.as_return());
+        call(&mut table, pid, syscall::SYS_CLOSE, &[1]);
+        let limits = read_proc(&mut table, "/proc/self/limits");
+        assert!(limits.contains("Max open files            2                    4                    files\n"));
+        assert!(limits.contains("Max cpu time              unlimited            unlimited            seconds\n"));
+
+        // Mappings stop at the memory limit, which children inherit
+        let used = table.get(pid).unwrap().memory();
+        assert_eq!(set_limit(&mut table, RLIMIT_AS, used + PAGE_SIZE, RLIM_INFINITY), 0);
+        let prot = syscall::PROT_READ | syscall::PROT_WRITE;
+        let flags = syscall::MAP_PRIVATE | syscall::MAP_ANONYMOUS;
+        assert_eq!(call(&mut table, pid, syscall::SYS_MMAP, &[0, 2 * PAGE_SIZE, prot, flags]), Errno::NoMemory.as_return());
+        assert!((call(&mut table, pid, syscall::SYS_MMAP, &[0, PAGE_SIZE, prot, flags]) as i64) > 0);
+        assert_eq!(table.get(pid).unwrap().usage().peak_memory, used + PAGE_SIZE);
+        assert_eq!(set_limit(&mut table, RLIMIT_AS, used - PAGE_SIZE, RLIM_INFINITY), 0);
+        poke(&table, scratch, b"/bin/worker\0");
+        assert_eq!(call(&mut table, pid, syscall::SYS_SPAWN, &[scratch, 0, 0]), Errno::NoMemory.as_return());
+        assert_eq!(set_limit(&mut table, RLIMIT_AS, RLIM_INFINITY, RLIM_INFINITY), 0);
+        let child = call(&mut table, pid, syscall::SYS_SPAWN, &[scratch, 0, 0]) as Pid;
+        assert_eq!(table.get(child).unwrap().limits().get(Resource::Files), Limit::new(2, 4));
+
+        // Time and system calls are charged, and running past the CPU
+        // time limit kills the process
+        let usage = *table.get(pid).unwrap().usage();
+        assert!(usage.syscalls >= 20 && usage.files_opened == 4);
+        assert!(usage.system_time > std::time::Duration::ZERO);
+        let text = read_proc(&mut table, "/proc/self/usage");
+        // Generated before the open that reads it is counted
+        assert!(text.contains(&format!("Syscalls:\t{}\nFilesOpened:\t4\n", usage.syscalls)));
+        assert_eq!(set_limit(&mut table, RLIMIT_CPU, 0, 0), 0);
+        let mut cpu = SyscallCpu { number: syscall::SYS_GETPID, arguments: [0; 6], flags: 0x202 };
+        assert_eq!(table.run(pid, &mut cpu), Err("CPU time limit exceeded"));
+        assert_eq!(table.wait(KERNEL_PID, Some(pid)), Ok(Some((pid, ExitStatus::Killed(24)))));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    // Touches an address the way user code would, which faults every time
+    // as far as the kernel can tell
+    struct TouchingCpu {
+        address: u64,
+        error: u64,
+    }
+
+    impl UserCpu for TouchingCpu {
+        fn run(&mut self, _: &AddressSpace, _: &mut UserContext) -> Trap {
+            Trap::PageFault { address: self.address, error: self.error }
+        }
+    }
+
+    #[test]
+    pub fn test_memory_mappings() {
+        let root = std::env::temp_dir().join(format!("vaelix-mappings-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("bin")).unwrap();
+        std::fs::create_dir_all(root.join("etc")).unwrap();
+        let code = [0x90u8; 32];
+        write_executable(&root.join("bin/mapper"), &build_elf(0x40_0000, &[(0x40_0000, PF_R | PF_X, &code, 32)]));
+        let mut contents = vec![0; PAGE_SIZE as usize + 6];
+        contents[..5].copy_from_slice(b"first");
+        contents[PAGE_SIZE as usize..].copy_from_slice(b"second");
+        std::fs::write(root.join("etc/data"), &contents).unwrap();
+        let memory = PhysicalMemory::new(512);
+        let mut table = ProcessTable::new(&memory, root.to_str().unwrap(), &VXChanManager::new()).unwrap();
+        table.set_randomize_layout(false);
+        let baseline = memory.allocated();
+        let pid = table.spawn(KERNEL_PID, "/bin/mapper", &["mapper"], &[], FileTable::new()).unwrap();
+        let other = table.spawn(KERNEL_PID, "/bin/mapper", &["mapper"], &[], FileTable::new()).unwrap();
+        let scratch = STACK_TOP - STACK_SIZE;
+        let poke = |table: &ProcessTable, pid: Pid, address: u64, data: &[u8]| table.get(pid).unwrap().space().write(address, data).unwrap();
+        let peek = |table: &ProcessTable, pid: Pid, address: u64, length: usize| {
+            let mut bytes = vec![0; length];
+            table.get(pid).unwrap().space().read(address, &mut bytes).unwrap();
+            bytes
+        };
+        let touch = |table: &mut ProcessTable, pid: Pid, address: u64, error: u64| table.run(pid, &mut TouchingCpu { address, error });
+        let rw = syscall::PROT_READ | syscall::PROT_WRITE;
+        let anonymous = syscall::MAP_PRIVATE | syscall::MAP_ANONYMOUS;
+
+        // Anonymous pages are only there once touched, and faults on them
+        // go back to the caller like any other trap
+        let anon = call(&mut table, pid, syscall::SYS_MMAP, &[0, 4 * PAGE_SIZE, rw, anonymous, u64::MAX, 0]);
+        let pages = table.get(pid).unwrap().space().mapped_pages();
+        assert_eq!(table.get(pid).unwrap().memory(), pages * PAGE_SIZE + 4 * PAGE_SIZE);
+        let fault = Trap::PageFault { address: anon + PAGE_SIZE + 8, error: FAULT_USER | FAULT_WRITE };
+        assert_eq!(touch(&mut table, pid, anon + PAGE_SIZE + 8, FAULT_USER | FAULT_WRITE), Ok(fault));
+        let space = table.get(pid).unwrap().space();
+        assert_eq!(space.mapped_pages(), pages + 1);
+        assert!(space.translate(anon).is_none());
+        assert_eq!(peek(&table, pid, anon + PAGE_SIZE, 8), [0; 8]);
+
+        // Protection changes reach the pages already there, and only whole
+        // mapped ranges can change
+        assert_eq!(call(&mut table, pid, syscall::SYS_MPROTECT, &[anon, 2 * PAGE_SIZE, syscall::PROT_READ]), 0);
+        let (_, flags) = table.get(pid).unwrap().space().translate(anon + PAGE_SIZE).unwrap();
+        assert_eq!(flags & (USER | WRITABLE), USER);
+        assert_eq!(table.get(pid).unwrap().space().mappings().count(), 2);
+        assert_eq!(call(&mut table, pid, syscall::SYS_MPROTECT, &[anon - PAGE_SIZE, 2 * PAGE_SIZE, rw]), Errno::NoMemory.as_return());
+        assert_eq!(call(&mut table, pid, syscall::SYS_MPROTECT, &[anon + 1, PAGE_SIZE, rw]), Errno::Invalid.as_return());
+
+        // Unmapping splits mappings, and a fixed mapping can fill the hole
+        // but never cover the program or its stack
+        assert_eq!(call(&mut table, pid, syscall::SYS_MUNMAP, &[anon + PAGE_SIZE, PAGE_SIZE]), 0);
+        assert_eq!(table.get(pid).unwrap().space().mapped_pages(), pages);
+        assert_eq!(table.get(pid).unwrap().space().mappings().count(), 2);
+        assert_eq!(table.get(pid).unwrap().memory(), pages * PAGE_SIZE + 3 * PAGE_SIZE);
+        let fixed = anonymous | syscall::MAP_FIXED;
+        assert_eq!(call(&mut table, pid, syscall::SYS_MMAP, &[anon + PAGE_SIZE, PAGE_SIZE, rw, fixed, u64::MAX, 0]), anon + PAGE_SIZE);
+        assert_eq!(call(&mut table, pid, syscall::SYS_MMAP, &[scratch, PAGE_SIZE, rw, fixed, u64::MAX, 0]), Errno::NoMemory.as_return());
+        assert_eq!(call(&mut table, pid, syscall::SYS_MMAP, &[anon + 8, PAGE_SIZE, rw, fixed, u64::MAX, 0]), Errno::Invalid.as_return());
+
+        // Private file mappings start as copies of the file's pages, with
+        // zeroes past its end, and never write to it
+        poke(&table, pid, scratch, b"/etc/data\0");
+        assert_eq!(call(&mut table, pid, syscall::SYS_OPEN, &[scratch, syscall::O_RDWR]), 0);
+        assert_eq!(call(&mut table, pid, syscall::SYS_OPEN, &[scratch, syscall::O_RDONLY]), 1);
+        let private = call(&mut table, pid, syscall::SYS_MMAP, &[0, 2 * PAGE_SIZE, rw, syscall::MAP_PRIVATE, 1, 0]);
+        assert_eq!(peek(&table, pid, private, 5), b"first");
+        assert_eq!(peek(&table, pid, private + PAGE_SIZE, 8), b"second\0\0");
+        poke(&table, pid, private, b"FIRST");
+        assert_eq!(call(&mut table, pid, syscall::SYS_MUNMAP, &[private, 2 * PAGE_SIZE]), 0);
+        assert_eq!(&std::fs::read(root.join("etc/data")).unwrap()[..5], b"first");
+        assert_eq!(call(&mut table, pid, syscall::SYS_MMAP, &[0, PAGE_SIZE, rw, syscall::MAP_PRIVATE, 1, 8]), Errno::Invalid.as_return());
+        assert_eq!(call(&mut table, pid, syscall::SYS_MMAP, &[0, PAGE_SIZE, rw, syscall::MAP_PRIVATE, 9, 0]), Errno::BadFile.as_return());
+
+        // Shared mappings of a page are one frame from the page cache, for
+        // every process, and writable only through a descriptor that is
+        let shared = syscall::MAP_SHARED;
+        assert_eq!(call(&mut table, pid, syscall::SYS_MMAP, &[0, PAGE_SIZE, rw, shared, 1, PAGE_SIZE]), Errno::Access.as_return());
+        let read_only = call(&mut table, pid, syscall::SYS_MMAP, &[0, PAGE_SIZE, syscall::PROT_READ, shared, 1, PAGE_SIZE]);
+        assert_eq!(call(&mut table, pid, syscall::SYS_MPROTECT, &[read_only, PAGE_SIZE, rw]), Errno::Access.as_return());
+        let mine = call(&mut table, pid, syscall::SYS_MMAP, &[0, PAGE_SIZE, rw, shared, 0, PAGE_SIZE]);
+        poke(&table, other, scratch, b"/etc/data\0");
+        assert_eq!(call(&mut table, other, syscall::SYS_OPEN, &[scratch, syscall::O_RDWR]), 0);
+        let theirs = call(&mut table, other, syscall::SYS_MMAP, &[0, PAGE_SIZE, rw, shared, 0, PAGE_SIZE]);
+        poke(&table, pid, mine, b"SECOND");
+        assert_eq!(peek(&table, other, theirs, 6), b"SECOND");
+        assert_eq!(peek(&table, pid, read_only, 6), b"SECOND");
+        let (frame, _) = table.get(pid).unwrap().space().translate(mine).unwrap();
+        assert_eq!(table.get(other).unwrap().space().translate(theirs).unwrap().0, frame);
+        assert_eq!(table.page_cache().len(), 1);
+        let copy = call(&mut table, pid, syscall::SYS_MMAP, &[0, PAGE_SIZE, rw, syscall::MAP_PRIVATE, 1, PAGE_SIZE]);
+        assert_eq!(peek(&table, pid, copy, 6), b"SECOND");
+
+        // They are written back when unmapped, or when the process goes,
+        // without the file growing; the last one out frees the frame
+        assert_eq!(call(&mut table, pid, syscall::SYS_MUNMAP, &[mine, PAGE_SIZE]), 0);
+        assert_eq!(&std::fs::read(root.join("etc/data")).unwrap()[PAGE_SIZE as usize..], b"SECOND");
+        assert_eq!(call(&mut table, pid, syscall::SYS_MUNMAP, &[read_only, PAGE_SIZE]), 0);
+        assert_eq!(table.page_cache().len(), 1);
+        poke(&table, other, theirs, b"Second");
+        assert_eq!(touch(&mut table, other, 0x1000, FAULT_USER), Err("Segmentation fault"));
+        assert_eq!(table.wait(KERNEL_PID, Some(other)), Ok(Some((other, ExitStatus::Killed(11)))));
+        assert_eq!(std::fs::read(root.join("etc/data")).unwrap().len(), PAGE_SIZE as usize + 6);
+        assert_eq!(&std::fs::read(root.join("etc/data")).unwrap()[PAGE_SIZE as usize..], b"Second");
+        assert!(table.page_cache().is_empty());
+
+        // Touching memory against its protection is fatal, and nothing the
+        // process had is left behind
+        let none = call(&mut table, pid, syscall::SYS_MMAP, &[0, PAGE_SIZE, 0, anonymous, u64::MAX, 0]);
+        assert_eq!(call(&mut table, pid, syscall::SYS_READ, &[0, none, 1]), Errno::Fault.as_return());
+        assert_eq!(touch(&mut table, pid, anon, FAULT_USER | FAULT_WRITE | FAULT_PRESENT), Err("Segmentation fault"));
+        assert_eq!(table.wait(KERNEL_PID, Some(pid)), Ok(Some((pid, ExitStatus::Killed(11)))));
+        assert_eq!(memory.allocated(), baseline);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    pub fn test_layout_randomization_and_w_xor_x() {
+        let root = std::env::temp_dir().join(format!("vaelix-layout-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("bin")).unwrap();
+        let code = [0x90u8; 32];
+        write_executable(&root.join("bin/prog"), &build_elf(0x40_0000, &[(0x40_0000, PF_R | PF_X, &code, 32)]));
+        let memory = PhysicalMemory::new(1024);
+        let mut table = ProcessTable::new(&memory, root.to_str().unwrap(), &VXChanManager::new()).unwrap();
+        assert!(table.randomize_layout());
+
+        // Every part moves by whole pages within its range, differently
+        // for each process
+        let pids: Vec<Pid> = (0..4).map(|_| table.spawn(KERNEL_PID, "/bin/prog", &["prog"], &[], FileTable::new()).unwrap()).collect();
+        let layouts: Vec<Layout> = pids.iter().map(|pid| *table.get(*pid).unwrap().layout()).collect();
+        for layout in &layouts {
+            assert!(layout.stack_top <= STACK_TOP && layout.stack_top > STACK_TOP - STACK_RANDOM_PAGES * PAGE_SIZE);
+            assert!(layout.mmap_top <= MMAP_TOP && layout.mmap_top > MMAP_TOP - MMAP_RANDOM_PAGES * PAGE_SIZE);
+            assert!(layout.program_base >= PROGRAM_BASE && layout.program_base < PROGRAM_BASE + PROGRAM_RANDOM_PAGES * PAGE_SIZE);
+            assert!(layout.heap_gap < HEAP_RANDOM_PAGES * PAGE_SIZE);
+            assert!([layout.stack_top, layout.mmap_top, layout.program_base, layout.heap_gap].iter().all(|address| address % PAGE_SIZE == 0));
+        }
+        assert!(layouts.windows(2).any(|pair| pair[0] != pair[1]));
+        let (pid, layout) = (pids[0], layouts[0]);
+        let process = table.get(pid).unwrap();
+        assert_eq!(process.break_start(), 0x40_1000 + layout.heap_gap);
+        assert!(process.space().translate(layout.stack_top - PAGE_SIZE).is_some());
+        assert!(process.space().translate(layout.stack_top).is_none());
+        assert!((layout.stack_top - STACK_SIZE..layout.stack_top).contains(&process.context().rsp));
+        let rw = syscall::PROT_READ | syscall::PROT_WRITE;
+        let anonymous = syscall::MAP_PRIVATE | syscall::MAP_ANONYMOUS;
+        let mapped = call(&mut table, pid, syscall::SYS_MMAP, &[0, PAGE_SIZE, rw, anonymous, u64::MAX, 0]);
+        assert_eq!(mapped, layout.mmap_top - PAGE_SIZE);
+        table.set_randomize_layout(false);
+        let fixed = table.spawn(KERNEL_PID, "/bin/prog", &["prog"], &[], FileTable::new()).unwrap();
+        assert_eq!(*table.get(fixed).unwrap().layout(), Layout::fixed());
+
+        // No page is ever writable and executable at once
+        let rwx = rw | syscall::PROT_EXEC;
+        assert_eq!(call(&mut table, pid, syscall::SYS_MMAP, &[0, PAGE_SIZE, rwx, anonymous, u64::MAX, 0]), Errno::Access.as_return());
+        assert_eq!(call(&mut table, pid, syscall::SYS_MPROTECT, &[mapped, PAGE_SIZE, rwx]), Errno::Access.as_return());
+        assert_eq!(call(&mut table, pid, syscall::SYS_MPROTECT, &[mapped, PAGE_SIZE, syscall::PROT_READ | syscall::PROT_EXEC]), 0);
+        let space = table.get_mut(pid).unwrap().space_mut();
+        assert_eq!(space.protect(0x40_0000, USER | WRITABLE), Err("Writable and executable"));
+        assert_eq!(space.map_zeroed(0x1000_0000, 1, USER | WRITABLE), Err("Writable and executable"));
+        let mut kernel = AddressSpace::kernel(&memory).unwrap();
+        assert_eq!(kernel.map_zeroed(KERNEL_BASE, 1, WRITABLE), Err("Writable and executable"));
+        assert_eq!(kernel.map_zeroed(KERNEL_BASE, 1, WRITABLE | NO_EXECUTE), Ok(()));
+        let writable_code = build_elf(0x40_0000, &[(0x40_0000, PF_R | PF_W | PF_X, &code, 32)]);
+        assert_eq!(Executable::parse(&writable_code).err(), Some("Writable and executable segment"));
+        // Nor when code and data share a page
+        let shared = build_elf(0x40_0000, &[(0x40_0000, PF_R | PF_X, &code, 32), (0x40_0800, PF_R | PF_W, b"data", 4)]);
+        assert_eq!(Process::load(9, "shared", &kernel, &shared, &[], &[]).err(), Some("Writable and executable"));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    pub fn test_syscall_filters() {
+        let filter = Filter::parse("read write close open:arg1&3==0 kill:arg0==0,arg1<=0x0f kill:arg1==9", Action::Deny).unwrap();
+        assert!(filter.allows(syscall::SYS_READ, &[7; 6]));
+        assert!(filter.allows(syscall::SYS_OPEN, &[0x1000, syscall::O_RDONLY | syscall::O_NONBLOCK, 0, 0, 0, 0]));
+        assert!(!filter.allows(syscall::SYS_OPEN, &[0x1000, syscall::O_WRONLY, 0, 0, 0, 0]));
+        assert!(filter.allows(syscall::SYS_KILL, &[0, 15, 0, 0, 0, 0]) && filter.allows(syscall::SYS_KILL, &[4, 9, 0, 0, 0, 0]));
+        assert!(!filter.allows(syscall::SYS_KILL, &[4, 15, 0, 0, 0, 0]) && !filter.allows(syscall::SYS_KILL, &[0, 16, 0, 0, 0, 0]));
+        assert!(!filter.allows(syscall::SYS_SPAWN, &[0; 6]) && !filter.allows(99, &[0; 6]));
+        assert_eq!(Filter::parse("read frobnicate", Action::Deny), Err("Unknown syscall"));
+        assert_eq!(Filter::parse("open:arg6==0", Action::Deny), Err("Unknown syscall argument"));
+        assert_eq!(Filter::parse("open:arg1~0", Action::Deny), Err("Malformed syscall predicate"));
+        assert_eq!(Filter::parse("open:arg1==0o9", Action::Deny), Err("Invalid number"));
+        assert_eq!(Action::parse("maybe"), Err("Unknown syscall action"));
+
+        let root = std::env::temp_dir().join(format!("vaelix-filters-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("bin")).unwrap();
+        std::fs::create_dir_all(root.join("etc")).unwrap();
+        std::fs::write(root.join("etc/config"), b"config").unwrap();
+        let code = [0x90u8; 32];
+        write_executable(&root.join("bin/daemon"), &build_elf(0x40_0000, &[(0x40_0000, PF_R | PF_X, &code, 32)]));
+        let memory = PhysicalMemory::new(1024);
+        let mut table = ProcessTable::new(&memory, root.to_str().unwrap(), &VXChanManager::new()).unwrap();
+        let pid = table.spawn(KERNEL_PID, "/bin/daemon", &["daemon"], &[], FileTable::new()).unwrap();
+        let scratch = table.get(pid).unwrap().layout().stack_top - STACK_SIZE;
+        table.get(pid).unwrap().space().write(scratch, b"/etc/config\0").unwrap();
+        assert!(procfs::read(&table, pid, "/proc/self/status").unwrap().ends_with("Seccomp:\t0\n"));
+
+        // Calls the filter does not allow fail, and children inherit it
+        table.get_mut(pid).unwrap().add_filter(Arc::new(filter));
+        assert!(procfs::read(&table, pid, "/proc/self/status").unwrap().ends_with("Seccomp:\t2\n"));
+        assert_eq!(call(&mut table, pid, syscall::SYS_GETPID, &[]), Errno::NotPermitted.as_return());
+        assert_eq!(call(&mut table, pid, syscall::SYS_OPEN, &[scratch, syscall::O_RDWR]), Errno::NotPermitted.as_return());
+        let fd = call(&mut table, pid, syscall::SYS_OPEN, &[scratch, syscall::O_RDONLY]);
+        assert_eq!(call(&mut table, pid, syscall::SYS_READ, &[fd, scratch + 64, 6]), 6);
+        let child = table.spawn(pid, "/bin/daemon", &["daemon"], &[], FileTable::new()).unwrap();
+        assert_eq!(table.get(child).unwrap().filters().len(), 1);
+        assert_eq!(call(&mut table, child, syscall::SYS_GETPID, &[]), Errno::NotPermitted.as_return());
+
+        // Another filter narrows it further, and the harsher action wins
+        table.get_mut(pid).unwrap().add_filter(Arc::new(Filter::parse("read write open", Action::Kill).unwrap()));
+        assert_eq!(call(&mut table, pid, syscall::SYS_OPEN, &[scratch, syscall::O_RDWR]), Errno::NotPermitted.as_return());
+        assert_eq!(call(&mut table, pid, syscall::SYS_READ, &[fd, scratch + 64, 6]), 0);
+        call(&mut table, pid, syscall::SYS_CLOSE, &[fd]);
+        assert_eq!(table.wait(KERNEL_PID, Some(pid)), Ok(Some((pid, ExitStatus::Killed(SIGSYS)))));
+        assert!(table.get(child).is_some());
+
+        // Services get theirs from the manifest
+        let manifest = Manifest::parse("[service]\nname=network\nexec=/bin/daemon\nsyscalls=read write open:arg1&3==0\n").unwrap();
+        assert_eq!(manifest.filter.as_ref().map(|filter| filter.action()), Some(Action::Kill));
+        let manifest = Manifest::parse("[service]\nname=network\nsyscall-action=deny\nexec=/bin/daemon\nsyscalls=read\n").unwrap();
+        assert_eq!(manifest.filter.as_ref().map(|filter| filter.action()), Some(Action::Deny));
+        assert_eq!(Manifest::parse("[service]\nname=network\nexec=/bin/daemon\nsyscalls=read raw_open\n"), Err("Unknown syscall"));
+        assert!(Manifest::parse("[service]\nname=network\nexec=/bin/daemon\n").unwrap().filter.is_none());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    struct RamDisk(Mutex<Vec<u8>>);
+
+    impl BlockDevice for RamDisk {
+        fn block_size(&self) -> usize {
+            512
+        }
+
+        fn block_count(&self) -> u64 {
+            self.0.lock().unwrap().len() as u64 / 512
+        }
+
+        fn read_blocks(&self, lba: u64, buffer: &mut [u8]) -> Result<(), &'static str> {
+            let start = lba as usize * 512;
+            buffer.copy_from_slice(&self.0.lock().unwrap()[start..start + buffer.len()]);
+            Ok(())
+        }
+
+        fn write_blocks(&self, lba: u64, buffer: &[u8]) -> Result<(), &'static str> {
+            let start = lba as usize * 512;
+            self.0.lock().unwrap()[start..start + buffer.len()].copy_from_slice(buffer);
+            Ok(())
+        }
+    }
+
+    #[test]
+    pub fn test_crash_dump_on_panic() {
+        let disk: Arc<RamDisk> = Arc::new(RamDisk(Mutex::new(vec![0; 128 * 512])));
+        assert!(DumpArea::new(disk.clone(), 120, 16).is_err());
+        crashdump::install(DumpArea::new(disk.clone(), 64, 64).unwrap()).unwrap();
+        assert!(crashdump::install(DumpArea::new(disk.clone(), 0, 64).unwrap()).is_err());
+        crashdump::register_module("rtl8168", 0xFFFF_FFFF_C000_0000, 0x4000);
+        crashdump::trace("rtl8168: link up");
+
+        let mut context = UserContext::new(0x40_1000, 0x7FFF_F000);
+        context.registers[RAX] = 0xDEAD;
+        let result = std::panic::catch_unwind(|| {
+            let _frame = crashdump::enter_trap(42, context);
+            panic!("Ring buffer overrun");
+        });
+        assert!(result.is_err());
+        // A second panic is taken for fallout from the first
+        let _ = std::panic::catch_unwind(|| panic!("Another one"));
+
+        // As read on the next boot
+        let area = DumpArea::new(disk.clone(), 64, 64).unwrap();
+        let dump = area.read().unwrap().unwrap();
+        assert!(dump.reason.starts_with("Ring buffer overrun at tests/test_kernel.rs:"));
+        assert_eq!(dump.trap, Some((42, context)));
+        assert!(!dump.backtrace.is_empty());
+        assert!(dump.trace.iter().any(|entry| entry.text == "rtl8168: link up"));
+        assert!(dump.modules.iter().any(|module| module.name == "rtl8168" && module.size == 0x4000));
+        assert_eq!(Minidump::decode(&dump.encode()).unwrap(), dump);
+        let report = crashdump::vxdump(&area, true).unwrap().unwrap();
+        assert!(report.contains("thread 42") && report.contains("rax 0x000000000000dead"));
+        assert!(report.contains("rtl8168: link up") && report.contains("rtl8168"));
+        assert_eq!(crashdump::vxdump(&area, true).unwrap(), None);
+
+        // One that does not fit loses its oldest events first
+        let small = DumpArea::new(disk.clone(), 0, 2).unwrap();
+        let mut big = dump.clone();
+        big.backtrace.clear();
+        big.trace = (0..100).map(|index| crashdump::TraceEntry { time: index, text: format!("event {}", index) }).collect();
+        small.write(&big).unwrap();
+        let kept = small.read().unwrap().unwrap().trace;
+        assert!(kept.len() < 100 && kept.last().unwrap().text == "event 99");
+        disk.0.lock().unwrap()[600] ^= 1;
+        assert_eq!(small.read(), Err("Crash dump checksum mismatch"));
+        crashdump::unregister_module("rtl8168");
+        assert!(crashdump::modules().iter().all(|module| module.name != "rtl8168"));
+    }
+
+    #[test]
+    pub fn test_smep_smap_and_user_copies() {
+        let msr = MsrSpace::new(2);
+        msr.set_cpuid(CPUID_EXTENDED_FEATURES, (0, CPUID_SMEP | CPUID_SMAP, CPUID_UMIP, 0));
+        msr.set_cpu_cpuid(1, CPUID_EXTENDED_FEATURES, (0, CPUID_SMEP, 0, 0));
+        msr.write_cr4(0, 0x20).unwrap();
+        let cr4 = uaccess::enable(&msr, 0).unwrap();
+        assert_eq!(cr4, CR4_SMEP | CR4_SMAP | CR4_UMIP);
+        assert_eq!(msr.read_cr4(0), Ok(0x20 | cr4));
+        // Only what the CPU has
+        assert_eq!(uaccess::enable(&msr, 1), Ok(CR4_SMEP));
+        assert!(uaccess::enable(&msr, 2).is_err());
+
+        assert_eq!(uaccess::check_kernel_fault(cr4, FAULT_FETCH), Err("SMEP: kernel executed user memory"));
+        assert_eq!(uaccess::check_kernel_fault(cr4, FAULT_WRITE), Err("SMAP: kernel accessed user memory directly"));
+        assert!(uaccess::check_kernel_fault(0, FAULT_WRITE).is_ok() && uaccess::check_kernel_fault(0, FAULT_FETCH).is_ok());
+        uaccess::with_user_access(|| {
+            uaccess::with_user_access(|| assert!(uaccess::check_kernel_fault(cr4, FAULT_WRITE).is_ok()));
+            // Still open once the inner window closes
+            assert!(uaccess::user_access_open());
+        });
+        assert!(!uaccess::user_access_open());
+
+        let root = std::env::temp_dir().join(format!("vaelix-smap-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("bin")).unwrap();
+        let code = [0x90u8; 32];
+        write_executable(&root.join("bin/init"), &build_elf(0x40_0000, &[(0x40_0000, PF_R | PF_X, &code, 32)]));
+        let memory = PhysicalMemory::new(1024);
+        let mut table = ProcessTable::new(&memory, root.to_str().unwrap(), &VXChanManager::new()).unwrap();
+        table.set_cr4(cr4);
+        let pid = table.spawn(KERNEL_PID, "/bin/init", &["init"], &[], FileTable::new()).unwrap();
+        let rw = syscall::PROT_READ | syscall::PROT_WRITE;
+        let anon = call(&mut table, pid, syscall::SYS_MMAP, &[0, 2 * PAGE_SIZE, rw, syscall::MAP_PRIVATE | syscall::MAP_ANONYMOUS, u64::MAX, 0]);
+        // The kernel touching a user page outside a copy is a bug, and
+        // running one always is
+        assert_eq!(table.fault(pid, anon, FAULT_WRITE), Err("SMAP: kernel accessed user memory directly"));
+        assert_eq!(table.fault(pid, anon, FAULT_FETCH), Err("SMEP: kernel executed user memory"));
+        assert!(uaccess::with_user_access(|| table.fault(pid, anon, FAULT_WRITE)).is_ok());
+        assert!(table.fault(pid, anon + PAGE_SIZE, FAULT_USER | FAULT_WRITE).is_ok());
+
+        let space = table.get(pid).unwrap().space();
+        syscall::copy_to_user(space, anon + 8, b"user data").unwrap();
+        assert_eq!(syscall::copy_from_user(space, anon + 8, 9).unwrap(), b"user data");
+        assert_eq!(syscall::copy_from_user(space, KERNEL_BASE, 8), Err(Errno::Fault));
+        assert!(!uaccess::user_access_open());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    pub fn test_keyring_access_and_syscalls() {
+        let secret = Secret::from_slice(b"correct horse battery");
+        assert_eq!(format!("{:?}", secret), "Secret(21 bytes)");
+        assert_eq!(secret.clone(), secret);
+        assert_ne!(Secret::from_slice(b"correct horse battery!"), secret);
+        assert!(KeyKind::WifiPsk.check(b"short").is_err() && KeyKind::WifiPsk.check(&[b'a'; 64]).is_ok());
+        assert!(KeyKind::WifiPsk.check(&[b'z'; 64]).is_err() && KeyKind::Encryption.check(&[0; 48]).is_err());
+
+        let alice = Credentials { uid: FIRST_USER_ID, gid: FIRST_USER_ID, groups: Vec::new() };
+        let bob = Credentials { uid: FIRST_USER_ID + 1, gid: FIRST_USER_ID + 1, groups: vec![FIRST_USER_ID] };
+        let mut keyring = Keyring::new();
+        let id = keyring.add(&alice, KeyKind::WifiPsk, "home", secret.clone(), 0o640).unwrap();
+        assert_eq!(keyring.add(&alice, KeyKind::WifiPsk, "home", Secret::from_slice(b"tiny"), 0o600), Err("Invalid key payload"));
+        assert_eq!(keyring.add(&alice, KeyKind::WifiPsk, "home", secret.clone(), 0o700), Err("Invalid key mode"));
+        // Bob is in Alice's group, so may read but not revoke it
+        assert_eq!(keyring.find(&bob, KeyKind::WifiPsk, "home"), Ok(id));
+        assert_eq!(keyring.read(&bob, id).unwrap(), &secret);
+        assert_eq!(keyring.revoke(&bob, id), Err("Permission denied"));
+        assert_eq!(keyring.set_mode(&bob, id, 0o666), Err("Permission denied"));
+        keyring.set_mode(&alice, id, 0o600).unwrap();
+        assert_eq!(keyring.read(&bob, id), Err("Permission denied"));
+        assert_eq!(keyring.find(&bob, KeyKind::WifiPsk, "home"), Err("Key not found"));
+        assert!(keyring.read(&Credentials::root(), id).is_ok());
+        // Adding it again updates it in place
+        let updated = Secret::from_slice(b"correct horse battery staple");
+        assert_eq!(keyring.add(&alice, KeyKind::WifiPsk, "home", updated.clone(), 0o600), Ok(id));
+        assert_eq!(keyring.read(&alice, id).unwrap(), &updated);
+        keyring.revoke(&alice, id).unwrap();
+        assert!(keyring.is_empty());
+
+        let root = std::env::temp_dir().join(format!("vaelix-keyring-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("bin")).unwrap();
+        let code = [0x90u8; 32];
+        write_executable(&root.join("bin/wpa"), &build_elf(0x40_0000, &[(0x40_0000, PF_R | PF_X, &code, 32)]));
+        let memory = PhysicalMemory::new(1024);
+        let mut table = ProcessTable::new(&memory, root.to_str().unwrap(), &VXChanManager::new()).unwrap();
+        let pid = table.spawn(KERNEL_PID, "/bin/wpa", &["wpa"], &[], FileTable::new()).unwrap();
+        let other = table.spawn(KERNEL_PID, "/bin/wpa", &["wpa"], &[], FileTable::new()).unwrap();
+        table.get_mut(pid).unwrap().set_credentials(alice.clone());
+        table.get_mut(other).unwrap().set_credentials(Credentials { uid: FIRST_USER_ID + 2, gid: FIRST_USER_ID + 2, groups: Vec::new() });
+        let scratch = table.get(pid).unwrap().layout().stack_top - STACK_SIZE;
+        let other_scratch = table.get(other).unwrap().layout().stack_top - STACK_SIZE;
+        table.get(pid).unwrap().space().write(scratch, b"wg0\0").unwrap();
+        table.get(other).unwrap().space().write(other_scratch, b"wg0\0").unwrap();
+        table.get(pid).unwrap().space().write(scratch + 64, &[0x42; 32]).unwrap();
+
+        let id = call(&mut table, pid, syscall::SYS_ADD_KEY, &[2, scratch, scratch + 64, 32, 0o600]);
+        assert_eq!(call(&mut table, pid, syscall::SYS_ADD_KEY, &[2, scratch, scratch + 64, 16, 0o600]), Errno::Invalid.as_return());
+        assert_eq!(call(&mut table, pid, syscall::SYS_ADD_KEY, &[9, scratch, scratch + 64, 32, 0o600]), Errno::Invalid.as_return());
+        assert_eq!(call(&mut table, pid, syscall::SYS_REQUEST_KEY, &[2, scratch]), id);
+        assert_eq!(call(&mut table, pid, syscall::SYS_REQUEST_KEY, &[0, scratch]), Errno::NoKey.as_return());
+        assert_eq!(table.keyring().info(id as u32).unwrap().owner, FIRST_USER_ID);
+        // Too small a buffer only gets the length
+        assert_eq!(call(&mut table, pid, syscall::SYS_READ_KEY, &[id, scratch + 128, 16]), 32);
+        assert_eq!(read_u64(table.get(pid).unwrap().space(), scratch + 128), 0);
+        assert_eq!(call(&mut table, pid, syscall::SYS_READ_KEY, &[id, scratch + 128, 64]), 32);
+        assert_eq!(read_u64(table.get(pid).unwrap().space(), scratch + 128), 0x4242_4242_4242_4242);
+        assert_eq!(call(&mut table, other, syscall::SYS_REQUEST_KEY, &[2, other_scratch]), Errno::NoKey.as_return());
+        assert_eq!(call(&mut table, other, syscall::SYS_READ_KEY, &[id, other_scratch + 128, 64]), Errno::Access.as_return());
+        assert_eq!(call(&mut table, other, syscall::SYS_REVOKE_KEY, &[id]), Errno::Access.as_return());
+        assert_eq!(call(&mut table, pid, syscall::SYS_REVOKE_KEY, &[id]), 0);
+        assert_eq!(call(&mut table, pid, syscall::SYS_READ_KEY, &[id, scratch + 128, 64]), Errno::NoKey.as_return());
+        assert!(table.keyring().is_empty());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    pub fn test_kernel_hardening() {
+        assert_eq!(hardening::add(u32::MAX - 1, 1, "Test counter"), u32::MAX);
+        if hardening::ENABLED {
+            let warnings = hardening::warnings();
+            assert_eq!(hardening::add(u64::MAX - 1, 2, "Test counter"), u64::MAX);
+            assert_eq!(hardening::warnings(), warnings + 1);
+        }
+        assert_eq!(hardening::canary() & 0xFF, 0);
+        assert_eq!(hardening::canary(), hardening::canary());
+
+        let memory = PhysicalMemory::new(64);
+        let mut space = AddressSpace::kernel(&memory).unwrap();
+        let stack = KernelStack::map(&mut space, IRQ_STACKS_BASE, IRQ_STACK_PAGES).unwrap();
+        assert_eq!(stack.top() - stack.bottom(), IRQ_STACK_PAGES * PAGE_SIZE);
+        assert!(stack.check(&space).is_ok());
+        assert!(space.write(stack.top() - 8, &[0xAA; 8]).is_ok());
+        match stack.guard() {
+            Some(guard) => {
+                // Running off the bottom faults, and getting near it
+                // clobbers the canary
+                assert!(space.write(guard + PAGE_SIZE - 8, &[0xAA; 8]).is_err());
+                assert!(stack.overflowed_at(guard + PAGE_SIZE - 8) && !stack.overflowed_at(stack.bottom()));
+                space.write(stack.bottom(), &[0xAA; 8]).unwrap();
+                assert_eq!(stack.check(&space), Err("Kernel stack canary clobbered"));
+            }
+            None => assert!(!hardening::ENABLED && stack.bottom() == IRQ_STACKS_BASE),
+        }
+
+        let root = std::env::temp_dir().join(format!("vaelix-hardening-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("bin")).unwrap();
+        let code = [0x90u8; 32];
+        write_executable(&root.join("bin/init"), &build_elf(0x40_0000, &[(0x40_0000, PF_R | PF_X, &code, 32)]));
+        let memory = PhysicalMemory::new(1024);
+        let mut table = ProcessTable::new(&memory, root.to_str().unwrap(), &VXChanManager::new()).unwrap();
+        table.map_irq_stacks(2).unwrap();
+        assert!(table.irq_stack(1).unwrap().bottom() > table.irq_stack(0).unwrap().top());
+        assert!(table.irq_stack(2).is_none() && table.check_irq_stacks().is_ok());
+        let pid = table.spawn(KERNEL_PID, "/bin/init", &["init"], &[], FileTable::new()).unwrap();
+        let stack = table.irq_stack(0).unwrap().clone();
+        // Processes share the kernel half, interrupt stacks and all
+        assert!(table.get(pid).unwrap().space().translate(stack.top() - PAGE_SIZE).is_some());
+        assert_eq!(table.map_irq_stacks(4), Err("Too late to map interrupt stacks"));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    // What goes into a position-independent object built by build_object.
+    // Imports are (name, binding) with a GOT slot each, in order, bound
+    // through the PLT relocations; one more slot after them holds the
+    // text's address through a relative relocation.
+    #[derive(Default)]
+    struct ObjectSpec<'a> {
+        entry: u64,
+        interpreter: Option<&'a str>,
+        soname: Option<&'a str>,
+        needed: &'a [&'a str],
+        // Name and offset into the text
+        exports: &'a [(&'a str, u64)],
+        imports: &'a [(&'a str, u8)],
+    }
+
+    fn elf_hash(name: &str) -> u32 {
+        name.bytes().fold(0u32, |hash, byte| {
+            let hash = (hash << 4).wrapping_add(byte as u32);
+            (hash ^ (hash & 0xF000_0000) >> 24) & !(hash & 0xF000_0000)
+        })
+    }
+
+    // Laid out as a linker would: text at 0x1000, then a writable page at
+    // 0x2000 with the GOT, strings at 0x100, symbols at 0x400, the hash
+    // table at 0x800, relocations at 0xA00 and 0xB00 and the dynamic
+    // section at 0xC00, and some .bss after it. The file goes on past
+    // the data with junk that must not show through.
+    fn build_object(spec: &ObjectSpec) -> Vec<u8> {
+        let mut strings = vec![0u8];
+        let mut string = |text: &str| {
+            strings.extend_from_slice(text.as_bytes());
+            strings.push(0);
+            (strings.len() - text.len() - 1) as u64
+        };
+        let needed: Vec<u64> = spec.needed.iter().map(|name| string(name)).collect();
+        let soname = spec.soname.map(&mut string);
+        let mut symbols = vec![[0u8; 24]];
+        let mut names = vec![String::new()];
+        for (name, offset) in spec.exports {
+            let mut symbol = [0u8; 24];
+            symbol[..4].copy_from_slice(&(string(name) as u32).to_le_bytes());
+            symbol[4] = 1 << 4 | 2;
+            symbol[6..8].copy_from_slice(&1u16.to_le_bytes());
+            symbol[8..16].copy_from_slice(&(0x1000 + offset).to_le_bytes());
+            symbols.push(symbol);
+            names.push(name.to_string());
+        }
+        for (name, binding) in spec.imports {
+            let mut symbol = [0u8; 24];
+            symbol[..4].copy_from_slice(&(string(name) as u32).to_le_bytes());
+            symbol[4] = binding << 4 | 2;
+            symbols.push(symbol);
+            names.push(name.to_string());
+        }
+        let buckets = 3;
+        let mut hash = vec![0u32; 2 + buckets + symbols.len()];
+        hash[0] = buckets as u32;
+        hash[1] = symbols.len() as u32;
+        for (index, name) in names.iter().enumerate().skip(1) {
+            let bucket = 2 + elf_hash(name) as usize % buckets;
+            hash[2 + buckets + index] = hash[bucket];
+            hash[bucket] = index as u32;
+        }
+        let relocation = |offset: u64, info: u64, addend: u64| [offset, info, addend].iter().flat_map(|word| word.to_le_bytes()).collect::<Vec<u8>>();
+        let imports = spec.imports.len() as u64;
+        let plt: Vec<u8> = (0..imports).flat_map(|index| relocation(0x2000 + index * 8, (1 + spec.exports.len() as u64 + index) << 32 | 7, 0)).collect();
+        let relative = relocation(0x2000 + imports * 8, 8, 0x1000);
+        let mut dynamic: Vec<(u64, u64)> = needed.iter().map(|name| (linker::DT_NEEDED, *name)).collect();
+        dynamic.extend(soname.map(|soname| (linker::DT_SONAME, soname)));
+        dynamic.extend([
+            (linker::DT_HASH, 0x2800),
+            (linker::DT_STRTAB, 0x2100),
+            (linker::DT_STRSZ, strings.len() as u64),
+            (linker::DT_SYMTAB, 0x2400),
+            (linker::DT_RELA, 0x2A00),
+            (linker::DT_RELASZ, relative.len() as u64),
+            (linker::DT_JMPREL, 0x2B00),
+            (linker::DT_PLTRELSZ, plt.len() as u64),
+            (linker::DT_NULL, 0),
+        ]);
+
+        let mut data = vec![0u8; 0x3000];
+        data[..4].copy_from_slice(b"\x7FELF");
+        data[4..7].copy_from_slice(&[2, 1, 1]);
+        data[16..18].copy_from_slice(&3u16.to_le_bytes());
+        data[18..20].copy_from_slice(&62u16.to_le_bytes());
+        data[24..32].copy_from_slice(&spec.entry.to_le_bytes());
+        data[32..40].copy_from_slice(&64u64.to_le_bytes());
+        data[54..56].copy_from_slice(&56u16.to_le_bytes());
+        // (type, flags, offset and address, size in the file, in memory)
+        let mut headers = vec![(1u32, 5u32, 0x1000u64, 0x100u64, 0x100u64), (1, 6, 0x2000, 0xD00, 0xD40), (2, 6, 0x2C00, 0x100, 0x100)];
+        if let Some(interpreter) = spec.interpreter {
+            data[0x200..0x200 + interpreter.len()].copy_from_slice(interpreter.as_bytes());
+            headers.push((3, 4, 0x200, interpreter.len() as u64 + 1, interpreter.len() as u64 + 1));
+        }
+        data[56..58].copy_from_slice(&(headers.len() as u16).to_le_bytes());
+        for (index, (kind, flags, offset, file_size, memory_size)) in headers.into_iter().enumerate() {
+            let header = &mut data[64 + index * 56..][..56];
+            header[..4].copy_from_slice(&kind.to_le_bytes());
+            header[4..8].copy_from_slice(&flags.to_le_bytes());
+            header[8..16].copy_from_slice(&offset.to_le_bytes());
+            header[16..24].copy_from_slice(&offset.to_le_bytes());
+            header[32..40].copy_from_slice(&file_size.to_le_bytes());
+            header[40..48].copy_from_slice(&memory_size.to_le_bytes());
+        }
+        data[0x1000..0x1100].fill(0x90);
+        data[0x2100..0x2100 + strings.len()].copy_from_slice(&strings);
+        data[0x2400..0x2400 + symbols.len() * 24].copy_from_slice(&symbols.concat());
+        let hash: Vec<u8> = hash.iter().flat_map(|word| word.to_le_bytes()).collect();
+        data[0x2800..0x2800 + hash.len()].copy_from_slice(&hash);
+        data[0x2A00..0x2A00 + relative.len()].copy_from_slice(&relative);
+        data[0x2B00..0x2B00 + plt.len()].copy_from_slice(&plt);
+        let dynamic: Vec<u8> = dynamic.iter().flat_map(|(tag, value)| [tag.to_le_bytes(), value.to_le_bytes()].concat()).collect();
+        data[0x2C00..0x2C00 + dynamic.len()].copy_from_slice(&dynamic);
+        data.resize(0x3000, 0xEE);
+        data[0x2D00..].fill(0xEE);
+        data
+    }
+
+    // The auxiliary vector on a fresh process's stack, past argc, the
+    // arguments and the environment
+    fn auxiliary_vector(process: &Process) -> Vec<(u64, u64)> {
+        let space = process.space();
+        let mut address = process.context().rsp + 8 * (read_u64(space, process.context().rsp) + 2);
+        while read_u64(space, address) != 0 {
+            address += 8;
+        }
+        let mut vector = Vec::new();
+        loop {
+            address += 8;
+            let (key, value) = (read_u64(space, address), read_u64(space, address + 8));
+            if key == AT_NULL {
+                return vector;
+            }
+            vector.push((key, value));
+            address += 8;
+        }
+    }
+
+    #[test]
+    pub fn test_dynamic_linking() {
+        let root = std::env::temp_dir().join(format!("vaelix-linking-{}", std::process::id()));
+        for directory in ["bin", "lib", "usr/lib"] {
+            std::fs::create_dir_all(root.join(directory)).unwrap();
+        }
+        let (global, weak) = (1, 2);
+        let libc = ObjectSpec {
+            soname: Some("libc.so.6"),
+            exports: &[("puts", 0x10), ("strlen", 0x20)],
+            ..ObjectSpec::default()
+        };
+        std::fs::write(root.join("lib/libc.so.6"), build_object(&libc)).unwrap();
+        let libm = ObjectSpec {
+            needed: &["libc.so.6"],
+            exports: &[("sqrt", 0x30)],
+            imports: &[("strlen", global)],
+            ..ObjectSpec::default()
+        };
+        std::fs::write(root.join("usr/lib/libm.so"), build_object(&libm)).unwrap();
+        let plugin = ObjectSpec {
+            needed: &["libc.so.6"],
+            exports: &[("plugin_init", 0x40)],
+            imports: &[("puts", global)],
+            ..ObjectSpec::default()
+        };
+        std::fs::write(root.join("usr/lib/libplugin.so"), build_object(&plugin)).unwrap();
+        let broken = ObjectSpec {
+            exports: &[("broken", 0)],
+            imports: &[("nowhere", global)],
+            ..ObjectSpec::default()
+        };
+        std::fs::write(root.join("usr/lib/libbroken.so"), build_object(&broken)).unwrap();
+        let app = ObjectSpec {
+            entry: 0x1000,
+            interpreter: Some(INTERPRETER),
+            needed: &["libm.so"],
+            imports: &[("puts", global), ("sqrt", global), ("missing", weak)],
+            ..ObjectSpec::default()
+        };
+        write_executable(&root.join("bin/app"), &build_object(&app));
+        let memory = PhysicalMemory::new(1024);
+        let mut table = ProcessTable::new(&memory, root.to_str().unwrap(), &VXChanManager::new()).unwrap();
+        table.set_randomize_layout(false);
+        let baseline = memory.allocated();
+
+        // Position-independent programs for the built-in linker come up
+        // with what they need loaded breadth first and their GOTs filled in
+        let pid = table.spawn(KERNEL_PID, "/bin/app", &["app"], &[], FileTable::new()).unwrap();
+        let process = table.get(pid).unwrap();
+        let names: Vec<&str> = process.link_map().objects().iter().map(|object| object.name.as_str()).collect();
+        assert_eq!(names, ["app", "libm.so", "libc.so.6"]);
+        let bases: Vec<u64> = process.link_map().objects().iter().map(|object| object.base).collect();
+        assert_eq!(bases[0], PROGRAM_BASE);
+        assert_eq!(process.context().rip, PROGRAM_BASE + 0x1000);
+        assert!(auxiliary_vector(process).contains(&(AT_ENTRY, PROGRAM_BASE + 0x1000)));
+        let space = process.space();
+        assert_eq!(read_u64(space, PROGRAM_BASE + 0x2000), bases[2] + 0x1010);
+        assert_eq!(read_u64(space, PROGRAM_BASE + 0x2008), bases[1] + 0x1030);
+        assert_eq!(read_u64(space, PROGRAM_BASE + 0x2010), 0);
+        assert_eq!(read_u64(space, PROGRAM_BASE + 0x2018), PROGRAM_BASE + 0x1000);
+        assert_eq!(read_u64(space, bases[1] + 0x2000), bases[2] + 0x1020);
+        assert_eq!(read_u64(space, bases[2] + 0x2000), bases[2] + 0x1000);
+        // Libraries are file mappings, with .bss zeroed over the rest of
+        // the file
+        let text = space.mapping_at(bases[1] + 0x1000).unwrap();
+        assert!(matches!(text.backing, Backing::File { .. }) && !text.shared);
+        assert_eq!(text.flags & (WRITABLE | NO_EXECUTE), 0);
+        assert_eq!(read_u64(space, bases[2] + 0x2D00), 0);
+        assert_eq!(read_u64(space, bases[2] + 0x2FF8), 0);
+
+        // Symbols and libraries at run time
+        let scratch = STACK_TOP - STACK_SIZE;
+        let poke = |table: &ProcessTable, address: u64, data: &[u8]| table.get(pid).unwrap().space().write(address, data).unwrap();
+        poke(&table, scratch, b"sqrt\0");
+        assert_eq!(call(&mut table, pid, syscall::SYS_DLSYM, &[DEFAULT_HANDLE, scratch]), bases[1] + 0x1030);
+        poke(&table, scratch, b"libplugin.so\0");
+        let handle = call(&mut table, pid, syscall::SYS_DLOPEN, &[scratch]);
+        assert_eq!(call(&mut table, pid, syscall::SYS_DLOPEN, &[scratch]), handle);
+        let plugin_base = table.get(pid).unwrap().link_map().get(handle).unwrap().base;
+        assert_eq!(read_u64(table.get(pid).unwrap().space(), plugin_base + 0x2000), bases[2] + 0x1010);
+        poke(&table, scratch, b"plugin_init\0");
+        assert_eq!(call(&mut table, pid, syscall::SYS_DLSYM, &[handle, scratch]), plugin_base + 0x1040);
+        assert_eq!(call(&mut table, pid, syscall::SYS_DLSYM, &[DEFAULT_HANDLE, scratch]), Errno::NoEntry.as_return());
+        poke(&table, scratch, b"puts\0");
+        assert_eq!(call(&mut table, pid, syscall::SYS_DLSYM, &[handle, scratch]), bases[2] + 0x1010);
+        assert_eq!(call(&mut table, pid, syscall::SYS_DLSYM, &[99, scratch]), Errno::Invalid.as_return());
+        poke(&table, scratch, b"/lib/libc.so.6\0");
+        let libc_handle = call(&mut table, pid, syscall::SYS_DLOPEN, &[scratch]);
+        assert_eq!(table.get(pid).unwrap().link_map().get(libc_handle).unwrap().base, bases[2]);
+        assert_eq!(call(&mut table, pid, syscall::SYS_DLCLOSE, &[libc_handle]), 0);
+        assert_eq!(call(&mut table, pid, syscall::SYS_DLCLOSE, &[libc_handle]), Errno::Invalid.as_return());
+
+        // The last close unloads it, leaving what the program needs
+        let mappings = table.get(pid).unwrap().space().mappings().count();
+        assert_eq!(call(&mut table, pid, syscall::SYS_DLCLOSE, &[handle]), 0);
+        assert_eq!(table.get(pid).unwrap().link_map().objects().len(), 4);
+        assert_eq!(call(&mut table, pid, syscall::SYS_DLCLOSE, &[handle]), 0);
+        assert_eq!(table.get(pid).unwrap().link_map().objects().len(), 3);
+        assert!(table.get(pid).unwrap().space().mappings().count() < mappings);
+        assert_eq!(call(&mut table, pid, syscall::SYS_DLCLOSE, &[handle]), Errno::Invalid.as_return());
+
+        // Failures leave nothing behind
+        let mappings = table.get(pid).unwrap().space().mappings().count();
+        poke(&table, scratch, b"libbroken.so\0");
+        assert_eq!(call(&mut table, pid, syscall::SYS_DLOPEN, &[scratch]), Errno::NotExecutable.as_return());
+        poke(&table, scratch, b"libnone.so\0");
+        assert_eq!(call(&mut table, pid, syscall::SYS_DLOPEN, &[scratch]), Errno::NoEntry.as_return());
+        assert_eq!(table.get(pid).unwrap().link_map().objects().len(), 3);
+        assert_eq!(table.get(pid).unwrap().space().mappings().count(), mappings);
+        let lonely = ObjectSpec {
+            entry: 0x1000,
+            interpreter: Some(INTERPRETER),
+            needed: &["libnone.so"],
+            ..ObjectSpec::default()
+        };
+        write_executable(&root.join("bin/lonely"), &build_object(&lonely));
+        assert_eq!(table.spawn(pid, "/bin/lonely", &[], &[], FileTable::new()), Err("Library not found"));
+        assert_eq!(table.pids(), [pid]);
+
+        // Any other interpreter is loaded under the mappings and entered
+        // instead, knowing where it is and where the program starts
+        let interpreter = ObjectSpec { entry: 0x1000, ..ObjectSpec::default() };
+        write_executable(&root.join("lib/ld-other.so"), &build_object(&interpreter));
+        let other = ObjectSpec {
+            entry: 0x1000,
+            interpreter: Some("/lib/ld-other.so"),
+            ..ObjectSpec::default()
+        };
+        write_executable(&root.join("bin/other"), &build_object(&other));
+        let child = table.spawn(pid, "/bin/other", &["other"], &[], FileTable::new()).unwrap();
+        let process = table.get(child).unwrap();
+        let interpreter_base = MMAP_TOP - 0x3000;
+        assert_eq!(process.context().rip, interpreter_base + 0x1000);
+        let vector = auxiliary_vector(process);
+        assert!(vector.contains(&(AT_BASE, interpreter_base)) && vector.contains(&(AT_ENTRY, PROGRAM_BASE + 0x1000)));
+        assert!(process.link_map().objects().is_empty());
+        let anonymous = syscall::MAP_PRIVATE | syscall::MAP_ANONYMOUS;
+        assert_eq!(call(&mut table, child, syscall::SYS_MMAP, &[0, PAGE_SIZE, syscall::PROT_READ, anonymous, u64::MAX, 0]), interpreter_base - PAGE_SIZE);
+        assert_eq!(Process::load(9, "other", &AddressSpace::kernel(&memory).unwrap(), &build_object(&other), &[], &[]).err(), Some("Interpreter not loaded"));
+
+        table.exit(child, ExitStatus::Exited(0));
+        table.exit(pid, ExitStatus::Exited(0));
+        assert_eq!(memory.allocated(), baseline);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    // GDB's end of the line
+    #[derive(Clone, Default)]
+    struct FakeSerial {
+        input: Arc<Mutex<VecDeque<u8>>>,
+        output: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl FakeSerial {
+        fn send(&self, packet: &str) {
+            let checksum = packet.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+            self.input.lock().unwrap().extend(format!("${}#{:02x}", packet, checksum).bytes());
+        }
+
+        // Replies since last asked, without the acks
+        fn replies(&self) -> Vec<String> {
+            let output = String::from_utf8(std::mem::take(&mut *self.output.lock().unwrap())).unwrap();
+            output.split('$').skip(1).map(|framed| framed.rsplit_once('#').unwrap().0.to_string()).collect()
+        }
+    }
+
+    impl SerialPort for FakeSerial {
+        fn read_byte(&self) -> Option<u8> {
+            self.input.lock().unwrap().pop_front()
+        }
+
+        fn write_byte(&self, byte: u8) -> Result<(), &'static str> {
+            self.output.lock().unwrap().push(byte);
+            Ok(())
+        }
+    }
+
+    #[test]
+    pub fn test_gdbstub_remote_debugging() {
+        let command_line = CommandLine::parse("root=/dev/vda2 console=\"ttyS0 tty0\" kgdboc=ttyS1,9600 kgdbwait quiet=0 quiet").unwrap();
+        assert_eq!((command_line.get("console"), command_line.get("quiet"), command_line.has("kgdbwait")), (Some("ttyS0 tty0"), None, true));
+        assert!(CommandLine::parse("console=\"ttyS0").is_err());
+
+        // 9600 baud is a divisor of 12, and THR empty lets bytes out
+        let ports = PortSpace::new();
+        let stub = gdbstub::from_command_line(&command_line, ports.clone()).unwrap().unwrap();
+        assert!(stub.waits_for_debugger());
+        assert_eq!(gdbstub::from_command_line(&command_line, ports.clone()).err(), Some("Resource busy"));
+        assert_eq!((ports.inb(COM2 + 3), ports.inb(COM2 + 4)), (0x03, 0x0B));
+        ports.outb(COM2 + 5, 0x20);
+        let serial = Uart16550::new(ports.clone(), COM2, 9600).unwrap();
+        assert_eq!(serial.read_byte(), None);
+        serial.write_byte(b'+').unwrap();
+        assert_eq!(ports.inb(COM2), b'+');
+        ports.outb(COM2 + 5, 0x00);
+        assert_eq!(serial.write_byte(b'+'), Err("UART transmit timeout"));
+        assert_eq!(Uart16550::new(PortSpace::new(), COM1, 7).err(), Some("Unsupported baud rate"));
+        assert_eq!(uart::port_for_tty("ttyS4"), None);
+        assert!(gdbstub::from_command_line(&CommandLine::parse("quiet").unwrap(), PortSpace::new()).unwrap().is_none());
+        assert!(gdbstub::from_command_line(&CommandLine::parse("kgdboc=ttyUSB0").unwrap(), PortSpace::new()).is_err());
+
+        let root = std::env::temp_dir().join(format!("vaelix-gdbstub-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("bin")).unwrap();
+        let code = [0x90u8; 32];
+        write_executable(&root.join("bin/init"), &build_elf(0x40_0000, &[(0x40_0000, PF_R | PF_X, &code, 32)]));
+        let memory = PhysicalMemory::new(512);
+        let mut table = ProcessTable::new(&memory, root.to_str().unwrap(), &VXChanManager::new()).unwrap();
+        table.set_randomize_layout(false);
+        let pid = table.spawn(KERNEL_PID, "/bin/init", &["init"], &[], FileTable::new()).unwrap();
+        let tid = table.threads()[0];
+
+        let line = FakeSerial::default();
+        let mut stub = GdbStub::new(line.clone());
+        line.send("qSupported:multiprocess+;swbreak+");
+        line.send("qfThreadInfo");
+        line.send("qsThreadInfo");
+        line.send(&format!("qThreadExtraInfo,{:x}", tid));
+        line.send("?");
+        assert_eq!(stub.poll(&mut table), None);
+        assert!(line.output.lock().unwrap().starts_with(b"+$"));
+        let replies = line.replies();
+        assert_eq!(replies[0], "PacketSize=1000;swbreak+;QStartNoAckMode+");
+        assert_eq!((replies[1].as_str(), replies[2].as_str()), (format!("m{:x}", tid).as_str(), "l"));
+        assert_eq!(String::from_utf8(replies[3].as_bytes().chunks(2).map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap()).collect()).unwrap(), format!("init (pid {})", pid));
+        assert_eq!(replies[4], format!("T05thread:{:x};", tid));
+
+        // A bad checksum is asked for again, and '-' gets the last reply resent
+        line.input.lock().unwrap().extend(b"$g#00-");
+        assert_eq!(stub.poll(&mut table), None);
+        assert!(line.output.lock().unwrap().starts_with(b"-$"));
+        line.replies();
+
+        // Registers in GDB's order: rsp eighth, rip seventeenth
+        line.send("g");
+        stub.poll(&mut table);
+        let registers = line.replies().remove(0);
+        let word = |index: usize| u64::from_str_radix(&registers[index * 16..index * 16 + 16], 16).unwrap().swap_bytes();
+        let context = table.registers(tid).unwrap();
+        assert_eq!((word(7), word(16)), (context.rsp, context.rip));
+        let mut changed = registers.clone();
+        changed.replace_range(0..16, &format!("{:016x}", 0xBEEFu64.swap_bytes()));
+        line.send(&format!("G{}", changed));
+        line.send(&format!("m{:x},4", context.rip));
+        line.send(&format!("M{:x},2:cccc", context.rip + 8));
+        line.send(&format!("m{:x},2", context.rip + 8));
+        line.send("m0,4");
+        line.send("Hgffff");
+        line.send("vMustReplyEmpty");
+        stub.poll(&mut table);
+        assert_eq!(line.replies(), ["OK", "90909090", "OK", "cccc", "E01", "E01", ""]);
+        assert_eq!(table.registers(tid).unwrap().registers[RAX], 0xBEEF);
+
+        // A breakpoint puts INT3 in and taking it stops the thread on it
+        let address = context.rip + 4;
+        line.send(&format!("Z0,{:x},1", address));
+        line.send("QStartNoAckMode");
+        line.send("c");
+        assert_eq!(stub.poll(&mut table), Some(Command::Continue));
+        assert_eq!(line.replies(), ["OK", "OK"]);
+        assert_eq!(stub.breakpoints(), [address]);
+        let mut byte = [0u8];
+        table.read_memory(tid, address, &mut byte).unwrap();
+        assert_eq!(byte[0], INT3);
+        let mut trapped = table.registers(tid).unwrap();
+        trapped.rip = address + 1;
+        table.set_registers(tid, trapped).unwrap();
+        assert!(stub.handle_trap(&mut table, tid, Trap::Exception { vector: BREAKPOINT_VECTOR, error: None }));
+        assert_eq!(table.registers(tid).unwrap().rip, address);
+        assert_eq!(line.replies(), [format!("T05thread:{:x};swbreak:;", tid)]);
+        // Not one of ours
+        assert!(!stub.handle_trap(&mut table, tid, Trap::Exception { vector: BREAKPOINT_VECTOR, error: None }));
+
+        // Acks are off now; a step sets the trap flag until the debug exception
+        line.send("s");
+        assert_eq!(stub.poll(&mut table), Some(Command::Step(tid)));
+        assert!(line.output.lock().unwrap().is_empty());
+        assert_ne!(table.registers(tid).unwrap().rflags & RFLAGS_TF, 0);
+        assert!(stub.handle_trap(&mut table, tid, Trap::Exception { vector: DEBUG_VECTOR, error: None }));
+        assert_eq!(table.registers(tid).unwrap().rflags & RFLAGS_TF, 0);
+        assert_eq!(line.replies(), [format!("T05thread:{:x};", tid)]);
+
+        // Ctrl-C, then detaching takes the breakpoints out
+        line.input.lock().unwrap().push_back(0x03);
+        assert_eq!(stub.poll(&mut table), Some(Command::Interrupt));
+        line.send("D");
+        assert_eq!(stub.poll(&mut table), Some(Command::Detach));
+        table.read_memory(tid, address, &mut byte).unwrap();
+        assert_eq!(byte[0], 0x90);
+        assert!(stub.breakpoints().is_empty());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    pub fn test_structured_logging() {
+        let logger = Logger::new(4);
+        assert_eq!(logger.record(Level::Debug, "vaelix_core::drivers::uart::uart", "probing"), None);
+        logger.set_target_level("drivers", Some(LevelFilter::Debug));
+        logger.set_target_level("drivers::tpm", Some(LevelFilter::Error));
+        assert_eq!(logger.record(Level::Debug, "vaelix_core::drivers::uart::uart", "probing"), Some(0));
+        assert_eq!(logger.record(Level::Warn, "vaelix_core::drivers::tpm::tpm", "slow"), None);
+        assert_eq!(logger.record(Level::Debug, "driversx", "not a driver"), None);
+        assert_eq!((logger.level("drivers::tpm::crb"), logger.level("vxfs")), (LevelFilter::Error, LevelFilter::Info));
+        let record = &logger.read(0, LevelFilter::Trace)[0];
+        assert_eq!((record.target.as_str(), record.level), ("drivers::uart", Level::Debug));
+        assert!(record.to_string().ends_with("] DEBUG drivers::uart: probing"));
+
+        // Sinks get the buffer so far, then new records at their level
+        let line = FakeSerial::default();
+        logger.add_sink("serial", LevelFilter::Info, Box::new(SerialSink::new(line.clone()))).unwrap();
+        assert!(line.output.lock().unwrap().is_empty());
+        let (master, slave) = pty::open(PtySize { rows: 24, cols: 80 });
+        logger.add_sink("console", LevelFilter::Trace, Box::new(ConsoleSink::new(slave))).unwrap();
+        assert!(String::from_utf8(master.read()).unwrap().contains("drivers::uart: probing"));
+        assert_eq!(logger.add_sink("serial", LevelFilter::Info, Box::new(SerialSink::new(line.clone()))), Err("Log sink already exists"));
+        for index in 1..=4 {
+            logger.record(Level::Info, "vxinit", &format!("unit {} started", index));
+        }
+        let serial = String::from_utf8(std::mem::take(&mut *line.output.lock().unwrap())).unwrap();
+        assert_eq!(serial.matches("\r\n").count(), 4);
+        assert!(serial.contains(" INFO  vxinit: unit 4 started\r\n"));
+        drop(master);
+        logger.record(Level::Error, "vxinit", "console gone");
+        assert_eq!(logger.sinks(), [("serial".to_string(), LevelFilter::Info, 0), ("console".to_string(), LevelFilter::Trace, 1)]);
+        logger.remove_sink("console").unwrap();
+        assert_eq!(logger.remove_sink("console"), Err("No such log sink"));
+
+        // The oldest go first
+        let sequences: Vec<u64> = logger.read(0, LevelFilter::Trace).iter().map(|record| record.sequence).collect();
+        assert_eq!(sequences, [2, 3, 4, 5]);
+        let root = std::env::temp_dir().join(format!("vaelix-log-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let path = root.join("kernel.log");
+        logger.add_sink("file", LevelFilter::Warn, Box::new(FileSink::new(path.to_str().unwrap()))).unwrap();
+        logger.record(Level::Warn, "vxfs", "journal replayed");
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 2);
+
+        // dmesg and log levels over vxchan
+        let manager = VXChanManager::new();
+        let service = LogService::new(&manager).unwrap();
+        log::send_request(&manager, 1, "dmesg warn since 4").unwrap();
+        log::send_request(&manager, 2, "level * debug").unwrap();
+        log::send_request(&manager, 3, "level drivers default").unwrap();
+        log::send_request(&manager, 4, "levels").unwrap();
+        log::send_request(&manager, 5, "level vxfs loud").unwrap();
+        log::send_request(&manager, 6, "clear").unwrap();
+        assert_eq!(service.poll(&manager, &logger), Ok(6));
+        let reply = manager.receive_message(log::REPLY_CHANNEL).unwrap();
+        let lines: Vec<&str> = reply.lines().collect();
+        assert_eq!((lines[0], lines.len()), ("1 ok", 3));
+        assert!(lines[1].starts_with("5 [") && lines[1].ends_with("ERROR vxinit: console gone"));
+        assert!(lines[2].ends_with("WARN  vxfs: journal replayed"));
+        assert_eq!(manager.receive_message(log::REPLY_CHANNEL).unwrap(), "2 ok");
+        assert_eq!(manager.receive_message(log::REPLY_CHANNEL).unwrap(), "3 ok");
+        assert_eq!(manager.receive_message(log::REPLY_CHANNEL).unwrap(), "4 ok\n* DEBUG\ndrivers::tpm ERROR");
+        assert_eq!(manager.receive_message(log::REPLY_CHANNEL).unwrap(), "5 error Unknown log level");
+        assert_eq!(manager.receive_message(log::REPLY_CHANNEL).unwrap(), "6 ok");
+        assert!(logger.read(0, LevelFilter::Trace).is_empty());
+        assert_eq!(logger.level("vxinit"), LevelFilter::Debug);
+
+        // The log macros reach the kernel logger once it is installed, and
+        // what they say ends up in crash dumps too
+        assert!(std::ptr::eq(log::init(), log::init()));
+        ::log::warn!(target: "vaelix_core::power::fan::fan", "fan {} stalled", 2);
+        let records = log::logger().unwrap().read(0, LevelFilter::Warn);
+        assert!(records.iter().any(|record| record.target == "power::fan" && record.message == "fan 2 stalled"));
+        assert!(crashdump::recent_trace().iter().any(|entry| entry.text == "power::fan: fan 2 stalled"));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[inline(never)]
+    fn innermost() -> Vec<u64> {
+        kallsyms::capture_backtrace()
+    }
+
+    #[test]
+    pub fn test_kallsyms_symbolication() {
+        let nm = "ffffffff81000000 0000000000000040 T vx_schedule\nffffffff81000040 0000000000000080 T vx_schedule_tail\nffffffff81000100 D vx_runqueue\nffffffff810000c0 t vx_idle\n";
+        let mut table = SymbolTable::from_nm(nm).unwrap();
+        assert_eq!(table.len(), 3);
+        assert!(SymbolTable::from_nm("ffffffff81000000 T").is_err());
+
+        // Shared prefixes are stored once
+        let encoded = table.encode();
+        assert!(encoded.len() < 8 + nm.len() / 3);
+        assert_eq!(SymbolTable::decode(&encoded).unwrap(), table);
+        assert!(SymbolTable::decode(&encoded[..encoded.len() - 2]).is_err());
+        let mut image = vec![0x90u8; 256];
+        table.embed(&mut image);
+        assert_eq!(SymbolTable::from_image(&image).unwrap(), table);
+        assert_eq!(SymbolTable::from_image(&image[..256]), Err("Kernel image has no symbol table"));
+
+        assert_eq!(table.symbolize(0xFFFF_FFFF_8100_001C).unwrap(), "vx_schedule+0x1c/0x40");
+        assert_eq!(table.symbolize(0xFFFF_FFFF_8100_0040).unwrap(), "vx_schedule_tail+0x0/0x80");
+        // Unsized, so up to the end
+        assert_eq!(table.symbolize(0xFFFF_FFFF_8100_1000).unwrap(), "vx_idle+0xf40");
+        assert_eq!(table.symbolize(0xFFFF_FFFF_80FF_FFFF), None);
+        table.relocate(0x20_0000);
+        assert_eq!(table.lookup(0xFFFF_FFFF_8120_0044).map(|(symbol, offset)| (symbol.name.as_str(), offset)), Some(("vx_schedule_tail", 4)));
+        assert_eq!(table.address_of("vx_idle"), Some(0xFFFF_FFFF_8120_00C0));
+
+        // Module code goes by module name
+        crashdump::register_module("kallsyms_test", 0xFFFF_FFFF_C100_0000, 0x1000);
+        assert_eq!(kallsyms::symbolize(0xFFFF_FFFF_C100_0010), "[kallsyms_test]+0x10");
+        assert_eq!(kallsyms::symbolize(0x1234), "0x1234");
+
+        // Return addresses of real code resolve once its symbols are in
+        let address = innermost as *const () as u64;
+        let table = SymbolTable::new(vec![Symbol { address, size: 0x400, name: "innermost".to_string() }]);
+        let mut image = Vec::new();
+        table.embed(&mut image);
+        vxboot::load_kernel_symbols(&image, 0).unwrap();
+        assert!(kallsyms::installed() && kallsyms::install(table).is_err());
+        assert!(innermost().iter().any(|frame| kallsyms::symbolize(*frame).starts_with("innermost+0x")));
+        crashdump::unregister_module("kallsyms_test");
+    }
+
+    #[test]
+    pub fn test_fault_injection() {
+        assert_eq!(FaultConfig::parse("3,100,2").unwrap(), FaultConfig { interval: 3, times: Some(2), ..FaultConfig::always() });
+        assert_eq!(FaultConfig::parse("1,50,-1,250").unwrap().delay, std::time::Duration::from_micros(250));
+        assert!(FaultConfig::parse("1,101").is_err() && FaultConfig::parse("x,1").is_err() && FaultConfig::parse("1").is_err());
+
+        // Every third call, twice
+        let point = FaultPoint::new("fail_test");
+        assert!(!point.should_fail());
+        point.configure(FaultConfig::parse("3,100,2").unwrap());
+        let failed: Vec<u64> = (1..=9).filter(|_| point.should_fail()).collect();
+        assert_eq!(failed.len(), 2);
+        assert_eq!((point.stats(), point.config().unwrap().times), ((9, 2), Some(0)));
+        faultinject::set_seed(7);
+        point.configure(FaultConfig { probability: 25, ..FaultConfig::always() });
+        let failed = (0..4000).filter(|_| point.should_fail()).count();
+        assert!((800..1200).contains(&failed), "{} of 4000 failed", failed);
+        let mut data = [0u8; 64];
+        point.configure(FaultConfig::always());
+        assert!(point.corrupt(&mut data));
+        assert_eq!(data.iter().map(|byte| byte.count_ones()).sum::<u32>(), 1);
+        point.disable();
+        assert!(!point.corrupt(&mut data) && point.config().is_none());
+
+        // Task-filtered points only fire for code run under inject
+        point.configure(FaultConfig { delay: std::time::Duration::from_millis(2), task_filter: true, ..FaultConfig::always() });
+        assert!(!point.delay());
+        let start = std::time::Instant::now();
+        assert!(faultinject::inject(|| point.delay()));
+        assert!(start.elapsed() >= std::time::Duration::from_millis(2));
+
+        // Every second allocation fails once, and try_reserve sees it
+        FAIL_ALLOC.configure(FaultConfig { interval: 2, times: Some(1), task_filter: true, ..FaultConfig::always() });
+        let (first, second, third) = faultinject::inject(|| {
+            let (mut a, mut b, mut c) = (Vec::<u8>::new(), Vec::<u8>::new(), Vec::<u8>::new());
+            (a.try_reserve(4096).is_ok(), b.try_reserve(4096).is_err(), c.try_reserve(4096).is_ok())
+        });
+        FAIL_ALLOC.disable();
+        assert!(first && second && third);
+        assert_eq!(FAIL_ALLOC.stats(), (3, 1));
+
+        // Filesystem errors
+        let root = std::env::temp_dir().join(format!("vaelix-faults-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let path = root.join("data");
+        let path = path.to_str().unwrap();
+        let mut vxfs = vaelix_core::vxfs::vxfs::VXFS::new();
+        FAIL_IO.configure(FaultConfig { times: Some(1), task_filter: true, ..FaultConfig::always() });
+        let error = faultinject::inject(|| vxfs.write_file(path, "lost")).unwrap_err();
+        assert_eq!(error.to_string(), "Injected I/O error");
+        assert!(!root.join("data").exists());
+        faultinject::inject(|| vxfs.write_file(path, "kept")).unwrap();
+        assert!(faultinject::report().contains("fail_io 1 100% 0 2 1\n"));
+        FAIL_IO.disable();
+
+        // From the command line, as the boot sets them up
+        let command_line = CommandLine::parse("fail_irq_delay=1,100,-1,0 quiet").unwrap();
+        assert_eq!(faultinject::configure_from_command_line(&command_line), Ok(1));
+        assert_eq!(faultinject::point("fail_irq_delay").unwrap().config(), Some(FaultConfig::always()));
+        assert!(FAIL_IRQ_DELAY.delay());
+        assert!(faultinject::configure_from_command_line(&CommandLine::parse("fail_dma=1").unwrap()).is_err());
+        FAIL_IRQ_DELAY.disable();
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    pub fn test_watchdog_lockups() {
+        let start = std::time::Instant::now();
+        let at = |seconds: u64| start + std::time::Duration::from_secs(seconds);
+        let config = WatchdogConfig { irq_storm: 1000, ..WatchdogConfig::default() };
+        let watchdog = Watchdog::new(config, 2, start);
+        watchdog.switch_to(0, Some("vxinit"), at(0));
+
+        // CPU 1 keeps scheduling its watchdog task and switching tasks
+        for seconds in (4..=40).step_by(4) {
+            watchdog.touch(1, at(seconds));
+            watchdog.switch_to(1, Some(if seconds % 8 == 0 { "vxsh" } else { "kworker" }), at(seconds));
+            assert!(sync::hardirq(|| watchdog.timer_interrupt(1, at(seconds))).is_empty());
+        }
+
+        // CPU 0 spins in vxinit with a lock held and never schedules
+        let lock = SpinLock::new("watchdog.test_table", ());
+        let guard = lock.lock();
+        assert!(sync::hardirq(|| watchdog.timer_interrupt(0, at(10))).is_empty());
+        let lockups = sync::hardirq(|| watchdog.timer_interrupt(0, at(20)));
+        assert_eq!(lockups.len(), 1);
+        assert_eq!((lockups[0].cpu, lockups[0].kind), (0, LockupKind::SoftLockup));
+        let report = lockups[0].report();
+        assert!(report.starts_with("watchdog: BUG: soft lockup - CPU#0 stuck for 20s!\ntask: vxinit\nlocks held: watchdog.test_table\nbacktrace:\n    "), "{}", report);
+        assert!(!lockups[0].backtrace.is_empty());
+        let lockups = sync::hardirq(|| watchdog.timer_interrupt(0, at(21)));
+        assert_eq!(lockups.iter().map(|lockup| lockup.kind).collect::<Vec<_>>(), [LockupKind::Stall]);
+        assert!(lockups[0].headline().contains("no context switch for 21s"));
+        // Once each until the CPU recovers
+        assert!(sync::hardirq(|| watchdog.timer_interrupt(0, at(30))).is_empty());
+        drop(guard);
+        watchdog.touch(0, at(31));
+        watchdog.switch_to(0, None, at(31));
+        assert!(sync::hardirq(|| watchdog.timer_interrupt(0, at(50))).is_empty());
+        let lockups = sync::hardirq(|| watchdog.timer_interrupt(0, at(51)));
+        assert_eq!(lockups.iter().map(|lockup| lockup.kind).collect::<Vec<_>>(), [LockupKind::SoftLockup]);
+        assert!(lockups[0].report().contains("task: idle\nlocks held: none\n"));
+
+        // An interrupt storm over a one-second window
+        for _ in 0..5000 {
+            sync::hardirq(|| watchdog.interrupt(1));
+        }
+        let lockups = sync::hardirq(|| watchdog.timer_interrupt(1, at(41)));
+        assert_eq!(lockups.iter().map(|lockup| lockup.kind).collect::<Vec<_>>(), [LockupKind::IrqStorm { rate: 5000 }]);
+        assert_eq!(lockups[0].headline(), "watchdog: interrupt storm on CPU#1: 5000 a second");
+        for _ in 0..10 {
+            watchdog.interrupt(1);
+        }
+        watchdog.touch(1, at(42));
+        watchdog.switch_to(1, Some("vxsh"), at(42));
+        assert!(sync::hardirq(|| watchdog.timer_interrupt(1, at(42))).is_empty());
+
+        let config = |text: &str| WatchdogConfig::from_command_line(&CommandLine::parse(text).unwrap());
+        let configured = config("watchdog_thresh=5 softlockup_panic=1 watchdog_irq_storm=20000").unwrap().unwrap();
+        assert_eq!((configured.soft_lockup.as_secs(), configured.stall.as_secs(), configured.irq_storm, configured.panic), (10, 11, 20000, true));
+        assert_eq!(config("").unwrap(), Some(WatchdogConfig::default()));
+        assert_eq!(config("nowatchdog").unwrap(), None);
+        assert_eq!(config("watchdog_thresh=0").unwrap(), None);
+        assert!(config("watchdog_thresh=soon").is_err());
+
+        // The per-core tasks keep a healthy CPU touched
+        let watchdog = Arc::new(Watchdog::new(WatchdogConfig::default(), 2, std::time::Instant::now() - std::time::Duration::from_secs(60)));
+        let tasks = watchdog.start().unwrap();
+        assert_eq!(tasks.iter().map(|task| task.name()).collect::<Vec<_>>(), ["watchdog/0", "watchdog/1"]);
+        let mut touched = false;
+        for _ in 0..100 {
+            if sync::hardirq(|| watchdog.timer_interrupt(1, std::time::Instant::now())).is_empty() {
+                touched = true;
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(touched);
+    }
+
+    #[test]
+    pub fn test_metrics_registry() {
+        let registry = Registry::new();
+        let requests = registry.counter("vxchan_messages_total", "Messages sent.", &[("channel", "vxlog")]).unwrap();
+        let other = registry.counter("vxchan_messages_total", "Messages sent.", &[("channel", "vxinit")]).unwrap();
+        let queued = registry.gauge("vx_tasklet_queued", "Tasklets waiting to run.", &[]).unwrap();
+        let latency = registry.histogram("vxfs_read_microseconds", "Time to read a file.", &[], &metrics::exponential_buckets(10, 10, 3)).unwrap();
+        requests.add(3);
+        other.inc();
+        queued.set(5);
+        queued.sub(2);
+        for value in [5, 10, 50, 999, 5000] {
+            latency.observe(value);
+        }
+        registry
+            .collect("vaelix_block_errors_total", "Errors by device.\nReset on reboot.", MetricType::Counter, || {
+                vec![Sample::new(2.0).with("device", "mmcblk0"), Sample::new(0.5).with("device", "sd\"a\\")]
+            })
+            .unwrap();
+        assert_eq!(
+            registry.render(),
+            "# HELP vaelix_block_errors_total Errors by device.\\nReset on reboot.\n\
+             # TYPE vaelix_block_errors_total counter\n\
+             vaelix_block_errors_total{device=\"mmcblk0\"} 2\n\
+             vaelix_block_errors_total{device=\"sd\\\"a\\\\\"} 0.5\n\
+             # HELP vx_tasklet_queued Tasklets waiting to run.\n\
+             # TYPE vx_tasklet_queued gauge\n\
+             vx_tasklet_queued 3\n\
+             # HELP vxchan_messages_total Messages sent.\n\
+             # TYPE vxchan_messages_total counter\n\
+             vxchan_messages_total{channel=\"vxlog\"} 3\n\
+             vxchan_messages_total{channel=\"vxinit\"} 1\n\
+             # HELP vxfs_read_microseconds Time to read a file.\n\
+             # TYPE vxfs_read_microseconds histogram\n\
+             vxfs_read_microseconds_bucket{le=\"10\"} 2\n\
+             vxfs_read_microseconds_bucket{le=\"100\"} 3\n\
+             vxfs_read_microseconds_bucket{le=\"1000\"} 4\n\
+             vxfs_read_microseconds_bucket{le=\"+Inf\"} 5\n\
+             vxfs_read_microseconds_sum 6064\n\
+             vxfs_read_microseconds_count 5\n"
+        );
+
+        assert_eq!(registry.counter("vxchan_messages_total", "Messages sent.", &[("channel", "vxlog")]).err(), Some("Metric already registered"));
+        assert_eq!(registry.gauge("vxchan_messages_total", "Messages sent.", &[]).err(), Some("Metric registered with another type"));
+        assert_eq!(registry.counter("2fast", "", &[]).err(), Some("Invalid metric name"));
+        assert_eq!(registry.counter("vx_ok", "", &[("le", "1")]).err(), Some("Invalid metric label"));
+        assert!(registry.collect("vx_histogram", "", MetricType::Histogram, Vec::new).is_err());
+        registry.unregister("vxchan_messages_total").unwrap();
+        assert_eq!(registry.names(), ["vaelix_block_errors_total", "vx_tasklet_queued", "vxfs_read_microseconds"]);
+        assert!(registry.unregister("vxchan_messages_total").is_err());
+
+        // The kernel's registry is what /proc/metrics shows
+        let warnings = metrics::registry().counter("test_metrics_proc_total", "Counted by the metrics test.", &[]).unwrap();
+        warnings.add(7);
+        let memory = PhysicalMemory::new(64);
+        let table = ProcessTable::new(&memory, std::env::temp_dir().to_str().unwrap(), &VXChanManager::new()).unwrap();
+        let exported = procfs::read(&table, KERNEL_PID, "/proc/metrics").unwrap();
+        assert!(exported.contains("# TYPE test_metrics_proc_total counter\ntest_metrics_proc_total 7\n"), "{}", exported);
+        let kernel = Registry::new();
+        metrics::register_kernel_metrics(&kernel).unwrap();
+        faultinject::register_metrics(&kernel).unwrap();
+        let exported = kernel.render();
+        assert!(exported.contains("# TYPE vaelix_uptime_seconds gauge\nvaelix_uptime_seconds "));
+        assert!(exported.contains("# TYPE vaelix_kernel_warnings_total counter\n"));
+        assert!(exported.contains("# TYPE vaelix_fault_injected_total counter\n"));
+    }
+
+    // /proc as canned files
+    struct CannedProc(RefCell<BTreeMap<String, String>>);
+
+    impl ProcSource for CannedProc {
+        fn read(&self, path: &str) -> Result<String, &'static str> {
+            self.0.borrow().get(path).cloned().ok_or("File not found")
+        }
+
+        fn list(&self, path: &str) -> Result<Vec<String>, &'static str> {
+            let prefix = format!("{}/", path);
+            let mut entries: Vec<String> = self.0.borrow().keys().filter_map(|file| file.strip_prefix(&prefix)).map(|rest| rest.split('/').next().unwrap().to_string()).collect();
+            entries.dedup();
+            Ok(entries)
+        }
+    }
+
+    #[test]
+    pub fn test_vxtop() {
+        let status = |pid: u32, name: &str, state: &str, rss: u64| format!("Name:\t{}\nState:\t{}\nPid:\t{}\nPPid:\t0\nUid:\t1000\nGid:\t1000\nThreads:\t2\nFDSize:\t3\nVmSize:\t{} kB\nVmPeak:\t{} kB\nVmRSS:\t{} kB\nSeccomp:\t0\n", name, state, pid, rss * 4, rss * 4, rss);
+        let usage = |micros: u64| format!("UserTime:\t{} us\nSystemTime:\t0 us\nSyscalls:\t7\nFilesOpened:\t1\n", micros);
+        let metrics = |rx: u64| format!("# TYPE vaelix_net_receive_bytes_total counter\nvaelix_net_receive_bytes_total{{interface=\"eth0\"}} {}\nvaelix_thermal_zone_celsius{{zone=\"cpu\"}} 45.5\nvaelix_uptime_seconds 3725.2\n", rx);
+        let source = CannedProc(RefCell::new(BTreeMap::from([
+            ("/proc/1/status".to_string(), status(1, "vxinit", "S (sleeping)", 100)),
+            ("/proc/1/usage".to_string(), usage(0)),
+            ("/proc/1/limits".to_string(), "Limit                     Soft Limit           Hard Limit           Units\n".to_string()),
+            ("/proc/2/status".to_string(), status(2, "vxsh", "R (running)", 300)),
+            ("/proc/2/usage".to_string(), usage(0)),
+            ("/proc/meminfo".to_string(), "MemTotal:\t2048 kB\nMemFree:\t1024 kB\nMemUsed:\t1024 kB\n".to_string()),
+            ("/proc/metrics".to_string(), metrics(0)),
+        ])));
+
+        let mut top = Top::new();
+        assert_eq!(top.render(80, 24), ["vxtop - reading /proc"]);
+        top.refresh(&source, std::time::Duration::from_secs(10)).unwrap();
+        // A second later vxinit has used half a second and eth0 taken 2 KiB
+        source.0.borrow_mut().insert("/proc/1/usage".to_string(), usage(500_000));
+        source.0.borrow_mut().insert("/proc/metrics".to_string(), metrics(2048));
+        top.refresh(&source, std::time::Duration::from_secs(11)).unwrap();
+        assert_eq!(top.cpu_percent(1), 50.0);
+        assert_eq!(top.interface_rate("eth0"), (2048.0, 0.0));
+        let frame = top.render(80, 24);
+        assert_eq!(frame[0], "vxtop - up 1:02:05, 2 tasks, 1 running, sorted by %CPU");
+        assert_eq!(frame[1], "Mem: 2048 kB total, 1024 kB used, 1024 kB free");
+        assert_eq!(frame[2], "Net: eth0 rx 2.0 KiB/s tx 0 B/s");
+        assert_eq!(frame[3], "Thermal: cpu 45.5C");
+        assert!(frame[6].starts_with(">     1  1000 S    50.0      100   0:00.50   2 vxinit"), "{:?}", frame);
+        assert!(frame[7].starts_with("      2  1000 R     0.0      300"));
+        assert!(top.render(20, 3).iter().all(|line| line.chars().count() <= 20) && top.render(20, 3).len() == 3);
+
+        // Sorting, and moving the selection with the arrow keys
+        for key in vxtop::parse_keys(b"M\x1b[B") {
+            top.handle_key(key);
+        }
+        assert_eq!(top.sort(), SortKey::Memory);
+        assert_eq!(top.tasks().iter().map(|task| task.pid).collect::<Vec<_>>(), [2, 1]);
+        assert_eq!(top.selected(), Some(1));
+        top.handle_key(Key::Char('r'));
+        assert_eq!(top.tasks()[0].pid, 1);
+        top.handle_key(Key::Up);
+        assert_eq!(top.selected(), Some(1));
+
+        // The detail view, back out of it and quitting
+        assert_eq!(vxtop::parse_keys(b"\r\x1b\x1b[5~q"), [Key::Enter, Key::Escape, Key::Char('q')]);
+        top.handle_key(Key::Enter);
+        top.refresh(&source, std::time::Duration::from_secs(12)).unwrap();
+        let detail = top.render(80, 24);
+        assert_eq!(detail[0], "vxtop - process 1 (vxinit)");
+        assert!(detail.contains(&"Memory: 100 kB resident, 400 kB reserved".to_string()));
+        assert!(detail.last().unwrap().starts_with("Limit"));
+        top.handle_key(Key::Escape);
+        assert_eq!(top.detail(), None);
+
+        // On a terminal, in raw mode until q
+        let (master, slave) = pty::open(PtySize { rows: 24, cols: 80 });
+        assert_eq!(top.frame(&slave, &source, std::time::Duration::from_secs(13)), Ok(true));
+        assert!(!slave.mode().canonical && !slave.mode().echo);
+        assert!(String::from_utf8(master.read()).unwrap().starts_with(&format!("{}vxtop - up", vxtop::CLEAR)));
+        master.write(b"N").unwrap();
+        assert_eq!(top.frame(&slave, &source, std::time::Duration::from_secs(14)), Ok(true));
+        assert_eq!(top.sort(), SortKey::Name);
+        master.write(b"q").unwrap();
+        assert_eq!(top.frame(&slave, &source, std::time::Duration::from_secs(15)), Ok(false));
+        assert_eq!(slave.mode(), LineMode::default());
+
+        // The kernel's own /proc has all of it
+        let memory = PhysicalMemory::new(256);
+        let table = ProcessTable::new(&memory, "/", &VXChanManager::new()).unwrap();
+        assert_eq!(procfs::list(&table, KERNEL_PID, "/proc"), Ok(vec!["config".to_string(), "interrupts".to_string(), "iomem".to_string(), "ioports".to_string(), "meminfo".to_string(), "metrics".to_string(), "self".to_string()]));
+        assert_eq!(procfs::list(&table, KERNEL_PID, "/proc/meminfo"), Err("Not a directory"));
+        let meminfo = procfs::read(&table, KERNEL_PID, "/proc/meminfo").unwrap();
+        assert!(meminfo.starts_with("MemTotal:\t1024 kB\n"), "{}", meminfo);
+        let snapshot = vxtop::Snapshot::read(&Procfs { table: &table, reader: KERNEL_PID }, std::time::Duration::ZERO).unwrap();
+        assert_eq!(snapshot.memory.unwrap().total, 1024);
+        assert!(snapshot.tasks.is_empty());
+    }
+
+    #[test]
+    pub fn test_latency_tracer() {
+        let tracer: &'static LatencyTracer = Box::leak(Box::new(LatencyTracer::new()));
+        let start = std::time::Instant::now();
+        let at = |micros: u64| start + std::time::Duration::from_micros(micros);
+        let us = std::time::Duration::from_micros;
+        let command_line = CommandLine::parse("latency_trace=0x30:500,49").unwrap();
+        assert_eq!(tracer.configure_from_command_line(&command_line), Ok(2));
+        assert_eq!(tracer.traced(), [0x30, 0x31]);
+        assert_eq!(tracer.configure_from_command_line(&CommandLine::parse("latency_trace=0x300").unwrap()), Err("Invalid interrupt vector"));
+        tracer.untrace(0x31);
+
+        // An audio interrupt a millisecond, waking the mixer 10us in and
+        // the mixer running 100us to 199us in; the last one runs late
+        for period in 0..100 {
+            let base = period * 1000;
+            tracer.irq_entry(0x30, at(base));
+            tracer.wakeup(7, at(base + 10));
+            tracer.irq_exit(0x30, at(base + 20));
+            tracer.run(7, at(base + 100 + period));
+        }
+        tracer.irq_entry(0x30, at(200_000));
+        sync::hardirq(|| tracer.wakeup(7, at(200_010)));
+        tracer.irq_exit(0x30, at(200_020));
+        tracer.run(7, at(200_900));
+
+        let handler = tracer.stats(0x30, Stage::Handler).unwrap();
+        assert_eq!((handler.count, handler.min, handler.max), (101, us(20), us(20)));
+        let total = tracer.stats(0x30, Stage::Total).unwrap();
+        assert_eq!((total.count, total.min, total.p50, total.p90, total.p99, total.max), (101, us(100), us(150), us(190), us(199), us(900)));
+        let schedule = tracer.stats(0x30, Stage::Schedule).unwrap();
+        assert_eq!((schedule.min, schedule.max), (us(90), us(890)));
+        assert_eq!(tracer.misses(0x30), Some(1));
+
+        // Wakeups outside a traced handler, and untraced vectors, are not
+        // counted; nor is a task run again without a new wakeup
+        tracer.wakeup(8, at(300_000));
+        tracer.run(8, at(300_100));
+        tracer.run(7, at(300_200));
+        tracer.irq_entry(0x31, at(300_300));
+        tracer.wakeup(9, at(300_310));
+        tracer.irq_exit(0x31, at(300_320));
+        tracer.run(9, at(300_400));
+        assert_eq!(tracer.stats(0x30, Stage::Total).unwrap().count, 101);
+        assert_eq!(tracer.stats(0x31, Stage::Total), None);
+        let mut ran = 0;
+        tracer.irq(0x31, || ran += 1);
+        assert_eq!(ran, 1);
+
+        let report = tracer.report();
+        assert!(report.lines().any(|line| line.split_whitespace().collect::<Vec<_>>() == ["0x30", "total", "101", "100", "150", "190", "199", "900"]), "{}", report);
+        assert!(report.contains("0x30    deadline 500us, missed 1 times\n"));
+        let registry = Registry::new();
+        tracer.register_metrics(&registry).unwrap();
+        let exported = registry.render();
+        assert!(exported.contains("vaelix_irq_latency_max_seconds{vector=\"48\",stage=\"total\"} 0.0009\n"), "{}", exported);
+        assert!(exported.contains("vaelix_irq_deadline_misses_total{vector=\"48\"} 1\n"));
+    }
+
+    #[test]
+    pub fn test_kernel_config_profiles() {
+        // Every profile shipped has to pass its own validation
+        for profile in Profile::ALL {
+            let config = KernelConfig::for_profile(profile);
+            assert_eq!(config.validate(), Ok(()), "{}", profile.name());
+            assert_eq!(Profile::parse(profile.name()), Ok(profile));
+        }
+        assert_eq!(kconfig::PROFILE, Profile::Generic);
+        assert_eq!(kconfig::ACTIVE, KernelConfig::for_profile(Profile::Generic));
+        assert_eq!(policy_manager().current_mode(), kconfig::ACTIVE.power_mode);
+        let laptop = KernelConfig::for_profile(Profile::Laptop);
+        assert!(laptop.has(Driver::Tpm) && !laptop.serial_console && laptop.watchdog);
+        assert_eq!(laptop.drivers.iter().collect::<Vec<_>>(), [Driver::Tpm]);
+        let qemu = KernelConfig::for_profile(Profile::Qemu);
+        assert!(qemu.serial_console && !qemu.watchdog);
+        assert_eq!(qemu.drivers.iter().count(), Driver::ALL.len());
+
+        // A missing dependency is refused
+        let mut broken = KernelConfig::for_profile(Profile::Qemu);
+        broken.disable(Driver::Uart);
+        assert_eq!(broken.validate(), Err("serial_console depends on uart"));
+        broken.serial_console = false;
+        assert_eq!(broken.validate(), Ok(()));
+        broken.enable(Driver::Uart);
+        assert!(broken.has(Driver::Uart));
+
+        // Readable back as a .config
+        let rendered = qemu.render();
+        assert!(rendered.starts_with("CONFIG_PROFILE=\"qemu\"\nCONFIG_DRIVER_TPM=y\nCONFIG_DRIVER_UART=y\n"), "{}", rendered);
+        assert!(rendered.ends_with("CONFIG_POWER_MODE=\"performance\"\nCONFIG_SERIAL_CONSOLE=y\n# CONFIG_WATCHDOG is not set\n# CONFIG_HARDENING is not set\n"), "{}", rendered);
+        let memory = PhysicalMemory::new(16);
+        let table = ProcessTable::new(&memory, "/", &VXChanManager::new()).unwrap();
+        assert_eq!(procfs::read(&table, KERNEL_PID, "/proc/config"), Ok(kconfig::ACTIVE.render()));
+    }
+
+    define_per_cpu!(static RUN_QUEUE: SpinLock<Vec<u32>> = SpinLock::new("test.run_queue", Vec::new()));
+
+    #[test]
+    pub fn test_per_cpu_variables() {
+        // CPUs 40 to 43, out of the way of tests running as the boot CPU
+        let msr = MsrSpace::new(48);
+        let threads: Vec<_> = (40..44)
+            .map(|cpu| {
+                let msr = msr.clone();
+                std::thread::spawn(move || {
+                    percpu::init_cpu(&msr, cpu).unwrap();
+                    assert_eq!(percpu::this_cpu(), cpu);
+                    this_cpu!(RUN_QUEUE).lock().push(cpu as u32 * 10);
+                    for tid in [cpu as u32, cpu as u32, cpu as u32 + 1] {
+                        percpu::switch_to(tid);
+                    }
+                    percpu::current()
+                })
+            })
+            .collect();
+        let current: Vec<u32> = threads.into_iter().map(|thread| thread.join().unwrap()).collect();
+        assert_eq!(current, [41, 42, 43, 44]);
+
+        // Each CPU's copy is its own, and GS points at its area
+        for cpu in 40..44 {
+            assert_eq!(*this_cpu!(RUN_QUEUE, cpu).unwrap().lock(), [cpu as u32 * 10]);
+            assert_eq!(this_cpu!(percpu::STATS, cpu).unwrap().context_switches.get(), 2);
+            assert_eq!(msr.rdmsr(cpu, IA32_GS_BASE), Ok(percpu::area(cpu)));
+            assert_eq!(msr.rdmsr(cpu, IA32_KERNEL_GS_BASE), Ok(0));
+        }
+        assert!(this_cpu!(RUN_QUEUE, 39).unwrap().lock().is_empty());
+        assert_eq!(RUN_QUEUE.iter().filter(|(_, queue)| !queue.lock().is_empty()).count(), 4);
+        assert_eq!(RUN_QUEUE.name(), "RUN_QUEUE");
+        assert!(this_cpu!(RUN_QUEUE, percpu::MAX_CPUS).is_none());
+        assert_eq!(percpu::init_cpu(&msr, percpu::MAX_CPUS), Err("CPU out of range"));
+        // A thread that never entered a CPU is the boot CPU
+        assert_eq!(std::thread::spawn(percpu::this_cpu).join().unwrap(), 0);
+
+        let local: PerCpu<std::sync::atomic::AtomicU64> = PerCpu::new("local", || std::sync::atomic::AtomicU64::new(7));
+        assert_eq!(local.get().load(std::sync::atomic::Ordering::Relaxed), 7);
+
+        let registry = Registry::new();
+        percpu::register_metrics(&registry).unwrap();
+        let exported = registry.render();
+        assert!(exported.contains("vaelix_cpu_context_switches_total{cpu=\"42\"} 2\n"), "{}", exported);
+        assert!(!exported.contains("cpu=\"39\""));
+    }
 }
@@ -0,0 +1,2006 @@
+#[cfg(test)]
+pub mod tests {
+    use std::collections::{BTreeMap, VecDeque};
+    use std::future::Future;
+    use std::io;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll, Wake, Waker};
+    use ed25519_dalek::SigningKey;
+    use vaelix_core::drivers::block::block::BlockDevice;
+    use vaelix_core::drivers::uart::uart::SerialPort;
+    use vaelix_core::faultinject::faultinject::{self, FaultConfig, FAIL_PACKET};
+    use vaelix_core::keyring::keyring::Secret;
+    use vaelix_core::ktest::ktest::{self, Outcome, TestCase, QEMU_EXIT_FAILURE, QEMU_EXIT_SUCCESS};
+    use vaelix_core::metrics::metrics::Registry;
+    use vaelix_core::power::wake::wake::WakeOnLan;
+    use vaelix_core::vxboot::vxboot::{BootControl, CommandLine, Slot};
+    use vaelix_core::vxchan::vxchan::VXChanManager;
+    use vaelix_networking::bridge::bridge::{Bridge, PortRole, PortState, FORWARD_DELAY_MS};
+    use vaelix_networking::fib::fib::{Fib, RoutingRule, TABLE_MAIN};
+    use vaelix_networking::http::http::{HttpClient, ResponseParser, TransferStatus, Url};
+    use vaelix_networking::neighbor::neighbor::{NeighborConfig, NeighborState};
+    use vaelix_networking::netdev::netdev::{MacAddress, NetDevice, QueueDevice};
+    use vaelix_networking::exporter::exporter::{self, MetricsExporter, CONTENT_TYPE, METRICS_PORT};
+    use vaelix_networking::dhcp::dhcp::{
+        build_dhcp, parse_dhcp, DhcpEvent, DhcpService, DhcpState, DHCPACK, DHCPOFFER, DHCPREQUEST,
+    };
+    use vaelix_networking::packet::packet::{
+        build_ethernet, build_ipv4, parse_ethernet, parse_ipv4, EthernetHeader, Ipv4Header, Ipv6Header, ETHERTYPE_IPV4,
+        ETHERTYPE_IPV6, ETHERTYPE_WAKE_ON_LAN, IPV4_HEADER_LEN, IP_PROTO_ICMP, IP_PROTO_TCP, IP_PROTO_UDP,
+    };
+    use vaelix_networking::pktbuf::pktbuf::{PacketBuffer, PacketSlice, DEFAULT_HEADROOM};
+    use vaelix_networking::qdisc::qdisc::{FqCodel, Prio, Qdisc, TokenBucket, DEFAULT_QUEUE_LIMIT};
+    use vaelix_networking::socket::socket::{AsyncSocket, Readiness, SocketKind, SocketSet};
+    use vaelix_networking::tcp::tcp::{
+        build_segment, parse_segment, CongestionAlgorithm, Endpoint, TcpSegment, TcpSocketHandle, TcpStack, TcpState,
+    };
+    use vaelix_networking::udp::udp::{build_udp, parse_udp, UdpHeader, UdpStack};
+    use vaelix_networking::vxnet_core::vxnet_core::{
+        fragment_ipv4, fragment_ipv6, Datagram, Direction, Hook, InterfaceId, IpCidr, LinkEvent, NetStack, Route, RouteProtocol, SocketOwner,
+        Verdict,
+    };
+    use vaelix_networking::vxcap::vxcap::{CaptureFilter, PacketCapture};
+    use vaelix_networking::vxdiag::vxdiag::{self, DiagService};
+    use vaelix_networking::vxnetctl::vxnetctl::{parse_reply, send_request, NetCtlService, REPLY_CHANNEL};
+    use vaelix_networking::vxupdate::vxupdate::{build_bundle, BundleHeader, UpdateService, UpdateStatus, Version};
+    use vaelix_networking::vxvpn::vxvpn::{PeerConfig, WgKey, WgTunnel, WireGuard, DEFAULT_PORT};
+    use vaelix_networking::vxwall::vxwall::{parse_nat_rule, parse_rule, ConnState, Firewall};
+
+    type TxQueue = Arc<Mutex<VecDeque<Vec<u8>>>>;
+
+    fn host(mac: u8, addresses: &[(IpAddr, u8)]) -> (NetStack, InterfaceId, TxQueue) {
+        let device = QueueDevice::new("eth0", MacAddress([0x02, 0, 0, 0, 0, mac]), 1500);
+        let queue = device.tx_queue();
+        let mut stack = NetStack::new();
+        let id = stack.add_interface(Box::new(device)).unwrap();
+        for (address, prefix_len) in addresses {
+            stack.add_address(id, IpCidr::new(*address, *prefix_len).unwrap()).unwrap();
+        }
+        (stack, id, queue)
+    }
+
+    // Moves every frame queued on one side into the other stack
+    fn pump(queue: &TxQueue, stack: &mut NetStack, id: InterfaceId) -> usize {
+        let frames: Vec<Vec<u8>> = queue.lock().unwrap().drain(..).collect();
+        for frame in &frames {
+            stack.receive(id, frame, 0).unwrap();
+        }
+        frames.len()
+    }
+
+    #[test]
+    pub fn test_ipv4_arp_and_fragmentation() {
+        let a_address = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let b_address = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let (mut a, a_id, a_queue) = host(1, &[(a_address, 24)]);
+        let (mut b, b_id, b_queue) = host(2, &[(b_address, 24)]);
+
+        let payload: Vec<u8> = (0..3000).map(|index| index as u8).collect();
+        a.send(b_address, IP_PROTO_UDP, &payload).unwrap();
+        // Only the ARP request goes out until the neighbor is resolved
+        assert_eq!(pump(&a_queue, &mut b, b_id), 1);
+        assert_eq!(pump(&b_queue, &mut a, a_id), 1);
+        assert_eq!(pump(&a_queue, &mut b, b_id), 3);
+
+        let datagram = b.take_inbound().unwrap();
+        assert_eq!(datagram.source, a_address);
+        assert_eq!(datagram.protocol, IP_PROTO_UDP);
+        assert_eq!(datagram.payload.data(), payload);
+
+        // No room for a header and eight bytes of data below the minimum
+        let header = Ipv4Header::new(Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2), IP_PROTO_UDP);
+        let buffer = PacketBuffer::from_slice(DEFAULT_HEADROOM, &payload);
+        assert!(fragment_ipv4(&header, buffer.clone(), 20).is_err());
+        assert!(fragment_ipv4(&header, buffer.clone(), 27).is_err());
+        let v6 = Ipv6Header::new(Ipv6Addr::LOCALHOST, Ipv6Addr::LOCALHOST, IP_PROTO_UDP);
+        assert_eq!(fragment_ipv6(&v6, buffer.clone(), 1000, 1), Err("MTU below the IPv6 minimum"));
+
+        // Fragments carry pushed headers ahead of slices of the payload
+        let fragments = fragment_ipv4(&header, buffer, 1500).unwrap();
+        assert_eq!(fragments.len(), 3);
+        assert!(fragments.iter().all(|fragment| fragment.linear_len() == IPV4_HEADER_LEN));
+        let (first, data) = parse_ipv4(&fragments[0].to_vec()).map(|(header, data)| (header, data.to_vec())).unwrap();
+        assert!(first.more_fragments);
+        assert_eq!(data, payload[..1480]);
+        let tiny = QueueDevice::new("slip0", MacAddress([0x02, 0, 0, 0, 0, 3]), 40);
+        assert_eq!(b.add_interface(Box::new(tiny)), Err("Device MTU below the IPv4 minimum"));
+
+        // Fragments of more packets than are held at once: the oldest goes
+        let ethernet = EthernetHeader {
+            destination: MacAddress([0x02, 0, 0, 0, 0, 2]),
+            source: MacAddress([0x02, 0, 0, 0, 0, 1]),
+            ethertype: ETHERTYPE_IPV4,
+        };
+        let fragment = |identification: u16, last: bool| {
+            let mut fragment = header;
+            fragment.identification = identification;
+            fragment.fragment_offset = if last { 8 } else { 0 };
+            fragment.more_fragments = !last;
+            build_ethernet(&ethernet, &build_ipv4(&fragment, &[0; 8]))
+        };
+        for identification in 100..165 {
+            b.receive(b_id, &fragment(identification, false), 0).unwrap();
+        }
+        b.receive(b_id, &fragment(100, true), 0).unwrap();
+        assert!(b.take_inbound().is_none());
+        b.receive(b_id, &fragment(164, true), 0).unwrap();
+        assert_eq!(b.take_inbound().unwrap().payload.data(), [0; 16]);
+
+        // A fragment repeating an offset cannot replace the bytes already held
+        let piece = |offset: usize, last: bool, fill: u8| {
+            let mut fragment = header;
+            fragment.identification = 200;
+            fragment.fragment_offset = offset;
+            fragment.more_fragments = !last;
+            build_ethernet(&ethernet, &build_ipv4(&fragment, &[fill; 8]))
+        };
+        b.receive(b_id, &piece(0, false, 1), 0).unwrap();
+        b.receive(b_id, &piece(0, false, 2), 0).unwrap();
+        b.receive(b_id, &piece(8, true, 3), 0).unwrap();
+        assert_eq!(b.take_inbound().unwrap().payload.data(), [[1; 8], [3; 8]].concat());
+
+        // IPv6 drops the whole packet on any overlap, later fragments included
+        let b6 = Ipv6Addr::new(0xFD00, 0, 0, 0, 0, 0, 0, 2);
+        b.add_address(b_id, IpCidr::new(IpAddr::V6(b6), 64).unwrap()).unwrap();
+        let v6_ethernet = EthernetHeader { ethertype: ETHERTYPE_IPV6, ..ethernet };
+        let v6 = Ipv6Header::new(Ipv6Addr::new(0xFD00, 0, 0, 0, 0, 0, 0, 1), b6, IP_PROTO_UDP);
+        let frames = |identification: u32| -> Vec<Vec<u8>> {
+            let buffer = PacketBuffer::from_slice(DEFAULT_HEADROOM, &payload);
+            let fragments = fragment_ipv6(&v6, buffer, 1500, identification).unwrap();
+            fragments.iter().map(|fragment| build_ethernet(&v6_ethernet, &fragment.to_vec())).collect()
+        };
+        let overlapped = frames(7);
+        assert_eq!(overlapped.len(), 3);
+        b.receive(b_id, &overlapped[0], 0).unwrap();
+        b.receive(b_id, &overlapped[0], 0).unwrap();
+        for frame in &overlapped {
+            b.receive(b_id, frame, 0).unwrap();
+        }
+        assert!(b.take_inbound().is_none());
+        for frame in &frames(8) {
+            b.receive(b_id, frame, 0).unwrap();
+        }
+        assert_eq!(b.take_inbound().unwrap().payload.data(), payload);
+    }
+
+    #[test]
+    pub fn test_ipv6_neighbor_discovery() {
+        let a_address = IpAddr::V6(Ipv6Addr::new(0xFE80, 0, 0, 0, 0, 0, 0, 1));
+        let b_address = IpAddr::V6(Ipv6Addr::new(0xFE80, 0, 0, 0, 0, 0, 0, 2));
+        let (mut a, a_id, a_queue) = host(1, &[(a_address, 64)]);
+        let (mut b, b_id, b_queue) = host(2, &[(b_address, 64)]);
+
+        a.send(b_address, IP_PROTO_UDP, b"hello").unwrap();
+        pump(&a_queue, &mut b, b_id);
+        pump(&b_queue, &mut a, a_id);
+        pump(&a_queue, &mut b, b_id);
+
+        assert_eq!(a.neighbor(&b_address), Some(MacAddress([0x02, 0, 0, 0, 0, 2])));
+        let datagram = b.take_inbound().unwrap();
+        assert_eq!(datagram.payload.data(), b"hello");
+    }
+
+    // Delivers queued TCP segments from one stack to the other, optionally dropping some
+    fn exchange(from: &mut TcpStack, to: &mut TcpStack, now: u64, drop: &mut dyn FnMut(usize) -> bool) -> usize {
+        let mut count = 0;
+        while let Some(outbound) = from.take_outbound() {
+            count += 1;
+            if drop(count) {
+                continue;
+            }
+            let datagram = Datagram {
+                interface: InterfaceId(0),
+                source: outbound.source,
+                destination: outbound.destination,
+                protocol: IP_PROTO_TCP,
+                ttl: 64,
+                payload: PacketBuffer::from_vec(outbound.segment),
+            };
+            to.handle_datagram(&datagram, now).unwrap();
+        }
+        count
+    }
+
+    fn tcp_pair(algorithm: CongestionAlgorithm) -> (TcpStack, TcpStack, TcpSocketHandle, TcpSocketHandle) {
+        let client_address = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let server_address = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let mut client = TcpStack::new(algorithm);
+        let mut server = TcpStack::new(algorithm);
+        server.listen(80, 4).unwrap();
+        let remote = Endpoint { address: server_address, port: 80 };
+        let handle = client.connect(client_address, remote, 0).unwrap();
+        exchange(&mut client, &mut server, 0, &mut |_| false);
+        exchange(&mut server, &mut client, 0, &mut |_| false);
+        exchange(&mut client, &mut server, 0, &mut |_| false);
+        let accepted = server.accept(80).unwrap();
+        (client, server, handle, accepted)
+    }
+
+    #[test]
+    pub fn test_tcp_handshake_transfer_and_close() {
+        let (mut client, mut server, c, s) = tcp_pair(CongestionAlgorithm::NewReno);
+        assert_eq!(client.state(c), Some(TcpState::Established));
+        assert_eq!(server.state(s), Some(TcpState::Established));
+
+        let data: Vec<u8> = (0..10_000).map(|index| (index % 251) as u8).collect();
+        assert_eq!(client.send(c, &data, 1).unwrap(), data.len());
+        let mut received = Vec::new();
+        for now in 1..20 {
+            exchange(&mut client, &mut server, now, &mut |_| false);
+            server.poll(now * 50);
+            exchange(&mut server, &mut client, now, &mut |_| false);
+            let mut buffer = [0u8; 4096];
+            let count = server.recv(s, &mut buffer, now).unwrap();
+            received.extend_from_slice(&buffer[..count]);
+        }
+        assert_eq!(received, data);
+
+        client.close(c, 2000).unwrap();
+        exchange(&mut client, &mut server, 2000, &mut |_| false);
+        assert_eq!(server.state(s), Some(TcpState::CloseWait));
+        assert!(server.is_eof(s));
+        server.close(s, 2000).unwrap();
+        exchange(&mut server, &mut client, 2000, &mut |_| false);
+        exchange(&mut client, &mut server, 2000, &mut |_| false);
+        assert_eq!(client.state(c), Some(TcpState::TimeWait));
+        assert_eq!(server.state(s), Some(TcpState::Closed));
+    }
+
+    #[test]
+    pub fn test_tcp_retransmits_lost_segment() {
+        let (mut client, mut server, c, s) = tcp_pair(CongestionAlgorithm::Cubic);
+
+        client.send(c, b"lost in transit", 10).unwrap();
+        assert_eq!(exchange(&mut client, &mut server, 10, &mut |_| true), 1);
+        assert_eq!(server.readable(s), 0);
+
+        // Nothing is resent before the initial RTO expires
+        client.poll(500);
+        assert_eq!(exchange(&mut client, &mut server, 500, &mut |_| false), 0);
+        let window = client.congestion_window(c).unwrap();
+        client.poll(1100);
+        assert_eq!(exchange(&mut client, &mut server, 1100, &mut |_| false), 1);
+        assert!(client.congestion_window(c).unwrap() < window);
+
+        let mut buffer = [0u8; 64];
+        let count = server.recv(s, &mut buffer, 1100).unwrap();
+        assert_eq!(&buffer[..count], b"lost in transit");
+    }
+
+    #[test]
+    pub fn test_tcp_syn_options_and_syn_backlog() {
+        let client_address = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let server_address = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let datagram = |source: IpAddr, destination: IpAddr, segment: &TcpSegment| Datagram {
+            interface: InterfaceId(0),
+            source,
+            destination,
+            protocol: IP_PROTO_TCP,
+            ttl: 64,
+            payload: PacketBuffer::from_vec(build_segment(source, destination, segment)),
+        };
+        let mut client = TcpStack::new(CongestionAlgorithm::NewReno);
+        let c = client.connect(client_address, Endpoint { address: server_address, port: 80 }, 0).unwrap();
+        let outbound = client.take_outbound().unwrap();
+        let syn = parse_segment(outbound.source, outbound.destination, &outbound.segment).unwrap();
+
+        // The window in a SYN-ACK is not scaled, even when it offers scaling
+        let syn_ack = TcpSegment {
+            source_port: 80,
+            destination_port: syn.source_port,
+            sequence: 5000,
+            acknowledgment: syn.sequence.wrapping_add(1),
+            flags: 0x12,
+            window: 1000,
+            mss: Some(1460),
+            window_shift: Some(7),
+            payload: Vec::new(),
+        };
+        client.handle_datagram(&datagram(server_address, client_address, &syn_ack), 0).unwrap();
+        assert_eq!(client.state(c), Some(TcpState::Established));
+        // And the congestion window is sized for the MSS agreed on
+        assert_eq!(client.congestion_window(c), Some(14600));
+        while client.take_outbound().is_some() {}
+        client.send(c, &[0; 5000], 1).unwrap();
+        let mut sent = 0;
+        while let Some(outbound) = client.take_outbound() {
+            sent += parse_segment(outbound.source, outbound.destination, &outbound.segment).unwrap().payload.len();
+        }
+        assert_eq!(sent, 1000);
+
+        // SYNs beyond the half-open limit of a listener are dropped
+        let mut server = TcpStack::new(CongestionAlgorithm::NewReno);
+        server.listen(80, 4).unwrap();
+        for port in 40000..40065 {
+            let syn = TcpSegment {
+                source_port: port,
+                destination_port: 80,
+                sequence: 1,
+                acknowledgment: 0,
+                flags: 0x02,
+                window: 1024,
+                mss: None,
+                window_shift: None,
+                payload: Vec::new(),
+            };
+            server.handle_datagram(&datagram(client_address, server_address, &syn), 0).unwrap();
+        }
+        let mut syn_acks = 0;
+        while server.take_outbound().is_some() {
+            syn_acks += 1;
+        }
+        assert_eq!(syn_acks, 64);
+    }
+
+    #[test]
+    pub fn test_tcp_out_of_order_queue_is_trimmed_and_merged() {
+        let client_address = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let server_address = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let remote = Endpoint { address: server_address, port: 80 };
+
+        // Initial sequence numbers are keyed per stack, not predictable
+        let mut other = TcpStack::new(CongestionAlgorithm::NewReno);
+        other.connect(client_address, remote, 0).unwrap();
+        let other_syn = other.take_outbound().unwrap();
+        let mut client = TcpStack::new(CongestionAlgorithm::NewReno);
+        let c = client.connect(client_address, remote, 0).unwrap();
+        let outbound = client.take_outbound().unwrap();
+        let syn = parse_segment(outbound.source, outbound.destination, &outbound.segment).unwrap();
+        assert_ne!(syn.sequence, parse_segment(other_syn.source, other_syn.destination, &other_syn.segment).unwrap().sequence);
+
+        let segment = |sequence: u32, flags: u8, payload: Vec<u8>| {
+            let segment = TcpSegment {
+                source_port: 80,
+                destination_port: syn.source_port,
+                sequence,
+                acknowledgment: syn.sequence.wrapping_add(1),
+                flags,
+                window: 1000,
+                mss: None,
+                window_shift: None,
+                payload,
+            };
+            Datagram {
+                interface: InterfaceId(0),
+                source: server_address,
+                destination: client_address,
+                protocol: IP_PROTO_TCP,
+                ttl: 64,
+                payload: PacketBuffer::from_vec(build_segment(server_address, client_address, &segment)),
+            }
+        };
+        client.handle_datagram(&segment(5000, 0x12, Vec::new()), 0).unwrap();
+        client.set_buffer_sizes(c, 4096, 4000).unwrap();
+
+        // Overlaps keep the bytes that arrived first and are stored once
+        client.handle_datagram(&segment(5101, 0x10, vec![b'B'; 100]), 0).unwrap();
+        client.handle_datagram(&segment(5151, 0x10, vec![b'C'; 100]), 0).unwrap();
+        client.handle_datagram(&segment(5101, 0x10, vec![b'X'; 100]), 0).unwrap();
+        assert_eq!(client.out_of_order_bytes(c), 150);
+        // Only what fits in the window is kept
+        client.handle_datagram(&segment(5001 + 3900, 0x10, vec![b'D'; 500]), 0).unwrap();
+        assert_eq!(client.out_of_order_bytes(c), 250);
+
+        // Filling the hole delivers across the overlap and frees what it passed
+        client.handle_datagram(&segment(5001, 0x10, vec![b'A'; 150]), 0).unwrap();
+        assert_eq!(client.out_of_order_bytes(c), 100);
+        let mut buffer = [0; 512];
+        assert_eq!(client.recv(c, &mut buffer, 0), Ok(250));
+        let expected: Vec<u8> = [vec![b'A'; 150], vec![b'B'; 50], vec![b'C'; 50]].concat();
+        assert_eq!(buffer[..250], expected[..]);
+    }
+
+    #[test]
+    pub fn test_tcp_closed_port_is_reset() {
+        let mut client = TcpStack::new(CongestionAlgorithm::NewReno);
+        let mut server = TcpStack::new(CongestionAlgorithm::NewReno);
+        let remote = Endpoint { address: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), port: 23 };
+        let handle = client.connect(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), remote, 0).unwrap();
+        exchange(&mut client, &mut server, 0, &mut |_| false);
+        exchange(&mut server, &mut client, 0, &mut |_| false);
+        assert_eq!(client.state(handle), Some(TcpState::Closed));
+        assert!(client.was_reset(handle));
+    }
+
+    #[test]
+    pub fn test_udp_multicast_and_unicast_reply() {
+        let a_address = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let b_address = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let group = IpAddr::V4(Ipv4Addr::new(224, 0, 0, 251));
+        let (mut a, a_id, a_queue) = host(1, &[(a_address, 24)]);
+        let (mut b, b_id, b_queue) = host(2, &[(b_address, 24)]);
+        let mut a_udp = UdpStack::new();
+        let mut b_udp = UdpStack::new();
+
+        let listener = b_udp.bind(None, 5353).unwrap();
+        b_udp.join_multicast(&mut b, listener, b_id, group).unwrap();
+        // The join announces itself with an IGMP membership report
+        assert_eq!(b_queue.lock().unwrap().drain(..).count(), 1);
+        assert!(b.interface(b_id).unwrap().is_member(&group));
+
+        let client = a_udp.bind(None, 0).unwrap();
+        let client_port = a_udp.local_port(client).unwrap();
+        assert!(client_port >= 49152);
+        a_udp.send_to(&mut a, client, group, 5353, b"query").unwrap();
+        assert_eq!(pump(&a_queue, &mut b, b_id), 1);
+        let datagram = b.take_inbound().unwrap();
+        assert!(b_udp.handle_datagram(&datagram).unwrap());
+        let message = b_udp.recv_from(listener).unwrap().unwrap();
+        assert_eq!(message.payload, b"query");
+        assert_eq!((message.source, message.source_port), (a_address, client_port));
+
+        b_udp.send_to(&mut b, listener, message.source, message.source_port, b"answer").unwrap();
+        pump(&b_queue, &mut a, a_id);
+        pump(&a_queue, &mut b, b_id);
+        pump(&b_queue, &mut a, a_id);
+        let datagram = a.take_inbound().unwrap();
+        assert!(a_udp.handle_datagram(&datagram).unwrap());
+        assert_eq!(a_udp.recv_from(client).unwrap().unwrap().payload, b"answer");
+
+        b_udp.close(&mut b, listener).unwrap();
+        assert!(!b.interface(b_id).unwrap().is_member(&group));
+    }
+
+    // Answers a client DHCP frame the way a server on 10.0.0.254 would
+    fn dhcp_reply(frame: &[u8], kind: u8) -> (u8, Vec<u8>) {
+        dhcp_reply_with(frame, kind, |_| {})
+    }
+
+    // As dhcp_reply, with the reply's options adjusted before it is sent
+    fn dhcp_reply_with(frame: &[u8], kind: u8, adjust: impl FnOnce(&mut BTreeMap<u8, Vec<u8>>)) -> (u8, Vec<u8>) {
+        let server = Ipv4Addr::new(10, 0, 0, 254);
+        let (_, packet) = parse_ethernet(frame).unwrap();
+        let (ip, datagram) = parse_ipv4(packet).unwrap();
+        let (_, payload) = parse_udp(IpAddr::V4(ip.source), IpAddr::V4(ip.destination), datagram).unwrap();
+        let request = parse_dhcp(payload).unwrap();
+        let request_type = request.message_type().unwrap();
+
+        let mut reply = request.clone();
+        reply.op = 2;
+        reply.yiaddr = Ipv4Addr::new(10, 0, 0, 42);
+        reply.options.clear();
+        reply.options.insert(53, vec![kind]);
+        reply.options.insert(54, server.octets().to_vec());
+        reply.options.insert(1, vec![255, 255, 255, 0]);
+        reply.options.insert(3, server.octets().to_vec());
+        reply.options.insert(6, vec![1, 1, 1, 1, 9, 9, 9, 9]);
+        reply.options.insert(51, 600u32.to_be_bytes().to_vec());
+        reply.options.insert(26, 1400u16.to_be_bytes().to_vec());
+        adjust(&mut reply.options);
+
+        let (source, destination) = (IpAddr::V4(server), IpAddr::V4(Ipv4Addr::BROADCAST));
+        let header = UdpHeader { source_port: 67, destination_port: 68 };
+        let udp = build_udp(source, destination, &header, &build_dhcp(&reply));
+        let ip = build_ipv4(&Ipv4Header::new(server, Ipv4Addr::BROADCAST, IP_PROTO_UDP), &udp);
+        let ethernet = EthernetHeader {
+            destination: MacAddress::BROADCAST,
+            source: MacAddress([0x02, 0, 0, 0, 0, 0xFE]),
+            ethertype: ETHERTYPE_IPV4,
+        };
+        (request_type, build_ethernet(&ethernet, &ip))
+    }
+
+    #[test]
+    pub fn test_dhcp_acquires_and_renews_lease() {
+        let (mut net, id, queue) = host(1, &[]);
+        let mut udp = UdpStack::new();
+        let mut dhcp = DhcpService::new(&mut udp).unwrap();
+        let deliver = |net: &mut NetStack, udp: &mut UdpStack, frame: &[u8]| {
+            net.receive(id, frame, 0).unwrap();
+            while let Some(datagram) = net.take_inbound() {
+                udp.handle_datagram(&datagram).unwrap();
+            }
+        };
+
+        net.poll_timers(0);
+        let event = net.take_link_event().unwrap();
+        assert!(event.up);
+        dhcp.handle_link_event(&mut net, event, 0).unwrap();
+
+        let discover = queue.lock().unwrap().pop_front().unwrap();
+        let (kind, offer) = dhcp_reply(&discover, DHCPOFFER);
+        assert_eq!(kind, 1);
+        deliver(&mut net, &mut udp, &offer);
+        assert!(dhcp.poll(&mut net, &mut udp, 0).unwrap().is_empty());
+        assert_eq!(dhcp.client(id).unwrap().state(), DhcpState::Requesting);
+
+        let request = queue.lock().unwrap().pop_front().unwrap();
+        let (kind, ack) = dhcp_reply(&request, DHCPACK);
+        assert_eq!(kind, DHCPREQUEST);
+        deliver(&mut net, &mut udp, &ack);
+        let events = dhcp.poll(&mut net, &mut udp, 0).unwrap();
+        let DhcpEvent::Bound(lease) = &events[0].1 else { panic!("expected a lease") };
+        assert_eq!(lease.address, Ipv4Addr::new(10, 0, 0, 42));
+        assert_eq!(lease.prefix_len, 24);
+        assert_eq!(lease.dns_servers.len(), 2);
+        assert!(net.interface(id).unwrap().has_address(&IpAddr::V4(lease.address)));
+        // The leased MTU and name servers are applied to the stack
+        assert_eq!(net.interface(id).unwrap().mtu(), 1400);
+        let dns: Vec<IpAddr> = lease.dns_servers.iter().copied().map(IpAddr::V4).collect();
+        assert_eq!(net.dns_servers(), dns);
+        let default = net.lookup_route(&IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8))).unwrap();
+        assert_eq!((default.protocol, default.metric), (RouteProtocol::Dhcp, 100));
+
+        // T1 (half of the 600 s lease) starts a unicast renewal
+        dhcp.poll(&mut net, &mut udp, 300_000).unwrap();
+        assert_eq!(dhcp.client(id).unwrap().state(), DhcpState::Renewing);
+
+        // An ack without option 26 restores the link MTU, and one with a new
+        // router moves the default route rather than adding a second
+        queue.lock().unwrap().clear();
+        dhcp.poll(&mut net, &mut udp, 525_000).unwrap();
+        assert_eq!(dhcp.client(id).unwrap().state(), DhcpState::Rebinding);
+        let rebind = queue.lock().unwrap().pop_back().unwrap();
+        let (_, ack) = dhcp_reply_with(&rebind, DHCPACK, |options| {
+            options.remove(&26);
+            options.insert(3, vec![10, 0, 0, 253]);
+        });
+        deliver(&mut net, &mut udp, &ack);
+        let events = dhcp.poll(&mut net, &mut udp, 525_000).unwrap();
+        assert!(matches!(events[0].1, DhcpEvent::Bound(_)));
+        assert_eq!(net.interface(id).unwrap().mtu(), 1500);
+        let dhcp_routes: Vec<Route> = net.routes().into_iter().filter(|route| route.protocol == RouteProtocol::Dhcp).collect();
+        assert_eq!(dhcp_routes.len(), 1);
+        assert_eq!(dhcp_routes[0].gateway, Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 253))));
+
+        // Losing the link drops the configuration
+        queue.lock().unwrap().clear();
+        let lost = dhcp
+            .handle_link_event(&mut net, LinkEvent { interface: id, up: false }, 0)
+            .unwrap();
+        assert_eq!(lost, Some(DhcpEvent::Lost));
+        assert!(net.interface(id).unwrap().addresses().is_empty());
+        assert!(net.routes().is_empty());
+        assert_eq!(net.interface(id).unwrap().mtu(), 1500);
+        assert!(net.dns_servers().is_empty());
+    }
+
+    // Exchanges frames between two socket sets until both sides go quiet
+    fn pump_sets(a: &mut SocketSet, a_queue: &TxQueue, b: &mut SocketSet, b_queue: &TxQueue, now: u64) {
+        loop {
+            let a_frames: Vec<Vec<u8>> = a_queue.lock().unwrap().drain(..).collect();
+            let b_frames: Vec<Vec<u8>> = b_queue.lock().unwrap().drain(..).collect();
+            if a_frames.is_empty() && b_frames.is_empty() {
+                return;
+            }
+            for frame in &a_frames {
+                b.net_mut().receive(InterfaceId(0), frame, now).unwrap();
+            }
+            for frame in &b_frames {
+                a.net_mut().receive(InterfaceId(0), frame, now).unwrap();
+            }
+            a.poll(now);
+            b.poll(now);
+        }
+    }
+
+    fn socket_pair() -> (SocketSet, TxQueue, SocketSet, TxQueue) {
+        let (a, _, a_queue) = host(1, &[(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 24)]);
+        let (b, _, b_queue) = host(2, &[(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), 24)]);
+        (SocketSet::new(a), a_queue, SocketSet::new(b), b_queue)
+    }
+
+    #[test]
+    pub fn test_socket_tcp_readiness_and_accounting() {
+        let (mut a, a_queue, mut b, b_queue) = socket_pair();
+        let server_address: SocketAddr = "10.0.0.2:7".parse().unwrap();
+
+        let listener = b.socket(SocketKind::Tcp).unwrap();
+        b.bind(listener, server_address).unwrap();
+        b.listen(listener, 8).unwrap();
+        assert_eq!(b.accept(listener).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+        let clash = b.socket(SocketKind::Tcp).unwrap();
+        assert_eq!(b.bind(clash, server_address).unwrap_err().kind(), io::ErrorKind::AddrInUse);
+
+        // Binding reserves the port, and connect sends from it
+        let client = a.socket(SocketKind::Tcp).unwrap();
+        a.set_buffer_sizes(client, 8192, 8192).unwrap();
+        a.bind(client, "10.0.0.1:5555".parse().unwrap()).unwrap();
+        let other = a.socket(SocketKind::Tcp).unwrap();
+        assert_eq!(a.bind(other, "0.0.0.0:5555".parse().unwrap()).unwrap_err().kind(), io::ErrorKind::AddrInUse);
+        a.connect(client, server_address).unwrap();
+        assert_eq!(a.local_addr(client).unwrap(), Some("10.0.0.1:5555".parse().unwrap()));
+        pump_sets(&mut a, &a_queue, &mut b, &b_queue, 1);
+
+        let ready = b.select(&[(listener, Readiness::READABLE)]);
+        assert_eq!(ready.len(), 1);
+        let (connection, peer) = b.accept(listener).unwrap();
+        assert_eq!(peer, "10.0.0.1:5555".parse().unwrap());
+        assert!(a.readiness(client).unwrap().contains(Readiness::WRITABLE));
+
+        assert_eq!(a.send(client, b"ping").unwrap(), 4);
+        pump_sets(&mut a, &a_queue, &mut b, &b_queue, 2);
+        let mut buffer = [0u8; 16];
+        assert_eq!(b.recv(connection, &mut buffer).unwrap(), 4);
+        assert_eq!(&buffer[..4], b"ping");
+        assert_eq!(b.recv(connection, &mut buffer).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+
+        a.close(client).unwrap();
+        pump_sets(&mut a, &a_queue, &mut b, &b_queue, 3);
+        assert!(b.readiness(connection).unwrap().contains(Readiness::HANGUP));
+        assert_eq!(b.recv(connection, &mut buffer).unwrap(), 0);
+
+        // The budget caps the total buffer space sockets may reserve
+        let (net, _, _) = host(3, &[]);
+        let mut small = SocketSet::with_memory_limit(net, 300 * 1024);
+        let first = small.socket(SocketKind::Udp).unwrap();
+        small.socket(SocketKind::Udp).unwrap();
+        assert_eq!(small.socket(SocketKind::Udp).unwrap_err().kind(), io::ErrorKind::OutOfMemory);
+        assert!(small.set_buffer_sizes(first, 1024 * 1024, 1024 * 1024).is_err());
+        small.close(first).unwrap();
+        assert!(small.socket(SocketKind::Udp).is_ok());
+    }
+
+    #[test]
+    pub fn test_socket_out_of_order_flood_is_charged_to_receive_buffer() {
+        let (mut a, a_queue, mut b, b_queue) = socket_pair();
+        let server_address: SocketAddr = "10.0.0.2:7".parse().unwrap();
+        let listener = b.socket(SocketKind::Tcp).unwrap();
+        b.bind(listener, server_address).unwrap();
+        b.listen(listener, 8).unwrap();
+        let client = a.socket(SocketKind::Tcp).unwrap();
+        a.connect(client, server_address).unwrap();
+        pump_sets(&mut a, &a_queue, &mut b, &b_queue, 1);
+        let (connection, _) = b.accept(listener).unwrap();
+        b.set_buffer_sizes(connection, 8192, 8192).unwrap();
+        let used = b.memory_used();
+
+        // Hold back the first byte so everything after it is out of order
+        a.send(client, b"x").unwrap();
+        let frame = a_queue.lock().unwrap().pop_front().unwrap();
+        let (ethernet, packet) = parse_ethernet(&frame).unwrap();
+        let (ip, data) = parse_ipv4(packet).unwrap();
+        let first = parse_segment(IpAddr::V4(ip.source), IpAddr::V4(ip.destination), data).unwrap();
+
+        // One byte every other sequence number: 4000 bytes in the window,
+        // but far more once each queued segment's bookkeeping is counted
+        for i in 0..4000u32 {
+            let segment = TcpSegment {
+                sequence: first.sequence.wrapping_add(1 + 2 * i),
+                payload: vec![b'y'],
+                ..first.clone()
+            };
+            let tcp = build_segment(IpAddr::V4(ip.source), IpAddr::V4(ip.destination), &segment);
+            let datagram = build_ipv4(&Ipv4Header::new(ip.source, ip.destination, IP_PROTO_TCP), &tcp);
+            b.net_mut().receive(InterfaceId(0), &build_ethernet(&ethernet, &datagram), 2).unwrap();
+            if i % 64 == 0 {
+                b.poll(2);
+            }
+        }
+        b.poll(2);
+        b_queue.lock().unwrap().clear();
+        let held = b.receive_memory(connection).unwrap();
+        assert!(held > 4096 && held <= 8192);
+        assert_eq!(b.memory_used(), used);
+
+        // Shrinking the buffer drops queued data that no longer fits
+        b.set_buffer_sizes(connection, 8192, 2048).unwrap();
+        assert!(b.receive_memory(connection).unwrap() <= 2048);
+
+        // The held back byte still fills the hole
+        b.net_mut().receive(InterfaceId(0), &frame, 3).unwrap();
+        b.poll(3);
+        let mut buffer = [0u8; 16];
+        assert_eq!(b.recv(connection, &mut buffer).unwrap(), 2);
+        assert_eq!(&buffer[..2], b"xy");
+    }
+
+    #[test]
+    pub fn test_socket_and_interface_statistics() {
+        let (mut a, a_queue, mut b, b_queue) = socket_pair();
+        let server_address: SocketAddr = "10.0.0.2:80".parse().unwrap();
+        let listener = b.socket(SocketKind::Tcp).unwrap();
+        b.bind(listener, server_address).unwrap();
+        b.listen(listener, 8).unwrap();
+        let client = a.socket(SocketKind::Tcp).unwrap();
+        a.connect(client, server_address).unwrap();
+        pump_sets(&mut a, &a_queue, &mut b, &b_queue, 0);
+        let (connection, _) = b.accept(listener).unwrap();
+
+        // The delayed ACK comes back 40 ms after the data went out
+        assert_eq!(a.send(client, b"sample").unwrap(), 6);
+        pump_sets(&mut a, &a_queue, &mut b, &b_queue, 0);
+        b.poll(40);
+        pump_sets(&mut a, &a_queue, &mut b, &b_queue, 40);
+        let info = a.socket_stats(client).unwrap().tcp.unwrap();
+        assert_eq!(info.srtt, Some(40));
+        assert_eq!((info.bytes_sent, info.bytes_acked), (6, 6));
+
+        // A lost segment is resent once the RTO expires
+        assert_eq!(a.send(client, b"lost").unwrap(), 4);
+        a_queue.lock().unwrap().clear();
+        a.poll(1000);
+        pump_sets(&mut a, &a_queue, &mut b, &b_queue, 1000);
+        let stats = a.socket_stats(client).unwrap();
+        let info = stats.tcp.unwrap();
+        assert_eq!((info.retransmits, info.timeouts), (1, 1));
+        assert_eq!(stats.bytes_sent, 14);
+        assert_eq!(b.socket_stats(connection).unwrap().bytes_received, 10);
+
+        // Datagram sockets count messages at the socket layer
+        let receiver = b.socket(SocketKind::Udp).unwrap();
+        b.bind(receiver, "10.0.0.2:9000".parse().unwrap()).unwrap();
+        let sender = a.socket(SocketKind::Udp).unwrap();
+        a.send_to(sender, b"datagram", "10.0.0.2:9000".parse().unwrap()).unwrap();
+        pump_sets(&mut a, &a_queue, &mut b, &b_queue, 1000);
+        let mut buffer = [0u8; 4];
+        assert_eq!(b.recv_from(receiver, &mut buffer).unwrap().0, 4);
+        let sent = a.socket_stats(sender).unwrap();
+        assert_eq!((sent.packets_sent, sent.bytes_sent, sent.tcp), (1, 8, None));
+        let received = b.socket_stats(receiver).unwrap();
+        assert_eq!((received.packets_received, received.bytes_received), (1, 8));
+        assert_eq!(b.handles().len(), 3);
+
+        // The ARP request arrived broadcast; runt frames count as errors
+        assert!(b.net_mut().receive(InterfaceId(0), &[0u8; 4], 1000).is_err());
+        let stats = b.net().interface(InterfaceId(0)).unwrap().stats();
+        assert_eq!((stats.rx_multicast, stats.rx_errors), (1, 1));
+        assert_eq!(b.net().interface(InterfaceId(0)).unwrap().device_stats().rx_missed, 0);
+    }
+
+    #[test]
+    pub fn test_wake_on_lan_configuration_and_magic_packet() {
+        let (mut net, id, queue) = host(1, &[]);
+        let magic = WakeOnLan {
+            magic_packet: true,
+            ..WakeOnLan::default()
+        };
+        // The software device cannot wake anything
+        assert!(net.set_wake_on_lan(id, magic).is_err());
+        assert_eq!(net.interface(id).unwrap().wake_on_lan(), WakeOnLan::default());
+        net.set_wake_on_lan(id, WakeOnLan::default()).unwrap();
+
+        let target = MacAddress([0x02, 0, 0, 0, 0, 0x42]);
+        net.send_magic_packet(id, target).unwrap();
+        let frame = queue.lock().unwrap().pop_front().unwrap();
+        let (ethernet, payload) = parse_ethernet(&frame).unwrap();
+        assert_eq!((ethernet.destination, ethernet.ethertype), (MacAddress::BROADCAST, ETHERTYPE_WAKE_ON_LAN));
+        assert_eq!(&payload[..6], &[0xFF; 6]);
+        assert!(payload[6..102].chunks(6).all(|chunk| chunk == target.0));
+    }
+
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    pub fn test_socket_async_udp_wakes_reader() {
+        let (a, a_queue, b, b_queue) = socket_pair();
+        let a = Arc::new(Mutex::new(a));
+        let b = Arc::new(Mutex::new(b));
+
+        let handle = {
+            let mut b = b.lock().unwrap();
+            let handle = b.socket(SocketKind::Udp).unwrap();
+            b.bind(handle, "0.0.0.0:9000".parse().unwrap()).unwrap();
+            handle
+        };
+        let receiver = AsyncSocket::new(Arc::clone(&b), handle);
+        let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = Waker::from(Arc::clone(&counter));
+        let mut context = Context::from_waker(&waker);
+        let mut buffer = [0u8; 32];
+        let mut future = Box::pin(receiver.recv_from(&mut buffer));
+        assert!(future.as_mut().poll(&mut context).is_pending());
+
+        {
+            let mut a = a.lock().unwrap();
+            let sender = a.socket(SocketKind::Udp).unwrap();
+            a.send_to(sender, b"wake up", "10.0.0.2:9000".parse().unwrap()).unwrap();
+        }
+        for now in 1..4 {
+            let mut a = a.lock().unwrap();
+            let mut b = b.lock().unwrap();
+            pump_sets(&mut a, &a_queue, &mut b, &b_queue, now);
+        }
+        // Wakers are handed out to run once the set is unlocked
+        assert_eq!(counter.0.load(Ordering::SeqCst), 0);
+        let wakers = b.lock().unwrap().take_wakers();
+        assert_eq!(wakers.len(), 1);
+        wakers.into_iter().for_each(Waker::wake);
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+        SocketSet::poll_shared(&b, 4);
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+        let Poll::Ready(Ok((count, source))) = future.as_mut().poll(&mut context) else {
+            panic!("receive should complete once woken");
+        };
+        assert_eq!(count, 7);
+        assert_eq!(source.ip(), IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+        drop(future);
+        assert_eq!(&buffer[..7], b"wake up");
+    }
+
+    #[test]
+    pub fn test_packet_buffer_headroom_clone_and_fragments() {
+        let mut packet = PacketBuffer::from_slice(32, b"payload");
+        assert_eq!((packet.headroom(), packet.len()), (32, 7));
+        packet.push(4).copy_from_slice(b"hdr:");
+        assert_eq!(packet.data(), b"hdr:payload");
+
+        // Clones share storage until one of them writes
+        let clone = packet.clone();
+        assert!(packet.is_shared());
+        packet.data_mut()[0] = b'H';
+        assert!(!packet.is_shared());
+        assert_eq!(clone.data(), b"hdr:payload");
+        assert_eq!(packet.pull(4).unwrap(), b"Hdr:");
+
+        packet.append_fragment(PacketSlice::new(b" and more".to_vec()));
+        assert_eq!(packet.len(), 16);
+        assert_eq!(packet.chunks().count(), 2);
+        let rest = packet.split_off(10).unwrap();
+        assert_eq!(packet.to_vec(), b"payload an");
+        assert_eq!(rest.to_vec(), b"d more");
+
+        packet.linearize();
+        assert!(packet.fragments().is_empty());
+        assert_eq!(packet.data(), b"payload an");
+        packet.trim(7);
+        assert_eq!(packet.data(), b"payload");
+    }
+
+    #[test]
+    pub fn test_vxwall_rules_reject_and_conntrack() {
+        let a_address = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let b_address = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let (mut a, a_id, a_queue) = host(1, &[(a_address, 24)]);
+        let (mut b, b_id, b_queue) = host(2, &[(b_address, 24)]);
+        let (mut a_udp, mut b_udp) = (UdpStack::new(), UdpStack::new());
+
+        let firewall = Arc::new(Mutex::new(Firewall::new()));
+        let dns = {
+            let mut firewall = firewall.lock().unwrap();
+            firewall.set_policy(Hook::Input, Verdict::Drop);
+            firewall.add_rule(parse_rule("input proto tcp dport 23 reject").unwrap());
+            firewall.add_rule(parse_rule("input proto udp from 10.0.0.0/24 dport 53 accept").unwrap())
+        };
+        b.set_packet_filter(Box::new(Arc::clone(&firewall)));
+
+        let exchange = |a: &mut NetStack, b: &mut NetStack| {
+            for _ in 0..3 {
+                pump(&a_queue, b, b_id);
+                pump(&b_queue, a, a_id);
+            }
+        };
+        let client = a_udp.bind(None, 6000).unwrap();
+        a_udp.send_to(&mut a, client, b_address, 53, b"allowed").unwrap();
+        a_udp.send_to(&mut a, client, b_address, 54, b"blocked").unwrap();
+        exchange(&mut a, &mut b);
+        let datagram = b.take_inbound().unwrap();
+        assert_eq!(&datagram.payload.data()[8..], b"allowed");
+        assert!(b.take_inbound().is_none());
+        assert_eq!(firewall.lock().unwrap().counters(dns).unwrap().packets, 1);
+
+        // Rejected traffic is answered with ICMP administratively prohibited
+        let syn = telnet_syn(a_address, b_address);
+        a.send_from(a_address, b_address, IP_PROTO_TCP, &syn, 64).unwrap();
+        exchange(&mut a, &mut b);
+        let error = a.take_inbound().unwrap();
+        assert_eq!(error.protocol, IP_PROTO_ICMP);
+        assert_eq!((error.payload.data()[0], error.payload.data()[1]), (3, 13));
+
+        // Replies to flows B opened are let in by connection tracking
+        let server = b_udp.bind(None, 7000).unwrap();
+        b_udp.send_to(&mut b, server, a_address, 6000, b"hello").unwrap();
+        exchange(&mut a, &mut b);
+        let datagram = a.take_inbound().unwrap();
+        a_udp.handle_datagram(&datagram).unwrap();
+        a_udp.send_to(&mut a, client, b_address, 7000, b"reply").unwrap();
+        exchange(&mut a, &mut b);
+        let datagram = b.take_inbound().unwrap();
+        assert_eq!(&datagram.payload.data()[8..], b"reply");
+        assert_eq!(firewall.lock().unwrap().conntrack().classify(&datagram), ConnState::Established);
+    }
+
+    #[test]
+    pub fn test_vxwall_matches_socket_owner() {
+        let (mut a, a_queue, mut b, b_queue) = socket_pair();
+        let server_address: SocketAddr = "10.0.0.2:443".parse().unwrap();
+        let listener = b.socket(SocketKind::Tcp).unwrap();
+        b.bind(listener, server_address).unwrap();
+        b.listen(listener, 8).unwrap();
+
+        // Only the update service may open outbound HTTPS connections
+        let firewall = Arc::new(Mutex::new(Firewall::new()));
+        let allowed = {
+            let mut firewall = firewall.lock().unwrap();
+            let allowed = firewall.add_rule(parse_rule("output proto tcp dport 443 service vxupdate accept").unwrap());
+            firewall.add_rule(parse_rule("output proto tcp dport 443 state new reject").unwrap());
+            allowed
+        };
+        a.net_mut().set_packet_filter(Box::new(Arc::clone(&firewall)));
+
+        let browser = a.socket(SocketKind::Tcp).unwrap();
+        a.set_owner(
+            browser,
+            SocketOwner {
+                task: 3,
+                service: "browser".to_string(),
+            },
+        )
+        .unwrap();
+        assert_eq!(a.connect(browser, server_address).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        assert!(a.local_addr(browser).unwrap().is_none());
+
+        let updater = a.socket(SocketKind::Tcp).unwrap();
+        let owner = SocketOwner {
+            task: 7,
+            service: "vxupdate".to_string(),
+        };
+        a.set_owner(updater, owner.clone()).unwrap();
+        a.connect(updater, server_address).unwrap();
+        let port = a.local_addr(updater).unwrap().unwrap().port();
+        assert_eq!(a.net().port_owner(IP_PROTO_TCP, port), Some(&owner));
+        pump_sets(&mut a, &a_queue, &mut b, &b_queue, 1);
+        assert!(b.accept(listener).is_ok());
+        assert_eq!(a.send(updater, b"check").unwrap(), 5);
+        assert!(firewall.lock().unwrap().counters(allowed).unwrap().packets >= 1);
+
+        // Task ids match too, and closing the socket releases its port
+        assert_eq!(parse_rule("output task 7 accept").unwrap().matcher.task, Some(7));
+        a.close(updater).unwrap();
+        assert!(a.net().port_owner(IP_PROTO_TCP, port).is_none());
+    }
+
+    fn telnet_syn(source: IpAddr, destination: IpAddr) -> Vec<u8> {
+        let syn = TcpSegment {
+            source_port: 40000,
+            destination_port: 23,
+            sequence: 1,
+            acknowledgment: 0,
+            flags: 0x02,
+            window: 1024,
+            mss: None,
+            window_shift: None,
+            payload: Vec::new(),
+        };
+        build_segment(source, destination, &syn)
+    }
+
+    #[test]
+    pub fn test_vxwall_masquerade_and_port_forward() {
+        let lan_host = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10));
+        let router_lan = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1));
+        let router_wan = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 1));
+        let remote = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 50));
+        let (mut lan, lan_id, lan_queue) = host(1, &[(lan_host, 24)]);
+        let (mut internet, internet_id, internet_queue) = host(2, &[(remote, 24)]);
+        let default = IpCidr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0).unwrap();
+        lan.add_route(Route::new(default, Some(router_lan), lan_id)).unwrap();
+
+        let lan_port = QueueDevice::new("eth0", MacAddress([0x02, 0, 0, 0, 0, 10]), 1500);
+        let wan_port = QueueDevice::new("wlan0", MacAddress([0x02, 0, 0, 0, 0, 11]), 1500);
+        let (router_lan_queue, router_wan_queue) = (lan_port.tx_queue(), wan_port.tx_queue());
+        let mut router = NetStack::new();
+        let router_lan_id = router.add_interface(Box::new(lan_port)).unwrap();
+        let router_wan_id = router.add_interface(Box::new(wan_port)).unwrap();
+        router.add_address(router_lan_id, IpCidr::new(router_lan, 24).unwrap()).unwrap();
+        router.add_address(router_wan_id, IpCidr::new(router_wan, 24).unwrap()).unwrap();
+        router.set_forwarding(true);
+
+        let firewall = Arc::new(Mutex::new(Firewall::new()));
+        let forward = {
+            let mut firewall = firewall.lock().unwrap();
+            firewall.set_policy(Hook::Forward, Verdict::Accept);
+            firewall.add_nat_rule(parse_nat_rule("masquerade iface 1 from 192.168.1.0/24").unwrap());
+            firewall.add_nat_rule(parse_nat_rule("dnat iface 1 proto udp dport 8080 to-destination 192.168.1.10:80").unwrap())
+        };
+        router.set_packet_filter(Box::new(Arc::clone(&firewall)));
+
+        let mut exchange = |lan: &mut NetStack, internet: &mut NetStack| {
+            for _ in 0..4 {
+                pump(&lan_queue, &mut router, router_lan_id);
+                pump(&router_lan_queue, lan, lan_id);
+                pump(&internet_queue, &mut router, router_wan_id);
+                pump(&router_wan_queue, internet, internet_id);
+            }
+        };
+        let (mut lan_udp, mut internet_udp) = (UdpStack::new(), UdpStack::new());
+        let client = lan_udp.bind(None, 5000).unwrap();
+        let dns = internet_udp.bind(None, 53).unwrap();
+
+        // Outbound flows leave with the router's uplink address
+        lan_udp.send_to(&mut lan, client, remote, 53, b"query").unwrap();
+        exchange(&mut lan, &mut internet);
+        let datagram = internet.take_inbound().unwrap();
+        assert_eq!(datagram.source, router_wan);
+        assert_eq!(datagram.ttl, 63);
+        internet_udp.handle_datagram(&datagram).unwrap();
+        let query = internet_udp.recv_from(dns).unwrap().unwrap();
+        assert_eq!((query.source_port, query.payload.as_slice()), (5000, &b"query"[..]));
+
+        // and replies find their way back to the LAN host
+        internet_udp.send_to(&mut internet, dns, router_wan, 5000, b"answer").unwrap();
+        exchange(&mut lan, &mut internet);
+        let datagram = lan.take_inbound().unwrap();
+        lan_udp.handle_datagram(&datagram).unwrap();
+        let answer = lan_udp.recv_from(client).unwrap().unwrap();
+        assert_eq!((answer.source, answer.source_port), (remote, 53));
+        assert_eq!(answer.payload, b"answer");
+
+        // Port forwarding exposes the LAN service on the uplink address
+        let server = lan_udp.bind(None, 80).unwrap();
+        let visitor = internet_udp.bind(None, 4000).unwrap();
+        internet_udp.send_to(&mut internet, visitor, router_wan, 8080, b"request").unwrap();
+        exchange(&mut lan, &mut internet);
+        let datagram = lan.take_inbound().unwrap();
+        lan_udp.handle_datagram(&datagram).unwrap();
+        let request = lan_udp.recv_from(server).unwrap().unwrap();
+        assert_eq!((request.source, request.source_port), (remote, 4000));
+
+        lan_udp.send_to(&mut lan, server, remote, 4000, b"response").unwrap();
+        exchange(&mut lan, &mut internet);
+        let datagram = internet.take_inbound().unwrap();
+        internet_udp.handle_datagram(&datagram).unwrap();
+        let response = internet_udp.recv_from(visitor).unwrap().unwrap();
+        assert_eq!((response.source, response.source_port), (router_wan, 8080));
+
+        let firewall = firewall.lock().unwrap();
+        assert_eq!(firewall.conntrack().len(), 2);
+        assert!(firewall.conntrack().connections().all(|connection| connection.is_translated()));
+        assert_eq!(firewall.nat_rules().iter().find(|(id, _, _)| *id == forward).unwrap().2.packets, 1);
+    }
+
+    #[test]
+    pub fn test_forwarding_expires_ttl_zero_and_one() {
+        let eth0 = QueueDevice::new("eth0", MacAddress([0x02, 0, 0, 0, 0, 20]), 1500);
+        let eth1 = QueueDevice::new("eth1", MacAddress([0x02, 0, 0, 0, 0, 21]), 1500);
+        let (eth0_queue, eth1_queue) = (eth0.tx_queue(), eth1.tx_queue());
+        let mut router = NetStack::new();
+        let eth0_id = router.add_interface(Box::new(eth0)).unwrap();
+        let eth1_id = router.add_interface(Box::new(eth1)).unwrap();
+        router.add_address(eth0_id, IpCidr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 24).unwrap()).unwrap();
+        router.add_address(eth1_id, IpCidr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 1, 1)), 24).unwrap()).unwrap();
+        router.set_forwarding(true);
+
+        let ethernet = EthernetHeader {
+            destination: MacAddress([0x02, 0, 0, 0, 0, 20]),
+            source: MacAddress([0x02, 0, 0, 0, 0, 30]),
+            ethertype: ETHERTYPE_IPV4,
+        };
+        let frame = |ttl: u8| {
+            let mut header = Ipv4Header::new(Ipv4Addr::new(10, 0, 0, 2), Ipv4Addr::new(10, 0, 1, 2), IP_PROTO_UDP);
+            header.ttl = ttl;
+            build_ethernet(&ethernet, &build_ipv4(&header, &[0; 16]))
+        };
+
+        // Neither goes out the other side, and TTL 0 does not wrap to 255
+        for ttl in [0, 1] {
+            router.receive(eth0_id, &frame(ttl), 0).unwrap();
+            assert!(eth1_queue.lock().unwrap().is_empty(), "ttl {}", ttl);
+        }
+        // The senders are told instead, once their address is resolved
+        assert_eq!(eth0_queue.lock().unwrap().len(), 1);
+
+        router.receive(eth0_id, &frame(2), 0).unwrap();
+        assert_eq!(eth1_queue.lock().unwrap().len(), 1);
+    }
+
+    fn deliver_udp(net: &mut NetStack, udp: &mut UdpStack) {
+        while let Some(datagram) = net.take_inbound() {
+            if datagram.protocol == IP_PROTO_UDP {
+                udp.handle_datagram(&datagram).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_fib_longest_prefix_metrics_and_policy_rules() {
+        let (eth0, eth1) = (InterfaceId(0), InterfaceId(1));
+        let cidr = |a: u8, b: u8, prefix_len: u8| IpCidr::new(IpAddr::V4(Ipv4Addr::new(a, b, 0, 0)), prefix_len).unwrap();
+        let address = |a: u8, b: u8, c: u8, d: u8| IpAddr::V4(Ipv4Addr::new(a, b, c, d));
+        let mut fib = Fib::new();
+        fib.add_route(TABLE_MAIN, Route::new(cidr(10, 0, 8), Some(address(192, 168, 1, 1)), eth0)).unwrap();
+        fib.add_route(TABLE_MAIN, Route::new(cidr(10, 1, 16), None, eth1)).unwrap();
+        assert_eq!(fib.lookup(&address(10, 1, 2, 3), None, None).unwrap().interface, eth1);
+        assert_eq!(fib.lookup(&address(10, 2, 0, 1), None, None).unwrap().interface, eth0);
+        assert!(fib.lookup(&address(8, 8, 8, 8), None, None).is_none());
+
+        // Equal prefixes fall back to the lowest metric
+        let uplink = |interface: InterfaceId, metric: u32| Route {
+            metric,
+            ..Route::new(cidr(0, 0, 0), None, interface)
+        };
+        fib.add_route(TABLE_MAIN, uplink(eth0, 100)).unwrap();
+        fib.add_route(TABLE_MAIN, uplink(eth1, 50)).unwrap();
+        assert_eq!(fib.add_route(TABLE_MAIN, uplink(eth1, 50)), Err("Route already exists"));
+        assert_eq!(fib.lookup(&address(8, 8, 8, 8), None, None).unwrap().interface, eth1);
+
+        // Source-based rule sends one subnet out through its own table
+        fib.add_route(100, Route::new(cidr(8, 0, 8), None, eth0)).unwrap();
+        let mut rule = RoutingRule::new(1000, 100);
+        rule.source = Some(cidr(192, 168, 16));
+        fib.add_rule(rule).unwrap();
+        assert_eq!(fib.add_rule(RoutingRule::new(1000, TABLE_MAIN)), Err("Rule priority already in use"));
+        let from_lan = address(192, 168, 50, 7);
+        assert_eq!(fib.lookup(&address(8, 8, 8, 8), Some(&from_lan), None).unwrap().interface, eth0);
+        assert_eq!(fib.lookup(&address(8, 8, 8, 8), None, None).unwrap().interface, eth1);
+        // Tables without a match fall through to the next rule
+        assert_eq!(fib.lookup(&address(10, 1, 0, 1), Some(&from_lan), None).unwrap().interface, eth1);
+        let mut incoming = RoutingRule::new(500, 100);
+        incoming.interface = Some(eth1);
+        fib.add_rule(incoming).unwrap();
+        assert_eq!(fib.lookup(&address(8, 8, 8, 8), None, Some(eth1)).unwrap().interface, eth0);
+        assert_eq!(fib.remove_rule(500).unwrap(), incoming);
+
+        assert_eq!(fib.retain_routes(|_, route| route.metric != 50), 1);
+        assert_eq!(fib.lookup(&address(8, 8, 8, 8), None, None).unwrap().metric, 100);
+        assert_eq!(fib.tables(), vec![100, TABLE_MAIN]);
+
+        // The stack keeps connected and multicast routes in step with addresses
+        let local = IpAddr::V6("fd00::1".parse::<Ipv6Addr>().unwrap());
+        let (mut net, id, _queue) = host(1, &[(address(10, 0, 0, 1), 24), (local, 64)]);
+        let mdns = IpAddr::V6("ff02::fb".parse::<Ipv6Addr>().unwrap());
+        assert_eq!(net.lookup_route(&mdns).unwrap().interface, id);
+        assert_eq!(net.source_address(&address(10, 0, 0, 9)), Some(address(10, 0, 0, 1)));
+        assert!(net.lookup_route(&address(8, 8, 8, 8)).is_none());
+        net.remove_address(id, IpCidr::new(local, 64).unwrap()).unwrap();
+        assert!(net.lookup_route(&mdns).is_none());
+        assert!(net.routes().iter().all(|route| route.destination.address.is_ipv4()));
+    }
+
+    fn wg_peer(public_key: WgKey, endpoint: Option<SocketAddr>, allowed: IpAddr) -> PeerConfig {
+        let mut config = PeerConfig::new(public_key);
+        config.endpoint = endpoint;
+        config.allowed_ips.push(IpCidr::new(allowed, 32).unwrap());
+        config
+    }
+
+    #[test]
+    pub fn test_wireguard_tunnel_through_stack() {
+        let a_address = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let b_address = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let a_inner = IpAddr::V4(Ipv4Addr::new(10, 9, 0, 1));
+        let b_inner = IpAddr::V4(Ipv4Addr::new(10, 9, 0, 2));
+        let (a_private, b_private) = (WgKey::generate_private(), WgKey::generate_private());
+        let (a_key, b_key) = (WgKey::from_private(&a_private).unwrap(), WgKey::from_private(&b_private).unwrap());
+        assert_eq!(WgKey::from_base64(&a_key.to_base64()).unwrap(), a_key);
+
+        let (mut a, a_id, a_queue) = host(1, &[(a_address, 24)]);
+        let (mut b, b_id, b_queue) = host(2, &[(b_address, 24)]);
+        let (mut a_udp, mut b_udp) = (UdpStack::new(), UdpStack::new());
+        let mut a_engine = WireGuard::new(&a_private, DEFAULT_PORT).unwrap();
+        let b_endpoint = SocketAddr::new(b_address, DEFAULT_PORT);
+        let preshared_key = WgKey::generate_private();
+        let mut b_peer = wg_peer(b_key, Some(b_endpoint), b_inner);
+        b_peer.preshared_key = Some(Secret::from_slice(&preshared_key.expose()[..16]));
+        assert!(a_engine.add_peer(b_peer.clone()).is_err());
+        b_peer.preshared_key = Some(preshared_key.clone());
+        a_engine.add_peer(b_peer).unwrap();
+        let mut b_engine = WireGuard::new(&b_private, DEFAULT_PORT).unwrap();
+        // B learns A's endpoint from the handshake
+        let mut a_peer = wg_peer(a_key, None, a_inner);
+        a_peer.preshared_key = Some(preshared_key);
+        b_engine.add_peer(a_peer).unwrap();
+        let mut a_tunnel = WgTunnel::attach(&mut a, &mut a_udp, "wg0", a_engine).unwrap();
+        let mut b_tunnel = WgTunnel::attach(&mut b, &mut b_udp, "wg0", b_engine).unwrap();
+        a.add_address(a_tunnel.interface(), IpCidr::new(a_inner, 24).unwrap()).unwrap();
+        b.add_address(b_tunnel.interface(), IpCidr::new(b_inner, 24).unwrap()).unwrap();
+        let b_engine = Arc::clone(b_tunnel.engine());
+        let route = a.lookup_route(&b_inner).unwrap();
+        assert_eq!((route.interface, route.protocol), (a_tunnel.interface(), RouteProtocol::WireGuard));
+        assert_eq!(route.destination.prefix_len, 32);
+
+        let mut run = |a: &mut NetStack, b: &mut NetStack, a_udp: &mut UdpStack, b_udp: &mut UdpStack| {
+            for _ in 0..6 {
+                pump(&a_queue, b, b_id);
+                pump(&b_queue, a, a_id);
+                deliver_udp(a, a_udp);
+                deliver_udp(b, b_udp);
+                a_tunnel.poll(a, a_udp, 0).unwrap();
+                b_tunnel.poll(b, b_udp, 0).unwrap();
+                deliver_udp(a, a_udp);
+                deliver_udp(b, b_udp);
+            }
+        };
+        let client = a_udp.bind(None, 7000).unwrap();
+        let server = b_udp.bind(None, 9000).unwrap();
+        a_udp.send_to(&mut a, client, b_inner, 9000, b"through the tunnel").unwrap();
+        run(&mut a, &mut b, &mut a_udp, &mut b_udp);
+
+        let message = b_udp.recv_from(server).unwrap().unwrap();
+        assert_eq!((message.source, message.source_port), (a_inner, 7000));
+        assert_eq!(message.payload, b"through the tunnel");
+        let status = b_engine.lock().unwrap().peer(&a_key).unwrap();
+        assert_eq!(status.endpoint, Some(SocketAddr::new(a_address, DEFAULT_PORT)));
+        assert!(status.has_session && status.last_handshake.is_some());
+
+        b_udp.send_to(&mut b, server, a_inner, 7000, b"and back").unwrap();
+        run(&mut a, &mut b, &mut a_udp, &mut b_udp);
+        let message = a_udp.recv_from(client).unwrap().unwrap();
+        assert_eq!((message.source, message.payload.as_slice()), (b_inner, &b"and back"[..]));
+    }
+
+    #[test]
+    pub fn test_wireguard_cookie_under_load_and_replay() {
+        let a_endpoint = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), DEFAULT_PORT);
+        let b_endpoint = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), DEFAULT_PORT);
+        let (a_inner, b_inner) = (Ipv4Addr::new(10, 9, 0, 1), Ipv4Addr::new(10, 9, 0, 2));
+        let (a_private, b_private) = (WgKey::generate_private(), WgKey::generate_private());
+        let (a_key, b_key) = (WgKey::from_private(&a_private).unwrap(), WgKey::from_private(&b_private).unwrap());
+        let mut a = WireGuard::new(&a_private, DEFAULT_PORT).unwrap();
+        let mut b = WireGuard::new(&b_private, DEFAULT_PORT).unwrap();
+        a.add_peer(wg_peer(b_key, Some(b_endpoint), IpAddr::V4(b_inner))).unwrap();
+        b.add_peer(wg_peer(a_key, None, IpAddr::V4(a_inner))).unwrap();
+        // Every handshake counts as load, so a cookie round trip is required
+        b.set_handshake_limit(0);
+
+        let packet = build_ipv4(&Ipv4Header::new(a_inner, b_inner, IP_PROTO_UDP), b"payload");
+        a.send_packet(&packet).unwrap();
+        let (_, initiation) = a.take_outbound().unwrap();
+        assert_eq!(b.receive(a_endpoint, &initiation, 0).unwrap(), None);
+        let (_, cookie_reply) = b.take_outbound().unwrap();
+        assert_eq!(cookie_reply[0], 3);
+        assert_eq!(a.receive(b_endpoint, &cookie_reply, 10).unwrap(), None);
+        assert!(!a.peer(&b_key).unwrap().has_session);
+
+        // The retry carries mac2 and is answered with a handshake response
+        a.update_timers(5_400);
+        let (_, initiation) = a.take_outbound().unwrap();
+        b.receive(a_endpoint, &initiation, 5_400).unwrap();
+        let (_, response) = b.take_outbound().unwrap();
+        assert_eq!(response[0], 2);
+        a.receive(b_endpoint, &response, 5_410).unwrap();
+        let (_, transport) = a.take_outbound().unwrap();
+        assert_eq!(b.receive(a_endpoint, &transport, 5_420).unwrap(), Some(packet));
+        assert_eq!(b.receive(a_endpoint, &transport, 5_430), Err("Replayed transport message"));
+    }
+
+    #[test]
+    pub fn test_vxnetctl_configures_interfaces_over_vxchan() {
+        let manager = VXChanManager::new();
+        let mut service = NetCtlService::new(&manager).unwrap();
+        let (mut net, id, queue) = host(1, &[(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 24)]);
+        net.poll_timers(0);
+        assert_eq!(net.take_link_event(), Some(LinkEvent { interface: id, up: true }));
+
+        let requests = [
+            "addr add eth0 10.0.1.1/24",
+            "mtu eth0 1400",
+            "mac 0 02:aa:bb:cc:dd:ee",
+            "down eth0",
+            "show eth0",
+            "mtu eth0 9000",
+            "addr del eth0 192.0.2.1/24",
+            "reboot eth0",
+        ];
+        for (index, request) in requests.iter().enumerate() {
+            send_request(&manager, index as u64 + 1, request).unwrap();
+        }
+        assert_eq!(service.poll(&manager, &mut net).unwrap(), requests.len());
+        let replies: Vec<(u64, Result<String, String>)> = (0..requests.len())
+            .map(|_| parse_reply(&manager.receive_message(REPLY_CHANNEL).unwrap()).unwrap())
+            .collect();
+        assert!(replies[..4].iter().all(|(_, result)| result == &Ok(String::new())));
+        assert_eq!(
+            replies[4],
+            (
+                5,
+                Ok("eth0 index=0 kind=ether admin=down carrier=up mtu=1400 mac=02:aa:bb:cc:dd:ee \
+                    addresses=10.0.0.1/24,10.0.1.1/24"
+                    .to_string())
+            )
+        );
+        assert_eq!(replies[5], (6, Err("MTU out of range".to_string())));
+        assert_eq!(replies[6], (7, Err("Address not assigned".to_string())));
+        assert_eq!(replies[7], (8, Err("Unknown command".to_string())));
+
+        // Going down is reported like a carrier loss and stops all traffic
+        assert_eq!(net.take_link_event(), Some(LinkEvent { interface: id, up: false }));
+        let peer = IpAddr::V4(Ipv4Addr::new(10, 0, 1, 2));
+        assert!(net.lookup_route(&peer).is_none());
+        assert!(net.send(peer, IP_PROTO_UDP, b"hello").is_err());
+        let arp = build_ethernet(
+            &EthernetHeader {
+                destination: MacAddress::BROADCAST,
+                source: MacAddress([0x02, 0, 0, 0, 0, 9]),
+                ethertype: ETHERTYPE_IPV4,
+            },
+            &[0; 20],
+        );
+        net.receive(id, &arp, 0).unwrap();
+
+        send_request(&manager, 9, "up eth0").unwrap();
+        send_request(&manager, 10, "stats eth0").unwrap();
+        service.poll(&manager, &mut net).unwrap();
+        assert_eq!(service.handled(), 10);
+        manager.receive_message(REPLY_CHANNEL).unwrap();
+        net.send(peer, IP_PROTO_UDP, b"hello").unwrap();
+        let frame = queue.lock().unwrap().pop_front().unwrap();
+        let (ethernet, _) = parse_ethernet(&frame).unwrap();
+        assert_eq!(ethernet.source, MacAddress([0x02, 0xaa, 0xbb, 0xcc, 0xdd, 0xee]));
+        let stats = net.interface(id).unwrap().stats();
+        assert_eq!((stats.rx_dropped, stats.tx_packets, stats.tx_bytes), (1, 1, frame.len() as u64));
+        let (_, stats_reply) = parse_reply(&manager.receive_message(REPLY_CHANNEL).unwrap()).unwrap();
+        assert_eq!(
+            stats_reply.unwrap(),
+            "rx_packets=0 rx_bytes=0 rx_dropped=1 rx_errors=0 rx_multicast=0 tx_packets=0 tx_bytes=0 tx_errors=0 \
+             tx_dropped=0 rx_crc_errors=0 rx_missed=0 tx_carrier_errors=0 collisions=0"
+        );
+    }
+
+    #[test]
+    pub fn test_http_chunked_redirect_and_resumed_range_download() {
+        let response = b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+            5;ext=1\r\nhello\r\n7\r\n, world\r\n0\r\nX-Trailer: 1\r\n\r\n";
+        let mut parser = ResponseParser::new(false);
+        let mut body = Vec::new();
+        for byte in response.chunks(1) {
+            parser
+                .feed(byte, &mut |_, data| {
+                    body.extend_from_slice(data);
+                    Ok(())
+                })
+                .unwrap();
+        }
+        assert!(parser.is_complete());
+        assert_eq!((parser.response().unwrap().status, body.as_slice()), (200, &b"hello, world"[..]));
+        let base = Url::parse("http://[fd00::1]:8080/a/b?x=1").unwrap();
+        assert_eq!(base.join("c").unwrap().to_string(), "http://[fd00::1]:8080/a/c");
+        assert_eq!(base.join("//mirror/d").unwrap().to_string(), "http://mirror/d");
+
+        let (mut a, a_queue, mut b, b_queue) = socket_pair();
+        let listener = b.socket(SocketKind::Tcp).unwrap();
+        b.bind(listener, "10.0.0.2:80".parse().unwrap()).unwrap();
+        b.listen(listener, 4).unwrap();
+        let mut client = HttpClient::new();
+        client.add_host("updates.vaelix.local", IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)));
+        let mut transfer = client.get(&mut a, "http://updates.vaelix.local/latest", Vec::new()).unwrap();
+        let payload: Vec<u8> = (0..1000u32).map(|i| (i * 7) as u8).collect();
+
+        // Each round the server answers the next request and hangs up
+        let replies: [Vec<u8>; 3] = [
+            b"HTTP/1.1 302 Found\r\nLocation: /files/fw.bin\r\nContent-Length: 0\r\n\r\n".to_vec(),
+            [&b"HTTP/1.1 200 OK\r\nContent-Length: 1000\r\n\r\n"[..], &payload[..400]].concat(),
+            [
+                &b"HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 400-999/1000\r\nContent-Length: 600\r\n\r\n"[..],
+                &payload[400..],
+            ]
+            .concat(),
+        ];
+        let mut requests = Vec::new();
+        let mut now = 1;
+        for reply in &replies {
+            for _ in 0..3 {
+                pump_sets(&mut a, &a_queue, &mut b, &b_queue, now);
+                assert_eq!(transfer.poll(&mut a).unwrap(), TransferStatus::InProgress);
+                now += 1;
+            }
+            let (connection, _) = b.accept(listener).unwrap();
+            let mut buffer = [0u8; 1024];
+            let count = b.recv(connection, &mut buffer).unwrap();
+            requests.push(String::from_utf8_lossy(&buffer[..count]).into_owned());
+            assert_eq!(b.send(connection, reply).unwrap(), reply.len());
+            b.close(connection).unwrap();
+        }
+        for _ in 0..3 {
+            pump_sets(&mut a, &a_queue, &mut b, &b_queue, now);
+            transfer.poll(&mut a).unwrap();
+            now += 1;
+        }
+
+        assert!(requests[0].starts_with("GET /latest HTTP/1.1\r\nHost: updates.vaelix.local\r\n"));
+        assert!(requests[1].starts_with("GET /files/fw.bin ") && !requests[1].contains("Range"));
+        assert!(requests[2].contains("Range: bytes=400-\r\n"));
+        assert_eq!(transfer.poll(&mut a).unwrap(), TransferStatus::Complete);
+        assert_eq!(transfer.response().unwrap().status, 206);
+        assert_eq!((transfer.received(), transfer.total_length()), (1000, Some(1000)));
+        assert_eq!(transfer.url().path, "/files/fw.bin");
+        assert_eq!(transfer.into_sink(), payload);
+        assert_eq!(
+            client.get(&mut a, "https://updates.vaelix.local/", Vec::new()).err(),
+            Some("HTTPS requires TLS, which is not available")
+        );
+    }
+
+    struct RamDisk(Mutex<Vec<u8>>);
+
+    impl BlockDevice for RamDisk {
+        fn block_size(&self) -> usize {
+            512
+        }
+
+        fn block_count(&self) -> u64 {
+            self.0.lock().unwrap().len() as u64 / 512
+        }
+
+        fn read_blocks(&self, lba: u64, buffer: &mut [u8]) -> Result<(), &'static str> {
+            let start = lba as usize * 512;
+            buffer.copy_from_slice(&self.0.lock().unwrap()[start..start + buffer.len()]);
+            Ok(())
+        }
+
+        fn write_blocks(&self, lba: u64, buffer: &[u8]) -> Result<(), &'static str> {
+            let start = lba as usize * 512;
+            self.0.lock().unwrap()[start..start + buffer.len()].copy_from_slice(buffer);
+            Ok(())
+        }
+    }
+
+    #[test]
+    pub fn test_vxupdate_signed_ab_updates() {
+        let root = std::env::temp_dir().join(format!("vaelix-update-{}", std::process::id()));
+        let root = root.to_str().unwrap();
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let image: Vec<u8> = (0..1500u32).map(|i| (i * 13) as u8).collect();
+        let bundle = build_bundle(&key, Version(1, 3, 0), &image);
+        let text = std::str::from_utf8(&bundle[..bundle.len() - image.len() - 1]).unwrap();
+        assert_eq!(BundleHeader::parse(text, &[key.verifying_key()]).unwrap().version, Version(1, 3, 0));
+        assert!(BundleHeader::parse(&text.replace("1.3.0", "9.3.0"), &[key.verifying_key()]).is_err());
+        assert_eq!("1.2".parse::<Version>(), Err("Invalid version"));
+
+        let (mut a, a_queue, mut b, b_queue) = socket_pair();
+        let listener = b.socket(SocketKind::Tcp).unwrap();
+        b.bind(listener, "10.0.0.2:80".parse().unwrap()).unwrap();
+        b.listen(listener, 4).unwrap();
+        let mut client = HttpClient::new();
+        client.add_host("updates.vaelix.local", IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)));
+        let slot_a: Arc<RamDisk> = Arc::new(RamDisk(Mutex::new(vec![0xAA; 4096])));
+        let slot_b: Arc<RamDisk> = Arc::new(RamDisk(Mutex::new(vec![0; 4096])));
+        let mut service = UpdateService::new(root, vec![key.verifying_key()], Slot::A, Version(1, 2, 0), [slot_a.clone(), slot_b.clone()]);
+
+        let mut tampered = bundle.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        let cases = [
+            (build_bundle(&SigningKey::from_bytes(&[9; 32]), Version(1, 3, 0), &image), Err("Bad update signature")),
+            (build_bundle(&key, Version(1, 1, 0), &image), Err("Update is not newer than the running system")),
+            (tampered, Err("Update image hash mismatch")),
+            (bundle, Ok(UpdateStatus::Ready(Version(1, 3, 0)))),
+        ];
+        let mut now = 1;
+        for (served, expected) in cases {
+            service.start(&client, &mut a, "http://updates.vaelix.local/vaelix.vxu").unwrap();
+            assert_eq!(BootControl::load(root).unwrap().active(), Some(Slot::A));
+            for _ in 0..3 {
+                pump_sets(&mut a, &a_queue, &mut b, &b_queue, now);
+                assert!(matches!(service.poll(&mut a), Ok(UpdateStatus::Downloading { .. })));
+                now += 1;
+            }
+            let (connection, _) = b.accept(listener).unwrap();
+            let mut buffer = [0u8; 1024];
+            b.recv(connection, &mut buffer).unwrap();
+            let reply = [format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", served.len()).as_bytes(), &served].concat();
+            assert_eq!(b.send(connection, &reply).unwrap(), reply.len());
+            b.close(connection).unwrap();
+            let mut result = service.poll(&mut a);
+            for _ in 0..20 {
+                pump_sets(&mut a, &a_queue, &mut b, &b_queue, now);
+                result = service.poll(&mut a);
+                now += 1;
+                if !matches!(result, Ok(UpdateStatus::Downloading { .. })) {
+                    break;
+                }
+            }
+            assert_eq!(result, expected);
+            // Nothing touches the slot before the header checks out
+            if expected == Err("Bad update signature") {
+                assert!(slot_b.0.lock().unwrap().iter().all(|byte| *byte == 0));
+            }
+        }
+
+        // The new image is in B, which is tried next with A to fall back to
+        assert_eq!(&slot_b.0.lock().unwrap()[..image.len()], &image[..]);
+        assert!(slot_a.0.lock().unwrap().iter().all(|byte| *byte == 0xAA));
+        let mut control = BootControl::load(root).unwrap();
+        assert_eq!(control.choose_boot_slot().unwrap(), Slot::B);
+        assert!(control.state(Slot::A).is_bootable());
+        control.save(root).unwrap();
+
+        // B has to prove itself before A may be overwritten
+        let mut service = UpdateService::new(root, vec![key.verifying_key()], Slot::B, Version(1, 3, 0), [slot_a.clone(), slot_b.clone()]);
+        assert_eq!(service.start(&client, &mut a, "http://updates.vaelix.local/vaelix.vxu"), Err("Running slot not yet marked successful"));
+        assert_eq!(service.status(), UpdateStatus::Idle);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn test_vxcap_filters_rings_and_pcap_export() {
+        let (a_address, b_address) = (IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)));
+        let (mut a, a_id, a_queue) = host(1, &[(a_address, 24)]);
+        let (mut b, b_id, b_queue) = host(2, &[(b_address, 24)]);
+        let capture = Arc::new(Mutex::new(PacketCapture::new()));
+        a.set_packet_tap(Box::new(Arc::clone(&capture)));
+        let (udp_session, arp_session) = {
+            let mut capture = capture.lock().unwrap();
+            let udp = capture.start(CaptureFilter::parse("udp port 9000 host 10.0.0.0/24").unwrap(), 64, 4096).unwrap();
+            // Room for a single ARP frame, so the request is overwritten by the reply
+            let arp = capture.start(CaptureFilter::parse("arp").unwrap(), 42, 60).unwrap();
+            (udp, arp)
+        };
+        assert_eq!(CaptureFilter::parse("udp port"), Err("Missing value in capture filter"));
+
+        let (mut a_udp, mut b_udp) = (UdpStack::new(), UdpStack::new());
+        let client = a_udp.bind(None, 7000).unwrap();
+        let server = b_udp.bind(None, 9000).unwrap();
+        a_udp.send_to(&mut a, client, b_address, 9000, &[0x5A; 200]).unwrap();
+        a_udp.send_to(&mut a, client, b_address, 9001, b"not captured").unwrap();
+        for _ in 0..3 {
+            pump(&a_queue, &mut b, b_id);
+            pump(&b_queue, &mut a, a_id);
+            deliver_udp(&mut b, &mut b_udp);
+        }
+        b_udp.send_to(&mut b, server, a_address, 7000, b"reply").unwrap();
+        pump(&b_queue, &mut a, a_id);
+
+        let mut capture = capture.lock().unwrap();
+        let stats = capture.stats(arp_session).unwrap();
+        assert_eq!((stats.captured, stats.overwritten, stats.queued), (2, 1, 1));
+        let arp = capture.read(arp_session, 10).unwrap();
+        assert_eq!(arp[0].direction, Direction::Rx);
+
+        let udp = capture.read(udp_session, 1).unwrap();
+        assert_eq!((udp[0].direction, udp[0].interface), (Direction::Tx, a_id));
+        assert_eq!((udp[0].original_len, udp[0].data.len()), (14 + 20 + 8 + 200, 64));
+        let pcap = capture.export_pcap(udp_session).unwrap();
+        assert_eq!(&pcap[..4], &[0xD4, 0xC3, 0xB2, 0xA1]);
+        assert_eq!(u32::from_le_bytes(pcap[16..20].try_into().unwrap()), 64);
+        // One record left: the reply, small enough to be kept whole
+        let record = &pcap[24..];
+        let included = u32::from_le_bytes(record[8..12].try_into().unwrap()) as usize;
+        assert_eq!((included, record.len()), (14 + 20 + 8 + 5, 16 + included));
+        assert_eq!(capture.stop(udp_session).unwrap().captured, 2);
+        assert!(capture.read(udp_session, 1).is_err());
+    }
+
+    #[test]
+    pub fn test_qdisc_priority_shaping_and_flow_queueing() {
+        let (a_address, b_address) = (IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)));
+        let (mut a, a_id, a_queue) = host(1, &[(a_address, 24)]);
+        let (mut b, b_id, b_queue) = host(2, &[(b_address, 24)]);
+        a.send(b_address, IP_PROTO_UDP, b"resolve").unwrap();
+        pump(&a_queue, &mut b, b_id);
+        pump(&b_queue, &mut a, a_id);
+        a_queue.lock().unwrap().clear();
+
+        // 10 kB/s with room for one full frame: the second bulk frame is
+        // held, and the small interactive one overtakes the third
+        let shaper = TokenBucket::new(10_000, 1514, Box::new(Prio::new(DEFAULT_QUEUE_LIMIT))).unwrap();
+        a.set_qdisc(a_id, Box::new(shaper)).unwrap();
+        for _ in 0..3 {
+            a.send(b_address, IP_PROTO_UDP, &[0xB0; 1000]).unwrap();
+        }
+        a.send(b_address, IP_PROTO_UDP, b"keystroke").unwrap();
+        assert_eq!(a_queue.lock().unwrap().drain(..).count(), 1);
+        assert_eq!(a.qdisc_stats(a_id).unwrap().backlog, 3);
+
+        let mut sent = Vec::new();
+        while let Some(release) = a.next_qdisc_release() {
+            a.poll_timers(release);
+            sent.extend(a_queue.lock().unwrap().drain(..).map(|frame| (release, frame.len())));
+        }
+        assert_eq!(sent.iter().map(|(_, len)| *len).collect::<Vec<_>>(), vec![1034, 43, 1034]);
+        // 1034 bytes at 10 bytes per millisecond, less the 480 left from the burst
+        assert_eq!(sent[0].0, 56);
+        // ARP request and the resolving datagram went out before shaping
+        assert_eq!(a.interfaces()[a_id.0].stats().tx_packets, 2 + 4);
+
+        // A sparse flow is served ahead of a backlogged bulk flow
+        let frames: Vec<PacketBuffer> = {
+            a.clear_qdisc(a_id).unwrap();
+            for port in [[1u8; 4], [2u8; 4]] {
+                a.send(b_address, IP_PROTO_UDP, &[&port[..], &[0; 996]].concat()).unwrap();
+            }
+            a_queue.lock().unwrap().drain(..).map(PacketBuffer::from_vec).collect()
+        };
+        assert_eq!(Prio::band(&frames[0]), 1);
+        let mut fq = FqCodel::with_params(DEFAULT_QUEUE_LIMIT, 5, 100);
+        for _ in 0..20 {
+            fq.enqueue(frames[0].clone(), 0).unwrap();
+        }
+        fq.enqueue(frames[1].clone(), 0).unwrap();
+        let order: Vec<PacketBuffer> = (0..3).map(|_| fq.dequeue(1).unwrap()).collect();
+        assert_eq!(order.iter().position(|frame| *frame == frames[1]), Some(2));
+
+        // Standing queue above target for a full interval: CoDel drops
+        assert_eq!(fq.stats().dropped, 0);
+        fq.dequeue(10).unwrap();
+        fq.dequeue(120).unwrap();
+        let stats = fq.stats();
+        assert_eq!(stats.dropped, 1);
+        assert_eq!(stats.enqueued, stats.dequeued + stats.dropped + stats.backlog as u64);
+    }
+
+    #[test]
+    pub fn test_vxdiag_ping_and_traceroute_over_vxchan() {
+        let lan_host = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10));
+        let router_lan = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1));
+        let router_wan = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 1));
+        let remote = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 50));
+        let default = IpCidr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0).unwrap();
+        let (mut lan, lan_id, lan_queue) = host(1, &[(lan_host, 24)]);
+        let (mut internet, internet_id, internet_queue) = host(2, &[(remote, 24)]);
+        lan.add_route(Route::new(default, Some(router_lan), lan_id)).unwrap();
+        internet.add_route(Route::new(default, Some(router_wan), internet_id)).unwrap();
+
+        let lan_port = QueueDevice::new("eth0", MacAddress([0x02, 0, 0, 0, 0, 10]), 1500);
+        let wan_port = QueueDevice::new("eth1", MacAddress([0x02, 0, 0, 0, 0, 11]), 1500);
+        let (router_lan_queue, router_wan_queue) = (lan_port.tx_queue(), wan_port.tx_queue());
+        let mut router = NetStack::new();
+        let router_lan_id = router.add_interface(Box::new(lan_port)).unwrap();
+        let router_wan_id = router.add_interface(Box::new(wan_port)).unwrap();
+        router.add_address(router_lan_id, IpCidr::new(router_lan, 24).unwrap()).unwrap();
+        router.add_address(router_wan_id, IpCidr::new(router_wan, 24).unwrap()).unwrap();
+        router.set_forwarding(true);
+
+        let manager = VXChanManager::new();
+        let mut service = DiagService::new(&manager).unwrap();
+        let requests = [
+            "traceroute 203.0.113.50 probes 2",
+            "ping 203.0.113.50 count 3 interval 100 size 16",
+            "ping 203.0.113.50 count 1 ttl 1",
+            "ping 2001:db8::1",
+            "ping 203.0.113.50 flood 1",
+        ];
+        for (index, request) in requests.iter().enumerate() {
+            vxdiag::send_request(&manager, index as u64 + 1, request).unwrap();
+        }
+
+        // Every reply is seen 3 ms after its probe went out
+        let mut replies = BTreeMap::new();
+        for now in (0..=400).step_by(10) {
+            let sent = service.poll(&manager, &mut lan, now).unwrap();
+            for _ in 0..sent {
+                let (id, result) = parse_reply(&manager.receive_message(vxdiag::REPLY_CHANNEL).unwrap()).unwrap();
+                replies.insert(id, result);
+            }
+            for _ in 0..6 {
+                pump(&lan_queue, &mut router, router_lan_id);
+                pump(&router_lan_queue, &mut lan, lan_id);
+                pump(&internet_queue, &mut router, router_wan_id);
+                pump(&router_wan_queue, &mut internet, internet_id);
+            }
+            while let Some(datagram) = lan.take_inbound() {
+                assert!(service.handle_datagram(&datagram, now + 3));
+            }
+        }
+
+        assert_eq!(replies.len(), requests.len());
+        assert_eq!(replies[&1], Ok("1 192.168.1.1 3 3\n2 203.0.113.50 3 3\nreached".to_string()));
+        assert_eq!(
+            replies[&2],
+            Ok([
+                "seq=0 from=203.0.113.50 ttl=63 rtt=3",
+                "seq=1 from=203.0.113.50 ttl=63 rtt=3",
+                "seq=2 from=203.0.113.50 ttl=63 rtt=3",
+                "sent=3 received=3 loss=0% rtt=3/3/3",
+            ]
+            .join("\n"))
+        );
+        assert_eq!(
+            replies[&3],
+            Ok("seq=0 from=192.168.1.1 time-exceeded\nsent=1 received=0 loss=100%".to_string())
+        );
+        assert_eq!(replies[&4], Err("No route to host".to_string()));
+        assert_eq!(replies[&5], Err("Unknown option".to_string()));
+        assert_eq!(service.handled(), requests.len() as u64);
+    }
+
+    #[test]
+    pub fn test_bridge_learning_flooding_and_stp_loop_blocking() {
+        let bridge = Bridge::new("br0", MacAddress([0x02, 0, 0, 0, 0, 0x30]));
+        let eth0 = QueueDevice::new("eth0", MacAddress([0x02, 0, 0, 0, 0, 0x31]), 1500);
+        let eth1 = QueueDevice::new("eth1", MacAddress([0x02, 0, 0, 0, 0, 0x32]), 1500);
+        let (eth0_queue, eth1_queue) = (eth0.tx_queue(), eth1.tx_queue());
+        let port0 = bridge.add_port(Box::new(eth0)).unwrap();
+        let port1 = bridge.add_port(Box::new(eth1)).unwrap();
+        let mut net = NetStack::new();
+        let br_id = net.add_interface(Box::new(bridge.device())).unwrap();
+        net.add_address(br_id, IpCidr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 24).unwrap()).unwrap();
+        let x_address = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let y_address = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 3));
+        let (mut x, x_id, x_queue) = host(0x21, &[(x_address, 24)]);
+        let (mut y, y_id, y_queue) = host(0x22, &[(y_address, 24)]);
+
+        // Hosts ignore the BPDUs the bridge sends them
+        let exchange = |x: &mut NetStack, y: &mut NetStack, net: &mut NetStack| {
+            for _ in 0..4 {
+                for frame in x_queue.lock().unwrap().drain(..) {
+                    bridge.receive(port0, &frame, 0).unwrap();
+                }
+                for frame in y_queue.lock().unwrap().drain(..) {
+                    bridge.receive(port1, &frame, 0).unwrap();
+                }
+                while let Some(frame) = bridge.take_host_frame() {
+                    net.receive(br_id, &frame, 0).unwrap();
+                }
+                for frame in eth0_queue.lock().unwrap().drain(..) {
+                    let _ = x.receive(x_id, &frame, 0);
+                }
+                for frame in eth1_queue.lock().unwrap().drain(..) {
+                    let _ = y.receive(y_id, &frame, 0);
+                }
+            }
+        };
+
+        // Learning ports pick up stations but forward nothing yet
+        bridge.poll(0);
+        assert_eq!(bridge.port_state(port0), Some((PortRole::Designated, PortState::Learning)));
+        eth1_queue.lock().unwrap().clear();
+        let hello = EthernetHeader {
+            destination: MacAddress::BROADCAST,
+            source: MacAddress([0x02, 0, 0, 0, 0, 0x21]),
+            ethertype: ETHERTYPE_IPV4,
+        };
+        bridge.receive(port0, &build_ethernet(&hello, &[0; 46]), 0).unwrap();
+        assert!(eth1_queue.lock().unwrap().is_empty() && bridge.take_host_frame().is_none());
+        assert_eq!(bridge.fdb(), vec![(MacAddress([0x02, 0, 0, 0, 0, 0x21]), port0)]);
+
+        bridge.poll(FORWARD_DELAY_MS);
+        net.poll_timers(FORWARD_DELAY_MS);
+        assert_eq!(net.take_link_event(), Some(LinkEvent { interface: br_id, up: true }));
+        x.send(y_address, IP_PROTO_UDP, b"switched").unwrap();
+        exchange(&mut x, &mut y, &mut net);
+        assert_eq!(y.take_inbound().unwrap().payload.data(), b"switched");
+        assert_eq!(bridge.fdb().len(), 2);
+
+        // Known unicast only goes out of the port its station sits behind
+        y.send(x_address, IP_PROTO_UDP, b"unicast").unwrap();
+        for frame in y_queue.lock().unwrap().drain(..) {
+            bridge.receive(port1, &frame, FORWARD_DELAY_MS).unwrap();
+        }
+        assert_eq!(eth0_queue.lock().unwrap().len(), 1);
+        assert!(eth1_queue.lock().unwrap().is_empty() && bridge.take_host_frame().is_none());
+        exchange(&mut x, &mut y, &mut net);
+        assert_eq!(x.take_inbound().unwrap().payload.data(), b"unicast");
+
+        // and the bridge's own address is reachable through it
+        net.send(x_address, IP_PROTO_UDP, b"from br0").unwrap();
+        exchange(&mut x, &mut y, &mut net);
+        assert_eq!(x.take_inbound().unwrap().payload.data(), b"from br0");
+
+        // Two ports cabled together: STP blocks the higher one, so a
+        // broadcast crosses the loop once and stops
+        let looped = Bridge::new("br1", MacAddress([0x02, 0, 0, 0, 0, 0x40]));
+        let (a, b) = (
+            QueueDevice::new("eth2", MacAddress([0x02, 0, 0, 0, 0, 0x41]), 1500),
+            QueueDevice::new("eth3", MacAddress([0x02, 0, 0, 0, 0, 0x42]), 1500),
+        );
+        let (a_queue, b_queue) = (a.tx_queue(), b.tx_queue());
+        let (a_port, b_port) = (looped.add_port(Box::new(a)).unwrap(), looped.add_port(Box::new(b)).unwrap());
+        let cable = || {
+            let mut crossed = 0;
+            loop {
+                let frames: Vec<(Vec<u8>, _)> = a_queue
+                    .lock()
+                    .unwrap()
+                    .drain(..)
+                    .map(|frame| (frame, b_port))
+                    .chain(b_queue.lock().unwrap().drain(..).map(|frame| (frame, a_port)))
+                    .collect();
+                if frames.is_empty() || crossed > 100 {
+                    return crossed;
+                }
+                crossed += frames.len();
+                for (frame, port) in frames {
+                    looped.receive(port, &frame, FORWARD_DELAY_MS).unwrap();
+                }
+            }
+        };
+        looped.poll(0);
+        assert_eq!(cable(), 2);
+        assert_eq!(looped.port_state(b_port), Some((PortRole::Blocked, PortState::Blocking)));
+        looped.poll(FORWARD_DELAY_MS);
+        assert_eq!(looped.port_state(a_port), Some((PortRole::Designated, PortState::Forwarding)));
+        assert!(looped.is_root());
+        let mut device = looped.device();
+        let broadcast = build_ethernet(
+            &EthernetHeader {
+                destination: MacAddress::BROADCAST,
+                source: MacAddress([0x02, 0, 0, 0, 0, 0x40]),
+                ethertype: ETHERTYPE_IPV4,
+            },
+            &[0; 46],
+        );
+        device.transmit(&broadcast).unwrap();
+        // The hello BPDU from the last poll plus the broadcast
+        assert_eq!(cable(), 2);
+        assert!(looped.take_host_frame().is_none());
+    }
+
+    #[test]
+    pub fn test_neighbor_states_retransmits_and_failure() {
+        let (a_address, b_address) = (IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)));
+        let (mut a, a_id, a_queue) = host(1, &[(a_address, 24)]);
+        let (mut b, b_id, b_queue) = host(2, &[(b_address, 24)]);
+        let b_mac = MacAddress([0x02, 0, 0, 0, 0, 2]);
+        a.set_neighbor_config(NeighborConfig {
+            reachable_time: 1_000,
+            retrans_time: 100,
+            delay_first_probe: 200,
+            multicast_probes: 2,
+            unicast_probes: 2,
+            failed_hold: 500,
+            gc_stale_time: 5_000,
+            queue_len: 2,
+        });
+        let deliver = |queue: &TxQueue, stack: &mut NetStack, id: InterfaceId, now: u64| {
+            let frames: Vec<Vec<u8>> = queue.lock().unwrap().drain(..).collect();
+            for frame in &frames {
+                stack.receive(id, frame, now).unwrap();
+            }
+            frames
+        };
+        let state = |a: &NetStack, address: IpAddr| a.neighbor_entry(&address).map(|entry| entry.state);
+
+        // Sends never wait for resolution; the oldest packet over the
+        // queue limit is dropped
+        for payload in [&b"one"[..], b"two", b"three"] {
+            a.send(b_address, IP_PROTO_UDP, payload).unwrap();
+        }
+        assert_eq!(state(&a, b_address), Some(NeighborState::Incomplete));
+        assert_eq!(a.interfaces()[a_id.0].stats().tx_dropped, 1);
+
+        // A lost request is retransmitted, and the answer releases the queue
+        a_queue.lock().unwrap().clear();
+        a.poll_timers(100);
+        deliver(&a_queue, &mut b, b_id, 100);
+        deliver(&b_queue, &mut a, a_id, 100);
+        assert_eq!(state(&a, b_address), Some(NeighborState::Reachable));
+        deliver(&a_queue, &mut b, b_id, 100);
+        let received: Vec<Vec<u8>> = std::iter::from_fn(|| b.take_inbound()).map(|datagram| datagram.payload.to_vec()).collect();
+        assert_eq!(received, vec![b"two".to_vec(), b"three".to_vec()]);
+
+        // Reachability lapses to stale; using the entry sends at once and
+        // later confirms it with a unicast probe
+        a.poll_timers(1_100);
+        assert_eq!(state(&a, b_address), Some(NeighborState::Stale));
+        a.send(b_address, IP_PROTO_UDP, b"stale").unwrap();
+        assert_eq!(deliver(&a_queue, &mut b, b_id, 1_100).len(), 1);
+        assert_eq!(state(&a, b_address), Some(NeighborState::Probe));
+        a.poll_timers(1_300);
+        let probe = deliver(&a_queue, &mut b, b_id, 1_300);
+        assert_eq!(parse_ethernet(&probe[0]).unwrap().0.destination, b_mac);
+        deliver(&b_queue, &mut a, a_id, 1_300);
+        assert_eq!(state(&a, b_address), Some(NeighborState::Reachable));
+
+        // Nobody answers for a missing host: it fails, its queue is
+        // dropped, and sends fail fast until the entry is held no longer
+        let missing = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 9));
+        a.send(missing, IP_PROTO_UDP, b"lost").unwrap();
+        a.poll_timers(1_400);
+        a.poll_timers(1_500);
+        assert_eq!(state(&a, missing), Some(NeighborState::Failed));
+        assert_eq!(a.interfaces()[a_id.0].stats().tx_dropped, 2);
+        assert_eq!(a.send(missing, IP_PROTO_UDP, b"again"), Err("Neighbor unreachable"));
+        a.poll_timers(2_000);
+        assert_eq!(state(&a, missing), None);
+        a.send(missing, IP_PROTO_UDP, b"retry").unwrap();
+        assert_eq!(state(&a, missing), Some(NeighborState::Incomplete));
+    }
+
+    #[derive(Default)]
+    struct ConsoleCapture(Mutex<Vec<u8>>);
+
+    impl SerialPort for ConsoleCapture {
+        fn read_byte(&self) -> Option<u8> {
+            None
+        }
+
+        fn write_byte(&self, byte: u8) -> Result<(), &'static str> {
+            self.0.lock().unwrap().push(byte);
+            Ok(())
+        }
+    }
+
+    fn failing_case() {
+        assert_eq!(1 + 1, 3, "Arithmetic is broken");
+    }
+
+    #[test]
+    pub fn test_ktest_suites_report_through_exit_codes() {
+        let selected = |text: &str| ktest::selected_suites(&CommandLine::parse(text).unwrap());
+        assert_eq!(selected("console=ttyS0"), None);
+        assert_eq!(selected("vxtest"), Some(vec![]));
+        assert_eq!(selected("vxtest=all"), Some(vec![]));
+        assert_eq!(selected("vxtest=tcp,vxchan"), Some(vec!["tcp".to_string(), "vxchan".to_string()]));
+        assert_eq!((ktest::qemu_exit_status(QEMU_EXIT_SUCCESS), ktest::qemu_exit_status(QEMU_EXIT_FAILURE)), (33, 35));
+
+        // Every suite the kernel boots with passes
+        let mut cases = ktest::cases();
+        cases.extend(vaelix_networking::ktest::ktest::cases());
+        let console = ConsoleCapture::default();
+        let report = ktest::run(&cases, &[], &console);
+        assert_eq!((report.passed(), report.failed()), (cases.len(), 0));
+        assert_eq!(report.exit_value(), QEMU_EXIT_SUCCESS);
+        for suite in ["allocator", "scheduler", "vxchan", "vxfs", "tcp"] {
+            assert!(report.results.iter().any(|result| result.suite == suite));
+        }
+        let output = String::from_utf8(console.0.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("vxtest: tcp::loopback_transfer ... ok"));
+        assert!(output.ends_with(&format!("vxtest: {} passed, 0 failed\r\n", cases.len())));
+
+        // A panicking case fails without stopping the run
+        cases.insert(0, TestCase { suite: "broken", name: "arithmetic", run: failing_case });
+        let report = ktest::run(&cases, &["broken".to_string(), "vxchan".to_string()], &console);
+        assert_eq!((report.passed(), report.failed()), (1, 1));
+        assert!(matches!(&report.results[0].outcome, Outcome::Failed(message) if message.contains("Arithmetic is broken")));
+        assert_eq!(report.exit_value(), QEMU_EXIT_FAILURE);
+        assert!(!ktest::run(&cases, &["missing".to_string()], &console).success());
+    }
+
+    #[test]
+    pub fn test_fault_injection_drops_packets() {
+        let (mut net, id, _) = host(1, &[(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 24)]);
+        let frame = build_ethernet(
+            &EthernetHeader {
+                destination: MacAddress::BROADCAST,
+                source: MacAddress([0x02, 0, 0, 0, 0, 9]),
+                ethertype: ETHERTYPE_IPV4,
+            },
+            &[0; 20],
+        );
+        // Half of them, on this thread only
+        faultinject::set_seed(11);
+        FAIL_PACKET.configure(FaultConfig { probability: 50, task_filter: true, ..FaultConfig::always() });
+        faultinject::inject(|| {
+            for _ in 0..200 {
+                let _ = net.receive(id, &frame, 0);
+            }
+        });
+        FAIL_PACKET.disable();
+        let stats = net.interface(id).unwrap().stats();
+        assert_eq!(stats.rx_packets + stats.rx_dropped, 200);
+        assert!((60..140).contains(&stats.rx_dropped), "{} of 200 dropped", stats.rx_dropped);
+        assert_eq!(FAIL_PACKET.stats(), (200, stats.rx_dropped));
+    }
+
+    #[test]
+    pub fn test_metrics_exporter_serves_prometheus_text() {
+        let (mut a, a_queue, b, b_queue) = socket_pair();
+        let b = Arc::new(Mutex::new(b));
+        let registry = Registry::new();
+        registry.counter("vaelix_test_scrapes_total", "Counted by the exporter test.", &[]).unwrap().add(41);
+        exporter::register_interface_metrics(&registry, b.clone()).unwrap();
+        let server = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), METRICS_PORT);
+        let mut exporter = MetricsExporter::bind(&mut b.lock().unwrap(), server).unwrap();
+        assert_eq!(exporter.local_addr(&b.lock().unwrap()).unwrap(), Some(server));
+
+        // As Prometheus scrapes: a request per connection, read to the end
+        let scrape = |a: &mut SocketSet, exporter: &mut MetricsExporter, request: &[u8], now: u64| {
+            let client = a.socket(SocketKind::Tcp).unwrap();
+            a.connect(client, server).unwrap();
+            let mut response = Vec::new();
+            for round in 0..10 {
+                pump_sets(a, &a_queue, &mut b.lock().unwrap(), &b_queue, now + round);
+                if round == 1 {
+                    assert_eq!(a.send(client, request).unwrap(), request.len());
+                }
+                exporter.poll(&b, &registry).unwrap();
+                pump_sets(a, &a_queue, &mut b.lock().unwrap(), &b_queue, now + round);
+                let mut buffer = [0u8; 4096];
+                while let Ok(count) = a.recv(client, &mut buffer) {
+                    if count == 0 {
+                        break;
+                    }
+                    response.extend_from_slice(&buffer[..count]);
+                }
+            }
+            a.close(client).unwrap();
+            String::from_utf8(response).unwrap()
+        };
+
+        let response = scrape(&mut a, &mut exporter, b"GET /metrics HTTP/1.1\r\nHost: 10.0.0.2:9100\r\nAccept: text/plain\r\n\r\n", 0);
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains(&format!("Content-Type: {}\r\n", CONTENT_TYPE)));
+        assert!(head.contains(&format!("Content-Length: {}\r\n", body.len())));
+        assert!(body.contains("# TYPE vaelix_test_scrapes_total counter\nvaelix_test_scrapes_total 41\n"));
+        // The stack's own interface, whose packets carried the scrape
+        let packets = body.lines().find(|line| line.starts_with("vaelix_net_receive_packets_total{interface=")).unwrap();
+        assert!(packets.rsplit(' ').next().unwrap().parse::<u64>().unwrap() > 0);
+        assert_eq!(exporter.scrapes(), 1);
+
+        assert!(scrape(&mut a, &mut exporter, b"HEAD /metrics HTTP/1.1\r\n\r\n", 100).ends_with("Connection: close\r\n\r\n"));
+        assert!(scrape(&mut a, &mut exporter, b"GET / HTTP/1.1\r\n\r\n", 200).starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(scrape(&mut a, &mut exporter, b"POST /metrics HTTP/1.1\r\n\r\n", 300).starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        assert_eq!(exporter.scrapes(), 2);
+        exporter.close(&mut b.lock().unwrap()).unwrap();
+        assert!(MetricsExporter::bind(&mut b.lock().unwrap(), server).is_ok());
+    }
+}
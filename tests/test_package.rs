@@ -0,0 +1,126 @@
+#[cfg(test)]
+pub mod tests {
+    use ed25519_dalek::SigningKey;
+    use std::collections::BTreeMap;
+    use std::fs;
+    use vaelix_package::vxp_installer::vxp_installer::Installer;
+    use vaelix_package::vxpkg::vxpkg::{self, build_package, Constraint, Manifest, Package, Version};
+    use vaelix_package::vxtoml::vxtoml::{self, Value};
+
+    fn manifest(name: &str, version: Version, depends: &[&str], conflicts: &[&str]) -> Manifest {
+        let mut manifest = Manifest::new(name, version);
+        manifest.depends = depends.iter().map(|text| Constraint::parse(text).unwrap()).collect();
+        manifest.conflicts = conflicts.iter().map(|text| Constraint::parse(text).unwrap()).collect();
+        manifest
+    }
+
+    #[test]
+    pub fn test_vxtoml_manifests_and_archives() {
+        let table = vxtoml::parse("# comment\nname = \"vx\\\"term\"\nsize = 1_024 # bytes\nflags = [true, \"a\", [1, 2],]\n\n[files]\n\"usr/bin/vxterm\" = [3, \"ab\"]\n").unwrap();
+        assert_eq!(table["name"], Value::String("vx\"term".to_string()));
+        assert_eq!(table["size"].as_integer(), Some(1024));
+        assert_eq!(table["flags"].as_array().unwrap().len(), 3);
+        assert_eq!(vxtoml::parse(&vxtoml::serialize(&table).unwrap()).unwrap(), table);
+        assert_eq!(vxtoml::parse("a = 1\na = 2"), Err("Duplicate key"));
+        assert_eq!(vxtoml::parse("a = \"open"), Err("Unterminated string"));
+        assert_eq!(vxtoml::parse("a = [1, 2"), Err("Unterminated array"));
+
+        let constraint = Constraint::parse("libvx >= 1.2.0").unwrap();
+        assert!(constraint.allows(Version(1, 2, 0)) && !constraint.allows(Version(1, 1, 9)));
+        assert_eq!(constraint.to_string(), "libvx >= 1.2.0");
+        assert!(Constraint::parse("libvx ~ 1.0.0").is_err());
+
+        let key = SigningKey::from_bytes(&[3; 32]);
+        let mut term = manifest("vxterm", Version(1, 2, 0), &["libvx >= 1.0.0"], &["oldterm"]);
+        term.description = "Terminal emulator".to_string();
+        let binary = vec![0u8; 4096];
+        let archive = build_package(&key, term.clone(), &[("usr/bin/vxterm", &binary), ("etc/vxterm.conf", b"font=mono\n")]).unwrap();
+        // Zero pages compress to almost nothing
+        assert!(archive.len() < 1024);
+        let package = Package::open(&archive, &[key.verifying_key()]).unwrap();
+        assert_eq!((package.manifest.description.as_str(), package.manifest.depends.clone()), ("Terminal emulator", term.depends.clone()));
+        assert_eq!(package.files["usr/bin/vxterm"], binary);
+        assert_eq!(Manifest::parse(&package.manifest.to_text()).unwrap(), package.manifest);
+
+        assert_eq!(Package::open(&archive, &[SigningKey::from_bytes(&[4; 32]).verifying_key()]), Err("Bad package signature"));
+        let mut tampered = archive.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(Package::open(&tampered, &[key.verifying_key()]).is_err());
+        assert_eq!(build_package(&key, term.clone(), &[("../etc/passwd", b"")]), Err("Invalid file path in package"));
+        assert_eq!(build_package(&key, term, &[("/etc/passwd", b"")]), Err("Invalid file path in package"));
+    }
+
+    #[test]
+    pub fn test_vxpkg_dependency_resolution() {
+        let available = [
+            manifest("libvx", Version(1, 0, 0), &[], &[]),
+            manifest("libvx", Version(2, 0, 0), &[], &[]),
+            manifest("vxfont", Version(1, 0, 0), &["libvx < 2.0.0"], &[]),
+            manifest("vxterm", Version(1, 0, 0), &["libvx", "vxfont"], &[]),
+            manifest("oldterm", Version(0, 9, 0), &[], &["vxterm"]),
+            manifest("vxedit", Version(1, 0, 0), &["libvx >= 2.0.0", "vxfont"], &[]),
+        ];
+        let installed = BTreeMap::new();
+
+        // libvx 2 is newest, but vxfont is pulled in first and pins it below
+        let plan = vxpkg::resolve(&installed, &available, &["vxfont"], &[]).unwrap();
+        let order: Vec<(&str, Version)> = plan.install.iter().map(|manifest| (manifest.name.as_str(), manifest.version)).collect();
+        assert_eq!(order, [("libvx", Version(1, 0, 0)), ("vxfont", Version(1, 0, 0))]);
+
+        let installed: BTreeMap<String, Manifest> = plan.install.into_iter().map(|manifest| (manifest.name.clone(), manifest)).collect();
+        let plan = vxpkg::resolve(&installed, &available, &["vxterm"], &[]).unwrap();
+        assert_eq!(plan.install.iter().map(|manifest| manifest.name.as_str()).collect::<Vec<_>>(), ["vxterm"]);
+        assert_eq!(vxpkg::resolve(&installed, &available, &["vxedit"], &[]), Err("Unsatisfiable dependency"));
+        assert_eq!(vxpkg::resolve(&installed, &available, &["libvx"], &[]).unwrap().install, []);
+        assert_eq!(vxpkg::resolve(&installed, &available, &["nothing"], &[]), Err("No installable version of package"));
+        assert_eq!(vxpkg::resolve(&installed, &available, &[], &["libvx"]), Err("Package is needed by another"));
+        let plan = vxpkg::resolve(&installed, &available, &[], &["vxfont", "libvx"]).unwrap();
+        assert_eq!(plan.remove, ["vxfont", "libvx"]);
+
+        let mut with_term = installed.clone();
+        with_term.insert("vxterm".to_string(), available[3].clone());
+        assert_eq!(vxpkg::resolve(&with_term, &available, &["oldterm"], &[]), Err("Conflicting packages"));
+        assert_eq!(vxpkg::resolve(&with_term, &available, &["oldterm"], &["vxterm"]).unwrap().install[0].name, "oldterm");
+    }
+
+    #[test]
+    pub fn test_vxpkg_transactions_roll_back() {
+        let root = std::env::temp_dir().join(format!("vaelix-vxpkg-{}", std::process::id()));
+        let root_str = root.to_str().unwrap();
+        let key = SigningKey::from_bytes(&[5; 32]);
+        let package = |name: &str, version: Version, depends: &[&str], files: &[(&str, &[u8])]| build_package(&key, manifest(name, version, depends, &[]), files).unwrap();
+        let mut installer = Installer::open(root_str, vec![key.verifying_key()]).unwrap();
+        installer.add_package(&package("libvx", Version(1, 0, 0), &[], &[("usr/lib/libvx.so", b"v1"), ("etc/vx/old.conf", b"old")])).unwrap();
+        installer.add_package(&package("vxapp", Version(1, 0, 0), &["libvx"], &[("usr/bin/vxapp", b"app v1")])).unwrap();
+        installer.install(&["vxapp"]).unwrap();
+        assert_eq!(fs::read(root.join("usr/lib/libvx.so")).unwrap(), b"v1");
+        assert_eq!(Installer::open(root_str, vec![]).unwrap().installed().keys().collect::<Vec<_>>(), ["libvx", "vxapp"]);
+
+        // The upgrade gets as far as libvx 2 before vxapp 2's file runs into
+        // a directory; everything has to be as it was
+        installer.add_package(&package("libvx", Version(2, 0, 0), &[], &[("usr/lib/libvx.so", b"v2")])).unwrap();
+        installer.add_package(&package("vxapp", Version(2, 0, 0), &["libvx >= 2.0.0"], &[("etc/vx", b"oops"), ("usr/bin/vxapp", b"app v2")])).unwrap();
+        assert_eq!(installer.upgrade(), Err("Package transaction failed"));
+        assert_eq!(fs::read(root.join("usr/lib/libvx.so")).unwrap(), b"v1");
+        assert_eq!(fs::read(root.join("etc/vx/old.conf")).unwrap(), b"old");
+        assert_eq!(fs::read(root.join("usr/bin/vxapp")).unwrap(), b"app v1");
+        let reopened = Installer::open(root_str, vec![]).unwrap();
+        assert_eq!(reopened.installed()["libvx"].version, Version(1, 0, 0));
+        assert_eq!(installer.installed()["vxapp"].version, Version(1, 0, 0));
+
+        // Files of one package may not be taken over by another
+        installer.add_package(&package("thief", Version(1, 0, 0), &[], &[("usr/bin/vxapp", b"mine")])).unwrap();
+        assert_eq!(installer.install(&["thief"]), Err("File belongs to another package"));
+
+        installer.add_package(&package("vxapp", Version(3, 0, 0), &["libvx >= 2.0.0"], &[("usr/bin/vxapp", b"app v3")])).unwrap();
+        let plan = installer.upgrade().unwrap();
+        assert_eq!(plan.install.iter().map(|manifest| manifest.version).collect::<Vec<_>>(), [Version(2, 0, 0), Version(3, 0, 0)]);
+        assert!(!root.join("etc/vx/old.conf").exists());
+        assert_eq!(fs::read(root.join("usr/bin/vxapp")).unwrap(), b"app v3");
+
+        installer.remove(&["vxapp", "libvx"]).unwrap();
+        assert!(!root.join("usr/lib/libvx.so").exists() && !root.join("usr/bin/vxapp").exists());
+        assert!(Installer::open(root_str, vec![]).unwrap().installed().is_empty());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
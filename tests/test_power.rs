@@ -0,0 +1,811 @@
+#[cfg(test)]
+pub mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::time::{Duration, Instant};
+    use vaelix_core::drivers::block::block::BlockDevice;
+    use vaelix_core::drivers::msr::msr::{BootCpu, MsrIo, MsrSpace};
+    use vaelix_core::power::button::button::{self, ButtonKind, ButtonManager, PowerEvent, StatusSource};
+    use vaelix_core::power::dpms::dpms::{DisplayState, IdleTimeouts, IdleTracker, PanelPower};
+    use vaelix_core::power::fan::fan::{FanConfig, FanControlService, FanCurve, FanDriver};
+    use vaelix_core::power::hibernate::hibernate::{self, MemoryImage, MemoryRegion, SwapArea};
+    use vaelix_core::power::idle::idle::{default_cstates, CState, IdleDriver, IdleGovernor, LatencyConstraints};
+    use vaelix_core::power::policy::policy::{
+        policy_manager, EventActions, LidAction, ModeChange, PerformanceCap, PerformanceScaling, PerformanceTarget,
+        PolicyManager, PolicyMode, PowerLimits, ThermalAction,
+    };
+    use vaelix_core::power::profile::profile::{self, PolicyProfile, ProfileChange, ProfileManager, ProfileService};
+    use vaelix_core::power::pstate::pstate::{HwpRequest, PStateDriver, ScalingMethod};
+    use vaelix_core::power::rapl::rapl::{self, RaplMeter};
+    use vaelix_core::power::runtime::runtime::{DeviceClass, DevicePowerState, RuntimePm, RuntimePower};
+    use vaelix_core::power::thermal::thermal::{TemperatureSensor, ThermalZone, TripPoint, TripType};
+    use vaelix_core::topology::topology::{CacheKind, CoreType, Topology};
+    use vaelix_core::vx_tasklet_init;
+    use vaelix_core::vxboot::vxboot::resume_from_hibernation;
+    use vaelix_core::vxchan::vxchan::VXChanManager;
+
+    struct RecordingFan {
+        duty: Mutex<u8>,
+    }
+
+    impl FanDriver for RecordingFan {
+        fn set_duty(&self, percent: u8) -> Result<(), &'static str> {
+            *self.duty.lock().unwrap() = percent;
+            Ok(())
+        }
+
+        fn rpm(&self) -> Result<u32, &'static str> {
+            Ok(*self.duty.lock().unwrap() as u32 * 50)
+        }
+    }
+
+    #[test]
+    pub fn test_fan_curve_interpolation() {
+        let curve = FanCurve::new(vec![(40, 20), (60, 60), (80, 100)]).unwrap();
+        assert_eq!(curve.duty_for(30), 20);
+        assert_eq!(curve.duty_for(50), 40);
+        assert_eq!(curve.duty_for(70), 80);
+        assert_eq!(curve.duty_for(90), 100);
+        assert!(FanCurve::new(vec![(60, 50), (40, 60)]).is_err());
+    }
+
+    #[test]
+    pub fn test_fan_hysteresis_and_failsafe() {
+        let fan = Arc::new(RecordingFan { duty: Mutex::new(0) });
+        let config = FanConfig {
+            curve: FanCurve::new(vec![(40, 20), (60, 60), (80, 100)]).unwrap(),
+            hysteresis: 5,
+            failsafe_temperature: 90,
+            zone: None,
+        };
+        let service = FanControlService::new(fan.clone(), config);
+
+        assert_eq!(service.update(Ok(60)).duty, 60);
+        // A small drop keeps the duty, a drop beyond the hysteresis lowers it
+        assert_eq!(service.update(Ok(57)).duty, 60);
+        assert_eq!(service.update(Ok(50)).duty, 40);
+
+        // Failsafe throttles on top of the thermal policy and hands its level back
+        policy_manager().throttle_components(1);
+        let status = service.update(Err("sensor offline"));
+        assert!(status.failsafe);
+        assert_eq!(*fan.duty.lock().unwrap(), 100);
+        assert_eq!(policy_manager().component_snapshot().throttle_level, 2);
+        let status = service.update(Ok(50));
+        assert!(!status.failsafe);
+        assert_eq!(status.duty, 40);
+        assert_eq!(policy_manager().component_snapshot().throttle_level, 1);
+
+        // Unless the thermal policy has moved it on in the meantime
+        service.update(Err("sensor offline"));
+        policy_manager().throttle_components(3);
+        service.update(Ok(50));
+        assert_eq!(policy_manager().component_snapshot().throttle_level, 3);
+        policy_manager().throttle_components(0);
+    }
+
+    #[test]
+    pub fn test_policy_mode_changes_and_snapshot() {
+        let manager = PolicyManager::new(PolicyMode::Performance);
+        let events = manager.subscribe();
+        assert!(manager.component_snapshot().turbo_enabled);
+
+        manager.set_mode(PolicyMode::PowerSaver);
+        manager.set_mode(PolicyMode::PowerSaver);
+        assert_eq!(manager.current_mode(), PolicyMode::PowerSaver);
+        assert_eq!(
+            events.try_recv(),
+            Ok(ModeChange {
+                previous: PolicyMode::Performance,
+                current: PolicyMode::PowerSaver,
+            })
+        );
+        assert!(events.try_recv().is_err());
+
+        let snapshot = manager.component_snapshot();
+        assert!(!snapshot.turbo_enabled);
+        manager.throttle_components(2);
+        assert_eq!((snapshot.throttle_level, manager.component_snapshot().throttle_level), (0, 2));
+
+        // Concurrent switches arrive in the order they were made, each
+        // starting from the mode the one before it left
+        let manager = Arc::new(manager);
+        let modes = [PolicyMode::Performance, PolicyMode::Balanced, PolicyMode::PowerSaver];
+        let workers: Vec<_> = (0..4)
+            .map(|worker| {
+                let manager = manager.clone();
+                std::thread::spawn(move || {
+                    for step in 0..2000 {
+                        manager.set_mode(modes[(worker + step) % modes.len()]);
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        let mut mode = PolicyMode::PowerSaver;
+        for change in events.try_iter() {
+            assert_eq!(change.previous, mode);
+            mode = change.current;
+        }
+        assert_eq!(mode, manager.current_mode());
+    }
+
+    #[test]
+    pub fn test_hwp_hints_follow_policy_and_legacy_fallback() {
+        // A hybrid part: one P-core, one E-core, both with HWP and EPP
+        let msr = MsrSpace::new(2);
+        msr.set_cpuid(6, ((1 << 7) | (1 << 10), 0, 0, 0));
+        msr.wrmsr(0, 0x771, 0x0A_14_24_30).unwrap();
+        msr.wrmsr(1, 0x771, 0x06_0C_18_20).unwrap();
+        let driver = PStateDriver::init(msr.clone()).unwrap();
+        assert!(matches!(driver.method(), ScalingMethod::Hwp { epp: true, .. }));
+        assert_eq!(msr.rdmsr(1, 0x770), Ok(1));
+
+        let manager = PolicyManager::new(PolicyMode::Performance);
+        manager.set_scaling_driver(Box::new(driver));
+        let request = HwpRequest::decode(msr.rdmsr(0, 0x774).unwrap());
+        assert_eq!(request, HwpRequest { min: 0x14, max: 0x30, desired: 0, epp: 0x00 });
+        assert_eq!(manager.component_snapshot().cpu_frequency_mhz, 4800);
+
+        // Power saving drops turbo (max falls to guaranteed) and biases EPP
+        manager.set_mode(PolicyMode::PowerSaver);
+        let request = HwpRequest::decode(msr.rdmsr(1, 0x774).unwrap());
+        assert_eq!(request, HwpRequest { min: 0x06, max: 0x18, desired: 0, epp: 0xC0 });
+        assert_eq!(manager.component_snapshot().cpu_frequency_mhz, 3600);
+
+        // Without HWP the ratio goes to IA32_PERF_CTL and turbo is fused off
+        let legacy = MsrSpace::new(1);
+        legacy.wrmsr(0, 0xCE, (8 << 40) | (28 << 8)).unwrap();
+        legacy.wrmsr(0, 0x1AD, 40).unwrap();
+        let mut driver = PStateDriver::init(legacy.clone()).unwrap();
+        let target = PerformanceTarget {
+            mode: PolicyMode::Balanced,
+            limit_percent: 100,
+            turbo: false,
+            park_efficiency_cores: false,
+        };
+        assert_eq!(driver.apply(target), Ok(2300));
+        assert_eq!(legacy.rdmsr(0, 0x199), Ok(23 << 8));
+        assert_ne!(legacy.rdmsr(0, 0x1A0).unwrap() & (1 << 38), 0);
+        assert!(PStateDriver::init(MsrSpace::new(1)).is_err());
+    }
+
+    // Wakes after a fixed period as a periodic interrupt would
+    struct PeriodicWake(Duration);
+
+    impl IdleDriver for PeriodicWake {
+        fn enter(&self, _core: usize, _state: &CState, deadline: Duration) -> Duration {
+            std::thread::sleep(Duration::from_millis(1));
+            self.0.min(deadline)
+        }
+    }
+
+    #[test]
+    pub fn test_idle_governor_prediction_and_latency_limits() {
+        let constraints = Arc::new(LatencyConstraints::new());
+        let governor = IdleGovernor::new(default_cstates(), 2, Arc::clone(&constraints));
+        assert_eq!(governor.select(0, Some(5_000)), 2);
+        assert_eq!(governor.select(0, Some(3)), 0);
+        assert_eq!(governor.select(0, Some(10)), 1);
+
+        // Audio asks for 200us wakeups, which rules out C6
+        constraints.request("audio", 200);
+        constraints.request("usb", 500);
+        assert_eq!(constraints.current(), Some(200));
+        assert_eq!(governor.select(0, Some(5_000)), 1);
+        constraints.release("audio");
+        assert_eq!(governor.select(0, Some(5_000)), 2);
+        constraints.release("usb");
+
+        // A steady 100us interrupt overrides the far-off timer
+        let driver = PeriodicWake(Duration::from_micros(100));
+        for _ in 0..4 {
+            governor.idle(1, None, &driver);
+        }
+        assert_eq!(governor.predict(1, None), 100);
+        assert_eq!(governor.select(1, Some(5_000)), 1);
+        assert_eq!(governor.predict(0, None), 1_000_000);
+        let usage = governor.usage(1);
+        // Too little history to trust until the fourth wakeup
+        assert_eq!((usage[1].entries, usage[2].entries), (0, 4));
+        assert_eq!(usage.iter().map(|state| state.residency_us).sum::<u64>(), 400);
+
+        // The tasklet scheduler hands empty-queue time to the governor
+        let scheduler = vx_tasklet_init();
+        let (tx, rx) = mpsc::channel();
+        scheduler.set_idle_handler(Box::new(move || {
+            let slept = governor.idle(0, Some(2_000), &driver);
+            let _ = tx.send(slept);
+        }));
+        assert_eq!(rx.recv_timeout(Duration::from_secs(1)), Ok(Duration::from_micros(100)));
+    }
+
+    struct RamDisk(Mutex<Vec<u8>>);
+
+    impl BlockDevice for RamDisk {
+        fn block_size(&self) -> usize {
+            512
+        }
+
+        fn block_count(&self) -> u64 {
+            self.0.lock().unwrap().len() as u64 / 512
+        }
+
+        fn read_blocks(&self, lba: u64, buffer: &mut [u8]) -> Result<(), &'static str> {
+            let start = lba as usize * 512;
+            buffer.copy_from_slice(&self.0.lock().unwrap()[start..start + buffer.len()]);
+            Ok(())
+        }
+
+        fn write_blocks(&self, lba: u64, buffer: &[u8]) -> Result<(), &'static str> {
+            let start = lba as usize * 512;
+            self.0.lock().unwrap()[start..start + buffer.len()].copy_from_slice(buffer);
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct FakeMemory(Mutex<Vec<MemoryRegion>>);
+
+    impl MemoryImage for FakeMemory {
+        fn snapshot(&self) -> Vec<MemoryRegion> {
+            self.0.lock().unwrap().clone()
+        }
+
+        fn restore(&self, region: &MemoryRegion) -> Result<(), &'static str> {
+            self.0.lock().unwrap().push(region.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    pub fn test_hibernate_image_round_trip() {
+        let data = vec![0, 0, 0, 0, 7, 1, 2, 2, 9, 9, 9, 9, 9];
+        assert_eq!(hibernate::decompress(&hibernate::compress(&data)), Ok(data));
+
+        let disk = Arc::new(RamDisk(Mutex::new(vec![0; 512 * 64])));
+        let area = SwapArea::new(disk.clone(), 32, 32).unwrap();
+        assert!(SwapArea::new(disk.clone(), 40, 32).is_err());
+        let pattern: Vec<u8> = (0..4096u32).map(|i| (i % 251) as u8).collect();
+        let memory = FakeMemory(Mutex::new(vec![
+            MemoryRegion {
+                base: 0x10_0000,
+                data: vec![0; 64 * 4096],
+            },
+            MemoryRegion {
+                base: 0x20_0000,
+                data: pattern,
+            },
+        ]));
+        let header = hibernate::hibernate(&area, &memory).unwrap();
+        assert!(header.image_size < header.raw_size / 16);
+        assert_eq!(area.read_header(), Ok(Some(header)));
+
+        // The next boot finds the image, restores it and consumes it
+        let restored = FakeMemory::default();
+        assert!(resume_from_hibernation(&area, &restored).unwrap());
+        assert_eq!(*restored.0.lock().unwrap(), memory.snapshot());
+        assert_eq!(area.read_header(), Ok(None));
+        assert!(!resume_from_hibernation(&area, &restored).unwrap());
+
+        // A damaged image is refused and discarded
+        hibernate::hibernate(&area, &memory).unwrap();
+        disk.0.lock().unwrap()[33 * 512 + 40] ^= 0xFF;
+        assert!(hibernate::resume(&area, &FakeMemory::default()).is_err());
+        assert_eq!(area.read_header(), Ok(None));
+
+        let small = SwapArea::new(disk, 0, 2).unwrap();
+        assert!(hibernate::hibernate(&small, &memory).is_err());
+    }
+
+    struct RecordingHook(Arc<Mutex<Vec<DevicePowerState>>>);
+
+    impl RuntimePower for RecordingHook {
+        fn set_power_state(&mut self, state: DevicePowerState) -> Result<(), &'static str> {
+            self.0.lock().unwrap().push(state);
+            Ok(())
+        }
+    }
+
+    #[test]
+    pub fn test_runtime_pm_autosuspend_and_resume_on_access() {
+        let pm = RuntimePm::new();
+        let wifi = Arc::new(Mutex::new(Vec::new()));
+        let gpu = Arc::new(Mutex::new(Vec::new()));
+        pm.register("wifi0", DeviceClass::Wifi, Box::new(RecordingHook(wifi.clone()))).unwrap();
+        pm.register("gpu0", DeviceClass::Gpu, Box::new(RecordingHook(gpu.clone()))).unwrap();
+        assert!(pm.register("gpu0", DeviceClass::Gpu, Box::new(RecordingHook(gpu.clone()))).is_err());
+
+        // Only the WiFi delay has run out
+        let later = Instant::now() + Duration::from_secs(3);
+        assert_eq!(pm.poll(later), vec!["wifi0".to_string()]);
+        assert_eq!(pm.state("wifi0"), Some(DevicePowerState::D3Hot));
+        assert_eq!(pm.state("gpu0"), Some(DevicePowerState::D0));
+
+        // Access resumes it, and a held reference blocks autosuspend
+        pm.get("wifi0").unwrap();
+        assert_eq!(*wifi.lock().unwrap(), vec![DevicePowerState::D3Hot, DevicePowerState::D0]);
+        assert_eq!(pm.usage("wifi0"), Some(1));
+        let much_later = Instant::now() + Duration::from_secs(60);
+        assert_eq!(pm.poll(much_later), vec!["gpu0".to_string()]);
+        pm.put("wifi0").unwrap();
+        assert!(pm.put("wifi0").is_err());
+        assert_eq!(pm.poll(much_later), vec!["wifi0".to_string()]);
+
+        // Pinning the GPU on brings it back and keeps it there
+        pm.set_allowed("gpu0", false).unwrap();
+        assert_eq!(pm.state("gpu0"), Some(DevicePowerState::D0));
+        assert!(pm.poll(much_later + Duration::from_secs(60)).is_empty());
+        pm.unregister("wifi0").unwrap();
+        assert_eq!(wifi.lock().unwrap().last(), Some(&DevicePowerState::D0));
+    }
+
+    // Asks the manager about its device mid-transition, as a driver
+    // taking a reference from its own resume path would
+    struct ReentrantHook(Arc<RuntimePm>, Arc<Mutex<Vec<Option<u32>>>>);
+
+    impl RuntimePower for ReentrantHook {
+        fn set_power_state(&mut self, _state: DevicePowerState) -> Result<(), &'static str> {
+            self.1.lock().unwrap().push(self.0.usage("audio0"));
+            Ok(())
+        }
+    }
+
+    #[test]
+    pub fn test_runtime_pm_hooks_run_unlocked() {
+        let pm = Arc::new(RuntimePm::new());
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        pm.register("audio0", DeviceClass::Audio, Box::new(ReentrantHook(pm.clone(), seen.clone()))).unwrap();
+        let later = Instant::now() + Duration::from_secs(2);
+        assert_eq!(pm.poll(later), vec!["audio0".to_string()]);
+        // The reference is already held while the device resumes
+        pm.get("audio0").unwrap();
+        pm.put("audio0").unwrap();
+        assert_eq!(pm.poll(later + Duration::from_secs(2)), vec!["audio0".to_string()]);
+        pm.set_allowed("audio0", false).unwrap();
+        assert_eq!(pm.poll(later + Duration::from_secs(4)), Vec::<String>::new());
+        pm.set_allowed("audio0", true).unwrap();
+        assert_eq!(pm.poll(later + Duration::from_secs(6)), vec!["audio0".to_string()]);
+        pm.unregister("audio0").unwrap();
+        assert_eq!(*seen.lock().unwrap(), vec![Some(0), Some(1), Some(0), Some(0), Some(0), None]);
+    }
+
+    struct FixedSensor;
+
+    impl TemperatureSensor for FixedSensor {
+        fn read_millicelsius(&self) -> Result<i32, &'static str> {
+            Ok(40_000)
+        }
+    }
+
+    #[test]
+    pub fn test_thermal_trips_escalate_with_hysteresis() {
+        // Hybrid part: CPU 0 is a P-core, CPU 1 an E-core
+        let msr = MsrSpace::new(2);
+        msr.set_cpuid(6, ((1 << 7) | (1 << 10), 0, 0, 0));
+        msr.set_cpu_cpuid(0, 0x1A, (0x40 << 24, 0, 0, 0));
+        msr.set_cpu_cpuid(1, 0x1A, (0x20 << 24, 0, 0, 0));
+        msr.wrmsr(0, 0x771, 0x0A_14_24_30).unwrap();
+        msr.wrmsr(1, 0x771, 0x06_0C_18_20).unwrap();
+        let manager = PolicyManager::new(PolicyMode::Performance);
+        manager.set_scaling_driver(Box::new(PStateDriver::init(msr.clone()).unwrap()));
+        let shutdowns = Arc::new(Mutex::new(Vec::new()));
+        let recorded = shutdowns.clone();
+        manager.set_critical_handler(Box::new(move |zone, temp| recorded.lock().unwrap().push((zone.to_string(), temp))));
+
+        let cpu = ThermalZone::new("cpu", Arc::new(FixedSensor));
+        let skin = ThermalZone::new("skin", Arc::new(FixedSensor));
+        assert_eq!(cpu.trip_points()[0].temperature, 85);
+        assert_eq!(manager.evaluate_policy(&cpu, 70), ThermalAction::Normal);
+
+        // Passive: turbo goes first, then the frequency range shrinks
+        assert_eq!(manager.evaluate_policy(&cpu, 86), ThermalAction::CapTurbo);
+        let snapshot = manager.component_snapshot();
+        assert!(!snapshot.turbo_enabled);
+        assert_eq!(HwpRequest::decode(msr.rdmsr(0, 0x774).unwrap()).max, 0x24);
+        assert_eq!(manager.evaluate_policy(&cpu, 87), ThermalAction::CapFrequency(2));
+
+        // Inside the hysteresis band the level holds; a cool zone elsewhere
+        // does not release a hot one
+        assert_eq!(manager.evaluate_policy(&cpu, 82), ThermalAction::CapFrequency(2));
+        assert_eq!(manager.evaluate_policy(&skin, 40), ThermalAction::CapFrequency(2));
+
+        // Hot parks the E-core at its floor with maximum power bias
+        assert_eq!(manager.evaluate_policy(&cpu, 96), ThermalAction::ParkEfficiencyCores);
+        assert!(manager.component_snapshot().efficiency_cores_parked);
+        let parked = HwpRequest::decode(msr.rdmsr(1, 0x774).unwrap());
+        assert_eq!((parked.min, parked.max, parked.epp), (0x06, 0x06, 0xFF));
+        assert_eq!(manager.evaluate_policy(&cpu, 92), ThermalAction::ParkEfficiencyCores);
+
+        // Cooling below every band unparks and steps down one level a reading
+        assert_eq!(manager.evaluate_policy(&cpu, 79), ThermalAction::CapFrequency(3));
+        assert!(!manager.component_snapshot().efficiency_cores_parked);
+        assert_eq!(manager.evaluate_policy(&cpu, 60), ThermalAction::CapFrequency(2));
+        assert_eq!(manager.evaluate_policy(&cpu, 60), ThermalAction::CapTurbo);
+        assert_eq!(manager.evaluate_policy(&cpu, 60), ThermalAction::Normal);
+        assert!(manager.component_snapshot().turbo_enabled);
+
+        // Critical fires the handler once
+        skin.set_trip_points(vec![TripPoint {
+            kind: TripType::Critical,
+            temperature: 50,
+            hysteresis: 0,
+        }]);
+        assert_eq!(manager.evaluate_policy(&skin, 55), ThermalAction::EmergencyShutdown);
+        assert_eq!(manager.evaluate_policy(&skin, 56), ThermalAction::EmergencyShutdown);
+        assert_eq!(*shutdowns.lock().unwrap(), vec![("skin".to_string(), 55)]);
+    }
+
+    #[test]
+    pub fn test_rapl_energy_and_core_activity() {
+        // CPU 0 a P-core and CPU 1 an E-core, with three fixed counters
+        let msr = MsrSpace::new(2);
+        msr.set_cpuid(0, (0x1A, 0, 0, 0));
+        msr.set_cpuid(0x0A, (4, 0, 0, 3));
+        msr.set_cpu_cpuid(0, 0x1A, (0x40 << 24, 0, 0, 0));
+        msr.set_cpu_cpuid(1, 0x1A, (0x20 << 24, 0, 0, 0));
+        msr.set_cpu_cpuid(1, 1, (0, 1 << 24, 0, 0));
+        // 2^-14 J energy units
+        msr.wrmsr(0, 0x606, 14 << 8).unwrap();
+        msr.wrmsr(0, 0x611, 0xFFFF_F000).unwrap();
+        for cpu in 0..2 {
+            msr.wrmsr(cpu, 0x10, 1_000).unwrap();
+            msr.wrmsr(cpu, 0xE7, 0).unwrap();
+            msr.wrmsr(cpu, 0x309, 0).unwrap();
+            msr.wrmsr(cpu, 0x38D, 0xB0).unwrap();
+        }
+        // Near the top of its 48 bits
+        msr.wrmsr(0, 0x309, (1 << 48) - 100_000).unwrap();
+        let mut meter = RaplMeter::new(msr.clone()).unwrap();
+
+        // Fixed counter 0 counts in both rings, and the others are untouched
+        for cpu in 0..2 {
+            assert_eq!(msr.rdmsr(cpu, 0x38D), Ok(0xB3));
+            assert_eq!(msr.rdmsr(cpu, 0x38F), Ok(1 << 32));
+        }
+
+        // The package counter wraps: 0x1000 + 0x3000 units is one joule
+        msr.wrmsr(0, 0x611, 0x3000).unwrap();
+        msr.wrmsr(0, 0x10, 11_000).unwrap();
+        msr.wrmsr(0, 0xE7, 7_500).unwrap();
+        msr.wrmsr(0, 0x309, 300_000).unwrap();
+        msr.wrmsr(1, 0x10, 11_000).unwrap();
+        msr.wrmsr(1, 0xE7, 2_000).unwrap();
+        msr.wrmsr(1, 0x309, 100_000).unwrap();
+        let sample = meter.sample().unwrap();
+        assert_eq!(sample.package_uj, 1_000_000);
+        assert_eq!(sample.cores_uj, None);
+        assert_eq!((sample.cores[0].utilization, sample.cores[1].utilization), (75, 20));
+        assert_eq!(sample.instructions(), 500_000);
+        assert_eq!(sample.nanojoules_per_instruction(), Some(2_000));
+        // The busy time splits the joule 75:20
+        assert_eq!(sample.nanojoules_per_instruction_on(&[0]), Some(1_973));
+        assert_eq!(sample.nanojoules_per_instruction_on(&[1]), Some(2_105));
+
+        // PowerSaver goes where the instructions were cheapest, the other
+        // modes to the least busy CPU in preference order
+        let topology = Topology::discover(&msr);
+        assert_eq!(rapl::select_target_core(&topology, PolicyMode::PowerSaver, Some(&sample)), Some(0));
+        assert_eq!(rapl::select_target_core(&topology, PolicyMode::Balanced, Some(&sample)), Some(1));
+        assert_eq!(rapl::select_target_core(&topology, PolicyMode::PowerSaver, None), Some(0));
+        // Retiring far more per joule, the E-core takes over even though
+        // it is now the busier one
+        msr.wrmsr(0, 0x611, 0x7000).unwrap();
+        msr.wrmsr(0, 0x10, 21_000).unwrap();
+        msr.wrmsr(0, 0xE7, 9_500).unwrap();
+        msr.wrmsr(0, 0x309, 400_000).unwrap();
+        msr.wrmsr(1, 0x10, 21_000).unwrap();
+        msr.wrmsr(1, 0xE7, 11_000).unwrap();
+        msr.wrmsr(1, 0x309, 3_100_000).unwrap();
+        let sample = meter.sample().unwrap();
+        assert_eq!(rapl::select_target_core(&topology, PolicyMode::PowerSaver, Some(&sample)), Some(1));
+        assert_eq!(rapl::select_target_core(&topology, PolicyMode::Performance, Some(&sample)), Some(0));
+
+        assert_eq!(meter.sample().unwrap().nanojoules_per_instruction(), None);
+        assert!(RaplMeter::new(MsrSpace::new(1)).is_err());
+    }
+
+    #[test]
+    pub fn test_policy_profiles_persist_and_switch_over_rpc() {
+        let path = std::env::temp_dir().join(format!("vxpower-profiles-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+        let policy = PolicyManager::new(PolicyMode::Performance);
+        let profiles = ProfileManager::load(path).unwrap();
+        assert_eq!(profiles.active(), "ac");
+        let changes = profiles.subscribe();
+
+        profiles.switch("battery", &policy).unwrap();
+        assert_eq!(policy.current_mode(), PolicyMode::PowerSaver);
+        assert_eq!(
+            policy.performance_cap(),
+            PerformanceCap {
+                turbo: false,
+                limit_percent: 80,
+            }
+        );
+        assert_eq!(
+            changes.try_recv(),
+            Ok(ProfileChange {
+                previous: "ac".to_string(),
+                current: "battery".to_string(),
+            })
+        );
+        assert!(profiles.switch("gaming", &policy).is_err());
+        assert!(profiles.remove_profile("battery").is_err());
+
+        // Settings UI over vxchan
+        let manager = VXChanManager::new();
+        let mut service = ProfileService::new(&manager, &profiles).unwrap();
+        let requests = ["set quiet balanced off 60", "switch quiet", "current", "show", "list", "switch"];
+        for (index, request) in requests.iter().enumerate() {
+            profile::send_request(&manager, index as u64 + 1, request).unwrap();
+        }
+        assert_eq!(service.poll(&manager, &profiles, &policy).unwrap(), requests.len());
+        let replies: Vec<String> = (0..requests.len())
+            .map(|_| manager.receive_message(profile::REPLY_CHANNEL).unwrap())
+            .collect();
+        assert_eq!(replies[0], "1 ok");
+        assert_eq!(replies[1], "2 ok");
+        assert!(replies[2].starts_with("3 ok\nprofile=quiet mode=balanced turbo=off limit=60 frequency="));
+        assert_eq!(replies[3], "4 ok\nquiet mode=balanced turbo=off limit=60");
+        assert_eq!(replies[4], "5 ok\nac\nbattery\nquiet");
+        assert_eq!(replies[5], "6 error Unknown command");
+        assert_eq!(manager.try_receive_message(profile::EVENT_CHANNEL), Ok(Some("profile quiet".to_string())));
+
+        // Everything survives a reload
+        let reloaded = ProfileManager::load(path).unwrap();
+        assert_eq!(reloaded.active(), "quiet");
+        assert_eq!(
+            reloaded.get("quiet"),
+            Some(PolicyProfile {
+                mode: PolicyMode::Balanced,
+                cap: PerformanceCap {
+                    turbo: false,
+                    limit_percent: 60,
+                },
+            })
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    struct Switch(Arc<AtomicBool>);
+
+    impl StatusSource for Switch {
+        fn read(&self) -> Result<bool, &'static str> {
+            Ok(self.0.load(Ordering::SeqCst))
+        }
+    }
+
+    #[test]
+    pub fn test_ac_and_lid_events_drive_profiles_and_suspend() {
+        let manager = VXChanManager::new();
+        let policy = PolicyManager::new(PolicyMode::Balanced);
+        let profiles = ProfileManager::new(None);
+        let mut buttons = ButtonManager::new(&manager).unwrap();
+        let (ac, lid) = (Arc::new(AtomicBool::new(true)), Arc::new(AtomicBool::new(true)));
+        let suspends = Arc::new(AtomicUsize::new(0));
+        let counter = suspends.clone();
+        buttons.set_suspend_handler(Box::new(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        }));
+
+        let ac_source: Box<dyn StatusSource> = Box::new(Switch(ac.clone()));
+        assert_eq!(buttons.add_device("\\_SB.AC", "ACPI0003", Some(ac_source)), Ok(Some(ButtonKind::AcAdapter)));
+        buttons.add_device("\\_SB.LID0", "PNP0C0D", Some(Box::new(Switch(lid.clone())))).unwrap();
+        buttons.add_device("\\_SB.PWRB", "PNP0C0C", None).unwrap();
+        assert_eq!(buttons.add_device("\\_SB.BAT0", "PNP0C0A", None), Ok(None));
+        assert!(buttons.add_device("\\_SB.LID1", "PNP0C0D", None).is_err());
+        assert!(buttons.process(&manager, &policy, &profiles).unwrap().is_empty());
+
+        // Unplugging switches to the battery profile and is broadcast
+        ac.store(false, Ordering::SeqCst);
+        assert_eq!(buttons.process(&manager, &policy, &profiles).unwrap(), vec![PowerEvent::AcUnplugged]);
+        assert_eq!(profiles.active(), "battery");
+        assert_eq!(policy.current_mode(), PolicyMode::PowerSaver);
+        assert_eq!(manager.try_receive_message(button::EVENT_CHANNEL), Ok(Some("ac unplugged".to_string())));
+        assert_eq!(buttons.state(ButtonKind::AcAdapter), Some(false));
+
+        lid.store(false, Ordering::SeqCst);
+        buttons.process(&manager, &policy, &profiles).unwrap();
+        assert_eq!(suspends.load(Ordering::SeqCst), 1);
+
+        // With the actions turned off only the events go out
+        policy.set_event_actions(EventActions {
+            lid_close: LidAction::Ignore,
+            ac_profiles: false,
+        });
+        lid.store(true, Ordering::SeqCst);
+        ac.store(true, Ordering::SeqCst);
+        buttons.process(&manager, &policy, &profiles).unwrap();
+        lid.store(false, Ordering::SeqCst);
+        buttons.process(&manager, &policy, &profiles).unwrap();
+        assert_eq!((suspends.load(Ordering::SeqCst), profiles.active()), (1, "battery".to_string()));
+
+        let press = buttons.pressed("\\_SB.PWRB").unwrap();
+        buttons.handle(press, &manager, &policy, &profiles).unwrap();
+        let mut events = Vec::new();
+        while let Ok(Some(event)) = manager.try_receive_message(button::EVENT_CHANNEL) {
+            events.push(event);
+        }
+        assert_eq!(events, ["lid closed", "ac plugged", "lid open", "lid closed", "button power"]);
+        assert_eq!(buttons.pressed("\\_SB.AC"), None);
+    }
+
+    // (brightness, panel on)
+    struct FakePanel(Arc<Mutex<(u8, bool)>>);
+
+    impl PanelPower for FakePanel {
+        fn brightness(&self) -> u8 {
+            self.0.lock().unwrap().0
+        }
+
+        fn set_brightness(&mut self, percent: u8) -> Result<(), &'static str> {
+            self.0.lock().unwrap().0 = percent;
+            Ok(())
+        }
+
+        fn set_panel_power(&mut self, on: bool) -> Result<(), &'static str> {
+            self.0.lock().unwrap().1 = on;
+            Ok(())
+        }
+    }
+
+    #[test]
+    pub fn test_idle_tracker_dims_blanks_and_suspends() {
+        let panel = Arc::new(Mutex::new((80, true)));
+        let start = Instant::now();
+        let timeouts = IdleTimeouts {
+            dim: Some(Duration::from_secs(60)),
+            blank: Some(Duration::from_secs(120)),
+            suspend: Some(Duration::from_secs(600)),
+            dim_brightness: 20,
+        };
+        let mut tracker = IdleTracker::new(Box::new(FakePanel(panel.clone())), timeouts, start);
+        let suspends = Arc::new(AtomicUsize::new(0));
+        let counter = suspends.clone();
+        tracker.set_suspend_handler(Box::new(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        }));
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert_eq!(tracker.tick(at(30)), Ok(DisplayState::On));
+        assert_eq!(tracker.tick(at(61)), Ok(DisplayState::Dimmed));
+        assert_eq!(*panel.lock().unwrap(), (20, true));
+
+        // Input restores the saved brightness and restarts the timers
+        tracker.input_activity(at(90)).unwrap();
+        assert_eq!((tracker.state(), *panel.lock().unwrap()), (DisplayState::On, (80, true)));
+        assert_eq!(tracker.tick(at(149)), Ok(DisplayState::On));
+
+        // A late tick still dims before it blanks
+        assert_eq!(tracker.tick(at(400)), Ok(DisplayState::Dimmed));
+        assert_eq!(tracker.tick(at(400)), Ok(DisplayState::Blanked));
+        assert_eq!(*panel.lock().unwrap(), (20, false));
+        assert_eq!(tracker.tick(at(700)), Ok(DisplayState::Suspended));
+        assert_eq!(tracker.tick(at(800)), Ok(DisplayState::Suspended));
+        assert_eq!(suspends.load(Ordering::SeqCst), 1);
+
+        tracker.input_activity(at(900)).unwrap();
+        assert_eq!(*panel.lock().unwrap(), (80, true));
+
+        // With dimming and suspend disabled only blanking remains
+        tracker.set_timeouts(IdleTimeouts {
+            dim: None,
+            suspend: None,
+            ..timeouts
+        });
+        assert_eq!(tracker.tick(at(1100)), Ok(DisplayState::Blanked));
+        assert_eq!(tracker.tick(at(5000)), Ok(DisplayState::Blanked));
+        assert_eq!(*panel.lock().unwrap(), (80, false));
+    }
+
+    #[test]
+    pub fn test_package_power_limits_follow_policy_mode() {
+        let msr = MsrSpace::new(1);
+        msr.set_cpuid(6, (1 << 7, 0, 0, 0));
+        msr.wrmsr(0, 0x771, 0x0A_14_24_30).unwrap();
+        // 1/8 W and 1/1024 s units; PL1 28 W over 28 s, PL2 64 W
+        msr.wrmsr(0, 0x606, 0xA_0E_03).unwrap();
+        let firmware = 0xE0 | (0b11 << 15) | (0x6E << 17) | (0x200 << 32) | (0b11 << 47) | (0x12 << 49);
+        msr.wrmsr(0, 0x610, firmware).unwrap();
+        let driver = PStateDriver::init(msr.clone()).unwrap();
+        let defaults = PowerLimits {
+            pl1_mw: 28_000,
+            pl2_mw: 64_000,
+            tau_ms: 28_000,
+            locked: false,
+        };
+        assert_eq!(driver.default_power_limits(), Some(defaults));
+
+        let manager = PolicyManager::new(PolicyMode::Performance);
+        manager.set_scaling_driver(Box::new(driver));
+        assert_eq!(msr.rdmsr(0, 0x610), Ok(firmware));
+        manager.set_mode(PolicyMode::Balanced);
+        assert_eq!(manager.component_snapshot().power_limits.map(|limits| limits.pl2_mw), Some(46_000));
+        manager.set_mode(PolicyMode::PowerSaver);
+        let limits = manager.component_snapshot().power_limits.unwrap();
+        assert_eq!((limits.pl1_mw, limits.pl2_mw, limits.tau_ms), (21_000, 21_000, 28_000));
+        // The PL2 window is firmware's
+        assert_eq!((msr.rdmsr(0, 0x610).unwrap() >> 49) & 0x7F, 0x12);
+
+        msr.wrmsr(0, 0x64F, (1 << 10) | (1 << 1)).unwrap();
+        let reasons = manager.component_snapshot().throttle_reasons;
+        assert!(reasons.pl1 && reasons.thermal && !reasons.pl2 && !reasons.prochot);
+
+        // A locked budget stays as firmware left it
+        msr.wrmsr(0, 0x610, firmware | (1 << 63)).unwrap();
+        let locked = PStateDriver::init(msr.clone()).unwrap();
+        assert!(locked.set_power_limits(defaults).is_err());
+        manager.set_scaling_driver(Box::new(locked));
+        manager.set_mode(PolicyMode::Performance);
+        assert_eq!(manager.component_snapshot().power_limits.map(|limits| limits.locked), Some(true));
+        assert_eq!(msr.rdmsr(0, 0x610), Ok(firmware | (1 << 63)));
+    }
+
+    #[test]
+    pub fn test_cpu_topology_from_cpuid() {
+        // Two P-cores with two threads each, then four E-cores sharing an
+        // L2, numbered by x2APIC ID as Alder Lake does
+        let msr = MsrSpace::new(8);
+        let apic_ids = [0, 1, 2, 3, 8, 10, 12, 14];
+        msr.set_cpuid(0, (0x20, 0, 0, 0));
+        msr.set_cpuid(0x16, (2100, 4700, 100, 0));
+        let cache = |kind: u32, level: u32, sharing: u32, ways: u32, sets: u32| (kind | level << 5 | (sharing - 1) << 14, (ways - 1) << 22 | 63, sets - 1, 0);
+        for (cpu, apic_id) in apic_ids.into_iter().enumerate() {
+            let efficiency = cpu >= 4;
+            msr.set_cpu_cpuid(cpu, 0x1A, (if efficiency { 0x20 } else { 0x40 } << 24, 0, 0, 0));
+            msr.set_cpu_cpuid_count(cpu, 0x1F, 0, (1, 2, 1 << 8, apic_id));
+            msr.set_cpu_cpuid_count(cpu, 0x1F, 1, (7, 8, 2 << 8 | 1, apic_id));
+            msr.set_cpu_cpuid_count(cpu, 0x1F, 2, (0, 0, 2, apic_id));
+            let (l1, l2) = match efficiency {
+                false => (cache(1, 1, 2, 12, 64), cache(3, 2, 2, 10, 2048)),
+                true => (cache(1, 1, 2, 8, 64), cache(3, 2, 8, 16, 2048)),
+            };
+            msr.set_cpu_cpuid_count(cpu, 4, 0, l1);
+            msr.set_cpu_cpuid_count(cpu, 4, 1, l2);
+            msr.set_cpu_cpuid_count(cpu, 4, 2, cache(3, 3, 16, 12, 16384));
+        }
+
+        let topology = Topology::discover(&msr);
+        assert_eq!((topology.packages(), topology.cores(), topology.cpus.len()), (1, 6, 8));
+        assert!(topology.is_hybrid());
+        assert_eq!(topology.summary(), "2 P-cores and 4 E-cores, 8 threads in 1 package");
+        assert_eq!(topology.cpus_of_type(CoreType::Efficiency), [4, 5, 6, 7]);
+        assert_eq!((topology.cpus[3].core, topology.cpus[3].thread), (1, 1));
+        assert_eq!(topology.siblings(1), [0, 1]);
+        assert_eq!(topology.siblings(5), [5]);
+        let l2 = topology.cache(1, 2).unwrap();
+        assert_eq!((l2.kind, l2.size, l2.line_size, l2.cpus.clone()), (CacheKind::Unified, 1280 << 10, 64, vec![0, 1]));
+        assert_eq!(topology.cache(6, 2).map(|cache| (cache.size, cache.cpus.clone())), Some((2 << 20, vec![4, 5, 6, 7])));
+        assert_eq!(topology.cache(4, 1).unwrap().size, 32 << 10);
+        assert_eq!(topology.cache(7, 3).map(|cache| (cache.size, cache.cpus.len())), Some((12 << 20, 8)));
+        // A thread on each P-core, their siblings, then the E-cores
+        assert_eq!(topology.preferred_order(), [0, 2, 1, 3, 4, 5, 6, 7]);
+        assert_eq!((topology.base_frequency_mhz, topology.max_frequency_mhz), (Some(2100), Some(4700)));
+
+        // The policy's estimate scales from what the CPU reports
+        let manager = PolicyManager::new(PolicyMode::Balanced);
+        manager.set_max_frequency(topology.max_frequency_mhz.unwrap());
+        assert_eq!(manager.component_snapshot().cpu_frequency_mhz, 3525);
+
+        // Without the topology leaves each CPU is a core of its own, and
+        // a part without leaf 0x1A is not hybrid
+        let old = MsrSpace::new(2);
+        old.set_cpuid(0, (0x0A, 0, 0, 0));
+        old.set_cpu_cpuid(1, 1, (0, 1 << 24, 0, 0));
+        old.set_cpu_cpuid(1, 0x1A, (0x20 << 24, 0, 0, 0));
+        let topology = Topology::discover(&old);
+        assert_eq!(topology.summary(), "2 cores, 2 threads in 1 package");
+        assert!(topology.caches.is_empty() && topology.max_frequency_mhz.is_none());
+
+        // The CPU the tests run on, as init discovers it, and the fallback
+        // estimate where it reports no maximum
+        let host = Topology::discover(&BootCpu);
+        assert_eq!(host.cpus.len(), 1);
+        assert!(BootCpu.rdmsr(0, 0x10).is_err());
+        assert_eq!(PolicyManager::new(PolicyMode::Performance).component_snapshot().cpu_frequency_mhz, 3000);
+    }
+}